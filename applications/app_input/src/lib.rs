@@ -0,0 +1,86 @@
+//! An application-level library that reads the input of applications that process files,
+//! such as `grep` or `wc`, which is either a file or the application's standard input.
+//!
+//! Like on Unix, such applications read their standard input if they are given no file or the file `-`,
+//! which lets them process the output of other applications in a pipeline.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+extern crate app_io;
+extern crate path;
+extern crate fs_node;
+extern crate stdio;
+extern crate bare_io;
+extern crate access_control;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use path::Path;
+use fs_node::{DirRef, FileOrDir, FileRef};
+use stdio::StdioReader;
+use bare_io::Read;
+use access_control::Access;
+
+
+/// The number of bytes read from the input at once.
+const BUFFER_SIZE: usize = 256;
+
+
+/// A source of input, which is either a file or the standard input.
+pub enum Input {
+    File { file: FileRef, offset: usize },
+    Stdin(StdioReader),
+}
+
+impl Input {
+    /// Opens the file at `path`, relative to `working_dir`, or the standard input if `path` is `-`.
+    pub fn open(path: &str, working_dir: &DirRef) -> Result<Input, String> {
+        if path == "-" {
+            return app_io::stdin().map(Input::Stdin).map_err(String::from);
+        }
+        match access_control::open(&Path::new(path.to_string()), working_dir, Access::Read) {
+            Ok(FileOrDir::File(file)) => Ok(Input::File { file, offset: 0 }),
+            Ok(FileOrDir::Dir(_)) => Err(format!("{}: is a directory", path)),
+            Err(e) => Err(format!("{}: {}", path, e)),
+        }
+    }
+
+    /// Reads the next bytes of this input into `buffer`, returning how many were read, which is zero at its end.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        match *self {
+            Input::File { ref file, ref mut offset } => {
+                let count = access_control::read(file, buffer, *offset)?;
+                *offset += count;
+                Ok(count)
+            }
+            Input::Stdin(ref stdin) => stdin.lock().read(buffer).map_err(|_| "failed to read from stdin"),
+        }
+    }
+
+    /// Calls `f` with each line of this input, without its line break, until `f` returns `false`.
+    pub fn for_each_line<F: FnMut(&str) -> bool>(&mut self, mut f: F) -> Result<(), &'static str> {
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let mut line = Vec::new();
+        loop {
+            let count = self.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            for &byte in &buffer[..count] {
+                if byte == b'\n' {
+                    if !f(&String::from_utf8_lossy(&line)) {
+                        return Ok(());
+                    }
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+        }
+        // The last line may not end with a line break.
+        if !line.is_empty() {
+            f(&String::from_utf8_lossy(&line));
+        }
+        Ok(())
+    }
+}
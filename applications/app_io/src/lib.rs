@@ -48,8 +48,9 @@ pub struct IoStreams {
     /// The reader to key event queue. This is the same reader as that in
     /// shell. Apps can take this reader to directly access keyboard events.
     key_event_reader: Arc<Mutex<Option<KeyEventQueueReader>>>,
-    /// Points to the terminal.
-    terminal: Arc<Mutex<Terminal>>
+    /// Points to the terminal, if the application runs in one,
+    /// which isn't the case for shells whose I/O goes elsewhere, e.g., over the network.
+    terminal: Option<Arc<Mutex<Terminal>>>
 }
 
 /// Applications set the flags in this structure to inform the parent shell to
@@ -57,15 +58,19 @@ pub struct IoStreams {
 pub struct IoControlFlags {
     /// When set to be `true`, the shell will immediately flush received character
     /// input to stdin rather than waiting for enter keystrike.
-    stdin_instant_flush: bool
+    stdin_instant_flush: bool,
+    /// Set by the shell to indicate whether the task belongs to its foreground job.
+    /// Only tasks in the foreground can take the key event queue.
+    foreground: bool,
 }
 
 impl IoControlFlags {
     /// Create a new `IoControlFlags` instance. `stdin_instant_flush` is set to
-    /// be `false` on default.
+    /// be `false` and `foreground` to be `true` on default.
     pub fn new() -> IoControlFlags {
         IoControlFlags {
-            stdin_instant_flush: false
+            stdin_instant_flush: false,
+            foreground: true,
         }
     }
 }
@@ -74,7 +79,7 @@ impl IoStreams {
     pub fn new(stdin: StdioReader, stdout: StdioWriter,
                stderr: StdioWriter,
                key_event_reader: Arc<Mutex<Option<KeyEventQueueReader>>>,
-               terminal: Arc<Mutex<Terminal>>) -> IoStreams {
+               terminal: Option<Arc<Mutex<Terminal>>>) -> IoStreams {
         IoStreams {
             stdin,
             stdout,
@@ -129,11 +134,13 @@ mod shared_maps {
 
 
 /// An application can call this function to get the terminal to which it should print.
+///
+/// Returns `None` if the application doesn't run in a terminal, e.g., when it was started by a remote shell.
 pub fn get_my_terminal() -> Option<Arc<Mutex<Terminal>>> {
     task::get_my_current_task_id()
         .and_then(|id| shared_maps::lock_stream_map()
             .get(&id)
-            .map(|property| property.terminal.clone())
+            .and_then(|property| property.terminal.clone())
         )
 }
 
@@ -218,9 +225,13 @@ pub fn stderr() -> Result<StdioWriter, &'static str> {
 /// to let it run. The third case happens when the reader of the key event queue has
 /// already been taken by some other task, which may be running simultaneously, or be killed
 /// prematurely so that it cannot return the key event reader on exit.
+/// Tasks that the shell runs in the background cannot take the key event queue either.
 pub fn take_key_event_queue() -> Result<KeyEventReadGuard, &'static str> {
     let task_id = task::get_my_current_task_id().ok_or("failed to get task_id to take key event queue")?;
-    let locked_streams = shared_maps::lock_stream_map();
+    let (locked_flags, locked_streams) = shared_maps::lock_all_maps();
+    if let Some(false) = locked_flags.get(&task_id).map(|flags| flags.foreground) {
+        return Err("a task running in the background cannot take the key event queue");
+    }
     match locked_streams.get(&task_id) {
         Some(queues) => {
             match queues.key_event_reader.lock().take() {
@@ -301,6 +312,21 @@ pub fn is_requesting_instant_flush(task_id: &usize) -> Result<bool, &'static str
     }
 }
 
+/// Shells call this function to move a task into the foreground or the background,
+/// which determines whether it is allowed to take the key event queue.
+/// 
+/// Error can occur when there is no IoControlFlags structure for that task.
+pub fn set_foreground(task_id: &usize, foreground: bool) -> Result<(), &'static str> {
+    let mut locked_flags = shared_maps::lock_flag_map();
+    match locked_flags.get_mut(task_id) {
+        Some(flags) => {
+            flags.foreground = foreground;
+            Ok(())
+        },
+        None => Err("no io control flags for this task")
+    }
+}
+
 /// Calls `print!()` with an extra newline ('\n') appended to the end. 
 #[macro_export]
 macro_rules! println {
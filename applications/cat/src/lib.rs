@@ -8,6 +8,7 @@ extern crate getopts;
 extern crate path;
 extern crate fs_node;
 extern crate bare_io;
+extern crate access_control;
 
 use core::str;
 use alloc::{
@@ -19,6 +20,7 @@ use getopts::Options;
 use path::Path;
 use fs_node::FileOrDir;
 use bare_io::{Read, Write};
+use access_control::Access;
 
 
 pub fn main(args: Vec<String>) -> isize {
@@ -60,30 +62,29 @@ pub fn main(args: Vec<String>) -> isize {
     };
     let path = Path::new(matches.free[0].to_string());
     
-    // navigate to the filepath specified by first argument
-    match path.get(&curr_wr) {
-        Some(file_dir_enum) => { 
+    // navigate to the filepath specified by first argument, checking that this task may read it
+    match access_control::open(&path, &curr_wr, Access::Read) {
+        Ok(file_dir_enum) => { 
             match file_dir_enum {
                 FileOrDir::Dir(directory) => {
                     println!("{:?} is a directory, cannot 'cat' non-files.", directory.lock().get_name());
                     return -1;
                 }
                 FileOrDir::File(file) => {
-                    let file_locked = file.lock();
-                    let file_size = file_locked.size();
+                    let file_size = file.lock().size();
                     let mut string_slice_as_bytes = vec![0; file_size];
                     
-                    let _num_bytes_read = match file_locked.read(&mut string_slice_as_bytes,0) {
+                    let _num_bytes_read = match access_control::read(&file, &mut string_slice_as_bytes, 0) {
                         Ok(num) => num,
                         Err(e) => {
-                            println!("Failed to read {:?}, error {:?}", file_locked.get_name(), e);
+                            println!("Failed to read {:?}, error {:?}", file.lock().get_name(), e);
                             return -1;
                         }
                     };
                     let read_string = match str::from_utf8(&string_slice_as_bytes) {
                         Ok(string_slice) => string_slice,
                         Err(utf8_err) => {
-                            println!("File {:?} was not a printable UTF-8 text file: {}", file_locked.get_name(), utf8_err);
+                            println!("File {:?} was not a printable UTF-8 text file: {}", file.lock().get_name(), utf8_err);
                             return -1;
                         }
                     };
@@ -91,8 +92,8 @@ pub fn main(args: Vec<String>) -> isize {
                 }
             }
         },
-        _ => {
-            println!("Couldn't find file at path {}", path);
+        Err(e) => {
+            println!("Couldn't open file at path {}: {}", path, e);
             return -1;
         }
     };
@@ -0,0 +1,69 @@
+//! Changes the permissions of files and directories.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate path;
+extern crate access_control;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() < 2 {
+        return print_usage(opts);
+    }
+
+    let permissions = match u16::from_str_radix(&matches.free[0], 8) {
+        Ok(mode) if mode <= 0o7777 => mode,
+        _ => {
+            println!("Error: invalid mode {}, expected octal permissions like 644", matches.free[0]);
+            return -1;
+        }
+    };
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let mut ret = 0;
+    for arg in matches.free[1..].iter() {
+        let result = access_control::lookup(&Path::new(arg.to_string()), &curr_wd)
+            .and_then(|node| access_control::set_permissions(&node, permissions));
+        if let Err(e) = result {
+            println!("Error: couldn't change the permissions of {}: {}", arg, e);
+            ret = -1;
+        }
+    }
+    ret
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: chmod [OPTIONS] MODE PATH...\n\n");
+
+    brief.push_str("Sets the permissions of each file or directory at PATH to MODE, given in octal, e.g., 644 or 1777.\n");
+    brief.push_str("Only the owner of a file or directory can change its permissions.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
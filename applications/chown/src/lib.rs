@@ -0,0 +1,83 @@
+//! Changes the owning user and group of files and directories.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate access_control;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use fs_node::FsNode;
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() < 2 {
+        return print_usage(opts);
+    }
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let owner = &matches.free[0];
+    let mut ret = 0;
+    for arg in matches.free[1..].iter() {
+        let result = access_control::lookup(&Path::new(arg.to_string()), &curr_wd).and_then(|node| {
+            let metadata = node.metadata();
+            let (uid, gid) = parse_owner(owner, metadata.owner, metadata.group)?;
+            access_control::set_owner(&node, uid, gid)
+        });
+        if let Err(e) = result {
+            println!("Error: couldn't change the owner of {}: {}", arg, e);
+            ret = -1;
+        }
+    }
+    ret
+}
+
+/// Parses an owner like `UID`, `UID:GID` or `:GID`,
+/// using the node's current user and group IDs for the parts that are omitted.
+fn parse_owner(owner: &str, current_uid: Option<u32>, current_gid: Option<u32>) -> Result<(u32, u32), &'static str> {
+    let mut parts = owner.splitn(2, ':');
+    let uid = parts.next().unwrap_or("");
+    let gid = parts.next().unwrap_or("");
+    let parse = |id: &str, current: Option<u32>| -> Result<u32, &'static str> {
+        if id.is_empty() {
+            current.ok_or("this filesystem does not support ownership")
+        } else {
+            id.parse::<u32>().map_err(|_| "invalid owner, expected numeric IDs like UID[:GID]")
+        }
+    };
+    Ok((parse(uid, current_uid)?, parse(gid, current_gid)?))
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: chown [OPTIONS] UID[:GID] PATH...\n\n");
+
+    brief.push_str("Sets the user (and group) that own each file or directory at PATH, given as numeric IDs.\n");
+    brief.push_str("Only system tasks can give files to another user.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
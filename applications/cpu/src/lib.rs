@@ -6,6 +6,7 @@ extern crate apic;
 extern crate getopts;
 extern crate task;
 extern crate runqueue;
+extern crate load_balancer;
 
 use getopts::Options;
 use alloc::vec::Vec;
@@ -15,6 +16,7 @@ use apic::get_lapics;
 pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
+    opts.optflag("l", "load", "print the load balancing statistics of each core");
 
     let matches = match opts.parse(&args) {
         Ok(m) => { m }
@@ -26,6 +28,14 @@ pub fn main(args: Vec<String>) -> isize {
         return print_usage(opts)
     }
 
+    if matches.opt_present("l") {
+        println!("{:<6} {:<10} {:<6} {:<13} {:<13}", "CORE", "QUEUED", "IDLE", "MIGRATED_IN", "MIGRATED_OUT");
+        for load in load_balancer::core_loads() {
+            println!("{:<6} {:<10} {:<6} {:<13} {:<13}", load.core, load.queued_tasks, load.idle, load.migrated_in, load.migrated_out);
+        }
+        return 0;
+    }
+
     let all_lapics = get_lapics();
     for lapic in all_lapics.iter() {
         let lapic = lapic.1;
@@ -62,7 +72,7 @@ pub fn main(args: Vec<String>) -> isize {
 fn print_usage(opts: Options) -> isize {
     let mut brief = format!("Usage: cpu \n \n");
 
-    brief.push_str("For each core, prints apic id, processor id, whether it is the bootstrap processor (the first processor to boot up), which tasks that is currently running on that core and which tasks are present in that core's runqueue");
+    brief.push_str("For each core, prints apic id, processor id, whether it is the bootstrap processor (the first processor to boot up), which tasks that is currently running on that core and which tasks are present in that core's runqueue.\n\nWith the -l option, prints how many tasks are on each core's runqueue and how many tasks have been migrated to and from each core.");
 
     println!("{} \n", opts.usage(&brief));
 
@@ -0,0 +1,150 @@
+//! Prints the crash dump left behind by the previous boot, if it crashed.
+//!
+//! The dump is written when a task panics or causes an exception, and it survives a warm reboot,
+//! so this shows what went wrong even if the crash left the previous boot unusable.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate crash_dump;
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use getopts::{Matches, Options};
+use crash_dump::CrashDump;
+
+
+/// The number of log records that are printed by default.
+const DEFAULT_LOG_RECORDS: usize = 20;
+/// The maximum width of a line in the list of crates.
+const LINE_WIDTH: usize = 80;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("n", "lines", "print the last NUM log records (default 20)", "NUM");
+    opts.optflag("a", "all", "print all log records and the list of loaded crates");
+    opts.optflag("c", "crates", "print the list of crates that were loaded");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let dump = match crash_dump::previous_dump() {
+        Some(dump) => dump,
+        None => {
+            println!("The previous boot didn't leave a crash dump.");
+            return 0;
+        }
+    };
+
+    match print_dump(dump, &matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn print_dump(dump: &CrashDump, matches: &Matches) -> Result<(), &'static str> {
+    let all = matches.opt_present("a");
+    let log_records = match matches.opt_str("n") {
+        Some(n) => n.parse::<usize>().map_err(|_| "NUM must be a non-negative number")?,
+        None if all => dump.log.len(),
+        None => DEFAULT_LOG_RECORDS,
+    };
+
+    println!("Crash during the previous boot: {}", dump.cause);
+    if let Some(ref task) = dump.task {
+        match dump.app_crate {
+            Some(ref app_crate) => println!("  Task:             {:?} (application {})", task, app_crate),
+            None                => println!("  Task:             {:?}", task),
+        }
+    }
+    if let Some(cpu) = dump.cpu {
+        println!("  CPU:              {}", cpu);
+    }
+    if let Some(ref faulting_crate) = dump.faulting_crate {
+        println!("  Crate:            {}", faulting_crate);
+    }
+    if let Some(error_code) = dump.error_code {
+        println!("  Error code:       {:#X}", error_code);
+    }
+    if let Some(address) = dump.address_accessed {
+        println!("  Accessed address: {:#X}", address);
+    }
+
+    if let Some(ref registers) = dump.registers {
+        println!("\nRegisters:");
+        for pair in registers.named_values().chunks(2) {
+            let mut line = String::new();
+            for &(name, value) in pair {
+                line.push_str(&format!("  {:<6} {:#018X}", name, value));
+            }
+            println!("{}", line);
+        }
+    }
+
+    if !dump.backtrace.is_empty() {
+        println!("\nBacktrace:");
+        for frame in &dump.backtrace {
+            println!("  {}", frame);
+        }
+    }
+
+    if log_records > 0 && !dump.log.is_empty() {
+        let skipped = dump.log.len().saturating_sub(log_records);
+        if skipped > 0 {
+            println!("\nLog (last {} of {} records):", dump.log.len() - skipped, dump.log.len());
+        } else {
+            println!("\nLog ({} records):", dump.log.len());
+        }
+        for record in &dump.log[skipped..] {
+            println!("{}", record);
+        }
+    }
+
+    if all || matches.opt_present("c") {
+        println!("\nLoaded crates ({}):", dump.crates.len());
+        let mut line = String::new();
+        for crate_name in &dump.crates {
+            if !line.is_empty() && line.len() + 2 + crate_name.len() > LINE_WIDTH {
+                println!("{}", line);
+                line.clear();
+            }
+            line.push_str("  ");
+            line.push_str(crate_name);
+        }
+        if !line.is_empty() {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+
+fn print_usage(opts: Options) {
+    let mut brief = String::from("Usage: crashdump [OPTIONS]\n\n");
+
+    brief.push_str("Prints the crash dump left behind by the previous boot, if a task panicked or caused an exception.\n");
+    brief.push_str("The dump includes the cause of the crash, the registers, a backtrace, and the most recent log records.\n");
+    brief.push_str("It only survives a warm reboot, not a power cycle.");
+
+    println!("{} \n", opts.usage(&brief));
+}
@@ -1,19 +1,101 @@
-#![no_std]
-// #![feature(plugin)]
-// #![plugin(application_main_fn)]
+//! Prints or sets the system's date and time.
+//!
+//! The time is printed in the system's time zone, unless the `TZ` environment variable names another one.
+//!
+//! Examples:
+//! * `date -s "2026-10-15 14:30:00"` sets the local date and time.
+//! * `date -z UTC+2` sets the system's time zone.
+//! * `date -n pool.ntp.org` synchronizes the clock with an NTP server.
 
+#![no_std]
 
-extern crate alloc;
+#[macro_use] extern crate alloc;
 #[macro_use] extern crate terminal_print;
-extern crate rtc;
+extern crate getopts;
+extern crate task;
+extern crate wall_clock;
+extern crate dns_resolver;
 
 use alloc::vec::Vec;
 use alloc::string::String;
+use getopts::{Matches, Options};
+use wall_clock::{DateTime, SystemTime, TimeZone};
+use wall_clock::sntp;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("u", "utc", "print the time in UTC");
+    opts.optflag("I", "iso-8601", "print the time in the form YYYY-MM-DD HH:MM:SS");
+    opts.optopt("s", "set", "set the local date and time to DATETIME, in the form \"YYYY-MM-DD HH:MM[:SS]\"", "DATETIME");
+    opts.optopt("z", "zone", "set the system's time zone to ZONE, e.g., UTC, UTC+2, or -08:00", "ZONE");
+    opts.optopt("n", "ntp", "synchronize the clock with the NTP server SERVER", "SERVER");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(&opts);
+    }
+
+    match rmain(&matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("date: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain(matches: &Matches) -> Result<(), String> {
+    if let Some(zone) = matches.opt_str("z") {
+        wall_clock::set_time_zone(TimeZone::parse(&zone)?);
+    }
+    let zone = if matches.opt_present("u") { TimeZone::UTC } else { current_time_zone()? };
 
+    if let Some(datetime) = matches.opt_str("s") {
+        let datetime = DateTime::parse(&datetime)?;
+        wall_clock::set_time(SystemTime::from_local(&datetime, zone));
+    }
 
-pub fn main(_args: Vec<String>) -> isize {
-    let now = rtc::read_rtc();
-    println!("{}", now);
+    if let Some(server) = matches.opt_str("n") {
+        let address = *dns_resolver::resolve(&server)?.first().ok_or("the NTP server's name has no addresses")?;
+        let reply = sntp::sync(address, sntp::DEFAULT_TIMEOUT)?;
+        println!("synchronized with {} (stratum {}): the clock was off by {:.3} s, round trip {} ms",
+            reply.server, reply.stratum, reply.offset_ns as f64 / 1e9, reply.round_trip.as_millis());
+    }
+
+    let now = SystemTime::now().to_local(zone);
+    if matches.opt_present("I") {
+        println!("{} {}", now, zone);
+    } else {
+        println!("{} {} {:2} {:02}:{:02}:{:02} {} {}",
+            now.weekday_name(), now.month_name(), now.day, now.hour, now.minute, now.second, zone, now.year);
+    }
+    Ok(())
+}
 
+/// Returns the time zone named by the `TZ` environment variable, or the system's time zone if it isn't set.
+fn current_time_zone() -> Result<TimeZone, String> {
+    let tz = task::get_my_current_task()
+        .and_then(|t| t.lock().env.lock().get("TZ").cloned());
+    match tz {
+        Some(tz) => TimeZone::parse(&tz).map_err(|e| format!("invalid TZ environment variable: {}", e)),
+        None => Ok(wall_clock::time_zone()),
+    }
+}
+
+fn print_usage(opts: &Options) -> isize {
+    println!("{}", opts.usage(USAGE));
     0
 }
+
+const USAGE: &'static str = "Usage: date [OPTION]...
+Prints or sets the system's date and time.";
@@ -0,0 +1,121 @@
+//! Configures a network interface via DHCP, releases its lease, or shows the DHCP status of every interface.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate network_manager;
+extern crate net_stack;
+extern crate dhcp_client;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+use network_manager::NETWORK_INTERFACES;
+use dhcp_client::{Lease, Status};
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("r", "release", "release the lease of the interface and remove its configuration");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let index = match matches.free.first() {
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => {
+                println!("invalid interface index {:?}", arg);
+                return -1;
+            }
+        },
+        None => {
+            if matches.opt_present("r") {
+                println!("dhcp: an interface must be given to release its lease");
+                return -1;
+            }
+            print_statuses();
+            return 0;
+        }
+    };
+
+    if matches.opt_present("r") {
+        match dhcp_client::release(index) {
+            Ok(()) => {
+                println!("released the lease of interface {}", index);
+                0
+            }
+            Err(e) => {
+                println!("dhcp: {}", e);
+                -1
+            }
+        }
+    } else {
+        println!("configuring interface {} via DHCP...", index);
+        match dhcp_client::configure(index) {
+            Ok(lease) => {
+                print_lease(index, &lease);
+                0
+            }
+            Err(e) => {
+                println!("dhcp: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+fn print_statuses() {
+    let num_interfaces = NETWORK_INTERFACES.lock().len();
+    if num_interfaces == 0 {
+        println!("no network interfaces");
+        return;
+    }
+    for index in 0 .. num_interfaces {
+        match dhcp_client::status(index) {
+            Some(Status::Bound(lease)) => print_lease(index, &lease),
+            Some(Status::Configuring) => println!("interface {}: obtaining a lease", index),
+            Some(Status::Failed(e)) => println!("interface {}: failed: {}", index, e),
+            None => println!("interface {}: not configured via DHCP", index),
+        }
+    }
+}
+
+fn print_lease(index: usize, lease: &Lease) {
+    println!("interface {}: address {}", index, lease.address);
+    match lease.gateway {
+        Some(gateway) => println!("    gateway      {}", gateway),
+        None => println!("    gateway      none"),
+    }
+    let dns_servers: Vec<String> = lease.dns_servers.iter().map(|server| format!("{}", server)).collect();
+    println!("    DNS servers  {}", if dns_servers.is_empty() { String::from("none") } else { dns_servers.join(", ") });
+    println!("    server       {}", lease.server);
+    let now = net_stack::now_millis().unwrap_or(lease.granted_at_ms);
+    match (lease.expires_at_ms(), lease.renews_at_ms()) {
+        (Some(expiry), Some(renewal)) => println!("    expires in   {} s (renewed in {} s)",
+            expiry.saturating_sub(now) / 1000, renewal.saturating_sub(now) / 1000,
+        ),
+        _ => println!("    expires      never"),
+    }
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: dhcp [OPTIONS] [INTERFACE]\n\n");
+
+    brief.push_str("Configures the network interface with the given index via DHCP and waits for it to obtain a lease,\n");
+    brief.push_str("or releases its lease with --release. Without an interface, shows the DHCP status of every interface.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
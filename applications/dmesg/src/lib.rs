@@ -0,0 +1,232 @@
+//! Prints the log records in the kernel's in-memory log ring buffer, optionally following new records,
+//! and allows the log levels and log sinks to be changed at runtime.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate log;
+extern crate logger;
+extern crate task;
+extern crate path;
+extern crate fs_node;
+extern crate memfs;
+extern crate hpet;
+extern crate scheduler;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use getopts::{Matches, Options};
+use log::LevelFilter;
+use logger::{LogRecord, FileSink};
+use path::Path;
+use fs_node::{FileOrDir, FileRef};
+use memfs::MemFile;
+
+/// How often to check for new log records when following the ring buffer.
+const FOLLOW_INTERVAL_MS: u64 = 100;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("f", "follow", "after printing the existing records, wait for and print new records");
+    opts.optopt("l", "level", "only print records at or above the given level, e.g., \"warn\"", "LEVEL");
+    opts.optopt("c", "crate", "only print records logged by the given crate", "CRATE");
+    opts.optflag("C", "clear", "clear the ring buffer instead of printing it");
+    opts.optmulti("s", "set-level", "set the default log level, or the log level of a crate. \
+        LEVEL is one of \"off\", \"error\", \"warn\", \"info\", \"debug\", \"trace\", \
+        or \"default\" to reset a crate's level", "[CRATE=]LEVEL");
+    opts.optflag("L", "levels", "print the default log level and all per-crate log levels");
+    opts.optopt("o", "output", "also write all future log messages to the given file, creating it if needed", "FILE");
+    opts.optopt("r", "remove-sink", "stop writing log messages to the sink with the given name, e.g., a file path", "NAME");
+    opts.optflag("S", "sinks", "list the sinks that log messages are written to");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match run(&matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(matches: &Matches) -> Result<(), String> {
+    // Options that configure the logger don't print the ring buffer.
+    let mut configured = false;
+    for setting in matches.opt_strs("s") {
+        set_level(&setting)?;
+        configured = true;
+    }
+    if let Some(path) = matches.opt_str("o") {
+        let file = get_or_create_file(&path)?;
+        let sink = FileSink::new(file);
+        let name = logger::LogSink::name(&sink).to_string();
+        logger::add_sink(Arc::new(sink))?;
+        println!("Writing log messages to {}", name);
+        configured = true;
+    }
+    if let Some(name) = matches.opt_str("r") {
+        logger::remove_sink(&name).ok_or_else(|| format!("no log sink named {:?}", name))?;
+        configured = true;
+    }
+    if matches.opt_present("L") {
+        println!("default: {}", logger::default_log_level());
+        for (crate_name, level) in logger::crate_log_levels() {
+            println!("{}: {}", crate_name, level);
+        }
+        configured = true;
+    }
+    if matches.opt_present("S") {
+        println!("serial (always enabled)");
+        for name in logger::sink_names() {
+            println!("{}", name);
+        }
+        configured = true;
+    }
+    if matches.opt_present("C") {
+        logger::clear();
+        configured = true;
+    }
+    if configured {
+        return Ok(());
+    }
+
+    let min_level = match matches.opt_str("l") {
+        Some(l) => Some(l.parse::<LevelFilter>().map_err(|_| format!("invalid log level {:?}", l))?),
+        None => None,
+    };
+    let crate_name = matches.opt_str("c");
+    let filter = |record: &LogRecord| {
+        min_level.map_or(true, |min| record.level <= min)
+            && crate_name.as_ref().map_or(true, |c| record.crate_name() == c)
+    };
+
+    let dropped = logger::dropped_records();
+    let (records, mut next) = logger::read_from(0);
+    for record in records.iter().filter(|r| filter(r)) {
+        println!("{}", record);
+    }
+    if dropped > 0 {
+        println!("({} records were dropped due to concurrent logging)", dropped);
+    }
+
+    if matches.opt_present("f") {
+        loop {
+            wait_ms(FOLLOW_INTERVAL_MS)?;
+            let (records, new_next) = logger::read_from(next);
+            if records.first().map_or(false, |r| r.sequence > next) {
+                println!("({} records were overwritten before they could be printed)", records[0].sequence - next);
+            }
+            for record in records.iter().filter(|r| filter(r)) {
+                println!("{}", record);
+            }
+            next = new_next;
+        }
+    }
+    Ok(())
+}
+
+/// Parses a `[CRATE=]LEVEL` setting and applies it.
+fn set_level(setting: &str) -> Result<(), String> {
+    let mut parts = setting.splitn(2, '=');
+    let (crate_name, level_str) = match (parts.next(), parts.next()) {
+        (Some(c), Some(l)) => (Some(c), l),
+        (Some(l), None) => (None, l),
+        _ => return Err(format!("invalid level setting {:?}", setting)),
+    };
+    let level = if crate_name.is_some() && level_str == "default" {
+        None
+    } else {
+        Some(level_str.parse::<LevelFilter>().map_err(|_| format!("invalid log level {:?}", level_str))?)
+    };
+
+    match (crate_name, level) {
+        (Some(c), level) => {
+            logger::set_crate_log_level(c, level);
+            match level {
+                Some(l) => println!("Set log level of crate {} to {}", c, l),
+                None => println!("Reset log level of crate {} to the default", c),
+            }
+        }
+        (None, Some(l)) => {
+            logger::set_default_log_level(l);
+            println!("Set default log level to {}", l);
+        }
+        (None, None) => return Err(String::from("\"default\" can only be used with a crate name")),
+    }
+    Ok(())
+}
+
+/// Returns the file at the given path relative to the current working directory,
+/// creating it in memory if it doesn't yet exist.
+fn get_or_create_file(path: &str) -> Result<FileRef, String> {
+    let curr_wd = task::get_my_current_task()
+        .map(|t| Arc::clone(&t.lock().env.lock().working_dir))
+        .ok_or_else(|| String::from("failed to get current task"))?;
+
+    match Path::new(path.to_string()).get(&curr_wd) {
+        Some(FileOrDir::File(file)) => return Ok(file),
+        Some(FileOrDir::Dir(_)) => return Err(format!("{:?} is a directory", path)),
+        None => { }
+    }
+
+    let (parent_path, file_name) = match path.rfind('/') {
+        Some(i) => (&path[..i + 1], &path[i + 1..]),
+        None => ("", path),
+    };
+    if file_name.is_empty() {
+        return Err(format!("{:?} is not a valid file name", path));
+    }
+    let parent_dir = if parent_path.is_empty() {
+        curr_wd
+    } else {
+        match Path::new(parent_path.to_string()).get(&curr_wd) {
+            Some(FileOrDir::Dir(dir)) => dir,
+            _ => return Err(format!("couldn't find directory {:?}", parent_path)),
+        }
+    };
+    MemFile::new(file_name.to_string(), &parent_dir).map_err(|e| e.to_string())
+}
+
+/// Repeatedly yields the current core until the given number of milliseconds have elapsed.
+fn wait_ms(duration_ms: u64) -> Result<(), String> {
+    let (start, period_fs) = {
+        let hpet = hpet::get_hpet().ok_or_else(|| String::from("couldn't get the HPET to wait for new log records"))?;
+        (hpet.get_counter(), hpet.counter_period_femtoseconds() as u64)
+    };
+    // 1 millisecond is 10^12 femtoseconds.
+    let ticks = duration_ms.saturating_mul(1_000_000_000_000) / core::cmp::max(period_fs, 1);
+    loop {
+        let now = hpet::get_hpet().map(|hpet| hpet.get_counter()).unwrap_or(core::u64::MAX);
+        if now.saturating_sub(start) >= ticks {
+            return Ok(());
+        }
+        scheduler::schedule();
+    }
+}
+
+fn print_usage(opts: Options) {
+    let brief = format!("Usage: dmesg [options]\n\n\
+        Prints the most recent {} kernel log records, which are kept in an in-memory ring buffer.\n\
+        Options that change log levels or sinks don't print the log records.", logger::RING_BUFFER_LEN);
+    println!("{}", opts.usage(&brief));
+}
@@ -0,0 +1,140 @@
+//! The text that is being edited, stored as a list of lines.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+
+/// A position in the buffer, given as the index of a line and the index of a character in that line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl Position {
+    pub fn new(row: usize, col: usize) -> Position {
+        Position { row, col }
+    }
+}
+
+
+/// The lines of a text file, without their line breaks. There is always at least one line.
+pub struct Buffer {
+    lines: Vec<Vec<char>>,
+}
+
+impl Buffer {
+    /// Creates a buffer that holds the given text.
+    /// A line break at the end of the text doesn't start another line.
+    pub fn from_text(text: &str) -> Buffer {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        Buffer {
+            lines: text.split('\n').map(|line| line.chars().collect()).collect(),
+        }
+    }
+
+    /// Returns the text in this buffer, with each line ending in a line break.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            text.extend(line.iter());
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Returns the line at index `row`, which must exist.
+    pub fn line(&self, row: usize) -> &[char] {
+        &self.lines[row]
+    }
+
+    pub fn line_len(&self, row: usize) -> usize {
+        self.lines[row].len()
+    }
+
+    /// Returns the given position, moved into the buffer if it lies beyond the end of its line or of the buffer.
+    pub fn clamp(&self, pos: Position) -> Position {
+        let row = core::cmp::min(pos.row, self.lines.len() - 1);
+        Position::new(row, core::cmp::min(pos.col, self.lines[row].len()))
+    }
+
+    /// Inserts the character `c` at `pos`, returning the position after it.
+    pub fn insert_char(&mut self, pos: Position, c: char) -> Position {
+        self.lines[pos.row].insert(pos.col, c);
+        Position::new(pos.row, pos.col + 1)
+    }
+
+    /// Splits the line at `pos`, returning the position at the start of the new line.
+    pub fn insert_line_break(&mut self, pos: Position) -> Position {
+        let rest = self.lines[pos.row].split_off(pos.col);
+        self.lines.insert(pos.row + 1, rest);
+        Position::new(pos.row + 1, 0)
+    }
+
+    /// Removes the character before `pos`, joining its line with the previous one if `pos` is at the start of a line.
+    /// Returns the position where the removed character was.
+    pub fn remove_char_before(&mut self, pos: Position) -> Position {
+        if pos.col > 0 {
+            self.lines[pos.row].remove(pos.col - 1);
+            Position::new(pos.row, pos.col - 1)
+        } else if pos.row > 0 {
+            let line = self.lines.remove(pos.row);
+            let previous = &mut self.lines[pos.row - 1];
+            let col = previous.len();
+            previous.extend(line);
+            Position::new(pos.row - 1, col)
+        } else {
+            pos
+        }
+    }
+
+    /// Removes the character at `pos`, joining its line with the next one if `pos` is at the end of a line.
+    pub fn remove_char_at(&mut self, pos: Position) {
+        if pos.col < self.lines[pos.row].len() {
+            self.lines[pos.row].remove(pos.col);
+        } else if pos.row + 1 < self.lines.len() {
+            let next = self.lines.remove(pos.row + 1);
+            self.lines[pos.row].extend(next);
+        }
+    }
+
+    /// Removes the line at index `row` and returns it.
+    /// If it is the only line, it is emptied instead.
+    pub fn remove_line(&mut self, row: usize) -> Vec<char> {
+        if self.lines.len() == 1 {
+            core::mem::take(&mut self.lines[0])
+        } else {
+            self.lines.remove(row)
+        }
+    }
+
+    /// Inserts the given `line` before the line at index `row`.
+    pub fn insert_line(&mut self, row: usize, line: Vec<char>) {
+        self.lines.insert(row, line);
+    }
+
+    /// Finds the next occurrence of `query` after `pos`, wrapping around to the start of the buffer.
+    /// Returns its position and whether the search wrapped around.
+    pub fn find(&self, query: &[char], pos: Position) -> Option<(Position, bool)> {
+        if query.is_empty() {
+            return None;
+        }
+        let line_count = self.lines.len();
+        // Search the rest of the current line, the following lines, and then the lines before, ending with the current line again.
+        for i in 0 ..= line_count {
+            let row = (pos.row + i) % line_count;
+            let line = &self.lines[row];
+            let start = if i == 0 { pos.col + 1 } else { 0 };
+            let found = (start .. (line.len() + 1).saturating_sub(query.len()))
+                .find(|&col| line[col .. col + query.len()] == *query);
+            if let Some(col) = found {
+                return Some((Position::new(row, col), pos.row + i >= line_count));
+            }
+        }
+        None
+    }
+}
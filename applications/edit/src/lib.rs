@@ -0,0 +1,561 @@
+//! A small text editor in the style of `nano`, which runs full-screen in the terminal.
+//!
+//! The text fills the screen above a status bar, which shows the file name, whether the text was modified,
+//! and the position of the cursor. The bottom line shows messages, prompts, and a summary of the commands:
+//! `Ctrl+O` saves the file, `Ctrl+X` exits, `Ctrl+W` searches, `Ctrl+K` cuts the current line and `Ctrl+U` pastes it.
+//!
+//! The editor draws the screen with the ANSI escape sequences that `libterm` understands,
+//! and switches to the alternate screen while it runs so that the shell's output is restored when it exits.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate path;
+extern crate fs_node;
+extern crate access_control;
+extern crate keycodes_ascii;
+extern crate libterm;
+extern crate stdio;
+extern crate scheduler;
+extern crate spin;
+
+mod buffer;
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::str;
+use getopts::Options;
+use path::Path;
+use fs_node::{DirRef, FileOrDir};
+use keycodes_ascii::{KeyAction, KeyEvent, Keycode};
+use libterm::Terminal;
+use spin::Mutex;
+use stdio::KeyEventQueueReader;
+use buffer::{Buffer, Position};
+
+
+/// The number of columns between tab stops.
+const TAB_WIDTH: usize = 4;
+
+/// Switches to the alternate screen and turns off line wrapping, so that long lines are cut off at the edge of the screen.
+const ENTER_SCREEN: &str = "\x1b[?1049h\x1b[?7l";
+/// Undoes `ENTER_SCREEN`, bringing back the screen as it was before the editor started.
+const LEAVE_SCREEN: &str = "\x1b[?7h\x1b[?1049l";
+
+const HELP: &str = "^O Save  ^X Exit  ^W Search  ^K Cut  ^U Paste  ^A Home  ^E End";
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() > 1 {
+        return print_usage(opts);
+    }
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let terminal = match app_io::get_my_terminal() {
+        Some(terminal) => terminal,
+        None => {
+            println!("couldn't get terminal for `edit` app");
+            return -1;
+        }
+    };
+
+    let mut editor = Editor::new(terminal, curr_wd);
+    if let Some(path) = matches.free.first() {
+        if let Err(e) = editor.open(path) {
+            println!("edit: {}", e);
+            return -1;
+        }
+    }
+
+    if let Err(e) = run(&mut editor) {
+        println!("edit: {}", e);
+        return -1;
+    }
+    0
+}
+
+/// Runs the `editor` on the alternate screen until the user exits.
+fn run(editor: &mut Editor) -> Result<(), &'static str> {
+    let key_event_queue = app_io::take_key_event_queue()?;
+    let key_event_queue = (*key_event_queue).as_ref()
+                          .ok_or("failed to take key event reader")?;
+
+    editor.terminal.lock().print_to_terminal(ENTER_SCREEN.to_string());
+    let result = editor.event_loop(key_event_queue);
+
+    let mut terminal = editor.terminal.lock();
+    terminal.print_to_terminal(LEAVE_SCREEN.to_string());
+    terminal.refresh_display()?;
+    result
+}
+
+
+/// A question asked on the bottom line, whose answer the user types in.
+enum Prompt {
+    /// Asks for the text to search for.
+    Search,
+    /// Asks for the path to save the file to.
+    SaveAs,
+}
+
+struct Editor {
+    terminal: Arc<Mutex<Terminal>>,
+    /// The directory that relative paths start from.
+    working_dir: DirRef,
+    /// The path of the edited file, which is `None` until a new text is saved.
+    path: Option<String>,
+    buffer: Buffer,
+    cursor: Position,
+    /// The column on the screen that moving the cursor up and down tries to keep,
+    /// even when passing through shorter lines.
+    preferred_column: usize,
+    /// The first line shown on the screen.
+    top_row: usize,
+    /// The first column shown on the screen, when the cursor is beyond the right edge of the screen.
+    left_column: usize,
+    /// Whether the text changed since it was last opened or saved.
+    modified: bool,
+    /// A message shown on the bottom line until the next key is pressed.
+    message: Option<String>,
+    /// The prompt being answered and the answer typed so far.
+    prompt: Option<(Prompt, String)>,
+    /// The text that was searched for last, which an empty search repeats.
+    last_search: String,
+    /// The lines cut with `Ctrl+K`. Consecutive cuts collect their lines together.
+    cut_lines: Vec<Vec<char>>,
+    /// Whether the previous key cut a line.
+    cutting: bool,
+    /// Whether the user was warned that exiting discards the unsaved changes.
+    exit_warned: bool,
+}
+
+impl Editor {
+    fn new(terminal: Arc<Mutex<Terminal>>, working_dir: DirRef) -> Editor {
+        Editor {
+            terminal,
+            working_dir,
+            path: None,
+            buffer: Buffer::from_text(""),
+            cursor: Position::default(),
+            preferred_column: 0,
+            top_row: 0,
+            left_column: 0,
+            modified: false,
+            message: None,
+            prompt: None,
+            last_search: String::new(),
+            cut_lines: Vec::new(),
+            cutting: false,
+            exit_warned: false,
+        }
+    }
+
+    /// Loads the file at `path` into the editor. If there is no such file, it is created once the text is saved.
+    fn open(&mut self, path: &str) -> Result<(), String> {
+        let file = match access_control::lookup(&Path::new(path.to_string()), &self.working_dir) {
+            Ok(FileOrDir::File(file)) => file,
+            Ok(FileOrDir::Dir(_)) => return Err(format!("{}: is a directory", path)),
+            Err(access_control::PERMISSION_DENIED) => return Err(format!("{}: {}", path, access_control::PERMISSION_DENIED)),
+            Err(_) => {
+                self.path = Some(path.to_string());
+                self.message = Some(String::from("New file"));
+                return Ok(());
+            }
+        };
+
+        let mut content = vec![0; file.lock().size()];
+        let count = access_control::read(&file, &mut content, 0).map_err(|e| format!("{}: {}", path, e))?;
+        content.truncate(count);
+        let text = str::from_utf8(&content).map_err(|_| format!("{}: not a UTF-8 text file", path))?;
+
+        self.buffer = Buffer::from_text(text);
+        self.path = Some(path.to_string());
+        self.message = Some(format!("Read {} lines", self.buffer.line_count()));
+        Ok(())
+    }
+
+    /// Writes the text to the file at `path`, creating the file if needed, and reports the outcome on the bottom line.
+    fn save(&mut self, path: String) {
+        let text = self.buffer.to_text();
+        match write_file(&path, &self.working_dir, text.as_bytes()) {
+            Ok(()) => {
+                self.message = Some(format!("Wrote {} lines", self.buffer.line_count()));
+                self.path = Some(path);
+                self.modified = false;
+            }
+            Err(e) => self.message = Some(format!("Couldn't save {}: {}", path, e)),
+        }
+    }
+
+    /// Handles key presses until the user exits.
+    fn event_loop(&mut self, key_event_queue: &KeyEventQueueReader) -> Result<(), &'static str> {
+        self.render()?;
+        loop {
+            let keyevent = match key_event_queue.read_one() {
+                Some(keyevent) => keyevent,
+                None => {
+                    scheduler::schedule();
+                    continue;
+                }
+            };
+            if keyevent.action != KeyAction::Pressed {
+                continue;
+            }
+            if !self.handle_key(keyevent) {
+                return Ok(());
+            }
+            self.render()?;
+        }
+    }
+
+    /// Carries out the command of the pressed key. Returns `false` if the editor should exit.
+    fn handle_key(&mut self, keyevent: KeyEvent) -> bool {
+        if self.prompt.is_some() {
+            self.handle_prompt_key(keyevent);
+            return true;
+        }
+
+        self.message = None;
+        let exit_warned = core::mem::replace(&mut self.exit_warned, false);
+        let cutting = core::mem::replace(&mut self.cutting, false);
+
+        if keyevent.modifiers.is_control() {
+            match keyevent.keycode {
+                Keycode::O | Keycode::S => match self.path.clone() {
+                    Some(path) => self.save(path),
+                    None => self.prompt = Some((Prompt::SaveAs, String::new())),
+                },
+                Keycode::X | Keycode::Q => {
+                    if !self.modified || exit_warned {
+                        return false;
+                    }
+                    self.exit_warned = true;
+                    self.message = Some(String::from("Unsaved changes! Press ^X again to exit without saving, or ^O to save"));
+                }
+                Keycode::W | Keycode::F => self.prompt = Some((Prompt::Search, String::new())),
+                Keycode::K => self.cut_line(cutting),
+                Keycode::U => self.paste_lines(),
+                Keycode::A => self.move_to(Position::new(self.cursor.row, 0)),
+                Keycode::E => self.move_to(Position::new(self.cursor.row, self.buffer.line_len(self.cursor.row))),
+                _ => {}
+            }
+            return true;
+        }
+
+        let page = self.text_rows().saturating_sub(1).max(1);
+        match keyevent.keycode {
+            Keycode::Left => {
+                if self.cursor.col > 0 {
+                    self.move_to(Position::new(self.cursor.row, self.cursor.col - 1));
+                } else if self.cursor.row > 0 {
+                    let row = self.cursor.row - 1;
+                    self.move_to(Position::new(row, self.buffer.line_len(row)));
+                }
+            }
+            Keycode::Right => {
+                if self.cursor.col < self.buffer.line_len(self.cursor.row) {
+                    self.move_to(Position::new(self.cursor.row, self.cursor.col + 1));
+                } else if self.cursor.row + 1 < self.buffer.line_count() {
+                    self.move_to(Position::new(self.cursor.row + 1, 0));
+                }
+            }
+            Keycode::Up => self.move_to_row(self.cursor.row.saturating_sub(1)),
+            Keycode::Down => self.move_to_row(self.cursor.row + 1),
+            Keycode::PageUp => self.move_to_row(self.cursor.row.saturating_sub(page)),
+            Keycode::PageDown => self.move_to_row(self.cursor.row + page),
+            Keycode::Home => self.move_to(Position::new(self.cursor.row, 0)),
+            Keycode::End => self.move_to(Position::new(self.cursor.row, self.buffer.line_len(self.cursor.row))),
+            Keycode::Backspace => {
+                let cursor = self.buffer.remove_char_before(self.cursor);
+                if cursor != self.cursor {
+                    self.modified = true;
+                }
+                self.move_to(cursor);
+            }
+            Keycode::Delete => {
+                if self.cursor != self.end_of_text() {
+                    self.buffer.remove_char_at(self.cursor);
+                    self.modified = true;
+                }
+            }
+            Keycode::Enter => {
+                let cursor = self.buffer.insert_line_break(self.cursor);
+                self.modified = true;
+                self.move_to(cursor);
+            }
+            Keycode::Tab => self.insert_char('\t'),
+            // Other keys that produce control characters, e.g., `Escape`, don't insert anything.
+            keycode => match keycode.to_ascii(keyevent.modifiers) {
+                Some(c) if !c.is_control() => self.insert_char(c),
+                _ => {}
+            },
+        }
+        true
+    }
+
+    /// Edits the answer to the current prompt, and carries out the prompt once the user presses `Enter`.
+    fn handle_prompt_key(&mut self, keyevent: KeyEvent) {
+        let (prompt, mut answer) = match self.prompt.take() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        match keyevent.keycode {
+            Keycode::Escape => self.message = Some(String::from("Cancelled")),
+            Keycode::C if keyevent.modifiers.is_control() => self.message = Some(String::from("Cancelled")),
+            Keycode::Enter => match prompt {
+                Prompt::Search => {
+                    if !answer.is_empty() {
+                        self.last_search = answer;
+                    }
+                    self.search();
+                }
+                Prompt::SaveAs if answer.is_empty() => self.message = Some(String::from("Cancelled")),
+                Prompt::SaveAs => self.save(answer),
+            },
+            Keycode::Backspace => {
+                answer.pop();
+                self.prompt = Some((prompt, answer));
+            }
+            keycode => {
+                match keycode.to_ascii(keyevent.modifiers) {
+                    Some(c) if !c.is_control() && !keyevent.modifiers.is_control() => answer.push(c),
+                    _ => {}
+                }
+                self.prompt = Some((prompt, answer));
+            }
+        }
+    }
+
+    /// Moves the cursor to the next occurrence of the last searched text.
+    fn search(&mut self) {
+        if self.last_search.is_empty() {
+            self.message = Some(String::from("Nothing to search for"));
+            return;
+        }
+        let query: Vec<char> = self.last_search.chars().collect();
+        match self.buffer.find(&query, self.cursor) {
+            Some((position, wrapped)) => {
+                if wrapped {
+                    self.message = Some(String::from("Search wrapped"));
+                }
+                self.move_to(position);
+            }
+            None => self.message = Some(format!("\"{}\" not found", self.last_search)),
+        }
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let cursor = self.buffer.insert_char(self.cursor, c);
+        self.modified = true;
+        self.move_to(cursor);
+    }
+
+    /// Cuts the line of the cursor, adding it to the lines cut before if the previous key also cut a line.
+    fn cut_line(&mut self, append: bool) {
+        if !append {
+            self.cut_lines.clear();
+        }
+        let line = self.buffer.remove_line(self.cursor.row);
+        self.cut_lines.push(line);
+        self.cutting = true;
+        self.modified = true;
+        let cursor = self.buffer.clamp(Position::new(self.cursor.row, 0));
+        self.move_to(cursor);
+    }
+
+    /// Inserts the cut lines before the line of the cursor.
+    fn paste_lines(&mut self) {
+        if self.cut_lines.is_empty() {
+            self.message = Some(String::from("Nothing to paste"));
+            return;
+        }
+        let row = self.cursor.row;
+        for (i, line) in self.cut_lines.iter().enumerate() {
+            self.buffer.insert_line(row + i, line.clone());
+        }
+        self.modified = true;
+        self.move_to(Position::new(row + self.cut_lines.len(), 0));
+    }
+
+    /// Moves the cursor to the given position, which becomes the column that vertical movements keep.
+    fn move_to(&mut self, position: Position) {
+        self.cursor = self.buffer.clamp(position);
+        self.preferred_column = display_width(&self.buffer.line(self.cursor.row)[..self.cursor.col]);
+    }
+
+    /// Moves the cursor to the given line, as close to the preferred column as that line allows.
+    fn move_to_row(&mut self, row: usize) {
+        let row = core::cmp::min(row, self.buffer.line_count() - 1);
+        let col = column_at(self.buffer.line(row), self.preferred_column);
+        self.cursor = Position::new(row, col);
+    }
+
+    fn end_of_text(&self) -> Position {
+        let row = self.buffer.line_count() - 1;
+        Position::new(row, self.buffer.line_len(row))
+    }
+
+    /// Returns the number of lines of text that fit on the screen above the status bar and the bottom line.
+    fn text_rows(&self) -> usize {
+        let (_width, height) = self.terminal.lock().get_text_dimensions();
+        height.saturating_sub(2).max(1)
+    }
+
+    /// Scrolls the view so that the cursor is on the screen.
+    fn scroll_to_cursor(&mut self, width: usize, rows: usize) {
+        if self.cursor.row < self.top_row {
+            self.top_row = self.cursor.row;
+        } else if self.cursor.row >= self.top_row + rows {
+            self.top_row = self.cursor.row + 1 - rows;
+        }
+        let column = display_width(&self.buffer.line(self.cursor.row)[..self.cursor.col]);
+        if column < self.left_column {
+            self.left_column = column;
+        } else if column >= self.left_column + width {
+            self.left_column = column + 1 - width;
+        }
+    }
+
+    /// Redraws the whole screen and places the cursor.
+    fn render(&mut self) -> Result<(), &'static str> {
+        let terminal = Arc::clone(&self.terminal);
+        let mut terminal = terminal.lock();
+        let (width, height) = terminal.get_text_dimensions();
+        let rows = height.saturating_sub(2).max(1);
+        self.scroll_to_cursor(width, rows);
+
+        let mut frame = String::new();
+        for screen_row in 0..rows {
+            frame.push_str(&format!("\x1b[{};1H", screen_row + 1));
+            let row = self.top_row + screen_row;
+            if row < self.buffer.line_count() {
+                frame.extend(expand_tabs(self.buffer.line(row)).into_iter().skip(self.left_column).take(width));
+            }
+            frame.push_str("\x1b[K");
+        }
+
+        // The status bar is shown in inverse video.
+        let name = self.path.as_deref().unwrap_or("[New File]");
+        let left = format!(" {}{}", name, if self.modified { " [Modified]" } else { "" });
+        let right = format!("Ln {}, Col {} ", self.cursor.row + 1, self.cursor.col + 1);
+        let padding = width.saturating_sub(left.chars().count() + right.chars().count()).max(1);
+        let status: String = format!("{}{:padding$}{}", left, "", right, padding = padding).chars().take(width).collect();
+        frame.push_str(&format!("\x1b[{};1H\x1b[7m{}\x1b[0m\x1b[K", rows + 1, status));
+
+        // The bottom line shows the prompt, a message, or the help. It stops short of the last column
+        // so that the terminal doesn't scroll when the line is full.
+        let bottom = match self.prompt {
+            Some((Prompt::Search, ref answer)) if self.last_search.is_empty() => format!("Search: {}", answer),
+            Some((Prompt::Search, ref answer)) => format!("Search [{}]: {}", self.last_search, answer),
+            Some((Prompt::SaveAs, ref answer)) => format!("File Name to Write: {}", answer),
+            None => self.message.clone().unwrap_or_else(|| HELP.to_string()),
+        };
+        let bottom: String = bottom.chars().filter(|c| !c.is_control()).take(width.saturating_sub(1)).collect();
+        frame.push_str(&format!("\x1b[{};1H{}\x1b[K", rows + 2, bottom));
+
+        // The cursor is at the end of the prompt's answer, or otherwise in the text.
+        if self.prompt.is_some() {
+            frame.push_str(&format!("\x1b[{};{}H", rows + 2, bottom.chars().count() + 1));
+        } else {
+            let column = display_width(&self.buffer.line(self.cursor.row)[..self.cursor.col]);
+            frame.push_str(&format!("\x1b[{};{}H", self.cursor.row - self.top_row + 1, column - self.left_column + 1));
+        }
+
+        terminal.print_to_terminal(frame);
+        terminal.refresh_display()?;
+        terminal.update_cursor_pos(0, 0);
+        terminal.display_cursor()
+    }
+}
+
+
+/// Returns the characters that show the given `line` on the screen, with tabs expanded to spaces
+/// and other control characters, which the terminal would carry out, replaced with `?`.
+fn expand_tabs(line: &[char]) -> Vec<char> {
+    let mut expanded = Vec::with_capacity(line.len());
+    for &c in line {
+        match c {
+            '\t' => {
+                let spaces = TAB_WIDTH - expanded.len() % TAB_WIDTH;
+                expanded.extend(core::iter::repeat(' ').take(spaces));
+            }
+            c if c.is_control() => expanded.push('?'),
+            c => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+/// Returns the number of columns that the given characters take up on the screen.
+fn display_width(chars: &[char]) -> usize {
+    chars.iter().fold(0, |width, &c| next_column(width, c))
+}
+
+/// Returns the index of the character of `line` that is shown at the given screen `column`,
+/// or the end of the line if it is shorter.
+fn column_at(line: &[char], column: usize) -> usize {
+    let mut width = 0;
+    for (i, &c) in line.iter().enumerate() {
+        let next = next_column(width, c);
+        if next > column {
+            return i;
+        }
+        width = next;
+    }
+    line.len()
+}
+
+/// Returns the screen column after the character `c` that is shown at `column`.
+fn next_column(column: usize, c: char) -> usize {
+    if c == '\t' {
+        column + TAB_WIDTH - column % TAB_WIDTH
+    } else {
+        column + 1
+    }
+}
+
+
+/// Writes `content` to the file at `path`, relative to the `working_dir`, replacing its previous content.
+/// The file is created if it doesn't exist.
+fn write_file(path: &str, working_dir: &DirRef, content: &[u8]) -> Result<(), String> {
+    let file = access_control::open_or_create_file(path, working_dir)?;
+    access_control::write(&file, content, 0)?;
+    if file.lock().size() > content.len() {
+        file.lock().truncate(content.len())?;
+    }
+    Ok(())
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: edit [FILE]\n\n");
+
+    brief.push_str("Edits the text file FILE, which is created when it is first saved if it doesn't exist.\n\n");
+    brief.push_str("Commands: ^O save, ^X exit, ^W search (an empty search repeats the last one),\n");
+    brief.push_str("^K cut the current line, ^U paste the cut lines, ^A and ^E go to the start and end of the line.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,236 @@
+//! Searches directory trees for files and directories by their name, size and type.
+//!
+//! Like other implementations of `find`, it takes the paths to search first, followed by the predicates,
+//! all of which must hold for a file or directory to be printed, e.g., `find /tmp -name "*.txt" -size +1k`.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate task;
+extern crate path;
+extern crate fs_node;
+extern crate access_control;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use path::Path;
+use fs_node::{FileOrDir, FsNode};
+use access_control::Access;
+
+
+/// A condition that a file or directory must meet to be printed.
+enum Predicate {
+    /// `-name PATTERN`: the name matches the shell pattern, which may contain `*`, `?` and `[...]`.
+    Name(Vec<char>),
+    /// `-size [+|-]N[c|k|M]`: the size of a file, rounded up to the unit, is more than, less than, or exactly `N`.
+    Size { comparison: Comparison, size: usize, unit: usize },
+    /// `-type f` or `-type d`: the node is a file or a directory, respectively.
+    Type { directory: bool },
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Less,
+    Equal,
+    Greater,
+}
+
+impl Predicate {
+    fn matches(&self, node: &FileOrDir) -> bool {
+        match *self {
+            Predicate::Name(ref pattern) => {
+                let name: Vec<char> = node.get_name().chars().collect();
+                glob_match(pattern, &name)
+            }
+            Predicate::Size { comparison, size, unit } => match *node {
+                FileOrDir::File(ref file) => {
+                    let file_size = (file.lock().size() + unit - 1) / unit;
+                    match comparison {
+                        Comparison::Less => file_size < size,
+                        Comparison::Equal => file_size == size,
+                        Comparison::Greater => file_size > size,
+                    }
+                }
+                FileOrDir::Dir(_) => false,
+            },
+            Predicate::Type { directory } => match *node {
+                FileOrDir::File(_) => !directory,
+                FileOrDir::Dir(_) => directory,
+            },
+        }
+    }
+}
+
+
+pub fn main(args: Vec<String>) -> isize {
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print_usage();
+        return 0;
+    }
+
+    // The paths to search come before the first predicate.
+    let path_count = args.iter().position(|arg| arg.starts_with('-') && arg.len() > 1).unwrap_or(args.len());
+    let (paths, predicate_args) = args.split_at(path_count);
+    let predicates = match parse_predicates(predicate_args) {
+        Ok(predicates) => predicates,
+        Err(e) => {
+            println!("Error: {}", e);
+            return -1;
+        }
+    };
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let default_path = [".".to_string()];
+    let paths = if paths.is_empty() { &default_path[..] } else { paths };
+
+    let mut ret = 0;
+    for path in paths {
+        match access_control::lookup(&Path::new(path.clone()), &curr_wd) {
+            Ok(node) => {
+                if !walk(path, &node, &predicates) {
+                    ret = -1;
+                }
+            }
+            Err(e) => {
+                println!("find: {}: {}", path, e);
+                ret = -1;
+            }
+        }
+    }
+    ret
+}
+
+fn parse_predicates(args: &[String]) -> Result<Vec<Predicate>, String> {
+    let mut predicates = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format!("missing argument to {}", arg))?;
+        let predicate = match arg.as_str() {
+            "-name" => Predicate::Name(value.chars().collect()),
+            "-size" => parse_size(value).ok_or_else(|| format!("invalid size {:?}", value))?,
+            "-type" => match value.as_str() {
+                "f" => Predicate::Type { directory: false },
+                "d" => Predicate::Type { directory: true },
+                _ => return Err(format!("unknown type {:?}, expected f or d", value)),
+            },
+            _ => return Err(format!("unknown predicate {:?}", arg)),
+        };
+        predicates.push(predicate);
+    }
+    Ok(predicates)
+}
+
+/// Parses the argument of `-size`, e.g., `+10k`. Sizes without a unit are given in bytes.
+fn parse_size(value: &str) -> Option<Predicate> {
+    let (comparison, rest) = if let Some(rest) = value.strip_prefix('+') {
+        (Comparison::Greater, rest)
+    } else if let Some(rest) = value.strip_prefix('-') {
+        (Comparison::Less, rest)
+    } else {
+        (Comparison::Equal, value)
+    };
+    let (number, unit) = match rest.chars().last() {
+        Some('c') => (&rest[..rest.len() - 1], 1),
+        Some('k') => (&rest[..rest.len() - 1], 1024),
+        Some('M') => (&rest[..rest.len() - 1], 1024 * 1024),
+        _ => (rest, 1),
+    };
+    let size = number.parse().ok()?;
+    Some(Predicate::Size { comparison, size, unit })
+}
+
+/// Prints `path` if its `node` meets all `predicates`, and then searches the contents of the node if it is a directory.
+/// Returns `false` if any directory in the tree couldn't be searched.
+fn walk(path: &str, node: &FileOrDir, predicates: &[Predicate]) -> bool {
+    if predicates.iter().all(|predicate| predicate.matches(node)) {
+        println!("{}", path);
+    }
+
+    let dir = match *node {
+        FileOrDir::Dir(ref dir) => dir,
+        FileOrDir::File(_) => return true,
+    };
+    if let Err(e) = access_control::check(node, Access::Read) {
+        println!("find: {}: {}", path, e);
+        return false;
+    }
+    let mut names = dir.lock().list();
+    names.sort();
+
+    let mut ok = true;
+    for name in names {
+        let child = match dir.lock().get(&name) {
+            Some(child) => child,
+            // The child was removed after the directory was listed.
+            None => continue,
+        };
+        let child_path = if path.ends_with('/') {
+            format!("{}{}", path, name)
+        } else {
+            format!("{}/{}", path, name)
+        };
+        ok &= walk(&child_path, &child, predicates);
+    }
+    ok
+}
+
+/// Returns whether the `name` matches the shell `pattern`.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&'*', rest)) => (0 ..= name.len()).any(|skipped| glob_match(rest, &name[skipped..])),
+        Some((&'?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((&'[', rest)) if rest.iter().skip(1).any(|&c| c == ']') => {
+            // The first character of the set may be `]` itself.
+            let end = 1 + rest.iter().skip(1).position(|&c| c == ']').unwrap_or(0);
+            let (negated, set) = match rest[..end].split_first() {
+                Some((&'!', set)) | Some((&'^', set)) if !set.is_empty() => (true, set),
+                _ => (false, &rest[..end]),
+            };
+            match name.split_first() {
+                Some((&c, name_rest)) => class_contains(set, c) != negated && glob_match(&rest[end + 1..], name_rest),
+                None => false,
+            }
+        }
+        Some((&c, rest)) => name.first() == Some(&c) && glob_match(rest, &name[1..]),
+    }
+}
+
+/// Returns whether the set of a bracket expression, such as `a-z_`, contains `c`.
+fn class_contains(set: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            if set[i] <= c && c <= set[i + 2] {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+
+fn print_usage() {
+    println!("Usage: find [PATH...] [PREDICATE...]
+
+Prints the paths of all files and directories in the tree under each PATH (by default, the current directory)
+that meet all of the given predicates:
+  -name PATTERN       the name matches PATTERN, which may contain * ? and [...]
+  -size [+|-]N[c|k|M] the size, rounded up to bytes (c, the default), KiB (k) or MiB (M), is more than (+), less than (-) or exactly N
+  -type f|d           it is a file (f) or a directory (d)
+");
+}
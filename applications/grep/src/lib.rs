@@ -0,0 +1,137 @@
+//! Prints the lines of files, or of its standard input, that match a regular expression.
+//!
+//! The supported syntax of regular expressions is described in the [`regex`](regex/index.html) module.
+//! Reading from the standard input lets `grep` filter the output of other applications in a pipeline.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate app_input;
+
+mod regex;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use getopts::Options;
+use app_input::Input;
+use regex::Regex;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("i", "ignore-case", "ignore the case of letters in the pattern and the input");
+    opts.optflag("v", "invert-match", "print the lines that don't match");
+    opts.optflag("n", "line-number", "print the line number before each line");
+    opts.optflag("c", "count", "only print the number of selected lines");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return print_usage(opts);
+    }
+
+    let regex = match Regex::new(&matches.free[0], matches.opt_present("i")) {
+        Ok(regex) => regex,
+        Err(e) => {
+            println!("Error: invalid pattern {:?}: {}", matches.free[0], e);
+            return -1;
+        }
+    };
+    let grep = Grep {
+        regex,
+        invert: matches.opt_present("v"),
+        line_numbers: matches.opt_present("n"),
+        count_only: matches.opt_present("c"),
+    };
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let mut paths: Vec<&str> = matches.free[1..].iter().map(|path| path.as_str()).collect();
+    if paths.is_empty() {
+        paths.push("-");
+    }
+    // The file names are only printed if there are several files to tell apart.
+    let show_names = paths.len() > 1;
+
+    let mut found = false;
+    let mut failed = false;
+    for path in paths {
+        let result = Input::open(path, &curr_wd)
+            .and_then(|mut input| grep.search(&mut input, if show_names { Some(path) } else { None }).map_err(String::from));
+        match result {
+            Ok(selected) => found |= selected > 0,
+            Err(e) => {
+                println!("grep: {}", e);
+                failed = true;
+            }
+        }
+    }
+
+    // Like other implementations, `grep` reports whether any line was selected in its exit value.
+    if failed { -1 } else if found { 0 } else { 1 }
+}
+
+
+/// The pattern to search for and how to print the selected lines.
+struct Grep {
+    regex: Regex,
+    invert: bool,
+    line_numbers: bool,
+    count_only: bool,
+}
+
+impl Grep {
+    /// Prints the selected lines of `input`, prefixed with `name` if given,
+    /// and returns the number of selected lines.
+    fn search(&self, input: &mut Input, name: Option<&str>) -> Result<usize, &'static str> {
+        let prefix = name.map(|name| format!("{}:", name)).unwrap_or_default();
+        let mut selected = 0;
+        let mut line_number = 0;
+        input.for_each_line(|line| {
+            line_number += 1;
+            if self.regex.is_match(line) != self.invert {
+                selected += 1;
+                if !self.count_only {
+                    if self.line_numbers {
+                        println!("{}{}:{}", prefix, line_number, line);
+                    } else {
+                        println!("{}{}", prefix, line);
+                    }
+                }
+            }
+            true
+        })?;
+        if self.count_only {
+            println!("{}{}", prefix, selected);
+        }
+        Ok(selected)
+    }
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: grep [OPTIONS] PATTERN [FILE...]\n\n");
+
+    brief.push_str("Prints the lines of each FILE that match the regular expression PATTERN.\n");
+    brief.push_str("If no FILE is given, or FILE is -, it reads the standard input.\n\n");
+    brief.push_str("PATTERN may contain . [abc] [a-z] [^abc] * + ? ^ $, and \\ escapes a special character.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
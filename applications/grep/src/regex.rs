@@ -0,0 +1,179 @@
+//! A small regular expression engine that supports a basic syntax:
+//!
+//! * `c` matches the character `c`, and `\c` matches `c` even if it is one of the special characters below,
+//! * `.` matches any character,
+//! * `[abc]`, `[a-z]` and `[^abc]` match any character that is, or isn't, in the given set,
+//! * `*`, `+` and `?` match the preceding item zero or more times, one or more times, and at most once,
+//! * `^` at the start and `$` at the end of the pattern anchor it to the start and end of the line.
+//!
+//! Repetitions are greedy, and matching backtracks when the rest of the pattern doesn't match.
+
+use alloc::vec::Vec;
+
+
+/// A compiled regular expression.
+pub struct Regex {
+    pieces: Vec<Piece>,
+    anchored_start: bool,
+    anchored_end: bool,
+    ignore_case: bool,
+}
+
+/// An item of a pattern along with how often it may be repeated.
+struct Piece {
+    atom: Atom,
+    repeat: Repeat,
+}
+
+/// An item of a pattern that matches a single character.
+enum Atom {
+    Char(char),
+    Any,
+    /// A bracket expression, given as inclusive ranges of characters.
+    Class { ranges: Vec<(char, char)>, negated: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    Once,
+    ZeroOrMore,
+    OneOrMore,
+    ZeroOrOne,
+}
+
+impl Atom {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        match *self {
+            Atom::Any => true,
+            Atom::Char(expected) => expected == c || (ignore_case && expected.eq_ignore_ascii_case(&c)),
+            Atom::Class { ref ranges, negated } => {
+                let in_class = |c: char| ranges.iter().any(|&(low, high)| low <= c && c <= high);
+                let found = in_class(c)
+                    || (ignore_case && (in_class(c.to_ascii_lowercase()) || in_class(c.to_ascii_uppercase())));
+                found != negated
+            }
+        }
+    }
+}
+
+impl Regex {
+    /// Compiles the given `pattern`. If `ignore_case` is true, letters match regardless of their case.
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Regex, &'static str> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let anchored_start = chars.first() == Some(&'^');
+        let mut i = if anchored_start { 1 } else { 0 };
+        let mut anchored_end = false;
+        let mut pieces: Vec<Piece> = Vec::new();
+
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            let atom = match c {
+                '$' if i == chars.len() => {
+                    anchored_end = true;
+                    break;
+                }
+                '*' | '+' | '?' => {
+                    let repeat = match c {
+                        '*' => Repeat::ZeroOrMore,
+                        '+' => Repeat::OneOrMore,
+                        _ => Repeat::ZeroOrOne,
+                    };
+                    match pieces.last_mut() {
+                        Some(piece) if piece.repeat == Repeat::Once => {
+                            piece.repeat = repeat;
+                            continue;
+                        }
+                        // A repetition with nothing to repeat stands for itself.
+                        _ => Atom::Char(c),
+                    }
+                }
+                '.' => Atom::Any,
+                '\\' => {
+                    let escaped = *chars.get(i).ok_or("trailing backslash")?;
+                    i += 1;
+                    Atom::Char(escaped)
+                }
+                '[' => {
+                    let (atom, end) = parse_class(&chars, i)?;
+                    i = end;
+                    atom
+                }
+                c => Atom::Char(c),
+            };
+            pieces.push(Piece { atom, repeat: Repeat::Once });
+        }
+
+        Ok(Regex { pieces, anchored_start, anchored_end, ignore_case })
+    }
+
+    /// Returns whether this regular expression matches any part of the given `line`.
+    pub fn is_match(&self, line: &str) -> bool {
+        let text: Vec<char> = line.chars().collect();
+        if self.anchored_start {
+            self.match_here(&self.pieces, &text)
+        } else {
+            (0 ..= text.len()).any(|start| self.match_here(&self.pieces, &text[start..]))
+        }
+    }
+
+    /// Returns whether the given `pieces` match at the start of `text`.
+    fn match_here(&self, pieces: &[Piece], text: &[char]) -> bool {
+        let (piece, rest) = match pieces.split_first() {
+            Some(split) => split,
+            None => return !self.anchored_end || text.is_empty(),
+        };
+        let (min, max) = match piece.repeat {
+            Repeat::Once => (1, 1),
+            Repeat::ZeroOrMore => (0, usize::MAX),
+            Repeat::OneOrMore => (1, usize::MAX),
+            Repeat::ZeroOrOne => (0, 1),
+        };
+        // Try the longest run of matching characters first, then backtrack.
+        let available = text.iter()
+            .take(max)
+            .take_while(|&&c| piece.atom.matches(c, self.ignore_case))
+            .count();
+        if available < min {
+            return false;
+        }
+        (min ..= available).rev().any(|count| self.match_here(rest, &text[count..]))
+    }
+}
+
+/// Parses a bracket expression that starts at index `start` of `chars`, right after its `[`.
+/// Returns the expression and the index right after its closing `]`.
+fn parse_class(chars: &[char], start: usize) -> Result<(Atom, usize), &'static str> {
+    let mut i = start;
+    let negated = chars.get(i) == Some(&'^');
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut low = *chars.get(i).ok_or("unterminated bracket expression")?;
+        i += 1;
+        // A `]` right after the opening `[` (or `[^`) is part of the set.
+        if low == ']' && !first {
+            break;
+        }
+        first = false;
+        if low == '\\' {
+            low = *chars.get(i).ok_or("unterminated bracket expression")?;
+            i += 1;
+        }
+        let high = match (chars.get(i), chars.get(i + 1)) {
+            (Some(&'-'), Some(&high)) if high != ']' => {
+                i += 2;
+                high
+            }
+            _ => low,
+        };
+        if high < low {
+            return Err("invalid range in bracket expression");
+        }
+        ranges.push((low, high));
+    }
+    Ok((Atom::Class { ranges, negated }, i))
+}
@@ -0,0 +1,104 @@
+//! Prints the first lines of files, or of its standard input.
+//!
+//! When reading from a pipeline, `head` exits as soon as it has printed enough lines,
+//! which in turn stops the application that writes into the pipeline.
+
+#![no_std]
+extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate app_input;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use getopts::Options;
+use app_input::Input;
+
+
+/// The number of lines that are printed by default.
+const DEFAULT_LINES: usize = 10;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("n", "lines", "print the first NUM lines instead of the first 10", "NUM");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let lines = match matches.opt_str("n").map(|n| n.parse::<usize>()) {
+        Some(Ok(lines)) => lines,
+        Some(Err(_)) => {
+            println!("Error: invalid number of lines");
+            return -1;
+        }
+        None => DEFAULT_LINES,
+    };
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let mut paths: Vec<&str> = matches.free.iter().map(|path| path.as_str()).collect();
+    if paths.is_empty() {
+        paths.push("-");
+    }
+    // Each file is preceded by a header with its name if there are several files.
+    let show_names = paths.len() > 1;
+
+    let mut ret = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if show_names {
+            if i > 0 {
+                println!("");
+            }
+            println!("==> {} <==", if *path == "-" { "standard input" } else { path });
+        }
+        let result = Input::open(path, &curr_wd).and_then(|mut input| print_lines(&mut input, lines).map_err(String::from));
+        if let Err(e) = result {
+            println!("head: {}", e);
+            ret = -1;
+        }
+    }
+    ret
+}
+
+/// Prints the first `lines` lines of `input`.
+fn print_lines(input: &mut Input, lines: usize) -> Result<(), &'static str> {
+    if lines == 0 {
+        return Ok(());
+    }
+    let mut printed = 0;
+    input.for_each_line(|line| {
+        println!("{}", line);
+        printed += 1;
+        printed < lines
+    })
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: head [OPTIONS] [FILE...]\n\n");
+
+    brief.push_str("Prints the first 10 lines of each FILE.\n");
+    brief.push_str("If no FILE is given, or FILE is -, it reads the standard input.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,69 @@
+//! Prints statistics about each size class of the per-core heaps,
+//! as reported by [`heap::stats()`](../heap/fn.stats.html).
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate heap;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("a", "all", "also print size classes that have never been used");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+    let print_all = matches.opt_present("a");
+
+    // gather the statistics before printing, since printing also allocates from the heap
+    let all_stats = heap::stats();
+    if all_stats.is_empty() {
+        println!("No heap statistics are available.");
+        return -1;
+    }
+
+    for heap_stats in all_stats.iter() {
+        println!("\nHeap {}:", heap_stats.heap_id);
+        println!("{:>8} {:>10} {:>12} {:>7} {:>8} {:>6}", "SIZE", "LIVE", "TOTAL", "EMPTY", "PARTIAL", "FULL");
+        for sc in heap_stats.size_classes.iter() {
+            if !print_all && sc.total_allocations == 0 {
+                continue;
+            }
+            println!("{:>8} {:>10} {:>12} {:>7} {:>8} {:>6}", 
+                sc.object_size, 
+                sc.live_objects, 
+                sc.total_allocations, 
+                sc.empty_pages, 
+                sc.partial_pages, 
+                sc.full_pages,
+            );
+        }
+    }
+
+    0
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: heap_stats [OPTIONS]\n\n");
+
+    brief.push_str("For each per-core heap, prints the object size, number of live objects, total number of allocations, and the number of empty, partially-used, and full pages of each size class.");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
@@ -0,0 +1,219 @@
+//! Prints the contents of files, or of its standard input, in hexadecimal and ASCII.
+//!
+//! The output uses the canonical format of other `hexdump` implementations: each line starts with the offset
+//! of its first byte, followed by 16 bytes in hexadecimal and the same bytes as printable ASCII characters.
+//! Lines that repeat the previous line are replaced by a single `*`.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate app_input;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+use getopts::Options;
+use app_input::Input;
+
+
+/// The number of bytes read from the input at once.
+const BUFFER_SIZE: usize = 256;
+/// The number of bytes shown on each line.
+const BYTES_PER_LINE: usize = 16;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("s", "skip", "skip OFFSET bytes from the beginning of the input", "OFFSET");
+    opts.optopt("n", "length", "only print LENGTH bytes of the input", "LENGTH");
+    opts.optflag("v", "no-squeezing", "print all lines, including those that repeat the previous line");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let skip = match matches.opt_str("s").map(|s| parse_number(&s)) {
+        Some(Some(skip)) => skip,
+        Some(None) => {
+            println!("Error: invalid offset");
+            return -1;
+        }
+        None => 0,
+    };
+    let length = match matches.opt_str("n").map(|n| parse_number(&n)) {
+        Some(Some(length)) => Some(length),
+        Some(None) => {
+            println!("Error: invalid length");
+            return -1;
+        }
+        None => None,
+    };
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    let mut paths: Vec<&str> = matches.free.iter().map(|path| path.as_str()).collect();
+    if paths.is_empty() {
+        paths.push("-");
+    }
+
+    // All inputs are dumped as if they were a single one.
+    let mut dumper = Dumper::new(skip, !matches.opt_present("v"));
+    let mut to_skip = skip;
+    let mut remaining = length;
+    let mut ret = 0;
+    let mut buffer = [0u8; BUFFER_SIZE];
+    for path in paths {
+        if remaining == Some(0) {
+            break;
+        }
+        let mut input = match Input::open(path, &curr_wd) {
+            Ok(input) => input,
+            Err(e) => {
+                println!("hexdump: {}", e);
+                ret = -1;
+                continue;
+            }
+        };
+        loop {
+            let count = match input.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(e) => {
+                    println!("hexdump: {}: {}", path, e);
+                    ret = -1;
+                    break;
+                }
+            };
+            let mut bytes = &buffer[..count];
+            let skipped = core::cmp::min(to_skip, bytes.len());
+            to_skip -= skipped;
+            bytes = &bytes[skipped..];
+            if let Some(ref mut remaining) = remaining {
+                bytes = &bytes[..core::cmp::min(*remaining, bytes.len())];
+                *remaining -= bytes.len();
+            }
+            dumper.push(bytes);
+            if remaining == Some(0) {
+                break;
+            }
+        }
+    }
+    dumper.finish();
+    ret
+}
+
+/// Parses a decimal number, or a hexadecimal number that starts with `0x`.
+fn parse_number(s: &str) -> Option<usize> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+
+/// Formats bytes into lines of the canonical hexdump format.
+struct Dumper {
+    /// The offset of the first byte in `line`.
+    offset: usize,
+    /// The bytes of the line that is being filled.
+    line: Vec<u8>,
+    /// The bytes of the last complete line.
+    previous: Vec<u8>,
+    /// Whether lines that repeat the previous line are replaced by `*`.
+    squeeze: bool,
+    /// Whether the previous line was replaced by `*`.
+    squeezing: bool,
+}
+
+impl Dumper {
+    /// Creates a dumper whose first byte is at the given `offset` of the input.
+    fn new(offset: usize, squeeze: bool) -> Dumper {
+        Dumper {
+            offset,
+            line: Vec::with_capacity(BYTES_PER_LINE),
+            previous: Vec::new(),
+            squeeze,
+            squeezing: false,
+        }
+    }
+
+    /// Adds the given bytes, printing each line as soon as it is complete.
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.line.push(byte);
+            if self.line.len() == BYTES_PER_LINE {
+                self.print_line();
+            }
+        }
+    }
+
+    /// Prints the last, possibly incomplete, line and the offset of the end of the input.
+    fn finish(mut self) {
+        self.print_line();
+        if self.offset > 0 {
+            println!("{:08x}", self.offset);
+        }
+    }
+
+    fn print_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        if self.squeeze && self.line.len() == BYTES_PER_LINE && self.line == self.previous {
+            if !self.squeezing {
+                println!("*");
+                self.squeezing = true;
+            }
+        } else {
+            self.squeezing = false;
+            let mut text = format!("{:08x}  ", self.offset);
+            for i in 0..BYTES_PER_LINE {
+                match self.line.get(i) {
+                    Some(byte) => text.push_str(&format!("{:02x} ", byte)),
+                    None => text.push_str("   "),
+                }
+                // The two halves of the line are separated by an extra space.
+                if i == BYTES_PER_LINE / 2 - 1 {
+                    text.push(' ');
+                }
+            }
+            text.push_str(" |");
+            text.extend(self.line.iter().map(|&byte| if byte == b' ' || byte.is_ascii_graphic() { byte as char } else { '.' }));
+            text.push('|');
+            println!("{}", text);
+        }
+        self.offset += self.line.len();
+        self.previous = mem::replace(&mut self.line, Vec::with_capacity(BYTES_PER_LINE));
+    }
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: hexdump [OPTIONS] [FILE...]\n\n");
+
+    brief.push_str("Prints the contents of the given FILEs, one after another, in hexadecimal and ASCII.\n");
+    brief.push_str("If no FILE is given, or FILE is -, it reads the standard input.\n");
+    brief.push_str("OFFSET and LENGTH are given in decimal, or in hexadecimal with a leading 0x.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,103 @@
+//! A demo HTTP server that serves the files of a directory, by default the current working directory.
+//!
+//! Besides the files, the server answers `GET /_theseus/status` with a short status message,
+//! to show how other handlers can be added to the router.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate path;
+extern crate fs_node;
+extern crate access_control;
+extern crate http;
+
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use getopts::{Matches, Options};
+use path::Path;
+use fs_node::{DirRef, FileOrDir};
+use http::{Method, Response, Router, Server};
+
+
+/// The port that the server listens on by default.
+const DEFAULT_PORT: u16 = 8080;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("p", "port", "listen on port N (default: 8080)", "N");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(&opts);
+    }
+
+    match rmain(&matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("httpd: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain(matches: &Matches) -> Result<(), String> {
+    let port = match matches.opt_str("p") {
+        Some(p) => p.parse::<u16>().map_err(|_e| "couldn't parse port")?,
+        None => DEFAULT_PORT,
+    };
+    let dir = served_dir(matches.free.first())?;
+    let dir_path = dir.lock().get_absolute_path();
+
+    let mut router = Router::new();
+    let status_dir_path = dir_path.clone();
+    router.route(Method::Get, "/_theseus/status", move |_request| {
+        Response::text(200, &format!("Theseus httpd serving {} on port {}\n", status_dir_path, port))
+    });
+    router.serve_dir("/", dir);
+
+    let server = Server::bind(port)?;
+    println!("Serving {} on port {}", dir_path, port);
+    let result = server.serve(&router);
+    server.close();
+    result.map_err(|e| e.to_string())
+}
+
+/// Returns the directory at the given path relative to the current working directory, or the working directory itself.
+fn served_dir(path: Option<&String>) -> Result<DirRef, String> {
+    let curr_wd = task::get_my_current_task()
+        .map(|t| Arc::clone(&t.lock().env.lock().working_dir))
+        .ok_or_else(|| String::from("failed to get current task"))?;
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(curr_wd),
+    };
+    match access_control::lookup(&Path::new(path.to_string()), &curr_wd) {
+        Ok(FileOrDir::Dir(dir)) => Ok(dir),
+        Ok(FileOrDir::File(_)) => Err(format!("{:?} is not a directory", path)),
+        Err(e) => Err(format!("{:?}: {}", path, e)),
+    }
+}
+
+fn print_usage(opts: &Options) -> isize {
+    let mut brief = format!("Usage: httpd [OPTION]... [DIRECTORY] \n \n");
+
+    brief.push_str("serves the files of a directory (default: the current directory) over HTTP");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
@@ -0,0 +1,113 @@
+//! Lists network interfaces, brings them up or down, and assigns their addresses and routes.
+//!
+//! * `ifconfig` lists every interface, and `ifconfig IFACE` lists one.
+//! * `ifconfig IFACE up` and `ifconfig IFACE down` bring an interface up or down.
+//! * `ifconfig IFACE add ADDRESS/PREFIX` and `ifconfig IFACE del ADDRESS/PREFIX` assign or remove an address.
+//! * `ifconfig IFACE gateway ROUTER` sets the default gateway, and `ifconfig IFACE gateway none` removes it.
+//! * `ifconfig IFACE route add DESTINATION/PREFIX ROUTER` and `ifconfig IFACE route del DESTINATION/PREFIX`
+//!   add or remove a route.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate smoltcp;
+extern crate net_config;
+
+use core::str::FromStr;
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+use smoltcp::wire::{IpAddress, IpCidr};
+use net_config::InterfaceInfo;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let free: Vec<&str> = matches.free.iter().map(|arg| arg.as_str()).collect();
+    let result = match free.as_slice() {
+        [] => {
+            let interfaces = net_config::interfaces();
+            if interfaces.is_empty() {
+                println!("no network interfaces");
+            }
+            for info in interfaces.iter() {
+                print_interface(info);
+            }
+            Ok(())
+        }
+        [name] => net_config::interface(name).map(|info| print_interface(&info)),
+        [name, "up"] => net_config::set_up(name, true),
+        [name, "down"] => net_config::set_up(name, false),
+        [name, "add", address] => parse_cidr(address).and_then(|address| net_config::add_address(name, address)),
+        [name, "del", address] => parse_cidr(address).and_then(|address| net_config::remove_address(name, address)),
+        [name, "gateway", "none"] => net_config::remove_default_gateway(name, false),
+        [name, "gateway", router] => parse_address(router).and_then(|router| net_config::set_default_gateway(name, router)),
+        [name, "route", "add", destination, router] => parse_cidr(destination).and_then(|destination|
+            parse_address(router).and_then(|router| net_config::add_route(name, destination, router))
+        ),
+        [name, "route", "del", destination] => parse_cidr(destination).and_then(|destination| net_config::remove_route(name, destination)),
+        _ => {
+            println!("ifconfig: invalid arguments\n");
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("ifconfig: {}", e);
+            -1
+        }
+    }
+}
+
+fn parse_cidr(arg: &str) -> Result<IpCidr, &'static str> {
+    IpCidr::from_str(arg).map_err(|_| "invalid address, expected ADDRESS/PREFIX")
+}
+
+fn parse_address(arg: &str) -> Result<IpAddress, &'static str> {
+    IpAddress::from_str(arg).map_err(|_| "invalid IP address")
+}
+
+fn print_interface(info: &InterfaceInfo) {
+    println!("{}: {}{}", info.name, if info.up { "UP" } else { "DOWN" }, if info.loopback { " LOOPBACK" } else { "" });
+    if !info.loopback {
+        println!("    ether    {}", info.ethernet_addr);
+    }
+    for address in info.ip_addrs.iter() {
+        println!("    inet     {}", address);
+    }
+    for route in info.routes.iter() {
+        if route.is_default() {
+            println!("    route    default via {}", route.via_router);
+        } else {
+            println!("    route    {} via {}", route.destination, route.via_router);
+        }
+    }
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: ifconfig [IFACE [up | down | add ADDRESS/PREFIX | del ADDRESS/PREFIX | gateway ROUTER | gateway none\n");
+    brief.push_str("                        | route add DESTINATION/PREFIX ROUTER | route del DESTINATION/PREFIX]]\n\n");
+
+    brief.push_str("Lists the network interfaces, or the given interface, or changes the configuration of the given interface.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,53 @@
+//! Prints the request statistics of each block device's request queue,
+//! as reported by [`BlockQueue::stats()`](../block_io/struct.BlockQueue.html#method.stats).
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate storage_manager;
+extern crate block_io;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let queues = storage_manager::BLOCK_QUEUES.lock().clone();
+    if queues.is_empty() {
+        println!("No block devices are available.");
+        return -1;
+    }
+
+    for queue in queues.iter().filter(|q| matches.free.is_empty() || matches.free.iter().any(|name| name == q.name())) {
+        println!("\n{}: {} blocks of {} bytes", queue.name(), queue.num_blocks(), queue.block_size());
+        print!("{}", queue.stats());
+    }
+
+    0
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: iostat [OPTIONS] [DEVICE]...\n\n");
+
+    brief.push_str("For each block device (or only the given ones, e.g., disk0), prints the number of completed reads and writes, merged requests, IOPS, and a histogram of request latencies.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
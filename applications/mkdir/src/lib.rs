@@ -6,12 +6,14 @@ extern crate task;
 extern crate getopts;
 extern crate fs_node;
 extern crate vfs_node;
+extern crate access_control;
 
 use alloc::vec::Vec;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::string::ToString;
 // use fs_node::FileOrDir;
+use fs_node::Directory;
 use vfs_node::VFSDirectory;
 
 pub fn main(args: Vec<String>) -> isize {
@@ -25,7 +27,12 @@ pub fn main(args: Vec<String>) -> isize {
                     let curr_env = locked_task.env.lock();
                     Arc::clone(&curr_env.working_dir)
                 };
-                let _new_dir = match VFSDirectory::new(dir_name.to_string(), &curr_dir) {
+                // directories backed by a filesystem create their own nodes (if this task may modify them); others hold VFS directories
+                let created = match access_control::create_dir(&curr_dir, dir_name) {
+                    Err(access_control::PERMISSION_DENIED) => Err(access_control::PERMISSION_DENIED),
+                    created => created.or_else(|_| VFSDirectory::new(dir_name.to_string(), &curr_dir)),
+                };
+                let _new_dir = match created {
                     Ok(dir) => dir,
                     Err(err) => {println!("{}", err);
                                 return -1;}
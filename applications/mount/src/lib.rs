@@ -0,0 +1,107 @@
+//! Mounts a filesystem into the VFS, or lists the mounted filesystems when given no arguments.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate vfs;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use fs_node::FsNode;
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("l", "list-types", "list the filesystem types that can be mounted");
+    opts.optopt("t", "type", "the type of filesystem to mount, which is detected from the device if not given", "TYPE");
+    opts.optopt("o", "options", "a comma-separated list of filesystem-specific mount options", "OPTIONS");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    if matches.opt_present("l") {
+        for fstype in vfs::filesystems() {
+            println!("{}", fstype);
+        }
+        return 0;
+    }
+
+    if matches.free.is_empty() {
+        for mount in vfs::mounts() {
+            if mount.options.is_empty() {
+                println!("{} on {} type {}", mount.device, mount.path, mount.fstype);
+            } else {
+                println!("{} on {} type {} ({})", mount.device, mount.path, mount.fstype, mount.options);
+            }
+        }
+        return 0;
+    }
+
+    if matches.free.len() != 2 {
+        return print_usage(opts);
+    }
+    let device = &matches.free[0];
+    let path = match absolute_path(&matches.free[1]) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("Error: {}", e);
+            return -1;
+        }
+    };
+
+    let fstype = match matches.opt_str("t") {
+        Some(fstype) => fstype,
+        None => match vfs::detect_filesystem(device) {
+            Ok(fstype) => fstype.to_string(),
+            Err(e) => {
+                println!("Error: {}, please specify it with -t", e);
+                return -1;
+            }
+        },
+    };
+    let device = if device == "none" { None } else { Some(device.as_str()) };
+
+    let options = matches.opt_str("o").unwrap_or_default();
+    match vfs::mount_with_options(device, &path, &fstype, &options) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: couldn't mount {} at {}: {}", device.unwrap_or("none"), path, e);
+            -1
+        }
+    }
+}
+
+/// Returns the absolute path of the directory at the given path, which may be relative to the working directory.
+fn absolute_path(path: &str) -> Result<String, &'static str> {
+    let taskref = task::get_my_current_task().ok_or("failed to get current task")?;
+    let curr_wd = taskref.get_env().lock().working_dir.clone();
+    let node = Path::new(path.to_string()).get(&curr_wd).ok_or("no such directory")?;
+    Ok(node.get_absolute_path())
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: mount [OPTIONS] [DEVICE DIRECTORY]\n\n");
+
+    brief.push_str("Mounts the filesystem on DEVICE (e.g., disk0 or disk0p1) at the existing DIRECTORY.\n");
+    brief.push_str("Filesystems that don't use a device, like tmpfs, are mounted with the device \"none\".\n");
+    brief.push_str("With no arguments, lists the mounted filesystems.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
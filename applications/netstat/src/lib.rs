@@ -0,0 +1,62 @@
+//! Lists the TCP sockets of the network stack, including listening sockets.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate net_stack;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+use net_stack::tcp;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("c", "congestion", "print the congestion control state of each socket");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let sockets = tcp::sockets();
+    if matches.opt_present("c") {
+        println!("{0:<24}  {1:<12}  {2:>8}  {3:>8}  {4:>8}  {5:>8}  {6}", "LOCAL", "STATE", "CWND", "SSTHRESH", "SRTT", "RTO", "ALGORITHM");
+        for info in sockets.iter() {
+            let srtt = info.smoothed_rtt_ms.map(|rtt| format!("{}ms", rtt)).unwrap_or_else(|| String::from("-"));
+            println!("{0:<24}  {1:<12}  {2:>8}  {3:>8}  {4:>8}  {5:>8}  {6:?}",
+                format!("{}", info.local_endpoint), format!("{}", info.state),
+                info.congestion_window, info.slow_start_threshold, srtt, format!("{}ms", info.rto_ms), info.congestion_control,
+            );
+        }
+    } else {
+        println!("{0:<24}  {1:<24}  {2:<12}  {3:>8}  {4:>8}  {5:>8}", "LOCAL", "REMOTE", "STATE", "SEND-Q", "RECV-Q", "BUFFERS");
+        for info in sockets.iter() {
+            println!("{0:<24}  {1:<24}  {2:<12}  {3:>8}  {4:>8}  {5:>8}",
+                format!("{}", info.local_endpoint), format!("{}", info.remote_endpoint), format!("{}", info.state),
+                info.send_queue, info.recv_queue, info.rx_buffer_size + info.tx_buffer_size,
+            );
+        }
+    }
+    println!("{} TCP sockets", sockets.len());
+    0
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: netstat [OPTIONS]\n\n");
+
+    brief.push_str("Lists the TCP sockets of the network stack, including the listening sockets in each listener's backlog.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,122 @@
+//! Plays WAV files on the sound card through the `audio` subsystem.
+//!
+//! The samples are streamed from the file rather than read all at once, so long files can be played, too.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate path;
+extern crate fs_node;
+extern crate access_control;
+extern crate audio;
+
+mod wav;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use getopts::Options;
+use path::Path;
+use fs_node::{DirRef, FileOrDir};
+use access_control::Access;
+
+
+/// The number of bytes read from the file and submitted to the output stream at once.
+const CHUNK_SIZE: usize = 4096;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("v", "volume", "play at the given volume, from 0 to 100 percent (default 100)", "PERCENT");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return print_usage(opts);
+    }
+
+    let volume = match matches.opt_str("v").map(|v| v.parse::<u8>()) {
+        None => 100,
+        Some(Ok(volume)) if volume <= 100 => volume,
+        Some(_) => {
+            println!("Error: the volume must be a number from 0 to 100");
+            return -1;
+        }
+    };
+
+    if !audio::has_device() {
+        println!("Error: no sound card is present");
+        return -1;
+    }
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    for path in &matches.free {
+        if let Err(e) = play(path, &curr_wd, volume) {
+            println!("play: {}: {}", path, e);
+            return -1;
+        }
+    }
+    0
+}
+
+/// Plays the WAV file at `path`, relative to `working_dir`, at the given `volume`.
+fn play(path: &str, working_dir: &DirRef, volume: u8) -> Result<(), String> {
+    let file = match access_control::open(&Path::new(path.to_string()), working_dir, Access::Read)? {
+        FileOrDir::File(file) => file,
+        FileOrDir::Dir(_) => return Err(String::from("is a directory")),
+    };
+    let data = wav::parse(&file)?;
+
+    let duration_ms = data.duration_ms();
+    println!("Playing {}: {} Hz, {}-bit, {}, {}.{} seconds",
+        path,
+        data.format.sample_rate,
+        data.format.sample_format.bytes_per_sample() * 8,
+        if data.format.channels == 1 { "mono" } else { "stereo" },
+        duration_ms / 1000,
+        duration_ms % 1000 / 100,
+    );
+
+    let stream = audio::open_output(data.format)?;
+    stream.set_volume(volume);
+
+    // Each chunk holds a whole number of frames, so no frame is split between two writes.
+    let chunk_size = CHUNK_SIZE - CHUNK_SIZE % data.format.bytes_per_frame();
+    let mut buffer = vec![0u8; chunk_size];
+    let mut played = 0;
+    while played < data.len {
+        let count = core::cmp::min(chunk_size, data.len - played);
+        wav::read_exact(&file, &mut buffer[..count], data.offset + played)?;
+        stream.write_all(&buffer[..count])?;
+        played += count;
+    }
+    stream.drain()?;
+    Ok(())
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: play [OPTIONS] FILE...\n\n");
+
+    brief.push_str("Plays each WAV FILE in turn on the sound card.\n");
+    brief.push_str("Only uncompressed 8-bit or 16-bit PCM files with one or two channels are supported.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
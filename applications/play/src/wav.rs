@@ -0,0 +1,97 @@
+//! Parsing of the headers of WAV files, which hold uncompressed PCM samples in a RIFF container.
+//!
+//! A WAV file starts with a `RIFF` header of the form `WAVE`, followed by chunks that each start with
+//! a four-character ID and a 32-bit little-endian size. The `fmt ` chunk describes the samples,
+//! which are stored in the `data` chunk. Other chunks, e.g., `LIST` metadata, are skipped.
+
+use fs_node::FileRef;
+use audio::{AudioFormat, SampleFormat};
+
+
+/// The format tag of uncompressed PCM samples in the `fmt ` chunk.
+const WAVE_FORMAT_PCM: u16 = 1;
+
+/// The part of a WAV file that holds its samples.
+pub struct WavData {
+    pub format: AudioFormat,
+    /// The offset of the first sample in the file.
+    pub offset: usize,
+    /// The size of the samples in bytes.
+    pub len: usize,
+}
+
+impl WavData {
+    /// Returns the duration of the samples in milliseconds.
+    pub fn duration_ms(&self) -> u64 {
+        let frames = (self.len / self.format.bytes_per_frame()) as u64;
+        frames * 1000 / self.format.sample_rate as u64
+    }
+}
+
+/// Reads the headers of the WAV file and returns where its samples are and what format they have.
+pub fn parse(file: &FileRef) -> Result<WavData, &'static str> {
+    let mut riff_header = [0u8; 12];
+    read_exact(file, &mut riff_header, 0)?;
+    if &riff_header[0..4] != b"RIFF" || &riff_header[8..12] != b"WAVE" {
+        return Err("not a WAV file");
+    }
+
+    let file_size = file.lock().size();
+    let mut format = None;
+    let mut offset = riff_header.len();
+    while offset + 8 <= file_size {
+        let mut chunk_header = [0u8; 8];
+        read_exact(file, &mut chunk_header, offset)?;
+        let chunk_size = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]) as usize;
+        let body_offset = offset + chunk_header.len();
+
+        match &chunk_header[0..4] {
+            b"fmt " => {
+                let mut body = [0u8; 16];
+                if chunk_size < body.len() {
+                    return Err("the fmt chunk is too short");
+                }
+                read_exact(file, &mut body, body_offset)?;
+                format = Some(parse_format(&body)?);
+            }
+            b"data" => {
+                let format = format.ok_or("the data chunk comes before the fmt chunk")?;
+                // Files that were cut short, or whose writer didn't know the length in advance, have a wrong size.
+                let len = core::cmp::min(chunk_size, file_size - body_offset);
+                return Ok(WavData { format, offset: body_offset, len });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        offset = body_offset + chunk_size + (chunk_size & 1);
+    }
+    Err("the file has no data chunk")
+}
+
+/// Parses the first 16 bytes of the `fmt ` chunk.
+fn parse_format(body: &[u8; 16]) -> Result<AudioFormat, &'static str> {
+    let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+    let format_tag = u16_at(0);
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+    let bits_per_sample = u16_at(14);
+
+    if format_tag != WAVE_FORMAT_PCM {
+        return Err("only uncompressed PCM samples are supported");
+    }
+    let sample_format = match bits_per_sample {
+        8 => SampleFormat::U8,
+        16 => SampleFormat::S16Le,
+        _ => return Err("only 8-bit and 16-bit samples are supported"),
+    };
+    Ok(AudioFormat { sample_rate, channels, sample_format })
+}
+
+/// Fills `buffer` with the bytes of the file starting at `offset`.
+pub fn read_exact(file: &FileRef, buffer: &mut [u8], offset: usize) -> Result<(), &'static str> {
+    let count = access_control::read(file, buffer, offset)?;
+    if count < buffer.len() {
+        return Err("unexpected end of file");
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Profiles the current core by sampling PMU events for a given period of time,
+//! then prints a flat profile and call graph that attribute the samples to functions.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate pmu_x86;
+extern crate hpet;
+extern crate scheduler;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+use pmu_x86::{EventType, Profile};
+
+/// The default profiling period, in milliseconds.
+const DEFAULT_DURATION_MS: u64 = 1000;
+/// The default number of events between each sample.
+const DEFAULT_EVENTS_PER_SAMPLE: u32 = 0xF_FFFF;
+/// The default maximum number of samples to take.
+const DEFAULT_MAX_SAMPLES: u32 = 10_000;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("e", "event", "the event to sample: cycles (default), ref-cycles, instructions, cache-misses, cache-refs, branches, branch-misses", "EVENT");
+    opts.optopt("d", "duration", "how long to sample for, in milliseconds (default 1000)", "MS");
+    opts.optopt("p", "period", "the number of events between each sample (default 0xFFFFF)", "EVENTS");
+    opts.optopt("n", "samples", "the maximum number of samples to take (default 10000)", "COUNT");
+    opts.optopt("t", "task", "only sample the task with the given ID", "TASK_ID");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match run(&matches) {
+        Ok(profile) => {
+            print!("{}", profile);
+            0
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(matches: &getopts::Matches) -> Result<Profile, String> {
+    let event = match matches.opt_str("e").as_ref().map(|s| s.as_str()) {
+        None | Some("cycles")  => EventType::UnhaltedCoreCycles,
+        Some("ref-cycles")     => EventType::UnhaltedReferenceCycles,
+        Some("instructions")   => EventType::InstructionsRetired,
+        Some("cache-misses")   => EventType::LastLevelCacheMisses,
+        Some("cache-refs")     => EventType::LastLevelCacheReferences,
+        Some("branches")       => EventType::BranchInstructionsRetired,
+        Some("branch-misses")  => EventType::BranchMissesRetired,
+        Some(other) => return Err(format!("unknown event {:?}", other)),
+    };
+    let duration_ms = parse_opt(matches, "d", DEFAULT_DURATION_MS)?;
+    let events_per_sample = parse_opt(matches, "p", DEFAULT_EVENTS_PER_SAMPLE)?;
+    let max_samples = parse_opt(matches, "n", DEFAULT_MAX_SAMPLES)?;
+    let task_id = match matches.opt_str("t") {
+        Some(id) => Some(id.parse::<usize>().map_err(|_| format!("invalid task ID {:?}", id))?),
+        None => None,
+    };
+
+    pmu_x86::init().map_err(|e| format!("couldn't initialize the PMU: {}", e))?;
+    pmu_x86::start_samples(event, events_per_sample, task_id, max_samples)
+        .map_err(|e| format!("couldn't start PMU sampling: {}", e))?;
+
+    // Yield this core to other tasks while sampling, such that they're the ones being profiled.
+    let wait_result = wait_ms(duration_ms);
+    pmu_x86::stop_sampling().map_err(|e| format!("couldn't stop PMU sampling: {}", e))?;
+    let samples = pmu_x86::retrieve_samples().map_err(|e| format!("couldn't retrieve samples: {}", e))?;
+    wait_result?;
+
+    Profile::from_samples(&samples).map_err(|e| format!("couldn't build profile: {}", e))
+}
+
+/// Parses the value of the given option, or returns the `default` if it wasn't provided.
+fn parse_opt<T: core::str::FromStr>(matches: &getopts::Matches, opt: &str, default: T) -> Result<T, String> {
+    match matches.opt_str(opt) {
+        Some(value) => value.parse::<T>().map_err(|_| format!("invalid value {:?} for option -{}", value, opt)),
+        None => Ok(default),
+    }
+}
+
+/// Repeatedly yields the current core until the given number of milliseconds have elapsed.
+fn wait_ms(duration_ms: u64) -> Result<(), String> {
+    let (start, period_fs) = {
+        let hpet = hpet::get_hpet().ok_or_else(|| String::from("couldn't get the HPET to measure the profiling period"))?;
+        (hpet.get_counter(), hpet.counter_period_femtoseconds() as u64)
+    };
+    // 1 millisecond is 10^12 femtoseconds.
+    let ticks = duration_ms.saturating_mul(1_000_000_000_000) / core::cmp::max(period_fs, 1);
+    loop {
+        let now = hpet::get_hpet().map(|hpet| hpet.get_counter()).unwrap_or(core::u64::MAX);
+        if now.saturating_sub(start) >= ticks {
+            return Ok(());
+        }
+        scheduler::schedule();
+    }
+}
+
+fn print_usage(opts: Options) {
+    let brief = format!("Usage: profile [options]\n\n\
+        Samples the current core with the PMU for the given duration, then prints the percentage of samples\n\
+        in each function (flat profile) and the number of samples in which each caller called each callee (call graph).\n\
+        The call graph is only available if Theseus was built with frame pointers.");
+    println!("{}", opts.usage(&brief));
+}
@@ -9,12 +9,13 @@ extern crate scheduler;
 use getopts::Options;
 use alloc::vec::Vec;
 use alloc::string::String;
-use task::{TASKLIST, RunState};
+use task::{TASKLIST, RunState, TaskStats};
 
 pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("b", "brief", "print only task id and name");
+    opts.optflag("t", "top", "print CPU time and scheduling statistics, sorted by CPU usage");
 
     let matches = match opts.parse(&args) {
         Ok(m) => m,
@@ -28,6 +29,11 @@ pub fn main(args: Vec<String>) -> isize {
         return print_usage(opts)
     }
 
+    if matches.opt_present("t") {
+        print_top();
+        return 0;
+    }
+
     // Print headers
     if matches.opt_present("b") {
         println!("{0:<5}  {1}", "ID", "NAME");
@@ -63,7 +69,7 @@ pub fn main(args: Vec<String>) -> isize {
                 RunState::Reaped     => "Reaped",
             };
             cpu = task.running_on_cpu.map(|cpu| format!("{}", cpu)).unwrap_or_else(|| String::from("-"));
-            pinned = task.pinned_core.map(|pin| format!("{}", pin)).unwrap_or_else(|| String::from("-"));
+            pinned = task.pinned_core().map(|pin| format!("{}", pin)).unwrap_or_else(|| String::from("-"));
             task_type = if task.is_an_idle_task {"I"}
                 else if task.is_application() {"A"}
                 else {" "} ;
@@ -94,6 +100,31 @@ pub fn main(args: Vec<String>) -> isize {
     0
 }
 
+/// Prints a `top`-style view of all tasks, sorted by their cumulative CPU time.
+fn print_top() {
+    let mut all_stats: Vec<(usize, String, TaskStats)> = TASKLIST.lock().iter()
+        .map(|(id, taskref)| {
+            let name = taskref.lock().name.clone();
+            (*id, name, taskref.stats())
+        })
+        .collect();
+    all_stats.sort_by(|a, b| b.2.cpu_ticks().cmp(&a.2.cpu_ticks()));
+    let total_ticks: u64 = all_stats.iter().map(|(_, _, stats)| stats.cpu_ticks()).sum();
+
+    println!("{0:<5}  {1:>6}  {2:>12}  {3:>12}  {4:>10}  {5:<4}  {6}", "ID", "%CPU", "CPU_TIME(ms)", "WAIT(ms)", "SWITCHES", "LAST", "NAME");
+    let mut task_string = String::new();
+    for (id, name, stats) in &all_stats {
+        let percent = if total_ticks == 0 { 0.0 } else { stats.cpu_ticks() as f64 * 100.0 / total_ticks as f64 };
+        let last_cpu = stats.last_ran_on_cpu().map(|cpu| format!("{}", cpu)).unwrap_or_else(|| String::from("-"));
+        task_string.push_str(
+            &format!("{0:<5}  {1:>6.2}  {2:>12}  {3:>12}  {4:>10}  {5:<4}  {6}\n", 
+            id, percent, stats.cpu_time().as_millis(), stats.wait_time().as_millis(), stats.context_switches(), last_cpu, name)
+        );
+    }
+    print!("{}", task_string);
+    println!("Total number of tasks: {}", all_stats.len());
+}
+
 fn print_usage(opts: Options) -> isize {
     let mut brief = format!("Usage: ps [options] \n \n");
 
@@ -102,7 +133,9 @@ fn print_usage(opts: Options) -> isize {
     brief.push_str("PIN is the core the task is pinned on, if any. \n");
     brief.push_str("RUNSATE is runnability status of this task, i.e. whether it's allowed to be scheduled in. \n");
     brief.push_str("ID is the unique id of task. \n");
-    brief.push_str("NAME is the simple name of the task");
+    brief.push_str("NAME is the simple name of the task. \n");
+    brief.push_str("With --top, %CPU is the task's share of the CPU time used by all tasks, \n");
+    brief.push_str("WAIT is the time spent runnable but not yet scheduled in, and LAST is the core it last ran on.");
 
     println!("{} \n", opts.usage(&brief));
 
@@ -0,0 +1,49 @@
+//! Resets the system, using the ACPI reset register if available.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate power;
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use getopts::Options;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    println!("Rebooting...");
+    match power::reboot() {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn print_usage(opts: Options) {
+    let brief = format!("Usage: reboot\n\n\
+        Suspends all devices and resets the system.");
+    println!("{}", opts.usage(&brief));
+}
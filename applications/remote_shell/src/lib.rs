@@ -0,0 +1,143 @@
+//! A daemon that provides remote shell access over TCP, which clients connect to with `telnet`.
+//!
+//! Each connection gets its own session, which runs in its own task and has its own working directory.
+//! A session runs the commands that the client enters, including pipelines, one at a time;
+//! the standard input and output of the running command are bridged to the connection.
+//! Because clients only send characters, applications that read key events receive none.
+//!
+//! **Warning:** there is no authentication, and sessions run commands with the system's credentials,
+//! so the daemon should only be started on trusted networks.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate spin;
+extern crate bare_io;
+extern crate spawn;
+extern crate task;
+extern crate runqueue;
+extern crate scheduler;
+extern crate event_types;
+extern crate dfqueue;
+extern crate stdio;
+extern crate app_io;
+extern crate environment;
+extern crate root;
+extern crate path;
+extern crate fs_node;
+extern crate net_stack;
+extern crate async_runtime;
+
+mod telnet;
+mod session;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use alloc::string::String;
+use getopts::{Matches, Options};
+use net_stack::tcp::{self, TcpHandle, TcpOptions};
+use session::Session;
+
+
+/// The port that the daemon listens on by default, which is the Telnet port.
+const DEFAULT_PORT: u16 = 23;
+/// The number of sessions that may be open at once by default.
+const DEFAULT_MAX_SESSIONS: usize = 8;
+/// How many connections may wait to be accepted.
+const BACKLOG: usize = 4;
+/// How long a connection may be idle before a keep-alive segment is sent, which detects clients that went away.
+const KEEP_ALIVE_MS: u64 = 60_000;
+
+/// The number of sessions that are currently open.
+static ACTIVE_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("p", "port", "listen on port N (default: 23)", "N");
+    opts.optopt("m", "max-sessions", "allow at most N sessions at once (default: 8)", "N");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(&opts);
+    }
+
+    match rmain(&matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("remote_shell: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain(matches: &Matches) -> Result<(), String> {
+    let port = match matches.opt_str("p") {
+        Some(p) => p.parse::<u16>().map_err(|_e| "couldn't parse port")?,
+        None => DEFAULT_PORT,
+    };
+    let max_sessions = match matches.opt_str("m") {
+        Some(n) => n.parse::<usize>().map_err(|_e| "couldn't parse the maximum number of sessions")?,
+        None => DEFAULT_MAX_SESSIONS,
+    };
+
+    let options = TcpOptions {
+        keep_alive_ms: Some(KEEP_ALIVE_MS),
+        ..Default::default()
+    };
+    let listener = tcp::listen(port, BACKLOG, options)?;
+    println!("Listening for remote shell connections on port {}", port);
+
+    let mut session_count = 0;
+    let result = loop {
+        let socket = match listener.accept() {
+            Ok(socket) => socket,
+            Err(e) => break Err(e),
+        };
+        if ACTIVE_SESSIONS.fetch_add(1, Ordering::SeqCst) >= max_sessions {
+            ACTIVE_SESSIONS.fetch_sub(1, Ordering::SeqCst);
+            let _ = socket.send(b"Too many remote shell sessions, try again later.\r\n");
+            socket.close();
+            continue;
+        }
+        session_count += 1;
+        let spawned = spawn::new_task_builder(run_session, socket)
+            .name(format!("remote_shell_session_{}", session_count))
+            .spawn();
+        if let Err(e) = spawned {
+            error!("remote_shell: failed to spawn session task: {}", e);
+            ACTIVE_SESSIONS.fetch_sub(1, Ordering::SeqCst);
+            socket.abort();
+        }
+    };
+    listener.close();
+    result.map_err(String::from)
+}
+
+/// The entry point of a session's task.
+fn run_session(socket: TcpHandle) {
+    Session::new(socket).run();
+    ACTIVE_SESSIONS.fetch_sub(1, Ordering::SeqCst);
+}
+
+fn print_usage(opts: &Options) -> isize {
+    let mut brief = format!("Usage: remote_shell [OPTION]... \n \n");
+
+    brief.push_str("runs a shell for each client that connects over TCP, e.g., with telnet");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
@@ -0,0 +1,490 @@
+//! A shell session on a single connection, which runs the commands that the client enters
+//! and bridges the standard I/O of the running command to the connection.
+
+use core::mem;
+use core::ops::Deref;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard};
+use bare_io::Write;
+use async_runtime::Executor;
+use dfqueue::{DFQueue, DFQueueConsumer, DFQueueProducer};
+use environment::{Environment, Credentials};
+use event_types::Event;
+use fs_node::FileOrDir;
+use path::Path;
+use stdio::{Stdio, StdioReader, StdioWriter, KeyEventQueue, KeyEventQueueReader};
+use task::{TaskRef, ExitValue, KillReason};
+use app_io::{IoStreams, IoControlFlags};
+use net_stack::WOULD_BLOCK;
+use net_stack::tcp::TcpHandle;
+use telnet::{self, Decoder};
+
+
+/// The longest command line or line of input that a client can enter.
+const MAX_LINE_LENGTH: usize = 4096;
+
+
+/// A command line that is running, i.e., one or more applications whose output is piped from one to the next.
+/// Its first application's stdin is fed from the connection, and its last application's stdout is sent to it.
+struct Job {
+    tasks: Vec<TaskRef>,
+    task_ids: Vec<usize>,
+    /// `pipe_queues[i]` is the stdin of the i-th task, and `pipe_queues[N]` is the stdout of the last task.
+    pipe_queues: Vec<Stdio>,
+    stderr_queues: Vec<Stdio>,
+    stdin_writer: StdioWriter,
+    stdout_reader: StdioReader,
+}
+
+/// Why the session ended.
+enum Closed {
+    /// The client closed the connection or entered `exit`.
+    ByClient,
+    Error(&'static str),
+}
+
+impl From<&'static str> for Closed {
+    fn from(e: &'static str) -> Closed {
+        Closed::Error(e)
+    }
+}
+
+
+/// The state of a session on a single connection.
+pub struct Session {
+    socket: TcpHandle,
+    decoder: Decoder,
+    /// The environment that the session's commands run in, which holds the working directory.
+    env: Arc<Mutex<Environment>>,
+    /// The command line being entered, or the line of input for the running job.
+    line: String,
+    /// Whether an escape sequence (e.g., of an arrow key) is being received, which is discarded.
+    in_escape_sequence: bool,
+    job: Option<Job>,
+    /// The queue of legacy output from applications that print with `terminal_print`.
+    print_consumer: DFQueueConsumer<Event>,
+    print_producer: DFQueueProducer<Event>,
+    /// The key event queue of the session's applications, which never receives events,
+    /// since clients only send characters.
+    key_event_reader: Arc<Mutex<Option<KeyEventQueueReader>>>,
+}
+
+impl Session {
+    pub fn new(socket: TcpHandle) -> Session {
+        let print_consumer = DFQueue::new().into_consumer();
+        let print_producer = print_consumer.obtain_producer();
+        let env = Environment {
+            working_dir: Arc::clone(root::get_root()),
+            variables: BTreeMap::new(),
+            credentials: Credentials::system(),
+        };
+        Session {
+            socket,
+            decoder: Decoder::new(),
+            env: Arc::new(Mutex::new(env)),
+            line: String::new(),
+            in_escape_sequence: false,
+            job: None,
+            print_consumer,
+            print_producer,
+            key_event_reader: Arc::new(Mutex::new(Some(KeyEventQueue::new().get_reader()))),
+        }
+    }
+
+    /// Runs the session until the client disconnects or exits, then kills any running job and closes the connection.
+    pub fn run(mut self) {
+        let result = self.serve();
+        self.kill_job();
+        self.remove_job();
+        match result {
+            Ok(()) | Err(Closed::ByClient) => {
+                let _ = self.socket.send(b"\r\n");
+                self.socket.close();
+            }
+            Err(Closed::Error(_e)) => {
+                warn!("remote_shell: session on {:?} ended with an error: {}", self.socket, _e);
+                self.socket.abort();
+            }
+        }
+    }
+
+    fn serve(&mut self) -> Result<(), Closed> {
+        let executor = Executor::new();
+        self.socket.send(&telnet::INITIAL_NEGOTIATION)?;
+        self.send_str("Theseus remote shell. Enter \"exit\" to end the session.\n")?;
+        self.send_prompt()?;
+        loop {
+            let mut progressed = self.receive()?;
+            progressed |= self.forward_output()?;
+            progressed |= self.check_job()?;
+            // Wait for the network stack to receive or send more data before checking again.
+            if !progressed {
+                executor.block_on(net_stack::next_poll())?;
+            }
+        }
+    }
+
+    /// Sends the given output to the client.
+    fn send_str(&self, output: &str) -> Result<(), Closed> {
+        self.send(output.as_bytes())
+    }
+
+    fn send(&self, output: &[u8]) -> Result<(), Closed> {
+        let mut encoded = Vec::with_capacity(output.len() + 16);
+        telnet::encode(output, &mut encoded);
+        self.socket.send(&encoded)?;
+        Ok(())
+    }
+
+    fn send_prompt(&self) -> Result<(), Closed> {
+        let prompt = format!("{}: ", self.env.lock().working_dir.lock().get_absolute_path());
+        self.send_str(&prompt)
+    }
+
+    /// Handles the input that the client has sent, and returns whether there was any.
+    fn receive(&mut self) -> Result<bool, Closed> {
+        let mut received = [0u8; 512];
+        let len = match self.socket.try_recv(&mut received) {
+            Ok(0) => return Err(Closed::ByClient),
+            Ok(len) => len,
+            Err(WOULD_BLOCK) => return Ok(false),
+            Err(e) => return Err(Closed::Error(e)),
+        };
+        let mut data = Vec::with_capacity(len);
+        let mut replies = Vec::new();
+        self.decoder.decode(&received[.. len], &mut data, &mut replies);
+        if !replies.is_empty() {
+            self.socket.send(&replies)?;
+        }
+        for byte in data {
+            self.handle_input(byte)?;
+        }
+        Ok(true)
+    }
+
+    /// Handles a single character of input, like the shell handles a key press.
+    fn handle_input(&mut self, byte: u8) -> Result<(), Closed> {
+        // Escape sequences end with a letter or `~`.
+        if self.in_escape_sequence {
+            if byte.is_ascii_alphabetic() || byte == b'~' {
+                self.in_escape_sequence = false;
+            }
+            return Ok(());
+        }
+        match byte {
+            0x1b => self.in_escape_sequence = true,
+            telnet::CTRL_C => {
+                self.send_str("^C\n")?;
+                self.line.clear();
+                if self.job.is_some() {
+                    self.kill_job();
+                } else {
+                    self.send_prompt()?;
+                }
+            }
+            telnet::CTRL_D => {
+                match self.job {
+                    Some(ref job) => job.stdin_writer.lock().set_eof(),
+                    // Like in most shells, Ctrl+D on an empty command line ends the session.
+                    None if self.line.is_empty() => return Err(Closed::ByClient),
+                    None => {}
+                }
+            }
+            telnet::DELETE | telnet::BACKSPACE => {
+                if self.line.pop().is_some() {
+                    self.send(b"\x08 \x08")?;
+                }
+            }
+            b'\n' => {
+                self.send_str("\n")?;
+                let mut line = mem::replace(&mut self.line, String::new());
+                match self.job {
+                    Some(ref job) => {
+                        line.push('\n');
+                        let _ = job.stdin_writer.lock().write_all(line.as_bytes());
+                    }
+                    None => {
+                        self.execute(line.trim())?;
+                        if self.job.is_none() {
+                            self.send_prompt()?;
+                        }
+                    }
+                }
+            }
+            byte if byte == b'\t' || (0x20..0x7f).contains(&byte) => {
+                if self.line.len() >= MAX_LINE_LENGTH {
+                    return Ok(());
+                }
+                self.line.push(byte as char);
+                self.send(&[byte])?;
+                // An application that reads its input character by character gets it right away.
+                if let Some(ref job) = self.job {
+                    if app_io::is_requesting_instant_flush(&job.task_ids[0]).unwrap_or(false) {
+                        let _ = job.stdin_writer.lock().write_all(self.line.as_bytes());
+                        self.line.clear();
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Executes the given command line, which is either a built-in command or starts a new job.
+    fn execute(&mut self, cmdline: &str) -> Result<(), Closed> {
+        let mut words = cmdline.split_whitespace();
+        match words.next() {
+            None => Ok(()),
+            Some("exit") | Some("logout") => Err(Closed::ByClient),
+            Some("clear") => self.send(b"\x1b[2J\x1b[H"),
+            Some(_) if cmdline.ends_with('&') => self.send_str("remote_shell: background jobs are not supported\n"),
+            Some(_) => match self.start_job(cmdline) {
+                Ok(()) => Ok(()),
+                Err(e) => self.send_str(&format!("{}\n", e)),
+            },
+        }
+    }
+
+    /// Spawns the applications of the given command line and connects their standard I/O.
+    fn start_job(&mut self, cmdline: &str) -> Result<(), String> {
+        let mut tasks: Vec<TaskRef> = Vec::new();
+        for single_task_cmd in cmdline.split('|') {
+            let mut args: Vec<String> = single_task_cmd.split_whitespace().map(|s| s.to_string()).collect();
+            let result = if args.is_empty() {
+                Err("remote_shell: empty command in pipeline".to_string())
+            } else {
+                let command = args.remove(0);
+                self.create_single_task(command, args)
+            };
+            match result {
+                Ok(task) => tasks.push(task),
+                // The tasks that have been spawned haven't run yet, so they can be killed right away.
+                Err(e) => {
+                    kill_unstarted_tasks(&tasks);
+                    return Err(e);
+                }
+            }
+        }
+
+        let task_ids: Vec<usize> = tasks.iter().map(|task| task.lock().id).collect();
+        let mut pipe_queues = Vec::new();
+        let mut stderr_queues = Vec::new();
+        let first_stdio_queue = Stdio::new();
+        let stdin_writer = first_stdio_queue.get_writer();
+        let mut previous_queue_reader = first_stdio_queue.get_reader();
+        pipe_queues.push(first_stdio_queue);
+        for task_id in &task_ids {
+            let stdio_queue_for_stdin_and_stdout = Stdio::new();
+            let stdio_queue_for_stderr = Stdio::new();
+            let streams = IoStreams::new(
+                previous_queue_reader,
+                stdio_queue_for_stdin_and_stdout.get_writer(),
+                stdio_queue_for_stderr.get_writer(),
+                self.key_event_reader.clone(),
+                None,
+            );
+            app_io::insert_child_streams(*task_id, streams);
+            previous_queue_reader = stdio_queue_for_stdin_and_stdout.get_reader();
+            stderr_queues.push(stdio_queue_for_stderr);
+            pipe_queues.push(stdio_queue_for_stdin_and_stdout);
+            // Support legacy output by `terminal_print`.
+            if let Err(e) = terminal_print::add_child(*task_id, self.print_producer.obtain_producer()) {
+                kill_unstarted_tasks(&tasks);
+                for task_id in &task_ids {
+                    app_io::remove_child_streams(task_id);
+                    let _ = terminal_print::remove_child(*task_id);
+                }
+                return Err(e.to_string());
+            }
+        }
+
+        let job = Job {
+            tasks,
+            task_ids,
+            pipe_queues,
+            stderr_queues,
+            stdin_writer,
+            stdout_reader: previous_queue_reader,
+        };
+        // All I/O streams have been set up for the new tasks, so they can run now.
+        for task in &job.tasks {
+            task.unblock();
+        }
+        self.job = Some(job);
+        Ok(())
+    }
+
+    /// Spawns the application with the given name or path in a blocked task.
+    fn create_single_task(&self, cmd: String, args: Vec<String>) -> Result<TaskRef, String> {
+        let app_path = if cmd.contains('/') || cmd.ends_with(".o") {
+            let working_dir = Arc::clone(&self.env.lock().working_dir);
+            match Path::new(cmd.clone()).get(&working_dir) {
+                Some(FileOrDir::File(f)) => Path::new(f.lock().get_absolute_path()),
+                _ => return Err(format!("{:?} command not found.", cmd)),
+            }
+        } else {
+            let namespace_dir = task::get_my_current_task()
+                .map(|t| t.get_namespace().dir().clone())
+                .ok_or_else(|| "Failed to find directory of application executables.".to_string())?;
+            let mut matching_apps = namespace_dir.get_files_starting_with(&format!("{}-", cmd)).into_iter();
+            let app_file = matching_apps.next();
+            let second_match = matching_apps.next();
+            app_file.xor(second_match)
+                .map(|f| Path::new(f.lock().get_absolute_path()))
+                .ok_or_else(|| format!("{:?} command not found.", cmd))?
+        };
+
+        let task = spawn::new_application_task_builder(app_path, None)
+            .and_then(|builder| builder.argument(args).block().spawn())
+            .map_err(|e| format!("Failed to spawn new task to run command. Error: {}.", e))?;
+        task.set_env(self.env.clone());
+        Ok(task)
+    }
+
+    /// Sends any output of the running job to the client, and returns whether there was any.
+    fn forward_output(&mut self) -> Result<bool, Closed> {
+        let mut progressed = false;
+
+        // Legacy output by `terminal_print`.
+        while let Some(print_event) = self.print_consumer.peek() {
+            if let &Event::OutputEvent(ref s) = print_event.deref() {
+                self.send_str(s)?;
+            }
+            print_event.mark_completed();
+            progressed = true;
+        }
+
+        let job = match self.job {
+            Some(ref job) => job,
+            None => return Ok(progressed),
+        };
+        let mut buf = [0u8; 1024];
+        let mut readers: Vec<StdioReader> = job.stderr_queues.iter().map(|queue| queue.get_reader()).collect();
+        readers.insert(0, job.stdout_reader.clone());
+        for reader in readers {
+            loop {
+                let count = reader.lock().try_read(&mut buf).unwrap_or(0);
+                if count == 0 {
+                    break;
+                }
+                self.send(&buf[.. count])?;
+                progressed = true;
+            }
+        }
+        Ok(progressed)
+    }
+
+    /// Checks whether the tasks of the running job have exited, and if they all have, removes the job.
+    /// Returns whether any task exited.
+    fn check_job(&mut self) -> Result<bool, Closed> {
+        let mut messages = Vec::new();
+        let mut progressed = false;
+        let mut has_alive = false;
+        if let Some(ref job) = self.job {
+            for (index, task) in job.tasks.iter().enumerate() {
+                if !task.lock().has_exited() {
+                    has_alive = true;
+                    continue;
+                }
+                let exit_value = match task.take_exit_value() {
+                    Some(exit_value) => exit_value,
+                    // The exit of this task has already been handled.
+                    None => continue,
+                };
+                progressed = true;
+                let task_id = job.task_ids[index];
+                match exit_value {
+                    ExitValue::Completed(exit_status) => {
+                        if let Some(val) = exit_status.downcast_ref::<isize>() {
+                            if *val < 0 {
+                                messages.push(format!("task [{}] returned error value {:?}\n", task_id, val));
+                            }
+                        }
+                    }
+                    ExitValue::Panicked(panic_info) => messages.push(format!("task [{}] panicked at {}\n", task_id, panic_info)),
+                    ExitValue::Killed(KillReason::Requested) => {}
+                    ExitValue::Killed(kill_reason) => messages.push(format!("task [{}] was killed because {:?}\n", task_id, kill_reason)),
+                }
+                let _ = terminal_print::remove_child(task_id);
+                // Set the EOF flags of the exited task's stdin, stdout, and stderr.
+                job.pipe_queues[index].get_writer().lock().set_eof();
+                job.pipe_queues[index + 1].get_writer().lock().set_eof();
+                job.stderr_queues[index].get_writer().lock().set_eof();
+            }
+        }
+        if !progressed {
+            return Ok(false);
+        }
+        // Send any remaining output before the messages about the exited tasks.
+        self.forward_output()?;
+        for message in messages {
+            self.send_str(&message)?;
+        }
+        if self.job.is_some() && !has_alive {
+            self.remove_job();
+            self.line.clear();
+            self.send_prompt()?;
+        }
+        Ok(true)
+    }
+
+    /// Kills the tasks of the running job, if any. The job is removed once they have exited.
+    fn kill_job(&mut self) {
+        if let Some(ref job) = self.job {
+            kill_tasks(&job.tasks);
+        }
+    }
+
+    /// Removes the running job, if any, along with the I/O streams of its tasks.
+    fn remove_job(&mut self) {
+        if let Some(job) = self.job.take() {
+            for task_id in job.task_ids {
+                app_io::remove_child_streams(&task_id);
+                let _ = terminal_print::remove_child(task_id);
+            }
+        }
+    }
+}
+
+
+/// Kills the given tasks, which are still blocked.
+fn kill_unstarted_tasks(tasks: &[TaskRef]) {
+    for task in tasks {
+        if let Err(e) = task.kill(KillReason::Requested) {
+            error!("remote_shell: could not kill task: {}", e);
+        }
+    }
+}
+
+/// Kills the given tasks, giving each a chance to exit on its own first, like the shell does on Ctrl+C.
+fn kill_tasks(tasks: &[TaskRef]) {
+    let tasks: Vec<TaskRef> = tasks.iter()
+        .filter(|task| !task.lock().has_exited() && !task.request_kill())
+        .cloned()
+        .collect();
+    // Lock the shared structures of `app_io` so that no task is killed while holding their locks.
+    app_io::lock_and_execute(&move |_flags_guard: MutexGuard<BTreeMap<usize, IoControlFlags>>,
+                                    _streams_guard: MutexGuard<BTreeMap<usize, IoStreams>>| {
+        for task in &tasks {
+            if task.lock().has_exited() { continue; }
+            match task.kill(KillReason::Requested) {
+                Ok(_) => {
+                    if let Err(e) = runqueue::remove_task_from_all(task) {
+                        error!("remote_shell: killed task but could not remove it from runqueue: {}", e);
+                    }
+                }
+                Err(e) => error!("remote_shell: could not kill task: {}", e),
+            }
+            // Wait for the task to finish its last time slice before releasing the locks.
+            loop {
+                scheduler::schedule();
+                if !task.lock().is_running() {
+                    break;
+                }
+            }
+        }
+    });
+}
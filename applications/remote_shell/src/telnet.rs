@@ -0,0 +1,129 @@
+//! The subset of the Telnet protocol (RFC 854) that the remote shell speaks.
+//!
+//! The server offers to echo input and to suppress go-aheads, which puts clients into character-at-a-time mode,
+//! so that the session can edit the command line itself and react to Ctrl+C immediately.
+//! All other options are refused.
+
+use alloc::vec::Vec;
+
+
+/// "Interpret as command", which introduces a command.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+/// Begins a subnegotiation, which is ended by `IAC SE`.
+const SB: u8 = 250;
+const SE: u8 = 240;
+/// "Interrupt process", which clients send when the user presses Ctrl+C.
+const IP: u8 = 244;
+/// "Erase character", which some clients send for backspace.
+const EC: u8 = 247;
+
+const OPTION_ECHO: u8 = 1;
+const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// The negotiation that the server sends when a client connects.
+pub const INITIAL_NEGOTIATION: [u8; 6] = [IAC, WILL, OPTION_ECHO, IAC, WILL, OPTION_SUPPRESS_GO_AHEAD];
+
+/// The byte that Ctrl+C produces.
+pub const CTRL_C: u8 = 0x03;
+/// The byte that Ctrl+D produces.
+pub const CTRL_D: u8 = 0x04;
+/// The byte that most clients send for backspace.
+pub const DELETE: u8 = 0x7f;
+pub const BACKSPACE: u8 = 0x08;
+
+
+/// The state of the decoder between two received segments.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    /// After `IAC`.
+    Command,
+    /// After `IAC` and the given negotiation command, awaiting the option.
+    Negotiation(u8),
+    /// Within a subnegotiation, which is ignored.
+    Subnegotiation,
+    /// After `IAC` within a subnegotiation.
+    SubnegotiationCommand,
+    /// After a carriage return, which may be followed by a NUL or line feed that belongs to it.
+    CarriageReturn,
+}
+
+/// Separates the data that a client sends from the Telnet commands that it contains.
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder { state: State::Data }
+    }
+
+    /// Decodes the given received bytes, appending the data to `data` and the replies to any negotiations to `replies`.
+    ///
+    /// A line ending (`CR LF`, `CR NUL`, or a lone `LF`) is decoded as `\n`,
+    /// an "interrupt process" command as `CTRL_C`, and an "erase character" command as `DELETE`.
+    pub fn decode(&mut self, received: &[u8], data: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &byte in received {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) | (State::CarriageReturn, IAC) => State::Command,
+                (State::Data, b'\r') | (State::CarriageReturn, b'\r') => {
+                    data.push(b'\n');
+                    State::CarriageReturn
+                }
+                (State::CarriageReturn, b'\n') | (State::CarriageReturn, 0) => State::Data,
+                (State::Data, byte) | (State::CarriageReturn, byte) => {
+                    data.push(byte);
+                    State::Data
+                }
+
+                (State::Command, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Command, IP) => {
+                    data.push(CTRL_C);
+                    State::Data
+                }
+                (State::Command, EC) => {
+                    data.push(DELETE);
+                    State::Data
+                }
+                (State::Command, SB) => State::Subnegotiation,
+                (State::Command, command @ WILL ..= DONT) => State::Negotiation(command),
+                (State::Command, _) => State::Data,
+
+                (State::Negotiation(command), option) => {
+                    match (command, option) {
+                        // The client agrees to the options that the server offered.
+                        (DO, OPTION_ECHO) | (DO, OPTION_SUPPRESS_GO_AHEAD) => {}
+                        (DO, option) => replies.extend_from_slice(&[IAC, WONT, option]),
+                        (WILL, option) => replies.extend_from_slice(&[IAC, DONT, option]),
+                        // Refusals need no reply.
+                        _ => {}
+                    }
+                    State::Data
+                }
+
+                (State::Subnegotiation, IAC) => State::SubnegotiationCommand,
+                (State::Subnegotiation, _) => State::Subnegotiation,
+                (State::SubnegotiationCommand, SE) => State::Data,
+                (State::SubnegotiationCommand, _) => State::Subnegotiation,
+            };
+        }
+    }
+}
+
+/// Encodes the given output for sending to a client: line feeds become `CR LF`, and `IAC` bytes are escaped.
+pub fn encode(output: &[u8], encoded: &mut Vec<u8>) {
+    for &byte in output {
+        match byte {
+            b'\n' => encoded.extend_from_slice(b"\r\n"),
+            IAC => encoded.extend_from_slice(&[IAC, IAC]),
+            byte => encoded.push(byte),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Lists the tasks that were spawned with a restart policy,
+//! along with how many times each has been restarted and how it last exited.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate spawn;
+extern crate task;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+use task::RestartPolicy;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => { 
+            println!("{} \n", _f);
+            return -1; 
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts)
+    }
+
+    let supervised = spawn::supervised_tasks();
+    println!("{0:<5}  {1:<10}  {2:<8}  {3:<7}  {4:<30}  {5}", "SID", "POLICY", "RESTARTS", "CURRENT", "NAME", "LAST EXIT");
+    for (supervision_id, st) in supervised.iter() {
+        let policy = match st.policy {
+            RestartPolicy::Never     => "Never",
+            RestartPolicy::OnFailure => "OnFailure",
+            RestartPolicy::Always    => "Always",
+        };
+        let current = st.current_task_id.map(|id| format!("{}", id)).unwrap_or_else(|| String::from("-"));
+        let last_exit = st.last_exit.as_ref().map(|e| e.as_str()).unwrap_or("-");
+        println!("{0:<5}  {1:<10}  {2:<8}  {3:<7}  {4:<30}  {5}", supervision_id, policy, st.restart_count, current, st.name, last_exit);
+    }
+    println!("Total number of supervised tasks: {}", supervised.len());
+
+    0
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: restarts [options] \n \n");
+
+    brief.push_str("SID is the supervision ID, the task ID of the first instance of a supervised task. \n");
+    brief.push_str("POLICY is when the task is restarted after it exits. \n");
+    brief.push_str("RESTARTS is the number of times the task has been restarted. \n");
+    brief.push_str("CURRENT is the task ID of its current instance, or '-' if it exited and was not restarted. \n");
+    brief.push_str("LAST EXIT describes how the most recent instance of the task exited.");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
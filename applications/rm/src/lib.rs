@@ -8,6 +8,7 @@ extern crate getopts;
 extern crate path;
 extern crate fs_node;
 extern crate root;
+extern crate access_control;
 
 use alloc::vec::Vec;
 use alloc::string::String;
@@ -76,13 +77,15 @@ pub fn remove_node(args: Vec<String>) -> Result<(), String> {
         let path_error = || { format!("Couldn't remove {} from its parent directory.", &path) };
         let parent = node_to_delete.get_parent_dir().ok_or_else(path_error)?;
 
+        // Removal checks that this task may modify the parent directory.
+        let unlink_error = |e| { format!("Couldn't remove {}: {}", &path, e) };
         match node_to_delete {
             FileOrDir::File(_) => {
-                parent.lock().remove(&node_to_delete).ok_or_else(path_error)?;
+                access_control::unlink(&parent, &node_to_delete).map_err(unlink_error)?;
             } 
             FileOrDir::Dir(_) => {
                 if can_remove_dirs {
-                    parent.lock().remove(&node_to_delete).ok_or_else(path_error)?;
+                    access_control::unlink(&parent, &node_to_delete).map_err(unlink_error)?;
                 } else {
                     println!("Skipping the removal of directory '{}', try specifying the \"-r\" flag", 
                         node_to_delete.get_name());
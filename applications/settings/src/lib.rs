@@ -0,0 +1,160 @@
+//! A graphical settings app, which lets the user choose the system's time zone and the opacity of the settings window.
+//!
+//! This app mostly serves as a demo of the `gui_widgets` toolkit, as it uses all of its widgets and a nested layout.
+//! Changes take effect when the "Apply" button is clicked or Enter is pressed in the opacity input box.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+extern crate spin;
+extern crate event_types;
+extern crate gui_widgets;
+extern crate keycodes_ascii;
+extern crate scheduler;
+extern crate shapes;
+extern crate wall_clock;
+extern crate window;
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+use event_types::Event;
+use gui_widgets::{Button, Gui, Label, Layout, ScrollList, TextInput};
+use keycodes_ascii::{KeyAction, Keycode};
+use shapes::Coord;
+use spin::Mutex;
+use wall_clock::TimeZone;
+use window::Window;
+
+
+const WINDOW_POSITION: Coord = Coord { x: 120, y: 80 };
+const WINDOW_WIDTH: usize = 520;
+const WINDOW_HEIGHT: usize = 380;
+
+/// The smallest window opacity that can be chosen, which keeps the window visible enough to change it back.
+const MIN_OPACITY: u8 = 32;
+
+
+/// What the user asked for via the widgets' callbacks, which the main loop carries out.
+#[derive(Default)]
+struct Requests {
+    apply: bool,
+    close: bool,
+}
+
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), &'static str> {
+    let requests = Arc::new(Mutex::new(Requests::default()));
+
+    let clock = Arc::new(Mutex::new(Label::new("")));
+    let status = Arc::new(Mutex::new(Label::new("Choose a time zone and an opacity, then click Apply.")));
+
+    // the time zones that can be chosen, from UTC-12:00 to UTC+14:00 in steps of one hour
+    let zones: Vec<String> = (-12 ..= 14)
+        .filter_map(|hours| TimeZone::from_offset_seconds(hours * 3600).ok())
+        .map(|zone| zone.to_string())
+        .collect();
+    let current_zone = wall_clock::time_zone().to_string();
+    let current_index = zones.iter().position(|zone| *zone == current_zone);
+    let zone_list = {
+        let status = status.clone();
+        let mut list = ScrollList::new(zones, 8, 12).on_select(move |_index, zone| {
+            status.lock().set_text(&format!("Selected time zone {}.", zone));
+        });
+        list.select(current_index);
+        Arc::new(Mutex::new(list))
+    };
+
+    let opacity_input = {
+        let requests = requests.clone();
+        let mut input = TextInput::new(5).on_submit(move |_text| requests.lock().apply = true);
+        input.set_text("255");
+        Arc::new(Mutex::new(input))
+    };
+    let apply_button = {
+        let requests = requests.clone();
+        Arc::new(Mutex::new(Button::new("Apply").on_click(move || requests.lock().apply = true)))
+    };
+    let close_button = {
+        let requests = requests.clone();
+        Arc::new(Mutex::new(Button::new("Close").on_click(move || requests.lock().close = true)))
+    };
+
+    let layout = Layout::column()
+        .padding(10)
+        .with(clock.clone())
+        .with(Arc::new(Mutex::new(Label::new("Time zone:"))))
+        .with_stretched(zone_list.clone())
+        .with_layout(Layout::row()
+            .with(Arc::new(Mutex::new(Label::new("Window opacity (32-255):"))))
+            .with(opacity_input.clone())
+        )
+        .with(status.clone())
+        .with_layout(Layout::row()
+            .with(apply_button)
+            .with(close_button)
+        );
+
+    let window = Window::new(WINDOW_POSITION, WINDOW_WIDTH, WINDOW_HEIGHT, gui_widgets::BACKGROUND_COLOR)?;
+    let mut gui = Gui::new(window, layout, gui_widgets::BACKGROUND_COLOR);
+    gui.focus(&(zone_list.clone() as gui_widgets::WidgetRef));
+
+    loop {
+        while let Some(event) = gui.handle_events()? {
+            match event {
+                Event::ExitEvent => return Ok(()),
+                Event::KeyboardEvent(ref input_event)
+                    if input_event.key_event.keycode == Keycode::Escape && input_event.key_event.action == KeyAction::Pressed =>
+                {
+                    return Ok(());
+                }
+                _ => { }
+            }
+        }
+
+        let requested = mem::take(&mut *requests.lock());
+        if requested.close {
+            return Ok(());
+        }
+        if requested.apply {
+            let zone = zone_list.lock().selected_item().map(|zone| zone.to_string());
+            let opacity = opacity_input.lock().text().trim().parse::<u8>();
+            let message = apply(&mut gui, zone, opacity.ok());
+            status.lock().set_text(&message);
+        }
+
+        clock.lock().set_text(&format!("Local time: {}", wall_clock::local_now()));
+        gui.update()?;
+        scheduler::schedule();
+    }
+}
+
+/// Applies the chosen time zone and window opacity, returning a message that describes the outcome.
+fn apply(gui: &mut Gui, zone: Option<String>, opacity: Option<u8>) -> String {
+    let zone = match zone.as_ref().map(|zone| TimeZone::parse(zone)) {
+        Some(Ok(zone)) => zone,
+        Some(Err(e)) => return e.to_string(),
+        None => return "Please select a time zone.".to_string(),
+    };
+    let opacity = match opacity {
+        Some(opacity) if opacity >= MIN_OPACITY => opacity,
+        _ => return format!("The opacity must be a number from {} to 255.", MIN_OPACITY),
+    };
+    wall_clock::set_time_zone(zone);
+    if let Err(e) = gui.window_mut().set_opacity(opacity) {
+        return e.to_string();
+    }
+    format!("Applied time zone {} and opacity {}.", zone, opacity)
+}
@@ -0,0 +1,106 @@
+//! The file that the shell's command history is persisted to, and the incremental search through that history.
+//!
+//! Each command that the user enters is appended to the history file as a line of its own,
+//! so that the history survives the shell and is shared by all shells that are started later.
+//! By default, the file is kept on the tmpfs mounted at `/tmp`.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use fs_node::FileRef;
+
+
+/// The absolute path of the file that the command history is persisted to.
+pub const HISTORY_FILE_PATH: &'static str = "/tmp/.shell_history";
+/// The maximum number of commands that are kept in the history file.
+/// Older commands are discarded when the history is loaded.
+pub const MAX_HISTORY_LEN: usize = 1000;
+
+
+/// The file that the command history is persisted to.
+pub struct HistoryFile {
+    file: FileRef,
+}
+
+impl HistoryFile {
+    /// Opens the history file, creating it if it doesn't yet exist, and returns it along with the commands in it,
+    /// from the oldest to the most recent one.
+    pub fn open() -> Result<(HistoryFile, Vec<String>), String> {
+        let root_dir = root::get_root();
+        let file = access_control::open_or_create_file(HISTORY_FILE_PATH, root_dir)
+            .map_err(|e| format!("{:?}: {}", HISTORY_FILE_PATH, e))?;
+
+        let contents = {
+            let mut contents = vec![0u8; file.lock().size()];
+            let count = access_control::read(&file, &mut contents, 0).map_err(|e| e.to_string())?;
+            contents.truncate(count);
+            contents
+        };
+        let mut commands: Vec<String> = String::from_utf8_lossy(&contents)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_string())
+            .collect();
+
+        // Rewrite the file if it has grown too long, keeping only the most recent commands.
+        if commands.len() > MAX_HISTORY_LEN {
+            commands.drain(.. commands.len() - MAX_HISTORY_LEN);
+            file.lock().truncate(0).map_err(|e| e.to_string())?;
+            let mut offset = 0;
+            for command in &commands {
+                offset += access_control::write(&file, format!("{}\n", command).as_bytes(), offset).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok((HistoryFile { file }, commands))
+    }
+
+    /// Appends the given command to the end of the history file.
+    pub fn append(&self, command: &str) -> Result<(), &'static str> {
+        let end = self.file.lock().size();
+        access_control::write(&self.file, format!("{}\n", command).as_bytes(), end)?;
+        Ok(())
+    }
+}
+
+
+/// The state of an incremental reverse search through the command history, which the user starts with Ctrl+R.
+pub struct HistorySearch {
+    /// The text that the user is searching for.
+    pub query: String,
+    /// The index in the command history of the most recent command that contains the query, if any.
+    pub found: Option<usize>,
+    /// The command line as it was before the search started, which is restored if the search is cancelled.
+    pub original_cmdline: String,
+    /// The number of characters of the search prompt that are currently displayed on the terminal.
+    pub displayed_len: usize,
+}
+
+impl HistorySearch {
+    /// Starts a new search for the given command line.
+    pub fn new(original_cmdline: String) -> HistorySearch {
+        HistorySearch {
+            query: String::new(),
+            found: None,
+            original_cmdline,
+            displayed_len: 0,
+        }
+    }
+
+    /// Searches the `history` backwards for the query, starting at the command before index `before`.
+    /// If no command contains the query, the previous match (if any) is kept and `false` is returned.
+    pub fn search(&mut self, history: &[String], before: usize) -> bool {
+        let before = core::cmp::min(before, history.len());
+        match history[..before].iter().rposition(|command| command.contains(self.query.as_str())) {
+            Some(index) => {
+                self.found = Some(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the text that shows the state of this search on the terminal, given whether the last search succeeded.
+    pub fn display_text(&self, history: &[String], succeeded: bool) -> String {
+        let matched = self.found.and_then(|index| history.get(index)).map(|command| command.as_str()).unwrap_or("");
+        format!("({}reverse-i-search)`{}': {}", if succeeded { "" } else { "failed " }, self.query, matched)
+    }
+}
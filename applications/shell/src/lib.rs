@@ -24,10 +24,15 @@ extern crate terminal_print;
 extern crate print;
 extern crate environment;
 extern crate libterm;
+extern crate pipe;
+extern crate access_control;
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
 
+mod pipeline;
+mod history;
+
 use event_types::{Event};
 use keycodes_ascii::{Keycode, KeyAction, KeyEvent};
 use alloc::string::{String, ToString};
@@ -38,7 +43,7 @@ use libterm::Terminal;
 use dfqueue::{DFQueue, DFQueueConsumer, DFQueueProducer};
 use alloc::sync::Arc;
 use spin::{Mutex, MutexGuard};
-use environment::Environment;
+use environment::{Environment, Credentials};
 use core::mem;
 use alloc::collections::BTreeMap;
 use stdio::{Stdio, KeyEventQueue, KeyEventQueueReader, KeyEventQueueWriter,
@@ -46,7 +51,8 @@ use stdio::{Stdio, KeyEventQueue, KeyEventQueueReader, KeyEventQueueWriter,
 use bare_io::Write;
 use core::ops::Deref;
 use app_io::{IoStreams, IoControlFlags};
-use fs_node::FileOrDir;
+use fs_node::{FileOrDir, FileRef};
+use history::{HistoryFile, HistorySearch};
 
 /// The status of a job.
 #[derive(PartialEq)]
@@ -61,7 +67,8 @@ enum JobStatus {
 /// This structure is used by shell to track its spawned applications. Each successfully
 /// evaluated command line will create a `Job`. Each job contains one or more tasks.
 /// Tasks are stored in `tasks` in the same sequence as in the command line.
-/// When pipe is used, the i-th job's `stdout` is directed to the (i+1)-th job's `stdin`.
+/// Each task has its own `stdin`, `stdout` and `stderr` queues. When pipe is used, the i-th task's `stdout`
+/// is connected to the (i+1)-th task's `stdin` through a kernel pipe; see the `pipeline` module.
 /// `stderr` is always read by shell and currently cannot be redirected.
 struct Job {
     /// References to the tasks that form this job. They are stored in the same sequence as
@@ -72,29 +79,39 @@ struct Job {
     task_ids: Vec<usize>,
     /// Status of the job.
     status: JobStatus,
-    /// The stdio queues between the running application and the shell, or between
-    /// running applications if pipe is used. Assume there are N tasks, counting from 0
-    /// to (N-1). `pipe_queues[0]` is the input for the 0-th task. `pipe_queues[N]` is the
-    /// output from the last task to shell. Other queues are the pipes between two
-    /// applications. Note that there are (N+1) queues in total.
-    pipe_queues: Vec<Stdio>,
+    /// The stdin queues of the tasks, stored in the same sequence as in `tasks`.
+    stdin_queues: Vec<Stdio>,
+    /// The stdout queues of the tasks, stored in the same sequence as in `tasks`.
+    stdout_queues: Vec<Stdio>,
     /// The stderr queues between the running applications and the shell. Assume there are
     /// N tasks, counting from 0 to (N-1). All of these queues are sending byte streams from
     /// the applications to the shell, and currently cannot be redirected. Note that there
     /// are N queues in total.
     stderr_queues: Vec<Stdio>,
-    /// The input writer of the job. It is the writer of `pipe_queues[0]`.
-    stdin_writer: StdioWriter,
-    /// The output reader of the job. It is the reader of `pipe_queues[N]`.
-    stdout_reader: StdioReader,
+    /// The input writer of the job. It is the writer of `stdin_queues[0]`,
+    /// or `None` if the first task reads its input from a file.
+    stdin_writer: Option<StdioWriter>,
+    /// The output reader of the job. It is the reader of the last task's stdout queue,
+    /// or `None` if the last task writes its output to a file.
+    stdout_reader: Option<StdioReader>,
     /// Command line that was used to create the job.
     cmd: String
 }
 
 /// A main function that spawns a new shell and waits for the shell loop to exit before returning an exit value
-pub fn main(_args: Vec<String>) -> isize {
+///
+/// The shell's terminal is attached to the virtual terminal given as `--vt <NUMBER>`, or to the first free one otherwise.
+pub fn main(args: Vec<String>) -> isize {
+    let virtual_terminal = match parse_virtual_terminal(&args) {
+        Ok(vt) => vt,
+        Err(err) => {
+            error!("{}", err);
+            return -1;
+        }
+    };
+
     {
-        let _task_ref = match spawn::new_task_builder(shell_loop, ())
+        let _task_ref = match spawn::new_task_builder(shell_loop, virtual_terminal)
             .name("shell_loop".to_string())
             .spawn() {
             Ok(task_ref) => { task_ref }
@@ -124,6 +141,17 @@ pub fn main(_args: Vec<String>) -> isize {
     // return 0;
 }
 
+/// Parses the `--vt <NUMBER>` argument, which selects the virtual terminal that the shell attaches to.
+fn parse_virtual_terminal(args: &[String]) -> Result<Option<usize>, &'static str> {
+    match args {
+        [] => Ok(None),
+        [flag, number] if flag == "--vt" => number.parse::<usize>()
+            .map(Some)
+            .map_err(|_| "shell: the virtual terminal must be a number"),
+        _ => Err("shell: usage: shell [--vt <NUMBER>]"),
+    }
+}
+
 /// Errors when attempting to invoke an application from the terminal. 
 enum AppErr {
     /// The command does not match the name of any existing application in the 
@@ -133,7 +161,18 @@ enum AppErr {
     NamespaceErr,
     /// The terminal could not spawn a new task to run the new application.
     /// Includes the String error returned from the task spawn function.
-    SpawnErr(String)
+    SpawnErr(String),
+    /// The command line is not a valid pipeline.
+    SyntaxErr(&'static str),
+    /// A file that the command line redirects input or output to could not be opened.
+    RedirectErr(String),
+}
+
+/// The files that the first task of a job reads its input from and the last task writes its output to, if any.
+struct Redirections {
+    input: Option<FileRef>,
+    /// The file and the offset at which writing starts.
+    output: Option<(FileRef, usize)>,
 }
 
 struct Shell {
@@ -145,6 +184,9 @@ struct Shell {
     key_event_consumer: Arc<Mutex<Option<KeyEventQueueReader>>>,
     /// Writer to the key event queue.
     key_event_producer: KeyEventQueueWriter,
+    /// The shell's own reader to the key event queue, which it uses to read key events while there is no
+    /// foreground job, even if a stopped job has taken the reader in `key_event_consumer` and not returned it.
+    own_key_event_reader: KeyEventQueueReader,
     /// Foreground job number.
     fg_job_num: Option<isize>,
     /// The string that stores the users keypresses after the prompt
@@ -159,6 +201,10 @@ struct Shell {
     /// When someone enters some commands, but before pressing `enter` it presses `up` to see previous commands,
     /// we must push it to command_history. We don't want to push it twice.
     buffered_cmd_recorded: bool,
+    /// The file that the command history is persisted to, if it could be opened.
+    history_file: Option<HistoryFile>,
+    /// The ongoing incremental search through the command history, if the user started one with Ctrl+R.
+    history_search: Option<HistorySearch>,
     /// The consumer to the terminal's print dfqueue
     print_consumer: DFQueueConsumer<Event>,
     /// The producer to the terminal's print dfqueue
@@ -172,7 +218,9 @@ struct Shell {
 impl Shell {
     /// Create a new shell. Currently the shell will bind to the default terminal instance provided
     /// by the `app_io` crate.
-    fn new() -> Result<Shell, &'static str> {
+    ///
+    /// The shell's terminal is attached to the given virtual terminal, or to the first free one if `None`.
+    fn new(virtual_terminal: Option<usize>) -> Result<Shell, &'static str> {
         // Initialize a dfqueue for the terminal object to handle printing from applications.
         // Note that this is only to support legacy output. Newly developed applications should
         // turn to use `stdio` provided by the `stdio` crate together with the support of `app_io`.
@@ -191,21 +239,38 @@ impl Shell {
 
         let env = Environment {
             working_dir: Arc::clone(root::get_root()), 
+            variables: BTreeMap::new(),
+            credentials: Credentials::system(),
+        };
+
+        let terminal = match virtual_terminal {
+            Some(number) => Terminal::with_virtual_terminal(number)?,
+            None => Terminal::new()?,
         };
+        let terminal = Arc::new(Mutex::new(terminal));
 
-        let terminal = Arc::new(Mutex::new(Terminal::new()?));
+        let (history_file, command_history) = match HistoryFile::open() {
+            Ok((history_file, command_history)) => (Some(history_file), command_history),
+            Err(e) => {
+                warn!("shell: failed to open the history file {:?}: {}", history::HISTORY_FILE_PATH, e);
+                (None, Vec::new())
+            }
+        };
 
         Ok(Shell {
             jobs: BTreeMap::new(),
             task_to_job: BTreeMap::new(),
+            own_key_event_reader: key_event_consumer.clone(),
             key_event_consumer: Arc::new(Mutex::new(Some(key_event_consumer))),
             key_event_producer,
             fg_job_num: None,
             cmdline: String::new(),
             input_buffer: String::new(),
-            command_history: Vec::new(),
+            command_history,
             history_index: 0,
             buffered_cmd_recorded: false,
+            history_file,
+            history_search: None,
             print_consumer,
             print_producer,
             env: Arc::new(Mutex::new(env)),
@@ -390,12 +455,138 @@ impl Shell {
         Ok(())
     }
 
+    /// Returns the index in the command line of the character under the cursor.
+    fn cursor_index(&self) -> usize {
+        self.cmdline.len().saturating_sub(self.terminal.lock().get_cursor_offset_from_end())
+    }
+
+    /// Remove `count` characters to the left of the cursor from the command line.
+    fn remove_chars_before_cursor(&mut self, count: usize) -> Result<(), &'static str> {
+        for _ in 0..count {
+            self.remove_char_from_cmdline(true, true)?;
+        }
+        Ok(())
+    }
+
+    /// Remove `count` characters from the command line, starting at the cursor.
+    fn remove_chars_after_cursor(&mut self, count: usize) -> Result<(), &'static str> {
+        for _ in 0..count {
+            self.remove_char_from_cmdline(false, true)?;
+        }
+        Ok(())
+    }
+
+    /// Start an incremental reverse search through the command history,
+    /// which replaces the command line with the search prompt until the search ends.
+    fn start_history_search(&mut self) -> Result<(), &'static str> {
+        // Forget the command line that was recorded when the user started cycling through the history.
+        if self.buffered_cmd_recorded {
+            self.command_history.pop();
+            self.buffered_cmd_recorded = false;
+        }
+        self.history_index = 0;
+        self.move_cursor_rightmost()?;
+        let original_cmdline = self.cmdline.clone();
+        self.clear_cmdline(true)?;
+        self.history_search = Some(HistorySearch::new(original_cmdline));
+        self.display_history_search(true)
+    }
+
+    /// Handle a key event during a history search. Returns `true` if the key was consumed by the search.
+    /// Otherwise, the search has ended with the matching command put into the command line,
+    /// and the key should be handled as usual.
+    fn handle_history_search_key(&mut self, keyevent: &KeyEvent) -> Result<bool, &'static str> {
+        // Pressing a modifier key on its own doesn't affect the search.
+        match keyevent.keycode {
+            Keycode::Control | Keycode::Alt | Keycode::LeftShift | Keycode::RightShift
+                | Keycode::CapsLock | Keycode::NumLock => return Ok(true),
+            _ => { }
+        }
+        let history_len = self.command_history.len();
+        let succeeded = {
+            let search = match self.history_search.as_mut() {
+                Some(search) => search,
+                None => return Ok(false),
+            };
+            let control = keyevent.modifiers.is_control();
+            if control && keyevent.keycode == Keycode::R {
+                // Find the next older match.
+                let before = search.found.unwrap_or(history_len);
+                search.search(&self.command_history, before)
+            } else if (control && keyevent.keycode == Keycode::G) || keyevent.keycode == Keycode::Escape {
+                self.end_history_search(false)?;
+                return Ok(true);
+            } else if keyevent.keycode == Keycode::Backspace {
+                search.query.pop();
+                search.found = None;
+                search.search(&self.command_history, history_len)
+            } else {
+                match keyevent.keycode.to_ascii(keyevent.modifiers) {
+                    Some(c) if !control && keyevent.keycode != Keycode::Enter && keyevent.keycode != Keycode::Tab => {
+                        // The current match may still contain the longer query.
+                        search.query.push(c);
+                        let before = search.found.map(|index| index + 1).unwrap_or(history_len);
+                        search.search(&self.command_history, before)
+                    }
+                    _ => {
+                        self.end_history_search(true)?;
+                        return Ok(false);
+                    }
+                }
+            }
+        };
+        self.display_history_search(succeeded)?;
+        Ok(true)
+    }
+
+    /// Redisplay the prompt of the ongoing history search, given whether the last search succeeded.
+    fn display_history_search(&mut self, succeeded: bool) -> Result<(), &'static str> {
+        let search = match self.history_search.as_mut() {
+            Some(search) => search,
+            None => return Ok(()),
+        };
+        let text = search.display_text(&self.command_history, succeeded);
+        let mut terminal = self.terminal.lock();
+        for _ in 0..search.displayed_len {
+            terminal.remove_char(1)?;
+        }
+        search.displayed_len = text.chars().count();
+        terminal.print_to_terminal(text);
+        Ok(())
+    }
+
+    /// End the ongoing history search, putting the matching command into the command line if `accept` is true,
+    /// or otherwise restoring the command line from before the search.
+    fn end_history_search(&mut self, accept: bool) -> Result<(), &'static str> {
+        let search = match self.history_search.take() {
+            Some(search) => search,
+            None => return Ok(()),
+        };
+        {
+            let mut terminal = self.terminal.lock();
+            for _ in 0..search.displayed_len {
+                terminal.remove_char(1)?;
+            }
+        }
+        let cmdline = match search.found.and_then(|index| self.command_history.get(index)) {
+            Some(command) if accept => command.clone(),
+            _ => search.original_cmdline,
+        };
+        self.set_cmdline(cmdline, true)
+    }
+
     fn handle_key_event(&mut self, keyevent: KeyEvent) -> Result<(), &'static str> {       
         // EVERYTHING BELOW HERE WILL ONLY OCCUR ON A KEY PRESS (not key release)
         if keyevent.action != KeyAction::Pressed {
             return Ok(()); 
         }
 
+        // While searching through the command history, keys edit the search query
+        // until a key that doesn't belong to the search accepts the match and is then handled as usual.
+        if self.history_search.is_some() && self.handle_history_search_key(&keyevent)? {
+            return Ok(());
+        }
+
         // Ctrl+C signals the shell to exit the job
         if keyevent.modifiers.is_control() && keyevent.keycode == Keycode::C {
             if let Some(ref fg_job_num) = self.fg_job_num {
@@ -410,6 +601,14 @@ impl Shell {
                     }
                 };
 
+                // First, give each task a chance to handle the request and exit on its own.
+                // Tasks without a kill request handler, or those that were already asked before
+                // (e.g., Ctrl+C was pressed twice), are forcibly killed below.
+                // This must be done before locking `app_io`, since the handlers may print output.
+                let task_refs: Vec<TaskRef> = task_refs.into_iter()
+                    .filter(|task_ref| !task_ref.request_kill())
+                    .collect();
+
                 // Lock the shared structure in `app_io` and then kill the running application
                 app_io::lock_and_execute(&move |_flags_guard: MutexGuard<BTreeMap<usize, IoControlFlags>>,
                                                 _streamss_guard: MutexGuard<BTreeMap<usize, IoStreams>>| {
@@ -461,6 +660,10 @@ impl Shell {
                         return Ok(());
                     }
                 };
+                // Characters typed for the stopped job are not handed to the next foreground job.
+                self.input_buffer.clear();
+                self.terminal.lock().print_to_terminal("^Z\n".to_string());
+                self.set_job_foreground(*fg_job_num, false);
 
                 // Lock the shared structure in `app_io` and then stop the running application
                 app_io::lock_and_execute(&move |_flags_guard: MutexGuard<BTreeMap<usize, IoControlFlags>>,
@@ -484,6 +687,7 @@ impl Shell {
                         }
                     }
                 });
+                // The job is marked as stopped and removed from the foreground by `task_handler()`.
             }
 
             return Ok(());
@@ -492,13 +696,36 @@ impl Shell {
         // Set EOF to the stdin of the foreground job.
         if keyevent.modifiers.is_control() && keyevent.keycode == Keycode::D {
             if let Some(ref fg_job_num) = self.fg_job_num {
-                if let Some(job) = self.jobs.get(fg_job_num) {
-                    job.stdin_writer.lock().set_eof();
+                if let Some(stdin_writer) = self.jobs.get(fg_job_num).and_then(|job| job.stdin_writer.as_ref()) {
+                    stdin_writer.lock().set_eof();
                 }
             }
             return Ok(());
         }
 
+        // Ctrl+R starts an incremental reverse search through the command history.
+        if keyevent.modifiers.is_control() && keyevent.keycode == Keycode::R {
+            if self.fg_job_num.is_none() {
+                self.start_history_search()?;
+            }
+            return Ok(());
+        }
+
+        // Readline-style line editing of the command line.
+        if keyevent.modifiers.is_control() && self.fg_job_num.is_none() {
+            let cursor_idx = self.cursor_index();
+            match keyevent.keycode {
+                Keycode::A => return self.move_cursor_leftmost(),
+                Keycode::E => return self.move_cursor_rightmost(),
+                Keycode::W => return self.remove_chars_before_cursor(cursor_idx - previous_word_start(&self.cmdline, cursor_idx)),
+                Keycode::U => return self.remove_chars_before_cursor(cursor_idx),
+                Keycode::K => return self.remove_chars_after_cursor(self.cmdline.len() - cursor_idx),
+                Keycode::Left => return self.update_cursor_pos(self.cmdline.len() - previous_word_start(&self.cmdline, cursor_idx)),
+                Keycode::Right => return self.update_cursor_pos(self.cmdline.len() - next_word_end(&self.cmdline, cursor_idx)),
+                _ => { }
+            }
+        }
+
         // Perform command line auto completion.
         if keyevent.keycode == Keycode::Tab {
             if self.fg_job_num.is_none() {
@@ -537,14 +764,23 @@ impl Shell {
                         let mut buffered_string = String::new();
                         mem::swap(&mut buffered_string, &mut self.input_buffer);
                         buffered_string.push('\n');
-                        job.stdin_writer.lock().write_all(buffered_string.as_bytes())
-                            .or(Err("shell failed to write to stdin"))?;
+                        if let Some(ref stdin_writer) = job.stdin_writer {
+                            stdin_writer.lock().write_all(buffered_string.as_bytes())
+                                .or(Err("shell failed to write to stdin"))?;
+                        }
                     },
                     _ => {}
                 }
                 return Ok(());
             } else { // start a new job
                 self.terminal.lock().print_to_terminal("\n".to_string());
+                if self.command_history.last() != Some(&cmdline) {
+                    if let Some(ref history_file) = self.history_file {
+                        if let Err(e) = history_file.append(&cmdline) {
+                            warn!("shell: failed to save the command to the history file: {}", e);
+                        }
+                    }
+                }
                 self.command_history.push(cmdline.clone());
                 self.command_history.dedup(); // Removes any duplicates
                 self.history_index = 0;
@@ -553,20 +789,18 @@ impl Shell {
                     self.execute_internal()?;
                     self.clear_cmdline(false)?;
                 } else { // shell invokes user programs
-                    let new_job_num = self.build_new_job()?;
-                    self.fg_job_num = Some(new_job_num);
-
                     // If the new job is to run in the background, then we should not put it to foreground.
-                    if let Some(last) = self.cmdline.split_whitespace().last() {
-                        if last == "&" {
-                            self.terminal.lock().print_to_terminal(
-                                format!("[{}] [running] {}\n", new_job_num, self.cmdline)
-                                .to_string()
-                            );
-                            self.fg_job_num = None;
-                            self.clear_cmdline(false)?;
-                            self.redisplay_prompt();
-                        }
+                    let background = split_background(&self.cmdline).1;
+                    let new_job_num = self.build_new_job(background)?;
+                    if background {
+                        self.terminal.lock().print_to_terminal(
+                            format!("[{}] [running] {}\n", new_job_num, self.cmdline)
+                            .to_string()
+                        );
+                        self.clear_cmdline(false)?;
+                        self.redisplay_prompt();
+                    } else {
+                        self.fg_job_num = Some(new_job_num);
                     }
                 }
             }
@@ -628,6 +862,11 @@ impl Shell {
             return self.move_cursor_right()
         }
 
+        // Control key combinations that aren't handled above don't type anything.
+        if keyevent.modifiers.is_control() {
+            return Ok(());
+        }
+
         // Tracks what the user has typed so far, excluding any keypresses by the backspace and Enter key, which are special and are handled directly below
         if keyevent.keycode.to_ascii(keyevent.modifiers).is_some() {
             match keyevent.keycode.to_ascii(keyevent.modifiers) {
@@ -638,8 +877,10 @@ impl Shell {
                         self.insert_char_to_input_buff(c, true)?;
                         if let Some(job) = self.jobs.get(&fg_job_num) {
                             if app_io::is_requesting_instant_flush(&job.task_ids[0])? {
-                                job.stdin_writer.lock().write_all(self.input_buffer.as_bytes())
-                                    .or(Err("shell failed to write to stdin"))?;
+                                if let Some(ref stdin_writer) = job.stdin_writer {
+                                    stdin_writer.lock().write_all(self.input_buffer.as_bytes())
+                                        .or(Err("shell failed to write to stdin"))?;
+                                }
                                 self.input_buffer.clear();
                             }
                         }
@@ -659,19 +900,31 @@ impl Shell {
 
     /// Create a single task. `cmd` is the name of the application. `args` are the provided
     /// arguments. It returns a task reference on success.
+    /// 
+    /// If `cmd` contains a path delimiter or ends with `".o"`, it is treated as the path of a crate object file
+    /// (relative to the current working directory) that can reside anywhere in the filesystem.
     fn create_single_task(&mut self, cmd: String, args: Vec<String>) -> Result<TaskRef, AppErr> {
 
-        // Check that the application actually exists
-        let namespace_dir = task::get_my_current_task()
-            .map(|t| t.get_namespace().dir().clone())
-            .ok_or(AppErr::NamespaceErr)?;
-        let cmd_crate_name = format!("{}-", cmd);
-        let mut matching_apps = namespace_dir.get_files_starting_with(&cmd_crate_name).into_iter();
-        let app_file = matching_apps.next();
-        let second_match = matching_apps.next(); // return an error if there are multiple matching apps 
-        let app_path = app_file.xor(second_match)
-            .map(|f| Path::new(f.lock().get_absolute_path()))
-            .ok_or(AppErr::NotFound(cmd))?;
+        let app_path = if cmd.contains('/') || cmd.ends_with(".o") {
+            // Load an out-of-tree application crate from the given path.
+            let working_dir = Arc::clone(&self.env.lock().working_dir);
+            match Path::new(cmd.clone()).get(&working_dir) {
+                Some(FileOrDir::File(f)) => Path::new(f.lock().get_absolute_path()),
+                _ => return Err(AppErr::NotFound(cmd)),
+            }
+        } else {
+            // Check that the application actually exists
+            let namespace_dir = task::get_my_current_task()
+                .map(|t| t.get_namespace().dir().clone())
+                .ok_or(AppErr::NamespaceErr)?;
+            let cmd_crate_name = format!("{}-", cmd);
+            let mut matching_apps = namespace_dir.get_files_starting_with(&cmd_crate_name).into_iter();
+            let app_file = matching_apps.next();
+            let second_match = matching_apps.next(); // return an error if there are multiple matching apps 
+            app_file.xor(second_match)
+                .map(|f| Path::new(f.lock().get_absolute_path()))
+                .ok_or(AppErr::NotFound(cmd))?
+        };
 
         let taskref = spawn::new_application_task_builder(app_path, None)
             .map_err(|e| AppErr::SpawnErr(e.to_string()))?
@@ -689,22 +942,29 @@ impl Shell {
     /// Evaluate the command line. It creates a sequence of jobs, which forms a chain of applications that
     /// pipe the output from one to the next, and finally back to the shell. If any task fails to start up,
     /// all tasks that have already been spawned will be killed immeidately before returning error.
-    fn eval_cmdline(&mut self) -> Result<Vec<TaskRef>, AppErr> {
+    ///
+    /// The files that the command line redirects input or output to are opened before any task is spawned,
+    /// and are returned along with the tasks.
+    fn eval_cmdline(&mut self) -> Result<(Vec<TaskRef>, Redirections), AppErr> {
 
         let cmdline = self.cmdline.clone();
-        let mut task_refs = Vec::new();
-
-        for single_task_cmd in cmdline.split("|") {
-            let mut args: Vec<String> = single_task_cmd.split_whitespace().map(|s| s.to_string()).collect();
-            let command = args.remove(0);
+        // The trailing `&` only decides whether the job runs in the background, so it is not passed on as an argument.
+        let (cmdline, _background) = split_background(&cmdline);
+        let stages = pipeline::parse(cmdline).map_err(AppErr::SyntaxErr)?;
+
+        let working_dir = Arc::clone(&self.env.lock().working_dir);
+        let input = match stages.first().and_then(|stage| stage.input.as_ref()) {
+            Some(path) => Some(pipeline::open_input_file(path, &working_dir).map_err(AppErr::RedirectErr)?),
+            None => None,
+        };
+        let output = match stages.last().and_then(|stage| stage.output.as_ref()) {
+            Some(redirect) => Some(pipeline::open_output_file(redirect, &working_dir).map_err(AppErr::RedirectErr)?),
+            None => None,
+        };
 
-            // If the last arg is `&`, remove it.
-            if let Some(last_arg) = args.last() {
-                if last_arg == "&" {
-                    args.pop();
-                }
-            }
-            match self.create_single_task(command, args) {
+        let mut task_refs = Vec::new();
+        for stage in stages {
+            match self.create_single_task(stage.command, stage.args) {
                 Ok(task_ref) => task_refs.push(task_ref),
 
                 // Once we run into an error, we must kill all previously spawned tasks in this command line.
@@ -718,43 +978,42 @@ impl Shell {
                 }
             }
         }
-        Ok(task_refs)
+        Ok((task_refs, Redirections { input, output }))
     }
 
     /// Start a new job in the shell by the command line.
-    fn build_new_job(&mut self) -> Result<isize, &'static str> {
+    /// A job started in the `background` cannot take the key event queue until it is moved to the foreground.
+    fn build_new_job(&mut self, background: bool) -> Result<isize, &'static str> {
         match self.eval_cmdline() {
-            Ok(task_refs) => {
+            Ok((task_refs, redirections)) => {
 
                 let mut task_ids = Vec::new();
-                let mut pipe_queues = Vec::new();
+                let mut stdin_queues = Vec::new();
+                let mut stdout_queues = Vec::new();
                 let mut stderr_queues = Vec::new();
 
                 for task_ref in &task_refs {
                     task_ids.push(task_ref.lock().id);
                 }
 
-                // Set up the chain of queues between applications, and between shell and applications.
-                // See the comments for `Job` to get a view of how queues are chained.
-                let first_stdio_queue = Stdio::new();
-                let job_stdin_writer = first_stdio_queue.get_writer();
-                let mut previous_queue_reader = first_stdio_queue.get_reader();
-                pipe_queues.push(first_stdio_queue);
+                // Give each task its own stdio queues. See the comments for `Job` to get a view of how they are connected.
                 for task_id in &task_ids {
-                    let stdio_queue_for_stdin_and_stdout = Stdio::new();
-                    let stdio_queue_for_stderr = Stdio::new();
+                    let stdin_queue = Stdio::new();
+                    let stdout_queue = Stdio::new();
+                    let stderr_queue = Stdio::new();
                     let streams = IoStreams::new(
-                        previous_queue_reader,
-                        stdio_queue_for_stdin_and_stdout.get_writer(),
-                        stdio_queue_for_stderr.get_writer(),
+                        stdin_queue.get_reader(),
+                        stdout_queue.get_writer(),
+                        stderr_queue.get_writer(),
                         self.key_event_consumer.clone(),
-                        self.terminal.clone(),
+                        Some(self.terminal.clone()),
                     );
                     app_io::insert_child_streams(*task_id, streams);
+                    app_io::set_foreground(task_id, !background)?;
 
-                    previous_queue_reader = stdio_queue_for_stdin_and_stdout.get_reader();
-                    stderr_queues.push(stdio_queue_for_stderr);
-                    pipe_queues.push(stdio_queue_for_stdin_and_stdout);
+                    stdin_queues.push(stdin_queue);
+                    stdout_queues.push(stdout_queue);
+                    stderr_queues.push(stderr_queue);
 
                     // Insert print event producer to `terminal_print` to support legacy output.
                     if let Err(msg) = terminal_print::add_child(*task_id, self.print_producer.obtain_producer()) {
@@ -763,13 +1022,27 @@ impl Shell {
                     }
                 }
 
-                let job_stdout_reader = previous_queue_reader;
+                // Connect the queues of the tasks to each other, to the shell, and to the redirected files.
+                let connected = Self::connect_stdio(&stdin_queues, &stdout_queues, redirections);
+                let (job_stdin_writer, job_stdout_reader) = match connected {
+                    Ok(endpoints) => endpoints,
+                    Err(msg) => {
+                        for task_ref in &task_refs {
+                            if let Err(kill_error) = task_ref.kill(KillReason::Requested) {
+                                error!("{}", kill_error);
+                            }
+                        }
+                        self.terminal.lock().print_to_terminal(format!("{}\n", msg).to_string());
+                        return Err(msg);
+                    }
+                };
 
                 let new_job = Job {
                     tasks: task_refs,
                     task_ids,
                     status: JobStatus::Running,
-                    pipe_queues,
+                    stdin_queues,
+                    stdout_queues,
                     stderr_queues,
                     stdin_writer: job_stdin_writer,
                     stdout_reader: job_stdout_reader,
@@ -804,6 +1077,8 @@ impl Shell {
                     AppErr::NotFound(command) => format!("{:?} command not found.\n", command),
                     AppErr::NamespaceErr      => format!("Failed to find directory of application executables.\n"),
                     AppErr::SpawnErr(e)       => format!("Failed to spawn new task to run command. Error: {}.\n", e),
+                    AppErr::SyntaxErr(e)      => format!("{}\n", e),
+                    AppErr::RedirectErr(e)    => format!("{}\n", e),
                 };
                 self.terminal.lock().print_to_terminal(err_msg);
                 if let Err(msg) = self.clear_cmdline(false) {
@@ -815,6 +1090,40 @@ impl Shell {
         }
     }
 
+    /// Connects the stdio queues of a job's tasks: the stdout of each task to the stdin of the next one through a pipe,
+    /// the stdin of the first task to a file or to the shell, and the stdout of the last task to a file or to the shell.
+    ///
+    /// Returns the writer that the shell sends input to the job with and the reader that it receives output from,
+    /// which are `None` if the input or output is redirected to a file.
+    fn connect_stdio(stdin_queues: &[Stdio], stdout_queues: &[Stdio], redirections: Redirections)
+        -> Result<(Option<StdioWriter>, Option<StdioReader>), &'static str>
+    {
+        let (first_stdin, last_stdout) = match (stdin_queues.first(), stdout_queues.last()) {
+            (Some(first_stdin), Some(last_stdout)) => (first_stdin, last_stdout),
+            _ => return Err("a job must have at least one task"),
+        };
+
+        for (stdout_queue, next_stdin_queue) in stdout_queues.iter().zip(stdin_queues.iter().skip(1)) {
+            pipeline::connect(stdout_queue.get_reader(), stdout_queue.get_writer(), next_stdin_queue.get_writer())?;
+        }
+
+        let stdin_writer = match redirections.input {
+            Some(file) => {
+                pipeline::feed_from_file(file, first_stdin.get_writer())?;
+                None
+            }
+            None => Some(first_stdin.get_writer()),
+        };
+        let stdout_reader = match redirections.output {
+            Some((file, offset)) => {
+                pipeline::drain_into_file(last_stdout.get_reader(), file, offset)?;
+                None
+            }
+            None => Some(last_stdout.get_reader()),
+        };
+        Ok((stdin_writer, stdout_reader))
+    }
+
     /// Try to match the incomplete command against all internal commands. Returns a
     /// vector that contains all matching results.
     fn find_internal_cmd_match(&mut self, incomplete_cmd: &String) -> Result<Vec<String>, &'static str> {
@@ -865,16 +1174,11 @@ impl Shell {
         // Stores all possible matches.
         let mut match_list = Vec::new();
 
-        let taskref = match task::get_my_current_task() {
-            Some(t) => t,
-            None => return Err("Failed to get task reference while completing cmdline.")
-        };
-
-        // Get current working dir.
-        let mut curr_wd = {
-            let locked_task = taskref.lock();
-            let curr_env = locked_task.env.lock();
-            Arc::clone(&curr_env.working_dir)
+        // Absolute paths are walked from the root directory, others from the current working dir.
+        let mut curr_wd = if incomplete_cmd.starts_with('/') {
+            Arc::clone(root::get_root())
+        } else {
+            Arc::clone(&self.env.lock().working_dir)
         };
 
         // Check if the last character is a slash.
@@ -917,7 +1221,7 @@ impl Shell {
         // Try to match the name of the file.
         let locked_working_dir = curr_wd.lock();
         let mut child_list = locked_working_dir.list(); 
-        child_list.reverse();
+        child_list.sort();
         for child in child_list.iter() {
             if child.starts_with(&incomplete_node) {
                 if let Some(_) = locked_working_dir.get_file(&child) {
@@ -1000,45 +1304,57 @@ impl Shell {
         Ok(())
     }
 
-    /// Automatically complete the half-entered command line if possible.
-    /// If there exists only one possibility, the half-entered command line is completed.
-    /// If there are several possibilities, it will show all possibilities.
-    /// Otherwise, it does nothing. It tries to match against all internal commands,
-    /// all applications in the namespace, and all valid file paths.
+    /// Automatically complete the half-entered word before the cursor if possible.
+    /// If there exists only one possibility, the word is completed.
+    /// If there are several possibilities, the word is extended by their common prefix,
+    /// or, if that isn't possible, all possibilities are shown.
+    /// Otherwise, it does nothing. A command name is matched against all internal commands
+    /// and all applications in the namespace, whereas arguments and the files of I/O redirections
+    /// are matched against all valid file paths.
     fn complete_cmdline(&mut self) -> Result<(), &'static str> {
 
-        // Get the last string slice in the pipe chain.
-        let cmdline = self.cmdline[0..self.cmdline.len()-self.terminal.lock().get_cursor_offset_from_end()].to_string();
-        let last_cmd_in_pipe = match cmdline.split("|").last() {
+        // Get the last stage in the pipe chain, up to the cursor.
+        let cmdline = self.cmdline[0..self.cursor_index()].to_string();
+        let last_cmd_in_pipe = match cmdline.rsplit('|').next() {
             Some(cmd) => cmd,
             None => return Ok(())
         };
 
         // Get the last word in the args (or maybe the command name itself).
-        let last_word_in_cmd = match last_cmd_in_pipe.split(" ").last() {
-            Some(word) => word.to_string(),
-            None => return Ok(())
+        let word_start = last_cmd_in_pipe.rfind(|c: char| c.is_whitespace() || c == '<' || c == '>')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let (before_word, last_word_in_cmd) = last_cmd_in_pipe.split_at(word_start);
+        let last_word_in_cmd = last_word_in_cmd.to_string();
+
+        // Only match against internal commands and applications within the namespace if we are entering the command,
+        // unless it is the path of an application crate object file. Otherwise, we are completing a file path.
+        let possible_names = if before_word.trim().is_empty() && !last_word_in_cmd.contains('/') {
+            let mut names = self.find_internal_cmd_match(&last_word_in_cmd)?;
+            names.extend(self.find_app_name_match(&last_word_in_cmd)?);
+            names.sort();
+            names.dedup();
+            names
+        } else {
+            self.find_file_path_match(&last_word_in_cmd)?
         };
-
-        // Try to find matches. Only match against internal commands and applications
-        // within the namespace if we are entering the command. Otherwise, we are trying
-        // to complete an argument, then we also include file paths to match against.
-        let mut possible_names = self.find_internal_cmd_match(&last_word_in_cmd)?;
-        possible_names.extend(self.find_app_name_match(&last_word_in_cmd)?.iter().cloned());
-        if !last_cmd_in_pipe.trim().is_empty() {
-            possible_names.extend(self.find_file_path_match(&last_word_in_cmd)?.iter().cloned());
+        if possible_names.is_empty() {
+            return Ok(());
         }
 
-        // If there is only one possiblity, complete the command line.
-        if possible_names.len() == 1 {
-            let char_num_to_pop = match last_word_in_cmd.split('/').last() {
-                Some(incomplete_basename) => incomplete_basename.len(),
-                None => last_word_in_cmd.len()
-            };
-            for _ in 0..char_num_to_pop {
-                self.remove_char_from_cmdline(true, true)?;
+        // All possibilities start with the part of the last word after its last slash.
+        let incomplete_basename = match last_word_in_cmd.rfind('/') {
+            Some(i) => &last_word_in_cmd[i + 1..],
+            None => &last_word_in_cmd[..]
+        };
+        let prefix = common_prefix(&possible_names);
+        if possible_names.len() == 1 || prefix.len() > incomplete_basename.len() {
+            let mut completion = prefix[incomplete_basename.len()..].to_string();
+            // A complete name is followed by a space, unless it is a directory that may be followed by a file name.
+            if possible_names.len() == 1 && !completion.ends_with('/') {
+                completion.push(' ');
             }
-            for c in possible_names[0].chars() {
+            for c in completion.chars() {
                 self.insert_char_to_cmdline(c, true)?;
             }
         } else { // Print our choice to the terminal.
@@ -1080,6 +1396,12 @@ impl Shell {
                                 }
                             },
 
+                            ExitValue::Panicked(panic_info) => {
+                                warn!("task [{}] panicked at {}", exited_task_id, panic_info);
+                                self.terminal.lock().print_to_terminal(
+                                    format!("task [{}] panicked at {}\n", exited_task_id, panic_info)
+                                );
+                            }
                             ExitValue::Killed(KillReason::Requested) => {
                                 // Nothing to do. We have already print "^C" while handling keyboard event.
                             },
@@ -1098,31 +1420,18 @@ impl Shell {
                         need_refresh = true;
 
                         // Set EOF flag for the stdin, stdout of the exited task.
-                        let mut pipe_queue_iter = job.pipe_queues.iter();
-                        let mut stderr_queue_iter = job.stderr_queues.iter();
-                        let mut task_id_iter = job.task_ids.iter();
-                        while let (Some(pipe_queue), Some(stderr_queue), Some(task_id))
-                            = (pipe_queue_iter.next(), stderr_queue_iter.next(), task_id_iter.next()) {
-
-                            // Find the exited task by matching task id.
-                            if *task_id == exited_task_id {
-
-                                // Set the EOF flag of its `stdin`, which effectively prevents it's
-                                // producer from writing more. (It returns an error upon writing to
-                                // the queue which has the EOF flag set.)
-                                pipe_queue.get_writer().lock().set_eof();
-
-                                // Also set the EOF of `stderr`.
-                                stderr_queue.get_writer().lock().set_eof();
-
-                                // Set the EOF flag of its `stdout`, which effectively notifies the reader
-                                // of the queue that the stream has ended. The `if let` clause should not
-                                // fail.
-                                if let Some(pipe_queue) = pipe_queue_iter.next() {
-                                    pipe_queue.get_writer().lock().set_eof();
-                                }
-                                break;
-                            }
+                        if let Some(index) = job.task_ids.iter().position(|task_id| *task_id == exited_task_id) {
+                            // Set the EOF flag of its `stdin`, which effectively prevents it's
+                            // producer from writing more. (It returns an error upon writing to
+                            // the queue which has the EOF flag set.)
+                            job.stdin_queues[index].get_writer().lock().set_eof();
+
+                            // Also set the EOF of `stderr`.
+                            job.stderr_queues[index].get_writer().lock().set_eof();
+
+                            // Set the EOF flag of its `stdout`, which effectively notifies the reader
+                            // of the queue (the shell, a file or the pipe to the next task) that the stream has ended.
+                            job.stdout_queues[index].get_writer().lock().set_eof();
                         }
                     }
 
@@ -1232,21 +1541,23 @@ impl Shell {
         // iterate through all jobs to see if they have something to print
         for (_job_num, job) in self.jobs.iter() {
 
-            // Deal with all stdout output.
-            let mut stdout = job.stdout_reader.lock();
-            match stdout.try_read(&mut buf) {
-                Ok(cnt) => {
-                    mem::drop(stdout);
-                    let s = String::from_utf8_lossy(&buf[0..cnt]);
-                    let mut locked_terminal = self.terminal.lock();
-                    locked_terminal.print_to_terminal(s.to_string());
-                    if cnt != 0 { need_refresh = true; }
-                },
-                Err(_) => {
-                    mem::drop(stdout);
-                    error!("failed to read from stdout");
-                }
-            };
+            // Deal with all stdout output, unless it is redirected to a file.
+            if let Some(ref stdout_reader) = job.stdout_reader {
+                let mut stdout = stdout_reader.lock();
+                match stdout.try_read(&mut buf) {
+                    Ok(cnt) => {
+                        mem::drop(stdout);
+                        let s = String::from_utf8_lossy(&buf[0..cnt]);
+                        let mut locked_terminal = self.terminal.lock();
+                        locked_terminal.print_to_terminal(s.to_string());
+                        if cnt != 0 { need_refresh = true; }
+                    },
+                    Err(_) => {
+                        mem::drop(stdout);
+                        error!("failed to read from stdout");
+                    }
+                };
+            }
 
             // Deal with all stderr output.
             for stderr in &job.stderr_queues {
@@ -1326,6 +1637,18 @@ impl Shell {
                         // the above function also refreshes the terminal display
                     }
 
+                    // Ctrl+Z is always handled by the shell, even if the foreground job
+                    // has taken the key event queue, such that any job can be suspended.
+                    Event::KeyboardEvent(ref input_event) if self.fg_job_num.is_some()
+                        && input_event.key_event.modifiers.is_control()
+                        && input_event.key_event.keycode == Keycode::Z =>
+                    {
+                        if let Err(e) = self.handle_key_event(input_event.key_event) {
+                            error!("{}", e);
+                        }
+                        need_refresh = true;
+                    }
+
                     // Handles ordinary keypresses
                     Event::KeyboardEvent(ref input_event) => {
                         self.key_event_producer.write_one(input_event.key_event);
@@ -1353,18 +1676,19 @@ impl Shell {
             // handle inputs
             need_refresh = false;
             loop {
-                let locked_consumer = self.key_event_consumer.lock();
-                if let Some(ref key_event_consumer) = locked_consumer.deref() {
-                    if let Some(key_event) = key_event_consumer.read_one() {
-                        mem::drop(locked_consumer); // drop the lock so that we can invoke the method on the next line
-                        if let Err(e) = self.handle_key_event(key_event) {
-                            error!("{}", e);
-                        }
-                        if key_event.action == KeyAction::Pressed { need_refresh = true; }
-                    } else { // currently the key event queue is empty, break the loop
-                        break;
+                let key_event = match self.key_event_consumer.lock().deref() {
+                    Some(ref key_event_consumer) => key_event_consumer.read_one(),
+                    // The reader was taken by a job that has since been stopped, so the shell owns the input again.
+                    None if self.fg_job_num.is_none() => self.own_key_event_reader.read_one(),
+                    // currently the key event queue is taken by the foreground job
+                    None => break,
+                };
+                if let Some(key_event) = key_event {
+                    if let Err(e) = self.handle_key_event(key_event) {
+                        error!("{}", e);
                     }
-                } else { // currently the key event queue is taken by an application
+                    if key_event.action == KeyAction::Pressed { need_refresh = true; }
+                } else { // currently the key event queue is empty, break the loop
                     break;
                 }
             }
@@ -1420,77 +1744,59 @@ impl Shell {
         Ok(())
     }
 
-    /// Execute `bg` command. It takes a job number and runs the in the background.
+    /// Execute `bg` command. It takes a job number and runs the stopped job in the background.
     fn execute_internal_bg(&mut self) -> Result<(), &'static str> {
-        let cmdline_copy = self.cmdline.clone();
-        let mut iter = cmdline_copy.split_whitespace();
-        iter.next();
-        let args: Vec<&str> = iter.collect();
-        if args.len() != 1 {
-            self.terminal.lock().print_to_terminal("Usage: bg %job_num\n".to_string());
-            return Ok(());
-        }
-        if let Some('%') = args[0].chars().nth(0) {
-            let job_num = args[0].chars().skip(1).collect::<String>();
-            if let Ok(job_num) = job_num.parse::<isize>() {
-                if let Some(job) = self.jobs.get_mut(&job_num) {
-                    for task_ref in &job.tasks {
-                        if !task_ref.lock().has_exited() {
-                            task_ref.unblock();
-                        }
-                        job.status = JobStatus::Running;
-                    }
-                    self.clear_cmdline(false)?;
-                    self.redisplay_prompt();
-                    return Ok(());
+        let job_num = match self.parse_job_arg("bg") {
+            Some(job_num) => job_num,
+            None => return Ok(()),
+        };
+        self.set_job_foreground(job_num, false);
+        if let Some(job) = self.jobs.get_mut(&job_num) {
+            for task_ref in &job.tasks {
+                if !task_ref.lock().has_exited() {
+                    task_ref.unblock();
                 }
-                self.terminal.lock().print_to_terminal(format!("No job number {} found!\n", job_num).to_string());
-                return Ok(());
             }
+            job.status = JobStatus::Running;
+            self.terminal.lock().print_to_terminal(format!("[{}] [running] {}\n", job_num, job.cmd).to_string());
         }
-        self.terminal.lock().print_to_terminal("Usage: bg %job_num\n".to_string());
+        self.clear_cmdline(false)?;
+        self.redisplay_prompt();
         Ok(())
     }
 
     /// Execute `fg` command. It takes a job number and runs the job in the foreground.
     fn execute_internal_fg(&mut self) -> Result<(), &'static str> {
-        let cmdline_copy = self.cmdline.clone();
-        let mut iter = cmdline_copy.split_whitespace();
-        iter.next();
-        let args: Vec<&str> = iter.collect();
-        if args.len() != 1 {
-            self.terminal.lock().print_to_terminal("Usage: fg %job_num\n".to_string());
-            return Ok(());
-        }
-        if let Some('%') = args[0].chars().nth(0) {
-            let job_num = args[0].chars().skip(1).collect::<String>();
-            if let Ok(job_num) = job_num.parse::<isize>() {
-                if let Some(job) = self.jobs.get_mut(&job_num) {
-                    self.fg_job_num = Some(job_num);
-                    for task_ref in &job.tasks {
-                        if !task_ref.lock().has_exited() {
-                            task_ref.unblock();
-                        }
-                        job.status = JobStatus::Running;
-                    }
-                    return Ok(());
+        let job_num = match self.parse_job_arg("fg") {
+            Some(job_num) => job_num,
+            None => return Ok(()),
+        };
+        // The job can take the key event queue again before it continues running.
+        self.set_job_foreground(job_num, true);
+        if let Some(job) = self.jobs.get_mut(&job_num) {
+            self.terminal.lock().print_to_terminal(format!("{}\n", job.cmd).to_string());
+            self.fg_job_num = Some(job_num);
+            self.input_buffer.clear();
+            for task_ref in &job.tasks {
+                if !task_ref.lock().has_exited() {
+                    task_ref.unblock();
                 }
-                self.terminal.lock().print_to_terminal(format!("No job number {} found!\n", job_num).to_string());
-                return Ok(());
             }
+            job.status = JobStatus::Running;
         }
-        self.terminal.lock().print_to_terminal("Usage: fg %job_num\n".to_string());
         Ok(())
     }
 
-    /// Execute `jobs` command. It lists all jobs.
+    /// Execute `jobs` command. It lists all jobs, marking the one that `fg` and `bg` act on by default with a `+`.
     fn execute_internal_jobs(&mut self) -> Result<(), &'static str> {
+        let current_job_num = self.current_job_num();
         for (job_num, job_ref) in self.jobs.iter() {
             let status = match &job_ref.status {
                 JobStatus::Running => "running",
                 JobStatus::Stopped => "stopped"
             };
-            self.terminal.lock().print_to_terminal(format!("[{}] [{}] {}\n", job_num, status, job_ref.cmd).to_string());
+            let marker = if Some(*job_num) == current_job_num { "+" } else { " " };
+            self.terminal.lock().print_to_terminal(format!("[{}]{} [{}] {}\n", job_num, marker, status, job_ref.cmd).to_string());
         }
         if self.jobs.is_empty() {
             self.terminal.lock().print_to_terminal("No running or stopped jobs.\n".to_string());
@@ -1499,11 +1805,100 @@ impl Shell {
         self.redisplay_prompt();
         Ok(())
     }
+
+    /// Returns the number of the job that `fg` and `bg` act on if no job number is given,
+    /// which is the most recent stopped job, or the most recent job if none is stopped.
+    fn current_job_num(&self) -> Option<isize> {
+        self.jobs.iter().rev()
+            .find(|(_, job)| job.status == JobStatus::Stopped)
+            .or_else(|| self.jobs.iter().next_back())
+            .map(|(job_num, _)| *job_num)
+    }
+
+    /// Parses the optional job number argument of the `fg` or `bg` command (`cmd`), given as `%job_num` or `job_num`.
+    /// If the argument is invalid or there is no such job, it prints a message, redisplays the prompt, and returns `None`.
+    fn parse_job_arg(&mut self, cmd: &str) -> Option<isize> {
+        let cmdline_copy = self.cmdline.clone();
+        let args: Vec<&str> = cmdline_copy.split_whitespace().skip(1).collect();
+        let job_num = match args.as_slice() {
+            [] => self.current_job_num().ok_or_else(|| "No current job.\n".to_string()),
+            [arg] => arg.trim_start_matches('%').parse::<isize>()
+                .map_err(|_| format!("Usage: {} [%job_num]\n", cmd))
+                .and_then(|job_num| if self.jobs.contains_key(&job_num) {
+                    Ok(job_num)
+                } else {
+                    Err(format!("No job number {} found!\n", job_num))
+                }),
+            _ => Err(format!("Usage: {} [%job_num]\n", cmd)),
+        };
+        match job_num {
+            Ok(job_num) => Some(job_num),
+            Err(msg) => {
+                self.terminal.lock().print_to_terminal(msg);
+                if let Err(e) = self.clear_cmdline(false) {
+                    error!("{}", e);
+                }
+                self.redisplay_prompt();
+                None
+            }
+        }
+    }
+
+    /// Moves all tasks of the given job into the foreground or the background in `app_io`,
+    /// which decides whether they may take the key event queue.
+    fn set_job_foreground(&self, job_num: isize, foreground: bool) {
+        if let Some(job) = self.jobs.get(&job_num) {
+            for task_id in &job.task_ids {
+                if let Err(e) = app_io::set_foreground(task_id, foreground) {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Splits a trailing `&`, which requests that the job runs in the background, off the given command line.
+/// Returns the rest of the command line and whether it ended with `&`.
+fn split_background(cmdline: &str) -> (&str, bool) {
+    let trimmed = cmdline.trim_end();
+    if trimmed.ends_with('&') {
+        (&trimmed[.. trimmed.len() - 1], true)
+    } else {
+        (cmdline, false)
+    }
+}
+
+/// Returns the index in `line` of the start of the word before `index`, skipping any whitespace in between.
+fn previous_word_start(line: &str, index: usize) -> usize {
+    let bytes = line.as_bytes();
+    let mut i = index;
+    while i > 0 && bytes[i - 1].is_ascii_whitespace() { i -= 1; }
+    while i > 0 && !bytes[i - 1].is_ascii_whitespace() { i -= 1; }
+    i
+}
+
+/// Returns the index in `line` of the end of the word after `index`, skipping any whitespace in between.
+fn next_word_end(line: &str, index: usize) -> usize {
+    let bytes = line.as_bytes();
+    let mut i = index;
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() { i += 1; }
+    while i < bytes.len() && !bytes[i].is_ascii_whitespace() { i += 1; }
+    i
+}
+
+/// Returns the longest prefix that all of the given non-empty list of `names` start with.
+fn common_prefix(names: &[String]) -> &str {
+    let first = &names[0];
+    let mut len = names[1..].iter().fold(first.len(), |len, name| {
+        first.bytes().zip(name.bytes()).take(len).take_while(|(a, b)| a == b).count()
+    });
+    while !first.is_char_boundary(len) { len -= 1; }
+    &first[..len]
 }
 
 
 /// Start a new shell. Shell::start() is an infinite loop, so normally we do not return from this function.
-fn shell_loop(mut _dummy: ()) -> Result<(), &'static str> {
-    Shell::new()?.start()?;
+fn shell_loop(virtual_terminal: Option<usize>) -> Result<(), &'static str> {
+    Shell::new(virtual_terminal)?.start()?;
     Ok(())
 }
@@ -0,0 +1,276 @@
+//! Parsing of pipelines and I/O redirections, and the relay tasks that connect the stdio of their stages.
+//!
+//! A command line such as `cat < in.txt | grep foo >> out.txt` consists of one or more stages separated by `|`.
+//! Each stage gets its own `stdin`, `stdout` and `stderr` queues. The shell connects them as follows:
+//! * the `stdout` of a stage is moved into a kernel pipe by one relay task,
+//!   and from that pipe into the `stdin` of the next stage by another relay task,
+//! * `< file` makes a relay task feed the file's contents into the `stdin` of the first stage,
+//! * `> file` and `>> file` make a relay task write the `stdout` of the last stage into the file,
+//!   truncating it or appending to it, respectively.
+//!
+//! The end of a stream propagates through the relays in both directions:
+//! once a stage exits, the shell sets the EOF flag of its queues,
+//! which makes the relays drop their end of the pipe and thus set the EOF flag of the neighbouring stage's queue.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bare_io::{Read, Write};
+use fs_node::{DirRef, FileOrDir, FileRef};
+use path::Path;
+use pipe::{PipeReader, PipeWriter};
+use stdio::{StdioReader, StdioWriter};
+use access_control::Access;
+
+
+/// The size of the buffer that relay tasks move bytes with.
+const RELAY_BUFFER_SIZE: usize = 256;
+
+
+/// One application in a pipeline, along with where it reads its input from and writes its output to
+/// if they are redirected to files.
+pub struct Stage {
+    /// The name of the application.
+    pub command: String,
+    /// The arguments of the application.
+    pub args: Vec<String>,
+    /// The path of the file given with `<`, which can only be used in the first stage.
+    pub input: Option<String>,
+    /// The file given with `>` or `>>`, which can only be used in the last stage.
+    pub output: Option<OutputRedirect>,
+}
+
+/// A redirection of a stage's `stdout` into a file.
+pub struct OutputRedirect {
+    /// The path of the file.
+    pub path: String,
+    /// Whether the output is appended to the file (`>>`) instead of replacing its contents (`>`).
+    pub append: bool,
+}
+
+/// The tokens that a command line consists of.
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    /// `|`
+    Pipe,
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+}
+
+/// Splits the command line into words and the operators `|`, `<`, `>` and `>>`,
+/// which don't need to be surrounded by whitespace.
+fn tokenize(cmdline: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut chars = cmdline.chars().peekable();
+    while let Some(c) = chars.next() {
+        let operator = match c {
+            '|' => Some(Token::Pipe),
+            '<' => Some(Token::Input),
+            '>' if chars.peek() == Some(&'>') => {
+                chars.next();
+                Some(Token::Append)
+            }
+            '>' => Some(Token::Output),
+            _ => None,
+        };
+        if operator.is_some() || c.is_whitespace() {
+            if !word.is_empty() {
+                tokens.push(Token::Word(core::mem::take(&mut word)));
+            }
+            tokens.extend(operator);
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    tokens
+}
+
+/// Parses the command line into the stages of a pipeline.
+pub fn parse(cmdline: &str) -> Result<Vec<Stage>, &'static str> {
+    let mut stages: Vec<Stage> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut input = None;
+    let mut output = None;
+
+    let mut tokens = tokenize(cmdline).into_iter();
+    loop {
+        match tokens.next() {
+            Some(Token::Word(word)) => words.push(word),
+            Some(redirect @ Token::Input) | Some(redirect @ Token::Output) | Some(redirect @ Token::Append) => {
+                let path = match tokens.next() {
+                    Some(Token::Word(path)) => path,
+                    _ => return Err("syntax error: expected a file name after a redirection"),
+                };
+                if redirect == Token::Input {
+                    input = Some(path);
+                } else {
+                    output = Some(OutputRedirect { path, append: redirect == Token::Append });
+                }
+            }
+            token => {
+                // The end of a stage, either at a `|` or at the end of the command line.
+                if words.is_empty() {
+                    return Err("syntax error: expected a command");
+                }
+                let command = words.remove(0);
+                stages.push(Stage {
+                    command,
+                    args: core::mem::take(&mut words),
+                    input: input.take(),
+                    output: output.take(),
+                });
+                if token.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let last = stages.len() - 1;
+    if stages.iter().skip(1).any(|stage| stage.input.is_some()) {
+        return Err("only the first command of a pipeline can redirect its input");
+    }
+    if stages.iter().take(last).any(|stage| stage.output.is_some()) {
+        return Err("only the last command of a pipeline can redirect its output");
+    }
+    Ok(stages)
+}
+
+
+/// Opens the file at the given `path`, relative to the `working_dir`, which the first stage of a pipeline reads from.
+pub fn open_input_file(path: &str, working_dir: &DirRef) -> Result<FileRef, String> {
+    match access_control::open(&Path::new(path.to_string()), working_dir, Access::Read) {
+        Ok(FileOrDir::File(file)) => Ok(file),
+        Ok(FileOrDir::Dir(_)) => Err(format!("{:?} is a directory", path)),
+        Err(e) => Err(format!("{:?}: {}", path, e)),
+    }
+}
+
+/// Opens the file of the given output redirection, relative to the `working_dir`, creating it if it doesn't yet exist.
+///
+/// Returns the file and the offset at which the output should be written,
+/// which is the end of the file when appending to it. Otherwise, the file is truncated.
+pub fn open_output_file(redirect: &OutputRedirect, working_dir: &DirRef) -> Result<(FileRef, usize), String> {
+    let path = &redirect.path;
+    let file = access_control::open_or_create_file(path, working_dir).map_err(|e| format!("{:?}: {}", path, e))?;
+    let offset = if redirect.append {
+        file.lock().size()
+    } else {
+        if file.lock().size() > 0 {
+            file.lock().truncate(0).map_err(|e| format!("{:?}: {}", path, e))?;
+        }
+        0
+    };
+    Ok((file, offset))
+}
+
+/// Connects the `stdout` of one stage to the `stdin` of the next stage through a new kernel pipe,
+/// spawning the two relay tasks that move bytes into and out of the pipe.
+///
+/// `stdout_writer` is only used to set the EOF flag of the first stage's `stdout` once the pipe is broken.
+pub fn connect(stdout_reader: StdioReader, stdout_writer: StdioWriter, stdin_writer: StdioWriter) -> Result<(), &'static str> {
+    let (pipe_writer, pipe_reader) = pipe::new_pipe_default();
+    spawn::new_task_builder(relay_into_pipe, (stdout_reader, stdout_writer, pipe_writer))
+        .name("shell_pipe_writer".to_string())
+        .spawn()?;
+    spawn::new_task_builder(relay_out_of_pipe, (pipe_reader, stdin_writer))
+        .name("shell_pipe_reader".to_string())
+        .spawn()?;
+    Ok(())
+}
+
+/// Spawns a relay task that writes the contents of the given `file` into the `stdin` of the first stage.
+pub fn feed_from_file(file: FileRef, stdin_writer: StdioWriter) -> Result<(), &'static str> {
+    spawn::new_task_builder(relay_from_file, (file, stdin_writer))
+        .name("shell_input_file".to_string())
+        .spawn()?;
+    Ok(())
+}
+
+/// Spawns a relay task that writes the `stdout` of the last stage into the given `file`, starting at `offset`.
+pub fn drain_into_file(stdout_reader: StdioReader, file: FileRef, offset: usize) -> Result<(), &'static str> {
+    spawn::new_task_builder(relay_into_file, (stdout_reader, file, offset))
+        .name("shell_output_file".to_string())
+        .spawn()?;
+    Ok(())
+}
+
+/// Moves bytes from a stage's `stdout` into a pipe until the stage exits or the pipe is broken,
+/// then drops the pipe's writer to mark the end of the stream.
+fn relay_into_pipe((stdout_reader, stdout_writer, pipe_writer): (StdioReader, StdioWriter, PipeWriter)) {
+    let mut buf = [0u8; RELAY_BUFFER_SIZE];
+    let mut stdout = stdout_reader.lock();
+    loop {
+        let count = match stdout.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        if pipe_writer.write_all(&buf[..count]).is_err() {
+            // The next stage has exited, so this stage can't write any more output.
+            stdout_writer.lock().set_eof();
+            break;
+        }
+    }
+}
+
+/// Moves bytes from a pipe into a stage's `stdin` until the pipe's writer is dropped or the stage exits,
+/// then drops the pipe's reader to break the pipe.
+fn relay_out_of_pipe((pipe_reader, stdin_writer): (PipeReader, StdioWriter)) {
+    let mut buf = [0u8; RELAY_BUFFER_SIZE];
+    loop {
+        let count = match pipe_reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        if stdin_writer.lock().write_all(&buf[..count]).is_err() {
+            return;
+        }
+    }
+    stdin_writer.lock().set_eof();
+}
+
+/// Writes the contents of a file into a stage's `stdin`, then sets its EOF flag.
+/// Each read is checked against the shell's credentials, which this relay task inherited.
+fn relay_from_file((file, stdin_writer): (FileRef, StdioWriter)) {
+    let mut buf = [0u8; RELAY_BUFFER_SIZE];
+    let mut offset = 0;
+    loop {
+        let count = match access_control::read(&file, &mut buf, offset) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        offset += count;
+        if stdin_writer.lock().write_all(&buf[..count]).is_err() {
+            return;
+        }
+    }
+    stdin_writer.lock().set_eof();
+}
+
+/// Writes a stage's `stdout` into a file, starting at the given offset, until the stage exits.
+/// Each write is checked against the shell's credentials, which this relay task inherited.
+fn relay_into_file((stdout_reader, file, mut offset): (StdioReader, FileRef, usize)) {
+    let mut buf = [0u8; RELAY_BUFFER_SIZE];
+    let mut stdout = stdout_reader.lock();
+    loop {
+        let count = match stdout.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        match access_control::write(&file, &buf[..count], offset) {
+            Ok(written) => offset += written,
+            Err(e) => {
+                error!("shell: failed to write output to file: {}", e);
+                break;
+            }
+        }
+    }
+}
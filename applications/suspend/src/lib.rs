@@ -0,0 +1,63 @@
+//! Suspends the system to RAM by entering the ACPI S3 sleep state,
+//! after which the system can be woken up, e.g., by pressing a key or the power button.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate power;
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use getopts::Options;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("l", "list", "list the devices that will be suspended, instead of suspending");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    if matches.opt_present("l") {
+        // Hooks are suspended in the reverse order that they were registered.
+        for name in power::suspend_hook_names().into_iter().rev() {
+            println!("{}", name);
+        }
+        return 0;
+    }
+
+    println!("Suspending to RAM...");
+    match power::suspend() {
+        Ok(_) => {
+            println!("Resumed.");
+            0
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn print_usage(opts: Options) {
+    let brief = format!("Usage: suspend [options]\n\n\
+        Suspends all devices and puts the system into the ACPI S3 (suspend to RAM) sleep state.\n\
+        This is only supported on single-core systems, e.g., QEMU with QEMU_CPUS=1.");
+    println!("{}", opts.usage(&brief));
+}
@@ -0,0 +1,60 @@
+//! Writes back all dirty pages in the page cache, like the POSIX `sync` command,
+//! and optionally prints statistics about the page cache.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate page_cache;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "stats", "print page cache statistics after syncing");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let ret = match page_cache::sync() {
+        Ok(written) => {
+            println!("Wrote back {} dirty pages.", written);
+            0
+        }
+        Err(e) => {
+            println!("Error: failed to write back dirty pages: {}", e);
+            -1
+        }
+    };
+
+    if matches.opt_present("s") {
+        let stats = page_cache::stats();
+        println!("cached pages: {} ({} dirty), hits: {}, misses: {}, write-backs: {}, evictions: {}",
+            stats.cached_pages, stats.dirty_pages, stats.hits, stats.misses, stats.writebacks, stats.evictions
+        );
+    }
+
+    ret
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: sync [OPTIONS]\n\n");
+
+    brief.push_str("Writes back all modified pages in the page cache to their block devices and files.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,180 @@
+//! Prints the last lines of files, or of its standard input.
+//!
+//! With `-f`, `tail` then follows the file as it grows, printing whatever is appended to it.
+//! The file is polled for changes through the VFS, so this works with files on any filesystem.
+
+#![no_std]
+extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate path;
+extern crate fs_node;
+extern crate bare_io;
+extern crate access_control;
+extern crate app_input;
+extern crate timer;
+
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+use getopts::Options;
+use path::Path;
+use fs_node::{DirRef, FileOrDir, FileRef};
+use bare_io::Write;
+use access_control::Access;
+use app_input::Input;
+
+
+/// The number of lines that are printed by default.
+const DEFAULT_LINES: usize = 10;
+/// The number of bytes read from the input at once.
+const BUFFER_SIZE: usize = 256;
+/// How often a followed file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("n", "lines", "print the last NUM lines instead of the last 10", "NUM");
+    opts.optflag("f", "follow", "keep printing the data that is appended to the file");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let lines = match matches.opt_str("n").map(|n| n.parse::<usize>()) {
+        Some(Ok(lines)) => lines,
+        Some(Err(_)) => {
+            println!("Error: invalid number of lines");
+            return -1;
+        }
+        None => DEFAULT_LINES,
+    };
+
+    let mut paths: Vec<&str> = matches.free.iter().map(|path| path.as_str()).collect();
+    if paths.is_empty() {
+        paths.push("-");
+    }
+    let follow = matches.opt_present("f");
+    if follow && paths.len() > 1 {
+        println!("Error: only a single file can be followed");
+        return -1;
+    }
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    // Each file is preceded by a header with its name if there are several files.
+    let show_names = paths.len() > 1;
+
+    let mut ret = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if show_names {
+            if i > 0 {
+                println!("");
+            }
+            println!("==> {} <==", if *path == "-" { "standard input" } else { path });
+        }
+        let result = Input::open(path, &curr_wd).and_then(|mut input| {
+            print_last_lines(&mut input, lines)?;
+            // The standard input can't be followed, as it ends once the application writing into it exits.
+            match input {
+                Input::File { file, offset } if follow => follow_file(path, &curr_wd, file, offset),
+                _ => Ok(()),
+            }
+        });
+        if let Err(e) = result {
+            println!("tail: {}", e);
+            ret = -1;
+        }
+    }
+    ret
+}
+
+/// Prints the last `lines` lines of `input`.
+fn print_last_lines(input: &mut Input, lines: usize) -> Result<(), &'static str> {
+    let mut last_lines = VecDeque::with_capacity(lines);
+    input.for_each_line(|line| {
+        if lines > 0 {
+            if last_lines.len() == lines {
+                last_lines.pop_front();
+            }
+            last_lines.push_back(line.to_string());
+        }
+        true
+    })?;
+    for line in last_lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Prints whatever is appended to the file at `path` from `offset` onwards, checking it for changes every `POLL_INTERVAL`.
+///
+/// If the file shrinks, it was truncated, so it is printed again from its start.
+/// If another file takes its place at `path`, that file is followed from its start instead.
+/// This only returns if the file can't be read or the output can't be written, e.g., because the rest of the pipeline exited,
+/// so it normally runs until the user stops it with Ctrl+C.
+fn follow_file(path: &str, working_dir: &DirRef, mut file: FileRef, mut offset: usize) -> Result<(), String> {
+    let stdout = app_io::stdout()?;
+    let mut buffer = [0u8; BUFFER_SIZE];
+    loop {
+        if let Ok(FileOrDir::File(current)) = access_control::open(&Path::new(path.to_string()), working_dir, Access::Read) {
+            if !is_same_file(&current, &file) {
+                println!("tail: {}: file replaced, following the new file", path);
+                file = current;
+                offset = 0;
+            }
+        }
+
+        let size = file.lock().size();
+        if size < offset {
+            println!("tail: {}: file truncated", path);
+            offset = 0;
+        }
+        while offset < size {
+            let count = access_control::read(&file, &mut buffer, offset)?;
+            if count == 0 {
+                break;
+            }
+            stdout.lock().write_all(&buffer[..count]).map_err(|_| "failed to write to stdout")?;
+            offset += count;
+        }
+
+        timer::sleep(POLL_INTERVAL)?;
+    }
+}
+
+/// Returns whether the two references refer to the same file.
+fn is_same_file(a: &FileRef, b: &FileRef) -> bool {
+    // Only compare the addresses of the files, not the vtables of the trait objects.
+    a.as_ref() as *const _ as *const u8 == b.as_ref() as *const _ as *const u8
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: tail [OPTIONS] [FILE...]\n\n");
+
+    brief.push_str("Prints the last 10 lines of each FILE.\n");
+    brief.push_str("If no FILE is given, or FILE is -, it reads the standard input, which can't be followed.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -0,0 +1,100 @@
+//! Simple filters that select captured frames by protocol, port, and host.
+//!
+//! A filter expression consists of any of the following primitives, optionally joined with `and`,
+//! all of which must match:
+//! * a protocol: `tcp`, `udp`, `icmp`, `arp`, `ip`, or `ip6`,
+//! * `port N`, which matches TCP and UDP segments from or to port N,
+//! * `host ADDR`, which matches IP and ARP packets from or to the address ADDR.
+
+use core::str::FromStr;
+use alloc::string::String;
+use smoltcp::wire::IpAddress;
+use packet::{Packet, Protocol};
+
+
+/// The protocols that a filter can select.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProtocolFilter {
+    Tcp,
+    Udp,
+    Icmp,
+    Arp,
+    Ipv4,
+    Ipv6,
+}
+
+/// A filter, which matches a frame if all of its primitives match.
+#[derive(Debug, Default)]
+pub struct Filter {
+    protocol: Option<ProtocolFilter>,
+    port: Option<u16>,
+    host: Option<IpAddress>,
+}
+
+impl Filter {
+    /// Parses the filter expression that consists of the given words,
+    /// where an empty expression matches every frame.
+    pub fn parse(words: &[String]) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        let mut words = words.iter().map(|word| word.as_str());
+        while let Some(word) = words.next() {
+            let protocol = match word {
+                "and" | "&&" => continue,
+                "port" => {
+                    let port = words.next().ok_or("expected a port number after \"port\"")?;
+                    let port = port.parse::<u16>().map_err(|_e| format!("invalid port {:?}", port))?;
+                    set_once(&mut filter.port, port, "port")?;
+                    continue;
+                }
+                "host" => {
+                    let host = words.next().ok_or("expected an IP address after \"host\"")?;
+                    let host = IpAddress::from_str(host).map_err(|_e| format!("invalid IP address {:?}", host))?;
+                    set_once(&mut filter.host, host, "host")?;
+                    continue;
+                }
+                "tcp" => ProtocolFilter::Tcp,
+                "udp" => ProtocolFilter::Udp,
+                "icmp" => ProtocolFilter::Icmp,
+                "arp" => ProtocolFilter::Arp,
+                "ip" => ProtocolFilter::Ipv4,
+                "ip6" => ProtocolFilter::Ipv6,
+                word => return Err(format!("unsupported filter primitive {:?}", word)),
+            };
+            set_once(&mut filter.protocol, protocol, "protocol")?;
+        }
+        Ok(filter)
+    }
+
+    /// Returns whether the given packet matches this filter.
+    pub fn matches(&self, packet: &Packet) -> bool {
+        let protocol_matches = match self.protocol {
+            None => true,
+            Some(ProtocolFilter::Tcp) => packet.protocol == Protocol::Tcp,
+            Some(ProtocolFilter::Udp) => packet.protocol == Protocol::Udp,
+            Some(ProtocolFilter::Icmp) => packet.protocol == Protocol::Icmp,
+            Some(ProtocolFilter::Arp) => packet.protocol == Protocol::Arp,
+            Some(ProtocolFilter::Ipv4) => packet.ip_version == Some(4),
+            Some(ProtocolFilter::Ipv6) => packet.ip_version == Some(6),
+        };
+        let port_matches = match (self.port, packet.ports) {
+            (None, _) => true,
+            (Some(port), Some((src_port, dst_port))) => port == src_port || port == dst_port,
+            (Some(_), None) => false,
+        };
+        let host_matches = match (self.host, packet.addrs) {
+            (None, _) => true,
+            (Some(host), Some((src_addr, dst_addr))) => host == src_addr || host == dst_addr,
+            (Some(_), None) => false,
+        };
+        protocol_matches && port_matches && host_matches
+    }
+}
+
+/// Sets the given primitive of a filter, which may only be given once.
+fn set_once<T>(primitive: &mut Option<T>, value: T, name: &str) -> Result<(), String> {
+    if primitive.is_some() {
+        return Err(format!("the {} may only be given once", name));
+    }
+    *primitive = Some(value);
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Captures the frames that the network interfaces send and receive, like a minimal `tcpdump`.
+//!
+//! Frames that match the filter expression given after the options are printed as one-line summaries,
+//! or saved to a pcap file with `-w`, which can be opened with Wireshark.
+//! The capture runs until `-c` frames have been captured or `Ctrl + C` is pressed.
+//!
+//! Examples:
+//! * `tcpdump -i eth0 tcp port 80`
+//! * `tcpdump -w dns.pcap udp and port 53 and host 10.0.2.3`
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate access_control;
+extern crate smoltcp;
+extern crate network_manager;
+extern crate pcap;
+extern crate async_runtime;
+
+mod packet;
+mod filter;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use getopts::{Matches, Options};
+use fs_node::FileRef;
+use async_runtime::Executor;
+use network_manager::capture::{self, CaptureOptions, CapturedFrame, Direction};
+use pcap::PcapWriter;
+use packet::Packet;
+use filter::Filter;
+
+
+/// How long to wait between taking the captured frames from the ring buffer.
+const POLL_INTERVAL_MS: u64 = 50;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("i", "interface", "capture only the frames of the interface IFACE (default: all interfaces)", "IFACE");
+    opts.optopt("c", "count", "exit after capturing N matching frames", "N");
+    opts.optopt("w", "write", "save the frames to FILE in the pcap format instead of printing them", "FILE");
+    opts.optopt("s", "snapshot-length", "capture at most N bytes of each frame (default: 65535)", "N");
+    opts.optopt("B", "buffer-size", "buffer up to N frames before dropping the oldest (default: 1024)", "N");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(&opts);
+    }
+
+    match rmain(&matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("tcpdump: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain(matches: &Matches) -> Result<(), String> {
+    let mut options = CaptureOptions::default();
+    options.interface = matches.opt_str("i");
+    if let Some(n) = matches.opt_str("s") {
+        options.snap_len = n.parse::<usize>().map_err(|_e| "couldn't parse snapshot length")?;
+    }
+    if let Some(n) = matches.opt_str("B") {
+        options.capacity = n.parse::<usize>().map_err(|_e| "couldn't parse buffer size")?;
+    }
+    let count = match matches.opt_str("c") {
+        Some(n) => Some(n.parse::<usize>().map_err(|_e| "couldn't parse count")?),
+        None => None,
+    };
+    let filter = Filter::parse(&matches.free)?;
+    if let Some(ref interface) = options.interface {
+        let exists = network_manager::NETWORK_INTERFACES.lock().iter().any(|iface| iface.lock().name() == interface);
+        if !exists {
+            return Err(format!("no such interface {:?}", interface));
+        }
+    }
+
+    let mut writer = match matches.opt_str("w") {
+        Some(path) => {
+            let file = get_or_create_file(&path)?;
+            let old_size = file.lock().size();
+            Some((PcapWriter::new(file, options.snap_len)?, path, old_size))
+        }
+        None => None,
+    };
+
+    // Ctrl + C only marks this task as asked to exit, rather than killing it, so that the capture is stopped cleanly.
+    task::set_my_kill_request_handler(Box::new(|| { }))?;
+    let is_stop_requested = || task::get_my_current_task().map(|t| t.is_kill_requested()).unwrap_or(true);
+
+    capture::start(options.clone())?;
+    println!("tcpdump: listening on {}, capture size {} bytes",
+        options.interface.as_ref().map(|i| i.as_str()).unwrap_or("all interfaces"), options.snap_len);
+
+    let executor = Executor::new();
+    let mut captured = 0;
+    let result = loop {
+        let frames = match capture::take_frames() {
+            Ok((frames, _dropped)) => frames,
+            Err(e) => break Err(e.to_string()),
+        };
+        let mut error = None;
+        for frame in frames.iter() {
+            let packet = Packet::parse(&frame.data);
+            if !filter.matches(&packet) {
+                continue;
+            }
+            let output = match writer {
+                Some((ref mut writer, _, _)) => writer.write_frame(frame),
+                None => {
+                    print_frame(frame, &packet);
+                    Ok(())
+                }
+            };
+            if let Err(e) = output {
+                error = Some(e.to_string());
+                break;
+            }
+            captured += 1;
+            if count == Some(captured) {
+                break;
+            }
+        }
+        if let Some(e) = error {
+            break Err(e);
+        }
+        if count == Some(captured) || is_stop_requested() {
+            break Ok(());
+        }
+        if let Err(e) = executor.block_on(async_runtime::sleep(POLL_INTERVAL_MS)) {
+            break Err(e.to_string());
+        }
+    };
+
+    let dropped = capture::stop().map(|(_frames, dropped)| dropped).unwrap_or(0);
+    println!("\n{} frames captured", captured);
+    if dropped > 0 {
+        println!("{} frames dropped because the buffer was full", dropped);
+    }
+    if let Some((writer, path, old_size)) = writer {
+        // Discard the rest of an existing file that was longer than the capture.
+        if old_size > writer.bytes_written() {
+            writer.file().lock().truncate(writer.bytes_written())?;
+        }
+        println!("Saved {} frames to {:?}", writer.frame_count(), path);
+    }
+    result
+}
+
+/// Prints a one-line summary of the given frame.
+fn print_frame(frame: &CapturedFrame, packet: &Packet) {
+    let direction = match frame.direction {
+        Direction::Received => "In",
+        Direction::Sent => "Out",
+    };
+    println!("{}.{:03} {} {} {}", frame.timestamp_ms / 1000, frame.timestamp_ms % 1000, frame.interface, direction, packet.summary);
+}
+
+/// Returns the file at the given path relative to the current working directory, if this task may write it,
+/// creating it if it doesn't yet exist.
+fn get_or_create_file(path: &str) -> Result<FileRef, String> {
+    let curr_wd = task::get_my_current_task()
+        .map(|t| Arc::clone(&t.lock().env.lock().working_dir))
+        .ok_or_else(|| String::from("failed to get current task"))?;
+
+    access_control::open_or_create_file(path, &curr_wd).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+fn print_usage(opts: &Options) -> isize {
+    let mut brief = format!("Usage: tcpdump [OPTION]... [EXPRESSION]\n \n");
+
+    brief.push_str("captures network frames that match EXPRESSION, which combines any of\n");
+    brief.push_str("  tcp | udp | icmp | arp | ip | ip6,  port N,  host ADDRESS\n");
+    brief.push_str("optionally joined with 'and'");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
@@ -0,0 +1,169 @@
+//! Dissecting captured Ethernet frames into the fields that filters match and the summaries that are printed.
+
+use alloc::string::String;
+use smoltcp::wire::{
+    EthernetFrame, EthernetProtocol, EthernetAddress,
+    ArpPacket, ArpOperation,
+    Ipv4Packet, Ipv4Address, Ipv6Packet, Ipv6Address, IpAddress, IpProtocol,
+    TcpPacket, UdpPacket, Icmpv4Packet, Icmpv6Packet,
+};
+
+
+/// The protocol of the innermost header of a frame that could be dissected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Arp,
+    Tcp,
+    Udp,
+    Icmp,
+    /// An IP packet whose payload isn't dissected further.
+    OtherIp,
+    /// A frame whose payload isn't dissected.
+    Other,
+}
+
+/// The fields of a frame that filters can match, along with a human-readable summary of the frame.
+pub struct Packet {
+    pub protocol: Protocol,
+    /// The IP version of the frame, which is `None` for non-IP frames.
+    pub ip_version: Option<u8>,
+    /// The source and destination addresses, i.e., the protocol addresses of an ARP packet or those of an IP packet.
+    pub addrs: Option<(IpAddress, IpAddress)>,
+    /// The source and destination ports of a TCP or UDP segment.
+    pub ports: Option<(u16, u16)>,
+    pub summary: String,
+}
+
+impl Packet {
+    /// Dissects the given frame, which may have been truncated to the snapshot length of the capture.
+    pub fn parse(frame: &[u8]) -> Packet {
+        let mut packet = Packet {
+            protocol: Protocol::Other,
+            ip_version: None,
+            addrs: None,
+            ports: None,
+            summary: String::new(),
+        };
+        let ethernet = match EthernetFrame::new_checked(frame) {
+            Ok(ethernet) => ethernet,
+            Err(_) => {
+                packet.summary = format!("truncated Ethernet frame, length {}", frame.len());
+                return packet;
+            }
+        };
+        match ethernet.ethertype() {
+            EthernetProtocol::Arp => packet.parse_arp(ethernet.payload()),
+            EthernetProtocol::Ipv4 => packet.parse_ipv4(ethernet.payload()),
+            EthernetProtocol::Ipv6 => packet.parse_ipv6(ethernet.payload()),
+            ethertype => {
+                packet.summary = format!("{} > {}, ethertype {}, length {}",
+                    ethernet.src_addr(), ethernet.dst_addr(), ethertype, frame.len());
+            }
+        }
+        packet
+    }
+
+    fn parse_arp(&mut self, payload: &[u8]) {
+        let arp = match ArpPacket::new_checked(payload) {
+            Ok(arp) if arp.protocol_len() == 4 && arp.hardware_len() == 6 => arp,
+            _ => {
+                self.summary = String::from("ARP, truncated or not for IPv4 over Ethernet");
+                return;
+            }
+        };
+        let source = Ipv4Address::from_bytes(arp.source_protocol_addr());
+        let target = Ipv4Address::from_bytes(arp.target_protocol_addr());
+        self.protocol = Protocol::Arp;
+        self.addrs = Some((source.into(), target.into()));
+        self.summary = match arp.operation() {
+            ArpOperation::Request => format!("ARP, Request who-has {} tell {}", target, source),
+            ArpOperation::Reply => format!("ARP, Reply {} is-at {}",
+                source, EthernetAddress::from_bytes(arp.source_hardware_addr())),
+            operation => format!("ARP, {:?} {} > {}", operation, source, target),
+        };
+    }
+
+    fn parse_ipv4(&mut self, payload: &[u8]) {
+        let ip = match Ipv4Packet::new_checked(payload) {
+            Ok(ip) => ip,
+            Err(_) => {
+                self.summary = String::from("IP, truncated");
+                return;
+            }
+        };
+        self.ip_version = Some(4);
+        self.addrs = Some((ip.src_addr().into(), ip.dst_addr().into()));
+        let transport = self.parse_transport(ip.protocol(), ip.payload());
+        self.summary = match self.ports {
+            Some((src_port, dst_port)) => format!("IP {}.{} > {}.{}: {}", ip.src_addr(), src_port, ip.dst_addr(), dst_port, transport),
+            None => format!("IP {} > {}: {}", ip.src_addr(), ip.dst_addr(), transport),
+        };
+    }
+
+    fn parse_ipv6(&mut self, payload: &[u8]) {
+        let ip = match Ipv6Packet::new_checked(payload) {
+            Ok(ip) => ip,
+            Err(_) => {
+                self.summary = String::from("IP6, truncated");
+                return;
+            }
+        };
+        let (src_addr, dst_addr): (Ipv6Address, Ipv6Address) = (ip.src_addr(), ip.dst_addr());
+        self.ip_version = Some(6);
+        self.addrs = Some((src_addr.into(), dst_addr.into()));
+        let transport = self.parse_transport(ip.next_header(), ip.payload());
+        self.summary = match self.ports {
+            Some((src_port, dst_port)) => format!("IP6 {}.{} > {}.{}: {}", src_addr, src_port, dst_addr, dst_port, transport),
+            None => format!("IP6 {} > {}: {}", src_addr, dst_addr, transport),
+        };
+    }
+
+    /// Dissects the payload of an IP packet, and returns its summary.
+    fn parse_transport(&mut self, protocol: IpProtocol, payload: &[u8]) -> String {
+        self.protocol = Protocol::OtherIp;
+        match protocol {
+            IpProtocol::Tcp => match TcpPacket::new_checked(payload) {
+                Ok(tcp) => {
+                    self.protocol = Protocol::Tcp;
+                    self.ports = Some((tcp.src_port(), tcp.dst_port()));
+                    let mut flags = String::new();
+                    for &(set, flag) in &[(tcp.syn(), 'S'), (tcp.fin(), 'F'), (tcp.rst(), 'R'), (tcp.psh(), 'P'), (tcp.ack(), '.')] {
+                        if set {
+                            flags.push(flag);
+                        }
+                    }
+                    let mut summary = format!("Flags [{}], seq {}", flags, tcp.seq_number().0 as u32);
+                    if tcp.ack() {
+                        summary.push_str(&format!(", ack {}", tcp.ack_number().0 as u32));
+                    }
+                    summary.push_str(&format!(", win {}, length {}", tcp.window_len(), tcp.payload().len()));
+                    summary
+                }
+                Err(_) => String::from("TCP, truncated"),
+            },
+            IpProtocol::Udp => match UdpPacket::new_checked(payload) {
+                Ok(udp) => {
+                    self.protocol = Protocol::Udp;
+                    self.ports = Some((udp.src_port(), udp.dst_port()));
+                    format!("UDP, length {}", udp.payload().len())
+                }
+                Err(_) => String::from("UDP, truncated"),
+            },
+            IpProtocol::Icmp => match Icmpv4Packet::new_checked(payload) {
+                Ok(icmp) => {
+                    self.protocol = Protocol::Icmp;
+                    format!("ICMP {}, length {}", icmp.msg_type(), payload.len())
+                }
+                Err(_) => String::from("ICMP, truncated"),
+            },
+            IpProtocol::Icmpv6 => match Icmpv6Packet::new_checked(payload) {
+                Ok(icmp) => {
+                    self.protocol = Protocol::Icmp;
+                    format!("ICMP6 {}, length {}", icmp.msg_type(), payload.len())
+                }
+                Err(_) => String::from("ICMP6, truncated"),
+            },
+            protocol => format!("{}, length {}", protocol, payload.len()),
+        }
+    }
+}
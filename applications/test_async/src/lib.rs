@@ -0,0 +1,122 @@
+//! Tests the `async_runtime` executor, including its timer futures and interrupt events.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate terminal_print;
+extern crate spawn;
+extern crate async_runtime;
+extern crate timer;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use alloc::{
+    vec::Vec,
+    string::String,
+    sync::Arc,
+};
+use async_runtime::{Executor, InterruptEvent};
+
+
+pub fn main(_args: Vec<String>) -> isize {
+    match rmain() {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("Error: {}", e);
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain() -> Result<(), &'static str> {
+    let executor = Executor::new();
+
+    // Test 1: a future that yields to the executor several times before completing.
+    let handle = executor.spawn_with_handle(Countdown { remaining: 5, polls: 0 });
+    let polls = executor.block_on(handle)?;
+    if polls != 6 {
+        return Err("Countdown future was polled the wrong number of times");
+    }
+    println!("Countdown future completed after {} polls.", polls);
+
+    // Test 2: a timer future.
+    let start = timer::Instant::now();
+    executor.block_on(async_runtime::sleep(100))?;
+    let elapsed = start.elapsed();
+    if elapsed < Duration::from_millis(100) {
+        return Err("sleep(100) completed too early");
+    }
+    println!("sleep(100) completed after {:?}.", elapsed);
+
+    // Test 3: an interrupt event notified by another task, as an interrupt handler would.
+    let event = Arc::new(InterruptEvent::new());
+    let handle = executor.spawn_with_handle(WaitForEvents { event: event.clone(), remaining: 3 });
+    let notifier = spawn::new_task_builder(
+        |event: Arc<InterruptEvent>| {
+            for _ in 0..3 {
+                event.notify();
+            }
+        },
+        event,
+    ).name(String::from("test_async_notifier")).spawn()?;
+
+    // Run the executor on its own dedicated task this time.
+    let executor_task = executor.spawn_executor_task(String::from("test_async_executor"))?;
+    notifier.join()?;
+    executor_task.join()?;
+    if handle.try_take().is_none() {
+        return Err("WaitForEvents future didn't complete");
+    }
+    println!("WaitForEvents future received all 3 notifications.");
+
+    println!("All async_runtime tests passed.");
+    Ok(())
+}
+
+
+/// A future that yields to the executor `remaining` times, then completes with the number of times it was polled.
+struct Countdown {
+    remaining: usize,
+    polls: usize,
+}
+
+impl Future for Countdown {
+    type Output = usize;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+        self.polls += 1;
+        if self.remaining == 0 {
+            return Poll::Ready(self.polls);
+        }
+        self.remaining -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+
+/// A future that completes once its `InterruptEvent` has been notified `remaining` times.
+struct WaitForEvents {
+    event: Arc<InterruptEvent>,
+    remaining: usize,
+}
+
+impl Future for WaitForEvents {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        loop {
+            let count = match Pin::new(&mut self.event.wait()).poll(cx) {
+                Poll::Ready(count) => count,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.remaining = self.remaining.saturating_sub(count);
+            if self.remaining == 0 {
+                return Poll::Ready(());
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Unmounts the filesystem mounted at a directory, putting back the directory it covered.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate vfs;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use fs_node::FsNode;
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return print_usage(opts);
+    }
+
+    let mut ret = 0;
+    for arg in matches.free.iter() {
+        let result = absolute_path(arg).and_then(|path| vfs::unmount(&path));
+        if let Err(e) = result {
+            println!("Error: couldn't unmount {}: {}", arg, e);
+            ret = -1;
+        }
+    }
+    ret
+}
+
+/// Returns the absolute path of the directory at the given path, which may be relative to the working directory.
+fn absolute_path(path: &str) -> Result<String, &'static str> {
+    let taskref = task::get_my_current_task().ok_or("failed to get current task")?;
+    let curr_wd = taskref.get_env().lock().working_dir.clone();
+    let node = Path::new(path.to_string()).get(&curr_wd).ok_or("no such directory")?;
+    Ok(node.get_absolute_path())
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: umount [OPTIONS] DIRECTORY...\n\n");
+
+    brief.push_str("Unmounts the filesystem mounted at each DIRECTORY.\n");
+    brief.push_str("A filesystem can't be unmounted while it's busy, e.g., while it's a task's working directory.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
@@ -297,11 +297,11 @@ fn get_my_current_namespace() -> Arc<CrateNamespace> {
 }
 
 
-/// Returns the first network interface available in the system.
+/// Returns the first network interface available in the system, other than a loopback interface.
 fn get_default_iface() -> Result<NetworkInterfaceRef, String> {
     NETWORK_INTERFACES.lock()
         .iter()
-        .next()
+        .find(|iface| !iface.lock().is_loopback())
         .cloned()
         .ok_or_else(|| format!("no network interfaces available"))
 }
@@ -0,0 +1,168 @@
+//! Counts the lines, words and bytes of files, or of its standard input.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate app_io;
+
+extern crate getopts;
+extern crate task;
+extern crate app_input;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::AddAssign;
+use getopts::Options;
+use app_input::Input;
+
+
+/// The number of bytes read from the input at once.
+const BUFFER_SIZE: usize = 256;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("l", "lines", "print the number of lines");
+    opts.optflag("w", "words", "print the number of words");
+    opts.optflag("c", "bytes", "print the number of bytes");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    // All counts are printed unless some of them are chosen.
+    let mut selection = Selection {
+        lines: matches.opt_present("l"),
+        words: matches.opt_present("w"),
+        bytes: matches.opt_present("c"),
+    };
+    if !(selection.lines || selection.words || selection.bytes) {
+        selection = Selection { lines: true, words: true, bytes: true };
+    }
+
+    let curr_wd = match task::get_my_current_task() {
+        Some(taskref) => taskref.get_env().lock().working_dir.clone(),
+        None => {
+            println!("failed to get current task");
+            return -1;
+        }
+    };
+
+    if matches.free.is_empty() {
+        return match Input::open("-", &curr_wd).and_then(|mut input| count(&mut input).map_err(String::from)) {
+            Ok(counts) => {
+                println!("{}", counts.format(&selection, ""));
+                0
+            }
+            Err(e) => {
+                println!("wc: {}", e);
+                -1
+            }
+        };
+    }
+
+    let mut ret = 0;
+    let mut total = Counts::default();
+    for path in matches.free.iter() {
+        match Input::open(path, &curr_wd).and_then(|mut input| count(&mut input).map_err(String::from)) {
+            Ok(counts) => {
+                println!("{}", counts.format(&selection, path));
+                total += counts;
+            }
+            Err(e) => {
+                println!("wc: {}", e);
+                ret = -1;
+            }
+        }
+    }
+    if matches.free.len() > 1 {
+        println!("{}", total.format(&selection, "total"));
+    }
+    ret
+}
+
+
+/// Which of the counts are printed.
+struct Selection {
+    lines: bool,
+    words: bool,
+    bytes: bool,
+}
+
+/// The counts of an input.
+#[derive(Default)]
+struct Counts {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+}
+
+impl Counts {
+    /// Formats the selected counts in aligned columns, followed by the given `name`.
+    fn format(&self, selection: &Selection, name: &str) -> String {
+        let mut line = String::new();
+        let columns = [(selection.lines, self.lines), (selection.words, self.words), (selection.bytes, self.bytes)];
+        for &(_, count) in columns.iter().filter(|(selected, _)| *selected) {
+            line.push_str(&format!("{:>8}", count));
+        }
+        if !name.is_empty() {
+            line.push(' ');
+            line.push_str(name);
+        }
+        line
+    }
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Counts the lines, words and bytes of `input`.
+/// Words are sequences of characters that are separated by whitespace.
+fn count(input: &mut Input) -> Result<Counts, &'static str> {
+    let mut counts = Counts::default();
+    let mut in_word = false;
+    let mut buffer = [0u8; BUFFER_SIZE];
+    loop {
+        let count = input.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        counts.bytes += count;
+        for &byte in &buffer[..count] {
+            if byte == b'\n' {
+                counts.lines += 1;
+            }
+            if byte.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                counts.words += 1;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = String::from("Usage: wc [OPTIONS] [FILE...]\n\n");
+
+    brief.push_str("Prints the number of lines, words and bytes of each FILE, and their total if there are several.\n");
+    brief.push_str("If no FILE is given, or FILE is -, it reads the standard input.");
+
+    println!("{} \n", opts.usage(&brief));
+    0
+}
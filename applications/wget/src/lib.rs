@@ -0,0 +1,150 @@
+//! Downloads a resource over HTTP and saves it to a file, like a minimal `wget`.
+//!
+//! The file is named after the last component of the URL's path, or `index.html` if it has none,
+//! unless another file is given with `-O`; `-O -` prints the resource instead.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate access_control;
+extern crate http;
+
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use getopts::{Matches, Options};
+use fs_node::FileRef;
+use http::{Client, Headers, Method, Response, Url};
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("O", "output-document", "write the resource to FILE, or print it if FILE is '-'", "FILE");
+    opts.optopt("", "post-data", "send a POST request with DATA as its body", "DATA");
+    opts.optopt("", "max-redirect", "follow at most N redirects (default: 5)", "N");
+    opts.optopt("T", "timeout", "give up after N seconds without receiving anything (default: 30)", "N");
+    opts.optflag("S", "server-response", "print the headers of the response");
+    opts.optflag("q", "quiet", "don't print progress messages");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(&opts);
+    }
+
+    let url = match matches.free.first() {
+        Some(url) => url,
+        None => {
+            println!("wget: missing URL");
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    match rmain(&matches, url) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("wget: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain(matches: &Matches, url: &str) -> Result<(), String> {
+    let mut client = Client::default();
+    if let Some(n) = matches.opt_str("max-redirect") {
+        client.max_redirects = n.parse::<usize>().map_err(|_e| "couldn't parse the maximum number of redirects")?;
+    }
+    if let Some(n) = matches.opt_str("T") {
+        client.timeout_ms = n.parse::<u64>().map_err(|_e| "couldn't parse timeout")?.saturating_mul(1000);
+    }
+    let quiet = matches.opt_present("q");
+    let parsed_url = Url::parse(url)?;
+
+    if !quiet {
+        println!("Connecting to {} ...", parsed_url.authority());
+    }
+    let response = match matches.opt_str("post-data") {
+        Some(data) => {
+            let mut headers = Headers::new();
+            headers.set("Content-Type", "application/x-www-form-urlencoded");
+            client.request(Method::Post, url, &headers, data.as_bytes())?
+        }
+        None => client.get(url)?,
+    };
+
+    if matches.opt_present("S") {
+        print_headers(&response);
+    }
+    if !quiet {
+        println!("HTTP request sent, response: {} {}", response.status_code, response.reason);
+    }
+    if !response.is_success() {
+        return Err(format!("server returned {} {}", response.status_code, response.reason));
+    }
+
+    let output = matches.opt_str("O").unwrap_or_else(|| default_file_name(&parsed_url));
+    if output == "-" {
+        print!("{}", String::from_utf8_lossy(&response.body));
+        return Ok(());
+    }
+    let file = get_or_create_file(&output)?;
+    access_control::write(&file, &response.body, 0)?;
+    // Discard the rest of an existing file that was longer than the resource.
+    let old_size = file.lock().size();
+    if old_size > response.body.len() {
+        file.lock().truncate(response.body.len())?;
+    }
+    if !quiet {
+        println!("Saved {} bytes to {:?}", response.body.len(), output);
+    }
+    Ok(())
+}
+
+/// Returns the name of the file that the resource at the given URL is saved to by default.
+fn default_file_name(url: &Url) -> String {
+    let path = url.target.split('?').next().unwrap_or("");
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => "index.html".to_string(),
+    }
+}
+
+fn print_headers(response: &Response) {
+    println!("  HTTP/1.1 {} {}", response.status_code, response.reason);
+    for (name, value) in response.headers.iter() {
+        println!("  {}: {}", name, value);
+    }
+}
+
+/// Returns the file at the given path relative to the current working directory, if this task may write it,
+/// creating it if it doesn't yet exist.
+fn get_or_create_file(path: &str) -> Result<FileRef, String> {
+    let curr_wd = task::get_my_current_task()
+        .map(|t| Arc::clone(&t.lock().env.lock().working_dir))
+        .ok_or_else(|| String::from("failed to get current task"))?;
+
+    access_control::open_or_create_file(path, &curr_wd).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+fn print_usage(opts: &Options) -> isize {
+    let mut brief = format!("Usage: wget [OPTION]... URL \n \n");
+
+    brief.push_str("downloads the resource at an http:// URL and saves it to a file");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
@@ -0,0 +1,354 @@
+//! Support for AC'97 audio controllers, such as the Intel 82801AA (ICH) controller that QEMU emulates.
+//!
+//! An AC'97 controller has two sets of I/O port registers: the Native Audio Mixer (NAM) registers of the codec,
+//! which control volumes and sample rates, and the Native Audio Bus Master (NABM) registers,
+//! which control the DMA engines that move samples between memory and the codec.
+//!
+//! This driver only uses the PCM output DMA engine. It plays from a ring of buffers described by a
+//! Buffer Descriptor List (BDL) and asks for an interrupt after each buffer, whose handler refills
+//! the buffers that were just played with the next samples from the [`audio`](../audio/index.html) mixer.
+//! Once all buffers hold only silence, the DMA engine is stopped until the mixer has samples again.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate irq_safety;
+extern crate memory;
+extern crate pci;
+extern crate pic;
+extern crate interrupts;
+extern crate pause;
+extern crate port_io;
+extern crate audio;
+
+use core::mem::size_of;
+use alloc::boxed::Box;
+use spin::Once;
+use irq_safety::MutexIrqSafe;
+use zerocopy::FromBytes;
+use x86_64::structures::idt::ExceptionStackFrame;
+use memory::dma::{DmaAddressLimit, DmaBuffer};
+use pci::{PciDevice, PCI_INTERRUPT_LINE};
+use interrupts::{eoi, register_interrupt};
+use pause::spin_loop_hint;
+use port_io::{Port, PortIn, PortOut};
+use audio::AudioDevice;
+
+
+/// The PCI class code of multimedia controllers.
+pub const AC97_PCI_CLASS: u8 = 0x04;
+/// The PCI subclass code of audio controllers (as opposed to HD Audio controllers, whose subclass is 0x03).
+pub const AC97_PCI_SUBCLASS: u8 = 0x01;
+
+/// How many times to poll for the controller to become ready before giving up.
+const POLL_ITERATIONS: usize = 100_000_000;
+
+/// The number of entries in the Buffer Descriptor List, which is fixed by the AC'97 specification.
+const BDL_ENTRIES: usize = 32;
+/// The number of stereo frames in each buffer, which is about 10 ms of audio at 48 kHz.
+const BUFFER_FRAMES: usize = 512;
+/// The number of 16-bit samples in each buffer.
+const BUFFER_SAMPLES: usize = BUFFER_FRAMES * 2;
+/// The size of each buffer in bytes.
+const BUFFER_SIZE: usize = BUFFER_SAMPLES * size_of::<i16>();
+
+/// The sample rate that the codec is set to, which every codec supports.
+const SAMPLE_RATE: u32 = 48_000;
+
+/// To use a BAR as a Port address, you must mask out the lowest 2 bits.
+const PCI_BAR_PORT_MASK: u16 = 0xFFFC;
+
+// Native Audio Mixer registers, as offsets from the port in BAR0
+const NAM_RESET:                u16 = 0x00;
+const NAM_MASTER_VOLUME:        u16 = 0x02;
+const NAM_PCM_OUT_VOLUME:       u16 = 0x18;
+const NAM_EXT_AUDIO_ID:         u16 = 0x28;
+const NAM_EXT_AUDIO_CONTROL:    u16 = 0x2A;
+const NAM_PCM_FRONT_DAC_RATE:   u16 = 0x2C;
+
+/// The bit in the extended audio ID and control registers for Variable Rate Audio, i.e., sample rates other than 48 kHz.
+const EXT_AUDIO_VRA: u16 = 1 << 0;
+/// A volume of 0 dB attenuation on both channels for the master volume.
+const VOLUME_MAX: u16 = 0x0000;
+/// A gain of 0 dB on both channels for the PCM output volume.
+const VOLUME_0DB: u16 = 0x0808;
+
+// Native Audio Bus Master registers, as offsets from the port in BAR1.
+// The PCM output box of registers starts at 0x10.
+const NABM_PO_BDBAR:            u16 = 0x10;
+const NABM_PO_CIV:              u16 = 0x14;
+const NABM_PO_LVI:              u16 = 0x15;
+const NABM_PO_SR:               u16 = 0x16;
+const NABM_PO_CR:               u16 = 0x1B;
+const NABM_GLOBAL_CONTROL:      u16 = 0x2C;
+const NABM_GLOBAL_STATUS:       u16 = 0x30;
+
+// Bits of the transfer control register (CR)
+const CR_RUN:                           u8 = 1 << 0;
+const CR_RESET_REGISTERS:               u8 = 1 << 1;
+const CR_LAST_VALID_INTERRUPT_ENABLE:   u8 = 1 << 2;
+const CR_COMPLETION_INTERRUPT_ENABLE:   u8 = 1 << 4;
+
+// Bits of the transfer status register (SR)
+const SR_DMA_HALTED:                u16 = 1 << 0;
+const SR_LAST_VALID_INTERRUPT:      u16 = 1 << 2;
+const SR_COMPLETION_INTERRUPT:      u16 = 1 << 3;
+const SR_FIFO_ERROR:                u16 = 1 << 4;
+
+// Bits of the global control and status registers
+const GLOBAL_CONTROL_COLD_RESET:    u32 = 1 << 1;
+const GLOBAL_STATUS_CODEC_READY:    u32 = 1 << 8;
+
+/// The flag of a buffer descriptor that asks for an interrupt once its buffer has been played.
+const BD_INTERRUPT_ON_COMPLETION: u16 = 1 << 15;
+
+
+/// A Buffer Descriptor, an entry in the Buffer Descriptor List.
+#[derive(FromBytes, Clone, Copy)]
+#[repr(C)]
+struct BufferDescriptor {
+    /// The physical address of the buffer.
+    address: u32,
+    /// The number of 16-bit samples in the buffer.
+    samples: u16,
+    flags: u16,
+}
+
+
+/// The single AC'97 controller, which is accessed by its interrupt handler.
+static AC97_CONTROLLER: Once<MutexIrqSafe<Ac97Controller>> = Once::new();
+
+
+/// Initializes the AC'97 controller that is connected as the given `PciDevice`
+/// and registers it as a sound card with the `audio` subsystem.
+pub fn init(pci_device: &PciDevice) -> Result<(), &'static str> {
+    if AC97_CONTROLLER.try().is_some() {
+        return Err("ac97: only one AC'97 controller is supported");
+    }
+    let controller = Ac97Controller::new(pci_device)?;
+    let interrupt_num = controller.interrupt_num;
+    AC97_CONTROLLER.call_once(|| MutexIrqSafe::new(controller));
+    register_interrupt(interrupt_num, ac97_handler)?;
+
+    info!("ac97: initialized controller at {} with interrupt {}", pci_device.location, interrupt_num);
+    audio::register_device(Box::new(Ac97Device));
+    Ok(())
+}
+
+
+/// An AC'97 controller and the memory that its PCM output DMA engine plays from.
+struct Ac97Controller {
+    /// The base port of the Native Audio Mixer registers.
+    nam_base: u16,
+    /// The base port of the Native Audio Bus Master registers.
+    nabm_base: u16,
+    /// The Buffer Descriptor List.
+    bdl: DmaBuffer,
+    /// The sample buffers, one for each buffer descriptor, stored contiguously.
+    buffers: DmaBuffer,
+    sample_rate: u32,
+    interrupt_num: u8,
+    /// Whether the DMA engine is running.
+    running: bool,
+    /// The index of the next buffer to refill once it has been played.
+    next_refill: usize,
+    /// The number of buffers in a row that were filled with silence.
+    silent_buffers: usize,
+}
+
+impl Ac97Controller {
+    /// Resets the controller and its codec, sets the volume and sample rate, and sets up the Buffer Descriptor List.
+    fn new(pci_device: &PciDevice) -> Result<Ac97Controller, &'static str> {
+        let nam_base = pci_device.bars[0] as u16 & PCI_BAR_PORT_MASK;
+        let nabm_base = pci_device.bars[1] as u16 & PCI_BAR_PORT_MASK;
+        if pci_device.bars[0] & 0x1 == 0 || pci_device.bars[1] & 0x1 == 0 {
+            return Err("ac97: BAR0 and BAR1 don't map I/O ports");
+        }
+        pci_device.pci_set_command_bus_master_bit();
+
+        // The controller only takes 32-bit physical addresses.
+        let mut bdl = DmaBuffer::new_with_limit(BDL_ENTRIES * size_of::<BufferDescriptor>(), DmaAddressLimit::Bits32)?;
+        let buffers = DmaBuffer::new_with_limit(BDL_ENTRIES * BUFFER_SIZE, DmaAddressLimit::Bits32)?;
+        let buffers_phys = buffers.phys_addr();
+        for (i, descriptor) in bdl.mapped_pages_mut().as_slice_mut::<BufferDescriptor>(0, BDL_ENTRIES)?.iter_mut().enumerate() {
+            *descriptor = BufferDescriptor {
+                address: (buffers_phys.value() + i * BUFFER_SIZE) as u32,
+                samples: BUFFER_SAMPLES as u16,
+                flags: BD_INTERRUPT_ON_COMPLETION,
+            };
+        }
+
+        let mut controller = Ac97Controller {
+            nam_base,
+            nabm_base,
+            bdl,
+            buffers,
+            sample_rate: SAMPLE_RATE,
+            interrupt_num: pci_device.pci_read_8(PCI_INTERRUPT_LINE) + pic::PIC_MASTER_OFFSET,
+            running: false,
+            next_refill: 0,
+            silent_buffers: 0,
+        };
+        controller.reset()?;
+        Ok(controller)
+    }
+
+    fn nam_read(&self, register: u16) -> u16 {
+        Port::<u16>::new(self.nam_base + register).read()
+    }
+
+    fn nam_write(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.nam_base + register).write(value); }
+    }
+
+    fn nabm_port<T: PortIn + PortOut>(&self, register: u16) -> Port<T> {
+        Port::new(self.nabm_base + register)
+    }
+
+    /// Takes the controller and its codec out of reset and configures the codec for output.
+    fn reset(&mut self) -> Result<(), &'static str> {
+        unsafe { self.nabm_port::<u32>(NABM_GLOBAL_CONTROL).write(GLOBAL_CONTROL_COLD_RESET); }
+        let global_status = self.nabm_port::<u32>(NABM_GLOBAL_STATUS);
+        let mut iterations = 0;
+        while global_status.read() & GLOBAL_STATUS_CODEC_READY == 0 {
+            iterations += 1;
+            if iterations >= POLL_ITERATIONS {
+                return Err("ac97: the codec didn't become ready");
+            }
+            spin_loop_hint();
+        }
+
+        // Writing any value to the reset register resets the codec's registers to their defaults.
+        self.nam_write(NAM_RESET, 0);
+        self.nam_write(NAM_MASTER_VOLUME, VOLUME_MAX);
+        self.nam_write(NAM_PCM_OUT_VOLUME, VOLUME_0DB);
+
+        // Codecs that support variable rates must have them enabled before the rate can be set.
+        if self.nam_read(NAM_EXT_AUDIO_ID) & EXT_AUDIO_VRA != 0 {
+            let control = self.nam_read(NAM_EXT_AUDIO_CONTROL);
+            self.nam_write(NAM_EXT_AUDIO_CONTROL, control | EXT_AUDIO_VRA);
+            self.nam_write(NAM_PCM_FRONT_DAC_RATE, SAMPLE_RATE as u16);
+            let rate = self.nam_read(NAM_PCM_FRONT_DAC_RATE) as u32;
+            if rate != 0 {
+                self.sample_rate = rate;
+            }
+        }
+
+        self.reset_output()
+    }
+
+    /// Stops the PCM output DMA engine and resets its registers.
+    fn reset_output(&mut self) -> Result<(), &'static str> {
+        let control = self.nabm_port::<u8>(NABM_PO_CR);
+        unsafe { control.write(0); }
+        unsafe { control.write(CR_RESET_REGISTERS); }
+        let mut iterations = 0;
+        while control.read() & CR_RESET_REGISTERS != 0 {
+            iterations += 1;
+            if iterations >= POLL_ITERATIONS {
+                return Err("ac97: the PCM output registers didn't reset");
+            }
+            spin_loop_hint();
+        }
+        self.running = false;
+        Ok(())
+    }
+
+    /// Fills all buffers with samples from the mixer and starts the PCM output DMA engine, if it isn't running.
+    fn start(&mut self) -> Result<(), &'static str> {
+        if self.running {
+            return Ok(());
+        }
+        self.reset_output()?;
+        self.silent_buffers = 0;
+        for i in 0..BDL_ENTRIES {
+            self.refill(i)?;
+        }
+        self.next_refill = 0;
+
+        unsafe {
+            self.nabm_port::<u32>(NABM_PO_BDBAR).write(self.bdl.phys_addr().value() as u32);
+            self.nabm_port::<u8>(NABM_PO_LVI).write((BDL_ENTRIES - 1) as u8);
+            self.nabm_port::<u8>(NABM_PO_CR).write(CR_RUN | CR_COMPLETION_INTERRUPT_ENABLE | CR_LAST_VALID_INTERRUPT_ENABLE);
+        }
+        self.running = true;
+        Ok(())
+    }
+
+    /// Fills the buffer at the given index with the next samples from the mixer.
+    fn refill(&mut self, index: usize) -> Result<(), &'static str> {
+        let buffer = self.buffers.mapped_pages_mut().as_slice_mut::<i16>(index * BUFFER_SIZE, BUFFER_SAMPLES)?;
+        if audio::mix(buffer) {
+            self.silent_buffers = 0;
+        } else {
+            self.silent_buffers += 1;
+        }
+        Ok(())
+    }
+
+    /// Acknowledges the interrupt, refills the buffers that have been played,
+    /// and moves the last valid buffer up to the one before the current one, so the DMA engine keeps playing.
+    fn handle_interrupt(&mut self) -> Result<(), &'static str> {
+        let status_port = self.nabm_port::<u16>(NABM_PO_SR);
+        let status = status_port.read();
+        unsafe { status_port.write(status & (SR_LAST_VALID_INTERRUPT | SR_COMPLETION_INTERRUPT | SR_FIFO_ERROR)); }
+        if !self.running {
+            return Ok(());
+        }
+
+        let current = self.nabm_port::<u8>(NABM_PO_CIV).read() as usize % BDL_ENTRIES;
+        while self.next_refill != current {
+            let index = self.next_refill;
+            self.refill(index)?;
+            self.next_refill = (index + 1) % BDL_ENTRIES;
+        }
+
+        // Once every buffer holds silence, there is no need to keep playing.
+        if self.silent_buffers >= BDL_ENTRIES {
+            return self.reset_output();
+        }
+
+        unsafe { self.nabm_port::<u8>(NABM_PO_LVI).write(((current + BDL_ENTRIES - 1) % BDL_ENTRIES) as u8); }
+        // The DMA engine halts if it reached the last valid buffer before this interrupt was handled.
+        if status & SR_DMA_HALTED != 0 {
+            unsafe { self.nabm_port::<u8>(NABM_PO_CR).write(CR_RUN | CR_COMPLETION_INTERRUPT_ENABLE | CR_LAST_VALID_INTERRUPT_ENABLE); }
+        }
+        Ok(())
+    }
+}
+
+
+/// The handle through which the `audio` subsystem controls the AC'97 controller.
+struct Ac97Device;
+
+impl AudioDevice for Ac97Device {
+    fn name(&self) -> &str {
+        "AC'97"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        AC97_CONTROLLER.try().map_or(SAMPLE_RATE, |controller| controller.lock().sample_rate)
+    }
+
+    fn start(&mut self) -> Result<(), &'static str> {
+        AC97_CONTROLLER.try().ok_or("ac97: controller isn't initialized")?.lock().start()
+    }
+}
+
+
+extern "x86-interrupt" fn ac97_handler(_stack_frame: &mut ExceptionStackFrame) {
+    if let Some(controller) = AC97_CONTROLLER.try() {
+        let mut controller = controller.lock();
+        if let Err(e) = controller.handle_interrupt() {
+            error!("ac97_handler(): error handling interrupt: {:?}", e);
+        }
+        eoi(Some(controller.interrupt_num));
+    } else {
+        error!("BUG: ac97_handler(): AC'97 controller hasn't yet been initialized!");
+    }
+}
@@ -0,0 +1,278 @@
+#![no_std]
+
+//! Enforcement of the owner and permission metadata of files and directories.
+//!
+//! Each task accesses the filesystem with the [`Credentials`] of its `Environment`.
+//! The functions in this crate check those credentials against a node's `Metadata` like Unix does,
+//! using the permission bits of the node's owner, of its group, or of everyone else,
+//! before opening, reading, writing, creating or unlinking nodes and before changing their metadata.
+//!
+//! Tasks whose credentials have `override_access` set, which includes all system tasks by default,
+//! bypass these checks, and nodes that don't record permissions (e.g., plain VFS nodes) are accessible to all.
+//!
+//! The `fs_node` traits themselves don't check permissions,
+//! so only accesses that go through this crate are checked.
+//! In particular, [`open`] only checks the given access when the node is opened:
+//! reading or writing the returned `FileRef` through its `File` methods isn't checked at all,
+//! so code that reads or writes files on behalf of a task should use [`read`] and [`write`],
+//! which check the current task's credentials on every access.
+
+extern crate alloc;
+extern crate fs_node;
+extern crate path;
+extern crate root;
+extern crate task;
+extern crate environment;
+extern crate memfs;
+
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use fs_node::{DirRef, FileRef, FileOrDir, FsNode, Metadata};
+use path::Path;
+use memfs::MemFile;
+pub use environment::Credentials;
+
+
+/// The set-user-ID bit.
+pub const S_ISUID: u16 = 0o4000;
+/// The set-group-ID bit.
+pub const S_ISGID: u16 = 0o2000;
+/// The sticky bit, which only allows a node in a directory to be unlinked by the owner of the node or directory.
+pub const S_ISVTX: u16 = 0o1000;
+
+/// The error returned when a permission check fails.
+pub const PERMISSION_DENIED: &'static str = "permission denied";
+/// The error returned when a path doesn't lead to a node.
+pub const NOT_FOUND: &'static str = "no such file or directory";
+
+
+/// A kind of access to a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Reading a file, or listing a directory.
+    Read,
+    /// Writing a file, or creating and removing nodes in a directory.
+    Write,
+    /// Executing a file, or looking up nodes in a directory.
+    Execute,
+}
+
+impl Access {
+    /// Returns the permission bit of this access within the "other" permission bits.
+    fn bit(self) -> u16 {
+        match self {
+            Access::Read => 0o4,
+            Access::Write => 0o2,
+            Access::Execute => 0o1,
+        }
+    }
+}
+
+
+/// Returns the credentials of the current task, or those of a system task if there is no current task.
+pub fn current_credentials() -> Credentials {
+    task::get_my_current_task()
+        .map(|taskref| taskref.get_env().lock().credentials.clone())
+        .unwrap_or_else(Credentials::system)
+}
+
+/// Returns whether the given `credentials` permit the given `access` to a node with the given `metadata`.
+pub fn is_permitted(credentials: &Credentials, metadata: &Metadata, access: Access) -> bool {
+    if credentials.override_access {
+        return true;
+    }
+    let permissions = match metadata.permissions {
+        Some(permissions) => permissions,
+        None => return true,
+    };
+    let shift = if metadata.owner == Some(credentials.uid) {
+        6
+    } else if metadata.group == Some(credentials.gid) {
+        3
+    } else {
+        0
+    };
+    (permissions >> shift) & access.bit() != 0
+}
+
+/// Checks whether the current task may perform the given `access` to the given `node`.
+pub fn check(node: &FileOrDir, access: Access) -> Result<(), &'static str> {
+    if is_permitted(&current_credentials(), &node.metadata(), access) {
+        Ok(())
+    } else {
+        Err(PERMISSION_DENIED)
+    }
+}
+
+
+/// Returns the node at the given `path`, which is either absolute or relative to the `starting_dir`,
+/// if the current task may perform the given `access` to it.
+///
+/// Like on Unix, the current task must also be able to search every directory that the path passes through;
+/// see [`lookup()`](fn.lookup.html).
+pub fn open(path: &Path, starting_dir: &DirRef, access: Access) -> Result<FileOrDir, &'static str> {
+    let node = lookup(path, starting_dir)?;
+    check(&node, access)?;
+    Ok(node)
+}
+
+/// Returns the node at the given `path`, which is either absolute or relative to the `starting_dir`,
+/// if the current task may search (i.e., has `Execute` access to) every directory that the path passes through.
+///
+/// This doesn't check any access to the node itself, e.g., for changing its metadata.
+pub fn lookup(path: &Path, starting_dir: &DirRef) -> Result<FileOrDir, &'static str> {
+    let credentials = current_credentials();
+    let mut curr_dir = if path.is_absolute() {
+        Arc::clone(root::get_root())
+    } else {
+        Arc::clone(starting_dir)
+    };
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        match component {
+            "." => {}
+            ".." => {
+                let parent_dir = curr_dir.lock().get_parent_dir().ok_or(NOT_FOUND)?;
+                curr_dir = parent_dir;
+            }
+            name => {
+                if !is_permitted(&credentials, &curr_dir.lock().metadata(), Access::Execute) {
+                    return Err(PERMISSION_DENIED);
+                }
+                let child = curr_dir.lock().get(name).ok_or(NOT_FOUND)?;
+                match child {
+                    FileOrDir::Dir(dir) => curr_dir = dir,
+                    FileOrDir::File(_) if components.peek().is_some() => return Err("not a directory"),
+                    file => return Ok(file),
+                }
+            }
+        }
+    }
+    Ok(FileOrDir::Dir(curr_dir))
+}
+
+/// Reads from the given `file` if the current task may read it; see `File::read()`.
+pub fn read(file: &FileRef, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+    let file = file.lock();
+    if !is_permitted(&current_credentials(), &file.metadata(), Access::Read) {
+        return Err(PERMISSION_DENIED);
+    }
+    file.read(buffer, offset)
+}
+
+/// Writes to the given `file` if the current task may write it; see `File::write()`.
+pub fn write(file: &FileRef, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+    let mut file = file.lock();
+    if !is_permitted(&current_credentials(), &file.metadata(), Access::Write) {
+        return Err(PERMISSION_DENIED);
+    }
+    file.write(buffer, offset)
+}
+
+/// Checks whether the current task may create or remove nodes in the given directory,
+/// which requires both `Write` and `Execute` access to it.
+fn check_modify_dir(credentials: &Credentials, dir_metadata: &Metadata) -> Result<(), &'static str> {
+    if is_permitted(credentials, dir_metadata, Access::Write) && is_permitted(credentials, dir_metadata, Access::Execute) {
+        Ok(())
+    } else {
+        Err(PERMISSION_DENIED)
+    }
+}
+
+/// Creates a new file named `name` in the given directory if the current task may modify the directory;
+/// see `Directory::create_file()`.
+pub fn create_file(dir: &DirRef, name: &str) -> Result<FileRef, &'static str> {
+    let mut dir = dir.lock();
+    check_modify_dir(&current_credentials(), &dir.metadata())?;
+    dir.create_file(name)
+}
+
+/// Creates a new file at the given `path`, which is either absolute or relative to the `working_dir`,
+/// if the current task may modify its parent directory.
+///
+/// Directories backed by a filesystem create their own files, and their errors are returned unchanged.
+/// In directories that don't support creating files, e.g., plain VFS directories, the file is held in memory.
+pub fn create_file_at(path: &str, working_dir: &DirRef) -> Result<FileRef, &'static str> {
+    let (parent_path, file_name) = match path.rfind('/') {
+        Some(i) => (&path[..i + 1], &path[i + 1..]),
+        None => ("", path),
+    };
+    if file_name.is_empty() {
+        return Err("not a valid file name");
+    }
+    let parent_dir = if parent_path.is_empty() {
+        Arc::clone(working_dir)
+    } else {
+        match lookup(&Path::new(parent_path.to_string()), working_dir)? {
+            FileOrDir::Dir(dir) => dir,
+            FileOrDir::File(_) => return Err("not a directory"),
+        }
+    };
+    match create_file(&parent_dir, file_name) {
+        Err(fs_node::CREATE_FILE_UNSUPPORTED) => MemFile::new(file_name.to_string(), &parent_dir),
+        created => created,
+    }
+}
+
+/// Returns the file at the given `path`, which is either absolute or relative to the `working_dir`,
+/// if the current task may write it, or creates it with [`create_file_at()`](fn.create_file_at.html) if it doesn't exist.
+///
+/// Errors other than the file not existing, e.g., `PERMISSION_DENIED`, are returned without creating a file.
+pub fn open_or_create_file(path: &str, working_dir: &DirRef) -> Result<FileRef, &'static str> {
+    match open(&Path::new(path.to_string()), working_dir, Access::Write) {
+        Ok(FileOrDir::File(file)) => Ok(file),
+        Ok(FileOrDir::Dir(_)) => Err("is a directory"),
+        Err(NOT_FOUND) => create_file_at(path, working_dir),
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates a new directory named `name` in the given directory if the current task may modify the directory;
+/// see `Directory::create_dir()`.
+pub fn create_dir(dir: &DirRef, name: &str) -> Result<DirRef, &'static str> {
+    let mut dir = dir.lock();
+    check_modify_dir(&current_credentials(), &dir.metadata())?;
+    dir.create_dir(name)
+}
+
+/// Removes the given `node` from its `parent` directory if the current task may modify that directory;
+/// see `Directory::remove()`.
+///
+/// If the sticky bit of the `parent` directory is set, the current task must also own the `node` or the `parent`.
+pub fn unlink(parent: &DirRef, node: &FileOrDir) -> Result<FileOrDir, &'static str> {
+    let credentials = current_credentials();
+    let node_metadata = node.metadata();
+    let mut parent = parent.lock();
+    let parent_metadata = parent.metadata();
+    check_modify_dir(&credentials, &parent_metadata)?;
+    let sticky = parent_metadata.permissions.map_or(false, |permissions| permissions & S_ISVTX != 0);
+    if sticky && !credentials.override_access
+        && node_metadata.owner != Some(credentials.uid) && parent_metadata.owner != Some(credentials.uid)
+    {
+        return Err(PERMISSION_DENIED);
+    }
+    parent.remove(node).ok_or("couldn't remove the node from its parent directory")
+}
+
+/// Sets the permission bits of the given `node` if the current task owns it; see `FsNode::set_permissions()`.
+pub fn set_permissions(node: &FileOrDir, permissions: u16) -> Result<(), &'static str> {
+    let credentials = current_credentials();
+    if !credentials.override_access && node.metadata().owner != Some(credentials.uid) {
+        return Err(PERMISSION_DENIED);
+    }
+    node.clone().set_permissions(permissions)
+}
+
+/// Sets the owner and group of the given `node`; see `FsNode::set_owner()`.
+///
+/// Only tasks that can bypass permission checks may give a node to another user.
+/// The owner of a node may change its group to the owner's own group.
+pub fn set_owner(node: &FileOrDir, owner: u32, group: u32) -> Result<(), &'static str> {
+    let credentials = current_credentials();
+    let is_owner = node.metadata().owner == Some(credentials.uid);
+    let keeps_owner = owner == credentials.uid && group == credentials.gid;
+    if !credentials.override_access && !(is_owner && keeps_owner) {
+        return Err(PERMISSION_DENIED);
+    }
+    node.clone().set_owner(owner, group)
+}
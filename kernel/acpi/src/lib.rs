@@ -27,6 +27,8 @@ extern crate rsdp;
 extern crate rsdt;
 extern crate fadt;
 extern crate madt;
+extern crate srat;
+extern crate slit;
 
 
 use alloc::vec::Vec;
@@ -79,12 +81,19 @@ pub fn init(page_table: &mut PageTable) -> Result<(), &'static str> {
         }
     }
 
-    // FADT is mandatory, and contains the address of the DSDT
-    {
+    // FADT is mandatory, and contains the addresses of the DSDT and FACS,
+    // which aren't listed in the RSDT/XSDT. Both are needed for power management (sleep states).
+    let (dsdt_paddr, facs_paddr) = {
         let acpi_tables = ACPI_TABLES.lock();
         let _fadt = fadt::Fadt::get(&acpi_tables).ok_or("The required FADT APIC table wasn't found (signature 'FACP')")?;
-        // here: do something with the DSDT here, when needed.
-        // debug!("DSDT physical address: {:#X}", fadt.dsdt);
+        (fadt::Fadt::dsdt_address(&acpi_tables), fadt::Fadt::facs_address(&acpi_tables))
+    };
+    {
+        let mut acpi_tables = ACPI_TABLES.lock();
+        for paddr in dsdt_paddr.into_iter().chain(facs_paddr) {
+            let (sdt_signature, sdt_total_length) = acpi_tables.map_new_table(paddr, page_table)?;
+            acpi_table_handler(&mut acpi_tables, sdt_signature, sdt_total_length, paddr)?;
+        }
     }
     
     // HPET is optional, but usually present.
@@ -104,5 +113,24 @@ pub fn init(page_table: &mut PageTable) -> Result<(), &'static str> {
         madt.bsp_init(page_table)?;
     }
 
+    // SRAT is optional, and only exists on NUMA systems. SLIT is optional even if SRAT exists.
+    {
+        let acpi_tables = ACPI_TABLES.lock();
+        if let Some(srat) = srat::Srat::get(&acpi_tables) {
+            let mut topology = srat.numa_topology();
+            if let Some(distances) = slit::Slit::get(&acpi_tables)
+                .and_then(|slit| slit.distance_matrix().map(|matrix| (slit.num_localities(), matrix)))
+            {
+                if let Err(e) = topology.set_distances(distances.0, distances.1) {
+                    warn!("Ignoring malformed SLIT table: {}", e);
+                }
+            }
+            info!("Found {} NUMA node(s): {:?}", topology.nodes().len(), topology.nodes());
+            memory::set_numa_topology(topology, || apic::get_my_apic_id() as u32)?;
+        } else {
+            debug!("This machine has no SRAT, so it is not treated as a NUMA system.");
+        }
+    }
+
     Ok(())
 }
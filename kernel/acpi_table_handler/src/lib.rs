@@ -14,6 +14,10 @@ extern crate rsdt;
 extern crate fadt;
 extern crate hpet;
 extern crate madt;
+extern crate srat;
+extern crate slit;
+extern crate dsdt;
+extern crate facs;
 
 
 use memory::PhysicalAddress;
@@ -43,6 +47,10 @@ pub fn acpi_table_handler(
         fadt::FADT_SIGNATURE => fadt::handle(acpi_tables, signature, length, phys_addr),
         hpet::HPET_SIGNATURE => hpet::handle(acpi_tables, signature, length, phys_addr),
         madt::MADT_SIGNATURE => madt::handle(acpi_tables, signature, length, phys_addr),
+        srat::SRAT_SIGNATURE => srat::handle(acpi_tables, signature, length, phys_addr),
+        slit::SLIT_SIGNATURE => slit::handle(acpi_tables, signature, length, phys_addr),
+        dsdt::DSDT_SIGNATURE => dsdt::handle(acpi_tables, signature, length, phys_addr),
+        facs::FACS_SIGNATURE => facs::handle(acpi_tables, signature, length, phys_addr),
         _ => {
             warn!("Skipping unsupported ACPI table {:?}", core::str::from_utf8(&signature).unwrap_or("Unknown Signature"));
             Ok(())
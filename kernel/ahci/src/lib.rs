@@ -0,0 +1,795 @@
+//! Support for SATA drives attached to an AHCI (Advanced Host Controller Interface) controller.
+//!
+//! An AHCI controller, known as an HBA (Host Bus Adapter), has up to 32 ports, each of which may have one SATA drive attached.
+//! Each port has a command list in memory that describes commands to issue to its drive,
+//! and a receive area where the HBA places the FISes (Frame Information Structures) that the drive sends back.
+//! Data is transferred by DMA, and the HBA raises an interrupt when a command completes
+//! or when a drive is plugged into or removed from a port.
+//!
+//! Each attached drive is represented by an [`AhciDrive`](struct.AhciDrive.html), which implements the `StorageDevice` trait.
+//! Only one command is outstanding on a port at a time, using command slot 0;
+//! the submitting task sleeps until the HBA's interrupt signals completion.
+//!
+//! Drives that are attached or removed after initialization are detected by a per-HBA hot-plug task,
+//! which is woken up by port status change interrupts.
+//!
+//! The primary struct of interest is [`AhciController`](struct.AhciController.html).
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate irq_safety;
+extern crate volatile;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate pic;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate spawn;
+extern crate pause;
+extern crate ata;
+extern crate storage_device;
+
+use core::{
+    fmt,
+    ops::DerefMut,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering, fence},
+};
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use volatile::Volatile;
+use zerocopy::FromBytes;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{
+    EntryFlags, FrameRange, MappedPages, PhysicalAddress,
+    allocate_pages_by_bytes, create_contiguous_mapping, get_frame_allocator_ref, get_kernel_mmi_ref,
+};
+use pci::{PciDevice, PciLocation, MsiInterrupt, PCI_INTERRUPT_LINE};
+use interrupts::{eoi, register_interrupt};
+use wait_queue::WaitQueue;
+use pause::spin_loop_hint;
+use ata::{AtaCommand, AtaIdentifyData, SECTOR_SIZE_IN_BYTES};
+use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+
+
+/// The PCI class code of mass storage controllers.
+pub const AHCI_PCI_CLASS: u8 = 0x01;
+/// The PCI subclass code of SATA controllers.
+pub const AHCI_PCI_SUBCLASS: u8 = 0x06;
+/// The PCI programming interface of AHCI 1.0 controllers.
+pub const AHCI_PCI_PROG_IF: u8 = 0x01;
+
+/// The maximum number of ports on one HBA.
+const MAX_PORTS: usize = 32;
+/// The index of the BAR that maps the HBA's registers, known as ABAR.
+const ABAR_INDEX: u8 = 5;
+/// The offset of the first port's registers from the start of the HBA's registers.
+const PORT_REGISTERS_OFFSET: usize = 0x100;
+
+/// The size of each port's DMA buffer, which bounds the size of one transfer.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = 32 * PAGE_SIZE;
+
+// The layout of the memory that each port uses for its commands, all in one page.
+// Only command slot 0 is used, so there is only one command table.
+const COMMAND_LIST_OFFSET:    usize = 0;     // 32 command headers of 32 bytes each, 1 KiB-aligned
+const RECEIVED_FIS_OFFSET:    usize = 1024;  // 256 bytes, 256-byte-aligned
+const COMMAND_TABLE_OFFSET:   usize = 1280;  // 128 bytes plus the PRDT, 128-byte-aligned
+
+/// How many times to poll for a completion or a port state change before giving up.
+const POLL_ITERATIONS: usize = 10_000_000;
+
+// Bits of the Global HBA Control (GHC) register.
+const GHC_INTERRUPT_ENABLE:   u32 = 1 << 1;
+const GHC_AHCI_ENABLE:        u32 = 1 << 31;
+
+// Bits of a port's Command and Status (PxCMD) register.
+const PORT_CMD_START:                 u32 = 1 << 0;
+const PORT_CMD_FIS_RECEIVE_ENABLE:    u32 = 1 << 4;
+const PORT_CMD_FIS_RECEIVE_RUNNING:   u32 = 1 << 14;
+const PORT_CMD_LIST_RUNNING:          u32 = 1 << 15;
+
+// Bits of a port's Interrupt Status (PxIS) and Interrupt Enable (PxIE) registers.
+const PORT_INT_D2H_REGISTER_FIS:      u32 = 1 << 0;
+const PORT_INT_PIO_SETUP_FIS:         u32 = 1 << 1;
+const PORT_INT_CONNECT_CHANGE:        u32 = 1 << 6;
+const PORT_INT_PHY_READY_CHANGE:      u32 = 1 << 22;
+const PORT_INT_TASK_FILE_ERROR:       u32 = 1 << 30;
+
+// Bits of a port's Task File Data (PxTFD) register, which mirror the ATA status register.
+const TFD_ERROR:                      u32 = 1 << 0;
+const TFD_DATA_REQUEST:               u32 = 1 << 3;
+const TFD_BUSY:                       u32 = 1 << 7;
+
+/// The value of a port's SATA Status (PxSSTS) Device Detection field when a drive is present and communicating.
+const SSTS_DEVICE_PRESENT: u32 = 0x3;
+/// The value of a port's Signature (PxSIG) register when a SATA (not ATAPI) drive is attached.
+const SATA_SIGNATURE: u32 = 0x0000_0101;
+
+/// The FIS type of a Register FIS sent from the host to the device.
+const FIS_TYPE_REGISTER_H2D: u8 = 0x27;
+
+/// The mapping flags used for the HBA's registers and for DMA memory.
+const AHCI_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+
+lazy_static! {
+    /// The tasks waiting for an AHCI command to complete, which are woken up by AHCI interrupts.
+    static ref COMPLETION_WAIT_QUEUE: WaitQueue = WaitQueue::new();
+}
+
+/// All initialized HBAs, which the interrupt handler checks for pending interrupts.
+static HBAS: MutexIrqSafe<Vec<Arc<Hba>>> = MutexIrqSafe::new(Vec::new());
+/// The legacy interrupt lines that `ahci_handler` has been registered for.
+static AHCI_INTERRUPT_LINES: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+
+/// The generic host control registers at the start of an HBA's ABAR.
+#[derive(FromBytes)]
+#[repr(C)]
+struct HbaRegisters {
+    /// Host Capabilities
+    cap:        Volatile<u32>,  // 0x00
+    /// Global HBA Control
+    ghc:        Volatile<u32>,  // 0x04
+    /// Interrupt Status, one bit per port
+    is:         Volatile<u32>,  // 0x08
+    /// Ports Implemented, one bit per port
+    pi:         Volatile<u32>,  // 0x0C
+    /// Version
+    vs:         Volatile<u32>,  // 0x10
+    /// Command Completion Coalescing Control
+    ccc_ctl:    Volatile<u32>,  // 0x14
+    /// Command Completion Coalescing Ports
+    ccc_ports:  Volatile<u32>,  // 0x18
+    /// Enclosure Management Location
+    em_loc:     Volatile<u32>,  // 0x1C
+    /// Enclosure Management Control
+    em_ctl:     Volatile<u32>,  // 0x20
+    /// Host Capabilities Extended
+    cap2:       Volatile<u32>,  // 0x24
+    /// BIOS/OS Handoff Control and Status
+    bohc:       Volatile<u32>,  // 0x28
+}
+
+/// The registers of one port, which start at `0x100 + 0x80 * port_number` in an HBA's ABAR.
+#[derive(FromBytes)]
+#[repr(C)]
+struct PortRegisters {
+    /// Command List Base Address (lower and upper 32 bits)
+    clb:        Volatile<u32>,  // 0x00
+    clbu:       Volatile<u32>,  // 0x04
+    /// FIS Base Address (lower and upper 32 bits)
+    fb:         Volatile<u32>,  // 0x08
+    fbu:        Volatile<u32>,  // 0x0C
+    /// Interrupt Status
+    is:         Volatile<u32>,  // 0x10
+    /// Interrupt Enable
+    ie:         Volatile<u32>,  // 0x14
+    /// Command and Status
+    cmd:        Volatile<u32>,  // 0x18
+    _reserved0: u32,            // 0x1C
+    /// Task File Data
+    tfd:        Volatile<u32>,  // 0x20
+    /// Signature
+    sig:        Volatile<u32>,  // 0x24
+    /// SATA Status
+    ssts:       Volatile<u32>,  // 0x28
+    /// SATA Control
+    sctl:       Volatile<u32>,  // 0x2C
+    /// SATA Error
+    serr:       Volatile<u32>,  // 0x30
+    /// SATA Active
+    sact:       Volatile<u32>,  // 0x34
+    /// Command Issue, one bit per command slot
+    ci:         Volatile<u32>,  // 0x38
+    /// SATA Notification
+    sntf:       Volatile<u32>,  // 0x3C
+    /// FIS-based Switching Control
+    fbs:        Volatile<u32>,  // 0x40
+    _reserved1: [u32; 11],      // 0x44
+    _vendor:    [u32; 4],       // 0x70
+}
+
+/// An entry in a port's command list, which describes one command.
+#[derive(FromBytes)]
+#[repr(C)]
+struct CommandHeader {
+    /// Bits 0-4: the length of the command FIS in dwords; bit 6: write; bits 16-31: the number of PRDT entries.
+    flags:      u32,
+    /// The number of bytes transferred so far
+    prdbc:      u32,
+    /// The physical address of the command table
+    ctba:       u64,
+    _reserved:  [u32; 4],
+}
+
+/// A Register FIS sent from the host to the device, which issues an ATA command.
+#[derive(FromBytes, Default)]
+#[repr(C)]
+struct RegisterFisH2D {
+    fis_type:   u8,
+    /// Bit 7 indicates that this FIS contains a command.
+    flags:      u8,
+    command:    u8,
+    feature_low: u8,
+    lba0:       u8,
+    lba1:       u8,
+    lba2:       u8,
+    device:     u8,
+    lba3:       u8,
+    lba4:       u8,
+    lba5:       u8,
+    feature_high: u8,
+    count_low:  u8,
+    count_high: u8,
+    icc:        u8,
+    control:    u8,
+    _reserved:  [u8; 4],
+}
+
+/// An entry in a command table's Physical Region Descriptor Table, which describes one DMA buffer.
+#[derive(FromBytes)]
+#[repr(C)]
+struct PrdtEntry {
+    /// The physical address of the data buffer
+    dba:        u64,
+    _reserved:  u32,
+    /// Bits 0-21: the byte count minus one; bit 31: raise an interrupt on completion.
+    dbc:        u32,
+}
+
+
+/// The state of one HBA that is shared by its ports, its hot-plug task, and the interrupt handler.
+struct Hba {
+    location: PciLocation,
+    registers: MutexIrqSafe<MappedPages>,
+    /// The ports whose connection status changed and haven't yet been handled by the hot-plug task.
+    pending_port_changes: AtomicU32,
+    /// The hot-plug task waits on this for `pending_port_changes`.
+    hotplug_wait_queue: WaitQueue,
+    /// Whether this HBA raises interrupts, such that tasks can sleep while waiting for commands to complete.
+    interrupts_enabled: AtomicBool,
+}
+
+impl Hba {
+    fn hba_registers(registers: &mut MappedPages) -> Result<&mut HbaRegisters, &'static str> {
+        registers.as_type_mut::<HbaRegisters>(0)
+    }
+
+    fn port_registers(registers: &mut MappedPages, port_num: u8) -> Result<&mut PortRegisters, &'static str> {
+        registers.as_type_mut::<PortRegisters>(PORT_REGISTERS_OFFSET + port_num as usize * core::mem::size_of::<PortRegisters>())
+    }
+
+    /// Acknowledges this HBA's pending interrupts, recording any port status changes for the hot-plug task.
+    /// Returns `true` if this HBA had any pending interrupts.
+    fn handle_interrupt(&self) -> bool {
+        let mut registers = self.registers.lock();
+        let pending = match Self::hba_registers(&mut registers) {
+            Ok(hba) => hba.is.read(),
+            Err(_) => return false,
+        };
+        if pending == 0 {
+            return false;
+        }
+        for port_num in (0..MAX_PORTS as u8).filter(|p| pending & (1 << p) != 0) {
+            if let Ok(port) = Self::port_registers(&mut registers, port_num) {
+                let status = port.is.read();
+                if status & (PORT_INT_CONNECT_CHANGE | PORT_INT_PHY_READY_CHANGE) != 0 {
+                    // These status bits are only cleared by clearing the corresponding SATA error bits.
+                    port.serr.write(0xFFFF_FFFF);
+                    self.pending_port_changes.fetch_or(1 << port_num, Ordering::AcqRel);
+                }
+                port.is.write(status);
+            }
+        }
+        if let Ok(hba) = Self::hba_registers(&mut registers) {
+            hba.is.write(pending);
+        }
+        if self.pending_port_changes.load(Ordering::Acquire) != 0 {
+            self.hotplug_wait_queue.notify_all();
+        }
+        true
+    }
+}
+
+
+/// The memory and registers used to issue commands through one port of an HBA.
+struct AhciPort {
+    hba: Arc<Hba>,
+    port_num: u8,
+    /// The command list, received FIS area, and command table.
+    command_memory: MappedPages,
+    command_memory_phys: PhysicalAddress,
+    /// The buffer through which all data is transferred to and from the drive.
+    dma_buffer: MappedPages,
+    dma_buffer_phys: PhysicalAddress,
+}
+
+impl AhciPort {
+    /// Allocates the memory for the given port and starts its command engine.
+    fn new(hba: Arc<Hba>, port_num: u8) -> Result<AhciPort, &'static str> {
+        let (mut command_memory, command_memory_phys) = create_contiguous_mapping(PAGE_SIZE, AHCI_MAPPING_FLAGS)?;
+        for b in command_memory.as_slice_mut::<u8>(0, PAGE_SIZE)? { *b = 0; }
+        let (dma_buffer, dma_buffer_phys) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, AHCI_MAPPING_FLAGS)?;
+
+        let port = AhciPort { hba, port_num, command_memory, command_memory_phys, dma_buffer, dma_buffer_phys };
+        port.stop()?;
+        {
+            let mut registers = port.hba.registers.lock();
+            let regs = Hba::port_registers(&mut registers, port_num)?;
+            let command_list = (command_memory_phys + COMMAND_LIST_OFFSET).value() as u64;
+            let received_fis = (command_memory_phys + RECEIVED_FIS_OFFSET).value() as u64;
+            regs.clb.write(command_list as u32);
+            regs.clbu.write((command_list >> 32) as u32);
+            regs.fb.write(received_fis as u32);
+            regs.fbu.write((received_fis >> 32) as u32);
+            regs.serr.write(0xFFFF_FFFF);
+            regs.is.write(0xFFFF_FFFF);
+            regs.ie.write(
+                PORT_INT_D2H_REGISTER_FIS | PORT_INT_PIO_SETUP_FIS | PORT_INT_TASK_FILE_ERROR |
+                PORT_INT_CONNECT_CHANGE | PORT_INT_PHY_READY_CHANGE
+            );
+        }
+        port.start()?;
+        Ok(port)
+    }
+
+    /// Runs the given closure with this port's registers.
+    fn with_registers<R, F: FnOnce(&mut PortRegisters) -> R>(&self, f: F) -> Result<R, &'static str> {
+        let mut registers = self.hba.registers.lock();
+        Hba::port_registers(&mut registers, self.port_num).map(f)
+    }
+
+    /// Polls this port's registers until the given `condition` is true.
+    fn wait_for(&self, condition: impl Fn(&mut PortRegisters) -> bool, error: &'static str) -> Result<(), &'static str> {
+        for _ in 0..POLL_ITERATIONS {
+            if self.with_registers(|regs| condition(regs))? {
+                return Ok(());
+            }
+            spin_loop_hint();
+        }
+        Err(error)
+    }
+
+    /// Stops this port's command engine and FIS receive engine.
+    fn stop(&self) -> Result<(), &'static str> {
+        self.with_registers(|regs| regs.cmd.write(regs.cmd.read() & !PORT_CMD_START))?;
+        self.wait_for(|regs| regs.cmd.read() & PORT_CMD_LIST_RUNNING == 0, "timed out stopping AHCI port command list")?;
+        self.with_registers(|regs| regs.cmd.write(regs.cmd.read() & !PORT_CMD_FIS_RECEIVE_ENABLE))?;
+        self.wait_for(|regs| regs.cmd.read() & PORT_CMD_FIS_RECEIVE_RUNNING == 0, "timed out stopping AHCI port FIS receive")
+    }
+
+    /// Starts this port's FIS receive engine and command engine.
+    fn start(&self) -> Result<(), &'static str> {
+        self.wait_for(|regs| regs.cmd.read() & PORT_CMD_LIST_RUNNING == 0, "AHCI port command list is still running")?;
+        self.with_registers(|regs| regs.cmd.write(regs.cmd.read() | PORT_CMD_FIS_RECEIVE_ENABLE | PORT_CMD_START))
+    }
+
+    /// Recovers from a task file error by restarting this port, which clears the error.
+    fn recover(&self) -> Result<(), &'static str> {
+        self.stop()?;
+        self.with_registers(|regs| {
+            regs.serr.write(0xFFFF_FFFF);
+            regs.is.write(0xFFFF_FFFF);
+        })?;
+        self.start()
+    }
+
+    /// Issues the given ATA command to this port's drive, transferring `length` bytes
+    /// to or from the start of the DMA buffer, and waits for it to complete.
+    fn issue_command(&mut self, command: AtaCommand, lba: u64, sector_count: u16, length: usize, write: bool) -> Result<(), &'static str> {
+        let table_phys = (self.command_memory_phys + COMMAND_TABLE_OFFSET).value() as u64;
+        {
+            let header = self.command_memory.as_type_mut::<CommandHeader>(COMMAND_LIST_OFFSET)?;
+            let fis_length_in_dwords = (core::mem::size_of::<RegisterFisH2D>() / 4) as u32;
+            let write_flag = if write { 1 << 6 } else { 0 };
+            // one PRDT entry
+            header.flags = fis_length_in_dwords | write_flag | (1 << 16);
+            header.prdbc = 0;
+            header.ctba = table_phys;
+        }
+        {
+            let fis = self.command_memory.as_type_mut::<RegisterFisH2D>(COMMAND_TABLE_OFFSET)?;
+            *fis = RegisterFisH2D {
+                fis_type: FIS_TYPE_REGISTER_H2D,
+                flags: 1 << 7,
+                command: command as u8,
+                lba0: lba as u8,
+                lba1: (lba >> 8) as u8,
+                lba2: (lba >> 16) as u8,
+                // use LBA addressing
+                device: 1 << 6,
+                lba3: (lba >> 24) as u8,
+                lba4: (lba >> 32) as u8,
+                lba5: (lba >> 40) as u8,
+                count_low: sector_count as u8,
+                count_high: (sector_count >> 8) as u8,
+                ..Default::default()
+            };
+        }
+        {
+            // The PRDT starts 128 bytes into the command table.
+            let prdt = self.command_memory.as_type_mut::<PrdtEntry>(COMMAND_TABLE_OFFSET + 0x80)?;
+            prdt.dba = self.dma_buffer_phys.value() as u64;
+            prdt.dbc = ((length - 1) as u32 & 0x3F_FFFF) | (1 << 31);
+        }
+
+        self.wait_for(|regs| regs.tfd.read() & (TFD_BUSY | TFD_DATA_REQUEST) == 0, "AHCI port was busy")?;
+        // Ensure the command is in memory before the HBA is told about it.
+        fence(Ordering::SeqCst);
+        self.with_registers(|regs| regs.ci.write(1))?;
+
+        // Returns `Some(true)` on success and `Some(false)` on an error once the command is no longer running.
+        let check_done = || -> Option<bool> {
+            self.with_registers(|regs| {
+                if regs.tfd.read() & TFD_ERROR != 0 {
+                    Some(false)
+                } else if regs.ci.read() & 1 == 0 {
+                    Some(true)
+                } else {
+                    None
+                }
+            }).unwrap_or(Some(false))
+        };
+
+        // Sleeping requires a current task, so this falls back to polling during early initialization.
+        let woken = if self.hba.interrupts_enabled.load(Ordering::Acquire) {
+            COMPLETION_WAIT_QUEUE.wait_until(&check_done).ok()
+        } else {
+            None
+        };
+        let success = match woken {
+            Some(s) => s,
+            None => {
+                let mut result = None;
+                for _ in 0..POLL_ITERATIONS {
+                    result = check_done();
+                    if result.is_some() {
+                        break;
+                    }
+                    spin_loop_hint();
+                }
+                result.ok_or("AHCI command timed out")?
+            }
+        };
+
+        if !success {
+            let tfd = self.with_registers(|regs| regs.tfd.read())?;
+            error!("AHCI port {}: command {:#X} failed, task file data: {:#X}", self.port_num, command as u8, tfd);
+            self.recover()?;
+            return Err("AHCI command failed");
+        }
+        Ok(())
+    }
+}
+
+/// A SATA drive attached to one port of an AHCI controller.
+pub struct AhciDrive {
+    port: AhciPort,
+    identify_data: AtaIdentifyData,
+    /// Whether this drive is still attached; it is set to false when the drive is unplugged.
+    present: Arc<AtomicBool>,
+}
+
+impl AhciDrive {
+    /// Initializes the given port and identifies the drive attached to it.
+    fn new(hba: Arc<Hba>, port_num: u8) -> Result<AhciDrive, &'static str> {
+        let mut port = AhciPort::new(hba, port_num)?;
+        let signature = port.with_registers(|regs| regs.sig.read())?;
+        if signature != SATA_SIGNATURE {
+            return Err("AHCI port has a non-SATA (e.g., ATAPI) device attached, which is unsupported");
+        }
+
+        port.issue_command(AtaCommand::IdentifyDevice, 0, 0, SECTOR_SIZE_IN_BYTES, false)?;
+        let mut identify_bytes = [0u8; SECTOR_SIZE_IN_BYTES];
+        identify_bytes.copy_from_slice(port.dma_buffer.as_slice::<u8>(0, SECTOR_SIZE_IN_BYTES)?);
+        let identify_data = AtaIdentifyData::new(identify_bytes);
+
+        // DMA commands on SATA drives always use LBA addressing.
+        if identify_data.capabilities & 0x200 == 0 {
+            return Err("drive doesn't support LBA addressing mode");
+        }
+
+        Ok(AhciDrive {
+            port,
+            identify_data,
+            present: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// Returns the number of the HBA port that this drive is attached to.
+    pub fn port_number(&self) -> u8 {
+        self.port.port_num
+    }
+
+    /// Returns the information that this drive reported about itself.
+    pub fn identify_data(&self) -> &AtaIdentifyData {
+        &self.identify_data
+    }
+
+    /// Returns whether this drive is still attached to its port.
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Acquire)
+    }
+
+    /// Checks that a transfer of `buffer_len` bytes starting at `offset_in_sectors` is valid for this drive.
+    fn check_transfer(&self, buffer_len: usize, offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if !self.is_present() {
+            return Err("AHCI drive was removed");
+        }
+        if buffer_len % SECTOR_SIZE_IN_BYTES != 0 {
+            return Err("The buffer length must be a multiple of sector size (512) bytes.");
+        }
+        let sector_count = buffer_len / SECTOR_SIZE_IN_BYTES;
+        if offset_in_sectors + sector_count > self.size_in_sectors() {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(sector_count)
+    }
+}
+
+impl StorageDevice for AhciDrive {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_transfer(buffer.len(), offset_in_sectors)?;
+        for (i, chunk) in buffer.chunks_mut(DMA_BUFFER_SIZE_IN_BYTES).enumerate() {
+            let lba = (offset_in_sectors + i * DMA_BUFFER_SIZE_IN_BYTES / SECTOR_SIZE_IN_BYTES) as u64;
+            let count = (chunk.len() / SECTOR_SIZE_IN_BYTES) as u16;
+            self.port.issue_command(AtaCommand::ReadDmaExt, lba, count, chunk.len(), false)?;
+            chunk.copy_from_slice(self.port.dma_buffer.as_slice::<u8>(0, chunk.len())?);
+        }
+        Ok(sector_count)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_transfer(buffer.len(), offset_in_sectors)?;
+        for (i, chunk) in buffer.chunks(DMA_BUFFER_SIZE_IN_BYTES).enumerate() {
+            let lba = (offset_in_sectors + i * DMA_BUFFER_SIZE_IN_BYTES / SECTOR_SIZE_IN_BYTES) as u64;
+            let count = (chunk.len() / SECTOR_SIZE_IN_BYTES) as u16;
+            self.port.dma_buffer.as_slice_mut::<u8>(0, chunk.len())?.copy_from_slice(chunk);
+            self.port.issue_command(AtaCommand::WriteDmaExt, lba, count, chunk.len(), true)?;
+        }
+        Ok(sector_count)
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        if self.identify_data.user_addressable_sectors != 0 {
+            self.identify_data.user_addressable_sectors as usize
+        } else {
+            self.identify_data.max_48_bit_lba as usize
+        }
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        SECTOR_SIZE_IN_BYTES
+    }
+}
+
+impl fmt::Debug for AhciDrive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // copy the field out of the packed struct before formatting it
+        let model_number = self.identify_data.model_number;
+        write!(f, "AhciDrive {{ port: {}, model: {}, sectors: {}, present: {} }}",
+            self.port.port_num, model_number, self.size_in_sectors(), self.is_present()
+        )
+    }
+}
+
+pub type AhciDriveRef = Arc<Mutex<AhciDrive>>;
+
+
+/// A drive attached to a port, along with its presence flag,
+/// which can be checked and cleared without locking the drive.
+struct AttachedDrive {
+    port_num: u8,
+    drive: AhciDriveRef,
+    present: Arc<AtomicBool>,
+}
+
+/// An AHCI controller (HBA) with up to 32 ports, each of which may have a SATA drive attached.
+pub struct AhciController {
+    hba: Arc<Hba>,
+    /// The drives currently attached to this controller, which the hot-plug task adds to and removes from.
+    drives: Arc<Mutex<Vec<AttachedDrive>>>,
+    /// The MSI vector that this controller's interrupts arrive on, if MSI is supported.
+    _msi: Option<MsiInterrupt>,
+}
+
+impl AhciController {
+    /// Initializes the AHCI controller that is connected as the given `PciDevice`,
+    /// probes all of its ports for attached drives, and starts its hot-plug task.
+    pub fn new(pci_device: &PciDevice) -> Result<AhciController, &'static str> {
+        let abar = pci_device.bar_address(ABAR_INDEX)?;
+        let registers = map_abar(abar)?;
+        pci_device.pci_set_command_bus_master_bit();
+
+        let hba = Arc::new(Hba {
+            location: pci_device.location,
+            registers: MutexIrqSafe::new(registers),
+            pending_port_changes: AtomicU32::new(0),
+            hotplug_wait_queue: WaitQueue::new(),
+            interrupts_enabled: AtomicBool::new(false),
+        });
+
+        let ports_implemented = {
+            let mut registers = hba.registers.lock();
+            let regs = Hba::hba_registers(&mut registers)?;
+            regs.ghc.write(regs.ghc.read() | GHC_AHCI_ENABLE);
+            let version = regs.vs.read();
+            debug!("AHCI controller at {}: version {}.{}, capabilities {:#X}", pci_device.location, version >> 16, version & 0xFFFF, regs.cap.read());
+            regs.pi.read()
+        };
+
+        // Probe each implemented port for a drive, before enabling interrupts.
+        let mut drives = Vec::new();
+        for port_num in (0..MAX_PORTS as u8).filter(|p| ports_implemented & (1 << p) != 0) {
+            if let Some(attached) = probe_port(&hba, port_num) {
+                drives.push(attached);
+            }
+        }
+
+        // Prefer a dedicated MSI vector, falling back to the legacy interrupt line.
+        HBAS.lock().push(Arc::clone(&hba));
+        let (msi, interrupts_registered) = match pci_device.enable_msi() {
+            Ok(mut msi) => {
+                msi.set_handler(ahci_handler)?;
+                (Some(msi), true)
+            }
+            Err(_e) => {
+                use pic::PIC_MASTER_OFFSET;
+                let interrupt_num = pci_device.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
+                // Multiple AHCI controllers may share one legacy interrupt line, as one handler checks all of them.
+                let already_registered = AHCI_INTERRUPT_LINES.lock().contains(&interrupt_num);
+                match register_interrupt(interrupt_num, ahci_handler) {
+                    Ok(_) => {
+                        AHCI_INTERRUPT_LINES.lock().push(interrupt_num);
+                        (None, true)
+                    }
+                    Err(_) if already_registered => (None, true),
+                    Err(e) => {
+                        warn!("AHCI controller at {}: couldn't register interrupt {}, so commands will be polled: {}", pci_device.location, interrupt_num, e);
+                        (None, false)
+                    }
+                }
+            }
+        };
+        if interrupts_registered {
+            let mut registers = hba.registers.lock();
+            let regs = Hba::hba_registers(&mut registers)?;
+            regs.is.write(0xFFFF_FFFF);
+            regs.ghc.write(regs.ghc.read() | GHC_INTERRUPT_ENABLE);
+            hba.interrupts_enabled.store(true, Ordering::Release);
+        }
+
+        let controller = AhciController {
+            hba: Arc::clone(&hba),
+            drives: Arc::new(Mutex::new(drives)),
+            _msi: msi,
+        };
+        info!("{}", controller);
+
+        spawn::new_task_builder(hotplug_task, (hba, Arc::clone(&controller.drives)))
+            .name(format!("ahci_hotplug_{}", pci_device.location))
+            .spawn()?;
+
+        Ok(controller)
+    }
+
+    /// Returns the drives currently attached to this controller.
+    pub fn drives(&self) -> Vec<AhciDriveRef> {
+        self.drives.lock().iter().map(|d| Arc::clone(&d.drive)).collect()
+    }
+}
+
+impl StorageController for AhciController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            self.drives().into_iter().map(|d| d as StorageDeviceRef)
+        )
+    }
+}
+
+impl fmt::Display for AhciController {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AHCI controller at {}:", self.hba.location)?;
+        for attached in self.drives.lock().iter() {
+            write!(f, "\n--> port {}: {:?}", attached.port_num, attached.drive.lock())?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Initializes the drive attached to the given port, if there is one.
+fn probe_port(hba: &Arc<Hba>, port_num: u8) -> Option<AttachedDrive> {
+    let device_detection = {
+        let mut registers = hba.registers.lock();
+        Hba::port_registers(&mut registers, port_num).ok()?.ssts.read() & 0xF
+    };
+    if device_detection != SSTS_DEVICE_PRESENT {
+        return None;
+    }
+    match AhciDrive::new(Arc::clone(hba), port_num) {
+        Ok(drive) => {
+            let present = Arc::clone(&drive.present);
+            Some(AttachedDrive { port_num, drive: Arc::new(Mutex::new(drive)), present })
+        }
+        Err(e) => {
+            warn!("AHCI controller at {}: couldn't initialize drive on port {}: {}", hba.location, port_num, e);
+            None
+        }
+    }
+}
+
+/// The entry point of each HBA's hot-plug task, which adds and removes drives
+/// when the HBA reports that a port's connection status has changed.
+fn hotplug_task((hba, drives): (Arc<Hba>, Arc<Mutex<Vec<AttachedDrive>>>)) -> Result<(), &'static str> {
+    loop {
+        let changed = hba.hotplug_wait_queue.wait_until(&|| {
+            match hba.pending_port_changes.swap(0, Ordering::AcqRel) {
+                0 => None,
+                changed => Some(changed),
+            }
+        }).map_err(|_| "AHCI hot-plug task failed to wait for port changes")?;
+
+        for port_num in (0..MAX_PORTS as u8).filter(|p| changed & (1 << p) != 0) {
+            // Any drive on this port is removed first, since a different drive may have been attached.
+            let removed = {
+                let mut drives = drives.lock();
+                let index = drives.iter().position(|d| d.port_num == port_num);
+                index.map(|i| drives.remove(i))
+            };
+            if let Some(removed) = removed {
+                removed.present.store(false, Ordering::Release);
+                info!("AHCI controller at {}: drive removed from port {}", hba.location, port_num);
+            }
+            if let Some(attached) = probe_port(&hba, port_num) {
+                info!("AHCI controller at {}: drive attached to port {}: {:?}", hba.location, port_num, attached.drive.lock());
+                drives.lock().push(attached);
+            }
+        }
+    }
+}
+
+
+/// Maps the HBA's registers (its ABAR memory region).
+fn map_abar(abar: PhysicalAddress) -> Result<MappedPages, &'static str> {
+    let size_in_bytes = PORT_REGISTERS_OFFSET + MAX_PORTS * core::mem::size_of::<PortRegisters>();
+    let pages = allocate_pages_by_bytes(size_in_bytes).ok_or("AHCI: couldn't allocate pages for the HBA registers")?;
+    let frames = FrameRange::from_phys_addr(abar, size_in_bytes);
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("AHCI: KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("AHCI: couldn't get the frame allocator")?;
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+    kernel_mmi.page_table.map_allocated_pages_to(pages, frames, AHCI_MAPPING_FLAGS, fa.lock().deref_mut())
+}
+
+
+/// The handler for all AHCI interrupts, which acknowledges each HBA's pending interrupts
+/// and wakes up the tasks waiting for commands to complete.
+extern "x86-interrupt" fn ahci_handler(_stack_frame: &mut ExceptionStackFrame) {
+    let mut handled = false;
+    for hba in HBAS.lock().iter() {
+        handled |= hba.handle_interrupt();
+    }
+    if handled {
+        COMPLETION_WAIT_QUEUE.notify_all();
+    }
+    eoi(None);
+}
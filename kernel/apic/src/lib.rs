@@ -79,6 +79,25 @@ pub fn has_x2apic() -> bool {
     *res // because call_once returns a reference to the cached IS_X2APIC value
 }
 
+/// Returns true if the local APIC timers support the TSC-deadline mode,
+/// in which a timer interrupt occurs once the TSC reaches the value written to the `IA32_TSC_DEADLINE` MSR.
+pub fn has_tsc_deadline() -> bool {
+    static HAS_TSC_DEADLINE: Once<bool> = Once::new(); // caches the result
+    *HAS_TSC_DEADLINE.call_once( || {
+        CpuId::new().get_feature_info().map_or(false, |f| f.has_tsc_deadline())
+    })
+}
+
+/// Arms the local APIC timer of the currently executing core, which must be in TSC-deadline mode
+/// (see [`LocalApic::enable_tsc_deadline_mode()`](struct.LocalApic.html#method.enable_tsc_deadline_mode)),
+/// such that a timer interrupt occurs once the TSC reaches `deadline`.
+///
+/// A deadline that has already passed causes an interrupt right away, and a deadline of zero disarms the timer.
+pub fn set_tsc_deadline(deadline: u64) {
+    // SAFE: the IA32_TSC_DEADLINE MSR only affects this core's APIC timer.
+    unsafe { wrmsr(IA32_TSC_DEADLINE, deadline); }
+}
+
 /// Returns a reference to the list of LocalApics, one per processor core
 pub fn get_lapics() -> &'static AtomicMap<u8, RwLockIrqSafe<LocalApic>> {
 	&LOCAL_APICS
@@ -181,6 +200,8 @@ const IA32_APIC_X2APIC_ENABLE: u64 = 1 << 10; // 0x400
 const IA32_APIC_BASE_MSR_IS_BSP: u64 = 1 << 8; // 0x100
 const APIC_SW_ENABLE: u32 = 1 << 8;
 const APIC_TIMER_PERIODIC:  u32 = 0x2_0000;
+const APIC_TIMER_TSC_DEADLINE: u32 = 0x4_0000;
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
 const APIC_DISABLE: u32 = 0x1_0000;
 const APIC_NMI: u32 = 4 << 8;
 
@@ -329,6 +350,23 @@ impl LocalApic {
 		Ok(lapic)
     }
 
+    /// Re-initializes this `LocalApic` after its hardware state was lost,
+    /// e.g., when the system resumes from the ACPI S3 sleep state.
+    ///
+    /// Like `new()`, this must be invoked on the core that this `LocalApic` belongs to.
+    pub fn reinit(&mut self, nmi_lint: u8, nmi_flags: u16) -> Result<(), &'static str> {
+        unsafe { wrmsr(IA32_TSC_AUX, self.apic_id as u64); }
+        if has_x2apic() {
+            self.enable_x2apic();
+            self.init_timer_x2apic();
+        }
+        else {
+            self.enable_apic()?;
+            self.init_timer()?;
+        }
+        self.set_nmi(nmi_lint, nmi_flags)
+    }
+
     /// enables the spurious interrupt vector, which enables the APIC itself.
     fn enable_apic(&mut self) -> Result<(), &'static str> {
         assert!(!has_x2apic(), "an x2apic system must not use enable_apic(), it should use enable_x2apic() instead.");
@@ -496,6 +534,26 @@ impl LocalApic {
         }
     }
 
+    /// Switches this core's APIC timer from periodic mode to TSC-deadline mode, which disarms it
+    /// until a deadline is set with [`set_tsc_deadline()`](fn.set_tsc_deadline.html).
+    /// The timer still raises interrupt 0x22.
+    ///
+    /// This must be invoked on the core that this `LocalApic` belongs to.
+    pub fn enable_tsc_deadline_mode(&mut self) -> Result<(), &'static str> {
+        if !has_tsc_deadline() {
+            return Err("enable_tsc_deadline_mode(): the APIC timer doesn't support TSC-deadline mode");
+        }
+        if has_x2apic() {
+            unsafe { wrmsr(IA32_X2APIC_LVT_TIMER, (0x22 | APIC_TIMER_TSC_DEADLINE) as u64); }
+        } else {
+            let regs = self.regs.as_mut().ok_or("enable_tsc_deadline_mode(): ApicRegisters were None")?;
+            regs.lvt_timer.write(0x22 | APIC_TIMER_TSC_DEADLINE);
+        }
+        // Intel's manual requires the LVT write to be ordered before the first write to IA32_TSC_DEADLINE.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        Ok(())
+    }
+
     
     pub fn id(&self) -> u8 {
         let id: u8 = if has_x2apic() {
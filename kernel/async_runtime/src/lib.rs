@@ -0,0 +1,409 @@
+//! An executor for asynchronous tasks (futures) that is integrated with Theseus's wait queues, interrupts, and timers.
+//!
+//! Each [`Executor`](struct.Executor.html) holds a set of futures, each of which runs until completion
+//! as an asynchronous task. An asynchronous task is only polled again after its `Waker` has been woken,
+//! so an executor with nothing to do puts the kernel task(s) driving it to sleep on a `WaitQueue`.
+//!
+//! An executor can be driven in two ways:
+//! * By a dedicated kernel task, see [`Executor::spawn_executor_task()`](struct.Executor.html#method.spawn_executor_task),
+//!   which runs the executor until all of its asynchronous tasks have completed.
+//! * Cooperatively by any existing task, see [`Executor::run_until_idle()`](struct.Executor.html#method.run_until_idle)
+//!   and [`Executor::block_on()`](struct.Executor.html#method.block_on).
+//!
+//! Futures can wait for interrupts using an [`InterruptEvent`](struct.InterruptEvent.html),
+//! which an interrupt handler notifies, and for time to pass using [`sleep()`](fn.sleep.html).
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate task;
+extern crate wait_queue;
+extern crate spawn;
+extern crate timer;
+
+mod time;
+
+pub use time::{Sleep, sleep, sleep_until, process_timers};
+pub use timer::Instant;
+
+use core::future::Future;
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use task::TaskRef;
+use wait_queue::WaitQueue;
+
+
+/// A pinned, heap-allocated future that can be run as an asynchronous task.
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+
+/// An asynchronous task, i.e., a future that is being run by an `Executor`.
+struct AsyncTask {
+    /// The future, which is `None` once it has completed.
+    future: Mutex<Option<BoxFuture>>,
+    /// The executor that this task belongs to.
+    executor: Weak<ExecutorInner>,
+    /// Whether this task is already on its executor's ready queue,
+    /// which prevents it from being added again by multiple wakeups.
+    queued: AtomicBool,
+}
+
+/// Adds the given asynchronous task to its executor's ready queue, if it isn't already there,
+/// and wakes up a kernel task that is driving the executor.
+///
+/// This may be invoked from an interrupt handler.
+fn schedule_task(task: &Arc<AsyncTask>) {
+    if task.queued.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    if let Some(executor) = task.executor.upgrade() {
+        executor.ready.lock().push_back(task.clone());
+        executor.wait_queue.notify_one();
+    }
+}
+
+
+// The `Waker` for an `AsyncTask` is a raw pointer obtained from an `Arc<AsyncTask>`.
+static WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake_by_ref, waker_drop);
+
+fn waker_for(task: Arc<AsyncTask>) -> Waker {
+    let raw = RawWaker::new(Arc::into_raw(task) as *const (), &WAKER_VTABLE);
+    // SAFE: the vtable functions uphold the `RawWaker` contract for an `Arc<AsyncTask>` pointer.
+    unsafe { Waker::from_raw(raw) }
+}
+
+unsafe fn waker_clone(ptr: *const ()) -> RawWaker {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const AsyncTask));
+    let cloned: Arc<AsyncTask> = Arc::clone(&task);
+    RawWaker::new(Arc::into_raw(cloned) as *const (), &WAKER_VTABLE)
+}
+
+unsafe fn waker_wake(ptr: *const ()) {
+    let task = Arc::from_raw(ptr as *const AsyncTask);
+    schedule_task(&task);
+}
+
+unsafe fn waker_wake_by_ref(ptr: *const ()) {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const AsyncTask));
+    schedule_task(&task);
+}
+
+unsafe fn waker_drop(ptr: *const ()) {
+    drop(Arc::from_raw(ptr as *const AsyncTask));
+}
+
+
+/// The state of an `Executor` that is shared with its asynchronous tasks' wakers.
+struct ExecutorInner {
+    /// The asynchronous tasks that have been woken and are ready to be polled.
+    /// This is an IRQ-safe lock because wakers may be invoked from interrupt handlers.
+    ready: MutexIrqSafe<VecDeque<Arc<AsyncTask>>>,
+    /// The kernel tasks driving this executor wait here until an asynchronous task becomes ready.
+    wait_queue: WaitQueue,
+    /// The number of asynchronous tasks that have not yet completed.
+    num_tasks: AtomicUsize,
+}
+
+
+/// An executor that runs asynchronous tasks (futures) until they complete.
+///
+/// An `Executor` can be cloned to obtain another reference to the same executor,
+/// e.g., to spawn new asynchronous tasks on it from within a running asynchronous task.
+#[derive(Clone)]
+pub struct Executor(Arc<ExecutorInner>);
+
+impl Executor {
+    /// Creates a new executor with no asynchronous tasks.
+    pub fn new() -> Executor {
+        Executor(Arc::new(ExecutorInner {
+            ready: MutexIrqSafe::new(VecDeque::new()),
+            wait_queue: WaitQueue::new(),
+            num_tasks: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Adds the given `future` to this executor as a new asynchronous task,
+    /// which will first be polled the next time this executor runs.
+    pub fn spawn<F>(&self, future: F) where F: Future<Output = ()> + Send + 'static {
+        self.spawn_boxed(Box::pin(future));
+    }
+
+    /// Like [`spawn()`](#method.spawn), but accepts a future that is already pinned and boxed.
+    pub fn spawn_boxed(&self, future: BoxFuture) {
+        let task = Arc::new(AsyncTask {
+            future: Mutex::new(Some(future)),
+            executor: Arc::downgrade(&self.0),
+            queued: AtomicBool::new(false),
+        });
+        self.0.num_tasks.fetch_add(1, Ordering::AcqRel);
+        schedule_task(&task);
+    }
+
+    /// Adds the given `future` to this executor as a new asynchronous task,
+    /// and returns a `JoinHandle` that can be used to obtain the future's output.
+    pub fn spawn_with_handle<F, T>(&self, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + Send + 'static,
+              T: Send + 'static,
+    {
+        let state = Arc::new(JoinState {
+            output: Mutex::new(None),
+            waker: Mutex::new(None),
+        });
+        let handle = JoinHandle { state: state.clone() };
+        self.spawn(WithOutput { future: Box::pin(future), state });
+        handle
+    }
+
+    /// Returns the number of asynchronous tasks in this executor that have not yet completed.
+    pub fn num_tasks(&self) -> usize {
+        self.0.num_tasks.load(Ordering::Acquire)
+    }
+
+    /// Polls every asynchronous task that is ready to make progress, until none are left,
+    /// and then returns the number of polls that occurred.
+    ///
+    /// This also wakes up any futures whose timers have expired, see [`process_timers()`](fn.process_timers.html).
+    /// It can be invoked repeatedly by a task that drives this executor cooperatively,
+    /// e.g., in its main loop, without ever blocking.
+    pub fn run_until_idle(&self) -> usize {
+        let mut polls = 0;
+        loop {
+            process_timers();
+            let task = match self.0.ready.lock().pop_front() {
+                Some(t) => t,
+                _ => return polls,
+            };
+            // Clear this before polling, such that a wakeup during the poll causes another poll.
+            task.queued.store(false, Ordering::Release);
+
+            let mut future_slot = task.future.lock();
+            let completed = match future_slot.as_mut() {
+                Some(future) => {
+                    let waker = waker_for(task.clone());
+                    let mut cx = Context::from_waker(&waker);
+                    future.as_mut().poll(&mut cx).is_ready()
+                }
+                // The task already completed, but it was woken again afterwards.
+                None => false,
+            };
+            if completed {
+                *future_slot = None;
+                self.0.num_tasks.fetch_sub(1, Ordering::AcqRel);
+            }
+            polls += 1;
+        }
+    }
+
+    /// Runs this executor on the current task until all of its asynchronous tasks have completed.
+    ///
+    /// When no asynchronous task is ready, the current task is put to sleep until one is woken up,
+    /// e.g., by the timer interrupt once a future's timer has expired.
+    pub fn run(&self) -> Result<(), &'static str> {
+        loop {
+            self.run_until_idle();
+            if self.num_tasks() == 0 {
+                return Ok(());
+            }
+            self.wait_for_work()?;
+        }
+    }
+
+    /// Runs this executor on the current task until the given `future` completes, and returns its output.
+    ///
+    /// Other asynchronous tasks on this executor also make progress while waiting for the `future`.
+    pub fn block_on<F, T>(&self, future: F) -> Result<T, &'static str>
+        where F: Future<Output = T> + Send + 'static,
+              T: Send + 'static,
+    {
+        let handle = self.spawn_with_handle(future);
+        loop {
+            self.run_until_idle();
+            if let Some(output) = handle.try_take() {
+                return Ok(output);
+            }
+            self.wait_for_work()?;
+        }
+    }
+
+    /// Spawns a new kernel task with the given `name` that runs this executor
+    /// until all of its asynchronous tasks have completed.
+    pub fn spawn_executor_task(&self, name: String) -> Result<TaskRef, &'static str> {
+        spawn::new_task_builder(executor_task_entry, self.clone())
+            .name(name)
+            .spawn()
+    }
+
+    /// Puts the current task to sleep until an asynchronous task is ready.
+    fn wait_for_work(&self) -> Result<(), &'static str> {
+        let inner = &self.0;
+        inner.wait_queue
+            .wait_until(&|| if !inner.ready.lock().is_empty() { Some(()) } else { None })
+            .map_err(|_| "Executor: failed to wait for an asynchronous task to become ready")
+    }
+}
+
+fn executor_task_entry(executor: Executor) -> Result<(), &'static str> {
+    let res = executor.run();
+    if let Err(e) = res {
+        error!("async_runtime: executor task failed: {}", e);
+    }
+    res
+}
+
+
+/// The state shared between a `JoinHandle` and the asynchronous task it refers to.
+struct JoinState<T> {
+    output: Mutex<Option<T>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// A future that runs an inner future and stores its output in a `JoinState`.
+struct WithOutput<T> {
+    future: Pin<Box<dyn Future<Output = T> + Send + 'static>>,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> Future for WithOutput<T> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(output) => {
+                *self.state.output.lock() = Some(output);
+                let waker = self.state.waker.lock().take();
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A handle to an asynchronous task that can be used to obtain its output,
+/// returned by [`Executor::spawn_with_handle()`](struct.Executor.html#method.spawn_with_handle).
+///
+/// A `JoinHandle` is itself a future that completes with the asynchronous task's output.
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns the asynchronous task's output if it has completed, without waiting.
+    ///
+    /// The output can only be taken once; afterwards, this returns `None`.
+    pub fn try_take(&self) -> Option<T> {
+        self.state.output.lock().take()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        // Register the waker before checking the output, so a completion in between isn't missed.
+        *self.state.waker.lock() = Some(cx.waker().clone());
+        match self.try_take() {
+            Some(output) => Poll::Ready(output),
+            None => Poll::Pending,
+        }
+    }
+}
+
+
+/// An event that an interrupt handler can notify in order to wake up a future that is waiting for it.
+///
+/// This allows a driver to be written in an asynchronous style:
+/// its interrupt handler calls [`notify()`](#method.notify),
+/// and an asynchronous task awaits the future returned by [`wait()`](#method.wait).
+/// Notifications that occur while no future is waiting are counted, not lost.
+pub struct InterruptEvent {
+    /// The number of notifications that have not yet been consumed by a waiting future.
+    pending: AtomicUsize,
+    waker: MutexIrqSafe<Option<Waker>>,
+}
+
+impl InterruptEvent {
+    /// Creates a new `InterruptEvent` with no pending notifications.
+    pub fn new() -> InterruptEvent {
+        InterruptEvent {
+            pending: AtomicUsize::new(0),
+            waker: MutexIrqSafe::new(None),
+        }
+    }
+
+    /// Notifies this event, waking up the future that is waiting for it, if any.
+    ///
+    /// This is safe to invoke from an interrupt handler, as it never blocks.
+    pub fn notify(&self) {
+        self.pending.fetch_add(1, Ordering::AcqRel);
+        let waker = self.waker.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Returns a future that completes once this event has been notified,
+    /// with the number of notifications that occurred since it was last waited for.
+    ///
+    /// Only one future should wait for a given `InterruptEvent` at a time.
+    pub fn wait(&self) -> InterruptEventFuture {
+        InterruptEventFuture { event: self }
+    }
+}
+
+/// A future that completes once an `InterruptEvent` has been notified,
+/// returned by [`InterruptEvent::wait()`](struct.InterruptEvent.html#method.wait).
+pub struct InterruptEventFuture<'e> {
+    event: &'e InterruptEvent,
+}
+
+impl<'e> Future for InterruptEventFuture<'e> {
+    type Output = usize;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+        // Register the waker before checking for notifications, so a notification in between isn't missed.
+        *self.event.waker.lock() = Some(cx.waker().clone());
+        match self.event.pending.swap(0, Ordering::AcqRel) {
+            0 => Poll::Pending,
+            count => {
+                self.event.waker.lock().take();
+                Poll::Ready(count)
+            }
+        }
+    }
+}
+
+
+/// Returns a future that yields once, i.e., it returns `Pending` the first time it is polled
+/// and lets the executor run other asynchronous tasks before it completes.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// A future that yields once to the executor, returned by [`yield_now()`](fn.yield_now.html).
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Timer futures that complete once a deadline has passed, built on the software timers of the `timer` crate.
+//!
+//! A [`Sleep`](struct.Sleep.html) future registers its waker with `timer::wake_at()`,
+//! so it is woken up by the timer interrupt once its deadline passes, while the executor driving it sleeps.
+//!
+//! Timestamps are taken with `timer::Instant`.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use timer::{Instant, TimerHandle};
+
+
+/// Returns a future that completes once at least `ms` milliseconds have passed.
+pub fn sleep(ms: u64) -> Sleep {
+    sleep_until(Instant::now() + Duration::from_millis(ms))
+}
+
+/// Returns a future that completes once the monotonic clock has reached the given `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+/// Wakes up every future whose timer has expired, and returns how many were woken up.
+///
+/// The timer interrupt already does this; executors also invoke it while running,
+/// so that futures are woken up promptly on systems where timers expire only at the preemption tick.
+pub fn process_timers() -> usize {
+    timer::process_timers()
+}
+
+
+/// A future that completes once the monotonic clock has reached a deadline,
+/// returned by [`sleep()`](fn.sleep.html) and [`sleep_until()`](fn.sleep_until.html).
+pub struct Sleep {
+    deadline: Instant,
+    /// The timer that wakes up this future, if it has been polled.
+    timer: Option<TimerHandle>,
+}
+
+impl Sleep {
+    /// Returns the instant at which this future completes.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        // Replace this future's existing timer, in case it is now being polled with a different waker.
+        if let Some(old_timer) = self.timer.take() {
+            old_timer.cancel();
+        }
+        self.timer = Some(timer::wake_at(self.deadline, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(ref timer) = self.timer {
+            timer.cancel();
+        }
+    }
+}
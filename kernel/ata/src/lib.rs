@@ -24,7 +24,7 @@ use pci::PciDevice;
 use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
 
 
-const SECTOR_SIZE_IN_BYTES: usize = 512;
+pub const SECTOR_SIZE_IN_BYTES: usize = 512;
 
 const DEFAULT_PRIMARY_CHANNEL_DATA_PORT:         u16 = 0x1F0;
 const DEFAULT_PRIMARY_CHANNEL_CONTROL_PORT:      u16 = 0x3F6;
@@ -86,7 +86,7 @@ bitflags! {
 /// The possible commands that can be issued to an ATA drive's command port. 
 /// More esoteric commands (nearly a full list) are here: <https://wiki.osdev.org/ATA_Command_Matrix>.
 #[repr(u8)]
-enum AtaCommand {
+pub enum AtaCommand {
 	/// Read sectors using PIO (28-bit LBA)
 	ReadPio         = 0x20,
 	/// Read sectors using PIO (48-bit LBA)
@@ -976,7 +976,7 @@ pub struct AtaIdentifyData {
 impl AtaIdentifyData {
 	/// Converts the given byte array, which should be the result of an ATA identify command,
 	/// into a struct that contains the identified details of an ATA drive.
-	fn new(arr: [u8; SECTOR_SIZE_IN_BYTES])-> AtaIdentifyData {
+	pub fn new(arr: [u8; SECTOR_SIZE_IN_BYTES])-> AtaIdentifyData {
 		let mut identify_data: AtaIdentifyData = unsafe { core::mem::transmute(arr) };
 		Self::flip_bytes(&mut identify_data.serial_number.0);
 		Self::flip_bytes(&mut identify_data.firmware_version.0);
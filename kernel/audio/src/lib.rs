@@ -0,0 +1,332 @@
+//! The audio subsystem, which mixes the sound of all open output streams and plays it on a sound card.
+//!
+//! Applications call [`open_output()`](fn.open_output.html) with the format of their PCM samples
+//! and submit samples to the returned [`OutputStream`](struct.OutputStream.html),
+//! which blocks while the stream's queue is full.
+//! Each stream may use its own sample rate and channel count; the mixer converts every stream
+//! to the format of the sound card, which is always interleaved 16-bit stereo at the card's sample rate.
+//!
+//! Sound card drivers register themselves with [`register_device()`](fn.register_device.html).
+//! Once started, a driver refills its DMA buffers from its interrupt handler by calling [`mix()`](fn.mix.html),
+//! and stops itself after it has played only silence for a while.
+//! Currently, only the first registered sound card is used.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate wait_queue;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use wait_queue::WaitQueue;
+
+
+/// How much audio each stream queues before submitting more samples blocks, in milliseconds.
+const QUEUE_DURATION_MS: usize = 200;
+
+/// The number of fractional bits in the fixed-point positions and steps used for resampling.
+const FRACTION_BITS: u32 = 16;
+
+/// The highest sample rate that a stream may use.
+const MAX_SAMPLE_RATE: u32 = 192_000;
+
+
+/// The encoding of each sample in a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Unsigned 8-bit samples, where silence is 128.
+    U8,
+    /// Signed 16-bit little-endian samples.
+    S16Le,
+}
+
+impl SampleFormat {
+    /// Returns the size of one sample in bytes.
+    pub fn bytes_per_sample(&self) -> usize {
+        match *self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16Le => 2,
+        }
+    }
+}
+
+/// The format of the PCM samples submitted to an output stream.
+/// Samples of multiple channels are interleaved, e.g., left and right for stereo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioFormat {
+    /// The number of frames (one sample per channel) per second.
+    pub sample_rate: u32,
+    /// Either 1 (mono) or 2 (stereo).
+    pub channels: u16,
+    pub sample_format: SampleFormat,
+}
+
+impl AudioFormat {
+    /// Returns the size of one frame, i.e., one sample for each channel, in bytes.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.channels as usize * self.sample_format.bytes_per_sample()
+    }
+
+    /// Converts the frame at the start of `bytes` to a stereo frame of 16-bit samples.
+    fn decode_frame(&self, bytes: &[u8]) -> [i16; 2] {
+        let sample = |i: usize| match self.sample_format {
+            SampleFormat::U8 => ((bytes[i] as i16) - 128) << 8,
+            SampleFormat::S16Le => i16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]),
+        };
+        if self.channels == 1 {
+            [sample(0), sample(0)]
+        } else {
+            [sample(0), sample(1)]
+        }
+    }
+}
+
+
+/// A sound card that plays interleaved 16-bit stereo samples.
+pub trait AudioDevice: Send {
+    /// Returns a short name of the device for logging, e.g., "AC'97".
+    fn name(&self) -> &str;
+
+    /// Returns the number of frames per second that the device plays.
+    fn sample_rate(&self) -> u32;
+
+    /// Starts playing, if the device isn't already.
+    ///
+    /// While playing, the device fills its buffers with [`mix()`](fn.mix.html).
+    /// It stops by itself once its buffers hold nothing but silence.
+    fn start(&mut self) -> Result<(), &'static str>;
+}
+
+/// The sound card that output streams are played on.
+static DEVICE: Mutex<Option<Box<dyn AudioDevice>>> = Mutex::new(None);
+
+/// All open output streams, which the mixer reads from.
+static STREAMS: MutexIrqSafe<Vec<Arc<Stream>>> = MutexIrqSafe::new(Vec::new());
+
+/// The volume that all streams are scaled by, as a percentage.
+static MASTER_VOLUME: AtomicUsize = AtomicUsize::new(100);
+
+
+/// Registers a sound card to play output streams on.
+///
+/// Only the first registered device is used; others are ignored.
+pub fn register_device(device: Box<dyn AudioDevice>) {
+    let mut current = DEVICE.lock();
+    if let Some(ref current) = *current {
+        warn!("audio: ignoring {} device, already using {} device", device.name(), current.name());
+        return;
+    }
+    info!("audio: playing output on {} device at {} Hz", device.name(), device.sample_rate());
+    *current = Some(device);
+}
+
+/// Returns whether a sound card has been registered.
+pub fn has_device() -> bool {
+    DEVICE.lock().is_some()
+}
+
+/// Sets the volume that all streams are scaled by, as a percentage from 0 to 100.
+pub fn set_master_volume(percent: u8) {
+    MASTER_VOLUME.store(core::cmp::min(percent, 100) as usize, Ordering::Release);
+}
+
+/// Returns the volume that all streams are scaled by, as a percentage.
+pub fn master_volume() -> u8 {
+    MASTER_VOLUME.load(Ordering::Acquire) as u8
+}
+
+
+/// Opens a new output stream, to which samples in the given `format` can be submitted.
+///
+/// Returns an error if no sound card is present or the format isn't supported.
+pub fn open_output(format: AudioFormat) -> Result<OutputStream, &'static str> {
+    if format.channels != 1 && format.channels != 2 {
+        return Err("audio: only mono and stereo streams are supported");
+    }
+    if format.sample_rate == 0 || format.sample_rate > MAX_SAMPLE_RATE {
+        return Err("audio: unsupported sample rate");
+    }
+
+    let device_sample_rate = DEVICE.lock().as_ref().ok_or("audio: no sound card is present")?.sample_rate();
+    let capacity = format.sample_rate as usize * QUEUE_DURATION_MS / 1000;
+    let stream = Arc::new(Stream {
+        state: MutexIrqSafe::new(StreamState {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            step: ((format.sample_rate as u64) << FRACTION_BITS) / device_sample_rate as u64,
+            position: 0,
+            volume: 100,
+        }),
+        waiting_tasks: WaitQueue::new(),
+    });
+    STREAMS.lock().push(Arc::clone(&stream));
+    Ok(OutputStream { stream, format })
+}
+
+
+/// Fills `output` with the next interleaved stereo samples of the mix of all output streams,
+/// at the sample rate of the device.
+///
+/// This is meant to be called by sound card drivers, typically from their interrupt handler.
+/// Returns `false` if no stream had any samples, in which case `output` is filled with silence.
+pub fn mix(output: &mut [i16]) -> bool {
+    let master_volume = MASTER_VOLUME.load(Ordering::Acquire) as i32;
+    for sample in output.iter_mut() {
+        *sample = 0;
+    }
+    let mut audible = false;
+    for stream in STREAMS.lock().iter() {
+        {
+            let mut state = stream.state.lock();
+            if state.frames.is_empty() {
+                continue;
+            }
+            audible = true;
+            let volume = state.volume as i32 * master_volume;
+            for samples in output.chunks_exact_mut(2) {
+                let frame = match state.next_frame() {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                for (sample, &stream_sample) in samples.iter_mut().zip(frame.iter()) {
+                    let mixed = *sample as i32 + stream_sample as i32 * volume / (100 * 100);
+                    *sample = core::cmp::max(core::cmp::min(mixed, i16::MAX as i32), i16::MIN as i32) as i16;
+                }
+            }
+        }
+        // Frames were taken from the queue, so tasks waiting to submit more can continue.
+        stream.waiting_tasks.notify_all();
+    }
+    audible
+}
+
+
+/// The shared state of an output stream, which the mixer reads from.
+struct Stream {
+    state: MutexIrqSafe<StreamState>,
+    /// The tasks waiting for frames to be taken from the queue.
+    waiting_tasks: WaitQueue,
+}
+
+struct StreamState {
+    /// The frames that haven't been mixed yet, at the stream's sample rate.
+    frames: VecDeque<[i16; 2]>,
+    /// The number of frames that the queue holds at most.
+    capacity: usize,
+    /// How far each output frame advances through the stream's frames, in fixed point.
+    step: u64,
+    /// How far the next output frame lies past the front frame, in fixed point.
+    position: u64,
+    /// The volume of this stream, as a percentage.
+    volume: u8,
+}
+
+impl StreamState {
+    /// Returns the next frame at the device's sample rate, interpolating between the stream's frames,
+    /// or `None` if the queue is empty.
+    fn next_frame(&mut self) -> Option<[i16; 2]> {
+        let one = 1u64 << FRACTION_BITS;
+        while self.position >= one {
+            self.frames.pop_front()?;
+            self.position -= one;
+        }
+        let current = *self.frames.front()?;
+        // The last queued frame is held until the next frame arrives.
+        let next = *self.frames.get(1).unwrap_or(&current);
+        let fraction = self.position as i32;
+        let interpolate = |a: i16, b: i16| (a as i32 + (((b as i32 - a as i32) * fraction) >> FRACTION_BITS)) as i16;
+        self.position += self.step;
+        Some([interpolate(current[0], next[0]), interpolate(current[1], next[1])])
+    }
+}
+
+
+/// An open output stream, whose samples are mixed with those of all other streams and played on the sound card.
+///
+/// The stream is closed when it is dropped; any samples that haven't been played yet are discarded.
+pub struct OutputStream {
+    stream: Arc<Stream>,
+    format: AudioFormat,
+}
+
+impl OutputStream {
+    /// Returns the format of the samples of this stream.
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Sets the volume of this stream, as a percentage from 0 to 100.
+    pub fn set_volume(&self, percent: u8) {
+        self.stream.state.lock().volume = core::cmp::min(percent, 100);
+    }
+
+    /// Submits the samples in `data`, blocking until there is space for at least one frame in the stream's queue.
+    ///
+    /// Returns the number of bytes submitted, which is always a whole number of frames
+    /// and at least one frame unless `data` holds less than a frame.
+    /// Use [`write_all()`](#method.write_all) to submit all of `data`.
+    pub fn write(&self, data: &[u8]) -> Result<usize, &'static str> {
+        let frame_size = self.format.bytes_per_frame();
+        if data.len() < frame_size {
+            return Ok(0);
+        }
+        let mut closure = || {
+            let mut state = self.stream.state.lock();
+            let space = state.capacity - state.frames.len();
+            if space == 0 {
+                return None;
+            }
+            let mut written = 0;
+            for frame in data.chunks_exact(frame_size).take(space) {
+                state.frames.push_back(self.format.decode_frame(frame));
+                written += frame_size;
+            }
+            Some(written)
+        };
+        let written = self.stream.waiting_tasks.wait_until_mut(&mut closure)
+            .map_err(|_| "audio: failed to wait for space in the stream")?;
+
+        // The device stops once all streams have gone quiet, so restart it in case it did.
+        if let Some(ref mut device) = *DEVICE.lock() {
+            device.start()?;
+        }
+        Ok(written)
+    }
+
+    /// Submits all whole frames in `data`, blocking as needed until they all fit in the stream's queue.
+    /// Any incomplete frame at the end of `data` is ignored.
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), &'static str> {
+        let frame_size = self.format.bytes_per_frame();
+        while data.len() >= frame_size {
+            let written = self.write(data)?;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+
+    /// Blocks until all submitted samples have been taken from the stream's queue by the mixer.
+    ///
+    /// The last samples may still be in the sound card's buffers when this returns.
+    pub fn drain(&self) -> Result<(), &'static str> {
+        self.stream.waiting_tasks.wait_until(&|| {
+            if self.stream.state.lock().frames.is_empty() { Some(()) } else { None }
+        }).map_err(|_| "audio: failed to wait for the stream to drain")
+    }
+}
+
+impl Drop for OutputStream {
+    fn drop(&mut self) {
+        let stream = &self.stream;
+        STREAMS.lock().retain(|s| !Arc::ptr_eq(s, stream));
+    }
+}
@@ -0,0 +1,184 @@
+//! Captures symbolized backtraces of the current call stack.
+//! 
+//! Each return address on the call stack is resolved to the `LoadedSection` containing it,
+//! using the section metadata of the current task's `CrateNamespace`, 
+//! which yields the function's name, its containing crate, and the offset into it.
+//! 
+//! By default, stack frames are found using DWARF debug info (see the `stack_trace` crate).
+//! If Theseus is built with frame pointers, they are used instead (see `stack_trace_frame_pointers`).
+//! 
+//! Unlike simply printing a stack trace, a captured [`Backtrace`](struct.Backtrace.html)
+//! can be stored and inspected later, e.g., by the fault log and fault recovery subsystem.
+
+#![no_std]
+
+extern crate alloc;
+extern crate memory;
+extern crate mod_mgmt;
+extern crate task;
+extern crate stack_trace;
+extern crate stack_trace_frame_pointers;
+
+use core::fmt;
+use alloc::{
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use memory::VirtualAddress;
+use mod_mgmt::CrateNamespace;
+
+
+/// The default maximum number of stack frames in a backtrace.
+pub const DEFAULT_MAX_FRAMES: usize = 64;
+
+/// The crates whose frames at the top of the call stack belong to the backtrace machinery itself,
+/// which are always omitted from a captured backtrace.
+const INTERNAL_CRATES: [&'static str; 4] = ["backtrace", "stack_trace", "stack_trace_frame_pointers", "unwind"];
+
+
+/// A single symbolized frame in a [`Backtrace`](struct.Backtrace.html).
+#[derive(Debug, Clone)]
+pub struct BacktraceFrame {
+    /// The call site (return) address of this frame.
+    pub address: VirtualAddress,
+    /// The full name of the section (function) containing the `address`, if known.
+    pub section_name: Option<String>,
+    /// The name of the crate containing that section, if known.
+    pub crate_name: Option<String>,
+    /// The offset of the `address` from the start of its containing section.
+    pub offset: usize,
+}
+
+impl BacktraceFrame {
+    /// Resolves the given `address` into a symbolized frame using the given `namespace`.
+    pub fn resolve(address: VirtualAddress, namespace: &CrateNamespace) -> BacktraceFrame {
+        match namespace.get_section_containing_address(address, false) {
+            Some((sec, offset)) => BacktraceFrame {
+                address,
+                section_name: Some(sec.name.clone()),
+                crate_name: sec.parent_crate.upgrade().map(|c| c.lock_as_ref().crate_name.clone()),
+                offset,
+            },
+            None => BacktraceFrame { address, section_name: None, crate_name: None, offset: 0 },
+        }
+    }
+}
+
+impl fmt::Display for BacktraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.section_name, &self.crate_name) {
+            (Some(sec), Some(krate)) => write!(f, "{:>#018X} in {} + {:#X} ({})", self.address, sec, self.offset, krate),
+            (Some(sec), None)        => write!(f, "{:>#018X} in {} + {:#X}", self.address, sec, self.offset),
+            _                        => write!(f, "{:>#018X} in ??", self.address),
+        }
+    }
+}
+
+
+/// A captured, symbolized backtrace of a call stack, innermost frame first.
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    /// The frames of the call stack, starting with the most recent call.
+    pub frames: Vec<BacktraceFrame>,
+    /// Why the backtrace stopped before reaching the beginning of the stack, if it did.
+    pub error: Option<&'static str>,
+}
+
+impl Backtrace {
+    /// Returns the name of the innermost crate on the call stack that matches the given predicate,
+    /// which is useful for finding which crate to blame for a fault.
+    pub fn find_crate<F: Fn(&str) -> bool>(&self, predicate: F) -> Option<&str> {
+        self.frames.iter()
+            .filter_map(|frame| frame.crate_name.as_ref())
+            .find(|name| predicate(name))
+            .map(|name| name.as_str())
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frame in &self.frames {
+            writeln!(f, "  {}", frame)?;
+        }
+        match self.error {
+            Some(e) => write!(f, "  {}", e),
+            None => write!(f, "  Beginning of stack"),
+        }
+    }
+}
+
+
+/// Captures a symbolized backtrace of the current call stack.
+/// 
+/// # Arguments
+/// * `skip_frames`: the number of innermost frames to omit, e.g., those of a panic or exception handler.
+///   Frames from the backtrace machinery itself (this crate and the stack tracing crates) are always omitted.
+/// * `max_frames`: the maximum number of frames to capture; if `None`, [`DEFAULT_MAX_FRAMES`] is used.
+/// 
+/// [`DEFAULT_MAX_FRAMES`]: constant.DEFAULT_MAX_FRAMES.html
+#[inline(never)]
+pub fn capture(skip_frames: usize, max_frames: Option<usize>) -> Backtrace {
+    let max_frames = max_frames.unwrap_or(DEFAULT_MAX_FRAMES);
+    let namespace = match current_namespace() {
+        Some(ns) => ns,
+        None => return Backtrace { frames: Vec::new(), error: Some("couldn't get current task's or default namespace") },
+    };
+    let mut addresses: Vec<VirtualAddress> = Vec::new();
+    let result = collect_addresses(&mut addresses, INTERNAL_CRATES.len() + skip_frames + max_frames);
+    let frames = addresses.into_iter()
+        .map(|addr| BacktraceFrame::resolve(addr, &namespace))
+        .skip_while(|frame| frame.crate_name.as_ref().map_or(false, |name| is_internal_crate(name)))
+        .skip(skip_frames)
+        .take(max_frames)
+        .collect();
+    Backtrace { frames, error: result.err() }
+}
+
+/// Returns true if the given crate name (which may include a hash suffix) is one of the [`INTERNAL_CRATES`].
+fn is_internal_crate(crate_name: &str) -> bool {
+    let name_without_hash = crate_name.split('-').next().unwrap_or(crate_name);
+    INTERNAL_CRATES.contains(&name_without_hash)
+}
+
+/// Returns the current task's namespace, or the initial kernel namespace if there is no current task.
+fn current_namespace() -> Option<Arc<CrateNamespace>> {
+    task::get_my_current_task()
+        .map(|t| t.get_namespace())
+        .or_else(|| mod_mgmt::get_initial_kernel_namespace().cloned())
+}
+
+/// Collects the call site addresses of up to `limit` frames in the current call stack using DWARF debug info.
+#[cfg(not(frame_pointers))]
+#[inline(never)]
+fn collect_addresses(addresses: &mut Vec<VirtualAddress>, limit: usize) -> Result<(), &'static str> {
+    let addresses = core::cell::RefCell::new(addresses);
+    let result = stack_trace::stack_trace(
+        &|stack_frame, _stack_frame_iter| {
+            addresses.borrow_mut().push(VirtualAddress::new_canonical(stack_frame.call_site_address() as usize));
+            true
+        },
+        Some(limit),
+    );
+    // Reaching the requested limit isn't an error for a backtrace.
+    if addresses.borrow().len() >= limit { Ok(()) } else { result }
+}
+
+/// Collects the call site addresses of up to `limit` frames in the current call stack using frame pointers.
+#[cfg(frame_pointers)]
+#[inline(never)]
+fn collect_addresses(addresses: &mut Vec<VirtualAddress>, limit: usize) -> Result<(), &'static str> {
+    let mmi_ref = task::get_my_current_task()
+        .map(|t| t.lock().mmi.clone())
+        .or_else(|| memory::get_kernel_mmi_ref())
+        .ok_or("couldn't get current task's or default kernel MMI")?;
+    let mmi = mmi_ref.lock();
+    stack_trace_frame_pointers::stack_trace_using_frame_pointers(
+        &mmi.page_table,
+        &mut |_frame_pointer, instruction_pointer: VirtualAddress| {
+            addresses.push(instruction_pointer);
+            addresses.len() < limit
+        },
+        Some(limit),
+    )
+}
@@ -0,0 +1,76 @@
+//! A barrier that puts tasks to sleep until a given number of tasks have all reached it.
+
+#![no_std]
+
+extern crate spin;
+extern crate wait_queue;
+
+use spin::Mutex;
+use wait_queue::{WaitQueue, WaitError};
+
+
+/// Indicates whether the current task was the "leader" among the tasks released by a `Barrier`,
+/// returned from [`Barrier::wait()`](struct.Barrier.html#method.wait).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` for exactly one task each time the barrier releases its waiting tasks,
+    /// namely the last task to reach the barrier.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+
+/// A reusable barrier that blocks tasks until a fixed number of them have called `wait()`.
+pub struct Barrier {
+    num_tasks: usize,
+    state: Mutex<BarrierState>,
+    queue: WaitQueue,
+}
+
+struct BarrierState {
+    /// The number of tasks currently waiting at the barrier.
+    count: usize,
+    /// Incremented each time the barrier releases its waiting tasks.
+    generation: usize,
+}
+
+impl Barrier {
+    /// Creates a new barrier that releases waiting tasks once `num_tasks` of them have reached it.
+    ///
+    /// A `num_tasks` of `0` is treated as `1`, meaning that `wait()` never blocks.
+    pub fn new(num_tasks: usize) -> Barrier {
+        Barrier {
+            num_tasks: core::cmp::max(num_tasks, 1),
+            state: Mutex::new(BarrierState { count: 0, generation: 0 }),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Blocks the current task until `num_tasks` tasks have reached this barrier.
+    ///
+    /// Once released, the barrier resets itself such that it can be reused.
+    pub fn wait(&self) -> Result<BarrierWaitResult, WaitError> {
+        let generation = {
+            let mut state = self.state.lock();
+            state.count += 1;
+            if state.count >= self.num_tasks {
+                state.count = 0;
+                state.generation = state.generation.wrapping_add(1);
+                drop(state);
+                self.queue.notify_all();
+                return Ok(BarrierWaitResult(true));
+            }
+            state.generation
+        };
+
+        // The condition is checked with the waitqueue locked, so the final task's `notify_all()`
+        // can't occur between checking the generation and blocking this task.
+        self.queue.wait_until(&|| {
+            if self.state.lock().generation != generation { Some(()) } else { None }
+        })?;
+        Ok(BarrierWaitResult(false))
+    }
+}
@@ -0,0 +1,48 @@
+//! The `BlockDevice` trait, the interface between the block I/O layer and the devices beneath it.
+
+use storage_device::StorageDeviceRef;
+
+
+/// A device that can be read from and written to in fixed-size blocks.
+///
+/// Unlike a [`StorageDevice`](../storage_device/trait.StorageDevice.html),
+/// a `BlockDevice` is accessed through a shared reference,
+/// so it is responsible for its own synchronization and can be shared by many request queues or users.
+pub trait BlockDevice: Send + Sync {
+    /// Returns the size of a single block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Returns the number of blocks in this device.
+    fn num_blocks(&self) -> usize;
+
+    /// Reads blocks into the given `buffer`, starting at block number `first_block`.
+    /// The length of the `buffer` must be a multiple of the [`block_size()`](#tymethod.block_size).
+    ///
+    /// Returns the number of blocks read.
+    fn read_blocks(&self, buffer: &mut [u8], first_block: usize) -> Result<usize, &'static str>;
+
+    /// Writes the given `buffer` to the device, starting at block number `first_block`.
+    /// The length of the `buffer` must be a multiple of the [`block_size()`](#tymethod.block_size).
+    ///
+    /// Returns the number of blocks written.
+    fn write_blocks(&self, buffer: &[u8], first_block: usize) -> Result<usize, &'static str>;
+}
+
+/// Every storage device is a block device whose blocks are its sectors.
+impl BlockDevice for StorageDeviceRef {
+    fn block_size(&self) -> usize {
+        self.lock().sector_size_in_bytes()
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.lock().size_in_sectors()
+    }
+
+    fn read_blocks(&self, buffer: &mut [u8], first_block: usize) -> Result<usize, &'static str> {
+        self.lock().read_sectors(buffer, first_block)
+    }
+
+    fn write_blocks(&self, buffer: &[u8], first_block: usize) -> Result<usize, &'static str> {
+        self.lock().write_sectors(buffer, first_block)
+    }
+}
@@ -1,36 +1,47 @@
-//! Wrappers for converting block I/O operations from one block size to another.
-//! 
-//! For example, these wrappers can expose a storage device that transfers 512-byte blocks at a time
-//! as a device that can transfer arbitrary bytes at a time (as little as one byte). 
-//! 
+//! The block I/O layer, through which all accesses to block storage devices should flow.
+//!
+//! This crate consists of:
+//! * The [`BlockDevice`](trait.BlockDevice.html) trait, which every storage device implements
+//!   by way of its `StorageDeviceRef`.
+//! * The [`BlockQueue`](struct.BlockQueue.html), an asynchronous request queue for one block device,
+//!   which sorts and merges requests before issuing them to the device
+//!   and keeps per-device statistics about them (see [`BlockDeviceStats`](struct.BlockDeviceStats.html)).
+//! * The [`BlockIo`](struct.BlockIo.html) wrapper, which converts reads and writes of arbitrary byte lengths
+//!   into block requests on a `BlockQueue`, with caching.
+//!   For example, it can expose a storage device that transfers 512-byte blocks at a time
+//!   as a device that can transfer arbitrary bytes at a time (as little as one byte).
+//!
 //! # Limitations
-//! Currently, the `BlockIo` struct is hardcoded to use a `StorageDevice` reference,
-//! when in reality it should just use anything that implements traits like `BlockReader + BlockWriter`. 
-//! 
-//! The read and write functions are implemented such that if the backing storage device
-//! needs to be accessed, it is done so by transferring only one block at a time. 
-//! This is quite inefficient, and we should instead transfer multiple blocks at once. 
-//! 
-//! Cached blocks are stored as vectors of bytes on the heap, 
-//! we should do something else such as separate mapped regions. 
-//! Cached blocks cannot yet be dropped to relieve memory pressure. 
+//! Cached blocks are stored as vectors of bytes on the heap,
+//! we should do something else such as separate mapped regions.
+//! Cached blocks cannot yet be dropped to relieve memory pressure.
 
 #![no_std]
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
 extern crate hashbrown;
+extern crate spin;
 extern crate storage_device;
+extern crate wait_queue;
+extern crate spawn;
+extern crate tsc;
+
+mod device;
+mod queue;
+mod stats;
+
+pub use device::BlockDevice;
+pub use queue::{BlockQueue, BlockRequestHandle, BlockOp, BlockResult, BlockCallback, MAX_MERGED_BLOCKS};
+pub use stats::{BlockDeviceStats, LATENCY_BUCKETS};
 
 use alloc::vec::Vec;
-use hashbrown::{
-    HashMap,
-    hash_map::Entry,
-};
-use storage_device::{StorageDevice, StorageDeviceRef, BlockBounds};
-
-/// A wrapper around a `StorageDevice` that supports reads and writes of arbitrary byte lengths
-/// (down to a single byte) by issuing commands to the underlying storage device.
+use core::ops::Range;
+use hashbrown::HashMap;
+use storage_device::BlockBounds;
+
+/// A wrapper around a `BlockQueue` that supports reads and writes of arbitrary byte lengths
+/// (down to a single byte) by issuing block requests to the queue.
 /// This is needed because most storage devices only allow reads/writes of larger blocks, 
 /// e.g., a 512-byte sector or 4KB cluster.  
 /// 
@@ -40,18 +51,23 @@ pub struct BlockIo {
     /// The cache of blocks (sectors) read from the storage device,
     /// a map from sector number to data byte array.
     cache: BlockCache, 
-    /// The underlying storage device from where the blocks are read/written.
-    device: StorageDeviceRef,
+    /// The request queue of the underlying storage device, through which the blocks are read/written.
+    queue: BlockQueue,
 }
 impl BlockIo {
-    /// Creates a new `BlockIo` device 
-    pub fn new(storage_device: StorageDeviceRef) -> BlockIo {
+    /// Creates a new `BlockIo` device on top of the given request queue.
+    pub fn new(queue: BlockQueue) -> BlockIo {
         BlockIo {
             cache: HashMap::new(),
-            device: storage_device, 
+            queue,
         }
     }
 
+    /// Returns the request queue that this `BlockIo` issues its requests to.
+    pub fn queue(&self) -> &BlockQueue {
+        &self.queue
+    }
+
     /// Reads data from this block storage device and places it into the provided `buffer`.
     /// The length of the given `buffer` determines the maximum number of bytes to be read.
 	/// 
@@ -60,17 +76,19 @@ impl BlockIo {
     /// 
     /// The read blocks will be cached in this `BlockIo` struct to accelerate future storage device access.
     pub fn read(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
-        let mut locked_device = self.device.lock();
-        let BlockBounds { range, first_block_offset, .. } = locked_device.block_bounds(buffer.len(), offset)?;
-        let block_size_in_bytes = locked_device.sector_size_in_bytes();
+        let BlockBounds { range, first_block_offset, .. } = self.queue.block_bounds(offset, buffer.len())?;
+        let block_size_in_bytes = self.queue.block_size();
+
+        // Read all of the uncached blocks at once, such that the queue can merge them into a single transfer.
+        self.fill_cache(range.clone())?;
 
-        // Read the actual data, one block at a time.
+        // Copy the actual data, one block at a time.
 		let mut src_offset = first_block_offset; 
 		let mut dest_offset = 0;
 		for block_num in range {
 			// don't copy past the end of `buffer`
 			let num_bytes_to_copy = core::cmp::min(block_size_in_bytes - src_offset, buffer.len() - dest_offset);
-            let block_bytes = Self::read_block(&mut self.cache, &mut *locked_device, block_num)?;
+            let block_bytes = &self.cache.get(&block_num).ok_or("BUG: BlockIo::read(): block was not cached")?.block;
 			buffer[dest_offset .. (dest_offset + num_bytes_to_copy)].copy_from_slice(&block_bytes[src_offset .. (src_offset + num_bytes_to_copy)]);
 			trace!("BlockIo::read(): for block {}, copied bytes into buffer[{}..{}] from block[{}..{}]",
 				block_num, dest_offset, dest_offset + num_bytes_to_copy, src_offset, src_offset + num_bytes_to_copy,
@@ -91,9 +109,8 @@ impl BlockIo {
     /// Currently, we use a *write-through* cache policy,
     /// in which the blocks are written directly to the cache and the backing storage device immediately.
     pub fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
-        let mut locked_device = self.device.lock();
-        let block_bounds = locked_device.block_bounds(buffer.len(), offset)?;
-        let block_size_in_bytes = locked_device.sector_size_in_bytes();
+        let block_bounds = self.queue.block_bounds(offset, buffer.len())?;
+        let block_size_in_bytes = self.queue.block_size();
 
         // // A write transfer (and a read too) can be broken down into three parts: 
         // // (1) Beginning: the first block, which may only be partially included in the transfer.
@@ -101,18 +118,29 @@ impl BlockIo {
         // // (3) End: the last block, which might be partially covered by the byte buffer.
         // // Only the middle blocks can be blindly written to without first reading the existing blocks' contents.
         // // If the first block or last block is aligned to a block boundary, it can be handled as a middle block.
+        let range = block_bounds.range.clone();
+        if !range.is_empty() {
+            if !block_bounds.is_first_block_aligned() {
+                self.fill_cache(range.start .. range.start + 1)?;
+            }
+            if !block_bounds.is_last_block_aligned() {
+                self.fill_cache(range.end - 1 .. range.end)?;
+            }
+        }
+
 		let mut src_offset = 0;
 		let mut dest_offset = block_bounds.first_block_offset;
+        let mut pending_writes = Vec::new();
 
-		for block_num in block_bounds.range {
+		for block_num in range {
 			let num_bytes_to_copy = core::cmp::min(block_size_in_bytes - dest_offset, buffer.len() - src_offset);
 
             let buffer_to_write = if num_bytes_to_copy == block_size_in_bytes {
                 // We're overwriting the entire block, so no need to read it first
                 (&buffer[src_offset .. (src_offset + num_bytes_to_copy)]).to_vec()
             } else {
-                // We're only partially writing to this block, so we need to read the old block first.
-                let old_block = Self::read_block(&mut self.cache, &mut *locked_device, block_num)?;
+                // We're only partially writing to this block, so the old block was read into the cache above.
+                let old_block = &self.cache.get(&block_num).ok_or("BUG: BlockIo::write(): partially-written block was not cached")?.block;
                 let mut new_block_contents = old_block.to_vec();
                 let overwrite_offset = dest_offset % block_size_in_bytes;
                 new_block_contents[overwrite_offset .. (overwrite_offset + num_bytes_to_copy)]
@@ -120,13 +148,8 @@ impl BlockIo {
                 new_block_contents
             };
 
-            let mut new_cached_block = CachedBlock {
-                block: buffer_to_write,
-                state: CacheState::Modified,
-            };
-            // Currently using a write-through policy right now, so flush the block immediately
-            Self::flush_block(&mut *locked_device, block_num, &mut new_cached_block)?;
-            self.cache.insert(block_num, new_cached_block);
+            // Currently using a write-through policy right now, so write the block immediately
+            pending_writes.push((block_num, self.queue.submit_write(block_num, buffer_to_write)?));
 			trace!("BlockIo::write(): for block {}, copied bytes from buffer[{}..{}] to block[{}..{}]",
 				block_num, src_offset, src_offset + num_bytes_to_copy, dest_offset, dest_offset + num_bytes_to_copy,
 			);
@@ -134,6 +157,21 @@ impl BlockIo {
 			dest_offset = 0;
 		}
 
+        for (block_num, handle) in pending_writes {
+            match handle.wait() {
+                Ok(block) => {
+                    self.cache.insert(block_num, CachedBlock { block, state: CacheState::Shared });
+                }
+                Err(e) => {
+                    // The block's contents on the device are now unknown.
+                    if let Some(cached_block) = self.cache.get_mut(&block_num) {
+                        cached_block.state = CacheState::Invalid;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
         Ok(src_offset)
     }
 
@@ -141,68 +179,45 @@ impl BlockIo {
     /// If the `block_to_flush` is None, all blocks in the entire cache
     /// will be written back to the storage device.
     pub fn flush(&mut self, block_num: Option<usize>) -> Result<(), &'static str> {
-        let mut locked_device = self.device.lock();
-        if let Some(bn) = block_num {
-            // Flush just one block
-            if let Some(cached_block) = self.cache.get_mut(&bn) {
-                Self::flush_block(&mut *locked_device, bn, cached_block)?;
+        let mut pending_writes = Vec::new();
+        for (bn, cached_block) in self.cache.iter() {
+            // we only need to actually write blocks in the `Modified` state.
+            if block_num.map_or(true, |b| b == *bn) {
+                if let CacheState::Modified = cached_block.state {
+                    pending_writes.push((*bn, self.queue.submit_write(*bn, cached_block.block.clone())?));
+                }
             }
-            // If the block wasn't in the cache, do nothing.
         }
-        else {
-            // Flush all blocks
-            for (bn, cached_block) in self.cache.iter_mut() {
-                Self::flush_block(&mut *locked_device, *bn, cached_block)?;
+        for (bn, handle) in pending_writes {
+            handle.wait()?;
+            if let Some(cached_block) = self.cache.get_mut(&bn) {
+                cached_block.state = CacheState::Shared;
             }
         }
         Ok(())
     }
 
-    /// An internal function that first checks the cache for a specific block
-    /// in order to avoid reading from the storage device.
-    /// If that block exists in the cache, it is copied into the buffer. 
-    /// If not, it is read from the storage device into the cache, and then copied into the buffer.
-    fn read_block<'c>(cache: &'c mut BlockCache, locked_device: &mut dyn StorageDevice, block: usize) -> Result<&'c [u8], &'static str> {
-        match cache.entry(block) {
-            Entry::Occupied(occ) => {
-                // An existing entry in the cache can be used directly (without going to the backing store)
-                // if it's in the `Modified` or `Shared` state.
-                // But if it's in the `Invalid` state, we have to re-read the block from the storage device.
-                let cached_block = occ.into_mut();
-                match cached_block.state {
-                    CacheState::Modified | CacheState::Shared => Ok(&cached_block.block),
-                    CacheState::Invalid => {
-                        locked_device.read_sectors(&mut cached_block.block, block)?;
-                        cached_block.state = CacheState::Shared;
-                        Ok(&cached_block.block)
-                    }
-                }
-            }
-            Entry::Vacant(vacant) => {
-                // A vacant entry will be read from the backing storage device,
-                // so it will always start out in the `Shared` state.
-                let mut v = vec![0; locked_device.sector_size_in_bytes()];
-                locked_device.read_sectors(&mut v, block)?;
-                let cb = CachedBlock {
-                    block: v,
-                    state: CacheState::Shared,
-                };
-                let cached_block = vacant.insert(cb);
-                Ok(&cached_block.block)
+    /// An internal function that reads the blocks in the given `range` into the cache,
+    /// unless they're already cached in the `Modified` or `Shared` state.
+    /// All of the reads are submitted before waiting for any of them, so the queue can merge them.
+    fn fill_cache(&mut self, range: Range<usize>) -> Result<(), &'static str> {
+        let mut pending_reads = Vec::new();
+        for block_num in range {
+            // An existing entry in the cache can be used directly (without going to the backing store)
+            // if it's in the `Modified` or `Shared` state.
+            // But if it's in the `Invalid` state, we have to re-read the block from the storage device.
+            let needs_read = match self.cache.get(&block_num) {
+                Some(CachedBlock { state: CacheState::Modified, .. }) | Some(CachedBlock { state: CacheState::Shared, .. }) => false,
+                Some(CachedBlock { state: CacheState::Invalid, .. }) | None => true,
+            };
+            if needs_read {
+                pending_reads.push((block_num, self.queue.submit_read(block_num, 1)?));
             }
         }
-    }
-
-    /// An internal function that writes out the given `cached_block`
-    /// to the given locked `StorageDevice` if the cached block is in the `Modified` state.
-    fn flush_block(locked_device: &mut dyn StorageDevice, block_num: usize, cached_block: &mut CachedBlock) -> Result<(), &'static str> {
-        // we only need to actually write blocks in the `Modified` state.
-        match cached_block.state {
-            CacheState::Shared | CacheState::Invalid => { },
-            CacheState::Modified => {
-                locked_device.write_sectors(&cached_block.block, block_num)?;
-                cached_block.state = CacheState::Shared;
-            }
+        for (block_num, handle) in pending_reads {
+            // A block read from the backing storage device always starts out in the `Shared` state.
+            let block = handle.wait()?;
+            self.cache.insert(block_num, CachedBlock { block, state: CacheState::Shared });
         }
         Ok(())
     }
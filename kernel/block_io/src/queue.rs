@@ -0,0 +1,449 @@
+//! An asynchronous request queue in front of a `BlockDevice`.
+//!
+//! Requests are submitted to a [`BlockQueue`](struct.BlockQueue.html) without waiting for them,
+//! and a dedicated kernel task per queue issues them to the device in batches.
+//! Within a batch, requests are sorted by block number and contiguous requests of the same kind
+//! are merged into a single device operation, which lets many small requests be served by one large transfer.
+//! Requests that overlap an earlier request in the same batch (where at least one of them is a write)
+//! are never reordered before it, so reads always observe prior writes.
+//!
+//! The completion of a request can be observed in three ways:
+//! * by blocking on its [`BlockRequestHandle`](struct.BlockRequestHandle.html) with `wait()`,
+//! * by awaiting the handle, which is a future, e.g., on an `async_runtime` executor,
+//! * or by supplying a callback when submitting it, which the queue's task invokes upon completion.
+
+use core::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use alloc::{
+    boxed::Box,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use wait_queue::WaitQueue;
+use storage_device::BlockBounds;
+use tsc::{TscTicks, tsc_ticks};
+use super::BlockDevice;
+use super::stats::{BlockDeviceStats, ticks_to_us};
+
+
+/// The maximum number of blocks that merged requests may transfer in a single device operation.
+pub const MAX_MERGED_BLOCKS: usize = 256;
+
+/// The result of a request: the request's buffer, filled with the read data for reads,
+/// or an error if the device operation failed.
+pub type BlockResult = Result<Vec<u8>, &'static str>;
+
+/// A callback that is invoked with the result of a request once it completes.
+pub type BlockCallback = Box<dyn FnOnce(BlockResult) + Send>;
+
+/// The kinds of block requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOp {
+    Read,
+    Write,
+}
+
+
+/// The completion state of a request, shared between the request and its `BlockRequestHandle`.
+struct RequestState {
+    result: Mutex<Option<BlockResult>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl RequestState {
+    fn take_result(&self) -> Option<BlockResult> {
+        self.result.lock().take()
+    }
+}
+
+/// How a request reports its completion.
+enum Completion {
+    Handle(Arc<RequestState>),
+    Callback(BlockCallback),
+}
+
+/// A request that has been submitted to a queue but has not yet completed.
+struct BlockRequest {
+    op: BlockOp,
+    first_block: usize,
+    num_blocks: usize,
+    buffer: Vec<u8>,
+    completion: Completion,
+    submitted: TscTicks,
+}
+
+impl BlockRequest {
+    fn end_block(&self) -> usize {
+        self.first_block + self.num_blocks
+    }
+
+    /// Returns true if this request must not be reordered with respect to the `other` request,
+    /// i.e., they access some of the same blocks and at least one of them is a write.
+    fn conflicts_with(&self, other: &BlockRequest) -> bool {
+        (self.op == BlockOp::Write || other.op == BlockOp::Write)
+            && self.first_block < other.end_block()
+            && other.first_block < self.end_block()
+    }
+}
+
+
+/// The state of a queue, shared between all `BlockQueue` instances for the same device and its task.
+struct QueueInner {
+    device: Arc<dyn BlockDevice>,
+    name: String,
+    block_size: usize,
+    num_blocks: usize,
+    /// The requests that have been submitted but not yet issued to the device, in submission order.
+    pending: Mutex<Vec<BlockRequest>>,
+    /// Held while issuing a batch of requests, which ensures that batches are issued in submission order.
+    dispatch_lock: Mutex<()>,
+    /// The queue's task waits here for new requests.
+    request_wait_queue: WaitQueue,
+    /// Tasks blocked on a `BlockRequestHandle` wait here for their request to complete.
+    completion_wait_queue: WaitQueue,
+    stats: Mutex<BlockDeviceStats>,
+    created: TscTicks,
+}
+
+/// A request queue for a block device, through which all I/O to that device should be issued.
+///
+/// `BlockQueue` is a cheap reference to the queue, and can be cloned and shared freely.
+#[derive(Clone)]
+pub struct BlockQueue(Arc<QueueInner>);
+
+impl BlockQueue {
+    /// Creates a new request queue for the given `device`,
+    /// and spawns a task that issues the queue's requests to the device.
+    ///
+    /// The `name` identifies this queue in log messages and statistics, e.g., `"disk0"`.
+    pub fn new<D: BlockDevice + 'static>(device: D, name: String) -> Result<BlockQueue, &'static str> {
+        let block_size = device.block_size();
+        if block_size == 0 {
+            return Err("BlockQueue::new(): device has a block size of 0");
+        }
+        let queue = BlockQueue(Arc::new(QueueInner {
+            num_blocks: device.num_blocks(),
+            device: Arc::new(device),
+            name,
+            block_size,
+            pending: Mutex::new(Vec::new()),
+            dispatch_lock: Mutex::new(()),
+            request_wait_queue: WaitQueue::new(),
+            completion_wait_queue: WaitQueue::new(),
+            stats: Mutex::new(BlockDeviceStats::default()),
+            created: tsc_ticks(),
+        }));
+        let task_name = format!("block_io_{}", queue.0.name);
+        spawn::new_task_builder(block_queue_task, queue.clone())
+            .name(task_name)
+            .spawn()?;
+        Ok(queue)
+    }
+
+    /// Returns a number that uniquely identifies this queue among all existing queues.
+    pub fn id(&self) -> usize {
+        &*self.0 as *const QueueInner as usize
+    }
+
+    /// Returns the name of this queue.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Returns the size of a block of the underlying device in bytes.
+    pub fn block_size(&self) -> usize {
+        self.0.block_size
+    }
+
+    /// Returns the number of blocks in the underlying device.
+    pub fn num_blocks(&self) -> usize {
+        self.0.num_blocks
+    }
+
+    /// Returns the size of the underlying device in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        self.0.block_size * self.0.num_blocks
+    }
+
+    /// Returns a snapshot of the statistics of the requests that have completed so far.
+    pub fn stats(&self) -> BlockDeviceStats {
+        let mut stats = self.0.stats.lock().clone();
+        stats.elapsed_us = tsc_ticks().sub(&self.0.created).map(ticks_to_us).unwrap_or(0);
+        stats
+    }
+
+    /// Calculates the blocks that a transfer of `length` bytes at the byte `offset` covers.
+    ///
+    /// This behaves like [`StorageDevice::block_bounds()`](../storage_device/trait.StorageDevice.html#method.block_bounds).
+    pub fn block_bounds(&self, offset: usize, length: usize) -> Result<BlockBounds, &'static str> {
+        let block_size = self.0.block_size;
+        if offset > self.size_in_bytes() {
+            return Err("offset was out of bounds");
+        }
+        let first_block = offset / block_size;
+        let last_block = core::cmp::min(self.0.num_blocks, (offset + length + block_size - 1) / block_size);
+        Ok(BlockBounds {
+            range: first_block..last_block,
+            first_block_offset: offset % block_size,
+            last_block_offset: (offset % block_size + length) % block_size,
+        })
+    }
+
+    /// Submits a request to read `num_blocks` blocks starting at block number `first_block`.
+    ///
+    /// The returned handle yields a buffer containing the read blocks.
+    pub fn submit_read(&self, first_block: usize, num_blocks: usize) -> Result<BlockRequestHandle, &'static str> {
+        let buffer = vec![0; num_blocks * self.0.block_size];
+        self.submit_with_handle(BlockOp::Read, first_block, buffer)
+    }
+
+    /// Submits a request to write the given `buffer` starting at block number `first_block`.
+    /// The length of the `buffer` must be a multiple of the block size.
+    ///
+    /// The returned handle yields the `buffer` back once it has been written.
+    pub fn submit_write(&self, first_block: usize, buffer: Vec<u8>) -> Result<BlockRequestHandle, &'static str> {
+        self.submit_with_handle(BlockOp::Write, first_block, buffer)
+    }
+
+    /// Submits a request of the given kind, and invokes the given `callback` with its result once it completes.
+    ///
+    /// For reads, the `buffer` determines how many blocks are read, and is passed to the `callback` filled with the read data.
+    /// The `callback` runs in the context of the queue's task, so it should not block for long.
+    pub fn submit_with_callback(&self, op: BlockOp, first_block: usize, buffer: Vec<u8>, callback: BlockCallback) -> Result<(), &'static str> {
+        self.submit(op, first_block, buffer, Completion::Callback(callback))
+    }
+
+    fn submit_with_handle(&self, op: BlockOp, first_block: usize, buffer: Vec<u8>) -> Result<BlockRequestHandle, &'static str> {
+        let state = Arc::new(RequestState {
+            result: Mutex::new(None),
+            waker: Mutex::new(None),
+        });
+        self.submit(op, first_block, buffer, Completion::Handle(state.clone()))?;
+        Ok(BlockRequestHandle { queue: self.clone(), state })
+    }
+
+    fn submit(&self, op: BlockOp, first_block: usize, buffer: Vec<u8>, completion: Completion) -> Result<(), &'static str> {
+        if buffer.is_empty() || buffer.len() % self.0.block_size != 0 {
+            return Err("block request buffer length must be a nonzero multiple of the block size");
+        }
+        let num_blocks = buffer.len() / self.0.block_size;
+        if first_block.checked_add(num_blocks).map_or(true, |end| end > self.0.num_blocks) {
+            return Err("block request extends past the end of the device");
+        }
+        self.0.pending.lock().push(BlockRequest {
+            op,
+            first_block,
+            num_blocks,
+            buffer,
+            completion,
+            submitted: tsc_ticks(),
+        });
+        self.0.request_wait_queue.notify_one();
+        Ok(())
+    }
+
+    /// Reads blocks into the given `buffer` starting at block number `first_block`,
+    /// blocking until the read completes.
+    ///
+    /// Returns the number of blocks read.
+    pub fn read_blocks(&self, buffer: &mut [u8], first_block: usize) -> Result<usize, &'static str> {
+        let num_blocks = buffer.len() / self.0.block_size;
+        let data = self.submit_read(first_block, num_blocks)?.wait()?;
+        buffer[..data.len()].copy_from_slice(&data);
+        Ok(num_blocks)
+    }
+
+    /// Writes the given `buffer` starting at block number `first_block`,
+    /// blocking until the write completes.
+    ///
+    /// Returns the number of blocks written.
+    pub fn write_blocks(&self, buffer: &[u8], first_block: usize) -> Result<usize, &'static str> {
+        self.submit_write(first_block, buffer.to_vec())?.wait()?;
+        Ok(buffer.len() / self.0.block_size)
+    }
+
+    /// Issues all pending requests to the device, returning once they have all completed.
+    ///
+    /// This is normally done by the queue's task, but can be invoked directly
+    /// by a task that cannot wait for the queue's task to run.
+    pub fn dispatch(&self) {
+        let _dispatch_guard = self.0.dispatch_lock.lock();
+        loop {
+            let batch = mem::replace(&mut *self.0.pending.lock(), Vec::new());
+            if batch.is_empty() {
+                break;
+            }
+            // Requests can only be reordered within a run of mutually non-conflicting requests.
+            let mut run: Vec<BlockRequest> = Vec::new();
+            for request in batch {
+                if run.iter().any(|r| r.conflicts_with(&request)) {
+                    self.issue_run(mem::replace(&mut run, Vec::new()));
+                }
+                run.push(request);
+            }
+            self.issue_run(run);
+        }
+    }
+
+    /// Sorts the given requests by block number, merges contiguous requests of the same kind,
+    /// and issues them to the device.
+    fn issue_run(&self, mut run: Vec<BlockRequest>) {
+        // A stable sort keeps requests for the same block in submission order.
+        run.sort_by_key(|r| r.first_block);
+        let mut merged: Vec<BlockRequest> = Vec::new();
+        let mut merged_blocks = 0;
+        for request in run {
+            let can_merge = merged.last().map_or(false, |last|
+                last.op == request.op
+                    && last.end_block() == request.first_block
+                    && merged_blocks + request.num_blocks <= MAX_MERGED_BLOCKS
+            );
+            if !can_merge && !merged.is_empty() {
+                self.issue_merged(mem::replace(&mut merged, Vec::new()));
+                merged_blocks = 0;
+            }
+            merged_blocks += request.num_blocks;
+            merged.push(request);
+        }
+        if !merged.is_empty() {
+            self.issue_merged(merged);
+        }
+    }
+
+    /// Issues the given contiguous requests of the same kind as a single device operation, and completes them.
+    fn issue_merged(&self, mut requests: Vec<BlockRequest>) {
+        let op = requests[0].op;
+        let first_block = requests[0].first_block;
+        let device = &self.0.device;
+
+        let result = if requests.len() == 1 {
+            let request = &mut requests[0];
+            match op {
+                BlockOp::Read => device.read_blocks(&mut request.buffer, first_block),
+                BlockOp::Write => device.write_blocks(&request.buffer, first_block),
+            }
+        } else {
+            let total_len = requests.iter().map(|r| r.buffer.len()).sum();
+            match op {
+                BlockOp::Read => {
+                    let mut combined = vec![0u8; total_len];
+                    let res = device.read_blocks(&mut combined, first_block);
+                    if res.is_ok() {
+                        let mut offset = 0;
+                        for request in requests.iter_mut() {
+                            let len = request.buffer.len();
+                            request.buffer.copy_from_slice(&combined[offset .. offset + len]);
+                            offset += len;
+                        }
+                    }
+                    res
+                }
+                BlockOp::Write => {
+                    let mut combined = Vec::with_capacity(total_len);
+                    for request in requests.iter() {
+                        combined.extend_from_slice(&request.buffer);
+                    }
+                    device.write_blocks(&combined, first_block)
+                }
+            }
+        };
+
+        if let Err(_e) = result {
+            error!("block_io: {:?} of {} blocks at block {} on {} failed: {}",
+                op, requests.iter().map(|r| r.num_blocks).sum::<usize>(), first_block, self.0.name, _e
+            );
+        }
+        {
+            let mut stats = self.0.stats.lock();
+            stats.device_operations += 1;
+            stats.merged_requests += requests.len() as u64 - 1;
+        }
+        for request in requests {
+            self.complete(request, result.map(|_| ()));
+        }
+        self.0.completion_wait_queue.notify_all();
+    }
+
+    /// Records the completion of the given request in the statistics and notifies whoever is waiting for it.
+    fn complete(&self, request: BlockRequest, result: Result<(), &'static str>) {
+        let latency_us = tsc_ticks().sub(&request.submitted).map(ticks_to_us).unwrap_or(0);
+        self.0.stats.lock().record_request(request.op == BlockOp::Write, request.num_blocks, result.is_ok(), latency_us);
+
+        let BlockRequest { buffer, completion, .. } = request;
+        let result = result.map(|_| buffer);
+        match completion {
+            Completion::Handle(state) => {
+                *state.result.lock() = Some(result);
+                let waker = state.waker.lock().take();
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            Completion::Callback(callback) => callback(result),
+        }
+    }
+}
+
+/// The entry point of a queue's task, which issues requests to the device as they are submitted.
+fn block_queue_task(queue: BlockQueue) -> Result<(), &'static str> {
+    loop {
+        let inner = &queue.0;
+        inner.request_wait_queue
+            .wait_until(&|| if inner.pending.lock().is_empty() { None } else { Some(()) })
+            .map_err(|_| "block_io: queue task failed to wait for requests")?;
+        queue.dispatch();
+    }
+}
+
+
+/// A handle to a submitted request, which can be used to wait for its result.
+///
+/// A `BlockRequestHandle` is also a future that completes with the request's result.
+pub struct BlockRequestHandle {
+    queue: BlockQueue,
+    state: Arc<RequestState>,
+}
+
+impl BlockRequestHandle {
+    /// Returns the request's result if it has completed, without waiting.
+    ///
+    /// The result can only be taken once; afterwards, this returns `None`.
+    pub fn try_take(&self) -> Option<BlockResult> {
+        self.state.take_result()
+    }
+
+    /// Blocks the current task until the request completes, and returns its result.
+    ///
+    /// If the current task cannot be blocked, e.g., early in the boot process,
+    /// the queue's pending requests are issued directly by the current task instead.
+    pub fn wait(self) -> BlockResult {
+        let state = &self.state;
+        match self.queue.0.completion_wait_queue.wait_until(&|| state.take_result()) {
+            Ok(result) => result,
+            Err(_) => loop {
+                if let Some(result) = state.take_result() {
+                    return result;
+                }
+                self.queue.dispatch();
+            },
+        }
+    }
+}
+
+impl Future for BlockRequestHandle {
+    type Output = BlockResult;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<BlockResult> {
+        // Register the waker before checking the result, so a completion in between isn't missed.
+        *self.state.waker.lock() = Some(cx.waker().clone());
+        match self.try_take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
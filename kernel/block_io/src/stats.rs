@@ -0,0 +1,107 @@
+//! Per-device statistics about the requests that pass through a request queue.
+
+use core::fmt;
+use tsc::{TscTicks, get_tsc_frequency};
+
+
+/// The number of buckets in a latency histogram.
+///
+/// Bucket `0` counts latencies under 2 microseconds, bucket `i` counts latencies
+/// from `2^i` up to `2^(i+1)` microseconds, and the last bucket counts everything longer.
+pub const LATENCY_BUCKETS: usize = 20;
+
+
+/// Statistics about the requests completed by one request queue.
+#[derive(Clone, Debug, Default)]
+pub struct BlockDeviceStats {
+    /// The number of read requests that have completed successfully.
+    pub reads: u64,
+    /// The number of write requests that have completed successfully.
+    pub writes: u64,
+    /// The number of blocks read by successful read requests.
+    pub blocks_read: u64,
+    /// The number of blocks written by successful write requests.
+    pub blocks_written: u64,
+    /// The number of requests that failed.
+    pub errors: u64,
+    /// The number of operations issued to the device,
+    /// which is lower than the number of requests when requests are merged.
+    pub device_operations: u64,
+    /// The number of requests that were merged into another request's device operation.
+    pub merged_requests: u64,
+    /// The latency of every completed request, from submission to completion, in microseconds.
+    pub latency_histogram: [u64; LATENCY_BUCKETS],
+    /// The sum of the latencies of all completed requests, in microseconds.
+    pub total_latency_us: u64,
+    /// The time in microseconds since the request queue was created,
+    /// as of when these statistics were obtained.
+    pub elapsed_us: u64,
+}
+
+impl BlockDeviceStats {
+    /// Records the completion of a request that transferred `num_blocks` blocks.
+    pub(crate) fn record_request(&mut self, is_write: bool, num_blocks: usize, succeeded: bool, latency_us: u64) {
+        if !succeeded {
+            self.errors += 1;
+        } else if is_write {
+            self.writes += 1;
+            self.blocks_written += num_blocks as u64;
+        } else {
+            self.reads += 1;
+            self.blocks_read += num_blocks as u64;
+        }
+        let bucket = (64 - latency_us.leading_zeros() as usize).saturating_sub(1);
+        self.latency_histogram[core::cmp::min(bucket, LATENCY_BUCKETS - 1)] += 1;
+        self.total_latency_us += latency_us;
+    }
+
+    /// Returns the total number of completed requests, including failed ones.
+    pub fn completed_requests(&self) -> u64 {
+        self.reads + self.writes + self.errors
+    }
+
+    /// Returns the average number of completed requests per second since the request queue was created.
+    pub fn iops(&self) -> u64 {
+        if self.elapsed_us == 0 {
+            return 0;
+        }
+        (self.completed_requests() as u128 * 1_000_000 / self.elapsed_us as u128) as u64
+    }
+
+    /// Returns the average latency of a request in microseconds.
+    pub fn average_latency_us(&self) -> u64 {
+        self.total_latency_us.checked_div(self.completed_requests()).unwrap_or(0)
+    }
+}
+
+impl fmt::Display for BlockDeviceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "reads: {} ({} blocks), writes: {} ({} blocks), errors: {}",
+            self.reads, self.blocks_read, self.writes, self.blocks_written, self.errors
+        )?;
+        writeln!(f, "device operations: {}, merged requests: {}, IOPS: {}, average latency: {} us",
+            self.device_operations, self.merged_requests, self.iops(), self.average_latency_us()
+        )?;
+        writeln!(f, "latency histogram:")?;
+        for (i, count) in self.latency_histogram.iter().enumerate().filter(|(_, &c)| c != 0) {
+            if i == 0 {
+                writeln!(f, "{:>10} us: {}", "< 2", count)?;
+            } else if i == LATENCY_BUCKETS - 1 {
+                writeln!(f, "{:>10} us: {}", format!(">= {}", 1u64 << i), count)?;
+            } else {
+                writeln!(f, "{:>10} us: {}", format!("{}-{}", 1u64 << i, (1u64 << (i + 1)) - 1), count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Converts the given number of TSC ticks into microseconds,
+/// or returns `0` if the TSC frequency is unknown.
+pub(crate) fn ticks_to_us(ticks: TscTicks) -> u64 {
+    match get_tsc_frequency() {
+        Ok(freq) if freq != 0 => (ticks.into() as u128 * 1_000_000 / freq as u128) as u64,
+        _ => 0,
+    }
+}
@@ -0,0 +1,45 @@
+//! Stores and parses the kernel command line passed in by the bootloader,
+//! e.g., the arguments after `multiboot2 /boot/kernel.bin` in grub.cfg.
+//! 
+//! The command line is a whitespace-separated list of parameters,
+//! each of which is either a flag like `gdb` or a key-value pair like `gdb=wait`.
+
+#![no_std]
+
+extern crate alloc;
+extern crate spin;
+
+use alloc::string::String;
+use spin::Once;
+
+
+static KERNEL_COMMAND_LINE: Once<String> = Once::new();
+
+/// Stores the kernel command line. Only the first invocation has any effect.
+pub fn init(command_line: &str) {
+    KERNEL_COMMAND_LINE.call_once(|| String::from(command_line));
+}
+
+/// Returns the full kernel command line, which is empty if none was given or it hasn't yet been initialized.
+pub fn command_line() -> &'static str {
+    KERNEL_COMMAND_LINE.try().map(|s| s.as_str()).unwrap_or("")
+}
+
+/// Returns true if the given parameter was present on the kernel command line,
+/// either as a flag (`name`) or as a key-value pair (`name=value`).
+pub fn is_present(name: &str) -> bool {
+    command_line().split_whitespace().any(|param| 
+        param == name || param.split('=').next() == Some(name)
+    )
+}
+
+/// Returns the value of the given key-value parameter (`name=value`) from the kernel command line.
+pub fn get_value(name: &str) -> Option<&'static str> {
+    command_line().split_whitespace().find_map(|param| {
+        let mut iter = param.splitn(2, '=');
+        match (iter.next(), iter.next()) {
+            (Some(key), Some(value)) if key == name => Some(value),
+            _ => None,
+        }
+    })
+}
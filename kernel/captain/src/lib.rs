@@ -26,25 +26,38 @@ extern crate irq_safety; // for irq-safe locking and interrupt utilities
 extern crate dfqueue; // decoupled, fault-tolerant queue
 
 extern crate logger;
+extern crate crash_dump;
 extern crate memory; // the virtual memory subsystem 
 extern crate stack;
 extern crate apic; 
 extern crate mod_mgmt;
 extern crate spawn;
 extern crate tsc;
+extern crate random;
+extern crate timer;
+extern crate wall_clock;
 extern crate task; 
 extern crate interrupts;
 extern crate acpi;
 extern crate device_manager;
+extern crate page_cache;
+extern crate tmpfs;
+extern crate fat32;
+extern crate ext2;
 extern crate e1000;
 extern crate scheduler;
 #[cfg(mirror_log_to_vga)] #[macro_use] extern crate print;
 extern crate first_application;
 extern crate exceptions_full;
+extern crate gdb_stub;
 extern crate network_manager;
+extern crate loopback;
+extern crate net_stack;
+#[cfg(use_dhcp)] extern crate dhcp_client;
 extern crate window_manager;
 extern crate multiple_heaps;
 #[cfg(simd_personality)] extern crate simd_personality;
+#[cfg(theseus_test)] extern crate test_runner;
 
 
 
@@ -84,8 +97,15 @@ pub fn init(
 
     // calculate TSC period and initialize it
     // not strictly necessary, but more accurate if we do it early on before interrupts, multicore, and multitasking
-    let _tsc_freq = tsc::get_tsc_frequency()?;
-    // info!("TSC frequency calculated: {}", _tsc_freq);
+    let tsc_freq = tsc::get_tsc_frequency()?;
+    // info!("TSC frequency calculated: {}", tsc_freq);
+    // now that the TSC frequency is known, log messages can be timestamped
+    logger::set_timestamp_frequency(tsc_freq);
+
+    // retrieve the previous boot's crash dump, if any, and prepare to record a crash during this boot
+    if let Err(e) = crash_dump::init() {
+        warn!("captain::init(): crash dumps are unavailable: {}", e);
+    }
 
     // now we initialize early driver stuff, like APIC/ACPI
     device_manager::early_init(kernel_mmi_ref.lock().deref_mut())?;
@@ -115,6 +135,9 @@ pub fn init(
 
     // after we've initialized the task subsystem, we can use better exception handlers
     exceptions_full::init(idt);
+
+    // the GDB stub replaces the breakpoint and debug exception handlers, if enabled by the `gdb` boot parameter
+    gdb_stub::init(idt)?;
     
     // boot up the other cores (APs)
     let ap_count = multicore_bringup::handle_ap_cores(kernel_mmi_ref.clone(), ap_start_realmode_begin, ap_start_realmode_end)?;
@@ -124,12 +147,33 @@ pub fn init(
     multiple_heaps::switch_to_multiple_heaps()?;
     info!("Initialized per-core heaps");
 
+    // the clock is calibrated against the HPET, and then every core's APIC timer can switch to TSC-deadline mode
+    timer::init()?;
+    // the wall clock starts from the RTC's time and then follows the monotonic clock
+    wall_clock::init()?;
+
+    // the random number generator is seeded by the CPU, and later reseeded with the timing of interrupts
+    random::init()?;
+
     // initialize window manager.
     let (key_producer, mouse_producer) = window_manager::init()?;
 
     // initialize the rest of our drivers
     device_manager::init(key_producer, mouse_producer)?;
+    // the page cache writes back to storage devices, so it must be initialized after them
+    page_cache::init()?;
+    // filesystem drivers register with the VFS and mount the volumes they find
+    tmpfs::init()?;
+    fat32::init()?;
+    ext2::init()?;
     task_fs::init()?;
+    // the loopback interface comes after those of the network cards that the device manager found
+    loopback::init()?;
+    // the network stack polls the network interfaces
+    net_stack::init()?;
+    // interfaces without a static IP address are configured in the background
+    #[cfg(use_dhcp)]
+    dhcp_client::init()?;
 
 
     // We can drop and unmap the identity mappings (e.g., for the multiboot2 boot_info) 
@@ -150,6 +194,10 @@ pub fn init(
     // Now that initialization is complete, we can spawn the first application(s)
     first_application::start()?;
 
+    // run all in-kernel tests, which exits QEMU once they complete
+    #[cfg(theseus_test)]
+    test_runner::start()?;
+
     info!("captain::init(): initialization done! Spawning an idle task on BSP core {} and enabling interrupts...", bsp_apic_id);
     spawn::create_idle_task(Some(bsp_apic_id))?;
     
@@ -0,0 +1,427 @@
+//! Bounded and unbounded multi-producer, multi-consumer (MPMC) channels for passing messages between tasks.
+//!
+//! * A [`bounded()`](fn.bounded.html) channel holds up to a fixed number of messages;
+//!   sending to a full bounded channel blocks until a receiver makes space.
+//!   Its buffer is allocated up front, so [`Sender::try_send()`](struct.Sender.html#method.try_send)
+//!   never allocates and can be used from an interrupt handler.
+//! * An [`unbounded()`](fn.unbounded.html) channel holds any number of messages, so sending never blocks.
+//!
+//! Receiving from an empty channel blocks the receiving task on a `WaitQueue` until a message is sent.
+//! Both `Sender`s and `Receiver`s can be cloned. Once all `Sender`s have been dropped,
+//! receivers can drain the remaining messages and then receive a `Disconnected` error;
+//! once all `Receiver`s have been dropped, sending fails with a `Disconnected` error.
+//!
+//! The API and error types mirror those of `std::sync::mpsc` such that this crate can back it.
+
+#![no_std]
+
+extern crate alloc;
+extern crate irq_safety;
+extern crate wait_queue;
+extern crate timer;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use irq_safety::MutexIrqSafe;
+use wait_queue::WaitQueue;
+use timer::Instant;
+
+
+/// Creates a new channel that can hold up to `capacity` messages at once,
+/// and returns its `(Sender, Receiver)` pair.
+///
+/// A `capacity` of `0` is treated as `1`.
+pub fn bounded<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let capacity = core::cmp::max(capacity, 1);
+    new_channel(VecDeque::with_capacity(capacity), Some(capacity))
+}
+
+/// Creates a new channel that can hold any number of messages,
+/// and returns its `(Sender, Receiver)` pair.
+pub fn unbounded<T: Send>() -> (Sender<T>, Receiver<T>) {
+    new_channel(VecDeque::new(), None)
+}
+
+fn new_channel<T: Send>(queue: VecDeque<T>, capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel {
+        queue: MutexIrqSafe::new(queue),
+        capacity,
+        waiting_senders: WaitQueue::new(),
+        waiting_receivers: Arc::new(WaitQueue::new()),
+        num_senders: AtomicUsize::new(1),
+        num_receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender   { channel: channel.clone() },
+        Receiver { channel },
+    )
+}
+
+
+/// An error returned from [`Sender::send()`](struct.Sender.html#method.send)
+/// when all receivers have been dropped. The unsent message is returned.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// An error returned from [`Sender::try_send()`](struct.Sender.html#method.try_send).
+/// The unsent message is returned.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full.
+    Full(T),
+    /// All receivers have been dropped.
+    Disconnected(T),
+}
+
+/// An error returned from [`Receiver::recv()`](struct.Receiver.html#method.recv)
+/// when the channel is empty and all senders have been dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecvError;
+
+/// An error returned from [`Receiver::try_recv()`](struct.Receiver.html#method.try_recv).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The channel is currently empty.
+    Empty,
+    /// The channel is empty and all senders have been dropped.
+    Disconnected,
+}
+
+/// An error returned from [`Receiver::recv_timeout()`](struct.Receiver.html#method.recv_timeout).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// No message was received before the timeout elapsed.
+    Timeout,
+    /// The channel is empty and all senders have been dropped.
+    Disconnected,
+}
+
+// The messages inside the errors may not be `Debug`, so we don't print them, just like `std`.
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SendError(..)")
+    }
+}
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Disconnected(_) => write!(f, "Disconnected(..)"),
+        }
+    }
+}
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sending on a disconnected channel")
+    }
+}
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "sending on a full channel"),
+            TrySendError::Disconnected(_) => write!(f, "sending on a disconnected channel"),
+        }
+    }
+}
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "receiving on a disconnected channel")
+    }
+}
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "receiving on an empty channel"),
+            TryRecvError::Disconnected => write!(f, "receiving on a disconnected channel"),
+        }
+    }
+}
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => write!(f, "timed out waiting on a channel"),
+            RecvTimeoutError::Disconnected => write!(f, "receiving on a disconnected channel"),
+        }
+    }
+}
+
+
+/// The state shared by all `Sender`s and `Receiver`s of a channel.
+struct Channel<T> {
+    /// The buffered messages. This is an IRQ-safe lock so that messages can be sent from interrupt handlers.
+    queue: MutexIrqSafe<VecDeque<T>>,
+    /// The maximum number of buffered messages, or `None` if unbounded.
+    capacity: Option<usize>,
+    waiting_senders: WaitQueue,
+    /// This is shared with the timers of receivers that are waiting with a timeout, which notify it once the timeout elapses.
+    waiting_receivers: Arc<WaitQueue>,
+    num_senders: AtomicUsize,
+    num_receivers: AtomicUsize,
+}
+
+impl<T> Channel<T> {
+    fn senders_disconnected(&self) -> bool {
+        self.num_senders.load(Ordering::Acquire) == 0
+    }
+
+    fn receivers_disconnected(&self) -> bool {
+        self.num_receivers.load(Ordering::Acquire) == 0
+    }
+
+    /// Tries to push the given message onto the queue, returning it if the queue is full.
+    fn push(&self, msg: T) -> Result<(), T> {
+        let mut queue = self.queue.lock();
+        if self.capacity.map_or(false, |cap| queue.len() >= cap) {
+            return Err(msg);
+        }
+        queue.push_back(msg);
+        Ok(())
+    }
+
+    /// Tries to pop a message off of the queue.
+    fn pop(&self) -> Option<T> {
+        self.queue.lock().pop_front()
+    }
+}
+
+
+/// The sending side of a channel, which can be cloned to obtain multiple senders.
+pub struct Sender<T: Send> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Sends a message, blocking until space in the channel's buffer is available.
+    ///
+    /// Sending to an unbounded channel never blocks.
+    /// Returns an error containing the unsent message if all receivers have been dropped.
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        // Fast path: the buffer isn't full.
+        let msg = match self.try_send(msg) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(msg)) => return Err(SendError(msg)),
+            Err(TrySendError::Full(msg)) => msg,
+        };
+
+        // Slow path: wait until space becomes available.
+        // The condition closure is invoked with the waitqueue locked, so it must not notify the receivers itself.
+        let mut msg = Some(msg);
+        let mut condition = || {
+            if self.channel.receivers_disconnected() {
+                return Some(false);
+            }
+            match self.channel.push(msg.take()?) {
+                Ok(()) => Some(true),
+                Err(returned_msg) => {
+                    msg = Some(returned_msg);
+                    None
+                }
+            }
+        };
+        match self.channel.waiting_senders.wait_until_mut(&mut condition) {
+            Ok(true) => {
+                self.channel.waiting_receivers.notify_one();
+                Ok(())
+            }
+            // The closure has been dropped, so the unsent message can be taken back.
+            _ => Err(SendError(msg.take().expect("BUG: channel::Sender::send(): lost the unsent message"))),
+        }
+    }
+
+    /// Tries to send a message without blocking.
+    ///
+    /// For a bounded channel, this never allocates, so it can be invoked from an interrupt handler.
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        if self.channel.receivers_disconnected() {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        self.channel.push(msg).map_err(TrySendError::Full)?;
+        self.channel.waiting_receivers.notify_one();
+        Ok(())
+    }
+
+    /// Returns the number of messages currently buffered in the channel.
+    pub fn len(&self) -> usize {
+        self.channel.queue.lock().len()
+    }
+
+    /// Returns `true` if no messages are currently buffered in the channel.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of messages the channel can hold, or `None` if it's unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.channel.capacity
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.channel.num_senders.fetch_add(1, Ordering::AcqRel);
+        Sender { channel: self.channel.clone() }
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // If this was the last sender, wake up all receivers so they can observe the disconnection.
+        if self.channel.num_senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.channel.waiting_receivers.notify_all();
+        }
+    }
+}
+
+
+/// The receiving side of a channel, which can be cloned to obtain multiple receivers.
+///
+/// Each message is received by exactly one receiver.
+pub struct Receiver<T: Send> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// Receives a message, blocking until one is available.
+    ///
+    /// Returns an error if the channel is empty and all senders have been dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        // Fast path: a message is already available.
+        match self.try_recv() {
+            Ok(msg) => return Ok(msg),
+            Err(TryRecvError::Disconnected) => return Err(RecvError),
+            Err(TryRecvError::Empty) => { }
+        }
+
+        // Slow path: wait until a message is sent.
+        // The condition closure is invoked with the waitqueue locked, so it must not notify the senders itself.
+        let res = self.channel.waiting_receivers.wait_until(&|| {
+            match self.channel.pop() {
+                Some(msg) => Some(Ok(msg)),
+                None if self.channel.senders_disconnected() => Some(Err(RecvError)),
+                None => None,
+            }
+        });
+        match res {
+            Ok(Ok(msg)) => {
+                self.channel.waiting_senders.notify_one();
+                Ok(msg)
+            }
+            _ => Err(RecvError),
+        }
+    }
+
+    /// Tries to receive a message without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.channel.pop() {
+            Some(msg) => {
+                self.channel.waiting_senders.notify_one();
+                Ok(msg)
+            }
+            // Check the queue again after observing the disconnection,
+            // in case the last sender sent a message right before it was dropped.
+            None if self.channel.senders_disconnected() => self.channel.pop().ok_or(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Receives a message, blocking until one is available or the given `timeout` elapses.
+    ///
+    /// While waiting, the current task sleeps until a message is sent or a timer wakes it up once the timeout elapses.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // Fast path: a message is already available.
+        match self.try_recv() {
+            Ok(msg) => return Ok(msg),
+            Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            Err(TryRecvError::Empty) => { }
+        }
+
+        // Slow path: wait until a message is sent or the timer expires.
+        let deadline = Instant::now() + timeout;
+        let timer = {
+            let waiting_receivers = self.channel.waiting_receivers.clone();
+            timer::call_at(deadline, move || {
+                waiting_receivers.notify_all();
+            })
+        };
+        let res = self.channel.waiting_receivers.wait_until(&|| {
+            match self.channel.pop() {
+                Some(msg) => Some(Ok(msg)),
+                None if self.channel.senders_disconnected() => Some(Err(RecvTimeoutError::Disconnected)),
+                None if Instant::now() >= deadline => Some(Err(RecvTimeoutError::Timeout)),
+                None => None,
+            }
+        });
+        timer.cancel();
+        match res {
+            Ok(Ok(msg)) => {
+                self.channel.waiting_senders.notify_one();
+                Ok(msg)
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Returns an iterator that blocks to receive each message, and ends once all senders have been dropped.
+    pub fn iter(&self) -> Iter<T> {
+        Iter { receiver: self }
+    }
+
+    /// Returns an iterator over the messages that are currently available, which never blocks.
+    pub fn try_iter(&self) -> TryIter<T> {
+        TryIter { receiver: self }
+    }
+
+    /// Returns the number of messages currently buffered in the channel.
+    pub fn len(&self) -> usize {
+        self.channel.queue.lock().len()
+    }
+
+    /// Returns `true` if no messages are currently buffered in the channel.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Send> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.channel.num_receivers.fetch_add(1, Ordering::AcqRel);
+        Receiver { channel: self.channel.clone() }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // If this was the last receiver, wake up all senders so they can observe the disconnection.
+        if self.channel.num_receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.channel.waiting_senders.notify_all();
+        }
+    }
+}
+
+/// A blocking iterator over the messages in a channel, returned by [`Receiver::iter()`](struct.Receiver.html#method.iter).
+pub struct Iter<'r, T: Send + 'r> {
+    receiver: &'r Receiver<T>,
+}
+
+impl<'r, T: Send> Iterator for Iter<'r, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// A non-blocking iterator over the messages in a channel, returned by [`Receiver::try_iter()`](struct.Receiver.html#method.try_iter).
+pub struct TryIter<'r, T: Send + 'r> {
+    receiver: &'r Receiver<T>,
+}
+
+impl<'r, T: Send> Iterator for TryIter<'r, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
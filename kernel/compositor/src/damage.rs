@@ -0,0 +1,118 @@
+//! Damage tracking, i.e., remembering which regions of a framebuffer changed since it was last composited.
+//!
+//! Instead of compositing the whole screen whenever something changes,
+//! every change adds the rectangle it covers to a [`Damage`](struct.Damage.html) set,
+//! and the next composite pass only redraws the rectangles in that set.
+//! Rectangles that overlap or nearly fill their bounding box together are merged,
+//! such that each pixel is usually composited only once per pass.
+
+use alloc::vec::Vec;
+use core::mem;
+use shapes::Rectangle;
+
+/// The maximum number of separate rectangles in a damage set.
+/// Beyond that, the whole set is merged into its bounding box, because compositing many small rectangles
+/// costs more than compositing a few extra pixels.
+pub const MAX_DAMAGE_RECTANGLES: usize = 16;
+
+/// A set of damaged rectangles that need to be composited again.
+#[derive(Clone, Debug, Default)]
+pub struct Damage {
+    rectangles: Vec<Rectangle>,
+    /// The bounds of the framebuffer, which all damaged rectangles are clipped to.
+    bounds: Option<Rectangle>,
+}
+
+impl Damage {
+    /// Creates an empty damage set whose rectangles can lie anywhere.
+    pub fn new() -> Damage {
+        Damage::default()
+    }
+
+    /// Creates an empty damage set whose rectangles are clipped to `bounds`,
+    /// typically the area of the framebuffer being composited.
+    pub fn with_bounds(bounds: Rectangle) -> Damage {
+        Damage {
+            rectangles: Vec::new(),
+            bounds: Some(bounds),
+        }
+    }
+
+    /// Adds the given `area` to this damage set, merging it with the rectangles that it overlaps.
+    pub fn add(&mut self, area: Rectangle) {
+        let mut area = match self.bounds {
+            Some(bounds) => match area.intersection(&bounds) {
+                Some(area) => area,
+                None => return,
+            },
+            None => area,
+        };
+        if area.is_empty() {
+            return;
+        }
+
+        // Merging two rectangles may make their union overlap a third one, so keep merging until nothing changes.
+        let mut i = 0;
+        while i < self.rectangles.len() {
+            let existing = self.rectangles[i];
+            let union = existing.union(&area);
+            if union.area() <= existing.area() + area.area() {
+                self.rectangles.swap_remove(i);
+                area = union;
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        self.rectangles.push(area);
+
+        if self.rectangles.len() > MAX_DAMAGE_RECTANGLES {
+            if let Some(bounding_box) = self.bounding_box() {
+                self.rectangles.clear();
+                self.rectangles.push(bounding_box);
+            }
+        }
+    }
+
+    /// Marks the whole framebuffer as damaged.
+    ///
+    /// This only has an effect if this damage set was created [`with_bounds`](#method.with_bounds).
+    pub fn add_all(&mut self) {
+        if let Some(bounds) = self.bounds {
+            self.rectangles.clear();
+            self.rectangles.push(bounds);
+        }
+    }
+
+    /// Changes the bounds that damaged rectangles are clipped to, e.g., after the framebuffer was resized.
+    pub fn set_bounds(&mut self, bounds: Rectangle) {
+        self.bounds = Some(bounds);
+        let rectangles = mem::take(&mut self.rectangles);
+        for rectangle in rectangles {
+            self.add(rectangle);
+        }
+    }
+
+    /// Returns `true` if nothing is damaged.
+    pub fn is_empty(&self) -> bool {
+        self.rectangles.is_empty()
+    }
+
+    /// Returns the damaged rectangles.
+    /// They rarely overlap, but if they do, the overlapping pixels are simply composited twice.
+    pub fn rectangles(&self) -> &[Rectangle] {
+        &self.rectangles
+    }
+
+    /// Returns the smallest rectangle that contains all damaged rectangles, or `None` if nothing is damaged.
+    pub fn bounding_box(&self) -> Option<Rectangle> {
+        let mut iter = self.rectangles.iter();
+        let first = *iter.next()?;
+        Some(iter.fold(first, |bounding_box, rectangle| bounding_box.union(rectangle)))
+    }
+
+    /// Removes and returns all damaged rectangles, leaving this damage set empty.
+    pub fn take(&mut self) -> Vec<Rectangle> {
+        mem::take(&mut self.rectangles)
+    }
+}
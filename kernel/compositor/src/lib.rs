@@ -1,11 +1,16 @@
 //! This crate defines a trait of `Compositor`  .
 //! A compositor composites a list of sources buffers to a single destination buffer.
+//!
+//! The [`damage`](damage/index.html) module tracks which regions of the destination buffer need to be composited again.
 
 #![no_std]
 
+extern crate alloc;
 extern crate framebuffer;
 extern crate shapes;
 
+pub mod damage;
+
 use core::iter::IntoIterator;
 
 use framebuffer::{Framebuffer, Pixel};
@@ -57,6 +62,21 @@ pub struct FramebufferUpdates<'a, P: Pixel> {
     /// should be composited. 
     /// This coordinate is expressed relative to the top-left corner of the destination framebuffer. 
     pub coordinate_in_dest_framebuffer: Coord,
+    /// The opacity with which the source framebuffer is blended into the destination,
+    /// in addition to the transparency of its individual pixels.
+    /// 255 means the source is fully opaque, and 0 means it is invisible.
+    pub opacity: u8,
+}
+
+impl<'a, P: Pixel> FramebufferUpdates<'a, P> {
+    /// Creates a fully opaque source framebuffer that is composited at the given coordinate.
+    pub fn new(src_framebuffer: &'a Framebuffer<P>, coordinate_in_dest_framebuffer: Coord) -> FramebufferUpdates<'a, P> {
+        FramebufferUpdates {
+            src_framebuffer,
+            coordinate_in_dest_framebuffer,
+            opacity: 255,
+        }
+    }
 }
 
 /// A `CompositableRegion` is an abstract region (i.e., a bounding box) 
@@ -81,12 +101,14 @@ pub trait CompositableRegion {
     /// The `dest_coord` is the coordinate in the destination buffer (relative to its top-left corner)
     /// where the `src_fb` will be composited (starting at the `src_fb`'s top-left corner).
     /// `src_fb_row_range` is the index range of rows in the source framebuffer to blend.
+    /// `opacity` fades the source framebuffer, where 255 leaves it unchanged and 0 makes it invisible.
     fn blend_buffers<P: Pixel>(
         &self, 
         src_fb: &Framebuffer<P>, 
         dest_fb: &mut Framebuffer<P>, 
         dest_coord: Coord,
-        src_fb_row_range: Range<usize>       
+        src_fb_row_range: Range<usize>,
+        opacity: u8,
     ) -> Result<(), &'static str>;
 }
 
@@ -107,10 +129,11 @@ impl CompositableRegion for Coord {
         dest_fb: &mut Framebuffer<P>, 
         dest_coord: Coord,        
         _src_fb_row_range: Range<usize>,
+        opacity: u8,
     ) -> Result<(), &'static str>{
         let relative_coord = self.clone() - dest_coord;
-        if let Some(pixel) = src_fb.get_pixel(relative_coord) {
-            dest_fb.draw_pixel(self.clone(), pixel);
+        if let (Some(pixel), Some(index)) = (src_fb.get_pixel(relative_coord), dest_fb.index_of(self.clone())) {
+            dest_fb.composite_buffer_with_opacity(&[pixel], index, opacity);
         }
         Ok(())
     }
@@ -133,6 +156,7 @@ impl CompositableRegion for Rectangle {
         dest_fb: &mut Framebuffer<P>,
         dest_coord: Coord,
         src_fb_row_range: Range<usize>,
+        opacity: u8,
     ) -> Result<(), &'static str> {
         let (dest_width, dest_height) = dest_fb.get_size();
         let (src_width, src_height) = src_fb.get_size();
@@ -190,7 +214,7 @@ impl CompositableRegion for Rectangle {
                 Some(index) => index,
                 None => {continue;}
             };
-            dest_fb.composite_buffer(&(src_buffer[src_start_index..src_end_index]), dest_start_index as usize);
+            dest_fb.composite_buffer_with_opacity(&(src_buffer[src_start_index..src_end_index]), dest_start_index as usize, opacity);
         }
 
         Ok(())
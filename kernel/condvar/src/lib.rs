@@ -0,0 +1,155 @@
+//! A condition variable that puts tasks to sleep while they wait to be notified,
+//! for use with a [`MutexSleep`](../mutex_sleep/struct.MutexSleep.html).
+//!
+//! Like `std::sync::Condvar`, waiting on a `CondVar` atomically releases the given mutex guard,
+//! blocks the current task until it is notified, and then re-acquires the mutex before returning.
+//! Spurious wakeups are possible, so callers should always re-check their condition,
+//! e.g., by using [`CondVar::wait_while()`](struct.CondVar.html#method.wait_while).
+
+#![no_std]
+
+extern crate alloc;
+extern crate wait_queue;
+extern crate mutex_sleep;
+extern crate timer;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use alloc::sync::Arc;
+use wait_queue::{WaitQueue, WaitError};
+use mutex_sleep::MutexSleepGuard;
+use timer::Instant;
+
+
+/// Indicates whether a timed wait on a `CondVar` returned because its timeout elapsed,
+/// returned from [`CondVar::wait_timeout()`](struct.CondVar.html#method.wait_timeout).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait returned because the timeout elapsed rather than due to a notification.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+
+/// A condition variable that blocks tasks on a `WaitQueue` until they are notified.
+///
+/// A `CondVar` should only ever be used with a single `MutexSleep` at a time.
+pub struct CondVar {
+    /// This is shared with the timers of tasks that are waiting with a timeout, which notify it once the timeout elapses.
+    queue: Arc<WaitQueue>,
+    /// Incremented upon every notification, which allows a waiting task to detect
+    /// a notification that occurred after it released the mutex but before it was added to the `queue`.
+    sequence: AtomicUsize,
+}
+
+impl CondVar {
+    /// Creates a new condition variable with no waiting tasks.
+    pub fn new() -> CondVar {
+        CondVar {
+            queue: Arc::new(WaitQueue::new()),
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    /// Releases the given `guard` and blocks the current task until this `CondVar` is notified,
+    /// after which the mutex is re-acquired and a new guard is returned.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexSleepGuard<'a, T>) -> Result<MutexSleepGuard<'a, T>, WaitError> {
+        let mutex = MutexSleepGuard::mutex(&guard);
+        let sequence = self.sequence.load(Ordering::Acquire);
+        drop(guard);
+
+        // The condition is checked with the waitqueue locked, so a notification
+        // can't slip in between checking the sequence number and blocking this task.
+        let res = self.queue.wait_until(&|| {
+            if self.sequence.load(Ordering::Acquire) != sequence { Some(()) } else { None }
+        });
+        let new_guard = mutex.lock().map_err(|_| WaitError::NoCurrentTask)?;
+        res.map(|_| new_guard)
+    }
+
+    /// Blocks the current task until the given `condition` returns `false`,
+    /// waiting on this `CondVar` each time the `condition` returns `true`.
+    ///
+    /// The `condition` is always invoked with the mutex locked.
+    pub fn wait_while<'a, T: ?Sized, F>(&self, mut guard: MutexSleepGuard<'a, T>, mut condition: F) -> Result<MutexSleepGuard<'a, T>, WaitError>
+        where F: FnMut(&mut T) -> bool
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Similar to [`wait()`](#method.wait), but gives up waiting once the given `timeout` elapses.
+    ///
+    /// While waiting, the current task sleeps until this `CondVar` is notified
+    /// or a timer wakes it up once the timeout elapses.
+    pub fn wait_timeout<'a, T: ?Sized>(&self, guard: MutexSleepGuard<'a, T>, timeout: Duration) -> Result<(MutexSleepGuard<'a, T>, WaitTimeoutResult), WaitError> {
+        self.wait_deadline(guard, Instant::now() + timeout)
+    }
+
+    /// Similar to [`wait_while()`](#method.wait_while), but gives up waiting once the given `timeout` elapses.
+    pub fn wait_timeout_while<'a, T: ?Sized, F>(&self, mut guard: MutexSleepGuard<'a, T>, timeout: Duration, mut condition: F) -> Result<(MutexSleepGuard<'a, T>, WaitTimeoutResult), WaitError>
+        where F: FnMut(&mut T) -> bool
+    {
+        let deadline = Instant::now() + timeout;
+        while condition(&mut *guard) {
+            let (new_guard, result) = self.wait_deadline(guard, deadline)?;
+            guard = new_guard;
+            if result.timed_out() {
+                let timed_out = condition(&mut *guard);
+                return Ok((guard, WaitTimeoutResult(timed_out)));
+            }
+        }
+        Ok((guard, WaitTimeoutResult(false)))
+    }
+
+    /// Releases the given `guard` and blocks the current task until this `CondVar` is notified
+    /// or the clock reaches `deadline`, after which the mutex is re-acquired and a new guard is returned.
+    fn wait_deadline<'a, T: ?Sized>(&self, guard: MutexSleepGuard<'a, T>, deadline: Instant) -> Result<(MutexSleepGuard<'a, T>, WaitTimeoutResult), WaitError> {
+        let mutex = MutexSleepGuard::mutex(&guard);
+        let sequence = self.sequence.load(Ordering::Acquire);
+        drop(guard);
+
+        let timer = {
+            let queue = self.queue.clone();
+            timer::call_at(deadline, move || {
+                queue.notify_all();
+            })
+        };
+        // As in `wait()`, the conditions are checked with the waitqueue locked.
+        let res = self.queue.wait_until(&|| {
+            if self.sequence.load(Ordering::Acquire) != sequence {
+                Some(false)
+            } else if Instant::now() >= deadline {
+                Some(true)
+            } else {
+                None
+            }
+        });
+        timer.cancel();
+        let new_guard = mutex.lock().map_err(|_| WaitError::NoCurrentTask)?;
+        res.map(|timed_out| (new_guard, WaitTimeoutResult(timed_out)))
+    }
+
+    /// Wakes up one task waiting on this `CondVar`, if any.
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::AcqRel);
+        self.queue.notify_one();
+    }
+
+    /// Wakes up all tasks waiting on this `CondVar`.
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::AcqRel);
+        self.queue.notify_all();
+    }
+}
+
+impl Default for CondVar {
+    fn default() -> CondVar {
+        CondVar::new()
+    }
+}
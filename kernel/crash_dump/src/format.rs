@@ -0,0 +1,288 @@
+//! The layout of a crash dump within its physical memory area.
+//!
+//! A dump starts with a header that holds a magic number, the length of the dump, and a checksum.
+//! It is followed by a sequence of sections, each of which is a one-byte tag and a 32-bit little-endian length,
+//! followed by that many bytes of data. All sections hold UTF-8 text, except for the numeric ones,
+//! which hold 64-bit little-endian values.
+//!
+//! The header is rewritten whenever a group of sections is complete, i.e., upon [`DumpWriter::commit()`].
+//! Thus, if writing a dump hangs or causes a nested fault, the sections committed up to that point remain valid.
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+
+
+/// Identifies a valid crash dump; the ASCII characters `"THSCRASH"`.
+const MAGIC: u64 = 0x4853_4152_4353_4854;
+/// The version of the dump layout, which must be changed whenever the layout changes.
+const VERSION: u32 = 1;
+/// The size of the header: the magic number, version, length, and checksum.
+const HEADER_LEN: usize = 20;
+/// The size of a section's tag and length.
+const SECTION_HEADER_LEN: usize = 5;
+/// The number of registers in [`Registers`](struct.Registers.html).
+const REGISTER_COUNT: usize = 7;
+
+const TAG_CAUSE: u8            = 1;
+const TAG_CPU: u8              = 2;
+const TAG_TASK: u8             = 3;
+const TAG_APP_CRATE: u8        = 4;
+const TAG_FAULTING_CRATE: u8   = 5;
+const TAG_ERROR_CODE: u8       = 6;
+const TAG_ADDRESS_ACCESSED: u8 = 7;
+const TAG_REGISTERS: u8        = 8;
+const TAG_BACKTRACE_FRAME: u8  = 9;
+const TAG_LOG_RECORD: u8       = 10;
+const TAG_CRATE: u8            = 11;
+
+
+/// The register state of the CPU when the crash occurred.
+///
+/// For an exception, this is the state of the interrupted code;
+/// for a panic, this is the state of the panic handler, since the panicking code is still running.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+    pub cs: u64,
+    pub ss: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+}
+
+impl Registers {
+    /// Returns the name and value of each register, in the order in which they should be displayed.
+    pub fn named_values(&self) -> [(&'static str, u64); REGISTER_COUNT] {
+        [
+            ("RIP", self.rip), ("RSP", self.rsp), ("RFLAGS", self.rflags),
+            ("CS", self.cs), ("SS", self.ss), ("CR2", self.cr2), ("CR3", self.cr3),
+        ]
+    }
+
+    fn to_bytes(self) -> [u8; REGISTER_COUNT * 8] {
+        let mut bytes = [0u8; REGISTER_COUNT * 8];
+        for (chunk, (_, value)) in bytes.chunks_exact_mut(8).zip(self.named_values().iter()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Registers> {
+        if bytes.len() != REGISTER_COUNT * 8 {
+            return None;
+        }
+        let value = |i: usize| read_u64(&bytes[i * 8 ..]).unwrap_or(0);
+        Some(Registers {
+            rip: value(0), rsp: value(1), rflags: value(2),
+            cs: value(3), ss: value(4), cr2: value(5), cr3: value(6),
+        })
+    }
+}
+
+
+/// A crash dump that was read back from its physical memory area.
+#[derive(Clone, Debug, Default)]
+pub struct CrashDump {
+    /// A description of the panic or exception that caused the crash.
+    pub cause: String,
+    /// The CPU core on which the crash occurred.
+    pub cpu: Option<u8>,
+    /// The name of the task that crashed.
+    pub task: Option<String>,
+    /// The application crate that the crashed task was running, if it was an application.
+    pub app_crate: Option<String>,
+    /// The crate in which the crash occurred.
+    pub faulting_crate: Option<String>,
+    /// The error code pushed by the CPU for exceptions that have one.
+    pub error_code: Option<u64>,
+    /// The address that was accessed, for page faults.
+    pub address_accessed: Option<u64>,
+    pub registers: Option<Registers>,
+    /// The symbolized frames of the crashed task's call stack, innermost frame first.
+    pub backtrace: Vec<String>,
+    /// The most recent records from the log ring buffer, oldest first.
+    pub log: Vec<String>,
+    /// The names of all crates that were loaded into the crashed task's namespace.
+    pub crates: Vec<String>,
+}
+
+
+/// Writes a crash dump section by section into a buffer, which is typically its physical memory area.
+pub struct DumpWriter<'b> {
+    buf: &'b mut [u8],
+    /// The end of the last section written.
+    len: usize,
+    /// The position beyond which no sections are written.
+    limit: usize,
+}
+
+impl<'b> DumpWriter<'b> {
+    /// Creates a writer that overwrites any dump in `buf`, beginning with an empty dump.
+    pub fn new(buf: &'b mut [u8]) -> DumpWriter<'b> {
+        let limit = buf.len();
+        let mut writer = DumpWriter { buf, len: HEADER_LEN, limit };
+        writer.commit();
+        writer
+    }
+
+    /// Returns the number of bytes that can still be written before the limit is reached.
+    pub fn remaining(&self) -> usize {
+        self.limit.saturating_sub(self.len)
+    }
+
+    /// Prevents more than `max_len` further bytes from being written, until the limit is changed again.
+    pub fn set_limit(&mut self, max_len: usize) {
+        self.limit = core::cmp::min(self.len.saturating_add(max_len), self.buf.len());
+    }
+
+    /// Makes all sections written so far part of the dump.
+    pub fn commit(&mut self) {
+        if self.buf.len() < HEADER_LEN {
+            return;
+        }
+        let checksum = checksum(&self.buf[HEADER_LEN .. self.len]);
+        self.buf[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        self.buf[12..16].copy_from_slice(&((self.len - HEADER_LEN) as u32).to_le_bytes());
+        self.buf[16..20].copy_from_slice(&checksum.to_le_bytes());
+        // write the magic number last, since it's what makes the dump valid
+        self.buf[0..8].copy_from_slice(&MAGIC.to_le_bytes());
+    }
+
+    pub fn cause(&mut self, cause: fmt::Arguments) -> bool { self.text(TAG_CAUSE, cause) }
+    pub fn cpu(&mut self, cpu: u8) -> bool { self.number(TAG_CPU, cpu as u64) }
+    pub fn task(&mut self, name: &str) -> bool { self.text(TAG_TASK, format_args!("{}", name)) }
+    pub fn app_crate(&mut self, name: &str) -> bool { self.text(TAG_APP_CRATE, format_args!("{}", name)) }
+    pub fn faulting_crate(&mut self, name: &str) -> bool { self.text(TAG_FAULTING_CRATE, format_args!("{}", name)) }
+    pub fn error_code(&mut self, error_code: u64) -> bool { self.number(TAG_ERROR_CODE, error_code) }
+    pub fn address_accessed(&mut self, address: u64) -> bool { self.number(TAG_ADDRESS_ACCESSED, address) }
+    pub fn registers(&mut self, registers: &Registers) -> bool { self.section(TAG_REGISTERS, &registers.to_bytes()) }
+    pub fn backtrace_frame(&mut self, frame: fmt::Arguments) -> bool { self.text(TAG_BACKTRACE_FRAME, frame) }
+    pub fn crate_name(&mut self, name: &str) -> bool { self.text(TAG_CRATE, format_args!("{}", name)) }
+
+    /// Writes a log record. Records must be written newest first,
+    /// such that the oldest records are the ones that are left out if the dump is full.
+    pub fn log_record(&mut self, record: fmt::Arguments) -> bool { self.text(TAG_LOG_RECORD, record) }
+
+    fn number(&mut self, tag: u8, value: u64) -> bool {
+        self.section(tag, &value.to_le_bytes())
+    }
+
+    fn section(&mut self, tag: u8, data: &[u8]) -> bool {
+        let start = self.len + SECTION_HEADER_LEN;
+        let end = start + data.len();
+        if end > self.limit {
+            return false;
+        }
+        self.buf[start..end].copy_from_slice(data);
+        self.finish_section(tag, end)
+    }
+
+    /// Formats the given text directly into the buffer, without allocating.
+    /// Text that doesn't fit is left out entirely rather than being truncated.
+    fn text(&mut self, tag: u8, args: fmt::Arguments) -> bool {
+        let start = self.len + SECTION_HEADER_LEN;
+        if start > self.limit {
+            return false;
+        }
+        let mut writer = SliceWriter { buf: &mut self.buf[start .. self.limit], len: 0 };
+        if writer.write_fmt(args).is_err() {
+            return false;
+        }
+        let end = start + writer.len;
+        self.finish_section(tag, end)
+    }
+
+    fn finish_section(&mut self, tag: u8, end: usize) -> bool {
+        let data_len = end - (self.len + SECTION_HEADER_LEN);
+        self.buf[self.len] = tag;
+        self.buf[self.len + 1 .. self.len + SECTION_HEADER_LEN].copy_from_slice(&(data_len as u32).to_le_bytes());
+        self.len = end;
+        true
+    }
+}
+
+
+/// Reads the crash dump in `buf`, returning `None` if it doesn't hold a valid dump.
+pub fn parse(buf: &[u8]) -> Option<CrashDump> {
+    if read_u64(buf)? != MAGIC || read_u32(&buf[8..])? != VERSION {
+        return None;
+    }
+    let len = read_u32(&buf[12..])? as usize;
+    let sections = buf.get(HEADER_LEN .. HEADER_LEN.checked_add(len)?)?;
+    if read_u32(&buf[16..])? != checksum(sections) {
+        return None;
+    }
+
+    let mut dump = CrashDump::default();
+    let mut offset = 0;
+    while offset + SECTION_HEADER_LEN <= sections.len() {
+        let tag = sections[offset];
+        let data_len = read_u32(&sections[offset + 1 ..])? as usize;
+        let data_start = offset + SECTION_HEADER_LEN;
+        let data = sections.get(data_start .. data_start.checked_add(data_len)?)?;
+        let text = || String::from_utf8_lossy(data).into_owned();
+        match tag {
+            TAG_CAUSE            => dump.cause = text(),
+            TAG_CPU              => dump.cpu = read_u64(data).map(|cpu| cpu as u8),
+            TAG_TASK             => dump.task = Some(text()),
+            TAG_APP_CRATE        => dump.app_crate = Some(text()),
+            TAG_FAULTING_CRATE   => dump.faulting_crate = Some(text()),
+            TAG_ERROR_CODE       => dump.error_code = read_u64(data),
+            TAG_ADDRESS_ACCESSED => dump.address_accessed = read_u64(data),
+            TAG_REGISTERS        => dump.registers = Registers::from_bytes(data),
+            TAG_BACKTRACE_FRAME  => dump.backtrace.push(text()),
+            TAG_LOG_RECORD       => dump.log.push(text()),
+            TAG_CRATE            => dump.crates.push(text()),
+            // sections from a newer layout version are skipped
+            _ => { }
+        }
+        offset = data_start + data_len;
+    }
+    // log records are written newest first
+    dump.log.reverse();
+    Some(dump)
+}
+
+/// Invalidates the crash dump in `buf`, if any.
+pub fn clear(buf: &mut [u8]) {
+    for byte in buf.iter_mut().take(HEADER_LEN) {
+        *byte = 0;
+    }
+}
+
+
+/// The 32-bit FNV-1a hash of the given bytes.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |hash: u32, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+fn read_u32(bytes: &[u8]) -> Option<u32> {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(bytes.get(..4)?);
+    Some(u32::from_le_bytes(value))
+}
+
+fn read_u64(bytes: &[u8]) -> Option<u64> {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes.get(..8)?);
+    Some(u64::from_le_bytes(value))
+}
+
+/// A writer that formats into a fixed-size buffer, failing if the text doesn't fit.
+struct SliceWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Write for SliceWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len.checked_add(s.len()).filter(|&end| end <= self.buf.len()).ok_or(fmt::Error)?;
+        self.buf[self.len .. end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+//! Crash dumps that preserve the state of the kernel across a reboot, for postmortem debugging.
+//!
+//! When a task panics or causes a CPU exception, a crash dump is written into a physical memory area
+//! that the `memory` subsystem reserves at the top of available memory and never gives out.
+//! That memory isn't cleared by a warm reboot, so the dump can be read back during the next boot,
+//! even if the crash left the system unusable. A cold boot (power cycle) loses the dump, of course.
+//!
+//! A dump holds the cause of the crash, the register state, a symbolized backtrace,
+//! the most recent records from the logger's ring buffer, and the list of loaded crates.
+//! Each crash overwrites the dump of the previous one, so only the most recent crash is kept.
+//!
+//! Upon boot, [`init()`](fn.init.html) reads the dump left behind by the previous boot, if any, and clears the area.
+//! That dump can then be retrieved with [`previous_dump()`](fn.previous_dump.html), e.g., by the `crashdump` application.
+
+#![no_std]
+#![feature(llvm_asm)]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate x86_64;
+extern crate memory;
+extern crate logger;
+extern crate task;
+extern crate mod_mgmt;
+extern crate fault_log;
+
+mod format;
+
+pub use format::{CrashDump, Registers};
+
+use core::fmt;
+use core::ops::DerefMut;
+use core::panic::PanicInfo;
+use spin::Once;
+use irq_safety::MutexIrqSafe;
+use x86_64::registers::control_regs;
+use x86_64::structures::idt::ExceptionStackFrame;
+use memory::{EntryFlags, FrameRange, MappedPages, allocate_pages_by_bytes, get_frame_allocator_ref, get_kernel_mmi_ref};
+use format::DumpWriter;
+
+
+/// The crash dump left behind by the previous boot, if there was one.
+static PREVIOUS_DUMP: Once<Option<CrashDump>> = Once::new();
+
+/// The mapped physical memory area that a crash dump is written into.
+static DUMP_AREA: Once<MutexIrqSafe<DumpArea>> = Once::new();
+
+struct DumpArea {
+    pages: MappedPages,
+    size_in_bytes: usize,
+}
+
+/// One in this many of the bytes that remain after the crash details and backtrace
+/// is kept free for the list of crates, such that the log records can't crowd it out.
+const CRATE_SPACE_DIVISOR: usize = 4;
+
+
+/// Maps the crash dump area, reads the dump of the previous boot from it, and then clears it
+/// such that it's ready to hold a dump of a crash during this boot.
+///
+/// This should be called as early as possible, since crashes that occur before this are not dumped.
+pub fn init() -> Result<(), &'static str> {
+    let area = memory::crash_dump_area().ok_or("crash_dump: no physical memory area was reserved for crash dumps")?;
+    let pages = allocate_pages_by_bytes(area.size_in_bytes).ok_or("crash_dump: couldn't allocate pages for the crash dump area")?;
+    let frames = FrameRange::from_phys_addr(area.base_addr, area.size_in_bytes);
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("crash_dump: KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("crash_dump: couldn't get the frame allocator")?;
+    let mut pages = kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
+        pages,
+        frames,
+        EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+        fa.lock().deref_mut(),
+    )?;
+
+    let previous_dump = {
+        let buf = pages.as_slice_mut::<u8>(0, area.size_in_bytes)?;
+        let dump = format::parse(buf);
+        format::clear(buf);
+        dump
+    };
+    match previous_dump {
+        Some(ref dump) => warn!("crash_dump: found a crash dump from the previous boot: {}", dump.cause),
+        None => info!("crash_dump: no crash dump from the previous boot, using area at {:#X}", area.base_addr),
+    }
+
+    PREVIOUS_DUMP.call_once(|| previous_dump);
+    DUMP_AREA.call_once(|| MutexIrqSafe::new(DumpArea { pages, size_in_bytes: area.size_in_bytes }));
+    Ok(())
+}
+
+/// Returns the crash dump that was left behind by the previous boot,
+/// or `None` if the previous boot didn't crash or the crash dump area hasn't been initialized.
+pub fn previous_dump() -> Option<&'static CrashDump> {
+    PREVIOUS_DUMP.try().and_then(|dump| dump.as_ref())
+}
+
+
+/// Writes a crash dump for the panic that is currently being handled on this CPU.
+///
+/// This should be called after the panic has been recorded in the fault log,
+/// along with its backtrace, since the details of the crash are taken from there.
+pub fn save_panic(panic_info: &PanicInfo) {
+    save(format_args!("{}", panic_info), &Registers::capture());
+}
+
+/// Writes a crash dump for the exception that is currently being handled on this CPU.
+///
+/// This should be called after the exception has been recorded in the fault log,
+/// along with its backtrace, since the details of the crash are taken from there.
+pub fn save_exception(exception_number: u8, stack_frame: &ExceptionStackFrame) {
+    save(
+        format_args!("exception {:#X} ({:?})", exception_number, fault_log::from_exception_number(exception_number)),
+        &Registers::from_exception(stack_frame),
+    );
+}
+
+fn save(cause: fmt::Arguments, registers: &Registers) {
+    // Only *try* to acquire the lock, because another CPU may be writing a dump right now,
+    // or this may be a nested crash that occurred while writing a dump on this CPU.
+    let mut area = match DUMP_AREA.try().and_then(|area| area.try_lock()) {
+        Some(area) => area,
+        None => return,
+    };
+    let size_in_bytes = area.size_in_bytes;
+    let buf = match area.pages.as_slice_mut::<u8>(0, size_in_bytes) {
+        Ok(buf) => buf,
+        Err(_) => return,
+    };
+    let mut writer = DumpWriter::new(buf);
+
+    writer.cause(cause);
+    writer.registers(registers);
+    if let Some(fault) = fault_log::current_fault() {
+        if let Some(cpu) = fault.core { writer.cpu(cpu); }
+        if let Some(ref task) = fault.running_task { writer.task(task); }
+        if let Some(ref app_crate) = fault.running_app_crate { writer.app_crate(app_crate); }
+        if let Some(ref faulting_crate) = fault.crate_error_occured { writer.faulting_crate(faulting_crate); }
+        if let Some(error_code) = fault.error_code { writer.error_code(error_code); }
+        if let Some(address) = fault.address_accessed { writer.address_accessed(address.value() as u64); }
+        if let Some(ref backtrace) = fault.backtrace {
+            for frame in &backtrace.frames {
+                writer.backtrace_frame(format_args!("{}", frame));
+            }
+            if let Some(e) = backtrace.error {
+                writer.backtrace_frame(format_args!("{}", e));
+            }
+        }
+    }
+    writer.commit();
+
+    // The newest log records are the most relevant, so they're written first.
+    let log_space = writer.remaining() - writer.remaining() / CRATE_SPACE_DIVISOR;
+    writer.set_limit(log_space);
+    let (records, _) = logger::read_from(0);
+    for record in records.iter().rev() {
+        if !writer.log_record(format_args!("{}", record)) {
+            break;
+        }
+    }
+    writer.set_limit(usize::MAX);
+    writer.commit();
+
+    // Listing the crates requires acquiring the namespace's lock, which could hang if the crash occurred while holding it,
+    // so that's done last, after everything else has been committed.
+    let namespace = task::get_my_current_task().map(|t| t.get_namespace())
+        .or_else(|| mod_mgmt::get_initial_kernel_namespace().cloned());
+    if let Some(namespace) = namespace {
+        for crate_name in namespace.crate_names(true) {
+            if !writer.crate_name(&crate_name) {
+                break;
+            }
+        }
+    }
+    writer.commit();
+}
+
+
+impl Registers {
+    /// Captures the current register state, which is used for panics,
+    /// since they don't interrupt the code that panicked.
+    #[inline(always)]
+    fn capture() -> Registers {
+        let rip: u64;
+        let rsp: u64;
+        let rflags: u64;
+        let cs: u64;
+        let ss: u64;
+        unsafe {
+            llvm_asm!("lea $0, [rip]" : "=r"(rip) : : "memory" : "intel", "volatile");
+            llvm_asm!("mov $0, rsp" : "=r"(rsp) : : "memory" : "intel", "volatile");
+            llvm_asm!("pushfq; pop $0" : "=r"(rflags) : : "memory" : "intel", "volatile");
+            llvm_asm!("mov $0, cs" : "=r"(cs) : : "memory" : "intel", "volatile");
+            llvm_asm!("mov $0, ss" : "=r"(ss) : : "memory" : "intel", "volatile");
+        }
+        Registers {
+            rip, rsp, rflags, cs, ss,
+            cr2: control_regs::cr2().0 as u64,
+            cr3: control_regs::cr3().0 as u64,
+        }
+    }
+
+    /// Returns the register state of the code that was interrupted by an exception.
+    fn from_exception(stack_frame: &ExceptionStackFrame) -> Registers {
+        Registers {
+            rip: stack_frame.instruction_pointer.0 as u64,
+            rsp: stack_frame.stack_pointer.0 as u64,
+            rflags: stack_frame.cpu_flags,
+            cs: stack_frame.code_segment,
+            ss: stack_frame.stack_segment,
+            cr2: control_regs::cr2().0 as u64,
+            cr3: control_regs::cr3().0 as u64,
+        }
+    }
+}
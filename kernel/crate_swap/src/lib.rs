@@ -15,17 +15,20 @@ extern crate fs_node;
 extern crate qp_trie;
 extern crate path;
 extern crate by_address;
+extern crate task;
 
 #[cfg(loscd_eval)]
 extern crate hpet;
 
 use core::{
+    any::Any,
     fmt,
     ops::Deref,
 };
 use spin::Mutex;
 use alloc::{
     borrow::Cow,
+    boxed::Box,
     collections::BTreeSet,
     string::{String, ToString},
     sync::Arc,
@@ -42,11 +45,13 @@ use mod_mgmt::{
     write_relocation,
     crate_name_from_path,
     replace_containing_crate_name,
+    StrongCrateRef,
     StrongSectionRef,
     WeakDependent,
 };
 use path::Path;
 use by_address::ByAddress;
+use task::TaskRef;
 
 
 lazy_static! {
@@ -73,6 +78,29 @@ pub fn clear_unloaded_crate_cache() {
 /// See the `swap_crates()` function for more details. 
 pub type StateTransferFunction = fn(&Arc<CrateNamespace>, &CrateNamespace) -> Result<(), &'static str>;
 
+/// An opaque blob of state handed off from an old crate to the new crate that replaces it. 
+/// 
+/// The old and new crates must agree on the concrete type within the blob.
+pub type StateBlob = Box<dyn Any + Send>;
+
+/// The signature of a crate's swap-out hook, a public function named `on_swap_out` at the root of the crate,
+/// which is invoked on an old crate being swapped out to capture its state.
+/// 
+/// This hook must not invalidate the old crate's state, because the old crate
+/// continues to be used if the swap is rolled back. 
+pub type SwapOutHook = fn() -> Result<StateBlob, &'static str>;
+
+/// The signature of a crate's swap-in hook, a public function named `on_swap_in` at the root of the crate,
+/// which is invoked on a new crate being swapped in to initialize it from the state of the old crate.
+/// 
+/// If this hook returns an error, the entire swap operation is rolled back.
+pub type SwapInHook = fn(StateBlob) -> Result<(), &'static str>;
+
+/// The name of the [`SwapOutHook`](type.SwapOutHook.html) function within a crate.
+pub const SWAP_OUT_HOOK_NAME: &'static str = "on_swap_out";
+/// The name of the [`SwapInHook`](type.SwapInHook.html) function within a crate.
+pub const SWAP_IN_HOOK_NAME: &'static str = "on_swap_in";
+
 
 /// Swaps in new crates that can optionally replace existing crates in this `CrateNamespace`.
 /// 
@@ -80,7 +108,9 @@ pub type StateTransferFunction = fn(&Arc<CrateNamespace>, &CrateNamespace) -> Re
 /// 
 /// In general, the strategy for replacing an old crate `C` with a new crate `C2` consists of three steps:
 /// 1) Load the new replacement crate `C2` from its object file.
-/// 2) Copy the .data and .bss sections from old crate `C` to the new crate `C2`
+/// 2) Transfer state from old crate `C` to the new crate `C2`: if `C` has an `on_swap_out` hook and `C2` has an `on_swap_in` hook,
+///    the state blob returned by the former is passed into the latter. 
+///    Otherwise, copy the .data and .bss sections from old crate `C` to the new crate `C2`.
 /// 3) Set up new relocation entries that redirect all dependencies on the old crate `C` to the new crate `C2`.
 /// 4) Remove crate `C` and clean it up, e.g., removing its entries from the symbol map.
 ///    Save the removed crate (and its symbol subtrie) in a cache for later use to expedite future swapping operations.
@@ -113,6 +143,15 @@ pub type StateTransferFunction = fn(&Arc<CrateNamespace>, &CrateNamespace) -> Re
 /// * `kernel_mmi_ref`: a reference to the kernel's `MemoryManagementInfo`.
 /// * `verbose_log`: enable verbose logging.
 /// 
+/// # State handoff hooks
+/// While crates are being swapped, all runnable tasks (other than the current task) in the namespaces of the old crates are paused,
+/// such that they cannot observe the old crate's state changing after it has been handed off. 
+/// The state handoff hooks (see [`SwapOutHook`] and [`SwapInHook`]) are invoked before any dependencies are rewritten,
+/// so if any hook fails, the swap is rolled back by simply discarding the new crates and resuming the paused tasks.
+/// 
+/// [`SwapOutHook`]: type.SwapOutHook.html
+/// [`SwapInHook`]: type.SwapInHook.html
+/// 
 /// # Warning: Correctness not guaranteed
 /// This function currently makes no attempt to guarantee correct operation after a crate is swapped. 
 /// For example, if the new crate changes a function or data structure, there is no guarantee that 
@@ -193,6 +232,13 @@ pub fn swap_crates(
     #[cfg(loscd_eval)]
     let hpet_after_load_crates = hpet.get_counter();
 
+    // Pause the tasks that may depend on the old crates until swapping is complete (or has failed).
+    let _paused_tasks = PausedTasks::pause(&swap_requests);
+
+    // Hand off state from old crates to new crates before anything in the old namespace is changed,
+    // such that a failed hook requires no rollback beyond discarding the new crates.
+    let crates_with_state_handoff = hand_off_state(&swap_requests, &namespace_of_new_crates)?;
+
     #[cfg(not(loscd_eval))]
     let (mut future_swap_requests, cached_crates) = if cache_old_crates {
         (
@@ -287,7 +333,10 @@ pub fn swap_crates(
 
             // Go through all the `.data` and `.bss` sections and copy over the old_sec into the new source_sec,
             // as they represent static variables that would otherwise result in a loss of data.
-            for old_sec in old_crate.data_sections_iter() {
+            // This is skipped for crates whose state was already handed off by their hooks.
+            let data_sections_to_copy = old_crate.data_sections_iter()
+                .filter(|_| !crates_with_state_handoff.contains(&new_crate_name));
+            for old_sec in data_sections_to_copy {
                 let old_sec_name_without_hash = old_sec.name_without_hash();
                 // get the section from the new crate that corresponds to the `old_sec`
                 let prefix = if crates_have_same_name {
@@ -783,6 +832,125 @@ pub fn swap_crates(
 }
 
 
+/// Invokes the state handoff hooks of each pair of old and new crates in the given `swap_requests`. 
+/// 
+/// All old crates' [`SwapOutHook`]s are invoked first, followed by all new crates' [`SwapInHook`]s.
+/// Only crate pairs in which the old crate has a swap-out hook and the new crate has a swap-in hook participate.
+/// 
+/// Returns the names of the new crates whose state was handed off. 
+/// 
+/// [`SwapOutHook`]: type.SwapOutHook.html
+/// [`SwapInHook`]: type.SwapInHook.html
+fn hand_off_state(swap_requests: &SwapRequestList, namespace_of_new_crates: &CrateNamespace) -> Result<BTreeSet<String>, &'static str> {
+    let mut handoffs: Vec<(String, StrongSectionRef, StrongSectionRef)> = Vec::new();
+    for req in swap_requests {
+        let old_crate_ref = match req.old_crate_name.as_deref().and_then(|ocn| CrateNamespace::get_crate_and_namespace(&req.old_namespace, ocn)) {
+            Some((ocr, _ns)) => ocr,
+            _ => continue,
+        };
+        let new_crate_name = crate_name_from_path(&Path::new(req.new_crate_object_file.lock().get_name())).to_string();
+        let new_crate_ref = namespace_of_new_crates.get_crate(&new_crate_name)
+            .ok_or("BUG: swap_crates(): couldn't get new crate to check for its swap-in hook")?;
+
+        match (find_hook(&old_crate_ref, SWAP_OUT_HOOK_NAME), find_hook(&new_crate_ref, SWAP_IN_HOOK_NAME)) {
+            (Some(swap_out), Some(swap_in)) => handoffs.push((new_crate_name, swap_out, swap_in)),
+            (None, None) => { }
+            (swap_out, _swap_in) => warn!("swap_crates(): only the {} crate of {:?} -> {:?} has a state handoff hook, falling back to copying .data/.bss sections",
+                if swap_out.is_some() { "old" } else { "new" }, req.old_crate_name, new_crate_name
+            ),
+        }
+    }
+
+    // Capture the state of every old crate before initializing any new crate, 
+    // such that no new crate observes a partial handoff if an old crate's hook fails.
+    let mut blobs: Vec<StateBlob> = Vec::with_capacity(handoffs.len());
+    for (new_crate_name, swap_out_sec, _) in &handoffs {
+        let mut space: usize = 0;
+        let swap_out = {
+            let mapped_pages = swap_out_sec.mapped_pages.lock();
+            mapped_pages.as_func::<SwapOutHook>(swap_out_sec.mapped_pages_offset, &mut space)?
+        };
+        #[cfg(not(loscd_eval))]
+        debug!("swap_crates(): invoking swap-out hook {:?} for new crate {:?}", swap_out_sec.name, new_crate_name);
+        blobs.push(swap_out().map_err(|e| {
+            error!("swap_crates(): swap-out hook {:?} failed: {}", swap_out_sec.name, e);
+            e
+        })?);
+    }
+
+    for ((new_crate_name, _, swap_in_sec), blob) in handoffs.iter().zip(blobs) {
+        let mut space: usize = 0;
+        let swap_in = {
+            let mapped_pages = swap_in_sec.mapped_pages.lock();
+            mapped_pages.as_func::<SwapInHook>(swap_in_sec.mapped_pages_offset, &mut space)?
+        };
+        #[cfg(not(loscd_eval))]
+        debug!("swap_crates(): invoking swap-in hook {:?} for new crate {:?}", swap_in_sec.name, new_crate_name);
+        swap_in(blob).map_err(|e| {
+            error!("swap_crates(): swap-in hook {:?} failed, rolling back the swap: {}", swap_in_sec.name, e);
+            e
+        })?;
+    }
+
+    Ok(handoffs.into_iter().map(|(new_crate_name, _, _)| new_crate_name).collect())
+}
+
+/// Returns the public function section in the given crate with the given `hook_name`
+/// at the root of the crate, e.g., `my_crate::on_swap_out::h0123456789abcdef`.
+fn find_hook(crate_ref: &StrongCrateRef, hook_name: &str) -> Option<StrongSectionRef> {
+    let krate = crate_ref.lock_as_ref();
+    let hook_path = format!("{}::{}", krate.crate_name_without_hash(), hook_name);
+    krate.global_sections_iter()
+        .find(|sec| sec.name_without_hash() == hook_path)
+        .cloned()
+}
+
+
+/// The set of tasks that have been paused during a crate swapping operation,
+/// which are resumed when this is dropped.
+struct PausedTasks(Vec<TaskRef>);
+
+impl PausedTasks {
+    /// Pauses all runnable tasks (except the current task and idle tasks) that run within
+    /// the old namespace of any of the given `swap_requests`, 
+    /// and waits for them to stop running on other cores.
+    fn pause(swap_requests: &SwapRequestList) -> PausedTasks {
+        let curr_task = task::get_my_current_task();
+        let mut paused = Vec::new();
+        // Don't hold the task list lock while locking each task.
+        let all_tasks: Vec<TaskRef> = task::TASKLIST.lock().values().cloned().collect();
+        for task_ref in &all_tasks {
+            if Some(task_ref) == curr_task {
+                continue;
+            }
+            let is_dependent = {
+                let t = task_ref.lock();
+                !t.is_an_idle_task && t.is_runnable() 
+                    && swap_requests.iter().any(|req| Arc::ptr_eq(&t.namespace, &req.old_namespace))
+            };
+            if is_dependent {
+                task_ref.block();
+                paused.push(task_ref.clone());
+            }
+        }
+        for task_ref in &paused {
+            while task_ref.lock().is_running() { }
+        }
+        #[cfg(not(loscd_eval))]
+        debug!("swap_crates(): paused {} tasks", paused.len());
+        PausedTasks(paused)
+    }
+}
+
+impl Drop for PausedTasks {
+    fn drop(&mut self) {
+        for task_ref in &self.0 {
+            task_ref.unblock();
+        }
+    }
+}
+
+
 /// Convenience function that removes the given `file` from its parent directory 
 /// and inserts it into the given destination directory. 
 /// 
@@ -4,6 +4,9 @@
 #[macro_use] extern crate log;
 extern crate event_types;
 extern crate e1000;
+extern crate virtio_net;
+extern crate xhci;
+extern crate ac97;
 extern crate memory;
 extern crate apic;
 extern crate acpi;
@@ -13,22 +16,26 @@ extern crate mouse;
 extern crate storage_manager;
 extern crate network_manager;
 extern crate ethernet_smoltcp_device;
-extern crate mpmc;
+extern crate channel;
 
 
-use mpmc::Queue;
+use channel::Sender;
 use event_types::Event;
 use memory::MemoryManagementInfo;
 use ethernet_smoltcp_device::EthernetNetworkInterface;
 use network_manager::add_to_network_interfaces;
 
 
-/// A randomly chosen IP address that must be outside of the DHCP range.. // TODO FIXME: use DHCP to acquire IP
+/// A randomly chosen IP address that must be outside of the DHCP range.
+/// Unless Theseus is built with the `use_dhcp` config flag, every network interface is given this address.
+#[cfg(not(use_dhcp))]
 const DEFAULT_LOCAL_IP: &'static str = "10.0.2.15/24"; // the default QEMU user-slirp network gives IP addresses of "10.0.2.*"
 // const DEFAULT_LOCAL_IP: &'static str = "192.168.1.252/24"; // home router reserved IP
 // const DEFAULT_LOCAL_IP: &'static str = "10.42.0.91/24"; // rice net IP
 
-/// Standard home router address. // TODO FIXME: use DHCP to acquire gateway IP
+/// Standard home router address.
+/// Unless Theseus is built with the `use_dhcp` config flag, every network interface uses this gateway.
+#[cfg(not(use_dhcp))]
 const DEFAULT_GATEWAY_IP: [u8; 4] = [10, 0, 2, 2]; // the default QEMU user-slirp networking gateway IP
 // const DEFAULT_GATEWAY_IP: [u8; 4] = [192, 168, 1, 1]; // the default gateway for our TAP-based bridge
 // const DEFAULT_GATEWAY_IP: [u8; 4] = [10, 42, 0, 1]; // rice net gateway ip
@@ -47,7 +54,7 @@ pub fn early_init(kernel_mmi: &mut MemoryManagementInfo) -> Result<(), &'static
 
 /// Initializes all other devices, such as the keyboard and mouse
 /// as well as all devices discovered on the PCI bus.
-pub fn init(key_producer: Queue<Event>, mouse_producer: Queue<Event>) -> Result<(), &'static str>  {
+pub fn init(key_producer: Sender<Event>, mouse_producer: Sender<Event>) -> Result<(), &'static str>  {
     keyboard::init(key_producer);
     mouse::init(mouse_producer);
 
@@ -76,16 +83,48 @@ pub fn init(key_producer: Queue<Event>, mouse_producer: Queue<Event>) -> Result<
             }
         }
 
+        // If this is a USB host controller, initialize it and the devices attached to it.
+        if dev.class == xhci::XHCI_PCI_CLASS && dev.subclass == xhci::XHCI_PCI_SUBCLASS && dev.prog_if == xhci::XHCI_PCI_PROG_IF {
+            info!("xHCI USB controller found at: {:?}", dev.location);
+            if let Err(e) = xhci::init(dev) {
+                error!("Failed to initialize xHCI USB controller, its devices will be unavailable.\n{:?}\nError: {}", dev, e);
+            }
+            continue;
+        }
+
+        // If this is an AC'97 audio controller, initialize it and use it for audio output.
+        if dev.class == ac97::AC97_PCI_CLASS && dev.subclass == ac97::AC97_PCI_SUBCLASS {
+            info!("AC'97 audio controller found at: {:?}", dev.location);
+            if let Err(e) = ac97::init(dev) {
+                error!("Failed to initialize AC'97 audio controller, audio output will be unavailable.\n{:?}\nError: {}", dev, e);
+            }
+            continue;
+        }
+
         // If this is a network device, initialize it as such.
         // Look for networking controllers, specifically ethernet cards
         if dev.class == 0x02 && dev.subclass == 0x00 {
             if dev.vendor_id == e1000::INTEL_VEND && dev.device_id == e1000::E1000_DEV {
                 info!("e1000 PCI device found at: {:?}", dev.location);
                 let e1000_nic_ref = e1000::E1000Nic::init(dev)?;
+                #[cfg(not(use_dhcp))]
                 let e1000_interface = EthernetNetworkInterface::new_ipv4_interface(e1000_nic_ref, DEFAULT_LOCAL_IP, &DEFAULT_GATEWAY_IP)?;
+                // The interface is configured via DHCP once the network stack is running, see `dhcp_client::init()`.
+                #[cfg(use_dhcp)]
+                let e1000_interface = EthernetNetworkInterface::new_dhcp_interface(e1000_nic_ref)?;
                 add_to_network_interfaces(e1000_interface);
                 continue;
             }
+            if virtio_net::is_virtio_net_device(dev) {
+                info!("virtio network PCI device found at: {:?}", dev.location);
+                let virtio_nic_ref = virtio_net::VirtioNetNic::init(dev)?;
+                #[cfg(not(use_dhcp))]
+                let virtio_interface = EthernetNetworkInterface::new_ipv4_interface(virtio_nic_ref, DEFAULT_LOCAL_IP, &DEFAULT_GATEWAY_IP)?;
+                #[cfg(use_dhcp)]
+                let virtio_interface = EthernetNetworkInterface::new_dhcp_interface(virtio_nic_ref)?;
+                add_to_network_interfaces(virtio_interface);
+                continue;
+            }
             // here: check for and initialize other ethernet cards
         }
 
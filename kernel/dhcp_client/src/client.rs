@@ -0,0 +1,437 @@
+//! The DHCP client of a single network interface, which runs as an asynchronous task.
+
+use core::cmp::{max, min};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
+use async_runtime::{InterruptEvent, Sleep};
+use network_manager::{NetworkInterface, NetworkInterfaceRef};
+use net_stack::raw::{self, RawHandle, IpVersion, IpProtocol};
+use message::{self, Message, MessageType};
+use {Lease, Status, set_status, stop_requested, remove_client};
+
+
+/// The delay before a message is first retransmitted, which doubles after each retransmission.
+const INITIAL_RETRANSMIT_MS: u64 = 2000;
+/// The upper bound of the delay between retransmissions.
+const MAX_RETRANSMIT_MS: u64 = 64_000;
+/// The number of DISCOVER messages that a client which has never held a lease sends before it gives up.
+const MAX_DISCOVER_ATTEMPTS: u32 = 4;
+/// The number of REQUEST messages for an offered address that are sent before starting over with a DISCOVER.
+const MAX_REQUEST_ATTEMPTS: u32 = 4;
+/// While renewing or rebinding, messages are retransmitted no more often than this (RFC 2131 section 4.4.5).
+const MIN_RENEW_RETRANSMIT_MS: u64 = 60_000;
+/// How often the socket is checked for replies while the client is waiting for one.
+const RECEIVE_POLL_MS: u64 = 10;
+/// The size of the buffer that replies are received into, which fits any packet on an Ethernet link.
+const RECEIVE_BUFFER_SIZE: usize = 1536;
+
+
+/// The states of a client, from RFC 2131 section 4.4.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Broadcasting DISCOVER messages and waiting for an OFFER.
+    Selecting,
+    /// Broadcasting a REQUEST for an offered address and waiting for the server's ACK.
+    Requesting { server: Ipv4Address, address: Ipv4Address },
+    /// Holding a lease that has been applied to the interface; nothing is sent until the renewal time.
+    Bound,
+    /// Sending REQUEST messages to the server that granted the lease, to extend it.
+    Renewing,
+    /// Broadcasting REQUEST messages to any server, to extend the lease.
+    Rebinding,
+}
+
+/// The DHCP client of a network interface.
+pub(crate) struct Client {
+    /// The index of the interface in `NETWORK_INTERFACES`.
+    index: usize,
+    iface: NetworkInterfaceRef,
+    hardware_address: EthernetAddress,
+    state: State,
+    lease: Option<Lease>,
+    /// The raw socket that messages are exchanged on, which is only open while the client isn't bound.
+    socket: Option<RawHandle>,
+    transaction_id: u32,
+    /// When the current exchange started, from which the `secs` field of messages is computed.
+    exchange_started_ms: u64,
+    /// When the message of the current state is next sent.
+    next_send_ms: u64,
+    /// The delay before the message of the current state is retransmitted.
+    retransmit_ms: u64,
+    /// The number of DISCOVER messages sent since the client last held a lease.
+    discovers_sent: u32,
+    /// The number of REQUEST messages sent for the current offer.
+    requests_sent: u32,
+    /// Whether the client keeps trying to obtain a lease forever, which it does once it has held one.
+    persistent: bool,
+    wake: Arc<InterruptEvent>,
+    sleep: Sleep,
+}
+
+impl Client {
+    pub(crate) fn new(index: usize, iface: NetworkInterfaceRef, wake: Arc<InterruptEvent>) -> Client {
+        let hardware_address = iface.lock().ethernet_addr();
+        Client {
+            index,
+            iface,
+            hardware_address,
+            state: State::Selecting,
+            lease: None,
+            socket: None,
+            transaction_id: 0,
+            exchange_started_ms: 0,
+            next_send_ms: 0,
+            retransmit_ms: INITIAL_RETRANSMIT_MS,
+            discovers_sent: 0,
+            requests_sent: 0,
+            persistent: false,
+            wake,
+            // The client takes its first step as soon as it's polled.
+            sleep: async_runtime::sleep(0),
+        }
+    }
+
+    /// Processes received replies, advances the lease timers, and sends the message of the current state if it's due.
+    /// Returns the number of milliseconds until the client needs to take its next step.
+    fn step(&mut self) -> Result<u64, &'static str> {
+        let now = net_stack::now_millis()?;
+        if self.socket.is_none() && self.state != State::Bound {
+            self.begin_exchange(self.state, now)?;
+        }
+        while let Some(reply) = self.receive()? {
+            self.handle_reply(reply, now)?;
+        }
+
+        if let Some(lease) = self.lease.clone() {
+            if lease.expires_at_ms().map_or(false, |expiry| now >= expiry) {
+                warn!("dhcp_client: the lease of address {} on interface {} expired", lease.address, self.index);
+                self.unapply(&lease);
+                self.lease = None;
+                set_status(self.index, Status::Configuring);
+                self.begin_exchange(State::Selecting, now)?;
+            } else if self.state == State::Bound && lease.renews_at_ms().map_or(false, |renewal| now >= renewal) {
+                self.begin_exchange(State::Renewing, now)?;
+            } else if self.state == State::Renewing && lease.rebinds_at_ms().map_or(false, |rebinding| now >= rebinding) {
+                self.state = State::Rebinding;
+                self.next_send_ms = now;
+            }
+        }
+
+        if self.state != State::Bound && now >= self.next_send_ms {
+            self.send_current(now)?;
+        }
+
+        Ok(match self.state {
+            State::Bound => self.lease.as_ref()
+                .and_then(Lease::renews_at_ms)
+                .map_or(u64::max_value(), |renewal| renewal.saturating_sub(now)),
+            _ => min(RECEIVE_POLL_MS, self.next_send_ms.saturating_sub(now)),
+        })
+    }
+
+    /// Enters the given state with a new transaction, opening the socket if needed,
+    /// such that the state's first message is sent right away.
+    fn begin_exchange(&mut self, state: State, now: u64) -> Result<(), &'static str> {
+        if self.socket.is_none() {
+            self.socket = Some(raw::open(IpVersion::Ipv4, IpProtocol::Udp)?);
+        }
+        self.state = state;
+        self.transaction_id = self.new_transaction_id();
+        self.exchange_started_ms = now;
+        self.next_send_ms = now;
+        self.retransmit_ms = INITIAL_RETRANSMIT_MS;
+        Ok(())
+    }
+
+    /// Returns a transaction ID that is unlikely to be used by other clients,
+    /// derived from the interface's hardware address and the current time.
+    fn new_transaction_id(&self) -> u32 {
+        let bytes = self.hardware_address.as_bytes();
+        let address_bits = u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]);
+        address_bits ^ timer::Instant::now().as_nanos() as u32
+    }
+
+    /// Returns a new message of the given type in the current transaction.
+    fn new_message(&self, message_type: MessageType, now: u64) -> Message {
+        let mut message = Message::new(message_type, self.transaction_id, self.hardware_address);
+        message.secs = min((now - self.exchange_started_ms) / 1000, u16::max_value() as u64) as u16;
+        message
+    }
+
+    /// Sends the message of the current state and schedules its retransmission.
+    fn send_current(&mut self, now: u64) -> Result<(), &'static str> {
+        match self.state {
+            State::Selecting => {
+                if !self.persistent && self.discovers_sent >= MAX_DISCOVER_ATTEMPTS {
+                    return Err("dhcp_client: no DHCP server responded");
+                }
+                let mut message = self.new_message(MessageType::Discover, now);
+                message.broadcast = true;
+                self.send(Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST, &message)?;
+                self.discovers_sent = self.discovers_sent.saturating_add(1);
+                self.schedule_retransmit(now);
+            }
+            State::Requesting { server, address } => {
+                if self.requests_sent >= MAX_REQUEST_ATTEMPTS {
+                    debug!("dhcp_client: server {} didn't acknowledge the offer of {}, starting over", server, address);
+                    return self.begin_exchange(State::Selecting, now);
+                }
+                let mut message = self.new_message(MessageType::Request, now);
+                message.broadcast = true;
+                message.requested_ip = Some(address);
+                message.server_identifier = Some(server);
+                self.send(Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST, &message)?;
+                self.requests_sent += 1;
+                self.schedule_retransmit(now);
+            }
+            State::Renewing | State::Rebinding => {
+                let lease = self.lease.clone().ok_or("dhcp_client: renewing without a lease")?;
+                let mut message = self.new_message(MessageType::Request, now);
+                message.client_ip = lease.address.address();
+                // Renewing is a unicast to the server that granted the lease, and rebinding is a broadcast to any server.
+                let (destination, deadline) = if self.state == State::Renewing {
+                    (lease.server, lease.rebinds_at_ms())
+                } else {
+                    (Ipv4Address::BROADCAST, lease.expires_at_ms())
+                };
+                self.send(lease.address.address(), destination, &message)?;
+                // Retransmit after half of the time remaining until the next deadline.
+                let remaining = deadline.map_or(0, |deadline| deadline.saturating_sub(now));
+                self.next_send_ms = now + max(remaining / 2, MIN_RENEW_RETRANSMIT_MS);
+            }
+            State::Bound => {}
+        }
+        Ok(())
+    }
+
+    fn schedule_retransmit(&mut self, now: u64) {
+        self.next_send_ms = now + self.retransmit_ms;
+        self.retransmit_ms = min(self.retransmit_ms * 2, MAX_RETRANSMIT_MS);
+    }
+
+    fn send(&self, source: Ipv4Address, destination: Ipv4Address, message: &Message) -> Result<(), &'static str> {
+        let socket = self.socket.ok_or("dhcp_client: the socket isn't open")?;
+        socket.try_send(&message::encapsulate(source, destination, &message.encode()))
+    }
+
+    /// Returns the next received reply that belongs to the current transaction, if any.
+    fn receive(&self) -> Result<Option<Message>, &'static str> {
+        let socket = match self.socket {
+            Some(socket) => socket,
+            None => return Ok(None),
+        };
+        let mut buffer = [0u8; RECEIVE_BUFFER_SIZE];
+        loop {
+            let len = match socket.try_recv(&mut buffer) {
+                Ok(len) => len,
+                Err(net_stack::WOULD_BLOCK) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            // The socket receives every UDP packet, so anything that isn't a reply to this client is skipped.
+            if let Some(Ok(message)) = message::decapsulate(&buffer[.. len]).map(Message::decode) {
+                if message.transaction_id == self.transaction_id && message.client_hardware_address == self.hardware_address {
+                    return Ok(Some(message));
+                }
+            }
+        }
+    }
+
+    fn handle_reply(&mut self, reply: Message, now: u64) -> Result<(), &'static str> {
+        match (self.state, reply.message_type) {
+            (State::Selecting, MessageType::Offer) => {
+                if let (Some(server), false) = (reply.server_identifier, reply.your_ip.is_unspecified()) {
+                    debug!("dhcp_client: server {} offered address {} on interface {}", server, reply.your_ip, self.index);
+                    // The REQUEST for an offer belongs to the same transaction as the DISCOVER.
+                    self.state = State::Requesting { server, address: reply.your_ip };
+                    self.requests_sent = 0;
+                    self.next_send_ms = now;
+                    self.retransmit_ms = INITIAL_RETRANSMIT_MS;
+                }
+            }
+            (State::Requesting { server, .. }, MessageType::Ack) => self.bind(reply, server, now),
+            (State::Renewing, MessageType::Ack) | (State::Rebinding, MessageType::Ack) => {
+                let server = self.lease.as_ref().map_or(Ipv4Address::UNSPECIFIED, |lease| lease.server);
+                self.bind(reply, server, now);
+            }
+            (State::Requesting { .. }, MessageType::Nak) => {
+                debug!("dhcp_client: the offer on interface {} was withdrawn, starting over", self.index);
+                self.begin_exchange(State::Selecting, now)?;
+            }
+            (State::Renewing, MessageType::Nak) | (State::Rebinding, MessageType::Nak) => {
+                warn!("dhcp_client: the lease on interface {} was revoked by the server, starting over", self.index);
+                if let Some(lease) = self.lease.take() {
+                    self.unapply(&lease);
+                }
+                set_status(self.index, Status::Configuring);
+                self.begin_exchange(State::Selecting, now)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Applies the lease granted by the given ACK to the interface and enters the bound state.
+    /// The `server` is used if the ACK doesn't identify the server that sent it.
+    fn bind(&mut self, ack: Message, server: Ipv4Address, now: u64) {
+        if ack.your_ip.is_unspecified() {
+            return;
+        }
+        let prefix_length = ack.subnet_mask.map_or_else(|| message::classful_prefix_length(ack.your_ip), message::prefix_length);
+        let lease_time = ack.lease_time.filter(|&secs| secs != u32::max_value());
+        let (renewal_time, rebinding_time) = match lease_time {
+            Some(secs) => {
+                // The default renewal and rebinding times are 50% and 87.5% of the lease time (RFC 2131 section 4.4.5).
+                let rebinding_time = ack.rebinding_time.filter(|&t| t <= secs).unwrap_or(secs / 8 * 7);
+                let renewal_time = ack.renewal_time.filter(|&t| t <= rebinding_time).unwrap_or(min(secs / 2, rebinding_time));
+                (renewal_time, rebinding_time)
+            }
+            None => (u32::max_value(), u32::max_value()),
+        };
+        let lease = Lease {
+            address: Ipv4Cidr::new(ack.your_ip, prefix_length),
+            gateway: ack.routers.first().cloned(),
+            dns_servers: ack.dns_servers,
+            server: ack.server_identifier.unwrap_or(server),
+            lease_time,
+            renewal_time,
+            rebinding_time,
+            granted_at_ms: now,
+        };
+
+        let old_lease = self.lease.take();
+        if let Some(ref old_lease) = old_lease {
+            self.unapply(old_lease);
+        } else {
+            info!("dhcp_client: configured interface {} with address {}, gateway {:?}, DNS servers {:?}",
+                self.index, lease.address, lease.gateway, lease.dns_servers);
+        }
+        self.apply(&lease);
+        set_status(self.index, Status::Bound(lease.clone()));
+        self.lease = Some(lease);
+        self.state = State::Bound;
+        self.discovers_sent = 0;
+        self.persistent = true;
+        // Since the socket receives every UDP packet, it's closed until the lease needs to be renewed.
+        self.close_socket();
+    }
+
+    /// Configures the interface with the address, default gateway, and DNS servers of the given lease.
+    fn apply(&self, lease: &Lease) {
+        {
+            let mut iface = self.iface.lock();
+            let mut addrs: Vec<IpCidr> = iface.ip_addrs().iter()
+                .filter(|addr| !is_ipv4(addr))
+                .cloned()
+                .collect();
+            addrs.insert(0, IpCidr::Ipv4(lease.address));
+            iface.set_ip_addrs(&addrs);
+            match lease.gateway {
+                Some(gateway) => if let Err(_e) = iface.routes_mut().add_default_ipv4_route(gateway) {
+                    error!("dhcp_client: couldn't set the default gateway of interface {}: {:?}", self.index, _e);
+                },
+                None => remove_default_ipv4_route(&mut *iface),
+            }
+        }
+        network_manager::add_dns_servers(&to_ip_addresses(&lease.dns_servers));
+    }
+
+    /// Removes the address, default gateway, and DNS servers of the given lease from the interface.
+    fn unapply(&self, lease: &Lease) {
+        {
+            let mut iface = self.iface.lock();
+            let mut addrs: Vec<IpCidr> = iface.ip_addrs().iter()
+                .filter(|&&addr| addr != IpCidr::Ipv4(lease.address))
+                .cloned()
+                .collect();
+            // An interface without an IPv4 address needs the unspecified address to obtain a new lease.
+            if !addrs.iter().any(is_ipv4) {
+                addrs.insert(0, IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0));
+            }
+            iface.set_ip_addrs(&addrs);
+            if lease.gateway.is_some() {
+                remove_default_ipv4_route(&mut *iface);
+            }
+        }
+        network_manager::remove_dns_servers(&to_ip_addresses(&lease.dns_servers));
+    }
+
+    /// Releases the lease, if any, and removes its configuration from the interface.
+    fn stop(&mut self) {
+        if let Some(lease) = self.lease.take() {
+            // Releasing is a courtesy to the server, so failures are ignored.
+            let _ = self.send_release(&lease);
+            self.unapply(&lease);
+            info!("dhcp_client: released address {} on interface {}", lease.address, self.index);
+        }
+        self.close_socket();
+    }
+
+    fn send_release(&mut self, lease: &Lease) -> Result<(), &'static str> {
+        let now = net_stack::now_millis()?;
+        if self.socket.is_none() {
+            self.socket = Some(raw::open(IpVersion::Ipv4, IpProtocol::Udp)?);
+        }
+        self.transaction_id = self.new_transaction_id();
+        self.exchange_started_ms = now;
+        let mut message = self.new_message(MessageType::Release, now);
+        message.client_ip = lease.address.address();
+        message.server_identifier = Some(lease.server);
+        self.send(lease.address.address(), lease.server, &message)?;
+        // Flush the message before the socket is closed, since closing it discards unsent packets.
+        net_stack::poll().map(|_| ())
+    }
+
+    fn close_socket(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            socket.close();
+        }
+    }
+}
+
+impl Future for Client {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        loop {
+            let woken = Pin::new(&mut self.wake.wait()).poll(cx).is_ready();
+            let timed_out = Pin::new(&mut self.sleep).poll(cx).is_ready();
+            if !woken && !timed_out {
+                return Poll::Pending;
+            }
+            if stop_requested(self.index) {
+                self.stop();
+                remove_client(self.index);
+                return Poll::Ready(());
+            }
+            match self.step() {
+                Ok(delay_ms) => self.sleep = async_runtime::sleep(delay_ms),
+                Err(e) => {
+                    error!("dhcp_client: failed to configure interface {}: {}", self.index, e);
+                    self.close_socket();
+                    set_status(self.index, Status::Failed(e));
+                    return Poll::Ready(());
+                }
+            }
+        }
+    }
+}
+
+
+fn is_ipv4(addr: &IpCidr) -> bool {
+    match *addr {
+        IpCidr::Ipv4(_) => true,
+        _ => false,
+    }
+}
+
+fn to_ip_addresses(addresses: &[Ipv4Address]) -> Vec<IpAddress> {
+    addresses.iter().map(|&address| IpAddress::Ipv4(address)).collect()
+}
+
+fn remove_default_ipv4_route(iface: &mut dyn NetworkInterface) {
+    let default_route = IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0);
+    iface.routes_mut().update(|routes| { routes.remove(&default_route); });
+}
@@ -0,0 +1,210 @@
+#![no_std]
+
+//! A DHCP client that configures the IPv4 address, default gateway, and DNS servers of network interfaces.
+//!
+//! Network interfaces are identified by their index in `network_manager::NETWORK_INTERFACES`.
+//! The client of an interface is started with [`start()`](fn.start.html), or with [`configure()`](fn.configure.html),
+//! which also waits until it has obtained a lease. Each client runs as an asynchronous task on its own executor task,
+//! which obtains a lease through a DISCOVER, OFFER, REQUEST and ACK exchange and then applies it to the interface.
+//! It renews the lease with the server that granted it once the renewal time (T1) has passed,
+//! and with any server once the rebinding time (T2) has passed.
+//! If the lease expires anyway, its configuration is removed from the interface and the client starts over.
+//!
+//! Messages are exchanged over a raw IPv4 socket of the `net_stack` crate, so the network stack must be initialized first.
+//!
+//! When Theseus is built with the `use_dhcp` config flag, e.g., `make run THESEUS_CONFIG=use_dhcp`,
+//! network interfaces are created without a static IP address, and [`init()`](fn.init.html) configures all of them at boot.
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate smoltcp;
+extern crate network_manager;
+extern crate net_stack;
+extern crate async_runtime;
+extern crate timer;
+extern crate wait_queue;
+
+mod message;
+mod client;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+use async_runtime::{Executor, InterruptEvent};
+use wait_queue::WaitQueue;
+use network_manager::NETWORK_INTERFACES;
+use client::Client;
+
+
+/// A lease of an IPv4 address and its network configuration, granted by a DHCP server.
+#[derive(Clone, Debug)]
+pub struct Lease {
+    /// The leased address, with the prefix length of its subnet.
+    pub address: Ipv4Cidr,
+    /// The default gateway, if the server provided any routers.
+    pub gateway: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    /// The server that granted the lease.
+    pub server: Ipv4Address,
+    /// The duration of the lease in seconds, or `None` if the lease never expires.
+    pub lease_time: Option<u32>,
+    /// The number of seconds after which the lease is renewed with the server that granted it (T1).
+    pub renewal_time: u32,
+    /// The number of seconds after which the lease is renewed with any server (T2).
+    pub rebinding_time: u32,
+    /// When the lease was granted, in milliseconds since the network stack was initialized (see `net_stack::now_millis()`).
+    pub granted_at_ms: u64,
+}
+
+impl Lease {
+    /// Returns when the lease expires, in milliseconds since the network stack was initialized,
+    /// or `None` if it never expires.
+    pub fn expires_at_ms(&self) -> Option<u64> {
+        self.lease_time.map(|secs| self.granted_at_ms + secs as u64 * 1000)
+    }
+
+    /// Returns when the lease is renewed with the server that granted it,
+    /// in milliseconds since the network stack was initialized, or `None` if it never expires.
+    pub fn renews_at_ms(&self) -> Option<u64> {
+        self.lease_time.map(|_| self.granted_at_ms + self.renewal_time as u64 * 1000)
+    }
+
+    /// Returns when the lease is renewed with any server,
+    /// in milliseconds since the network stack was initialized, or `None` if it never expires.
+    pub fn rebinds_at_ms(&self) -> Option<u64> {
+        self.lease_time.map(|_| self.granted_at_ms + self.rebinding_time as u64 * 1000)
+    }
+}
+
+/// The status of the DHCP client of a network interface.
+#[derive(Clone, Debug)]
+pub enum Status {
+    /// The client is trying to obtain a lease.
+    Configuring,
+    /// The interface is configured with the given lease, which the client renews before it expires.
+    Bound(Lease),
+    /// The client has stopped trying to obtain a lease, e.g., because no server responded.
+    Failed(&'static str),
+}
+
+
+/// The state of a running client that is shared with the rest of this crate.
+struct ClientEntry {
+    status: Status,
+    /// Whether the client has been asked to release its lease and stop.
+    stop_requested: bool,
+    /// Wakes up the client, e.g., so that it notices that it has been asked to stop.
+    wake: Arc<InterruptEvent>,
+}
+
+lazy_static! {
+    /// The clients that have been started, by the index of their network interface.
+    static ref CLIENTS: Mutex<BTreeMap<usize, ClientEntry>> = Mutex::new(BTreeMap::new());
+    /// The tasks that are waiting for a client's status to change, which are woken up whenever it does.
+    static ref STATUS_CHANGES: WaitQueue = WaitQueue::new();
+}
+
+
+/// Starts the DHCP client of every network interface except loopback interfaces, without waiting for them to obtain leases.
+///
+/// This is invoked at boot when Theseus is built with the `use_dhcp` config flag.
+pub fn init() -> Result<(), &'static str> {
+    let interfaces = NETWORK_INTERFACES.lock().clone();
+    for (index, iface) in interfaces.iter().enumerate() {
+        if !iface.lock().is_loopback() {
+            start(index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts the DHCP client of the network interface with the given index, without waiting for it to obtain a lease.
+///
+/// Does nothing if the client is already running; a client that has failed is started again.
+pub fn start(index: usize) -> Result<(), &'static str> {
+    let iface = NETWORK_INTERFACES.lock().get(index).cloned().ok_or("dhcp_client: no network interface with that index")?;
+    if iface.lock().is_loopback() {
+        return Err("dhcp_client: a loopback interface can't be configured via DHCP");
+    }
+    let wake = Arc::new(InterruptEvent::new());
+    {
+        let mut clients = CLIENTS.lock();
+        match clients.get(&index) {
+            Some(ClientEntry { status: Status::Failed(_), .. }) | None => {}
+            Some(_) => return Ok(()),
+        }
+        clients.insert(index, ClientEntry { status: Status::Configuring, stop_requested: false, wake: wake.clone() });
+    }
+    let executor = Executor::new();
+    executor.spawn(Client::new(index, iface, wake));
+    if let Err(e) = executor.spawn_executor_task(format!("dhcp_client_{}", index)) {
+        CLIENTS.lock().remove(&index);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Starts the DHCP client of the network interface with the given index, if it isn't already running,
+/// and waits until it has obtained a lease, which is returned.
+pub fn configure(index: usize) -> Result<Lease, &'static str> {
+    start(index)?;
+    STATUS_CHANGES.wait_until(&|| match CLIENTS.lock().get(&index).map(|entry| &entry.status) {
+        Some(Status::Bound(lease)) => Some(Ok(lease.clone())),
+        Some(Status::Failed(e)) => Some(Err(*e)),
+        Some(Status::Configuring) => None,
+        None => Some(Err("dhcp_client: the DHCP client was stopped")),
+    }).map_err(|_| "dhcp_client: failed to wait for the DHCP client")?
+}
+
+/// Stops the DHCP client of the network interface with the given index,
+/// and waits until it has released its lease and removed its configuration from the interface.
+pub fn release(index: usize) -> Result<(), &'static str> {
+    let wake = {
+        let mut clients = CLIENTS.lock();
+        let entry = clients.get_mut(&index).ok_or("dhcp_client: no DHCP client was started on that interface")?;
+        if let Status::Failed(_) = entry.status {
+            clients.remove(&index);
+            return Ok(());
+        }
+        entry.stop_requested = true;
+        entry.wake.clone()
+    };
+    wake.notify();
+    STATUS_CHANGES.wait_until(&|| if CLIENTS.lock().contains_key(&index) { None } else { Some(()) })
+        .map_err(|_| "dhcp_client: failed to wait for the DHCP client")
+}
+
+/// Returns the status of the DHCP client of the network interface with the given index, if it was started.
+pub fn status(index: usize) -> Option<Status> {
+    CLIENTS.lock().get(&index).map(|entry| entry.status.clone())
+}
+
+/// Returns the status of every DHCP client that was started, with the index of its network interface.
+pub fn statuses() -> Vec<(usize, Status)> {
+    CLIENTS.lock().iter().map(|(&index, entry)| (index, entry.status.clone())).collect()
+}
+
+
+/// Updates the status of the client of the given interface, and wakes up the tasks waiting for it to change.
+fn set_status(index: usize, status: Status) {
+    if let Some(entry) = CLIENTS.lock().get_mut(&index) {
+        entry.status = status;
+    }
+    // The clients must be unlocked here, since waiting tasks lock them while checking their status.
+    STATUS_CHANGES.notify_all();
+}
+
+/// Returns whether the client of the given interface has been asked to stop.
+fn stop_requested(index: usize) -> bool {
+    CLIENTS.lock().get(&index).map_or(true, |entry| entry.stop_requested)
+}
+
+/// Removes the client of the given interface once it has stopped.
+fn remove_client(index: usize) {
+    CLIENTS.lock().remove(&index);
+    STATUS_CHANGES.notify_all();
+}
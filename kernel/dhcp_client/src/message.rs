@@ -0,0 +1,333 @@
+//! Encoding and decoding of DHCP messages (RFC 2131 and RFC 2132),
+//! and of the IPv4 and UDP headers that carry them over a raw socket.
+
+use alloc::vec::Vec;
+use smoltcp::wire::{EthernetAddress, Ipv4Address};
+
+
+/// The UDP port that DHCP servers listen on.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port that DHCP clients listen on.
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_BOOTREQUEST: u8 = 1;
+const HARDWARE_TYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+/// The length of the fixed-size part of a message, which it inherited from BOOTP.
+const FIXED_LENGTH: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Some servers ignore messages that are shorter than a BOOTP message, so shorter messages are padded to this length.
+const MIN_MESSAGE_LENGTH: usize = 300;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_IDENTIFIER: u8 = 54;
+const OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_CLIENT_IDENTIFIER: u8 = 61;
+const OPTION_END: u8 = 255;
+
+/// The options that the client asks servers to include in their replies.
+const REQUESTED_PARAMETERS: [u8; 6] = [
+    OPTION_SUBNET_MASK, OPTION_ROUTER, OPTION_DNS_SERVER,
+    OPTION_LEASE_TIME, OPTION_RENEWAL_TIME, OPTION_REBINDING_TIME,
+];
+
+const IPV4_HEADER_LENGTH: usize = 20;
+const UDP_HEADER_LENGTH: usize = 8;
+const IP_PROTOCOL_UDP: u8 = 17;
+const DEFAULT_TTL: u8 = 64;
+
+
+/// The type of a DHCP message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<MessageType> {
+        match value {
+            1 => Some(MessageType::Discover),
+            2 => Some(MessageType::Offer),
+            3 => Some(MessageType::Request),
+            4 => Some(MessageType::Decline),
+            5 => Some(MessageType::Ack),
+            6 => Some(MessageType::Nak),
+            7 => Some(MessageType::Release),
+            8 => Some(MessageType::Inform),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            MessageType::Discover => 1,
+            MessageType::Offer => 2,
+            MessageType::Request => 3,
+            MessageType::Decline => 4,
+            MessageType::Ack => 5,
+            MessageType::Nak => 6,
+            MessageType::Release => 7,
+            MessageType::Inform => 8,
+        }
+    }
+}
+
+
+/// A DHCP message, with the fields and options that this client uses.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub message_type: MessageType,
+    pub transaction_id: u32,
+    /// The number of seconds since the client began acquiring or renewing its lease.
+    pub secs: u16,
+    /// Whether the server must broadcast its reply, because the client can't yet receive unicasts to its new address.
+    pub broadcast: bool,
+    pub client_hardware_address: EthernetAddress,
+    /// The client's current address (`ciaddr`), which is only set while it holds a lease.
+    pub client_ip: Ipv4Address,
+    /// The address offered or assigned to the client by the server (`yiaddr`).
+    pub your_ip: Ipv4Address,
+    pub requested_ip: Option<Ipv4Address>,
+    pub server_identifier: Option<Ipv4Address>,
+    pub subnet_mask: Option<Ipv4Address>,
+    pub routers: Vec<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    /// The lease time in seconds, where `u32::max_value()` means an infinite lease.
+    pub lease_time: Option<u32>,
+    /// The time in seconds after which the client starts renewing its lease (T1).
+    pub renewal_time: Option<u32>,
+    /// The time in seconds after which the client starts rebinding its lease with any server (T2).
+    pub rebinding_time: Option<u32>,
+}
+
+impl Message {
+    /// Creates a message of the given type from the client with the given hardware address, without any options.
+    pub fn new(message_type: MessageType, transaction_id: u32, client_hardware_address: EthernetAddress) -> Message {
+        Message {
+            message_type,
+            transaction_id,
+            secs: 0,
+            broadcast: false,
+            client_hardware_address,
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            requested_ip: None,
+            server_identifier: None,
+            subnet_mask: None,
+            routers: Vec::new(),
+            dns_servers: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        }
+    }
+
+    /// Encodes this message as a request from a client.
+    ///
+    /// Only the options that a client sends are encoded: the message type, the client identifier,
+    /// the requested IP address, the server identifier, and a parameter request list.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; FIXED_LENGTH];
+        bytes[0] = OP_BOOTREQUEST;
+        bytes[1] = HARDWARE_TYPE_ETHERNET;
+        bytes[2] = self.client_hardware_address.as_bytes().len() as u8;
+        write_u32(&mut bytes[4 .. 8], self.transaction_id);
+        write_u16(&mut bytes[8 .. 10], self.secs);
+        write_u16(&mut bytes[10 .. 12], if self.broadcast { FLAG_BROADCAST } else { 0 });
+        bytes[12 .. 16].copy_from_slice(self.client_ip.as_bytes());
+        bytes[16 .. 20].copy_from_slice(self.your_ip.as_bytes());
+        bytes[28 .. 34].copy_from_slice(self.client_hardware_address.as_bytes());
+        bytes.extend_from_slice(&MAGIC_COOKIE);
+
+        bytes.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, self.message_type.to_u8()]);
+        bytes.extend_from_slice(&[OPTION_CLIENT_IDENTIFIER, 7, HARDWARE_TYPE_ETHERNET]);
+        bytes.extend_from_slice(self.client_hardware_address.as_bytes());
+        if let Some(requested_ip) = self.requested_ip {
+            bytes.extend_from_slice(&[OPTION_REQUESTED_IP, 4]);
+            bytes.extend_from_slice(requested_ip.as_bytes());
+        }
+        if let Some(server_identifier) = self.server_identifier {
+            bytes.extend_from_slice(&[OPTION_SERVER_IDENTIFIER, 4]);
+            bytes.extend_from_slice(server_identifier.as_bytes());
+        }
+        if self.message_type == MessageType::Discover || self.message_type == MessageType::Request {
+            bytes.extend_from_slice(&[OPTION_PARAMETER_REQUEST_LIST, REQUESTED_PARAMETERS.len() as u8]);
+            bytes.extend_from_slice(&REQUESTED_PARAMETERS);
+        }
+        bytes.push(OPTION_END);
+        while bytes.len() < MIN_MESSAGE_LENGTH {
+            bytes.push(OPTION_PAD);
+        }
+        bytes
+    }
+
+    /// Decodes a message, e.g., a reply from a server.
+    pub fn decode(bytes: &[u8]) -> Result<Message, &'static str> {
+        if bytes.len() < FIXED_LENGTH + MAGIC_COOKIE.len() || bytes[FIXED_LENGTH .. FIXED_LENGTH + 4] != MAGIC_COOKIE {
+            return Err("dhcp_client: not a DHCP message");
+        }
+        if bytes[1] != HARDWARE_TYPE_ETHERNET || bytes[2] != 6 {
+            return Err("dhcp_client: DHCP message for a non-Ethernet client");
+        }
+        let mut message = Message::new(MessageType::Discover, read_u32(&bytes[4 .. 8]), EthernetAddress::from_bytes(&bytes[28 .. 34]));
+        message.secs = read_u16(&bytes[8 .. 10]);
+        message.broadcast = read_u16(&bytes[10 .. 12]) & FLAG_BROADCAST != 0;
+        message.client_ip = Ipv4Address::from_bytes(&bytes[12 .. 16]);
+        message.your_ip = Ipv4Address::from_bytes(&bytes[16 .. 20]);
+
+        let mut message_type = None;
+        let mut options = &bytes[FIXED_LENGTH + MAGIC_COOKIE.len() ..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPTION_PAD => { options = rest; continue; }
+                OPTION_END => break,
+                _ => {}
+            }
+            let (&length, rest) = rest.split_first().ok_or("dhcp_client: truncated DHCP option")?;
+            let length = length as usize;
+            if rest.len() < length {
+                return Err("dhcp_client: truncated DHCP option");
+            }
+            let (data, rest) = rest.split_at(length);
+            options = rest;
+            match code {
+                OPTION_MESSAGE_TYPE if length == 1 => message_type = MessageType::from_u8(data[0]),
+                OPTION_SUBNET_MASK if length == 4 => message.subnet_mask = Some(Ipv4Address::from_bytes(data)),
+                OPTION_ROUTER => message.routers = read_addresses(data),
+                OPTION_DNS_SERVER => message.dns_servers = read_addresses(data),
+                OPTION_REQUESTED_IP if length == 4 => message.requested_ip = Some(Ipv4Address::from_bytes(data)),
+                OPTION_SERVER_IDENTIFIER if length == 4 => message.server_identifier = Some(Ipv4Address::from_bytes(data)),
+                OPTION_LEASE_TIME if length == 4 => message.lease_time = Some(read_u32(data)),
+                OPTION_RENEWAL_TIME if length == 4 => message.renewal_time = Some(read_u32(data)),
+                OPTION_REBINDING_TIME if length == 4 => message.rebinding_time = Some(read_u32(data)),
+                _ => {}
+            }
+        }
+        message.message_type = message_type.ok_or("dhcp_client: DHCP message without a valid message type")?;
+        Ok(message)
+    }
+}
+
+
+/// Wraps the given DHCP message into a UDP datagram from the client port to the server port, within an IPv4 packet,
+/// which can be sent on a raw IPv4 socket. The IPv4 header checksum is left for the network stack to fill in.
+pub fn encapsulate(src_addr: Ipv4Address, dst_addr: Ipv4Address, message: &[u8]) -> Vec<u8> {
+    let udp_length = UDP_HEADER_LENGTH + message.len();
+    let total_length = IPV4_HEADER_LENGTH + udp_length;
+    let mut packet = vec![0u8; IPV4_HEADER_LENGTH + UDP_HEADER_LENGTH];
+
+    let ip_header = &mut packet[.. IPV4_HEADER_LENGTH];
+    ip_header[0] = 0x45; // version 4, header length of 5 words
+    write_u16(&mut ip_header[2 .. 4], total_length as u16);
+    write_u16(&mut ip_header[6 .. 8], 0x4000); // don't fragment
+    ip_header[8] = DEFAULT_TTL;
+    ip_header[9] = IP_PROTOCOL_UDP;
+    ip_header[12 .. 16].copy_from_slice(src_addr.as_bytes());
+    ip_header[16 .. 20].copy_from_slice(dst_addr.as_bytes());
+
+    let udp_header = &mut packet[IPV4_HEADER_LENGTH ..];
+    write_u16(&mut udp_header[0 .. 2], CLIENT_PORT);
+    write_u16(&mut udp_header[2 .. 4], SERVER_PORT);
+    write_u16(&mut udp_header[4 .. 6], udp_length as u16);
+    packet.extend_from_slice(message);
+
+    let checksum = udp_checksum(src_addr, dst_addr, &packet[IPV4_HEADER_LENGTH ..]);
+    write_u16(&mut packet[IPV4_HEADER_LENGTH + 6 .. IPV4_HEADER_LENGTH + 8], checksum);
+    packet
+}
+
+/// Returns the payload of the given IPv4 packet if it's a UDP datagram from the server port to the client port,
+/// i.e., a DHCP reply, or `None` otherwise.
+pub fn decapsulate(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < IPV4_HEADER_LENGTH || packet[0] >> 4 != 4 || packet[9] != IP_PROTOCOL_UDP {
+        return None;
+    }
+    let header_length = ((packet[0] & 0x0F) as usize) * 4;
+    let total_length = read_u16(&packet[2 .. 4]) as usize;
+    if header_length < IPV4_HEADER_LENGTH || total_length > packet.len() || total_length < header_length + UDP_HEADER_LENGTH {
+        return None;
+    }
+    let datagram = &packet[header_length .. total_length];
+    let udp_length = read_u16(&datagram[4 .. 6]) as usize;
+    if read_u16(&datagram[0 .. 2]) != SERVER_PORT || read_u16(&datagram[2 .. 4]) != CLIENT_PORT
+        || udp_length < UDP_HEADER_LENGTH || udp_length > datagram.len()
+    {
+        return None;
+    }
+    Some(&datagram[UDP_HEADER_LENGTH .. udp_length])
+}
+
+/// Computes the checksum of the given UDP datagram, whose checksum field must be zero,
+/// including the IPv4 pseudo-header.
+fn udp_checksum(src_addr: Ipv4Address, dst_addr: Ipv4Address, datagram: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut add_words = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let word = if chunk.len() == 2 { read_u16(chunk) } else { (chunk[0] as u16) << 8 };
+            sum += word as u32;
+        }
+    };
+    add_words(src_addr.as_bytes());
+    add_words(dst_addr.as_bytes());
+    add_words(&[0, IP_PROTOCOL_UDP]);
+    add_words(&(datagram.len() as u16).to_be_bytes());
+    add_words(datagram);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    match !(sum as u16) {
+        // A computed checksum of zero is sent as all ones, since zero means that there is no checksum.
+        0 => 0xFFFF,
+        checksum => checksum,
+    }
+}
+
+/// Returns the length of the network prefix of the given subnet mask, e.g., 24 for `255.255.255.0`.
+pub fn prefix_length(subnet_mask: Ipv4Address) -> u8 {
+    read_u32(subnet_mask.as_bytes()).leading_ones() as u8
+}
+
+/// Returns the prefix length of the classful network that the given address belongs to,
+/// which RFC 2131 says to use when a server doesn't provide a subnet mask.
+pub fn classful_prefix_length(address: Ipv4Address) -> u8 {
+    match address.as_bytes()[0] {
+        0 ..= 127 => 8,
+        128 ..= 191 => 16,
+        _ => 24,
+    }
+}
+
+fn read_addresses(data: &[u8]) -> Vec<Ipv4Address> {
+    data.chunks_exact(4).map(Ipv4Address::from_bytes).collect()
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn write_u16(bytes: &mut [u8], value: u16) {
+    bytes.copy_from_slice(&value.to_be_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], value: u32) {
+    bytes.copy_from_slice(&value.to_be_bytes());
+}
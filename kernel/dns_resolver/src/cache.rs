@@ -0,0 +1,94 @@
+//! The cache of resolved names, whose entries expire after the TTL of the records they were resolved from.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use smoltcp::wire::IpAddress;
+
+
+/// The most names that are cached at once.
+pub const MAX_CACHE_ENTRIES: usize = 256;
+/// The longest time that a name is cached for, in seconds, regardless of its TTL.
+pub const MAX_TTL_SECS: u32 = 24 * 60 * 60;
+/// How long the absence of a name's addresses is cached for, in seconds,
+/// if the server didn't specify it with an SOA record.
+pub const DEFAULT_NEGATIVE_TTL_SECS: u32 = 60;
+
+
+/// A cached name, see [`cache_entries()`](../fn.cache_entries.html).
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub name: String,
+    /// The addresses of the name, which are empty if the name was found to have none.
+    pub addresses: Vec<IpAddress>,
+    /// The number of milliseconds until the entry expires.
+    pub expires_in_ms: u64,
+}
+
+struct Entry {
+    addresses: Vec<IpAddress>,
+    /// When the entry expires, in milliseconds since the network stack was initialized.
+    expires_at_ms: u64,
+}
+
+pub(crate) struct Cache {
+    /// The entries by their lowercase name.
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Cache {
+    pub(crate) fn new() -> Cache {
+        Cache { entries: BTreeMap::new() }
+    }
+
+    /// Returns the cached addresses of the given lowercase `name`, removing its entry if it has expired.
+    pub(crate) fn get(&mut self, name: &str, now_ms: u64) -> Option<Vec<IpAddress>> {
+        let expired = match self.entries.get(name) {
+            Some(entry) if entry.expires_at_ms > now_ms => return Some(entry.addresses.clone()),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            self.entries.remove(name);
+        }
+        None
+    }
+
+    /// Caches the addresses of the given lowercase `name` for `ttl_secs` seconds, capped at `MAX_TTL_SECS`.
+    ///
+    /// If the cache is full, expired entries are removed first, and then the entry that expires soonest.
+    pub(crate) fn insert(&mut self, name: String, addresses: Vec<IpAddress>, ttl_secs: u32, now_ms: u64) {
+        if ttl_secs == 0 {
+            return;
+        }
+        if self.entries.len() >= MAX_CACHE_ENTRIES && !self.entries.contains_key(&name) {
+            self.entries.retain(|_, entry| entry.expires_at_ms > now_ms);
+            if self.entries.len() >= MAX_CACHE_ENTRIES {
+                let soonest = self.entries.iter()
+                    .min_by_key(|&(_, entry)| entry.expires_at_ms)
+                    .map(|(name, _)| name.clone());
+                if let Some(soonest) = soonest {
+                    self.entries.remove(&soonest);
+                }
+            }
+        }
+        let expires_at_ms = now_ms + ttl_secs.min(MAX_TTL_SECS) as u64 * 1000;
+        self.entries.insert(name, Entry { addresses, expires_at_ms });
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the entries that haven't expired.
+    pub(crate) fn entries(&self, now_ms: u64) -> Vec<CacheEntry> {
+        self.entries.iter()
+            .filter(|&(_, entry)| entry.expires_at_ms > now_ms)
+            .map(|(name, entry)| CacheEntry {
+                name: name.clone(),
+                addresses: entry.addresses.clone(),
+                expires_in_ms: entry.expires_at_ms - now_ms,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,195 @@
+#![no_std]
+
+//! A DNS resolver that looks up the IP addresses of host names.
+//!
+//! Names are resolved by querying the nameservers in `network_manager::dns_servers()` in turn,
+//! which are either configured statically or obtained by DHCP. Queries are sent over UDP,
+//! and repeated over TCP if the response didn't fit into a UDP datagram.
+//! A name's IPv4 addresses are looked up first, and its IPv6 addresses only if it has no IPv4 addresses.
+//!
+//! Resolved names are cached until the TTL of the records they were resolved from has passed,
+//! and names that don't exist are cached for the negative TTL given by their zone.
+//!
+//! Names can be resolved asynchronously with [`resolve_async()`](fn.resolve_async.html),
+//! or by blocking the current task with [`resolve()`](fn.resolve.html).
+//! [`resolve_endpoints()`](fn.resolve_endpoints.html) resolves a `"host:port"` string into endpoints
+//! that can be connected to, e.g., with `net_stack::tcp::connect()`.
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate smoltcp;
+extern crate network_manager;
+extern crate net_stack;
+extern crate async_runtime;
+extern crate timer;
+
+mod message;
+mod cache;
+mod query;
+
+pub use cache::{CacheEntry, MAX_CACHE_ENTRIES, MAX_TTL_SECS, DEFAULT_NEGATIVE_TTL_SECS};
+
+use core::future::Future;
+use core::pin::Pin;
+use core::str::FromStr;
+use core::task::{Context, Poll};
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+use async_runtime::Executor;
+use cache::Cache;
+use message::{RecordType, ResponseCode};
+use query::Query;
+
+
+/// The error returned when a name doesn't exist or has no addresses.
+pub const HOST_NOT_FOUND: &'static str = "dns_resolver: host not found";
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::new());
+}
+
+
+/// Resolves the given host name into its IP addresses, blocking the current task until it has been resolved.
+///
+/// A name that is an IP address literal is returned as is.
+pub fn resolve(name: &str) -> Result<Vec<IpAddress>, &'static str> {
+    Executor::new().block_on(resolve_async(name))?
+}
+
+/// Returns a future that resolves the given host name into its IP addresses.
+///
+/// A name that is an IP address literal is returned as is.
+pub fn resolve_async(name: &str) -> Resolve {
+    Resolve {
+        name: name.trim_end_matches('.').to_ascii_lowercase(),
+        state: ResolveState::Start,
+    }
+}
+
+/// Resolves the given `"host:port"` address into the endpoints that it refers to,
+/// blocking the current task until the host has been resolved.
+///
+/// The host can be a name or an IP address literal; IPv6 literals must be enclosed in brackets, e.g., `"[::1]:80"`.
+pub fn resolve_endpoints(address: &str) -> Result<Vec<IpEndpoint>, &'static str> {
+    let separator = address.rfind(':').ok_or("dns_resolver: the address has no port")?;
+    let host = &address[.. separator];
+    let port = u16::from_str(&address[separator + 1 ..]).map_err(|_| "dns_resolver: invalid port")?;
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1 .. host.len() - 1]
+    } else if host.contains(':') {
+        return Err("dns_resolver: IPv6 addresses must be enclosed in brackets");
+    } else {
+        host
+    };
+    Ok(resolve(host)?.into_iter().map(|address| IpEndpoint::new(address, port)).collect())
+}
+
+/// Removes all names from the cache.
+pub fn flush_cache() {
+    CACHE.lock().clear();
+}
+
+/// Returns the names in the cache that haven't expired.
+pub fn cache_entries() -> Vec<CacheEntry> {
+    match net_stack::now_millis() {
+        Ok(now_ms) => CACHE.lock().entries(now_ms),
+        Err(_) => Vec::new(),
+    }
+}
+
+
+enum ResolveState {
+    /// The cache hasn't been checked yet.
+    Start,
+    /// Querying the nameservers for the records of the given type.
+    Querying(RecordType, Query),
+    Done,
+}
+
+/// A future that resolves a host name into its IP addresses, see [`resolve_async()`](fn.resolve_async.html).
+pub struct Resolve {
+    /// The lowercase name.
+    name: String,
+    state: ResolveState,
+}
+
+impl Resolve {
+    /// Checks the cache, and otherwise starts querying the nameservers, returning the addresses if the name needn't be queried.
+    fn start(&mut self) -> Result<Option<Vec<IpAddress>>, &'static str> {
+        if let Ok(address) = IpAddress::from_str(&self.name) {
+            return Ok(Some(vec![address]));
+        }
+        let now_ms = net_stack::now_millis()?;
+        if let Some(addresses) = CACHE.lock().get(&self.name, now_ms) {
+            return if addresses.is_empty() { Err(HOST_NOT_FOUND) } else { Ok(Some(addresses)) };
+        }
+        let servers = network_manager::dns_servers();
+        if servers.is_empty() {
+            return Err("dns_resolver: no nameservers are configured");
+        }
+        self.state = ResolveState::Querying(RecordType::A, Query::new(&self.name, RecordType::A, servers)?);
+        Ok(None)
+    }
+}
+
+impl Future for Resolve {
+    type Output = Result<Vec<IpAddress>, &'static str>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            let (record_type, response) = match this.state {
+                ResolveState::Start => match this.start() {
+                    Ok(Some(addresses)) => {
+                        this.state = ResolveState::Done;
+                        return Poll::Ready(Ok(addresses));
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        this.state = ResolveState::Done;
+                        return Poll::Ready(Err(e));
+                    }
+                },
+                ResolveState::Querying(record_type, ref mut query) => match Pin::new(query).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(response) => (record_type, response),
+                },
+                ResolveState::Done => return Poll::Ready(Err("dns_resolver: the name has already been resolved")),
+            };
+            this.state = ResolveState::Done;
+            let response = match response {
+                Ok(response) => response,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            let now_ms = match net_stack::now_millis() {
+                Ok(now_ms) => now_ms,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+
+            let (addresses, ttl) = if response.code == ResponseCode::NameError {
+                (Vec::new(), 0)
+            } else {
+                response.addresses(&this.name, record_type)
+            };
+            if !addresses.is_empty() {
+                CACHE.lock().insert(this.name.clone(), addresses.clone(), ttl, now_ms);
+                return Poll::Ready(Ok(addresses));
+            }
+            // The name exists but has no IPv4 addresses, so it may have IPv6 addresses instead.
+            if response.code != ResponseCode::NameError && record_type == RecordType::A {
+                let servers = network_manager::dns_servers();
+                match Query::new(&this.name, RecordType::Aaaa, servers) {
+                    Ok(query) => this.state = ResolveState::Querying(RecordType::Aaaa, query),
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+                continue;
+            }
+            let negative_ttl = response.negative_ttl().unwrap_or(DEFAULT_NEGATIVE_TTL_SECS);
+            CACHE.lock().insert(this.name.clone(), Vec::new(), negative_ttl, now_ms);
+            return Poll::Ready(Err(HOST_NOT_FOUND));
+        }
+    }
+}
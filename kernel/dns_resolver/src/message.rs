@@ -0,0 +1,309 @@
+//! Encoding of DNS queries and decoding of DNS responses (RFC 1035), for the record types that the resolver uses.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+
+
+/// The port that DNS servers listen on, over both UDP and TCP.
+pub const DNS_PORT: u16 = 53;
+/// The largest message that is sent over UDP, since the resolver doesn't use EDNS (RFC 1035 section 4.2.1).
+pub const MAX_UDP_MESSAGE_SIZE: usize = 512;
+
+const HEADER_LENGTH: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RESPONSE_CODE_MASK: u16 = 0x000F;
+const CLASS_IN: u16 = 1;
+/// The longest label in a name.
+const MAX_LABEL_LENGTH: usize = 63;
+/// The longest name, in its encoded form.
+const MAX_NAME_LENGTH: usize = 255;
+/// The most compression pointers followed while reading a name, which prevents loops.
+const MAX_POINTERS: usize = 32;
+/// The most CNAME records followed while looking up the addresses of a name.
+const MAX_CNAME_CHAIN: usize = 8;
+
+
+/// The type of a resource record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordType {
+    /// An IPv4 address.
+    A,
+    /// An IPv6 address.
+    Aaaa,
+    /// The canonical name of an alias.
+    Cname,
+    /// The start of a zone of authority, whose minimum TTL is the TTL of negative responses (RFC 2308).
+    Soa,
+    Other(u16),
+}
+
+impl RecordType {
+    fn from_u16(value: u16) -> RecordType {
+        match value {
+            1 => RecordType::A,
+            5 => RecordType::Cname,
+            6 => RecordType::Soa,
+            28 => RecordType::Aaaa,
+            other => RecordType::Other(other),
+        }
+    }
+
+    fn to_u16(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Aaaa => 28,
+            RecordType::Other(other) => other,
+        }
+    }
+}
+
+/// The response code of a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseCode {
+    NoError,
+    FormatError,
+    ServerFailure,
+    /// The queried name doesn't exist (NXDOMAIN).
+    NameError,
+    NotImplemented,
+    Refused,
+    Other(u8),
+}
+
+impl ResponseCode {
+    fn from_u8(value: u8) -> ResponseCode {
+        match value {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormatError,
+            2 => ResponseCode::ServerFailure,
+            3 => ResponseCode::NameError,
+            4 => ResponseCode::NotImplemented,
+            5 => ResponseCode::Refused,
+            other => ResponseCode::Other(other),
+        }
+    }
+}
+
+/// The data of a resource record, for the record types that the resolver uses.
+#[derive(Clone, Debug)]
+pub enum RecordData {
+    Address(IpAddress),
+    Cname(String),
+    Soa { minimum: u32 },
+    Other,
+}
+
+/// A resource record in a response.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub name: String,
+    pub record_type: RecordType,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+/// A response from a DNS server.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub id: u16,
+    /// Whether the response didn't fit into a UDP datagram, such that the query must be repeated over TCP.
+    pub truncated: bool,
+    pub code: ResponseCode,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+}
+
+
+/// Encodes a recursive query for the records of the given type with the given `name`.
+pub fn encode_query(id: u16, name: &str, record_type: RecordType) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH + name.len() + 6);
+    push_u16(&mut bytes, id);
+    push_u16(&mut bytes, FLAG_RECURSION_DESIRED);
+    push_u16(&mut bytes, 1); // one question
+    push_u16(&mut bytes, 0);
+    push_u16(&mut bytes, 0);
+    push_u16(&mut bytes, 0);
+
+    let name_start = bytes.len();
+    let name = name.trim_end_matches('.');
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+                return Err("dns_resolver: invalid host name");
+            }
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+    }
+    bytes.push(0);
+    if bytes.len() - name_start > MAX_NAME_LENGTH {
+        return Err("dns_resolver: host name is too long");
+    }
+    push_u16(&mut bytes, record_type.to_u16());
+    push_u16(&mut bytes, CLASS_IN);
+    Ok(bytes)
+}
+
+impl Response {
+    /// Decodes a response.
+    pub fn decode(bytes: &[u8]) -> Result<Response, &'static str> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err("dns_resolver: truncated DNS message");
+        }
+        let flags = read_u16(bytes, 2)?;
+        if flags & FLAG_RESPONSE == 0 {
+            return Err("dns_resolver: DNS message is not a response");
+        }
+        let num_questions = read_u16(bytes, 4)?;
+        let num_answers = read_u16(bytes, 6)?;
+        let num_authorities = read_u16(bytes, 8)?;
+
+        let mut offset = HEADER_LENGTH;
+        for _ in 0 .. num_questions {
+            let (_name, next) = read_name(bytes, offset)?;
+            offset = next + 4; // the type and class
+        }
+        let mut answers = Vec::new();
+        for _ in 0 .. num_answers {
+            let (record, next) = read_record(bytes, offset)?;
+            answers.push(record);
+            offset = next;
+        }
+        let mut authorities = Vec::new();
+        for _ in 0 .. num_authorities {
+            let (record, next) = read_record(bytes, offset)?;
+            authorities.push(record);
+            offset = next;
+        }
+
+        Ok(Response {
+            id: read_u16(bytes, 0)?,
+            truncated: flags & FLAG_TRUNCATED != 0,
+            code: ResponseCode::from_u8((flags & RESPONSE_CODE_MASK) as u8),
+            answers,
+            authorities,
+        })
+    }
+
+    /// Returns the addresses of the given type that the answers give for the given `name`, following CNAME records,
+    /// and the smallest TTL of the records that were used.
+    pub fn addresses(&self, name: &str, record_type: RecordType) -> (Vec<IpAddress>, u32) {
+        let mut current = name.trim_end_matches('.');
+        let mut ttl = u32::max_value();
+        for _ in 0 .. MAX_CNAME_CHAIN {
+            let mut addresses = Vec::new();
+            for record in self.answers.iter().filter(|record| record.record_type == record_type && names_equal(&record.name, current)) {
+                if let RecordData::Address(address) = record.data {
+                    addresses.push(address);
+                    ttl = ttl.min(record.ttl);
+                }
+            }
+            if !addresses.is_empty() {
+                return (addresses, ttl);
+            }
+            let alias = self.answers.iter().find(|record| record.record_type == RecordType::Cname && names_equal(&record.name, current));
+            match alias {
+                Some(Record { data: RecordData::Cname(ref canonical_name), ttl: alias_ttl, .. }) => {
+                    current = canonical_name.trim_end_matches('.');
+                    ttl = ttl.min(*alias_ttl);
+                }
+                _ => break,
+            }
+        }
+        (Vec::new(), 0)
+    }
+
+    /// Returns how long, in seconds, the absence of the queried records may be cached,
+    /// which is given by the SOA record in the authority section (RFC 2308 section 5).
+    pub fn negative_ttl(&self) -> Option<u32> {
+        self.authorities.iter().filter_map(|record| match record.data {
+            RecordData::Soa { minimum } => Some(record.ttl.min(minimum)),
+            _ => None,
+        }).next()
+    }
+}
+
+/// Returns whether the two names are equal, which ignores the case of ASCII letters and a trailing dot.
+fn names_equal(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// Reads the resource record at the given `offset`, and returns it with the offset of the data after it.
+fn read_record(bytes: &[u8], offset: usize) -> Result<(Record, usize), &'static str> {
+    let (name, offset) = read_name(bytes, offset)?;
+    let record_type = RecordType::from_u16(read_u16(bytes, offset)?);
+    let ttl = read_u32(bytes, offset + 4)?;
+    let data_length = read_u16(bytes, offset + 8)? as usize;
+    let data_start = offset + 10;
+    let data_end = data_start + data_length;
+    let data = bytes.get(data_start .. data_end).ok_or("dns_resolver: truncated DNS record")?;
+    let data = match record_type {
+        RecordType::A if data_length == 4 => RecordData::Address(IpAddress::Ipv4(Ipv4Address::from_bytes(data))),
+        RecordType::Aaaa if data_length == 16 => RecordData::Address(IpAddress::Ipv6(Ipv6Address::from_bytes(data))),
+        RecordType::Cname => RecordData::Cname(read_name(bytes, data_start)?.0),
+        RecordType::Soa => {
+            // The primary name server and the responsible mailbox precede five 32-bit values, the last of which is the minimum TTL.
+            let (_primary, next) = read_name(bytes, data_start)?;
+            let (_mailbox, next) = read_name(bytes, next)?;
+            RecordData::Soa { minimum: read_u32(bytes, next + 16)? }
+        }
+        _ => RecordData::Other,
+    };
+    Ok((Record { name, record_type, ttl, data }, data_end))
+}
+
+/// Reads the possibly-compressed name at the given `offset`,
+/// and returns it with the offset of the data after it.
+fn read_name(bytes: &[u8], mut offset: usize) -> Result<(String, usize), &'static str> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *bytes.get(offset).ok_or("dns_resolver: truncated DNS name")? as usize;
+        if length & 0xC0 == 0xC0 {
+            // A pointer to the rest of the name elsewhere in the message.
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return Err("dns_resolver: DNS name has too many compression pointers");
+            }
+            let target = (read_u16(bytes, offset)? & 0x3FFF) as usize;
+            if end.is_none() {
+                end = Some(offset + 2);
+            }
+            offset = target;
+        } else if length == 0 {
+            return Ok((name, end.unwrap_or(offset + 1)));
+        } else {
+            let label = bytes.get(offset + 1 .. offset + 1 + length).ok_or("dns_resolver: truncated DNS name")?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.extend(label.iter().map(|&byte| byte as char));
+            if name.len() > MAX_NAME_LENGTH {
+                return Err("dns_resolver: DNS name is too long");
+            }
+            offset += 1 + length;
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, &'static str> {
+    bytes.get(offset .. offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or("dns_resolver: truncated DNS message")
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, &'static str> {
+    bytes.get(offset .. offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or("dns_resolver: truncated DNS message")
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
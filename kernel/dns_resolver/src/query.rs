@@ -0,0 +1,298 @@
+//! A single DNS query, which is sent to each configured nameserver in turn until one of them answers it.
+
+use core::future::Future;
+use core::mem;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU16, Ordering};
+use core::task::{Context, Poll};
+use alloc::vec::Vec;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+use net_stack::{NextPoll, WOULD_BLOCK};
+use net_stack::udp::{self, UdpHandle};
+use net_stack::tcp::{self, TcpHandle, TcpOptions};
+use message::{self, RecordType, Response, ResponseCode, DNS_PORT, MAX_UDP_MESSAGE_SIZE};
+
+
+/// How long to wait for a response over UDP before the query is retransmitted.
+const UDP_TIMEOUT_MS: u64 = 2000;
+/// The number of times a query is sent to a nameserver over UDP before moving on to the next one.
+const UDP_ATTEMPTS: u32 = 2;
+/// How long to wait for a complete response over TCP, including establishing the connection.
+const TCP_TIMEOUT_MS: u64 = 5000;
+/// The size of the receive buffer of the TCP connection, which holds any response that it's read from as it arrives.
+const TCP_RX_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The ID of the next query, which is mixed with the current time so that IDs are harder to guess.
+static NEXT_QUERY_ID: AtomicU16 = AtomicU16::new(1);
+
+fn new_query_id() -> u16 {
+    let counter = NEXT_QUERY_ID.fetch_add(1, Ordering::Relaxed);
+    counter.wrapping_mul(0x9E37) ^ (timer::Instant::now().as_nanos() as u16)
+}
+
+
+/// How a query is currently being sent to a nameserver.
+enum Transport {
+    /// Nothing has been sent to the current nameserver yet.
+    Idle,
+    Udp(UdpHandle),
+    /// The response over UDP was truncated, so the query is repeated over a TCP connection,
+    /// where messages are preceded by their length (RFC 1035 section 4.2.2).
+    Tcp {
+        connection: TcpHandle,
+        /// The number of bytes of the length-prefixed query that have been sent.
+        sent: usize,
+        /// The bytes of the length-prefixed response that have been received.
+        received: Vec<u8>,
+    },
+}
+
+/// What became of the query to the current nameserver after a step.
+enum Outcome {
+    /// The response hasn't been received yet.
+    Wait,
+    Done(Response),
+    /// The response didn't fit into a UDP datagram.
+    Truncated,
+    /// The nameserver failed to answer, so the next one is tried.
+    Failed(&'static str),
+}
+
+/// A future that sends a query to each of the given nameservers in turn until one of them answers it,
+/// and resolves to the response.
+pub(crate) struct Query {
+    id: u16,
+    /// The encoded query.
+    request: Vec<u8>,
+    servers: Vec<IpAddress>,
+    /// The index of the nameserver that is being queried.
+    server_index: usize,
+    transport: Transport,
+    /// The number of times the query has been sent to the current nameserver over UDP.
+    attempts: u32,
+    /// When the query is retransmitted over UDP, or when the TCP connection is given up on.
+    deadline_ms: u64,
+    /// Why the previous nameserver failed, which is returned if all of them fail.
+    error: &'static str,
+    waiting: Option<NextPoll>,
+}
+
+impl Query {
+    pub(crate) fn new(name: &str, record_type: RecordType, servers: Vec<IpAddress>) -> Result<Query, &'static str> {
+        let id = new_query_id();
+        Ok(Query {
+            id,
+            request: message::encode_query(id, name, record_type)?,
+            servers,
+            server_index: 0,
+            transport: Transport::Idle,
+            attempts: 0,
+            deadline_ms: 0,
+            error: "dns_resolver: no nameservers are configured",
+            waiting: None,
+        })
+    }
+
+    fn server(&self) -> IpEndpoint {
+        IpEndpoint::new(self.servers[self.server_index], DNS_PORT)
+    }
+
+    /// Advances the query, returning the response once a nameserver has answered,
+    /// or `None` if the socket must be checked again after the next poll of the network stack.
+    fn step(&mut self, now_ms: u64) -> Result<Option<Response>, &'static str> {
+        loop {
+            let outcome = match self.transport {
+                Transport::Idle => {
+                    if self.server_index >= self.servers.len() {
+                        return Err(self.error);
+                    }
+                    let socket = udp::bind(0)?;
+                    self.transport = Transport::Udp(socket);
+                    self.attempts = 0;
+                    self.send_udp(socket, now_ms)
+                }
+                Transport::Udp(socket) => self.step_udp(socket, now_ms),
+                Transport::Tcp { .. } => self.step_tcp(now_ms),
+            };
+            match outcome {
+                Outcome::Wait => return Ok(None),
+                Outcome::Done(response) => {
+                    self.close();
+                    return Ok(Some(response));
+                }
+                Outcome::Truncated => {
+                    self.close();
+                    let options = TcpOptions {
+                        rx_buffer_size: Some(TCP_RX_BUFFER_SIZE),
+                        tx_buffer_size: Some(tcp::MIN_BUFFER_SIZE),
+                        ..Default::default()
+                    };
+                    match tcp::start_connect(self.server(), &options) {
+                        Ok(connection) => {
+                            self.transport = Transport::Tcp { connection, sent: 0, received: Vec::new() };
+                            self.deadline_ms = now_ms + TCP_TIMEOUT_MS;
+                        }
+                        Err(e) => self.fail(e),
+                    }
+                }
+                Outcome::Failed(e) => self.fail(e),
+            }
+        }
+    }
+
+    /// Gives up on the current nameserver, so that the next one is queried.
+    fn fail(&mut self, error: &'static str) {
+        debug!("dns_resolver: nameserver {} failed: {}", self.servers[self.server_index], error);
+        self.close();
+        self.error = error;
+        self.server_index += 1;
+    }
+
+    fn send_udp(&mut self, socket: UdpHandle, now_ms: u64) -> Outcome {
+        match socket.try_send_to(&self.request, self.server()) {
+            Ok(()) | Err(WOULD_BLOCK) => {
+                // A query that couldn't be queued is simply sent again once the deadline passes.
+                self.attempts += 1;
+                self.deadline_ms = now_ms + UDP_TIMEOUT_MS;
+                Outcome::Wait
+            }
+            Err(e) => Outcome::Failed(e),
+        }
+    }
+
+    fn step_udp(&mut self, socket: UdpHandle, now_ms: u64) -> Outcome {
+        let server = self.server();
+        let mut buffer = [0; MAX_UDP_MESSAGE_SIZE];
+        loop {
+            match socket.try_recv_from(&mut buffer) {
+                Ok((length, sender)) => {
+                    if sender != server {
+                        continue;
+                    }
+                    match Response::decode(&buffer[.. length]) {
+                        Ok(ref response) if response.id == self.id && response.truncated => return Outcome::Truncated,
+                        Ok(response) => if response.id == self.id { return check(response); },
+                        Err(e) => debug!("dns_resolver: ignoring a malformed response: {}", e),
+                    }
+                }
+                Err(WOULD_BLOCK) => break,
+                Err(e) => return Outcome::Failed(e),
+            }
+        }
+        if now_ms < self.deadline_ms {
+            Outcome::Wait
+        } else if self.attempts < UDP_ATTEMPTS {
+            self.send_udp(socket, now_ms)
+        } else {
+            Outcome::Failed("dns_resolver: the nameserver didn't respond")
+        }
+    }
+
+    fn step_tcp(&mut self, now_ms: u64) -> Outcome {
+        let id = self.id;
+        let request_length = self.request.len();
+        let request = &self.request;
+        let (connection, sent, received) = match self.transport {
+            Transport::Tcp { connection, ref mut sent, ref mut received } => (connection, sent, received),
+            _ => return Outcome::Failed("dns_resolver: no TCP connection to the nameserver"),
+        };
+        if now_ms >= self.deadline_ms {
+            return Outcome::Failed("dns_resolver: timed out querying the nameserver over TCP");
+        }
+        match connection.is_connected() {
+            Ok(true) => {}
+            Ok(false) => return Outcome::Wait,
+            Err(e) => return Outcome::Failed(e),
+        }
+
+        // The query is sent after its two-byte length.
+        while *sent < 2 + request_length {
+            let result = if *sent < 2 {
+                connection.try_send(&(request_length as u16).to_be_bytes()[*sent ..])
+            } else {
+                connection.try_send(&request[*sent - 2 ..])
+            };
+            match result {
+                Ok(0) | Err(WOULD_BLOCK) => break,
+                Ok(length) => *sent += length,
+                Err(e) => return Outcome::Failed(e),
+            }
+        }
+
+        let mut buffer = [0; 1024];
+        loop {
+            match connection.try_recv(&mut buffer) {
+                Ok(0) => break,
+                Ok(length) => received.extend_from_slice(&buffer[.. length]),
+                Err(WOULD_BLOCK) => break,
+                Err(e) => return Outcome::Failed(e),
+            }
+        }
+        if received.len() < 2 {
+            return Outcome::Wait;
+        }
+        let response_length = u16::from_be_bytes([received[0], received[1]]) as usize;
+        if received.len() < 2 + response_length {
+            return Outcome::Wait;
+        }
+        match Response::decode(&received[2 .. 2 + response_length]) {
+            Ok(response) => if response.id == id {
+                check(response)
+            } else {
+                Outcome::Failed("dns_resolver: the nameserver responded to a different query")
+            },
+            Err(e) => Outcome::Failed(e),
+        }
+    }
+
+    /// Closes the socket of the current nameserver, if any.
+    fn close(&mut self) {
+        match mem::replace(&mut self.transport, Transport::Idle) {
+            Transport::Idle => {}
+            Transport::Udp(socket) => socket.close(),
+            Transport::Tcp { connection, .. } => connection.close(),
+        }
+    }
+}
+
+/// Returns whether the response answers the query, or whether the next nameserver should be tried instead.
+fn check(response: Response) -> Outcome {
+    match response.code {
+        ResponseCode::NoError | ResponseCode::NameError => Outcome::Done(response),
+        ResponseCode::ServerFailure => Outcome::Failed("dns_resolver: the nameserver failed to answer the query"),
+        ResponseCode::Refused => Outcome::Failed("dns_resolver: the nameserver refused the query"),
+        _ => Outcome::Failed("dns_resolver: the nameserver returned an error"),
+    }
+}
+
+impl Future for Query {
+    type Output = Result<Response, &'static str>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            if let Some(ref mut waiting) = self.waiting {
+                if Pin::new(waiting).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            self.waiting = None;
+            let now_ms = match net_stack::now_millis() {
+                Ok(now_ms) => now_ms,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            match self.step(now_ms) {
+                Ok(Some(response)) => return Poll::Ready(Ok(response)),
+                Ok(None) => self.waiting = Some(net_stack::next_poll()),
+                Err(e) => {
+                    self.close();
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
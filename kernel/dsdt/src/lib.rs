@@ -0,0 +1,142 @@
+//! Support for the DSDT ACPI table (Differentiated System Description Table),
+//! which contains the AML bytecode that describes the system's devices and power states.
+//!
+//! Theseus has no AML interpreter, so this crate only scans the AML for
+//! the simple, static package definitions of the sleep states, e.g., `\_S5_`,
+//! which is enough to put the system into a sleep state or power it off.
+
+#![no_std]
+
+extern crate memory;
+extern crate sdt;
+extern crate acpi_table;
+
+use core::mem::size_of;
+use memory::PhysicalAddress;
+use sdt::Sdt;
+use acpi_table::{AcpiSignature, AcpiTables};
+
+pub const DSDT_SIGNATURE: &'static [u8; 4] = b"DSDT";
+
+/// The name of the AML object that describes the S3 (suspend to RAM) sleep state.
+pub const S3_NAME: &'static [u8; 4] = b"_S3_";
+/// The name of the AML object that describes the S5 (soft off) sleep state.
+pub const S5_NAME: &'static [u8; 4] = b"_S5_";
+
+const AML_NAME_OP:     u8 = 0x08;
+const AML_PACKAGE_OP:  u8 = 0x12;
+const AML_ZERO_OP:     u8 = 0x00;
+const AML_ONE_OP:      u8 = 0x01;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_WORD_PREFIX: u8 = 0x0B;
+const AML_DWORD_PREFIX: u8 = 0x0C;
+const AML_ROOT_PREFIX: u8 = b'\\';
+
+
+/// The handler for parsing the DSDT table and adding it to the ACPI tables list.
+pub fn handle(
+    acpi_tables: &mut AcpiTables,
+    signature: AcpiSignature,
+    length: usize,
+    phys_addr: PhysicalAddress
+) -> Result<(), &'static str> {
+    // The AML bytecode is one byte per entry, and fills the rest of the table.
+    let slice_start_paddr = phys_addr + size_of::<Sdt>();
+    let slice_len = length.checked_sub(size_of::<Sdt>()).ok_or("DSDT table was too short")?;
+    acpi_tables.add_table_location(signature, phys_addr, Some((slice_start_paddr, slice_len)))
+}
+
+
+/// The values that must be written to the `SLP_TYP` field of the PM1a and PM1b control registers
+/// in order to enter a given sleep state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SleepType {
+    pub pm1a: u8,
+    pub pm1b: u8,
+}
+
+
+/// A wrapper around the DSDT ACPI table (Differentiated System Description Table).
+pub struct Dsdt<'t> {
+    header: &'t Sdt,
+    aml: &'t [u8],
+}
+
+impl<'t> Dsdt<'t> {
+    /// Finds the DSDT in the given `AcpiTables` and returns a reference to it.
+    pub fn get(acpi_tables: &'t AcpiTables) -> Option<Dsdt<'t>> {
+        let header: &Sdt = acpi_tables.table(&DSDT_SIGNATURE).ok()?;
+        let aml: &[u8] = acpi_tables.table_slice(&DSDT_SIGNATURE).ok()?;
+        Some(Dsdt { header, aml })
+    }
+
+    /// Returns a reference to the `Sdt` header in this DSDT table.
+    pub fn sdt(&self) -> &Sdt {
+        self.header
+    }
+
+    /// Returns the AML bytecode in this DSDT table.
+    pub fn aml(&self) -> &[u8] {
+        self.aml
+    }
+
+    /// Finds the sleep type values for the sleep state object with the given `name`,
+    /// e.g., [`S5_NAME`](constant.S5_NAME.html).
+    ///
+    /// This looks for an AML definition of the form `Name(_S5_, Package() { a, b, ... })`
+    /// whose first two elements are constant integers.
+    /// Returns `None` if no such definition exists, e.g., if the sleep state is not supported.
+    pub fn sleep_type(&self, name: &[u8; 4]) -> Option<SleepType> {
+        let aml = self.aml;
+        let mut start = 0;
+        while let Some(pos) = find(&aml[start..], name).map(|p| p + start) {
+            start = pos + 1;
+            // The name must directly follow a NameOp, optionally with a root prefix in between.
+            let is_name_def = match (pos.checked_sub(2).map(|i| aml[i]), pos.checked_sub(1).map(|i| aml[i])) {
+                (_, Some(AML_NAME_OP)) => true,
+                (Some(AML_NAME_OP), Some(AML_ROOT_PREFIX)) => true,
+                _ => false,
+            };
+            if !is_name_def {
+                continue;
+            }
+            if let Some(sleep_type) = parse_sleep_package(&aml[pos + name.len() ..]) {
+                return Some(sleep_type);
+            }
+        }
+        None
+    }
+}
+
+/// Parses a `Package` whose first two elements are integer constants,
+/// returning the low byte of each as the PM1a and PM1b sleep types.
+fn parse_sleep_package(aml: &[u8]) -> Option<SleepType> {
+    if *aml.get(0)? != AML_PACKAGE_OP {
+        return None;
+    }
+    // The upper two bits of the first PkgLength byte give the number of additional PkgLength bytes.
+    let pkg_length_bytes = 1 + (*aml.get(1)? >> 6) as usize;
+    // Skip the PackageOp, the PkgLength, and the NumElements byte.
+    let mut rest = aml.get(1 + pkg_length_bytes + 1 ..)?;
+    let (pm1a, len) = parse_integer(rest)?;
+    rest = rest.get(len..)?;
+    let (pm1b, _) = parse_integer(rest)?;
+    Some(SleepType { pm1a, pm1b })
+}
+
+/// Parses an AML integer constant, returning its low byte and the number of bytes it occupied.
+fn parse_integer(aml: &[u8]) -> Option<(u8, usize)> {
+    match *aml.get(0)? {
+        AML_ZERO_OP      => Some((0, 1)),
+        AML_ONE_OP       => Some((1, 1)),
+        AML_BYTE_PREFIX  => aml.get(1).map(|&b| (b, 2)),
+        AML_WORD_PREFIX  => aml.get(1).filter(|_| aml.len() >= 3).map(|&b| (b, 3)),
+        AML_DWORD_PREFIX => aml.get(1).filter(|_| aml.len() >= 5).map(|&b| (b, 5)),
+        _ => None,
+    }
+}
+
+/// Returns the index of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -27,6 +27,8 @@ extern crate intel_ethernet;
 extern crate nic_buffers;
 extern crate nic_queues;
 extern crate nic_initialization;
+extern crate channel;
+extern crate random;
 
 pub mod test_e1000_driver;
 mod regs;
@@ -34,13 +36,12 @@ mod regs;
 
 use spin::Once; 
 use alloc::vec::Vec;
-use alloc::collections::VecDeque;
 use irq_safety::MutexIrqSafe;
 use volatile::{Volatile, ReadOnly};
 use zerocopy::FromBytes;
 use alloc::boxed::Box;
 use memory::{PhysicalAddress, MappedPages, create_contiguous_mapping};
-use pci::{PciDevice, PCI_INTERRUPT_LINE, PciConfigSpaceAccessMechanism};
+use pci::{PciDevice, PCI_INTERRUPT_LINE, PciConfigSpaceAccessMechanism, MsiInterrupt};
 use kernel_config::memory::PAGE_SIZE;
 use owning_ref::BoxRefMut;
 use interrupts::{eoi,register_interrupt};
@@ -205,6 +206,9 @@ pub struct E1000Nic {
     mem_base: PhysicalAddress,
     ///interrupt number
     interrupt_num: u8,
+    /// The dedicated MSI vector that this NIC's interrupts arrive on,
+    /// or `None` if the NIC doesn't support MSI and uses its legacy interrupt line instead.
+    msi: Option<MsiInterrupt>,
     /// The actual MAC address burnt into the hardware of this E1000 NIC.
     mac_hardware: [u8; 6],
     /// The optional spoofed MAC address to use in place of `mac_hardware` when transmitting.  
@@ -236,7 +240,7 @@ impl NetworkInterfaceCard for E1000Nic {
     }
 
     fn get_received_frame(&mut self) -> Option<ReceivedFrame> {
-        self.rx_queue.received_frames.pop_front()
+        self.rx_queue.received_frames.try_recv().ok()
     }
 
     fn poll_receive(&mut self) -> Result<(), &'static str> {
@@ -285,7 +289,10 @@ impl NetworkInterfaceCard for E1000Nic {
 
             if rxq.rx_descs[cur].end_of_packet() {
                 let buffers = core::mem::replace(&mut receive_buffers_in_frame, Vec::new());
-                rxq.received_frames.push_back(ReceivedFrame(buffers));
+                // The channel is unbounded and this queue holds its receiving side, so this should never fail.
+                if let Err(_e) = rxq.received_frames_producer.try_send(ReceivedFrame(buffers)) {
+                    error!("NIC::remove_frames_from_queue(): failed to push a received frame onto the receive channel");
+                }
             } else {
                 warn!("NIC::remove_frames_from_queue(): Received multi-rxbuffer frame, this scenario not fully tested!");
             }
@@ -307,13 +314,7 @@ impl NetworkInterfaceCard for E1000Nic {
 impl E1000Nic {
     /// Initializes the new E1000 network interface card that is connected as the given PciDevice.
     pub fn init(e1000_pci_dev: &PciDevice) -> Result<&'static MutexIrqSafe<E1000Nic>, &'static str> {
-        use pic::PIC_MASTER_OFFSET;
-
         //debug!("e1000_nc bar_type: {0}, mem_base: {1}, io_base: {2}", e1000_nc.bar_type, e1000_nc.mem_base, e1000_nc.io_base);
-        
-        // Get interrupt number
-        let interrupt_num = e1000_pci_dev.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
-        // debug!("e1000 IRQ number: {}", interrupt_num);
 
         let bar0 = e1000_pci_dev.bars[0];
         // Determine the access mechanism from the base address register's bit 0
@@ -340,19 +341,22 @@ impl E1000Nic {
         //e1000_nc.clear_statistics();
         
         Self::enable_interrupts(&mut mapped_registers);
-        register_interrupt(interrupt_num, e1000_handler)?;
+        let (interrupt_num, msi) = Self::register_interrupt_handler(e1000_pci_dev)?;
+        // debug!("e1000 interrupt number: {}", interrupt_num);
 
         // initialize the buffer pool
         init_rx_buf_pool(RX_BUFFER_POOL_SIZE, E1000_RX_BUFFER_SIZE_IN_BYTES, &RX_BUFFER_POOL)?;
 
         let (rx_descs, rx_buffers) = Self::rx_init(&mut mapped_registers)?;
+        let (received_frames_producer, received_frames) = channel::unbounded();
         let rxq = RxQueue {
             id: 0,
             rx_descs: rx_descs,
             rx_cur: 0,
             rx_bufs_in_use: rx_buffers,
-            received_frames: VecDeque::new(),
-            // here the cpu id is irrelevant because there's no DCA or MSI 
+            received_frames_producer,
+            received_frames,
+            // the MSI vector (if any) is sent to this core, and there's no DCA 
             cpu_id: get_my_apic_id(),
         };
 
@@ -368,6 +372,7 @@ impl E1000Nic {
             bar_type: bar_type,
             mem_base: mem_base,
             interrupt_num: interrupt_num,
+            msi: msi,
             mac_hardware: mac_addr_hardware,
             mac_spoofed: None,
             rx_queue: rxq,
@@ -475,6 +480,26 @@ impl E1000Nic {
         Ok(tx_descs)
     }       
     
+    /// Registers the interrupt handler for this NIC, preferring a dedicated MSI vector
+    /// and falling back to the legacy interrupt line if the NIC doesn't support MSI.
+    /// Returns the interrupt number and the MSI vector handle, if MSI was enabled.
+    fn register_interrupt_handler(dev: &PciDevice) -> Result<(u8, Option<MsiInterrupt>), &'static str> {
+        match dev.enable_msi() {
+            Ok(mut msi) => {
+                msi.set_handler(e1000_handler)?;
+                info!("e1000: using MSI vector {}", msi.vector());
+                Ok((msi.vector(), Some(msi)))
+            }
+            Err(_e) => {
+                use pic::PIC_MASTER_OFFSET;
+                debug!("e1000: couldn't enable MSI ({}), using the legacy interrupt line", _e);
+                let interrupt_num = dev.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
+                register_interrupt(interrupt_num, e1000_handler)?;
+                Ok((interrupt_num, None))
+            }
+        }
+    }
+
     /// Enable Interrupts 
     fn enable_interrupts(regs: &mut E1000Registers) {
         //self.write_command(REG_IMASK ,0x1F6DC);
@@ -521,6 +546,7 @@ impl E1000Nic {
 extern "x86-interrupt" fn e1000_handler(_stack_frame: &mut ExceptionStackFrame) {
     if let Some(ref e1000_nic_ref) = E1000_NIC.try() {
         let mut e1000_nic = e1000_nic_ref.lock();
+        random::add_interrupt_entropy(e1000_nic.interrupt_num);
         if let Err(e) = e1000_nic.handle_interrupt() {
             error!("e1000_handler(): error handling interrupt: {:?}", e);
         }
@@ -21,6 +21,12 @@ bitflags! {
         const HUGE_PAGE         = 1 << 7;
         // const GLOBAL            = 1 << 8;
         const GLOBAL            = 0; // disabling because VirtualBox doesn't like it
+        /// A software-defined bit (ignored by the hardware) that marks a not-present entry
+        /// as a lazily-backed page, i.e., one that will be given a frame upon its first access.
+        const LAZY              = 1 << 9;
+        /// A software-defined bit (ignored by the hardware) that marks a read-only entry
+        /// as a copy-on-write page, i.e., one whose frame is shared and will be copied upon the first write.
+        const COPY_ON_WRITE     = 1 << 10;
         const NO_EXECUTE        = 1 << 63;
     }
 
@@ -37,6 +43,16 @@ impl EntryFlags {
         self | EntryFlags::HUGE_PAGE
     }
 
+    /// Returns true if the page is lazily backed by a frame allocated upon first access.
+    pub fn is_lazy(&self) -> bool {
+        self.intersects(EntryFlags::LAZY)
+    }
+
+    /// Returns true if the page is a copy-on-write page whose frame is shared with other mappings.
+    pub fn is_copy_on_write(&self) -> bool {
+        self.intersects(EntryFlags::COPY_ON_WRITE)
+    }
+
     /// Returns true if the page is writable.
     pub fn is_writable(&self) -> bool {
         self.intersects(EntryFlags::WRITABLE)
@@ -5,6 +5,7 @@ extern crate fs_node;
 extern crate root;
 
 use alloc::{
+    collections::BTreeMap,
     string::String,
     sync::Arc,
 };
@@ -14,11 +15,22 @@ use fs_node::DirRef;
 /// 
 /// A default environment can be created with the following state:
 /// * The working directory is the `root` directory.
+/// * There are no environment variables.
+/// * The credentials are those of a system task, see [`Credentials::system()`].
 ///
+/// By default, a new `Task` receives its own copy of its parent's `Environment` when it is spawned,
+/// so changes made by the child (e.g., setting a variable) are not visible to the parent.
+/// Tasks that should share an environment, e.g., a shell and the applications it runs,
+/// can explicitly share the same `Arc<Mutex<Environment>>` instead.
+#[derive(Clone)]
 pub struct Environment {
     /// The "current working directory", i.e., 
     /// where a task's relative path begins upon first execution.
     pub working_dir: DirRef, 
+    /// The environment variables, a map from each variable's name to its value.
+    pub variables: BTreeMap<String, String>,
+    /// The identity under which filesystem accesses are checked.
+    pub credentials: Credentials,
 }
 
 impl Environment {
@@ -27,12 +39,61 @@ impl Environment {
         let wd = self.working_dir.lock();
         wd.get_absolute_path()
     }
+
+    /// Returns the value of the environment variable with the given `key`, if it is set.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.variables.get(key)
+    }
+
+    /// Sets the environment variable `key` to the given `value`,
+    /// returning the variable's previous value if it was already set.
+    pub fn set(&mut self, key: String, value: String) -> Option<String> {
+        self.variables.insert(key, value)
+    }
+
+    /// Removes the environment variable with the given `key`,
+    /// returning its value if it was set.
+    pub fn unset(&mut self, key: &str) -> Option<String> {
+        self.variables.remove(key)
+    }
+
+    /// Removes all environment variables, but leaves the working directory unchanged.
+    pub fn clear_variables(&mut self) {
+        self.variables.clear();
+    }
 }
 
 impl Default for Environment {
     fn default() -> Environment {
         Environment {
             working_dir: Arc::clone(root::get_root()),
+            variables: BTreeMap::new(),
+            credentials: Credentials::system(),
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// The identity of a task (or group of tasks) when accessing files and directories.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    /// The ID of the user that the task acts as.
+    pub uid: u32,
+    /// The ID of the group that the task acts as.
+    pub gid: u32,
+    /// Whether the task may bypass permission checks, like a Unix capability.
+    /// Unlike on Unix, having a `uid` of `0` doesn't grant this.
+    pub override_access: bool,
+}
+
+impl Credentials {
+    /// The credentials of a system task, which is the root user and may bypass permission checks.
+    pub fn system() -> Credentials {
+        Credentials { uid: 0, gid: 0, override_access: true }
+    }
+
+    /// The credentials of an unprivileged task acting as the given user and group.
+    pub fn user(uid: u32, gid: u32) -> Credentials {
+        Credentials { uid, gid, override_access: false }
+    }
+}
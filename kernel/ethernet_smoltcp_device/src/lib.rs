@@ -15,7 +15,9 @@ extern crate network_manager;
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
+    string::String,
 };
+use core::sync::atomic::{AtomicUsize, Ordering};
 use irq_safety::MutexIrqSafe;
 use smoltcp::{
     socket::SocketSet,
@@ -27,20 +29,38 @@ use smoltcp::{
 use network_interface_card::NetworkInterfaceCard;
 use nic_buffers::{TransmitBuffer, ReceivedFrame};
 use owning_ref::BoxRefMut;
-use network_manager::NetworkInterface;
+use network_manager::{NetworkInterface, capture::CaptureDevice};
 use core::str::FromStr;
 
 /// standard MTU for ethernet cards
 const DEFAULT_MTU: usize = 1500;
 
+/// The number in the name of the next ethernet interface, e.g., `eth0` and then `eth1`.
+static NEXT_INTERFACE_NUMBER: AtomicUsize = AtomicUsize::new(0);
+
 
 /// A struct that implements the `NetworkInterface` trait for a NIC. 
 /// There should be one instance of this struct per interface, i.e., an Ethernet port on the NIC.
+/// Its device is wrapped in a `CaptureDevice`, so that the frames it sends and receives can be captured.
 pub struct EthernetNetworkInterface<N: NetworkInterfaceCard + 'static> {
-    pub iface: EthernetInterface<'static, 'static, 'static, EthernetDevice<N>>,
+    pub iface: EthernetInterface<'static, 'static, 'static, CaptureDevice<EthernetDevice<N>>>,
+    name: String,
+    up: bool,
 }
 
 impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInterface<N> { 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_up(&self) -> bool {
+        self.up
+    }
+
+    fn set_up(&mut self, up: bool) {
+        self.up = up;
+    }
+
     fn ethernet_addr(&self) -> EthernetAddress {
         self.iface.ethernet_addr()
     }
@@ -50,6 +70,9 @@ impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInte
     }
 
     fn poll(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> smoltcp::Result<bool> {
+        if !self.up {
+            return Ok(false);
+        }
         self.iface.poll(sockets, timestamp)
     }
 
@@ -57,6 +80,10 @@ impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInte
         self.iface.ip_addrs()
     }
 
+    fn set_ip_addrs(&mut self, addrs: &[IpCidr]) {
+        self.iface.update_ip_addrs(|ip_addrs| *ip_addrs = addrs.to_vec().into());
+    }
+
     fn has_ip_addr(&self, addr: IpAddress) -> bool {
         self.iface.has_ip_addr(addr)
     }
@@ -72,14 +99,16 @@ impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInte
 
 impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
     /// Creates a new instance of an ethernet network interface, which can be used for handling sockets. 
+    /// The interface is named after the number of ethernet interfaces created before it, e.g., `eth0`, and is up.
     /// 
     /// Arguments: 
     /// * `nic`:  a reference to an initialized Ethernet NIC, which must implement the `NetworkInterfaceCard` trait.
-    /// * `static_ip`: the IP that this network interface should locally use. If `None`, one will be assigned via DHCP.
-    /// * `gateway_ip`: the IP of this network interface's local gateway (access point, router). If `None`, will be discovered via DHCP.
+    /// * `static_ip`: the IP that this network interface should locally use. If `None`, one must be assigned via DHCP.
+    /// * `gateway_ip`: the IP of this network interface's local gateway (access point, router). If `None`, it must be discovered via DHCP.
     /// 
     /// # Note
-    /// Currently, `static_ip` and `gateway_ip` are required because we don't yet support DHCP.
+    /// An interface created without a `static_ip` has the unspecified address `0.0.0.0/0`,
+    /// which lets it send and receive the broadcasts of a DHCP client, see the `dhcp_client` crate.
     /// 
     pub fn new<G: Into<IpAddress>>(
         nic: &'static MutexIrqSafe<N>,
@@ -88,23 +117,25 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
     ) -> Result<EthernetNetworkInterface<N>, &'static str> 
     {
         // here, we have to create the iface for the first time because it didn't yet exist
-        let static_ip = static_ip.ok_or("static_ip is currently required because we do not yet support DHCP")?;
-        let ip_addrs = vec![static_ip];
+        let ip_addrs = vec![static_ip.unwrap_or_else(|| IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0))];
 
         let mut routes = Routes::new(BTreeMap::new());
-        let res = match gateway_ip.ok_or("gateway_ip is currently required because we do not yet support DHCP")?.into() {
-            IpAddress::Ipv4(ipv4) => routes.add_default_ipv4_route(ipv4),
-            IpAddress::Ipv6(ipv6) => routes.add_default_ipv6_route(ipv6),
-            _ => {
-                return Err("gateway_ip must be an Ipv4Address or an Ipv6Address");
-            }
-        };
-        res.map_err(|_e| {
-            error!("ethernet_smoltcp_device(): couldn't set default gateway IP address: {:?}", _e);
-            "couldn't set default gateway IP address"
-        })?;
+        if let Some(gateway_ip) = gateway_ip {
+            let res = match gateway_ip.into() {
+                IpAddress::Ipv4(ipv4) => routes.add_default_ipv4_route(ipv4),
+                IpAddress::Ipv6(ipv6) => routes.add_default_ipv6_route(ipv6),
+                _ => {
+                    return Err("gateway_ip must be an Ipv4Address or an Ipv6Address");
+                }
+            };
+            res.map_err(|_e| {
+                error!("ethernet_smoltcp_device(): couldn't set default gateway IP address: {:?}", _e);
+                "couldn't set default gateway IP address"
+            })?;
+        }
 
-        let device = EthernetDevice::new(nic);
+        let name = format!("eth{}", NEXT_INTERFACE_NUMBER.fetch_add(1, Ordering::SeqCst));
+        let device = CaptureDevice::new(EthernetDevice::new(nic), name.clone());
         let hardware_mac_addr = EthernetAddress(nic.lock().mac_address());
         // When creating an EthernetInterface, only the `ethernet_addr` and `neighbor_cache` are required.
         let iface = EthernetInterfaceBuilder::new(device)
@@ -115,7 +146,7 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
             .finalize();
 
         Ok(
-            EthernetNetworkInterface { iface }
+            EthernetNetworkInterface { iface, name, up: true }
         )
     }
 
@@ -137,6 +168,15 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
 
         Self::new(nic_ref, Some(static_ip), Some(gateway_ip))
     }
+
+    /// Creates a new ethernet network interface without an IP address or gateway,
+    /// which are meant to be configured via DHCP.
+    /// 
+    /// # Arguments
+    /// * `nic_ref`: a reference to an initialized Ethernet NIC, which must implement the `NetworkInterfaceCard` trait.
+    pub fn new_dhcp_interface(nic_ref: &'static MutexIrqSafe<N>) -> Result<EthernetNetworkInterface<N>, &'static str> {
+        Self::new(nic_ref, None, None::<Ipv4Address>)
+    }
 }
 
 
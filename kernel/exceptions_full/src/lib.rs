@@ -7,6 +7,7 @@ extern crate x86_64;
 extern crate task;
 // extern crate apic;
 extern crate tlb_shootdown;
+extern crate tss;
 extern crate pmu_x86;
 #[macro_use] extern crate log;
 #[macro_use] extern crate vga_buffer; // for println_raw!()
@@ -16,8 +17,9 @@ extern crate debug_info;
 extern crate gimli;
 
 extern crate memory;
-extern crate stack_trace;
+extern crate backtrace;
 extern crate fault_log;
+extern crate crash_dump;
 
 use x86_64::structures::idt::{LockedIdt, ExceptionStackFrame, PageFaultErrorCode};
 use x86_64::registers::msr::*;
@@ -36,7 +38,11 @@ pub fn init(idt_ref: &'static LockedIdt) {
         idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
         idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
         idt.device_not_available.set_handler_fn(device_not_available_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
+        // use a special stack for the double fault handler, such that a stack overflow doesn't cause a triple fault
+        unsafe {
+            idt.double_fault.set_handler_fn(double_fault_handler)
+                            .set_stack_index(tss::DOUBLE_FAULT_IST_INDEX as u16);
+        }
         // reserved: 0x09 coprocessor segment overrun exception
         idt.invalid_tss.set_handler_fn(invalid_tss_handler);
         idt.segment_not_present.set_handler_fn(segment_not_present_handler);
@@ -71,6 +77,18 @@ macro_rules! println_both {
 }
 
 
+/// Returns the current task if the given address lies within the guard page beneath its stack,
+/// which means that accessing that address was caused by the current task overflowing its stack.
+fn stack_overflow_task(vaddr: usize) -> Option<&'static task::TaskRef> {
+    let curr_task = task::get_my_current_task()?;
+    if curr_task.lock().kstack.is_in_guard_page(memory::VirtualAddress::new_canonical(vaddr)) {
+        Some(curr_task)
+    } else {
+        None
+    }
+}
+
+
 /// Kills the current task (the one that caused an exception) by unwinding it.
 /// 
 /// # Important Note
@@ -120,30 +138,18 @@ fn kill_and_halt(exception_number: u8, stack_frame: &ExceptionStackFrame) {
         }
     }
 
-    // print a stack trace
+    // print a symbolized backtrace, skipping the frame of this function,
+    // and store it in the fault log for use by the fault recovery subsystem.
     #[cfg(not(downtime_eval))]
     {
-        println_both!("------------------ Stack Trace (DWARF) ---------------------------");
-        let stack_trace_result = stack_trace::stack_trace(
-            &|stack_frame, stack_frame_iter| {
-                let symbol_offset = stack_frame_iter.namespace().get_section_containing_address(
-                    memory::VirtualAddress::new_canonical(stack_frame.call_site_address() as usize),
-                    false
-                ).map(|(sec, offset)| (sec.name.clone(), offset));
-                if let Some((symbol_name, offset)) = symbol_offset {
-                    println_both!("  {:>#018X} in {} + {:#X}", stack_frame.call_site_address(), symbol_name, offset);
-                } else {
-                    println_both!("  {:>#018X} in ??", stack_frame.call_site_address());
-                }
-                true
-            },
-            None,
-        );
-        match stack_trace_result {
-            Ok(()) => { println_both!("  Beginning of stack"); }
-            Err(e) => { println_both!("  {}", e); }
-        }
-        println_both!("---------------------- End of Stack Trace ------------------------");
+        println_both!("------------------ Backtrace -------------------------------------");
+        let backtrace = backtrace::capture(1, None);
+        println_both!("{}", backtrace);
+        println_both!("---------------------- End of Backtrace --------------------------");
+        let _ = fault_log::attach_backtrace(backtrace);
+
+        // persist the details of this exception, so that they can be inspected after a reboot
+        crash_dump::save_exception(exception_number, stack_frame);
     }
 
     let cause = task::KillReason::Exception(exception_number);
@@ -305,7 +311,18 @@ pub extern "x86-interrupt" fn device_not_available_handler(stack_frame: &mut Exc
 
 /// exception 0x08
 pub extern "x86-interrupt" fn double_fault_handler(stack_frame: &mut ExceptionStackFrame, error_code: u64) {
-    println_both!("\nEXCEPTION: DOUBLE FAULT\n{:#?}\n", stack_frame);
+    // A stack overflow typically causes a double fault, because the CPU cannot push 
+    // the page fault's exception frame onto the overflowed stack (its guard page).
+    let stack_pointer = stack_frame.stack_pointer.0;
+    let overflowed_task = stack_overflow_task(stack_pointer)
+        .or_else(|| stack_overflow_task(stack_pointer.saturating_sub(core::mem::size_of::<usize>())));
+    if let Some(task) = overflowed_task {
+        println_both!("\nEXCEPTION: DOUBLE FAULT caused by stack overflow in task {:?}, stack pointer: {:#X}\n{:#?}\n",
+            task, stack_pointer, stack_frame
+        );
+    } else {
+        println_both!("\nEXCEPTION: DOUBLE FAULT\n{:#?}\n", stack_frame);
+    }
     
     log_exception(0x8, stack_frame.instruction_pointer.0, Some(error_code), None);
     kill_and_halt(0x8, stack_frame)
@@ -348,6 +365,30 @@ pub extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: &mut
 pub extern "x86-interrupt" fn page_fault_handler(stack_frame: &mut ExceptionStackFrame, error_code: PageFaultErrorCode) {
     use x86_64::registers::control_regs;
 
+    // A fault on a not-present page may be the first access to a lazily-backed page,
+    // in which case we give it a frame and return in order to retry the faulting instruction.
+    // Similarly, a write fault on a present page may be the first write to a copy-on-write page,
+    // in which case we give it a private copy of its frame and retry.
+    let fault_vaddr = memory::VirtualAddress::new_canonical(control_regs::cr2().0);
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        if memory::handle_lazy_page_fault(fault_vaddr) {
+            return;
+        }
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        if memory::handle_cow_page_fault(fault_vaddr) {
+            return;
+        }
+    }
+
+    if let Some(task) = stack_overflow_task(fault_vaddr.value()) {
+        println_both!("\nEXCEPTION: PAGE FAULT caused by stack overflow in task {:?}, accessed guard page at {:#X}\n{:#?}\n",
+            task, fault_vaddr, stack_frame
+        );
+        log_exception(0xE, stack_frame.instruction_pointer.0, None, Some(fault_vaddr.value()));
+        kill_and_halt(0xE, stack_frame);
+        return;
+    }
+
     #[cfg(not(downtime_eval))]
     println_both!("\nEXCEPTION: PAGE FAULT while accessing {:#X}\nerror code: \
                                   {:?}\n{:#?}\n",
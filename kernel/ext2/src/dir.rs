@@ -0,0 +1,90 @@
+//! Encoding and decoding of the variable-length entries in an ext2 directory block.
+
+use alloc::vec::Vec;
+use superblock::{read_u16, read_u32, write_u16, write_u32};
+
+
+/// The size of a directory entry's fixed fields, which are followed by its name.
+const ENTRY_HEADER_SIZE: usize = 8;
+/// The maximum length of a file name in bytes.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// The file types stored in directory entries when the filetype feature is enabled.
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+
+
+/// An entry in a directory block.
+pub struct DirEntry {
+    /// The byte offset of this entry within its block.
+    pub offset: usize,
+    /// The inode this entry refers to, or `0` if the entry is unused.
+    pub inode: u32,
+    /// The distance in bytes to the next entry, which includes any unused space after this entry.
+    pub rec_len: usize,
+    pub name: Vec<u8>,
+}
+
+impl DirEntry {
+    /// Returns the number of bytes this entry actually needs, as opposed to its `rec_len`.
+    pub fn used_len(&self) -> usize {
+        if self.inode == 0 { 0 } else { entry_len(self.name.len()) }
+    }
+}
+
+/// Returns the number of bytes needed for an entry with a name of `name_len` bytes,
+/// which is rounded up to a multiple of 4.
+pub fn entry_len(name_len: usize) -> usize {
+    (ENTRY_HEADER_SIZE + name_len + 3) & !3
+}
+
+/// Parses the entries of the given directory `block`.
+/// Parsing stops early at an entry whose length is invalid.
+pub fn parse_block(block: &[u8], has_filetype: bool) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + ENTRY_HEADER_SIZE <= block.len() {
+        let inode = read_u32(block, offset);
+        let rec_len = read_u16(block, offset + 4) as usize;
+        // With the filetype feature, the upper byte of the name length holds the file type instead.
+        let name_len = if has_filetype { block[offset + 6] as usize } else { read_u16(block, offset + 6) as usize };
+        if rec_len < ENTRY_HEADER_SIZE || rec_len % 4 != 0 || offset + rec_len > block.len()
+            || (inode != 0 && entry_len(name_len) > rec_len)
+        {
+            break;
+        }
+        let name = if inode != 0 { block[offset + ENTRY_HEADER_SIZE .. offset + ENTRY_HEADER_SIZE + name_len].to_vec() } else { Vec::new() };
+        entries.push(DirEntry { offset, inode, rec_len, name });
+        offset += rec_len;
+    }
+    entries
+}
+
+/// Writes an entry into the given directory `block` at `offset`.
+pub fn write_entry(block: &mut [u8], offset: usize, inode: u32, rec_len: usize, name: &[u8], file_type: u8, has_filetype: bool) {
+    write_u32(block, offset, inode);
+    write_u16(block, offset + 4, rec_len as u16);
+    if has_filetype {
+        block[offset + 6] = name.len() as u8;
+        block[offset + 7] = file_type;
+    } else {
+        write_u16(block, offset + 6, name.len() as u16);
+    }
+    block[offset + ENTRY_HEADER_SIZE .. offset + ENTRY_HEADER_SIZE + name.len()].copy_from_slice(name);
+}
+
+/// Changes the `rec_len` of the entry at `offset` in the given directory `block`.
+pub fn set_rec_len(block: &mut [u8], offset: usize, rec_len: usize) {
+    write_u16(block, offset + 4, rec_len as u16);
+}
+
+/// Checks whether `name` can be used as the name of a new file or directory.
+pub fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err("ext2: invalid file name");
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err("ext2: file name is too long");
+    }
+    Ok(())
+}
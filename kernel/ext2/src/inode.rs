@@ -0,0 +1,203 @@
+//! The on-disk ext2 inode, which holds a file's metadata and the addresses of its blocks.
+
+use alloc::vec::Vec;
+use superblock::{read_u16, read_u32, write_u16, write_u32};
+
+
+/// The inode number of the root directory.
+pub const ROOT_INODE: u32 = 2;
+
+/// The number of block addresses in an inode: 12 direct blocks, then single, double and triple indirect blocks.
+pub const NUM_BLOCK_POINTERS: usize = 15;
+pub const NUM_DIRECT_BLOCKS: usize = 12;
+pub const SINGLE_INDIRECT: usize = 12;
+pub const DOUBLE_INDIRECT: usize = 13;
+pub const TRIPLE_INDIRECT: usize = 14;
+
+/// The inode flag of a directory with a hashed b-tree index, which this driver doesn't maintain.
+pub const INDEX_FL: u32 = 0x1000;
+
+/// The mask of the file type bits of an inode's mode.
+pub const S_IFMT: u16 = 0xF000;
+pub const S_IFLNK: u16 = 0xA000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFDIR: u16 = 0x4000;
+/// The bits of a mode that are permissions, including the set-user-ID, set-group-ID and sticky bits.
+pub const PERMISSION_BITS: u16 = 0o7777;
+/// The permissions given to new files (`rw-r--r--`).
+pub const DEFAULT_FILE_PERMISSIONS: u16 = 0o644;
+/// The permissions given to new directories (`rwxr-xr-x`).
+pub const DEFAULT_DIR_PERMISSIONS: u16 = 0o755;
+
+const MODE_OFFSET: usize = 0;
+const UID_OFFSET: usize = 2;
+const SIZE_OFFSET: usize = 4;
+const ATIME_OFFSET: usize = 8;
+const CTIME_OFFSET: usize = 12;
+const MTIME_OFFSET: usize = 16;
+const DTIME_OFFSET: usize = 20;
+const GID_OFFSET: usize = 24;
+const LINKS_COUNT_OFFSET: usize = 26;
+const BLOCKS_OFFSET: usize = 28;
+const FLAGS_OFFSET: usize = 32;
+const BLOCK_POINTERS_OFFSET: usize = 40;
+const SIZE_HIGH_OFFSET: usize = 108;
+/// The upper 16 bits of the user and group IDs, as stored by Linux.
+const UID_HIGH_OFFSET: usize = 120;
+const GID_HIGH_OFFSET: usize = 122;
+
+
+/// An inode, kept in its on-disk form so that fields this driver doesn't use are preserved.
+#[derive(Clone)]
+pub struct Inode {
+    raw: Vec<u8>,
+}
+
+impl Inode {
+    /// Wraps the given on-disk inode.
+    pub fn from_raw(raw: Vec<u8>) -> Inode {
+        Inode { raw }
+    }
+
+    /// Creates a new inode of `size` bytes with the given mode, link count and owner,
+    /// whose timestamps are all set to `now`.
+    pub fn new(size: usize, mode: u16, links_count: u16, (uid, gid): (u32, u32), now: u32) -> Inode {
+        let mut inode = Inode { raw: vec![0; size] };
+        write_u16(&mut inode.raw, MODE_OFFSET, mode);
+        write_u16(&mut inode.raw, LINKS_COUNT_OFFSET, links_count);
+        inode.set_owner(uid, gid);
+        for &offset in &[ATIME_OFFSET, CTIME_OFFSET, MTIME_OFFSET] {
+            write_u32(&mut inode.raw, offset, now);
+        }
+        inode
+    }
+
+    /// Returns the on-disk form of this inode.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn mode(&self) -> u16 {
+        read_u16(&self.raw, MODE_OFFSET)
+    }
+
+    /// Returns the permission bits of this inode's mode.
+    pub fn permissions(&self) -> u16 {
+        self.mode() & PERMISSION_BITS
+    }
+
+    /// Sets the permission bits of this inode's mode, keeping its file type.
+    pub fn set_permissions(&mut self, permissions: u16) {
+        let mode = (self.mode() & S_IFMT) | (permissions & PERMISSION_BITS);
+        write_u16(&mut self.raw, MODE_OFFSET, mode);
+    }
+
+    pub fn uid(&self) -> u32 {
+        (read_u16(&self.raw, UID_HIGH_OFFSET) as u32) << 16 | read_u16(&self.raw, UID_OFFSET) as u32
+    }
+
+    pub fn gid(&self) -> u32 {
+        (read_u16(&self.raw, GID_HIGH_OFFSET) as u32) << 16 | read_u16(&self.raw, GID_OFFSET) as u32
+    }
+
+    pub fn set_owner(&mut self, uid: u32, gid: u32) {
+        write_u16(&mut self.raw, UID_OFFSET, uid as u16);
+        write_u16(&mut self.raw, UID_HIGH_OFFSET, (uid >> 16) as u16);
+        write_u16(&mut self.raw, GID_OFFSET, gid as u16);
+        write_u16(&mut self.raw, GID_HIGH_OFFSET, (gid >> 16) as u16);
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode() & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode() & S_IFMT == S_IFLNK
+    }
+
+    /// Returns the size of this inode's contents in bytes.
+    /// The upper 32 bits are only used by regular files.
+    pub fn size(&self) -> u64 {
+        let high = if self.mode() & S_IFMT == S_IFREG { read_u32(&self.raw, SIZE_HIGH_OFFSET) } else { 0 };
+        (high as u64) << 32 | read_u32(&self.raw, SIZE_OFFSET) as u64
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        write_u32(&mut self.raw, SIZE_OFFSET, size as u32);
+        if self.mode() & S_IFMT == S_IFREG {
+            write_u32(&mut self.raw, SIZE_HIGH_OFFSET, (size >> 32) as u32);
+        }
+    }
+
+    pub fn links_count(&self) -> u16 {
+        read_u16(&self.raw, LINKS_COUNT_OFFSET)
+    }
+
+    pub fn set_links_count(&mut self, links_count: u16) {
+        write_u16(&mut self.raw, LINKS_COUNT_OFFSET, links_count);
+    }
+
+    pub fn flags(&self) -> u32 {
+        read_u32(&self.raw, FLAGS_OFFSET)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        write_u32(&mut self.raw, FLAGS_OFFSET, flags);
+    }
+
+    /// Returns the number of 512-byte sectors allocated to this inode, including indirect blocks.
+    pub fn sectors(&self) -> u32 {
+        read_u32(&self.raw, BLOCKS_OFFSET)
+    }
+
+    pub fn set_sectors(&mut self, sectors: u32) {
+        write_u32(&mut self.raw, BLOCKS_OFFSET, sectors);
+    }
+
+    /// Returns the `index`th block address, see `NUM_BLOCK_POINTERS`.
+    pub fn block(&self, index: usize) -> u32 {
+        read_u32(&self.raw, BLOCK_POINTERS_OFFSET + index * 4)
+    }
+
+    pub fn set_block(&mut self, index: usize, block: u32) {
+        write_u32(&mut self.raw, BLOCK_POINTERS_OFFSET + index * 4, block);
+    }
+
+    /// Returns the raw bytes of the block addresses,
+    /// which hold the target of a "fast" symbolic link that has no blocks.
+    pub fn block_bytes(&self) -> &[u8] {
+        &self.raw[BLOCK_POINTERS_OFFSET .. BLOCK_POINTERS_OFFSET + NUM_BLOCK_POINTERS * 4]
+    }
+
+    /// Returns the last access time, in seconds since the Unix epoch.
+    pub fn access_time(&self) -> u32 {
+        read_u32(&self.raw, ATIME_OFFSET)
+    }
+
+    /// Returns the last modification time, in seconds since the Unix epoch.
+    pub fn modification_time(&self) -> u32 {
+        read_u32(&self.raw, MTIME_OFFSET)
+    }
+
+    /// Sets the change time, which records changes to the inode's metadata, to `now`.
+    pub fn set_change_time(&mut self, now: u32) {
+        write_u32(&mut self.raw, CTIME_OFFSET, now);
+    }
+
+    /// Sets the modification and change times to `now`.
+    pub fn touch(&mut self, now: u32) {
+        write_u32(&mut self.raw, MTIME_OFFSET, now);
+        write_u32(&mut self.raw, CTIME_OFFSET, now);
+    }
+
+    /// Sets the deletion time to `now`.
+    pub fn set_deletion_time(&mut self, now: u32) {
+        write_u32(&mut self.raw, DTIME_OFFSET, now);
+    }
+}
+
+
+/// Returns the current wall-clock time as seconds since the Unix epoch, as stored in an inode.
+pub fn unix_time() -> u32 {
+    wall_clock::now().to_unix().0.max(0) as u32
+}
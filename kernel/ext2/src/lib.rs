@@ -0,0 +1,135 @@
+#![no_std]
+
+//! An ext2 filesystem driver with read and write support.
+//!
+//! ext2 is registered with the VFS as the `ext2` filesystem type, so an ext2 volume is attached
+//! to the directory tree with `vfs::mount()`. Its directories and files are then [`Ext2Directory`]
+//! and [`Ext2File`] nodes that are used through the regular `fs_node` traits:
+//! * files and directories are created with `Directory::create_file()` and `Directory::create_dir()`,
+//! * files and directories (including their contents) are deleted with `Directory::remove()`,
+//! * files are extended by writing past their end and resized with `File::truncate()`.
+//!
+//! Blocks and inodes are allocated from the bitmaps of each block group,
+//! preferring the group of the parent directory, and a file's blocks are addressed
+//! through its direct block pointers and its single, double and triple indirect blocks.
+//! Files may be sparse; holes read as zeros.
+//! New files and directories are owned by the user and group of the task that creates them,
+//! and each node's owner, permissions and times are returned by `FsNode::metadata()`.
+//!
+//! Volumes that use ext3/ext4 features that would change the on-disk layout (such as extents or a journal)
+//! cannot be mounted, and volumes with unknown read-only-compatible features are mounted read-only.
+//!
+//! All I/O goes through the page cache: files are read and written as cached file pages,
+//! which are written back to their blocks on the volume, while directories, inodes and bitmaps
+//! are read and written as cached pages of the block device.
+//! Changes are thus written to the device by the page cache's periodic flusher, or immediately with `page_cache::sync()`.
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate fs_node;
+extern crate vfs;
+extern crate memory;
+extern crate block_io;
+extern crate page_cache;
+extern crate kernel_config;
+extern crate wall_clock;
+extern crate access_control;
+
+mod dir;
+mod inode;
+mod node;
+mod superblock;
+mod volume;
+
+pub use node::{Ext2Directory, Ext2File};
+pub use volume::Volume;
+
+use core::sync::atomic::AtomicBool;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use block_io::BlockQueue;
+use fs_node::{DirRef, WeakDirRef};
+use vfs::{FileSystem, Device};
+use superblock::{Superblock, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE, MBR_SIZE};
+
+
+/// The name of this filesystem type in the VFS.
+pub const FILESYSTEM_NAME: &'static str = "ext2";
+
+
+/// Registers ext2 as a filesystem type with the VFS, and mounts every ext2 volume
+/// on the storage devices known to the `storage_manager` at `/mnt/<device>`,
+/// or `/mnt/<device>p<partition>` for a volume in an MBR partition.
+pub fn init() -> Result<(), &'static str> {
+    vfs::register_filesystem(Arc::new(Ext2FileSystem))?;
+    vfs::automount(FILESYSTEM_NAME)
+}
+
+/// Returns whether an ext2 superblock can be found at the byte `offset` of the given block device.
+fn has_superblock(queue: &BlockQueue, offset: usize) -> bool {
+    let mut raw = [0u8; SUPERBLOCK_SIZE];
+    volume::read_exact(queue, &mut raw, offset + SUPERBLOCK_OFFSET).is_ok() && Superblock::parse(&raw).is_ok()
+}
+
+/// Returns the ext2 volumes on the given block device, each of which is given as
+/// its MBR partition number (or `None` if the volume spans the whole device) and its byte offset.
+pub fn find_volumes(queue: &BlockQueue) -> Result<Vec<(Option<usize>, usize)>, &'static str> {
+    // A device without a partition table holds the volume's superblock in its second kilobyte.
+    if has_superblock(queue, 0) {
+        return Ok(vec![(None, 0)]);
+    }
+
+    let mut sector = [0u8; MBR_SIZE];
+    volume::read_exact(queue, &mut sector, 0)?;
+    Ok(superblock::linux_partitions(&sector)
+        .map(|(number, start_sector)| (number, start_sector * queue.block_size()))
+        .filter(|&(_, offset)| has_superblock(queue, offset))
+        .map(|(number, offset)| (Some(number), offset))
+        .collect())
+}
+
+
+/// The ext2 filesystem type, through which the VFS mounts ext2 volumes.
+pub struct Ext2FileSystem;
+
+impl FileSystem for Ext2FileSystem {
+    fn name(&self) -> &'static str {
+        FILESYSTEM_NAME
+    }
+
+    fn probe(&self, queue: &BlockQueue) -> Vec<Option<usize>> {
+        find_volumes(queue).map(|volumes| volumes.into_iter().map(|(partition, _)| partition).collect()).unwrap_or_default()
+    }
+
+    fn mount(&self, device: Option<&Device>, name: String, parent: WeakDirRef, options: &str) -> Result<DirRef, &'static str> {
+        if !options.is_empty() {
+            return Err("ext2: mount options are not supported");
+        }
+        let device = device.ok_or("ext2: mounting requires a device")?;
+        let offset = find_volumes(&device.queue)?.into_iter()
+            .find(|&(partition, _)| partition == device.partition)
+            .map(|(_, offset)| offset)
+            .ok_or("ext2: no ext2 volume found on the device")?;
+        let volume = Arc::new(Volume::open(device.queue.clone(), offset)?);
+        if volume.is_read_only() {
+            warn!("ext2: {} uses unsupported features, mounting it read-only", device.name);
+        }
+        if !volume.read_inode(inode::ROOT_INODE)?.is_dir() {
+            return Err("ext2: the root inode is not a directory");
+        }
+        Ok(Ext2Directory::new_ref(name, parent, volume, inode::ROOT_INODE, Arc::new(AtomicBool::new(false))))
+    }
+
+    fn unmount(&self, root: &DirRef, device: Option<&Device>) -> Result<(), &'static str> {
+        // Writing back the files' pages dirties the device's pages, so they're written back first.
+        if let Some(filesystem) = root.lock().metadata().filesystem {
+            page_cache::sync_filesystem(filesystem)?;
+        }
+        match device {
+            Some(device) => page_cache::sync_block_device(&device.queue).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,730 @@
+//! The directories and files of an ext2 volume, exposed through the `fs_node` traits.
+
+use core::cmp::min;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use fs_node::{DirRef, WeakDirRef, FileRef, Directory, File, FileOrDir, FsNode, Metadata};
+use memory::MappedPages;
+use kernel_config::memory::PAGE_SIZE;
+use page_cache::{FileId, PageBacking};
+use dir::{self, FT_DIR, FT_REG_FILE};
+use inode::{self, Inode, INDEX_FL, S_IFDIR, S_IFREG, DEFAULT_DIR_PERMISSIONS, DEFAULT_FILE_PERMISSIONS};
+use volume::Volume;
+
+
+/// Files larger than this require the large file feature, which older drivers don't support.
+const LARGE_FILE_THRESHOLD: u64 = 0x7FFF_FFFF;
+
+
+/// A child of an `Ext2Directory`.
+struct Child {
+    node: FileOrDir,
+    /// The child's name as stored on disk, which may not be valid UTF-8.
+    raw_name: Vec<u8>,
+    inode_number: u32,
+    /// Shared with the child node, and set once the child has been deleted.
+    removed: Arc<AtomicBool>,
+    /// Shared with the contents of a child file, and set once its inode has been freed.
+    freed: Arc<AtomicBool>,
+}
+
+
+/// A directory on an ext2 volume.
+pub struct Ext2Directory {
+    name: String,
+    parent: WeakDirRef,
+    /// A weak reference to this directory itself, which is the parent of its children.
+    self_ref: WeakDirRef,
+    volume: Arc<Volume>,
+    inode_number: u32,
+    /// This directory's children, which are read from the volume the first time they're needed.
+    children: Mutex<Option<BTreeMap<String, Child>>>,
+    removed: Arc<AtomicBool>,
+}
+
+impl Ext2Directory {
+    /// Creates a directory node for the directory with the given inode.
+    ///
+    /// This does not insert the new directory into its `parent`.
+    pub(crate) fn new_ref(
+        name: String,
+        parent: WeakDirRef,
+        volume: Arc<Volume>,
+        inode_number: u32,
+        removed: Arc<AtomicBool>,
+    ) -> DirRef {
+        let dir = Ext2Directory {
+            name,
+            parent,
+            self_ref: Weak::<Mutex<Ext2Directory>>::new(),
+            volume,
+            inode_number,
+            children: Mutex::new(None),
+            removed,
+        };
+        let concrete_ref = Arc::new(Mutex::new(dir));
+        let dir_ref = concrete_ref.clone() as DirRef;
+        // Directories refer to themselves as the parent of their children, so this is set after creation.
+        concrete_ref.lock().self_ref = Arc::downgrade(&dir_ref);
+        dir_ref
+    }
+
+    /// Returns the ext2 volume that this directory is on.
+    pub fn volume(&self) -> &Arc<Volume> {
+        &self.volume
+    }
+
+    fn check_exists(&self) -> Result<(), &'static str> {
+        if self.removed.load(Ordering::Acquire) {
+            return Err("ext2: directory has been deleted");
+        }
+        Ok(())
+    }
+
+    /// Returns this directory's children, reading them from the volume if they haven't been yet.
+    fn loaded<'a>(&self, children: &'a mut Option<BTreeMap<String, Child>>) -> Result<&'a mut BTreeMap<String, Child>, &'static str> {
+        self.check_exists()?;
+        let loaded = match children.take() {
+            Some(loaded) => loaded,
+            None => self.load_children()?,
+        };
+        Ok(children.get_or_insert(loaded))
+    }
+
+    fn load_children(&self) -> Result<BTreeMap<String, Child>, &'static str> {
+        let dir_inode = self.volume.read_inode(self.inode_number)?;
+        let mut children = BTreeMap::new();
+        let mut block = vec![0u8; self.volume.block_size()];
+        for logical in 0 .. self.num_blocks(&dir_inode) {
+            let physical = match self.volume.lookup_block(&dir_inode, logical)? {
+                Some(physical) => physical,
+                None => continue,
+            };
+            self.volume.read_at(&mut block, self.volume.block_offset(physical))?;
+            for entry in dir::parse_block(&block, self.volume.has_filetype()) {
+                if entry.inode == 0 || entry.name == b"." || entry.name == b".." {
+                    continue;
+                }
+                let name = String::from_utf8_lossy(&entry.name).into_owned();
+                let child_inode = self.volume.read_inode(entry.inode)?;
+                let removed = Arc::new(AtomicBool::new(false));
+                let freed = Arc::new(AtomicBool::new(false));
+                let node = self.new_child_node(name.clone(), entry.inode, child_inode, &removed, &freed);
+                children.insert(name, Child { node, raw_name: entry.name, inode_number: entry.inode, removed, freed });
+            }
+        }
+        Ok(children)
+    }
+
+    fn new_child_node(&self, name: String, inode_number: u32, inode: Inode, removed: &Arc<AtomicBool>, freed: &Arc<AtomicBool>) -> FileOrDir {
+        if inode.is_dir() {
+            FileOrDir::Dir(Ext2Directory::new_ref(name, self.self_ref.clone(), Arc::clone(&self.volume), inode_number, Arc::clone(removed)))
+        } else {
+            FileOrDir::File(Ext2File::new_ref(name, self.self_ref.clone(), Arc::clone(&self.volume), inode_number, inode, Arc::clone(removed), Arc::clone(freed)))
+        }
+    }
+
+    /// Returns the number of blocks in a directory with the given inode.
+    fn num_blocks(&self, dir_inode: &Inode) -> u64 {
+        let block_size = self.volume.block_size() as u64;
+        (dir_inode.size() + block_size - 1) / block_size
+    }
+
+    /// Marks this directory as no longer having a hashed index before its entries are changed,
+    /// since this driver doesn't update the index. The entries themselves remain readable by any driver.
+    fn drop_index(&self, dir_inode: &mut Inode) -> Result<(), &'static str> {
+        if dir_inode.flags() & INDEX_FL != 0 {
+            dir_inode.set_flags(dir_inode.flags() & !INDEX_FL);
+            self.volume.write_inode(self.inode_number, dir_inode)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an entry for the given child inode to this directory, adding a block to the directory if necessary.
+    fn add_entry(&self, name: &[u8], child_inode: u32, file_type: u8) -> Result<(), &'static str> {
+        let has_filetype = self.volume.has_filetype();
+        let block_size = self.volume.block_size();
+        let needed = dir::entry_len(name.len());
+        let mut dir_inode = self.volume.read_inode(self.inode_number)?;
+        self.drop_index(&mut dir_inode)?;
+        let num_blocks = self.num_blocks(&dir_inode);
+        let mut block = vec![0u8; block_size];
+        for logical in 0 .. num_blocks {
+            let physical = match self.volume.lookup_block(&dir_inode, logical)? {
+                Some(physical) => physical,
+                None => continue,
+            };
+            let offset = self.volume.block_offset(physical);
+            self.volume.read_at(&mut block, offset)?;
+            for entry in dir::parse_block(&block, has_filetype) {
+                // Use the space after an entry's name, which is all of an unused entry.
+                let used = entry.used_len();
+                if entry.rec_len - used >= needed {
+                    if used != 0 {
+                        dir::set_rec_len(&mut block, entry.offset, used);
+                    }
+                    dir::write_entry(&mut block, entry.offset + used, child_inode, entry.rec_len - used, name, file_type, has_filetype);
+                    return self.volume.write_at(&block, offset);
+                }
+            }
+        }
+
+        // There's no room in the existing blocks, so add a block holding just the new entry.
+        let physical = self.volume.map_block(self.inode_number, &mut dir_inode, num_blocks)?;
+        let mut block = vec![0u8; block_size];
+        dir::write_entry(&mut block, 0, child_inode, block_size, name, file_type, has_filetype);
+        self.volume.write_at(&block, self.volume.block_offset(physical))?;
+        dir_inode.set_size((num_blocks + 1) * block_size as u64);
+        dir_inode.touch(inode::unix_time());
+        self.volume.write_inode(self.inode_number, &dir_inode)
+    }
+
+    /// Removes the entry with the given name from this directory.
+    fn remove_entry(&self, name: &[u8]) -> Result<(), &'static str> {
+        let has_filetype = self.volume.has_filetype();
+        let mut dir_inode = self.volume.read_inode(self.inode_number)?;
+        self.drop_index(&mut dir_inode)?;
+        let mut block = vec![0u8; self.volume.block_size()];
+        for logical in 0 .. self.num_blocks(&dir_inode) {
+            let physical = match self.volume.lookup_block(&dir_inode, logical)? {
+                Some(physical) => physical,
+                None => continue,
+            };
+            let offset = self.volume.block_offset(physical);
+            self.volume.read_at(&mut block, offset)?;
+            let entries = dir::parse_block(&block, has_filetype);
+            if let Some(i) = entries.iter().position(|e| e.inode != 0 && e.name == name) {
+                // Merge the entry into the previous one, or mark it unused if it's the first in the block.
+                match i.checked_sub(1).map(|prev| &entries[prev]) {
+                    Some(prev) => dir::set_rec_len(&mut block, prev.offset, prev.rec_len + entries[i].rec_len),
+                    None => block[entries[i].offset .. entries[i].offset + 4].copy_from_slice(&[0; 4]),
+                }
+                return self.volume.write_at(&block, offset);
+            }
+        }
+        Err("ext2: directory entry not found")
+    }
+
+    /// Creates a new file or directory named `name` and returns its node.
+    fn create_child(&self, name: &str, is_dir: bool) -> Result<FileOrDir, &'static str> {
+        if self.volume.is_read_only() {
+            return Err("ext2: volume is mounted read-only");
+        }
+        let mut children = self.children.lock();
+        let children = self.loaded(&mut children)?;
+        dir::validate_name(name)?;
+        if children.contains_key(name) {
+            return Err("ext2: a file or directory with that name already exists");
+        }
+
+        let group = self.volume.inode_group(self.inode_number);
+        let (mode, links_count, file_type) = if is_dir {
+            (S_IFDIR | DEFAULT_DIR_PERMISSIONS, 2, FT_DIR)
+        } else {
+            (S_IFREG | DEFAULT_FILE_PERMISSIONS, 1, FT_REG_FILE)
+        };
+        // New files and directories are owned by the user and group of the task that creates them.
+        let credentials = access_control::current_credentials();
+        let inode_number = self.volume.allocate_inode(group, mode, links_count, (credentials.uid, credentials.gid))?;
+        let result = if is_dir { self.init_dir(inode_number) } else { Ok(()) }
+            .and_then(|_| self.add_entry(name.as_bytes(), inode_number, file_type));
+        if let Err(e) = result {
+            if let Ok(mut inode) = self.volume.read_inode(inode_number) {
+                let _ = self.volume.truncate_blocks(&mut inode, 0);
+                let _ = self.volume.free_inode(inode_number, &mut inode);
+            }
+            return Err(e);
+        }
+        if is_dir {
+            // The new directory's `..` entry is a link to this directory.
+            let mut dir_inode = self.volume.read_inode(self.inode_number)?;
+            dir_inode.set_links_count(dir_inode.links_count() + 1);
+            self.volume.write_inode(self.inode_number, &dir_inode)?;
+        }
+
+        let inode = self.volume.read_inode(inode_number)?;
+        let removed = Arc::new(AtomicBool::new(false));
+        let freed = Arc::new(AtomicBool::new(false));
+        let node = self.new_child_node(name.to_string(), inode_number, inode, &removed, &freed);
+        children.insert(name.to_string(), Child {
+            node: node.clone(),
+            raw_name: name.as_bytes().to_vec(),
+            inode_number,
+            removed,
+            freed,
+        });
+        Ok(node)
+    }
+
+    /// Gives the new directory with the given inode its first block, holding its `.` and `..` entries.
+    fn init_dir(&self, inode_number: u32) -> Result<(), &'static str> {
+        let has_filetype = self.volume.has_filetype();
+        let block_size = self.volume.block_size();
+        let mut inode = self.volume.read_inode(inode_number)?;
+        let physical = self.volume.map_block(inode_number, &mut inode, 0)?;
+        let mut block = vec![0u8; block_size];
+        let dot_len = dir::entry_len(1);
+        dir::write_entry(&mut block, 0, inode_number, dot_len, b".", FT_DIR, has_filetype);
+        dir::write_entry(&mut block, dot_len, self.inode_number, block_size - dot_len, b"..", FT_DIR, has_filetype);
+        self.volume.write_at(&block, self.volume.block_offset(physical))?;
+        inode.set_size(block_size as u64);
+        self.volume.write_inode(inode_number, &inode)
+    }
+
+    /// Deletes the child named `name` from the volume, including the contents of a child directory.
+    fn delete_child(&self, name: &str) -> Result<FileOrDir, &'static str> {
+        if self.volume.is_read_only() {
+            return Err("ext2: volume is mounted read-only");
+        }
+        let node = {
+            let mut children = self.children.lock();
+            let children = self.loaded(&mut children)?;
+            children.get(name).map(|c| c.node.clone()).ok_or("ext2: no such file or directory")?
+        };
+
+        // Empty a directory through its own `Directory` implementation,
+        // so that the nodes of its contents are marked as removed too.
+        if let FileOrDir::Dir(ref dir) = node {
+            let mut dir = dir.lock();
+            for child_name in dir.list() {
+                if let Some(grandchild) = dir.get(&child_name) {
+                    dir.remove(&grandchild).ok_or("ext2: couldn't remove the directory's contents")?;
+                }
+            }
+        }
+
+        let child = {
+            let mut children = self.children.lock();
+            self.loaded(&mut children)?.remove(name).ok_or("ext2: no such file or directory")?
+        };
+        child.removed.store(true, Ordering::Release);
+        self.remove_entry(&child.raw_name)?;
+
+        let mut inode = self.volume.read_inode(child.inode_number)?;
+        if inode.is_dir() {
+            // A directory's links are its entry in this directory and its own `.` entry,
+            // and its `..` entry was a link to this directory.
+            inode.set_links_count(0);
+            let mut dir_inode = self.volume.read_inode(self.inode_number)?;
+            dir_inode.set_links_count(dir_inode.links_count().saturating_sub(1));
+            self.volume.write_inode(self.inode_number, &dir_inode)?;
+        } else {
+            inode.set_links_count(inode.links_count().saturating_sub(1));
+        }
+        if inode.links_count() == 0 {
+            // The cached pages are keyed by the inode, so they must be dropped before it can be reused,
+            // but only once no other links to it remain. They're never written back to its freed blocks.
+            child.freed.store(true, Ordering::Release);
+            if let FileOrDir::File(ref file) = child.node {
+                page_cache::invalidate_file(file);
+            }
+            self.volume.truncate_blocks(&mut inode, 0)?;
+            self.volume.free_inode(child.inode_number, &mut inode)?;
+        } else {
+            self.volume.write_inode(child.inode_number, &inode)?;
+        }
+        Ok(child.node)
+    }
+}
+
+impl Directory for Ext2Directory {
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let mut children = self.children.lock();
+        let children = self.loaded(&mut children).ok()?;
+        children.get(name).map(|c| c.node.clone())
+    }
+
+    fn list(&self) -> Vec<String> {
+        let mut children = self.children.lock();
+        match self.loaded(&mut children) {
+            Ok(children) => children.keys().cloned().collect(),
+            Err(e) => {
+                error!("ext2: couldn't read directory {:?}: {}", self.name, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// ext2 directories can only hold nodes that exist on the volume,
+    /// so new nodes must be created with `create_file()` or `create_dir()` instead.
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("ext2: nodes can't be inserted into an ext2 directory, use create_file() or create_dir() instead")
+    }
+
+    /// Deletes the given node from the volume, freeing its inode and blocks once it has no other links.
+    /// If the node is a directory, all of its contents are deleted too.
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        let name = node.get_name();
+        match self.delete_child(&name) {
+            Ok(mut removed) => {
+                removed.set_parent_dir(Weak::<Mutex<Ext2Directory>>::new());
+                Some(removed)
+            }
+            Err(e) => {
+                error!("ext2: couldn't remove {:?}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn create_file(&mut self, name: &str) -> Result<FileRef, &'static str> {
+        match self.create_child(name, false)? {
+            FileOrDir::File(file) => Ok(file),
+            FileOrDir::Dir(_) => Err("ext2: created a directory instead of a file"),
+        }
+    }
+
+    fn create_dir(&mut self, name: &str) -> Result<DirRef, &'static str> {
+        match self.create_child(name, true)? {
+            FileOrDir::Dir(dir) => Ok(dir),
+            FileOrDir::File(_) => Err("ext2: created a file instead of a directory"),
+        }
+    }
+}
+
+impl FsNode for Ext2Directory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        // A directory's inode isn't cached, since it changes whenever the directory's contents do.
+        match self.check_exists().and_then(|_| self.volume.read_inode(self.inode_number)) {
+            Ok(inode) => inode_metadata(&self.volume, self.inode_number, &inode),
+            Err(_) => Metadata::default(),
+        }
+    }
+
+    fn set_permissions(&mut self, permissions: u16) -> Result<(), &'static str> {
+        self.check_exists()?;
+        let mut inode = self.volume.read_inode(self.inode_number)?;
+        inode.set_permissions(permissions);
+        write_changed_inode(&self.volume, self.inode_number, &mut inode)
+    }
+
+    fn set_owner(&mut self, owner: u32, group: u32) -> Result<(), &'static str> {
+        self.check_exists()?;
+        let mut inode = self.volume.read_inode(self.inode_number)?;
+        inode.set_owner(owner, group);
+        write_changed_inode(&self.volume, self.inode_number, &mut inode)
+    }
+}
+
+
+/// Returns the metadata recorded in the given inode of the given volume.
+fn inode_metadata(volume: &Volume, inode_number: u32, inode: &Inode) -> Metadata {
+    Metadata {
+        filesystem: Some(volume.id()),
+        inode: Some(inode_number as u64),
+        // ext2 doesn't record when an inode was created.
+        created: None,
+        modified: Some(inode.modification_time() as u64),
+        accessed: Some(inode.access_time() as u64),
+        permissions: Some(inode.permissions()),
+        owner: Some(inode.uid()),
+        group: Some(inode.gid()),
+        allocated_bytes: Some(inode.sectors() as usize * 512),
+    }
+}
+
+/// Writes back an inode whose metadata has changed, updating its change time.
+fn write_changed_inode(volume: &Volume, inode_number: u32, inode: &mut Inode) -> Result<(), &'static str> {
+    if volume.is_read_only() {
+        return Err("ext2: volume is mounted read-only");
+    }
+    inode.set_change_time(inode::unix_time());
+    volume.write_inode(inode_number, inode)
+}
+
+
+/// A file on an ext2 volume, which may also be a symbolic link or special file.
+///
+/// Its contents are read and written through the page cache,
+/// which reads and writes back its pages through the file's `Ext2Contents`.
+pub struct Ext2File {
+    name: String,
+    parent: WeakDirRef,
+    contents: Arc<Ext2Contents>,
+    removed: Arc<AtomicBool>,
+}
+
+impl Ext2File {
+    /// Creates a file node for the file with the given inode, and registers its contents with the page cache.
+    ///
+    /// This does not insert the new file into its `parent`.
+    fn new_ref(
+        name: String,
+        parent: WeakDirRef,
+        volume: Arc<Volume>,
+        inode_number: u32,
+        inode: Inode,
+        removed: Arc<AtomicBool>,
+        freed: Arc<AtomicBool>,
+    ) -> FileRef {
+        let file = Ext2File {
+            name,
+            parent,
+            contents: Arc::new(Ext2Contents { volume, inode_number, inode: Mutex::new(inode), freed }),
+            removed,
+        };
+        page_cache::register_file(file.id(), &file.backing());
+        Arc::new(Mutex::new(file))
+    }
+
+    /// Returns the number of this file's inode.
+    pub fn inode_number(&self) -> u32 {
+        self.contents.inode_number
+    }
+
+    /// Returns the ID of this file's pages in the page cache.
+    fn id(&self) -> FileId {
+        FileId::Inode { filesystem: self.contents.volume.id(), inode: self.contents.inode_number as u64 }
+    }
+
+    /// Returns this file's contents as the backing of its pages in the page cache.
+    fn backing(&self) -> Arc<dyn PageBacking> {
+        self.contents.clone()
+    }
+
+    fn check_exists(&self) -> Result<(), &'static str> {
+        if self.removed.load(Ordering::Acquire) {
+            return Err("ext2: file has been deleted");
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self, inode: &Inode) -> Result<(), &'static str> {
+        self.check_exists()?;
+        if self.contents.volume.is_read_only() {
+            return Err("ext2: volume is mounted read-only");
+        }
+        if inode.is_symlink() {
+            return Err("ext2: symbolic links cannot be modified");
+        }
+        Ok(())
+    }
+
+    /// Allocates the blocks of this file that hold the `len` bytes at `offset`, if they aren't already allocated.
+    fn allocate_blocks(&self, inode: &mut Inode, offset: usize, len: usize) -> Result<(), &'static str> {
+        let block_size = self.contents.volume.block_size();
+        for logical in offset / block_size ..= (offset + len - 1) / block_size {
+            self.contents.volume.map_block(self.contents.inode_number, inode, logical as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the size of this file, marking the volume as holding large files if necessary.
+    fn set_size(&self, inode: &mut Inode, size: u64) -> Result<(), &'static str> {
+        if size > LARGE_FILE_THRESHOLD {
+            self.contents.volume.enable_large_files()?;
+        }
+        inode.set_size(size);
+        Ok(())
+    }
+}
+
+impl File for Ext2File {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        self.check_exists()?;
+        let inode = self.contents.inode.lock().clone();
+        let size = inode.size() as usize;
+        if offset > size {
+            return Err("read offset exceeds file size");
+        }
+
+        // A "fast" symbolic link stores its target in the inode's block addresses.
+        if inode.is_symlink() && inode.sectors() == 0 {
+            let target = inode.block_bytes();
+            let end = min(offset + min(size - offset, buffer.len()), target.len());
+            buffer[.. end - offset].copy_from_slice(&target[offset .. end]);
+            return Ok(end - offset);
+        }
+
+        page_cache::read_file_pages(self.id(), &self.backing(), size, buffer, offset)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        let size = {
+            let mut inode = self.contents.inode.lock();
+            self.check_writable(&inode)?;
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+            let end = offset.checked_add(buffer.len())
+                .filter(|&end| end as u64 <= self.contents.volume.max_file_size())
+                .ok_or("ext2: write would exceed the maximum file size")?;
+            // The blocks are allocated and the file is extended first, such that running out of space fails the write,
+            // because the page cache only writes within the file's size and writes its pages back later.
+            let result = self.allocate_blocks(&mut inode, offset, buffer.len());
+            // The inode is written back even if allocating failed, since some blocks may have been allocated.
+            if result.is_ok() && end as u64 > inode.size() {
+                self.set_size(&mut inode, end as u64)?;
+            }
+            inode.touch(inode::unix_time());
+            self.contents.volume.write_inode(self.contents.inode_number, &inode)?;
+            result?;
+            inode.size() as usize
+        };
+        page_cache::write_file_pages(self.id(), &self.backing(), size, buffer, offset)
+    }
+
+    fn size(&self) -> usize {
+        self.contents.inode.lock().size() as usize
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("cannot treat an ext2 file as a memory mapped region, use memory_mapped_file instead")
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), &'static str> {
+        let volume = &self.contents.volume;
+        let mut inode = self.contents.inode.lock();
+        self.check_writable(&inode)?;
+        let size = size as u64;
+        if size > volume.max_file_size() {
+            return Err("ext2: file would exceed the maximum file size");
+        }
+        if size < inode.size() {
+            let block_size = volume.block_size() as u64;
+            volume.truncate_blocks(&mut inode, (size + block_size - 1) / block_size)?;
+            // Zero the rest of the new last block, so that it reads as zeros if the file grows again.
+            let within = (size % block_size) as usize;
+            if within != 0 {
+                if let Some(physical) = volume.lookup_block(&inode, size / block_size)? {
+                    let zeros = vec![0u8; block_size as usize - within];
+                    volume.write_at(&zeros, volume.block_offset(physical) + within)?;
+                }
+            }
+            page_cache::truncate_file_pages(self.id(), size as usize);
+        }
+        // Growing a file leaves a hole, which reads as zeros.
+        self.set_size(&mut inode, size)?;
+        inode.touch(inode::unix_time());
+        volume.write_inode(self.contents.inode_number, &inode)
+    }
+}
+
+impl FsNode for Ext2File {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        inode_metadata(&self.contents.volume, self.contents.inode_number, &self.contents.inode.lock())
+    }
+
+    fn set_permissions(&mut self, permissions: u16) -> Result<(), &'static str> {
+        self.check_exists()?;
+        let mut inode = self.contents.inode.lock();
+        let mut changed = inode.clone();
+        changed.set_permissions(permissions);
+        write_changed_inode(&self.contents.volume, self.contents.inode_number, &mut changed)?;
+        *inode = changed;
+        Ok(())
+    }
+
+    fn set_owner(&mut self, owner: u32, group: u32) -> Result<(), &'static str> {
+        self.check_exists()?;
+        let mut inode = self.contents.inode.lock();
+        let mut changed = inode.clone();
+        changed.set_owner(owner, group);
+        write_changed_inode(&self.contents.volume, self.contents.inode_number, &mut changed)?;
+        *inode = changed;
+        Ok(())
+    }
+}
+
+
+/// The inode that holds the contents of an `Ext2File`, which backs the file's pages in the page cache.
+///
+/// It's shared by the file's node and its cached pages, such that its dirty pages can be written back without locking its node.
+struct Ext2Contents {
+    volume: Arc<Volume>,
+    inode_number: u32,
+    /// The file's inode, which is written back to the volume whenever it changes.
+    inode: Mutex<Inode>,
+    /// Shared with the file's `Child`, and set once its inode has been freed.
+    freed: Arc<AtomicBool>,
+}
+
+impl Ext2Contents {
+    /// Calls `f` for each block-sized piece of the page at index `page` that lies within the file,
+    /// with the piece's logical block, its offset within that block, and its range within the page.
+    fn for_each_block<F>(&self, inode: &mut Inode, page: usize, mut f: F) -> Result<(), &'static str>
+        where F: FnMut(&mut Inode, u64, usize, Range<usize>) -> Result<(), &'static str>
+    {
+        let block_size = self.volume.block_size();
+        let offset = page * PAGE_SIZE;
+        let len = min(PAGE_SIZE, (inode.size() as usize).saturating_sub(offset));
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let within = pos % block_size;
+            let n = min(block_size - within, len - done);
+            f(inode, (pos / block_size) as u64, within, done .. done + n)?;
+            done += n;
+        }
+        Ok(())
+    }
+}
+
+impl PageBacking for Ext2Contents {
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<(), &'static str> {
+        for b in buffer.iter_mut() { *b = 0; }
+        let mut inode = self.inode.lock();
+        self.for_each_block(&mut inode, page, |inode, logical, within, range| {
+            match self.volume.lookup_block(inode, logical)? {
+                Some(physical) => self.volume.read_at(&mut buffer[range], self.volume.block_offset(physical) + within),
+                // Holes in sparse files read as zeros.
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn write_page(&self, page: usize, buffer: &[u8]) -> Result<(), &'static str> {
+        let mut inode = self.inode.lock();
+        // A freed inode's blocks may already belong to another file.
+        if self.freed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let mut allocated = false;
+        self.for_each_block(&mut inode, page, |inode, logical, within, range| {
+            let data = &buffer[range];
+            let physical = match self.volume.lookup_block(inode, logical)? {
+                Some(physical) => physical,
+                // Holes were only written to if the page was modified through a mapping.
+                None if data.iter().all(|&b| b == 0) => return Ok(()),
+                None => {
+                    allocated = true;
+                    self.volume.map_block(self.inode_number, inode, logical)?
+                }
+            };
+            self.volume.write_at(data, self.volume.block_offset(physical) + within)
+        })?;
+        if allocated {
+            self.volume.write_inode(self.inode_number, &inode)?;
+        }
+        Ok(())
+    }
+}
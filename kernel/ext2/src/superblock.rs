@@ -0,0 +1,199 @@
+//! Parsing of the ext2 superblock and block group descriptors,
+//! and of the MBR partition table that may precede an ext2 volume.
+
+/// The byte offset of the superblock from the start of the volume, regardless of block size.
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+/// The size of the superblock.
+pub const SUPERBLOCK_SIZE: usize = 1024;
+/// The magic number that identifies an ext2 superblock.
+pub const EXT2_MAGIC: u16 = 0xEF53;
+/// The size of a block group descriptor.
+pub const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+/// The byte offsets of the superblock fields that are updated while the volume is mounted.
+pub const SB_FREE_BLOCKS_OFFSET: usize = 12;
+pub const SB_FREE_INODES_OFFSET: usize = 16;
+pub const SB_FEATURE_RO_COMPAT_OFFSET: usize = 100;
+
+/// Directory entries hold the type of the inode they refer to.
+pub const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Only some block groups hold backups of the superblock and group descriptors.
+pub const FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Regular files may be larger than 2 GiB, using the upper 32 bits of their size.
+pub const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+/// The incompatible features this driver supports; a volume with any others cannot be mounted.
+const SUPPORTED_INCOMPAT: u32 = FEATURE_INCOMPAT_FILETYPE;
+/// The read-only-compatible features this driver supports; a volume with any others is mounted read-only.
+const SUPPORTED_RO_COMPAT: u32 = FEATURE_RO_COMPAT_SPARSE_SUPER | FEATURE_RO_COMPAT_LARGE_FILE;
+
+/// The first inode number of revision 0 volumes, which don't record it.
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+/// The inode size of revision 0 volumes, which don't record it.
+const GOOD_OLD_INODE_SIZE: u16 = 128;
+
+/// The MBR partition type of a Linux native partition.
+const LINUX_PARTITION_TYPE: u8 = 0x83;
+const MBR_SIGNATURE: u16 = 0xAA55;
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_SIZE: usize = 16;
+const NUM_PARTITION_ENTRIES: usize = 4;
+/// The size of the sector that holds the MBR.
+pub const MBR_SIZE: usize = 512;
+
+
+pub(crate) fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub(crate) fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+pub(crate) fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset .. offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset .. offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+
+/// The fields of an ext2 superblock that describe the layout of the volume.
+#[derive(Clone, Debug)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    pub block_size: usize,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub first_inode: u32,
+    pub inode_size: u16,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    pub volume_name: [u8; 16],
+}
+
+impl Superblock {
+    /// Parses the given superblock, returning an error if it doesn't describe an ext2 volume
+    /// that this driver can mount.
+    pub fn parse(raw: &[u8]) -> Result<Superblock, &'static str> {
+        if raw.len() < SUPERBLOCK_SIZE || read_u16(raw, 56) != EXT2_MAGIC {
+            return Err("ext2: missing superblock magic number");
+        }
+        let log_block_size = read_u32(raw, 24);
+        // Like Linux, only support blocks that are no larger than a page.
+        if log_block_size > 2 {
+            return Err("ext2: block sizes larger than 4 KiB are not supported");
+        }
+        let revision = read_u32(raw, 76);
+        let (first_inode, inode_size) = if revision == 0 {
+            (GOOD_OLD_FIRST_INODE, GOOD_OLD_INODE_SIZE)
+        } else {
+            (read_u32(raw, 84), read_u16(raw, 88))
+        };
+        let mut volume_name = [0u8; 16];
+        volume_name.copy_from_slice(&raw[120..136]);
+
+        let sb = Superblock {
+            inodes_count: read_u32(raw, 0),
+            blocks_count: read_u32(raw, 4),
+            free_blocks_count: read_u32(raw, SB_FREE_BLOCKS_OFFSET),
+            free_inodes_count: read_u32(raw, SB_FREE_INODES_OFFSET),
+            first_data_block: read_u32(raw, 20),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: read_u32(raw, 32),
+            inodes_per_group: read_u32(raw, 40),
+            first_inode,
+            inode_size,
+            feature_incompat: if revision == 0 { 0 } else { read_u32(raw, 96) },
+            feature_ro_compat: if revision == 0 { 0 } else { read_u32(raw, SB_FEATURE_RO_COMPAT_OFFSET) },
+            volume_name,
+        };
+
+        if sb.feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err("ext2: volume uses incompatible features (e.g., ext3/ext4 extents or journaling)");
+        }
+        if sb.blocks_per_group == 0 || sb.inodes_per_group == 0 || sb.blocks_per_group as usize > sb.block_size * 8 {
+            return Err("ext2: invalid blocks or inodes per group");
+        }
+        if (sb.inode_size as usize) < GOOD_OLD_INODE_SIZE as usize || !sb.inode_size.is_power_of_two() || sb.inode_size as usize > sb.block_size {
+            return Err("ext2: invalid inode size");
+        }
+        if sb.first_data_block >= sb.blocks_count {
+            return Err("ext2: invalid block count");
+        }
+        Ok(sb)
+    }
+
+    /// Returns the number of block groups in the volume.
+    pub fn group_count(&self) -> usize {
+        let data_blocks = (self.blocks_count - self.first_data_block) as usize;
+        (data_blocks + self.blocks_per_group as usize - 1) / self.blocks_per_group as usize
+    }
+
+    /// Returns the block number of the first block of the group descriptor table.
+    pub fn group_descriptor_block(&self) -> u32 {
+        self.first_data_block + 1
+    }
+
+    /// Returns whether the volume uses features this driver doesn't know how to update,
+    /// in which case it can only be mounted read-only.
+    pub fn requires_read_only(&self) -> bool {
+        self.feature_ro_compat & !SUPPORTED_RO_COMPAT != 0
+    }
+
+    /// Returns whether directory entries hold the types of their inodes.
+    pub fn has_filetype(&self) -> bool {
+        self.feature_incompat & FEATURE_INCOMPAT_FILETYPE != 0
+    }
+}
+
+
+/// A block group descriptor, which locates a group's bitmaps and inode table.
+#[derive(Clone, Debug)]
+pub struct GroupDescriptor {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+}
+
+impl GroupDescriptor {
+    pub fn parse(raw: &[u8]) -> GroupDescriptor {
+        GroupDescriptor {
+            block_bitmap: read_u32(raw, 0),
+            inode_bitmap: read_u32(raw, 4),
+            inode_table: read_u32(raw, 8),
+            free_blocks_count: read_u16(raw, 12),
+            free_inodes_count: read_u16(raw, 14),
+            used_dirs_count: read_u16(raw, 16),
+        }
+    }
+
+    /// Writes the fields of this descriptor into the given on-disk descriptor, preserving its other fields.
+    pub fn encode_into(&self, raw: &mut [u8]) {
+        write_u32(raw, 0, self.block_bitmap);
+        write_u32(raw, 4, self.inode_bitmap);
+        write_u32(raw, 8, self.inode_table);
+        write_u16(raw, 12, self.free_blocks_count);
+        write_u16(raw, 14, self.free_inodes_count);
+        write_u16(raw, 16, self.used_dirs_count);
+    }
+}
+
+
+/// Returns the partition numbers (starting at 1) and starting sector numbers
+/// of the Linux partitions in the given MBR `sector`.
+pub fn linux_partitions(sector: &[u8]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let valid = sector.len() >= MBR_SIZE && read_u16(sector, 510) == MBR_SIGNATURE;
+    (0 .. if valid { NUM_PARTITION_ENTRIES } else { 0 })
+        .map(move |i| (i + 1, &sector[PARTITION_TABLE_OFFSET + i * PARTITION_ENTRY_SIZE ..][.. PARTITION_ENTRY_SIZE]))
+        .filter(|&(_, entry)| entry[4] == LINUX_PARTITION_TYPE)
+        .map(|(number, entry)| (number, read_u32(entry, 8) as usize))
+        .filter(|&(_, start)| start != 0)
+}
@@ -0,0 +1,457 @@
+//! Access to a mounted ext2 volume: its block groups, block and inode bitmaps,
+//! inode tables, and the mapping of a file's logical blocks to blocks on the volume.
+//!
+//! All reads and writes go through the page cache, so metadata updates are cheap
+//! and are written back to the device by the page cache's periodic flusher.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use block_io::BlockQueue;
+use inode::{Inode, S_IFMT, S_IFDIR, NUM_DIRECT_BLOCKS, SINGLE_INDIRECT, DOUBLE_INDIRECT, TRIPLE_INDIRECT};
+use superblock::{
+    Superblock, GroupDescriptor, read_u32, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE, GROUP_DESCRIPTOR_SIZE,
+    SB_FREE_BLOCKS_OFFSET, SB_FREE_INODES_OFFSET, SB_FEATURE_RO_COMPAT_OFFSET, FEATURE_RO_COMPAT_LARGE_FILE,
+};
+
+
+/// The state of block and inode allocation, cached from and written back to
+/// the group descriptors and superblock.
+struct AllocState {
+    groups: Vec<GroupDescriptor>,
+    free_blocks: u32,
+    free_inodes: u32,
+    feature_ro_compat: u32,
+}
+
+/// An ext2 volume on a block device.
+pub struct Volume {
+    queue: BlockQueue,
+    /// The byte offset of the volume within the block device.
+    offset: usize,
+    sb: Superblock,
+    read_only: bool,
+    alloc: Mutex<AllocState>,
+    /// The number that identifies this volume in its nodes' metadata.
+    id: u64,
+}
+
+impl Volume {
+    /// Opens the ext2 volume that begins at the byte `offset` of the given block device.
+    pub fn open(queue: BlockQueue, offset: usize) -> Result<Volume, &'static str> {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        read_exact(&queue, &mut raw, offset + SUPERBLOCK_OFFSET)?;
+        let sb = Superblock::parse(&raw)?;
+
+        let group_count = sb.group_count();
+        let mut descriptors = vec![0u8; group_count * GROUP_DESCRIPTOR_SIZE];
+        read_exact(&queue, &mut descriptors, offset + sb.group_descriptor_block() as usize * sb.block_size)?;
+        let groups: Vec<GroupDescriptor> = descriptors.chunks(GROUP_DESCRIPTOR_SIZE).map(GroupDescriptor::parse).collect();
+        if groups.iter().any(|g| g.inode_table == 0 || g.inode_table >= sb.blocks_count) {
+            return Err("ext2: invalid block group descriptor");
+        }
+
+        Ok(Volume {
+            read_only: sb.requires_read_only(),
+            alloc: Mutex::new(AllocState {
+                groups,
+                free_blocks: sb.free_blocks_count,
+                free_inodes: sb.free_inodes_count,
+                feature_ro_compat: sb.feature_ro_compat,
+            }),
+            id: ::fs_node::new_filesystem_id(),
+            queue,
+            offset,
+            sb,
+        })
+    }
+
+    /// Returns the block device request queue that this volume is on.
+    pub fn queue(&self) -> &BlockQueue {
+        &self.queue
+    }
+
+    /// Returns the volume name from the superblock.
+    pub fn name(&self) -> &[u8] {
+        let len = self.sb.volume_name.iter().position(|&b| b == 0).unwrap_or(self.sb.volume_name.len());
+        &self.sb.volume_name[..len]
+    }
+
+    /// Returns the number that identifies this volume in its nodes' metadata.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns whether this volume uses features that prevent it from being modified.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns the size in bytes of a block.
+    pub fn block_size(&self) -> usize {
+        self.sb.block_size
+    }
+
+    /// Returns whether directory entries on this volume hold the types of their inodes.
+    pub fn has_filetype(&self) -> bool {
+        self.sb.has_filetype()
+    }
+
+    /// Returns the number of free blocks and free inodes.
+    pub fn free_counts(&self) -> (u32, u32) {
+        let state = self.alloc.lock();
+        (state.free_blocks, state.free_inodes)
+    }
+
+    /// Reads from the volume into the `buffer`, starting at the volume-relative byte `offset`.
+    pub fn read_at(&self, buffer: &mut [u8], offset: usize) -> Result<(), &'static str> {
+        read_exact(&self.queue, buffer, self.offset + offset)
+    }
+
+    /// Writes the `buffer` to the volume, starting at the volume-relative byte `offset`.
+    pub fn write_at(&self, buffer: &[u8], offset: usize) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("ext2: volume is mounted read-only");
+        }
+        let written = page_cache::write_block_device(&self.queue, buffer, self.offset + offset)?;
+        if written != buffer.len() {
+            return Err("ext2: write extends past the end of the device");
+        }
+        Ok(())
+    }
+
+    /// Writes all of this volume's dirty pages back to the device.
+    pub fn sync(&self) -> Result<usize, &'static str> {
+        page_cache::sync_block_device(&self.queue)
+    }
+
+    /// Returns the volume-relative byte offset of the given `block`.
+    pub fn block_offset(&self, block: u32) -> usize {
+        block as usize * self.sb.block_size
+    }
+
+
+    /// Returns the block group that holds the given inode.
+    pub fn inode_group(&self, inode_number: u32) -> usize {
+        ((inode_number - 1) / self.sb.inodes_per_group) as usize
+    }
+
+    /// Returns the volume-relative byte offset of the given inode within its group's inode table.
+    fn inode_offset(&self, inode_number: u32) -> Result<usize, &'static str> {
+        if inode_number == 0 || inode_number > self.sb.inodes_count {
+            return Err("ext2: invalid inode number");
+        }
+        let index = ((inode_number - 1) % self.sb.inodes_per_group) as usize;
+        let inode_table = self.alloc.lock().groups[self.inode_group(inode_number)].inode_table;
+        Ok(self.block_offset(inode_table) + index * self.sb.inode_size as usize)
+    }
+
+    /// Reads the given inode from its inode table.
+    pub fn read_inode(&self, inode_number: u32) -> Result<Inode, &'static str> {
+        let mut raw = vec![0u8; self.sb.inode_size as usize];
+        self.read_at(&mut raw, self.inode_offset(inode_number)?)?;
+        Ok(Inode::from_raw(raw))
+    }
+
+    /// Writes the given inode to its inode table.
+    pub fn write_inode(&self, inode_number: u32, inode: &Inode) -> Result<(), &'static str> {
+        self.write_at(inode.as_bytes(), self.inode_offset(inode_number)?)
+    }
+
+    /// Creates a new inode with the given mode, link count and owning user and group,
+    /// preferably in the given block group, and returns its number.
+    pub fn allocate_inode(&self, preferred_group: usize, mode: u16, links_count: u16, owner: (u32, u32)) -> Result<u32, &'static str> {
+        let is_dir = mode & S_IFMT == S_IFDIR;
+        let inode_number = {
+            let mut state = self.alloc.lock();
+            if state.free_inodes == 0 {
+                return Err("ext2: no free inodes left on the volume");
+            }
+            let mut allocated = None;
+            for i in 0 .. state.groups.len() {
+                let group = (preferred_group + i) % state.groups.len();
+                if state.groups[group].free_inodes_count == 0 {
+                    continue;
+                }
+                let bitmap = state.groups[group].inode_bitmap;
+                // The inodes before the first non-reserved inode are never allocated.
+                let start = if group == 0 { self.sb.first_inode as usize - 1 } else { 0 };
+                if let Some(index) = self.allocate_bit(bitmap, start, self.sb.inodes_per_group as usize)? {
+                    let desc = &mut state.groups[group];
+                    desc.free_inodes_count -= 1;
+                    if is_dir {
+                        desc.used_dirs_count += 1;
+                    }
+                    state.free_inodes -= 1;
+                    self.write_alloc_state(&state, group)?;
+                    allocated = Some(group as u32 * self.sb.inodes_per_group + index as u32 + 1);
+                    break;
+                }
+            }
+            allocated.ok_or("ext2: no free inodes left on the volume")?
+        };
+        let inode = Inode::new(self.sb.inode_size as usize, mode, links_count, owner, ::inode::unix_time());
+        self.write_inode(inode_number, &inode)?;
+        Ok(inode_number)
+    }
+
+    /// Frees the given inode, which must no longer have any blocks or links.
+    pub fn free_inode(&self, inode_number: u32, inode: &mut Inode) -> Result<(), &'static str> {
+        let is_dir = inode.is_dir();
+        inode.set_links_count(0);
+        inode.set_deletion_time(::inode::unix_time());
+        self.write_inode(inode_number, inode)?;
+
+        let group = self.inode_group(inode_number);
+        let index = ((inode_number - 1) % self.sb.inodes_per_group) as usize;
+        let mut state = self.alloc.lock();
+        self.free_bit(state.groups[group].inode_bitmap, index)?;
+        let desc = &mut state.groups[group];
+        desc.free_inodes_count += 1;
+        if is_dir {
+            desc.used_dirs_count = desc.used_dirs_count.saturating_sub(1);
+        }
+        state.free_inodes += 1;
+        self.write_alloc_state(&state, group)
+    }
+
+    /// Allocates a free block, preferably in the given block group, fills it with zeros, and returns its number.
+    pub fn allocate_block(&self, preferred_group: usize) -> Result<u32, &'static str> {
+        let block = {
+            let mut state = self.alloc.lock();
+            if state.free_blocks == 0 {
+                return Err("ext2: no free blocks left on the volume");
+            }
+            let mut allocated = None;
+            for i in 0 .. state.groups.len() {
+                let group = (preferred_group + i) % state.groups.len();
+                if state.groups[group].free_blocks_count == 0 {
+                    continue;
+                }
+                let bitmap = state.groups[group].block_bitmap;
+                if let Some(index) = self.allocate_bit(bitmap, 0, self.blocks_in_group(group))? {
+                    state.groups[group].free_blocks_count -= 1;
+                    state.free_blocks -= 1;
+                    self.write_alloc_state(&state, group)?;
+                    allocated = Some(self.sb.first_data_block + group as u32 * self.sb.blocks_per_group + index as u32);
+                    break;
+                }
+            }
+            allocated.ok_or("ext2: no free blocks left on the volume")?
+        };
+        let zeros = vec![0u8; self.sb.block_size];
+        self.write_at(&zeros, self.block_offset(block))?;
+        Ok(block)
+    }
+
+    /// Frees the given block.
+    pub fn free_block(&self, block: u32) -> Result<(), &'static str> {
+        if block < self.sb.first_data_block || block >= self.sb.blocks_count {
+            return Err("ext2: invalid block number");
+        }
+        let relative = block - self.sb.first_data_block;
+        let group = (relative / self.sb.blocks_per_group) as usize;
+        let mut state = self.alloc.lock();
+        self.free_bit(state.groups[group].block_bitmap, (relative % self.sb.blocks_per_group) as usize)?;
+        state.groups[group].free_blocks_count += 1;
+        state.free_blocks += 1;
+        self.write_alloc_state(&state, group)
+    }
+
+    /// Marks the volume as holding files larger than 2 GiB, which older drivers can't modify.
+    pub fn enable_large_files(&self) -> Result<(), &'static str> {
+        let mut state = self.alloc.lock();
+        if state.feature_ro_compat & FEATURE_RO_COMPAT_LARGE_FILE == 0 {
+            state.feature_ro_compat |= FEATURE_RO_COMPAT_LARGE_FILE;
+            self.write_at(&state.feature_ro_compat.to_le_bytes(), SUPERBLOCK_OFFSET + SB_FEATURE_RO_COMPAT_OFFSET)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of blocks in the given block group; the last group may be smaller than the others.
+    fn blocks_in_group(&self, group: usize) -> usize {
+        let first = self.sb.first_data_block as usize + group * self.sb.blocks_per_group as usize;
+        core::cmp::min(self.sb.blocks_per_group as usize, self.sb.blocks_count as usize - first)
+    }
+
+    /// Finds a clear bit from bit `start` up to bit `limit` of the given bitmap block, sets it, and returns its index.
+    fn allocate_bit(&self, bitmap_block: u32, start: usize, limit: usize) -> Result<Option<usize>, &'static str> {
+        let mut bitmap = vec![0u8; self.sb.block_size];
+        let offset = self.block_offset(bitmap_block);
+        self.read_at(&mut bitmap, offset)?;
+        let index = match (start .. limit).find(|&i| bitmap[i / 8] & (1 << (i % 8)) == 0) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let byte = bitmap[index / 8] | 1 << (index % 8);
+        self.write_at(&[byte], offset + index / 8)?;
+        Ok(Some(index))
+    }
+
+    /// Clears the given bit of the given bitmap block.
+    fn free_bit(&self, bitmap_block: u32, index: usize) -> Result<(), &'static str> {
+        let offset = self.block_offset(bitmap_block) + index / 8;
+        let mut byte = [0u8];
+        self.read_at(&mut byte, offset)?;
+        if byte[0] & (1 << (index % 8)) == 0 {
+            return Err("ext2: freeing a block or inode that is already free");
+        }
+        self.write_at(&[byte[0] & !(1 << (index % 8))], offset)
+    }
+
+    /// Writes the given group's descriptor and the superblock's free counts to the volume.
+    fn write_alloc_state(&self, state: &AllocState, group: usize) -> Result<(), &'static str> {
+        let desc_offset = self.block_offset(self.sb.group_descriptor_block()) + group * GROUP_DESCRIPTOR_SIZE;
+        let mut raw = [0u8; GROUP_DESCRIPTOR_SIZE];
+        self.read_at(&mut raw, desc_offset)?;
+        state.groups[group].encode_into(&mut raw);
+        self.write_at(&raw, desc_offset)?;
+        self.write_at(&state.free_blocks.to_le_bytes(), SUPERBLOCK_OFFSET + SB_FREE_BLOCKS_OFFSET)?;
+        self.write_at(&state.free_inodes.to_le_bytes(), SUPERBLOCK_OFFSET + SB_FREE_INODES_OFFSET)
+    }
+
+
+    /// Returns the number of block addresses in an indirect block.
+    fn pointers_per_block(&self) -> u64 {
+        (self.sb.block_size / 4) as u64
+    }
+
+    /// Returns the maximum size of a file, which is limited by the number of blocks its inode can address.
+    pub fn max_file_size(&self) -> u64 {
+        let p = self.pointers_per_block();
+        let blocks = NUM_DIRECT_BLOCKS as u64 + p + p * p + p * p * p;
+        // Block counts are kept in 512-byte sectors in a 32-bit field.
+        core::cmp::min(blocks * self.sb.block_size as u64, u32::max_value() as u64 * 512)
+    }
+
+    /// Returns the inode block pointer and the indices into each level of indirect blocks
+    /// that lead to the given logical block of a file.
+    fn block_path(&self, logical: u64) -> Result<(usize, Vec<usize>), &'static str> {
+        let p = self.pointers_per_block();
+        let mut rest = logical;
+        if rest < NUM_DIRECT_BLOCKS as u64 {
+            return Ok((rest as usize, Vec::new()));
+        }
+        rest -= NUM_DIRECT_BLOCKS as u64;
+        if rest < p {
+            return Ok((SINGLE_INDIRECT, vec![rest as usize]));
+        }
+        rest -= p;
+        if rest < p * p {
+            return Ok((DOUBLE_INDIRECT, vec![(rest / p) as usize, (rest % p) as usize]));
+        }
+        rest -= p * p;
+        if rest < p * p * p {
+            return Ok((TRIPLE_INDIRECT, vec![(rest / (p * p)) as usize, (rest / p % p) as usize, (rest % p) as usize]));
+        }
+        Err("ext2: file offset is beyond the maximum file size")
+    }
+
+    /// Returns the volume block that holds the given logical block of a file,
+    /// or `None` if that part of the file is a hole.
+    pub fn lookup_block(&self, inode: &Inode, logical: u64) -> Result<Option<u32>, &'static str> {
+        let (root, path) = self.block_path(logical)?;
+        let mut block = inode.block(root);
+        for index in path {
+            if block == 0 {
+                break;
+            }
+            let mut entry = [0u8; 4];
+            self.read_at(&mut entry, self.block_offset(block) + index * 4)?;
+            block = u32::from_le_bytes(entry);
+        }
+        Ok(if block == 0 { None } else { Some(block) })
+    }
+
+    /// Like `lookup_block()`, but allocates the block, and any indirect blocks leading to it,
+    /// if it isn't already allocated. The caller must write the modified `inode` back afterwards.
+    pub fn map_block(&self, inode_number: u32, inode: &mut Inode, logical: u64) -> Result<u32, &'static str> {
+        let (root, path) = self.block_path(logical)?;
+        let group = self.inode_group(inode_number);
+        let sectors_per_block = (self.sb.block_size / 512) as u32;
+        let mut block = inode.block(root);
+        if block == 0 {
+            block = self.allocate_block(group)?;
+            inode.set_block(root, block);
+            inode.set_sectors(inode.sectors() + sectors_per_block);
+        }
+        for index in path {
+            let entry_offset = self.block_offset(block) + index * 4;
+            let mut entry = [0u8; 4];
+            self.read_at(&mut entry, entry_offset)?;
+            block = u32::from_le_bytes(entry);
+            if block == 0 {
+                block = self.allocate_block(group)?;
+                self.write_at(&block.to_le_bytes(), entry_offset)?;
+                inode.set_sectors(inode.sectors() + sectors_per_block);
+            }
+        }
+        Ok(block)
+    }
+
+    /// Frees every block of the given inode, including indirect blocks,
+    /// that isn't needed to hold its first `keep` logical blocks.
+    /// The caller must write the modified `inode` back afterwards.
+    pub fn truncate_blocks(&self, inode: &mut Inode, keep: u64) -> Result<(), &'static str> {
+        let p = self.pointers_per_block();
+        let mut freed = 0;
+        let mut first = 0;
+        for root in 0 .. NUM_DIRECT_BLOCKS + 3 {
+            let level = if root < NUM_DIRECT_BLOCKS { 0 } else { (root - NUM_DIRECT_BLOCKS + 1) as u32 };
+            let mut block = inode.block(root);
+            self.truncate_tree(&mut block, level, first, keep, &mut freed)?;
+            inode.set_block(root, block);
+            first += p.pow(level);
+        }
+        let sectors_per_block = (self.sb.block_size / 512) as u32;
+        inode.set_sectors(inode.sectors().saturating_sub(freed * sectors_per_block));
+        Ok(())
+    }
+
+    /// Frees the blocks in the tree of indirect blocks of the given `level` rooted at `*block`,
+    /// which maps the logical blocks that begin at `first`, that aren't needed to hold logical blocks before `keep`.
+    /// `*block` is set to `0` if the whole tree is freed.
+    fn truncate_tree(&self, block: &mut u32, level: u32, first: u64, keep: u64, freed: &mut u32) -> Result<(), &'static str> {
+        if *block == 0 || keep >= first + self.pointers_per_block().pow(level) {
+            return Ok(());
+        }
+        if level > 0 {
+            let child_span = self.pointers_per_block().pow(level - 1);
+            let offset = self.block_offset(*block);
+            let mut entries = vec![0u8; self.sb.block_size];
+            self.read_at(&mut entries, offset)?;
+            let mut changed = false;
+            for (i, raw) in entries.chunks_mut(4).enumerate() {
+                let mut child = read_u32(raw, 0);
+                if child == 0 {
+                    continue;
+                }
+                self.truncate_tree(&mut child, level - 1, first + i as u64 * child_span, keep, freed)?;
+                if child == 0 {
+                    raw.copy_from_slice(&[0; 4]);
+                    changed = true;
+                }
+            }
+            // An indirect block that still maps kept blocks must itself be kept.
+            if keep > first {
+                if changed {
+                    self.write_at(&entries, offset)?;
+                }
+                return Ok(());
+            }
+        }
+        self.free_block(*block)?;
+        *block = 0;
+        *freed += 1;
+        Ok(())
+    }
+}
+
+
+/// Reads exactly `buffer.len()` bytes from the given block device at the byte `offset`, through the page cache.
+pub(crate) fn read_exact(queue: &BlockQueue, buffer: &mut [u8], offset: usize) -> Result<(), &'static str> {
+    let read = page_cache::read_block_device(queue, buffer, offset)?;
+    if read != buffer.len() {
+        return Err("ext2: read extends past the end of the device");
+    }
+    Ok(())
+}
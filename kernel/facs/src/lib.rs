@@ -0,0 +1,73 @@
+//! Support for the FACS ACPI table (Firmware ACPI Control Structure),
+//! which contains the waking vector that the firmware jumps to when resuming from a sleep state.
+//!
+//! Unlike other ACPI tables, the FACS is not listed in the RSDT/XSDT;
+//! it is only found through the FADT's `firmware_ctrl` field.
+//! It also lacks a full `Sdt` header, though its signature and length are in the same place.
+
+#![no_std]
+
+extern crate memory;
+extern crate acpi_table;
+extern crate zerocopy;
+
+use memory::PhysicalAddress;
+use acpi_table::{AcpiSignature, AcpiTables};
+use zerocopy::FromBytes;
+
+
+pub const FACS_SIGNATURE: &'static [u8; 4] = b"FACS";
+
+
+/// The handler for parsing the FACS table and adding it to the ACPI tables list.
+pub fn handle(
+    acpi_tables: &mut AcpiTables,
+    signature: AcpiSignature,
+    _length: usize,
+    phys_addr: PhysicalAddress
+) -> Result<(), &'static str> {
+    acpi_tables.add_table_location(signature, phys_addr, None)
+}
+
+
+/// The FACS ACPI table.
+/// Its layout and total size must exactly match that of the ACPI specification.
+#[repr(packed)]
+#[derive(Clone, Copy, Debug, FromBytes)]
+pub struct Facs {
+    pub signature: [u8; 4],
+    pub length: u32,
+    /// A value that changes if the hardware configuration changed while the system was asleep.
+    pub hardware_signature: u32,
+    /// The 32-bit physical address of the real-mode code that the firmware jumps to upon waking.
+    pub firmware_waking_vector: u32,
+    pub global_lock: u32,
+    pub flags: u32,
+    /// The 64-bit physical address of the code that the firmware jumps to upon waking.
+    /// If nonzero, this takes precedence over `firmware_waking_vector`.
+    pub x_firmware_waking_vector: u64,
+    pub version: u8,
+    _reserved: [u8; 3],
+    pub ospm_flags: u32,
+    _reserved2: [u8; 24],
+}
+
+impl Facs {
+    /// Finds the FACS in the given `AcpiTables` and returns a reference to it.
+    pub fn get<'t>(acpi_tables: &'t AcpiTables) -> Option<&'t Facs> {
+        acpi_tables.table(&FACS_SIGNATURE).ok()
+    }
+
+    /// Sets the real-mode waking vector to the given physical address,
+    /// which must be below 1 MiB, and clears the 64-bit waking vector
+    /// such that the firmware will resume execution in real mode.
+    pub fn set_waking_vector(acpi_tables: &mut AcpiTables, vector: PhysicalAddress) -> Result<(), &'static str> {
+        if vector.value() >= 0x10_0000 {
+            return Err("the FACS waking vector must be a real-mode address below 1 MiB");
+        }
+        let facs: &mut Facs = acpi_tables.table_mut(&FACS_SIGNATURE)?;
+        facs.firmware_waking_vector = vector.value() as u32;
+        facs.x_firmware_waking_vector = 0;
+        Ok(())
+    }
+}
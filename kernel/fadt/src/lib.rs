@@ -12,14 +12,21 @@ extern crate sdt;
 extern crate acpi_table;
 extern crate zerocopy;
 
+use core::mem::size_of;
 use memory::PhysicalAddress;
-use sdt::Sdt;
+use sdt::{Sdt, GenericAddressStructure};
 use acpi_table::{AcpiSignature, AcpiTables};
 use zerocopy::FromBytes;
 
 
 pub const FADT_SIGNATURE: &'static [u8; 4] = b"FACP";
 
+/// The FADT `flags` bit indicating that the `reset_reg` in the [`FadtExtended`](struct.FadtExtended.html) is supported.
+pub const FLAG_RESET_REG_SUPPORTED: u32 = 1 << 10;
+/// The FADT `flags` bit indicating that this is a hardware-reduced ACPI system,
+/// which has no fixed-function hardware like the PM1 control blocks.
+pub const FLAG_HW_REDUCED_ACPI: u32 = 1 << 20;
+
 
 /// The handler for parsing the FADT table and adding it to the ACPI tables list.
 pub fn handle(
@@ -82,4 +89,59 @@ impl Fadt {
     pub fn get<'t>(acpi_tables: &'t AcpiTables) -> Option<&'t Fadt> {
         acpi_tables.table(&FADT_SIGNATURE).ok()
     }
+
+    /// Finds the FADT in the given `AcpiTables` and returns a reference to its ACPI 2.0+ extended form,
+    /// or `None` if the FADT is too short to contain the extended fields.
+    pub fn get_extended<'t>(acpi_tables: &'t AcpiTables) -> Option<&'t FadtExtended> {
+        let fadt = Fadt::get(acpi_tables)?;
+        if (fadt.header.length as usize) < size_of::<FadtExtended>() {
+            return None;
+        }
+        acpi_tables.table(&FADT_SIGNATURE).ok()
+    }
+
+    /// Returns the physical address of the DSDT, preferring the 64-bit `x_dsdt` field if it exists.
+    pub fn dsdt_address(acpi_tables: &AcpiTables) -> Option<PhysicalAddress> {
+        let extended = Fadt::get_extended(acpi_tables).map(|ext| ext.x_dsdt).unwrap_or(0);
+        let addr = if extended != 0 { extended } else { Fadt::get(acpi_tables)?.dsdt as u64 };
+        nonzero_address(addr)
+    }
+
+    /// Returns the physical address of the FACS, preferring the 64-bit `x_firmware_ctrl` field if it exists.
+    pub fn facs_address(acpi_tables: &AcpiTables) -> Option<PhysicalAddress> {
+        let extended = Fadt::get_extended(acpi_tables).map(|ext| ext.x_firmware_ctrl).unwrap_or(0);
+        let addr = if extended != 0 { extended } else { Fadt::get(acpi_tables)?.firmware_ctrl as u64 };
+        nonzero_address(addr)
+    }
+}
+
+fn nonzero_address(addr: u64) -> Option<PhysicalAddress> {
+    if addr == 0 {
+        None
+    } else {
+        PhysicalAddress::new(addr as usize).ok()
+    }
+}
+
+
+/// The FADT as defined in ACPI 2.0 and later, which adds 64-bit addresses
+/// and the reset register after the original fields.
+#[repr(packed)]
+#[derive(Clone, Copy, Debug, FromBytes)]
+pub struct FadtExtended {
+    pub fadt: Fadt,
+    /// The register that resets the system when `reset_value` is written to it,
+    /// if `FLAG_RESET_REG_SUPPORTED` is set.
+    pub reset_reg: GenericAddressStructure,
+    pub reset_value: u8,
+    pub arm_boot_architecture_flags: u16,
+    pub fadt_minor_version: u8,
+    /// The 64-bit physical address of the FACS table
+    pub x_firmware_ctrl: u64,
+    /// The 64-bit physical address of the DSDT table
+    pub x_dsdt: u64,
+    pub x_pm1a_event_block: GenericAddressStructure,
+    pub x_pm1b_event_block: GenericAddressStructure,
+    pub x_pm1a_control_block: GenericAddressStructure,
+    pub x_pm1b_control_block: GenericAddressStructure,
 }
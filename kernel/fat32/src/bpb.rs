@@ -0,0 +1,152 @@
+//! Parsing of the boot sector (BIOS Parameter Block), FSInfo sector,
+//! and MBR partition table that describe where a FAT32 volume is and how it is laid out.
+
+/// The signature at the end of a boot sector, an MBR, and an FSInfo sector.
+pub const BOOT_SIGNATURE: u16 = 0xAA55;
+/// The size of the sectors that hold the MBR and boot sector.
+pub const BOOT_SECTOR_SIZE: usize = 512;
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+/// The offset of the free cluster count within the FSInfo sector,
+/// which is immediately followed by the next free cluster hint.
+pub const FSINFO_FREE_COUNT_OFFSET: usize = 488;
+/// The value of an FSInfo field that is unknown.
+pub const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The MBR partition types that denote a FAT32 partition (CHS and LBA addressing).
+const FAT32_PARTITION_TYPES: [u8; 2] = [0x0B, 0x0C];
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_SIZE: usize = 16;
+const NUM_PARTITION_ENTRIES: usize = 4;
+
+
+pub(crate) fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub(crate) fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+
+/// The fields of a FAT32 boot sector that describe the layout of the volume.
+#[derive(Clone, Debug)]
+pub struct BiosParameterBlock {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    pub total_sectors: u32,
+    pub sectors_per_fat: u32,
+    /// Bit 7 is set if only one FAT is active (given by bits 0-3) and the FATs are not mirrored.
+    pub ext_flags: u16,
+    pub root_cluster: u32,
+    /// The sector number of the FSInfo sector within the reserved area, or `0` if there is none.
+    pub fsinfo_sector: u16,
+    pub volume_label: [u8; 11],
+}
+
+impl BiosParameterBlock {
+    /// Parses the given boot `sector`, returning an error if it doesn't describe a valid FAT32 volume.
+    pub fn parse(sector: &[u8]) -> Result<BiosParameterBlock, &'static str> {
+        if sector.len() < BOOT_SECTOR_SIZE || read_u16(sector, 510) != BOOT_SIGNATURE {
+            return Err("fat32: missing boot sector signature");
+        }
+        if sector[0] != 0xEB && sector[0] != 0xE9 {
+            return Err("fat32: boot sector doesn't begin with a jump instruction");
+        }
+        // FAT12 and FAT16 have a fixed-size root directory and a 16-bit FAT size, whereas FAT32 has neither.
+        let root_entry_count = read_u16(sector, 17);
+        let sectors_per_fat_16 = read_u16(sector, 22);
+        let total_sectors_16 = read_u16(sector, 19);
+        if root_entry_count != 0 || sectors_per_fat_16 != 0 {
+            return Err("fat32: volume is FAT12 or FAT16, not FAT32");
+        }
+
+        let mut volume_label = [0u8; 11];
+        volume_label.copy_from_slice(&sector[71..82]);
+        let bpb = BiosParameterBlock {
+            bytes_per_sector: read_u16(sector, 11),
+            sectors_per_cluster: sector[13],
+            reserved_sectors: read_u16(sector, 14),
+            num_fats: sector[16],
+            total_sectors: if total_sectors_16 != 0 { total_sectors_16 as u32 } else { read_u32(sector, 32) },
+            sectors_per_fat: read_u32(sector, 36),
+            ext_flags: read_u16(sector, 40),
+            root_cluster: read_u32(sector, 44),
+            fsinfo_sector: read_u16(sector, 48),
+            volume_label,
+        };
+
+        match bpb.bytes_per_sector {
+            512 | 1024 | 2048 | 4096 => { }
+            _ => return Err("fat32: invalid bytes per sector"),
+        }
+        if !bpb.sectors_per_cluster.is_power_of_two() {
+            return Err("fat32: invalid sectors per cluster");
+        }
+        if bpb.reserved_sectors == 0 || bpb.num_fats == 0 || bpb.sectors_per_fat == 0 {
+            return Err("fat32: invalid reserved sector, FAT count, or FAT size");
+        }
+        if bpb.data_sector() >= bpb.total_sectors as usize || bpb.root_cluster < 2 {
+            return Err("fat32: invalid volume size or root directory cluster");
+        }
+        Ok(bpb)
+    }
+
+    /// Returns the sector number at which the data region (cluster 2) begins.
+    pub fn data_sector(&self) -> usize {
+        self.reserved_sectors as usize + self.num_fats as usize * self.sectors_per_fat as usize
+    }
+
+    /// Returns the number of data clusters in the volume,
+    /// limited by the number of entries that fit in one FAT.
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors = self.total_sectors as usize - self.data_sector();
+        let clusters = data_sectors / self.sectors_per_cluster as usize;
+        let fat_entries = self.sectors_per_fat as usize * self.bytes_per_sector as usize / 4;
+        core::cmp::min(clusters, fat_entries.saturating_sub(2)) as u32
+    }
+
+    /// Returns the indices of the FATs that must be updated when a FAT entry changes.
+    pub fn mirrored_fats(&self) -> core::ops::Range<u8> {
+        if self.ext_flags & 0x80 != 0 {
+            let active = (self.ext_flags & 0x0F) as u8;
+            active .. active + 1
+        } else {
+            0 .. self.num_fats
+        }
+    }
+
+    /// Returns the index of the FAT that is read from.
+    pub fn active_fat(&self) -> u8 {
+        self.mirrored_fats().start
+    }
+}
+
+
+/// Parses an FSInfo `sector`, returning its free cluster count and next free cluster hint,
+/// or `None` if the sector isn't a valid FSInfo sector.
+pub fn parse_fsinfo(sector: &[u8]) -> Option<(u32, u32)> {
+    if sector.len() < BOOT_SECTOR_SIZE
+        || read_u32(sector, 0) != FSINFO_LEAD_SIGNATURE
+        || read_u32(sector, 484) != FSINFO_STRUCT_SIGNATURE
+        || read_u16(sector, 510) != BOOT_SIGNATURE
+    {
+        return None;
+    }
+    Some((read_u32(sector, FSINFO_FREE_COUNT_OFFSET), read_u32(sector, FSINFO_FREE_COUNT_OFFSET + 4)))
+}
+
+
+/// Returns the partition numbers (starting at 1) and starting sector numbers
+/// of the FAT32 partitions in the given MBR `sector`.
+pub fn fat32_partitions(sector: &[u8]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let valid = sector.len() >= BOOT_SECTOR_SIZE && read_u16(sector, 510) == BOOT_SIGNATURE;
+    (0 .. if valid { NUM_PARTITION_ENTRIES } else { 0 })
+        .map(move |i| (i + 1, &sector[PARTITION_TABLE_OFFSET + i * PARTITION_ENTRY_SIZE ..][.. PARTITION_ENTRY_SIZE]))
+        .filter(|&(_, entry)| FAT32_PARTITION_TYPES.contains(&entry[4]))
+        .map(|(number, entry)| (number, read_u32(entry, 8) as usize))
+        .filter(|&(_, start)| start != 0)
+}
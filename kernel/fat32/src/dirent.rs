@@ -0,0 +1,359 @@
+//! Encoding and decoding of FAT directory entries, including the 8.3 short names
+//! and the VFAT long file name (LFN) entries that precede them.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use bpb::{read_u16, read_u32};
+
+
+/// The size in bytes of a directory entry.
+pub const DIR_ENTRY_SIZE: usize = 32;
+/// The maximum number of entries in a directory.
+pub const MAX_DIR_ENTRIES: usize = 65536;
+/// The maximum length of a long file name, in UTF-16 code units.
+pub const MAX_NAME_LEN: usize = 255;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// The attributes of a long file name entry.
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// The first name byte of a deleted entry.
+pub const ENTRY_DELETED: u8 = 0xE5;
+/// The first name byte of the entry after the last entry in a directory.
+pub const ENTRY_END: u8 = 0x00;
+/// A first name byte of `0x05` stands for `0xE5`, which would otherwise mean deleted.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+/// The flag in an LFN entry's sequence number that marks the last (first on disk) entry of a long name.
+const LFN_LAST_ENTRY: u8 = 0x40;
+/// The number of UTF-16 code units stored in each LFN entry.
+const LFN_CHARS_PER_ENTRY: usize = 13;
+/// The byte offsets of the 13 UTF-16 code units within an LFN entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// The flag in a short entry's reserved byte, used by Windows NT and Linux,
+/// indicating that the base name is displayed in lowercase.
+const NT_LOWERCASE_BASE: u8 = 0x08;
+/// Like `NT_LOWERCASE_BASE`, but for the extension.
+const NT_LOWERCASE_EXT: u8 = 0x10;
+
+/// Characters that are allowed in a short name besides uppercase letters and digits.
+const SHORT_NAME_SPECIAL_CHARS: &[u8] = b"$%'-_@~`!(){}^#&";
+/// Characters that are not allowed in a long file name.
+const INVALID_NAME_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+
+/// A short (8.3) directory entry, which holds the metadata of a file or directory.
+#[derive(Clone, Debug)]
+pub struct ShortEntry {
+    /// The base name and extension, padded with spaces.
+    pub name: [u8; 11],
+    pub attributes: u8,
+    /// Case flags for the short name, see `NT_LOWERCASE_BASE`.
+    pub nt_flags: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+    /// The date and time at which the entry was created, in FAT format.
+    pub created: (u16, u16),
+    /// The date and time at which the entry was last written, in FAT format.
+    pub modified: (u16, u16),
+}
+
+impl ShortEntry {
+    /// Creates a new entry with the given short name and attributes, stamped with the current time.
+    pub fn new(name: [u8; 11], nt_flags: u8, attributes: u8, first_cluster: u32) -> ShortEntry {
+        let now = fat_timestamp();
+        ShortEntry { name, attributes, nt_flags, first_cluster, size: 0, created: now, modified: now }
+    }
+
+    /// Decodes a short entry from its on-disk representation.
+    pub fn parse(raw: &[u8]) -> ShortEntry {
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&raw[0..11]);
+        ShortEntry {
+            name,
+            attributes: raw[11],
+            nt_flags: raw[12],
+            first_cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+            size: read_u32(raw, 28),
+            created: (read_u16(raw, 16), read_u16(raw, 14)),
+            modified: (read_u16(raw, 24), read_u16(raw, 22)),
+        }
+    }
+
+    /// Encodes this short entry into its on-disk representation.
+    pub fn encode(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0..11].copy_from_slice(&self.name);
+        raw[11] = self.attributes;
+        raw[12] = self.nt_flags;
+        raw[14..16].copy_from_slice(&self.created.1.to_le_bytes());
+        raw[16..18].copy_from_slice(&self.created.0.to_le_bytes());
+        raw[18..20].copy_from_slice(&self.modified.0.to_le_bytes());
+        raw[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        raw[22..24].copy_from_slice(&self.modified.1.to_le_bytes());
+        raw[24..26].copy_from_slice(&self.modified.0.to_le_bytes());
+        raw[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size.to_le_bytes());
+        raw
+    }
+
+    /// Returns whether this entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Returns the name to display for this entry when it has no long file name.
+    pub fn display_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == ENTRY_KANJI_E5 {
+            name[0] = ENTRY_DELETED;
+        }
+        let to_char = |b: u8, lowercase: bool| match b {
+            0x20 ..= 0x7E if lowercase => (b as char).to_ascii_lowercase(),
+            0x20 ..= 0x7E => b as char,
+            _ => '_',
+        };
+        let mut display: String = trim_spaces(&name[0..8]).iter()
+            .map(|&b| to_char(b, self.nt_flags & NT_LOWERCASE_BASE != 0))
+            .collect();
+        let ext = trim_spaces(&name[8..11]);
+        if !ext.is_empty() {
+            display.push('.');
+            display.extend(ext.iter().map(|&b| to_char(b, self.nt_flags & NT_LOWERCASE_EXT != 0)));
+        }
+        display
+    }
+}
+
+fn trim_spaces(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &bytes[..len]
+}
+
+
+/// A file or directory found in a directory, along with the location of its entries.
+pub struct DirEntry {
+    /// The long file name if there is a valid one, otherwise the short name.
+    pub name: String,
+    pub short: ShortEntry,
+    /// The volume-relative byte offset of the short entry.
+    pub offset: usize,
+    /// The volume-relative byte offsets of the LFN entries that precede the short entry.
+    pub lfn_offsets: Vec<usize>,
+}
+
+/// The pieces of a long file name collected while walking its LFN entries.
+struct LongName {
+    units: Vec<u16>,
+    checksum: u8,
+    /// The sequence number of the last LFN entry seen; the name is complete when this reaches 1.
+    sequence: u8,
+    offsets: Vec<usize>,
+}
+
+/// Parses the given directory `slots`, each of which is a raw entry and its volume-relative byte offset,
+/// into the files and directories they describe.
+///
+/// Deleted entries, volume labels, and the `.` and `..` entries are skipped,
+/// and parsing stops at the end-of-directory marker.
+pub fn parse_entries(slots: &[(usize, [u8; DIR_ENTRY_SIZE])]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut long_name: Option<LongName> = None;
+    for &(offset, ref raw) in slots {
+        match raw[0] {
+            ENTRY_END => break,
+            ENTRY_DELETED => { long_name = None; continue; }
+            _ => { }
+        }
+
+        if raw[11] & 0x3F == ATTR_LONG_NAME {
+            let sequence = raw[0] & 0x1F;
+            if raw[0] & LFN_LAST_ENTRY != 0 {
+                long_name = Some(LongName {
+                    units: vec![0xFFFF; sequence as usize * LFN_CHARS_PER_ENTRY],
+                    checksum: raw[13],
+                    sequence: sequence + 1,
+                    offsets: Vec::new(),
+                });
+            }
+            long_name = long_name.take().filter(|ln| sequence != 0 && ln.sequence == sequence + 1 && ln.checksum == raw[13]);
+            if let Some(ref mut ln) = long_name {
+                let start = (sequence as usize - 1) * LFN_CHARS_PER_ENTRY;
+                for (i, &char_offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    ln.units[start + i] = read_u16(raw, char_offset);
+                }
+                ln.sequence = sequence;
+                ln.offsets.push(offset);
+            }
+            continue;
+        }
+
+        let short = ShortEntry::parse(raw);
+        let long_name = long_name.take().filter(|ln| ln.sequence == 1 && ln.checksum == lfn_checksum(&short.name));
+        if short.attributes & ATTR_VOLUME_ID != 0 || short.name[0] == b'.' {
+            continue;
+        }
+        let (name, lfn_offsets) = match long_name {
+            Some(ln) => {
+                let end = ln.units.iter().position(|&u| u == 0).unwrap_or(ln.units.len());
+                let name: String = core::char::decode_utf16(ln.units[..end].iter().cloned())
+                    .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+                    .collect();
+                (name, ln.offsets)
+            }
+            None => (short.display_name(), Vec::new()),
+        };
+        entries.push(DirEntry { name, short, offset, lfn_offsets });
+    }
+    entries
+}
+
+
+/// Returns the checksum of a short name that is stored in each of its LFN entries.
+pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &b| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b))
+}
+
+/// Encodes the LFN entries for the given long `name`, in the order they appear on disk,
+/// i.e., the entry holding the end of the name comes first.
+pub fn encode_lfn_entries(name: &str, short_name: &[u8; 11]) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let checksum = lfn_checksum(short_name);
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let num_entries = (units.len() + LFN_CHARS_PER_ENTRY - 1) / LFN_CHARS_PER_ENTRY;
+    // The name is terminated with a null if it doesn't fill the last entry, and padded with 0xFFFF.
+    if units.len() % LFN_CHARS_PER_ENTRY != 0 {
+        units.push(0);
+    }
+    units.resize(num_entries * LFN_CHARS_PER_ENTRY, 0xFFFF);
+
+    (1 ..= num_entries).rev().map(|sequence| {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0] = sequence as u8 | if sequence == num_entries { LFN_LAST_ENTRY } else { 0 };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum;
+        let chars = &units[(sequence - 1) * LFN_CHARS_PER_ENTRY ..][.. LFN_CHARS_PER_ENTRY];
+        for (&unit, &char_offset) in chars.iter().zip(LFN_CHAR_OFFSETS.iter()) {
+            raw[char_offset .. char_offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        raw
+    }).collect()
+}
+
+/// Checks whether `name` can be used as the name of a new file or directory.
+pub fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("fat32: invalid file name");
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err("fat32: file names cannot end with a period or space");
+    }
+    if name.chars().any(|c| (c as u32) < 0x20 || INVALID_NAME_CHARS.contains(&c)) {
+        return Err("fat32: file name contains an invalid character");
+    }
+    if name.encode_utf16().count() > MAX_NAME_LEN {
+        return Err("fat32: file name is too long");
+    }
+    Ok(())
+}
+
+/// The short name generated for a new file or directory.
+pub struct ShortName {
+    pub name: [u8; 11],
+    pub nt_flags: u8,
+    /// Whether the short name can't represent the full name, so LFN entries are required.
+    pub needs_lfn: bool,
+}
+
+/// Generates a short name for the given long `name` that doesn't collide with any short name
+/// for which `exists` returns `true`.
+///
+/// Names that fit in 8.3 format and are entirely upper- or lowercase in each part
+/// are stored as a short name alone; other names get a numeric tail, e.g., `LONGFI~1.TXT`.
+pub fn generate_short_name<F: Fn(&[u8; 11]) -> bool>(name: &str, exists: F) -> Result<ShortName, &'static str> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+
+    // Convert each part to uppercase short name characters, noting whether anything was lost.
+    let mut lossy = false;
+    let mut convert = |part: &str, max_len: usize| -> Vec<u8> {
+        let mut converted = Vec::new();
+        for c in part.chars() {
+            let b = match c {
+                ' ' | '.' => { lossy = true; continue; }
+                'a' ..= 'z' | 'A' ..= 'Z' | '0' ..= '9' => c.to_ascii_uppercase() as u8,
+                _ if c.is_ascii() && SHORT_NAME_SPECIAL_CHARS.contains(&(c as u8)) => c as u8,
+                _ => { lossy = true; b'_' }
+            };
+            if converted.len() == max_len {
+                lossy = true;
+                break;
+            }
+            converted.push(b);
+        }
+        converted
+    };
+    let short_base = convert(base, 8);
+    let short_ext = convert(ext, 3);
+    if short_base.is_empty() {
+        lossy = true;
+    }
+
+    let case_flag = |part: &str, flag: u8| -> Option<u8> {
+        let has_lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = part.chars().any(|c| c.is_ascii_uppercase());
+        match (has_lower, has_upper) {
+            (true, true) => None,
+            (true, false) => Some(flag),
+            _ => Some(0),
+        }
+    };
+    let nt_flags = case_flag(base, NT_LOWERCASE_BASE).and_then(|b| case_flag(ext, NT_LOWERCASE_EXT).map(|e| b | e));
+
+    let make = |base: &[u8], tail: &[u8]| -> [u8; 11] {
+        let mut short = [b' '; 11];
+        let base_len = core::cmp::min(base.len(), 8 - tail.len());
+        short[..base_len].copy_from_slice(&base[..base_len]);
+        short[base_len .. base_len + tail.len()].copy_from_slice(tail);
+        short[8 .. 8 + short_ext.len()].copy_from_slice(&short_ext);
+        short
+    };
+
+    if !lossy {
+        let short = make(&short_base, b"");
+        if !exists(&short) {
+            return Ok(match nt_flags {
+                Some(flags) => ShortName { name: short, nt_flags: flags, needs_lfn: false },
+                None => ShortName { name: short, nt_flags: 0, needs_lfn: true },
+            });
+        }
+    }
+
+    let base = if short_base.is_empty() { &b"_"[..] } else { &short_base[..] };
+    for n in 1 .. 1_000_000u32 {
+        let tail = format!("~{}", n);
+        let short = make(base, tail.as_bytes());
+        if !exists(&short) {
+            return Ok(ShortName { name: short, nt_flags: 0, needs_lfn: true });
+        }
+    }
+    Err("fat32: couldn't generate a unique short name")
+}
+
+
+/// Returns the current local date and time in FAT format, i.e., `(date, time)`.
+pub fn fat_timestamp() -> (u16, u16) {
+    let now = wall_clock::local_now();
+    // FAT dates begin in 1980 and store the year in 7 bits.
+    let year = (now.year.max(1980).min(1980 + 127) - 1980) as u16;
+    let date = year << 9 | (now.month as u16 & 0xF) << 5 | (now.day as u16 & 0x1F);
+    let time = (now.hour as u16 & 0x1F) << 11 | (now.minute as u16 & 0x3F) << 5 | (now.second as u16 / 2);
+    (date, time)
+}
@@ -0,0 +1,121 @@
+#![no_std]
+
+//! A FAT32 filesystem driver with full read and write support and VFAT long file names.
+//!
+//! FAT32 is registered with the VFS as the `fat32` filesystem type, so a FAT32 volume is attached
+//! to the directory tree with `vfs::mount()`. Its directories and files are then [`Fat32Directory`]
+//! and [`Fat32File`] nodes that are used through the regular `fs_node` traits:
+//! * files and directories are created with `Directory::create_file()` and `Directory::create_dir()`,
+//! * files and directories (including their contents) are deleted with `Directory::remove()`,
+//! * files are extended by writing past their end and resized with `File::truncate()`.
+//!
+//! Clusters are allocated from and freed to the File Allocation Table,
+//! and every change to the FAT is mirrored to all of the volume's FATs (unless mirroring is disabled).
+//! Names that don't fit in the 8.3 format are stored as VFAT long file names,
+//! alongside a generated short name like `LONGFI~1.TXT`.
+//!
+//! All I/O goes through the page cache: files are read and written as cached file pages,
+//! which are written back to their clusters on the volume, while directories and the FATs
+//! are read and written as cached pages of the block device.
+//! Changes are thus written to the device by the page cache's periodic flusher, or immediately with `page_cache::sync()`.
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate fs_node;
+extern crate vfs;
+extern crate memory;
+extern crate block_io;
+extern crate page_cache;
+extern crate kernel_config;
+extern crate wall_clock;
+
+mod bpb;
+mod dirent;
+mod node;
+mod volume;
+
+pub use node::{Fat32Directory, Fat32File, MAX_FILE_SIZE};
+pub use volume::Volume;
+
+use core::sync::atomic::AtomicBool;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use block_io::BlockQueue;
+use fs_node::{DirRef, WeakDirRef};
+use vfs::{FileSystem, Device};
+use bpb::{BiosParameterBlock, BOOT_SECTOR_SIZE};
+
+
+/// The name of this filesystem type in the VFS.
+pub const FILESYSTEM_NAME: &'static str = "fat32";
+
+
+/// Registers FAT32 as a filesystem type with the VFS, and mounts every FAT32 volume
+/// on the storage devices known to the `storage_manager` at `/mnt/<device>`,
+/// or `/mnt/<device>p<partition>` for a volume in an MBR partition.
+pub fn init() -> Result<(), &'static str> {
+    vfs::register_filesystem(Arc::new(Fat32FileSystem))?;
+    vfs::automount(FILESYSTEM_NAME)
+}
+
+/// Returns the FAT32 volumes on the given block device, each of which is given as
+/// its MBR partition number (or `None` if the volume spans the whole device) and its byte offset.
+pub fn find_volumes(queue: &BlockQueue) -> Result<Vec<(Option<usize>, usize)>, &'static str> {
+    let mut sector = [0u8; BOOT_SECTOR_SIZE];
+    volume::read_exact(queue, &mut sector, 0)?;
+    // A device without a partition table begins with the volume's own boot sector.
+    if BiosParameterBlock::parse(&sector).is_ok() {
+        return Ok(vec![(None, 0)]);
+    }
+
+    let mut volumes = Vec::new();
+    let mut boot_sector = [0u8; BOOT_SECTOR_SIZE];
+    for (number, start_sector) in bpb::fat32_partitions(&sector) {
+        let offset = start_sector * queue.block_size();
+        if volume::read_exact(queue, &mut boot_sector, offset).is_ok() && BiosParameterBlock::parse(&boot_sector).is_ok() {
+            volumes.push((Some(number), offset));
+        }
+    }
+    Ok(volumes)
+}
+
+
+/// The FAT32 filesystem type, through which the VFS mounts FAT32 volumes.
+pub struct Fat32FileSystem;
+
+impl FileSystem for Fat32FileSystem {
+    fn name(&self) -> &'static str {
+        FILESYSTEM_NAME
+    }
+
+    fn probe(&self, queue: &BlockQueue) -> Vec<Option<usize>> {
+        find_volumes(queue).map(|volumes| volumes.into_iter().map(|(partition, _)| partition).collect()).unwrap_or_default()
+    }
+
+    fn mount(&self, device: Option<&Device>, name: String, parent: WeakDirRef, options: &str) -> Result<DirRef, &'static str> {
+        if !options.is_empty() {
+            return Err("fat32: mount options are not supported");
+        }
+        let device = device.ok_or("fat32: mounting requires a device")?;
+        let offset = find_volumes(&device.queue)?.into_iter()
+            .find(|&(partition, _)| partition == device.partition)
+            .map(|(_, offset)| offset)
+            .ok_or("fat32: no FAT32 volume found on the device")?;
+        let volume = Arc::new(Volume::open(device.queue.clone(), offset)?);
+        let root_cluster = volume.root_cluster();
+        Ok(Fat32Directory::new_ref(name, parent, volume, root_cluster, Arc::new(AtomicBool::new(false))))
+    }
+
+    fn unmount(&self, root: &DirRef, device: Option<&Device>) -> Result<(), &'static str> {
+        // Writing back the files' pages dirties the device's pages, so they're written back first.
+        if let Some(filesystem) = root.lock().metadata().filesystem {
+            page_cache::sync_filesystem(filesystem)?;
+        }
+        match device {
+            Some(device) => page_cache::sync_block_device(&device.queue).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,626 @@
+//! The directories and files of a FAT32 volume, exposed through the `fs_node` traits.
+
+use core::cmp::min;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use fs_node::{DirRef, WeakDirRef, FileRef, Directory, File, FileOrDir, FsNode, Metadata};
+use memory::MappedPages;
+use kernel_config::memory::PAGE_SIZE;
+use page_cache::{FileId, PageBacking};
+use dirent::{self, DirEntry, ShortEntry, DIR_ENTRY_SIZE, MAX_DIR_ENTRIES, ENTRY_DELETED, ENTRY_END, ATTR_ARCHIVE, ATTR_DIRECTORY};
+use volume::Volume;
+
+
+/// The maximum size of a file on a FAT32 volume, whose size field is 32 bits.
+pub const MAX_FILE_SIZE: usize = u32::max_value() as usize;
+
+
+/// A child of a `Fat32Directory`, along with the location of its directory entries.
+struct Child {
+    node: FileOrDir,
+    short_name: [u8; 11],
+    /// The volume-relative byte offset of the child's short entry.
+    offset: usize,
+    /// The volume-relative byte offsets of the child's LFN entries.
+    lfn_offsets: Vec<usize>,
+    /// Shared with the child node, and set once the child has been deleted.
+    removed: Arc<AtomicBool>,
+}
+
+
+/// A directory on a FAT32 volume.
+pub struct Fat32Directory {
+    name: String,
+    parent: WeakDirRef,
+    /// A weak reference to this directory itself, which is the parent of its children.
+    self_ref: WeakDirRef,
+    volume: Arc<Volume>,
+    first_cluster: u32,
+    /// This directory's children, which are read from the volume the first time they're needed.
+    children: Mutex<Option<BTreeMap<String, Child>>>,
+    removed: Arc<AtomicBool>,
+}
+
+impl Fat32Directory {
+    /// Creates a directory node for the directory whose entries begin at `first_cluster`.
+    ///
+    /// This does not insert the new directory into its `parent`.
+    pub(crate) fn new_ref(
+        name: String,
+        parent: WeakDirRef,
+        volume: Arc<Volume>,
+        first_cluster: u32,
+        removed: Arc<AtomicBool>,
+    ) -> DirRef {
+        let dir = Fat32Directory {
+            name,
+            parent,
+            self_ref: Weak::<Mutex<Fat32Directory>>::new(),
+            volume,
+            first_cluster,
+            children: Mutex::new(None),
+            removed,
+        };
+        let concrete_ref = Arc::new(Mutex::new(dir));
+        let dir_ref = concrete_ref.clone() as DirRef;
+        // Directories refer to themselves as the parent of their children, so this is set after creation.
+        concrete_ref.lock().self_ref = Arc::downgrade(&dir_ref);
+        dir_ref
+    }
+
+    /// Returns the FAT32 volume that this directory is on.
+    pub fn volume(&self) -> &Arc<Volume> {
+        &self.volume
+    }
+
+    fn check_exists(&self) -> Result<(), &'static str> {
+        if self.removed.load(Ordering::Acquire) {
+            return Err("fat32: directory has been deleted");
+        }
+        Ok(())
+    }
+
+    /// Returns this directory's children, reading them from the volume if they haven't been yet.
+    fn loaded<'a>(&self, children: &'a mut Option<BTreeMap<String, Child>>) -> Result<&'a mut BTreeMap<String, Child>, &'static str> {
+        self.check_exists()?;
+        let loaded = match children.take() {
+            Some(loaded) => loaded,
+            None => self.load_children()?,
+        };
+        Ok(children.get_or_insert(loaded))
+    }
+
+    fn load_children(&self) -> Result<BTreeMap<String, Child>, &'static str> {
+        let slots = read_slots(&self.volume, self.first_cluster)?;
+        let mut children = BTreeMap::new();
+        for DirEntry { name, short, offset, lfn_offsets } in dirent::parse_entries(&slots) {
+            let removed = Arc::new(AtomicBool::new(false));
+            let node = if short.is_dir() {
+                FileOrDir::Dir(Fat32Directory::new_ref(name.clone(), self.self_ref.clone(), Arc::clone(&self.volume), short.first_cluster, Arc::clone(&removed)))
+            } else {
+                let layout = Layout { first_cluster: short.first_cluster, size: short.size as usize, clusters: None };
+                FileOrDir::File(Fat32File::new_ref(name.clone(), self.self_ref.clone(), Arc::clone(&self.volume), offset, layout, Arc::clone(&removed)))
+            };
+            children.insert(name, Child { node, short_name: short.name, offset, lfn_offsets, removed });
+        }
+        Ok(children)
+    }
+
+    /// Writes the directory entries for a new child named `name` and returns the new child.
+    fn create_child(&self, name: &str, is_dir: bool) -> Result<FileOrDir, &'static str> {
+        let mut children = self.children.lock();
+        let children = self.loaded(&mut children)?;
+        dirent::validate_name(name)?;
+        if find_child(children, name).is_some() {
+            return Err("fat32: a file or directory with that name already exists");
+        }
+        let short_name = dirent::generate_short_name(name, |short| children.values().any(|c| &c.short_name == short))?;
+
+        let first_cluster = if is_dir { self.allocate_dir_cluster()? } else { 0 };
+        let attributes = if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+        let mut raw_entries = if short_name.needs_lfn {
+            dirent::encode_lfn_entries(name, &short_name.name)
+        } else {
+            Vec::new()
+        };
+        raw_entries.push(ShortEntry::new(short_name.name, short_name.nt_flags, attributes, first_cluster).encode());
+
+        let write_entries = || -> Result<Vec<usize>, &'static str> {
+            let offsets = self.find_free_slots(raw_entries.len())?;
+            for (raw, &offset) in raw_entries.iter().zip(offsets.iter()) {
+                self.volume.write_at(raw, offset)?;
+            }
+            Ok(offsets)
+        };
+        let mut offsets = match write_entries() {
+            Ok(offsets) => offsets,
+            Err(e) => {
+                if first_cluster != 0 {
+                    let _ = self.volume.free_chain(first_cluster);
+                }
+                return Err(e);
+            }
+        };
+        let offset = offsets.pop().ok_or("fat32: no directory entries were written")?;
+
+        let removed = Arc::new(AtomicBool::new(false));
+        let node = if is_dir {
+            FileOrDir::Dir(Fat32Directory::new_ref(name.to_string(), self.self_ref.clone(), Arc::clone(&self.volume), first_cluster, Arc::clone(&removed)))
+        } else {
+            let layout = Layout { first_cluster: 0, size: 0, clusters: Some(Vec::new()) };
+            FileOrDir::File(Fat32File::new_ref(name.to_string(), self.self_ref.clone(), Arc::clone(&self.volume), offset, layout, Arc::clone(&removed)))
+        };
+        children.insert(name.to_string(), Child {
+            node: node.clone(),
+            short_name: short_name.name,
+            offset,
+            lfn_offsets: offsets,
+            removed,
+        });
+        Ok(node)
+    }
+
+    /// Allocates the first cluster of a new subdirectory and writes its `.` and `..` entries.
+    fn allocate_dir_cluster(&self) -> Result<u32, &'static str> {
+        let cluster = self.volume.allocate_cluster(None)?;
+        // The `..` entry of a directory in the root directory refers to cluster 0.
+        let parent_cluster = if self.first_cluster == self.volume.root_cluster() { 0 } else { self.first_cluster };
+        let dot = ShortEntry::new(*b".          ", 0, ATTR_DIRECTORY, cluster);
+        let dotdot = ShortEntry::new(*b"..         ", 0, ATTR_DIRECTORY, parent_cluster);
+        let base = self.volume.cluster_offset(cluster);
+        let result = self.volume.write_at(&dot.encode(), base)
+            .and_then(|_| self.volume.write_at(&dotdot.encode(), base + DIR_ENTRY_SIZE));
+        if let Err(e) = result {
+            let _ = self.volume.free_chain(cluster);
+            return Err(e);
+        }
+        Ok(cluster)
+    }
+
+    /// Returns the offsets of `count` consecutive free directory entries, growing the directory if necessary.
+    fn find_free_slots(&self, count: usize) -> Result<Vec<usize>, &'static str> {
+        let slots = read_slots(&self.volume, self.first_cluster)?;
+        let mut run = Vec::with_capacity(count);
+        for &(offset, ref raw) in &slots {
+            if raw[0] == ENTRY_DELETED || raw[0] == ENTRY_END {
+                run.push(offset);
+                if run.len() == count {
+                    return Ok(run);
+                }
+            } else {
+                run.clear();
+            }
+        }
+
+        // Any free entries at the end of the directory are followed by the newly-allocated clusters.
+        let slots_per_cluster = self.volume.cluster_size() / DIR_ENTRY_SIZE;
+        let mut num_slots = slots.len();
+        let mut last_cluster = *self.volume.cluster_chain(self.first_cluster)?.last().ok_or("fat32: directory has no clusters")?;
+        while run.len() < count {
+            if num_slots + slots_per_cluster > MAX_DIR_ENTRIES {
+                return Err("fat32: directory is full");
+            }
+            last_cluster = self.volume.allocate_cluster(Some(last_cluster))?;
+            let base = self.volume.cluster_offset(last_cluster);
+            run.extend((0 .. slots_per_cluster).map(|i| base + i * DIR_ENTRY_SIZE));
+            num_slots += slots_per_cluster;
+        }
+        run.truncate(count);
+        Ok(run)
+    }
+
+    /// Deletes the child named `name` from the volume, including the contents of a child directory.
+    fn delete_child(&self, name: &str) -> Result<FileOrDir, &'static str> {
+        let node = {
+            let mut children = self.children.lock();
+            let children = self.loaded(&mut children)?;
+            children.get(name).map(|c| c.node.clone()).ok_or("fat32: no such file or directory")?
+        };
+
+        // Empty a directory through its own `Directory` implementation,
+        // so that the nodes of its contents are marked as removed too.
+        if let FileOrDir::Dir(ref dir) = node {
+            let mut dir = dir.lock();
+            for child_name in dir.list() {
+                if let Some(grandchild) = dir.get(&child_name) {
+                    dir.remove(&grandchild).ok_or("fat32: couldn't remove the directory's contents")?;
+                }
+            }
+        }
+
+        let child = {
+            let mut children = self.children.lock();
+            self.loaded(&mut children)?.remove(name).ok_or("fat32: no such file or directory")?
+        };
+        child.removed.store(true, Ordering::Release);
+
+        // The short entry is read again because the child's first cluster may have changed since it was loaded.
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        self.volume.read_at(&mut raw, child.offset)?;
+        let first_cluster = ShortEntry::parse(&raw).first_cluster;
+        for &offset in child.lfn_offsets.iter().chain(Some(&child.offset)) {
+            self.volume.write_at(&[ENTRY_DELETED], offset)?;
+        }
+        // A file's cached pages are dropped before its clusters are freed, so they're never written back to another file.
+        if let FileOrDir::File(ref file) = child.node {
+            page_cache::invalidate_file(file);
+        }
+        if first_cluster != 0 {
+            self.volume.free_chain(first_cluster)?;
+        }
+        Ok(child.node)
+    }
+}
+
+impl Directory for Fat32Directory {
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let mut children = self.children.lock();
+        let children = self.loaded(&mut children).ok()?;
+        find_child(children, name).map(|c| c.node.clone())
+    }
+
+    fn list(&self) -> Vec<String> {
+        let mut children = self.children.lock();
+        match self.loaded(&mut children) {
+            Ok(children) => children.keys().cloned().collect(),
+            Err(e) => {
+                error!("fat32: couldn't read directory {:?}: {}", self.name, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// FAT32 directories can only hold nodes that exist on the volume,
+    /// so new nodes must be created with `create_file()` or `create_dir()` instead.
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("fat32: nodes can't be inserted into a FAT32 directory, use create_file() or create_dir() instead")
+    }
+
+    /// Deletes the given node from the volume, freeing its clusters.
+    /// If the node is a directory, all of its contents are deleted too.
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        let name = node.get_name();
+        match self.delete_child(&name) {
+            Ok(mut removed) => {
+                removed.set_parent_dir(Weak::<Mutex<Fat32Directory>>::new());
+                Some(removed)
+            }
+            Err(e) => {
+                error!("fat32: couldn't remove {:?}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn create_file(&mut self, name: &str) -> Result<FileRef, &'static str> {
+        match self.create_child(name, false)? {
+            FileOrDir::File(file) => Ok(file),
+            FileOrDir::Dir(_) => Err("fat32: created a directory instead of a file"),
+        }
+    }
+
+    fn create_dir(&mut self, name: &str) -> Result<DirRef, &'static str> {
+        match self.create_child(name, true)? {
+            FileOrDir::Dir(dir) => Ok(dir),
+            FileOrDir::File(_) => Err("fat32: created a file instead of a directory"),
+        }
+    }
+}
+
+impl FsNode for Fat32Directory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata { filesystem: Some(self.volume.id()), ..Metadata::default() }
+    }
+}
+
+
+/// A file on a FAT32 volume.
+///
+/// Its contents are read and written through the page cache,
+/// which reads and writes back its pages through the file's `Fat32Contents`.
+pub struct Fat32File {
+    name: String,
+    parent: WeakDirRef,
+    /// The volume-relative byte offset of this file's short directory entry.
+    entry_offset: usize,
+    contents: Arc<Fat32Contents>,
+}
+
+impl Fat32File {
+    /// Creates a file node for the file whose short directory entry is at the volume-relative byte `entry_offset`,
+    /// and registers its contents with the page cache.
+    ///
+    /// This does not insert the new file into its `parent`.
+    fn new_ref(
+        name: String,
+        parent: WeakDirRef,
+        volume: Arc<Volume>,
+        entry_offset: usize,
+        layout: Layout,
+        removed: Arc<AtomicBool>,
+    ) -> FileRef {
+        let file = Fat32File {
+            name,
+            parent,
+            entry_offset,
+            contents: Arc::new(Fat32Contents { volume, layout: Mutex::new(layout), removed }),
+        };
+        page_cache::register_file(file.id(), &file.backing());
+        Arc::new(Mutex::new(file))
+    }
+
+    /// Returns the ID of this file's pages in the page cache.
+    fn id(&self) -> FileId {
+        FileId::Inode { filesystem: self.contents.volume.id(), inode: self.entry_offset as u64 }
+    }
+
+    /// Returns this file's contents as the backing of its pages in the page cache.
+    fn backing(&self) -> Arc<dyn PageBacking> {
+        self.contents.clone()
+    }
+
+    fn check_exists(&self) -> Result<(), &'static str> {
+        if self.contents.removed.load(Ordering::Acquire) {
+            return Err("fat32: file has been deleted");
+        }
+        Ok(())
+    }
+
+    /// Writes this file's first cluster, size, and modification time to its directory entry.
+    fn update_entry(&self, layout: &Layout) -> Result<(), &'static str> {
+        let volume = &self.contents.volume;
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        volume.read_at(&mut raw, self.entry_offset)?;
+        let mut entry = ShortEntry::parse(&raw);
+        entry.first_cluster = layout.first_cluster;
+        entry.size = layout.size as u32;
+        entry.modified = dirent::fat_timestamp();
+        entry.attributes |= ATTR_ARCHIVE;
+        volume.write_at(&entry.encode(), self.entry_offset)
+    }
+}
+
+impl File for Fat32File {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        self.check_exists()?;
+        let size = self.size();
+        if offset > size {
+            return Err("read offset exceeds file size");
+        }
+        page_cache::read_file_pages(self.id(), &self.backing(), size, buffer, offset)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        self.check_exists()?;
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(buffer.len())
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or("fat32: write would exceed the maximum file size of 4 GiB")?;
+        // The file is extended on the volume first, because the page cache only writes within its size.
+        let size = {
+            let mut layout = self.contents.layout.lock();
+            if end > layout.size {
+                self.contents.resize(&mut layout, end)?;
+            }
+            layout.size
+        };
+        let written = page_cache::write_file_pages(self.id(), &self.backing(), size, buffer, offset)?;
+        self.update_entry(&self.contents.layout.lock())?;
+        Ok(written)
+    }
+
+    fn size(&self) -> usize {
+        self.contents.layout.lock().size
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("cannot treat a FAT32 file as a memory mapped region, use memory_mapped_file instead")
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), &'static str> {
+        self.check_exists()?;
+        if size > MAX_FILE_SIZE {
+            return Err("fat32: files cannot exceed the maximum file size of 4 GiB");
+        }
+        let mut layout = self.contents.layout.lock();
+        self.contents.resize(&mut layout, size)?;
+        page_cache::truncate_file_pages(self.id(), size);
+        self.update_entry(&layout)
+    }
+}
+
+impl FsNode for Fat32File {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata { filesystem: Some(self.contents.volume.id()), inode: Some(self.entry_offset as u64), ..Metadata::default() }
+    }
+}
+
+
+/// The clusters that hold the contents of a `Fat32File`, which back the file's pages in the page cache.
+///
+/// They're shared by the file's node and its cached pages, such that its dirty pages can be written back without locking its node.
+struct Fat32Contents {
+    volume: Arc<Volume>,
+    layout: Mutex<Layout>,
+    removed: Arc<AtomicBool>,
+}
+
+/// The location and size of a FAT32 file's contents.
+struct Layout {
+    /// The first cluster of the file's data, or `0` if the file is empty and has no clusters.
+    first_cluster: u32,
+    size: usize,
+    /// The file's cluster chain, which is read from the volume the first time it's needed.
+    clusters: Option<Vec<u32>>,
+}
+
+impl Fat32Contents {
+    /// Takes the file's cluster chain out of its `layout`, reading it from the volume if necessary.
+    /// If an error occurs before it's put back, the chain will be read from the volume again next time.
+    fn take_clusters(&self, layout: &mut Layout) -> Result<Vec<u32>, &'static str> {
+        match layout.clusters.take() {
+            Some(clusters) => Ok(clusters),
+            None => self.volume.cluster_chain(layout.first_cluster),
+        }
+    }
+
+    /// Allocates or frees clusters such that the file has exactly `count` clusters.
+    fn set_cluster_count(&self, layout: &mut Layout, clusters: &mut Vec<u32>, count: usize) -> Result<(), &'static str> {
+        if count < clusters.len() {
+            match count {
+                0 => {
+                    self.volume.free_chain(layout.first_cluster)?;
+                    layout.first_cluster = 0;
+                }
+                _ => self.volume.truncate_chain(clusters[count - 1])?,
+            }
+            clusters.truncate(count);
+        }
+        while clusters.len() < count {
+            let cluster = self.volume.allocate_cluster(clusters.last().cloned())?;
+            if clusters.is_empty() {
+                layout.first_cluster = cluster;
+            }
+            clusters.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Sets the size of the file to `new_size`, allocating or freeing clusters as needed.
+    /// Any part of the file beyond its previous size will read as zeros.
+    fn resize(&self, layout: &mut Layout, new_size: usize) -> Result<(), &'static str> {
+        let cluster_size = self.volume.cluster_size();
+        let mut clusters = self.take_clusters(layout)?;
+        let old_count = clusters.len();
+        if let Err(e) = self.set_cluster_count(layout, &mut clusters, (new_size + cluster_size - 1) / cluster_size) {
+            // Don't leave clusters allocated beyond the end of the file if the volume filled up.
+            let _ = self.set_cluster_count(layout, &mut clusters, old_count);
+            return Err(e);
+        }
+        // Newly-allocated clusters are already zeroed, but the rest of the previous last cluster may not be.
+        let stale_end = min(new_size, old_count * cluster_size);
+        if stale_end > layout.size {
+            let zeros = vec![0u8; stale_end - layout.size];
+            for_each_extent(&self.volume, &clusters, layout.size, zeros.len(), |disk_offset, range| {
+                self.volume.write_at(&zeros[range], disk_offset)
+            })?;
+        }
+        layout.size = new_size;
+        layout.clusters = Some(clusters);
+        Ok(())
+    }
+}
+
+impl PageBacking for Fat32Contents {
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let mut layout = self.layout.lock();
+        let offset = page * PAGE_SIZE;
+        let len = min(PAGE_SIZE, layout.size.saturating_sub(offset));
+        if len > 0 {
+            let clusters = self.take_clusters(&mut layout)?;
+            for_each_extent(&self.volume, &clusters, offset, len, |disk_offset, range| {
+                self.volume.read_at(&mut buffer[range], disk_offset)
+            })?;
+            layout.clusters = Some(clusters);
+        }
+        for b in buffer[len..].iter_mut() { *b = 0; }
+        Ok(())
+    }
+
+    fn write_page(&self, page: usize, buffer: &[u8]) -> Result<(), &'static str> {
+        let mut layout = self.layout.lock();
+        // A deleted file's clusters may already belong to another file.
+        if self.removed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let offset = page * PAGE_SIZE;
+        let len = min(PAGE_SIZE, layout.size.saturating_sub(offset));
+        if len > 0 {
+            let clusters = self.take_clusters(&mut layout)?;
+            for_each_extent(&self.volume, &clusters, offset, len, |disk_offset, range| {
+                self.volume.write_at(&buffer[range], disk_offset)
+            })?;
+            layout.clusters = Some(clusters);
+        }
+        Ok(())
+    }
+}
+
+
+/// Finds the child named `name`, ignoring ASCII case like FAT does.
+fn find_child<'a>(children: &'a BTreeMap<String, Child>, name: &str) -> Option<&'a Child> {
+    children.get(name).or_else(|| {
+        children.iter().find(|(child_name, _)| child_name.eq_ignore_ascii_case(name)).map(|(_, child)| child)
+    })
+}
+
+/// Reads every entry slot of the directory that begins at `first_cluster`,
+/// returning each slot's volume-relative byte offset and contents.
+fn read_slots(volume: &Volume, first_cluster: u32) -> Result<Vec<(usize, [u8; DIR_ENTRY_SIZE])>, &'static str> {
+    let mut slots = Vec::new();
+    let mut cluster_data = vec![0u8; volume.cluster_size()];
+    for cluster in volume.cluster_chain(first_cluster)? {
+        let base = volume.cluster_offset(cluster);
+        volume.read_at(&mut cluster_data, base)?;
+        for (i, raw) in cluster_data.chunks(DIR_ENTRY_SIZE).enumerate() {
+            let mut slot = [0u8; DIR_ENTRY_SIZE];
+            slot.copy_from_slice(raw);
+            slots.push((base + i * DIR_ENTRY_SIZE, slot));
+        }
+    }
+    Ok(slots)
+}
+
+/// Calls `f` for each physically contiguous piece of the byte range `offset .. offset + len`
+/// of the file with the given `clusters`, with the piece's volume-relative byte offset
+/// and its range relative to `offset`.
+fn for_each_extent<F>(volume: &Volume, clusters: &[u32], offset: usize, len: usize, mut f: F) -> Result<(), &'static str>
+    where F: FnMut(usize, Range<usize>) -> Result<(), &'static str>
+{
+    let cluster_size = volume.cluster_size();
+    let end = offset + len;
+    let mut pos = offset;
+    while pos < end {
+        let index = pos / cluster_size;
+        let first = *clusters.get(index).ok_or("fat32: file's cluster chain is shorter than its size")?;
+        let mut next = index + 1;
+        while next * cluster_size < end && clusters.get(next) == Some(&(clusters[next - 1] + 1)) {
+            next += 1;
+        }
+        let piece_end = min(end, next * cluster_size);
+        f(volume.cluster_offset(first) + pos % cluster_size, pos - offset .. piece_end - offset)?;
+        pos = piece_end;
+    }
+    Ok(())
+}
@@ -0,0 +1,289 @@
+//! Access to a mounted FAT32 volume: its File Allocation Tables and the clusters they chain together.
+//!
+//! All reads and writes go through the page cache, so metadata updates are cheap
+//! and are written back to the device by the page cache's periodic flusher.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use block_io::BlockQueue;
+use bpb::{BiosParameterBlock, FSINFO_FREE_COUNT_OFFSET, FSINFO_UNKNOWN, parse_fsinfo, read_u32, BOOT_SECTOR_SIZE};
+
+
+/// The bits of a FAT entry that hold a cluster number; the top 4 bits are reserved.
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+/// The FAT entry of a free cluster.
+const FREE_CLUSTER: u32 = 0;
+/// The FAT entry of a bad cluster.
+const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+/// The smallest FAT entry that marks the end of a cluster chain.
+const END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+/// The FAT entry written at the end of a cluster chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+/// The number of bytes of a FAT read at once when searching for a free cluster.
+const FAT_SCAN_CHUNK: usize = 4096;
+
+
+/// The state of cluster allocation, cached from and written back to the FSInfo sector.
+struct AllocInfo {
+    /// The number of free clusters, if known.
+    free_count: Option<u32>,
+    /// The cluster at which to start searching for a free cluster.
+    next_free: u32,
+}
+
+/// A FAT32 volume on a block device.
+pub struct Volume {
+    queue: BlockQueue,
+    /// The byte offset of the volume within the block device.
+    offset: usize,
+    bpb: BiosParameterBlock,
+    cluster_size: usize,
+    cluster_count: u32,
+    alloc: Mutex<AllocInfo>,
+    /// The number that identifies this volume in its nodes' metadata.
+    id: u64,
+}
+
+impl Volume {
+    /// Opens the FAT32 volume that begins at the byte `offset` of the given block device.
+    pub fn open(queue: BlockQueue, offset: usize) -> Result<Volume, &'static str> {
+        let mut sector = [0u8; BOOT_SECTOR_SIZE];
+        read_exact(&queue, &mut sector, offset)?;
+        let bpb = BiosParameterBlock::parse(&sector)?;
+        let volume = Volume {
+            cluster_size: bpb.bytes_per_sector as usize * bpb.sectors_per_cluster as usize,
+            cluster_count: bpb.cluster_count(),
+            queue,
+            offset,
+            bpb,
+            alloc: Mutex::new(AllocInfo { free_count: None, next_free: 2 }),
+            id: ::fs_node::new_filesystem_id(),
+        };
+        if volume.bpb.root_cluster >= volume.cluster_count + 2 {
+            return Err("fat32: root directory cluster is out of range");
+        }
+
+        if let Some(fsinfo_offset) = volume.fsinfo_offset() {
+            volume.read_at(&mut sector, fsinfo_offset)?;
+            if let Some((free_count, next_free)) = parse_fsinfo(&sector) {
+                let mut alloc = volume.alloc.lock();
+                if free_count != FSINFO_UNKNOWN && free_count <= volume.cluster_count {
+                    alloc.free_count = Some(free_count);
+                }
+                if volume.is_data_cluster(next_free) {
+                    alloc.next_free = next_free;
+                }
+            }
+        }
+        Ok(volume)
+    }
+
+    /// Returns the block device request queue that this volume is on.
+    pub fn queue(&self) -> &BlockQueue {
+        &self.queue
+    }
+
+    /// Returns the volume label from the boot sector, with trailing spaces removed.
+    pub fn label(&self) -> &[u8] {
+        let len = self.bpb.volume_label.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        &self.bpb.volume_label[..len]
+    }
+
+    /// Returns the number that identifies this volume in its nodes' metadata.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the size in bytes of a cluster.
+    pub fn cluster_size(&self) -> usize {
+        self.cluster_size
+    }
+
+    /// Returns the first cluster of the root directory.
+    pub fn root_cluster(&self) -> u32 {
+        self.bpb.root_cluster
+    }
+
+    /// Returns the number of free clusters, if known.
+    pub fn free_clusters(&self) -> Option<u32> {
+        self.alloc.lock().free_count
+    }
+
+    /// Reads from the volume into the `buffer`, starting at the volume-relative byte `offset`.
+    pub fn read_at(&self, buffer: &mut [u8], offset: usize) -> Result<(), &'static str> {
+        read_exact(&self.queue, buffer, self.offset + offset)
+    }
+
+    /// Writes the `buffer` to the volume, starting at the volume-relative byte `offset`.
+    pub fn write_at(&self, buffer: &[u8], offset: usize) -> Result<(), &'static str> {
+        let written = page_cache::write_block_device(&self.queue, buffer, self.offset + offset)?;
+        if written != buffer.len() {
+            return Err("fat32: write extends past the end of the device");
+        }
+        Ok(())
+    }
+
+    /// Writes all of this volume's dirty pages back to the device.
+    pub fn sync(&self) -> Result<usize, &'static str> {
+        page_cache::sync_block_device(&self.queue)
+    }
+
+    /// Returns whether the given `cluster` number refers to a cluster in the data region.
+    pub fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    /// Returns the volume-relative byte offset of the given data `cluster`.
+    pub fn cluster_offset(&self, cluster: u32) -> usize {
+        self.bpb.data_sector() * self.bpb.bytes_per_sector as usize
+            + (cluster as usize - 2) * self.cluster_size
+    }
+
+    /// Returns the list of clusters in the chain that begins at `first_cluster`,
+    /// which is empty if `first_cluster` is `0`.
+    pub fn cluster_chain(&self, first_cluster: u32) -> Result<Vec<u32>, &'static str> {
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        if cluster == 0 {
+            return Ok(chain);
+        }
+        loop {
+            if !self.is_data_cluster(cluster) || chain.len() >= self.cluster_count as usize {
+                return Err("fat32: corrupted cluster chain");
+            }
+            chain.push(cluster);
+            match self.fat_entry(cluster)? {
+                next if next >= END_OF_CHAIN_MIN => return Ok(chain),
+                FREE_CLUSTER | BAD_CLUSTER => return Err("fat32: cluster chain contains a free or bad cluster"),
+                next => cluster = next,
+            }
+        }
+    }
+
+    /// Allocates a free cluster, fills it with zeros, and marks it as the end of a chain.
+    /// If `previous` is given, the new cluster is appended to the chain that `previous` ends.
+    pub fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32, &'static str> {
+        let cluster = {
+            let mut alloc = self.alloc.lock();
+            if alloc.free_count == Some(0) {
+                return Err("fat32: no free space left on the volume");
+            }
+            let cluster = self.find_free_cluster(alloc.next_free)?;
+            self.set_fat_entry(cluster, END_OF_CHAIN)?;
+            alloc.free_count = alloc.free_count.map(|count| count - 1);
+            alloc.next_free = if self.is_data_cluster(cluster + 1) { cluster + 1 } else { 2 };
+            self.write_fsinfo(&alloc)?;
+            cluster
+        };
+
+        let zeros = vec![0u8; self.cluster_size];
+        self.write_at(&zeros, self.cluster_offset(cluster))?;
+        if let Some(prev) = previous {
+            self.set_fat_entry(prev, cluster)?;
+        }
+        Ok(cluster)
+    }
+
+    /// Frees every cluster in the chain that begins at `first_cluster`.
+    pub fn free_chain(&self, first_cluster: u32) -> Result<(), &'static str> {
+        let chain = self.cluster_chain(first_cluster)?;
+        let mut alloc = self.alloc.lock();
+        for &cluster in &chain {
+            self.set_fat_entry(cluster, FREE_CLUSTER)?;
+        }
+        alloc.free_count = alloc.free_count.map(|count| count + chain.len() as u32);
+        if let Some(&first) = chain.iter().min() {
+            alloc.next_free = core::cmp::min(alloc.next_free, first);
+        }
+        self.write_fsinfo(&alloc)
+    }
+
+    /// Marks the given `cluster` as the end of its chain, freeing every cluster after it.
+    pub fn truncate_chain(&self, cluster: u32) -> Result<(), &'static str> {
+        let next = self.fat_entry(cluster)?;
+        self.set_fat_entry(cluster, END_OF_CHAIN)?;
+        if self.is_data_cluster(next) {
+            self.free_chain(next)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the given `cluster`'s entry in the active FAT.
+    fn fat_entry(&self, cluster: u32) -> Result<u32, &'static str> {
+        let mut entry = [0u8; 4];
+        self.read_at(&mut entry, self.fat_entry_offset(self.bpb.active_fat(), cluster))?;
+        Ok(u32::from_le_bytes(entry) & FAT_ENTRY_MASK)
+    }
+
+    /// Sets the given `cluster`'s entry in every mirrored FAT to `value`,
+    /// preserving the reserved top bits of the existing entry.
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), &'static str> {
+        for fat in self.bpb.mirrored_fats() {
+            let offset = self.fat_entry_offset(fat, cluster);
+            let mut entry = [0u8; 4];
+            self.read_at(&mut entry, offset)?;
+            let new = (u32::from_le_bytes(entry) & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+            self.write_at(&new.to_le_bytes(), offset)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the volume-relative byte offset of the given `cluster`'s entry in the given `fat`.
+    fn fat_entry_offset(&self, fat: u8, cluster: u32) -> usize {
+        let bytes_per_sector = self.bpb.bytes_per_sector as usize;
+        (self.bpb.reserved_sectors as usize + fat as usize * self.bpb.sectors_per_fat as usize) * bytes_per_sector
+            + cluster as usize * 4
+    }
+
+    /// Searches the active FAT for a free cluster, starting at `start` and wrapping around.
+    fn find_free_cluster(&self, start: u32) -> Result<u32, &'static str> {
+        let end = self.cluster_count + 2;
+        let mut chunk = vec![0u8; FAT_SCAN_CHUNK];
+        let mut cluster = if self.is_data_cluster(start) { start } else { 2 };
+        let mut scanned = 0;
+        while scanned < self.cluster_count {
+            let count = core::cmp::min(FAT_SCAN_CHUNK / 4, (end - cluster) as usize);
+            let entries = &mut chunk[.. count * 4];
+            self.read_at(entries, self.fat_entry_offset(self.bpb.active_fat(), cluster))?;
+            if let Some(i) = entries.chunks(4).position(|e| read_u32(e, 0) & FAT_ENTRY_MASK == FREE_CLUSTER) {
+                return Ok(cluster + i as u32);
+            }
+            scanned += count as u32;
+            cluster += count as u32;
+            if cluster >= end {
+                cluster = 2;
+            }
+        }
+        Err("fat32: no free space left on the volume")
+    }
+
+    /// Returns the volume-relative byte offset of the FSInfo sector, if the volume has one.
+    fn fsinfo_offset(&self) -> Option<usize> {
+        match self.bpb.fsinfo_sector {
+            0 | 0xFFFF => None,
+            sector if sector < self.bpb.reserved_sectors => Some(sector as usize * self.bpb.bytes_per_sector as usize),
+            _ => None,
+        }
+    }
+
+    /// Updates the free cluster count and next free cluster hint in the FSInfo sector.
+    fn write_fsinfo(&self, alloc: &AllocInfo) -> Result<(), &'static str> {
+        if let Some(offset) = self.fsinfo_offset() {
+            let mut fields = [0u8; 8];
+            fields[..4].copy_from_slice(&alloc.free_count.unwrap_or(FSINFO_UNKNOWN).to_le_bytes());
+            fields[4..].copy_from_slice(&alloc.next_free.to_le_bytes());
+            self.write_at(&fields, offset + FSINFO_FREE_COUNT_OFFSET)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Reads exactly `buffer.len()` bytes from the given block device at the byte `offset`, through the page cache.
+pub(crate) fn read_exact(queue: &BlockQueue, buffer: &mut [u8], offset: usize) -> Result<(), &'static str> {
+    let read = page_cache::read_block_device(queue, buffer, offset)?;
+    if read != buffer.len() {
+        return Err("fat32: read extends past the end of the device");
+    }
+    Ok(())
+}
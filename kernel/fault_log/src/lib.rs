@@ -15,6 +15,7 @@ extern crate memory;
 extern crate task;
 extern crate apic;
 extern crate irq_safety;
+extern crate backtrace;
 
 use alloc::{
     string::{String,ToString},
@@ -24,6 +25,7 @@ use memory::VirtualAddress;
 use apic::get_my_apic_id;
 use irq_safety::MutexIrqSafe;
 use core::panic::PanicInfo;
+use backtrace::Backtrace;
 
 /// The possible faults (panics and exceptions) encountered 
 /// during operations.
@@ -100,6 +102,8 @@ pub struct FaultEntry {
     pub instruction_pointer: Option<VirtualAddress>,    
     /// Crate the address at which exception occured located
     pub crate_error_occured: Option<String>,
+    /// The symbolized backtrace of the faulting task's call stack, if one was captured.
+    pub backtrace: Option<Backtrace>,
     /// List of crates reloaded from memory to recover from fault
     pub replaced_crates: Vec<String>,
     /// Recovery Action taken as a result of the fault
@@ -120,6 +124,7 @@ impl FaultEntry {
             address_accessed: None,
             instruction_pointer: None,
             crate_error_occured: None,
+            backtrace: None,
             replaced_crates: Vec::<String>::new(),
             action_taken: RecoveryAction::None,
         }
@@ -216,6 +221,31 @@ pub fn log_panic_entry(panic_info: &PanicInfo) {
     update_and_insert_fault_entry_internal(fe, None);
 }
 
+/// Attaches the given `backtrace` to the most recent unhandled fault that occurred on this core
+/// and doesn't yet have a backtrace, i.e., the fault currently being handled.
+/// 
+/// Returns `Err` with the given `backtrace` if there is no such fault.
+pub fn attach_backtrace(backtrace: Backtrace) -> Result<(), Backtrace> {
+    let my_core = Some(get_my_apic_id());
+    let mut list = FAULT_LIST.lock();
+    match list.iter_mut().rev().find(|fe| fe.core == my_core && fe.action_taken == RecoveryAction::None && fe.backtrace.is_none()) {
+        Some(fe) => {
+            fe.backtrace = Some(backtrace);
+            Ok(())
+        }
+        None => Err(backtrace),
+    }
+}
+
+/// Returns a copy of the most recent unhandled fault that occurred on this core,
+/// i.e., the fault currently being handled, if there is one.
+pub fn current_fault() -> Option<FaultEntry> {
+    let my_core = Some(get_my_apic_id());
+    FAULT_LIST.lock().iter().rev()
+        .find(|fe| fe.core == my_core && fe.action_taken == RecoveryAction::None)
+        .cloned()
+}
+
 /// Removes the unhandled faults from the fault log and returns. 
 /// Is useful when we update the recovery detail about unhandled exceptions. 
 pub fn remove_unhandled_exceptions() -> Vec<FaultEntry> {
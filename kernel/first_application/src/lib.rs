@@ -20,6 +20,8 @@ use path::Path;
 /// 
 /// Currently this only spawns a shell (terminal),
 /// but in the future it could spawn a fuller desktop environment. 
+/// If Theseus is built with `THESEUS_CONFIG=remote_shell`, 
+/// the `remote_shell` daemon is also started on its default port.
 /// 
 /// Kernel initialization routines should be complete before invoking this. 
 pub fn start() -> Result<(), &'static str> {
@@ -30,9 +32,19 @@ pub fn start() -> Result<(), &'static str> {
     let path = Path::new(shell_file.lock().get_absolute_path());
     info!("Starting first application: crate at {:?}", path);
     // Spawn the default shell
-    spawn::new_application_task_builder(path, Some(new_app_ns))?
+    spawn::new_application_task_builder(path, Some(new_app_ns.clone()))?
         .name("default_shell".to_string())
         .spawn()?;
 
+    #[cfg(remote_shell)] {
+        let (remote_shell_file, _ns) = CrateNamespace::get_crate_object_file_starting_with(&new_app_ns, "remote_shell-")
+            .ok_or("Couldn't find remote_shell application in default app namespace")?;
+        let path = Path::new(remote_shell_file.lock().get_absolute_path());
+        info!("Starting remote shell daemon: crate at {:?}", path);
+        spawn::new_application_task_builder(path, Some(new_app_ns))?
+            .name("remote_shell".to_string())
+            .spawn()?;
+    }
+
     Ok(())
 }
@@ -15,7 +15,7 @@ pub mod pixel;
 use alloc::boxed::Box;
 use core::ops::DerefMut;
 
-use memory::{EntryFlags, FrameRange, MappedPages, PhysicalAddress, get_frame_allocator_ref};
+use memory::{EntryFlags, FrameRange, MappedPages, PageSize, PhysicalAddress, get_frame_allocator_ref};
 use owning_ref::BoxRefMut;
 use shapes::Coord;
 pub use pixel::*;
@@ -73,10 +73,16 @@ impl<P: Pixel> Framebuffer<P> {
             EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::GLOBAL | EntryFlags::NO_CACHE;
 
         let size = width * height * core::mem::size_of::<P>();
-        let pages = memory::allocate_pages_by_bytes(size).ok_or("could not allocate pages")?;
 
         let mapped_framebuffer = if let Some(address) = physical_address {
             let frame = FrameRange::from_phys_addr(address, size);
+            // Align the virtual pages like the physical frames, such that large framebuffers can be mapped using huge pages.
+            let huge_page_alignment = PageSize::Huge2MiB.size_in_pages();
+            let pages = if frame.size_in_frames() >= huge_page_alignment && frame.start().number % huge_page_alignment == 0 {
+                memory::allocate_pages_aligned(frame.size_in_frames(), huge_page_alignment)
+            } else {
+                memory::allocate_pages_by_bytes(size)
+            }.ok_or("could not allocate pages")?;
             kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
                 pages,
                 frame,
@@ -84,6 +90,7 @@ impl<P: Pixel> Framebuffer<P> {
                 allocator.lock().deref_mut()
             )?
         } else {
+            let pages = memory::allocate_pages_by_bytes(size).ok_or("could not allocate pages")?;
             kernel_mmi_ref.lock().page_table.map_allocated_pages(
                 pages,
                 vesa_display_flags,
@@ -124,6 +131,19 @@ impl<P: Pixel> Framebuffer<P> {
         Pixel::composite_buffer(src, &mut self.buffer_mut()[index..dest_end]);
     }
 
+    /// Composites `src` to the buffer starting from `index`, fading it by `opacity`,
+    /// where 255 leaves `src` unchanged and 0 makes it invisible.
+    pub fn composite_buffer_with_opacity(&mut self, src: &[P], index: usize, opacity: u8) {
+        let dest_end = index + src.len();
+        Pixel::composite_buffer_with_opacity(src, &mut self.buffer_mut()[index..dest_end], opacity);
+    }
+
+    /// Copies `src` to the buffer starting from `index`, overwriting the existing pixels instead of blending with them.
+    pub fn overwrite_buffer(&mut self, src: &[P], index: usize) {
+        let dest_end = index + src.len();
+        self.buffer_mut()[index..dest_end].copy_from_slice(src);
+    }
+
     /// Draw a pixel at the given 1coordinate1. 
     /// The `pixel` will be blended with the existing pixel value
     /// at that `coordinate` in this framebuffer.
@@ -136,7 +156,9 @@ impl<P: Pixel> Framebuffer<P> {
     /// Overwites a pixel at the given coordinate in this framebuffer
     /// instead of blending it like [`draw_pixel`](#method.draw_pixel).
     pub fn overwrite_pixel(&mut self, coordinate: Coord, pixel: P) {
-        self.draw_pixel(coordinate, pixel)
+        if let Some(index) = self.index_of(coordinate) {
+            self.buffer[index] = pixel;
+        }
     }
 
     /// Returns the pixel value at the given `coordinate` in this framebuffer.
@@ -14,6 +14,18 @@ pub trait Pixel: Copy + Hash + FromBytes {
 
     /// Blend two pixels linearly with weights, as `blend` for `origin` and (1-`blend`) for `other`.
     fn weight_blend(origin: Self, other: Self, blend: f32) -> Self;
+
+    /// Composites the `src` pixel slice to the `dest` pixel slice like `composite_buffer`,
+    /// but additionally fades `src` by `opacity`, where 255 leaves it unchanged and 0 makes it invisible.
+    fn composite_buffer_with_opacity(src: &[Self], dest: &mut[Self], opacity: u8) {
+        if opacity == 255 {
+            return Self::composite_buffer(src, dest);
+        }
+        let weight = opacity as f32 / 255f32;
+        for i in 0..src.len() {
+            dest[i] = Self::weight_blend(src[i].blend(dest[i]), dest[i], weight);
+        }
+    }
 }
 
 
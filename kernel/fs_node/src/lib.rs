@@ -19,6 +19,7 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicU64, Ordering};
 use memory::MappedPages;
 
 
@@ -60,8 +61,69 @@ pub trait FsNode {
     /// This is useful for ensuring correctness when inserting or remonving 
     /// files or directories from their parent directory.
     fn set_parent_dir(&mut self, new_parent: WeakDirRef);
+
+    /// Returns this node's metadata, e.g., its timestamps and permissions.
+    ///
+    /// The default implementation returns metadata with no fields set, for nodes that don't record any.
+    fn metadata(&self) -> Metadata {
+        Metadata::default()
+    }
+
+    /// Sets this node's Unix permission bits, e.g., `0o644`.
+    ///
+    /// This doesn't check whether the caller may do so; see the `access_control` crate for that.
+    /// The default implementation returns an error, for nodes that don't record permissions.
+    fn set_permissions(&mut self, _permissions: u16) -> Result<(), &'static str> {
+        Err("this filesystem does not support permissions")
+    }
+
+    /// Sets the IDs of the user and group that own this node.
+    ///
+    /// This doesn't check whether the caller may do so; see the `access_control` crate for that.
+    /// The default implementation returns an error, for nodes that don't record ownership.
+    fn set_owner(&mut self, _owner: u32, _group: u32) -> Result<(), &'static str> {
+        Err("this filesystem does not support ownership")
+    }
 } 
 
+/// The metadata of a file or directory, each field of which is `None` if the node doesn't record it.
+/// Timestamps are given in seconds since the Unix epoch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// A number that identifies the filesystem that the node is on, as given by [`new_filesystem_id()`](fn.new_filesystem_id.html).
+    /// Together with the `inode`, this identifies the node among all filesystems.
+    pub filesystem: Option<u64>,
+    /// A number that identifies the node within its filesystem.
+    pub inode: Option<u64>,
+    /// The time the node was created.
+    pub created: Option<u64>,
+    /// The time the node's contents were last changed.
+    pub modified: Option<u64>,
+    /// The time the node's contents were last read.
+    pub accessed: Option<u64>,
+    /// The Unix permission bits, e.g., `0o644`.
+    pub permissions: Option<u16>,
+    /// The ID of the user that owns the node.
+    pub owner: Option<u32>,
+    /// The ID of the group that owns the node.
+    pub group: Option<u32>,
+    /// The number of bytes of storage used by the node's contents,
+    /// which is less than its size for a sparse file.
+    pub allocated_bytes: Option<usize>,
+}
+
+/// The error returned by the default implementation of [`Directory::create_file()`](trait.Directory.html#method.create_file).
+pub const CREATE_FILE_UNSUPPORTED: &'static str = "this directory does not support creating files";
+
+/// The number that will be given to the next filesystem by `new_filesystem_id()`.
+static NEXT_FILESYSTEM_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns a new number that identifies a filesystem in the [`Metadata`](struct.Metadata.html) of its nodes,
+/// which is never given to any other filesystem.
+pub fn new_filesystem_id() -> u64 {
+    NEXT_FILESYSTEM_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 // Trait for files, implementors of File must also implement FsNode
 pub trait File : FsNode {
     /// Reads the contents of this file starting at the given `offset` and copies them into the given `buffer`.
@@ -76,6 +138,14 @@ pub trait File : FsNode {
 
     /// Returns a view of this file as an immutable memory-mapped region.
     fn as_mapping(&self) -> Result<&MappedPages, &'static str>;
+
+    /// Sets the size of this file to `size` bytes, discarding any contents past the new end
+    /// or filling the extended part with zeros.
+    ///
+    /// The default implementation returns an error, for files that cannot be resized.
+    fn truncate(&mut self, _size: usize) -> Result<(), &'static str> {
+        Err("this file does not support truncation")
+    }
 }
 
 /// Trait for directories, implementors of Directory must also implement FsNode
@@ -118,6 +188,22 @@ pub trait Directory : FsNode {
 
     /// Lists the names of the nodes in this directory.
     fn list(&self) -> Vec<String>;
+
+    /// Creates a new, empty file named `name` in this directory and returns it.
+    ///
+    /// This is how files are created in directories backed by a storage device,
+    /// which cannot hold arbitrary nodes passed to [`insert()`](#tymethod.insert).
+    /// The default implementation returns an error, for directories that don't support it.
+    fn create_file(&mut self, _name: &str) -> Result<FileRef, &'static str> {
+        Err(CREATE_FILE_UNSUPPORTED)
+    }
+
+    /// Creates a new, empty directory named `name` in this directory and returns it.
+    ///
+    /// The default implementation returns an error, for directories that don't support it.
+    fn create_dir(&mut self, _name: &str) -> Result<DirRef, &'static str> {
+        Err("this directory does not support creating directories")
+    }
 }
 
 /// Allows us to return a generic type that can be matched by the caller to extract the underlying type
@@ -157,4 +243,25 @@ impl FsNode for FileOrDir {
             FileOrDir::Dir(dir) => dir.lock().set_parent_dir(new_parent),
         }
     }
+
+    fn metadata(&self) -> Metadata {
+        match self {
+            FileOrDir::File(file) => file.lock().metadata(),
+            FileOrDir::Dir(dir) => dir.lock().metadata(),
+        }
+    }
+
+    fn set_permissions(&mut self, permissions: u16) -> Result<(), &'static str> {
+        match self {
+            FileOrDir::File(file) => file.lock().set_permissions(permissions),
+            FileOrDir::Dir(dir) => dir.lock().set_permissions(permissions),
+        }
+    }
+
+    fn set_owner(&mut self, owner: u32, group: u32) -> Result<(), &'static str> {
+        match self {
+            FileOrDir::File(file) => file.lock().set_owner(owner, group),
+            FileOrDir::Dir(dir) => dir.lock().set_owner(owner, group),
+        }
+    }
 }
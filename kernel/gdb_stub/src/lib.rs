@@ -0,0 +1,588 @@
+//! A stub that implements the GDB remote serial protocol over a dedicated serial port (COM2),
+//! allowing Theseus to be debugged with GDB on real hardware, not just via QEMU's built-in `-gdb` server.
+//!
+//! The stub is activated by the `gdb` boot parameter on the kernel command line.
+//! With `gdb=wait`, Theseus stops at a breakpoint during boot until GDB connects, e.g.:
+//! ```text
+//! (gdb) target remote /dev/ttyS1        # on real hardware, or
+//! (gdb) target remote localhost:1235    # with QEMU's `-serial tcp::1235,server,nowait` as the second serial port
+//! ```
+//!
+//! Supported features include software breakpoints, single-stepping,
+//! register and memory reads and writes, and listing Theseus tasks as GDB threads.
+//!
+//! # Limitations
+//! * Only the registers saved in the exception stack frame (`rip`, `rsp`, `rflags`, `cs`, `ss`) are available;
+//!   the general-purpose registers are reported to GDB as unavailable.
+//!   For threads (tasks) other than the one that stopped, only `rsp` (its saved stack pointer) is available.
+//! * Only the core that hit a breakpoint is stopped; other cores continue running.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+#![feature(llvm_asm)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate x86_64;
+extern crate irq_safety;
+extern crate port_io;
+extern crate kernel_config;
+extern crate memory;
+extern crate task;
+extern crate boot_params;
+
+mod uart;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    vec::Vec,
+};
+use core::fmt::Write;
+use irq_safety::MutexIrqSafe;
+use x86_64::structures::idt::{LockedIdt, ExceptionStackFrame};
+use kernel_config::memory::PAGE_SIZE;
+use memory::VirtualAddress;
+use task::{TASKLIST, RunState};
+use uart::Uart;
+
+
+/// The boot parameter that activates the GDB stub; `gdb=wait` also waits for GDB to connect during boot.
+pub const BOOT_PARAM: &'static str = "gdb";
+
+/// The x86 `int3` software breakpoint instruction.
+const INT3: u8 = 0xCC;
+/// The trap flag in RFLAGS, which causes a debug exception after every instruction.
+const TRAP_FLAG: u64 = 1 << 8;
+/// The write protect bit in CR0, which prevents the kernel from writing to read-only pages.
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
+/// The POSIX signal number reported to GDB for breakpoints and single steps.
+const SIGTRAP: u8 = 5;
+/// The maximum packet size we advertise to GDB.
+const MAX_PACKET_SIZE: usize = 0x4000;
+/// The number of registers in the amd64 `g` packet: 16 GPRs, rip, eflags, and 6 segment registers.
+const NUM_REGISTERS: usize = 24;
+
+static GDB_UART: Uart = Uart::new(uart::COM2_BASE);
+
+/// The state of the debugger that persists while the stopped code is running.
+struct GdbState {
+    /// The original byte at the address of each inserted software breakpoint.
+    breakpoints: BTreeMap<usize, u8>,
+    /// The address of a breakpoint that was temporarily removed in order to step over it.
+    reinsert_after_step: Option<usize>,
+    /// Whether GDB asked to single-step, in which case the next debug exception stops again.
+    stepping: bool,
+    /// Whether GDB resumed execution and is thus waiting for a stop reply packet.
+    awaiting_stop_reply: bool,
+    /// The GDB thread ID selected for register accesses via the `Hg` packet, if any.
+    selected_thread: Option<usize>,
+}
+
+lazy_static! {
+    static ref STATE: MutexIrqSafe<GdbState> = MutexIrqSafe::new(GdbState {
+        breakpoints: BTreeMap::new(),
+        reinsert_after_step: None,
+        stepping: false,
+        awaiting_stop_reply: false,
+        selected_thread: None,
+    });
+}
+
+
+/// Initializes the GDB stub if the `gdb` boot parameter is present,
+/// replacing the breakpoint and debug exception handlers in the given IDT.
+///
+/// This must be invoked after the task-aware exception handlers have been set up,
+/// otherwise they would overwrite the GDB stub's handlers.
+pub fn init(idt_ref: &'static LockedIdt) -> Result<(), &'static str> {
+    if !boot_params::is_present(BOOT_PARAM) {
+        return Ok(());
+    }
+
+    GDB_UART.init();
+    {
+        let mut idt = idt_ref.lock(); // withholds interrupts
+        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.debug.set_handler_fn(debug_handler);
+    }
+    info!("GDB stub is listening on COM2 ({:#X})", uart::COM2_BASE);
+
+    if boot_params::get_value(BOOT_PARAM) == Some("wait") {
+        info!("Waiting for GDB to connect on COM2...");
+        breakpoint();
+    }
+    Ok(())
+}
+
+/// Stops the current core at a breakpoint, transferring control to GDB.
+#[inline(always)]
+pub fn breakpoint() {
+    // SAFE: the breakpoint exception handler returns to the next instruction.
+    unsafe { llvm_asm!("int3" : : : "memory" : "volatile"); }
+}
+
+
+/// exception 0x03
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut ExceptionStackFrame) {
+    // The instruction pointer is just past the `int3` instruction,
+    // so if it was one of our breakpoints, rewind it such that the original instruction will run.
+    let bp_addr = stack_frame.instruction_pointer.0.wrapping_sub(1);
+    if STATE.lock().breakpoints.contains_key(&bp_addr) {
+        stack_frame.instruction_pointer = x86_64::VirtualAddress(bp_addr);
+    }
+    debug_loop(stack_frame, SIGTRAP);
+}
+
+/// exception 0x01
+extern "x86-interrupt" fn debug_handler(stack_frame: &mut ExceptionStackFrame) {
+    let (stepping, reinserted) = {
+        let mut state = STATE.lock();
+        let reinserted = match state.reinsert_after_step.take() {
+            Some(addr) => {
+                let _ = write_memory(addr, &[INT3]);
+                true
+            }
+            None => false,
+        };
+        (state.stepping, reinserted)
+    };
+
+    if reinserted && !stepping {
+        // We only single-stepped over a breakpoint in order to re-insert it, so keep running.
+        stack_frame.cpu_flags &= !TRAP_FLAG;
+    } else {
+        debug_loop(stack_frame, SIGTRAP);
+    }
+}
+
+
+/// The action to take after handling a packet from GDB.
+enum Action {
+    /// Send the given reply and keep waiting for packets.
+    Reply(String),
+    /// Resume execution, either continuing or single-stepping.
+    Resume { step: bool },
+    /// Remove all breakpoints and resume execution without stopping again.
+    Detach,
+}
+
+/// Handles packets from GDB until it resumes execution of the stopped code in the given `stack_frame`.
+fn debug_loop(stack_frame: &mut ExceptionStackFrame, signal: u8) {
+    let current_thread = current_gdb_thread_id();
+    let send_stop_reply = {
+        let mut state = STATE.lock();
+        state.stepping = false;
+        state.selected_thread = None;
+        core::mem::replace(&mut state.awaiting_stop_reply, false)
+    };
+    if send_stop_reply {
+        send_packet(&stop_reply(signal, current_thread));
+    }
+
+    loop {
+        let packet = recv_packet();
+        match handle_packet(&packet, stack_frame, signal, current_thread) {
+            Action::Reply(reply) => send_packet(&reply),
+            Action::Resume { step } => {
+                resume(stack_frame, step);
+                return;
+            }
+            Action::Detach => {
+                send_packet("OK");
+                let mut state = STATE.lock();
+                let breakpoints = core::mem::replace(&mut state.breakpoints, BTreeMap::new());
+                for (addr, orig_byte) in breakpoints {
+                    let _ = write_memory(addr, &[orig_byte]);
+                }
+                state.reinsert_after_step = None;
+                stack_frame.cpu_flags &= !TRAP_FLAG;
+                return;
+            }
+        }
+    }
+}
+
+/// Prepares the stopped code to resume execution, stepping over a breakpoint at the current instruction if needed.
+fn resume(stack_frame: &mut ExceptionStackFrame, step: bool) {
+    let rip = stack_frame.instruction_pointer.0;
+    let mut state = STATE.lock();
+    state.stepping = step;
+    state.awaiting_stop_reply = true;
+    if let Some(&orig_byte) = state.breakpoints.get(&rip) {
+        // Temporarily restore the original instruction, and re-insert the breakpoint after executing it.
+        let _ = write_memory(rip, &[orig_byte]);
+        state.reinsert_after_step = Some(rip);
+        stack_frame.cpu_flags |= TRAP_FLAG;
+    } else if step {
+        stack_frame.cpu_flags |= TRAP_FLAG;
+    } else {
+        stack_frame.cpu_flags &= !TRAP_FLAG;
+    }
+}
+
+fn handle_packet(packet: &[u8], stack_frame: &mut ExceptionStackFrame, signal: u8, current_thread: usize) -> Action {
+    let packet = core::str::from_utf8(packet).unwrap_or("");
+    let reply = |s: &str| Action::Reply(String::from(s));
+    let (cmd, args) = packet.split_at(core::cmp::min(1, packet.len()));
+
+    match cmd {
+        "?" => Action::Reply(stop_reply(signal, current_thread)),
+        "q" => handle_query(args, current_thread),
+        "H" => {
+            // `Hg<tid>` selects the thread for register accesses; `Hc` is for resuming, which we ignore.
+            if args.starts_with('g') {
+                STATE.lock().selected_thread = parse_thread_id(&args[1..]);
+            }
+            reply("OK")
+        }
+        "T" => match parse_thread_id(args) {
+            Some(tid) if task::get_task(tid - 1).is_some() => reply("OK"),
+            _ => reply("E01"),
+        },
+        "g" => {
+            let selected = STATE.lock().selected_thread.unwrap_or(current_thread);
+            let mut out = String::new();
+            for regnum in 0..NUM_REGISTERS {
+                out.push_str(&read_register(regnum, stack_frame, selected, current_thread));
+            }
+            Action::Reply(out)
+        }
+        "G" => {
+            // Only rip and eflags of the stopped thread can be written.
+            let mut offset = 0;
+            for regnum in 0..NUM_REGISTERS {
+                let width = register_width(regnum) * 2;
+                if let Some(hex) = args.get(offset .. offset + width) {
+                    let _ = write_register(regnum, hex, stack_frame);
+                }
+                offset += width;
+            }
+            reply("OK")
+        }
+        "p" => match usize::from_str_radix(args, 16) {
+            Ok(regnum) if regnum < NUM_REGISTERS => {
+                let selected = STATE.lock().selected_thread.unwrap_or(current_thread);
+                Action::Reply(read_register(regnum, stack_frame, selected, current_thread))
+            }
+            _ => reply("E01"),
+        },
+        "P" => {
+            let mut iter = args.splitn(2, '=');
+            match (iter.next().and_then(|r| usize::from_str_radix(r, 16).ok()), iter.next()) {
+                (Some(regnum), Some(hex)) if write_register(regnum, hex, stack_frame) => reply("OK"),
+                _ => reply("E01"),
+            }
+        }
+        "m" => match parse_addr_len(args) {
+            Some((addr, len)) => {
+                let len = core::cmp::min(len, MAX_PACKET_SIZE / 2);
+                match read_memory(addr, len) {
+                    Some(bytes) => Action::Reply(encode_hex(&bytes)),
+                    None => reply("E14"),
+                }
+            }
+            None => reply("E01"),
+        },
+        "M" => {
+            let mut iter = args.splitn(2, ':');
+            match (iter.next().and_then(parse_addr_len), iter.next().and_then(decode_hex)) {
+                (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                    if write_memory(addr, &bytes).is_ok() { reply("OK") } else { reply("E14") }
+                }
+                _ => reply("E01"),
+            }
+        }
+        "Z" | "z" => {
+            // Only software breakpoints (type 0) are supported.
+            let mut iter = args.split(',');
+            let typ = iter.next();
+            let addr = iter.next().and_then(|a| usize::from_str_radix(a, 16).ok());
+            match (typ, addr) {
+                (Some("0"), Some(addr)) => {
+                    let res = if cmd == "Z" { insert_breakpoint(addr) } else { remove_breakpoint(addr) };
+                    if res.is_ok() { reply("OK") } else { reply("E14") }
+                }
+                _ => reply(""),
+            }
+        }
+        "c" | "s" => {
+            if let Ok(addr) = usize::from_str_radix(args, 16) {
+                stack_frame.instruction_pointer = x86_64::VirtualAddress(addr);
+            }
+            Action::Resume { step: cmd == "s" }
+        }
+        // We can't kill the whole kernel, so treat a kill request like a detach.
+        "D" | "k" => Action::Detach,
+        // An empty reply indicates that the packet is unsupported.
+        _ => reply(""),
+    }
+}
+
+fn handle_query(query: &str, current_thread: usize) -> Action {
+    if query.starts_with("Supported") {
+        Action::Reply(format!("PacketSize={:x}", MAX_PACKET_SIZE))
+    }
+    else if query == "Attached" {
+        Action::Reply(String::from("1"))
+    }
+    else if query == "C" {
+        Action::Reply(format!("QC{:x}", current_thread))
+    }
+    else if query == "fThreadInfo" {
+        let ids: Vec<String> = TASKLIST.lock().keys().map(|id| format!("{:x}", id + 1)).collect();
+        Action::Reply(format!("m{}", ids.join(",")))
+    }
+    else if query == "sThreadInfo" {
+        Action::Reply(String::from("l"))
+    }
+    else if query.starts_with("ThreadExtraInfo,") {
+        let info = parse_thread_id(&query["ThreadExtraInfo,".len()..])
+            .and_then(|tid| task::get_task(tid - 1))
+            .map(|taskref| {
+                let t = taskref.lock();
+                let runstate = match t.runstate {
+                    RunState::Initing   => "Initing",
+                    RunState::Runnable  => "Runnable",
+                    RunState::Blocked   => "Blocked",
+                    RunState::Exited(_) => "Exited",
+                    RunState::Reaped    => "Reaped",
+                };
+                format!("{} ({})", t.name, runstate)
+            })
+            .unwrap_or_else(|| String::from("unknown task"));
+        Action::Reply(encode_hex(info.as_bytes()))
+    }
+    else {
+        Action::Reply(String::new())
+    }
+}
+
+fn stop_reply(signal: u8, current_thread: usize) -> String {
+    format!("T{:02x}thread:{:x};", signal, current_thread)
+}
+
+/// GDB thread IDs must be positive, so each is the Theseus task ID plus one.
+fn current_gdb_thread_id() -> usize {
+    task::get_my_current_task_id().map(|id| id + 1).unwrap_or(1)
+}
+
+/// Parses a GDB thread ID, for which `0` and `-1` mean "any thread" and thus return `None`.
+fn parse_thread_id(s: &str) -> Option<usize> {
+    match usize::from_str_radix(s, 16) {
+        Ok(0) | Err(_) => None,
+        Ok(tid) => Some(tid),
+    }
+}
+
+/// Parses a memory range argument of the form `addr,length`.
+fn parse_addr_len(s: &str) -> Option<(usize, usize)> {
+    let mut iter = s.splitn(2, ',');
+    let addr = usize::from_str_radix(iter.next()?, 16).ok()?;
+    let len = usize::from_str_radix(iter.next()?, 16).ok()?;
+    Some((addr, len))
+}
+
+
+/// Returns the size in bytes of the given register in the amd64 `g` packet.
+fn register_width(regnum: usize) -> usize {
+    if regnum <= 16 { 8 } else { 4 }
+}
+
+/// Returns the given register as little-endian hex, or `x`s if its value is unavailable.
+fn read_register(regnum: usize, stack_frame: &ExceptionStackFrame, thread: usize, current_thread: usize) -> String {
+    let value: Option<u64> = if thread == current_thread {
+        match regnum {
+            7  => Some(stack_frame.stack_pointer.0 as u64),
+            16 => Some(stack_frame.instruction_pointer.0 as u64),
+            17 => Some(stack_frame.cpu_flags),
+            18 => Some(stack_frame.code_segment),
+            19 => Some(stack_frame.stack_segment),
+            _  => None,
+        }
+    } else {
+        // For other tasks, only the saved stack pointer is known.
+        match regnum {
+            7 => task::get_task(thread - 1).map(|t| t.lock().saved_sp as u64),
+            _ => None,
+        }
+    };
+    let width = register_width(regnum);
+    match value {
+        Some(v) => encode_hex(&v.to_le_bytes()[..width]),
+        None => "xx".repeat(width),
+    }
+}
+
+/// Writes the given little-endian hex value into a register of the stopped thread.
+/// Only rip and eflags can be written; returns false for other registers.
+fn write_register(regnum: usize, hex: &str, stack_frame: &mut ExceptionStackFrame) -> bool {
+    let bytes = match decode_hex(hex) {
+        Some(b) if b.len() == register_width(regnum) => b,
+        _ => return false,
+    };
+    let mut le = [0u8; 8];
+    le[..bytes.len()].copy_from_slice(&bytes);
+    let value = u64::from_le_bytes(le);
+    match regnum {
+        16 => stack_frame.instruction_pointer = x86_64::VirtualAddress(value as usize),
+        17 => stack_frame.cpu_flags = value,
+        _ => return false,
+    }
+    true
+}
+
+
+fn insert_breakpoint(addr: usize) -> Result<(), &'static str> {
+    let mut state = STATE.lock();
+    if state.breakpoints.contains_key(&addr) {
+        return Ok(());
+    }
+    let orig_byte = read_memory(addr, 1).ok_or("breakpoint address is not mapped")?[0];
+    write_memory(addr, &[INT3])?;
+    state.breakpoints.insert(addr, orig_byte);
+    Ok(())
+}
+
+fn remove_breakpoint(addr: usize) -> Result<(), &'static str> {
+    let mut state = STATE.lock();
+    if state.reinsert_after_step == Some(addr) {
+        state.reinsert_after_step = None;
+    }
+    match state.breakpoints.remove(&addr) {
+        Some(orig_byte) => write_memory(addr, &[orig_byte]),
+        None => Ok(()),
+    }
+}
+
+/// Returns true if every page in the given range of memory is mapped in the kernel's page table.
+fn is_mapped(addr: usize, len: usize) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let end = match addr.checked_add(len - 1) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mmi_ref = match memory::get_kernel_mmi_ref() {
+        Some(mmi_ref) => mmi_ref,
+        None => return false,
+    };
+    // The stopped code may hold the lock, so we can't wait for it.
+    let mmi = match mmi_ref.try_lock() {
+        Some(mmi) => mmi,
+        None => return false,
+    };
+    let mut page_addr = addr & !(PAGE_SIZE - 1);
+    while page_addr <= end {
+        let mapped = VirtualAddress::new(page_addr).ok()
+            .and_then(|vaddr| mmi.page_table.translate(vaddr))
+            .is_some();
+        if !mapped {
+            return false;
+        }
+        page_addr = match page_addr.checked_add(PAGE_SIZE) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    true
+}
+
+fn read_memory(addr: usize, len: usize) -> Option<Vec<u8>> {
+    if !is_mapped(addr, len) {
+        return None;
+    }
+    // SAFE: the memory range was checked above to be mapped.
+    Some((0..len).map(|i| unsafe { core::ptr::read_volatile((addr + i) as *const u8) }).collect())
+}
+
+/// Writes the given bytes to memory, even if it's mapped read-only (e.g., a `.text` section).
+fn write_memory(addr: usize, bytes: &[u8]) -> Result<(), &'static str> {
+    if !is_mapped(addr, bytes.len()) {
+        return Err("memory range is not mapped");
+    }
+    // SAFE: the memory range was checked above to be mapped, and interrupts are disabled
+    // in the exception handlers, so no other code on this core runs with write protection disabled.
+    unsafe {
+        let cr0: u64;
+        llvm_asm!("mov %cr0, $0" : "=r"(cr0) : : : "volatile");
+        llvm_asm!("mov $0, %cr0" : : "r"(cr0 & !CR0_WRITE_PROTECT) : "memory" : "volatile");
+        for (i, byte) in bytes.iter().enumerate() {
+            core::ptr::write_volatile((addr + i) as *mut u8, *byte);
+        }
+        llvm_asm!("mov $0, %cr0" : : "r"(cr0) : "memory" : "volatile");
+    }
+    Ok(())
+}
+
+
+/// Receives the next valid packet from GDB, acknowledging it, and returns its contents.
+fn recv_packet() -> Vec<u8> {
+    loop {
+        // Skip everything (e.g., acks and interrupt requests) until the start of a packet.
+        while GDB_UART.read_byte() != b'$' { }
+
+        let mut data = Vec::new();
+        let mut checksum: u8 = 0;
+        loop {
+            let byte = GDB_UART.read_byte();
+            if byte == b'#' {
+                break;
+            }
+            checksum = checksum.wrapping_add(byte);
+            data.push(byte);
+        }
+        let expected = [GDB_UART.read_byte(), GDB_UART.read_byte()];
+        let expected = core::str::from_utf8(&expected).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+
+        if expected == Some(checksum) {
+            GDB_UART.write_byte(b'+');
+            return data;
+        } else {
+            warn!("gdb_stub: packet checksum mismatch, requesting retransmission");
+            GDB_UART.write_byte(b'-');
+        }
+    }
+}
+
+/// Sends the given packet contents to GDB, retransmitting it until GDB acknowledges it.
+fn send_packet(data: &str) {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    let mut packet = String::with_capacity(data.len() + 4);
+    let _ = write!(packet, "${}#{:02x}", data, checksum);
+
+    for _attempt in 0..8 {
+        for b in packet.bytes() {
+            GDB_UART.write_byte(b);
+        }
+        // Wait for the acknowledgment, ignoring any other bytes.
+        loop {
+            match GDB_UART.read_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => continue,
+            }
+        }
+    }
+    warn!("gdb_stub: GDB did not acknowledge packet {:?}", data);
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i .. i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
@@ -0,0 +1,72 @@
+//! A minimal polling driver for a 16550 UART, used exclusively by the GDB stub
+//! such that debugger traffic never interleaves with the log output on COM1.
+
+use port_io::Port;
+
+/// The base I/O port of COM2.
+pub const COM2_BASE: u16 = 0x2F8;
+
+/// Line status register bit that indicates a received byte is available.
+const LSR_DATA_READY: u8 = 0x01;
+/// Line status register bit that indicates the transmit buffer is empty.
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+
+/// A serial port that is accessed by polling, without interrupts.
+pub struct Uart {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+impl Uart {
+    /// Creates a UART for the serial port at the given base I/O port. It must be initialized before use.
+    pub const fn new(base: u16) -> Uart {
+        Uart {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    /// Configures the UART for 115200 baud, 8 data bits, no parity, and one stop bit, with interrupts disabled.
+    pub fn init(&self) {
+        // SAFE: this UART is dedicated to the GDB stub, so no one else accesses these ports.
+        unsafe {
+            self.interrupt_enable.write(0x00);
+            self.line_control.write(0x80);     // enable DLAB to set the baud rate divisor
+            self.data.write(0x01);             // divisor low byte: 115200 baud
+            self.interrupt_enable.write(0x00); // divisor high byte
+            self.line_control.write(0x03);     // 8 bits, no parity, one stop bit, DLAB off
+            self.fifo_control.write(0xC7);     // enable and clear FIFOs, 14-byte threshold
+            self.modem_control.write(0x03);    // assert DTR and RTS
+        }
+    }
+
+    /// Blocks until a byte is received, then returns it.
+    pub fn read_byte(&self) -> u8 {
+        while self.line_status.read() & LSR_DATA_READY == 0 { }
+        self.data.read()
+    }
+
+    /// Returns a received byte if one is available, without blocking.
+    pub fn try_read_byte(&self) -> Option<u8> {
+        if self.line_status.read() & LSR_DATA_READY != 0 {
+            Some(self.data.read())
+        } else {
+            None
+        }
+    }
+
+    /// Blocks until the transmit buffer is empty, then sends the given byte.
+    pub fn write_byte(&self, byte: u8) {
+        while self.line_status.read() & LSR_TRANSMIT_EMPTY == 0 { }
+        // SAFE: see `init()`.
+        unsafe { self.data.write(byte); }
+    }
+}
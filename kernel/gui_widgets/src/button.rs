@@ -0,0 +1,127 @@
+//! A push button, which invokes a callback when it is clicked.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use font::CHARACTER_HEIGHT;
+use framebuffer::{Framebuffer, AlphaPixel};
+use keycodes_ascii::{KeyAction, Keycode};
+use shapes::{Coord, Rectangle};
+use super::{Widget, WidgetEvent, BUTTON_COLOR, BUTTON_PRESSED_COLOR, BORDER_COLOR, FOCUS_COLOR, TEXT_COLOR};
+use super::{fill, draw_border, draw_text, text_width};
+
+
+/// The number of pixels between the label of a button and its left and right edges.
+const PADDING_X: usize = 12;
+/// The number of pixels between the label of a button and its top and bottom edges.
+const PADDING_Y: usize = 5;
+
+
+/// A button with a text label, which invokes its callback when it is clicked with the mouse,
+/// or when Enter or Space is pressed while it has the keyboard focus.
+pub struct Button {
+    label: String,
+    on_click: Option<Box<dyn FnMut() + Send>>,
+    /// Whether the left mouse button was pressed over this button and not released yet.
+    pressed: bool,
+    focused: bool,
+    needs_redraw: bool,
+}
+
+impl Button {
+    /// Creates a new button with the given `label`, which does nothing when clicked.
+    pub fn new(label: &str) -> Button {
+        Button {
+            label: String::from(label),
+            on_click: None,
+            pressed: false,
+            focused: false,
+            needs_redraw: true,
+        }
+    }
+
+    /// Sets the callback that is invoked whenever this button is clicked.
+    pub fn on_click<F: FnMut() + Send + 'static>(mut self, callback: F) -> Button {
+        self.on_click = Some(Box::new(callback));
+        self
+    }
+
+    /// Returns the label of this button.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Changes the label of this button.
+    pub fn set_label(&mut self, label: &str) {
+        if self.label != label {
+            self.label = String::from(label);
+            self.needs_redraw = true;
+        }
+    }
+
+    fn click(&mut self) {
+        if let Some(ref mut callback) = self.on_click {
+            callback();
+        }
+    }
+}
+
+impl Widget for Button {
+    fn preferred_size(&self) -> (usize, usize) {
+        (text_width(&self.label) + 2 * PADDING_X, CHARACTER_HEIGHT + 2 * PADDING_Y)
+    }
+
+    fn draw(&mut self, framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle) {
+        let color = if self.pressed { BUTTON_PRESSED_COLOR } else { BUTTON_COLOR };
+        fill(framebuffer, area, color);
+        draw_border(framebuffer, area, if self.focused { FOCUS_COLOR } else { BORDER_COLOR });
+
+        // center the label within the button
+        let width = core::cmp::min(text_width(&self.label), area.width());
+        let x = (area.width() - width) / 2;
+        let y = area.height().saturating_sub(CHARACTER_HEIGHT) / 2;
+        let coordinate = area.top_left + Coord::new(x as isize, y as isize);
+        draw_text(framebuffer, coordinate, width, &self.label, TEXT_COLOR, color);
+        self.needs_redraw = false;
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    fn handle_event(&mut self, event: &WidgetEvent, _size: (usize, usize)) -> bool {
+        match *event {
+            WidgetEvent::MouseDown(_) => {
+                self.pressed = true;
+                self.needs_redraw = true;
+                true
+            }
+            WidgetEvent::MouseUp { inside, .. } => {
+                self.pressed = false;
+                self.needs_redraw = true;
+                if inside {
+                    self.click();
+                }
+                true
+            }
+            WidgetEvent::Key(key_event) => match key_event.keycode {
+                Keycode::Enter | Keycode::Space => {
+                    if key_event.action == KeyAction::Pressed {
+                        self.click();
+                    }
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn is_focusable(&self) -> bool {
+        true
+    }
+
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.needs_redraw = true;
+    }
+}
@@ -0,0 +1,80 @@
+//! A label, which shows one or more lines of text that the user can't edit.
+
+use alloc::string::String;
+use color::Color;
+use font::CHARACTER_HEIGHT;
+use framebuffer::{Framebuffer, AlphaPixel};
+use shapes::{Coord, Rectangle};
+use super::{Widget, BACKGROUND_COLOR, TEXT_COLOR, fill, draw_text, text_width};
+
+
+/// A widget that shows text, which may span several lines separated by `'\n'`.
+/// Text that doesn't fit within the label's area is cut off.
+pub struct Label {
+    text: String,
+    fg_color: Color,
+    bg_color: Color,
+    needs_redraw: bool,
+}
+
+impl Label {
+    /// Creates a new label showing the given `text` in the default colors.
+    pub fn new(text: &str) -> Label {
+        Label {
+            text: String::from(text),
+            fg_color: TEXT_COLOR,
+            bg_color: BACKGROUND_COLOR,
+            needs_redraw: true,
+        }
+    }
+
+    /// Sets the color of the text and the background behind it.
+    pub fn colors(mut self, fg_color: Color, bg_color: Color) -> Label {
+        self.fg_color = fg_color;
+        self.bg_color = bg_color;
+        self
+    }
+
+    /// Returns the text shown by this label.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Changes the text shown by this label.
+    ///
+    /// If the new text needs more space than the old one, the `Gui` showing this label should be laid out again.
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = String::from(text);
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Changes the color of the text.
+    pub fn set_color(&mut self, fg_color: Color) {
+        self.fg_color = fg_color;
+        self.needs_redraw = true;
+    }
+}
+
+impl Widget for Label {
+    fn preferred_size(&self) -> (usize, usize) {
+        let width = self.text.lines().map(text_width).max().unwrap_or(0);
+        let lines = core::cmp::max(self.text.lines().count(), 1);
+        (width, lines * CHARACTER_HEIGHT)
+    }
+
+    fn draw(&mut self, framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle) {
+        fill(framebuffer, area, self.bg_color);
+        let lines = area.height() / CHARACTER_HEIGHT;
+        for (line, text) in self.text.lines().take(lines).enumerate() {
+            let coordinate = area.top_left + Coord::new(0, (line * CHARACTER_HEIGHT) as isize);
+            draw_text(framebuffer, coordinate, area.width(), text, self.fg_color, self.bg_color);
+        }
+        self.needs_redraw = false;
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+}
@@ -0,0 +1,203 @@
+//! A simple layout system that arranges widgets in rows and columns.
+//!
+//! A [`Layout`](struct.Layout.html) places its items one after another, either from left to right (a row)
+//! or from top to bottom (a column), with some spacing between them and some padding around them.
+//! Each item gets its preferred size along the layout's direction and the full size of the layout across it.
+//! Stretched items share whatever space is left over, and layouts can be nested to build more complex arrangements.
+
+use alloc::vec::Vec;
+use shapes::{Coord, Rectangle};
+use super::WidgetRef;
+
+
+/// The default number of pixels between two items of a layout.
+pub const DEFAULT_SPACING: usize = 6;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Row,
+    Column,
+}
+
+enum Content {
+    Widget(WidgetRef),
+    Layout(Layout),
+}
+
+struct Item {
+    content: Content,
+    /// Whether this item is given a share of the space that is left over after all other items got their preferred size.
+    stretch: bool,
+}
+
+impl Item {
+    fn preferred_size(&self) -> (usize, usize) {
+        match self.content {
+            Content::Widget(ref widget) => widget.lock().preferred_size(),
+            Content::Layout(ref layout) => layout.preferred_size(),
+        }
+    }
+}
+
+
+/// Arranges widgets and nested layouts in a row or a column.
+///
+/// # Example
+/// ```ignore
+/// let layout = Layout::column()
+///     .padding(10)
+///     .with(title)
+///     .with_stretched(list)
+///     .with_layout(Layout::row().with(ok_button).with(cancel_button));
+/// ```
+pub struct Layout {
+    direction: Direction,
+    spacing: usize,
+    padding: usize,
+    items: Vec<Item>,
+}
+
+impl Layout {
+    /// Creates an empty layout that arranges its items from left to right.
+    pub fn row() -> Layout {
+        Layout::new(Direction::Row)
+    }
+
+    /// Creates an empty layout that arranges its items from top to bottom.
+    pub fn column() -> Layout {
+        Layout::new(Direction::Column)
+    }
+
+    fn new(direction: Direction) -> Layout {
+        Layout {
+            direction,
+            spacing: DEFAULT_SPACING,
+            padding: 0,
+            items: Vec::new(),
+        }
+    }
+
+    /// Sets the number of pixels between two items.
+    pub fn spacing(mut self, spacing: usize) -> Layout {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Sets the number of pixels between the edges of this layout and its items.
+    pub fn padding(mut self, padding: usize) -> Layout {
+        self.padding = padding;
+        self
+    }
+
+    /// Adds a widget, which gets its preferred size along this layout's direction.
+    pub fn with(self, widget: WidgetRef) -> Layout {
+        self.push(Content::Widget(widget), false)
+    }
+
+    /// Adds a widget that is stretched to fill the space left over by the other items.
+    pub fn with_stretched(self, widget: WidgetRef) -> Layout {
+        self.push(Content::Widget(widget), true)
+    }
+
+    /// Adds a nested layout, which gets its preferred size along this layout's direction.
+    pub fn with_layout(self, layout: Layout) -> Layout {
+        self.push(Content::Layout(layout), false)
+    }
+
+    /// Adds a nested layout that is stretched to fill the space left over by the other items.
+    pub fn with_layout_stretched(self, layout: Layout) -> Layout {
+        self.push(Content::Layout(layout), true)
+    }
+
+    fn push(mut self, content: Content, stretch: bool) -> Layout {
+        self.items.push(Item { content, stretch });
+        self
+    }
+
+    /// Returns the size that this layout needs to give all of its items their preferred size, as `(width, height)` in pixels.
+    pub fn preferred_size(&self) -> (usize, usize) {
+        let mut along = 0;
+        let mut across = 0;
+        for item in self.items.iter() {
+            let (main, cross) = self.split(item.preferred_size());
+            along += main;
+            across = core::cmp::max(across, cross);
+        }
+        along += self.spacing * self.items.len().saturating_sub(1);
+        let (width, height) = self.join(along, across);
+        (width + 2 * self.padding, height + 2 * self.padding)
+    }
+
+    /// Arranges the items of this layout within the given `area`,
+    /// appending each widget and the area it was given to `placements`.
+    pub(crate) fn arrange(&self, area: Rectangle, placements: &mut Vec<(Rectangle, WidgetRef)>) {
+        let padding = self.padding as isize;
+        let inner = Rectangle {
+            top_left: area.top_left + (padding, padding),
+            bottom_right: area.bottom_right - (padding, padding),
+        };
+        if inner.is_empty() || self.items.is_empty() {
+            return;
+        }
+        let (available, _) = self.split((inner.width(), inner.height()));
+
+        let sizes: Vec<usize> = self.items.iter().map(|item| self.split(item.preferred_size()).0).collect();
+        let fixed: usize = self.items.iter().zip(sizes.iter())
+            .filter(|(item, _)| !item.stretch)
+            .map(|(_, size)| *size)
+            .sum::<usize>()
+            + self.spacing * (self.items.len() - 1);
+        let stretched = self.items.iter().filter(|item| item.stretch).count();
+        let leftover = available.saturating_sub(fixed);
+
+        let mut position = 0;
+        let mut stretched_so_far = 0;
+        for (item, preferred) in self.items.iter().zip(sizes) {
+            let size = if item.stretch {
+                // The last stretched item also gets the pixels lost to rounding.
+                stretched_so_far += 1;
+                if stretched_so_far == stretched {
+                    leftover - leftover / stretched * (stretched - 1)
+                } else {
+                    leftover / stretched
+                }
+            } else {
+                preferred
+            };
+
+            let (start, end) = (position as isize, (position + size) as isize);
+            let item_area = match self.direction {
+                Direction::Row => Rectangle {
+                    top_left: Coord::new(inner.top_left.x + start, inner.top_left.y),
+                    bottom_right: Coord::new(inner.top_left.x + end, inner.bottom_right.y),
+                },
+                Direction::Column => Rectangle {
+                    top_left: Coord::new(inner.top_left.x, inner.top_left.y + start),
+                    bottom_right: Coord::new(inner.bottom_right.x, inner.top_left.y + end),
+                },
+            };
+            // Items that don't fit are cut off at the edge of the layout.
+            if let Some(item_area) = item_area.intersection(&inner) {
+                match item.content {
+                    Content::Widget(ref widget) => placements.push((item_area, widget.clone())),
+                    Content::Layout(ref layout) => layout.arrange(item_area, placements),
+                }
+            }
+            position += size + self.spacing;
+        }
+    }
+
+    /// Splits a `(width, height)` size into its parts along and across this layout's direction.
+    fn split(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        match self.direction {
+            Direction::Row => (width, height),
+            Direction::Column => (height, width),
+        }
+    }
+
+    /// The inverse of `split()`, which turns sizes along and across this layout's direction into `(width, height)`.
+    fn join(&self, along: usize, across: usize) -> (usize, usize) {
+        self.split((along, across))
+    }
+}
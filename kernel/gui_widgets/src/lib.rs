@@ -0,0 +1,422 @@
+//! A toolkit of widgets for graphical applications, layered on top of the `window` crate.
+//!
+//! Instead of drawing pixels by hand, an application builds its user interface out of widgets,
+//! i.e., [`Label`](label/struct.Label.html)s, [`Button`](button/struct.Button.html)s,
+//! [`TextInput`](text_input/struct.TextInput.html)s, and [`ScrollList`](list/struct.ScrollList.html)s,
+//! which are arranged in rows and columns by a [`Layout`](layout/struct.Layout.html).
+//! A [`Gui`](struct.Gui.html) then owns the application's window, lays out the widgets within it,
+//! routes the mouse and keyboard events from the window manager to the widgets they are meant for,
+//! and redraws only the widgets that changed.
+//!
+//! Widgets are shared as [`WidgetRef`](type.WidgetRef.html)s, such that the application can keep a reference
+//! to a widget in order to change it later, e.g., to update the text of a label when a button is clicked.
+//! Widgets report user interactions via callbacks, e.g., [`Button::on_click()`](button/struct.Button.html#method.on_click).
+//! A callback runs while the widget that invokes it is locked, so it must not lock that same widget.
+//!
+//! The keyboard focus can be moved between the focusable widgets by clicking on them, or by pressing Tab and Shift+Tab.
+
+#![no_std]
+
+extern crate alloc;
+extern crate spin;
+extern crate color;
+extern crate event_types;
+extern crate font;
+extern crate framebuffer;
+extern crate framebuffer_drawer;
+extern crate framebuffer_printer;
+extern crate keycodes_ascii;
+extern crate shapes;
+extern crate window;
+
+pub mod button;
+pub mod label;
+pub mod layout;
+pub mod list;
+pub mod text_input;
+
+pub use button::Button;
+pub use label::Label;
+pub use layout::Layout;
+pub use list::ScrollList;
+pub use text_input::TextInput;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use color::Color;
+use event_types::{Event, MousePositionEvent};
+use font::CHARACTER_WIDTH;
+use framebuffer::{Framebuffer, AlphaPixel};
+use keycodes_ascii::{KeyAction, KeyEvent, Keycode};
+use shapes::{Coord, Rectangle};
+use spin::Mutex;
+use window::Window;
+
+
+/// The color behind the widgets of a window.
+pub const BACKGROUND_COLOR: Color = Color::new(0x00D3D3D3);
+/// The color of text.
+pub const TEXT_COLOR: Color = Color::new(0x00000000);
+/// The color of buttons.
+pub const BUTTON_COLOR: Color = Color::new(0x00BBBBBB);
+/// The color of a button while it is pressed.
+pub const BUTTON_PRESSED_COLOR: Color = Color::new(0x00888888);
+/// The background color of text inputs and lists.
+pub const INPUT_BACKGROUND_COLOR: Color = Color::new(0x00FFFFFF);
+/// The color of the border around widgets.
+pub const BORDER_COLOR: Color = Color::new(0x00666666);
+/// The color of the border around the widget that has the keyboard focus.
+pub const FOCUS_COLOR: Color = Color::new(0x000078D7);
+/// The background color of a selected item.
+pub const SELECTION_COLOR: Color = Color::new(0x000078D7);
+/// The color of the text of a selected item.
+pub const SELECTION_TEXT_COLOR: Color = Color::new(0x00FFFFFF);
+
+
+/// A widget, i.e., an element of a graphical user interface that draws itself and may react to input.
+pub trait Widget {
+    /// Returns the size that this widget would like to have, as `(width, height)` in pixels.
+    ///
+    /// A layout may give the widget more or less space than that.
+    fn preferred_size(&self) -> (usize, usize);
+
+    /// Draws this widget into the given `area` of the `framebuffer`.
+    ///
+    /// The widget must cover its whole `area`, as the area isn't cleared before a widget is redrawn.
+    /// Afterwards, [`needs_redraw()`](#tymethod.needs_redraw) should return `false` until the widget changes again.
+    fn draw(&mut self, framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle);
+
+    /// Returns `true` if this widget changed since it was last drawn.
+    fn needs_redraw(&self) -> bool;
+
+    /// Handles the given `event`, where `size` is the size of the area that this widget was laid out in.
+    ///
+    /// Returns `true` if the widget handled the event, or `false` if it should be passed on to the application.
+    fn handle_event(&mut self, _event: &WidgetEvent, _size: (usize, usize)) -> bool {
+        false
+    }
+
+    /// Returns `true` if this widget can receive the keyboard focus.
+    fn is_focusable(&self) -> bool {
+        false
+    }
+
+    /// Tells this widget whether it has the keyboard focus, which it typically shows by a differently colored border.
+    fn set_focused(&mut self, _focused: bool) { }
+}
+
+/// A reference to a widget that is shared between a `Gui`, its layout, and the application.
+pub type WidgetRef = Arc<Mutex<dyn Widget + Send>>;
+
+/// An input event that a `Gui` passes to one of its widgets.
+///
+/// Positions are relative to the top-left corner of the widget.
+#[derive(Clone, Copy, Debug)]
+pub enum WidgetEvent {
+    /// The left mouse button was pressed at the given position over this widget.
+    MouseDown(Coord),
+    /// The left mouse button, which was pressed over this widget, was released.
+    /// `inside` tells whether the mouse was still over this widget, e.g., to only click a button in that case.
+    MouseUp {
+        position: Coord,
+        inside: bool,
+    },
+    /// The mouse wheel was scrolled up while the mouse was over this widget.
+    ScrollUp,
+    /// The mouse wheel was scrolled down while the mouse was over this widget.
+    ScrollDown,
+    /// A key was pressed or released while this widget had the keyboard focus.
+    Key(KeyEvent),
+}
+
+
+/// A graphical user interface, which owns a window and shows a layout of widgets in it.
+///
+/// An application typically calls [`handle_events()`](#method.handle_events) and [`update()`](#method.update)
+/// in its main loop.
+pub struct Gui {
+    window: Window,
+    root: Layout,
+    background: Color,
+    /// The widgets and the areas that they were laid out in, in the order of the layout, which is also the focus order.
+    placements: Vec<(Rectangle, WidgetRef)>,
+    /// The index of the widget in `placements` that has the keyboard focus.
+    focused: Option<usize>,
+    /// The index of the widget in `placements` that the left mouse button was pressed over,
+    /// which receives the matching `MouseUp` event.
+    pressed: Option<usize>,
+    /// Whether the left mouse button was held in the last mouse event.
+    left_button_hold: bool,
+    /// Whether the widgets must be laid out again and the whole window redrawn.
+    needs_layout: bool,
+}
+
+impl Gui {
+    /// Creates a new graphical user interface that shows the widgets arranged by the `root` layout
+    /// in the content area of the given `window`, on top of the `background` color.
+    ///
+    /// Nothing is drawn until the next [`update()`](#method.update).
+    pub fn new(window: Window, root: Layout, background: Color) -> Gui {
+        Gui {
+            window,
+            root,
+            background,
+            placements: Vec::new(),
+            focused: None,
+            pressed: None,
+            left_button_hold: false,
+            needs_layout: true,
+        }
+    }
+
+    /// Returns the window that this GUI is shown in.
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    /// Returns the window that this GUI is shown in, e.g., to change its opacity.
+    pub fn window_mut(&mut self) -> &mut Window {
+        &mut self.window
+    }
+
+    /// Replaces the layout of widgets shown in the window, which removes the keyboard focus.
+    pub fn set_layout(&mut self, root: Layout) {
+        self.set_focus(None);
+        self.root = root;
+        self.placements.clear();
+        self.pressed = None;
+        self.needs_layout = true;
+    }
+
+    /// Lays out the widgets and redraws the whole window the next time that [`update()`](#method.update) is invoked,
+    /// e.g., after a widget's preferred size changed.
+    pub fn relayout(&mut self) {
+        self.needs_layout = true;
+    }
+
+    /// Gives the keyboard focus to the given `widget`, if it is shown in this GUI and can receive the focus.
+    pub fn focus(&mut self, widget: &WidgetRef) {
+        if self.needs_layout {
+            self.layout();
+        }
+        let index = self.placements.iter().position(|(_, w)| Arc::ptr_eq(w, widget));
+        if let Some(index) = index {
+            if self.placements[index].1.lock().is_focusable() {
+                self.set_focus(Some(index));
+            }
+        }
+    }
+
+    /// Handles the events that the window manager sent to this GUI's window,
+    /// passing mouse events to the widget under the mouse and keyboard events to the focused widget.
+    ///
+    /// Returns the first event that no widget handled, such that the application can handle it,
+    /// e.g., an `ExitEvent` or a keyboard shortcut.
+    /// Returns `None` once all pending events were handled.
+    pub fn handle_events(&mut self) -> Result<Option<Event>, &'static str> {
+        while let Some(event) = self.window.handle_event()? {
+            match event {
+                Event::MousePositionEvent(ref mouse_event) => self.handle_mouse_event(mouse_event),
+                Event::KeyboardEvent(ref input_event) => {
+                    if !self.handle_key_event(input_event.key_event) {
+                        return Ok(Some(event));
+                    }
+                }
+                Event::WindowResizeEvent(_) => self.needs_layout = true,
+                other => return Ok(Some(other)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Redraws the widgets that changed and renders them to the screen.
+    ///
+    /// If the window was resized or the layout replaced, the widgets are laid out again and the whole window is redrawn.
+    pub fn update(&mut self) -> Result<(), &'static str> {
+        if self.needs_layout {
+            self.layout();
+            let content_area = self.window.area();
+            {
+                let mut framebuffer = self.window.framebuffer_mut();
+                fill(&mut framebuffer, content_area, self.background);
+                for (area, widget) in self.placements.iter() {
+                    widget.lock().draw(&mut framebuffer, *area);
+                }
+            }
+            return self.window.render(Some(content_area));
+        }
+
+        let mut updated_areas = Vec::new();
+        {
+            let mut framebuffer = self.window.framebuffer_mut();
+            for (area, widget) in self.placements.iter() {
+                let mut widget = widget.lock();
+                if widget.needs_redraw() {
+                    widget.draw(&mut framebuffer, *area);
+                    updated_areas.push(*area);
+                }
+            }
+        }
+        if updated_areas.is_empty() {
+            return Ok(());
+        }
+        self.window.render_areas(updated_areas)
+    }
+
+    /// Arranges the widgets within the window's content area, keeping the focus on the same widget.
+    fn layout(&mut self) {
+        let focused_widget = self.focused.map(|index| self.placements[index].1.clone());
+        self.placements.clear();
+        self.root.arrange(self.window.area(), &mut self.placements);
+        self.focused = focused_widget.and_then(|focused| self.placements.iter().position(|(_, w)| Arc::ptr_eq(w, &focused)));
+        self.pressed = None;
+        self.needs_layout = false;
+    }
+
+    /// Moves the keyboard focus to the widget at the given `index` in `placements`, or removes it if `None`.
+    fn set_focus(&mut self, index: Option<usize>) {
+        if self.focused == index {
+            return;
+        }
+        if let Some((_, widget)) = self.focused.and_then(|old| self.placements.get(old)) {
+            widget.lock().set_focused(false);
+        }
+        if let Some((_, widget)) = index.and_then(|new| self.placements.get(new)) {
+            widget.lock().set_focused(true);
+        }
+        self.focused = index;
+    }
+
+    /// Moves the keyboard focus to the next focusable widget, or to the previous one if `backwards` is true.
+    fn focus_next(&mut self, backwards: bool) {
+        let count = self.placements.len();
+        if count == 0 {
+            return;
+        }
+        let start = match self.focused {
+            Some(index) => index,
+            None if backwards => 0,
+            None => count - 1,
+        };
+        for step in 1 ..= count {
+            let index = if backwards {
+                (start + count - step % count) % count
+            } else {
+                (start + step) % count
+            };
+            if self.placements[index].1.lock().is_focusable() {
+                self.set_focus(Some(index));
+                return;
+            }
+        }
+    }
+
+    /// Passes a mouse event, whose coordinate is relative to the window, to the widget under the mouse.
+    fn handle_mouse_event(&mut self, mouse_event: &MousePositionEvent) {
+        let position = mouse_event.coordinate;
+        let hovered = self.placements.iter().position(|(area, _)| contains(area, position));
+
+        if mouse_event.left_button_hold && !self.left_button_hold {
+            // The button was just pressed, which also moves the focus to the widget under the mouse.
+            if let Some(index) = hovered {
+                let focusable = self.placements[index].1.lock().is_focusable();
+                if focusable {
+                    self.set_focus(Some(index));
+                }
+                self.send_event(index, WidgetEvent::MouseDown(position - self.placements[index].0.top_left));
+            }
+            self.pressed = hovered;
+        } else if !mouse_event.left_button_hold && self.left_button_hold {
+            if let Some(index) = self.pressed.take() {
+                let area = self.placements[index].0;
+                self.send_event(index, WidgetEvent::MouseUp {
+                    position: position - area.top_left,
+                    inside: contains(&area, position),
+                });
+            }
+        }
+        self.left_button_hold = mouse_event.left_button_hold;
+
+        if let Some(index) = hovered {
+            if mouse_event.scrolling_up {
+                self.send_event(index, WidgetEvent::ScrollUp);
+            } else if mouse_event.scrolling_down {
+                self.send_event(index, WidgetEvent::ScrollDown);
+            }
+        }
+    }
+
+    /// Passes a key event to the focused widget, except for Tab, which moves the focus.
+    ///
+    /// Returns `false` if no widget handled the event.
+    fn handle_key_event(&mut self, key_event: KeyEvent) -> bool {
+        if key_event.keycode == Keycode::Tab && !key_event.modifiers.is_control() && !key_event.modifiers.is_alt() {
+            if key_event.action == KeyAction::Pressed {
+                self.focus_next(key_event.modifiers.is_shift());
+            }
+            return true;
+        }
+        match self.focused {
+            Some(index) => self.send_event(index, WidgetEvent::Key(key_event)),
+            None => false,
+        }
+    }
+
+    /// Passes the given `event` to the widget at `index` in `placements`.
+    fn send_event(&self, index: usize, event: WidgetEvent) -> bool {
+        let (area, widget) = &self.placements[index];
+        let size = (area.width(), area.height());
+        widget.lock().handle_event(&event, size)
+    }
+}
+
+
+/// Returns `true` if the given `coordinate` lies within the `area`.
+fn contains(area: &Rectangle, coordinate: Coord) -> bool {
+    coordinate.x >= area.top_left.x && coordinate.x < area.bottom_right.x
+        && coordinate.y >= area.top_left.y && coordinate.y < area.bottom_right.y
+}
+
+/// Fills the given `area` of the `framebuffer` with `color`.
+fn fill(framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle, color: Color) {
+    if area.is_empty() {
+        return;
+    }
+    framebuffer_drawer::fill_rectangle(framebuffer, area.top_left, area.width(), area.height(), color.into());
+}
+
+/// Draws a one-pixel border along the edges of the given `area` of the `framebuffer`.
+fn draw_border(framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle, color: Color) {
+    if area.is_empty() {
+        return;
+    }
+    framebuffer_drawer::draw_rectangle(framebuffer, area.top_left, area.width(), area.height(), color.into());
+}
+
+/// Draws a single line of `text` with its top-left corner at `coordinate`,
+/// cutting off the characters that don't fit within `max_width` pixels.
+fn draw_text(
+    framebuffer: &mut Framebuffer<AlphaPixel>,
+    coordinate: Coord,
+    max_width: usize,
+    text: &str,
+    fg_color: Color,
+    bg_color: Color,
+) {
+    for (column, byte) in text.bytes().take(max_width / CHARACTER_WIDTH).enumerate() {
+        framebuffer_printer::print_ascii_character(
+            framebuffer,
+            byte,
+            fg_color.into(),
+            bg_color.into(),
+            coordinate,
+            column,
+            0,
+        );
+    }
+}
+
+/// Returns the width in pixels of the given single line of `text`.
+fn text_width(text: &str) -> usize {
+    text.len() * CHARACTER_WIDTH
+}
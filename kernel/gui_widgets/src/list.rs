@@ -0,0 +1,246 @@
+//! A scrollable list of items, of which the user can select one.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use font::{CHARACTER_HEIGHT, CHARACTER_WIDTH};
+use framebuffer::{Framebuffer, AlphaPixel};
+use keycodes_ascii::{KeyAction, Keycode};
+use shapes::{Coord, Rectangle};
+use super::{Widget, WidgetEvent, INPUT_BACKGROUND_COLOR, BORDER_COLOR, FOCUS_COLOR, TEXT_COLOR};
+use super::{SELECTION_COLOR, SELECTION_TEXT_COLOR, fill, draw_border, draw_text};
+
+
+/// The number of pixels between the items and the edges of the list.
+const INSET: usize = 2;
+/// The width of the scroll bar in pixels.
+const SCROLL_BAR_WIDTH: usize = 6;
+/// The number of items that one step of the mouse wheel scrolls by.
+const SCROLL_STEP: usize = 3;
+
+/// A callback that receives the index and text of the selected item.
+type SelectCallback = Box<dyn FnMut(usize, &str) + Send>;
+
+
+/// A list that shows one item per line, scrolling through them if they don't all fit.
+///
+/// The user selects an item by clicking it, or by moving the selection with the Up, Down, Page Up, Page Down, Home, and End keys
+/// while the list has the keyboard focus. The mouse wheel scrolls the list without changing the selection.
+pub struct ScrollList {
+    items: Vec<String>,
+    selected: Option<usize>,
+    /// The index of the item shown on the first line.
+    first_visible: usize,
+    /// The number of items that the list would like to show at once.
+    rows: usize,
+    /// The number of characters per item that the list would like to show.
+    columns: usize,
+    /// The number of items that fit into the list's area, as of the last time it was drawn.
+    visible_rows: usize,
+    on_select: Option<SelectCallback>,
+    focused: bool,
+    needs_redraw: bool,
+}
+
+impl ScrollList {
+    /// Creates a new list of the given `items`, which would like to show `rows` items
+    /// that are `columns` characters wide at once.
+    pub fn new(items: Vec<String>, rows: usize, columns: usize) -> ScrollList {
+        ScrollList {
+            items,
+            selected: None,
+            first_visible: 0,
+            rows,
+            columns,
+            visible_rows: rows,
+            on_select: None,
+            focused: false,
+            needs_redraw: true,
+        }
+    }
+
+    /// Sets the callback that is invoked with the index and text of an item whenever the user selects it.
+    pub fn on_select<F: FnMut(usize, &str) + Send + 'static>(mut self, callback: F) -> ScrollList {
+        self.on_select = Some(Box::new(callback));
+        self
+    }
+
+    /// Returns the items in this list.
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    /// Replaces the items in this list, which clears the selection.
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.items = items;
+        self.selected = None;
+        self.first_visible = 0;
+        self.needs_redraw = true;
+    }
+
+    /// Returns the index of the selected item, if any.
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Returns the text of the selected item, if any.
+    pub fn selected_item(&self) -> Option<&str> {
+        self.selected.and_then(|index| self.items.get(index)).map(|item| item.as_str())
+    }
+
+    /// Selects the item at the given `index`, or clears the selection if `None`,
+    /// and scrolls the list to show the selected item.
+    /// This doesn't invoke the `on_select` callback.
+    pub fn select(&mut self, index: Option<usize>) {
+        self.selected = index.filter(|&index| index < self.items.len());
+        if let Some(index) = self.selected {
+            self.scroll_to(index);
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Selects the item at `index` on behalf of the user, invoking the `on_select` callback if the selection changed.
+    fn select_by_user(&mut self, index: usize) {
+        if self.items.is_empty() {
+            return;
+        }
+        let index = core::cmp::min(index, self.items.len() - 1);
+        let changed = self.selected != Some(index);
+        self.select(Some(index));
+        if changed {
+            if let Some(ref mut callback) = self.on_select {
+                callback(index, &self.items[index]);
+            }
+        }
+    }
+
+    /// Scrolls the list such that the item at `index` is visible.
+    fn scroll_to(&mut self, index: usize) {
+        let rows = core::cmp::max(self.visible_rows, 1);
+        if index < self.first_visible {
+            self.first_visible = index;
+        } else if index >= self.first_visible + rows {
+            self.first_visible = index + 1 - rows;
+        }
+    }
+
+    /// Scrolls the list by the given number of items, up if `delta` is negative.
+    fn scroll_by(&mut self, delta: isize) {
+        let max_first = self.items.len().saturating_sub(self.visible_rows);
+        let first = self.first_visible as isize + delta;
+        self.first_visible = core::cmp::min(core::cmp::max(first, 0) as usize, max_first);
+        self.needs_redraw = true;
+    }
+
+    /// Returns the number of items that fit into a list of the given `height` in pixels.
+    fn rows_in(height: usize) -> usize {
+        height.saturating_sub(2 * INSET) / CHARACTER_HEIGHT
+    }
+}
+
+impl Widget for ScrollList {
+    fn preferred_size(&self) -> (usize, usize) {
+        (
+            self.columns * CHARACTER_WIDTH + 2 * INSET + SCROLL_BAR_WIDTH,
+            self.rows * CHARACTER_HEIGHT + 2 * INSET,
+        )
+    }
+
+    fn draw(&mut self, framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle) {
+        self.visible_rows = Self::rows_in(area.height());
+        self.first_visible = core::cmp::min(self.first_visible, self.items.len().saturating_sub(self.visible_rows));
+
+        fill(framebuffer, area, INPUT_BACKGROUND_COLOR);
+        draw_border(framebuffer, area, if self.focused { FOCUS_COLOR } else { BORDER_COLOR });
+
+        let text_width = area.width().saturating_sub(2 * INSET + SCROLL_BAR_WIDTH);
+        let visible_items = self.items.iter().enumerate().skip(self.first_visible).take(self.visible_rows);
+        for (row, (index, item)) in visible_items.enumerate() {
+            let coordinate = area.top_left + Coord::new(INSET as isize, (INSET + row * CHARACTER_HEIGHT) as isize);
+            let (fg_color, bg_color) = if self.selected == Some(index) {
+                let highlight = Rectangle {
+                    top_left: coordinate,
+                    bottom_right: coordinate + (text_width as isize, CHARACTER_HEIGHT as isize),
+                };
+                fill(framebuffer, highlight, SELECTION_COLOR);
+                (SELECTION_TEXT_COLOR, SELECTION_COLOR)
+            } else {
+                (TEXT_COLOR, INPUT_BACKGROUND_COLOR)
+            };
+            draw_text(framebuffer, coordinate, text_width, item, fg_color, bg_color);
+        }
+
+        // The scroll bar's thumb shows which part of the list is visible, if it doesn't all fit.
+        if self.items.len() > self.visible_rows {
+            let track_height = area.height().saturating_sub(2 * INSET);
+            let thumb_top = track_height * self.first_visible / self.items.len();
+            let thumb_height = core::cmp::max(track_height * self.visible_rows / self.items.len(), 1);
+            let x = area.bottom_right.x - (INSET + SCROLL_BAR_WIDTH) as isize;
+            let thumb = Rectangle {
+                top_left: Coord::new(x, area.top_left.y + (INSET + thumb_top) as isize),
+                bottom_right: Coord::new(x + SCROLL_BAR_WIDTH as isize, area.top_left.y + (INSET + thumb_top + thumb_height) as isize),
+            };
+            fill(framebuffer, thumb, BORDER_COLOR);
+        }
+        self.needs_redraw = false;
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    fn handle_event(&mut self, event: &WidgetEvent, size: (usize, usize)) -> bool {
+        self.visible_rows = Self::rows_in(size.1);
+        let page = core::cmp::max(self.visible_rows, 1);
+        match *event {
+            WidgetEvent::MouseDown(position) => {
+                let row = (position.y - INSET as isize) / CHARACTER_HEIGHT as isize;
+                if row >= 0 && (row as usize) < self.visible_rows {
+                    let index = self.first_visible + row as usize;
+                    if index < self.items.len() {
+                        self.select_by_user(index);
+                    }
+                }
+                true
+            }
+            WidgetEvent::MouseUp { .. } => true,
+            WidgetEvent::ScrollUp => {
+                self.scroll_by(-(SCROLL_STEP as isize));
+                true
+            }
+            WidgetEvent::ScrollDown => {
+                self.scroll_by(SCROLL_STEP as isize);
+                true
+            }
+            WidgetEvent::Key(key_event) => {
+                let last = match self.items.len() {
+                    0 => return false,
+                    len => len - 1,
+                };
+                let target = match (key_event.keycode, self.selected) {
+                    (Keycode::Up, Some(index)) => index.saturating_sub(1),
+                    (Keycode::Down, Some(index)) => index + 1,
+                    (Keycode::PageUp, Some(index)) => index.saturating_sub(page),
+                    (Keycode::PageDown, Some(index)) => index + page,
+                    (Keycode::Up, None) | (Keycode::PageUp, None) | (Keycode::Home, _) => 0,
+                    (Keycode::Down, None) | (Keycode::PageDown, None) => self.first_visible,
+                    (Keycode::End, _) => last,
+                    _ => return false,
+                };
+                if key_event.action == KeyAction::Pressed {
+                    self.select_by_user(target);
+                }
+                true
+            }
+        }
+    }
+
+    fn is_focusable(&self) -> bool {
+        true
+    }
+
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.needs_redraw = true;
+    }
+}
@@ -0,0 +1,196 @@
+//! A single-line text input box.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use font::{CHARACTER_HEIGHT, CHARACTER_WIDTH};
+use framebuffer::{Framebuffer, AlphaPixel};
+use keycodes_ascii::{KeyAction, Keycode};
+use shapes::Rectangle;
+use super::{Widget, WidgetEvent, INPUT_BACKGROUND_COLOR, BORDER_COLOR, FOCUS_COLOR, TEXT_COLOR};
+use super::{fill, draw_border, draw_text};
+
+
+/// The number of pixels between the text and the edges of the input box.
+const INSET: usize = 4;
+
+/// A callback that receives the text of an input box.
+type TextCallback = Box<dyn FnMut(&str) + Send>;
+
+
+/// A box in which the user can enter and edit a single line of text while it has the keyboard focus.
+///
+/// The cursor is moved with the Left, Right, Home, and End keys, or by clicking into the text.
+/// Pressing Enter submits the text.
+pub struct TextInput {
+    text: String,
+    /// The index of the character in front of which the cursor is.
+    cursor: usize,
+    /// The index of the first character that is visible, as long texts are scrolled to keep the cursor visible.
+    first_visible: usize,
+    /// The number of characters that the input box would like to show.
+    columns: usize,
+    on_change: Option<TextCallback>,
+    on_submit: Option<TextCallback>,
+    focused: bool,
+    needs_redraw: bool,
+}
+
+impl TextInput {
+    /// Creates a new, empty input box that is wide enough to show the given number of `columns` of text.
+    pub fn new(columns: usize) -> TextInput {
+        TextInput {
+            text: String::new(),
+            cursor: 0,
+            first_visible: 0,
+            columns,
+            on_change: None,
+            on_submit: None,
+            focused: false,
+            needs_redraw: true,
+        }
+    }
+
+    /// Sets the callback that is invoked with the new text whenever the user changes the text.
+    pub fn on_change<F: FnMut(&str) + Send + 'static>(mut self, callback: F) -> TextInput {
+        self.on_change = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the callback that is invoked with the text when the user presses Enter.
+    pub fn on_submit<F: FnMut(&str) + Send + 'static>(mut self, callback: F) -> TextInput {
+        self.on_submit = Some(Box::new(callback));
+        self
+    }
+
+    /// Returns the text in this input box.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text in this input box and moves the cursor to its end, without invoking the `on_change` callback.
+    /// Non-ASCII characters are dropped, as they can't be displayed.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.chars().filter(|c| c.is_ascii() && !c.is_ascii_control()).collect();
+        self.cursor = self.text.len();
+        self.needs_redraw = true;
+    }
+
+    fn changed(&mut self) {
+        self.needs_redraw = true;
+        if let Some(ref mut callback) = self.on_change {
+            callback(&self.text);
+        }
+    }
+
+    /// Returns the number of characters that fit into an input box of the given `width` in pixels.
+    fn visible_columns(width: usize) -> usize {
+        width.saturating_sub(2 * INSET) / CHARACTER_WIDTH
+    }
+}
+
+impl Widget for TextInput {
+    fn preferred_size(&self) -> (usize, usize) {
+        // one more column for the cursor at the end of the text
+        ((self.columns + 1) * CHARACTER_WIDTH + 2 * INSET, CHARACTER_HEIGHT + 2 * INSET)
+    }
+
+    fn draw(&mut self, framebuffer: &mut Framebuffer<AlphaPixel>, area: Rectangle) {
+        fill(framebuffer, area, INPUT_BACKGROUND_COLOR);
+        draw_border(framebuffer, area, if self.focused { FOCUS_COLOR } else { BORDER_COLOR });
+
+        // scroll the text such that the cursor is visible
+        let columns = core::cmp::max(Self::visible_columns(area.width()), 1);
+        if self.cursor < self.first_visible {
+            self.first_visible = self.cursor;
+        } else if self.cursor >= self.first_visible + columns {
+            self.first_visible = self.cursor + 1 - columns;
+        }
+
+        let text_origin = area.top_left + (INSET as isize, INSET as isize);
+        let visible = &self.text[core::cmp::min(self.first_visible, self.text.len()) ..];
+        draw_text(framebuffer, text_origin, area.width().saturating_sub(2 * INSET), visible, TEXT_COLOR, INPUT_BACKGROUND_COLOR);
+
+        if self.focused {
+            let x = ((self.cursor - self.first_visible) * CHARACTER_WIDTH) as isize;
+            let cursor_area = Rectangle {
+                top_left: text_origin + (x, 0),
+                bottom_right: text_origin + (x + 1, CHARACTER_HEIGHT as isize),
+            };
+            if let Some(cursor_area) = cursor_area.intersection(&area) {
+                fill(framebuffer, cursor_area, TEXT_COLOR);
+            }
+        }
+        self.needs_redraw = false;
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    fn handle_event(&mut self, event: &WidgetEvent, _size: (usize, usize)) -> bool {
+        match *event {
+            WidgetEvent::MouseDown(position) => {
+                let column = core::cmp::max(position.x - INSET as isize, 0) as usize / CHARACTER_WIDTH;
+                self.cursor = core::cmp::min(self.first_visible + column, self.text.len());
+                self.needs_redraw = true;
+                true
+            }
+            WidgetEvent::MouseUp { .. } => true,
+            WidgetEvent::Key(key_event) => {
+                if key_event.action != KeyAction::Pressed {
+                    // releasing a key has no effect, but shouldn't be passed on either
+                    return true;
+                }
+                match key_event.keycode {
+                    Keycode::Left => self.cursor = self.cursor.saturating_sub(1),
+                    Keycode::Right => self.cursor = core::cmp::min(self.cursor + 1, self.text.len()),
+                    Keycode::Home => self.cursor = 0,
+                    Keycode::End => self.cursor = self.text.len(),
+                    Keycode::Backspace => {
+                        if self.cursor > 0 {
+                            self.cursor -= 1;
+                            self.text.remove(self.cursor);
+                            self.changed();
+                        }
+                    }
+                    Keycode::Delete => {
+                        if self.cursor < self.text.len() {
+                            self.text.remove(self.cursor);
+                            self.changed();
+                        }
+                    }
+                    Keycode::Enter => {
+                        if let Some(ref mut callback) = self.on_submit {
+                            callback(&self.text);
+                        }
+                    }
+                    keycode => {
+                        if key_event.modifiers.is_control() || key_event.modifiers.is_alt() {
+                            return false;
+                        }
+                        match keycode.to_ascii(key_event.modifiers) {
+                            Some(c) if c.is_ascii() && !c.is_ascii_control() => {
+                                self.text.insert(self.cursor, c);
+                                self.cursor += 1;
+                                self.changed();
+                            }
+                            _ => return false,
+                        }
+                    }
+                }
+                self.needs_redraw = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_focusable(&self) -> bool {
+        true
+    }
+
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.needs_redraw = true;
+    }
+}
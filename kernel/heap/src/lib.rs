@@ -19,6 +19,7 @@ use kernel_config::memory::{KERNEL_HEAP_START, KERNEL_HEAP_INITIAL_SIZE};
 use irq_safety::MutexIrqSafe;
 use spin::Once;
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use block_allocator::FixedSizeBlockAllocator;
 
 
@@ -55,6 +56,52 @@ pub fn set_allocator(allocator: Box<dyn GlobalAlloc + Send + Sync>) {
 }
 
 
+/// Statistics about one size class of a slab-based heap, 
+/// i.e., the slab allocator that serves all allocations up to `object_size` bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeClassStats {
+    /// The largest allocation size (in bytes) served by this size class.
+    pub object_size: usize,
+    /// The number of objects currently allocated from this size class.
+    pub live_objects: usize,
+    /// The total number of allocations served by this size class so far.
+    pub total_allocations: usize,
+    /// The number of pages in this size class that have no allocated objects.
+    pub empty_pages: usize,
+    /// The number of pages in this size class that have some allocated objects.
+    pub partial_pages: usize,
+    /// The number of pages in this size class that are completely full.
+    pub full_pages: usize,
+}
+
+/// Statistics about one heap, e.g., one of the per-core heaps.
+#[derive(Clone, Debug)]
+pub struct HeapStats {
+    /// The ID of the heap, which is currently the APIC ID of the core it belongs to.
+    pub heap_id: usize,
+    /// The statistics of each size class in this heap, from smallest to largest.
+    pub size_classes: Vec<SizeClassStats>,
+}
+
+/// The function that gathers statistics about the heaps in the default allocator.
+static HEAP_STATS_FUNC: Once<fn() -> Vec<HeapStats>> = Once::new();
+
+/// Sets the function that is invoked by [`stats()`](fn.stats.html) 
+/// to gather statistics about the heaps of the default allocator.
+/// This should be called by the allocator that is given to [`set_allocator()`](fn.set_allocator.html).
+pub fn set_stats_callback(func: fn() -> Vec<HeapStats>) {
+    HEAP_STATS_FUNC.call_once(|| func);
+}
+
+/// Returns statistics about each heap in the default allocator.
+/// 
+/// Returns an empty `Vec` if the default allocator has not been set up yet
+/// or if it doesn't support gathering statistics.
+pub fn stats() -> Vec<HeapStats> {
+    HEAP_STATS_FUNC.try().map(|func| func()).unwrap_or_default()
+}
+
+
 /// The heap which is used as a global allocator for the system.
 /// It starts off with one basic fixed size allocator, the `initial allocator`. 
 /// When a more complex heap is created and set as the `DEFAULT_ALLOCATOR`, then it is used.
@@ -0,0 +1,176 @@
+//! A blocking HTTP client.
+//!
+//! The simplest requests are sent with [`get()`](fn.get.html) and [`post()`](fn.post.html),
+//! which use the default [`Client`](struct.Client.html) options.
+//! Every request opens a new connection, which is closed once the response has been received.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use net_stack::tcp::{self, TcpHandle, TcpOptions};
+use message::{self, BodyLength, Connection};
+use {Headers, Method, Request, Response, Url};
+
+
+/// The number of redirects that are followed by default.
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+/// How long a request may go without receiving anything from the server by default, in milliseconds.
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+/// The `User-Agent` header that is sent by default.
+pub const DEFAULT_USER_AGENT: &'static str = "Theseus-http/0.1";
+
+
+/// Sends a `GET` request to the given URL with the default options, and returns the response.
+pub fn get(url: &str) -> Result<Response, &'static str> {
+    Client::default().get(url)
+}
+
+/// Sends a `POST` request with the given body of the given content type to the given URL with the default options,
+/// and returns the response.
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> Result<Response, &'static str> {
+    Client::default().post(url, content_type, body)
+}
+
+
+/// The options of the requests that a client sends.
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// The number of redirects that are followed before giving up; `0` returns redirect responses as they are.
+    pub max_redirects: usize,
+    /// How long connecting and each wait for data from the server may take, in milliseconds.
+    pub timeout_ms: u64,
+    pub user_agent: String,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client {
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+}
+
+impl Client {
+    /// Sends a `GET` request to the given URL, and returns the response.
+    pub fn get(&self, url: &str) -> Result<Response, &'static str> {
+        self.request(Method::Get, url, &Headers::new(), &[])
+    }
+
+    /// Sends a `POST` request with the given body of the given content type to the given URL, and returns the response.
+    pub fn post(&self, url: &str, content_type: &str, body: &[u8]) -> Result<Response, &'static str> {
+        let mut headers = Headers::new();
+        headers.set("Content-Type", content_type);
+        self.request(Method::Post, url, &headers, body)
+    }
+
+    /// Sends a request with the given method, headers, and body to the given URL, and returns the response,
+    /// blocking the current task until the whole response has been received.
+    ///
+    /// Redirects are followed up to `max_redirects` times. Like browsers do, a `303 See Other` redirect,
+    /// as well as a `301` or `302` redirect of a `POST` request, is followed with a `GET` request without a body.
+    pub fn request(&self, method: Method, url: &str, headers: &Headers, body: &[u8]) -> Result<Response, &'static str> {
+        let mut url = Url::parse(url)?;
+        let mut request = Request::new(method, &url.target);
+        request.headers = headers.clone();
+        request.body = body.to_vec();
+
+        let mut redirects = 0;
+        loop {
+            let response = self.send(&url, &request)?;
+            if !response.is_redirect() || self.max_redirects == 0 {
+                return Ok(response);
+            }
+            let location = match response.headers.get("Location") {
+                Some(location) => location,
+                None => return Ok(response),
+            };
+            if redirects == self.max_redirects {
+                return Err("http: too many redirects");
+            }
+            redirects += 1;
+            url = url.join(location)?;
+            debug!("http: following redirect to {}", url);
+            request.target = url.target.clone();
+            let use_get = response.status_code == 303
+                || (request.method == Method::Post && (response.status_code == 301 || response.status_code == 302));
+            if use_get && request.method != Method::Head {
+                request.method = Method::Get;
+                request.body.clear();
+                request.headers.remove("Content-Type");
+            }
+        }
+    }
+
+    /// Sends the given request to the server of the given URL over a new connection, and receives its response.
+    fn send(&self, url: &Url, request: &Request) -> Result<Response, &'static str> {
+        let socket = self.connect(url)?;
+        let mut connection = Connection::new(socket);
+        let result = self.exchange(&mut connection, url, request);
+        match result {
+            Ok(_) => socket.close(),
+            Err(_) => socket.abort(),
+        }
+        result
+    }
+
+    /// Connects to the first address of the URL's host that accepts the connection.
+    fn connect(&self, url: &Url) -> Result<TcpHandle, &'static str> {
+        let endpoints = dns_resolver::resolve_endpoints(&url.host_and_port())?;
+        let mut result = Err("http: the host has no addresses");
+        for endpoint in endpoints {
+            let options = TcpOptions {
+                timeout_ms: Some(self.timeout_ms),
+                connect_timeout_ms: self.timeout_ms,
+                ..Default::default()
+            };
+            result = tcp::connect(endpoint, options);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn exchange(&self, connection: &mut Connection, url: &Url, request: &Request) -> Result<Response, &'static str> {
+        let mut headers = Headers::new();
+        headers.set("Host", &url.authority());
+        headers.set("User-Agent", &self.user_agent);
+        headers.set("Accept", "*/*");
+        for (name, value) in request.headers.iter() {
+            headers.set(name, value);
+        }
+        headers.set("Connection", "close");
+        headers.remove("Transfer-Encoding");
+        if !request.body.is_empty() || request.method == Method::Post || request.method == Method::Put {
+            headers.set("Content-Length", &request.body.len().to_string());
+        }
+        let start_line = format!("{} {} HTTP/1.1", request.method, request.target);
+        let mut data = message::encode_head(&start_line, &headers);
+        data.extend_from_slice(&request.body);
+        connection.write_all(&data)?;
+
+        loop {
+            let head = connection.read_head()?.ok_or("http: the server closed the connection without responding")?;
+            let (status_code, reason, headers) = message::parse_response_head(&head)?;
+            // Interim responses, e.g., `100 Continue`, precede the actual response.
+            if status_code >= 100 && status_code < 200 {
+                continue;
+            }
+            let length = if request.method == Method::Head || status_code == 204 || status_code == 304 {
+                BodyLength::Empty
+            } else {
+                BodyLength::from_headers(&headers, BodyLength::UntilClose)?
+            };
+            let body = connection.read_body(length)?;
+            return Ok(Response { status_code, reason, headers, body });
+        }
+    }
+}
+
+/// Returns the body of a response, or an error if its status code doesn't indicate success.
+///
+/// This is a convenience for the common case of downloading a resource with [`get()`](fn.get.html).
+pub fn fetch(url: &str) -> Result<Vec<u8>, &'static str> {
+    get(url)?.error_for_status().map(|response| response.body)
+}
@@ -0,0 +1,296 @@
+#![no_std]
+
+//! A minimal HTTP/1.1 client and server that run on top of the `net_stack` TCP sockets.
+//!
+//! The client in the [`client`](client/index.html) module sends a request, blocking the current task until
+//! the whole response has been received. Host names are resolved with the `dns_resolver`, response bodies
+//! may be delimited by their `Content-Length`, by chunked transfer encoding, or by the server closing the
+//! connection, and redirects are followed. Only `http://` URLs are supported, as there is no TLS.
+//!
+//! The server in the [`server`](server/index.html) module accepts connections on a port and dispatches
+//! each request to the handler that a [`Router`](server/struct.Router.html) has for its path,
+//! which may also serve the files of a directory of the VFS.
+//!
+//! Each connection carries a single request, as both sides send `Connection: close`.
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate httparse;
+extern crate smoltcp;
+extern crate net_stack;
+extern crate dns_resolver;
+extern crate fs_node;
+extern crate path;
+extern crate access_control;
+extern crate percent_encoding;
+
+mod url;
+mod message;
+pub mod client;
+pub mod server;
+
+pub use url::Url;
+pub use client::{get, post, Client};
+pub use server::{Router, Server};
+
+use core::fmt;
+use core::str;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+
+/// The largest request or response head (start line and headers) that is accepted, in bytes.
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// The largest number of headers that a request or response may have.
+pub const MAX_HEADERS: usize = 64;
+/// The largest request or response body that is accepted, in bytes.
+pub const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+
+/// The method of a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+}
+
+impl Method {
+    /// Returns the method with the given name, which is case-sensitive like in HTTP.
+    pub fn from_name(name: &str) -> Option<Method> {
+        match name {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "OPTIONS" => Some(Method::Options),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+
+/// The headers of a request or response, in the order that they were added.
+///
+/// Header names are compared case-insensitively.
+#[derive(Clone, Debug, Default)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers(Vec::new())
+    }
+
+    /// Returns the value of the first header with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns whether there is a header with the given name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a header, keeping any other headers with the same name.
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_string(), value.to_string()));
+    }
+
+    /// Sets the value of the header with the given name, replacing any headers with that name.
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.add(name, value);
+    }
+
+    /// Removes all headers with the given name.
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+    }
+
+    /// Returns an iterator over the names and values of the headers.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+
+/// A request, either one that the client sends or one that the server received.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    /// The target of the request, i.e., the path of the URL including its query, e.g., `/index.html?lang=en`.
+    pub target: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Creates a request with no headers and an empty body.
+    pub fn new(method: Method, target: &str) -> Request {
+        Request {
+            method,
+            target: target.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Returns the path of the target, without its query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+
+    /// Returns the query of the target, i.e., the part after the `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.target.find('?').map(|index| &self.target[index + 1 ..])
+    }
+}
+
+
+/// A response, either one that the client received or one that a server handler returns.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status_code: u16,
+    pub reason: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates a response with the given status code and its standard reason phrase, no headers, and an empty body.
+    pub fn new(status_code: u16) -> Response {
+        Response {
+            status_code,
+            reason: reason_phrase(status_code).to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Creates a response with the given status code and a body of the given content type.
+    pub fn with_body(status_code: u16, content_type: &str, body: Vec<u8>) -> Response {
+        let mut response = Response::new(status_code);
+        response.headers.set("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    /// Creates a response with the given status code and a plain text body.
+    pub fn text(status_code: u16, text: &str) -> Response {
+        Response::with_body(status_code, "text/plain; charset=utf-8", text.as_bytes().to_vec())
+    }
+
+    /// Creates a response with the given status code and an HTML body.
+    pub fn html(status_code: u16, html: &str) -> Response {
+        Response::with_body(status_code, "text/html; charset=utf-8", html.as_bytes().to_vec())
+    }
+
+    /// Creates a response with the given redirect status code that redirects to the given location.
+    pub fn redirect(status_code: u16, location: &str) -> Response {
+        let mut response = Response::new(status_code);
+        response.headers.set("Location", location);
+        response
+    }
+
+    /// Creates a `404 Not Found` response with a plain text body.
+    pub fn not_found() -> Response {
+        Response::text(404, "404 Not Found\n")
+    }
+
+    /// Returns whether the status code indicates success, i.e., is `2xx`.
+    pub fn is_success(&self) -> bool {
+        self.status_code >= 200 && self.status_code < 300
+    }
+
+    /// Returns whether the status code indicates a redirect that a client can follow.
+    pub fn is_redirect(&self) -> bool {
+        match self.status_code {
+            301 | 302 | 303 | 307 | 308 => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the body as a string, if it is valid UTF-8.
+    pub fn body_str(&self) -> Result<&str, &'static str> {
+        str::from_utf8(&self.body).map_err(|_| "http: the response body isn't valid UTF-8")
+    }
+
+    /// Returns this response if its status code indicates success, or an error otherwise.
+    pub fn error_for_status(self) -> Result<Response, &'static str> {
+        if self.is_success() {
+            Ok(self)
+        } else if self.status_code == 404 {
+            Err("http: the server responded with 404 Not Found")
+        } else if self.status_code >= 500 {
+            Err("http: the server responded with a server error")
+        } else {
+            Err("http: the server responded with an unsuccessful status code")
+        }
+    }
+}
+
+
+/// Returns the standard reason phrase of the given status code, or an empty string for unknown codes.
+pub fn reason_phrase(status_code: u16) -> &'static str {
+    match status_code {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
@@ -0,0 +1,246 @@
+//! Reading and writing the requests and responses that are exchanged over a TCP connection.
+
+use core::str;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use net_stack::tcp::TcpHandle;
+use {Headers, Method, MAX_HEAD_SIZE, MAX_HEADERS, MAX_BODY_SIZE};
+
+
+/// The error returned when the peer closed the connection in the middle of a message.
+pub const UNEXPECTED_EOF: &'static str = "http: the connection was closed in the middle of a message";
+
+/// The number of bytes that are received from the socket at once.
+const RECV_SIZE: usize = 4096;
+
+
+/// How the end of a message body is determined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyLength {
+    /// The message has no body.
+    Empty,
+    /// The body has the given `Content-Length`.
+    Fixed(usize),
+    /// The body is sent in chunks, the last of which is empty.
+    Chunked,
+    /// The body ends when the peer closes the connection, which is only possible for responses.
+    UntilClose,
+}
+
+impl BodyLength {
+    /// Determines the length of the body of a message with the given headers,
+    /// where `until_close` is the length of a body that has neither a `Transfer-Encoding` nor a `Content-Length`.
+    pub fn from_headers(headers: &Headers, until_close: BodyLength) -> Result<BodyLength, &'static str> {
+        if let Some(encoding) = headers.get("Transfer-Encoding") {
+            let last_coding = encoding.rsplit(',').next().unwrap_or("").trim();
+            if last_coding.eq_ignore_ascii_case("chunked") {
+                return Ok(BodyLength::Chunked);
+            }
+            if !last_coding.eq_ignore_ascii_case("identity") {
+                return Err("http: unsupported transfer encoding");
+            }
+        }
+        match headers.get("Content-Length") {
+            Some(length) => length.trim().parse::<usize>()
+                .map(BodyLength::Fixed)
+                .map_err(|_| "http: invalid Content-Length"),
+            None => Ok(until_close),
+        }
+    }
+}
+
+
+/// A TCP connection over which messages are read and written, which buffers the data that has been received
+/// but not yet read.
+pub struct Connection {
+    socket: TcpHandle,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(socket: TcpHandle) -> Connection {
+        Connection { socket, buffer: Vec::new() }
+    }
+
+    /// Sends all of the given data.
+    pub fn write_all(&self, data: &[u8]) -> Result<(), &'static str> {
+        self.socket.send(data)
+    }
+
+    /// Receives more data into the buffer, and returns how many bytes were received,
+    /// which is `0` once the peer has closed the connection.
+    fn fill(&mut self) -> Result<usize, &'static str> {
+        let mut chunk = [0u8; RECV_SIZE];
+        let received = self.socket.recv(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[.. received]);
+        Ok(received)
+    }
+
+    /// Removes and returns the first `len` bytes of the buffer.
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        let rest = self.buffer.split_off(len);
+        core::mem::replace(&mut self.buffer, rest)
+    }
+
+    /// Reads the head of a message, i.e., everything up to and including the empty line that ends its headers.
+    ///
+    /// Returns `None` if the peer closed the connection before sending anything.
+    pub fn read_head(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        let mut searched = 0;
+        loop {
+            if let Some(index) = find(&self.buffer[searched ..], b"\r\n\r\n") {
+                return Ok(Some(self.take(searched + index + 4)));
+            }
+            // The terminator may straddle the data that is received next.
+            searched = self.buffer.len().saturating_sub(3);
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err("http: the message head is too large");
+            }
+            if self.fill()? == 0 {
+                return if self.buffer.is_empty() { Ok(None) } else { Err(UNEXPECTED_EOF) };
+            }
+        }
+    }
+
+    /// Reads a line that ends with `\r\n` or `\n`, and returns it without the line ending.
+    fn read_line(&mut self) -> Result<Vec<u8>, &'static str> {
+        let mut searched = 0;
+        loop {
+            if let Some(index) = self.buffer[searched ..].iter().position(|&b| b == b'\n') {
+                let mut line = self.take(searched + index + 1);
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            searched = self.buffer.len();
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err("http: line too long");
+            }
+            if self.fill()? == 0 {
+                return Err(UNEXPECTED_EOF);
+            }
+        }
+    }
+
+    /// Reads exactly `len` bytes and appends them to the given `body`.
+    fn read_exact(&mut self, len: usize, body: &mut Vec<u8>) -> Result<(), &'static str> {
+        while self.buffer.len() < len {
+            if self.fill()? == 0 {
+                return Err(UNEXPECTED_EOF);
+            }
+        }
+        body.extend(self.buffer.drain(.. len));
+        Ok(())
+    }
+
+    /// Reads a message body of the given length.
+    pub fn read_body(&mut self, length: BodyLength) -> Result<Vec<u8>, &'static str> {
+        let mut body = Vec::new();
+        match length {
+            BodyLength::Empty => {}
+            BodyLength::Fixed(len) => {
+                if len > MAX_BODY_SIZE {
+                    return Err("http: the message body is too large");
+                }
+                body.reserve(len);
+                self.read_exact(len, &mut body)?;
+            }
+            BodyLength::Chunked => loop {
+                let line = self.read_line()?;
+                // Chunk extensions after a `;` are ignored.
+                let size = str::from_utf8(&line).ok()
+                    .and_then(|line| line.split(';').next())
+                    .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                    .ok_or("http: invalid chunk size")?;
+                if size == 0 {
+                    // Skip the trailer headers, which end with an empty line.
+                    while !self.read_line()?.is_empty() {}
+                    break;
+                }
+                if body.len().saturating_add(size) > MAX_BODY_SIZE {
+                    return Err("http: the message body is too large");
+                }
+                self.read_exact(size, &mut body)?;
+                if !self.read_line()?.is_empty() {
+                    return Err("http: chunk is longer than its size");
+                }
+            },
+            BodyLength::UntilClose => {
+                body.append(&mut self.buffer);
+                while self.fill()? != 0 {
+                    if body.len() + self.buffer.len() > MAX_BODY_SIZE {
+                        return Err("http: the message body is too large");
+                    }
+                    body.append(&mut self.buffer);
+                }
+            }
+        }
+        Ok(body)
+    }
+}
+
+
+/// Returns the index of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn to_headers(parsed: &[httparse::Header]) -> Headers {
+    let mut headers = Headers::new();
+    for header in parsed {
+        headers.add(header.name, &String::from_utf8_lossy(header.value));
+    }
+    headers
+}
+
+/// Parses the head of a response into its status code, reason phrase, and headers.
+pub fn parse_response_head(head: &[u8]) -> Result<(u16, String, Headers), &'static str> {
+    let mut parsed_headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut response = httparse::Response::new(&mut parsed_headers);
+    match response.parse(head) {
+        Ok(httparse::Status::Complete(_)) => {}
+        Ok(httparse::Status::Partial) => return Err("http: incomplete response head"),
+        Err(httparse::Error::TooManyHeaders) => return Err("http: the response has too many headers"),
+        Err(_) => return Err("http: malformed response head"),
+    }
+    let status_code = response.code.ok_or("http: the response has no status code")?;
+    let reason = response.reason.unwrap_or("").to_string();
+    Ok((status_code, reason, to_headers(response.headers)))
+}
+
+/// Parses the head of a request into its method, target, and headers.
+///
+/// A request whose method isn't supported is reported with `Ok(None)`.
+pub fn parse_request_head(head: &[u8]) -> Result<Option<(Method, String, Headers)>, &'static str> {
+    let mut parsed_headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut parsed_headers);
+    match request.parse(head) {
+        Ok(httparse::Status::Complete(_)) => {}
+        Ok(httparse::Status::Partial) => return Err("http: incomplete request head"),
+        Err(httparse::Error::TooManyHeaders) => return Err("http: the request has too many headers"),
+        Err(_) => return Err("http: malformed request head"),
+    }
+    let method = match request.method.and_then(Method::from_name) {
+        Some(method) => method,
+        None => return Ok(None),
+    };
+    let target = request.path.ok_or("http: the request has no target")?.to_string();
+    Ok(Some((method, target, to_headers(request.headers))))
+}
+
+/// Serializes the given start line and headers into a message head.
+pub fn encode_head(start_line: &str, headers: &Headers) -> Vec<u8> {
+    let mut head = String::with_capacity(256);
+    head.push_str(start_line);
+    head.push_str("\r\n");
+    for (name, value) in headers.iter() {
+        head.push_str(name);
+        head.push_str(": ");
+        head.push_str(value);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
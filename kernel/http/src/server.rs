@@ -0,0 +1,360 @@
+//! A small HTTP server that dispatches requests to handlers by their path.
+//!
+//! A [`Router`](struct.Router.html) maps paths to handlers, which are closures that turn a request into a response,
+//! and can serve the files of a VFS directory with [`Router::serve_dir()`](struct.Router.html#method.serve_dir).
+//! A [`Server`](struct.Server.html) accepts connections on a port and answers their requests with a router.
+//!
+//! Connections are handled one at a time on the task that runs the server, so files are served
+//! with the credentials of that task, see the `access_control` crate.
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use fs_node::{DirRef, FileRef, FileOrDir};
+use path::Path;
+use access_control::Access;
+use percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use net_stack::tcp::{self, TcpListener, TcpOptions};
+use message::{self, BodyLength, Connection};
+use {Method, Request, Response};
+
+
+/// The number of connections that may wait to be accepted.
+pub const BACKLOG: usize = 8;
+/// How long a client may go without sending anything before its connection is aborted, in milliseconds.
+pub const REQUEST_TIMEOUT_MS: u64 = 10_000;
+/// The `Server` header of responses.
+pub const SERVER_NAME: &'static str = "Theseus-http/0.1";
+
+/// The file that is served for a directory, if the directory contains it.
+const INDEX_FILE: &'static str = "index.html";
+
+
+/// A function that handles a request and returns its response.
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Which request paths a route applies to.
+enum PathPattern {
+    /// Only the given path.
+    Exact(String),
+    /// The given path and every path below it.
+    Prefix(String),
+}
+
+impl PathPattern {
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            PathPattern::Exact(pattern) => path == pattern,
+            PathPattern::Prefix(prefix) => path.starts_with(prefix.as_str())
+                && (prefix.ends_with('/') || path.len() == prefix.len() || path[prefix.len() ..].starts_with('/')),
+        }
+    }
+}
+
+struct Route {
+    /// The method that the route applies to, or `None` for every method.
+    method: Option<Method>,
+    pattern: PathPattern,
+    handler: Handler,
+}
+
+
+/// Dispatches requests to handlers by their method and path.
+///
+/// Routes are tried in the order that they were added, and the first one that matches a request handles it.
+/// A request whose path matches no route gets a `404 Not Found` response,
+/// and one whose path only matches routes for other methods gets a `405 Method Not Allowed` response.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new() }
+    }
+
+    /// Adds a route that handles requests with the given method for exactly the given path.
+    pub fn route<F>(&mut self, method: Method, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.routes.push(Route {
+            method: Some(method),
+            pattern: PathPattern::Exact(path.to_string()),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Adds a route that handles requests with the given method, or with any method if `None`,
+    /// for the given path and every path below it, e.g., `/files` matches `/files` and `/files/a.txt` but not `/filesystem`.
+    pub fn route_prefix<F>(&mut self, method: Option<Method>, prefix: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.routes.push(Route {
+            method,
+            pattern: PathPattern::Prefix(prefix.to_string()),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Adds a route that serves the files in the given directory and its subdirectories
+    /// for `GET` and `HEAD` requests below the given path prefix.
+    ///
+    /// A request for a directory is answered with its `index.html` file, if it has one, or otherwise with a listing of it.
+    pub fn serve_dir(&mut self, prefix: &str, dir: DirRef) -> &mut Router {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let handler_prefix = prefix.clone();
+        let handler = move |request: &Request| match request.method {
+            Method::Get | Method::Head => serve_file(&handler_prefix, &dir, request),
+            _ => method_not_allowed(&[Method::Get, Method::Head]),
+        };
+        if prefix.is_empty() {
+            self.route_prefix(None, "/", handler)
+        } else {
+            self.route_prefix(None, &prefix, handler)
+        }
+    }
+
+    /// Returns the response of the route that matches the given request.
+    pub fn handle(&self, request: &Request) -> Response {
+        let path = request.path();
+        let mut allowed = Vec::new();
+        for route in self.routes.iter().filter(|route| route.pattern.matches(path)) {
+            match route.method {
+                Some(method) if method != request.method
+                    // A `HEAD` request is answered like a `GET` request, whose body is then dropped.
+                    && !(method == Method::Get && request.method == Method::Head) => allowed.push(method),
+                _ => return (route.handler)(request),
+            }
+        }
+        if allowed.is_empty() { Response::not_found() } else { method_not_allowed(&allowed) }
+    }
+}
+
+
+/// A server that accepts connections on a port and answers their requests with a router.
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    /// Starts listening for connections on the given port.
+    pub fn bind(port: u16) -> Result<Server, &'static str> {
+        let listener = tcp::listen(port, BACKLOG, TcpOptions {
+            timeout_ms: Some(REQUEST_TIMEOUT_MS),
+            ..Default::default()
+        })?;
+        Ok(Server { listener })
+    }
+
+    /// Returns the port that this server accepts connections on.
+    pub fn port(&self) -> Result<u16, &'static str> {
+        self.listener.port()
+    }
+
+    /// Accepts connections and answers their requests with the given router, until accepting a connection fails.
+    pub fn serve(&self, router: &Router) -> Result<(), &'static str> {
+        loop {
+            self.handle_next(router)?;
+        }
+    }
+
+    /// Waits for the next connection and answers its request with the given router.
+    ///
+    /// Only a failure to accept the connection is returned; errors of the connection itself are logged.
+    pub fn handle_next(&self, router: &Router) -> Result<(), &'static str> {
+        let socket = self.listener.accept()?;
+        let mut connection = Connection::new(socket);
+        match handle_connection(&mut connection, router) {
+            Ok(()) => socket.close(),
+            Err(_e) => {
+                debug!("http: error on a connection of the server on port {:?}: {}", self.listener.port(), _e);
+                socket.abort();
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops accepting connections.
+    pub fn close(self) {
+        self.listener.close();
+    }
+}
+
+/// Reads a request from the given connection, and writes the response that the router returns for it.
+fn handle_connection(connection: &mut Connection, router: &Router) -> Result<(), &'static str> {
+    let head = match connection.read_head() {
+        Ok(Some(head)) => head,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            let _ = write_response(connection, Method::Get, Response::text(400, "400 Bad Request\n"));
+            return Err(e);
+        }
+    };
+    let (method, target, headers) = match message::parse_request_head(&head) {
+        Ok(Some(request_head)) => request_head,
+        Ok(None) => return write_response(connection, Method::Get, Response::text(501, "501 Not Implemented\n")),
+        Err(e) => {
+            let _ = write_response(connection, Method::Get, Response::text(400, "400 Bad Request\n"));
+            return Err(e);
+        }
+    };
+    let body = match BodyLength::from_headers(&headers, BodyLength::Empty).and_then(|length| connection.read_body(length)) {
+        Ok(body) => body,
+        Err(e) => {
+            let _ = write_response(connection, method, Response::text(400, "400 Bad Request\n"));
+            return Err(e);
+        }
+    };
+    let request = Request { method, target, headers, body };
+    let response = router.handle(&request);
+    debug!("http: {} {} -> {}", request.method, request.target, response.status_code);
+    write_response(connection, request.method, response)
+}
+
+/// Writes the given response to a request with the given method, which has no body if the request was a `HEAD` request.
+fn write_response(connection: &mut Connection, method: Method, mut response: Response) -> Result<(), &'static str> {
+    response.headers.set("Content-Length", &response.body.len().to_string());
+    response.headers.set("Connection", "close");
+    response.headers.remove("Transfer-Encoding");
+    if !response.headers.contains("Server") {
+        response.headers.set("Server", SERVER_NAME);
+    }
+    let start_line = format!("HTTP/1.1 {} {}", response.status_code, response.reason);
+    let mut data = message::encode_head(&start_line, &response.headers);
+    if method != Method::Head {
+        data.extend_from_slice(&response.body);
+    }
+    connection.write_all(&data)
+}
+
+/// Returns a `405 Method Not Allowed` response that lists the given allowed methods.
+fn method_not_allowed(allowed: &[Method]) -> Response {
+    let allowed: Vec<&str> = allowed.iter().map(|method| method.as_str()).collect();
+    let mut response = Response::text(405, "405 Method Not Allowed\n");
+    response.headers.set("Allow", &allowed.join(", "));
+    response
+}
+
+
+/// Serves the file or directory that the given request refers to within the given directory,
+/// which is served below the given path prefix.
+fn serve_file(prefix: &str, root: &DirRef, request: &Request) -> Response {
+    let path = request.path();
+    let relative_path = match percent_decode(path[prefix.len() ..].as_bytes()).decode_utf8() {
+        Ok(relative_path) => relative_path,
+        Err(_) => return Response::text(400, "400 Bad Request\n"),
+    };
+    let components: Vec<&str> = relative_path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    // Don't let a request escape the served directory.
+    if components.iter().any(|c| *c == "..") {
+        return Response::text(403, "403 Forbidden\n");
+    }
+    let node = if components.is_empty() {
+        access_control::check(&FileOrDir::Dir(root.clone()), Access::Read).map(|_| FileOrDir::Dir(root.clone()))
+    } else {
+        access_control::open(&Path::new(components.join("/")), root, Access::Read)
+    };
+
+    match node {
+        Ok(FileOrDir::File(file)) => read_file(&file).map_or_else(
+            |_| Response::text(500, "500 Internal Server Error\n"),
+            |contents| Response::with_body(200, content_type(components.last().cloned().unwrap_or("")), contents),
+        ),
+        Ok(FileOrDir::Dir(dir)) => {
+            if !path.ends_with('/') {
+                let location = match request.query() {
+                    Some(query) => format!("{}/?{}", path, query),
+                    None => format!("{}/", path),
+                };
+                return Response::redirect(301, &location);
+            }
+            let index = dir.lock().get_file(INDEX_FILE);
+            match index {
+                Some(index) => read_file(&index).map_or_else(
+                    |_| Response::text(500, "500 Internal Server Error\n"),
+                    |contents| Response::with_body(200, content_type(INDEX_FILE), contents),
+                ),
+                None => Response::html(200, &directory_listing(path, &dir)),
+            }
+        }
+        Err(access_control::PERMISSION_DENIED) => Response::text(403, "403 Forbidden\n"),
+        Err(_) => Response::not_found(),
+    }
+}
+
+fn read_file(file: &FileRef) -> Result<Vec<u8>, &'static str> {
+    let size = file.lock().size();
+    let mut contents = vec![0; size];
+    let read = access_control::read(file, &mut contents, 0)?;
+    contents.truncate(read);
+    Ok(contents)
+}
+
+/// Returns an HTML page that lists the files and subdirectories of the given directory, which has the given path.
+fn directory_listing(path: &str, dir: &DirRef) -> String {
+    let dir = dir.lock();
+    let mut entries: Vec<(String, bool)> = dir.list().into_iter()
+        .map(|name| {
+            let is_dir = match dir.get(&name) {
+                Some(FileOrDir::Dir(_)) => true,
+                _ => false,
+            };
+            (name, is_dir)
+        })
+        .collect();
+    entries.sort();
+
+    let title = escape_html(path);
+    let mut html = format!("<!DOCTYPE html>\n<html><head><title>Index of {0}</title></head>\n<body><h1>Index of {0}</h1>\n<ul>\n", title);
+    if path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (name, is_dir) in entries {
+        let suffix = if is_dir { "/" } else { "" };
+        html.push_str(&format!("<li><a href=\"{}{}\">{}{}</a></li>\n",
+            utf8_percent_encode(&name, PATH_SEGMENT_ENCODE_SET), suffix, escape_html(&name), suffix));
+    }
+    html.push_str("</ul></body></html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the content type of a file with the given name, based on its extension.
+pub fn content_type(file_name: &str) -> &'static str {
+    let extension = match file_name.rfind('.') {
+        Some(index) => &file_name[index + 1 ..],
+        None => return "application/octet-stream",
+    };
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" | "md" | "rs" | "toml" => "text/plain; charset=utf-8",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
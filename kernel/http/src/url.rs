@@ -0,0 +1,114 @@
+//! Parsing of `http://` URLs, and resolving the locations of redirects against them.
+
+use core::fmt;
+use core::str::FromStr;
+use alloc::string::{String, ToString};
+
+
+/// The port that HTTP servers listen on by default.
+pub const DEFAULT_PORT: u16 = 80;
+
+
+/// An `http://` URL, split into the parts that are needed to send a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    /// The host name or IP address, without the brackets around IPv6 addresses.
+    pub host: String,
+    pub port: u16,
+    /// The path including the query, which always starts with `/`; the fragment is dropped.
+    pub target: String,
+}
+
+impl Url {
+    /// Parses the given URL, which may omit the `http://` scheme.
+    pub fn parse(url: &str) -> Result<Url, &'static str> {
+        let url = url.trim();
+        let rest = match url.find("://") {
+            Some(index) if url[.. index].eq_ignore_ascii_case("http") => &url[index + 3 ..],
+            Some(index) if url[.. index].eq_ignore_ascii_case("https") => return Err("http: HTTPS is not supported"),
+            Some(_) => return Err("http: unsupported URL scheme"),
+            None => url,
+        };
+        let authority_end = rest.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(rest.len());
+        let (authority, target) = rest.split_at(authority_end);
+        if authority.contains('@') {
+            return Err("http: URLs with user information are not supported");
+        }
+
+        let (host, port) = if authority.starts_with('[') {
+            let end = authority.find(']').ok_or("http: invalid IPv6 address in URL")?;
+            (&authority[1 .. end], &authority[end + 1 ..])
+        } else {
+            match authority.rfind(':') {
+                Some(index) => (&authority[.. index], &authority[index ..]),
+                None => (authority, ""),
+            }
+        };
+        if host.is_empty() {
+            return Err("http: the URL has no host");
+        }
+        let port = match port {
+            "" | ":" => DEFAULT_PORT,
+            port if port.starts_with(':') => u16::from_str(&port[1 ..]).map_err(|_| "http: invalid port in URL")?,
+            _ => return Err("http: invalid host in URL"),
+        };
+
+        let target = target.split('#').next().unwrap_or("");
+        let target = if target.starts_with('/') {
+            target.to_string()
+        } else {
+            format!("/{}", target)
+        };
+        Ok(Url { host: host.to_ascii_lowercase(), port, target })
+    }
+
+    /// Returns the host and port in the form of the `Host` header, which omits the default port.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == DEFAULT_PORT { host } else { format!("{}:{}", host, self.port) }
+    }
+
+    /// Returns the host and port in the `"host:port"` form that `dns_resolver::resolve_endpoints()` expects.
+    pub fn host_and_port(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Returns the URL that the given location, e.g., that of a redirect, refers to relative to this URL.
+    ///
+    /// The location may be an absolute URL, a URL without a scheme (`//host/path`), an absolute path, a query, or a relative path.
+    pub fn join(&self, location: &str) -> Result<Url, &'static str> {
+        let location = location.trim();
+        if location.contains("://") {
+            Url::parse(location)
+        } else if location.starts_with("//") {
+            Url::parse(&location[2 ..])
+        } else if location.starts_with('/') {
+            Ok(Url { target: location.split('#').next().unwrap_or("/").to_string(), ..self.clone() })
+        } else if location.starts_with('?') {
+            let path = self.target.split('?').next().unwrap_or("/");
+            Ok(Url { target: format!("{}{}", path, location.split('#').next().unwrap_or("")), ..self.clone() })
+        } else {
+            let path = self.target.split('?').next().unwrap_or("/");
+            let directory = &path[..= path.rfind('/').unwrap_or(0)];
+            let location = location.split('#').next().unwrap_or("");
+            Ok(Url { target: format!("{}{}", directory, location), ..self.clone() })
+        }
+    }
+}
+
+impl FromStr for Url {
+    type Err = &'static str;
+    fn from_str(url: &str) -> Result<Url, &'static str> {
+        Url::parse(url)
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.target)
+    }
+}
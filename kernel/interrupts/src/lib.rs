@@ -8,6 +8,7 @@
 
 #[macro_use] extern crate log;
 #[macro_use] extern crate vga_buffer;
+extern crate alloc;
 extern crate x86_64;
 extern crate spin;
 extern crate port_io;
@@ -24,9 +25,12 @@ extern crate keyboard;
 extern crate mouse;
 extern crate ps2;
 extern crate tlb_shootdown;
+extern crate random;
+extern crate timer;
 
 
 
+use alloc::vec::Vec;
 use ps2::handle_mouse_packet;
 use x86_64::structures::idt::{Idt, LockedIdt, ExceptionStackFrame, HandlerFunc};
 use spin::Once;
@@ -211,6 +215,52 @@ pub fn register_msi_interrupt(func: HandlerFunc) -> Result<u8, &'static str> {
     Ok(interrupt_num as u8)
 } 
 
+/// Reserves an unused interrupt number for an MSI or MSI-X vector, without yet giving it a real handler.
+/// Until `set_msi_handler()` is called, the vector is handled by `unhandled_msi_interrupt_handler`,
+/// which acknowledges and ignores any interrupts that arrive on it.
+/// 
+/// This allows a device's MSI vectors to be allocated and programmed before its driver is ready to handle them.
+pub fn allocate_msi_vector() -> Result<u8, &'static str> {
+    register_msi_interrupt(unhandled_msi_interrupt_handler)
+        .map_err(|_| "allocate_msi_vector: no available interrupt")
+}
+
+/// Reserves `count` unused interrupt numbers for MSI or MSI-X vectors, see `allocate_msi_vector()`.
+/// If not enough interrupt numbers are available, none of them are reserved.
+pub fn allocate_msi_vectors(count: usize) -> Result<Vec<u8>, &'static str> {
+    let mut vectors = Vec::with_capacity(count);
+    for _ in 0..count {
+        match allocate_msi_vector() {
+            Ok(v) => vectors.push(v),
+            Err(e) => {
+                for v in vectors {
+                    let _ = deregister_interrupt(v, unhandled_msi_interrupt_handler);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(vectors)
+}
+
+/// Sets the handler function of an MSI vector that was reserved by `allocate_msi_vector()`.
+/// The function fails if the vector already has a handler, or wasn't allocated as an MSI vector.
+/// 
+/// # Arguments
+/// * `vector` - the interrupt number returned from `allocate_msi_vector()`
+/// * `func` - the handler to be registered for `vector`
+pub fn set_msi_handler(vector: u8, func: HandlerFunc) -> Result<(), &'static str> {
+    let mut idt = IDT.lock();
+    if idt[vector as usize].handler_eq(unhandled_msi_interrupt_handler) {
+        idt[vector as usize].set_handler_fn(func);
+        Ok(())
+    }
+    else {
+        error!("set_msi_handler: interrupt {} is not an unused MSI vector", vector);
+        Err("set_msi_handler: the given interrupt is not an unused MSI vector")
+    }
+}
+
 /// Returns an interrupt to the system by setting the handler to the default function. 
 /// The application provides the current interrupt handler as a safety check. 
 /// The function fails if the current handler and 'func' do not match
@@ -248,6 +298,14 @@ pub fn eoi(irq: Option<u8>) {
 }
 
 
+/// The placeholder handler for MSI vectors that have been allocated but not yet given a handler.
+/// Devices may raise an interrupt as soon as MSI is enabled, so this must acknowledge the interrupt.
+pub extern "x86-interrupt" fn unhandled_msi_interrupt_handler(_stack_frame: &mut ExceptionStackFrame) {
+    warn!("Received an MSI on a vector that has no handler yet");
+    eoi(None);
+}
+
+
 /// 0x20
 extern "x86-interrupt" fn pit_timer_handler(_stack_frame: &mut ExceptionStackFrame) {
     pit_clock::handle_timer_interrupt();
@@ -261,6 +319,7 @@ static EXTENDED_SCANCODE: AtomicBool = AtomicBool::new(false);
 
 /// 0x21
 extern "x86-interrupt" fn ps2_keyboard_handler(_stack_frame: &mut ExceptionStackFrame) {
+    random::add_interrupt_entropy(PIC_MASTER_OFFSET + 0x1);
 
     let indicator = ps2::ps2_status_register();
 
@@ -305,6 +364,7 @@ extern "x86-interrupt" fn ps2_keyboard_handler(_stack_frame: &mut ExceptionStack
 
 /// 0x2C
 extern "x86-interrupt" fn ps2_mouse_handler(_stack_frame: &mut ExceptionStackFrame) {
+    random::add_interrupt_entropy(PIC_MASTER_OFFSET + 0xc);
 
     let indicator = ps2::ps2_status_register();
 
@@ -333,12 +393,18 @@ pub static APIC_TIMER_TICKS: AtomicUsize = AtomicUsize::new(0);
 /// 0x22
 extern "x86-interrupt" fn lapic_timer_handler(_stack_frame: &mut ExceptionStackFrame) {
     let _ticks = APIC_TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+    random::add_interrupt_entropy(0x22);
     // info!(" ({}) APIC TIMER HANDLER! TICKS = {}", apic::get_my_apic_id(), _ticks);
+
+    // delivers expired software timers; in TSC-deadline mode, not every timer interrupt is a preemption tick
+    let is_preemption_tick = timer::handle_timer_interrupt();
     
     // we must acknowledge the interrupt first before handling it because we switch tasks here, which doesn't return
     eoi(None); // None, because 0x22 IRQ cannot possibly be a PIC interrupt
     
-    scheduler::schedule();
+    if is_preemption_tick {
+        scheduler::schedule();
+    }
 }
 
 
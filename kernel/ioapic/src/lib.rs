@@ -61,7 +61,7 @@ struct IoApicRegisters {
 
 
 /// Each IoApic handles a maximum of 24 interrupt redirection entries. 
-const INTERRUPT_ENTRIES_PER_IOAPIC: u32 = 24; 
+pub const INTERRUPT_ENTRIES_PER_IOAPIC: u32 = 24; 
 
 
 /// A representation of an IoApic (x86-specific interrupt chip for I/O devices).
@@ -148,6 +148,25 @@ impl IoApic {
         self.write_reg(irq_reg, direction | (1 << 16));
     }
 
+    /// Returns the raw 64-bit redirection table entry for the given IRQ line,
+    /// e.g., to save it before the system enters a sleep state.
+    pub fn redirection_entry(&mut self, ioapic_irq: u8) -> u64 {
+        let low_index: u32 = 0x10 + (ioapic_irq as u32) * 2;
+        let low = self.read_reg(low_index) as u64;
+        let high = self.read_reg(low_index + 1) as u64;
+        (high << 32) | low
+    }
+
+    /// Sets the raw 64-bit redirection table entry for the given IRQ line,
+    /// e.g., to restore it after the system resumes from a sleep state.
+    pub fn set_redirection_entry(&mut self, ioapic_irq: u8, entry: u64) {
+        let low_index: u32 = 0x10 + (ioapic_irq as u32) * 2;
+        // Keep the entry masked while it's only partially written.
+        self.write_reg(low_index, (entry as u32) | (1 << 16));
+        self.write_reg(low_index + 1, (entry >> 32) as u32);
+        self.write_reg(low_index, entry as u32);
+    }
+
     /// Set IRQ to an interrupt vector.
     /// # Arguments
     /// ioapic_irq: the IRQ number that this interrupt will trigger on this IoApic.
@@ -56,6 +56,10 @@ pub const MAX_PAGE_NUMBER: usize = MAX_VIRTUAL_ADDRESS / PAGE_SIZE;
 /// If it's too small, complex kernel functions will overflow, causing a page fault / double fault.
 pub const KERNEL_STACK_SIZE_IN_PAGES: usize = 16;
 
+/// The size of the physical memory area, at the top of available memory, that holds the crash dump.
+/// This area is never given out by the frame allocator, so its contents survive a warm reboot.
+pub const CRASH_DUMP_SIZE_IN_BYTES: usize = 256 * 1024;
+
 /// The virtual address where the initial kernel (the nano_core) is mapped to.
 /// Actual value: 0xFFFFFFFF80000000.
 /// i.e., the linear offset between physical memory and kernel memory.
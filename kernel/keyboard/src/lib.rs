@@ -4,13 +4,13 @@ extern crate keycodes_ascii;
 extern crate spin;
 extern crate event_types;
 extern crate ps2;
-extern crate mpmc;
+extern crate channel;
 #[macro_use] extern crate log;
 
 
 use keycodes_ascii::{Keycode, KeyboardModifiers, KEY_RELEASED_OFFSET, KeyAction, KeyEvent};
 use spin::Once;
-use mpmc::Queue;
+use channel::Sender;
 use event_types::Event;
 use ps2::{init_ps2_port1,test_ps2_port1,keyboard_led,keyboard_detect,KeyboardType};
 
@@ -19,7 +19,7 @@ use ps2::{init_ps2_port1,test_ps2_port1,keyboard_led,keyboard_detect,KeyboardTyp
 static mut KBD_MODIFIERS: KeyboardModifiers = KeyboardModifiers::new();
 
 
-static KEYBOARD_PRODUCER: Once<Queue<Event>> = Once::new();
+static KEYBOARD_PRODUCER: Once<Sender<Event>> = Once::new();
 
 /// Bitmask for the Scroll Lock keyboard LED
 const SCROLL_LED: u8 = 0b001;
@@ -29,8 +29,8 @@ const NUM_LED: u8 = 0b010;
 const CAPS_LED: u8 = 0b100;
 
 /// Initialize the keyboard driver. 
-/// Arguments: a reference to a queue onto which keyboard events should be enqueued. 
-pub fn init(keyboard_queue_producer: Queue<Event>) { 
+/// Arguments: the sending side of a channel onto which keyboard events should be sent. 
+pub fn init(keyboard_queue_producer: Sender<Event>) { 
     // set keyboard to scancode set 1
 
     //init the first ps2 port for keyboard
@@ -116,7 +116,7 @@ pub fn handle_keyboard_input(scan_code: u8, extended: bool) -> Result<(), &'stat
                 Some(keycode) => {
                     let event = Event::new_keyboard_event(KeyEvent::new(keycode, action, modifiers.clone()));
                     if let Some(producer) = KEYBOARD_PRODUCER.try() {
-                        producer.push(event).map_err(|_e| "keyboard input queue is full")
+                        producer.try_send(event).map_err(|_e| "keyboard input channel is full or disconnected")
                     }
                     else {
                         warn!("handle_keyboard_input(): KEYBOARD_PRODUCER wasn't yet initialized, dropping keyboard event {:?}.", event);
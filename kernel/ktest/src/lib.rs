@@ -0,0 +1,69 @@
+//! Registration of in-kernel tests, which are run by the `test_runner` when Theseus is built with `make test`.
+//!
+//! A test is a function that takes no arguments and returns `Result<(), &'static str>`,
+//! declared within the [`theseus_test!()`](macro.theseus_test.html) macro in any kernel crate:
+//! ```ignore
+//! #[macro_use] extern crate ktest;
+//!
+//! theseus_test! {
+//!     fn my_feature_works() {
+//!         if 1 + 1 == 2 { Ok(()) } else { Err("math is broken") }
+//!     }
+//! }
+//! ```
+//! Tests are only compiled in when the `theseus_test` config option is enabled,
+//! so they don't affect normal builds.
+//!
+//! This crate is deliberately free of dependencies, such that any kernel crate can define tests.
+
+#![no_std]
+
+/// The name of the function generated for each test, which the `test_runner` uses to find tests.
+/// A test named `foo` in module `my_crate::bar` has the symbol `my_crate::bar::foo::__theseus_test::<hash>`.
+pub const TEST_FUNCTION_NAME: &'static str = "__theseus_test";
+
+/// The signature of every test function.
+pub type TestFunction = fn() -> Result<(), &'static str>;
+
+/// Registers one or more test functions, each of which returns `Result<(), &'static str>`.
+///
+/// Each test `foo` is placed in its own module `foo` as a function named `__theseus_test`,
+/// along with a `#[used]` static reference that prevents the compiler from removing it as dead code.
+/// Items from the enclosing module are imported into each test's module.
+#[macro_export]
+macro_rules! theseus_test {
+    ($( $(#[$attr:meta])* fn $name:ident() $body:block )*) => {
+        $(
+            #[cfg(theseus_test)]
+            #[allow(non_snake_case, dead_code)]
+            #[doc(hidden)]
+            pub mod $name {
+                #[allow(unused_imports)]
+                use super::*;
+
+                $(#[$attr])*
+                #[inline(never)]
+                pub fn __theseus_test() -> Result<(), &'static str> $body
+
+                #[used]
+                static __THESEUS_TEST_REF: fn() -> Result<(), &'static str> = __theseus_test;
+            }
+        )*
+    };
+}
+
+/// Returns an error with the given message if the given condition is false.
+/// This is meant for use within test functions, in place of `assert!()`.
+#[macro_export]
+macro_rules! test_assert {
+    ($cond:expr) => {
+        if !($cond) {
+            return Err(concat!("assertion failed: ", stringify!($cond)));
+        }
+    };
+    ($cond:expr, $msg:expr) => {
+        if !($cond) {
+            return Err($msg);
+        }
+    };
+}
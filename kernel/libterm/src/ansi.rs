@@ -0,0 +1,293 @@
+//! A parser for the VT100/ANSI escape sequences that programs write to a terminal.
+//!
+//! The parser is a simplified version of the state machine described at <https://vt100.net/emu/dec_ansi_parser>.
+//! It turns a stream of characters into [`Action`](enum.Action.html)s, which the terminal's screen then carries out.
+//! Operating system commands (OSC), e.g., ones that set the window title, are recognized but ignored.
+
+/// The maximum number of numeric parameters of a control sequence; further parameters are ignored.
+const MAX_PARAMS: usize = 16;
+
+/// The escape character, which starts every escape sequence.
+const ESC: char = '\x1b';
+/// The "cancel" and "substitute" characters, which abort the escape sequence being parsed.
+const CAN: char = '\x18';
+const SUB: char = '\x1a';
+/// The bell character, which also ends an operating system command.
+const BEL: char = '\x07';
+
+
+/// The numeric parameters of a control sequence, e.g., `1` and `31` in `ESC [ 1 ; 31 m`.
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    len: usize,
+}
+
+impl Params {
+    fn new() -> Params {
+        Params { values: [0; MAX_PARAMS], len: 0 }
+    }
+
+    /// Returns the number of parameters.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the control sequence has no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the parameter at `index`, or `default` if it was omitted or is zero.
+    ///
+    /// Like in real terminals, a parameter of zero means the same as an omitted one for most control sequences.
+    pub fn get(&self, index: usize, default: u16) -> u16 {
+        match self.values[.. self.len].get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+
+    /// Returns the parameter at `index` as given, where an omitted parameter is zero.
+    pub fn get_raw(&self, index: usize) -> u16 {
+        self.values[.. self.len].get(index).cloned().unwrap_or(0)
+    }
+
+    /// Returns an iterator over all parameters, where omitted parameters are zero.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = u16> + 'a {
+        self.values[.. self.len].iter().cloned()
+    }
+
+    fn push_digit(&mut self, digit: u16) {
+        if self.len == 0 {
+            self.len = 1;
+        }
+        let value = &mut self.values[self.len - 1];
+        *value = value.saturating_mul(10).saturating_add(digit);
+    }
+
+    fn next_param(&mut self) {
+        if self.len == 0 {
+            // the first parameter was omitted, e.g., in `ESC [ ; 5 H`
+            self.len = 1;
+        }
+        if self.len < MAX_PARAMS {
+            self.len += 1;
+        }
+    }
+}
+
+
+/// An action that the terminal should carry out, as parsed from the characters written to it.
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    /// Print a character at the cursor position.
+    Print(char),
+    /// Execute a C0 control character, e.g., a newline, carriage return, tab, or backspace.
+    Execute(char),
+    /// Carry out a control sequence (CSI), which starts with `ESC [`.
+    Csi {
+        params: Params,
+        /// The private marker that may precede the parameters, e.g., `?` in `ESC [ ? 25 h`.
+        private: Option<char>,
+        /// The intermediate character that may follow the parameters.
+        intermediate: Option<char>,
+        /// The final character, which determines the function of the control sequence.
+        action: char,
+    },
+    /// Carry out an escape sequence, e.g., `ESC 7` to save the cursor.
+    Esc {
+        /// The intermediate character that may precede the final character, e.g., `(` in `ESC ( B`.
+        intermediate: Option<char>,
+        action: char,
+    },
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiParam,
+    CsiIntermediate,
+    /// An invalid control sequence, whose characters are ignored until its final character.
+    CsiIgnore,
+    OscString,
+    /// An `ESC` was received within an operating system command, which is probably the start of its `ESC \` terminator.
+    OscEscape,
+}
+
+
+/// A parser that turns characters into the [`Action`](enum.Action.html)s they stand for.
+pub struct Parser {
+    state: State,
+    params: Params,
+    private: Option<char>,
+    intermediate: Option<char>,
+}
+
+impl Parser {
+    /// Creates a new parser, which isn't in the middle of an escape sequence.
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: Params::new(),
+            private: None,
+            intermediate: None,
+        }
+    }
+
+    /// Feeds the next character to the parser, and returns the action to carry out, if the character completes one.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        // These characters have the same effect in every state.
+        match c {
+            CAN | SUB => {
+                self.state = State::Ground;
+                return None;
+            }
+            ESC if self.state != State::OscString => {
+                self.start_sequence(State::Escape);
+                return None;
+            }
+            _ => { }
+        }
+
+        match self.state {
+            State::Ground => {
+                if is_control(c) {
+                    Some(Action::Execute(c))
+                } else {
+                    Some(Action::Print(c))
+                }
+            }
+
+            State::Escape => match c {
+                '[' => {
+                    self.start_sequence(State::CsiParam);
+                    None
+                }
+                ']' => {
+                    self.state = State::OscString;
+                    None
+                }
+                ' ' ..= '/' => {
+                    self.intermediate = Some(c);
+                    self.state = State::EscapeIntermediate;
+                    None
+                }
+                _ if is_control(c) => Some(Action::Execute(c)),
+                _ => {
+                    self.state = State::Ground;
+                    Some(Action::Esc { intermediate: None, action: c })
+                }
+            },
+
+            State::EscapeIntermediate => match c {
+                ' ' ..= '/' => None,
+                _ if is_control(c) => Some(Action::Execute(c)),
+                _ => {
+                    self.state = State::Ground;
+                    Some(Action::Esc { intermediate: self.intermediate, action: c })
+                }
+            },
+
+            State::CsiParam => match c {
+                '0' ..= '9' => {
+                    self.params.push_digit(c as u16 - '0' as u16);
+                    None
+                }
+                ';' => {
+                    self.params.next_param();
+                    None
+                }
+                '<' ..= '?' if self.params.is_empty() && self.private.is_none() => {
+                    self.private = Some(c);
+                    None
+                }
+                ':' | '<' ..= '?' => {
+                    self.state = State::CsiIgnore;
+                    None
+                }
+                ' ' ..= '/' => {
+                    self.intermediate = Some(c);
+                    self.state = State::CsiIntermediate;
+                    None
+                }
+                _ => self.finish_csi(c),
+            },
+
+            State::CsiIntermediate => match c {
+                ' ' ..= '/' => None,
+                '0' ..= '?' => {
+                    self.state = State::CsiIgnore;
+                    None
+                }
+                _ => self.finish_csi(c),
+            },
+
+            State::CsiIgnore => {
+                if is_control(c) {
+                    Some(Action::Execute(c))
+                } else {
+                    if ('@' ..= '~').contains(&c) {
+                        self.state = State::Ground;
+                    }
+                    None
+                }
+            }
+
+            State::OscString => {
+                match c {
+                    BEL => self.state = State::Ground,
+                    ESC => self.state = State::OscEscape,
+                    _ => { }
+                }
+                None
+            }
+
+            State::OscEscape => {
+                // `ESC \` ends the command; anything else starts a new escape sequence.
+                if c == '\\' {
+                    self.state = State::Ground;
+                    None
+                } else {
+                    self.start_sequence(State::Escape);
+                    self.advance(c)
+                }
+            }
+        }
+    }
+
+    fn start_sequence(&mut self, state: State) {
+        self.state = state;
+        self.params = Params::new();
+        self.private = None;
+        self.intermediate = None;
+    }
+
+    fn finish_csi(&mut self, c: char) -> Option<Action> {
+        if is_control(c) {
+            // C0 controls within a control sequence are executed without ending it.
+            return Some(Action::Execute(c));
+        }
+        self.state = State::Ground;
+        Some(Action::Csi {
+            params: self.params,
+            private: self.private,
+            intermediate: self.intermediate,
+            action: c,
+        })
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+/// Returns whether `c` is a C0 control character or DEL.
+fn is_control(c: char) -> bool {
+    c < ' ' || c == '\x7f'
+}
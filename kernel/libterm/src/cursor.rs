@@ -17,7 +17,7 @@ pub struct Cursor {
     /// The position of the cursor relative to the end of terminal text in number of characters.
     pub offset_from_end: usize,
     /// The underlying character at the position of the cursor.
-    /// It is shown when the cursor is unseen.
+    /// When the cursor is unseen, the terminal shows the character at that position on its screen instead.
     pub underlying_char: u8,
 }
 
@@ -66,6 +66,7 @@ impl Cursor {
     /// * `coordinate`: the start point of a textarea in the framebuffer.
     /// * `column`: the column of the cursor in the textarea.
     /// * `line`: the line of the cursor in the textarea.
+    /// * `cell`: the character under the cursor, which is shown when the cursor is unseen.
     /// * `framebuffer`: the framebuffer to display the cursor in.
     ///
     /// Returns a bounding box which wraps the cursor.
@@ -74,6 +75,7 @@ impl Cursor {
         coordinate: Coord,
         column: usize,
         line: usize,
+        cell: &Cell,
         framebuffer: &mut Framebuffer<P>,
     ) -> Result<Rectangle, &'static str> where Color: Into<P> {
         if self.blink() {
@@ -91,11 +93,12 @@ impl Cursor {
                     self.color.into(),
                 );
             } else {
+                let (fg, bg) = cell.style.colors();
                 framebuffer_printer::print_ascii_character(
                     framebuffer,
-                    self.underlying_char,
-                    FONT_FOREGROUND_COLOR.into(),
-                    FONT_BACKGROUND_COLOR.into(),
+                    cell.character,
+                    fg.into(),
+                    bg.into(),
                     coordinate,
                     column,
                     line,
@@ -0,0 +1,931 @@
+//! The contents of a terminal: a grid of character cells, the scrollback lines above it, and the cursor.
+//!
+//! The screen carries out the [`Action`](../ansi/enum.Action.html)s parsed from the output of programs,
+//! which covers the common subset of VT100/xterm control functions:
+//! cursor movement and positioning, erasing, inserting and deleting characters and lines,
+//! scroll regions, the alternate screen, and SGR attributes with 16, 256, and 24-bit colors.
+//!
+//! A row that is filled up wraps onto the next row, and remembers that it did so,
+//! such that wrapped lines are joined back together when the screen is resized.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::mem;
+use ansi::{Action, Params};
+use color::Color;
+use super::{FONT_BACKGROUND_COLOR, FONT_FOREGROUND_COLOR};
+
+/// The distance between tab stops, in number of characters.
+pub const TAB_WIDTH: usize = 8;
+/// The default number of lines that are kept after they scroll off the top of the screen.
+pub const DEFAULT_SCROLLBACK_LINES: usize = 1000;
+
+
+/// A color of a terminal character, as chosen by SGR control sequences.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TermColor {
+    /// The terminal's default foreground or background color.
+    Default,
+    /// One of the 256 colors of the xterm palette, where 0 to 7 are the standard colors and 8 to 15 their bright variants.
+    Indexed(u8),
+    /// A 24-bit color.
+    Rgb(Color),
+}
+
+/// The attributes with which a character is displayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+    pub fg: TermColor,
+    pub bg: TermColor,
+    /// Bold characters are shown in the bright variant of their standard color.
+    pub bold: bool,
+    pub underline: bool,
+    /// Swaps the foreground and background colors.
+    pub inverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            fg: TermColor::Default,
+            bg: TermColor::Default,
+            bold: false,
+            underline: false,
+            inverse: false,
+        }
+    }
+}
+
+impl Style {
+    /// Returns the actual `(foreground, background)` colors of characters with this style.
+    pub fn colors(&self) -> (Color, Color) {
+        let fg = match self.fg {
+            TermColor::Default => FONT_FOREGROUND_COLOR,
+            TermColor::Indexed(index) if self.bold && index < 8 => palette_color(index + 8),
+            TermColor::Indexed(index) => palette_color(index),
+            TermColor::Rgb(color) => color,
+        };
+        let bg = match self.bg {
+            TermColor::Default => FONT_BACKGROUND_COLOR,
+            TermColor::Indexed(index) => palette_color(index),
+            TermColor::Rgb(color) => color,
+        };
+        if self.inverse { (bg, fg) } else { (fg, bg) }
+    }
+}
+
+/// A single character on the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    /// The character, as an index into the terminal font.
+    pub character: u8,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell { character: b' ', style: Style::default() }
+    }
+}
+
+impl Cell {
+    /// Returns an empty cell, as left behind by erasing with the given current style.
+    ///
+    /// Like in xterm, erased cells keep the current background color but no other attributes.
+    fn erased(style: &Style) -> Cell {
+        Cell {
+            character: b' ',
+            style: Style { bg: style.bg, ..Style::default() },
+        }
+    }
+}
+
+/// A row of cells, which is always as wide as the screen.
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// Whether the line in this row continues in the next row because it was too long to fit.
+    pub wrapped: bool,
+}
+
+impl Row {
+    fn new(width: usize) -> Row {
+        Row { cells: vec![Cell::default(); width], wrapped: false }
+    }
+
+    fn blank(width: usize, style: &Style) -> Row {
+        Row { cells: vec![Cell::erased(style); width], wrapped: false }
+    }
+}
+
+/// A cursor position, along with the style and modes that are saved and restored with it.
+#[derive(Clone, Copy, Debug)]
+struct SavedCursor {
+    row: usize,
+    col: usize,
+    style: Style,
+    origin_mode: bool,
+}
+
+
+/// The contents of a terminal, see the [module-level documentation](index.html).
+pub struct Screen {
+    width: usize,
+    height: usize,
+    /// The scrollback lines followed by the `height` rows of the screen itself.
+    rows: VecDeque<Row>,
+    /// The maximum number of scrollback lines.
+    scrollback_limit: usize,
+    /// How many lines the view is scrolled back from the bottom of the screen, where 0 shows the screen itself.
+    view_offset: usize,
+    /// The row of the cursor on the screen.
+    cursor_row: usize,
+    /// The column of the cursor, which is equal to `width` if a character was just printed into the last column.
+    /// The next character then goes to the start of the next row, if autowrap is enabled.
+    cursor_col: usize,
+    /// The style of newly printed characters.
+    style: Style,
+    saved_cursor: Option<SavedCursor>,
+    /// The first and last row of the scroll region, which is the whole screen by default.
+    scroll_top: usize,
+    scroll_bottom: usize,
+    /// Whether printing into the last column wraps onto the next row.
+    autowrap: bool,
+    /// Whether cursor positions are relative to the scroll region.
+    origin_mode: bool,
+    /// Whether the cursor should be displayed.
+    cursor_visible: bool,
+    /// The rows of the normal screen, including the scrollback, while the alternate screen is shown.
+    primary_rows: Option<VecDeque<Row>>,
+}
+
+impl Screen {
+    /// Creates an empty screen with the given dimensions in number of characters.
+    pub fn new(width: usize, height: usize) -> Screen {
+        let (width, height) = (max(width, 1), max(height, 1));
+        Screen {
+            width,
+            height,
+            rows: (0 .. height).map(|_| Row::new(width)).collect(),
+            scrollback_limit: DEFAULT_SCROLLBACK_LINES,
+            view_offset: 0,
+            cursor_row: 0,
+            cursor_col: 0,
+            style: Style::default(),
+            saved_cursor: None,
+            scroll_top: 0,
+            scroll_bottom: height - 1,
+            autowrap: true,
+            origin_mode: false,
+            cursor_visible: true,
+            primary_rows: None,
+        }
+    }
+
+    /// Returns the `(width, height)` of the screen in number of characters.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the number of lines that are kept after they scroll off the top of the screen.
+    pub fn scrollback_limit(&self) -> usize {
+        self.scrollback_limit
+    }
+
+    /// Sets the number of lines that are kept after they scroll off the top of the screen,
+    /// discarding the oldest lines if there are more.
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scrollback_limit = lines;
+        self.trim_scrollback();
+    }
+
+    /// Returns the number of scrollback lines above the screen.
+    pub fn scrollback_len(&self) -> usize {
+        self.rows.len() - self.height
+    }
+
+    /// Returns whether the cursor should be displayed, which programs can change with `ESC [ ? 25 l` and `ESC [ ? 25 h`.
+    pub fn is_cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    /// Clears the screen and the scrollback lines, and moves the cursor to the top-left corner.
+    pub fn clear(&mut self) {
+        self.rows = (0 .. self.height).map(|_| Row::new(self.width)).collect();
+        self.view_offset = 0;
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+    }
+
+    /// Resets the screen to its initial state, except for its dimensions and scrollback limit.
+    pub fn reset(&mut self) {
+        let scrollback_limit = self.scrollback_limit;
+        *self = Screen::new(self.width, self.height);
+        self.scrollback_limit = scrollback_limit;
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // The view, which shows either the screen or a part of the scrollback.
+
+    /// Returns whether the view shows the screen itself rather than scrollback lines.
+    pub fn is_view_at_bottom(&self) -> bool {
+        self.view_offset == 0
+    }
+
+    /// Returns how many lines the view is scrolled back from the screen.
+    pub fn view_offset(&self) -> usize {
+        self.view_offset
+    }
+
+    /// Scrolls the view `lines` lines back into the scrollback, stopping at the oldest line.
+    pub fn scroll_view_up(&mut self, lines: usize) {
+        self.view_offset = min(self.view_offset + lines, self.scrollback_len());
+    }
+
+    /// Scrolls the view `lines` lines forward, stopping at the screen itself.
+    pub fn scroll_view_down(&mut self, lines: usize) {
+        self.view_offset = self.view_offset.saturating_sub(lines);
+    }
+
+    /// Scrolls the view to the oldest scrollback line.
+    pub fn scroll_view_to_top(&mut self) {
+        self.view_offset = self.scrollback_len();
+    }
+
+    /// Scrolls the view back to the screen itself.
+    pub fn scroll_view_to_bottom(&mut self) {
+        self.view_offset = 0;
+    }
+
+    /// Returns the rows that are currently in view, from top to bottom.
+    pub fn view_rows(&self) -> impl Iterator<Item = &Row> {
+        let top = self.scrollback_len() - self.view_offset;
+        self.rows.range(top .. top + self.height)
+    }
+
+    /// Returns the `(column, line)` in the view where the cursor should be displayed,
+    /// after moving it `offset_from_end` characters back from its actual position within its line,
+    /// along with the cell under it. Returns `None` if that position isn't in view.
+    pub fn cursor_in_view(&self, offset_from_end: usize) -> Option<(usize, usize, Cell)> {
+        let (mut row, mut col) = self.cursor_absolute();
+        col = min(col, self.width - 1);
+        for _ in 0 .. offset_from_end {
+            let (r, c) = self.previous_position((row, col))?;
+            row = r;
+            col = c;
+        }
+        let view_top = self.scrollback_len() - self.view_offset;
+        if row < view_top || row >= view_top + self.height {
+            return None;
+        }
+        Some((col, row - view_top, self.rows[row].cells[col]))
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Editing the end of the current line, which is how the shell edits its command line.
+
+    /// Replaces the `count` characters before the cursor in the current line with the characters that `edit` produces from them,
+    /// and moves the cursor behind the new characters.
+    ///
+    /// For example, the shell inserts a character into its command line by adding it in front of the characters after the insertion point.
+    /// Returns an error if the line has fewer than `count` characters before the cursor.
+    pub fn edit_before_cursor<F>(&mut self, count: usize, edit: F) -> Result<(), &'static str>
+        where F: FnOnce(&mut Vec<Cell>)
+    {
+        let mut start = self.cursor_absolute();
+        let mut cells = Vec::with_capacity(count + 1);
+        for _ in 0 .. count {
+            start = self.previous_position(start).ok_or("libterm: the line has fewer characters before the cursor than the given offset")?;
+            cells.push(self.rows[start.0].cells[start.1]);
+        }
+        cells.reverse();
+        let old_len = cells.len();
+        edit(&mut cells);
+
+        let mut position = start;
+        for cell in cells.iter() {
+            position = self.put_at(position, *cell);
+        }
+        let end = position;
+        // Erase what remains of the old characters if there are fewer new ones.
+        for _ in cells.len() .. old_len {
+            position = self.put_at(position, Cell::default());
+        }
+        if end.1 == self.width && cells.len() < old_len {
+            self.rows[end.0].wrapped = false;
+        }
+
+        // The rows may have scrolled while the characters were put, so the end is converted to a screen position last.
+        let top = self.scrollback_len();
+        if end.0 >= top {
+            self.cursor_row = end.0 - top;
+            self.cursor_col = end.1;
+        } else {
+            self.cursor_row = 0;
+            self.cursor_col = 0;
+        }
+        Ok(())
+    }
+
+    /// Returns the position of the cursor as `(index into rows, column)`.
+    fn cursor_absolute(&self) -> (usize, usize) {
+        (self.scrollback_len() + self.cursor_row, self.cursor_col)
+    }
+
+    /// Returns the position of the character before the given one in the same line, following wrapped rows upwards.
+    fn previous_position(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+        if col > 0 {
+            Some((row, col - 1))
+        } else if row > 0 && self.rows[row - 1].wrapped {
+            Some((row - 1, self.width - 1))
+        } else {
+            None
+        }
+    }
+
+    /// Puts a cell at the given position, wrapping onto the next row if the position is past the end of its row,
+    /// and returns the position after it.
+    fn put_at(&mut self, (mut row, mut col): (usize, usize), cell: Cell) -> (usize, usize) {
+        if col >= self.width {
+            self.rows[row].wrapped = true;
+            if row + 1 == self.rows.len() {
+                self.push_row();
+                row = self.rows.len() - 2;
+            }
+            row += 1;
+            col = 0;
+        }
+        self.rows[row].cells[col] = cell;
+        (row, col + 1)
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Carrying out actions.
+
+    /// Carries out an action that was parsed from the output of a program.
+    pub fn perform(&mut self, action: Action) {
+        match action {
+            Action::Print(c) => self.print(c),
+            Action::Execute(c) => self.execute(c),
+            Action::Csi { params, private, intermediate, action } => self.csi(&params, private, intermediate, action),
+            Action::Esc { intermediate, action } => self.esc(intermediate, action),
+        }
+    }
+
+    /// Prints a character at the cursor and advances the cursor.
+    pub fn print(&mut self, c: char) {
+        // The font only has the first 256 characters.
+        let character = if (c as u32) < 256 { c as u8 } else { b'?' };
+        if self.cursor_col >= self.width {
+            if self.autowrap {
+                let row = self.cursor_absolute().0;
+                self.rows[row].wrapped = true;
+                self.cursor_col = 0;
+                self.index();
+            } else {
+                self.cursor_col = self.width - 1;
+            }
+        }
+        let (row, col) = self.cursor_absolute();
+        self.rows[row].cells[col] = Cell { character, style: self.style };
+        self.cursor_col += 1;
+    }
+
+    fn execute(&mut self, c: char) {
+        match c {
+            // Programs in Theseus end their lines with just a line feed, so it also returns the cursor to the start of the line.
+            '\n' | '\x0b' | '\x0c' => {
+                self.cursor_col = 0;
+                self.index();
+            }
+            '\r' => self.cursor_col = 0,
+            '\t' => {
+                let next_stop = (min(self.cursor_col, self.width - 1) / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.cursor_col = min(next_stop, self.width - 1);
+            }
+            '\x08' => self.cursor_col = min(self.cursor_col, self.width - 1).saturating_sub(1),
+            _ => { } // e.g., the bell
+        }
+    }
+
+    fn esc(&mut self, intermediate: Option<char>, action: char) {
+        if intermediate.is_some() {
+            return; // character set designations, which don't apply to the terminal font
+        }
+        match action {
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            'D' => self.index(),
+            'E' => {
+                self.cursor_col = 0;
+                self.index();
+            }
+            'M' => self.reverse_index(),
+            'c' => self.reset(),
+            _ => { }
+        }
+    }
+
+    fn csi(&mut self, params: &Params, private: Option<char>, intermediate: Option<char>, action: char) {
+        if intermediate.is_some() {
+            return;
+        }
+        if private == Some('?') {
+            match action {
+                'h' => self.set_private_modes(params, true),
+                'l' => self.set_private_modes(params, false),
+                _ => { }
+            }
+            return;
+        }
+        if private.is_some() {
+            return;
+        }
+
+        let n = params.get(0, 1) as usize;
+        match action {
+            'A' => self.move_cursor_vertically(-(n as isize)),
+            'B' | 'e' => self.move_cursor_vertically(n as isize),
+            'C' | 'a' => self.cursor_col = min(self.cursor_col + n, self.width - 1),
+            'D' => self.cursor_col = min(self.cursor_col, self.width - 1).saturating_sub(n),
+            'E' => {
+                self.move_cursor_vertically(n as isize);
+                self.cursor_col = 0;
+            }
+            'F' => {
+                self.move_cursor_vertically(-(n as isize));
+                self.cursor_col = 0;
+            }
+            'G' | '`' => self.cursor_col = min(n - 1, self.width - 1),
+            'H' | 'f' => self.set_cursor_position(params.get(0, 1) as usize - 1, params.get(1, 1) as usize - 1),
+            'd' => {
+                let col = self.cursor_col;
+                self.set_cursor_position(n - 1, col);
+            }
+            'J' => self.erase_in_display(params.get_raw(0)),
+            'K' => self.erase_in_line(params.get_raw(0)),
+            '@' => self.insert_characters(n),
+            'P' => self.delete_characters(n),
+            'X' => self.erase_characters(n),
+            'L' => self.insert_lines(n),
+            'M' => self.delete_lines(n),
+            'S' => for _ in 0 .. n { self.scroll_region_up() },
+            'T' => for _ in 0 .. n { self.scroll_region_down() },
+            'm' => self.select_graphic_rendition(params),
+            'r' => self.set_scroll_region(params),
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => { }
+        }
+    }
+
+    fn set_private_modes(&mut self, params: &Params, enable: bool) {
+        for mode in params.iter() {
+            match mode {
+                6 => {
+                    self.origin_mode = enable;
+                    self.set_cursor_position(0, 0);
+                }
+                7 => self.autowrap = enable,
+                25 => self.cursor_visible = enable,
+                47 | 1047 => self.set_alternate_screen(enable),
+                1049 => {
+                    if enable {
+                        self.save_cursor();
+                        self.set_alternate_screen(true);
+                    } else {
+                        self.set_alternate_screen(false);
+                        self.restore_cursor();
+                    }
+                }
+                _ => { }
+            }
+        }
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Cursor movement.
+
+    /// Moves the cursor up (if negative) or down, stopping at the scroll region's margins if it starts within them.
+    fn move_cursor_vertically(&mut self, lines: isize) {
+        let row = self.cursor_row as isize + lines;
+        let (top, bottom) = if self.cursor_row >= self.scroll_top && self.cursor_row <= self.scroll_bottom {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.height - 1)
+        };
+        self.cursor_row = max(top as isize, min(row, bottom as isize)) as usize;
+        self.cursor_col = min(self.cursor_col, self.width - 1);
+    }
+
+    /// Moves the cursor to the given row and column, which are relative to the scroll region in origin mode.
+    fn set_cursor_position(&mut self, row: usize, col: usize) {
+        let (top, bottom) = if self.origin_mode { (self.scroll_top, self.scroll_bottom) } else { (0, self.height - 1) };
+        self.cursor_row = min(top + row, bottom);
+        self.cursor_col = min(col, self.width - 1);
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            row: self.cursor_row,
+            col: self.cursor_col,
+            style: self.style,
+            origin_mode: self.origin_mode,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor.unwrap_or(SavedCursor { row: 0, col: 0, style: Style::default(), origin_mode: false });
+        self.cursor_row = min(saved.row, self.height - 1);
+        self.cursor_col = min(saved.col, self.width);
+        self.style = saved.style;
+        self.origin_mode = saved.origin_mode;
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Scrolling.
+
+    /// Moves the cursor down one row, scrolling the scroll region up if the cursor is at its bottom.
+    fn index(&mut self) {
+        if self.cursor_row == self.scroll_bottom {
+            self.scroll_region_up();
+        } else if self.cursor_row + 1 < self.height {
+            self.cursor_row += 1;
+        }
+    }
+
+    /// Moves the cursor up one row, scrolling the scroll region down if the cursor is at its top.
+    fn reverse_index(&mut self) {
+        if self.cursor_row == self.scroll_top {
+            self.scroll_region_down();
+        } else if self.cursor_row > 0 {
+            self.cursor_row -= 1;
+        }
+    }
+
+    /// Scrolls the contents of the scroll region up by one row.
+    ///
+    /// If the scroll region is the whole normal screen, the top row becomes a scrollback line.
+    fn scroll_region_up(&mut self) {
+        if self.scroll_top == 0 && self.scroll_bottom == self.height - 1 && self.primary_rows.is_none() {
+            self.push_row();
+        } else {
+            let top = self.scrollback_len();
+            self.rows.remove(top + self.scroll_top);
+            self.rows.insert(top + self.scroll_bottom, Row::blank(self.width, &self.style));
+        }
+    }
+
+    /// Scrolls the contents of the scroll region down by one row, discarding its bottom row.
+    fn scroll_region_down(&mut self) {
+        let top = self.scrollback_len();
+        self.rows.remove(top + self.scroll_bottom);
+        self.rows.insert(top + self.scroll_top, Row::blank(self.width, &self.style));
+    }
+
+    /// Adds a new row at the bottom of the screen, which turns the screen's top row into a scrollback line.
+    fn push_row(&mut self) {
+        self.rows.push_back(Row::blank(self.width, &self.style));
+        if self.view_offset > 0 {
+            // keep the view on the same lines
+            self.view_offset += 1;
+        }
+        if self.primary_rows.is_some() {
+            // the alternate screen has no scrollback
+            self.rows.pop_front();
+        }
+        self.trim_scrollback();
+    }
+
+    fn trim_scrollback(&mut self) {
+        while self.scrollback_len() > self.scrollback_limit {
+            self.rows.pop_front();
+        }
+        self.view_offset = min(self.view_offset, self.scrollback_len());
+    }
+
+    fn set_scroll_region(&mut self, params: &Params) {
+        let top = params.get(0, 1) as usize - 1;
+        let bottom = min(params.get(1, self.height as u16) as usize, self.height) - 1;
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.set_cursor_position(0, 0);
+        }
+    }
+
+    fn set_alternate_screen(&mut self, enable: bool) {
+        if enable && self.primary_rows.is_none() {
+            let alternate = (0 .. self.height).map(|_| Row::new(self.width)).collect();
+            self.primary_rows = Some(mem::replace(&mut self.rows, alternate));
+            self.view_offset = 0;
+        } else if !enable {
+            if let Some(primary) = self.primary_rows.take() {
+                self.rows = primary;
+            }
+        }
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Erasing, inserting, and deleting.
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let top = self.scrollback_len();
+        match mode {
+            // from the cursor to the end of the screen
+            0 => {
+                self.erase_in_line(0);
+                for row in self.cursor_row + 1 .. self.height {
+                    self.rows[top + row] = Row::blank(self.width, &self.style);
+                }
+            }
+            // from the start of the screen to the cursor
+            1 => {
+                self.erase_in_line(1);
+                for row in 0 .. self.cursor_row {
+                    self.rows[top + row] = Row::blank(self.width, &self.style);
+                }
+            }
+            // the whole screen
+            2 => {
+                for row in 0 .. self.height {
+                    self.rows[top + row] = Row::blank(self.width, &self.style);
+                }
+            }
+            // the scrollback lines
+            3 => {
+                self.rows.drain(.. top);
+                self.view_offset = 0;
+            }
+            _ => { }
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let (row, col) = self.cursor_absolute();
+        let col = min(col, self.width - 1);
+        let blank = Cell::erased(&self.style);
+        let range = match mode {
+            0 => col .. self.width,
+            1 => 0 .. col + 1,
+            2 => 0 .. self.width,
+            _ => return,
+        };
+        let row = &mut self.rows[row];
+        for cell in &mut row.cells[range] {
+            *cell = blank;
+        }
+        if mode != 1 {
+            row.wrapped = false;
+        }
+    }
+
+    fn erase_characters(&mut self, n: usize) {
+        let (row, col) = self.cursor_absolute();
+        let col = min(col, self.width - 1);
+        let end = min(col + n, self.width);
+        let blank = Cell::erased(&self.style);
+        for cell in &mut self.rows[row].cells[col .. end] {
+            *cell = blank;
+        }
+    }
+
+    fn insert_characters(&mut self, n: usize) {
+        let (row, col) = self.cursor_absolute();
+        let col = min(col, self.width - 1);
+        let n = min(n, self.width - col);
+        let blank = Cell::erased(&self.style);
+        let cells = &mut self.rows[row].cells;
+        cells.truncate(self.width - n);
+        for _ in 0 .. n {
+            cells.insert(col, blank);
+        }
+        self.cursor_col = col;
+    }
+
+    fn delete_characters(&mut self, n: usize) {
+        let (row, col) = self.cursor_absolute();
+        let col = min(col, self.width - 1);
+        let n = min(n, self.width - col);
+        let blank = Cell::erased(&self.style);
+        let cells = &mut self.rows[row].cells;
+        cells.drain(col .. col + n);
+        cells.resize(self.width, blank);
+        self.cursor_col = col;
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        if self.cursor_row < self.scroll_top || self.cursor_row > self.scroll_bottom {
+            return;
+        }
+        let top = self.scrollback_len();
+        for _ in 0 .. min(n, self.scroll_bottom - self.cursor_row + 1) {
+            self.rows.remove(top + self.scroll_bottom);
+            self.rows.insert(top + self.cursor_row, Row::blank(self.width, &self.style));
+        }
+        self.cursor_col = 0;
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        if self.cursor_row < self.scroll_top || self.cursor_row > self.scroll_bottom {
+            return;
+        }
+        let top = self.scrollback_len();
+        for _ in 0 .. min(n, self.scroll_bottom - self.cursor_row + 1) {
+            self.rows.remove(top + self.cursor_row);
+            self.rows.insert(top + self.scroll_bottom, Row::blank(self.width, &self.style));
+        }
+        self.cursor_col = 0;
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Attributes.
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.style = Style::default();
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params.get_raw(i) {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                4 => self.style.underline = true,
+                7 => self.style.inverse = true,
+                21 | 22 => self.style.bold = false,
+                24 => self.style.underline = false,
+                27 => self.style.inverse = false,
+                code @ 30 ..= 37 => self.style.fg = TermColor::Indexed((code - 30) as u8),
+                38 => {
+                    if let Some((color, used)) = extended_color(params, i + 1) {
+                        self.style.fg = color;
+                        i += used;
+                    }
+                }
+                39 => self.style.fg = TermColor::Default,
+                code @ 40 ..= 47 => self.style.bg = TermColor::Indexed((code - 40) as u8),
+                48 => {
+                    if let Some((color, used)) = extended_color(params, i + 1) {
+                        self.style.bg = color;
+                        i += used;
+                    }
+                }
+                49 => self.style.bg = TermColor::Default,
+                code @ 90 ..= 97 => self.style.fg = TermColor::Indexed((code - 90 + 8) as u8),
+                code @ 100 ..= 107 => self.style.bg = TermColor::Indexed((code - 100 + 8) as u8),
+                _ => { } // e.g., italic or blinking, which the terminal font can't show
+            }
+            i += 1;
+        }
+    }
+
+
+    // ---------------------------------------------------------------------------------------------
+    // Resizing.
+
+    /// Changes the dimensions of the screen, rewrapping the lines of the normal screen and its scrollback to the new width.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let (width, height) = (max(width, 1), max(height, 1));
+        if width == self.width && height == self.height {
+            return;
+        }
+
+        let cursor = self.cursor_absolute();
+        if let Some(primary) = self.primary_rows.take() {
+            // The alternate screen is cut off or padded rather than rewrapped, since full-screen programs redraw it anyway.
+            let (primary, _) = reflow(primary, (0, 0), self.width, width);
+            self.primary_rows = Some(primary);
+            for row in self.rows.iter_mut() {
+                row.cells.resize(width, Cell::default());
+                row.wrapped = false;
+            }
+            while self.rows.len() > height {
+                self.rows.pop_back();
+            }
+            while self.rows.len() < height {
+                self.rows.push_back(Row::new(width));
+            }
+            self.width = width;
+            self.height = height;
+            self.cursor_row = min(self.cursor_row, height - 1);
+        } else {
+            let rows = mem::take(&mut self.rows);
+            let (rows, (cursor_row, cursor_col)) = reflow(rows, cursor, self.width, width);
+            self.rows = rows;
+            self.width = width;
+            self.fit_to_height(height, cursor_row);
+            self.cursor_row = cursor_row - self.scrollback_len();
+            self.cursor_col = cursor_col;
+        }
+        self.cursor_col = min(self.cursor_col, self.width);
+        self.scroll_top = 0;
+        self.scroll_bottom = self.height - 1;
+        self.trim_scrollback();
+    }
+
+    /// Sets the height of the screen, adding blank rows at the bottom or removing rows below the cursor,
+    /// such that the given cursor row is on the screen. The rows above the screen become scrollback lines.
+    fn fit_to_height(&mut self, height: usize, cursor_row: usize) {
+        self.height = height;
+        while self.rows.len() < height {
+            self.rows.push_back(Row::new(self.width));
+        }
+        while cursor_row + height < self.rows.len() {
+            self.rows.pop_back();
+        }
+    }
+}
+
+
+/// Joins the wrapped rows into lines and wraps those lines again at `new_width`,
+/// returning the new rows and the new position of the given cursor.
+fn reflow(rows: VecDeque<Row>, cursor: (usize, usize), old_width: usize, new_width: usize) -> (VecDeque<Row>, (usize, usize)) {
+    let mut new_rows = VecDeque::with_capacity(rows.len());
+    let mut new_cursor = (0, 0);
+    let mut line: Vec<Cell> = Vec::new();
+    // the offset of the cursor within `line`, if it lies in that line
+    let mut cursor_in_line: Option<usize> = None;
+
+    for (index, row) in rows.into_iter().enumerate() {
+        if index == cursor.0 {
+            cursor_in_line = Some(line.len() + min(cursor.1, old_width));
+        }
+        let wrapped = row.wrapped;
+        line.extend(row.cells);
+        if wrapped {
+            continue;
+        }
+
+        // trim the trailing blanks of the line, but not those before the cursor
+        let keep = cursor_in_line.unwrap_or(0);
+        while line.len() > keep && line.last() == Some(&Cell::default()) {
+            line.pop();
+        }
+
+        let first_row = new_rows.len();
+        let mut chunks = line.chunks(new_width).peekable();
+        if chunks.peek().is_none() {
+            new_rows.push_back(Row::new(new_width));
+        }
+        while let Some(chunk) = chunks.next() {
+            let mut cells = chunk.to_vec();
+            cells.resize(new_width, Cell::default());
+            new_rows.push_back(Row { cells, wrapped: chunks.peek().is_some() });
+        }
+        if let Some(offset) = cursor_in_line.take() {
+            // a cursor right after a full row stays at the end of that row rather than at the start of the next one
+            let row = if offset > 0 && offset % new_width == 0 { offset / new_width - 1 } else { offset / new_width };
+            let col = offset - row * new_width;
+            while first_row + row >= new_rows.len() {
+                new_rows.push_back(Row::new(new_width));
+            }
+            new_cursor = (first_row + row, col);
+        }
+        line.clear();
+    }
+    (new_rows, new_cursor)
+}
+
+/// Parses an extended color, `5;INDEX` or `2;R;G;B`, from the parameters starting at `index`,
+/// and returns it along with the number of parameters it used.
+fn extended_color(params: &Params, index: usize) -> Option<(TermColor, usize)> {
+    match params.get_raw(index) {
+        5 if index + 1 < params.len() => Some((TermColor::Indexed(params.get_raw(index + 1) as u8), 2)),
+        2 if index + 3 < params.len() => {
+            let (r, g, b) = (params.get_raw(index + 1) as u32, params.get_raw(index + 2) as u32, params.get_raw(index + 3) as u32);
+            Some((TermColor::Rgb(Color::new((min(r, 255) << 16) | (min(g, 255) << 8) | min(b, 255))), 4))
+        }
+        _ => None,
+    }
+}
+
+/// Returns a color of the xterm 256-color palette.
+fn palette_color(index: u8) -> Color {
+    const STANDARD: [u32; 16] = [
+        0x000000, 0xCD0000, 0x00CD00, 0xCDCD00, 0x0000EE, 0xCD00CD, 0x00CDCD, 0xE5E5E5,
+        0x7F7F7F, 0xFF0000, 0x00FF00, 0xFFFF00, 0x5C5CFF, 0xFF00FF, 0x00FFFF, 0xFFFFFF,
+    ];
+    match index {
+        0 ..= 15 => Color::new(STANDARD[index as usize]),
+        // a 6x6x6 color cube
+        16 ..= 231 => {
+            let index = index as u32 - 16;
+            let level = |value: u32| if value == 0 { 0 } else { value * 40 + 55 };
+            Color::new((level(index / 36) << 16) | (level(index / 6 % 6) << 8) | level(index % 6))
+        }
+        // a ramp of grays
+        _ => {
+            let gray = (index as u32 - 232) * 10 + 8;
+            Color::new((gray << 16) | (gray << 8) | gray)
+        }
+    }
+}
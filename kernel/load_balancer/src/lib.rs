@@ -0,0 +1,190 @@
+//! Balances the load across the per-core runqueues by migrating tasks between them.
+//!
+//! Each core invokes [`balance()`](fn.balance.html) every time it schedules, which does two things:
+//! * **Idle-time work stealing:** a core that has no tasks on its runqueue (other than its idle task)
+//!   advertises itself as idle, and the next busy core to schedule hands one of its tasks over to it.
+//! * **Periodic load balancing:** every [`BALANCE_INTERVAL`](constant.BALANCE_INTERVAL.html) invocations,
+//!   a core compares its load against the least busy core,
+//!   and migrates one of its tasks there if the difference is at least [`IMBALANCE_THRESHOLD`](constant.IMBALANCE_THRESHOLD.html).
+//!
+//! Tasks are always pushed from a busy core to another core, rather than pulled by the other core,
+//! because only the busy core itself knows that none of its tasks are in the middle of a context switch.
+//! Tasks are only ever migrated to a core that their affinity allows them to run on,
+//! and tasks whose affinity has changed to exclude their current core are migrated away from it.
+//!
+//! A core's load is the number of tasks on its runqueue, including blocked ones,
+//! which each runqueue keeps count of such that it can be read without locking every task.
+//!
+//! The per-core load statistics gathered here can be obtained with [`core_loads()`](fn.core_loads.html).
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate atomic_linked_list;
+extern crate runqueue;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use atomic_linked_list::atomic_map::AtomicMap;
+
+
+/// The number of invocations of [`balance()`](fn.balance.html) on a core between its periodic load balancing attempts.
+pub const BALANCE_INTERVAL: usize = 64;
+
+/// The minimum difference in load between two cores that causes a task to be migrated
+/// during periodic load balancing.
+pub const IMBALANCE_THRESHOLD: usize = 2;
+
+
+/// The load balancing state of a single core.
+#[derive(Debug, Default)]
+struct CoreState {
+    /// Whether this core has no tasks, and is thus waiting for another core to give it one.
+    idle: AtomicBool,
+    /// The number of times `balance()` has been invoked on this core.
+    invocations: AtomicUsize,
+    /// The number of tasks that have been migrated to this core.
+    migrated_in: AtomicUsize,
+    /// The number of tasks that have been migrated away from this core.
+    migrated_out: AtomicUsize,
+}
+
+lazy_static! {
+    /// The load balancing state of each core, which is added the first time that core invokes `balance()`.
+    static ref CORE_STATES: AtomicMap<u8, CoreState> = AtomicMap::new();
+}
+
+/// The number of cores that are currently advertising themselves as idle.
+static NUM_IDLE_CORES: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Load statistics for a single core, as returned by [`core_loads()`](fn.core_loads.html).
+#[derive(Clone, Copy, Debug)]
+pub struct CoreLoad {
+    /// The core (APIC ID) that these statistics describe.
+    pub core: u8,
+    /// The number of tasks on this core's runqueue, not including its idle task.
+    pub queued_tasks: usize,
+    /// Whether this core is idle and waiting to be given a task by another core.
+    pub idle: bool,
+    /// The number of tasks that have been migrated to this core.
+    pub migrated_in: usize,
+    /// The number of tasks that have been migrated away from this core.
+    pub migrated_out: usize,
+}
+
+/// Returns the load statistics of every core that participates in load balancing.
+pub fn core_loads() -> Vec<CoreLoad> {
+    CORE_STATES.iter().map(|(&core, state)| CoreLoad {
+        core,
+        queued_tasks: runqueue::get_load(core).unwrap_or(0),
+        idle: state.idle.load(Ordering::Relaxed),
+        migrated_in: state.migrated_in.load(Ordering::Relaxed),
+        migrated_out: state.migrated_out.load(Ordering::Relaxed),
+    }).collect()
+}
+
+
+/// Balances the load between the current core, given by `apic_id`, and the other cores.
+/// 
+/// This should be invoked by the scheduler on the current core before it selects the next task,
+/// and must not be invoked on behalf of a different core. 
+pub fn balance(apic_id: u8) {
+    let state = match CORE_STATES.get(&apic_id) {
+        Some(s) => s,
+        _ => {
+            CORE_STATES.insert(apic_id, CoreState::default());
+            match CORE_STATES.get(&apic_id) {
+                Some(s) => s,
+                _ => return,
+            }
+        }
+    };
+
+    // Tasks whose affinity no longer allows them to run on this core must be moved elsewhere.
+    match runqueue::migrate_disallowed_tasks(apic_id) {
+        Ok(0) => { }
+        Ok(n) => {
+            state.migrated_out.fetch_add(n, Ordering::Relaxed);
+        }
+        Err(e) => error!("load_balancer: failed to migrate disallowed tasks away from core {}: {}", apic_id, e),
+    }
+
+    let load = match runqueue::get_load(apic_id) {
+        Some(l) => l,
+        _ => return,
+    };
+
+    // A core with nothing to run advertises itself as idle, and waits for a busy core to give it a task.
+    if load == 0 {
+        if !state.idle.swap(true, Ordering::AcqRel) {
+            NUM_IDLE_CORES.fetch_add(1, Ordering::AcqRel);
+        }
+        return;
+    }
+    if state.idle.swap(false, Ordering::AcqRel) {
+        NUM_IDLE_CORES.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    // Only a core with more than one task (including the current one) has a task to give away.
+    if load < 2 {
+        return;
+    }
+
+    // First, give a task to an idle core, if there is one.
+    if NUM_IDLE_CORES.load(Ordering::Acquire) > 0 {
+        for (&core, other) in CORE_STATES.iter() {
+            // Claim the idle core such that other busy cores don't all give it a task at once. 
+            // If it's still idle after it receives our task, it will advertise itself again.
+            if core != apic_id && other.idle.compare_and_swap(true, false, Ordering::AcqRel) {
+                NUM_IDLE_CORES.fetch_sub(1, Ordering::AcqRel);
+                if migrate(apic_id, state, core, other) {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Second, periodically balance the load with the least busy core.
+    let invocations = state.invocations.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    if invocations % BALANCE_INTERVAL != 0 {
+        return;
+    }
+    let mut least_busy: Option<(u8, &CoreState, usize)> = None;
+    for (&core, other) in CORE_STATES.iter() {
+        if core == apic_id {
+            continue;
+        }
+        if let Some(other_load) = runqueue::get_load(core) {
+            if least_busy.map_or(true, |(_, _, min_load)| other_load < min_load) {
+                least_busy = Some((core, other, other_load));
+            }
+        }
+    }
+    if let Some((core, other, other_load)) = least_busy {
+        if load >= other_load + IMBALANCE_THRESHOLD {
+            migrate(apic_id, state, core, other);
+        }
+    }
+}
+
+/// Migrates one task from the current core `from_core` to `to_core`, and updates both cores' statistics.
+/// Returns `true` if a task was migrated.
+fn migrate(from_core: u8, from_state: &CoreState, to_core: u8, to_state: &CoreState) -> bool {
+    match runqueue::migrate_task(from_core, to_core) {
+        Ok(Some(_task)) => {
+            #[cfg(not(any(rq_eval, downtime_eval)))]
+            debug!("load_balancer: migrated task {:?} from core {} to core {}", _task, from_core, to_core);
+            from_state.migrated_out.fetch_add(1, Ordering::Relaxed);
+            to_state.migrated_in.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            error!("load_balancer: failed to migrate a task from core {} to core {}: {}", from_core, to_core, e);
+            false
+        }
+    }
+}
@@ -1,12 +1,36 @@
+//! The Theseus system logger, which implements the `log` crate's `Log` trait.
+//!
+//! Every log message is timestamped and written to the serial port, 
+//! recorded in a lock-free in-memory ring buffer (see the `dmesg` application),
+//! and passed to any additional [`LogSink`]s, such as a terminal or a file.
+//!
+//! The log level can be set globally as well as for individual crates at runtime.
+
 #![no_std]
+#![feature(const_in_array_repeat_expressions)]
 
+extern crate alloc;
 extern crate serial_port;
 extern crate log;
-extern crate spin;
+extern crate irq_safety;
+extern crate tsc;
+extern crate fs_node;
+
+mod ring_buffer;
+mod sinks;
+
+pub use ring_buffer::{LogRecord, RING_BUFFER_LEN, read_from, next_sequence, dropped_records, clear, set_timestamp_frequency};
+pub use sinks::{LogEntry, LogSink, TerminalSink, FileSink, add_sink, remove_sink, sink_names};
 
-use log::{Record, Level, SetLoggerError, Metadata, Log};
+use alloc::{
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use irq_safety::RwLockIrqSafe;
+use log::{Record, Level, LevelFilter, SetLoggerError, Metadata, Log};
 use core::fmt;
-use spin::Once;
 
 
 /// The static logger instance, an empty struct that implements the `Log` trait.
@@ -15,8 +39,16 @@ static LOGGER: Logger = Logger { };
 /// By default, Theseus will log 
 const DEFAULT_LOG_LEVEL: Level = Level::Trace;
 
+/// The name of the sink added by [`mirror_to_vga()`](fn.mirror_to_vga.html).
+pub const VGA_SINK_NAME: &'static str = "vga";
+
+/// The level used for crates that don't have their own level, stored as a `LevelFilter` value.
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+/// The per-crate log levels that override the default level.
+static CRATE_LEVELS: RwLockIrqSafe<Vec<(String, LevelFilter)>> = RwLockIrqSafe::new(Vec::new());
+
 pub type LogOutputFunc = fn(fmt::Arguments);
-static MIRROR_VGA_FUNC: Once<LogOutputFunc> = Once::new();
 
 /// See ANSI terminal formatting schemes
 #[allow(dead_code)]
@@ -49,9 +81,23 @@ impl LogColor {
     }
 }
 
-/// Call this to enable mirroring logging macros to the screen
+/// Call this to enable mirroring logging macros to the screen.
+///
+/// This adds a [`TerminalSink`] named `"vga"` that invokes the given `func`,
+/// so the heap must be initialized before this is called.
 pub fn mirror_to_vga(func: LogOutputFunc) {
-    MIRROR_VGA_FUNC.call_once(|| func);
+    let _ = add_sink(Arc::new(TerminalSink::new(String::from(VGA_SINK_NAME), func)));
+}
+
+/// Returns the short prefix that denotes the given level in log output, e.g., `"[I] "`.
+fn level_str(level: Level) -> &'static str {
+    match level {
+        Level::Error => "[E] ",
+        Level::Warn =>  "[W] ",
+        Level::Info =>  "[I] ",
+        Level::Debug => "[D] ",
+        Level::Trace => "[T] ",
+    }
 }
 
 /// A dummy struct that exists so we can implement the Log trait's methods.
@@ -59,7 +105,7 @@ struct Logger { }
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= log::max_level()
+        metadata.level() <= level_for_target(metadata.target())
     }
 
     fn log(&self, record: &Record) {
@@ -67,37 +113,54 @@ impl Log for Logger {
             return;
         }
 
-        let (level_str, color) = match record.level() {
-            Level::Error => ("[E] ", LogColor::Red),
-            Level::Warn =>  ("[W] ", LogColor::Yellow),
-            Level::Info =>  ("[I] ", LogColor::Cyan),
-            Level::Debug => ("[D] ", LogColor::Green),
-            Level::Trace => ("[T] ", LogColor::Purple),
+        let ticks = tsc::tsc_ticks().into();
+        let timestamp = ring_buffer::ticks_to_timestamp(ticks);
+        let level_str = level_str(record.level());
+        let color = match record.level() {
+            Level::Error => LogColor::Red,
+            Level::Warn =>  LogColor::Yellow,
+            Level::Info =>  LogColor::Cyan,
+            Level::Debug => LogColor::Green,
+            Level::Trace => LogColor::Purple,
         };
 
+        let module_path = record.module_path().unwrap_or(record.target());
         let file_loc = record.file().unwrap_or("??");
         let line_loc = record.line().unwrap_or(0);
-        let _result = serial_port::write_fmt(format_args!("{}{}{}:{}: {}{}",
-            color.as_terminal_string(),
-            level_str,
-            file_loc,
-            line_loc,
-            record.args(),
-            LogColor::Reset.as_terminal_string(),
-        ));
-        // If there was an error above, there's literally nothing we can do but ignore it,
-        // because there is no other lower-level way to log errors than the serial port.
-        
-        if let Some(func) = MIRROR_VGA_FUNC.try() {
-            // Currently printing to the VGA terminal doesn't support ANSI color escape sequences,
-            // so we exclude the first and the last elements that set those colors.
-            func(format_args!("{}{}:{}: {}",
+
+        ring_buffer::push(record.level(), ticks, module_path, *record.args());
+
+        let _result = match timestamp {
+            Some(ts) => serial_port::write_fmt(format_args!("{}[{:>5}.{:06}] {}{}:{}: {}{}",
+                color.as_terminal_string(),
+                ts.as_secs(),
+                ts.subsec_micros(),
                 level_str,
                 file_loc,
                 line_loc,
                 record.args(),
-            ));
-        }
+                LogColor::Reset.as_terminal_string(),
+            )),
+            None => serial_port::write_fmt(format_args!("{}{}{}:{}: {}{}",
+                color.as_terminal_string(),
+                level_str,
+                file_loc,
+                line_loc,
+                record.args(),
+                LogColor::Reset.as_terminal_string(),
+            )),
+        };
+        // If there was an error above, there's literally nothing we can do but ignore it,
+        // because there is no other lower-level way to log errors than the serial port.
+
+        sinks::write_to_sinks(&LogEntry {
+            level: record.level(),
+            timestamp,
+            module_path,
+            file: file_loc,
+            line: line_loc,
+            args: *record.args(),
+        });
     }
 
     fn flush(&self) {
@@ -108,6 +171,7 @@ impl Log for Logger {
 
 /// Initialize the Theseus system logger, which writes log messages to the serial port. 
 pub fn init() -> Result<(), SetLoggerError> {
+    ring_buffer::set_boot_ticks(tsc::tsc_ticks().into());
     log::set_logger(&LOGGER)?;
     set_log_level(DEFAULT_LOG_LEVEL);
     Ok(())
@@ -119,6 +183,75 @@ pub fn init() -> Result<(), SetLoggerError> {
 /// 
 /// If `Level::Info` is set, `debug!()` and `trace!()` will not be logged, 
 /// but `info!()`, `warn!()`, and `error!()` will be. 
+///
+/// This is the default level for all crates that don't have their own level set
+/// with [`set_crate_log_level()`](fn.set_crate_log_level.html).
 pub fn set_log_level(level: Level) {
-    log::set_max_level(level.to_level_filter())
+    set_default_log_level(level.to_level_filter())
+}
+
+/// Like [`set_log_level()`](fn.set_log_level.html), but also accepts `LevelFilter::Off` to disable logging.
+pub fn set_default_log_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as usize, Ordering::Release);
+    update_max_level();
+}
+
+/// Returns the default log level for crates that don't have their own level.
+pub fn default_log_level() -> LevelFilter {
+    level_filter_from_usize(DEFAULT_LEVEL.load(Ordering::Acquire))
+}
+
+/// Sets the log level for the crate with the given name, which overrides the default log level.
+/// If `level` is `None`, the crate's level is reset to the default.
+///
+/// A crate's level can be either more or less verbose than the default level.
+pub fn set_crate_log_level(crate_name: &str, level: Option<LevelFilter>) {
+    {
+        let mut levels = CRATE_LEVELS.write();
+        let existing = levels.iter().position(|(name, _)| name == crate_name);
+        match (existing, level) {
+            (Some(i), Some(level)) => levels[i].1 = level,
+            (Some(i), None)        => { levels.remove(i); }
+            (None, Some(level))    => levels.push((String::from(crate_name), level)),
+            (None, None)           => { }
+        }
+    }
+    update_max_level();
+}
+
+/// Returns the crates that have their own log level, along with that level.
+pub fn crate_log_levels() -> Vec<(String, LevelFilter)> {
+    CRATE_LEVELS.read().clone()
+}
+
+/// Returns the log level that applies to the given log target, which starts with its crate name.
+fn level_for_target(target: &str) -> LevelFilter {
+    let crate_name = target.split("::").next().unwrap_or(target);
+    // The crate levels can't be read while being modified on this core, so fall back to the default level.
+    if let Some(levels) = CRATE_LEVELS.try_read() {
+        if let Some((_, level)) = levels.iter().find(|(name, _)| name == crate_name) {
+            return *level;
+        }
+    }
+    default_log_level()
+}
+
+/// Sets the `log` crate's global maximum level to the most verbose of all levels,
+/// such that the `log` macros don't filter out messages that a crate's own level allows.
+fn update_max_level() {
+    let max = CRATE_LEVELS.read().iter()
+        .map(|(_, level)| *level)
+        .fold(default_log_level(), core::cmp::max);
+    log::set_max_level(max);
+}
+
+fn level_filter_from_usize(level: usize) -> LevelFilter {
+    match level {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
 }
@@ -0,0 +1,258 @@
+//! A fixed-size, lock-free ring buffer that holds the most recent log records in memory.
+//!
+//! The ring buffer is statically allocated so that it can be used before the heap exists.
+//! Each slot is guarded by a sequence number in the style of a seqlock:
+//! a writer claims a slot by atomically setting its state to an odd value,
+//! and readers copy a slot's contents and then check that its state didn't change in the meantime.
+//! Thus, neither writers nor readers ever wait for one another;
+//! a record whose slot is still being written by an older writer is simply dropped.
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering, fence};
+use core::time::Duration;
+use log::Level;
+
+
+/// The number of records the ring buffer can hold before the oldest ones are overwritten.
+pub const RING_BUFFER_LEN: usize = 1024;
+/// The maximum length in bytes of a record's message; longer messages are truncated.
+pub const MAX_MESSAGE_LEN: usize = 192;
+/// The maximum length in bytes of a record's module path; longer paths are truncated.
+pub const MAX_MODULE_PATH_LEN: usize = 48;
+
+/// The sequence number that will be given to the next record written to the ring buffer.
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+/// The number of records that couldn't be written into the ring buffer.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+/// The sequence number of the first record that hasn't been cleared.
+static CLEARED_BEFORE: AtomicU64 = AtomicU64::new(0);
+/// The TSC tick frequency, used to convert timestamps into durations. Zero if not yet known.
+static TIMESTAMP_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// The TSC tick value when the logger was initialized, which is the start of all timestamps.
+static BOOT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+static SLOTS: [Slot; RING_BUFFER_LEN] = [EMPTY_SLOT; RING_BUFFER_LEN];
+
+const EMPTY_SLOT: Slot = Slot {
+    state: AtomicU64::new(0),
+    data: UnsafeCell::new(SlotData {
+        level: Level::Trace,
+        ticks: 0,
+        module_path_len: 0,
+        module_path: [0; MAX_MODULE_PATH_LEN],
+        message_len: 0,
+        message: [0; MAX_MESSAGE_LEN],
+    }),
+};
+
+struct Slot {
+    /// `2 * seq + 1` while record `seq` is being written, `2 * seq + 2` once it is complete,
+    /// and `0` if nothing has ever been written to this slot.
+    state: AtomicU64,
+    data: UnsafeCell<SlotData>,
+}
+
+// SAFE: access to the slot data is synchronized by the `state` sequence number.
+unsafe impl Sync for Slot { }
+
+#[derive(Clone, Copy)]
+struct SlotData {
+    level: Level,
+    ticks: u64,
+    module_path_len: usize,
+    module_path: [u8; MAX_MODULE_PATH_LEN],
+    message_len: usize,
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+
+/// A log record that was copied out of the ring buffer.
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    /// The sequence number of this record; every record logged since boot has a unique, increasing number.
+    pub sequence: u64,
+    pub level: Level,
+    /// The value of the TSC when this record was logged.
+    pub ticks: u64,
+    /// The module path (starting with the crate name) of the code that logged this record.
+    pub module_path: String,
+    /// The message, which may have been truncated to `MAX_MESSAGE_LEN` bytes.
+    pub message: String,
+}
+
+impl LogRecord {
+    /// Returns the time since the logger was initialized at which this record was logged,
+    /// or `None` if the TSC frequency isn't yet known.
+    pub fn timestamp(&self) -> Option<Duration> {
+        ticks_to_timestamp(self.ticks)
+    }
+
+    /// Returns the name of the crate that logged this record.
+    pub fn crate_name(&self) -> &str {
+        self.module_path.split("::").next().unwrap_or(&self.module_path)
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.timestamp() {
+            Some(ts) => write!(f, "[{:>5}.{:06}] ", ts.as_secs(), ts.subsec_micros())?,
+            None     => write!(f, "[{:>12}] ", self.ticks)?,
+        }
+        write!(f, "{}{}: {}", crate::level_str(self.level), self.module_path, self.message)
+    }
+}
+
+
+/// Sets the TSC tick frequency used to convert record timestamps into durations.
+pub fn set_timestamp_frequency(ticks_per_sec: u64) {
+    TIMESTAMP_FREQUENCY.store(ticks_per_sec, Ordering::Release);
+}
+
+pub(crate) fn set_boot_ticks(ticks: u64) {
+    BOOT_TICKS.store(ticks, Ordering::Release);
+}
+
+/// Converts the given TSC tick value into a duration since the logger was initialized.
+pub(crate) fn ticks_to_timestamp(ticks: u64) -> Option<Duration> {
+    let freq = TIMESTAMP_FREQUENCY.load(Ordering::Acquire);
+    if freq == 0 {
+        return None;
+    }
+    let elapsed = ticks.saturating_sub(BOOT_TICKS.load(Ordering::Acquire)) as u128;
+    Some(Duration::from_nanos((elapsed * 1_000_000_000 / freq as u128) as u64))
+}
+
+/// Returns the sequence number that the next record will be given.
+pub fn next_sequence() -> u64 {
+    NEXT_SEQUENCE.load(Ordering::Acquire)
+}
+
+/// Returns the number of records that were dropped from the ring buffer
+/// because their slot was still being written by another writer.
+pub fn dropped_records() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Clears the ring buffer, such that records logged before now will no longer be read.
+pub fn clear() {
+    CLEARED_BEFORE.store(next_sequence(), Ordering::Release);
+}
+
+/// Writes a new record into the ring buffer, overwriting the oldest record if it is full.
+pub(crate) fn push(level: Level, ticks: u64, module_path: &str, args: fmt::Arguments) {
+    let seq = NEXT_SEQUENCE.fetch_add(1, Ordering::AcqRel);
+    let slot = &SLOTS[(seq % RING_BUFFER_LEN as u64) as usize];
+
+    // Claim the slot, unless another writer is currently using it or a newer record has already claimed it.
+    let mut current = slot.state.load(Ordering::Acquire);
+    loop {
+        if current & 1 == 1 || current > 2 * seq {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        match slot.state.compare_exchange_weak(current, 2 * seq + 1, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => break,
+            Err(s) => current = s,
+        }
+    }
+
+    // SAFE: this writer exclusively owns the slot data while the slot state is odd.
+    let data = unsafe { &mut *slot.data.get() };
+    data.level = level;
+    data.ticks = ticks;
+    data.module_path_len = copy_truncated(&mut data.module_path, module_path);
+    let mut writer = TruncatingWriter { buf: &mut data.message, len: 0 };
+    let _ = writer.write_fmt(args);
+    data.message_len = writer.len;
+
+    slot.state.store(2 * seq + 2, Ordering::Release);
+}
+
+/// Reads all available records with a sequence number of at least `from`,
+/// returning them along with the sequence number at which to continue reading.
+///
+/// Records that have already been overwritten or cleared are skipped.
+pub fn read_from(from: u64) -> (Vec<LogRecord>, u64) {
+    let end = next_sequence();
+    let oldest = end.saturating_sub(RING_BUFFER_LEN as u64);
+    let start = core::cmp::max(core::cmp::max(from, oldest), CLEARED_BEFORE.load(Ordering::Acquire));
+
+    let mut records = Vec::new();
+    let mut seq = start;
+    while seq < end {
+        match read(seq) {
+            ReadResult::Record(record) => records.push(record),
+            // The writer hasn't finished yet, so stop here and retry this record next time.
+            ReadResult::InProgress => break,
+            ReadResult::Missing => { }
+        }
+        seq += 1;
+    }
+    (records, seq)
+}
+
+enum ReadResult {
+    Record(LogRecord),
+    InProgress,
+    /// The record was dropped or has already been overwritten.
+    Missing,
+}
+
+fn read(seq: u64) -> ReadResult {
+    let slot = &SLOTS[(seq % RING_BUFFER_LEN as u64) as usize];
+    let before = slot.state.load(Ordering::Acquire);
+    if before == 2 * seq + 1 {
+        return ReadResult::InProgress;
+    }
+    if before != 2 * seq + 2 {
+        return ReadResult::Missing;
+    }
+    // SAFE: a concurrent writer may modify the data while we copy it, in which case
+    // the state will have changed and the torn copy is discarded below.
+    let data: SlotData = unsafe { core::ptr::read_volatile(slot.data.get()) };
+    fence(Ordering::Acquire);
+    if slot.state.load(Ordering::Relaxed) != before {
+        return ReadResult::Missing;
+    }
+
+    let module_path = &data.module_path[.. core::cmp::min(data.module_path_len, MAX_MODULE_PATH_LEN)];
+    let message = &data.message[.. core::cmp::min(data.message_len, MAX_MESSAGE_LEN)];
+    ReadResult::Record(LogRecord {
+        sequence: seq,
+        level: data.level,
+        ticks: data.ticks,
+        module_path: String::from_utf8_lossy(module_path).into_owned(),
+        message: String::from_utf8_lossy(message).into_owned(),
+    })
+}
+
+/// Copies as much of `s` into `buf` as fits without splitting a UTF-8 character,
+/// returning the number of bytes copied.
+fn copy_truncated(buf: &mut [u8], s: &str) -> usize {
+    let mut len = core::cmp::min(buf.len(), s.len());
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    len
+}
+
+/// A writer that formats into a fixed-size buffer, silently discarding anything that doesn't fit.
+struct TruncatingWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Write for TruncatingWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.len;
+        self.len += copy_truncated(&mut self.buf[len..], s);
+        Ok(())
+    }
+}
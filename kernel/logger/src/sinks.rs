@@ -0,0 +1,143 @@
+//! Log sinks, the pluggable destinations to which log records are written in addition to the ring buffer.
+
+use alloc::{
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+use core::time::Duration;
+use fs_node::FileRef;
+use irq_safety::RwLockIrqSafe;
+use log::Level;
+
+
+/// A log record that is being logged, as passed to each `LogSink`.
+pub struct LogEntry<'a> {
+    pub level: Level,
+    /// The time since the logger was initialized, if the TSC frequency is known.
+    pub timestamp: Option<Duration>,
+    /// The module path (starting with the crate name) of the code that logged this entry.
+    pub module_path: &'a str,
+    pub file: &'a str,
+    pub line: u32,
+    pub args: fmt::Arguments<'a>,
+}
+
+/// A destination for log messages, such as a terminal or a file.
+///
+/// A sink is invoked from whatever context a log message was logged in, including interrupt handlers,
+/// so it must not block. If a sink can't write an entry right now, it should just skip it.
+pub trait LogSink: Send + Sync {
+    /// The name of this sink, which is used to remove it.
+    fn name(&self) -> &str;
+    /// Writes the given log entry to this sink.
+    fn write(&self, entry: &LogEntry);
+}
+
+/// The additional sinks that receive every log message, after the serial port.
+static SINKS: RwLockIrqSafe<Vec<Arc<dyn LogSink>>> = RwLockIrqSafe::new(Vec::new());
+
+/// Adds a sink that will receive all future log messages.
+///
+/// Returns an error if a sink with the same name already exists.
+pub fn add_sink(sink: Arc<dyn LogSink>) -> Result<(), &'static str> {
+    let mut sinks = SINKS.write();
+    if sinks.iter().any(|s| s.name() == sink.name()) {
+        return Err("a log sink with that name already exists");
+    }
+    sinks.push(sink);
+    Ok(())
+}
+
+/// Removes and returns the sink with the given name.
+pub fn remove_sink(name: &str) -> Option<Arc<dyn LogSink>> {
+    let mut sinks = SINKS.write();
+    let index = sinks.iter().position(|s| s.name() == name)?;
+    Some(sinks.remove(index))
+}
+
+/// Returns the names of all sinks that log messages are currently written to, other than the serial port.
+pub fn sink_names() -> Vec<String> {
+    SINKS.read().iter().map(|s| String::from(s.name())).collect()
+}
+
+pub(crate) fn write_to_sinks(entry: &LogEntry) {
+    // Skip the sinks rather than deadlock if a sink is being added or removed on this core,
+    // e.g., when the heap allocator logs something while `add_sink()` grows the list.
+    if let Some(sinks) = SINKS.try_read() {
+        for sink in sinks.iter() {
+            sink.write(entry);
+        }
+    }
+}
+
+/// Formats a log entry as plain text, without any terminal colors.
+fn write_plain<W: Write>(w: &mut W, entry: &LogEntry) -> fmt::Result {
+    if let Some(ts) = entry.timestamp {
+        write!(w, "[{:>5}.{:06}] ", ts.as_secs(), ts.subsec_micros())?;
+    }
+    write!(w, "{}{}:{}: {}", crate::level_str(entry.level), entry.file, entry.line, entry.args)
+}
+
+
+/// A sink that passes each log message to a function, e.g., one that prints it to a terminal window.
+pub struct TerminalSink {
+    name: String,
+    func: crate::LogOutputFunc,
+}
+
+impl TerminalSink {
+    pub fn new(name: String, func: crate::LogOutputFunc) -> TerminalSink {
+        TerminalSink { name, func }
+    }
+}
+
+impl LogSink for TerminalSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        // Terminals don't support ANSI color escape sequences, so none are included.
+        let mut line = String::new();
+        if write_plain(&mut line, entry).is_ok() {
+            (self.func)(format_args!("{}", line));
+        }
+    }
+}
+
+
+/// A sink that appends each log message as a line of text to a file.
+pub struct FileSink {
+    name: String,
+    file: FileRef,
+}
+
+impl FileSink {
+    /// Creates a sink that appends to the end of the given `file`, named after its absolute path.
+    pub fn new(file: FileRef) -> FileSink {
+        let name = file.lock().get_absolute_path();
+        FileSink { name, file }
+    }
+}
+
+impl LogSink for FileSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        // If the file is locked, this message was likely logged by the filesystem itself while writing to it.
+        let mut file = match self.file.try_lock() {
+            Some(f) => f,
+            None => return,
+        };
+        let mut line = String::new();
+        if write_plain(&mut line, entry).is_ok() {
+            line.push('\n');
+            let end = file.size();
+            let _ = file.write(line.as_bytes(), end);
+        }
+    }
+}
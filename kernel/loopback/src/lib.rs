@@ -0,0 +1,113 @@
+//! The loopback network interface, `lo`, which delivers every packet sent to its addresses back to this host,
+//! so that a client and a server on the same machine can talk to each other.
+//!
+//! The interface has the address `127.0.0.1/8`, so packets to any address in `127.0.0.0/8` are looped back.
+//! It is added to `network_manager::NETWORK_INTERFACES` by [`init()`](fn.init.html),
+//! after the interfaces of the network cards, so that the first interface is still a real one.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+extern crate spin;
+extern crate smoltcp;
+extern crate network_manager;
+
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use spin::Once;
+use smoltcp::{
+    socket::SocketSet,
+    time::Instant,
+    phy::Loopback,
+    wire::{EthernetAddress, IpAddress, IpCidr},
+    iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
+};
+use network_manager::{NetworkInterface, capture::CaptureDevice};
+
+
+/// The name of the loopback interface.
+pub const LOOPBACK_NAME: &'static str = "lo";
+
+/// Whether the loopback interface has been added to the list of network interfaces.
+static INITIALIZED: Once<()> = Once::new();
+
+
+/// Adds the loopback interface to `network_manager::NETWORK_INTERFACES`, if it hasn't been added already.
+pub fn init() -> Result<(), &'static str> {
+    INITIALIZED.call_once(|| network_manager::add_to_network_interfaces(LoopbackInterface::new()));
+    Ok(())
+}
+
+
+/// The loopback interface, which sends packets to a queue that it receives them from when it's next polled.
+pub struct LoopbackInterface {
+    iface: EthernetInterface<'static, 'static, 'static, CaptureDevice<Loopback>>,
+    up: bool,
+}
+
+impl LoopbackInterface {
+    /// Creates a loopback interface with the address `127.0.0.1/8`, which is up.
+    pub fn new() -> LoopbackInterface {
+        let device = CaptureDevice::new(Loopback::new(), LOOPBACK_NAME.to_string());
+        let iface = EthernetInterfaceBuilder::new(device)
+            .ethernet_addr(EthernetAddress::default())
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)])
+            .routes(Routes::new(BTreeMap::new()))
+            .finalize();
+        LoopbackInterface { iface, up: true }
+    }
+}
+
+impl NetworkInterface for LoopbackInterface {
+    fn name(&self) -> &str {
+        LOOPBACK_NAME
+    }
+
+    fn is_up(&self) -> bool {
+        self.up
+    }
+
+    fn set_up(&mut self, up: bool) {
+        self.up = up;
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+
+    fn ethernet_addr(&self) -> EthernetAddress {
+        self.iface.ethernet_addr()
+    }
+
+    fn set_ethernet_addr(&mut self, addr: EthernetAddress) {
+        self.iface.set_ethernet_addr(addr)
+    }
+
+    fn poll(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> smoltcp::Result<bool> {
+        if !self.up {
+            return Ok(false);
+        }
+        self.iface.poll(sockets, timestamp)
+    }
+
+    fn ip_addrs(&self) -> &[IpCidr] {
+        self.iface.ip_addrs()
+    }
+
+    fn set_ip_addrs(&mut self, addrs: &[IpCidr]) {
+        self.iface.update_ip_addrs(|ip_addrs| *ip_addrs = addrs.to_vec().into());
+    }
+
+    fn has_ip_addr(&self, addr: IpAddress) -> bool {
+        self.iface.has_ip_addr(addr)
+    }
+
+    fn routes(&self) -> &Routes<'static> {
+        self.iface.routes()
+    }
+
+    fn routes_mut(&mut self) -> &mut Routes<'static> {
+        self.iface.routes_mut()
+    }
+}
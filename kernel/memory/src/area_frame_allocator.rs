@@ -8,6 +8,7 @@
 // except according to those terms.
 
 use super::{Frame, FrameAllocator, FrameRange, PhysicalAddress, PhysicalMemoryArea};
+use super::numa::{NodeId, NumaMemoryRange};
 use alloc::vec::Vec;
 use kernel_config::memory::PAGE_SIZE;
 
@@ -34,6 +35,14 @@ impl<T: Clone> VectorArray<T> {
         }
     }
 
+    /// Returns the valid elements of this `VectorArray` as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        match *self {
+            VectorArray::Array((count, ref arr)) => &arr[..count],
+            VectorArray::Vector(ref v) => &v[..],
+        }
+    }
+
     // pub fn iter(&self) -> ::core::slice::Iter<T> {
     //     match self {
     //         &VectorArray::Array((_count, arr)) => arr.iter(),
@@ -51,11 +60,32 @@ impl<T: Clone> VectorArray<T> {
 /// already in use.
 ///
 /// `kernel_end` and `multiboot_end` are _inclusive_ bounds.
+/// 
+/// Once the NUMA topology is known, frames can also be allocated from a specific NUMA node
+/// with [`allocate_frames_on_node()`](#method.allocate_frames_on_node).
 pub struct AreaFrameAllocator {
     next_free_frame: Frame,
     current_area: Option<PhysicalMemoryArea>,
     available: VectorArray<PhysicalMemoryArea>,
     occupied: VectorArray<PhysicalMemoryArea>,
+    numa_regions: Vec<NumaRegion>,
+}
+
+/// A range of physical memory that belongs to one NUMA node.
+/// 
+/// Node-local frames are allocated downwards from the end of the region,
+/// while the regular allocation path moves upwards through all of memory and skips the node-local frames,
+/// so the two never hand out the same frame.
+#[derive(Clone, Copy, Debug)]
+struct NumaRegion {
+    node: NodeId,
+    /// The first frame in this region.
+    start: Frame,
+    /// The last frame in this region (inclusive).
+    end: Frame,
+    /// The frame number below which no node-local frames have been allocated yet,
+    /// i.e., all frames from this number up to `end` were allocated from this node.
+    lowest_allocated: usize,
 }
 
 impl AreaFrameAllocator {
@@ -70,6 +100,7 @@ impl AreaFrameAllocator {
             current_area: None,
             available: VectorArray::Array((avail_len, available)),
             occupied: VectorArray::Array((occ_len, occupied)),
+            numa_regions: Vec::new(),
         };
         allocator.select_next_area();
         Ok(allocator)
@@ -109,6 +140,145 @@ impl AreaFrameAllocator {
         Ok(())
     }
 
+    /// Allocates `num_frames` contiguous frames such that the first frame is aligned to a multiple of `alignment_in_frames`.
+    /// 
+    /// This is needed for frames that will be mapped as huge pages, 
+    /// e.g., with an alignment of `PageSize::Huge2MiB.size_in_pages()`.
+    /// Like `allocate_frames()`, any frames that are skipped over to satisfy the alignment are wasted.
+    pub fn allocate_frames_aligned(&mut self, num_frames: usize, alignment_in_frames: usize) -> Option<FrameRange> {
+        if alignment_in_frames <= 1 { 
+            return self.allocate_frames(num_frames);
+        }
+
+        loop {
+            let misalignment = self.next_free_frame.number % alignment_in_frames;
+            if misalignment != 0 {
+                self.next_free_frame += alignment_in_frames - misalignment;
+            }
+            let frames = self.allocate_frames(num_frames)?;
+            if frames.start().number % alignment_in_frames == 0 {
+                return Some(frames);
+            }
+            // Here, the allocator moved on to a new area or skipped an occupied area, so try aligning again from there.
+        }
+    }
+
+    /// Returns the given `frames` to this allocator, which is only possible if they are the frames that were allocated most recently,
+    /// because this allocator doesn't keep track of individual free frames. 
+    /// 
+    /// Returns `true` if the frames can be allocated again, or `false` if they were not the most recent allocation,
+    /// in which case they remain allocated.
+    pub fn deallocate_most_recent_frames(&mut self, frames: FrameRange) -> bool {
+        if *frames.end() + 1 == self.next_free_frame {
+            self.next_free_frame = *frames.start();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Assigns each of the given ranges of physical memory to its NUMA node,
+    /// which enables node-local allocation with [`allocate_frames_on_node()`](#method.allocate_frames_on_node).
+    /// 
+    /// This requires the heap to be initialized.
+    pub fn set_numa_memory_ranges(&mut self, ranges: &[NumaMemoryRange]) {
+        self.numa_regions = ranges.iter()
+            .filter(|range| range.size_in_bytes != 0)
+            .map(|range| {
+                let end = Frame::containing_address(range.start + (range.size_in_bytes - 1));
+                NumaRegion {
+                    node: range.node,
+                    start: Frame::containing_address(range.start),
+                    end,
+                    lowest_allocated: end.number + 1,
+                }
+            })
+            .collect();
+    }
+
+    /// Returns the NUMA node that the given `frame` belongs to, if known.
+    pub fn node_of_frame(&self, frame: Frame) -> Option<NodeId> {
+        self.numa_regions.iter()
+            .find(|region| frame >= region.start && frame <= region.end)
+            .map(|region| region.node)
+    }
+
+    /// Allocates `num_frames` contiguous frames from the memory that belongs to the given NUMA `node`.
+    /// 
+    /// Returns `None` if the node has no memory left that hasn't already been passed over
+    /// by the regular allocation path, or if the NUMA memory ranges have not been set.
+    pub fn allocate_frames_on_node(&mut self, num_frames: usize, node: NodeId) -> Option<FrameRange> {
+        if num_frames == 0 { return None; }
+        for i in 0..self.numa_regions.len() {
+            if self.numa_regions[i].node != node {
+                continue;
+            }
+            if let Some(frames) = self.allocate_from_numa_region(i, num_frames) {
+                return Some(frames);
+            }
+        }
+        None
+    }
+
+    /// Allocates `num_frames` contiguous frames from the top of the free part of the NUMA region at `region_index`.
+    fn allocate_from_numa_region(&mut self, region_index: usize, num_frames: usize) -> Option<FrameRange> {
+        let region = self.numa_regions[region_index];
+        // Frames below `next_free_frame` have already been handed out by the regular allocation path.
+        let floor = core::cmp::max(region.start.number, self.next_free_frame.number);
+        let mut top = region.lowest_allocated;
+        loop {
+            if top < floor + num_frames {
+                return None;
+            }
+            let first = Frame { number: top - num_frames };
+            let last = Frame { number: top - 1 };
+            match self.lowest_conflicting_frame(first, last) {
+                // Try again below the frames that aren't usable.
+                Some(conflict) => top = conflict,
+                None => {
+                    self.numa_regions[region_index].lowest_allocated = first.number;
+                    return Some(FrameRange::new(first, last));
+                }
+            }
+        }
+    }
+
+    /// Checks whether all frames from `first` to `last` (inclusive) are free to be allocated, 
+    /// i.e., they lie within a single available memory area and don't overlap any occupied memory area.
+    /// 
+    /// If not, this returns the frame number below which the next attempt should be made.
+    fn lowest_conflicting_frame(&self, first: Frame, last: Frame) -> Option<usize> {
+        let containing_area = self.available.as_slice().iter().find(|area| {
+            area.typ == 1 && area.size_in_bytes != 0
+                && Frame::containing_address(area.base_addr) <= last
+                && Frame::containing_address(area.base_addr + (area.size_in_bytes - 1)) >= last
+        });
+        match containing_area {
+            Some(area) => {
+                let area_start = Frame::containing_address(area.base_addr);
+                if area_start > first {
+                    return Some(area_start.number);
+                }
+            }
+            None => {
+                // move down to the end of the closest available area below `last`, if any
+                let below = self.available.as_slice().iter()
+                    .filter(|area| area.typ == 1 && area.size_in_bytes != 0)
+                    .map(|area| Frame::containing_address(area.base_addr + (area.size_in_bytes - 1)))
+                    .filter(|&area_end| area_end < last)
+                    .max();
+                return Some(below.map_or(0, |area_end| area_end.number + 1));
+            }
+        }
+
+        // Occupied areas are bounded the same way as in `skip_occupied_frames()`.
+        self.occupied.as_slice().iter()
+            .map(|area| (Frame::containing_address(area.base_addr), Frame::containing_address(area.base_addr + area.size_in_bytes)))
+            .filter(|&(start, end)| start <= last && end >= first)
+            .map(|(start, _end)| start.number)
+            .min()
+    }
+
     fn select_next_area(&mut self) {
         self.current_area = match self.available {
             VectorArray::Array((len, ref arr)) => {
@@ -171,6 +341,18 @@ impl AreaFrameAllocator {
             }
         };
         
+        // Frames that were allocated from a specific NUMA node are also occupied.
+        if !rerun {
+            for region in self.numa_regions.iter() {
+                if self.next_free_frame.number >= region.lowest_allocated && self.next_free_frame <= region.end {
+                    self.next_free_frame = region.end + 1;
+                    trace!("AreaFrameAllocator: skipping NUMA node {} frames to next frame {:?}", region.node, self.next_free_frame);
+                    rerun = true;
+                    break;
+                }
+            }
+        }
+        
         // If we actually skipped an occupied area, then we need to rerun this again,
         // to ensure that we didn't skip into another occupied area.
         if rerun {
@@ -0,0 +1,292 @@
+//! Buffers for Direct Memory Access (DMA) that devices can safely read from and write to.
+//!
+//! A [`DmaBuffer`](struct.DmaBuffer.html) is always physically contiguous and mapped as uncacheable,
+//! such that drivers can hand its physical address directly to a device
+//! while still accessing its contents as a regular slice of bytes.
+//!
+//! Some devices can only address a limited range of physical memory, e.g., only the first 4 GiB for 32-bit DMA.
+//! For those devices, a driver can use a [`BounceBuffer`](struct.BounceBuffer.html) to transfer data
+//! that resides in arbitrary memory through a `DmaBuffer` that the device is able to reach.
+
+use super::{
+    MappedPages, PhysicalAddress, VirtualAddress, EntryFlags, Mapper, FrameAllocator,
+    allocate_pages_by_bytes, get_kernel_mmi_ref, get_frame_allocator_ref,
+};
+use alloc::vec::Vec;
+use kernel_config::memory::PAGE_SIZE;
+use core::ops::DerefMut;
+
+
+/// Returns the flags used for all DMA buffer mappings.
+/// DMA memory must not be cached, otherwise the CPU and the device may see different contents.
+pub fn dma_flags() -> EntryFlags {
+    EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE
+}
+
+
+/// The range of physical addresses that a device is able to access via DMA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DmaAddressLimit {
+    /// The device can access all of physical memory.
+    Unlimited,
+    /// The device can only access physical addresses below 4 GiB, i.e., it uses 32-bit DMA.
+    Bits32,
+    /// The device can only access physical addresses below the given (exclusive) address.
+    Below(PhysicalAddress),
+}
+
+impl DmaAddressLimit {
+    /// Returns the exclusive upper bound of physical addresses that the device can access,
+    /// or `None` if there is no limit.
+    pub fn max_address(&self) -> Option<usize> {
+        match *self {
+            DmaAddressLimit::Unlimited => None,
+            DmaAddressLimit::Bits32    => Some(1 << 32),
+            DmaAddressLimit::Below(addr) => Some(addr.value()),
+        }
+    }
+
+    /// Returns `true` if the device can access every byte
+    /// in the `len` bytes of physical memory starting at `phys_addr`.
+    pub fn contains(&self, phys_addr: PhysicalAddress, len: usize) -> bool {
+        match self.max_address() {
+            Some(max) => phys_addr.value().checked_add(len).map_or(false, |end| end <= max),
+            None => true,
+        }
+    }
+}
+
+
+/// A physically-contiguous piece of a DMA buffer,
+/// which can be used as one entry of a device's scatter-gather list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmaSegment {
+    /// The starting physical address of this segment.
+    pub phys_addr: PhysicalAddress,
+    /// The length in bytes of this segment.
+    pub len: usize,
+}
+
+
+/// A buffer that is physically contiguous and mapped as uncacheable, making it suitable for DMA.
+///
+/// The underlying memory is unmapped when this buffer is dropped.
+pub struct DmaBuffer {
+    mp: MappedPages,
+    phys_addr: PhysicalAddress,
+    len: usize,
+    limit: DmaAddressLimit,
+}
+
+impl DmaBuffer {
+    /// Allocates a new zeroed `DmaBuffer` of `len` bytes that may reside anywhere in physical memory.
+    pub fn new(len: usize) -> Result<DmaBuffer, &'static str> {
+        DmaBuffer::new_with_limit(len, DmaAddressLimit::Unlimited)
+    }
+
+    /// Allocates a new zeroed `DmaBuffer` of `len` bytes that resides entirely within the given `limit`,
+    /// e.g., below 4 GiB for a device that only supports 32-bit DMA.
+    ///
+    /// Returns an error if there is no more free physical memory within the `limit`.
+    ///
+    /// # Locking / Deadlock
+    /// This function acquires the lock on the frame allocator and the kernel's `MemoryManagementInfo` instance.
+    pub fn new_with_limit(len: usize, limit: DmaAddressLimit) -> Result<DmaBuffer, &'static str> {
+        if len == 0 {
+            return Err("DmaBuffer::new(): cannot create a DMA buffer of length 0");
+        }
+        let pages = allocate_pages_by_bytes(len).ok_or("DmaBuffer::new(): couldn't allocate pages")?;
+
+        let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("DmaBuffer::new(): KERNEL_MMI was not yet initialized!")?;
+        let mut kernel_mmi = kernel_mmi_ref.lock();
+        let mut frame_allocator = get_frame_allocator_ref().ok_or("DmaBuffer::new(): couldn't get frame allocator")?.lock();
+
+        let frames = frame_allocator.allocate_frames(pages.size_in_pages())
+            .ok_or("DmaBuffer::new(): couldn't allocate contiguous frames")?;
+        let phys_addr = frames.start_address();
+        // The frame allocator hands out frames in increasing order, so once it has passed the limit
+        // we cannot obtain any more frames below it.
+        if !limit.contains(phys_addr, frames.size_in_frames() * PAGE_SIZE) {
+            if !frame_allocator.deallocate_most_recent_frames(frames) {
+                warn!("DmaBuffer::new(): couldn't free the frames above the DMA address limit, they are leaked");
+            }
+            return Err("DmaBuffer::new(): no free physical memory is available within the DMA address limit");
+        }
+        let mut mp = kernel_mmi.page_table.map_allocated_pages_to(pages, frames, dma_flags(), frame_allocator.deref_mut())?;
+        drop(frame_allocator);
+        drop(kernel_mmi);
+
+        // The newly-allocated frames may contain stale data, which must not be leaked to the device.
+        let size_in_bytes = mp.size_in_bytes();
+        mp.as_slice_mut::<u8>(0, size_in_bytes)?.iter_mut().for_each(|b| *b = 0);
+
+        Ok(DmaBuffer { mp, phys_addr, len, limit })
+    }
+
+    /// Returns the length in bytes of this buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the starting physical address of this buffer, which can be given to a device.
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr
+    }
+
+    /// Returns the starting virtual address of this buffer.
+    pub fn virt_addr(&self) -> VirtualAddress {
+        self.mp.start_address()
+    }
+
+    /// Returns the address limit that this buffer was allocated within.
+    pub fn limit(&self) -> DmaAddressLimit {
+        self.limit
+    }
+
+    /// Returns the list of physically-contiguous segments that make up this buffer,
+    /// which can be used to fill in a device's scatter-gather list.
+    ///
+    /// Since every `DmaBuffer` is physically contiguous, this currently contains exactly one segment.
+    pub fn segments(&self) -> Vec<DmaSegment> {
+        let mut segments = Vec::with_capacity(1);
+        segments.push(DmaSegment { phys_addr: self.phys_addr, len: self.len });
+        segments
+    }
+
+    /// Returns the contents of this buffer as a slice of bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        // This cannot fail because the mapping is at least `len` bytes long.
+        self.mp.as_slice(0, self.len).unwrap_or(&[])
+    }
+
+    /// Returns the contents of this buffer as a mutable slice of bytes.
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        // This cannot fail because the mapping is writable and at least `len` bytes long.
+        self.mp.as_slice_mut(0, len).unwrap_or(&mut [])
+    }
+
+    /// Returns a reference to the underlying `MappedPages`,
+    /// e.g., for interpreting the buffer as a struct with `MappedPages::as_type()`.
+    pub fn mapped_pages(&self) -> &MappedPages {
+        &self.mp
+    }
+
+    /// Returns a mutable reference to the underlying `MappedPages`.
+    pub fn mapped_pages_mut(&mut self) -> &mut MappedPages {
+        &mut self.mp
+    }
+}
+
+
+/// Returns the list of physically-contiguous segments that back the given `buffer`,
+/// or `None` if any part of it is not currently mapped.
+///
+/// This walks the current page table, so `buffer` must reside in the current address space.
+pub fn physical_segments(buffer: &[u8]) -> Option<Vec<DmaSegment>> {
+    let mapper = Mapper::from_current();
+    let mut segments: Vec<DmaSegment> = Vec::new();
+    let start = buffer.as_ptr() as usize;
+    let end = start + buffer.len();
+    let mut vaddr = start;
+    while vaddr < end {
+        let chunk_len = core::cmp::min(PAGE_SIZE - (vaddr % PAGE_SIZE), end - vaddr);
+        let phys_addr = mapper.translate(VirtualAddress::new(vaddr).ok()?)?;
+        match segments.last_mut() {
+            Some(last) if last.phys_addr + last.len == phys_addr => last.len += chunk_len,
+            _ => segments.push(DmaSegment { phys_addr, len: chunk_len }),
+        }
+        vaddr += chunk_len;
+    }
+    Some(segments)
+}
+
+/// Returns `true` if a device with the given `limit` cannot directly access all of `buffer`,
+/// meaning that a [`BounceBuffer`](struct.BounceBuffer.html) must be used to transfer it.
+///
+/// If `need_contiguous` is `true`, the device cannot use a scatter-gather list,
+/// so `buffer` must also be physically contiguous to be accessed directly.
+pub fn needs_bounce_buffer(buffer: &[u8], limit: DmaAddressLimit, need_contiguous: bool) -> bool {
+    match physical_segments(buffer) {
+        Some(segments) => {
+            (need_contiguous && segments.len() > 1)
+                || segments.iter().any(|seg| !limit.contains(seg.phys_addr, seg.len))
+        }
+        None => true,
+    }
+}
+
+
+/// The direction of a DMA transfer through a [`BounceBuffer`](struct.BounceBuffer.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads data from memory, e.g., for a disk write or a packet transmission.
+    ToDevice,
+    /// The device writes data into memory, e.g., for a disk read or a packet reception.
+    FromDevice,
+    /// The device both reads and writes the memory.
+    Bidirectional,
+}
+
+/// A `DmaBuffer` within a device's address limit that stands in for memory the device cannot access directly.
+///
+/// For transfers to the device, the original data is copied into the bounce buffer when it is created.
+/// For transfers from the device, the received data is copied out of the bounce buffer with
+/// [`copy_to()`](#method.copy_to) once the device has finished.
+pub struct BounceBuffer {
+    buffer: DmaBuffer,
+    direction: DmaDirection,
+}
+
+impl BounceBuffer {
+    /// Creates a new bounce buffer for transferring `data` to a device with the given `limit`.
+    pub fn to_device(data: &[u8], limit: DmaAddressLimit) -> Result<BounceBuffer, &'static str> {
+        let mut buffer = DmaBuffer::new_with_limit(data.len(), limit)?;
+        buffer.as_slice_mut().copy_from_slice(data);
+        Ok(BounceBuffer { buffer, direction: DmaDirection::ToDevice })
+    }
+
+    /// Creates a new bounce buffer for receiving `len` bytes from a device with the given `limit`.
+    pub fn from_device(len: usize, limit: DmaAddressLimit) -> Result<BounceBuffer, &'static str> {
+        let buffer = DmaBuffer::new_with_limit(len, limit)?;
+        Ok(BounceBuffer { buffer, direction: DmaDirection::FromDevice })
+    }
+
+    /// Creates a new bounce buffer for a transfer in both directions,
+    /// which is initialized with the contents of `data`.
+    pub fn bidirectional(data: &[u8], limit: DmaAddressLimit) -> Result<BounceBuffer, &'static str> {
+        let mut bounce = BounceBuffer::to_device(data, limit)?;
+        bounce.direction = DmaDirection::Bidirectional;
+        Ok(bounce)
+    }
+
+    /// Returns the direction of the transfer that this bounce buffer was created for.
+    pub fn direction(&self) -> DmaDirection {
+        self.direction
+    }
+
+    /// Returns the `DmaBuffer` that the device should actually access.
+    pub fn dma_buffer(&self) -> &DmaBuffer {
+        &self.buffer
+    }
+
+    /// Returns the starting physical address of the bounce buffer, which can be given to the device.
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.buffer.phys_addr()
+    }
+
+    /// Copies the data that the device wrote into this bounce buffer into `dest`.
+    ///
+    /// Returns an error if this bounce buffer is only used for transfers to the device,
+    /// or if `dest` is longer than this bounce buffer.
+    pub fn copy_to(&self, dest: &mut [u8]) -> Result<(), &'static str> {
+        if self.direction == DmaDirection::ToDevice {
+            return Err("BounceBuffer::copy_to(): the device does not write into this bounce buffer");
+        }
+        if dest.len() > self.buffer.len() {
+            return Err("BounceBuffer::copy_to(): destination is larger than the bounce buffer");
+        }
+        dest.copy_from_slice(&self.buffer.as_slice()[..dest.len()]);
+        Ok(())
+    }
+}
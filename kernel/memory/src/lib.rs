@@ -25,6 +25,9 @@ extern crate zerocopy;
 
 
 mod area_frame_allocator;
+pub mod dma;
+pub mod numa;
+pub mod reclaim;
 #[cfg(not(mapper_spillful))]
 mod paging;
 
@@ -49,8 +52,9 @@ use spin::Once;
 use irq_safety::MutexIrqSafe;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
-use kernel_config::memory::KERNEL_OFFSET;
+use kernel_config::memory::{KERNEL_OFFSET, PAGE_SIZE, CRASH_DUMP_SIZE_IN_BYTES};
 use core::ops::DerefMut;
+use numa::{NumaTopology, NodeId};
 
 /// The memory management info and address space of the kernel
 static KERNEL_MMI: Once<MmiRef> = Once::new();
@@ -90,6 +94,74 @@ pub fn allocate_frames(num_frames: usize) -> Option<FrameRange> {
     FRAME_ALLOCATOR.try().and_then(|fa| fa.lock().allocate_frames(num_frames))
 }
 
+/// Convenience method for allocating several contiguous Frames, 
+/// in which the first Frame is aligned to a multiple of `alignment_in_frames`.
+pub fn allocate_frames_aligned(num_frames: usize, alignment_in_frames: usize) -> Option<FrameRange> {
+    FRAME_ALLOCATOR.try().and_then(|fa| fa.lock().allocate_frames_aligned(num_frames, alignment_in_frames))
+}
+
+
+
+/// The NUMA topology of the system, if it has been discovered.
+static NUMA_TOPOLOGY: Once<NumaTopology> = Once::new();
+
+/// The function that returns the APIC ID of the current CPU,
+/// which is used to determine the local NUMA node of the requesting CPU.
+static CURRENT_CPU_FUNC: Once<fn() -> u32> = Once::new();
+
+/// Sets the system's NUMA topology, which can only be done once.
+/// This also assigns the memory of each NUMA node to that node within the frame allocator,
+/// enabling node-local frame allocation.
+/// 
+/// The given `current_cpu_func` must return the APIC ID of the CPU that it is invoked on,
+/// which is used by [`allocate_frames_local()`](fn.allocate_frames_local.html).
+pub fn set_numa_topology(topology: NumaTopology, current_cpu_func: fn() -> u32) -> Result<(), &'static str> {
+    if NUMA_TOPOLOGY.try().is_some() {
+        return Err("memory::set_numa_topology(): the NUMA topology was already set");
+    }
+    let frame_allocator = get_frame_allocator_ref().ok_or("memory::set_numa_topology(): couldn't get frame allocator")?;
+    frame_allocator.lock().set_numa_memory_ranges(topology.memory_ranges());
+    CURRENT_CPU_FUNC.call_once(|| current_cpu_func);
+    NUMA_TOPOLOGY.call_once(|| topology);
+    Ok(())
+}
+
+/// Returns the system's NUMA topology, 
+/// or `None` if it hasn't been discovered, e.g., because the system has no ACPI SRAT table.
+pub fn numa_topology() -> Option<&'static NumaTopology> {
+    NUMA_TOPOLOGY.try()
+}
+
+/// Returns the NUMA node of the CPU that this is invoked on, if known.
+pub fn current_numa_node() -> Option<NodeId> {
+    let apic_id = CURRENT_CPU_FUNC.try()?();
+    numa_topology()?.node_of_processor(apic_id)
+}
+
+/// Convenience method for allocating several contiguous Frames from the memory of the given NUMA `node`.
+/// Unlike [`allocate_frames_local()`](fn.allocate_frames_local.html), this does not fall back to other nodes.
+pub fn allocate_frames_on_node(num_frames: usize, node: NodeId) -> Option<FrameRange> {
+    FRAME_ALLOCATOR.try().and_then(|fa| fa.lock().allocate_frames_on_node(num_frames, node))
+}
+
+/// Convenience method for allocating several contiguous Frames, 
+/// preferring memory that is local to the NUMA node of the current CPU.
+/// 
+/// If the local node has no free memory, the other nodes are tried in order of their distance from the local node.
+/// If none of them have free memory, or the NUMA topology is unknown, 
+/// then this falls back to [`allocate_frames()`](fn.allocate_frames.html).
+pub fn allocate_frames_local(num_frames: usize) -> Option<FrameRange> {
+    let fa = FRAME_ALLOCATOR.try()?;
+    if let (Some(topology), Some(local_node)) = (numa_topology(), current_numa_node()) {
+        for node in topology.nodes_by_distance(local_node) {
+            if let Some(frames) = fa.lock().allocate_frames_on_node(num_frames, node) {
+                return Some(frames);
+            }
+        }
+    }
+    fa.lock().allocate_frames(num_frames)
+}
+
 
 /// This holds all the information for a `Task`'s memory mappings and address space
 /// (this is basically the equivalent of Linux's mm_struct)
@@ -109,17 +181,29 @@ pub struct MemoryManagementInfo {
 /// Returns a tuple containing the new `MappedPages` and the starting PhysicalAddress of the first frame,
 /// which is a convenient way to get the physical address without walking the page tables.
 /// 
+/// Mappings of at least 2 MiB are aligned in both virtual and physical memory, 
+/// such that they are mapped using huge pages where possible.
+/// 
+/// If there aren't enough free frames, memory is reclaimed (see the [`reclaim`](reclaim/index.html) module) and the mapping is retried.
+/// 
 /// # Locking / Deadlock
 /// Currently, this function acquires the lock on the frame allocator and the kernel's `MemoryManagementInfo` instance.
 /// Thus, the caller should ensure that the locks on those two variables are not held when invoking this function.
 pub fn create_contiguous_mapping(size_in_bytes: usize, flags: EntryFlags) -> Result<(MappedPages, PhysicalAddress), &'static str> {
-    let allocated_pages = allocate_pages_by_bytes(size_in_bytes).ok_or("memory::create_contiguous_mapping(): couldn't allocate pages!")?;
+    reclaim::with_reclaim(size_in_bytes, || try_create_contiguous_mapping(size_in_bytes, flags))
+}
+
+fn try_create_contiguous_mapping(size_in_bytes: usize, flags: EntryFlags) -> Result<(MappedPages, PhysicalAddress), &'static str> {
+    let num_pages = (size_in_bytes + PAGE_SIZE - 1) / PAGE_SIZE; // round up
+    let huge_page_alignment = PageSize::Huge2MiB.size_in_pages();
+    let alignment = if num_pages >= huge_page_alignment { huge_page_alignment } else { 1 };
+    let allocated_pages = allocate_pages_aligned(num_pages, alignment).ok_or("memory::create_contiguous_mapping(): couldn't allocate pages!")?;
 
     let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("create_contiguous_mapping(): KERNEL_MMI was not yet initialized!")?;
     let mut kernel_mmi = kernel_mmi_ref.lock();
 
     let mut frame_allocator = get_frame_allocator_ref().ok_or("create_contiguous_mapping(): couldnt get frame allocator")?.lock();
-    let frames = frame_allocator.allocate_frames(allocated_pages.size_in_pages())
+    let frames = frame_allocator.allocate_frames_aligned(allocated_pages.size_in_pages(), alignment)
         .ok_or("create_contiguous_mapping(): couldnt allocate a new frame")?;
     let starting_phys_addr = frames.start_address();
     let mp = kernel_mmi.page_table.map_allocated_pages_to(allocated_pages, frames, flags, &mut *frame_allocator)?;
@@ -132,10 +216,16 @@ pub fn create_contiguous_mapping(size_in_bytes: usize, flags: EntryFlags) -> Res
 /// then see [`create_contiguous_mapping()`](fn.create_contiguous_mapping.html).
 /// Returns the new `MappedPages.` 
 /// 
+/// If there aren't enough free frames, memory is reclaimed (see the [`reclaim`](reclaim/index.html) module) and the mapping is retried.
+/// 
 /// # Locking / Deadlock
 /// Currently, this function acquires the lock on the `FRAME_ALLOCATOR` and the kernel's `MemoryManagementInfo` instance.
 /// Thus, the caller should ensure that the locks on those two variables are not held when invoking this function.
 pub fn create_mapping(size_in_bytes: usize, flags: EntryFlags) -> Result<MappedPages, &'static str> {
+    reclaim::with_reclaim(size_in_bytes, || try_create_mapping(size_in_bytes, flags))
+}
+
+fn try_create_mapping(size_in_bytes: usize, flags: EntryFlags) -> Result<MappedPages, &'static str> {
     let allocated_pages = allocate_pages_by_bytes(size_in_bytes).ok_or("memory::create_mapping(): couldn't allocate pages!")?;
 
     let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("create_contiguous_mapping(): KERNEL_MMI was not yet initialized!")?;
@@ -149,6 +239,127 @@ pub fn create_mapping(size_in_bytes: usize, flags: EntryFlags) -> Result<MappedP
 }
 
 
+/// A convenience function that creates a new lazily-backed memory mapping, 
+/// in which no frames are allocated until each page is first accessed.
+/// See [`Mapper::map_allocated_pages_lazily()`](struct.Mapper.html#method.map_allocated_pages_lazily).
+/// 
+/// This is useful for large regions that may be only sparsely used, e.g., heaps or big buffers.
+/// 
+/// # Locking / Deadlock
+/// Currently, this function acquires the lock on the `FRAME_ALLOCATOR` and the kernel's `MemoryManagementInfo` instance.
+/// Thus, the caller should ensure that the locks on those two variables are not held when invoking this function.
+pub fn create_lazy_mapping(size_in_bytes: usize, flags: EntryFlags) -> Result<MappedPages, &'static str> {
+    let allocated_pages = allocate_pages_by_bytes(size_in_bytes).ok_or("memory::create_lazy_mapping(): couldn't allocate pages!")?;
+
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("create_lazy_mapping(): KERNEL_MMI was not yet initialized!")?;
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+
+    let mut frame_allocator = FRAME_ALLOCATOR.try()
+        .ok_or("create_lazy_mapping(): couldnt get FRAME_ALLOCATOR")?
+        .lock();
+    
+    kernel_mmi.page_table.map_allocated_pages_lazily(allocated_pages, flags, frame_allocator.deref_mut())
+}
+
+
+/// Handles a page fault on the given virtual address, if it was caused by the first access to a lazily-backed page.
+/// This should be invoked by the page fault handler for faults on not-present pages.
+/// 
+/// Returns `true` if the faulting page was lazily mapped and is now backed by a frame, 
+/// or if another core backed it in the meantime, meaning that the faulting instruction can be safely retried. 
+/// Returns `false` if the fault was not caused by a lazily-backed page or if it could not be handled. 
+/// 
+/// # Locking / Deadlock
+/// This uses the currently-active page table directly, so it does not acquire the lock on any `MemoryManagementInfo`.
+/// Faults on pages that aren't lazily backed are rejected before any lock is acquired.
+/// Otherwise, it tries to acquire the `FRAME_ALLOCATOR` lock, which also serializes concurrent faults 
+/// on the same lazily-backed page; if that lock is already held, e.g., by the faulting code itself, 
+/// the fault is reported as unhandled rather than deadlocking.
+pub fn handle_lazy_page_fault(vaddr: VirtualAddress) -> bool {
+    let page = Page::containing_address(vaddr);
+    let mut mapper = Mapper::from_current();
+    match mapper.p1_entry_flags(page) {
+        Some(flags) if flags.contains(EntryFlags::PRESENT) => return true,
+        Some(flags) if flags.is_lazy() => { }
+        _ => return false,
+    }
+
+    let mut frame_allocator = match FRAME_ALLOCATOR.try().map(|fa| fa.try_lock()) {
+        Some(Some(fa)) => fa,
+        Some(None) => {
+            error!("handle_lazy_page_fault(): FRAME_ALLOCATOR is already locked, cannot handle fault at {:#X}", vaddr);
+            return false;
+        }
+        None => {
+            error!("handle_lazy_page_fault(): FRAME_ALLOCATOR was not yet initialized, cannot handle fault at {:#X}", vaddr);
+            return false;
+        }
+    };
+    match mapper.populate_lazy_page(page, frame_allocator.deref_mut()) {
+        Ok(populated) => populated,
+        Err(e) => {
+            error!("handle_lazy_page_fault(): failed to back the page at {:#X}, error: {}", vaddr, e);
+            false
+        }
+    }
+}
+
+
+/// Handles a page fault on the given virtual address, if it was caused by a write to a copy-on-write page
+/// (see [`MappedPages::clone_cow()`](struct.MappedPages.html#method.clone_cow)).
+/// This should be invoked by the page fault handler for write faults on present pages. 
+/// 
+/// Returns `true` if the faulting page was a copy-on-write page and is now privately writable, 
+/// meaning that the faulting instruction can be safely retried. 
+/// Returns `false` if the fault was not caused by a copy-on-write page or if it could not be handled. 
+/// 
+/// # Locking / Deadlock
+/// Faults on pages that aren't copy-on-write are rejected before any lock is acquired.
+/// Otherwise, it tries to acquire the `FRAME_ALLOCATOR` lock, reporting the fault as unhandled if that lock 
+/// is already held, and then acquires the lock on the copy-on-write frame refcounts, 
+/// so the faulting code must not hold the latter. 
+/// Every other path that acquires both locks does so in that same order.
+pub fn handle_cow_page_fault(vaddr: VirtualAddress) -> bool {
+    let page = Page::containing_address(vaddr);
+    let mut mapper = Mapper::from_current();
+    match mapper.p1_entry_flags(page) {
+        Some(flags) if flags.contains(EntryFlags::PRESENT) && flags.is_copy_on_write() => { }
+        _ => return false,
+    }
+
+    let mut frame_allocator = match FRAME_ALLOCATOR.try().map(|fa| fa.try_lock()) {
+        Some(Some(fa)) => fa,
+        Some(None) => {
+            error!("handle_cow_page_fault(): FRAME_ALLOCATOR is already locked, cannot handle fault at {:#X}", vaddr);
+            return false;
+        }
+        None => {
+            error!("handle_cow_page_fault(): FRAME_ALLOCATOR was not yet initialized, cannot handle fault at {:#X}", vaddr);
+            return false;
+        }
+    };
+    match mapper.handle_cow_write(page, frame_allocator.deref_mut()) {
+        Ok(handled) => handled,
+        Err(e) => {
+            error!("handle_cow_page_fault(): failed to copy the page at {:#X}, error: {}", vaddr, e);
+            false
+        }
+    }
+}
+
+
+/// The physical memory area reserved for crash dumps, if one was found.
+static CRASH_DUMP_AREA: Once<PhysicalMemoryArea> = Once::new();
+
+/// Returns the physical memory area that holds the crash dump, which is preserved across warm reboots.
+/// 
+/// This area is never given out by the frame allocator, so it must be mapped explicitly.
+/// Returns `None` if there was no suitable area, or if the memory subsystem hasn't been initialized.
+pub fn crash_dump_area() -> Option<PhysicalMemoryArea> {
+    CRASH_DUMP_AREA.try().cloned()
+}
+
+
 pub static BROADCAST_TLB_SHOOTDOWN_FUNC: Once<fn(PageRange)> = Once::new();
 
 /// Set the function callback that will be invoked every time a TLB shootdown is necessary,
@@ -206,11 +417,26 @@ pub fn init(boot_info: &BootInformation)
     occup_index += 1;
     occupied[occup_index] = PhysicalMemoryArea::new(kernel_phys_start, kernel_phys_end.value() - kernel_phys_start.value(), 1, 0); // the kernel boot image is already in use
     occup_index += 1;
-    occupied[occup_index] = get_boot_info_mem_area(&boot_info)?; // preserve the multiboot information for x86_64. 
+    let boot_info_mem_area = get_boot_info_mem_area(&boot_info)?;
+    occupied[occup_index] = boot_info_mem_area; // preserve the multiboot information for x86_64. 
     occup_index += 1;
     occupied[occup_index] = PhysicalMemoryArea::new(modules_start_paddr, modules_end_paddr.value() - modules_start_paddr.value(), 1, 0); // preserve all bootloader modules
     occup_index += 1;
 
+    // preserve the crash dump area, which may hold a dump from the previous boot
+    let crash_dump_lowest_paddr = core::cmp::max(
+        core::cmp::max(kernel_phys_end, modules_end_paddr),
+        PhysicalAddress::new_canonical(boot_info_mem_area.base_addr.value() + boot_info_mem_area.size_in_bytes),
+    );
+    match get_crash_dump_area(&boot_info, CRASH_DUMP_SIZE_IN_BYTES, crash_dump_lowest_paddr) {
+        Some(area) => {
+            occupied[occup_index] = area;
+            occup_index += 1;
+            CRASH_DUMP_AREA.call_once(|| area);
+        }
+        None => warn!("couldn't find a physical memory area for crash dumps"),
+    }
+
 
     // init the frame allocator with the available memory sections and the occupied memory sections
     let fa = AreaFrameAllocator::new(available, avail_len, occupied, occup_index)?;
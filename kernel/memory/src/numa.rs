@@ -0,0 +1,142 @@
+//! Describes the Non-Uniform Memory Access (NUMA) topology of the system,
+//! i.e., which processors and ranges of physical memory belong to each NUMA node,
+//! and the relative distance (access latency) between nodes.
+//!
+//! The topology is typically discovered from the ACPI SRAT and SLIT tables
+//! and then registered with [`set_numa_topology()`](../fn.set_numa_topology.html).
+
+use super::{PhysicalAddress, Frame};
+use alloc::vec::Vec;
+
+
+/// The ID of a NUMA node, which is the proximity domain number given by ACPI.
+pub type NodeId = u32;
+
+/// The distance from a node to itself, as defined by the ACPI SLIT table.
+pub const LOCAL_DISTANCE: u8 = 10;
+/// The distance between two different nodes that is assumed if the system has no SLIT table.
+pub const DEFAULT_REMOTE_DISTANCE: u8 = 20;
+
+
+/// A range of physical memory that belongs to a single NUMA node.
+#[derive(Clone, Copy, Debug)]
+pub struct NumaMemoryRange {
+    /// The node that this memory belongs to.
+    pub node: NodeId,
+    /// The starting physical address of this range.
+    pub start: PhysicalAddress,
+    /// The size in bytes of this range.
+    pub size_in_bytes: usize,
+    /// Whether this memory may be hot-plugged or hot-removed.
+    pub hot_pluggable: bool,
+}
+
+impl NumaMemoryRange {
+    /// Returns `true` if this range includes the given `frame`.
+    pub fn contains_frame(&self, frame: Frame) -> bool {
+        self.size_in_bytes != 0
+            && frame >= Frame::containing_address(self.start)
+            && frame <= Frame::containing_address(self.start + (self.size_in_bytes - 1))
+    }
+}
+
+
+/// The NUMA topology of the system.
+#[derive(Clone, Debug, Default)]
+pub struct NumaTopology {
+    /// All nodes, ordered by ID.
+    nodes: Vec<NodeId>,
+    /// Tuples of (APIC ID, node) for each processor.
+    processors: Vec<(u32, NodeId)>,
+    memory_ranges: Vec<NumaMemoryRange>,
+    /// The number of localities covered by `distances`.
+    num_localities: usize,
+    /// A `num_localities` by `num_localities` matrix of distances, in row-major order.
+    distances: Vec<u8>,
+}
+
+impl NumaTopology {
+    /// Creates an empty topology, to which processors and memory can be added.
+    pub fn new() -> NumaTopology {
+        NumaTopology::default()
+    }
+
+    fn add_node(&mut self, node: NodeId) {
+        if let Err(index) = self.nodes.binary_search(&node) {
+            self.nodes.insert(index, node);
+        }
+    }
+
+    /// Records that the processor with the given APIC ID belongs to the given `node`.
+    pub fn add_processor(&mut self, apic_id: u32, node: NodeId) {
+        self.add_node(node);
+        self.processors.retain(|&(id, _)| id != apic_id);
+        self.processors.push((apic_id, node));
+    }
+
+    /// Records that the given range of physical memory belongs to the range's node.
+    pub fn add_memory_range(&mut self, range: NumaMemoryRange) {
+        self.add_node(range.node);
+        self.memory_ranges.push(range);
+    }
+
+    /// Sets the matrix of relative distances between nodes, as given by the ACPI SLIT table.
+    /// The `distances` matrix must contain `num_localities * num_localities` entries in row-major order.
+    pub fn set_distances(&mut self, num_localities: usize, distances: Vec<u8>) -> Result<(), &'static str> {
+        if num_localities.checked_mul(num_localities) != Some(distances.len()) {
+            return Err("NumaTopology::set_distances(): the distance matrix has the wrong number of entries");
+        }
+        self.num_localities = num_localities;
+        self.distances = distances;
+        Ok(())
+    }
+
+    /// Returns the IDs of all nodes, in ascending order.
+    pub fn nodes(&self) -> &[NodeId] {
+        &self.nodes
+    }
+
+    /// Returns the ranges of physical memory that belong to every node.
+    pub fn memory_ranges(&self) -> &[NumaMemoryRange] {
+        &self.memory_ranges
+    }
+
+    /// Returns the node that the processor with the given APIC ID belongs to.
+    pub fn node_of_processor(&self, apic_id: u32) -> Option<NodeId> {
+        self.processors.iter().find(|&&(id, _)| id == apic_id).map(|&(_, node)| node)
+    }
+
+    /// Returns the node that the given `frame` of physical memory belongs to.
+    pub fn node_of_frame(&self, frame: Frame) -> Option<NodeId> {
+        self.memory_ranges.iter().find(|range| range.contains_frame(frame)).map(|range| range.node)
+    }
+
+    /// Returns the APIC IDs of all processors that belong to the given `node`.
+    pub fn processors_on_node(&self, node: NodeId) -> Vec<u32> {
+        self.processors.iter().filter(|&&(_, n)| n == node).map(|&(id, _)| id).collect()
+    }
+
+    /// Returns the relative distance from node `from` to node `to`,
+    /// in which [`LOCAL_DISTANCE`](constant.LOCAL_DISTANCE.html) is the distance from a node to itself.
+    ///
+    /// If the distance isn't known, e.g., because the system has no SLIT table,
+    /// [`DEFAULT_REMOTE_DISTANCE`](constant.DEFAULT_REMOTE_DISTANCE.html) is returned for two different nodes.
+    pub fn distance(&self, from: NodeId, to: NodeId) -> u8 {
+        let (from, to) = (from as usize, to as usize);
+        if from < self.num_localities && to < self.num_localities {
+            self.distances[from * self.num_localities + to]
+        } else if from == to {
+            LOCAL_DISTANCE
+        } else {
+            DEFAULT_REMOTE_DISTANCE
+        }
+    }
+
+    /// Returns all nodes ordered by their distance from the given node `from`, nearest first.
+    /// The first node is always `from` itself, if it exists.
+    pub fn nodes_by_distance(&self, from: NodeId) -> Vec<NodeId> {
+        let mut nodes = self.nodes.clone();
+        nodes.sort_by_key(|&node| (node != from, self.distance(from, node)));
+        nodes
+    }
+}
@@ -46,6 +46,22 @@ impl Entry {
         self.0 = (frame.start_address().value() as u64) | flags.bits();
     }
 
+    /// Returns true if this entry is a not-present placeholder for a lazily-backed page,
+    /// which will be given a frame upon its first access.
+    pub fn is_lazy(&self) -> bool {
+        let flags = self.flags();
+        !flags.contains(EntryFlags::PRESENT) && flags.is_lazy()
+    }
+
+    /// Sets this entry to be a not-present placeholder for a lazily-backed page 
+    /// that will be mapped with the given `flags` once a frame is allocated for it.
+    /// 
+    /// The `flags` are stored in the entry itself (without the `PRESENT` bit), 
+    /// which the hardware ignores for not-present entries.
+    pub fn set_lazy(&mut self, flags: EntryFlags) {
+        self.0 = ((flags - EntryFlags::PRESENT) | EntryFlags::LAZY).bits();
+    }
+
     // we use this to force explicit copying rather than deriving Copy/Clone
     pub fn copy(&self) -> Entry {
         Entry(self.0)
@@ -11,14 +11,80 @@ use core::mem;
 use core::ops::Deref;
 use core::ptr::Unique;
 use core::slice;
-use {BROADCAST_TLB_SHOOTDOWN_FUNC, VirtualAddress, PhysicalAddress, get_frame_allocator_ref, FrameRange, Page, Frame, FrameAllocator, AllocatedPages}; 
+use alloc::collections::BTreeMap;
+use spin::Once;
+use {BROADCAST_TLB_SHOOTDOWN_FUNC, VirtualAddress, PhysicalAddress, get_frame_allocator_ref, FrameRange, Page, PageSize, Frame, FrameAllocator, AllocatedPages}; 
 use paging::{PageRange, get_current_p4};
 use paging::table::{P4, Table, Level4};
 use kernel_config::memory::{ENTRIES_PER_PAGE_TABLE, PAGE_SIZE};
 use irq_safety::MutexIrqSafe;
-use super::{EntryFlags, tlb_flush_virt_addr};
+use super::{Entry, EntryFlags, tlb_flush_virt_addr};
 use zerocopy::FromBytes;
 
+
+/// The number of mappings that currently share each frame mapped as copy-on-write.
+/// 
+/// Frames are only added here by [`MappedPages::clone_cow()`](struct.MappedPages.html#method.clone_cow).
+/// A count of `0` or `1` means that the frame is no longer shared and can be made writable in place.
+static COW_FRAME_REFCOUNTS: Once<MutexIrqSafe<BTreeMap<Frame, usize>>> = Once::new();
+
+fn cow_frame_refcounts() -> &'static MutexIrqSafe<BTreeMap<Frame, usize>> {
+    COW_FRAME_REFCOUNTS.call_once(|| MutexIrqSafe::new(BTreeMap::new()))
+}
+
+/// Gives the copy-on-write `page` in the given page table its own private, writable frame.
+/// 
+/// If other mappings still share its frame, as given by `share_count`, the frame's contents are copied into a new frame
+/// through a temporary mapping before the page is switched over to it, such that no other core can ever observe
+/// the new frame before it holds the page's contents. Otherwise, the existing frame is simply made writable in place. 
+/// The `share_count` is decremented accordingly.
+/// 
+/// The caller must hold the lock on the copy-on-write frame refcounts, which must be acquired after the frame allocator's lock.
+fn make_cow_page_private<A>(mapper: &mut Mapper, page: Page, share_count: &mut usize, allocator: &mut A) -> Result<(), &'static str>
+    where A: FrameAllocator
+{
+    let (frame, flags) = {
+        let (entry, _) = mapper.leaf_entry_mut(page).ok_or("make_cow_page_private(): page not mapped")?;
+        (entry.pointed_frame().ok_or("make_cow_page_private(): page not mapped")?, entry.flags())
+    };
+    let private_flags = (flags - EntryFlags::COPY_ON_WRITE) | EntryFlags::WRITABLE | EntryFlags::PRESENT;
+
+    let private_frame = if *share_count <= 1 {
+        *share_count = 0;
+        frame
+    } else {
+        let new_frame = allocator.allocate_frame()
+            .ok_or("make_cow_page_private(): couldn't allocate new frame, out of memory!")?;
+
+        use paging::allocate_pages;
+        let temp_pages = allocate_pages(1).ok_or("make_cow_page_private(): couldn't allocate a temporary page")?;
+        let mut temp_mapping = mapper.map_allocated_pages_to(
+            temp_pages,
+            FrameRange::new(new_frame, new_frame),
+            EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            allocator,
+        )?;
+        {
+            let source = unsafe { slice::from_raw_parts(page.start_address().value() as *const u8, PAGE_SIZE) };
+            let dest: &mut [u8] = temp_mapping.as_slice_mut(0, PAGE_SIZE)?;
+            dest.copy_from_slice(source);
+        }
+        // unmaps the temporary page, but the new frame stays allocated
+        drop(temp_mapping);
+
+        *share_count -= 1;
+        new_frame
+    };
+
+    let (entry, _) = mapper.leaf_entry_mut(page).ok_or("make_cow_page_private(): page not mapped")?;
+    entry.set(private_frame, private_flags);
+    tlb_flush_virt_addr(page.start_address());
+    if let Some(func) = BROADCAST_TLB_SHOOTDOWN_FUNC.try() {
+        func(PageRange::new(page, page));
+    }
+    Ok(())
+}
+
 pub struct Mapper {
     p4: Unique<Table<Level4>>,
     /// The Frame contaning the top-level P4 page table.
@@ -124,12 +190,75 @@ impl Mapper {
     }
 
 
+    /// Returns the flags of the P1 page table entry for the given `page`, 
+    /// or `None` if the page tables that would contain that entry don't exist (or `page` is part of a huge page).
+    /// 
+    /// This only reads the page tables, so it can be used to inspect a faulting page before acquiring any locks.
+    pub fn p1_entry_flags(&self, page: Page) -> Option<EntryFlags> {
+        self.p4().next_table(page.p4_index())
+            .and_then(|p3| p3.next_table(page.p3_index()))
+            .and_then(|p2| p2.next_table(page.p2_index()))
+            .map(|p1| p1[page.p1_index()].flags())
+    }
+
+
+    /// Returns the lowest-level page table entry that maps the given `page`, 
+    /// along with the size of the (possibly huge) page mapped by that entry.
+    /// 
+    /// Returns `None` if the page tables that would contain that entry don't exist.
+    fn leaf_entry_mut(&mut self, page: Page) -> Option<(&mut Entry, PageSize)> {
+        let p3 = self.p4_mut().next_table_mut(page.p4_index())?;
+        if p3[page.p3_index()].flags().is_huge() {
+            return Some((&mut p3[page.p3_index()], PageSize::Huge1GiB));
+        }
+        let p2 = p3.next_table_mut(page.p3_index())?;
+        if p2[page.p2_index()].flags().is_huge() {
+            return Some((&mut p2[page.p2_index()], PageSize::Huge2MiB));
+        }
+        let p1 = p2.next_table_mut(page.p2_index())?;
+        Some((&mut p1[page.p1_index()], PageSize::Normal4KiB))
+    }
+
+
     /// Maps the given `AllocatedPages` to the given physical frames.
     /// 
+    /// Wherever a 2 MiB-aligned page is mapped to a 2 MiB-aligned frame (and at least 2 MiB remain to be mapped),
+    /// a single huge page is used instead of 512 normal pages, which saves TLB entries for large mappings. 
+    /// 
     /// Consumes the given `AllocatedPages` and returns a `MappedPages` object which contains those `AllocatedPages`.
     pub fn map_allocated_pages_to<A>(&mut self, pages: AllocatedPages, frames: FrameRange, flags: EntryFlags, allocator: &mut A)
         -> Result<MappedPages, &'static str>
         where A: FrameAllocator
+    {
+        self.internal_map_to(pages, frames, flags, PageSize::Huge2MiB, allocator)
+    }
+
+
+    /// Maps the given `AllocatedPages` to the given physical frames using pages of up to the given `page_size`.
+    /// 
+    /// The `pages` and `frames` must both start at an address aligned to `page_size`. 
+    /// Normal 4 KiB pages are still used for any part of the mapping that is smaller than one `page_size` page,
+    /// or that falls within a page table that already exists. 
+    /// Note that `PageSize::Huge1GiB` pages can only be used if the processor supports them.
+    /// 
+    /// Consumes the given `AllocatedPages` and returns a `MappedPages` object which contains those `AllocatedPages`.
+    pub fn map_allocated_pages_to_huge<A>(&mut self, pages: AllocatedPages, frames: FrameRange, flags: EntryFlags, page_size: PageSize, allocator: &mut A)
+        -> Result<MappedPages, &'static str>
+        where A: FrameAllocator
+    {
+        let alignment = page_size.size_in_pages();
+        if (pages.start_address().value() / PAGE_SIZE) % alignment != 0 || frames.start().number % alignment != 0 {
+            error!("map_allocated_pages_to_huge(): pages {:?} and frames {:?} must be aligned to {:?}", pages, frames, page_size);
+            return Err("map_allocated_pages_to_huge(): pages and frames must be aligned to the page size");
+        }
+        self.internal_map_to(pages, frames, flags, page_size, allocator)
+    }
+
+
+    /// The internal routine for mapping pages to frames, which uses pages of up to `max_page_size` wherever possible.
+    fn internal_map_to<A>(&mut self, pages: AllocatedPages, frames: FrameRange, flags: EntryFlags, max_page_size: PageSize, allocator: &mut A)
+        -> Result<MappedPages, &'static str>
+        where A: FrameAllocator
     {
         // P4, P3, and P2 entries should never set NO_EXECUTE, only the lowest-level P1 entry should. 
         let mut top_level_flags = flags.clone();
@@ -145,18 +274,53 @@ impl Mapper {
             return Err("map_allocated_pages_to(): page count must equal frame count");
         }
 
-        // iterate over pages and frames in lockstep
-        for (page, frame) in pages.deref().clone().into_iter().zip(frames) {
-            let p3 = self.p4_mut().next_table_create(page.p4_index(), top_level_flags, allocator);
-            let p2 = p3.next_table_create(page.p3_index(), top_level_flags, allocator);
-            let p1 = p2.next_table_create(page.p2_index(), top_level_flags, allocator);
+        // iterate over pages and frames in lockstep, skipping ahead by a huge page's worth whenever one is used
+        let mut page = *pages.start();
+        let mut frame = *frames.start();
+        let mut remaining = pages_count;
+        while remaining > 0 {
+            let fits = |size: PageSize| {
+                let n = size.size_in_pages();
+                size <= max_page_size && remaining >= n
+                    && (page.start_address().value() / PAGE_SIZE) % n == 0 
+                    && frame.number % n == 0
+            };
+
+            let mut mapped_size = None;
+            if fits(PageSize::Huge1GiB) {
+                let p3 = self.p4_mut().next_table_create(page.p4_index(), top_level_flags, allocator);
+                if p3[page.p3_index()].is_unused() {
+                    p3[page.p3_index()].set(frame, flags.into_huge() | EntryFlags::PRESENT);
+                    mapped_size = Some(PageSize::Huge1GiB);
+                }
+            }
+            if mapped_size.is_none() && fits(PageSize::Huge2MiB) {
+                let p3 = self.p4_mut().next_table_create(page.p4_index(), top_level_flags, allocator);
+                let p2 = p3.next_table_create(page.p3_index(), top_level_flags, allocator);
+                if p2[page.p2_index()].is_unused() {
+                    p2[page.p2_index()].set(frame, flags.into_huge() | EntryFlags::PRESENT);
+                    mapped_size = Some(PageSize::Huge2MiB);
+                }
+            }
+            if mapped_size.is_none() {
+                let p3 = self.p4_mut().next_table_create(page.p4_index(), top_level_flags, allocator);
+                let p2 = p3.next_table_create(page.p3_index(), top_level_flags, allocator);
+                let p1 = p2.next_table_create(page.p2_index(), top_level_flags, allocator);
 
-            if !p1[page.p1_index()].is_unused() {
-                error!("map_allocated_pages_to(): page {:#X} -> frame {:#X}, page was already in use!", page.start_address(), frame.start_address());
-                return Err("map_allocated_pages_to(): page was already in use");
-            } 
+                if !p1[page.p1_index()].is_unused() {
+                    error!("map_allocated_pages_to(): page {:#X} -> frame {:#X}, page was already in use!", page.start_address(), frame.start_address());
+                    return Err("map_allocated_pages_to(): page was already in use");
+                } 
 
-            p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+                p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+            }
+
+            let n = mapped_size.unwrap_or(PageSize::Normal4KiB).size_in_pages();
+            remaining -= n;
+            if remaining > 0 {
+                page += n;
+                frame += n;
+            }
         }
 
         Ok(MappedPages {
@@ -203,6 +367,123 @@ impl Mapper {
             flags,
         })
     }
+
+
+    /// Maps the given `AllocatedPages` lazily, i.e., without allocating any physical frames for them yet.
+    /// 
+    /// Each page's P1 entry is set to a not-present placeholder that records the given `flags`.
+    /// Upon the first access to one of those pages, the page fault handler (see [`handle_lazy_page_fault()`])
+    /// allocates a new zeroed frame and maps the page to it, such that frames are only used for pages that are actually touched.
+    /// Note that the intermediate page tables (P3, P2, P1) are still allocated eagerly.
+    /// 
+    /// Consumes the given `AllocatedPages` and returns a `MappedPages` object which contains those `AllocatedPages`.
+    /// 
+    /// [`handle_lazy_page_fault()`]: ../fn.handle_lazy_page_fault.html
+    pub fn map_allocated_pages_lazily<A>(&mut self, pages: AllocatedPages, flags: EntryFlags, allocator: &mut A)
+        -> Result<MappedPages, &'static str>
+        where A: FrameAllocator
+    {
+        // P4, P3, and P2 entries should never set NO_EXECUTE, only the lowest-level P1 entry should. 
+        let mut top_level_flags = flags.clone();
+        top_level_flags.set(EntryFlags::NO_EXECUTE, false);
+
+        for page in pages.deref().clone() {
+            let p3 = self.p4_mut().next_table_create(page.p4_index(), top_level_flags, allocator);
+            let p2 = p3.next_table_create(page.p3_index(), top_level_flags, allocator);
+            let p1 = p2.next_table_create(page.p2_index(), top_level_flags, allocator);
+
+            if !p1[page.p1_index()].is_unused() {
+                error!("map_allocated_pages_lazily(): page {:#X} was already in use!", page.start_address());
+                return Err("map_allocated_pages_lazily(): page was already in use");
+            } 
+
+            p1[page.p1_index()].set_lazy(flags);
+        }
+
+        Ok(MappedPages {
+            page_table_p4: self.target_p4.clone(),
+            pages,
+            flags,
+        })
+    }
+
+
+    /// Backs the given lazily-mapped `page` with a newly-allocated frame, which is zeroed before it becomes accessible.
+    /// 
+    /// Returns `Ok(true)` if the page is now backed by a frame, which includes the case where 
+    /// another core already populated it (e.g., because it faulted on the same page at the same time),
+    /// or `Ok(false)` if the page was not a lazily-mapped page in this page table.
+    pub fn populate_lazy_page<A>(&mut self, page: Page, allocator: &mut A) -> Result<bool, &'static str>
+        where A: FrameAllocator
+    {
+        let p1 = match self.p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))
+        {
+            Some(p1) => p1,
+            _ => return Ok(false),
+        };
+
+        if !p1[page.p1_index()].is_lazy() {
+            return Ok(p1[page.p1_index()].flags().contains(EntryFlags::PRESENT));
+        }
+        let flags = p1[page.p1_index()].flags() - EntryFlags::LAZY;
+        let frame = allocator.allocate_frame()
+            .ok_or("populate_lazy_page(): couldn't allocate new frame, out of memory!")?;
+
+        // Temporarily map the page as writable such that we can zero it before anyone uses it.
+        // Not-present entries are never cached in the TLB, so no flush is needed for the first mapping.
+        p1[page.p1_index()].set(frame.clone(), flags | EntryFlags::WRITABLE | EntryFlags::PRESENT);
+        unsafe {
+            core::ptr::write_bytes(page.start_address().value() as *mut u8, 0, PAGE_SIZE);
+        }
+
+        if !flags.is_writable() {
+            p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+            tlb_flush_virt_addr(page.start_address());
+            if let Some(func) = BROADCAST_TLB_SHOOTDOWN_FUNC.try() {
+                func(PageRange::new(page, page));
+            }
+        }
+
+        Ok(true)
+    }
+
+
+    /// Handles a write to the given copy-on-write `page` by giving it its own private, writable frame.
+    /// 
+    /// Returns `Ok(true)` if the page was a copy-on-write page and is now writable, 
+    /// or `Ok(false)` if the page was not a copy-on-write page in this page table.
+    /// 
+    /// The caller must hold the lock on the frame allocator that `allocator` came from (if any), 
+    /// since this acquires the lock on the copy-on-write frame refcounts, which must always be acquired second.
+    pub fn handle_cow_write<A>(&mut self, page: Page, allocator: &mut A) -> Result<bool, &'static str>
+        where A: FrameAllocator
+    {
+        let frame = {
+            let p1 = match self.p4_mut()
+                .next_table_mut(page.p4_index())
+                .and_then(|p3| p3.next_table_mut(page.p3_index()))
+                .and_then(|p2| p2.next_table_mut(page.p2_index()))
+            {
+                Some(p1) => p1,
+                _ => return Ok(false),
+            };
+
+            let entry = &mut p1[page.p1_index()];
+            if !entry.flags().contains(EntryFlags::PRESENT) || !entry.flags().is_copy_on_write() {
+                return Ok(false);
+            }
+            entry.pointed_frame().ok_or("handle_cow_write(): page not mapped")?
+        };
+
+        let mut refcounts = cow_frame_refcounts().lock();
+        let mut unknown_share_count = 0;
+        let share_count = refcounts.get_mut(&frame).unwrap_or(&mut unknown_share_count);
+        make_cow_page_private(self, page, share_count, allocator)?;
+        Ok(true)
+    }
 }
 
 
@@ -334,8 +615,96 @@ impl MappedPages {
         Ok(new_mapped_pages)
     }
 
+
+    /// Creates a copy-on-write clone of this `MappedPages` at a new virtual memory region,
+    /// which initially shares all of the same frames as this `MappedPages`.
+    /// 
+    /// If this mapping is writable, the page table entries of both mappings are marked read-only and copy-on-write,
+    /// such that the first write to a page in either mapping causes the page fault handler to give that page
+    /// its own private copy of the frame. Both `MappedPages` objects still report the original flags.
+    /// If this mapping is not writable, the frames are simply shared between both mappings.
+    /// Lazily-backed pages that haven't yet been accessed are also lazily-backed in the new mapping.
+    /// 
+    /// Returns a new `MappedPages` object with the same in-memory contents as this object, 
+    /// which is much cheaper than a [`deep_copy()`](#method.deep_copy) for large, read-mostly mappings.
+    /// 
+    /// This acquires the lock on the copy-on-write frame refcounts, so if `allocator` is the locked frame allocator, 
+    /// it must have been locked before calling this, as the frame allocator's lock is always acquired first.
+    pub fn clone_cow<A: FrameAllocator>(&mut self, active_table_mapper: &mut Mapper, allocator: &mut A) -> Result<MappedPages, &'static str> {
+        if active_table_mapper.target_p4 != self.page_table_p4 {
+            return Err("clone_cow(): the given Mapper must be for the page table that this MappedPages was mapped into");
+        }
+
+        use paging::allocate_pages;
+        let new_pages = allocate_pages(self.size_in_pages()).ok_or_else(|| "Couldn't allocate_pages()")?;
+
+        // P4, P3, and P2 entries should never set NO_EXECUTE, only the lowest-level P1 entry should. 
+        let mut top_level_flags = self.flags.clone();
+        top_level_flags.set(EntryFlags::NO_EXECUTE, false);
+
+        let mut refcounts = cow_frame_refcounts().lock();
+        for (page, new_page) in self.pages.deref().clone().into_iter().zip(new_pages.deref().clone()) {
+            // First, mark the existing page as copy-on-write (if needed) and get the frame it maps to.
+            let shared = {
+                let p1 = active_table_mapper.p4_mut()
+                    .next_table_mut(page.p4_index())
+                    .and_then(|p3| p3.next_table_mut(page.p3_index()))
+                    .and_then(|p2| p2.next_table_mut(page.p2_index()))
+                    .ok_or("mapping code does not support huge pages")?;
+                let entry = &mut p1[page.p1_index()];
+
+                if entry.is_lazy() {
+                    None
+                } else {
+                    let frame = entry.pointed_frame().ok_or("clone_cow(): page not mapped")?;
+                    let mut shared_flags = entry.flags();
+                    if shared_flags.is_writable() || shared_flags.is_copy_on_write() {
+                        shared_flags = (shared_flags - EntryFlags::WRITABLE) | EntryFlags::COPY_ON_WRITE;
+                        entry.set(frame, shared_flags);
+                        tlb_flush_virt_addr(page.start_address());
+                    }
+                    Some((frame, shared_flags))
+                }
+            };
+
+            // Second, map the new page to that same frame with those same flags.
+            let p3 = active_table_mapper.p4_mut().next_table_create(new_page.p4_index(), top_level_flags, allocator);
+            let p2 = p3.next_table_create(new_page.p3_index(), top_level_flags, allocator);
+            let p1 = p2.next_table_create(new_page.p2_index(), top_level_flags, allocator);
+
+            if !p1[new_page.p1_index()].is_unused() {
+                error!("clone_cow(): page {:#X} was already in use!", new_page.start_address());
+                return Err("clone_cow(): page was already in use");
+            }
+
+            match shared {
+                Some((frame, shared_flags)) => {
+                    p1[new_page.p1_index()].set(frame, shared_flags);
+                    if shared_flags.is_copy_on_write() {
+                        let share_count = refcounts.entry(frame).or_insert(0);
+                        *share_count = core::cmp::max(*share_count, 1) + 1;
+                    }
+                }
+                None => p1[new_page.p1_index()].set_lazy(self.flags),
+            }
+        }
+
+        if let Some(func) = BROADCAST_TLB_SHOOTDOWN_FUNC.try() {
+            func(self.pages.deref().clone());
+        }
+
+        Ok(MappedPages {
+            page_table_p4: self.page_table_p4.clone(),
+            pages: new_pages,
+            flags: self.flags,
+        })
+    }
+
     
     /// Change the permissions (`new_flags`) of this `MappedPages`'s page table entries.
+    /// 
+    /// Any copy-on-write pages in this mapping are first given their own private frames,
+    /// so this may acquire the lock on the frame allocator.
     pub fn remap(&mut self, active_table_mapper: &mut Mapper, new_flags: EntryFlags) -> Result<(), &'static str> {
         if self.size_in_pages() == 0 { return Ok(()); }
 
@@ -344,17 +713,37 @@ impl MappedPages {
             return Ok(());
         }
 
-        for page in self.pages.clone() {
-            let p1 = active_table_mapper.p4_mut()
-                .next_table_mut(page.p4_index())
-                .and_then(|p3| p3.next_table_mut(page.p3_index()))
-                .and_then(|p2| p2.next_table_mut(page.p2_index()))
-                .ok_or("mapping code does not support huge pages")?;
+        let start_page = *self.pages.start();
+        let mut offset = 0;
+        while offset < self.size_in_pages() {
+            let page = start_page + offset;
+            let (entry, page_size) = active_table_mapper.leaf_entry_mut(page).ok_or("remap(): page not mapped")?;
             
-            let frame = p1[page.p1_index()].pointed_frame().ok_or("remap(): page not mapped")?;
-            p1[page.p1_index()].set(frame, new_flags | EntryFlags::PRESENT);
+            // A lazily-backed page that hasn't yet been accessed just records its new flags.
+            if entry.is_lazy() {
+                entry.set_lazy(new_flags);
+                offset += 1;
+                continue;
+            }
+
+            // A copy-on-write page must stop sharing its frame before its permissions can be changed.
+            // The frame allocator's lock is always acquired before the lock on the copy-on-write frame refcounts.
+            if entry.flags().is_copy_on_write() {
+                let frame = entry.pointed_frame().ok_or("remap(): page not mapped")?;
+                let mut allocator = get_frame_allocator_ref().ok_or("remap(): couldn't get frame allocator")?.lock();
+                let mut refcounts = cow_frame_refcounts().lock();
+                let mut unknown_share_count = 0;
+                let share_count = refcounts.get_mut(&frame).unwrap_or(&mut unknown_share_count);
+                make_cow_page_private(active_table_mapper, page, share_count, &mut *allocator)?;
+            }
+
+            let (entry, _) = active_table_mapper.leaf_entry_mut(page).ok_or("remap(): page not mapped")?;
+            let frame = entry.pointed_frame().ok_or("remap(): page not mapped")?;
+            let page_size_flags = if page_size == PageSize::Normal4KiB { EntryFlags::empty() } else { EntryFlags::HUGE_PAGE };
+            entry.set(frame, new_flags | page_size_flags | EntryFlags::PRESENT);
 
             tlb_flush_virt_addr(page.start_address());
+            offset += page_size.size_in_pages();
         }
         
         if let Some(func) = BROADCAST_TLB_SHOOTDOWN_FUNC.try() {
@@ -373,17 +762,29 @@ impl MappedPages {
     {
         if self.size_in_pages() == 0 { return Ok(()); }
 
-        for page in self.pages.clone() {            
-            let p1 = active_table_mapper.p4_mut()
-                .next_table_mut(page.p4_index())
-                .and_then(|p3| p3.next_table_mut(page.p3_index()))
-                .and_then(|p2| p2.next_table_mut(page.p2_index()))
-                .ok_or("mapping code does not support huge pages")?;
+        let start_page = *self.pages.start();
+        let mut offset = 0;
+        while offset < self.size_in_pages() {
+            let page = start_page + offset;
+            let (entry, page_size) = active_table_mapper.leaf_entry_mut(page).ok_or("unmap(): page not mapped")?;
             
-            let _frame = p1[page.p1_index()].pointed_frame().ok_or("unmap(): page not mapped")?;
-            p1[page.p1_index()].set_unused();
+            // A lazily-backed page that was never accessed has no frame and was never cached in the TLB.
+            if entry.is_lazy() {
+                entry.set_unused();
+                offset += 1;
+                continue;
+            }
+
+            let frame = entry.pointed_frame().ok_or("unmap(): page not mapped")?;
+            if entry.flags().is_copy_on_write() {
+                if let Some(share_count) = cow_frame_refcounts().lock().get_mut(&frame) {
+                    *share_count = share_count.saturating_sub(1);
+                }
+            }
+            entry.set_unused();
 
             tlb_flush_virt_addr(page.start_address());
+            offset += page_size.size_in_pages();
             
             // TODO free p(1,2,3) table if empty
             // _allocator_ref.lock().deallocate_frame(frame);
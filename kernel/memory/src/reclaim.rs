@@ -0,0 +1,55 @@
+//! A registry of reclaimers: subsystems that hold memory which they can give back on demand,
+//! such as caches whose contents can be dropped or re-read later.
+//!
+//! When a convenience mapping function like [`create_mapping()`](../fn.create_mapping.html) fails
+//! to obtain frames, it asks the registered reclaimers to free some memory and then retries once.
+
+use alloc::vec::Vec;
+use irq_safety::MutexIrqSafe;
+
+
+/// A function that tries to free at least the given number of frames,
+/// and returns the number of frames that it actually freed.
+///
+/// A reclaimer is invoked while a memory allocation is in progress, possibly from a task that holds
+/// the reclaimer's own locks, so it must only *try* to acquire its locks and give up if they're unavailable.
+/// It is invoked without holding the lock on the frame allocator or the kernel's `MemoryManagementInfo`,
+/// so it may unmap (drop) `MappedPages`.
+pub type ReclaimFunc = fn(usize) -> usize;
+
+static RECLAIMERS: MutexIrqSafe<Vec<ReclaimFunc>> = MutexIrqSafe::new(Vec::new());
+
+
+/// Registers a function that will be invoked to free memory when the system runs low on it.
+pub fn register_reclaimer(func: ReclaimFunc) {
+    RECLAIMERS.lock().push(func);
+}
+
+/// Asks the registered reclaimers, in the order they were registered, to free `num_frames` frames,
+/// stopping once enough have been freed.
+///
+/// Returns the total number of frames that were freed.
+pub fn reclaim(num_frames: usize) -> usize {
+    // Reclaimers may allocate or register other reclaimers, so they're invoked without holding the lock.
+    let reclaimers = RECLAIMERS.lock().clone();
+    let mut freed = 0;
+    for reclaimer in reclaimers {
+        if freed >= num_frames {
+            break;
+        }
+        freed += reclaimer(num_frames - freed);
+    }
+    if freed > 0 {
+        debug!("memory::reclaim(): freed {} of {} requested frames", freed, num_frames);
+    }
+    freed
+}
+
+/// Invokes the given allocation function `f`, and if it fails, reclaims memory
+/// (about `size_in_bytes` worth of frames) and invokes it once more.
+pub(crate) fn with_reclaim<T, F: Fn() -> Result<T, &'static str>>(size_in_bytes: usize, f: F) -> Result<T, &'static str> {
+    f().or_else(|e| {
+        let num_frames = (size_in_bytes + super::PAGE_SIZE - 1) / super::PAGE_SIZE;
+        if reclaim(num_frames) > 0 { f() } else { Err(e) }
+    })
+}
@@ -0,0 +1,201 @@
+//! Maps the contents of a file directly into memory, similar to `mmap()` in POSIX systems.
+//!
+//! A file can be mapped in one of two modes:
+//! * [`MapMode::Shared`](enum.MapMode.html): changes made to the mapped memory are written back to the file
+//!   when [`sync()`](struct.MemoryMappedFile.html#method.sync) is called, and when the mapping is dropped.
+//! * [`MapMode::Private`](enum.MapMode.html): changes made to the mapped memory are only visible through that mapping
+//!   and are never written back to the file.
+//!
+//! A mapping doesn't copy the file's contents: it shares the frames that hold the file's pages in the
+//! [`page_cache`](../page_cache/index.html), which are pinned in the cache for as long as the mapping exists.
+//! Thus, a shared mapping is written to the file by simply marking its cached pages dirty and syncing them,
+//! whereas a writable private mapping is a copy-on-write clone of the cached pages, such that each page
+//! is only copied when it's first written to. Until then, a private mapping sees changes made to the file.
+//! Bytes of the mapping that lie beyond the end of the file are zeroed, and are never written back to the file.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate kernel_config;
+extern crate memory;
+extern crate fs_node;
+extern crate page_cache;
+
+use core::ops::{Deref, DerefMut};
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, EntryFlags, get_frame_allocator_ref, get_kernel_mmi_ref};
+use fs_node::FileRef;
+
+
+/// Whether changes to a memory-mapped file are written back to the underlying file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapMode {
+    /// Changes to the mapping are written back to the file, like `MAP_SHARED`.
+    Shared,
+    /// Changes to the mapping are private to it and never written back to the file, like `MAP_PRIVATE`.
+    Private,
+}
+
+
+/// Maps `len` bytes of the given `file`, starting at `offset` within the file, into memory.
+///
+/// The given `flags` determine the permissions of the mapping, e.g., `EntryFlags::WRITABLE`.
+/// The `offset` must be a multiple of `PAGE_SIZE` and must not exceed the file's size, but `offset + len` may,
+/// in which case the bytes beyond the end of the file are zeroed.
+///
+/// # Locking / Deadlock
+/// This acquires the lock on the given `file`, the page cache, the frame allocator, and the kernel's `MemoryManagementInfo`.
+pub fn map(file: &FileRef, offset: usize, len: usize, flags: EntryFlags, mode: MapMode) -> Result<MemoryMappedFile, &'static str> {
+    if len == 0 {
+        return Err("memory_mapped_file::map(): cannot map a range of length 0");
+    }
+    if offset % PAGE_SIZE != 0 {
+        return Err("memory_mapped_file::map(): offset must be a multiple of the page size");
+    }
+    if offset > file.lock().size() {
+        return Err("memory_mapped_file::map(): offset exceeds the file's size");
+    }
+
+    let first_page = offset / PAGE_SIZE;
+    let num_pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let mut cached = page_cache::map_file_pages(file, first_page, num_pages, flags)?;
+
+    // A private mapping that can't be written to can simply share the cached pages, like a shared one.
+    let (mp, cached) = if mode == MapMode::Private && flags.is_writable() {
+        match clone_cow(&mut cached) {
+            Ok(mp) => (mp, Some(cached)),
+            Err(e) => {
+                drop(cached);
+                page_cache::unpin_file_pages(file, first_page, num_pages);
+                return Err(e);
+            }
+        }
+    } else {
+        (cached, None)
+    };
+
+    Ok(MemoryMappedFile {
+        mp,
+        cached,
+        file: file.clone(),
+        offset,
+        len,
+        mode,
+    })
+}
+
+/// Creates a copy-on-write clone of the given mapping in the kernel's page table.
+fn clone_cow(mp: &mut MappedPages) -> Result<MappedPages, &'static str> {
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("memory_mapped_file::map(): KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("memory_mapped_file::map(): couldn't get the frame allocator")?;
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+    mp.clone_cow(&mut kernel_mmi.page_table, fa.lock().deref_mut())
+}
+
+
+/// A region of memory that contains the contents of a range of a file.
+///
+/// Auto-dereferences into the `MappedPages` that hold the file's contents,
+/// in which the byte at offset `0` corresponds to the byte at [`file_offset()`](#method.file_offset) in the file.
+///
+/// If this is a [`MapMode::Shared`](enum.MapMode.html) mapping,
+/// it is synced back to the file when dropped.
+pub struct MemoryMappedFile {
+    mp: MappedPages,
+    /// For a writable private mapping, the mapping of the cached pages that `mp` is a copy-on-write clone of.
+    /// It's kept until `mp` is dropped, such that the cached pages are never made writable in place for `mp`.
+    cached: Option<MappedPages>,
+    file: FileRef,
+    offset: usize,
+    len: usize,
+    mode: MapMode,
+}
+
+impl MemoryMappedFile {
+    /// Returns the file that this mapping was created from.
+    pub fn file(&self) -> &FileRef {
+        &self.file
+    }
+
+    /// Returns the offset into the file at which this mapping begins.
+    pub fn file_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of bytes of the file that this mapping covers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the mode of this mapping.
+    pub fn mode(&self) -> MapMode {
+        self.mode
+    }
+
+    /// Writes the entire contents of this mapping back to the file, like `msync()`.
+    ///
+    /// Returns the number of bytes written to the file.
+    /// See [`sync_range()`](#method.sync_range) for more details.
+    pub fn sync(&self) -> Result<usize, &'static str> {
+        self.sync_range(0, self.len)
+    }
+
+    /// Writes `len` bytes of this mapping, starting at `offset` within the mapping, back to the file.
+    /// The mapping shares the file's pages in the page cache, so they're marked dirty and then written back to the file.
+    ///
+    /// Only the bytes that resided within the file when they are synced are written back,
+    /// i.e., syncing never extends the file.
+    /// Returns the number of bytes written to the file, which is always `0` for
+    /// [`MapMode::Private`](enum.MapMode.html) mappings or mappings that aren't writable.
+    pub fn sync_range(&self, offset: usize, len: usize) -> Result<usize, &'static str> {
+        if offset.checked_add(len).map_or(true, |end| end > self.len) {
+            return Err("MemoryMappedFile::sync_range(): range exceeds the bounds of the mapping");
+        }
+        if self.mode == MapMode::Private || !self.mp.flags().is_writable() {
+            return Ok(0);
+        }
+
+        let file_offset = self.offset + offset;
+        let file_size = self.file.lock().size();
+        if file_offset >= file_size {
+            return Ok(0);
+        }
+        let len = core::cmp::min(len, file_size - file_offset);
+        if len == 0 {
+            return Ok(0);
+        }
+        let first_page = file_offset / PAGE_SIZE;
+        let end_page = (file_offset + len + PAGE_SIZE - 1) / PAGE_SIZE;
+        page_cache::mark_file_pages_dirty(&self.file, first_page, end_page - first_page);
+        page_cache::sync_file(&self.file)?;
+        Ok(len)
+    }
+}
+
+impl Deref for MemoryMappedFile {
+    type Target = MappedPages;
+    fn deref(&self) -> &MappedPages {
+        &self.mp
+    }
+}
+impl DerefMut for MemoryMappedFile {
+    fn deref_mut(&mut self) -> &mut MappedPages {
+        &mut self.mp
+    }
+}
+
+impl Drop for MemoryMappedFile {
+    fn drop(&mut self) {
+        if self.mode == MapMode::Shared {
+            if let Err(e) = self.sync() {
+                error!("MemoryMappedFile: failed to sync {} bytes at offset {} back to file {:?}: {}", 
+                    self.len, self.offset, self.file.lock().get_name(), e
+                );
+            }
+        }
+        // Both mappings of the cached pages must be gone before they're unpinned.
+        self.mp = MappedPages::empty();
+        self.cached = None;
+        page_cache::unpin_file_pages(&self.file, self.offset / PAGE_SIZE, (self.len + PAGE_SIZE - 1) / PAGE_SIZE);
+    }
+}
@@ -19,7 +19,7 @@ use core::{
     iter::Step,
     ops::{Add, AddAssign, Deref, DerefMut, RangeInclusive, Sub, SubAssign},
 };
-use kernel_config::memory::{MAX_PAGE_NUMBER, PAGE_SIZE};
+use kernel_config::memory::{MAX_PAGE_NUMBER, PAGE_SIZE, ENTRIES_PER_PAGE_TABLE};
 #[cfg(target_arch = "x86_64")]
 use entryflags_x86_64::EntryFlags;
 use zerocopy::FromBytes;
@@ -638,6 +638,36 @@ impl IntoIterator for PageRange {
 }
 
 
+/// The sizes of pages (and frames) that can be mapped by a single page table entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PageSize {
+    /// A normal 4 KiB page, mapped by a P1 entry.
+    Normal4KiB,
+    /// A huge 2 MiB page, mapped by a P2 entry.
+    Huge2MiB,
+    /// A huge 1 GiB page, mapped by a P3 entry. 
+    /// Not all processors support this page size.
+    Huge1GiB,
+}
+
+impl PageSize {
+    /// Returns the number of normal 4 KiB pages (or frames) covered by one page of this size.
+    /// This is also the required alignment (in pages) of a page of this size.
+    pub fn size_in_pages(&self) -> usize {
+        match self {
+            PageSize::Normal4KiB => 1,
+            PageSize::Huge2MiB => ENTRIES_PER_PAGE_TABLE,
+            PageSize::Huge1GiB => ENTRIES_PER_PAGE_TABLE * ENTRIES_PER_PAGE_TABLE,
+        }
+    }
+
+    /// Returns the size in bytes of one page of this size.
+    pub fn size_in_bytes(&self) -> usize {
+        self.size_in_pages() * PAGE_SIZE
+    }
+}
+
+
 /// The address bounds and mapping flags of a section's memory region.
 #[derive(Debug)]
 pub struct SectionMemoryBounds {
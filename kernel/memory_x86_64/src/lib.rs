@@ -132,6 +132,29 @@ pub fn get_modules_address(boot_info: &BootInformation) -> (PhysicalAddress, Phy
     (PhysicalAddress::new_canonical(mod_min), PhysicalAddress::new_canonical(mod_max))
 }
 
+/// Gets the physical memory area in which the crash dump is kept across reboots,
+/// which is the last `size_in_bytes` bytes of the highest available memory area.
+///
+/// As long as the machine's memory doesn't change, this is the same area on every boot.
+/// Returns `None` if there is no such area that lies entirely above `lowest_paddr`,
+/// which should be the end of all memory occupied by the kernel, its modules, and the boot information.
+pub fn get_crash_dump_area(
+    boot_info: &BootInformation,
+    size_in_bytes: usize,
+    lowest_paddr: PhysicalAddress,
+) -> Option<PhysicalMemoryArea> {
+    let highest_area = boot_info.memory_map_tag()?
+        .memory_areas()
+        .max_by_key(|area| area.end_address())?;
+    // align down to the start of a frame
+    let end = Frame::containing_address(PhysicalAddress::new(highest_area.end_address() as usize).ok()?).start_address();
+    let start = PhysicalAddress::new(end.value().checked_sub(size_in_bytes)?).ok()?;
+    if start < lowest_paddr || start.value() < highest_area.start_address() as usize {
+        return None;
+    }
+    Some(PhysicalMemoryArea::new(start, size_in_bytes, 1, 0))
+}
+
 /// Gets the physical memory area occupied by the bootloader information.
 pub fn get_boot_info_mem_area(
     boot_info: &BootInformation,
@@ -57,6 +57,54 @@ pub mod parse_nano_core;
 pub mod replace_nano_core_crates;
 
 
+/// The signature of a function that returns the IDs of all tasks that have a stack frame
+/// within the given range of virtual addresses, see [`TASK_FRAMES_IN_RANGE_FUNCTION`].
+/// 
+/// [`TASK_FRAMES_IN_RANGE_FUNCTION`]: static.TASK_FRAMES_IN_RANGE_FUNCTION.html
+pub type TaskFramesInRangeFunction = fn(&Range<VirtualAddress>) -> Vec<usize>;
+
+/// A callback used by [`CrateNamespace::unload_crate()`] to check whether any task is still executing a crate's code.
+/// It should be initialized by the tasking subsystem, since this crate cannot depend on it. 
+/// 
+/// [`CrateNamespace::unload_crate()`]: struct.CrateNamespace.html#method.unload_crate
+pub static TASK_FRAMES_IN_RANGE_FUNCTION: Once<TaskFramesInRangeFunction> = Once::new();
+
+
+/// The reasons that [`CrateNamespace::unload_crate()`] may fail to unload a crate.
+/// 
+/// [`CrateNamespace::unload_crate()`]: struct.CrateNamespace.html#method.unload_crate
+#[derive(Debug)]
+pub enum UnloadCrateError {
+    /// No crate with the given name is loaded into the namespace itself (its recursive namespace is not searched).
+    NotFound,
+    /// Sections in other crates still depend on this crate's sections. 
+    /// Each entry is a tuple of `(dependent section name, this crate's section name)`.
+    HasDependents(Vec<(String, String)>),
+    /// The tasks with the enclosed IDs have stack frames within this crate's `.text` sections.
+    InUseByTasks(Vec<usize>),
+    /// The crate is still referenced elsewhere, e.g., it is shared with another namespace
+    /// or is the application crate of a task that hasn't yet been reaped.
+    Shared,
+}
+
+impl fmt::Display for UnloadCrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnloadCrateError::NotFound => write!(f, "crate not found in namespace"),
+            UnloadCrateError::HasDependents(deps) => {
+                write!(f, "{} sections in other crates depend on this crate:", deps.len())?;
+                for (dependent, section) in deps {
+                    write!(f, "\n    {} -> {}", dependent, section)?;
+                }
+                Ok(())
+            }
+            UnloadCrateError::InUseByTasks(task_ids) => write!(f, "tasks {:?} are executing this crate's code", task_ids),
+            UnloadCrateError::Shared => write!(f, "crate is still referenced by another namespace or task"),
+        }
+    }
+}
+
+
 /// The name of the directory that contains all of the CrateNamespace files.
 pub const NAMESPACES_DIRECTORY_NAME: &'static str = "namespaces";
 
@@ -104,6 +152,79 @@ pub fn create_application_namespace(recursive_namespace: Option<Arc<CrateNamespa
 }
 
 
+/// The name of the directory (within the namespaces directory) that holds in-memory copies of
+/// crate object files loaded from outside of any namespace directory, see [`load_crate_from_file()`].
+/// 
+/// [`load_crate_from_file()`]: fn.load_crate_from_file.html
+pub const EXTERNAL_CRATES_DIRECTORY_NAME: &'static str = "_external";
+
+/// Loads the relocatable object file at the given `path` as an application crate into the given `namespace`,
+/// resolving its symbols against that `namespace` and its recursive namespaces.
+/// 
+/// Unlike crates loaded by prefix or name, the object file need not reside in a namespace directory;
+/// it can be at any path in the filesystem, e.g., a newly-compiled crate on a mounted disk. 
+/// A relative `path` is resolved against the `namespace`'s directory, 
+/// and `".o"` is appended to the `path` if no file exists there without it. 
+/// 
+/// Files whose contents can't be directly mapped into memory (e.g., those on a disk-backed filesystem)
+/// are first copied into a new in-memory file in the [`EXTERNAL_CRATES_DIRECTORY_NAME`] directory.
+/// 
+/// Returns the newly-loaded application crate, which is removed from the `namespace` when dropped.
+/// 
+/// [`EXTERNAL_CRATES_DIRECTORY_NAME`]: constant.EXTERNAL_CRATES_DIRECTORY_NAME.html
+pub fn load_crate_from_file(
+    path: &Path,
+    namespace: &Arc<CrateNamespace>,
+    kernel_mmi_ref: &MmiRef,
+    verbose_log: bool
+) -> Result<AppCrateRef, &'static str> {
+    let file = match path.get(namespace.dir())
+        .or_else(|| Path::new(format!("{}.o", path)).get(namespace.dir())) // retry with ".o" extension
+    {
+        Some(FileOrDir::File(f)) => f,
+        Some(FileOrDir::Dir(_)) => return Err("load_crate_from_file(): the given path was a directory, not a crate object file"),
+        None => return Err("load_crate_from_file(): couldn't find a crate object file at the given path"),
+    };
+
+    let is_mappable = file.lock().as_mapping().is_ok();
+    let file = if is_mappable {
+        file
+    } else {
+        copy_to_external_crates_directory(&file)?
+    };
+    CrateNamespace::load_crate_as_application(namespace, &file, kernel_mmi_ref, verbose_log)
+}
+
+/// Copies the contents of the given crate object `file` into a new `MemFile` of the same name
+/// in the [`EXTERNAL_CRATES_DIRECTORY_NAME`](constant.EXTERNAL_CRATES_DIRECTORY_NAME.html) directory,
+/// replacing any existing file with that name.
+fn copy_to_external_crates_directory(file: &FileRef) -> Result<FileRef, &'static str> {
+    let namespaces_dir = get_namespaces_directory().ok_or("couldn't get the top-level namespaces directory")?;
+    let external_dir = {
+        let existing_dir = namespaces_dir.lock().get_dir(EXTERNAL_CRATES_DIRECTORY_NAME);
+        match existing_dir {
+            Some(dir) => dir,
+            None => VFSDirectory::new(EXTERNAL_CRATES_DIRECTORY_NAME.to_string(), &namespaces_dir)?,
+        }
+    };
+
+    let (name, content) = {
+        let f = file.lock();
+        let mut content = vec![0u8; f.size()];
+        let bytes_read = f.read(&mut content, 0)?;
+        content.truncate(bytes_read);
+        (f.get_name(), content)
+    };
+    let existing_file = external_dir.lock().get(&name);
+    if let Some(existing) = existing_file {
+        external_dir.lock().remove(&existing);
+    }
+    let mem_file = MemFile::new(name, &external_dir)?;
+    mem_file.lock().write(&content, 0)?;
+    Ok(mem_file)
+}
+
+
 /// Initializes the module management system based on the bootloader-provided modules, 
 /// and creates and returns the default `CrateNamespace` for kernel crates.
 pub fn init(boot_info: &BootInformation, kernel_mmi: &mut MemoryManagementInfo) -> Result<&'static Arc<CrateNamespace>, &'static str> {
@@ -843,6 +964,92 @@ impl CrateNamespace {
     }
 
 
+    /// Unloads the crate with the given `crate_name` from this `CrateNamespace` (but not its recursive namespace),
+    /// removing its symbols and dependencies such that its memory is reclaimed and its pages unmapped.
+    /// 
+    /// A crate can only be unloaded if:
+    /// * no sections in other crates depend on its sections,
+    /// * no live task has a stack frame within its `.text` sections, and 
+    /// * it is not shared with another namespace or referenced by a task as its application crate.
+    /// 
+    /// Otherwise, an error describing exactly what blocks unloading is returned and nothing is changed.
+    /// The crate's object file remains in this namespace's directory, so it can be loaded again later.
+    pub fn unload_crate(&self, crate_name: &str) -> Result<(), UnloadCrateError> {
+        let crate_ref = self.crate_tree.lock().get_str(crate_name)
+            .map(|c| CowArc::clone_shallow(c))
+            .ok_or(UnloadCrateError::NotFound)?;
+
+        {
+            let krate = crate_ref.lock_as_ref();
+
+            // Check for sections in other crates that depend on this crate's sections.
+            let mut dependents: Vec<(String, String)> = Vec::new();
+            for sec in krate.sections.values() {
+                for weak_dep in &sec.inner.read().sections_dependent_on_me {
+                    if let Some(dep_sec) = weak_dep.section.upgrade() {
+                        let is_foreign = !krate.sections.values().any(|s| Arc::ptr_eq(s, &dep_sec));
+                        if is_foreign {
+                            dependents.push((dep_sec.name.clone(), sec.name.clone()));
+                        }
+                    }
+                }
+            }
+            if !dependents.is_empty() {
+                return Err(UnloadCrateError::HasDependents(dependents));
+            }
+
+            // Check for tasks that may return into this crate's code.
+            if let (Some((_, text_range)), Some(task_frames_in_range)) = (krate.text_pages.as_ref(), TASK_FRAMES_IN_RANGE_FUNCTION.try()) {
+                let task_ids = task_frames_in_range(text_range);
+                if !task_ids.is_empty() {
+                    return Err(UnloadCrateError::InUseByTasks(task_ids));
+                }
+            }
+        }
+
+        if crate_ref.is_shared() {
+            return Err(UnloadCrateError::Shared);
+        }
+
+        // Now we know the crate can be unloaded, so remove it from this namespace.
+        let removed_crate = self.crate_tree.lock().remove_str(crate_name).ok_or(UnloadCrateError::NotFound)?;
+        {
+            let krate = removed_crate.lock_as_ref();
+            let mut symbol_map = self.symbol_map.lock();
+            for sec in krate.global_sections_iter() {
+                // Only remove symbols that still refer to this crate's sections, not ones that have been replaced.
+                let refers_to_sec = symbol_map.get_str(&sec.name)
+                    .and_then(|weak_sec| weak_sec.upgrade())
+                    .map_or(false, |s| Arc::ptr_eq(&s, sec));
+                if refers_to_sec {
+                    symbol_map.remove_str(&sec.name);
+                }
+            }
+            for sym in &krate.reexported_symbols {
+                symbol_map.remove_str(sym);
+            }
+            drop(symbol_map);
+
+            // Remove this crate's sections from the dependents lists of the sections they depend on,
+            // and release the strong references to those sections.
+            for sec in krate.sections.values() {
+                let strong_deps = core::mem::replace(&mut sec.inner.write().sections_i_depend_on, Vec::new());
+                for strong_dep in strong_deps {
+                    strong_dep.section.inner.write().sections_dependent_on_me.retain(|weak_dep| 
+                        weak_dep.section.upgrade().map_or(false, |s| !Arc::ptr_eq(&s, sec))
+                    );
+                }
+            }
+        }
+
+        #[cfg(not(loscd_eval))]
+        info!("unload_crate(): unloaded crate {:?} from namespace {:?}", crate_name, self.name);
+        // Dropping the last reference to the crate drops its sections and unmaps their pages.
+        drop(removed_crate);
+        Ok(())
+    }
+
+
     /// Finds all of the weak dependents (sections that depend on the given `old_section`)
     /// and rewrites their relocation entries to point to the given `new_section`.
     /// This effectively replaces the usage of the `old_section` with the `new_section`,
@@ -28,7 +28,7 @@ use core::{
     sync::atomic::Ordering,
 };
 use alloc::sync::Arc;
-use spin::Mutex;
+use spin::{Mutex, Once};
 use volatile::Volatile;
 use zerocopy::FromBytes;
 use irq_safety::MutexIrqSafe;
@@ -50,6 +50,10 @@ const TRAMPOLINE: usize = AP_STARTUP - PAGE_SIZE;
 
 const GRAPHIC_INFO_TRAMPOLINE_OFFSET: usize = 0x100;
 
+/// The virtual address range of the AP startup code in the kernel's text section,
+/// which is needed again to resume the BSP from ACPI S3 sleep.
+static AP_REALMODE_CODE: Once<(VirtualAddress, VirtualAddress)> = Once::new();
+
 // graphic mode information
 pub static GRAPHIC_INFO:Mutex<GraphicInfo> = Mutex::new(GraphicInfo{
     width:0,
@@ -81,63 +85,16 @@ pub fn handle_ap_cores(
     ap_start_realmode_end: VirtualAddress
 ) -> Result<usize, &'static str> {
     let frame_allocator_ref = get_frame_allocator_ref().ok_or("Couldn't get FRAME ALLOCATOR")?;
-    let ap_startup_size_in_bytes = ap_start_realmode_end.value() - ap_start_realmode_begin.value();
-
-    let page_table_phys_addr: PhysicalAddress;
-    let mut trampoline_mapped_pages: MappedPages; // must be held throughout APs being booted up
-    let mut ap_startup_mapped_pages: MappedPages; // must be held throughout APs being booted up
-    {
-        let mut kernel_mmi = kernel_mmi_ref.lock();
-        let page_table = &mut kernel_mmi.page_table;
-        // first, double check that the ap_start_realmode address is mapped and valid
-        page_table.translate(ap_start_realmode_begin).ok_or("handle_ap_cores(): couldn't translate ap_start_realmode address")?;
+    AP_REALMODE_CODE.call_once(|| (ap_start_realmode_begin, ap_start_realmode_end));
 
-        // Map trampoline frame and the ap_startup code to the AP_STARTUP frame.
-        // These frames MUST be identity mapped because they're accessed in AP boot up code,
-        // which has no page tables because it operates in 16-bit real mode.
-        let trampoline_frame  = FrameRange::from_phys_addr(PhysicalAddress::new_canonical(TRAMPOLINE), 1);
-        let trampoline_page   = memory::allocate_pages_at(VirtualAddress::new_canonical(TRAMPOLINE), trampoline_frame.size_in_frames())
-            .map_err(|_e| "handle_ap_cores(): failed to allocate trampoline page")?;
-        let ap_startup_frames = FrameRange::from_phys_addr(PhysicalAddress::new_canonical(AP_STARTUP), ap_startup_size_in_bytes);
-        let ap_startup_pages  = memory::allocate_pages_at(VirtualAddress::new_canonical(AP_STARTUP), ap_startup_frames.size_in_frames())
-            .map_err(|_e| "handle_ap_cores(): failed to allocate AP startup pages")?;
-        let mut allocator = frame_allocator_ref.lock();
-        
-        trampoline_mapped_pages = page_table.map_allocated_pages_to(
-            trampoline_page, 
-            trampoline_frame, 
-            EntryFlags::PRESENT | EntryFlags::WRITABLE, 
-            allocator.deref_mut()
-        )?;
-        ap_startup_mapped_pages = page_table.map_allocated_pages_to(
-            ap_startup_pages,
-            ap_startup_frames,
-            EntryFlags::PRESENT | EntryFlags::WRITABLE,
-            allocator.deref_mut()
-        )?;
-        page_table_phys_addr = page_table.physical_address();
-    }
+    // These must be held throughout APs being booted up.
+    let (mut trampoline_mapped_pages, _ap_startup_mapped_pages, page_table_phys_addr) = 
+        map_ap_startup_code(&kernel_mmi_ref, ap_start_realmode_begin, ap_start_realmode_end)?;
+    // Now, the AP startup code is at the PhysicalAddress `AP_STARTUP`.
 
     let all_lapics = get_lapics();
     let me = get_my_apic_id();
 
-    // Copy the AP startup code (from the kernel's text section pages) into the AP_STARTUP physical address entry point.
-    {
-        // First, get the kernel's text pages, which is the MappedPages object that contains the vaddr `ap_start_realmode_begin`.
-        let kernel_text_pages_ref = mod_mgmt::get_initial_kernel_namespace()
-            .ok_or("BUG: couldn't get the initial kernel CrateNamespace")
-            .and_then(|namespace| namespace.get_crate("nano_core").ok_or("BUG: couldn't get the 'nano_core' crate"))
-            .and_then(|nano_core_crate| nano_core_crate.lock_as_ref().text_pages.clone().ok_or("BUG: nano_core crate had no text pages"))?;
-        let kernel_text_pages = kernel_text_pages_ref.0.lock();
-        // Second, perform the actual copy.
-        let source_slice: &[u8] = kernel_text_pages.offset_of_address(ap_start_realmode_begin)
-            .ok_or("BUG: the 'ap_start_realmode_begin' virtual address was not covered by the kernel's text pages")
-            .and_then(|offset| kernel_text_pages.as_slice(offset, ap_startup_size_in_bytes))?;
-        let dest_slice: &mut [u8] = ap_startup_mapped_pages.as_slice_mut(0, ap_startup_size_in_bytes)?;
-        dest_slice.copy_from_slice(source_slice);
-    }
-    // Now, the AP startup code is at the PhysicalAddress `AP_STARTUP`.
-
     let mut ap_count = 0;
     let ap_trampoline_data: &mut ApTrampolineData = trampoline_mapped_pages.as_type_mut(0)?;
 
@@ -215,6 +172,158 @@ pub fn handle_ap_cores(
 }
 
 
+/// Identity maps the trampoline frame and the AP_STARTUP frames, and copies the AP startup code
+/// (from the kernel's text section pages) into the AP_STARTUP physical address entry point.
+///
+/// Returns the `MappedPages` covering the trampoline and the AP startup code, which must be held
+/// until the AP startup code is no longer needed, along with the physical address of the kernel's page table.
+fn map_ap_startup_code(
+    kernel_mmi_ref: &Arc<MutexIrqSafe<MemoryManagementInfo>>,
+    ap_start_realmode_begin: VirtualAddress,
+    ap_start_realmode_end: VirtualAddress
+) -> Result<(MappedPages, MappedPages, PhysicalAddress), &'static str> {
+    let frame_allocator_ref = get_frame_allocator_ref().ok_or("Couldn't get FRAME ALLOCATOR")?;
+    let ap_startup_size_in_bytes = ap_start_realmode_end.value() - ap_start_realmode_begin.value();
+
+    let page_table_phys_addr: PhysicalAddress;
+    let trampoline_mapped_pages: MappedPages;
+    let mut ap_startup_mapped_pages: MappedPages;
+    {
+        let mut kernel_mmi = kernel_mmi_ref.lock();
+        let page_table = &mut kernel_mmi.page_table;
+        // first, double check that the ap_start_realmode address is mapped and valid
+        page_table.translate(ap_start_realmode_begin).ok_or("map_ap_startup_code(): couldn't translate ap_start_realmode address")?;
+
+        // Map trampoline frame and the ap_startup code to the AP_STARTUP frame.
+        // These frames MUST be identity mapped because they're accessed in AP boot up code,
+        // which has no page tables because it operates in 16-bit real mode.
+        let trampoline_frame  = FrameRange::from_phys_addr(PhysicalAddress::new_canonical(TRAMPOLINE), 1);
+        let trampoline_page   = memory::allocate_pages_at(VirtualAddress::new_canonical(TRAMPOLINE), trampoline_frame.size_in_frames())
+            .map_err(|_e| "map_ap_startup_code(): failed to allocate trampoline page")?;
+        let ap_startup_frames = FrameRange::from_phys_addr(PhysicalAddress::new_canonical(AP_STARTUP), ap_startup_size_in_bytes);
+        let ap_startup_pages  = memory::allocate_pages_at(VirtualAddress::new_canonical(AP_STARTUP), ap_startup_frames.size_in_frames())
+            .map_err(|_e| "map_ap_startup_code(): failed to allocate AP startup pages")?;
+        let mut allocator = frame_allocator_ref.lock();
+        
+        trampoline_mapped_pages = page_table.map_allocated_pages_to(
+            trampoline_page, 
+            trampoline_frame, 
+            EntryFlags::PRESENT | EntryFlags::WRITABLE, 
+            allocator.deref_mut()
+        )?;
+        ap_startup_mapped_pages = page_table.map_allocated_pages_to(
+            ap_startup_pages,
+            ap_startup_frames,
+            EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            allocator.deref_mut()
+        )?;
+        page_table_phys_addr = page_table.physical_address();
+    }
+
+    // Copy the AP startup code (from the kernel's text section pages) into the AP_STARTUP physical address entry point.
+    {
+        // First, get the kernel's text pages, which is the MappedPages object that contains the vaddr `ap_start_realmode_begin`.
+        let kernel_text_pages_ref = get_nano_core_crate()
+            .and_then(|nano_core_crate| nano_core_crate.lock_as_ref().text_pages.clone().ok_or("BUG: nano_core crate had no text pages"))?;
+        let kernel_text_pages = kernel_text_pages_ref.0.lock();
+        // Second, perform the actual copy.
+        let source_slice: &[u8] = kernel_text_pages.offset_of_address(ap_start_realmode_begin)
+            .ok_or("BUG: the 'ap_start_realmode_begin' virtual address was not covered by the kernel's text pages")
+            .and_then(|offset| kernel_text_pages.as_slice(offset, ap_startup_size_in_bytes))?;
+        let dest_slice: &mut [u8] = ap_startup_mapped_pages.as_slice_mut(0, ap_startup_size_in_bytes)?;
+        dest_slice.copy_from_slice(source_slice);
+    }
+
+    Ok((trampoline_mapped_pages, ap_startup_mapped_pages, page_table_phys_addr))
+}
+
+fn get_nano_core_crate() -> Result<mod_mgmt::StrongCrateRef, &'static str> {
+    mod_mgmt::get_initial_kernel_namespace()
+        .ok_or("BUG: couldn't get the initial kernel CrateNamespace")
+        .and_then(|namespace| namespace.get_crate("nano_core").ok_or("BUG: couldn't get the 'nano_core' crate"))
+}
+
+
+/// The offset from `AP_STARTUP` of the real-mode entry point that the BSP resumes at after ACPI S3 sleep.
+/// This must match the position of the `ap_start_realmode_s3_resume` label in `ap_realmode.asm`.
+const S3_RESUME_OFFSET: usize = 2;
+
+/// The physical address at which the kernel image is loaded, where its `.init` sections begin.
+const KERNEL_LOAD_PHYS_ADDR: usize = 0x10_0000;
+
+/// The memory that must remain mapped while the system is in the ACPI S3 sleep state,
+/// such that the BSP can resume by running through the same startup code used to boot APs.
+pub struct S3ResumeTrampoline {
+    _trampoline: MappedPages,
+    _ap_startup: MappedPages,
+    _kernel_identity: MappedPages,
+    /// The physical address that the FACS waking vector must point to.
+    pub waking_vector: PhysicalAddress,
+}
+
+/// Prepares the AP startup code and trampoline such that when the firmware resumes the BSP from ACPI S3 sleep,
+/// the BSP runs through the AP boot sequence into long mode and then jumps to the given `resume_entry` function
+/// on the given stack, which must have the same signature as `ap_start::kstart_ap()`.
+///
+/// Because the AP boot sequence runs partially from the kernel's physical load address,
+/// the kernel image is also identity mapped, as it was during the initial boot.
+/// The returned `S3ResumeTrampoline` must be held until the BSP has resumed.
+pub fn prepare_s3_resume(
+    kernel_mmi_ref: &Arc<MutexIrqSafe<MemoryManagementInfo>>,
+    resume_entry: VirtualAddress,
+    resume_stack: &stack::Stack,
+    processor_id: u8,
+    apic_id: u8,
+    nmi_lint: u8,
+    nmi_flags: u16,
+) -> Result<S3ResumeTrampoline, &'static str> {
+    let (begin, end) = *AP_REALMODE_CODE.try().ok_or("prepare_s3_resume(): the AP startup code location is unknown; were the APs booted?")?;
+    let (mut trampoline, ap_startup, page_table_phys_addr) = map_ap_startup_code(kernel_mmi_ref, begin, end)?;
+
+    // The kernel image spans from its load address to the end of its data sections.
+    let kernel_identity = {
+        let data_end = get_nano_core_crate()
+            .and_then(|nano_core_crate| nano_core_crate.lock_as_ref().data_pages.as_ref().map(|(_mp, range)| range.end).ok_or("BUG: nano_core crate had no data pages"))?;
+        let mut kernel_mmi = kernel_mmi_ref.lock();
+        let page_table = &mut kernel_mmi.page_table;
+        let kernel_phys_end = page_table.translate(VirtualAddress::new_canonical(data_end.value() - 1))
+            .ok_or("prepare_s3_resume(): couldn't translate the end of the kernel's data sections")?;
+        let kernel_frames = FrameRange::from_phys_addr(
+            PhysicalAddress::new_canonical(KERNEL_LOAD_PHYS_ADDR),
+            kernel_phys_end.value() + 1 - KERNEL_LOAD_PHYS_ADDR,
+        );
+        let kernel_pages = memory::allocate_pages_at(VirtualAddress::new_canonical(KERNEL_LOAD_PHYS_ADDR), kernel_frames.size_in_frames())
+            .map_err(|_e| "prepare_s3_resume(): failed to allocate pages to identity map the kernel image")?;
+        let frame_allocator_ref = get_frame_allocator_ref().ok_or("Couldn't get FRAME ALLOCATOR")?;
+        let mut allocator = frame_allocator_ref.lock();
+        page_table.map_allocated_pages_to(
+            kernel_pages,
+            kernel_frames,
+            EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            allocator.deref_mut()
+        )?
+    };
+
+    let trampoline_data: &mut ApTrampolineData = trampoline.as_type_mut(0)?;
+    trampoline_data.ap_ready.write(0);
+    trampoline_data.ap_processor_id.write(processor_id);
+    trampoline_data.ap_apic_id.write(apic_id);
+    trampoline_data.ap_page_table.write(page_table_phys_addr);
+    trampoline_data.ap_stack_start.write(resume_stack.bottom());
+    trampoline_data.ap_stack_end.write(resume_stack.top_unusable());
+    trampoline_data.ap_code.write(resume_entry);
+    trampoline_data.ap_nmi_lint.write(nmi_lint);
+    trampoline_data.ap_nmi_flags.write(nmi_flags);
+
+    Ok(S3ResumeTrampoline {
+        _trampoline: trampoline,
+        _ap_startup: ap_startup,
+        _kernel_identity: kernel_identity,
+        waking_vector: PhysicalAddress::new_canonical(AP_STARTUP + S3_RESUME_OFFSET),
+    })
+}
+
+
 /// The data items used when an AP core is booting up in real mode.
 /// # Important Layout Note
 /// The order of the members in this struct must exactly match how they are used
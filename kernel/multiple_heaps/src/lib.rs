@@ -18,6 +18,8 @@
 //! When a per-core heap runs out of memory, pages are first moved between the slab allocators of the per-core heap, then requested from other per-core heaps.
 //! If no empty pages are available within any of the per-core heaps, then more virtual pages are allocated from the range of virtual addresses dedicated to the heap
 //! [KERNEL_HEAP_START](../kernel_config/memory/constant.KERNEL_HEAP_START.html) and dynamically mapped to physical memory frames.
+//! On NUMA systems, empty pages are preferably taken from heaps of cores on the same NUMA node, 
+//! and newly-mapped frames are allocated from the memory local to the heap's core.
 
 #![feature(const_fn)]
 #![feature(allocator_api)]
@@ -32,6 +34,7 @@ extern crate kernel_config;
 extern crate apic;
 extern crate heap;
 extern crate hashbrown;
+extern crate spin;
 #[macro_use] extern crate cfg_if;
 
 #[cfg(all(not(unsafe_heap), not(safe_heap)))]
@@ -54,6 +57,7 @@ use core::ptr;
 use heap::HEAP_FLAGS;
 use irq_safety::MutexIrqSafe;
 use page_allocator::{DeferredAllocAction, allocate_pages_by_bytes_deferred};
+use spin::Once;
 
 #[cfg(all(not(unsafe_heap), not(safe_heap)))]
 use slabmalloc::{ZoneAllocator, ObjectPage8k, AllocablePage, MappedPages8k};
@@ -112,20 +116,78 @@ fn initialize_multiple_heaps() -> Result<MultipleHeaps, &'static str> {
 /// then sets the multiple heaps as the default allocator.
 /// Only call this function when the multiple heaps are ready to be used.
 pub fn switch_to_multiple_heaps() -> Result<(), &'static str> {
-    let multiple_heaps = Box::new(initialize_multiple_heaps()?);
+    // the multiple heaps are never dropped, so we keep a static reference to them for gathering statistics
+    let multiple_heaps: &'static MultipleHeaps = Box::leak(Box::new(initialize_multiple_heaps()?));
+    MULTIPLE_HEAPS.call_once(|| multiple_heaps);
     //set the multiple heaps as the default allocator
-    heap::set_allocator(multiple_heaps);
+    heap::set_allocator(Box::new(multiple_heaps));
+    #[cfg(all(not(unsafe_heap), not(safe_heap)))]
+    heap::set_stats_callback(heap_stats);
 
     Ok(())
 }
 
+/// A reference to the multiple heaps that were set as the default allocator.
+static MULTIPLE_HEAPS: Once<&'static MultipleHeaps> = Once::new();
+
+/// Gathers the statistics of every size class in each per-core heap.
+/// 
+/// Each heap's statistics are copied out while its lock is held, 
+/// but the returned `Vec`s are only allocated after the lock is released,
+/// since allocating while holding a heap's lock could deadlock on that same heap.
+#[cfg(all(not(unsafe_heap), not(safe_heap)))]
+fn heap_stats() -> alloc::vec::Vec<heap::HeapStats> {
+    let multiple_heaps = match MULTIPLE_HEAPS.try() {
+        Some(mh) => *mh,
+        None => return alloc::vec::Vec::new(),
+    };
+
+    let mut all_stats = alloc::vec::Vec::with_capacity(multiple_heaps.heaps.len());
+    for (heap_id, locked_heap) in multiple_heaps.heaps.iter() {
+        let mut size_classes = [heap::SizeClassStats::default(); ZoneAllocator::MAX_BASE_SIZE_CLASSES];
+        {
+            let zone_allocator = locked_heap.lock();
+            for (stats, sc) in size_classes.iter_mut().zip(zone_allocator.size_class_allocators()) {
+                let (empty_pages, partial_pages, full_pages) = sc.page_counts();
+                *stats = heap::SizeClassStats {
+                    object_size: sc.size(),
+                    live_objects: sc.live_objects(),
+                    total_allocations: sc.total_allocations(),
+                    empty_pages,
+                    partial_pages,
+                    full_pages,
+                };
+            }
+        }
+        all_stats.push(heap::HeapStats {
+            heap_id: *heap_id,
+            size_classes: size_classes.to_vec(),
+        });
+    }
+    all_stats.sort_by_key(|stats| stats.heap_id);
+    all_stats
+}
+
 
 
+/// Returns `true` if the heaps with the given IDs belong to cores on the same NUMA node,
+/// or if the system's NUMA topology is unknown.
+fn on_same_numa_node(heap_id_a: usize, heap_id_b: usize) -> bool {
+    match memory::numa_topology() {
+        Some(topology) => topology.node_of_processor(heap_id_a as u32) == topology.node_of_processor(heap_id_b as u32),
+        None => true,
+    }
+}
+
 /// Allocates pages from the given starting address and maps them to frames.
 /// Returns the new mapped pages or an error if the heap memory limit is reached.
+/// 
+/// If the NUMA topology is known, the frames are allocated from the NUMA node 
+/// of the core that owns the heap given by `heap_id`, if possible.
 fn create_heap_mapping(
     starting_address: VirtualAddress, 
-    size_in_bytes: usize
+    size_in_bytes: usize,
+    heap_id: usize,
 ) -> Result<(MappedPages, DeferredAllocAction<'static>), &'static str> {
     let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("create_heap_mapping(): KERNEL_MMI was not yet initialized!")?;
     let mut kernel_mmi = kernel_mmi_ref.lock();
@@ -139,7 +201,13 @@ fn create_heap_mapping(
     if pages.start_address().value() % HEAP_MAPPED_PAGES_SIZE_IN_BYTES != 0 {
         return Err("multiple_heaps: the allocated pages for the heap wasn't properly aligned");
     }
-    let mp = kernel_mmi.page_table.map_allocated_pages(pages, HEAP_FLAGS, frame_allocator.deref_mut())?;
+    let local_frames = memory::numa_topology()
+        .and_then(|topology| topology.node_of_processor(heap_id as u32))
+        .and_then(|node| frame_allocator.allocate_frames_on_node(pages.size_in_pages(), node));
+    let mp = match local_frames {
+        Some(frames) => kernel_mmi.page_table.map_allocated_pages_to(pages, frames, HEAP_FLAGS, frame_allocator.deref_mut())?,
+        None => kernel_mmi.page_table.map_allocated_pages(pages, HEAP_FLAGS, frame_allocator.deref_mut())?,
+    };
 
     // trace!("Allocated heap pages at: {:#X}", starting_address);
 
@@ -156,9 +224,6 @@ fn create_heap_mapping(
 cfg_if! {
 if #[cfg(unsafe_heap)] {
     extern crate alloc;
-    extern crate spin;
-
-    use spin::Once;
 
     /// Initializes the heap given by `key`.
     /// There are 11 size classes in each heap ranging from [8,16,32,64 ..`ZoneAllocator::MAX_ALLOC_SIZE`].
@@ -182,7 +247,7 @@ if #[cfg(unsafe_heap)] {
                 let layout = Layout::from_size_align(*size, alignment).map_err(|_e| "Incorrect layout")?;
 
                 // create the mapped pages starting from the previous end of the heap
-                let (mp, _action) = create_heap_mapping(heap_end_addr, HEAP_MAPPED_PAGES_SIZE_IN_BYTES)?;
+                let (mp, _action) = create_heap_mapping(heap_end_addr, HEAP_MAPPED_PAGES_SIZE_IN_BYTES, key)?;
 
                 let start_addr = mp.start_address().value();
                 if start_addr % ObjectPage8k::SIZE != 0 {
@@ -235,7 +300,7 @@ if #[cfg(unsafe_heap)] {
                 let layout = Layout::from_size_align(*size, alignment).map_err(|_e| "Incorrect layout")?;
 
                 // create the mapped pages starting from the previous end of the heap
-                let (mp, _action) = create_heap_mapping(heap_end_addr, HEAP_MAPPED_PAGES_SIZE_IN_BYTES)?;
+                let (mp, _action) = create_heap_mapping(heap_end_addr, HEAP_MAPPED_PAGES_SIZE_IN_BYTES, key)?;
                 let mapping = MappedPages8k::new(mp)?;
                 // add page to the allocator
                 zone_allocator.refill(layout, mapping)?;
@@ -345,18 +410,24 @@ if #[cfg(unsafe_heap)] {
         /// * `layout`: layout.size will determine which allocation size the retrieved pages will be used for. 
         /// * `heap_to_grow`: heap that needs to grow.
         fn grow_heap(&self, layout: Layout, heap_to_grow: &LockedHeap) -> Result<(), &'static str> {
-            // (1) Try to retrieve a page from the another heap
-            for heap_ref in self.heaps.values() {
-                if let Some(mp) = heap_ref.try_lock().and_then(|mut giving_heap| giving_heap.retrieve_empty_page(EMPTY_PAGES_THRESHOLD)) {
-                    info!("Added page from another heap to heap: {}", heap_to_grow.lock().heap_id);
-                    return heap_to_grow.lock().refill(layout, mp);
+            let heap_id = heap_to_grow.lock().heap_id;
+            // (1) Try to retrieve a page from the another heap, preferring heaps on the same NUMA node
+            for &same_node in &[true, false] {
+                let candidate_heaps = self.heaps.iter()
+                    .filter(|&(id, _)| on_same_numa_node(*id, heap_id) == same_node)
+                    .map(|(_, heap_ref)| heap_ref);
+                for heap_ref in candidate_heaps {
+                    if let Some(mp) = heap_ref.try_lock().and_then(|mut giving_heap| giving_heap.retrieve_empty_page(EMPTY_PAGES_THRESHOLD)) {
+                        info!("Added page from another heap to heap: {}", heap_id);
+                        return heap_to_grow.lock().refill(layout, mp);
+                    }
                 }
             }
             // (2) Allocate page from the OS
             let mut deferred_alloc_actions = [None; HEAP_GROWTH_AMOUNT];
             let mut heap_end = self.end.lock();
             for saved_action in &mut deferred_alloc_actions {
-                let (mp, action) = create_heap_mapping(*heap_end, HEAP_MAPPED_PAGES_SIZE_IN_BYTES)?;
+                let (mp, action) = create_heap_mapping(*heap_end, HEAP_MAPPED_PAGES_SIZE_IN_BYTES, heap_id)?;
                 let start_addr = mp.start_address().value();
                 self.extend_heap_mp(mp)?;
                 let page = unsafe{ core::mem::transmute(start_addr) };
@@ -407,18 +478,24 @@ if #[cfg(unsafe_heap)] {
         /// * `layout`: layout.size will determine which allocation size the retrieved pages will be used for. 
         /// * `heap_to_grow`: heap that needs to grow.
         fn grow_heap(&self, layout: Layout, heap_to_grow: &LockedHeap) -> Result<(), &'static str> {
-            // (1) Try to retrieve a page from the another heap
-            for heap_ref in self.heaps.values() {
-                if let Some(mp) = heap_ref.try_lock().and_then(|mut giving_heap| giving_heap.retrieve_empty_page(EMPTY_PAGES_THRESHOLD)) {
-                    info!("Added page from another heap to heap: {}", heap_to_grow.lock().heap_id);
-                    return heap_to_grow.lock().refill(layout, mp);
+            let heap_id = heap_to_grow.lock().heap_id;
+            // (1) Try to retrieve a page from the another heap, preferring heaps on the same NUMA node
+            for &same_node in &[true, false] {
+                let candidate_heaps = self.heaps.iter()
+                    .filter(|&(id, _)| on_same_numa_node(*id, heap_id) == same_node)
+                    .map(|(_, heap_ref)| heap_ref);
+                for heap_ref in candidate_heaps {
+                    if let Some(mp) = heap_ref.try_lock().and_then(|mut giving_heap| giving_heap.retrieve_empty_page(EMPTY_PAGES_THRESHOLD)) {
+                        info!("Added page from another heap to heap: {}", heap_id);
+                        return heap_to_grow.lock().refill(layout, mp);
+                    }
                 }
             }
             // (2) Allocate page from the OS
             let mut deferred_alloc_actions = [None; HEAP_GROWTH_AMOUNT];
             let mut heap_end = self.end.lock();
             for saved_action in &mut deferred_alloc_actions {
-                let (mp, action) = create_heap_mapping(*heap_end, HEAP_MAPPED_PAGES_SIZE_IN_BYTES)?;
+                let (mp, action) = create_heap_mapping(*heap_end, HEAP_MAPPED_PAGES_SIZE_IN_BYTES, heap_id)?;
                 let mp = MappedPages8k::new(mp)?;
                 info!("grow_heap:: Allocated a page to refill core heap {} for size :{} at address: {:#X}", 
                     heap_to_grow.lock().heap_id, layout.size(), *heap_end
@@ -455,11 +532,17 @@ if #[cfg(unsafe_heap)] {
         /// * `layout`: layout.size will determine which allocation size the retrieved pages will be used for. 
         /// * `heap_to_grow`: heap that needs to grow.
         fn grow_heap(&self, layout: Layout, heap_to_grow: &LockedHeap) -> Result<(), &'static str> {
-            // (1) Try to retrieve a page from the another heap
-            for heap_ref in self.heaps.values() {
-                if let Some(mp) = heap_ref.try_lock().and_then(|mut giving_heap| giving_heap.retrieve_empty_page(EMPTY_PAGES_THRESHOLD)) {
-                    info!("Added page from another heap to heap: {}", heap_to_grow.lock().heap_id);
-                    return heap_to_grow.lock().refill(layout, mp);
+            let heap_id = heap_to_grow.lock().heap_id;
+            // (1) Try to retrieve a page from the another heap, preferring heaps on the same NUMA node
+            for &same_node in &[true, false] {
+                let candidate_heaps = self.heaps.iter()
+                    .filter(|&(id, _)| on_same_numa_node(*id, heap_id) == same_node)
+                    .map(|(_, heap_ref)| heap_ref);
+                for heap_ref in candidate_heaps {
+                    if let Some(mp) = heap_ref.try_lock().and_then(|mut giving_heap| giving_heap.retrieve_empty_page(EMPTY_PAGES_THRESHOLD)) {
+                        info!("Added page from another heap to heap: {}", heap_id);
+                        return heap_to_grow.lock().refill(layout, mp);
+                    }
                 }
             }
 
@@ -467,7 +550,7 @@ if #[cfg(unsafe_heap)] {
             let mut deferred_alloc_actions = [None; HEAP_GROWTH_AMOUNT];
             let mut heap_end = self.end.lock();
             for saved_action in &mut deferred_alloc_actions {
-                let (mp, action) = create_heap_mapping(*heap_end, HEAP_MAPPED_PAGES_SIZE_IN_BYTES)?;
+                let (mp, action) = create_heap_mapping(*heap_end, HEAP_MAPPED_PAGES_SIZE_IN_BYTES, heap_id)?;
                 let mp = MappedPages8k::new(mp)?;
                 info!("grow_heap:: Allocated a page to refill core heap {} for size :{} at address: {:#X}", 
                     heap_to_grow.lock().heap_id, layout.size(), *heap_end
@@ -546,6 +629,18 @@ unsafe impl GlobalAlloc for MultipleHeaps {
     }
 }
 
+/// A static reference to the multiple heaps is what is actually set as the default allocator,
+/// so that the heaps can still be accessed to gather statistics.
+unsafe impl GlobalAlloc for &'static MultipleHeaps {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        (**self).alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+}
+
 
 
 cfg_if! {
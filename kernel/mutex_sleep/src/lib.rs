@@ -1,4 +1,8 @@
 //! A mutex that puts tasks to sleep while they wait for the lock. 
+//!
+//! The mutex implements priority inheritance: while a task waits for the lock,
+//! the task holding the lock temporarily inherits the waiting task's priority (if higher),
+//! such that a high-priority waiter isn't starved by medium-priority tasks preempting the lock holder.
 
 #![no_std]
 
@@ -14,6 +18,7 @@ use spin::{Mutex, MutexGuard};
 use owning_ref::{OwningRef, OwningRefMut};
 use stable_deref_trait::StableDeref;
 use wait_queue::WaitQueue;
+use task::{TaskRef, get_my_current_task};
 
 
 /// A mutual exclusion wrapper that puts a `Task` to sleep while waiting for the lock to become available. 
@@ -23,6 +28,8 @@ use wait_queue::WaitQueue;
 /// will be notified (woken up) so they can attempt to acquire the lock again.
 pub struct MutexSleep<T: ?Sized> {
     queue: WaitQueue,
+    /// The task that currently holds the lock, which inherits the priority of tasks waiting for the lock.
+    owner: Mutex<Option<TaskRef>>,
     lock: Mutex<T>,
 }
 
@@ -32,7 +39,7 @@ pub struct MutexSleep<T: ?Sized> {
 /// which then notifies any `Task`s waiting on the lock.
 pub struct MutexSleepGuard<'a, T: ?Sized + 'a> {
     guard: MutexGuard<'a, T>,
-    queue: &'a WaitQueue,
+    mutex: &'a MutexSleep<T>,
 }
 
 // Same unsafe impls as `std::sync::Mutex`
@@ -50,6 +57,7 @@ impl<T> MutexSleep<T> {
         MutexSleep {
             lock: Mutex::new(data),
             queue: WaitQueue::new(),
+            owner: Mutex::new(None),
         }
     }
 
@@ -71,9 +79,19 @@ impl<T: ?Sized> MutexSleep<T> {
         if let Some(guard) = self.try_lock() {
             return Ok(guard);
         }
-        // Slow path if already locked elsewhere: wait until we obtain the lock.
+        // Slow path if already locked elsewhere: wait until we obtain the lock,
+        // lending our priority to the lock holder each time we fail to obtain it.
+        let my_priority = get_my_current_task().map(|t| t.priority());
         self.queue
-            .wait_until(&|| self.try_lock())
+            .wait_until(&|| {
+                let guard = self.try_lock();
+                if guard.is_none() {
+                    if let Some(priority) = my_priority {
+                        self.lend_priority_to_owner(priority);
+                    }
+                }
+                guard
+            })
             .map_err(|_| "failed to add current task to waitqueue")
     }
 
@@ -81,12 +99,22 @@ impl<T: ?Sized> MutexSleep<T> {
     /// Otherwise it returns a guard within `Some`.
     pub fn try_lock(&self) -> Option<MutexSleepGuard<T>> {
         self.lock.try_lock().map(|spinlock_guard| {
+            *self.owner.lock() = get_my_current_task().cloned();
             MutexSleepGuard {
                 guard: spinlock_guard,
-                queue: &self.queue,
+                mutex: self,
             }
         })
     }
+
+    /// Raises the priority of the task holding the lock to at least `priority`.
+    fn lend_priority_to_owner(&self, priority: u8) {
+        if let Some(ref owner) = *self.owner.lock() {
+            if owner.priority() < priority {
+                owner.inherit_priority(priority);
+            }
+        }
+    }
 }
 
 impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexSleep<T> {
@@ -104,6 +132,16 @@ impl<T: ?Sized + Default> Default for MutexSleep<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexSleepGuard<'a, T> {
+    /// Returns the `MutexSleep` that this guard has locked, 
+    /// which allows the lock to be re-acquired after the guard is dropped, e.g., by a `CondVar`.
+    ///
+    /// This is an associated function rather than a method to avoid conflicting with methods on the locked data.
+    pub fn mutex(guard: &Self) -> &'a MutexSleep<T> {
+        guard.mutex
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexSleepGuard<'a, T> {
     type Target = T;
 
@@ -121,9 +159,14 @@ impl<'a, T: ?Sized> DerefMut for MutexSleepGuard<'a, T> {
 
 impl<'a, T: ?Sized> Drop for MutexSleepGuard<'a, T> {
     fn drop(&mut self) {
+        // Give up any priority that was inherited from tasks waiting for this lock.
+        // Note that this also drops priority inherited through other locks that the owner still holds.
+        if let Some(owner) = self.mutex.owner.lock().take() {
+            owner.clear_inherited_priority();
+        }
         // Notify a task on the waitqueue that the lock is released,
         // which occurs automatically when the inner `guard` is dropped after this method executes.
-        self.queue.notify_one();
+        self.mutex.queue.notify_one();
     }
 }
 
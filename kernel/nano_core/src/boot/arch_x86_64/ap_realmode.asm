@@ -58,7 +58,26 @@ bits 16 ; we're in real mode, that's how APs boot up
 
 global ap_start_realmode
 
+; This code is copied to AP_STARTUP (0x10000), so its entry points are at fixed offsets from there.
+; Offset 0 is where an AP starts executing after receiving a startup IPI.
 ap_start_realmode:
+    jmp short ap_start_realmode_boot
+
+; Offset 2 is where the BSP resumes after ACPI S3 sleep, because the FACS waking vector points here.
+; The firmware has already initialized the hardware, and the graphic mode was set at boot,
+; so we skip the BIOS calls and go straight to protected mode.
+ap_start_realmode_s3_resume:
+    cli
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov sp, 0xFC00
+    jmp gdt
+
+ap_start_realmode_boot:
     cli
 
     xor ax, ax
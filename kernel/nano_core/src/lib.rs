@@ -35,6 +35,7 @@ extern crate exceptions_early;
 extern crate panic_entry; // contains required panic-related lang items
 #[cfg(not(loadable))] extern crate captain;
 extern crate memory_initialization;
+extern crate boot_params;
 
 
 use core::ops::DerefMut;
@@ -127,6 +128,10 @@ pub extern "C" fn nano_core_start(multiboot_information_virtual_address: usize)
     trace!("state_store initialized.");
     println_raw!("nano_core_start(): initialized state store.");     
 
+    // save the kernel command line so later subsystems can check their boot parameters
+    boot_params::init(boot_info.command_line_tag().map(|tag| tag.command_line()).unwrap_or(""));
+    info!("kernel command line: {:?}", boot_params::command_line());
+
     // initialize the module management subsystem, so we can create the default crate namespace
     let default_namespace = match mod_mgmt::init(&boot_info, kernel_mmi_ref.lock().deref_mut()) {
         Ok(namespace) => namespace,
@@ -0,0 +1,180 @@
+//! Runtime configuration of network interfaces: listing them, bringing them up or down,
+//! and assigning their IP addresses and routes.
+//!
+//! Interfaces are referred to by name, e.g., `eth0` or `lo`, see `NetworkInterface::name()`.
+//! Changes take effect the next time an interface is polled.
+//! An interface that is configured via DHCP (see the `dhcp_client` crate) may have its address and default route
+//! replaced by its DHCP client when the lease is renewed or expires.
+
+#![no_std]
+
+extern crate alloc;
+extern crate smoltcp;
+extern crate network_manager;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use smoltcp::iface::Route;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv6Address};
+use network_manager::{NetworkInterface, NetworkInterfaceRef, NETWORK_INTERFACES};
+
+
+/// A snapshot of the configuration of a network interface.
+#[derive(Clone, Debug)]
+pub struct InterfaceInfo {
+    /// The index of the interface in `network_manager::NETWORK_INTERFACES`.
+    pub index: usize,
+    pub name: String,
+    pub up: bool,
+    pub loopback: bool,
+    pub ethernet_addr: EthernetAddress,
+    pub ip_addrs: Vec<IpCidr>,
+    pub routes: Vec<RouteInfo>,
+}
+
+/// A route of a network interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The destinations that the route applies to, which is `0.0.0.0/0` or `::/0` for a default route.
+    pub destination: IpCidr,
+    /// The router that packets to those destinations are sent to.
+    pub via_router: IpAddress,
+}
+
+impl RouteInfo {
+    /// Returns whether this is a default route, i.e., one that applies to all destinations.
+    pub fn is_default(&self) -> bool {
+        self.destination.prefix_len() == 0
+    }
+}
+
+
+/// Returns the configuration of every network interface.
+pub fn interfaces() -> Vec<InterfaceInfo> {
+    let interfaces = NETWORK_INTERFACES.lock().clone();
+    interfaces.iter().enumerate().map(|(index, iface)| info(index, &mut *iface.lock())).collect()
+}
+
+/// Returns the configuration of the network interface with the given name.
+pub fn interface(name: &str) -> Result<InterfaceInfo, &'static str> {
+    let (index, iface) = find_interface(name)?;
+    let info = info(index, &mut *iface.lock());
+    Ok(info)
+}
+
+/// Returns the index and a reference to the network interface with the given name.
+pub fn find_interface(name: &str) -> Result<(usize, NetworkInterfaceRef), &'static str> {
+    NETWORK_INTERFACES.lock().iter()
+        .enumerate()
+        .find(|(_, iface)| iface.lock().name() == name)
+        .map(|(index, iface)| (index, iface.clone()))
+        .ok_or("net_config: no network interface with that name")
+}
+
+/// Brings the network interface with the given name up or down.
+pub fn set_up(name: &str, up: bool) -> Result<(), &'static str> {
+    let (_, iface) = find_interface(name)?;
+    iface.lock().set_up(up);
+    Ok(())
+}
+
+/// Assigns the given address to the network interface with the given name, in addition to its other addresses.
+///
+/// An interface that has only the unspecified address `0.0.0.0/0`, e.g., one that awaits configuration via DHCP,
+/// has that address replaced instead.
+pub fn add_address(name: &str, address: IpCidr) -> Result<(), &'static str> {
+    let (_, iface) = find_interface(name)?;
+    let mut iface = iface.lock();
+    let mut addrs: Vec<IpCidr> = iface.ip_addrs().iter()
+        .filter(|addr| !addr.address().is_unspecified())
+        .cloned()
+        .collect();
+    if addrs.contains(&address) {
+        return Err("net_config: the interface already has that address");
+    }
+    addrs.push(address);
+    iface.set_ip_addrs(&addrs);
+    Ok(())
+}
+
+/// Removes the given address from the network interface with the given name.
+pub fn remove_address(name: &str, address: IpCidr) -> Result<(), &'static str> {
+    let (_, iface) = find_interface(name)?;
+    let mut iface = iface.lock();
+    let mut addrs = iface.ip_addrs().to_vec();
+    let num_addrs = addrs.len();
+    addrs.retain(|addr| *addr != address);
+    if addrs.len() == num_addrs {
+        return Err("net_config: the interface doesn't have that address");
+    }
+    iface.set_ip_addrs(&addrs);
+    Ok(())
+}
+
+/// Adds a route to the given destinations via the given router to the network interface with the given name,
+/// replacing any route it has to the same destinations.
+pub fn add_route(name: &str, destination: IpCidr, via_router: IpAddress) -> Result<(), &'static str> {
+    match (destination, via_router) {
+        (IpCidr::Ipv4(_), IpAddress::Ipv4(_)) | (IpCidr::Ipv6(_), IpAddress::Ipv6(_)) => {}
+        _ => return Err("net_config: the destination and the router must have the same IP version"),
+    }
+    let (_, iface) = find_interface(name)?;
+    let mut iface = iface.lock();
+    let route = Route { via_router, preferred_until: None, expires_at: None };
+    let mut result = Ok(());
+    iface.routes_mut().update(|routes| {
+        if routes.insert(destination, route).is_err() {
+            result = Err("net_config: the interface's route table is full");
+        }
+    });
+    result
+}
+
+/// Removes the route to the given destinations from the network interface with the given name.
+pub fn remove_route(name: &str, destination: IpCidr) -> Result<(), &'static str> {
+    let (_, iface) = find_interface(name)?;
+    let mut iface = iface.lock();
+    let mut removed = false;
+    iface.routes_mut().update(|routes| removed = routes.remove(&destination).is_some());
+    if removed { Ok(()) } else { Err("net_config: the interface has no route to that destination") }
+}
+
+/// Sets the default gateway of the network interface with the given name to the given router,
+/// replacing its default route for the router's IP version.
+pub fn set_default_gateway(name: &str, gateway: IpAddress) -> Result<(), &'static str> {
+    add_route(name, default_destination(&gateway)?, gateway)
+}
+
+/// Removes the default route of the given IP version from the network interface with the given name.
+pub fn remove_default_gateway(name: &str, ipv6: bool) -> Result<(), &'static str> {
+    let any_address = if ipv6 { IpAddress::Ipv6(Ipv6Address::UNSPECIFIED) } else { IpAddress::Ipv4(Ipv4Address::UNSPECIFIED) };
+    remove_route(name, default_destination(&any_address)?)
+}
+
+
+/// Returns the destinations of a default route for the IP version of the given address.
+fn default_destination(address: &IpAddress) -> Result<IpCidr, &'static str> {
+    match *address {
+        IpAddress::Ipv4(_) => Ok(IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)),
+        IpAddress::Ipv6(_) => Ok(IpCidr::new(Ipv6Address::UNSPECIFIED.into(), 0)),
+        _ => Err("net_config: the address must be an IPv4 or IPv6 address"),
+    }
+}
+
+fn info(index: usize, iface: &mut dyn NetworkInterface) -> InterfaceInfo {
+    let mut routes = Vec::new();
+    iface.routes_mut().update(|table| {
+        for (destination, route) in table.iter() {
+            routes.push(RouteInfo { destination: *destination, via_router: route.via_router });
+        }
+    });
+    InterfaceInfo {
+        index,
+        name: iface.name().to_string(),
+        up: iface.is_up(),
+        loopback: iface.is_loopback(),
+        ethernet_addr: iface.ethernet_addr(),
+        ip_addrs: iface.ip_addrs().to_vec(),
+        routes,
+    }
+}
@@ -0,0 +1,226 @@
+//! ICMP echo requests ("pings") over IPv4, which are sent through ICMP sockets that are referred to by handles.
+//!
+//! The simplest way to check whether a host is reachable is [`ping()`](fn.ping.html), which sends a single echo request.
+//! A series of echo requests is sent through a socket opened with [`open()`](fn.open.html),
+//! which is bound to an identifier of its own so that it only receives the replies to its own requests.
+//!
+//! Echo requests addressed to this host are answered by the interfaces themselves, without any socket.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use smoltcp::socket::{IcmpSocket, IcmpSocketBuffer, IcmpPacketMetadata, IcmpEndpoint, SocketHandle};
+use smoltcp::wire::{IpAddress, Icmpv4Packet, Icmpv4Repr};
+use smoltcp::phy::ChecksumCapabilities;
+use timer::Instant;
+use {Stack, lock_stack, now_millis, wait_until, wake_poller, WOULD_BLOCK};
+
+
+/// The error returned when a handle doesn't refer to an open ICMP socket.
+const INVALID_HANDLE: &'static str = "net_stack: invalid ICMP handle";
+/// The error returned when no echo reply was received in time.
+pub const TIMED_OUT: &'static str = "net_stack: timed out waiting for an echo reply";
+
+/// The number of packets that each of an ICMP socket's buffers can hold.
+pub const BUFFER_PACKETS: usize = 8;
+/// The size of each of an ICMP socket's buffers, in bytes.
+pub const BUFFER_SIZE: usize = BUFFER_PACKETS * 1536;
+/// The largest amount of data that an echo request can carry, such that it fits into an Ethernet frame.
+pub const MAX_DATA_SIZE: usize = 1500 - 20 - 8;
+/// The amount of data that [`ping()`](fn.ping.html) sends, which is the same as the default of most `ping` tools.
+pub const DEFAULT_DATA_SIZE: usize = 56;
+
+
+/// The handle of an open ICMP socket.
+///
+/// A handle remains valid until the socket is closed with [`close()`](#method.close), after which it is never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IcmpHandle(u64);
+
+/// A reply to an echo request.
+#[derive(Clone, Debug)]
+pub struct EchoReply {
+    /// The host that sent the reply.
+    pub from: IpAddress,
+    pub seq_no: u16,
+    /// The number of bytes of data in the reply, which echoes the data of the request.
+    pub data_size: usize,
+    /// The time between sending the request and receiving the reply, in microseconds.
+    pub rtt_us: u64,
+}
+
+/// The ICMP sockets of the network stack, with the echo identifier that each is bound to.
+pub(crate) struct IcmpSockets {
+    sockets: BTreeMap<u64, (SocketHandle, u16)>,
+    next_id: u64,
+    next_ident: u16,
+}
+
+impl IcmpSockets {
+    pub(crate) fn new() -> IcmpSockets {
+        IcmpSockets {
+            sockets: BTreeMap::new(),
+            next_id: 1,
+            next_ident: 1,
+        }
+    }
+
+    pub(crate) fn num_sockets(&self) -> usize {
+        self.sockets.len()
+    }
+
+    /// Returns an echo identifier that no open socket is bound to.
+    fn allocate_ident(&mut self) -> Result<u16, &'static str> {
+        for _ in 0 ..= u16::max_value() as usize {
+            let ident = self.next_ident;
+            self.next_ident = self.next_ident.wrapping_add(1);
+            if !self.sockets.values().any(|&(_, used)| used == ident) {
+                return Ok(ident);
+            }
+        }
+        Err("net_stack: no free ICMP identifiers")
+    }
+}
+
+
+/// Sends an echo request to the given IPv4 address and waits up to `timeout_ms` milliseconds for the reply.
+///
+/// Returns the round-trip time in microseconds, or `TIMED_OUT` if no reply arrived in time.
+pub fn ping<T: Into<IpAddress>>(address: T, timeout_ms: u64) -> Result<u64, &'static str> {
+    let socket = open()?;
+    let result = socket.ping(address, 0, DEFAULT_DATA_SIZE, timeout_ms);
+    socket.close();
+    result.map(|reply| reply.rtt_us)
+}
+
+/// Opens an ICMP socket that is bound to an unused echo identifier.
+pub fn open() -> Result<IcmpHandle, &'static str> {
+    let mut stack = lock_stack();
+    let ident = stack.icmp.allocate_ident()?;
+    stack.reserve_socket(2 * BUFFER_SIZE)?;
+    let mut socket = IcmpSocket::new(
+        IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY; BUFFER_PACKETS], vec![0; BUFFER_SIZE]),
+        IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY; BUFFER_PACKETS], vec![0; BUFFER_SIZE]),
+    );
+    if socket.bind(IcmpEndpoint::Ident(ident)).is_err() {
+        stack.release_socket(2 * BUFFER_SIZE);
+        return Err("net_stack: failed to bind the ICMP socket");
+    }
+    let handle = stack.sockets.add(socket);
+    let icmp = &mut stack.icmp;
+    let id = icmp.next_id;
+    icmp.next_id += 1;
+    icmp.sockets.insert(id, (handle, ident));
+    Ok(IcmpHandle(id))
+}
+
+/// Returns the smoltcp handle and echo identifier of the open ICMP socket with the given ID.
+fn socket_handle(stack: &Stack, id: u64) -> Result<(SocketHandle, u16), &'static str> {
+    stack.icmp.sockets.get(&id).cloned().ok_or(INVALID_HANDLE)
+}
+
+fn try_send_echo_request(stack: &mut Stack, id: u64, address: IpAddress, seq_no: u16, data: &[u8]) -> Result<(), &'static str> {
+    let (handle, ident) = socket_handle(stack, id)?;
+    let repr = Icmpv4Repr::EchoRequest { ident, seq_no, data };
+    let mut socket = stack.sockets.get::<IcmpSocket>(handle);
+    let payload = match socket.send(repr.buffer_len(), address) {
+        Ok(payload) => payload,
+        Err(smoltcp::Error::Exhausted) => return Err(WOULD_BLOCK),
+        Err(_) => return Err("net_stack: the echo request is too large for the ICMP socket's send buffer"),
+    };
+    repr.emit(&mut Icmpv4Packet::new_unchecked(payload), &ChecksumCapabilities::default());
+    Ok(())
+}
+
+/// Receives the next echo reply without blocking, and returns its sender, sequence number, and data size.
+/// Received packets that aren't valid echo replies are discarded.
+fn try_recv_echo_reply(stack: &mut Stack, id: u64) -> Result<(IpAddress, u16, usize), &'static str> {
+    let (handle, _ident) = socket_handle(stack, id)?;
+    let mut socket = stack.sockets.get::<IcmpSocket>(handle);
+    loop {
+        let (payload, from) = match socket.recv() {
+            Ok(received) => received,
+            Err(smoltcp::Error::Exhausted) => return Err(WOULD_BLOCK),
+            Err(_) => return Err("net_stack: failed to receive on the ICMP socket"),
+        };
+        let packet = match Icmpv4Packet::new_checked(payload) {
+            Ok(packet) => packet,
+            Err(_) => continue,
+        };
+        if let Ok(Icmpv4Repr::EchoReply { seq_no, data, .. }) = Icmpv4Repr::parse(&packet, &ChecksumCapabilities::default()) {
+            return Ok((from, seq_no, data.len()));
+        }
+    }
+}
+
+impl IcmpHandle {
+    /// Returns the echo identifier that this socket is bound to.
+    pub fn ident(&self) -> Result<u16, &'static str> {
+        Ok(socket_handle(&lock_stack(), self.0)?.1)
+    }
+
+    /// Queues an echo request with the given sequence number and data for sending to the given IPv4 address,
+    /// waiting for space in the send buffer as needed.
+    pub fn send_echo_request<T: Into<IpAddress>>(&self, address: T, seq_no: u16, data: &[u8]) -> Result<(), &'static str> {
+        let id = self.0;
+        let address = address.into();
+        match address {
+            IpAddress::Ipv4(_) => {}
+            _ => return Err("net_stack: only IPv4 addresses can be pinged"),
+        }
+        if data.len() > MAX_DATA_SIZE {
+            return Err("net_stack: too much data for an echo request");
+        }
+        wait_until(&|stack| match try_send_echo_request(stack, id, address, seq_no, data) {
+            Err(WOULD_BLOCK) => None,
+            result => Some(result),
+        })?;
+        wake_poller();
+        Ok(())
+    }
+
+    /// Receives the next echo reply without blocking, and returns its sender, sequence number, and data size.
+    ///
+    /// Returns `WOULD_BLOCK` if no echo reply has been received yet.
+    pub fn try_recv_echo_reply(&self) -> Result<(IpAddress, u16, usize), &'static str> {
+        try_recv_echo_reply(&mut lock_stack(), self.0)
+    }
+
+    /// Sends an echo request with the given sequence number and `data_size` bytes of data to the given IPv4 address,
+    /// and waits up to `timeout_ms` milliseconds for its reply.
+    ///
+    /// Replies to earlier requests that arrive in the meantime are discarded.
+    /// Returns `TIMED_OUT` if no reply arrived in time.
+    pub fn ping<T: Into<IpAddress>>(&self, address: T, seq_no: u16, data_size: usize, timeout_ms: u64) -> Result<EchoReply, &'static str> {
+        let id = self.0;
+        let data: Vec<u8> = (0 .. data_size).map(|i| i as u8).collect();
+        let deadline = now_millis()?.saturating_add(timeout_ms);
+        let sent_at = Instant::now();
+        self.send_echo_request(address, seq_no, &data)?;
+        let (from, data_size, received_at) = wait_until(&|stack| {
+            loop {
+                match try_recv_echo_reply(stack, id) {
+                    Ok((from, reply_seq_no, data_size)) => if reply_seq_no == seq_no {
+                        return Some(Ok((from, data_size, Instant::now())));
+                    },
+                    Err(WOULD_BLOCK) => break,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            match now_millis() {
+                Ok(now) if now < deadline => None,
+                Ok(_) => Some(Err(TIMED_OUT)),
+                Err(e) => Some(Err(e)),
+            }
+        })?;
+        Ok(EchoReply { from, seq_no, data_size, rtt_us: received_at.duration_since(sent_at).as_micros() as u64 })
+    }
+
+    /// Closes the socket, discarding any packets that haven't been sent or received, and frees its identifier.
+    pub fn close(self) {
+        let mut stack = lock_stack();
+        if let Some((handle, _ident)) = stack.icmp.sockets.remove(&self.0) {
+            stack.sockets.remove(handle);
+            stack.release_socket(2 * BUFFER_SIZE);
+        }
+    }
+}
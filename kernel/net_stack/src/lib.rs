@@ -0,0 +1,253 @@
+//! A management layer for the network stack that owns every socket and polls the network interfaces.
+//!
+//! Instead of each user of the network creating its own `SocketSet` and polling an interface by hand,
+//! all sockets created through this crate live in a single socket set that is polled by a dedicated task,
+//! which is spawned by [`init()`](fn.init.html). Sockets are referred to by handles, see the [`tcp`] module,
+//! so kernel services and applications never hold a lock on the socket set themselves.
+//! Besides TCP sockets, the [`udp`] module provides UDP sockets, the [`icmp`] module sends pings,
+//! and the [`raw`] module provides raw IP sockets for protocols that smoltcp doesn't implement.
+//!
+//! The poll task flushes every interface in `network_manager::NETWORK_INTERFACES` every [`POLL_INTERVAL_MS`],
+//! or sooner when a socket operation has queued something to send, see [`wake_poller()`](fn.wake_poller.html).
+//! After each poll, tasks that are blocked on a socket operation re-check whether they can proceed,
+//! and asynchronous tasks that are waiting for the [`next_poll()`](fn.next_poll.html) are woken up.
+//!
+//! Since every socket is polled with every interface, a packet is sent through the first interface
+//! that has a route to its destination, with loopback interfaces (see the `loopback` crate) being polled first.
+//!
+//! Code that still polls an interface with its own `SocketSet` must not do so while sockets
+//! created through this crate are open, since each poll only delivers packets to the sockets it was given.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate smoltcp;
+extern crate network_manager;
+extern crate smoltcp_helper;
+extern crate async_runtime;
+extern crate timer;
+extern crate wait_queue;
+
+pub mod tcp;
+pub mod udp;
+pub mod icmp;
+pub mod raw;
+
+pub use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+use core::convert::TryInto;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard, Once};
+use smoltcp::socket::SocketSet;
+use smoltcp::time::Instant;
+use async_runtime::{Executor, InterruptEvent, Sleep};
+use wait_queue::WaitQueue;
+use network_manager::NETWORK_INTERFACES;
+
+
+/// The longest time that the poll task waits between two polls of the network interfaces.
+pub const POLL_INTERVAL_MS: u64 = 5;
+/// The maximum number of sockets that can be open at once.
+pub const MAX_SOCKETS: usize = 1024;
+/// The maximum number of bytes of socket buffers that can be allocated at once, across all sockets.
+pub const MAX_BUFFER_MEMORY: usize = 64 * 1024 * 1024;
+
+/// The error returned by a non-blocking socket operation that cannot make progress yet.
+pub const WOULD_BLOCK: &'static str = "net_stack: operation would block";
+
+
+/// The state of the network stack: the socket set and the bookkeeping for each socket in it.
+pub(crate) struct Stack {
+    pub(crate) sockets: SocketSet<'static, 'static, 'static>,
+    pub(crate) tcp: tcp::TcpSockets,
+    pub(crate) udp: udp::UdpSockets,
+    pub(crate) icmp: icmp::IcmpSockets,
+    pub(crate) raw: raw::RawSockets,
+    /// The number of bytes of socket buffers currently allocated.
+    buffer_memory: usize,
+}
+
+impl Stack {
+    /// Returns the total number of open sockets.
+    fn num_sockets(&self) -> usize {
+        self.tcp.num_sockets() + self.udp.num_sockets() + self.icmp.num_sockets() + self.raw.num_sockets()
+    }
+
+    /// Accounts for a new socket with `bytes` bytes of buffers,
+    /// failing if that would exceed `MAX_SOCKETS` or `MAX_BUFFER_MEMORY`.
+    pub(crate) fn reserve_socket(&mut self, bytes: usize) -> Result<(), &'static str> {
+        if self.num_sockets() >= MAX_SOCKETS {
+            return Err("net_stack: too many open sockets");
+        }
+        if self.buffer_memory + bytes > MAX_BUFFER_MEMORY {
+            return Err("net_stack: out of socket buffer memory");
+        }
+        self.buffer_memory += bytes;
+        Ok(())
+    }
+
+    /// Accounts for a socket with `bytes` bytes of buffers being removed.
+    pub(crate) fn release_socket(&mut self, bytes: usize) {
+        self.buffer_memory -= bytes;
+    }
+
+    /// Returns the number of bytes of socket buffers that can still be allocated.
+    pub(crate) fn free_buffer_memory(&self) -> usize {
+        MAX_BUFFER_MEMORY - self.buffer_memory
+    }
+}
+
+lazy_static! {
+    static ref STACK: Mutex<Stack> = Mutex::new(Stack {
+        sockets: SocketSet::new(vec![]),
+        tcp: tcp::TcpSockets::new(),
+        udp: udp::UdpSockets::new(),
+        icmp: icmp::IcmpSockets::new(),
+        raw: raw::RawSockets::new(),
+        buffer_memory: 0,
+    });
+    /// Notified to make the poll task poll the interfaces without waiting for its next interval.
+    static ref WAKE_POLLER: InterruptEvent = InterruptEvent::new();
+    /// The tasks that are blocked until a socket can make progress, which are woken up after every poll.
+    static ref SOCKET_EVENTS: WaitQueue = WaitQueue::new();
+    /// The wakers of the asynchronous tasks that are waiting for the next poll, which are woken up after every poll.
+    static ref POLL_WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+}
+
+/// The number of times that the network interfaces have been polled.
+static POLL_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// The time at which the network stack was initialized, from which socket timestamps are measured.
+static STARTUP_TIME: Once<timer::Instant> = Once::new();
+static POLL_EXECUTOR: Once<Executor> = Once::new();
+
+
+/// Initializes the network stack and spawns the task that polls the network interfaces.
+pub fn init() -> Result<(), &'static str> {
+    STARTUP_TIME.call_once(timer::Instant::now);
+    let executor = POLL_EXECUTOR.call_once(Executor::new);
+    executor.spawn(Poller { sleep: async_runtime::sleep(POLL_INTERVAL_MS) });
+    executor.spawn_executor_task(String::from("net_stack_poller"))?;
+    Ok(())
+}
+
+/// Makes the poll task poll the network interfaces as soon as possible,
+/// e.g., after data has been queued for sending.
+pub fn wake_poller() {
+    WAKE_POLLER.notify();
+}
+
+/// Returns the current time as seen by the sockets, in milliseconds since the network stack was initialized.
+pub fn now_millis() -> Result<u64, &'static str> {
+    let startup_time = STARTUP_TIME.try().ok_or("net_stack: the network stack has not been initialized")?;
+    Ok(startup_time.elapsed().as_millis() as u64)
+}
+
+/// Polls every network interface once, sending and receiving packets for all sockets,
+/// and then wakes up all tasks that are waiting for a socket to make progress.
+///
+/// Returns whether any packets were sent or received.
+/// This is invoked periodically by the poll task, so it rarely needs to be invoked directly.
+pub fn poll() -> Result<bool, &'static str> {
+    let packet_io_occurred = {
+        let mut stack = STACK.lock();
+        if stack.num_sockets() == 0 {
+            return Ok(false);
+        }
+        let now = now_millis()?;
+        let timestamp = Instant::from_millis(now.try_into().map_err(|_| "net_stack: timestamp overflowed")?);
+        let mut packet_io_occurred = false;
+        let mut interfaces = NETWORK_INTERFACES.lock().clone();
+        // Loopback interfaces are polled first, so that packets to their addresses are looped back
+        // rather than routed out of another interface that happens to be polled before them.
+        interfaces.sort_by_key(|iface| !iface.lock().is_loopback());
+        for iface in interfaces.iter() {
+            match iface.lock().poll(&mut stack.sockets, timestamp) {
+                Ok(io) => packet_io_occurred |= io,
+                Err(_e) => debug!("net_stack: poll error: {}", _e),
+            }
+        }
+        tcp::after_poll(&mut stack, now);
+        packet_io_occurred
+    };
+    // The stack must be unlocked here, since waiting tasks lock it while checking whether they can proceed.
+    SOCKET_EVENTS.notify_all();
+    POLL_COUNT.fetch_add(1, Ordering::AcqRel);
+    let wakers = core::mem::replace(&mut *POLL_WAKERS.lock(), Vec::new());
+    for waker in wakers {
+        waker.wake();
+    }
+    Ok(packet_io_occurred)
+}
+
+/// Locks the network stack.
+pub(crate) fn lock_stack() -> MutexGuard<'static, Stack> {
+    STACK.lock()
+}
+
+/// Blocks the current task until the given `condition` returns `Some` or an error,
+/// re-checking it with the stack locked after every poll.
+pub(crate) fn wait_until<R>(condition: &dyn Fn(&mut Stack) -> Option<Result<R, &'static str>>) -> Result<R, &'static str> {
+    SOCKET_EVENTS.wait_until(&|| condition(&mut STACK.lock()))
+        .map_err(|_| "net_stack: failed to wait for a socket")?
+}
+
+/// Returns a future that completes once the network interfaces have been polled again,
+/// after which a non-blocking socket operation that returned `WOULD_BLOCK` may be able to proceed.
+///
+/// This lets asynchronous tasks use sockets without blocking.
+/// The interfaces are only polled while at least one socket is open.
+pub fn next_poll() -> NextPoll {
+    NextPoll { poll_count: POLL_COUNT.load(Ordering::Acquire) }
+}
+
+/// A future that completes once the network interfaces have been polled again, see [`next_poll()`](fn.next_poll.html).
+pub struct NextPoll {
+    /// The number of polls that had occurred when this future was created.
+    poll_count: u64,
+}
+
+impl Future for NextPoll {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // Register the waker before checking for a poll, so a poll in between isn't missed.
+        POLL_WAKERS.lock().push(cx.waker().clone());
+        if POLL_COUNT.load(Ordering::Acquire) != self.poll_count {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+
+/// The asynchronous task that polls the network interfaces every `POLL_INTERVAL_MS`,
+/// or immediately after `wake_poller()` is invoked.
+struct Poller {
+    sleep: Sleep,
+}
+
+impl Future for Poller {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        loop {
+            let woken = Pin::new(&mut WAKE_POLLER.wait()).poll(cx).is_ready();
+            let timed_out = Pin::new(&mut self.sleep).poll(cx).is_ready();
+            if !woken && !timed_out {
+                return Poll::Pending;
+            }
+            if let Err(e) = poll() {
+                error!("net_stack: failed to poll the network interfaces: {}", e);
+            }
+            self.sleep = async_runtime::sleep(POLL_INTERVAL_MS);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Raw IP sockets, which send and receive whole IP packets of a single protocol and are referred to by handles.
+//!
+//! A raw socket is opened with [`open()`](fn.open.html) and is then used through its [`RawHandle`].
+//! It receives a copy of every incoming packet with its IP version and protocol, on any interface,
+//! including packets that are also delivered to other sockets and packets that aren't addressed to this host,
+//! e.g., broadcasts received before the interface has an address.
+//!
+//! Outgoing packets must include their IP header; smoltcp fills in its checksum but not that of the payload.
+//! Since all sockets are polled together with every interface,
+//! an outgoing packet is sent through whichever interface is polled next that has a route to its destination.
+
+use alloc::collections::BTreeMap;
+use smoltcp::socket::{RawSocket, RawSocketBuffer, RawPacketMetadata, SocketHandle};
+use {Stack, lock_stack, wait_until, wake_poller, WOULD_BLOCK};
+
+pub use smoltcp::wire::{IpVersion, IpProtocol};
+
+
+/// The error returned when a handle doesn't refer to an open raw socket.
+const INVALID_HANDLE: &'static str = "net_stack: invalid raw socket handle";
+
+/// The number of packets that each of a raw socket's buffers can hold.
+pub const BUFFER_PACKETS: usize = 16;
+/// The size of each of a raw socket's buffers, in bytes.
+pub const BUFFER_SIZE: usize = BUFFER_PACKETS * 1536;
+
+
+/// The handle of an open raw socket.
+///
+/// A handle remains valid until the socket is closed with [`close()`](#method.close), after which it is never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RawHandle(u64);
+
+/// The raw sockets of the network stack.
+pub(crate) struct RawSockets {
+    sockets: BTreeMap<u64, SocketHandle>,
+    next_id: u64,
+}
+
+impl RawSockets {
+    pub(crate) fn new() -> RawSockets {
+        RawSockets {
+            sockets: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub(crate) fn num_sockets(&self) -> usize {
+        self.sockets.len()
+    }
+}
+
+
+/// Opens a raw socket that sends and receives packets with the given IP version and protocol.
+pub fn open(ip_version: IpVersion, protocol: IpProtocol) -> Result<RawHandle, &'static str> {
+    let mut stack = lock_stack();
+    stack.reserve_socket(2 * BUFFER_SIZE)?;
+    let socket = RawSocket::new(
+        ip_version,
+        protocol,
+        RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; BUFFER_PACKETS], vec![0; BUFFER_SIZE]),
+        RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; BUFFER_PACKETS], vec![0; BUFFER_SIZE]),
+    );
+    let handle = stack.sockets.add(socket);
+    let raw = &mut stack.raw;
+    let id = raw.next_id;
+    raw.next_id += 1;
+    raw.sockets.insert(id, handle);
+    Ok(RawHandle(id))
+}
+
+/// Returns the smoltcp handle of the open raw socket with the given ID.
+fn socket_handle(stack: &Stack, id: u64) -> Result<SocketHandle, &'static str> {
+    stack.raw.sockets.get(&id).cloned().ok_or(INVALID_HANDLE)
+}
+
+fn try_send(stack: &mut Stack, id: u64, packet: &[u8]) -> Result<(), &'static str> {
+    let handle = socket_handle(stack, id)?;
+    let mut socket = stack.sockets.get::<RawSocket>(handle);
+    match socket.send_slice(packet) {
+        Ok(()) => Ok(()),
+        Err(smoltcp::Error::Exhausted) => Err(WOULD_BLOCK),
+        Err(_) => Err("net_stack: the packet is too large for the raw socket's send buffer"),
+    }
+}
+
+fn try_recv(stack: &mut Stack, id: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let handle = socket_handle(stack, id)?;
+    let mut socket = stack.sockets.get::<RawSocket>(handle);
+    match socket.recv_slice(buffer) {
+        Ok(len) => Ok(len),
+        Err(smoltcp::Error::Exhausted) => Err(WOULD_BLOCK),
+        Err(_) => Err("net_stack: failed to receive on the raw socket"),
+    }
+}
+
+impl RawHandle {
+    /// Queues the given IP packet for sending without blocking.
+    ///
+    /// Returns `WOULD_BLOCK` if the send buffer is full.
+    pub fn try_send(&self, packet: &[u8]) -> Result<(), &'static str> {
+        try_send(&mut lock_stack(), self.0, packet)?;
+        wake_poller();
+        Ok(())
+    }
+
+    /// Queues the given IP packet for sending, waiting for space in the send buffer as needed.
+    pub fn send(&self, packet: &[u8]) -> Result<(), &'static str> {
+        let id = self.0;
+        wait_until(&|stack| match try_send(stack, id, packet) {
+            Err(WOULD_BLOCK) => None,
+            result => Some(result),
+        })?;
+        wake_poller();
+        Ok(())
+    }
+
+    /// Receives the next packet into the given `buffer` without blocking, and returns its length.
+    /// A packet that is larger than the `buffer` is truncated.
+    ///
+    /// Returns `WOULD_BLOCK` if no packet has been received yet.
+    pub fn try_recv(&self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        try_recv(&mut lock_stack(), self.0, buffer)
+    }
+
+    /// Waits until a packet has been received, receives it into the given `buffer`, and returns its length.
+    /// A packet that is larger than the `buffer` is truncated.
+    pub fn recv(&self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let id = self.0;
+        // The buffer can't be borrowed by the wait condition, so the packet is received after waiting.
+        wait_until(&|stack| match socket_handle(stack, id) {
+            Ok(handle) => if stack.sockets.get::<RawSocket>(handle).can_recv() { Some(Ok(())) } else { None },
+            Err(e) => Some(Err(e)),
+        })?;
+        self.try_recv(buffer)
+    }
+
+    /// Closes the socket, discarding any packets that haven't been sent or received.
+    pub fn close(self) {
+        let mut stack = lock_stack();
+        if let Some(handle) = stack.raw.sockets.remove(&self.0) {
+            stack.sockets.remove(handle);
+            stack.release_socket(2 * BUFFER_SIZE);
+        }
+    }
+}
@@ -0,0 +1,800 @@
+//! TCP sockets and listeners, which are referred to by handles.
+//!
+//! A connection is opened with [`connect()`](fn.connect.html), or accepted from a [`TcpListener`]
+//! created with [`listen()`](fn.listen.html), and is then used through its [`TcpHandle`].
+//! Each operation exists in a blocking form (e.g., [`TcpHandle::recv()`]) and a non-blocking form
+//! (e.g., [`TcpHandle::try_recv()`]) that returns [`WOULD_BLOCK`] instead of waiting.
+//!
+//! Each socket's buffers are allocated when it's created, with the sizes given in its [`TcpOptions`].
+//! Sizes that aren't given are chosen from the socket buffer memory that is still free,
+//! so that many sockets can be open at once while few sockets still get large buffers.
+//!
+//! smoltcp sends data as soon as the peer's receive window allows it, so this module adds congestion control
+//! on top of it (see [`CongestionControl`]) by limiting how much data is handed to smoltcp
+//! that has not yet been acknowledged. smoltcp retransmits unacknowledged segments on its own schedule;
+//! the retransmission timeout (RTO) computed here from measured round-trip times determines
+//! when a lack of acknowledgements is treated as a loss that collapses the congestion window.
+
+use core::cmp::{max, min};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use smoltcp::socket::{TcpSocket, TcpSocketBuffer, SocketHandle};
+use smoltcp::time::Duration;
+use smoltcp::wire::IpEndpoint;
+use smoltcp_helper::STARTING_FREE_PORT;
+use {Stack, lock_stack, wait_until, wake_poller, now_millis};
+
+pub use WOULD_BLOCK;
+
+pub use smoltcp::socket::TcpState;
+
+
+/// The error returned when a handle doesn't refer to an open socket or listener.
+const INVALID_HANDLE: &'static str = "net_stack: invalid TCP handle";
+
+/// The smallest socket buffer, in bytes.
+pub const MIN_BUFFER_SIZE: usize = 4 * 1024;
+/// The largest socket buffer, in bytes.
+pub const MAX_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+/// The largest buffer that is given to a socket whose buffer size isn't specified.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+/// A buffer whose size isn't specified gets at most this fraction of the free socket buffer memory.
+const AUTO_BUFFER_DIVISOR: usize = 64;
+
+/// The segment size assumed by congestion control.
+pub const SEGMENT_SIZE: usize = 1460;
+/// The initial congestion window, in segments (RFC 6928).
+const INITIAL_WINDOW_SEGMENTS: usize = 10;
+/// The clock granularity used when computing the retransmission timeout.
+const CLOCK_GRANULARITY_MS: u64 = 10;
+
+
+/// The congestion control algorithm of a TCP socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionControl {
+    /// No congestion control: data is sent as fast as the peer's receive window allows.
+    None,
+    /// Slow start and additive-increase/multiplicative-decrease congestion avoidance,
+    /// which restarts slow start with a one-segment window after a retransmission timeout (RFC 5681).
+    Reno,
+}
+
+/// The options of a TCP socket, given when it is created.
+#[derive(Clone, Debug)]
+pub struct TcpOptions {
+    /// The size of the receive buffer in bytes, which is chosen automatically if `None`.
+    pub rx_buffer_size: Option<usize>,
+    /// The size of the send buffer in bytes, which is chosen automatically if `None`.
+    pub tx_buffer_size: Option<usize>,
+    /// If set, a keep-alive segment is sent after the connection has been idle for this many milliseconds.
+    pub keep_alive_ms: Option<u64>,
+    /// If set, the connection is aborted when nothing has been received from the peer for this many milliseconds.
+    pub timeout_ms: Option<u64>,
+    /// How long [`connect()`](fn.connect.html) waits for the connection to be established.
+    pub connect_timeout_ms: u64,
+    /// The hop limit (TTL) of outgoing packets, or `None` for the default.
+    pub hop_limit: Option<u8>,
+    pub congestion_control: CongestionControl,
+    /// The retransmission timeout used before any round-trip time has been measured.
+    pub initial_rto_ms: u64,
+    /// The lower bound of the retransmission timeout.
+    pub min_rto_ms: u64,
+    /// The upper bound of the retransmission timeout, which limits its exponential backoff.
+    pub max_rto_ms: u64,
+}
+
+impl Default for TcpOptions {
+    fn default() -> TcpOptions {
+        TcpOptions {
+            rx_buffer_size: None,
+            tx_buffer_size: None,
+            keep_alive_ms: None,
+            timeout_ms: None,
+            connect_timeout_ms: 10_000,
+            hop_limit: None,
+            congestion_control: CongestionControl::Reno,
+            initial_rto_ms: 1000,
+            min_rto_ms: 200,
+            max_rto_ms: 60_000,
+        }
+    }
+}
+
+
+/// A snapshot of the state of a TCP socket.
+#[derive(Clone, Debug)]
+pub struct TcpInfo {
+    pub handle: TcpHandle,
+    pub state: TcpState,
+    pub local_endpoint: IpEndpoint,
+    pub remote_endpoint: IpEndpoint,
+    /// The listener whose backlog this socket is in, if it hasn't been accepted yet.
+    pub listener: Option<TcpListener>,
+    /// The number of bytes in the send buffer, which have either not been sent or not been acknowledged.
+    pub send_queue: usize,
+    /// The number of received bytes that have not yet been read.
+    pub recv_queue: usize,
+    pub rx_buffer_size: usize,
+    pub tx_buffer_size: usize,
+    pub congestion_control: CongestionControl,
+    /// The maximum number of unacknowledged bytes.
+    pub congestion_window: usize,
+    pub slow_start_threshold: usize,
+    pub smoothed_rtt_ms: Option<u64>,
+    pub rto_ms: u64,
+}
+
+
+/// The handle of an open TCP socket.
+///
+/// A handle remains valid until the socket is closed with [`close()`](#method.close) or [`abort()`](#method.abort),
+/// after which it is never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TcpHandle(u64);
+
+/// The handle of a TCP listener, which accepts incoming connections on a port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TcpListener(u64);
+
+
+/// The congestion control state of a socket.
+struct Congestion {
+    algorithm: CongestionControl,
+    window: usize,
+    threshold: usize,
+    /// The largest useful window, which is the size of the send buffer.
+    max_window: usize,
+    /// The total number of bytes handed to smoltcp.
+    sent: u64,
+    /// The total number of bytes that the peer has acknowledged.
+    acked: u64,
+    /// The time at which the peer last acknowledged new data, or at which data was sent while none was in flight.
+    last_progress: u64,
+    /// The byte count that must be acknowledged to complete the current round-trip time measurement,
+    /// and the time at which it started.
+    rtt_sample: Option<(u64, u64)>,
+    smoothed_rtt: Option<u64>,
+    rtt_variation: u64,
+    rto: u64,
+    min_rto: u64,
+    max_rto: u64,
+}
+
+impl Congestion {
+    fn new(options: &TcpOptions, tx_buffer_size: usize) -> Congestion {
+        let min_rto = max(options.min_rto_ms, 1);
+        let max_rto = max(options.max_rto_ms, min_rto);
+        Congestion {
+            algorithm: options.congestion_control,
+            window: min(INITIAL_WINDOW_SEGMENTS * SEGMENT_SIZE, tx_buffer_size),
+            threshold: tx_buffer_size,
+            max_window: tx_buffer_size,
+            sent: 0,
+            acked: 0,
+            last_progress: 0,
+            rtt_sample: None,
+            smoothed_rtt: None,
+            rtt_variation: 0,
+            rto: min(max(options.initial_rto_ms, min_rto), max_rto),
+            min_rto,
+            max_rto,
+        }
+    }
+
+    /// Returns how many more bytes may be handed to smoltcp while `in_flight` bytes are unacknowledged.
+    fn sendable(&self, in_flight: usize) -> usize {
+        match self.algorithm {
+            CongestionControl::None => usize::max_value(),
+            CongestionControl::Reno => self.window.saturating_sub(in_flight),
+        }
+    }
+
+    /// Accounts for `bytes` more bytes being handed to smoltcp while `in_flight` bytes were already unacknowledged.
+    fn on_send(&mut self, bytes: usize, in_flight: usize, now: u64) {
+        if in_flight == 0 {
+            self.last_progress = now;
+        }
+        self.sent += bytes as u64;
+        if self.rtt_sample.is_none() {
+            self.rtt_sample = Some((self.sent, now));
+        }
+    }
+
+    /// Updates the congestion window after a poll, given the number of bytes that are still unacknowledged.
+    fn on_poll(&mut self, in_flight: usize, now: u64) {
+        let acked = self.sent.saturating_sub(in_flight as u64);
+        let newly_acked = acked.saturating_sub(self.acked) as usize;
+        self.acked = max(self.acked, acked);
+
+        if newly_acked > 0 {
+            self.last_progress = now;
+            if let Some((until, started)) = self.rtt_sample {
+                if self.acked >= until {
+                    self.update_rtt(now.saturating_sub(started));
+                    self.rtt_sample = None;
+                }
+            }
+            if self.window < self.threshold {
+                // Slow start: grow by the number of bytes acknowledged.
+                self.window += newly_acked;
+            } else {
+                // Congestion avoidance: grow by about one segment per window of acknowledged data.
+                self.window += max(1, SEGMENT_SIZE * newly_acked / self.window);
+            }
+            self.window = min(self.window, self.max_window);
+        } else if in_flight > 0 && now.saturating_sub(self.last_progress) > self.rto {
+            // Nothing was acknowledged within the RTO, so treat the data in flight as lost.
+            self.threshold = max(in_flight / 2, 2 * SEGMENT_SIZE);
+            self.window = min(SEGMENT_SIZE, self.max_window);
+            self.rto = min(self.rto.saturating_mul(2), self.max_rto);
+            self.last_progress = now;
+            // Karn's algorithm: a measurement that spans a retransmission is ambiguous.
+            self.rtt_sample = None;
+        }
+    }
+
+    /// Updates the smoothed round-trip time and the RTO with a new measurement (RFC 6298).
+    fn update_rtt(&mut self, rtt: u64) {
+        let smoothed_rtt = match self.smoothed_rtt {
+            None => {
+                self.rtt_variation = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                let difference = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                self.rtt_variation = (3 * self.rtt_variation + difference) / 4;
+                (7 * srtt + rtt) / 8
+            }
+        };
+        self.smoothed_rtt = Some(smoothed_rtt);
+        let rto = smoothed_rtt + max(CLOCK_GRANULARITY_MS, 4 * self.rtt_variation);
+        self.rto = min(max(rto, self.min_rto), self.max_rto);
+    }
+}
+
+
+/// The bookkeeping of one TCP socket in the socket set.
+struct SocketEntry {
+    handle: SocketHandle,
+    rx_buffer_size: usize,
+    tx_buffer_size: usize,
+    congestion: Congestion,
+    /// The listener whose backlog this socket is in, until it's accepted.
+    listener: Option<u64>,
+    /// Whether the socket's owner has closed it, such that it's removed once its connection has closed.
+    released: bool,
+}
+
+/// The bookkeeping of a listener.
+struct ListenerEntry {
+    port: u16,
+    backlog: usize,
+    options: TcpOptions,
+    /// The sockets that are listening for a new connection.
+    listening: Vec<u64>,
+    /// The sockets that have received a connection, in the order they received it, which have not been accepted yet.
+    incoming: VecDeque<u64>,
+}
+
+/// The TCP sockets and listeners of the network stack.
+pub(crate) struct TcpSockets {
+    sockets: BTreeMap<u64, SocketEntry>,
+    listeners: BTreeMap<u64, ListenerEntry>,
+    next_id: u64,
+    next_local_port: u16,
+}
+
+impl TcpSockets {
+    pub(crate) fn new() -> TcpSockets {
+        TcpSockets {
+            sockets: BTreeMap::new(),
+            listeners: BTreeMap::new(),
+            next_id: 1,
+            next_local_port: STARTING_FREE_PORT,
+        }
+    }
+
+    pub(crate) fn num_sockets(&self) -> usize {
+        self.sockets.len()
+    }
+}
+
+
+/// Returns the size of a socket buffer, given the requested size (if any) and the free socket buffer memory.
+fn buffer_size(requested: Option<usize>, free_memory: usize) -> usize {
+    let size = requested.unwrap_or_else(|| min(free_memory / AUTO_BUFFER_DIVISOR, DEFAULT_BUFFER_SIZE));
+    min(max(size, MIN_BUFFER_SIZE), MAX_BUFFER_SIZE)
+}
+
+/// Creates a new closed socket with the given options, and returns its ID.
+fn create_socket(stack: &mut Stack, options: &TcpOptions, listener: Option<u64>) -> Result<u64, &'static str> {
+    let free_memory = stack.free_buffer_memory();
+    let rx_buffer_size = buffer_size(options.rx_buffer_size, free_memory);
+    let tx_buffer_size = buffer_size(options.tx_buffer_size, free_memory);
+    stack.reserve_socket(rx_buffer_size + tx_buffer_size)?;
+
+    let mut socket = TcpSocket::new(
+        TcpSocketBuffer::new(vec![0; rx_buffer_size]),
+        TcpSocketBuffer::new(vec![0; tx_buffer_size]),
+    );
+    socket.set_keep_alive(options.keep_alive_ms.map(Duration::from_millis));
+    socket.set_timeout(options.timeout_ms.map(Duration::from_millis));
+    socket.set_hop_limit(options.hop_limit);
+    let handle = stack.sockets.add(socket);
+
+    let tcp = &mut stack.tcp;
+    let id = tcp.next_id;
+    tcp.next_id += 1;
+    tcp.sockets.insert(id, SocketEntry {
+        handle,
+        rx_buffer_size,
+        tx_buffer_size,
+        congestion: Congestion::new(options, tx_buffer_size),
+        listener,
+        released: false,
+    });
+    Ok(id)
+}
+
+/// Removes the socket with the given ID from the socket set, freeing its buffers.
+fn remove_socket(stack: &mut Stack, id: u64) {
+    if let Some(entry) = stack.tcp.sockets.remove(&id) {
+        stack.sockets.remove(entry.handle);
+        stack.release_socket(entry.rx_buffer_size + entry.tx_buffer_size);
+    }
+}
+
+/// Returns the smoltcp handle of the open socket with the given ID.
+fn socket_handle(stack: &Stack, id: u64) -> Result<SocketHandle, &'static str> {
+    match stack.tcp.sockets.get(&id) {
+        Some(entry) if !entry.released => Ok(entry.handle),
+        _ => Err(INVALID_HANDLE),
+    }
+}
+
+/// Returns whether the given local port is used by any socket or listener.
+fn port_in_use(stack: &mut Stack, port: u16) -> bool {
+    let Stack { ref mut sockets, ref tcp, .. } = *stack;
+    tcp.listeners.values().any(|listener| listener.port == port)
+        || tcp.sockets.values().any(|entry| sockets.get::<TcpSocket>(entry.handle).local_endpoint().port == port)
+}
+
+/// Returns an unused local port from the range of ports that aren't reserved for well-known services.
+fn allocate_local_port(stack: &mut Stack) -> Result<u16, &'static str> {
+    let num_ports = (u16::max_value() - STARTING_FREE_PORT) as usize + 1;
+    for _ in 0 .. num_ports {
+        let port = stack.tcp.next_local_port;
+        stack.tcp.next_local_port = if port == u16::max_value() { STARTING_FREE_PORT } else { port + 1 };
+        if !port_in_use(stack, port) {
+            return Ok(port);
+        }
+    }
+    Err("net_stack: no free local ports")
+}
+
+
+/// Opens a TCP connection to the given remote endpoint and waits until it's established,
+/// or until `options.connect_timeout_ms` milliseconds have passed.
+pub fn connect<T: Into<IpEndpoint>>(remote_endpoint: T, options: TcpOptions) -> Result<TcpHandle, &'static str> {
+    let handle = start_connect(remote_endpoint, &options)?;
+    let id = handle.0;
+    let deadline = now_millis()?.saturating_add(options.connect_timeout_ms);
+    let result = wait_until(&|stack| {
+        match connect_progress(stack, id) {
+            Ok(true) => return Some(Ok(())),
+            Ok(false) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        match now_millis() {
+            Ok(now) if now < deadline => None,
+            Ok(_) => Some(Err("net_stack: timed out while connecting")),
+            Err(e) => Some(Err(e)),
+        }
+    });
+    match result {
+        Ok(()) => Ok(handle),
+        Err(e) => {
+            remove_socket(&mut lock_stack(), id);
+            Err(e)
+        }
+    }
+}
+
+/// Starts opening a TCP connection to the given remote endpoint, without waiting for it to be established.
+///
+/// The connection's progress can be checked with [`TcpHandle::is_connected()`](struct.TcpHandle.html#method.is_connected),
+/// and data that is sent before then is sent once the connection has been established.
+/// `options.connect_timeout_ms` is ignored, so the caller must abort the connection if it takes too long.
+pub fn start_connect<T: Into<IpEndpoint>>(remote_endpoint: T, options: &TcpOptions) -> Result<TcpHandle, &'static str> {
+    let remote_endpoint = remote_endpoint.into();
+    let mut stack = lock_stack();
+    let id = create_socket(&mut stack, options, None)?;
+    let result = allocate_local_port(&mut stack).and_then(|local_port| {
+        let handle = socket_handle(&stack, id)?;
+        stack.sockets.get::<TcpSocket>(handle).connect(remote_endpoint, local_port)
+            .map_err(|_| "net_stack: failed to start connecting the TCP socket")
+    });
+    if let Err(e) = result {
+        remove_socket(&mut stack, id);
+        return Err(e);
+    }
+    drop(stack);
+    wake_poller();
+    Ok(TcpHandle(id))
+}
+
+/// Returns whether the connection of the socket with the given ID has been established,
+/// or an error if it was refused.
+fn connect_progress(stack: &mut Stack, id: u64) -> Result<bool, &'static str> {
+    let handle = socket_handle(stack, id)?;
+    match stack.sockets.get::<TcpSocket>(handle).state() {
+        TcpState::SynSent | TcpState::SynReceived => Ok(false),
+        TcpState::Closed => Err("net_stack: the connection was refused"),
+        _ => Ok(true),
+    }
+}
+
+/// Starts listening for incoming connections on the given local `port`,
+/// keeping up to `backlog` connections that have not yet been accepted.
+pub fn listen(port: u16, backlog: usize, options: TcpOptions) -> Result<TcpListener, &'static str> {
+    if port == 0 {
+        return Err("net_stack: cannot listen on port 0");
+    }
+    if backlog == 0 {
+        return Err("net_stack: a listener's backlog must not be empty");
+    }
+    let mut stack = lock_stack();
+    if port_in_use(&mut stack, port) {
+        return Err("net_stack: the port is already in use");
+    }
+    let id = stack.tcp.next_id;
+    stack.tcp.next_id += 1;
+    stack.tcp.listeners.insert(id, ListenerEntry {
+        port,
+        backlog,
+        options,
+        listening: Vec::new(),
+        incoming: VecDeque::new(),
+    });
+    if let Err(e) = replenish_listener(&mut stack, id) {
+        close_listener(&mut stack, id);
+        return Err(e);
+    }
+    Ok(TcpListener(id))
+}
+
+/// Creates listening sockets for the given listener until its backlog is full.
+fn replenish_listener(stack: &mut Stack, listener_id: u64) -> Result<(), &'static str> {
+    loop {
+        let (port, options) = match stack.tcp.listeners.get(&listener_id) {
+            Some(l) if l.listening.len() + l.incoming.len() < l.backlog => (l.port, l.options.clone()),
+            Some(_) => return Ok(()),
+            None => return Err(INVALID_HANDLE),
+        };
+        let id = create_socket(stack, &options, Some(listener_id))?;
+        let handle = socket_handle(stack, id)?;
+        if stack.sockets.get::<TcpSocket>(handle).listen(port).is_err() {
+            remove_socket(stack, id);
+            return Err("net_stack: failed to listen on the TCP socket");
+        }
+        if let Some(listener) = stack.tcp.listeners.get_mut(&listener_id) {
+            listener.listening.push(id);
+        }
+    }
+}
+
+/// Closes the given listener, removing its listening sockets and aborting its connections that weren't accepted.
+fn close_listener(stack: &mut Stack, listener_id: u64) {
+    if let Some(listener) = stack.tcp.listeners.remove(&listener_id) {
+        for id in listener.listening {
+            remove_socket(stack, id);
+        }
+        for id in listener.incoming {
+            if let Some(entry) = stack.tcp.sockets.get_mut(&id) {
+                entry.released = true;
+                let handle = entry.handle;
+                stack.sockets.get::<TcpSocket>(handle).abort();
+            }
+        }
+    }
+}
+
+/// Returns the first connection in the given listener's backlog that has been established,
+/// removing it from the backlog.
+fn take_established(stack: &mut Stack, listener_id: u64) -> Result<Option<u64>, &'static str> {
+    let Stack { ref mut sockets, ref mut tcp, .. } = *stack;
+    let TcpSockets { sockets: ref mut entries, ref mut listeners, .. } = *tcp;
+    let listener = listeners.get_mut(&listener_id).ok_or(INVALID_HANDLE)?;
+    let position = listener.incoming.iter().position(|id| {
+        entries.get(id).map_or(false, |entry| {
+            let state = sockets.get::<TcpSocket>(entry.handle).state();
+            state != TcpState::SynReceived && state != TcpState::Listen
+        })
+    });
+    let id = match position.and_then(|position| listener.incoming.remove(position)) {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    if let Some(entry) = entries.get_mut(&id) {
+        entry.listener = None;
+    }
+    Ok(Some(id))
+}
+
+impl TcpListener {
+    /// Waits for an incoming connection and returns the handle of its socket.
+    pub fn accept(&self) -> Result<TcpHandle, &'static str> {
+        let listener_id = self.0;
+        wait_until(&|stack| match try_accept(stack, listener_id) {
+            Err(WOULD_BLOCK) => None,
+            result => Some(result),
+        })
+    }
+
+    /// Returns the handle of an incoming connection's socket, or `WOULD_BLOCK` if there is none yet.
+    pub fn try_accept(&self) -> Result<TcpHandle, &'static str> {
+        try_accept(&mut lock_stack(), self.0)
+    }
+
+    /// Returns the local port that this listener accepts connections on.
+    pub fn port(&self) -> Result<u16, &'static str> {
+        lock_stack().tcp.listeners.get(&self.0).map(|listener| listener.port).ok_or(INVALID_HANDLE)
+    }
+
+    /// Stops accepting connections, and aborts the connections that have not been accepted yet.
+    /// Connections that have already been accepted are not affected.
+    pub fn close(self) {
+        close_listener(&mut lock_stack(), self.0);
+        wake_poller();
+    }
+}
+
+fn try_accept(stack: &mut Stack, listener_id: u64) -> Result<TcpHandle, &'static str> {
+    let id = take_established(stack, listener_id)?.ok_or(WOULD_BLOCK)?;
+    // Replace the accepted connection in the backlog with a new listening socket.
+    if let Err(_e) = replenish_listener(stack, listener_id) {
+        warn!("net_stack: couldn't replenish the backlog of a listener: {}", _e);
+    }
+    Ok(TcpHandle(id))
+}
+
+
+impl TcpHandle {
+    /// Returns whether the connection has been established, which is only `false` after
+    /// [`start_connect()`](fn.start_connect.html), or an error if the connection was refused.
+    pub fn is_connected(&self) -> Result<bool, &'static str> {
+        connect_progress(&mut lock_stack(), self.0)
+    }
+
+    /// Sends as much of the given `data` as possible without blocking, and returns how many bytes were sent.
+    ///
+    /// Returns `WOULD_BLOCK` if no data could be sent, because the send buffer or the congestion window is full.
+    pub fn try_send(&self, data: &[u8]) -> Result<usize, &'static str> {
+        let sent = try_send(&mut lock_stack(), self.0, data)?;
+        wake_poller();
+        Ok(sent)
+    }
+
+    /// Sends all of the given `data`, waiting for space in the send buffer and the congestion window as needed.
+    pub fn send(&self, data: &[u8]) -> Result<(), &'static str> {
+        let id = self.0;
+        let mut sent = 0;
+        while sent < data.len() {
+            let remaining = &data[sent ..];
+            sent += wait_until(&|stack| match try_send(stack, id, remaining) {
+                Err(WOULD_BLOCK) => None,
+                result => Some(result),
+            })?;
+            wake_poller();
+        }
+        Ok(())
+    }
+
+    /// Receives data into the given `buffer` without blocking, and returns the number of bytes received,
+    /// which is `0` once the peer has closed the connection and all data has been received.
+    ///
+    /// Returns `WOULD_BLOCK` if no data has been received yet.
+    pub fn try_recv(&self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let received = try_recv(&mut lock_stack(), self.0, buffer)?;
+        // Receiving opens the receive window, which the peer should learn about.
+        wake_poller();
+        Ok(received)
+    }
+
+    /// Waits until data has been received, receives it into the given `buffer`, and returns the number of bytes received,
+    /// which is `0` once the peer has closed the connection and all data has been received.
+    pub fn recv(&self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let id = self.0;
+        let buffer_len = buffer.len();
+        // The buffer can't be borrowed by the wait condition, so the data is received after waiting.
+        wait_until(&|stack| match socket_handle(stack, id) {
+            Ok(handle) => {
+                let socket = stack.sockets.get::<TcpSocket>(handle);
+                if socket.can_recv() || !socket.may_recv() || buffer_len == 0 { Some(Ok(())) } else { None }
+            }
+            Err(e) => Some(Err(e)),
+        })?;
+        self.try_recv(buffer)
+    }
+
+    /// Closes the sending half of the connection once all sent data has been transmitted,
+    /// and releases this handle; the socket is removed once the connection has closed.
+    pub fn close(self) {
+        release(self.0, |socket| socket.close());
+    }
+
+    /// Resets the connection immediately, discarding any data that hasn't been sent or received, and releases this handle.
+    pub fn abort(self) {
+        release(self.0, |socket| socket.abort());
+    }
+
+    /// Returns the current state of this socket.
+    pub fn info(&self) -> Result<TcpInfo, &'static str> {
+        let mut stack = lock_stack();
+        let handle = socket_handle(&stack, self.0)?;
+        Ok(socket_info(&mut stack, self.0, handle))
+    }
+
+    /// Sets how long the connection may be idle before a keep-alive segment is sent, or disables keep-alives.
+    pub fn set_keep_alive(&self, keep_alive_ms: Option<u64>) -> Result<(), &'static str> {
+        with_socket(self.0, |socket| socket.set_keep_alive(keep_alive_ms.map(Duration::from_millis)))
+    }
+
+    /// Sets how long the connection may go without receiving anything from the peer before it's aborted,
+    /// or disables this timeout.
+    pub fn set_timeout(&self, timeout_ms: Option<u64>) -> Result<(), &'static str> {
+        with_socket(self.0, |socket| socket.set_timeout(timeout_ms.map(Duration::from_millis)))
+    }
+
+    /// Changes the congestion control algorithm, keeping the current congestion window.
+    pub fn set_congestion_control(&self, algorithm: CongestionControl) -> Result<(), &'static str> {
+        let mut stack = lock_stack();
+        socket_handle(&stack, self.0)?;
+        let entry = stack.tcp.sockets.get_mut(&self.0).ok_or(INVALID_HANDLE)?;
+        entry.congestion.algorithm = algorithm;
+        Ok(())
+    }
+}
+
+/// Invokes the given function on the open socket with the given ID.
+fn with_socket<R, F: FnOnce(&mut TcpSocket) -> R>(id: u64, f: F) -> Result<R, &'static str> {
+    let mut stack = lock_stack();
+    let handle = socket_handle(&stack, id)?;
+    let result = f(&mut stack.sockets.get::<TcpSocket>(handle));
+    Ok(result)
+}
+
+/// Invokes the given function to start closing the open socket with the given ID, and marks it as released.
+fn release<F: FnOnce(&mut TcpSocket)>(id: u64, f: F) {
+    let mut stack = lock_stack();
+    if let Ok(handle) = socket_handle(&stack, id) {
+        f(&mut stack.sockets.get::<TcpSocket>(handle));
+        if let Some(entry) = stack.tcp.sockets.get_mut(&id) {
+            entry.released = true;
+        }
+    }
+    drop(stack);
+    wake_poller();
+}
+
+fn try_send(stack: &mut Stack, id: u64, data: &[u8]) -> Result<usize, &'static str> {
+    let handle = socket_handle(stack, id)?;
+    let now = now_millis()?;
+    let Stack { ref mut sockets, ref mut tcp, .. } = *stack;
+    let mut socket = sockets.get::<TcpSocket>(handle);
+    if !socket.may_send() {
+        return Err("net_stack: the connection is closed for sending");
+    }
+    if data.is_empty() {
+        return Ok(0);
+    }
+    let entry = tcp.sockets.get_mut(&id).ok_or(INVALID_HANDLE)?;
+    let in_flight = socket.send_queue();
+    let sendable = min(entry.congestion.sendable(in_flight), data.len());
+    let sent = socket.send_slice(&data[.. sendable]).map_err(|_| "net_stack: failed to send on the TCP socket")?;
+    if sent == 0 {
+        return Err(WOULD_BLOCK);
+    }
+    entry.congestion.on_send(sent, in_flight, now);
+    Ok(sent)
+}
+
+fn try_recv(stack: &mut Stack, id: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let handle = socket_handle(stack, id)?;
+    let mut socket = stack.sockets.get::<TcpSocket>(handle);
+    if socket.can_recv() {
+        socket.recv_slice(buffer).map_err(|_| "net_stack: failed to receive on the TCP socket")
+    } else if !socket.may_recv() {
+        Ok(0)
+    } else {
+        Err(WOULD_BLOCK)
+    }
+}
+
+fn socket_info(stack: &mut Stack, id: u64, handle: SocketHandle) -> TcpInfo {
+    let socket = stack.sockets.get::<TcpSocket>(handle);
+    let entry = &stack.tcp.sockets[&id];
+    TcpInfo {
+        handle: TcpHandle(id),
+        state: socket.state(),
+        local_endpoint: socket.local_endpoint(),
+        remote_endpoint: socket.remote_endpoint(),
+        listener: entry.listener.map(TcpListener),
+        send_queue: socket.send_queue(),
+        recv_queue: socket.recv_queue(),
+        rx_buffer_size: entry.rx_buffer_size,
+        tx_buffer_size: entry.tx_buffer_size,
+        congestion_control: entry.congestion.algorithm,
+        congestion_window: entry.congestion.window,
+        slow_start_threshold: entry.congestion.threshold,
+        smoothed_rtt_ms: entry.congestion.smoothed_rtt,
+        rto_ms: entry.congestion.rto,
+    }
+}
+
+/// Returns the state of every TCP socket, including the listening sockets of listeners
+/// and the sockets that have been closed but whose connections haven't finished closing.
+pub fn sockets() -> Vec<TcpInfo> {
+    let mut stack = lock_stack();
+    let sockets: Vec<(u64, SocketHandle)> = stack.tcp.sockets.iter().map(|(&id, entry)| (id, entry.handle)).collect();
+    sockets.into_iter().map(|(id, handle)| socket_info(&mut stack, id, handle)).collect()
+}
+
+
+/// Updates the congestion control state of every socket, removes released sockets whose connections have closed,
+/// and moves the connections received by listeners into their backlogs.
+pub(crate) fn after_poll(stack: &mut Stack, now: u64) {
+    let mut closed = Vec::new();
+    let mut received = Vec::new();
+    {
+        let Stack { ref mut sockets, ref mut tcp, .. } = *stack;
+        for (&id, entry) in tcp.sockets.iter_mut() {
+            let socket = sockets.get::<TcpSocket>(entry.handle);
+            let state = socket.state();
+            entry.congestion.on_poll(socket.send_queue(), now);
+            // The TIME-WAIT state is skipped, since removing the socket frees its port and buffers sooner.
+            if entry.released && (state == TcpState::Closed || state == TcpState::TimeWait) {
+                closed.push(id);
+            } else if state != TcpState::Listen && state != TcpState::Closed {
+                if let Some(listener_id) = entry.listener {
+                    received.push((listener_id, id));
+                }
+            }
+        }
+    }
+    for id in closed {
+        remove_socket(stack, id);
+    }
+
+    for (listener_id, id) in received {
+        if let Some(listener) = stack.tcp.listeners.get_mut(&listener_id) {
+            if let Some(position) = listener.listening.iter().position(|&l| l == id) {
+                listener.listening.remove(position);
+                listener.incoming.push_back(id);
+            }
+        }
+    }
+    // A connection that was reset during its handshake returns to listening, and one that closed is discarded.
+    let listener_ids: Vec<u64> = stack.tcp.listeners.keys().cloned().collect();
+    for listener_id in listener_ids {
+        let incoming: Vec<u64> = stack.tcp.listeners[&listener_id].incoming.iter().cloned().collect();
+        for id in incoming {
+            let state = match stack.tcp.sockets.get(&id) {
+                Some(entry) => stack.sockets.get::<TcpSocket>(entry.handle).state(),
+                None => TcpState::Closed,
+            };
+            if state == TcpState::Listen || state == TcpState::Closed {
+                let listener = stack.tcp.listeners.get_mut(&listener_id).unwrap();
+                listener.incoming.retain(|&i| i != id);
+                if state == TcpState::Listen {
+                    listener.listening.push(id);
+                } else {
+                    remove_socket(stack, id);
+                }
+            }
+        }
+        if let Err(_e) = replenish_listener(stack, listener_id) {
+            debug!("net_stack: couldn't replenish the backlog of a listener: {}", _e);
+        }
+    }
+}
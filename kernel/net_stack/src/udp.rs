@@ -0,0 +1,183 @@
+//! UDP sockets, which are referred to by handles.
+//!
+//! A socket is bound to a local port with [`bind()`](fn.bind.html), and then sends datagrams to
+//! and receives datagrams from any remote endpoint through its [`UdpHandle`].
+//! Each operation exists in a blocking form (e.g., [`UdpHandle::recv_from()`]) and a non-blocking form
+//! (e.g., [`UdpHandle::try_recv_from()`]) that returns `WOULD_BLOCK` instead of waiting.
+
+use alloc::collections::BTreeMap;
+use smoltcp::socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata, SocketHandle};
+use smoltcp::wire::IpEndpoint;
+use smoltcp_helper::STARTING_FREE_PORT;
+use {Stack, lock_stack, wait_until, wake_poller, WOULD_BLOCK};
+
+
+/// The error returned when a handle doesn't refer to an open UDP socket.
+const INVALID_HANDLE: &'static str = "net_stack: invalid UDP handle";
+
+/// The number of datagrams that each of a UDP socket's buffers can hold.
+pub const BUFFER_PACKETS: usize = 32;
+/// The size of each of a UDP socket's buffers, in bytes.
+pub const BUFFER_SIZE: usize = BUFFER_PACKETS * 1536;
+
+
+/// The handle of an open UDP socket.
+///
+/// A handle remains valid until the socket is closed with [`close()`](#method.close), after which it is never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UdpHandle(u64);
+
+/// The UDP sockets of the network stack.
+pub(crate) struct UdpSockets {
+    sockets: BTreeMap<u64, SocketHandle>,
+    next_id: u64,
+    next_local_port: u16,
+}
+
+impl UdpSockets {
+    pub(crate) fn new() -> UdpSockets {
+        UdpSockets {
+            sockets: BTreeMap::new(),
+            next_id: 1,
+            next_local_port: STARTING_FREE_PORT,
+        }
+    }
+
+    pub(crate) fn num_sockets(&self) -> usize {
+        self.sockets.len()
+    }
+}
+
+
+/// Returns whether the given local port is used by any UDP socket.
+fn port_in_use(stack: &mut Stack, port: u16) -> bool {
+    let Stack { ref mut sockets, ref udp, .. } = *stack;
+    udp.sockets.values().any(|&handle| sockets.get::<UdpSocket>(handle).endpoint().port == port)
+}
+
+/// Returns an unused local port from the range of ports that aren't reserved for well-known services.
+fn allocate_local_port(stack: &mut Stack) -> Result<u16, &'static str> {
+    let num_ports = (u16::max_value() - STARTING_FREE_PORT) as usize + 1;
+    for _ in 0 .. num_ports {
+        let port = stack.udp.next_local_port;
+        stack.udp.next_local_port = if port == u16::max_value() { STARTING_FREE_PORT } else { port + 1 };
+        if !port_in_use(stack, port) {
+            return Ok(port);
+        }
+    }
+    Err("net_stack: no free local ports")
+}
+
+/// Opens a UDP socket bound to the given local `port`, or to an unused port if `port` is `0`.
+pub fn bind(port: u16) -> Result<UdpHandle, &'static str> {
+    let mut stack = lock_stack();
+    let port = if port == 0 {
+        allocate_local_port(&mut stack)?
+    } else if port_in_use(&mut stack, port) {
+        return Err("net_stack: the UDP port is already in use");
+    } else {
+        port
+    };
+    stack.reserve_socket(2 * BUFFER_SIZE)?;
+    let mut socket = UdpSocket::new(
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; BUFFER_PACKETS], vec![0; BUFFER_SIZE]),
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; BUFFER_PACKETS], vec![0; BUFFER_SIZE]),
+    );
+    if socket.bind(port).is_err() {
+        stack.release_socket(2 * BUFFER_SIZE);
+        return Err("net_stack: failed to bind the UDP socket");
+    }
+    let handle = stack.sockets.add(socket);
+    let udp = &mut stack.udp;
+    let id = udp.next_id;
+    udp.next_id += 1;
+    udp.sockets.insert(id, handle);
+    Ok(UdpHandle(id))
+}
+
+/// Returns the smoltcp handle of the open UDP socket with the given ID.
+fn socket_handle(stack: &Stack, id: u64) -> Result<SocketHandle, &'static str> {
+    stack.udp.sockets.get(&id).cloned().ok_or(INVALID_HANDLE)
+}
+
+fn try_send_to(stack: &mut Stack, id: u64, data: &[u8], remote_endpoint: IpEndpoint) -> Result<(), &'static str> {
+    let handle = socket_handle(stack, id)?;
+    let mut socket = stack.sockets.get::<UdpSocket>(handle);
+    match socket.send_slice(data, remote_endpoint) {
+        Ok(()) => Ok(()),
+        Err(smoltcp::Error::Exhausted) => Err(WOULD_BLOCK),
+        Err(smoltcp::Error::Truncated) => Err("net_stack: the datagram is too large for the UDP socket's send buffer"),
+        Err(_) => Err("net_stack: failed to send on the UDP socket"),
+    }
+}
+
+fn try_recv_from(stack: &mut Stack, id: u64, buffer: &mut [u8]) -> Result<(usize, IpEndpoint), &'static str> {
+    let handle = socket_handle(stack, id)?;
+    let mut socket = stack.sockets.get::<UdpSocket>(handle);
+    match socket.recv_slice(buffer) {
+        Ok(received) => Ok(received),
+        Err(smoltcp::Error::Exhausted) => Err(WOULD_BLOCK),
+        Err(_) => Err("net_stack: failed to receive on the UDP socket"),
+    }
+}
+
+impl UdpHandle {
+    /// Returns the local port that this socket is bound to.
+    pub fn local_port(&self) -> Result<u16, &'static str> {
+        let mut stack = lock_stack();
+        let handle = socket_handle(&stack, self.0)?;
+        let port = stack.sockets.get::<UdpSocket>(handle).endpoint().port;
+        Ok(port)
+    }
+
+    /// Queues a datagram with the given `data` for sending to the given remote endpoint, without blocking.
+    ///
+    /// Returns `WOULD_BLOCK` if the send buffer is full.
+    pub fn try_send_to<T: Into<IpEndpoint>>(&self, data: &[u8], remote_endpoint: T) -> Result<(), &'static str> {
+        try_send_to(&mut lock_stack(), self.0, data, remote_endpoint.into())?;
+        wake_poller();
+        Ok(())
+    }
+
+    /// Queues a datagram with the given `data` for sending to the given remote endpoint,
+    /// waiting for space in the send buffer as needed.
+    pub fn send_to<T: Into<IpEndpoint>>(&self, data: &[u8], remote_endpoint: T) -> Result<(), &'static str> {
+        let id = self.0;
+        let remote_endpoint = remote_endpoint.into();
+        wait_until(&|stack| match try_send_to(stack, id, data, remote_endpoint) {
+            Err(WOULD_BLOCK) => None,
+            result => Some(result),
+        })?;
+        wake_poller();
+        Ok(())
+    }
+
+    /// Receives the next datagram into the given `buffer` without blocking,
+    /// and returns its length and the endpoint that sent it. A datagram that is larger than the `buffer` is truncated.
+    ///
+    /// Returns `WOULD_BLOCK` if no datagram has been received yet.
+    pub fn try_recv_from(&self, buffer: &mut [u8]) -> Result<(usize, IpEndpoint), &'static str> {
+        try_recv_from(&mut lock_stack(), self.0, buffer)
+    }
+
+    /// Waits until a datagram has been received, receives it into the given `buffer`,
+    /// and returns its length and the endpoint that sent it. A datagram that is larger than the `buffer` is truncated.
+    pub fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, IpEndpoint), &'static str> {
+        let id = self.0;
+        // The buffer can't be borrowed by the wait condition, so the datagram is received after waiting.
+        wait_until(&|stack| match socket_handle(stack, id) {
+            Ok(handle) => if stack.sockets.get::<UdpSocket>(handle).can_recv() { Some(Ok(())) } else { None },
+            Err(e) => Some(Err(e)),
+        })?;
+        self.try_recv_from(buffer)
+    }
+
+    /// Closes the socket, discarding any datagrams that haven't been sent or received, and frees its port.
+    pub fn close(self) {
+        let mut stack = lock_stack();
+        if let Some(handle) = stack.udp.sockets.remove(&self.0) {
+            stack.sockets.remove(handle);
+            stack.release_socket(2 * BUFFER_SIZE);
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! A tap point that mirrors the Ethernet frames that network interfaces send and receive into a ring buffer,
+//! from which a packet capture tool (e.g., the `tcpdump` application) takes them.
+//!
+//! Network interfaces wrap their devices in a [`CaptureDevice`](struct.CaptureDevice.html),
+//! which hands every frame to the tap point. Capturing is off by default,
+//! in which case the tap point costs only a single atomic load per frame.
+//! Only one capture can be in progress at a time.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use spin::Mutex;
+use smoltcp::{
+    time::Instant,
+    phy::{self, Device, DeviceCapabilities},
+};
+
+
+/// The number of frames that the ring buffer holds by default.
+pub const DEFAULT_CAPACITY: usize = 1024;
+/// The number of bytes of each frame that are captured by default, which captures whole frames.
+pub const DEFAULT_SNAP_LEN: usize = 65535;
+
+/// Whether a capture is in progress, which is checked before locking `CAPTURE`.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// The capture in progress, if any.
+    static ref CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+}
+
+
+/// Whether a frame was received or sent by an interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+/// A frame that was captured at the tap point.
+#[derive(Clone, Debug)]
+pub struct CapturedFrame {
+    /// The time at which the frame was sent or received, in milliseconds since the network stack was initialized.
+    pub timestamp_ms: u64,
+    /// The name of the interface that sent or received the frame.
+    pub interface: String,
+    pub direction: Direction,
+    /// The length of the whole frame, which may be longer than the captured `data`.
+    pub original_len: usize,
+    /// The captured bytes of the frame, at most the snapshot length of the capture.
+    pub data: Vec<u8>,
+}
+
+/// The settings of a capture.
+#[derive(Clone, Debug)]
+pub struct CaptureOptions {
+    /// The interface whose frames are captured, or `None` for all interfaces.
+    pub interface: Option<String>,
+    /// The number of frames that the ring buffer holds; once it's full, the oldest frames are dropped.
+    pub capacity: usize,
+    /// The number of bytes of each frame that are captured.
+    pub snap_len: usize,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> CaptureOptions {
+        CaptureOptions {
+            interface: None,
+            capacity: DEFAULT_CAPACITY,
+            snap_len: DEFAULT_SNAP_LEN,
+        }
+    }
+}
+
+/// A capture in progress.
+struct Capture {
+    options: CaptureOptions,
+    frames: VecDeque<CapturedFrame>,
+    /// The number of frames that were dropped because the ring buffer was full.
+    dropped: u64,
+}
+
+
+/// Starts capturing frames with the given options.
+///
+/// Returns an error if a capture is already in progress.
+pub fn start(options: CaptureOptions) -> Result<(), &'static str> {
+    if options.capacity == 0 || options.snap_len == 0 {
+        return Err("network_manager: the capacity and snapshot length of a capture must not be zero");
+    }
+    let mut capture = CAPTURE.lock();
+    if capture.is_some() {
+        return Err("network_manager: a capture is already in progress");
+    }
+    *capture = Some(Capture {
+        frames: VecDeque::with_capacity(options.capacity),
+        options,
+        dropped: 0,
+    });
+    CAPTURING.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Stops the capture in progress, and returns the frames that haven't been taken yet
+/// along with the number of frames that were dropped because the ring buffer was full.
+pub fn stop() -> Result<(Vec<CapturedFrame>, u64), &'static str> {
+    let mut capture = CAPTURE.lock();
+    CAPTURING.store(false, Ordering::Release);
+    let capture = capture.take().ok_or("network_manager: no capture is in progress")?;
+    Ok((capture.frames.into_iter().collect(), capture.dropped))
+}
+
+/// Returns whether a capture is in progress.
+pub fn is_capturing() -> bool {
+    CAPTURING.load(Ordering::Acquire)
+}
+
+/// Removes and returns the frames that have been captured so far, oldest first,
+/// along with the number of frames that have been dropped so far because the ring buffer was full.
+pub fn take_frames() -> Result<(Vec<CapturedFrame>, u64), &'static str> {
+    let mut capture = CAPTURE.lock();
+    let capture = capture.as_mut().ok_or("network_manager: no capture is in progress")?;
+    Ok((capture.frames.drain(..).collect(), capture.dropped))
+}
+
+/// Mirrors the given frame into the ring buffer of the capture in progress, if any.
+///
+/// This is invoked by a [`CaptureDevice`](struct.CaptureDevice.html) for every frame that its device sends or receives.
+pub fn tap(interface: &str, direction: Direction, timestamp: Instant, frame: &[u8]) {
+    if !CAPTURING.load(Ordering::Acquire) {
+        return;
+    }
+    let mut capture = CAPTURE.lock();
+    let capture = match capture.as_mut() {
+        Some(capture) => capture,
+        None => return,
+    };
+    if let Some(ref captured_interface) = capture.options.interface {
+        if captured_interface != interface {
+            return;
+        }
+    }
+    if capture.frames.len() >= capture.options.capacity {
+        capture.frames.pop_front();
+        capture.dropped += 1;
+    }
+    let captured_len = core::cmp::min(frame.len(), capture.options.snap_len);
+    capture.frames.push_back(CapturedFrame {
+        timestamp_ms: timestamp.total_millis() as u64,
+        interface: interface.to_string(),
+        direction,
+        original_len: frame.len(),
+        data: frame[.. captured_len].to_vec(),
+    });
+}
+
+
+/// A wrapper around a smoltcp `Device` that hands every frame that the device sends or receives to the tap point.
+pub struct CaptureDevice<D> {
+    inner: D,
+    /// The name of the interface that the device belongs to.
+    interface: String,
+}
+
+impl<D> CaptureDevice<D> {
+    /// Wraps the given device of the interface with the given name.
+    pub fn new(inner: D, interface: String) -> CaptureDevice<D> {
+        CaptureDevice { inner, interface }
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+}
+
+impl<'a, D> Device<'a> for CaptureDevice<D>
+    where D: for<'b> Device<'b>
+{
+    type RxToken = RxToken<'a, <D as Device<'a>>::RxToken>;
+    type TxToken = TxToken<'a, <D as Device<'a>>::TxToken>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let CaptureDevice { ref mut inner, ref interface } = *self;
+        inner.receive().map(|(rx_token, tx_token)| {
+            (RxToken { inner: rx_token, interface }, TxToken { inner: tx_token, interface })
+        })
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        let CaptureDevice { ref mut inner, ref interface } = *self;
+        inner.transmit().map(|tx_token| TxToken { inner: tx_token, interface })
+    }
+}
+
+/// The receive token of a [`CaptureDevice`](struct.CaptureDevice.html), which taps the frame before it's processed.
+pub struct RxToken<'a, T> {
+    inner: T,
+    interface: &'a str,
+}
+
+impl<'a, T: phy::RxToken> phy::RxToken for RxToken<'a, T> {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> smoltcp::Result<R>
+        where F: FnOnce(&mut [u8]) -> smoltcp::Result<R>
+    {
+        let interface = self.interface;
+        self.inner.consume(timestamp, |frame| {
+            tap(interface, Direction::Received, timestamp, frame);
+            f(frame)
+        })
+    }
+}
+
+/// The transmit token of a [`CaptureDevice`](struct.CaptureDevice.html), which taps the frame after it has been filled in.
+pub struct TxToken<'a, T> {
+    inner: T,
+    interface: &'a str,
+}
+
+impl<'a, T: phy::TxToken> phy::TxToken for TxToken<'a, T> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+        where F: FnOnce(&mut [u8]) -> smoltcp::Result<R>
+    {
+        let interface = self.interface;
+        self.inner.consume(timestamp, len, |frame| {
+            let result = f(frame);
+            if result.is_ok() {
+                tap(interface, Direction::Sent, timestamp, frame);
+            }
+            result
+        })
+    }
+}
@@ -10,6 +10,8 @@ extern crate smoltcp;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 use spin::Mutex;
+pub mod capture;
+
 use smoltcp::{
     socket::SocketSet,
     time::Instant,
@@ -22,11 +24,29 @@ use smoltcp::{
 lazy_static! {
     /// A list of all of the available and initialized network interfaces that exist on this system.
     pub static ref NETWORK_INTERFACES: Mutex<Vec<NetworkInterfaceRef>> = Mutex::new(Vec::new());
+
+    /// The addresses of the DNS servers that names should be resolved with,
+    /// e.g., those learned when an interface was configured via DHCP.
+    static ref DNS_SERVERS: Mutex<Vec<IpAddress>> = Mutex::new(Vec::new());
 }
 
 /// A trait that represents a Network Interface within Theseus. 
 /// Currently this is a thin wrapper around what `smoltcp` offers. 
 pub trait NetworkInterface {
+    /// Get the name of the interface, e.g., `eth0` or `lo`.
+    fn name(&self) -> &str;
+
+    /// Returns whether the interface is up, i.e., whether polling it sends and receives packets.
+    fn is_up(&self) -> bool;
+
+    /// Brings the interface up or down. While an interface is down, polling it neither sends nor receives any packets.
+    fn set_up(&mut self, up: bool);
+
+    /// Returns whether this is a loopback interface, which only delivers packets back to this host.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+
     /// Get the Ethernet address of the interface.
     fn ethernet_addr(&self) -> EthernetAddress;
 
@@ -47,6 +67,9 @@ pub trait NetworkInterface {
     /// Get the IP addresses of the interface.
     fn ip_addrs(&self) -> &[IpCidr];
 
+    /// Replaces the IP addresses of the interface with the given addresses.
+    fn set_ip_addrs(&mut self, addrs: &[IpCidr]);
+
     /// Check whether the interface has the given IP address assigned.
     fn has_ip_addr(&self, addr: IpAddress) -> bool;
 
@@ -64,3 +87,23 @@ pub type NetworkInterfaceRef = Arc<Mutex<dyn NetworkInterface + Send>>;
 pub fn add_to_network_interfaces<T: NetworkInterface + 'static + Send> (iface: T) {
     NETWORK_INTERFACES.lock().push(Arc::new(Mutex::new(iface)));
 }
+
+/// Returns the addresses of the DNS servers that names should be resolved with, in order of preference.
+pub fn dns_servers() -> Vec<IpAddress> {
+    DNS_SERVERS.lock().clone()
+}
+
+/// Adds the given DNS servers to the end of the list of DNS servers, skipping those that are already in it.
+pub fn add_dns_servers(servers: &[IpAddress]) {
+    let mut dns_servers = DNS_SERVERS.lock();
+    for server in servers {
+        if !dns_servers.contains(server) {
+            dns_servers.push(*server);
+        }
+    }
+}
+
+/// Removes the given DNS servers from the list of DNS servers.
+pub fn remove_dns_servers(servers: &[IpAddress]) {
+    DNS_SERVERS.lock().retain(|server| !servers.contains(server));
+}
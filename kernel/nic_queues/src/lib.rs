@@ -12,15 +12,14 @@ extern crate memory;
 extern crate intel_ethernet;
 extern crate nic_buffers;
 extern crate owning_ref;
+extern crate channel;
 
 use owning_ref::BoxRefMut;
-use alloc::{
-    vec::Vec,
-    collections::VecDeque
-};
+use alloc::vec::Vec;
 use memory::MappedPages;
 use intel_ethernet::descriptors::{RxDescriptor, TxDescriptor};
 use nic_buffers::{ReceiveBuffer, ReceivedFrame};
+use channel::{Sender, Receiver};
 
 
 /// A struct that holds all information for one receive queue.
@@ -35,12 +34,14 @@ pub struct RxQueue<T: RxDescriptor> {
     /// The list of rx buffers, in which the index in the vector corresponds to the index in `rx_descs`.
     /// For example, `rx_bufs_in_use[2]` is the receive buffer that will be used when `rx_descs[2]` is the current rx descriptor (rx_cur = 2).
     pub rx_bufs_in_use: Vec<ReceiveBuffer>,
-    /// The queue of received Ethernet frames, ready for consumption by a higher layer.
-    /// Just like a regular FIFO queue, newly-received frames are pushed onto the back
-    /// and frames are popped off of the front.
+    /// The sending side of the channel of received Ethernet frames, 
+    /// onto which the receive path (typically the NIC's interrupt handler) pushes newly-received frames.
     /// Each frame is represented by a Vec<ReceiveBuffer>, because a single frame can span multiple receive buffers.
-    /// TODO: improve this? probably not the best cleanest way to expose received frames to higher layers   
-    pub received_frames: VecDeque<ReceivedFrame>,
+    pub received_frames_producer: Sender<ReceivedFrame>,
+    /// The receiving side of the channel of received Ethernet frames, ready for consumption by a higher layer.
+    /// Frames are received in the order they arrived. 
+    /// A higher layer can clone this to block until a new frame arrives, rather than polling for one.
+    pub received_frames: Receiver<ReceivedFrame>,
     /// The cpu which this queue is mapped to. 
     /// This in itself doesn't guarantee anything, but we use this value when setting the cpu id for interrupts and DCA.
     pub cpu_id: u8,
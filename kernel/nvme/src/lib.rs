@@ -0,0 +1,746 @@
+//! Support for NVMe (NVM Express) solid-state drives.
+//!
+//! An NVMe controller is a PCI device that exposes one or more namespaces,
+//! each of which is an independently-addressable array of logical blocks, i.e., a disk.
+//! Commands are submitted to the controller through submission queues in memory,
+//! and the controller posts their results to paired completion queues and raises an interrupt.
+//!
+//! This driver sets up the admin queue pair and one I/O queue pair per controller,
+//! and exposes each active namespace as an [`NvmeNamespace`](struct.NvmeNamespace.html),
+//! which implements the `StorageDevice` trait.
+//! Only one command is outstanding on a controller at a time;
+//! the submitting task sleeps until the controller's MSI-X (or MSI) interrupt signals completion.
+//!
+//! The primary struct of interest is [`NvmeController`](struct.NvmeController.html).
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate volatile;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate pause;
+extern crate storage_device;
+
+use core::{
+    fmt,
+    ops::DerefMut,
+    sync::atomic::{fence, Ordering},
+};
+use alloc::{
+    boxed::Box,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use volatile::Volatile;
+use zerocopy::FromBytes;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{
+    EntryFlags, FrameRange, MappedPages, PhysicalAddress,
+    allocate_pages_by_bytes, create_contiguous_mapping, get_frame_allocator_ref, get_kernel_mmi_ref,
+};
+use pci::{PciDevice, PciLocation, MsiInterrupt};
+use interrupts::eoi;
+use wait_queue::WaitQueue;
+use pause::spin_loop_hint;
+use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+
+
+/// The PCI class code of mass storage controllers.
+pub const NVME_PCI_CLASS: u8 = 0x01;
+/// The PCI subclass code of non-volatile memory controllers.
+pub const NVME_PCI_SUBCLASS: u8 = 0x08;
+/// The PCI programming interface of NVMe controllers.
+pub const NVME_PCI_PROG_IF: u8 = 0x02;
+
+/// The number of entries in the admin submission and completion queues.
+const ADMIN_QUEUE_ENTRIES: u16 = 32;
+/// The number of entries in the I/O submission and completion queues.
+const IO_QUEUE_ENTRIES: u16 = 64;
+/// The ID of the single I/O queue pair.
+const IO_QUEUE_ID: u16 = 1;
+
+/// The size of the DMA buffer through which all data is transferred, which bounds the size of one transfer.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = 32 * PAGE_SIZE;
+/// The size of the data returned by an Identify command.
+const IDENTIFY_DATA_SIZE: usize = 4096;
+
+/// How many times to poll for a completion or a controller state change before giving up.
+const POLL_ITERATIONS: usize = 100_000_000;
+
+/// The offset of the first doorbell register from the start of the controller's registers.
+const DOORBELL_BASE_OFFSET: usize = 0x1000;
+
+// Bits of the Controller Configuration (CC) register.
+const CC_ENABLE:              u32 = 1 << 0;
+const CC_IO_SQ_ENTRY_SIZE:    u32 = 6 << 16; // 2^6 = 64 bytes
+const CC_IO_CQ_ENTRY_SIZE:    u32 = 4 << 20; // 2^4 = 16 bytes
+
+// Bits of the Controller Status (CSTS) register.
+const CSTS_READY:             u32 = 1 << 0;
+const CSTS_FATAL_STATUS:      u32 = 1 << 1;
+
+// Admin command opcodes
+const ADMIN_CREATE_IO_SQ:     u8 = 0x01;
+const ADMIN_CREATE_IO_CQ:     u8 = 0x05;
+const ADMIN_IDENTIFY:         u8 = 0x06;
+
+// I/O command opcodes
+const IO_WRITE:               u8 = 0x01;
+const IO_READ:                u8 = 0x02;
+
+// Identify command CNS (Controller or Namespace Structure) values
+const IDENTIFY_NAMESPACE:           u32 = 0x00;
+const IDENTIFY_CONTROLLER:          u32 = 0x01;
+const IDENTIFY_ACTIVE_NAMESPACES:   u32 = 0x02;
+
+/// The mapping flags used for the controller's registers and for DMA memory.
+const NVME_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+
+lazy_static! {
+    /// The tasks waiting for an NVMe command to complete, which are woken up by NVMe interrupts.
+    static ref COMPLETION_WAIT_QUEUE: WaitQueue = WaitQueue::new();
+}
+
+
+/// The memory-mapped registers at the start of an NVMe controller's BAR0.
+#[derive(FromBytes)]
+#[repr(C)]
+struct NvmeRegisters {
+    /// Controller Capabilities
+    cap:        Volatile<u64>,  // 0x00
+    /// Version
+    vs:         Volatile<u32>,  // 0x08
+    /// Interrupt Mask Set
+    intms:      Volatile<u32>,  // 0x0C
+    /// Interrupt Mask Clear
+    intmc:      Volatile<u32>,  // 0x10
+    /// Controller Configuration
+    cc:         Volatile<u32>,  // 0x14
+    _reserved:  u32,            // 0x18
+    /// Controller Status
+    csts:       Volatile<u32>,  // 0x1C
+    /// NVM Subsystem Reset
+    nssr:       Volatile<u32>,  // 0x20
+    /// Admin Queue Attributes
+    aqa:        Volatile<u32>,  // 0x24
+    /// Admin Submission Queue Base Address
+    asq:        Volatile<u64>,  // 0x28
+    /// Admin Completion Queue Base Address
+    acq:        Volatile<u64>,  // 0x30
+}
+
+
+/// A command in a submission queue, which has the same layout for admin and I/O commands.
+#[derive(FromBytes, Clone, Copy, Default)]
+#[repr(C)]
+struct NvmeCommand {
+    opcode:     u8,
+    flags:      u8,
+    command_id: u16,
+    nsid:       u32,
+    _reserved:  u64,
+    metadata:   u64,
+    prp1:       u64,
+    prp2:       u64,
+    cdw10:      u32,
+    cdw11:      u32,
+    cdw12:      u32,
+    cdw13:      u32,
+    cdw14:      u32,
+    cdw15:      u32,
+}
+
+/// An entry in a completion queue, which the controller writes when a command completes.
+#[derive(FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+struct NvmeCompletion {
+    result:     u32,
+    _reserved:  u32,
+    sq_head:    u16,
+    sq_id:      u16,
+    command_id: u16,
+    /// Bit 0 is the phase tag, and bits 1-15 are the status field.
+    status:     u16,
+}
+
+impl NvmeCompletion {
+    fn phase(&self) -> bool {
+        self.status & 0x1 == 0x1
+    }
+
+    /// Returns the status code type and status code, which are both zero on success.
+    fn status_code(&self) -> u16 {
+        self.status >> 1
+    }
+}
+
+
+/// A submission queue and the completion queue that it is paired with, which share the same ID.
+struct QueuePair {
+    id: u16,
+    entries: u16,
+    submission_queue: MappedPages,
+    submission_queue_phys: PhysicalAddress,
+    completion_queue: MappedPages,
+    completion_queue_phys: PhysicalAddress,
+    sq_tail: u16,
+    cq_head: u16,
+    /// The phase tag that marks new completion queue entries, which flips every time the queue wraps around.
+    phase: bool,
+    next_command_id: u16,
+}
+
+impl QueuePair {
+    fn new(id: u16, entries: u16) -> Result<QueuePair, &'static str> {
+        let sq_size = entries as usize * core::mem::size_of::<NvmeCommand>();
+        let cq_size = entries as usize * core::mem::size_of::<NvmeCompletion>();
+        let (mut submission_queue, submission_queue_phys) = create_contiguous_mapping(sq_size, NVME_MAPPING_FLAGS)?;
+        let (mut completion_queue, completion_queue_phys) = create_contiguous_mapping(cq_size, NVME_MAPPING_FLAGS)?;
+        for b in submission_queue.as_slice_mut::<u8>(0, sq_size)? { *b = 0; }
+        for b in completion_queue.as_slice_mut::<u8>(0, cq_size)? { *b = 0; }
+        Ok(QueuePair {
+            id,
+            entries,
+            submission_queue,
+            submission_queue_phys,
+            completion_queue,
+            completion_queue_phys,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            next_command_id: 0,
+        })
+    }
+
+    /// Writes the given command into the next submission queue entry, assigning it a command ID.
+    /// Returns the command ID; the caller must then ring the submission queue's doorbell with the new `sq_tail`.
+    fn push(&mut self, mut command: NvmeCommand) -> Result<u16, &'static str> {
+        let command_id = self.next_command_id;
+        self.next_command_id = self.next_command_id.wrapping_add(1);
+        command.command_id = command_id;
+
+        let offset = self.sq_tail as usize * core::mem::size_of::<NvmeCommand>();
+        *self.submission_queue.as_type_mut::<NvmeCommand>(offset)? = command;
+        self.sq_tail = (self.sq_tail + 1) % self.entries;
+        Ok(command_id)
+    }
+
+    /// Returns the next new completion queue entry, if the controller has posted one.
+    /// The caller must then ring the completion queue's doorbell with the new `cq_head`.
+    fn pop(&mut self) -> Option<NvmeCompletion> {
+        let offset = self.cq_head as usize * core::mem::size_of::<NvmeCompletion>();
+        let entry = self.completion_queue.as_type::<NvmeCompletion>(offset).ok()?;
+        // The controller writes this memory, so it must be read anew every time.
+        let completion = unsafe { core::ptr::read_volatile(entry) };
+        if completion.phase() != self.phase {
+            return None;
+        }
+        self.cq_head += 1;
+        if self.cq_head == self.entries {
+            self.cq_head = 0;
+            self.phase = !self.phase;
+        }
+        Some(completion)
+    }
+}
+
+
+/// Which of a controller's queue pairs to submit a command to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Queue {
+    Admin,
+    Io,
+}
+
+
+/// The state of an NVMe controller that is shared by all of its namespaces,
+/// which is needed to submit commands to it.
+struct ControllerState {
+    registers: MappedPages,
+    /// The distance in bytes between consecutive doorbell registers.
+    doorbell_stride: usize,
+    admin_queue: QueuePair,
+    io_queue: Option<QueuePair>,
+    /// Whether the I/O completion queue raises interrupts, such that tasks can sleep while waiting for it.
+    io_interrupts_enabled: bool,
+    /// The buffer through which all data is transferred to and from the controller.
+    dma_buffer: MappedPages,
+    dma_buffer_phys: PhysicalAddress,
+    /// A list of the physical addresses of the pages of `dma_buffer`, after the first page.
+    _prp_list: MappedPages,
+    prp_list_phys: PhysicalAddress,
+    /// The maximum number of bytes that one command can transfer.
+    max_transfer_size_in_bytes: usize,
+    /// The MSI-X or MSI vectors that the controller's interrupts arrive on.
+    interrupts: Vec<MsiInterrupt>,
+}
+
+impl ControllerState {
+    fn registers(&mut self) -> Result<&mut NvmeRegisters, &'static str> {
+        self.registers.as_type_mut::<NvmeRegisters>(0)
+    }
+
+    /// Writes the given value to the submission queue tail doorbell (if `completion` is `false`)
+    /// or the completion queue head doorbell (if `completion` is `true`) of the queue with the given ID.
+    fn ring_doorbell(registers: &mut MappedPages, doorbell_stride: usize, queue_id: u16, completion: bool, value: u16) -> Result<(), &'static str> {
+        let index = 2 * queue_id as usize + if completion { 1 } else { 0 };
+        let doorbell = registers.as_type_mut::<Volatile<u32>>(DOORBELL_BASE_OFFSET + index * doorbell_stride)?;
+        doorbell.write(value as u32);
+        Ok(())
+    }
+
+    /// Submits the given command to the given queue and waits for it to complete.
+    ///
+    /// If the queue's completions raise interrupts, the current task sleeps until the command completes.
+    /// Otherwise, or if there is no current task yet, this polls the completion queue.
+    fn submit_and_wait(&mut self, queue: Queue, command: NvmeCommand) -> Result<NvmeCompletion, &'static str> {
+        let use_interrupts = queue == Queue::Io && self.io_interrupts_enabled;
+        let registers = &mut self.registers;
+        let doorbell_stride = self.doorbell_stride;
+        let queue_pair = match queue {
+            Queue::Admin => &mut self.admin_queue,
+            Queue::Io => self.io_queue.as_mut().ok_or("NVMe I/O queue was not yet created")?,
+        };
+
+        let command_id = queue_pair.push(command)?;
+        // Ensure the command is in memory before the controller is told about it.
+        fence(Ordering::SeqCst);
+        Self::ring_doorbell(registers, doorbell_stride, queue_pair.id, false, queue_pair.sq_tail)?;
+
+        let mut poll = || -> Option<Result<NvmeCompletion, &'static str>> {
+            let completion = queue_pair.pop()?;
+            Some(Self::ring_doorbell(registers, doorbell_stride, queue_pair.id, true, queue_pair.cq_head).map(|_| completion))
+        };
+
+        // Sleeping requires a current task, so this falls back to polling during early initialization.
+        let woken = if use_interrupts { COMPLETION_WAIT_QUEUE.wait_until_mut(&mut poll).ok() } else { None };
+        let completion = match woken {
+            Some(result) => result?,
+            None => {
+                let mut iterations = 0;
+                loop {
+                    if let Some(result) = poll() {
+                        break result?;
+                    }
+                    iterations += 1;
+                    if iterations > POLL_ITERATIONS {
+                        return Err("NVMe command timed out");
+                    }
+                    spin_loop_hint();
+                }
+            }
+        };
+
+        if completion.command_id != command_id {
+            error!("NVMe: expected completion of command {}, got {:?}", command_id, completion);
+            return Err("NVMe controller completed an unexpected command");
+        }
+        if completion.status_code() != 0 {
+            error!("NVMe: command {:#X} failed with status {:#X}", command.opcode, completion.status_code());
+            return Err("NVMe command failed");
+        }
+        Ok(completion)
+    }
+
+    /// Fills in the PRP (Physical Region Page) entries of the given command
+    /// such that it transfers `length` bytes to or from the start of the DMA buffer.
+    fn set_data_pointer(&self, command: &mut NvmeCommand, length: usize) {
+        command.prp1 = self.dma_buffer_phys.value() as u64;
+        let pages = (length + PAGE_SIZE - 1) / PAGE_SIZE;
+        command.prp2 = match pages {
+            0 | 1 => 0,
+            2 => (self.dma_buffer_phys + PAGE_SIZE).value() as u64,
+            _ => self.prp_list_phys.value() as u64,
+        };
+    }
+
+    /// Issues an Identify command and returns the resulting data structure, which is 4096 bytes.
+    fn identify(&mut self, cns: u32, nsid: u32) -> Result<&[u8], &'static str> {
+        let mut command = NvmeCommand {
+            opcode: ADMIN_IDENTIFY,
+            nsid,
+            cdw10: cns,
+            ..Default::default()
+        };
+        self.set_data_pointer(&mut command, IDENTIFY_DATA_SIZE);
+        self.submit_and_wait(Queue::Admin, command)?;
+        self.dma_buffer.as_slice::<u8>(0, IDENTIFY_DATA_SIZE)
+    }
+
+    /// Creates the I/O queue pair, whose completions are signaled on the given interrupt vector, if any.
+    fn create_io_queue(&mut self, interrupt_vector_index: Option<u16>) -> Result<(), &'static str> {
+        let io_queue = QueuePair::new(IO_QUEUE_ID, IO_QUEUE_ENTRIES)?;
+        let queue_size = ((IO_QUEUE_ENTRIES - 1) as u32) << 16;
+
+        // The completion queue must be created first, as the submission queue refers to it.
+        const PHYSICALLY_CONTIGUOUS: u32 = 1 << 0;
+        const INTERRUPTS_ENABLED:    u32 = 1 << 1;
+        let interrupt_flags = match interrupt_vector_index {
+            Some(index) => ((index as u32) << 16) | INTERRUPTS_ENABLED,
+            None => 0,
+        };
+        self.submit_and_wait(Queue::Admin, NvmeCommand {
+            opcode: ADMIN_CREATE_IO_CQ,
+            prp1: io_queue.completion_queue_phys.value() as u64,
+            cdw10: queue_size | IO_QUEUE_ID as u32,
+            cdw11: interrupt_flags | PHYSICALLY_CONTIGUOUS,
+            ..Default::default()
+        })?;
+        self.submit_and_wait(Queue::Admin, NvmeCommand {
+            opcode: ADMIN_CREATE_IO_SQ,
+            prp1: io_queue.submission_queue_phys.value() as u64,
+            cdw10: queue_size | IO_QUEUE_ID as u32,
+            cdw11: ((IO_QUEUE_ID as u32) << 16) | PHYSICALLY_CONTIGUOUS,
+            ..Default::default()
+        })?;
+
+        self.io_queue = Some(io_queue);
+        self.io_interrupts_enabled = interrupt_vector_index.is_some();
+        Ok(())
+    }
+
+    /// Reads or writes `sector_count` sectors of the given namespace, starting at `lba`,
+    /// to or from the start of the DMA buffer.
+    fn read_write(&mut self, opcode: u8, nsid: u32, lba: u64, sector_count: usize, sector_size: usize) -> Result<(), &'static str> {
+        let mut command = NvmeCommand {
+            opcode,
+            nsid,
+            cdw10: lba as u32,
+            cdw11: (lba >> 32) as u32,
+            // the number of logical blocks is zero-based
+            cdw12: (sector_count - 1) as u32 & 0xFFFF,
+            ..Default::default()
+        };
+        self.set_data_pointer(&mut command, sector_count * sector_size);
+        self.submit_and_wait(Queue::Io, command).map(|_| ())
+    }
+}
+
+
+/// Reads a little-endian integer of `num_bytes` bytes at the given offset in the given data structure.
+fn read_le(data: &[u8], offset: usize, num_bytes: usize) -> u64 {
+    data[offset .. offset + num_bytes].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+/// Converts a space-padded ASCII string in an Identify data structure into a `String`.
+fn read_ascii(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim().into()
+}
+
+
+/// An NVMe controller, which contains one or more namespaces (disks).
+pub struct NvmeController {
+    location: PciLocation,
+    serial_number: String,
+    model_number: String,
+    firmware_revision: String,
+    namespaces: Vec<NvmeNamespaceRef>,
+}
+
+impl NvmeController {
+    /// Initializes the NVMe controller that is connected as the given `PciDevice`
+    /// and discovers its active namespaces.
+    pub fn new(pci_device: &PciDevice) -> Result<NvmeController, &'static str> {
+        let mem_base = pci_device.determine_mem_base()?;
+        let registers = map_registers(pci_device, mem_base)?;
+        pci_device.pci_set_command_bus_master_bit();
+
+        let (dma_buffer, dma_buffer_phys) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, NVME_MAPPING_FLAGS)?;
+        let (mut prp_list, prp_list_phys) = create_contiguous_mapping(PAGE_SIZE, NVME_MAPPING_FLAGS)?;
+        {
+            // The DMA buffer is physically contiguous, so the PRP list never changes.
+            let entries = prp_list.as_slice_mut::<u64>(0, PAGE_SIZE / core::mem::size_of::<u64>())?;
+            for (i, entry) in entries.iter_mut().enumerate() {
+                let page = i + 1;
+                *entry = if page * PAGE_SIZE < DMA_BUFFER_SIZE_IN_BYTES {
+                    (dma_buffer_phys + page * PAGE_SIZE).value() as u64
+                } else {
+                    0
+                };
+            }
+        }
+
+        let mut state = ControllerState {
+            registers,
+            doorbell_stride: 0,
+            admin_queue: QueuePair::new(0, ADMIN_QUEUE_ENTRIES)?,
+            io_queue: None,
+            io_interrupts_enabled: false,
+            dma_buffer,
+            dma_buffer_phys,
+            _prp_list: prp_list,
+            prp_list_phys,
+            max_transfer_size_in_bytes: DMA_BUFFER_SIZE_IN_BYTES,
+            interrupts: Vec::new(),
+        };
+
+        Self::reset(&mut state)?;
+
+        // Identify the controller.
+        let (serial_number, model_number, firmware_revision, mdts) = {
+            let data = state.identify(IDENTIFY_CONTROLLER, 0)?;
+            (read_ascii(&data[4..24]), read_ascii(&data[24..64]), read_ascii(&data[64..72]), data[77])
+        };
+        // MDTS is a power of two in units of the minimum page size (4 KiB), where 0 means no limit.
+        if mdts != 0 {
+            state.max_transfer_size_in_bytes = core::cmp::min(state.max_transfer_size_in_bytes, PAGE_SIZE << mdts);
+        }
+
+        // Set up interrupts, preferring MSI-X, in which vector 0 is used by the admin queue.
+        let interrupt_vector_index = match pci_device.enable_msix(2) {
+            Ok(vectors) => {
+                state.interrupts = vectors;
+                Some(1)
+            }
+            Err(_e) => match pci_device.enable_msi() {
+                Ok(vector) => {
+                    state.interrupts = vec![vector];
+                    Some(0)
+                }
+                Err(_e) => {
+                    warn!("NVMe controller at {} supports neither MSI-X nor MSI, so completions will be polled", pci_device.location);
+                    None
+                }
+            }
+        };
+        for vector in state.interrupts.iter_mut() {
+            vector.set_handler(nvme_handler)?;
+        }
+        state.create_io_queue(interrupt_vector_index)?;
+
+        // Discover the active namespaces.
+        let nsids: Vec<u32> = {
+            let data = state.identify(IDENTIFY_ACTIVE_NAMESPACES, 0)?;
+            data.chunks_exact(4)
+                .map(|id| read_le(id, 0, 4) as u32)
+                .take_while(|&id| id != 0)
+                .collect()
+        };
+        let mut namespace_info = Vec::with_capacity(nsids.len());
+        for nsid in nsids {
+            let data = state.identify(IDENTIFY_NAMESPACE, nsid)?;
+            let size_in_sectors = read_le(data, 0, 8) as usize;
+            let format_index = (data[26] & 0xF) as usize;
+            let lba_format = read_le(data, 128 + 4 * format_index, 4);
+            let sector_size = 1usize << ((lba_format >> 16) & 0xFF);
+            namespace_info.push((nsid, size_in_sectors, sector_size));
+        }
+
+        let max_transfer_size_in_bytes = state.max_transfer_size_in_bytes;
+        let state = Arc::new(Mutex::new(state));
+        let namespaces: Vec<NvmeNamespaceRef> = namespace_info.into_iter()
+            .filter(|&(_, _, sector_size)| sector_size <= max_transfer_size_in_bytes)
+            .map(|(nsid, size_in_sectors, sector_size)| Arc::new(Mutex::new(NvmeNamespace {
+                nsid,
+                size_in_sectors,
+                sector_size,
+                controller: Arc::clone(&state),
+            })))
+            .collect();
+
+        let controller = NvmeController {
+            location: pci_device.location,
+            serial_number,
+            model_number,
+            firmware_revision,
+            namespaces,
+        };
+        info!("{}", controller);
+        Ok(controller)
+    }
+
+    /// Disables the controller, configures its admin queue pair, and then re-enables it.
+    fn reset(state: &mut ControllerState) -> Result<(), &'static str> {
+        let admin_sq_phys = state.admin_queue.submission_queue_phys.value() as u64;
+        let admin_cq_phys = state.admin_queue.completion_queue_phys.value() as u64;
+        let regs = state.registers()?;
+
+        let cap = regs.cap.read();
+        let max_queue_entries = (cap & 0xFFFF) as usize + 1;
+        let doorbell_stride = 4 << ((cap >> 32) & 0xF);
+        let min_page_size = 1usize << (12 + ((cap >> 48) & 0xF));
+        const NVM_COMMAND_SET_SUPPORTED: u64 = 1 << 37;
+        if cap & NVM_COMMAND_SET_SUPPORTED == 0 {
+            return Err("NVMe controller doesn't support the NVM command set");
+        }
+        if min_page_size > PAGE_SIZE {
+            return Err("NVMe controller doesn't support 4 KiB memory pages");
+        }
+        if max_queue_entries < IO_QUEUE_ENTRIES as usize {
+            return Err("NVMe controller doesn't support large enough queues");
+        }
+        let version = regs.vs.read();
+        debug!("NVMe controller version {}.{}.{}", version >> 16, (version >> 8) & 0xFF, version & 0xFF);
+
+        regs.cc.write(regs.cc.read() & !CC_ENABLE);
+        Self::wait_for_ready(regs, false)?;
+
+        regs.aqa.write(((ADMIN_QUEUE_ENTRIES - 1) as u32) << 16 | (ADMIN_QUEUE_ENTRIES - 1) as u32);
+        regs.asq.write(admin_sq_phys);
+        regs.acq.write(admin_cq_phys);
+        // Use the NVM command set (0), 4 KiB pages (0), and round robin arbitration (0).
+        regs.cc.write(CC_IO_SQ_ENTRY_SIZE | CC_IO_CQ_ENTRY_SIZE | CC_ENABLE);
+        Self::wait_for_ready(regs, true)?;
+
+        state.doorbell_stride = doorbell_stride;
+        Ok(())
+    }
+
+    /// Waits until the controller's ready status matches `ready`.
+    fn wait_for_ready(regs: &NvmeRegisters, ready: bool) -> Result<(), &'static str> {
+        for _ in 0..POLL_ITERATIONS {
+            let status = regs.csts.read();
+            if status & CSTS_FATAL_STATUS != 0 {
+                return Err("NVMe controller reported a fatal error");
+            }
+            if (status & CSTS_READY != 0) == ready {
+                return Ok(());
+            }
+            spin_loop_hint();
+        }
+        Err("timed out waiting for the NVMe controller to change its ready status")
+    }
+
+    /// Returns the namespaces (disks) of this controller.
+    pub fn namespaces(&self) -> &[NvmeNamespaceRef] {
+        &self.namespaces
+    }
+
+    pub fn serial_number(&self) -> &str {
+        &self.serial_number
+    }
+
+    pub fn model_number(&self) -> &str {
+        &self.model_number
+    }
+
+    pub fn firmware_revision(&self) -> &str {
+        &self.firmware_revision
+    }
+}
+
+impl StorageController for NvmeController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            self.namespaces.iter().map(|ns| Arc::clone(ns) as StorageDeviceRef)
+        )
+    }
+}
+
+impl fmt::Display for NvmeController {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NVMe controller at {}: model {:?}, serial {:?}, firmware {:?}",
+            self.location, self.model_number, self.serial_number, self.firmware_revision
+        )?;
+        for ns in &self.namespaces {
+            let ns = ns.lock();
+            write!(f, "\n--> namespace {}: {} sectors of {} bytes", ns.nsid, ns.size_in_sectors, ns.sector_size)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// An NVMe namespace, which is one disk on an NVMe controller.
+pub struct NvmeNamespace {
+    nsid: u32,
+    size_in_sectors: usize,
+    sector_size: usize,
+    controller: Arc<Mutex<ControllerState>>,
+}
+
+impl NvmeNamespace {
+    /// Returns the ID of this namespace on its controller.
+    pub fn id(&self) -> u32 {
+        self.nsid
+    }
+
+    /// Checks that a transfer of `buffer_len` bytes starting at `offset_in_sectors` is within this namespace.
+    fn check_bounds(&self, buffer_len: usize, offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if buffer_len % self.sector_size != 0 {
+            return Err("The buffer length must be a multiple of the NVMe namespace's sector size");
+        }
+        let sector_count = buffer_len / self.sector_size;
+        if offset_in_sectors + sector_count > self.size_in_sectors {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(sector_count)
+    }
+}
+
+impl StorageDevice for NvmeNamespace {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        let mut controller = self.controller.lock();
+        let chunk_size = controller.max_transfer_size_in_bytes / self.sector_size * self.sector_size;
+
+        for (i, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let lba = (offset_in_sectors + i * chunk_size / self.sector_size) as u64;
+            controller.read_write(IO_READ, self.nsid, lba, chunk.len() / self.sector_size, self.sector_size)?;
+            chunk.copy_from_slice(controller.dma_buffer.as_slice::<u8>(0, chunk.len())?);
+        }
+        Ok(sector_count)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        let mut controller = self.controller.lock();
+        let chunk_size = controller.max_transfer_size_in_bytes / self.sector_size * self.sector_size;
+
+        for (i, chunk) in buffer.chunks(chunk_size).enumerate() {
+            let lba = (offset_in_sectors + i * chunk_size / self.sector_size) as u64;
+            controller.dma_buffer.as_slice_mut::<u8>(0, chunk.len())?.copy_from_slice(chunk);
+            controller.read_write(IO_WRITE, self.nsid, lba, chunk.len() / self.sector_size, self.sector_size)?;
+        }
+        Ok(sector_count)
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        self.sector_size
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        self.size_in_sectors
+    }
+}
+
+pub type NvmeNamespaceRef = Arc<Mutex<NvmeNamespace>>;
+
+
+/// Maps the NVMe controller's registers (its BAR0 memory region).
+fn map_registers(pci_device: &PciDevice, mem_base: PhysicalAddress) -> Result<MappedPages, &'static str> {
+    let mem_size_in_bytes = pci_device.determine_mem_size() as usize;
+    let pages = allocate_pages_by_bytes(mem_size_in_bytes).ok_or("NVMe: couldn't allocate pages for the controller registers")?;
+    let frames = FrameRange::from_phys_addr(mem_base, mem_size_in_bytes);
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("NVMe: KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("NVMe: couldn't get the frame allocator")?;
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+    kernel_mmi.page_table.map_allocated_pages_to(pages, frames, NVME_MAPPING_FLAGS, fa.lock().deref_mut())
+}
+
+
+/// The handler for all NVMe interrupts, which wakes up the tasks waiting for commands to complete.
+/// Each waiting task checks its own completion queue, so it doesn't matter which controller or queue raised it.
+extern "x86-interrupt" fn nvme_handler(_stack_frame: &mut ExceptionStackFrame) {
+    COMPLETION_WAIT_QUEUE.notify_all();
+    eoi(None);
+}
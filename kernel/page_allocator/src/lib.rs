@@ -376,11 +376,23 @@ impl<'list> Drop for DeferredAllocAction<'list> {
 pub fn allocate_pages_deferred(
 	requested_vaddr: Option<VirtualAddress>,
 	num_pages: usize,
+) -> Result<(AllocatedPages, DeferredAllocAction<'static>), &'static str> {
+	allocate_pages_deferred_internal(requested_vaddr, num_pages, 1)
+}
+
+
+/// The internal page allocation routine, which additionally ensures that the first allocated `Page`
+/// is aligned to a multiple of `alignment_in_pages` if no `requested_vaddr` is given.
+fn allocate_pages_deferred_internal(
+	requested_vaddr: Option<VirtualAddress>,
+	num_pages: usize,
+	alignment_in_pages: usize,
 ) -> Result<(AllocatedPages, DeferredAllocAction<'static>), &'static str> {
 	if num_pages == 0 {
 		warn!("PageAllocator: requested an allocation of 0 pages... stupid!");
 		return Err("cannot allocate zero pages");
 	}
+	let alignment_in_pages = core::cmp::max(alignment_in_pages, 1);
 
 	let desired_start_page = requested_vaddr.map(|vaddr| Page::containing_address(vaddr));
 
@@ -389,7 +401,11 @@ pub fn allocate_pages_deferred(
 		// Look for the chunk that contains the desired address, 
 		// or any chunk that is large enough, if no desired address was requested.
 		// Obviously, we cannot use any chunk that is already allocated. 
-		let potential_start_page = desired_start_page.unwrap_or(*c.pages.start());
+		let potential_start_page = desired_start_page.unwrap_or_else(|| {
+			let chunk_start = *c.pages.start();
+			let misalignment = (chunk_start.start_address().value() / PAGE_SIZE) % alignment_in_pages;
+			if misalignment == 0 { chunk_start } else { chunk_start + (alignment_in_pages - misalignment) }
+		});
 		// The end page is an inclusive bound, hence the -1. Parentheses are needed to avoid overflow.
 		let potential_end_page   = potential_start_page + (num_pages - 1); 
 		if potential_start_page >= *c.pages.start() && potential_end_page <= *c.pages.end() {
@@ -491,6 +507,19 @@ pub fn allocate_pages(num_pages: usize) -> Option<AllocatedPages> {
 }
 
 
+/// Allocates the given number of pages such that the starting virtual address 
+/// is aligned to a multiple of `alignment_in_pages` pages.
+/// 
+/// This is useful for pages that will be mapped as huge pages, 
+/// e.g., with an alignment of `PageSize::Huge2MiB.size_in_pages()`.
+/// See [`allocate_pages_deferred()`](fn.allocate_pages_deferred.html) for more details. 
+pub fn allocate_pages_aligned(num_pages: usize, alignment_in_pages: usize) -> Option<AllocatedPages> {
+	allocate_pages_deferred_internal(None, num_pages, alignment_in_pages)
+		.map(|(ap, _action)| ap)
+		.ok()
+}
+
+
 /// Allocates pages with no constraints on the starting virtual address, 
 /// with a size given by the number of bytes. 
 /// 
@@ -0,0 +1,738 @@
+//! A unified cache of pages of block devices and files, which sits between their users and the backing storage.
+//!
+//! Each cached page is one frame of memory that holds `PAGE_SIZE` bytes of either
+//! a block device (through its [`BlockQueue`](../block_io/struct.BlockQueue.html)) or a file,
+//! identified by a [`CacheKey`](enum.CacheKey.html).
+//! Reads are served from the cache when possible, and writes only modify the cached page,
+//! which is marked dirty and written back to the backing storage later:
+//! * periodically, every [`FLUSH_INTERVAL_MS`](constant.FLUSH_INTERVAL_MS.html), by a flusher task,
+//! * explicitly, by [`sync()`](fn.sync.html) and its per-device and per-file variants,
+//! * and before the system shuts down, reboots, or suspends.
+//!
+//! Clean pages are dropped in least-recently-used order when the cache grows beyond
+//! [`MAX_CACHED_PAGES`](constant.MAX_CACHED_PAGES.html), or when the memory subsystem runs low on frames
+//! (see [`memory::reclaim`](../memory/reclaim/index.html)).
+//!
+//! Writes that extend a file past its current end bypass the cache, because the file's size
+//! must change immediately; such writes first sync the file's dirty pages and then drop its affected cached pages.
+//!
+//! Filesystems that store files on a block device, e.g., FAT32 and ext2, read and write their files through the cache
+//! with [`read_file_pages()`](fn.read_file_pages.html) and [`write_file_pages()`](fn.write_file_pages.html),
+//! giving it a [`PageBacking`](trait.PageBacking.html) that accesses the file's contents on the device directly.
+//! They [register](fn.register_file.html) that backing, such that the functions that take a `FileRef` use it too.
+//!
+//! A file's cached pages can also be mapped directly into memory with [`map_file_pages()`](fn.map_file_pages.html),
+//! e.g., by the [`memory_mapped_file`](../memory_mapped_file/index.html) crate.
+//! Mapped pages are pinned in the cache, i.e., never dropped, until they're unpinned.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate kernel_config;
+extern crate memory;
+extern crate block_io;
+extern crate fs_node;
+extern crate async_runtime;
+extern crate power;
+
+use core::{
+    future::Future,
+    ops::DerefMut,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::{Mutex, Once};
+use kernel_config::memory::PAGE_SIZE;
+use memory::{EntryFlags, FrameRange, MappedPages, Page, allocate_pages, create_mapping, get_frame_allocator_ref, get_kernel_mmi_ref};
+use block_io::BlockQueue;
+use fs_node::FileRef;
+use async_runtime::{Executor, Sleep};
+use power::PowerEvent;
+
+
+/// The interval between two write-backs of all dirty pages by the flusher task.
+pub const FLUSH_INTERVAL_MS: u64 = 5000;
+
+/// The maximum number of pages that the cache holds before it starts dropping clean pages.
+pub const MAX_CACHED_PAGES: usize = 16384;
+
+
+/// Identifies a cached page: the page-sized chunk at index `page` of a block device or a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheKey {
+    /// A page of the block device with the given [`BlockQueue::id()`](../block_io/struct.BlockQueue.html#method.id).
+    Block { device: usize, page: usize },
+    /// A page of the given file.
+    File { file: FileId, page: usize },
+}
+
+/// Identifies a file in a [`CacheKey`](enum.CacheKey.html), such that all nodes of the same file share its cached pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileId {
+    /// The file with the given inode on the filesystem with the given ID, as given by its [`Metadata`](../fs_node/struct.Metadata.html).
+    Inode { filesystem: u64, inode: u64 },
+    /// A file whose metadata doesn't include an inode, identified by the address of its node.
+    /// The cache holds a reference to that node while any of its pages are cached,
+    /// so the address can't be reused by another file until they have all been dropped.
+    Node(usize),
+}
+
+/// Statistics about the page cache.
+#[derive(Clone, Debug, Default)]
+pub struct PageCacheStats {
+    /// The number of page lookups that found the page in the cache.
+    pub hits: u64,
+    /// The number of page lookups that had to read the page from its backing storage.
+    pub misses: u64,
+    /// The number of pages currently in the cache.
+    pub cached_pages: usize,
+    /// The number of cached pages that have been modified but not yet written back.
+    pub dirty_pages: usize,
+    /// The number of dirty pages that have been written back.
+    pub writebacks: u64,
+    /// The number of clean pages that have been dropped to free memory.
+    pub evictions: u64,
+}
+
+
+/// The storage that backs a cached page, from which it is read and to which it is written back.
+///
+/// Filesystems implement this for their files' contents on the device, see [`read_file_pages()`](fn.read_file_pages.html).
+/// Pages are read and written back while the page cache isn't locked, but possibly while the file's node is locked,
+/// so an implementation must not lock the file's node.
+pub trait PageBacking: Send + Sync {
+    /// Reads the page at index `page` into the page-sized `buffer`, zero-filling whatever lies beyond the end of the storage.
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<(), &'static str>;
+    /// Writes the page-sized `buffer` to the page at index `page`, excluding whatever lies beyond the end of the storage.
+    fn write_page(&self, page: usize, buffer: &[u8]) -> Result<(), &'static str>;
+}
+
+struct BlockBacking(BlockQueue);
+
+impl BlockBacking {
+    /// Returns the first block of the given page and the number of bytes of the page that lie on the device.
+    fn page_bounds(&self, page: usize) -> (usize, usize) {
+        let offset = page * PAGE_SIZE;
+        let len = core::cmp::min(PAGE_SIZE, self.0.size_in_bytes().saturating_sub(offset));
+        (offset / self.0.block_size(), len)
+    }
+}
+
+impl PageBacking for BlockBacking {
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let (first_block, len) = self.page_bounds(page);
+        if len > 0 {
+            self.0.read_blocks(&mut buffer[..len], first_block)?;
+        }
+        for b in buffer[len..].iter_mut() { *b = 0; }
+        Ok(())
+    }
+
+    fn write_page(&self, page: usize, buffer: &[u8]) -> Result<(), &'static str> {
+        let (first_block, len) = self.page_bounds(page);
+        if len > 0 {
+            self.0.write_blocks(&buffer[..len], first_block)?;
+        }
+        Ok(())
+    }
+}
+
+struct FileBacking(FileRef);
+
+impl PageBacking for FileBacking {
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let file = self.0.lock();
+        let offset = page * PAGE_SIZE;
+        let bytes_read = if offset < file.size() { file.read(buffer, offset)? } else { 0 };
+        for b in buffer[bytes_read..].iter_mut() { *b = 0; }
+        Ok(())
+    }
+
+    fn write_page(&self, page: usize, buffer: &[u8]) -> Result<(), &'static str> {
+        let mut file = self.0.lock();
+        let offset = page * PAGE_SIZE;
+        // Writing back a page never extends the file.
+        let len = core::cmp::min(PAGE_SIZE, file.size().saturating_sub(offset));
+        if len > 0 {
+            file.write(&buffer[..len], offset)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// A page in the cache.
+struct CachedPage {
+    frame: MappedPages,
+    backing: Arc<dyn PageBacking>,
+    dirty: bool,
+    /// The number of mappings created by `map_file_pages()` that share this page's frame, which prevent it from being evicted.
+    mappings: usize,
+    /// The value of `ACCESS_CLOCK` when this page was last accessed, used to find the least-recently-used pages.
+    last_access: u64,
+}
+
+lazy_static! {
+    static ref PAGE_CACHE: Mutex<BTreeMap<CacheKey, CachedPage>> = Mutex::new(BTreeMap::new());
+    /// The backings of the files whose filesystems read and write them through the cache, see `register_file()`.
+    static ref FILE_BACKINGS: Mutex<BTreeMap<FileId, Weak<dyn PageBacking>>> = Mutex::new(BTreeMap::new());
+}
+
+static ACCESS_CLOCK: AtomicU64 = AtomicU64::new(0);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static WRITEBACKS: AtomicU64 = AtomicU64::new(0);
+static EVICTIONS: AtomicU64 = AtomicU64::new(0);
+
+static FLUSHER_EXECUTOR: Once<Executor> = Once::new();
+
+
+/// Initializes the page cache: registers it with the memory subsystem's reclaim path and the power subsystem,
+/// and spawns the flusher task that periodically writes back dirty pages.
+pub fn init() -> Result<(), &'static str> {
+    memory::reclaim::register_reclaimer(reclaim);
+    power::register_suspend_hook("page_cache", sync_before_power_event, || { })?;
+
+    let executor = FLUSHER_EXECUTOR.call_once(Executor::new);
+    executor.spawn(Flusher { sleep: async_runtime::sleep(FLUSH_INTERVAL_MS) });
+    executor.spawn_executor_task(String::from("page_cache_flusher"))?;
+    Ok(())
+}
+
+
+/// Reads from the given block device into the `buffer`, starting at the byte `offset`, through the page cache.
+///
+/// Returns the number of bytes read, which is less than the length of the `buffer`
+/// if the read extends past the end of the device.
+pub fn read_block_device(queue: &BlockQueue, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+    let device = queue.id();
+    let backing: Arc<dyn PageBacking> = Arc::new(BlockBacking(queue.clone()));
+    read(|page| CacheKey::Block { device, page }, &backing, queue.size_in_bytes(), buffer, offset)
+}
+
+/// Writes the `buffer` to the given block device, starting at the byte `offset`, through the page cache.
+/// The written pages are written back to the device later.
+///
+/// Returns the number of bytes written, which is less than the length of the `buffer`
+/// if the write extends past the end of the device.
+pub fn write_block_device(queue: &BlockQueue, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+    let device = queue.id();
+    let backing: Arc<dyn PageBacking> = Arc::new(BlockBacking(queue.clone()));
+    write(|page| CacheKey::Block { device, page }, &backing, queue.size_in_bytes(), buffer, offset)
+}
+
+/// Reads from the given `file` into the `buffer`, starting at the byte `offset`, through the page cache.
+///
+/// Returns the number of bytes read, which is less than the length of the `buffer`
+/// if the read extends past the end of the file.
+pub fn read_file(file: &FileRef, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+    let id = file_id(file);
+    if registered_backing(id).is_some() {
+        // The file's filesystem reads it through the cache itself.
+        return file.lock().read(buffer, offset);
+    }
+    let size = file.lock().size();
+    let backing: Arc<dyn PageBacking> = Arc::new(FileBacking(file.clone()));
+    read(|page| CacheKey::File { file: id, page }, &backing, size, buffer, offset)
+}
+
+/// Writes the `buffer` to the given `file`, starting at the byte `offset`, through the page cache.
+///
+/// Writes within the file's current size are written back later,
+/// whereas writes that extend the file are written to it immediately.
+/// Returns the number of bytes written.
+pub fn write_file(file: &FileRef, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+    let id = file_id(file);
+    if registered_backing(id).is_some() {
+        // The file's filesystem writes it through the cache itself.
+        return file.lock().write(buffer, offset);
+    }
+    let size = file.lock().size();
+    if offset.saturating_add(buffer.len()) > size {
+        sync_file(file)?;
+        let written = file.lock().write(buffer, offset)?;
+        let first_page = offset / PAGE_SIZE;
+        let end_page = (offset + written + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut dropped = Vec::new();
+        let mut mapped = Vec::new();
+        {
+            let mut cache = PAGE_CACHE.lock();
+            for page in first_page .. end_page {
+                let key = CacheKey::File { file: id, page };
+                match cache.get(&key).map(|p| p.mappings) {
+                    Some(0) => dropped.extend(cache.remove(&key)),
+                    Some(_) => mapped.push(page),
+                    None => { }
+                }
+            }
+        }
+        drop(dropped);
+        // Mapped pages can't be dropped, because their mappings would no longer see the file's contents,
+        // so they're re-read from the file instead.
+        let backing = FileBacking(file.clone());
+        let mut data = vec![0u8; PAGE_SIZE];
+        for page in mapped {
+            backing.read_page(page, &mut data)?;
+            if let Some(cached) = PAGE_CACHE.lock().get_mut(&CacheKey::File { file: id, page }) {
+                cached.frame.as_slice_mut::<u8>(0, PAGE_SIZE)?.copy_from_slice(&data);
+            }
+        }
+        return Ok(written);
+    }
+    let backing: Arc<dyn PageBacking> = Arc::new(FileBacking(file.clone()));
+    write(|page| CacheKey::File { file: id, page }, &backing, size, buffer, offset)
+}
+
+/// Registers the `backing` of the file with the given `id`, whose filesystem reads and writes it through the cache
+/// with [`read_file_pages()`](fn.read_file_pages.html) and [`write_file_pages()`](fn.write_file_pages.html).
+///
+/// The functions that take a `FileRef` then use this backing for the file instead of its `File` methods,
+/// which go through the cache themselves. The registration is removed when the `backing` is dropped
+/// or when the file's pages are invalidated, e.g., because it was deleted.
+pub fn register_file(id: FileId, backing: &Arc<dyn PageBacking>) {
+    let mut backings = FILE_BACKINGS.lock();
+    backings.retain(|_, backing| backing.strong_count() > 0);
+    backings.insert(id, Arc::downgrade(backing));
+}
+
+/// Reads from the file with the given `id` and `size` into the `buffer`, starting at the byte `offset`, through the cache,
+/// reading any pages that aren't cached from the given `backing`.
+///
+/// This is meant to be used by a filesystem to implement `File::read()`.
+/// Returns the number of bytes read, which is less than the length of the `buffer`
+/// if the read extends past the end of the file.
+pub fn read_file_pages(id: FileId, backing: &Arc<dyn PageBacking>, size: usize, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+    read(|page| CacheKey::File { file: id, page }, backing, size, buffer, offset)
+}
+
+/// Writes the `buffer` to the file with the given `id` and `size`, starting at the byte `offset`, through the cache.
+/// The pages are written back to the given `backing` later.
+///
+/// This is meant to be used by a filesystem to implement `File::write()`, which must first extend the file
+/// (and its `size`) as needed, since only the part of the `buffer` that lies within the file is written.
+/// Returns the number of bytes written.
+pub fn write_file_pages(id: FileId, backing: &Arc<dyn PageBacking>, size: usize, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+    write(|page| CacheKey::File { file: id, page }, backing, size, buffer, offset)
+}
+
+/// Discards the cached contents of the file with the given `id` past its new `size`, after it has been truncated,
+/// such that they read as zeros if the file grows again.
+///
+/// Pages that lie completely past the end are dropped, unless they're mapped, in which case they're zeroed instead.
+pub fn truncate_file_pages(id: FileId, size: usize) {
+    let dropped: Vec<CachedPage> = {
+        let mut cache = PAGE_CACHE.lock();
+        let first = CacheKey::File { file: id, page: size / PAGE_SIZE };
+        let last = CacheKey::File { file: id, page: usize::max_value() };
+        let keys: Vec<CacheKey> = cache.range(first ..= last).map(|(key, _)| *key).collect();
+        let mut dropped = Vec::new();
+        for key in keys {
+            let start = match key {
+                CacheKey::File { page, .. } => page * PAGE_SIZE,
+                _ => continue,
+            };
+            match cache.get(&key).map(|cached| cached.mappings) {
+                Some(0) if start >= size => dropped.extend(cache.remove(&key)),
+                _ => if let Some(cached) = cache.get_mut(&key) {
+                    if let Ok(data) = cached.frame.as_slice_mut::<u8>(0, PAGE_SIZE) {
+                        for b in data[size.saturating_sub(start) ..].iter_mut() { *b = 0; }
+                    }
+                },
+            }
+        }
+        dropped
+    };
+    drop(dropped);
+}
+
+/// Maps `num_pages` pages of the given `file`, starting at the page index `first_page`, into a new region of memory
+/// with the given `flags`. The new mapping shares the frames that hold those pages in the cache instead of copying them,
+/// so it sees all reads and writes through the cache, and vice versa.
+///
+/// The mapped pages are pinned in the cache until [`unpin_file_pages()`](fn.unpin_file_pages.html) is called for them,
+/// which must be done after the returned mapping is dropped.
+/// Writes through the mapping aren't tracked, so the pages must be marked with
+/// [`mark_file_pages_dirty()`](fn.mark_file_pages_dirty.html) in order to be written back to the file.
+///
+/// # Locking / Deadlock
+/// This acquires the lock on the given `file`, the page cache, the frame allocator, and the kernel's `MemoryManagementInfo`.
+pub fn map_file_pages(file: &FileRef, first_page: usize, num_pages: usize, flags: EntryFlags) -> Result<MappedPages, &'static str> {
+    if num_pages == 0 {
+        return Err("page_cache::map_file_pages(): cannot map 0 pages");
+    }
+    let id = file_id(file);
+    let backing = registered_backing(id).unwrap_or_else(|| Arc::new(FileBacking(file.clone())));
+    let mut cached_pages = Vec::with_capacity(num_pages);
+    for page in first_page .. first_page + num_pages {
+        let pinned = with_cached_page(CacheKey::File { file: id, page }, &backing, true, |cached| {
+            cached.mappings += 1;
+            *cached.frame.start()
+        });
+        match pinned {
+            Ok(cached_page) => cached_pages.push(cached_page),
+            Err(e) => {
+                unpin_file_pages(file, first_page, cached_pages.len());
+                return Err(e);
+            }
+        }
+    }
+
+    map_cached_pages(&cached_pages, flags).map_err(|e| {
+        unpin_file_pages(file, first_page, num_pages);
+        e
+    })
+}
+
+/// Marks `num_pages` pages of the given `file`, starting at the page index `first_page`, as dirty,
+/// such that they're written back to the file, e.g., after they were modified through a mapping from
+/// [`map_file_pages()`](fn.map_file_pages.html). Pages that aren't cached are ignored.
+pub fn mark_file_pages_dirty(file: &FileRef, first_page: usize, num_pages: usize) {
+    let id = file_id(file);
+    let mut cache = PAGE_CACHE.lock();
+    for page in first_page .. first_page + num_pages {
+        if let Some(cached) = cache.get_mut(&CacheKey::File { file: id, page }) {
+            cached.dirty = true;
+        }
+    }
+}
+
+/// Unpins `num_pages` pages of the given `file`, starting at the page index `first_page`,
+/// that were pinned by [`map_file_pages()`](fn.map_file_pages.html), such that they can be evicted again.
+pub fn unpin_file_pages(file: &FileRef, first_page: usize, num_pages: usize) {
+    let id = file_id(file);
+    let mut cache = PAGE_CACHE.lock();
+    for page in first_page .. first_page + num_pages {
+        if let Some(cached) = cache.get_mut(&CacheKey::File { file: id, page }) {
+            cached.mappings = cached.mappings.saturating_sub(1);
+        }
+    }
+}
+
+
+/// Writes back all dirty pages in the cache, returning the number of pages written.
+pub fn sync() -> Result<usize, &'static str> {
+    // Writing back a file's pages may dirty the pages of the block device that the file is on,
+    // so files are written back first.
+    let files = write_back(|key| match *key { CacheKey::File { .. } => true, _ => false });
+    let block_devices = write_back(|key| match *key { CacheKey::Block { .. } => true, _ => false });
+    Ok(files? + block_devices?)
+}
+
+/// Writes back all dirty pages of the files on the filesystem with the given ID
+/// (see [`fs_node::new_filesystem_id()`](../fs_node/fn.new_filesystem_id.html)), returning the number of pages written.
+///
+/// This doesn't write back the pages of the block device that the filesystem is on, which this may dirty;
+/// use [`sync_block_device()`](fn.sync_block_device.html) for that afterwards.
+pub fn sync_filesystem(filesystem: u64) -> Result<usize, &'static str> {
+    write_back(|key| match *key {
+        CacheKey::File { file: FileId::Inode { filesystem: fs, .. }, .. } => fs == filesystem,
+        _ => false,
+    })
+}
+
+/// Writes back all dirty pages of the given block device, returning the number of pages written.
+pub fn sync_block_device(queue: &BlockQueue) -> Result<usize, &'static str> {
+    let id = queue.id();
+    write_back(|key| match *key {
+        CacheKey::Block { device, .. } => device == id,
+        _ => false,
+    })
+}
+
+/// Writes back all dirty pages of the given `file`, returning the number of pages written.
+pub fn sync_file(file: &FileRef) -> Result<usize, &'static str> {
+    let id = file_id(file);
+    write_back(|key| match *key {
+        CacheKey::File { file, .. } => file == id,
+        _ => false,
+    })
+}
+
+/// Drops all of the given `file`'s pages from the cache *without* writing them back,
+/// e.g., because the file is being deleted. Returns the number of pages dropped.
+pub fn invalidate_file(file: &FileRef) -> usize {
+    invalidate_file_pages(file_id(file))
+}
+
+
+/// Drops up to `num_pages` clean pages that aren't mapped from the cache, least-recently-used first,
+/// and returns the number of pages dropped.
+///
+/// This is registered as a reclaimer with the memory subsystem, so it gives up
+/// if the cache is currently locked, e.g., by the task whose allocation triggered it.
+pub fn reclaim(num_pages: usize) -> usize {
+    let evicted = match PAGE_CACHE.try_lock() {
+        Some(mut cache) => evict_clean_pages(&mut cache, num_pages),
+        None => return 0,
+    };
+    // The pages are unmapped (dropped) after the lock on the cache is released.
+    evicted.len()
+}
+
+/// Returns statistics about the page cache.
+pub fn stats() -> PageCacheStats {
+    let cache = PAGE_CACHE.lock();
+    PageCacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        cached_pages: cache.len(),
+        dirty_pages: cache.values().filter(|p| p.dirty).count(),
+        writebacks: WRITEBACKS.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+    }
+}
+
+
+/// Returns the backing that the filesystem of the file with the given `id` registered, if any.
+fn registered_backing(id: FileId) -> Option<Arc<dyn PageBacking>> {
+    FILE_BACKINGS.lock().get(&id).and_then(Weak::upgrade)
+}
+
+/// Drops all of the pages of the file with the given `id` from the cache without writing them back,
+/// and removes its registered backing, if any. Returns the number of pages dropped.
+fn invalidate_file_pages(id: FileId) -> usize {
+    FILE_BACKINGS.lock().remove(&id);
+    let dropped: Vec<CachedPage> = {
+        let mut cache = PAGE_CACHE.lock();
+        let keys: Vec<CacheKey> = cache.keys()
+            .filter(|key| match **key { CacheKey::File { file, .. } => file == id, _ => false })
+            .cloned()
+            .collect();
+        keys.iter().filter_map(|key| cache.remove(key)).collect()
+    };
+    dropped.len()
+}
+
+/// Returns the ID of the given file in a `CacheKey`.
+fn file_id(file: &FileRef) -> FileId {
+    let metadata = file.lock().metadata();
+    match (metadata.filesystem, metadata.inode) {
+        (Some(filesystem), Some(inode)) => FileId::Inode { filesystem, inode },
+        _ => FileId::Node(&**file as *const _ as *const u8 as usize),
+    }
+}
+
+/// Copies `len` bytes of the backing storage of size `size`, starting at `offset`, into `buffer` through the cache.
+fn read<K: Fn(usize) -> CacheKey>(key: K, backing: &Arc<dyn PageBacking>, size: usize, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+    if offset >= size {
+        return Ok(0);
+    }
+    let len = core::cmp::min(buffer.len(), size - offset);
+    let mut done = 0;
+    while done < len {
+        let position = offset + done;
+        let page_offset = position % PAGE_SIZE;
+        let n = core::cmp::min(PAGE_SIZE - page_offset, len - done);
+        with_page(key(position / PAGE_SIZE), backing, true, |data, _dirty| {
+            buffer[done .. done + n].copy_from_slice(&data[page_offset .. page_offset + n]);
+        })?;
+        done += n;
+    }
+    Ok(done)
+}
+
+/// Copies the `buffer` into the cached pages of the backing storage of size `size`, starting at `offset`,
+/// and marks them dirty.
+fn write<K: Fn(usize) -> CacheKey>(key: K, backing: &Arc<dyn PageBacking>, size: usize, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+    if offset >= size {
+        return Ok(0);
+    }
+    let len = core::cmp::min(buffer.len(), size - offset);
+    let mut done = 0;
+    while done < len {
+        let position = offset + done;
+        let page_offset = position % PAGE_SIZE;
+        let n = core::cmp::min(PAGE_SIZE - page_offset, len - done);
+        // A page that will be completely overwritten doesn't need to be read first.
+        let load = n != PAGE_SIZE;
+        with_page(key(position / PAGE_SIZE), backing, load, |data, dirty| {
+            data[page_offset .. page_offset + n].copy_from_slice(&buffer[done .. done + n]);
+            *dirty = true;
+        })?;
+        done += n;
+    }
+    Ok(done)
+}
+
+/// Maps new pages to the frames of the given pages of the cache, which must be pinned, in the same order.
+fn map_cached_pages(cached_pages: &[Page], flags: EntryFlags) -> Result<MappedPages, &'static str> {
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("page_cache: KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("page_cache: couldn't get the frame allocator")?;
+    let mut remaining_pages = Some(allocate_pages(cached_pages.len()).ok_or("page_cache: couldn't allocate pages for the mapping")?);
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+    let mut allocator = fa.lock();
+
+    // The cached frames aren't contiguous, so each page is mapped separately and then merged into one mapping.
+    let mut mapping: Option<MappedPages> = None;
+    for cached_page in cached_pages {
+        let frame = kernel_mmi.page_table.translate_page(*cached_page).ok_or("page_cache: a pinned page is no longer mapped")?;
+        let pages = remaining_pages.take().ok_or("page_cache: ran out of pages for the mapping")?;
+        let page = if pages.size_in_pages() > 1 {
+            let second_page = *pages.start() + 1;
+            let (first, rest) = pages.split(second_page).ok_or("page_cache: couldn't split the pages for the mapping")?;
+            remaining_pages = Some(rest);
+            first
+        } else {
+            pages
+        };
+        let mp = kernel_mmi.page_table.map_allocated_pages_to(page, FrameRange::new(frame, frame), flags, allocator.deref_mut())?;
+        match mapping {
+            Some(ref mut mapping) => mapping.merge(mp).map_err(|(e, _mp)| e)?,
+            None => mapping = Some(mp),
+        }
+    }
+    mapping.ok_or("page_cache: cannot map 0 pages")
+}
+
+/// Invokes `f` with the contents of the page with the given `key` and its dirty flag.
+/// See `with_cached_page()`.
+fn with_page<R, F: FnOnce(&mut [u8], &mut bool) -> R>(key: CacheKey, backing: &Arc<dyn PageBacking>, load: bool, f: F) -> Result<R, &'static str> {
+    with_cached_page(key, backing, load, |page| {
+        let CachedPage { ref mut frame, ref mut dirty, .. } = *page;
+        frame.as_slice_mut::<u8>(0, PAGE_SIZE).map(|data| f(data, dirty))
+    })?
+}
+
+/// Invokes `f` with the cached page with the given `key`.
+///
+/// If the page isn't cached, a frame is allocated for it and, if `load` is true, it is read from the `backing` storage.
+/// Neither happens while the cache is locked, because they may block or trigger reclaim.
+fn with_cached_page<R, F: FnOnce(&mut CachedPage) -> R>(key: CacheKey, backing: &Arc<dyn PageBacking>, load: bool, f: F) -> Result<R, &'static str> {
+    {
+        let mut cache = PAGE_CACHE.lock();
+        if let Some(page) = cache.get_mut(&key) {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            page.last_access = ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed);
+            return Ok(f(page));
+        }
+    }
+
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    let mut frame = create_mapping(PAGE_SIZE, EntryFlags::WRITABLE)?;
+    {
+        let data = frame.as_slice_mut::<u8>(0, PAGE_SIZE)?;
+        let page_index = match key {
+            CacheKey::Block { page, .. } | CacheKey::File { page, .. } => page,
+        };
+        if load {
+            backing.read_page(page_index, data)?;
+        } else {
+            for b in data.iter_mut() { *b = 0; }
+        }
+    }
+
+    let mut evicted = Vec::new();
+    let result = {
+        let mut cache = PAGE_CACHE.lock();
+        if cache.len() >= MAX_CACHED_PAGES {
+            let excess = cache.len() + 1 - MAX_CACHED_PAGES;
+            evicted = evict_clean_pages(&mut cache, excess);
+        }
+        // Another task may have cached this page in the meantime, in which case its copy is used.
+        let page = cache.entry(key).or_insert_with(|| CachedPage {
+            frame,
+            backing: backing.clone(),
+            dirty: false,
+            mappings: 0,
+            last_access: 0,
+        });
+        page.last_access = ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed);
+        f(page)
+    };
+    drop(evicted);
+    Ok(result)
+}
+
+/// Removes up to `num_pages` clean pages that aren't mapped from the given locked cache, least-recently-used first, and returns them.
+fn evict_clean_pages(cache: &mut BTreeMap<CacheKey, CachedPage>, num_pages: usize) -> Vec<CachedPage> {
+    let mut clean: Vec<(u64, CacheKey)> = cache.iter()
+        .filter(|(_, page)| !page.dirty && page.mappings == 0)
+        .map(|(key, page)| (page.last_access, *key))
+        .collect();
+    clean.sort_unstable();
+    let evicted: Vec<CachedPage> = clean.iter()
+        .take(num_pages)
+        .filter_map(|(_, key)| cache.remove(key))
+        .collect();
+    EVICTIONS.fetch_add(evicted.len() as u64, Ordering::Relaxed);
+    evicted
+}
+
+/// Writes back the dirty pages whose keys match the given filter, returning the number of pages written.
+///
+/// Each page's contents are copied out of the cache so that it isn't locked during the write.
+/// If a write fails, the page is marked dirty again and the first error is returned after trying all other pages.
+fn write_back<P: Fn(&CacheKey) -> bool>(filter: P) -> Result<usize, &'static str> {
+    let dirty_pages: Vec<(CacheKey, Arc<dyn PageBacking>, Vec<u8>)> = {
+        let mut cache = PAGE_CACHE.lock();
+        let mut dirty_pages = Vec::new();
+        for (key, page) in cache.iter_mut().filter(|(key, page)| page.dirty && filter(key)) {
+            if let Ok(data) = page.frame.as_slice::<u8>(0, PAGE_SIZE) {
+                dirty_pages.push((*key, page.backing.clone(), data.to_vec()));
+                page.dirty = false;
+            }
+        }
+        dirty_pages
+    };
+
+    let mut written = 0;
+    let mut first_error = None;
+    for (key, backing, data) in dirty_pages {
+        let page_index = match key {
+            CacheKey::Block { page, .. } | CacheKey::File { page, .. } => page,
+        };
+        match backing.write_page(page_index, &data) {
+            Ok(()) => written += 1,
+            Err(e) => {
+                error!("page_cache: failed to write back page {:?}: {}", key, e);
+                if let Some(page) = PAGE_CACHE.lock().get_mut(&key) {
+                    page.dirty = true;
+                }
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    WRITEBACKS.fetch_add(written as u64, Ordering::Relaxed);
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(written),
+    }
+}
+
+/// The suspend hook that writes back all dirty pages before the system shuts down, reboots, or suspends.
+fn sync_before_power_event(_event: PowerEvent) -> Result<(), &'static str> {
+    sync().map(|_written| debug!("page_cache: wrote back {} dirty pages before {:?}", _written, _event))
+}
+
+
+/// The asynchronous task that writes back all dirty pages every `FLUSH_INTERVAL_MS`.
+struct Flusher {
+    sleep: Sleep,
+}
+
+impl Future for Flusher {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        loop {
+            match Pin::new(&mut self.sleep).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(()) => {
+                    if let Err(e) = sync() {
+                        error!("page_cache: periodic write-back failed: {}", e);
+                    }
+                    self.sleep = async_runtime::sleep(FLUSH_INTERVAL_MS);
+                }
+            }
+        }
+    }
+}
@@ -7,24 +7,22 @@
 
 extern crate alloc;
 #[macro_use] extern crate log;
-extern crate memory;
-extern crate mod_mgmt;
 extern crate task;
 extern crate unwind;
-extern crate stack_trace;
-extern crate stack_trace_frame_pointers;
+extern crate backtrace;
 extern crate fault_log;
+extern crate crash_dump;
 
 use core::panic::PanicInfo;
 // use alloc::string::String;
-use memory::VirtualAddress;
 use task::{KillReason, PanicInfoOwned};
 use fault_log::log_panic_entry;
 
 /// Performs the standard panic handling routine, which involves the following:
 /// 
 /// * Invoking the current `Task`'s `kill_handler` routine, if it has registered one.
-/// * Printing a backtrace of the call stack.
+/// * Printing a symbolized backtrace of the call stack and recording it in the fault log.
+/// * Writing a crash dump, which persists across a reboot.
 /// * Finally, it performs stack unwinding of this `Task'`s stack and kills it.
 /// 
 /// Returns `Ok(())` if everything ran successfully, and `Err` otherwise.
@@ -33,60 +31,16 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
     log_panic_entry (panic_info);
     // fault_log::print_fault_log();
 
-    // print a stack trace
-    let stack_trace_result = {
-        // By default, we use DWARF-based debugging stack traces
-        #[cfg(not(frame_pointers))] {
-            error!("------------------ Stack Trace (DWARF) ---------------------------");
-            stack_trace::stack_trace(
-                &|stack_frame, stack_frame_iter| {
-                    let symbol_offset = stack_frame_iter.namespace().get_section_containing_address(
-                        VirtualAddress::new_canonical(stack_frame.call_site_address() as usize),
-                        false
-                    ).map(|(sec, offset)| (sec.name.clone(), offset));
-                    if let Some((symbol_name, offset)) = symbol_offset {
-                        error!("  {:>#018X} in {} + {:#X}", stack_frame.call_site_address(), symbol_name, offset);
-                    } else {
-                        error!("  {:>#018X} in ??", stack_frame.call_site_address());
-                    }
-                    true
-                },
-                None,
-            )
-        }
-        #[cfg(frame_pointers)] {
-            error!("------------------ Stack Trace (frame pointers) ------------------");
-            let namespace = task::get_my_current_task()
-                .map(|t| t.get_namespace())
-                .or_else(|| mod_mgmt::get_initial_kernel_namespace().cloned())
-                .ok_or("couldn't get current task's or default namespace")?;
-            let mmi_ref = task::get_my_current_task()
-                .map(|t| t.lock().mmi.clone())
-                .or_else(|| memory::get_kernel_mmi_ref())
-                .ok_or("couldn't get current task's or default kernel MMI")?;
-            let mmi = mmi_ref.lock();
-
-            stack_trace_frame_pointers::stack_trace_using_frame_pointers(
-                &mmi.page_table,
-                &mut |_frame_pointer, instruction_pointer: VirtualAddress| {
-                    let symbol_offset = namespace.get_section_containing_address(instruction_pointer, false)
-                        .map(|(sec, offset)| (sec.name.clone(), offset));
-                    if let Some((symbol_name, offset)) = symbol_offset {
-                        error!("  {:>#018X} in {} + {:#X}", instruction_pointer, symbol_name, offset);
-                    } else {
-                        error!("  {:>#018X} in ??", instruction_pointer);
-                    }
-                    true
-                },
-                None,
-            )
-        }
-    };
-    match stack_trace_result {
-        Ok(()) => error!("  Beginning of stack"),
-        Err(e) => error!("  {}", e),
-    }
+    // print a symbolized backtrace, skipping the frame of this function,
+    // and store it in the fault log for use by the fault recovery subsystem.
+    error!("------------------ Backtrace -------------------------------------");
+    let backtrace = backtrace::capture(1, None);
+    error!("{}", backtrace);
     error!("------------------------------------------------------------------");
+    let _ = fault_log::attach_backtrace(backtrace);
+
+    // persist the details of this panic, so that they can be inspected after a reboot
+    crash_dump::save_panic(panic_info);
 
     // Call this task's kill handler, if it has one.
     {
@@ -3,6 +3,7 @@
 /// directories via the Path struct 
 // #[macro_use] extern crate log;
 #[macro_use] extern crate alloc;
+#[macro_use] extern crate ktest;
 extern crate spin;
 extern crate fs_node;
 extern crate root;
@@ -252,4 +253,24 @@ impl PathComponent {
             PathComponent::ParentDir => String::from(".."),
         }
     }
-}
\ No newline at end of file
+}
+
+theseus_test! {
+    fn basename_stem_and_extension() {
+        let path = Path::new(String::from("/path/to/my/file.tar.gz"));
+        test_assert!(path.basename() == "file.tar.gz");
+        test_assert!(path.file_stem() == "file");
+        test_assert!(path.extension() == Some("gz"));
+        test_assert!(path.is_absolute());
+        test_assert!(!Path::new(String::from("my/file")).is_absolute());
+        Ok(())
+    }
+
+    fn relative_path() {
+        let path = Path::new(String::from("a/b/c"));
+        test_assert!(path.relative(&Path::new(String::from("a"))) == Some(Path::new(String::from("b/c"))));
+        test_assert!(path.relative(&Path::new(String::from("a/d"))) == Some(Path::new(String::from("../b/c"))));
+        test_assert!(path.relative(&Path::new(String::from("a/.."))).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+#![no_std]
+
+//! Writes the frames captured by `network_manager::capture` to a file in the classic pcap format,
+//! which Wireshark, tcpdump, and most other network analysis tools can read.
+//!
+//! The file starts with a global header, followed by a record header and the captured bytes of each frame.
+//! All captured frames are Ethernet frames, including those of the loopback interface.
+//!
+//! The timestamps of the frames are relative to when the network stack was initialized,
+//! so tools show them as times shortly after the Unix epoch.
+
+extern crate alloc;
+extern crate fs_node;
+extern crate network_manager;
+
+use alloc::vec::Vec;
+use fs_node::FileRef;
+use network_manager::capture::CapturedFrame;
+
+
+/// The magic number that identifies a pcap file with timestamps in microseconds.
+const MAGIC_NUMBER: u32 = 0xa1b2_c3d4;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+/// The link-layer header type of Ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// The size of the global header at the start of a pcap file.
+pub const GLOBAL_HEADER_SIZE: usize = 24;
+/// The size of the header in front of each frame.
+pub const RECORD_HEADER_SIZE: usize = 16;
+
+
+/// Returns the global header of a pcap file whose frames are captured with the given snapshot length.
+pub fn global_header(snap_len: usize) -> [u8; GLOBAL_HEADER_SIZE] {
+    let mut header = [0u8; GLOBAL_HEADER_SIZE];
+    header[0 .. 4].copy_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    header[4 .. 6].copy_from_slice(&VERSION_MAJOR.to_le_bytes());
+    header[6 .. 8].copy_from_slice(&VERSION_MINOR.to_le_bytes());
+    // The time zone offset (bytes 8..12) and timestamp accuracy (bytes 12..16) are always zero.
+    header[16 .. 20].copy_from_slice(&(snap_len as u32).to_le_bytes());
+    header[20 .. 24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
+
+/// Appends the record of the given frame, i.e., its header and its captured bytes, to `record`.
+pub fn encode_record(frame: &CapturedFrame, record: &mut Vec<u8>) {
+    let seconds = (frame.timestamp_ms / 1000) as u32;
+    let microseconds = ((frame.timestamp_ms % 1000) * 1000) as u32;
+    record.reserve(RECORD_HEADER_SIZE + frame.data.len());
+    record.extend_from_slice(&seconds.to_le_bytes());
+    record.extend_from_slice(&microseconds.to_le_bytes());
+    record.extend_from_slice(&(frame.data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(frame.original_len as u32).to_le_bytes());
+    record.extend_from_slice(&frame.data);
+}
+
+
+/// Writes captured frames to a file in the pcap format.
+pub struct PcapWriter {
+    file: FileRef,
+    /// The offset in the file at which the next record is written.
+    offset: usize,
+    frame_count: usize,
+}
+
+impl PcapWriter {
+    /// Writes the global header to the start of the given file, which should be empty,
+    /// and returns a writer that writes frames after it.
+    ///
+    /// The `snap_len` should be the snapshot length of the capture that the frames come from.
+    pub fn new(file: FileRef, snap_len: usize) -> Result<PcapWriter, &'static str> {
+        let mut writer = PcapWriter { file, offset: 0, frame_count: 0 };
+        writer.write_all(&global_header(snap_len))?;
+        Ok(writer)
+    }
+
+    /// Writes the given frame to the file.
+    pub fn write_frame(&mut self, frame: &CapturedFrame) -> Result<(), &'static str> {
+        let mut record = Vec::new();
+        encode_record(frame, &mut record);
+        self.write_all(&record)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Returns the number of frames that have been written.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Returns the number of bytes that have been written to the file.
+    pub fn bytes_written(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the file that the frames are written to.
+    pub fn file(&self) -> &FileRef {
+        &self.file
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let written = self.file.lock().write(data, self.offset)?;
+        if written != data.len() {
+            return Err("pcap: couldn't write the whole record to the file");
+        }
+        self.offset += written;
+        Ok(())
+    }
+}
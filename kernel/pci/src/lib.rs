@@ -8,6 +8,14 @@ extern crate spin;
 extern crate port_io;
 extern crate memory;
 extern crate bit_field;
+extern crate volatile;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate apic;
+extern crate interrupts;
+
+mod msi;
+pub use msi::{MsiInterrupt, MsiCapability, MsixCapability};
 
 use core::fmt;
 use core::ops::{Deref, DerefMut};
@@ -309,6 +317,27 @@ impl PciLocation {
         }
         None
     }
+
+    /// Like [`find_pci_capability`](#method.find_pci_capability), but returns the addresses of
+    /// every instance of the requested capability, in the order they appear in the list.
+    /// This is needed for capabilities that a device may have several of, e.g., vendor-specific ones.
+    pub fn find_pci_capabilities(&self, pci_capability: u16) -> Vec<u16> {
+        let mut found = Vec::new();
+        const CAPABILITIES_VALID: u16 = 1 << 4;
+        if self.pci_read_16(PCI_STATUS) & CAPABILITIES_VALID == 0 {
+            return found;
+        }
+
+        let mut cap_addr = self.pci_read_8(PCI_CAPABILITIES) as u16 & 0xFFFC;
+        while cap_addr != 0 {
+            let cap_header = self.pci_read_16(cap_addr);
+            if cap_header & 0xFF == pci_capability {
+                found.push(cap_addr);
+            }
+            cap_addr = (cap_header >> 8) & 0xFC;
+        }
+        found
+    }
 }
 
 impl fmt::Display for PciLocation {
@@ -356,22 +385,33 @@ impl PciDevice {
     /// Returns the base address of the memory mapped registers of the PCI device from BAR0 if 32-bit
     /// or BAR1:BAR0 if 64-bit
     pub fn determine_mem_base(&self) -> Result<PhysicalAddress, &'static str> {
+        self.bar_address(0)
+    }
+
+    /// Returns the base address of the memory region mapped by the BAR at the given index,
+    /// which also uses the following BAR for the upper 32 bits if it is a 64-bit BAR.
+    /// 
+    /// Returns an error if the BAR maps I/O ports rather than memory.
+    pub fn bar_address(&self, bar_index: u8) -> Result<PhysicalAddress, &'static str> {
         // value in the BAR which means a 64-bit address space
         const ADDRESS_64_BIT: u32 = 2;
-        let mut bar0 = self.bars[0];
+        let mut bar = *self.bars.get(bar_index as usize).ok_or("BAR index out of bounds")?;
+        if bar.get_bit(0) {
+            return Err("BAR maps I/O ports, not memory");
+        }
 
         // memory mapped base address
         let mem_base = 
             // retrieve bits 1-2 to determine address space size
-            if bar0.get_bits(1..3) == ADDRESS_64_BIT { 
-                // a 64-bit address so need to access BAR1 for the upper 32 bits
-                let bar1 = self.bars[1];
+            if bar.get_bits(1..3) == ADDRESS_64_BIT { 
+                // a 64-bit address so need to access the next BAR for the upper 32 bits
+                let upper_bar = *self.bars.get(bar_index as usize + 1).ok_or("64-bit BAR is missing its upper half")?;
                 // clear out the bottom 4 bits because it's a 16-byte aligned address
-                PhysicalAddress::new(*bar0.set_bits(0..4, 0) as usize | ((bar1 as usize) << 32))?
+                PhysicalAddress::new(*bar.set_bits(0..4, 0) as usize | ((upper_bar as usize) << 32))?
             }
             else {
                 // clear out the bottom 4 bits because it's a 16-byte aligned address
-                PhysicalAddress::new(*bar0.set_bits(0..4, 0) as usize)?
+                PhysicalAddress::new(*bar.set_bits(0..4, 0) as usize)?
             };  
         Ok(mem_base)
     }
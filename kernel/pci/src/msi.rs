@@ -0,0 +1,431 @@
+//! Support for Message Signaled Interrupts (MSI and MSI-X).
+//!
+//! Rather than asserting a legacy interrupt line shared with other devices,
+//! a device using MSI raises an interrupt by writing a message to a special address,
+//! which the local APIC of the destination core receives as an interrupt on a dedicated vector.
+//! MSI supports one vector per device in Theseus, while MSI-X supports many independent vectors,
+//! e.g., one for each receive queue of a NIC.
+//!
+//! The vectors themselves are allocated by the `interrupts` crate.
+//! Each enabled vector is represented by an `MsiInterrupt` handle, which frees its vector when dropped.
+
+use alloc::{
+    sync::Arc,
+    vec::Vec,
+};
+use core::ops::DerefMut;
+use spin::Mutex;
+use volatile::Volatile;
+use zerocopy::FromBytes;
+use memory::{EntryFlags, FrameRange, MappedPages, allocate_pages_by_bytes, get_frame_allocator_ref, get_kernel_mmi_ref};
+use x86_64::structures::idt::HandlerFunc;
+use interrupts::{allocate_msi_vector, allocate_msi_vectors, set_msi_handler, deregister_interrupt, unhandled_msi_interrupt_handler};
+use super::{PciDevice, PciLocation, MSI_CAPABILITY, MSIX_CAPABILITY};
+
+
+/// The base of the address range that MSI messages are written to (Intel SDM Vol. 3, 10.11.1).
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+/// The bit position of the destination APIC ID within an MSI message address.
+const MSI_DESTINATION_SHIFT: u32 = 12;
+
+// Register offsets within the MSI capability
+const MSI_MESSAGE_CONTROL:      u16 = 2;
+const MSI_MESSAGE_ADDRESS_LOW:  u16 = 4;
+const MSI_MESSAGE_ADDRESS_HIGH: u16 = 8;
+
+// Bits of the MSI Message Control register
+const MSI_ENABLE:               u16 = 1 << 0;
+const MSI_64_BIT_CAPABLE:       u16 = 1 << 7;
+const MSI_PER_VECTOR_MASKING:   u16 = 1 << 8;
+
+// Register offsets within the MSI-X capability
+const MSIX_MESSAGE_CONTROL:     u16 = 2;
+const MSIX_TABLE_OFFSET:        u16 = 4;
+const MSIX_PBA_OFFSET:          u16 = 8;
+
+// Bits of the MSI-X Message Control register
+const MSIX_FUNCTION_MASK:       u16 = 1 << 14;
+const MSIX_ENABLE:              u16 = 1 << 15;
+
+/// The bit in an MSI-X table entry's Vector Control register that masks that vector.
+const MSIX_VECTOR_MASKED:       u32 = 1;
+
+/// The mapping flags used for the MSI-X table.
+const MSIX_TABLE_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+
+/// Returns the MSI message address that targets the local APIC with the given ID.
+fn message_address(apic_id: u8) -> u32 {
+    MSI_ADDRESS_BASE | ((apic_id as u32) << MSI_DESTINATION_SHIFT)
+}
+
+/// Returns the MSI message data for the given vector, using fixed delivery mode and edge triggering.
+fn message_data(vector: u8) -> u32 {
+    vector as u32
+}
+
+
+/// The information in a PCI device's MSI capability.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiCapability {
+    /// The offset of the capability in the device's configuration space.
+    pub offset: u16,
+    /// Whether the device supports a 64-bit message address.
+    pub is_64bit: bool,
+    /// Whether the device supports masking individual vectors.
+    pub per_vector_masking: bool,
+    /// The number of vectors the device requests, which is a power of two up to 32.
+    pub max_vectors: u8,
+}
+
+impl MsiCapability {
+    fn message_data_offset(&self) -> u16 {
+        self.offset + if self.is_64bit { 12 } else { 8 }
+    }
+
+    fn mask_bits_offset(&self) -> u16 {
+        self.offset + if self.is_64bit { 16 } else { 12 }
+    }
+}
+
+
+/// The information in a PCI device's MSI-X capability.
+#[derive(Debug, Clone, Copy)]
+pub struct MsixCapability {
+    /// The offset of the capability in the device's configuration space.
+    pub offset: u16,
+    /// The number of entries in the MSI-X table, i.e., the number of vectors the device supports.
+    pub table_size: u16,
+    /// The index of the BAR that maps the MSI-X table.
+    pub table_bar: u8,
+    /// The offset of the MSI-X table from the start of its BAR.
+    pub table_offset: u32,
+    /// The index of the BAR that maps the Pending Bit Array.
+    pub pba_bar: u8,
+    /// The offset of the Pending Bit Array from the start of its BAR.
+    pub pba_offset: u32,
+}
+
+
+/// One entry in the MSI-X table, which configures one vector.
+#[derive(FromBytes)]
+#[repr(C)]
+struct MsixTableEntry {
+    message_address_low:  Volatile<u32>,
+    message_address_high: Volatile<u32>,
+    message_data:         Volatile<u32>,
+    vector_control:       Volatile<u32>,
+}
+
+/// The memory-mapped MSI-X table of a device, which is shared by all of its `MsiInterrupt`s.
+/// MSI-X is disabled on the device when this is dropped.
+struct MsixTable {
+    location: PciLocation,
+    capability: MsixCapability,
+    mapped_pages: MappedPages,
+    /// The offset of the table from the start of `mapped_pages`.
+    offset_in_pages: usize,
+}
+
+impl MsixTable {
+    fn entry(&mut self, index: u16) -> Result<&mut MsixTableEntry, &'static str> {
+        if index >= self.capability.table_size {
+            return Err("MSI-X table index out of bounds");
+        }
+        let offset = self.offset_in_pages + index as usize * core::mem::size_of::<MsixTableEntry>();
+        self.mapped_pages.as_type_mut::<MsixTableEntry>(offset)
+    }
+}
+
+impl Drop for MsixTable {
+    fn drop(&mut self) {
+        let control_offset = self.capability.offset + MSIX_MESSAGE_CONTROL;
+        let control = self.location.pci_read_16(control_offset);
+        write_16(&self.location, control_offset, control & !MSIX_ENABLE);
+    }
+}
+
+
+/// The kind of message signaled interrupt that an `MsiInterrupt` was enabled as.
+enum MsiKind {
+    Msi {
+        location: PciLocation,
+        capability: MsiCapability,
+    },
+    MsiX {
+        table: Arc<Mutex<MsixTable>>,
+        index: u16,
+    },
+}
+
+/// A handle to one MSI or MSI-X vector of a PCI device.
+///
+/// Interrupts from the device arrive on this handle's `vector()`, which the driver assigns a handler to
+/// with `set_handler()`. Until then, interrupts on that vector are acknowledged and ignored.
+///
+/// When dropped, the vector is masked (or MSI is disabled) and the interrupt number is freed.
+pub struct MsiInterrupt {
+    vector: u8,
+    destination: u8,
+    handler: Option<HandlerFunc>,
+    kind: MsiKind,
+}
+
+impl MsiInterrupt {
+    /// Returns the interrupt number that this vector's interrupts arrive on.
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// Returns the index of this vector in the device's MSI-X table, or 0 for MSI.
+    pub fn index(&self) -> u16 {
+        match self.kind {
+            MsiKind::Msi { .. } => 0,
+            MsiKind::MsiX { index, .. } => index,
+        }
+    }
+
+    /// Returns the ID of the local APIC (core) that this vector's interrupts are sent to.
+    pub fn destination(&self) -> u8 {
+        self.destination
+    }
+
+    /// Sets the function that handles this vector's interrupts.
+    /// The handler is responsible for sending an end of interrupt signal, i.e., `interrupts::eoi(None)`.
+    /// A handler can only be set once.
+    pub fn set_handler(&mut self, func: HandlerFunc) -> Result<(), &'static str> {
+        set_msi_handler(self.vector, func)?;
+        self.handler = Some(func);
+        Ok(())
+    }
+
+    /// Routes this vector's interrupts to the local APIC (core) with the given ID.
+    pub fn set_destination(&mut self, apic_id: u8) -> Result<(), &'static str> {
+        match self.kind {
+            MsiKind::Msi { ref location, capability } => {
+                location.pci_write(capability.offset + MSI_MESSAGE_ADDRESS_LOW, message_address(apic_id));
+            }
+            MsiKind::MsiX { ref table, index } => {
+                let mut table = table.lock();
+                let entry = table.entry(index)?;
+                // The address must only be changed while the vector is masked.
+                let control = entry.vector_control.read();
+                entry.vector_control.write(control | MSIX_VECTOR_MASKED);
+                entry.message_address_low.write(message_address(apic_id));
+                entry.vector_control.write(control);
+            }
+        }
+        self.destination = apic_id;
+        Ok(())
+    }
+
+    /// Prevents the device from raising interrupts on this vector.
+    /// Returns an error if this is an MSI vector and the device doesn't support per-vector masking.
+    pub fn mask(&mut self) -> Result<(), &'static str> {
+        self.set_masked(true)
+    }
+
+    /// Allows the device to raise interrupts on this vector again after `mask()`.
+    pub fn unmask(&mut self) -> Result<(), &'static str> {
+        self.set_masked(false)
+    }
+
+    fn set_masked(&mut self, masked: bool) -> Result<(), &'static str> {
+        match self.kind {
+            MsiKind::Msi { ref location, capability } => {
+                if !capability.per_vector_masking {
+                    return Err("device doesn't support masking MSI vectors");
+                }
+                let mask_bits = location.pci_read_32(capability.mask_bits_offset());
+                let mask_bits = if masked { mask_bits | 1 } else { mask_bits & !1 };
+                location.pci_write(capability.mask_bits_offset(), mask_bits);
+            }
+            MsiKind::MsiX { ref table, index } => {
+                let mut table = table.lock();
+                let entry = table.entry(index)?;
+                let control = entry.vector_control.read();
+                let control = if masked { control | MSIX_VECTOR_MASKED } else { control & !MSIX_VECTOR_MASKED };
+                entry.vector_control.write(control);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MsiInterrupt {
+    fn drop(&mut self) {
+        match self.kind {
+            MsiKind::Msi { ref location, capability } => {
+                let control_offset = capability.offset + MSI_MESSAGE_CONTROL;
+                let control = location.pci_read_16(control_offset);
+                write_16(location, control_offset, control & !MSI_ENABLE);
+            }
+            MsiKind::MsiX { .. } => {
+                let _ = self.set_masked(true);
+            }
+        }
+        let handler = self.handler.unwrap_or(unhandled_msi_interrupt_handler);
+        if let Err(e) = deregister_interrupt(self.vector, handler) {
+            error!("Failed to free MSI vector {}: {}", self.vector, e);
+        }
+    }
+}
+
+
+/// Writes a 16-bit value to the given `offset` in the configuration space,
+/// preserving the other 16 bits of the 32-bit register that contains it.
+fn write_16(location: &PciLocation, offset: u16, value: u16) {
+    let aligned = offset & !0x3;
+    let shift = (offset & 0x2) * 8;
+    let old = location.pci_read_32(aligned);
+    let new = (old & !(0xFFFF << shift)) | ((value as u32) << shift);
+    location.pci_write(aligned, new);
+}
+
+
+impl PciDevice {
+    /// Returns this device's MSI capability, if it has one.
+    pub fn msi_capability(&self) -> Option<MsiCapability> {
+        let offset = self.find_pci_capability(MSI_CAPABILITY)?;
+        let control = self.pci_read_16(offset + MSI_MESSAGE_CONTROL);
+        Some(MsiCapability {
+            offset,
+            is_64bit: control & MSI_64_BIT_CAPABLE != 0,
+            per_vector_masking: control & MSI_PER_VECTOR_MASKING != 0,
+            max_vectors: 1 << ((control >> 1) & 0x7),
+        })
+    }
+
+    /// Returns this device's MSI-X capability, if it has one.
+    pub fn msix_capability(&self) -> Option<MsixCapability> {
+        let offset = self.find_pci_capability(MSIX_CAPABILITY)?;
+        let control = self.pci_read_16(offset + MSIX_MESSAGE_CONTROL);
+        let table = self.pci_read_32(offset + MSIX_TABLE_OFFSET);
+        let pba = self.pci_read_32(offset + MSIX_PBA_OFFSET);
+        Some(MsixCapability {
+            offset,
+            table_size: (control & 0x7FF) + 1,
+            table_bar: (table & 0x7) as u8,
+            table_offset: table & !0x7,
+            pba_bar: (pba & 0x7) as u8,
+            pba_offset: pba & !0x7,
+        })
+    }
+
+    /// Enables MSI for this device with a single vector that is sent to the current core,
+    /// and disables its legacy interrupt line.
+    ///
+    /// Returns an error if the device isn't MSI capable or there are no free interrupt numbers.
+    pub fn enable_msi(&self) -> Result<MsiInterrupt, &'static str> {
+        let capability = self.msi_capability().ok_or("Device not MSI capable")?;
+        let vector = allocate_msi_vector()?;
+        let destination = apic::get_my_apic_id();
+
+        let control_offset = capability.offset + MSI_MESSAGE_CONTROL;
+        // Disable MSI while it's being configured, and only enable one vector (Multiple Message Enable = 0).
+        let control = self.pci_read_16(control_offset) & !MSI_ENABLE & !(0x7 << 4);
+        write_16(&self.location, control_offset, control);
+
+        self.pci_write(capability.offset + MSI_MESSAGE_ADDRESS_LOW, message_address(destination));
+        if capability.is_64bit {
+            self.pci_write(capability.offset + MSI_MESSAGE_ADDRESS_HIGH, 0);
+        }
+        write_16(&self.location, capability.message_data_offset(), message_data(vector) as u16);
+        if capability.per_vector_masking {
+            self.pci_write(capability.mask_bits_offset(), 0);
+        }
+
+        write_16(&self.location, control_offset, control | MSI_ENABLE);
+        self.pci_set_interrupt_disable_bit();
+
+        Ok(MsiInterrupt {
+            vector,
+            destination,
+            handler: None,
+            kind: MsiKind::Msi { location: self.location, capability },
+        })
+    }
+
+    /// Enables MSI-X for this device with `num_vectors` vectors, e.g., one per queue,
+    /// which are all sent to the current core, and disables its legacy interrupt line.
+    ///
+    /// The returned handles correspond to MSI-X table entries `0..num_vectors`, in order.
+    /// Any remaining table entries are masked.
+    ///
+    /// Returns an error if the device isn't MSI-X capable, doesn't support `num_vectors` vectors,
+    /// or there aren't enough free interrupt numbers.
+    pub fn enable_msix(&self, num_vectors: usize) -> Result<Vec<MsiInterrupt>, &'static str> {
+        let capability = self.msix_capability().ok_or("Device not MSI-X capable")?;
+        if num_vectors == 0 || num_vectors > capability.table_size as usize {
+            return Err("Device doesn't support the requested number of MSI-X vectors");
+        }
+
+        let table = map_msix_table(self, capability)?;
+        let vectors = allocate_msi_vectors(num_vectors)?;
+        let destination = apic::get_my_apic_id();
+
+        // Mask all vectors while the table is being configured.
+        let control_offset = capability.offset + MSIX_MESSAGE_CONTROL;
+        let control = self.pci_read_16(control_offset);
+        write_16(&self.location, control_offset, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+
+        let table = Arc::new(Mutex::new(table));
+        let mut interrupts = Vec::with_capacity(num_vectors);
+        {
+            let mut t = table.lock();
+            for index in 0..capability.table_size {
+                let entry = t.entry(index)?;
+                match vectors.get(index as usize) {
+                    Some(&vector) => {
+                        entry.message_address_low.write(message_address(destination));
+                        entry.message_address_high.write(0);
+                        entry.message_data.write(message_data(vector));
+                        entry.vector_control.write(0);
+                    }
+                    None => entry.vector_control.write(MSIX_VECTOR_MASKED),
+                }
+            }
+        }
+        for (index, &vector) in vectors.iter().enumerate() {
+            interrupts.push(MsiInterrupt {
+                vector,
+                destination,
+                handler: None,
+                kind: MsiKind::MsiX { table: table.clone(), index: index as u16 },
+            });
+        }
+
+        write_16(&self.location, control_offset, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+        self.pci_set_interrupt_disable_bit();
+
+        Ok(interrupts)
+    }
+}
+
+
+/// Maps the MSI-X table of the given device into the kernel's address space.
+fn map_msix_table(dev: &PciDevice, capability: MsixCapability) -> Result<MsixTable, &'static str> {
+    let bar_base = dev.bar_address(capability.table_bar)?;
+    let table_start = bar_base + capability.table_offset as usize;
+    let table_size_in_bytes = capability.table_size as usize * core::mem::size_of::<MsixTableEntry>();
+    let offset_in_pages = table_start.frame_offset();
+
+    // The table is in device memory rather than RAM, so the frame allocator doesn't own these frames.
+    let pages = allocate_pages_by_bytes(offset_in_pages + table_size_in_bytes).ok_or("couldn't allocate pages for the MSI-X table")?;
+    let frames = FrameRange::from_phys_addr(table_start, table_size_in_bytes);
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("couldn't get the frame allocator")?;
+    let mapped_pages = kernel_mmi_ref.lock().page_table.map_allocated_pages_to(pages, frames, MSIX_TABLE_MAPPING_FLAGS, fa.lock().deref_mut())?;
+
+    Ok(MsixTable {
+        location: dev.location,
+        capability,
+        mapped_pages,
+        offset_in_pages,
+    })
+}
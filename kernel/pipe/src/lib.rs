@@ -0,0 +1,295 @@
+//! An anonymous pipe for Inter-Task Communication (ITC) that carries a stream of bytes
+//! from one writer task to one reader task through a bounded-capacity buffer.
+//!
+//! Unlike the unbounded `stdio` ring buffers, a pipe applies backpressure:
+//! a writer blocks once the buffer is full until the reader drains some of it,
+//! and a reader blocks while the buffer is empty until the writer sends more bytes.
+//!
+//! Dropping the `PipeWriter` marks the end of the stream, after which the reader
+//! receives any remaining buffered bytes and then reads of length `0` (EOF).
+//! Dropping the `PipeReader` causes all subsequent writes to fail with `PipeError::BrokenPipe`.
+//!
+//! Both ends implement `bare_io::Read`/`bare_io::Write`, so they can be used
+//! anywhere the stdio streams are used.
+
+#![no_std]
+
+extern crate alloc;
+extern crate spin;
+extern crate bare_io;
+extern crate wait_queue;
+
+use core::cmp::min;
+use alloc::{
+    collections::VecDeque,
+    sync::Arc,
+};
+use spin::Mutex;
+use wait_queue::WaitQueue;
+
+
+/// The default capacity in bytes of a pipe's internal buffer,
+/// which is used by [`new_pipe_default()`](fn.new_pipe_default.html).
+pub const DEFAULT_PIPE_CAPACITY: usize = 4096;
+
+
+/// Creates a new pipe whose internal buffer holds at most `capacity` bytes,
+/// with a minimum capacity of 1 byte.
+///
+/// Returns a tuple of `(PipeWriter, PipeReader)`.
+pub fn new_pipe(capacity: usize) -> (PipeWriter, PipeReader) {
+    let capacity = core::cmp::max(capacity, 1);
+    let pipe = Arc::new(Pipe {
+        buffer: Mutex::new(PipeBuffer {
+            bytes: VecDeque::with_capacity(capacity),
+            capacity,
+            writer_closed: false,
+            reader_closed: false,
+        }),
+        waiting_readers: WaitQueue::new(),
+        waiting_writers: WaitQueue::new(),
+    });
+    (
+        PipeWriter { pipe: pipe.clone() },
+        PipeReader { pipe },
+    )
+}
+
+/// Creates a new pipe with the default capacity of [`DEFAULT_PIPE_CAPACITY`](constant.DEFAULT_PIPE_CAPACITY.html) bytes.
+pub fn new_pipe_default() -> (PipeWriter, PipeReader) {
+    new_pipe(DEFAULT_PIPE_CAPACITY)
+}
+
+
+/// The errors that can occur when reading from or writing to a pipe.
+#[derive(Debug, PartialEq)]
+pub enum PipeError {
+    /// The `PipeReader` was dropped, so written bytes can never be received.
+    BrokenPipe,
+    /// A non-blocking operation could not make progress:
+    /// the buffer was empty (for a read) or full (for a write).
+    WouldBlock,
+    /// An error occurred while waiting on the pipe's `WaitQueue`.
+    WaitError(wait_queue::WaitError),
+}
+
+impl From<PipeError> for bare_io::Error {
+    fn from(e: PipeError) -> bare_io::Error {
+        match e {
+            PipeError::BrokenPipe => bare_io::Error::new(bare_io::ErrorKind::BrokenPipe, "the pipe's reader was dropped"),
+            PipeError::WouldBlock => bare_io::Error::new(bare_io::ErrorKind::WouldBlock, "the pipe operation would block"),
+            PipeError::WaitError(_) => bare_io::Error::new(bare_io::ErrorKind::Other, "failed to wait on the pipe"),
+        }
+    }
+}
+
+
+/// The state shared between the two ends of a pipe.
+struct PipeBuffer {
+    bytes: VecDeque<u8>,
+    capacity: usize,
+    writer_closed: bool,
+    reader_closed: bool,
+}
+
+impl PipeBuffer {
+    /// Moves as many buffered bytes as will fit into `buf`, returning the number of bytes moved.
+    fn pop_into(&mut self, buf: &mut [u8]) -> usize {
+        let count = min(buf.len(), self.bytes.len());
+        for (dest, src) in buf.iter_mut().zip(self.bytes.drain(..count)) {
+            *dest = src;
+        }
+        count
+    }
+
+    /// Appends as many bytes from `buf` as there is space for, returning the number of bytes appended.
+    fn push_from(&mut self, buf: &[u8]) -> usize {
+        let count = min(buf.len(), self.capacity - self.bytes.len());
+        self.bytes.extend(&buf[..count]);
+        count
+    }
+}
+
+/// The inner pipe, which is shared by its `PipeWriter` and `PipeReader` using an `Arc`.
+struct Pipe {
+    buffer: Mutex<PipeBuffer>,
+    waiting_readers: WaitQueue,
+    waiting_writers: WaitQueue,
+}
+
+
+/// The writing end of a pipe.
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+impl PipeWriter {
+    /// Writes some bytes from `buf` into the pipe, blocking until buffer space is available.
+    ///
+    /// Returns the number of bytes written, which is at least 1 unless `buf` is empty.
+    /// Use [`write_all()`](#method.write_all) to ensure that all bytes in `buf` are written.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, PipeError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // This closure is invoked from within the waitqueue's locked context,
+        // so we must notify the readers after it returns to avoid deadlock.
+        let mut closure = || {
+            let mut buffer = self.pipe.buffer.lock();
+            if buffer.reader_closed {
+                Some(Err(PipeError::BrokenPipe))
+            } else if buffer.bytes.len() < buffer.capacity {
+                Some(Ok(buffer.push_from(buf)))
+            } else {
+                None
+            }
+        };
+
+        let res = match self.pipe.waiting_writers.wait_until_mut(&mut closure) {
+            Ok(r) => r,
+            Err(wait_error) => Err(PipeError::WaitError(wait_error)),
+        };
+        if res.is_ok() {
+            self.pipe.waiting_readers.notify_one();
+        }
+        res
+    }
+
+    /// Writes all of the bytes in `buf` into the pipe, blocking as needed until they all fit.
+    pub fn write_all(&self, mut buf: &[u8]) -> Result<(), PipeError> {
+        while !buf.is_empty() {
+            let written = self.write(buf)?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+
+    /// Same as [`write()`](#method.write), but is non-blocking.
+    ///
+    /// Returns `PipeError::WouldBlock` if the buffer is full.
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize, PipeError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let written = {
+            let mut buffer = self.pipe.buffer.lock();
+            if buffer.reader_closed {
+                return Err(PipeError::BrokenPipe);
+            }
+            buffer.push_from(buf)
+        };
+        if written == 0 {
+            return Err(PipeError::WouldBlock);
+        }
+        self.pipe.waiting_readers.notify_one();
+        Ok(written)
+    }
+
+    /// Returns `true` if the `PipeReader` has been dropped.
+    pub fn is_broken(&self) -> bool {
+        self.pipe.buffer.lock().reader_closed
+    }
+}
+
+impl bare_io::Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, bare_io::Error> {
+        PipeWriter::write(self, buf).map_err(|e| e.into())
+    }
+
+    /// Bytes are written directly into the pipe's buffer, so there is nothing to flush.
+    fn flush(&mut self) -> Result<(), bare_io::Error> {
+        Ok(())
+    }
+}
+
+/// Dropping the writer marks the end of the stream and wakes up the waiting reader.
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.buffer.lock().writer_closed = true;
+        self.pipe.waiting_readers.notify_all();
+    }
+}
+
+
+/// The reading end of a pipe.
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+impl PipeReader {
+    /// Reads some bytes from the pipe into `buf`, blocking until at least one byte is available.
+    ///
+    /// Returns the number of bytes read.
+    /// This only returns `0` if `buf` is empty or if the `PipeWriter` has been dropped
+    /// and all of the buffered bytes have already been read (EOF).
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, PipeError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // This closure is invoked from within the waitqueue's locked context,
+        // so we must notify the writers after it returns to avoid deadlock.
+        let mut closure = || {
+            let mut buffer = self.pipe.buffer.lock();
+            if !buffer.bytes.is_empty() {
+                Some(buffer.pop_into(buf))
+            } else if buffer.writer_closed {
+                Some(0)
+            } else {
+                None
+            }
+        };
+
+        let count = self.pipe.waiting_readers
+            .wait_until_mut(&mut closure)
+            .map_err(PipeError::WaitError)?;
+        if count > 0 {
+            self.pipe.waiting_writers.notify_one();
+        }
+        Ok(count)
+    }
+
+    /// Same as [`read()`](#method.read), but is non-blocking.
+    ///
+    /// Returns `PipeError::WouldBlock` if the buffer is empty but the stream has not yet ended.
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize, PipeError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let count = {
+            let mut buffer = self.pipe.buffer.lock();
+            if buffer.bytes.is_empty() {
+                return if buffer.writer_closed { Ok(0) } else { Err(PipeError::WouldBlock) };
+            }
+            buffer.pop_into(buf)
+        };
+        self.pipe.waiting_writers.notify_one();
+        Ok(count)
+    }
+
+    /// Returns the number of bytes currently buffered in the pipe.
+    pub fn available_bytes(&self) -> usize {
+        self.pipe.buffer.lock().bytes.len()
+    }
+
+    /// Returns `true` if the `PipeWriter` has been dropped and all buffered bytes have been read.
+    pub fn is_eof(&self) -> bool {
+        let buffer = self.pipe.buffer.lock();
+        buffer.writer_closed && buffer.bytes.is_empty()
+    }
+}
+
+impl bare_io::Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, bare_io::Error> {
+        PipeReader::read(self, buf).map_err(|e| e.into())
+    }
+}
+
+/// Dropping the reader breaks the pipe and wakes up the waiting writer.
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.buffer.lock().reader_closed = true;
+        self.pipe.waiting_writers.notify_all();
+    }
+}
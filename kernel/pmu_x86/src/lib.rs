@@ -54,11 +54,16 @@
 //! }
 //! ```
 //!
+//! Once samples have been retrieved, [`Profile::from_samples()`](struct.Profile.html#method.from_samples)
+//! attributes them to the crate sections (functions) that contain each sampled instruction pointer,
+//! producing a flat profile and, if Theseus was built with frame pointers, a caller-callee call graph.
+//! 
 //! # Note
 //! Currently, the PMU-based sampler will only capture samples on the same core as it was initialized and started from. 
 //! So, if you run `pmu_x86::init()` and `pmu_x86::start_samples()` on CPU core 2, it will only sample events on core 2.
 
 #![no_std]
+#![feature(llvm_asm)]
 
 extern crate spin;
 #[macro_use] extern crate lazy_static;
@@ -86,6 +91,7 @@ use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
 use bit_field::BitField;
 use core::sync::atomic::{Ordering, AtomicU64, AtomicU8};
+use core::fmt;
 
 pub mod stat;
 
@@ -119,6 +125,8 @@ const CORES_SUPPORTED_BY_PMU: usize = 256;
 const PMCS_SUPPORTED_BY_PMU: u8 = 8;
 /// The initial value for the bitmaps in PMCS_AVAILABLE 
 const INIT_VAL_PMCS_AVAILABLE: u8 = core::u8::MAX;
+/// The maximum number of callers recorded for each sample, used to build a call graph.
+pub const MAX_CALL_CHAIN_DEPTH: usize = 8;
 
 /// Stores an 8-bit bitmap for each core to show whether the PMCs for that core are available (1) or in use (0).
 /// This restricts us to 8 general purpose PMCs per core.
@@ -622,6 +630,7 @@ struct SampledEvents{
     sample_count: u32,
     ip_list: Vec<VirtualAddress>,
     task_id_list: Vec<usize>,
+    call_chain_list: Vec<CallChain>,
 }
 
 impl SampledEvents {
@@ -632,8 +641,78 @@ impl SampledEvents {
             sample_count: 0,
             ip_list: Vec::with_capacity(capacity),
             task_id_list: Vec::with_capacity(capacity),
+            call_chain_list: Vec::with_capacity(capacity),
+        }
+    }
+}
+
+/// The return addresses of the callers of a sampled instruction pointer, innermost first.
+/// 
+/// This has a fixed size such that recording it in the sampling interrupt handler doesn't allocate.
+#[derive(Clone, Copy)]
+struct CallChain {
+    return_addresses: [usize; MAX_CALL_CHAIN_DEPTH],
+    len: usize,
+}
+
+impl CallChain {
+    fn empty() -> CallChain {
+        CallChain { return_addresses: [0; MAX_CALL_CHAIN_DEPTH], len: 0 }
+    }
+
+    fn to_vec(&self) -> Vec<VirtualAddress> {
+        self.return_addresses[..self.len].iter().map(|&addr| VirtualAddress(addr)).collect()
+    }
+}
+
+/// Walks up the frame pointer chain from the sampling interrupt handler to find the callers
+/// of the interrupted function at `interrupted_ip`, without allocating or taking any locks.
+/// 
+/// The interrupt stack frame sits where a return address would normally be,
+/// so the frame whose "return address" is `interrupted_ip` marks the boundary between
+/// the interrupt handler's frames and the interrupted code's frames.
+/// Only frames within the given `stack_bounds` are followed.
+#[cfg(frame_pointers)]
+#[inline(never)]
+fn record_call_chain(interrupted_ip: usize, stack_bounds: (usize, usize)) -> CallChain {
+    let mut chain = CallChain::empty();
+    let (stack_bottom, stack_top) = stack_bounds;
+    let mut rbp: usize;
+    // SAFE: just reading current register value
+    unsafe {
+        llvm_asm!("" : "={rbp}"(rbp) : : "memory" : "intel", "volatile");
+    }
+
+    // The handler's frames may be on a separate interrupt stack, so they aren't bounds-checked,
+    // but they are few and were just created by the handler itself.
+    let mut found_interrupted_frame = false;
+    for _i in 0 .. (MAX_CALL_CHAIN_DEPTH + 8) {
+        if found_interrupted_frame && (rbp < stack_bottom || rbp + 2 * core::mem::size_of::<usize>() > stack_top) {
+            break;
+        }
+        if rbp == 0 || rbp % core::mem::size_of::<usize>() != 0 {
+            break;
         }
+        // SAFE: the frame pointer was checked above to be aligned and, for the interrupted code, within its stack
+        let (prev_rbp, rip) = unsafe { (*(rbp as *const usize), *((rbp + core::mem::size_of::<usize>()) as *const usize)) };
+        if found_interrupted_frame {
+            if rip == 0 || chain.len == MAX_CALL_CHAIN_DEPTH {
+                break;
+            }
+            chain.return_addresses[chain.len] = rip;
+            chain.len += 1;
+        } else if rip == interrupted_ip {
+            found_interrupted_frame = true;
+        }
+        rbp = prev_rbp;
     }
+    chain
+}
+
+/// Call chains cannot be recorded without frame pointers.
+#[cfg(not(frame_pointers))]
+fn record_call_chain(_interrupted_ip: usize, _stack_bounds: (usize, usize)) -> CallChain {
+    CallChain::empty()
 }
 
 /// Start interrupt process in order to take samples using the PMU. 
@@ -741,6 +820,9 @@ fn stop_samples(core_id: u8, samples: &mut SampledEvents) -> Result<(), &'static
 pub struct SampleResults {
     pub instruction_pointers: Vec<VirtualAddress>,
     pub task_ids:  Vec<usize>,
+    /// The return addresses of each sample's callers, innermost first.
+    /// These are empty unless Theseus was built with frame pointers.
+    pub call_chains: Vec<Vec<VirtualAddress>>,
 }
 
 /// Returns the samples that were stored during sampling in the form of a SampleResults object. 
@@ -758,7 +840,100 @@ pub fn retrieve_samples() -> Result<SampleResults, &'static str> {
     
     sampling_results_have_been_retrieved(my_core_id)?;
 
-    Ok(SampleResults{instruction_pointers: samples.ip_list.clone(), task_ids: samples.task_id_list.clone()})   
+    Ok(SampleResults{
+        instruction_pointers: samples.ip_list.clone(),
+        task_ids: samples.task_id_list.clone(),
+        call_chains: samples.call_chain_list.iter().map(|chain| chain.to_vec()).collect(),
+    })   
+}
+
+/// Stops event based sampling on this core early, e.g., at the end of a profiling period,
+/// such that the samples taken so far can be retrieved with [`retrieve_samples()`](fn.retrieve_samples.html).
+pub fn stop_sampling() -> Result<(), &'static str> {
+    let my_core_id = apic::get_my_apic_id();
+    let mut sampling_info = SAMPLING_INFO.lock();
+    let mut samples = sampling_info.get_mut(&my_core_id).ok_or("pmu_x86::stop_sampling: could not retrieve sampling information for this core")?;
+    if core_is_currently_sampling(my_core_id) {
+        stop_samples(my_core_id, &mut samples)?;
+    }
+    Ok(())
+}
+
+
+/// A profile built from PMU samples, in which each sample is attributed to the crate section
+/// (typically a function) containing its instruction pointer.
+pub struct Profile {
+    /// The total number of samples, including those that couldn't be attributed to any section.
+    pub total_samples: usize,
+    /// The number of samples in each section, sorted from most to fewest samples.
+    pub flat: Vec<(String, usize)>,
+    /// The number of samples in which the caller section (first) had called the callee section (second),
+    /// sorted from most to fewest samples. This is empty unless Theseus was built with frame pointers.
+    pub call_graph: Vec<((String, String), usize)>,
+}
+
+impl Profile {
+    /// Builds a flat profile and call graph from the given `sample_results`,
+    /// resolving addresses using the current task's `CrateNamespace`.
+    pub fn from_samples(sample_results: &SampleResults) -> Result<Profile, &'static str> {
+        let taskref = task::get_my_current_task().ok_or("pmu_x86::Profile::from_samples: Could not get reference to current task")?;
+        let namespace = taskref.get_namespace();
+        let section_name = |addr: &VirtualAddress| -> Option<String> {
+            let vaddr = memory::VirtualAddress::new(addr.0).ok()?;
+            namespace.get_section_containing_address(vaddr, true)
+                .map(|(sec, _offset)| sec.name_without_hash().to_string())
+        };
+
+        let mut flat: BTreeMap<String, usize> = BTreeMap::new();
+        let mut call_graph: BTreeMap<(String, String), usize> = BTreeMap::new();
+        for (i, ip) in sample_results.instruction_pointers.iter().enumerate() {
+            let mut callee = match section_name(ip) {
+                Some(name) => name,
+                None => continue,
+            };
+            *flat.entry(callee.clone()).or_insert(0) += 1;
+
+            for return_addr in sample_results.call_chains.get(i).into_iter().flatten() {
+                let caller = match section_name(return_addr) {
+                    Some(name) => name,
+                    None => break,
+                };
+                *call_graph.entry((caller.clone(), callee)).or_insert(0) += 1;
+                callee = caller;
+            }
+        }
+
+        let mut flat: Vec<(String, usize)> = flat.into_iter().collect();
+        flat.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut call_graph: Vec<((String, String), usize)> = call_graph.into_iter().collect();
+        call_graph.sort_by(|a, b| b.1.cmp(&a.1));
+
+        Ok(Profile {
+            total_samples: sample_results.instruction_pointers.len(),
+            flat,
+            call_graph,
+        })
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Flat profile ({} samples):", self.total_samples)?;
+        writeln!(f, "{:>8}  {:>7}  {}", "SAMPLES", "%", "FUNCTION")?;
+        for (name, count) in &self.flat {
+            let percent = *count as f32 * 100.0 / core::cmp::max(self.total_samples, 1) as f32;
+            writeln!(f, "{:>8}  {:>6.2}%  {}", count, percent, name)?;
+        }
+
+        writeln!(f, "\nCall graph:")?;
+        if self.call_graph.is_empty() {
+            writeln!(f, "    <no call chains were recorded; build with frame pointers to enable them>")?;
+        }
+        for ((caller, callee), count) in &self.call_graph {
+            writeln!(f, "{:>8}  {} -> {}", count, caller, callee)?;
+        }
+        Ok(())
+    }
 }
 
 /// Simple function to print values from SampleResults in a form that the script "post-mortem pmu analysis.py" can parse. 
@@ -817,14 +992,19 @@ pub fn handle_sample(stack_frame: &mut ExceptionStackFrame) -> Result<(), &'stat
     if let Some(taskref) = task::get_my_current_task() {
         let requested_task_id = samples.task_id;
         
-        let task_id = taskref.lock().id;
+        let (task_id, stack_bounds) = {
+            let task = taskref.lock();
+            (task.id, (task.kstack.bottom().value(), task.kstack.top_unusable().value()))
+        };
         if (requested_task_id == 0) | (requested_task_id == task_id) {
             samples.ip_list.push(stack_frame.instruction_pointer);
             samples.task_id_list.push(task_id);
+            samples.call_chain_list.push(record_call_chain(stack_frame.instruction_pointer.0, stack_bounds));
         }
     } else {
         samples.ip_list.push(stack_frame.instruction_pointer);
         samples.task_id_list.push(0);
+        samples.call_chain_list.push(CallChain::empty());
     }
 
     // stops the counter, resets it, and restarts it
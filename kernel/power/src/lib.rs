@@ -0,0 +1,626 @@
+//! Power management using ACPI: shutting down (S5), rebooting, and suspending to RAM (S3).
+//!
+//! Drivers whose devices must be quiesced before the system powers off or sleeps,
+//! or reinitialized after it resumes, should register a suspend hook with [`register_suspend_hook()`].
+//! Hooks are suspended in the reverse order that they were registered, and resumed in the order they were registered.
+//!
+//! # Suspend to RAM
+//! When resuming from S3, the firmware restarts the BSP in real mode at the waking vector in the FACS.
+//! We point that at the AP startup code, which brings the BSP back into long mode
+//! with the kernel's page table, after which we restore the BSP's saved registers and descriptor tables,
+//! reinitialize its interrupt controllers, and resume all suspended devices.
+//!
+//! Only single-core systems can currently be suspended, because the APs are not restarted upon resume.
+//! Also, the TSC restarts from zero when resuming, so timestamps taken across a suspension are not comparable.
+
+#![no_std]
+#![feature(llvm_asm, naked_functions)]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate irq_safety;
+extern crate x86_64;
+extern crate port_io;
+extern crate memory;
+extern crate stack;
+extern crate kernel_config;
+extern crate pit_clock;
+extern crate apic;
+extern crate ioapic;
+extern crate pic;
+extern crate acpi;
+extern crate sdt;
+extern crate fadt;
+extern crate facs;
+extern crate dsdt;
+extern crate madt;
+extern crate multicore_bringup;
+extern crate timer;
+
+use core::ops::DerefMut;
+use alloc::vec::Vec;
+use irq_safety::{MutexIrqSafe, hold_interrupts};
+use x86_64::registers::msr::{rdmsr, wrmsr};
+use port_io::Port;
+use memory::{VirtualAddress, PhysicalAddress, FrameRange, EntryFlags, get_kernel_mmi_ref, get_frame_allocator_ref, allocate_pages};
+use kernel_config::memory::KERNEL_STACK_SIZE_IN_PAGES;
+use sdt::GenericAddressStructure;
+use fadt::Fadt;
+use facs::Facs;
+use dsdt::{Dsdt, SleepType};
+use madt::{Madt, find_nmi_entry_for_processor};
+
+
+/// The reason that devices are being suspended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerEvent {
+    /// The system is about to power off. Devices will not be resumed unless the shutdown fails.
+    Shutdown,
+    /// The system is about to reset. Devices will not be resumed unless the reboot fails.
+    Reboot,
+    /// The system is about to enter the S3 sleep state, and devices will be resumed afterwards.
+    Suspend,
+}
+
+/// A function that quiesces a device before a `PowerEvent`.
+/// If it returns an error, the power event is aborted and all already-suspended devices are resumed.
+pub type SuspendFunc = fn(PowerEvent) -> Result<(), &'static str>;
+/// A function that reinitializes a device after it was suspended.
+pub type ResumeFunc = fn();
+
+#[derive(Clone)]
+struct SuspendHook {
+    name: &'static str,
+    suspend: SuspendFunc,
+    resume: ResumeFunc,
+}
+
+/// The registered suspend hooks, in the order they were registered.
+static SUSPEND_HOOKS: MutexIrqSafe<Vec<SuspendHook>> = MutexIrqSafe::new(Vec::new());
+
+/// Registers a pair of functions that will be invoked to suspend a device before the system
+/// shuts down, reboots, or sleeps, and to resume it after the system wakes up.
+///
+/// Returns an error if a hook with the same `name` was already registered.
+pub fn register_suspend_hook(name: &'static str, suspend: SuspendFunc, resume: ResumeFunc) -> Result<(), &'static str> {
+    let mut hooks = SUSPEND_HOOKS.lock();
+    if hooks.iter().any(|h| h.name == name) {
+        return Err("a suspend hook with that name was already registered");
+    }
+    hooks.push(SuspendHook { name, suspend, resume });
+    Ok(())
+}
+
+/// Removes the suspend hook with the given `name`, returning whether it existed.
+pub fn unregister_suspend_hook(name: &str) -> bool {
+    let mut hooks = SUSPEND_HOOKS.lock();
+    let len_before = hooks.len();
+    hooks.retain(|h| h.name != name);
+    hooks.len() != len_before
+}
+
+/// Returns the names of all registered suspend hooks, in the order they were registered.
+pub fn suspend_hook_names() -> Vec<&'static str> {
+    SUSPEND_HOOKS.lock().iter().map(|h| h.name).collect()
+}
+
+/// Invokes all suspend hooks in reverse registration order, returning the hooks that were suspended
+/// in the order they were suspended. If any hook fails, the already-suspended ones are resumed.
+fn suspend_devices(event: PowerEvent) -> Result<Vec<SuspendHook>, &'static str> {
+    // Clone the hooks so that a hook can (un)register hooks without deadlocking.
+    let hooks = SUSPEND_HOOKS.lock().clone();
+    let mut suspended = Vec::with_capacity(hooks.len());
+    for hook in hooks.into_iter().rev() {
+        if let Err(e) = (hook.suspend)(event) {
+            error!("power: failed to suspend {:?} for {:?}: {}", hook.name, event, e);
+            resume_devices(&suspended);
+            return Err(e);
+        }
+        suspended.push(hook);
+    }
+    Ok(suspended)
+}
+
+/// Resumes the given hooks in the reverse order that they were suspended.
+fn resume_devices(suspended: &[SuspendHook]) {
+    for hook in suspended.iter().rev() {
+        (hook.resume)();
+    }
+}
+
+
+const ADDRESS_SPACE_SYSTEM_MEMORY: u8 = 0;
+const ADDRESS_SPACE_SYSTEM_IO: u8 = 1;
+
+/// The `SCI_EN` bit in the PM1 control register, which is set when the system is in ACPI mode.
+const PM1_SCI_EN: u16 = 1 << 0;
+/// The bits of the `SLP_TYP` field in the PM1 control register.
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+/// The `SLP_EN` bit in the PM1 control register, which enters the sleep state given by `SLP_TYP`.
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// The legacy keyboard controller's command port, and the command that pulses the CPU reset line.
+const KEYBOARD_CONTROLLER_COMMAND_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_RESET: u8 = 0xFE;
+
+/// How long to wait for the system to enter a sleep state or reset before giving up, in milliseconds.
+const POWER_TRANSITION_TIMEOUT_MS: u32 = 1000;
+/// How long to wait for the firmware to switch the system into ACPI mode, in milliseconds.
+const ACPI_ENABLE_TIMEOUT_MS: u32 = 3000;
+
+/// The fixed-function power management hardware described by the FADT and DSDT.
+#[derive(Clone, Copy, Debug)]
+struct PowerRegisters {
+    /// The I/O port of the PM1a control register.
+    pm1a_control: u16,
+    /// The I/O port of the PM1b control register, if it exists.
+    pm1b_control: Option<u16>,
+    smi_command_port: u16,
+    acpi_enable: u8,
+    reset: Option<(GenericAddressStructure, u8)>,
+    s3: Option<SleepType>,
+    s5: Option<SleepType>,
+}
+
+impl PowerRegisters {
+    /// Reads the power management registers and sleep types from the ACPI tables.
+    fn get() -> Result<PowerRegisters, &'static str> {
+        let acpi_tables = acpi::get_acpi_tables().lock();
+        let fadt = Fadt::get(&acpi_tables).ok_or("couldn't find the FADT. Has the ACPI subsystem been initialized yet?")?;
+        let extended = Fadt::get_extended(&acpi_tables);
+        let flags = fadt.flags;
+        if flags & fadt::FLAG_HW_REDUCED_ACPI != 0 {
+            return Err("hardware-reduced ACPI systems have no PM1 control registers, which aren't supported");
+        }
+
+        let pm1a_control = io_port(fadt.pm1a_control_block, extended.map(|ext| ext.x_pm1a_control_block))
+            .ok_or("the FADT doesn't specify an I/O port for the PM1a control register")?;
+        let pm1b_control = io_port(fadt.pm1b_control_block, extended.map(|ext| ext.x_pm1b_control_block));
+        let reset = extended
+            .filter(|_| flags & fadt::FLAG_RESET_REG_SUPPORTED != 0)
+            .map(|ext| (ext.reset_reg, ext.reset_value));
+        let dsdt = Dsdt::get(&acpi_tables);
+
+        Ok(PowerRegisters {
+            pm1a_control,
+            pm1b_control,
+            smi_command_port: fadt.smi_command_port as u16,
+            acpi_enable: fadt.acpi_enable,
+            reset,
+            s3: dsdt.as_ref().and_then(|d| d.sleep_type(dsdt::S3_NAME)),
+            s5: dsdt.as_ref().and_then(|d| d.sleep_type(dsdt::S5_NAME)),
+        })
+    }
+
+    /// Switches the system from legacy mode into ACPI mode, if it isn't already.
+    fn enable_acpi_mode(&self) -> Result<(), &'static str> {
+        let pm1a = Port::<u16>::new(self.pm1a_control);
+        if pm1a.read() & PM1_SCI_EN != 0 {
+            return Ok(());
+        }
+        if self.smi_command_port == 0 || self.acpi_enable == 0 {
+            return Err("the system is not in ACPI mode, and the FADT doesn't say how to enable it");
+        }
+        unsafe { Port::<u8>::new(self.smi_command_port).write(self.acpi_enable); }
+        for _ in 0 .. ACPI_ENABLE_TIMEOUT_MS {
+            if pm1a.read() & PM1_SCI_EN != 0 {
+                return Ok(());
+            }
+            pit_clock::pit_wait(1000)?;
+        }
+        Err("timed out waiting for the firmware to enable ACPI mode")
+    }
+}
+
+/// Returns the I/O port given by either the legacy 32-bit FADT field or the extended address structure.
+fn io_port(legacy: u32, extended: Option<GenericAddressStructure>) -> Option<u16> {
+    if legacy != 0 {
+        return Some(legacy as u16);
+    }
+    extended
+        .filter(|gas| gas.address_space == ADDRESS_SPACE_SYSTEM_IO && gas.phys_addr != 0)
+        .map(|gas| gas.phys_addr as u16)
+}
+
+/// Writes the given sleep type and the sleep enable bit to the PM1 control registers,
+/// which immediately transitions the system into that sleep state.
+///
+/// If `flush_caches` is true, all caches are written back to memory first, as is required before entering S3.
+fn enter_sleep_state(pm1a_control: u16, pm1b_control: Option<u16>, sleep_type: SleepType, flush_caches: bool) {
+    let pm1a = Port::<u16>::new(pm1a_control);
+    let pm1b = pm1b_control.map(Port::<u16>::new);
+    let pm1a_value = (pm1a.read() & !(PM1_SLP_TYP_MASK | PM1_SLP_EN)) | ((sleep_type.pm1a as u16) << PM1_SLP_TYP_SHIFT);
+    let pm1b_value = pm1b.as_ref().map(|p| (p.read() & !(PM1_SLP_TYP_MASK | PM1_SLP_EN)) | ((sleep_type.pm1b as u16) << PM1_SLP_TYP_SHIFT));
+
+    if flush_caches {
+        unsafe { llvm_asm!("wbinvd" : : : "memory" : "intel", "volatile"); }
+    }
+    unsafe {
+        pm1a.write(pm1a_value);
+        if let (Some(p), Some(v)) = (pm1b.as_ref(), pm1b_value) {
+            p.write(v);
+        }
+        pm1a.write(pm1a_value | PM1_SLP_EN);
+        if let (Some(p), Some(v)) = (pm1b.as_ref(), pm1b_value) {
+            p.write(v | PM1_SLP_EN);
+        }
+    }
+}
+
+/// Waits for a power transition to take effect, which should never finish.
+fn wait_for_power_transition() {
+    for _ in 0 .. POWER_TRANSITION_TIMEOUT_MS {
+        let _ = pit_clock::pit_wait(1000);
+    }
+}
+
+
+/// Powers off the system by entering the ACPI S5 (soft off) sleep state,
+/// after suspending all devices with `PowerEvent::Shutdown`.
+///
+/// If successful, this function does not return.
+pub fn shutdown() -> Result<(), &'static str> {
+    let regs = PowerRegisters::get()?;
+    let s5 = regs.s5.ok_or("the DSDT doesn't define the S5 (soft off) sleep state")?;
+    regs.enable_acpi_mode()?;
+
+    let suspended = suspend_devices(PowerEvent::Shutdown)?;
+    info!("power: shutting down...");
+    {
+        let _held_interrupts = hold_interrupts();
+        enter_sleep_state(regs.pm1a_control, regs.pm1b_control, s5, false);
+        wait_for_power_transition();
+    }
+    resume_devices(&suspended);
+    Err("the system did not power off after entering the S5 sleep state")
+}
+
+
+/// Resets the system, after suspending all devices with `PowerEvent::Reboot`.
+///
+/// This first tries the ACPI reset register, then the legacy keyboard controller,
+/// and finally causes a triple fault, which resets the CPU.
+/// If successful, this function does not return.
+pub fn reboot() -> Result<(), &'static str> {
+    // The reset register is optional, so failing to find the ACPI tables isn't fatal.
+    let reset = PowerRegisters::get().ok().and_then(|regs| regs.reset);
+
+    let _suspended = suspend_devices(PowerEvent::Reboot)?;
+    info!("power: rebooting...");
+    let _held_interrupts = hold_interrupts();
+
+    if let Some((reset_reg, reset_value)) = reset {
+        match write_reset_register(reset_reg, reset_value) {
+            Ok(()) => wait_for_power_transition(),
+            Err(e) => warn!("power: couldn't write the ACPI reset register: {}", e),
+        }
+    }
+
+    unsafe { Port::<u8>::new(KEYBOARD_CONTROLLER_COMMAND_PORT).write(KEYBOARD_CONTROLLER_RESET); }
+    wait_for_power_transition();
+
+    // Load an empty IDT such that the breakpoint exception can't be handled, which triple faults.
+    let empty_idt_pointer = [0u8; 10];
+    unsafe {
+        llvm_asm!("lidt [$0]; int3" : : "r"(&empty_idt_pointer) : "memory" : "intel", "volatile");
+    }
+    Err("the system did not reset")
+}
+
+/// Writes `value` to the ACPI reset register, which is either an I/O port or a memory-mapped register.
+fn write_reset_register(reg: GenericAddressStructure, value: u8) -> Result<(), &'static str> {
+    match reg.address_space {
+        ADDRESS_SPACE_SYSTEM_IO => {
+            unsafe { Port::<u8>::new(reg.phys_addr as u16).write(value); }
+            Ok(())
+        }
+        ADDRESS_SPACE_SYSTEM_MEMORY => {
+            let paddr = PhysicalAddress::new(reg.phys_addr as usize)?;
+            let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("couldn't get kernel MMI")?;
+            let frame_allocator_ref = get_frame_allocator_ref().ok_or("couldn't get frame allocator")?;
+            let pages = allocate_pages(1).ok_or("couldn't allocate a page for the reset register")?;
+            let mut mapped_pages = kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
+                pages,
+                FrameRange::from_phys_addr(paddr, 1),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE,
+                frame_allocator_ref.lock().deref_mut(),
+            )?;
+            let register: &mut u8 = mapped_pages.as_type_mut(paddr.frame_offset())?;
+            unsafe { core::ptr::write_volatile(register, value); }
+            Ok(())
+        }
+        _ => Err("the ACPI reset register is in an unsupported address space"),
+    }
+}
+
+
+const IA32_FS_BASE: u32 = 0xC000_0100;
+const IA32_GS_BASE: u32 = 0xC000_0101;
+const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+const IA32_PAT: u32 = 0x277;
+
+/// The model-specific registers that are lost in S3 sleep and not restored by the AP startup code.
+struct SavedMsrs {
+    fs_base: u64,
+    gs_base: u64,
+    kernel_gs_base: u64,
+    pat: u64,
+}
+
+impl SavedMsrs {
+    fn save() -> SavedMsrs {
+        SavedMsrs {
+            fs_base: rdmsr(IA32_FS_BASE),
+            gs_base: rdmsr(IA32_GS_BASE),
+            kernel_gs_base: rdmsr(IA32_KERNEL_GS_BASE),
+            pat: rdmsr(IA32_PAT),
+        }
+    }
+
+    fn restore(&self) {
+        unsafe {
+            wrmsr(IA32_PAT, self.pat);
+            wrmsr(IA32_FS_BASE, self.fs_base);
+            wrmsr(IA32_GS_BASE, self.gs_base);
+            wrmsr(IA32_KERNEL_GS_BASE, self.kernel_gs_base);
+        }
+    }
+}
+
+/// Saves the redirection table entries of every IOAPIC.
+fn save_ioapics() -> Vec<(u8, Vec<u64>)> {
+    ioapic::get_ioapics().iter().map(|(&id, ioapic_ref)| {
+        let mut ioapic = ioapic_ref.lock();
+        let entries = (0 .. ioapic::INTERRUPT_ENTRIES_PER_IOAPIC as u8).map(|irq| ioapic.redirection_entry(irq)).collect();
+        (id, entries)
+    }).collect()
+}
+
+/// Restores the redirection table entries of every IOAPIC that were saved by `save_ioapics()`.
+fn restore_ioapics(saved: &[(u8, Vec<u64>)]) {
+    for (id, entries) in saved {
+        if let Some(mut ioapic) = ioapic::get_ioapic(*id) {
+            for (irq, entry) in entries.iter().enumerate() {
+                ioapic.set_redirection_entry(irq as u8, *entry);
+            }
+        }
+    }
+}
+
+
+/// Suspends the system to RAM by entering the ACPI S3 sleep state,
+/// after suspending all devices with `PowerEvent::Suspend`.
+///
+/// This returns after the system has been woken up and all devices have been resumed.
+/// Currently, only single-core systems can be suspended, e.g., QEMU with `QEMU_CPUS=1`.
+pub fn suspend() -> Result<(), &'static str> {
+    if apic::core_count() > 1 {
+        return Err("suspending to RAM is only supported on single-core systems, as APs aren't restarted upon resume");
+    }
+    let regs = PowerRegisters::get()?;
+    let s3 = regs.s3.ok_or("the DSDT doesn't define the S3 (suspend to RAM) sleep state")?;
+    regs.enable_acpi_mode()?;
+
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("couldn't get kernel MMI")?;
+    let frame_allocator_ref = get_frame_allocator_ref().ok_or("couldn't get frame allocator")?;
+    let resume_stack = stack::alloc_stack(KERNEL_STACK_SIZE_IN_PAGES, &mut kernel_mmi_ref.lock().page_table, frame_allocator_ref)
+        .ok_or("couldn't allocate a stack for resuming from S3 sleep")?;
+    let apic_id = apic::get_my_apic_id();
+    let processor_id = apic::get_my_apic().ok_or("couldn't get this core's LocalApic")?.read().processor;
+    let (nmi_lint, nmi_flags) = {
+        let acpi_tables = acpi::get_acpi_tables().lock();
+        let madt = Madt::get(&acpi_tables).ok_or("couldn't find the MADT ACPI table")?;
+        find_nmi_entry_for_processor(processor_id, madt.iter())
+    };
+
+    // The firmware will resume the BSP through the AP startup code, which jumps to `s3_resume_entry()`.
+    let trampoline = multicore_bringup::prepare_s3_resume(
+        &kernel_mmi_ref,
+        VirtualAddress::new_canonical(s3_resume_entry as usize),
+        &resume_stack,
+        processor_id,
+        apic_id,
+        nmi_lint,
+        nmi_flags,
+    )?;
+    Facs::set_waking_vector(&mut acpi::get_acpi_tables().lock(), trampoline.waking_vector)?;
+
+    let suspended = suspend_devices(PowerEvent::Suspend)?;
+    info!("power: suspending to RAM...");
+    let result = {
+        let _held_interrupts = hold_interrupts();
+        let msrs = SavedMsrs::save();
+        let ioapics = save_ioapics();
+        // the TSC and HPET counters may be reset during sleep, so the clock must continue from the current time
+        timer::suspend();
+        let args = SleepArgs { pm1a_control: regs.pm1a_control, pm1b_control: regs.pm1b_control, sleep_type: s3 };
+
+        let slept = unsafe { save_context_and_sleep(&mut SAVED_CONTEXT, sleep_s3, &args) } == 0;
+
+        // Here, we have either resumed from S3 sleep, or failed to enter it.
+        if slept {
+            msrs.restore();
+            // The firmware may have reset the legacy PIC, so remap and mask it again.
+            let _pic = pic::ChainedPics::init(0xFF, 0xFF);
+            let lapic_result = apic::get_my_apic()
+                .ok_or("couldn't get this core's LocalApic")
+                .and_then(|lapic| lapic.write().reinit(nmi_lint, nmi_flags));
+            timer::resume();
+            restore_ioapics(&ioapics);
+            lapic_result
+        } else {
+            Err("the system did not enter the S3 sleep state")
+        }
+    };
+    drop(trampoline);
+    drop(resume_stack);
+
+    if result.is_ok() {
+        info!("power: resumed from S3 sleep.");
+    }
+    resume_devices(&suspended);
+    result
+}
+
+/// The arguments passed to `sleep_s3()` by `save_context_and_sleep()`.
+struct SleepArgs {
+    pm1a_control: u16,
+    pm1b_control: Option<u16>,
+    sleep_type: SleepType,
+}
+
+/// Enters the S3 sleep state. Returns a nonzero value only if the system failed to sleep.
+extern "C" fn sleep_s3(args: &SleepArgs) -> u64 {
+    enter_sleep_state(args.pm1a_control, args.pm1b_control, args.sleep_type, true);
+    wait_for_power_transition();
+    1
+}
+
+
+/// The BSP's execution context, saved right before entering S3 sleep.
+/// The field offsets are used directly in `save_context_and_sleep()` and `restore_context()`.
+#[repr(C)]
+struct CpuContext {
+    rsp:    u64, // 0x00
+    rbx:    u64, // 0x08
+    rbp:    u64, // 0x10
+    r12:    u64, // 0x18
+    r13:    u64, // 0x20
+    r14:    u64, // 0x28
+    r15:    u64, // 0x30
+    rflags: u64, // 0x38
+    cr0:    u64, // 0x40
+    cr3:    u64, // 0x48
+    cr4:    u64, // 0x50
+    cs:     u64, // 0x58
+    tr:     u64, // 0x60
+    /// The 2-byte limit and 8-byte base of the GDT, as stored by `sgdt`.
+    gdtr: [u8; 16], // 0x68
+    /// The 2-byte limit and 8-byte base of the IDT, as stored by `sidt`.
+    idtr: [u8; 16], // 0x78
+}
+
+static mut SAVED_CONTEXT: CpuContext = CpuContext {
+    rsp: 0, rbx: 0, rbp: 0, r12: 0, r13: 0, r14: 0, r15: 0, rflags: 0,
+    cr0: 0, cr3: 0, cr4: 0, cs: 0, tr: 0,
+    gdtr: [0; 16],
+    idtr: [0; 16],
+};
+
+/// Saves the callee-saved registers, control registers, and descriptor tables into `context`,
+/// and then invokes `sleep(args)`.
+///
+/// Returns the value returned by `sleep()` if the system didn't go to sleep,
+/// or returns `0` when the system resumes and `restore_context()` is invoked.
+#[naked]
+#[inline(never)]
+unsafe extern "C" fn save_context_and_sleep(
+    _context: *mut CpuContext,
+    _sleep: extern "C" fn(&SleepArgs) -> u64,
+    _args: *const SleepArgs,
+) -> u64 {
+    // Arguments: rdi = context, rsi = sleep, rdx = args
+    llvm_asm!("
+        mov [rdi + 0x00], rsp
+        mov [rdi + 0x08], rbx
+        mov [rdi + 0x10], rbp
+        mov [rdi + 0x18], r12
+        mov [rdi + 0x20], r13
+        mov [rdi + 0x28], r14
+        mov [rdi + 0x30], r15
+        pushfq
+        pop rax
+        mov [rdi + 0x38], rax
+        mov rax, cr0
+        mov [rdi + 0x40], rax
+        mov rax, cr3
+        mov [rdi + 0x48], rax
+        mov rax, cr4
+        mov [rdi + 0x50], rax
+        xor eax, eax
+        mov ax, cs
+        mov [rdi + 0x58], rax
+        xor eax, eax
+        str ax
+        mov [rdi + 0x60], rax
+        sgdt [rdi + 0x68]
+        sidt [rdi + 0x78]
+
+        # this function was entered with rsp = 8 (mod 16), so realign the stack for the call
+        sub rsp, 8
+        mov rdi, rdx
+        call rsi
+        add rsp, 8
+        ret
+        "
+        : : : "memory" : "intel", "volatile"
+    );
+}
+
+/// Restores the execution context saved by `save_context_and_sleep()`,
+/// which then returns `0` to its caller.
+#[naked]
+#[inline(never)]
+unsafe extern "C" fn restore_context(_context: *const CpuContext) -> ! {
+    // Argument: rdi = context
+    llvm_asm!("
+        lgdt [rdi + 0x68]
+        lidt [rdi + 0x78]
+        mov rax, [rdi + 0x50]
+        mov cr4, rax
+        mov rax, [rdi + 0x48]
+        mov cr3, rax
+        mov rax, [rdi + 0x40]
+        mov cr0, rax
+
+        # reload the code segment selector from the restored GDT with a far return
+        push qword ptr [rdi + 0x58]
+        lea rax, [rip + power_restore_context_reloaded_cs]
+        push rax
+        .byte 0x48, 0xCB # retfq
+    power_restore_context_reloaded_cs:
+        xor eax, eax
+        mov ss, ax
+        mov ds, ax
+        mov es, ax
+
+        # the TSS descriptor is still marked busy from before sleeping, which would make ltr fault,
+        # so clear its busy bit first (bit 1 of the type field in byte 5 of the descriptor)
+        mov rcx, [rdi + 0x60]
+        and rcx, 0xFFF8
+        jz power_restore_context_no_tss
+        mov rax, [rdi + 0x6A]
+        and byte ptr [rax + rcx + 5], 0xFD
+        ltr cx
+    power_restore_context_no_tss:
+
+        mov rsp, [rdi + 0x00]
+        mov rbx, [rdi + 0x08]
+        mov rbp, [rdi + 0x10]
+        mov r12, [rdi + 0x18]
+        mov r13, [rdi + 0x20]
+        mov r14, [rdi + 0x28]
+        mov r15, [rdi + 0x30]
+        push qword ptr [rdi + 0x38]
+        popfq
+        xor eax, eax
+        ret
+        "
+        : : : "memory" : "intel", "volatile"
+    );
+}
+
+/// The Rust entry point that the BSP jumps to after the firmware wakes it from S3 sleep
+/// and it has run through the AP startup code into long mode, on the resume stack.
+/// The arguments must match those of `ap_start::kstart_ap()`, but are unused.
+fn s3_resume_entry(
+    _processor_id: u8,
+    _apic_id: u8,
+    _stack_start: VirtualAddress,
+    _stack_end: VirtualAddress,
+    _nmi_lint: u8,
+    _nmi_flags: u16,
+) -> ! {
+    unsafe { restore_context(&SAVED_CONTEXT) }
+}
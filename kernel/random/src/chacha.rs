@@ -0,0 +1,98 @@
+//! A random number generator built on the ChaCha20 block function (RFC 8439).
+//!
+//! After every request, the generator replaces its key with output that it never hands out ("fast key erasure"),
+//! so that the bytes it produced before can't be reconstructed from its state.
+
+/// The constant words of the ChaCha state, "expand 32-byte k".
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// The number of bytes in a block of output.
+pub const BLOCK_SIZE: usize = 64;
+/// The number of 32-bit words in a key.
+pub const KEY_WORDS: usize = 8;
+
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Computes the ChaCha20 block with the given key and 64-bit block counter, and an all-zero nonce.
+fn block(key: &[u32; KEY_WORDS], counter: u64) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[.. 4].copy_from_slice(&CONSTANTS);
+    input[4 .. 12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+
+    let mut state = input;
+    for _ in 0 .. 10 {
+        // column rounds
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        // diagonal rounds
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, input_word) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input_word);
+    }
+    state
+}
+
+
+/// A ChaCha20-based random number generator.
+pub struct ChaChaRng {
+    key: [u32; KEY_WORDS],
+    counter: u64,
+}
+
+impl ChaChaRng {
+    /// Creates a generator whose key is the given seed.
+    pub fn new(seed: [u32; KEY_WORDS]) -> ChaChaRng {
+        let mut rng = ChaChaRng { key: seed, counter: 0 };
+        rng.rekey();
+        rng
+    }
+
+    /// Mixes the given seed into the key, so that the output depends on both the old key and the seed.
+    pub fn reseed(&mut self, seed: &[u32; KEY_WORDS]) {
+        for (word, seed_word) in self.key.iter_mut().zip(seed.iter()) {
+            *word ^= *seed_word;
+        }
+        self.rekey();
+    }
+
+    /// Fills the given buffer with random bytes.
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(BLOCK_SIZE) {
+            let block = self.next_block();
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[.. bytes.len()]);
+            }
+        }
+        self.rekey();
+    }
+
+    fn next_block(&mut self) -> [u32; 16] {
+        let block = block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        block
+    }
+
+    /// Replaces the key with the first half of the next block, which is never handed out.
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[.. KEY_WORDS]);
+    }
+}
@@ -0,0 +1,222 @@
+#![no_std]
+
+//! The system's cryptographically secure random number generator.
+//!
+//! Random bytes are produced by a ChaCha20-based generator, which is seeded from the CPU's hardware
+//! random number generator (`RDSEED`, or `RDRAND` if the former is unavailable) and the TSC.
+//! Interrupt handlers feed the timing of interrupts into an entropy pool with
+//! [`add_interrupt_entropy()`](fn.add_interrupt_entropy.html), which is mixed into the generator
+//! whenever enough new interrupts have arrived, along with fresh hardware randomness.
+//! Thus, the generator is unpredictable even on CPUs without a hardware generator once the system has run a while.
+//!
+//! [`fill_bytes()`](fn.fill_bytes.html) is the main interface. [`getrandom()`](fn.getrandom.html) and
+//! [`hashmap_random_keys()`](fn.hashmap_random_keys.html) have the signatures that a port of `std`
+//! expects of the custom source of the `getrandom` crate and of its `sys::rand` module, respectively.
+//!
+//! The generator must not be used from interrupt handlers, which may only add entropy.
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate tsc;
+
+mod chacha;
+
+use core::num::NonZeroU32;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdrand64_step, _rdseed64_step};
+use spin::Mutex;
+use chacha::{ChaChaRng, KEY_WORDS};
+
+
+/// The number of times that `RDRAND` or `RDSEED` is retried when it has no random number ready.
+const HARDWARE_RETRIES: usize = 10;
+/// The number of new interrupts after which the entropy pool is mixed into the generator.
+const RESEED_INTERRUPTS: usize = 256;
+/// The number of bytes after which the generator is reseeded even if too few new interrupts have arrived.
+const RESEED_BYTES: usize = 1 << 20;
+
+/// The entropy pool, into which interrupt handlers mix the timing of interrupts.
+/// It consists of atomics so that interrupt handlers never have to take a lock.
+static POOL: [AtomicU64; 8] = [
+    AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),
+    AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),
+];
+/// The number of interrupts that have been mixed into the pool.
+static POOL_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// The hardware random number generators that the CPU supports.
+    static ref HARDWARE: Hardware = Hardware::detect();
+
+    /// The generator, which is seeded when it's first used.
+    static ref RNG: Mutex<Generator> = Mutex::new(Generator::new());
+}
+
+
+/// The hardware random number generators that the CPU supports.
+#[derive(Clone, Copy, Debug)]
+struct Hardware {
+    rdrand: bool,
+    rdseed: bool,
+}
+
+impl Hardware {
+    fn detect() -> Hardware {
+        // SAFE: CPUID is available on every x86_64 CPU.
+        let (features, extended_features) = unsafe { (__cpuid(1), __cpuid_count(7, 0)) };
+        Hardware {
+            rdrand: features.ecx & (1 << 30) != 0,
+            rdseed: extended_features.ebx & (1 << 18) != 0,
+        }
+    }
+
+    /// Returns a random number from `RDSEED`, or from `RDRAND` if `RDSEED` is unavailable or exhausted.
+    fn next_u64(&self) -> Option<u64> {
+        // SAFE: the instructions are only executed if the CPU supports them.
+        if self.rdseed {
+            if let Some(value) = unsafe { rdseed() } {
+                return Some(value);
+            }
+        }
+        if self.rdrand {
+            return unsafe { rdrand() };
+        }
+        None
+    }
+}
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed() -> Option<u64> {
+    let mut value = 0;
+    for _ in 0 .. HARDWARE_RETRIES {
+        if _rdseed64_step(&mut value) == 1 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand() -> Option<u64> {
+    let mut value = 0;
+    for _ in 0 .. HARDWARE_RETRIES {
+        if _rdrand64_step(&mut value) == 1 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+
+/// The generator along with the bookkeeping that decides when it's reseeded.
+struct Generator {
+    rng: ChaChaRng,
+    /// The value of `POOL_EVENTS` when the generator was last reseeded.
+    events_at_reseed: usize,
+    bytes_since_reseed: usize,
+}
+
+impl Generator {
+    fn new() -> Generator {
+        let hardware = *HARDWARE;
+        if !hardware.rdrand && !hardware.rdseed {
+            warn!("random: the CPU has no hardware random number generator, so the generator is seeded only from timings");
+        }
+        Generator {
+            rng: ChaChaRng::new(gather_seed()),
+            events_at_reseed: POOL_EVENTS.load(Ordering::Relaxed),
+            bytes_since_reseed: 0,
+        }
+    }
+
+    fn reseed(&mut self) {
+        self.rng.reseed(&gather_seed());
+        self.events_at_reseed = POOL_EVENTS.load(Ordering::Relaxed);
+        self.bytes_since_reseed = 0;
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let new_events = POOL_EVENTS.load(Ordering::Relaxed).wrapping_sub(self.events_at_reseed);
+        if new_events >= RESEED_INTERRUPTS || self.bytes_since_reseed >= RESEED_BYTES {
+            self.reseed();
+        }
+        self.rng.fill_bytes(dest);
+        self.bytes_since_reseed = self.bytes_since_reseed.saturating_add(dest.len());
+    }
+}
+
+/// Collects a seed from the hardware random number generator, the entropy pool, and the TSC.
+fn gather_seed() -> [u32; KEY_WORDS] {
+    let mut seed = [0u32; KEY_WORDS];
+    for (i, pair) in seed.chunks_mut(2).enumerate() {
+        let hardware = HARDWARE.next_u64().unwrap_or(0);
+        let pool = POOL[i].load(Ordering::Relaxed) ^ POOL[i + KEY_WORDS / 2].load(Ordering::Relaxed).rotate_left(32);
+        let value = hardware ^ pool ^ tsc::tsc_ticks().into().rotate_left(i as u32 * 16);
+        pair[0] ^= value as u32;
+        pair[1] ^= (value >> 32) as u32;
+    }
+    seed
+}
+
+
+/// Seeds the generator, if it hasn't been used yet, and logs which sources of randomness are available.
+///
+/// The generator is also seeded on its first use, so calling this is optional.
+pub fn init() -> Result<(), &'static str> {
+    let hardware = *HARDWARE;
+    lazy_static::initialize(&RNG);
+    info!("random: initialized the random number generator (RDRAND: {}, RDSEED: {})", hardware.rdrand, hardware.rdseed);
+    Ok(())
+}
+
+/// Mixes the timing of an interrupt into the entropy pool, where `source` identifies the interrupt, e.g., its vector.
+///
+/// This is meant to be invoked by interrupt handlers, and is cheap and lock-free.
+pub fn add_interrupt_entropy(source: u8) {
+    let ticks = tsc::tsc_ticks().into();
+    let events = POOL_EVENTS.fetch_add(1, Ordering::Relaxed);
+    let word = &POOL[events % POOL.len()];
+    // A racing update of the same word may be lost, which is harmless.
+    let mixed = word.load(Ordering::Relaxed).rotate_left(7) ^ (ticks ^ ((source as u64) << 56)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    word.store(mixed, Ordering::Relaxed);
+}
+
+/// Mixes fresh randomness from the hardware, the entropy pool, and the TSC into the generator right away.
+pub fn reseed() {
+    RNG.lock().reseed();
+}
+
+/// Fills the given buffer with cryptographically secure random bytes.
+pub fn fill_bytes(dest: &mut [u8]) {
+    RNG.lock().fill_bytes(dest);
+}
+
+/// Returns a random `u32`.
+pub fn next_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    fill_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+/// Returns a random `u64`.
+pub fn next_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Fills the given buffer with random bytes, with the signature that the `getrandom` crate expects
+/// of a custom source of randomness (see its `register_custom_getrandom!` macro).
+///
+/// This never fails; the error type is the code that `getrandom::Error` is created from.
+pub fn getrandom(dest: &mut [u8]) -> Result<(), NonZeroU32> {
+    fill_bytes(dest);
+    Ok(())
+}
+
+/// Returns a pair of random keys for seeding hash maps,
+/// with the signature of the `hashmap_random_keys()` function in the `sys::rand` module of a port of `std`.
+pub fn hashmap_random_keys() -> (u64, u64) {
+    (next_u64(), next_u64())
+}
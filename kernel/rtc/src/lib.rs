@@ -95,6 +95,14 @@ fn read_register(register: u8) -> u8{
     (bcd/16)*10 + (bcd & 0xf)
 }
 
+//selects the given register and writes a value to it, which is converted from binary to bcd
+fn write_register(register: u8, value: u8) {
+    write_cmos(register);
+    unsafe{
+        CMOS_WRITE_SETTINGS.lock().write((value / 10) * 16 + value % 10);
+    }
+}
+
 /// A timestamp obtained from the real-time clock.
 #[derive(Debug)]
 pub struct RtcTime {
@@ -113,6 +121,29 @@ impl fmt::Display for RtcTime {
     }
 }
 
+impl RtcTime {
+    /// Returns this time as the number of seconds since the Unix epoch, 1970-01-01 00:00:00 UTC.
+    ///
+    /// The RTC only stores a two-digit year, which is assumed to be in the 2000s.
+    pub fn unix_timestamp(&self) -> u64 {
+        let (year, month, day) = (2000 + self.years as i64, self.months as i64, self.days as i64);
+        // The number of days since 1970-01-01 in the proleptic Gregorian calendar,
+        // counting years from March so that leap days fall at the end of a year.
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y / 400;
+        let year_of_era = y - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        (days * 86_400 + self.hours as i64 * 3600 + self.minutes as i64 * 60 + self.seconds as i64) as u64
+    }
+}
+
+/// Returns the current time from the RTC as the number of seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    read_rtc().unix_timestamp()
+}
+
 //call this function to print RTC's date and time
 pub fn read_rtc() -> RtcTime {
 
@@ -134,6 +165,35 @@ pub fn read_rtc() -> RtcTime {
     }
 }
 
+/// Sets the RTC's date and time to the given `time`, e.g., after the system's wall-clock time was changed.
+///
+/// Like `read_rtc()`, this assumes that the RTC stores its values in BCD, with a two-digit year.
+pub fn write_rtc(time: &RtcTime) {
+    let _held_interrupts = hold_interrupts();
+
+    //setting bit 7 of register B stops the RTC from updating its values while they are written
+    write_cmos(0x0B);
+    let prev = read_cmos();
+    write_cmos(0x0B);
+    unsafe{
+        CMOS_WRITE_SETTINGS.lock().write(prev | 0x80);
+    }
+
+    write_register(0x00, time.seconds);
+    write_register(0x02, time.minutes);
+    write_register(0x04, time.hours);
+    write_register(0x07, time.days);
+    write_register(0x08, time.months);
+    write_register(0x09, time.years);
+
+    //clearing bit 7 of register B lets the RTC resume counting from the new values
+    write_cmos(0x0B);
+    unsafe{
+        CMOS_WRITE_SETTINGS.lock().write(prev & !0x80);
+    }
+    // here: _held_interrupts falls out of scope, re-enabling interrupts if they were previously enabled.
+}
+
 pub fn get_rtc_ticks() -> Result<usize, ()> {
      if let Some(ticks) = RTC_TICKS.get() {
          Ok(ticks.load(Ordering::Acquire))
@@ -36,7 +36,7 @@ pub fn get_least_busy_core() -> Option<u8>{
     RunQueue::get_least_busy_core()
 }
 
-/// Chooses the "least busy" core's runqueue
+/// Chooses the "least busy" core's runqueue among the cores that the given `Task`'s affinity allows,
 /// and adds the given `Task` reference to that core's runqueue.
 pub fn add_task_to_any_runqueue(task: TaskRef) -> Result<(), &'static str>{
     RunQueue::add_task_to_any_runqueue(task)
@@ -54,3 +54,55 @@ pub fn remove_task_from_all(task: &TaskRef) -> Result<(), &'static str>{
 
 
 
+/// Returns the number of tasks on the given core's runqueue, not including its idle task.
+/// Blocked tasks stay on their runqueue, so they are included.
+pub fn get_load(which_core: u8) -> Option<usize> {
+    RunQueue::get_runqueue(which_core).map(|rq| rq.read().load())
+}
+
+/// Moves one task that is eligible for migration from the runqueue of `from_core` to the runqueue of `to_core`.
+/// 
+/// Returns the task that was migrated, or `None` if `from_core`'s runqueue had no task that could be migrated. 
+/// Tasks that are running, blocked, idle, or whose affinity doesn't allow them to run on `to_core` are never migrated.
+/// 
+/// This must only be invoked on `from_core` itself, as that is the only core that can be certain
+/// that none of its runqueue's tasks are in the middle of being switched in or out. 
+pub fn migrate_task(from_core: u8, to_core: u8) -> Result<Option<TaskRef>, &'static str> {
+    if from_core == to_core {
+        return Ok(None);
+    }
+    if RunQueue::get_runqueue(to_core).is_none() {
+        return Err("migrate_task(): couldn't get runqueue for destination core");
+    }
+    let taskref = RunQueue::get_runqueue(from_core)
+        .ok_or("migrate_task(): couldn't get runqueue for source core")?
+        .write()
+        .take_migratable_task(to_core);
+    // Only one runqueue lock is held at a time, which avoids deadlock between two cores migrating tasks to each other.
+    if let Some(ref t) = taskref {
+        RunQueue::add_task_to_specific_runqueue(to_core, t.clone())?;
+    }
+    Ok(taskref)
+}
+
+/// Moves every task on the runqueue of `which_core` whose affinity doesn't allow it to run on `which_core`
+/// to the least busy core that it is allowed to run on. 
+/// 
+/// Returns the number of tasks that were migrated.
+/// Like [`migrate_task()`](fn.migrate_task.html), this must only be invoked on `which_core` itself.
+pub fn migrate_disallowed_tasks(which_core: u8) -> Result<usize, &'static str> {
+    let rq = RunQueue::get_runqueue(which_core).ok_or("migrate_disallowed_tasks(): couldn't get runqueue for the given core")?;
+    let mut migrated = 0;
+    loop {
+        let taskref = match rq.write().take_disallowed_task() {
+            Some(t) => t,
+            _ => return Ok(migrated),
+        };
+        if let Err(e) = RunQueue::add_task_to_any_runqueue(taskref.clone()) {
+            // Don't lose the task if it has no other place to go; it just won't be scheduled here.
+            RunQueue::add_task_to_specific_runqueue(which_core, taskref)?;
+            return Err(e);
+        }
+        migrated += 1;
+    }
+}
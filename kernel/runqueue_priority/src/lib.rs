@@ -21,8 +21,7 @@ use atomic_linked_list::atomic_map::AtomicMap;
 use task::{TaskRef, Task};
 use core::ops::{Deref, DerefMut};
 
-pub const MAX_PRIORITY: u8 = 40;
-pub const DEFAULT_PRIORITY: u8 = 20;
+pub use task::{MAX_PRIORITY, DEFAULT_PRIORITY};
 pub const INITIAL_TOKENS: usize = 10;
 
 /// A cloneable reference to a `Taskref` that exposes more methods
@@ -46,6 +45,13 @@ pub struct PriorityTaskRef{
 
     /// Number of context switches the task has undergone. Not used in scheduling algorithm
     context_switches: usize,
+
+    /// Whether the task is an idle task, which never counts towards the load of a runqueue.
+    is_idle: bool,
+
+    /// Whether the task's affinity has been found to allow no core that has a runqueue,
+    /// such that this is only reported once.
+    stranded: bool,
 }
 
 impl Deref for PriorityTaskRef {
@@ -66,11 +72,14 @@ impl PriorityTaskRef {
     /// We just give an initial number of tokens to run the task till 
     /// next scheduling epoch
     pub fn new(taskref: TaskRef) -> PriorityTaskRef {
+        let is_idle = taskref.lock().is_an_idle_task;
         let priority_taskref = PriorityTaskRef {
             taskref: taskref,
             priority: DEFAULT_PRIORITY,
             tokens_remaining: INITIAL_TOKENS,
             context_switches: 0,
+            is_idle,
+            stranded: false,
         };
         priority_taskref
     }
@@ -103,6 +112,9 @@ lazy_static! {
 pub struct RunQueue {
     core: u8,
     queue: VecDeque<PriorityTaskRef>,
+    /// The number of tasks in `queue` that aren't idle tasks, which is kept up to date as tasks are added and removed
+    /// such that the load of this `RunQueue` can be read without locking each of its tasks.
+    load: usize,
 }
 
 impl Deref for RunQueue {
@@ -143,6 +155,7 @@ impl RunQueue {
         let new_rq = RwLockIrqSafe::new(RunQueue {
             core: which_core,
             queue: VecDeque::new(),
+            load: 0,
         });
 
         #[cfg(runqueue_spillful)] 
@@ -168,16 +181,19 @@ impl RunQueue {
 
     /// Returns the "least busy" core, which is currently very simple, based on runqueue size.
     pub fn get_least_busy_core() -> Option<u8> {
-        Self::get_least_busy_runqueue().map(|rq| rq.read().core)
+        Self::get_least_busy_runqueue(&AffinityMask::all()).map(|rq| rq.read().core)
     }
 
 
-    /// Returns the `RunQueue` for the "least busy" core.
+    /// Returns the `RunQueue` for the "least busy" core among the cores in the `allowed` set.
     /// See [`get_least_busy_core()`](#method.get_least_busy_core)
-    fn get_least_busy_runqueue() -> Option<&'static RwLockIrqSafe<RunQueue>> {
+    fn get_least_busy_runqueue(allowed: &AffinityMask) -> Option<&'static RwLockIrqSafe<RunQueue>> {
         let mut min_rq: Option<(&'static RwLockIrqSafe<RunQueue>, usize)> = None;
 
-        for (_, rq) in RUNQUEUES.iter() {
+        for (&core, rq) in RUNQUEUES.iter() {
+            if !allowed.contains(core) {
+                continue;
+            }
             let rq_size = rq.read().queue.len();
 
             if let Some(min) = min_rq {
@@ -195,10 +211,11 @@ impl RunQueue {
 
     /// Chooses the "least busy" core's runqueue (based on simple runqueue-size-based load balancing)
     /// and adds the given `Task` reference to that core's runqueue.
+    /// Only the cores that the `Task`'s affinity allows it to run on are considered.
     pub fn add_task_to_any_runqueue(task: TaskRef) -> Result<(), &'static str> {
-        let rq = RunQueue::get_least_busy_runqueue()
-            .or_else(|| RUNQUEUES.iter().next().map(|r| r.1))
-            .ok_or("couldn't find any runqueues to add the task to!")?;
+        let affinity = task.lock().affinity;
+        let rq = RunQueue::get_least_busy_runqueue(&affinity)
+            .ok_or("couldn't find any runqueues that the task's affinity allows it to be added to!")?;
 
         rq.write().add_task(task)
     }
@@ -224,6 +241,9 @@ impl RunQueue {
         #[cfg(not(loscd_eval))]
         debug!("Adding task to runqueue_priority {}, {:?}", self.core, task);
         let priority_task_ref = PriorityTaskRef::new(task);
+        if !priority_task_ref.is_idle {
+            self.load += 1;
+        }
         self.push_back(priority_task_ref);
         
         #[cfg(single_simd_task_optimization)]
@@ -241,6 +261,8 @@ impl RunQueue {
     /// The internal function that actually removes the task from the runqueue.
     fn remove_internal(&mut self, task: &TaskRef) -> Result<(), &'static str> {
         debug!("Removing task from runqueue_priority {}, {:?}", self.core, task);
+        let removed_load = self.iter().filter(|x| &x.taskref == task && !x.is_idle).count();
+        self.load -= removed_load;
         self.retain(|x| &x.taskref != task);
 
         #[cfg(single_simd_task_optimization)]
@@ -281,6 +303,66 @@ impl RunQueue {
         Ok(())
     }
 
+    /// Returns the number of tasks in this `RunQueue`, not including its idle task.
+    /// This is used as a measure of how busy this `RunQueue`'s core is.
+    /// 
+    /// Blocked tasks stay in their `RunQueue`, so they are counted as well,
+    /// which allows this to be read without locking every task.
+    pub fn load(&self) -> usize {
+        self.load
+    }
+
+    /// Removes and returns a task from this `RunQueue` that can be migrated to the given `dest_core`.
+    ///
+    /// A task can only be migrated if it is runnable, is not an idle task, 
+    /// is not currently running on any core, and its affinity allows it to run on `dest_core`. 
+    /// The task that was most recently added to or selected from this `RunQueue` (the one at the back)
+    /// is never migrated, as this `RunQueue`'s core may be in the middle of switching to it.
+    pub fn take_migratable_task(&mut self, dest_core: u8) -> Option<TaskRef> {
+        let last = self.len().checked_sub(1)?;
+        let index = self.iter().take(last).position(|t| {
+            let t = t.lock();
+            t.is_runnable() && !t.is_an_idle_task && !t.is_running() 
+                && t.affinity.contains(dest_core)
+        })?;
+        let taskref = self.get(index)?.taskref.clone();
+        self.remove_internal(&taskref).ok()?;
+        Some(taskref)
+    }
+
+    /// Removes and returns a task from this `RunQueue` whose affinity doesn't allow it to run on this `RunQueue`'s core,
+    /// such that it can be migrated to an allowed core.
+    /// 
+    /// Tasks that are currently running are never removed; they are removed the next time this is invoked
+    /// after they have been switched out.
+    /// Tasks whose affinity doesn't allow any core that has a `RunQueue` are left here, as they have nowhere to go;
+    /// a warning is logged the first time that such a task is found.
+    pub fn take_disallowed_task(&mut self) -> Option<TaskRef> {
+        let core = self.core;
+        let mut index = None;
+        for (i, t) in self.queue.iter_mut().enumerate() {
+            let (disallowed, has_destination) = {
+                let task = t.taskref.lock();
+                let disallowed = !task.affinity.contains(core) && !task.is_an_idle_task && !task.is_running();
+                (disallowed, disallowed && RUNQUEUES.iter().any(|(&other_core, _)| task.affinity.contains(other_core)))
+            };
+            if !disallowed {
+                continue;
+            }
+            if has_destination {
+                index = Some(i);
+                break;
+            }
+            if !t.stranded {
+                t.stranded = true;
+                warn!("Task {:?} isn't allowed to run on any core that has a runqueue, so it stays on core {}", t.taskref, core);
+            }
+        }
+        let taskref = self.get(index?)?.taskref.clone();
+        self.remove_internal(&taskref).ok()?;
+        Some(taskref)
+    }
+
 
     #[cfg(runqueue_spillful)]
     /// Removes a `TaskRef` from the RunQueue(s) on the given `core`.
@@ -18,7 +18,7 @@ extern crate single_simd_task_optimization;
 use alloc::collections::VecDeque;
 use irq_safety::RwLockIrqSafe;
 use atomic_linked_list::atomic_map::AtomicMap;
-use task::TaskRef;
+use task::{TaskRef, AffinityMask};
 use core::ops::{Deref, DerefMut};
 
 /// A cloneable reference to a `Taskref` that exposes more methods
@@ -40,6 +40,13 @@ pub struct RoundRobinTaskRef{
 
     /// Number of context switches the task has undergone. Not used in scheduling algorithm
     context_switches: usize,
+
+    /// Whether the task is an idle task, which never counts towards the load of a runqueue.
+    is_idle: bool,
+
+    /// Whether the task's affinity has been found to allow no core that has a runqueue,
+    /// such that this is only reported once.
+    stranded: bool,
 }
 
 // impl Drop for RoundRobinTaskRef {
@@ -64,9 +71,12 @@ impl DerefMut for RoundRobinTaskRef {
 impl RoundRobinTaskRef {
     /// Creates a new `RoundRobinTaskRef` that wraps the given `TaskRef`.
     pub fn new(taskref: TaskRef) -> RoundRobinTaskRef {
+        let is_idle = taskref.lock().is_an_idle_task;
         RoundRobinTaskRef {
             taskref: taskref,
             context_switches: 0,
+            is_idle,
+            stranded: false,
         }
     }
 
@@ -91,6 +101,9 @@ lazy_static! {
 pub struct RunQueue {
     core: u8,
     queue: VecDeque<RoundRobinTaskRef>,
+    /// The number of tasks in `queue` that aren't idle tasks, which is kept up to date as tasks are added and removed
+    /// such that the load of this `RunQueue` can be read without locking each of its tasks.
+    load: usize,
 }
 // impl Drop for RunQueue {
 //     fn drop(&mut self) {
@@ -129,6 +142,7 @@ impl RunQueue {
         let new_rq = RwLockIrqSafe::new(RunQueue {
             core: which_core,
             queue: VecDeque::new(),
+            load: 0,
         });
 
         #[cfg(runqueue_spillful)] 
@@ -154,16 +168,19 @@ impl RunQueue {
 
     /// Returns the "least busy" core, which is currently very simple, based on runqueue size.
     pub fn get_least_busy_core() -> Option<u8> {
-        Self::get_least_busy_runqueue().map(|rq| rq.read().core)
+        Self::get_least_busy_runqueue(&AffinityMask::all()).map(|rq| rq.read().core)
     }
 
 
-    /// Returns the `RunQueue` for the "least busy" core.
+    /// Returns the `RunQueue` for the "least busy" core among the cores in the `allowed` set.
     /// See [`get_least_busy_core()`](#method.get_least_busy_core)
-    fn get_least_busy_runqueue() -> Option<&'static RwLockIrqSafe<RunQueue>> {
+    fn get_least_busy_runqueue(allowed: &AffinityMask) -> Option<&'static RwLockIrqSafe<RunQueue>> {
         let mut min_rq: Option<(&'static RwLockIrqSafe<RunQueue>, usize)> = None;
 
-        for (_, rq) in RUNQUEUES.iter() {
+        for (&core, rq) in RUNQUEUES.iter() {
+            if !allowed.contains(core) {
+                continue;
+            }
             let rq_size = rq.read().queue.len();
 
             if let Some(min) = min_rq {
@@ -181,10 +198,11 @@ impl RunQueue {
 
     /// Chooses the "least busy" core's runqueue (based on simple runqueue-size-based load balancing)
     /// and adds the given `Task` reference to that core's runqueue.
+    /// Only the cores that the `Task`'s affinity allows it to run on are considered.
     pub fn add_task_to_any_runqueue(task: TaskRef) -> Result<(), &'static str> {
-        let rq = RunQueue::get_least_busy_runqueue()
-            .or_else(|| RUNQUEUES.iter().next().map(|r| r.1))
-            .ok_or("couldn't find any runqueues to add the task to!")?;
+        let affinity = task.lock().affinity;
+        let rq = RunQueue::get_least_busy_runqueue(&affinity)
+            .ok_or("couldn't find any runqueues that the task's affinity allows it to be added to!")?;
 
         rq.write().add_task(task)
     }
@@ -211,6 +229,9 @@ impl RunQueue {
         debug!("Adding task to runqueue_round_robin {}, {:?}", self.core, task);
 
         let round_robin_taskref = RoundRobinTaskRef::new(task);
+        if !round_robin_taskref.is_idle {
+            self.load += 1;
+        }
         self.push_back(round_robin_taskref);
         
         #[cfg(single_simd_task_optimization)]
@@ -230,6 +251,8 @@ impl RunQueue {
     fn remove_internal(&mut self, task: &TaskRef) -> Result<(), &'static str> {
         #[cfg(not(any(rq_eval, downtime_eval)))]
         debug!("Removing task from runqueue_round_robin {}, {:?}", self.core, task);
+        let removed_load = self.iter().filter(|x| &x.taskref == task && !x.is_idle).count();
+        self.load -= removed_load;
         self.retain(|x| &x.taskref != task);
 
         #[cfg(single_simd_task_optimization)]
@@ -270,6 +293,66 @@ impl RunQueue {
         Ok(())
     }
 
+    /// Returns the number of tasks in this `RunQueue`, not including its idle task.
+    /// This is used as a measure of how busy this `RunQueue`'s core is.
+    /// 
+    /// Blocked tasks stay in their `RunQueue`, so they are counted as well,
+    /// which allows this to be read without locking every task.
+    pub fn load(&self) -> usize {
+        self.load
+    }
+
+    /// Removes and returns a task from this `RunQueue` that can be migrated to the given `dest_core`.
+    ///
+    /// A task can only be migrated if it is runnable, is not an idle task, 
+    /// is not currently running on any core, and its affinity allows it to run on `dest_core`. 
+    /// The task that was most recently added to or selected from this `RunQueue` (the one at the back)
+    /// is never migrated, as this `RunQueue`'s core may be in the middle of switching to it.
+    pub fn take_migratable_task(&mut self, dest_core: u8) -> Option<TaskRef> {
+        let last = self.len().checked_sub(1)?;
+        let index = self.iter().take(last).position(|t| {
+            let t = t.lock();
+            t.is_runnable() && !t.is_an_idle_task && !t.is_running() 
+                && t.affinity.contains(dest_core)
+        })?;
+        let taskref = self.get(index)?.taskref.clone();
+        self.remove_internal(&taskref).ok()?;
+        Some(taskref)
+    }
+
+    /// Removes and returns a task from this `RunQueue` whose affinity doesn't allow it to run on this `RunQueue`'s core,
+    /// such that it can be migrated to an allowed core.
+    /// 
+    /// Tasks that are currently running are never removed; they are removed the next time this is invoked
+    /// after they have been switched out.
+    /// Tasks whose affinity doesn't allow any core that has a `RunQueue` are left here, as they have nowhere to go;
+    /// a warning is logged the first time that such a task is found.
+    pub fn take_disallowed_task(&mut self) -> Option<TaskRef> {
+        let core = self.core;
+        let mut index = None;
+        for (i, t) in self.queue.iter_mut().enumerate() {
+            let (disallowed, has_destination) = {
+                let task = t.taskref.lock();
+                let disallowed = !task.affinity.contains(core) && !task.is_an_idle_task && !task.is_running();
+                (disallowed, disallowed && RUNQUEUES.iter().any(|(&other_core, _)| task.affinity.contains(other_core)))
+            };
+            if !disallowed {
+                continue;
+            }
+            if has_destination {
+                index = Some(i);
+                break;
+            }
+            if !t.stranded {
+                t.stranded = true;
+                warn!("Task {:?} isn't allowed to run on any core that has a runqueue, so it stays on core {}", t.taskref, core);
+            }
+        }
+        let taskref = self.get(index?)?.taskref.clone();
+        self.remove_internal(&taskref).ok()?;
+        Some(taskref)
+    }
+
     #[cfg(runqueue_spillful)]
     /// Removes a `TaskRef` from the RunQueue(s) on the given `core`.
     /// Note: This method is only used by the state spillful runqueue implementation.
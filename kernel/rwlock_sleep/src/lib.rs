@@ -0,0 +1,207 @@
+//! A readers-writer lock that puts tasks to sleep while they wait for the lock.
+//!
+//! By default, readers are preferred: new readers may acquire the lock while a writer is waiting,
+//! which maximizes concurrency but may starve writers.
+//! A lock created with [`RwLockSleep::with_preference()`](struct.RwLockSleep.html#method.with_preference)
+//! and [`RwLockPreference::Writer`](enum.RwLockPreference.html) instead blocks new readers while any writer is waiting.
+
+#![no_std]
+
+extern crate spin;
+extern crate wait_queue;
+
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use wait_queue::WaitQueue;
+
+
+/// Which kind of task is given priority when both readers and writers are waiting for an `RwLockSleep`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RwLockPreference {
+    /// New readers can acquire the lock even if a writer is waiting.
+    Reader,
+    /// New readers cannot acquire the lock while a writer is waiting.
+    Writer,
+}
+
+impl Default for RwLockPreference {
+    fn default() -> RwLockPreference {
+        RwLockPreference::Reader
+    }
+}
+
+
+/// A readers-writer lock that puts a `Task` to sleep while waiting for the lock to become available.
+///
+/// Any number of readers or at most one writer can hold the lock at a time.
+pub struct RwLockSleep<T: ?Sized> {
+    queue: WaitQueue,
+    preference: RwLockPreference,
+    /// The number of tasks currently waiting to acquire the write lock.
+    waiting_writers: AtomicUsize,
+    lock: RwLock<T>,
+}
+
+/// A guard that allows the locked data to be immutably accessed, during which no writer can hold the lock.
+///
+/// When the guard falls out of scope, the read lock will be released,
+/// which then notifies any `Task`s waiting on the lock.
+pub struct RwLockSleepReadGuard<'a, T: ?Sized + 'a> {
+    guard: ManuallyDrop<RwLockReadGuard<'a, T>>,
+    queue: &'a WaitQueue,
+}
+
+/// A guard that allows the locked data to be mutably accessed, during which mutual exclusion is guaranteed.
+///
+/// When the guard falls out of scope, the write lock will be released,
+/// which then notifies any `Task`s waiting on the lock.
+pub struct RwLockSleepWriteGuard<'a, T: ?Sized + 'a> {
+    guard: ManuallyDrop<RwLockWriteGuard<'a, T>>,
+    queue: &'a WaitQueue,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLockSleep<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLockSleep<T> {}
+
+impl<T> RwLockSleep<T> {
+    /// Creates a new reader-preferred lock wrapping the supplied data.
+    pub fn new(data: T) -> RwLockSleep<T> {
+        RwLockSleep::with_preference(data, RwLockPreference::Reader)
+    }
+
+    /// Creates a new lock wrapping the supplied data that uses the given `preference`
+    /// to decide whether new readers may acquire the lock while a writer is waiting.
+    pub fn with_preference(data: T, preference: RwLockPreference) -> RwLockSleep<T> {
+        RwLockSleep {
+            queue: WaitQueue::new(),
+            preference,
+            waiting_writers: AtomicUsize::new(0),
+            lock: RwLock::new(data),
+        }
+    }
+
+    /// Consumes this `RwLockSleep`, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLockSleep<T> {
+    /// Blocks until the read lock is acquired by putting this `Task` to sleep
+    /// until the writer holding the lock releases it.
+    ///
+    /// The read lock will be released when the returned guard falls out of scope and is dropped.
+    pub fn read(&self) -> Result<RwLockSleepReadGuard<T>, &'static str> {
+        // Fast path: check for the uncontended case.
+        if let Some(guard) = self.try_read() {
+            return Ok(guard);
+        }
+        self.queue
+            .wait_until(&|| self.try_read())
+            .map_err(|_| "failed to add current task to waitqueue")
+    }
+
+    /// Blocks until the write lock is acquired by putting this `Task` to sleep
+    /// until all other tasks holding the lock release it.
+    ///
+    /// The write lock will be released when the returned guard falls out of scope and is dropped.
+    pub fn write(&self) -> Result<RwLockSleepWriteGuard<T>, &'static str> {
+        // Fast path: check for the uncontended case.
+        if let Some(guard) = self.try_write() {
+            return Ok(guard);
+        }
+        self.waiting_writers.fetch_add(1, Ordering::AcqRel);
+        let res = self.queue
+            .wait_until(&|| self.try_write())
+            .map_err(|_| "failed to add current task to waitqueue");
+        // If this was the last waiting writer, readers blocked due to writer preference may now proceed,
+        // but only once this writer's guard is dropped, which notifies them.
+        self.waiting_writers.fetch_sub(1, Ordering::AcqRel);
+        res
+    }
+
+    /// Tries to acquire the read lock. If a writer holds the lock,
+    /// or a writer is waiting for a writer-preferred lock, it will return `None`.
+    pub fn try_read(&self) -> Option<RwLockSleepReadGuard<T>> {
+        if self.preference == RwLockPreference::Writer && self.waiting_writers.load(Ordering::Acquire) > 0 {
+            return None;
+        }
+        self.lock.try_read().map(|guard| RwLockSleepReadGuard {
+            guard: ManuallyDrop::new(guard),
+            queue: &self.queue,
+        })
+    }
+
+    /// Tries to acquire the write lock. If the lock is already held, it will return `None`.
+    pub fn try_write(&self) -> Option<RwLockSleepWriteGuard<T>> {
+        self.lock.try_write().map(|guard| RwLockSleepWriteGuard {
+            guard: ManuallyDrop::new(guard),
+            queue: &self.queue,
+        })
+    }
+
+    /// Returns the reader/writer preference of this lock.
+    pub fn preference(&self) -> RwLockPreference {
+        self.preference
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockSleep<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.lock.try_read() {
+            Some(guard) => write!(f, "RwLockSleep {{ data: {:?} }}", &*guard),
+            None => write!(f, "RwLockSleep {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLockSleep<T> {
+    fn default() -> RwLockSleep<T> {
+        RwLockSleep::new(Default::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockSleepReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref<'b>(&'b self) -> &'b T {
+        &*(self.guard)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockSleepWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref<'b>(&'b self) -> &'b T {
+        &*(self.guard)
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockSleepWriteGuard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+        &mut *(self.guard)
+    }
+}
+
+// Releasing either kind of lock may allow both readers and writers to proceed,
+// so all waiting tasks are notified. The inner guard is dropped *before* notifying,
+// otherwise a woken task could fail to acquire the lock and go back to sleep for good.
+impl<'a, T: ?Sized> Drop for RwLockSleepReadGuard<'a, T> {
+    fn drop(&mut self) {
+        // SAFETY: the inner guard is never used again after this.
+        unsafe { ManuallyDrop::drop(&mut self.guard); }
+        self.queue.notify_all();
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockSleepWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        // SAFETY: the inner guard is never used again after this.
+        unsafe { ManuallyDrop::drop(&mut self.guard); }
+        self.queue.notify_all();
+    }
+}
@@ -1,3 +1,14 @@
+//! Provides the scheduling functionality for selecting the next task and switching to it.
+//!
+//! The scheduler policy can be changed at runtime with [`set_policy()`](fn.set_policy.html).
+//! Which policies are available depends on the runqueue implementation chosen at build time:
+//! * By default, the [`RoundRobin`](enum.SchedulerPolicy.html#variant.RoundRobin) policy is used,
+//!   and the [`StrictPriority`](enum.SchedulerPolicy.html#variant.StrictPriority) policy is also available.
+//! * With the `priority_scheduler` cfg option, the [`EpochFair`](enum.SchedulerPolicy.html#variant.EpochFair) policy is used,
+//!   and the [`StrictPriority`](enum.SchedulerPolicy.html#variant.StrictPriority) policy is also available.
+//!
+//! Every time a core schedules, it first balances its load with the other cores, see the `load_balancer` crate.
+
 #![no_std]
 
 extern crate alloc;
@@ -6,16 +17,89 @@ extern crate irq_safety;
 extern crate apic;
 extern crate task;
 extern crate runqueue;
+extern crate load_balancer;
+extern crate scheduler_strict_priority;
 #[cfg(priority_scheduler)] extern crate scheduler_priority;
 #[cfg(not(priority_scheduler))] extern crate scheduler_round_robin;
 
 
 use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
 use irq_safety::hold_interrupts;
 use apic::get_my_apic_id;
 use task::{Task, get_my_current_task, TaskRef};
-#[cfg(priority_scheduler)] use scheduler_priority::select_next_task;
-#[cfg(not(priority_scheduler))] use scheduler_round_robin::select_next_task;
+
+
+/// The policies that the scheduler can use to select the next task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SchedulerPolicy {
+    /// Every runnable task is picked in turn, regardless of its priority.
+    /// This is only available when the `priority_scheduler` cfg option is not set.
+    RoundRobin = 0,
+    /// The runnable task with the highest priority is always picked,
+    /// with round robin scheduling among tasks of the same priority.
+    StrictPriority = 1,
+    /// Each task receives a share of every scheduling epoch proportional to its priority,
+    /// such that all runnable tasks eventually run but higher-priority tasks run more often.
+    /// This is only available when the `priority_scheduler` cfg option is set.
+    EpochFair = 2,
+}
+
+impl SchedulerPolicy {
+    /// Returns `true` if this policy can be used with the runqueue implementation that this kernel was built with.
+    pub fn is_available(&self) -> bool {
+        match *self {
+            SchedulerPolicy::StrictPriority => true,
+            SchedulerPolicy::RoundRobin => cfg!(not(priority_scheduler)),
+            SchedulerPolicy::EpochFair => cfg!(priority_scheduler),
+        }
+    }
+
+    fn from_u8(value: u8) -> SchedulerPolicy {
+        match value {
+            1 => SchedulerPolicy::StrictPriority,
+            2 => SchedulerPolicy::EpochFair,
+            _ => SchedulerPolicy::RoundRobin,
+        }
+    }
+}
+
+#[cfg(priority_scheduler)]
+const DEFAULT_POLICY: SchedulerPolicy = SchedulerPolicy::EpochFair;
+#[cfg(not(priority_scheduler))]
+const DEFAULT_POLICY: SchedulerPolicy = SchedulerPolicy::RoundRobin;
+
+/// The currently-active scheduler policy, which is shared by all cores.
+static POLICY: AtomicU8 = AtomicU8::new(DEFAULT_POLICY as u8);
+
+/// Changes the scheduler policy used on all cores, which takes effect the next time each core schedules.
+/// 
+/// Returns an error if the given `policy` is not available in this build, 
+/// see [`SchedulerPolicy::is_available()`](enum.SchedulerPolicy.html#method.is_available).
+pub fn set_policy(policy: SchedulerPolicy) -> Result<(), &'static str> {
+    if !policy.is_available() {
+        return Err("the given scheduler policy is not available with the runqueue implementation in this build");
+    }
+    POLICY.store(policy as u8, Ordering::Release);
+    Ok(())
+}
+
+/// Returns the currently-active scheduler policy.
+pub fn get_policy() -> SchedulerPolicy {
+    SchedulerPolicy::from_u8(POLICY.load(Ordering::Acquire))
+}
+
+/// Selects the next task to run on the given core based on the current scheduler policy.
+fn select_next_task(apic_id: u8) -> Option<TaskRef> {
+    match get_policy() {
+        SchedulerPolicy::StrictPriority => scheduler_strict_priority::select_next_task(apic_id),
+        #[cfg(priority_scheduler)]
+        _ => scheduler_priority::select_next_task(apic_id),
+        #[cfg(not(priority_scheduler))]
+        _ => scheduler_round_robin::select_next_task(apic_id),
+    }
+}
 
 /// Yields the current CPU by selecting a new `Task` to run 
 /// and then performs a task switch to that new `Task`.
@@ -28,6 +112,9 @@ pub fn schedule() -> bool {
     let next_task: *mut Task; 
     let apic_id = get_my_apic_id();
 
+    // Give away some of this core's tasks if it is busier than the other cores.
+    load_balancer::balance(apic_id);
+
     {
         if let Some(selected_next_task) = select_next_task(apic_id) {
             next_task = selected_next_task.lock().deref() as *const Task as *mut Task;
@@ -70,26 +157,25 @@ pub fn schedule() -> bool {
 
 /// Changes the priority of the given task with the given priority level.
 /// Priority values must be between 40 (maximum priority) and 0 (minimum prriority).
-/// This function returns an error when a scheduler without priority is loaded. 
-pub fn set_priority(_task: &TaskRef, _priority: u8) -> Result<(), &'static str> {
+/// 
+/// The priority is used by the `StrictPriority` and `EpochFair` scheduler policies, 
+/// but is ignored by the `RoundRobin` policy.
+pub fn set_priority(task: &TaskRef, priority: u8) -> Result<(), &'static str> {
     #[cfg(priority_scheduler)] {
-        scheduler_priority::set_priority(_task, _priority)
+        scheduler_priority::set_priority(task, priority)
     }
     #[cfg(not(priority_scheduler))] {
-        Err("no scheduler that uses task priority is currently loaded")
+        task.set_priority(priority);
+        Ok(())
     }
 }
 
-/// Returns the priority of a given task.
-/// This function returns None when a scheduler without priority is loaded.
-pub fn get_priority(_task: &TaskRef) -> Option<u8> {
+/// Returns the effective priority of a given task, including any priority it has inherited.
+pub fn get_priority(task: &TaskRef) -> Option<u8> {
     #[cfg(priority_scheduler)] {
-        scheduler_priority::get_priority(_task)
+        scheduler_priority::get_priority(task)
     }
     #[cfg(not(priority_scheduler))] {
-        //Err("no scheduler that uses task priority is currently loaded")
-        None
+        Some(task.priority())
     }
 }
-
-
@@ -11,7 +11,7 @@
 #![no_std]
 
 extern crate alloc;
-#[macro_use] extern crate log;
+// #[macro_use] extern crate log;
 extern crate task;
 extern crate runqueue_priority;
 
@@ -30,12 +30,13 @@ struct NextTaskResult{
 /// Priority values must be between 40 (maximum priority) and 0 (minimum prriority).
 pub fn set_priority(task: &TaskRef, priority: u8) -> Result<(), &'static str> {
     let priority = core::cmp::min(priority, MAX_PRIORITY);
+    task.set_priority(priority);
     RunQueue::set_priority(task, priority)
 }
 
-/// Returns the priority of the given task.
+/// Returns the effective priority of the given task, including any inherited priority.
 pub fn get_priority(task: &TaskRef) -> Option<u8> {
-    RunQueue::get_priority(task)
+    Some(task.priority())
 }
 
 /// This defines the priority scheduler policy.
@@ -96,13 +97,10 @@ fn select_next_task_priority(apic_id: u8) -> Option<NextTaskResult>  {
             continue;
         }
 
-        // if this task is pinned, it must not be pinned to a different core
-        if let Some(pinned) = t.pinned_core {
-            if pinned != apic_id {
-                // with per-core runqueues, this should never happen!
-                error!("select_next_task() (AP {}) found a task pinned to a different core: {:?}", apic_id, *t);
-                return None;
-            }
+        // the task's affinity must allow it to run on this core; 
+        // if not, it will be migrated to an allowed core by the load balancer.
+        if !t.affinity.contains(apic_id) {
+            continue;
         }
 
         // if the task has no remaining tokens we ignore the task
@@ -168,19 +166,16 @@ fn assign_tokens(apic_id: u8) -> bool  {
             continue;
         }
 
-        // if this task is pinned, it must not be pinned to a different core
-        if let Some(pinned) = t.pinned_core {
-            if pinned != apic_id {
-                // with per-core runqueues, this should never happen!
-                error!("select_next_task() (AP {}) found a task pinned to a different core: {:?}", apic_id, *t);
-                return false;
-            }
+        // tasks that aren't allowed to run on this core don't receive any tokens
+        if !t.affinity.contains(apic_id) {
+            continue;
         }
             
         // found a runnable task!
         // We add its priority
         // debug!("assign_tokens(): AP {} Task {:?} priority {}", apic_id, *t, priority_taskref.priority);
-        total_priorities = total_priorities.saturating_add(1).saturating_add(priority_taskref.priority as usize);
+        // The task's effective priority is used so that tasks with inherited priority receive more tokens.
+        total_priorities = total_priorities.saturating_add(1).saturating_add(t.priority() as usize);
         
         
         
@@ -214,16 +209,13 @@ fn assign_tokens(apic_id: u8) -> bool  {
                 continue;
             }
 
-            // if this task is pinned, it must not be pinned to a different core
-            if let Some(pinned) = t.pinned_core {
-                if pinned != apic_id {
-                    // with per-core runqueues, this should never happen!
-                    error!("select_next_task() (AP {}) found a task pinned to a different core: {:?}", apic_id, *t);
-                    return false;
-                }
+            // tasks that aren't allowed to run on this core don't receive any tokens
+            if !t.affinity.contains(apic_id) {
+                continue;
             }
+
             // task_tokens = epoch * (taskref + 1) / total_priorities;
-            task_tokens = epoch.saturating_mul((priority_taskref.priority as usize).saturating_add(1)).wrapping_div(total_priorities);
+            task_tokens = epoch.saturating_mul((t.priority() as usize).saturating_add(1)).wrapping_div(total_priorities);
         }
         
         {
@@ -0,0 +1,78 @@
+//! This crate picks the next task based on a strict priority policy.
+//! The runnable task with the highest effective priority is always picked,
+//! including any priority it has inherited (see `TaskRef::inherit_priority()`).
+//! Among tasks of the same priority, the one closest to the front of the queue is picked
+//! and then moved to the back of the queue, such that they are scheduled in round robin fashion.
+//!
+//! This policy works with either runqueue implementation, whichever one is currently in use.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate task;
+#[cfg(priority_scheduler)] extern crate runqueue_priority;
+#[cfg(not(priority_scheduler))] extern crate runqueue_round_robin;
+
+use task::TaskRef;
+#[cfg(priority_scheduler)] use runqueue_priority::RunQueue;
+#[cfg(not(priority_scheduler))] use runqueue_round_robin::RunQueue;
+
+
+/// This defines the strict priority scheduler policy.
+/// Returns None if there is no schedule-able task.
+pub fn select_next_task(apic_id: u8) -> Option<TaskRef> {
+
+    let mut runqueue_locked = match RunQueue::get_runqueue(apic_id) {
+        Some(rq) => rq.write(),
+        _ => {
+            error!("BUG: select_next_task_strict_priority(): couldn't get runqueue for core {}", apic_id);
+            return None;
+        }
+    };
+
+    let mut idle_task_index: Option<usize> = None;
+    // The index and priority of the highest-priority runnable task found so far.
+    let mut chosen_task: Option<(usize, u8)> = None;
+
+    for (i, taskref) in runqueue_locked.iter().enumerate() {
+        let t = taskref.lock();
+
+        // we skip the idle task, and only choose it if no other tasks are runnable
+        if t.is_an_idle_task {
+            idle_task_index = Some(i);
+            continue;
+        }
+
+        // must be runnable
+        if !t.is_runnable() {
+            continue;
+        }
+
+        // must be allowed to run on this core
+        if !t.affinity.contains(apic_id) {
+            continue;
+        }
+
+        // only a strictly higher priority replaces an earlier task, which keeps round robin order within a priority level
+        let priority = t.priority();
+        if chosen_task.map_or(true, |(_, chosen_priority)| priority > chosen_priority) {
+            chosen_task = Some((i, priority));
+        }
+    }
+
+    // idle task is a backup iff no other task has been chosen
+    let index = chosen_task.map(|(i, _)| i).or(idle_task_index)?;
+    move_to_end(&mut runqueue_locked, index)
+}
+
+#[cfg(priority_scheduler)]
+fn move_to_end(runqueue: &mut RunQueue, index: usize) -> Option<TaskRef> {
+    // This policy doesn't use tokens, so the task's remaining tokens are left unchanged.
+    let tokens = runqueue.get(index)?.tokens_remaining;
+    runqueue.update_and_move_to_end(index, tokens)
+}
+
+#[cfg(not(priority_scheduler))]
+fn move_to_end(runqueue: &mut RunQueue, index: usize) -> Option<TaskRef> {
+    runqueue.move_to_end(index)
+}
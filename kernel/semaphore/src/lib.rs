@@ -0,0 +1,82 @@
+//! A counting semaphore that puts tasks to sleep while they wait for a permit to become available.
+
+#![no_std]
+
+extern crate wait_queue;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use wait_queue::{WaitQueue, WaitError};
+
+
+/// A counting semaphore, which holds a number of permits that tasks can acquire and release.
+///
+/// A `Task` that tries to acquire a permit when none are available will be put to sleep
+/// on a `WaitQueue` until another `Task` releases a permit.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the given number of initially-available permits.
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Acquires one permit, blocking the current task until one is available.
+    pub fn acquire(&self) -> Result<(), WaitError> {
+        // Fast path: a permit is available.
+        if self.try_acquire() {
+            return Ok(());
+        }
+        // Slow path: the condition is checked with the waitqueue locked,
+        // so a permit released after a failed check will always notify this task.
+        self.queue.wait_until(&|| if self.try_acquire() { Some(()) } else { None })
+    }
+
+    /// Tries to acquire one permit without blocking.
+    /// Returns `true` if a permit was acquired.
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Acquire);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(permits, permits - 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    /// Acquires one permit, blocking the current task until one is available,
+    /// and returns a guard that releases the permit when dropped.
+    pub fn acquire_guard(&self) -> Result<SemaphoreGuard, WaitError> {
+        self.acquire().map(|_| SemaphoreGuard { semaphore: self })
+    }
+
+    /// Releases one permit, waking up a task waiting to acquire it, if any.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::AcqRel);
+        self.queue.notify_one();
+    }
+
+    /// Returns the number of permits that are currently available.
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Acquire)
+    }
+}
+
+
+/// A guard that holds one permit of a `Semaphore`,
+/// which is automatically released when the guard is dropped.
+pub struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
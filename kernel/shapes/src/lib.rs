@@ -100,6 +100,45 @@ impl Rectangle {
     pub fn height(&self) -> usize {
         (self.bottom_right.y - self.top_left.y) as usize
     }
+
+    /// Returns `true` if this Rectangle contains no pixels.
+    pub fn is_empty(&self) -> bool {
+        self.bottom_right.x <= self.top_left.x || self.bottom_right.y <= self.top_left.y
+    }
+
+    /// Returns the number of pixels in this Rectangle.
+    pub fn area(&self) -> usize {
+        if self.is_empty() { 0 } else { self.width() * self.height() }
+    }
+
+    /// Returns the overlapping part of this Rectangle and `other`, or `None` if they do not overlap.
+    pub fn intersection(&self, other: &Rectangle) -> Option<Rectangle> {
+        let intersection = Rectangle {
+            top_left: Coord::new(
+                core::cmp::max(self.top_left.x, other.top_left.x),
+                core::cmp::max(self.top_left.y, other.top_left.y),
+            ),
+            bottom_right: Coord::new(
+                core::cmp::min(self.bottom_right.x, other.bottom_right.x),
+                core::cmp::min(self.bottom_right.y, other.bottom_right.y),
+            ),
+        };
+        if intersection.is_empty() { None } else { Some(intersection) }
+    }
+
+    /// Returns the smallest Rectangle that contains both this Rectangle and `other`.
+    pub fn union(&self, other: &Rectangle) -> Rectangle {
+        Rectangle {
+            top_left: Coord::new(
+                core::cmp::min(self.top_left.x, other.top_left.x),
+                core::cmp::min(self.top_left.y, other.top_left.y),
+            ),
+            bottom_right: Coord::new(
+                core::cmp::max(self.bottom_right.x, other.bottom_right.x),
+                core::cmp::max(self.bottom_right.y, other.bottom_right.y),
+            ),
+        }
+    }
 }
 
 impl Add<Coord> for Rectangle {
@@ -49,6 +49,10 @@ pub struct SCAllocator<'a, P: AllocablePage> {
     pub(crate) size: usize,
     /// Keeps track of succeeded allocations.
     pub(crate) allocation_count: usize,
+    /// The number of objects that are currently allocated (and not yet deallocated).
+    pub(crate) live_objects: usize,
+    /// The total number of successful allocations since this `SCAllocator` was created.
+    pub(crate) total_allocations: usize,
     /// max objects per page
     pub(crate) obj_per_page: usize,
     /// List of empty ObjectPages (nothing allocated in these).
@@ -66,6 +70,8 @@ macro_rules! new_sc_allocator {
         SCAllocator {
             size: $size,
             allocation_count: 0,
+            live_objects: 0,
+            total_allocations: 0,
             obj_per_page: cmin((P::SIZE - P::METADATA_SIZE) / $size, 8 * 64),
             empty_slabs: PageList::new(),
             slabs: PageList::new(),
@@ -93,6 +99,22 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         self.size
     }
 
+    /// Returns the number of objects that are currently allocated from this allocator.
+    pub fn live_objects(&self) -> usize {
+        self.live_objects
+    }
+
+    /// Returns the total number of successful allocations from this allocator since it was created.
+    pub fn total_allocations(&self) -> usize {
+        self.total_allocations
+    }
+
+    /// Returns the number of pages in this allocator's lists of 
+    /// empty pages, partially-used pages, and full pages, in that order.
+    pub fn page_counts(&self) -> (usize, usize, usize) {
+        (self.empty_slabs.elements, self.slabs.elements, self.full_slabs.elements)
+    }
+
     /// Add page to partial list.
     fn insert_partial_slab(&mut self, new_head: &'a mut P) {
         self.slabs.insert_front(new_head);
@@ -291,6 +313,10 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         };
 
         let res = NonNull::new(ptr).ok_or("AllocationError::OutOfMemory");
+        if res.is_ok() {
+            self.live_objects += 1;
+            self.total_allocations += 1;
+        }
 
         // if !ptr.is_null() {
         //     trace!(
@@ -329,6 +355,9 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         let slab_page_was_full = slab_page.is_full();
         let ret = slab_page.deallocate(ptr, new_layout);
         debug_assert!(ret.is_ok(), "Slab page deallocate won't fail at the moment");
+        if ret.is_ok() {
+            self.live_objects = self.live_objects.saturating_sub(1);
+        }
 
         if slab_page.is_empty(self.obj_per_page) {
             // We need to move it from self.slabs -> self.empty_slabs
@@ -153,6 +153,11 @@ impl<'a> ZoneAllocator<'a> {
         self.refill(layout, mp)
     }  
 
+    /// Returns the size class allocators in this zone allocator, ordered from smallest to largest size class.
+    pub fn size_class_allocators(&self) -> &[SCAllocator<'a, ObjectPage8k<'a>>] {
+        &self.small_slabs
+    }
+
     /// The total number of empty pages in this zone allocator
     pub fn empty_pages(&self) -> usize {
         let mut empty_pages = 0;
@@ -0,0 +1,88 @@
+//! Support for the SLIT ACPI table (System Locality Information Table),
+//! which specifies the relative distance (memory latency) between each pair of NUMA nodes.
+
+#![no_std]
+
+extern crate alloc;
+extern crate memory;
+extern crate sdt;
+extern crate acpi_table;
+extern crate zerocopy;
+
+use core::mem::size_of;
+use alloc::vec::Vec;
+use memory::PhysicalAddress;
+use sdt::Sdt;
+use acpi_table::{AcpiSignature, AcpiTables};
+use zerocopy::FromBytes;
+
+pub const SLIT_SIGNATURE: &'static [u8; 4] = b"SLIT";
+
+/// The handler for parsing the SLIT table and adding it to the ACPI tables list.
+pub fn handle(
+    acpi_tables: &mut AcpiTables,
+    signature: AcpiSignature,
+    length: usize,
+    phys_addr: PhysicalAddress
+) -> Result<(), &'static str> {
+    // The distance matrix is one byte per entry, and fills the rest of the table.
+    let slice_start_paddr = phys_addr + size_of::<SlitAcpiTable>();
+    let slice_len = length.checked_sub(size_of::<SlitAcpiTable>()).ok_or("SLIT table was too short")?;
+    acpi_tables.add_table_location(signature, phys_addr, Some((slice_start_paddr, slice_len)))
+}
+
+
+/// The fixed-size components of the SLIT ACPI table.
+/// Its layout and total size must exactly match that of the ACPI specification.
+#[derive(Clone, Copy, Debug, FromBytes)]
+#[repr(packed)]
+struct SlitAcpiTable {
+    header: Sdt,
+    num_localities: u64,
+    // Following this is a `num_localities` by `num_localities` matrix of distances.
+}
+
+
+/// A wrapper around the SLIT ACPI table (System Locality Information Table).
+pub struct Slit<'t> {
+    table: &'t SlitAcpiTable,
+    /// The distance matrix, in row-major order.
+    entries: &'t [u8],
+}
+
+impl<'t> Slit<'t> {
+    /// Finds the SLIT in the given `AcpiTables` and returns a reference to it.
+    pub fn get(acpi_tables: &'t AcpiTables) -> Option<Slit<'t>> {
+        let table: &SlitAcpiTable = acpi_tables.table(&SLIT_SIGNATURE).ok()?;
+        let entries: &[u8] = acpi_tables.table_slice(&SLIT_SIGNATURE).ok()?;
+        Some(Slit { table, entries })
+    }
+
+    /// Returns a reference to the `Sdt` header in this SLIT table.
+    pub fn sdt(&self) -> &Sdt {
+        &self.table.header
+    }
+
+    /// Returns the number of localities (NUMA nodes) in this SLIT table.
+    pub fn num_localities(&self) -> usize {
+        self.table.num_localities as usize
+    }
+
+    /// Returns the relative distance from locality `from` to locality `to`,
+    /// where `10` is the distance from a locality to itself.
+    pub fn distance(&self, from: usize, to: usize) -> Option<u8> {
+        let n = self.num_localities();
+        if from >= n || to >= n {
+            return None;
+        }
+        self.entries.get(from * n + to).cloned()
+    }
+
+    /// Returns a copy of the full distance matrix in row-major order,
+    /// or `None` if the table is too short to hold all of its entries.
+    pub fn distance_matrix(&self) -> Option<Vec<u8>> {
+        let n = self.num_localities();
+        let len = n.checked_mul(n)?;
+        self.entries.get(..len).map(|entries| entries.to_vec())
+    }
+}
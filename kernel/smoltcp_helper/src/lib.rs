@@ -52,11 +52,11 @@ pub fn millis_since(start_time: u64) -> Result<u64, &'static str> {
 }
 
 
-/// Returns the first network interface available in the system.
+/// Returns the first network interface available in the system, other than a loopback interface.
 pub fn get_default_iface() -> Result<NetworkInterfaceRef, &'static str> {
     NETWORK_INTERFACES.lock()
         .iter()
-        .next()
+        .find(|iface| !iface.lock().is_loopback())
         .cloned()
         .ok_or_else(|| "no network interfaces available")
 }
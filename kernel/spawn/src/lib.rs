@@ -14,6 +14,7 @@
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate debugit;
 extern crate irq_safety;
 extern crate memory;
@@ -25,18 +26,22 @@ extern crate mod_mgmt;
 extern crate apic;
 extern crate context_switch;
 extern crate path;
-extern crate fs_node;
 extern crate catch_unwind;
 extern crate fault_crate_swap;
 extern crate pause;
+extern crate spin;
+extern crate environment;
+extern crate timer;
 
 
 use core::{
     mem,
     marker::PhantomData,
     ops::Deref,
+    time::Duration,
 };
 use alloc::{
+    collections::BTreeMap,
     vec::Vec,
     string::String,
     sync::Arc,
@@ -45,16 +50,47 @@ use alloc::{
 use irq_safety::{MutexIrqSafe, hold_interrupts, enable_interrupts};
 use memory::{get_kernel_mmi_ref, MemoryManagementInfo};
 use stack::Stack;
-use task::{Task, TaskRef, get_my_current_task, RunState, RestartInfo, TASKLIST};
+use task::{Task, TaskRef, get_my_current_task, RunState, RestartInfo, RestartPolicy, RestartBackoff, TASKLIST, AffinityMask};
 use mod_mgmt::{CrateNamespace, SectionType, SECTION_HASH_DELIMITER};
 use path::Path;
 use apic::get_my_apic_id;
-use fs_node::FileOrDir;
+use spin::Mutex;
+use environment::Environment;
 
 #[cfg(simd_personality)]
 use task::SimdExt;
 
 
+lazy_static! {
+    /// The registry of all tasks spawned with a restart policy, keyed by their supervision ID,
+    /// which is the ID of the first instance of each task. 
+    static ref SUPERVISED_TASKS: MutexIrqSafe<BTreeMap<usize, SupervisedTask>> = MutexIrqSafe::new(BTreeMap::new());
+}
+
+/// The status of a task that was spawned with a restart policy, see [`TaskBuilder::spawn_restartable()`].
+/// 
+/// [`TaskBuilder::spawn_restartable()`]: struct.TaskBuilder.html#method.spawn_restartable
+#[derive(Clone, Debug)]
+pub struct SupervisedTask {
+    /// The name of the task.
+    pub name: String,
+    /// When the task is restarted after it exits.
+    pub policy: RestartPolicy,
+    /// The number of times the task has been restarted.
+    pub restart_count: usize,
+    /// The ID of the currently-running instance of the task, 
+    /// or `None` if it exited and was not restarted.
+    pub current_task_id: Option<usize>,
+    /// A description of how the most recent instance of the task exited, if any have exited.
+    pub last_exit: Option<String>,
+}
+
+/// Returns the status of every task that was spawned with a restart policy, keyed by its supervision ID.
+pub fn supervised_tasks() -> BTreeMap<usize, SupervisedTask> {
+    SUPERVISED_TASKS.lock().clone()
+}
+
+
 /// Initializes tasking for the given AP core, including creating a runqueue for it
 /// and creating its initial task bootstrapped from the current execution context for that core. 
 pub fn init(
@@ -63,6 +99,7 @@ pub fn init(
     stack: Stack,
 ) -> Result<BootstrapTaskRef, &'static str> {
     runqueue::init(apic_id)?;
+    mod_mgmt::TASK_FRAMES_IN_RANGE_FUNCTION.call_once(|| task::tasks_with_frames_in);
     
     let task_ref = task::bootstrap_task(apic_id, stack, kernel_mmi_ref)?;
     runqueue::add_task_to_specific_runqueue(apic_id, task_ref.clone())?;
@@ -126,7 +163,8 @@ pub fn new_task_builder<F, A, R>(
 /// but the actual new application task will not be spawned until [`TaskBuilder::spawn()`](struct.TaskBuilder.html#method.spawn) is invoked.
 /// 
 /// # Arguments
-/// * `crate_object_file`: the object file that the application crate will be loaded from.
+/// * `crate_object_file`: the object file that the application crate will be loaded from,
+///    which can be an absolute path anywhere in the filesystem or a path relative to the namespace's directory.
 /// * `new_namespace`: if provided, the new application task will be spawned within the new `CrateNamespace`,
 ///    meaning that the new application crate will be linked against the crates within that new namespace. 
 ///    If not provided, the new Task will be spawned within the same namespace as the current task.
@@ -140,17 +178,10 @@ pub fn new_application_task_builder(
         .or_else(|| task::get_my_current_task().map(|taskref| taskref.get_namespace()))
         .ok_or("spawn::new_application_task_builder(): couldn't get current task to use its CrateNamespace")?;
     
-    let crate_object_file = match crate_object_file.get(namespace.dir())
-        .or_else(|| Path::new(format!("{}.o", &crate_object_file)).get(namespace.dir())) // retry with ".o" extension
-    {
-        Some(FileOrDir::File(f)) => f,
-        _ => return Err("Couldn't find specified file path for new application crate"),
-    };
-    
-    // Load the new application crate
+    // Load the new application crate, which may reside anywhere in the filesystem.
     let app_crate_ref = {
         let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("couldn't get_kernel_mmi_ref")?;
-        CrateNamespace::load_crate_as_application(&namespace, &crate_object_file, &kernel_mmi_ref, false)?
+        mod_mgmt::load_crate_from_file(&crate_object_file, &namespace, &kernel_mmi_ref, false)?
     };
 
     // Find the "main" entry point function in the new app crate
@@ -204,9 +235,14 @@ pub struct TaskBuilder<F, A, R> {
     argument: A,
     _return_type: PhantomData<R>,
     name: Option<String>,
-    pin_on_core: Option<u8>,
+    affinity: Option<AffinityMask>,
     blocked: bool,
     idle: bool,
+    env: Option<Arc<Mutex<Environment>>>,
+    restart_policy: RestartPolicy,
+    restart_backoff: Option<RestartBackoff>,
+    /// The restart count and supervision ID, only used when restarting a task.
+    restarted_from: Option<(usize, usize)>,
     post_build_function: Option<Box< dyn FnOnce(&mut Task) -> Result<(), &'static str> >>,
 
     #[cfg(simd_personality)]
@@ -226,9 +262,13 @@ impl<F, A, R> TaskBuilder<F, A, R>
             func: func,
             _return_type: PhantomData,
             name: None,
-            pin_on_core: None,
+            affinity: None,
             blocked: false,
             idle: false,
+            env: None,
+            restart_policy: RestartPolicy::Always,
+            restart_backoff: None,
+            restarted_from: None,
             post_build_function: None,
 
             #[cfg(simd_personality)]
@@ -249,8 +289,18 @@ impl<F, A, R> TaskBuilder<F, A, R>
     }
 
     /// Pin the new Task to a specific core.
-    pub fn pin_on_core(mut self, core_apic_id: u8) -> TaskBuilder<F, A, R> {
-        self.pin_on_core = Some(core_apic_id);
+    /// 
+    /// This is equivalent to setting the new Task's affinity to only that core.
+    pub fn pin_on_core(self, core_apic_id: u8) -> TaskBuilder<F, A, R> {
+        self.affinity(AffinityMask::single(core_apic_id))
+    }
+
+    /// Set the cores that the new Task is allowed to run on. By default, it can run on any core.
+    /// 
+    /// The new Task will be added to the runqueue of the least busy core among those cores. 
+    /// Its affinity can be changed later with [`TaskRef::set_affinity()`](../task/struct.TaskRef.html#method.set_affinity).
+    pub fn affinity(mut self, affinity: AffinityMask) -> TaskBuilder<F, A, R> {
+        self.affinity = Some(affinity);
         self
     }
 
@@ -262,6 +312,14 @@ impl<F, A, R> TaskBuilder<F, A, R>
         self
     }
 
+    /// Set the `Environment` of the new Task, which will be shared with any other tasks that use the same `env`.
+    /// 
+    /// If not provided, the new Task will receive its own copy of the current task's `Environment`.
+    pub fn env(mut self, env: Arc<Mutex<Environment>>) -> TaskBuilder<F, A, R> {
+        self.env = Some(env);
+        self
+    }
+
     /// Set the new Task's `RunState` to be `Blocked` instead of `Runnable` when it is first spawned.
     /// This allows another task to delay the new task's execution arbitrarily, 
     /// e.g., to set up other things for the newly-spawned (but not yet running) task. 
@@ -288,6 +346,10 @@ impl<F, A, R> TaskBuilder<F, A, R>
             new_task.simd = self.simd;
         }
 
+        if let Some(env) = self.env {
+            new_task.env = env;
+        }
+
         setup_context_trampoline(&mut new_task, task_wrapper::<F, A, R>)?;
 
         // Currently we're using the very bottom of the kstack for kthread arguments. 
@@ -308,6 +370,14 @@ impl<F, A, R> TaskBuilder<F, A, R>
             new_task.runstate = RunState::Blocked;
         } else {
             new_task.runstate = RunState::Runnable;
+            new_task.stats.mark_runnable();
+        }
+
+        if let Some(affinity) = self.affinity {
+            if affinity.is_empty() {
+                return Err("TaskBuilder::spawn(): the new task's affinity must allow it to run on at least one core");
+            }
+            new_task.affinity = affinity;
         }
 
         // The new task is marked as idle
@@ -329,7 +399,7 @@ impl<F, A, R> TaskBuilder<F, A, R>
             return Err("BUG: TASKLIST a contained a task with the new task's ID");
         }
         
-        if let Some(core) = self.pin_on_core {
+        if let Some(core) = self.affinity.and_then(|a| a.single_core()) {
             runqueue::add_task_to_specific_runqueue(core, task_ref.clone())?;
         }
         else {
@@ -363,23 +433,51 @@ impl<F, A, R> TaskBuilder<F, A, R>
         self.pin_on_core(core_id)
     }
 
+    /// Set when the new Task will be restarted after it exits, which is only used by `spawn_restartable()`.
+    /// 
+    /// By default, restartable tasks use [`RestartPolicy::Always`](../task/enum.RestartPolicy.html).
+    pub fn restart_policy(mut self, policy: RestartPolicy) -> TaskBuilder<F, A, R> {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Delay each restart of the new Task, starting with the `initial` delay
+    /// and doubling it upon each subsequent restart, up to the `max` delay.
+    /// 
+    /// By default, restartable tasks are restarted immediately.
+    pub fn restart_backoff(mut self, initial: Duration, max: Duration) -> TaskBuilder<F, A, R> {
+        self.restart_backoff = Some(RestartBackoff { initial, max });
+        self
+    }
+
     /// Like `spawn()`, this finishes this `TaskBuilder` and spawns the new task. 
     /// It additionally stores the new Task's function and argument within the Task,
-    /// enabling it to be restarted upon exit.
+    /// enabling it to be restarted upon exit according to its [`restart_policy()`](#method.restart_policy).
+    /// 
+    /// The new task is tracked in the registry of supervised tasks, see [`supervised_tasks()`](fn.supervised_tasks.html).
     /// 
     /// This merely makes the new task Runnable, it does not switch to it immediately; that will happen on the next scheduler invocation.
     #[inline(never)]
     pub fn spawn_restartable(mut self) -> Result<TaskRef, &'static str> {
-        let restart_info = RestartInfo {
-            argument: Box::new(self.argument.clone()),
-            func: Box::new(self.func.clone()),
-        };
+        let policy = self.restart_policy;
+        let backoff = self.restart_backoff;
+        let restarted_from = self.restarted_from;
+        let argument: Box<dyn core::any::Any + Send> = Box::new(self.argument.clone());
+        let func: Box<dyn core::any::Any + Send> = Box::new(self.func.clone());
 
         // Once the new task is created, we set its restart info (func and arg),
         // and tell it to use the restartable version of the final task cleanup function.
         self.post_build_function = Some(Box::new(
             move |new_task| {
-                new_task.restart_info = Some(restart_info);
+                let (restart_count, supervision_id) = restarted_from.unwrap_or((0, new_task.id));
+                new_task.restart_info = Some(RestartInfo {
+                    argument,
+                    func,
+                    policy,
+                    backoff,
+                    restart_count,
+                    supervision_id,
+                });
                 new_task.failure_cleanup_function = task_restartable_cleanup_failure::<F, A, R>;
                 setup_context_trampoline(new_task, task_wrapper_restartable::<F, A, R>)?;
                 Ok(())
@@ -387,7 +485,27 @@ impl<F, A, R> TaskBuilder<F, A, R>
         ));
 
         // Code path is shared between `spawn` and `spawn_restartable` from this point
-        self.spawn()
+        let task_ref = self.spawn()?;
+
+        let (name, task_id) = {
+            let t = task_ref.lock();
+            (t.name.clone(), t.id)
+        };
+        // A restarted instance keeps its existing registry entry, such that its `last_exit` is preserved.
+        let (restart_count, supervision_id) = restarted_from.unwrap_or((0, task_id));
+        SUPERVISED_TASKS.lock().entry(supervision_id)
+            .and_modify(|supervised| {
+                supervised.restart_count = restart_count;
+                supervised.current_task_id = Some(task_id);
+            })
+            .or_insert(SupervisedTask {
+                name,
+                policy,
+                restart_count,
+                current_task_id: Some(task_id),
+                last_exit: None,
+            });
+        Ok(task_ref)
     }
 }
 
@@ -541,6 +659,21 @@ fn task_wrapper_restartable<F, A, R>() -> !
           R: Send + 'static,
           F: FnOnce(A) -> R + Send + Clone + 'static,
 {
+    // If this is a restarted instance of a task, wait out its restart backoff delay before running it.
+    let restart_delay = {
+        let curr_task = get_my_current_task().expect("BUG: task_wrapper: couldn't get current task (before restart delay).");
+        let t = curr_task.lock();
+        t.restart_info.as_ref().and_then(|ri| {
+            if ri.restart_count > 0 { ri.backoff.map(|b| b.delay(ri.restart_count - 1)) } else { None }
+        })
+    };
+    if let Some(delay) = restart_delay {
+        enable_interrupts();
+        if let Err(e) = timer::sleep(delay) {
+            warn!("task_wrapper_restartable(): failed to wait out the restart delay: {}", e);
+        }
+    }
+
     let result = task_wrapper_internal::<F, A, R>();
 
     // See `task_wrapper` for an explanation of how the below functions work.
@@ -687,53 +820,67 @@ fn task_restartable_cleanup_final<F, A, R>(held_interrupts: irq_safety::HeldInte
             }
         }
 
-        // Re-spawn a new instance of the task if it was spawned as a restartable task. 
+        // Re-spawn a new instance of the task if it was spawned as a restartable task
+        // and its restart policy allows restarting it after this kind of exit. 
         // We must not hold the current task's lock when calling spawn().
         let restartable_info = {
             let t = current_task.lock();
-            if let Some(restart_info) = t.restart_info.as_ref() {
-                #[cfg(use_crate_replacement)] {
-                    let func_ptr = &(restart_info.func) as *const _ as usize;
-                    let arg_ptr = &(restart_info.argument) as *const _ as usize;
-
-                    let arg_size = mem::size_of::<A>();
-                    #[cfg(not(downtime_eval))] {
-                        debug!("func_ptr {:#X}", func_ptr);
-                        debug!("arg_ptr {:#X} , {}", arg_ptr, arg_size);
-                    }
-
-                    // func_ptr is of size 16. Argument is of the argument_size + 8.
-                    // This extra size comes due to argument and function both stored in +8 location pointed by the pointer. 
-                    // The exact location pointed by the pointer has value 0x1. (Indicates Some for option ?). 
-                    if fault_crate_swap::constant_offset_fix(&se, func_ptr, func_ptr + 16).is_ok() &&  fault_crate_swap::constant_offset_fix(&se, arg_ptr, arg_ptr + 8).is_ok() {
-                        #[cfg(not(downtime_eval))]
-                        debug!("Function and argument addresses corrected");
+            let exit_value = t.get_exit_value();
+            if let (Some(restart_info), Some(exit_value)) = (t.restart_info.as_ref(), exit_value) {
+                let exit_description = match exit_value {
+                    task::ExitValue::Completed(_) => String::from("completed"),
+                    task::ExitValue::Panicked(panic_info) => format!("panicked at {}", panic_info),
+                    task::ExitValue::Killed(reason) => format!("killed: {}", reason),
+                };
+                let restart = restart_info.policy.should_restart(exit_value);
+                if let Some(supervised) = SUPERVISED_TASKS.lock().get_mut(&restart_info.supervision_id) {
+                    supervised.last_exit = Some(exit_description);
+                    if !restart {
+                        supervised.current_task_id = None;
                     }
                 }
+                if !restart {
+                    None
+                } else {
+                    #[cfg(use_crate_replacement)] {
+                        let func_ptr = &(restart_info.func) as *const _ as usize;
+                        let arg_ptr = &(restart_info.argument) as *const _ as usize;
+
+                        let arg_size = mem::size_of::<A>();
+                        #[cfg(not(downtime_eval))] {
+                            debug!("func_ptr {:#X}", func_ptr);
+                            debug!("arg_ptr {:#X} , {}", arg_ptr, arg_size);
+                        }
+
+                        // func_ptr is of size 16. Argument is of the argument_size + 8.
+                        // This extra size comes due to argument and function both stored in +8 location pointed by the pointer. 
+                        // The exact location pointed by the pointer has value 0x1. (Indicates Some for option ?). 
+                        if fault_crate_swap::constant_offset_fix(&se, func_ptr, func_ptr + 16).is_ok() &&  fault_crate_swap::constant_offset_fix(&se, arg_ptr, arg_ptr + 8).is_ok() {
+                            #[cfg(not(downtime_eval))]
+                            debug!("Function and argument addresses corrected");
+                        }
+                    }
                 
 
-                let func: &F = restart_info.func.downcast_ref().expect("BUG: failed to downcast restartable task's function");
-                let arg : &A = restart_info.argument.downcast_ref().expect("BUG: failed to downcast restartable task's argument");
-                Some((t.name.clone(), func.clone(), arg.clone(), t.pinned_core.clone()))
+                    let func: &F = restart_info.func.downcast_ref().expect("BUG: failed to downcast restartable task's function");
+                    let arg : &A = restart_info.argument.downcast_ref().expect("BUG: failed to downcast restartable task's argument");
+                    Some((t.name.clone(), func.clone(), arg.clone(), t.affinity, restart_info.policy, restart_info.backoff,
+                        (restart_info.restart_count + 1, restart_info.supervision_id)))
+                }
             } else {
+                error!("BUG : Restartable task has no restart information or exit value available");
                 None
             }
         };
 
-        if let Some((name, func, arg, pinned_core)) = restartable_info {
-            let new_task = new_task_builder(func, arg)
-                    .name(name);
-
-            if let Some(core) = pinned_core {
-                new_task.pin_on_core(core)
-                .spawn_restartable()
-                .expect("Could not restart the task");
-            } else {
-                new_task.spawn_restartable()
-                .expect("Could not restart the task");
-            }
-        } else {
-            error!("BUG : Restartable task has no restart information available");
+        if let Some((name, func, arg, affinity, policy, backoff, restarted_from)) = restartable_info {
+            let mut builder = new_task_builder(func, arg)
+                .name(name)
+                .affinity(affinity)
+                .restart_policy(policy);
+            builder.restart_backoff = backoff;
+            builder.restarted_from = Some(restarted_from);
+            builder.spawn_restartable().expect("Could not restart the task");
         }
     }
 
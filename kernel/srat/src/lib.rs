@@ -0,0 +1,274 @@
+//! Support for the SRAT ACPI table (System Resource Affinity Table),
+//! which specifies the NUMA node (proximity domain) of each processor and range of physical memory.
+
+#![no_std]
+#![allow(safe_packed_borrows)]
+
+extern crate memory;
+extern crate sdt;
+extern crate acpi_table;
+extern crate zerocopy;
+
+use core::mem::size_of;
+use memory::{MappedPages, PhysicalAddress};
+use memory::numa::{NumaTopology, NumaMemoryRange, NodeId};
+use sdt::Sdt;
+use acpi_table::{AcpiSignature, AcpiTables};
+use zerocopy::FromBytes;
+
+pub const SRAT_SIGNATURE: &'static [u8; 4] = b"SRAT";
+
+/// The handler for parsing the SRAT table and adding it to the ACPI tables list.
+pub fn handle(
+    acpi_tables: &mut AcpiTables,
+    signature: AcpiSignature,
+    _length: usize,
+    phys_addr: PhysicalAddress
+) -> Result<(), &'static str> {
+    // Like the MADT, the SRAT has a variable number of variable-sized entries after its fixed-size part.
+    let slice_start_paddr = phys_addr + size_of::<SratAcpiTable>();
+    acpi_tables.add_table_location(signature, phys_addr, Some((slice_start_paddr, 0)))
+}
+
+
+/// The fixed-size components of the SRAT ACPI table.
+/// Its layout and total size must exactly match that of the ACPI specification.
+#[derive(Debug, FromBytes)]
+#[repr(packed)]
+struct SratAcpiTable {
+    header: Sdt,
+    _reserved1: u32,
+    _reserved2: u64,
+    // Following this is a variable number of variable-sized table entries.
+}
+
+
+/// A wrapper around the SRAT ACPI table (System Resource Affinity Table).
+pub struct Srat<'t> {
+    /// The fixed-size part of the actual SRAT ACPI table.
+    table: &'t SratAcpiTable,
+    /// The underlying MappedPages that cover this SRAT.
+    mapped_pages: &'t MappedPages,
+    /// The starting offset of the dynamic part of the SRAT table.
+    dynamic_entries_starting_offset: usize,
+    /// The total size in bytes of all dynamic entries.
+    dynamic_entries_total_size: usize,
+}
+
+impl<'t> Srat<'t> {
+    /// Finds the SRAT in the given `AcpiTables` and returns a reference to it.
+    pub fn get(acpi_tables: &'t AcpiTables) -> Option<Srat<'t>> {
+        let table: &SratAcpiTable = acpi_tables.table(&SRAT_SIGNATURE).ok()?;
+        let total_length = table.header.length as usize;
+        let dynamic_part_length = total_length.checked_sub(size_of::<SratAcpiTable>())?;
+        let loc = acpi_tables.table_location(&SRAT_SIGNATURE)?;
+        Some(Srat {
+            table: table,
+            mapped_pages: acpi_tables.mapping(),
+            dynamic_entries_starting_offset: loc.slice_offset_and_length?.0,
+            dynamic_entries_total_size: dynamic_part_length,
+        })
+    }
+
+    /// Returns a reference to the `Sdt` header in this SRAT table.
+    pub fn sdt(&self) -> &Sdt {
+        &self.table.header
+    }
+
+    /// Returns an iterator over the SRAT's entries,
+    /// which are variable in both number and size.
+    pub fn iter(&self) -> SratIter<'t> {
+        SratIter {
+            mapped_pages: self.mapped_pages,
+            offset: self.dynamic_entries_starting_offset,
+            end_of_entries: self.dynamic_entries_starting_offset + self.dynamic_entries_total_size,
+        }
+    }
+
+    /// Builds a `NumaTopology` from all of the enabled processor and memory entries in this SRAT.
+    /// The distances between nodes are not included; those come from the SLIT table.
+    pub fn numa_topology(&self) -> NumaTopology {
+        let mut topology = NumaTopology::new();
+        for entry in self.iter() {
+            match entry {
+                SratEntry::LocalApicAffinity(e) if e.flags & AFFINITY_ENABLED != 0 => {
+                    topology.add_processor(e.apic_id as u32, e.proximity_domain());
+                }
+                SratEntry::X2ApicAffinity(e) if e.flags & AFFINITY_ENABLED != 0 => {
+                    topology.add_processor(e.x2apic_id, e.proximity_domain);
+                }
+                SratEntry::MemoryAffinity(e) if e.flags & AFFINITY_ENABLED != 0 => {
+                    topology.add_memory_range(NumaMemoryRange {
+                        node: e.proximity_domain,
+                        start: PhysicalAddress::new_canonical(e.base_address() as usize),
+                        size_in_bytes: e.length() as usize,
+                        hot_pluggable: e.flags & MEMORY_HOT_PLUGGABLE != 0,
+                    });
+                }
+                _ => { }
+            }
+        }
+        topology
+    }
+}
+
+
+/// An Iterator over the dynamic entries of the SRAT.
+#[derive(Clone)]
+pub struct SratIter<'t> {
+    /// The underlying MappedPages that contain all ACPI tables.
+    mapped_pages: &'t MappedPages,
+    /// The offset of the next entry, which should point to a `EntryRecord`
+    /// at the start of each iteration.
+    offset: usize,
+    /// The end bound of all SRAT entries.
+    end_of_entries: usize,
+}
+
+impl<'t> Iterator for SratIter<'t> {
+    type Item = SratEntry<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if (self.offset + ENTRY_RECORD_SIZE) < self.end_of_entries {
+            let (entry_type, entry_size) = {
+                let entry_record: &EntryRecord = self.mapped_pages.as_type(self.offset).ok()?;
+                (entry_record.typ, entry_record.size as usize)
+            };
+            // An entry of size 0 would cause us to loop forever.
+            if entry_size < ENTRY_RECORD_SIZE || (self.offset + entry_size) > self.end_of_entries {
+                return None;
+            }
+            let entry: Option<SratEntry> = match entry_type {
+                ENTRY_TYPE_LOCAL_APIC_AFFINITY if entry_size == size_of::<SratLocalApicAffinity>() => {
+                    self.mapped_pages.as_type(self.offset).ok().map(|ent| SratEntry::LocalApicAffinity(ent))
+                },
+                ENTRY_TYPE_MEMORY_AFFINITY if entry_size == size_of::<SratMemoryAffinity>() => {
+                    self.mapped_pages.as_type(self.offset).ok().map(|ent| SratEntry::MemoryAffinity(ent))
+                },
+                ENTRY_TYPE_X2APIC_AFFINITY if entry_size == size_of::<SratX2ApicAffinity>() => {
+                    self.mapped_pages.as_type(self.offset).ok().map(|ent| SratEntry::X2ApicAffinity(ent))
+                },
+                _ => None,
+            };
+            self.offset += entry_size;
+            entry.or(Some(SratEntry::UnknownOrCorrupt(entry_type)))
+        }
+        else {
+            None
+        }
+    }
+}
+
+
+/// A SRAT entry record, which precedes each actual SRAT entry
+/// and describes its type and size.
+#[derive(Clone, Copy, Debug, FromBytes)]
+#[repr(packed)]
+struct EntryRecord {
+    /// The type identifier of a SRAT entry.
+    typ: u8,
+    /// The size in bytes of a SRAT entry.
+    size: u8,
+}
+const ENTRY_RECORD_SIZE: usize = size_of::<EntryRecord>();
+
+// The following list specifies SRAT entry type IDs.
+const ENTRY_TYPE_LOCAL_APIC_AFFINITY: u8 = 0;
+const ENTRY_TYPE_MEMORY_AFFINITY:     u8 = 1;
+const ENTRY_TYPE_X2APIC_AFFINITY:     u8 = 2;
+
+/// The flag bit that indicates an entry is enabled; disabled entries must be ignored.
+const AFFINITY_ENABLED: u32 = 1 << 0;
+/// The flag bit that indicates a memory range is hot-pluggable.
+const MEMORY_HOT_PLUGGABLE: u32 = 1 << 1;
+
+
+/// The set of possible SRAT Entries.
+#[derive(Copy, Clone, Debug)]
+pub enum SratEntry<'t> {
+    /// A Processor Local APIC Affinity entry.
+    LocalApicAffinity(&'t SratLocalApicAffinity),
+    /// A Memory Affinity entry.
+    MemoryAffinity(&'t SratMemoryAffinity),
+    /// A Processor Local x2APIC Affinity entry.
+    X2ApicAffinity(&'t SratX2ApicAffinity),
+    /// The SRAT table had an entry of an unknown type or mismatched length.
+    /// The entry type ID is included.
+    UnknownOrCorrupt(u8),
+}
+
+/// SRAT Processor Local APIC Affinity
+#[derive(Copy, Clone, Debug, FromBytes)]
+#[repr(packed)]
+pub struct SratLocalApicAffinity {
+    header: EntryRecord,
+    /// Bits [7:0] of the proximity domain
+    proximity_domain_low: u8,
+    /// Local APIC ID
+    pub apic_id: u8,
+    /// Flags. Bit 0 means that this entry is enabled.
+    pub flags: u32,
+    /// Local SAPIC EID
+    pub local_sapic_eid: u8,
+    /// Bits [31:8] of the proximity domain
+    proximity_domain_high: [u8; 3],
+    /// Clock domain
+    pub clock_domain: u32,
+}
+
+impl SratLocalApicAffinity {
+    /// Returns the full proximity domain (NUMA node) of this processor.
+    pub fn proximity_domain(&self) -> NodeId {
+        self.proximity_domain_low as u32
+            | (self.proximity_domain_high[0] as u32) << 8
+            | (self.proximity_domain_high[1] as u32) << 16
+            | (self.proximity_domain_high[2] as u32) << 24
+    }
+}
+
+/// SRAT Memory Affinity
+#[derive(Copy, Clone, Debug, FromBytes)]
+#[repr(packed)]
+pub struct SratMemoryAffinity {
+    header: EntryRecord,
+    /// Proximity domain (NUMA node)
+    pub proximity_domain: u32,
+    _reserved1: u16,
+    base_address_low: u32,
+    base_address_high: u32,
+    length_low: u32,
+    length_high: u32,
+    _reserved2: u32,
+    /// Flags. Bit 0 means enabled, bit 1 means hot-pluggable, bit 2 means non-volatile.
+    pub flags: u32,
+    _reserved3: u64,
+}
+
+impl SratMemoryAffinity {
+    /// Returns the starting physical address of this memory range.
+    pub fn base_address(&self) -> u64 {
+        (self.base_address_high as u64) << 32 | self.base_address_low as u64
+    }
+
+    /// Returns the length in bytes of this memory range.
+    pub fn length(&self) -> u64 {
+        (self.length_high as u64) << 32 | self.length_low as u64
+    }
+}
+
+/// SRAT Processor Local x2APIC Affinity
+#[derive(Copy, Clone, Debug, FromBytes)]
+#[repr(packed)]
+pub struct SratX2ApicAffinity {
+    header: EntryRecord,
+    _reserved1: u16,
+    /// Proximity domain (NUMA node)
+    pub proximity_domain: u32,
+    /// x2APIC ID
+    pub x2apic_id: u32,
+    /// Flags. Bit 0 means that this entry is enabled.
+    pub flags: u32,
+    /// Clock domain
+    pub clock_domain: u32,
+    _reserved2: u32,
+}
@@ -111,6 +111,17 @@ impl Stack {
         self.pages.start_address()
     }
 
+    /// Returns the unmapped guard page(s) beneath the bottom of this stack.
+    pub fn guard_page(&self) -> &AllocatedPages {
+        &self.guard_page
+    }
+
+    /// Returns `true` if the given `VirtualAddress` lies within this stack's guard page,
+    /// which indicates that an access to it was caused by overflowing this stack.
+    pub fn is_in_guard_page(&self, vaddr: VirtualAddress) -> bool {
+        self.guard_page.contains_virt_addr(vaddr)
+    }
+
     /// Creates a stack from its constituent parts: 
     /// a guard page and a series of mapped pages. 
     /// 
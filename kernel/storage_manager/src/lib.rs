@@ -3,14 +3,18 @@
 
 #![no_std]
 
-extern crate alloc;
+#[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
 #[macro_use] extern crate lazy_static;
 extern crate spin;
 extern crate owning_ref;
 extern crate pci;
 extern crate ata;
+extern crate ahci;
+extern crate nvme;
+extern crate virtio_blk;
 extern crate storage_device;
+extern crate block_io;
 
 use alloc::{
     vec::Vec,
@@ -19,6 +23,7 @@ use alloc::{
 use spin::Mutex;
 use pci::PciDevice;
 use storage_device::StorageControllerRef;
+use block_io::BlockQueue;
 
 pub use storage_device::*;
 
@@ -26,6 +31,10 @@ pub use storage_device::*;
 lazy_static! {
     /// A list of all of the available and initialized storage controllers that exist on this system.
     pub static ref STORAGE_CONTROLLERS: Mutex<Vec<StorageControllerRef>> = Mutex::new(Vec::new());
+
+    /// The request queues of all storage devices attached to the above storage controllers,
+    /// through which all block I/O to those devices should be issued.
+    pub static ref BLOCK_QUEUES: Mutex<Vec<BlockQueue>> = Mutex::new(Vec::new());
 }
 
 
@@ -36,15 +45,54 @@ lazy_static! {
 /// `Ok(false)` if the given `PciDevice` isn't a supported storage device,
 /// and an error if it fails to initialize a supported storage device.
 pub fn init_device(pci_device: &PciDevice) -> Result<bool, &'static str> {
-    // We currently only support IDE controllers for ATA drives (aka PATA).
+    // IDE controllers for ATA drives (aka PATA).
     if pci_device.class == 0x01 && pci_device.subclass == 0x01 {
         info!("IDE controller PCI device found at: {:?}", pci_device.location);
         let ide_controller = ata::IdeController::new(pci_device)?;
-        STORAGE_CONTROLLERS.lock().push(Arc::new(Mutex::new(ide_controller)));
+        add_storage_controller(Arc::new(Mutex::new(ide_controller)))?;
+        return Ok(true);
+    }
+
+    // AHCI controllers for SATA drives.
+    if pci_device.class == ahci::AHCI_PCI_CLASS && pci_device.subclass == ahci::AHCI_PCI_SUBCLASS && pci_device.prog_if == ahci::AHCI_PCI_PROG_IF {
+        info!("AHCI controller PCI device found at: {:?}", pci_device.location);
+        let ahci_controller = ahci::AhciController::new(pci_device)?;
+        add_storage_controller(Arc::new(Mutex::new(ahci_controller)))?;
+        return Ok(true);
+    }
+
+    // NVMe controllers, which may have multiple namespaces (disks).
+    if pci_device.class == nvme::NVME_PCI_CLASS && pci_device.subclass == nvme::NVME_PCI_SUBCLASS && pci_device.prog_if == nvme::NVME_PCI_PROG_IF {
+        info!("NVMe controller PCI device found at: {:?}", pci_device.location);
+        let nvme_controller = nvme::NvmeController::new(pci_device)?;
+        add_storage_controller(Arc::new(Mutex::new(nvme_controller)))?;
+        return Ok(true);
+    }
+
+    // virtio block devices, i.e., paravirtualized disks.
+    if virtio_blk::is_virtio_blk_device(pci_device) {
+        info!("virtio block PCI device found at: {:?}", pci_device.location);
+        let virtio_blk_controller = virtio_blk::VirtioBlkController::new(pci_device)?;
+        add_storage_controller(Arc::new(Mutex::new(virtio_blk_controller)))?;
         return Ok(true);
     }
 
     // Here: in the future, handle other supported storage devices
 
     Ok(false)
-}
\ No newline at end of file
+}
+
+
+/// Adds the given storage controller to the list of storage controllers,
+/// and creates a request queue for each of its storage devices.
+fn add_storage_controller(controller: StorageControllerRef) -> Result<(), &'static str> {
+    {
+        let mut block_queues = BLOCK_QUEUES.lock();
+        for device in controller.lock().devices() {
+            let name = format!("disk{}", block_queues.len());
+            block_queues.push(BlockQueue::new(device, name)?);
+        }
+    }
+    STORAGE_CONTROLLERS.lock().push(controller);
+    Ok(())
+}
@@ -16,6 +16,10 @@
 //!             let val: Option<&isize> = exit_value.downcast_ref::<isize>();
 //!             warn!("task returned exit value: {:?}", val);
 //!         }
+//!         ExitValue::Panicked(panic_info) => {
+//!             // here: the task panicked and was unwound.
+//!             warn!("task panicked: {}", panic_info);
+//!         }
 //!         ExitValue::Killed(kill_reason) => {
 //!             // here: the task exited prematurely, e.g., it was killed for some reason.
 //!             warn!("task was killed, reason: {:?}", kill_reason);
@@ -38,22 +42,26 @@ extern crate tss;
 extern crate mod_mgmt;
 extern crate context_switch;
 extern crate environment;
+extern crate fs_node;
 extern crate root;
 extern crate x86_64;
 extern crate spin;
 extern crate kernel_config;
+extern crate tsc;
 
 
 use core::fmt;
 use core::sync::atomic::{Ordering, AtomicUsize, AtomicBool};
 use core::any::Any;
 use core::panic::PanicInfo;
-use core::ops::Deref;
+use core::time::Duration;
+use core::ops::{Deref, Range};
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
     string::String,
     sync::Arc,
+    vec::Vec,
 };
 use irq_safety::{MutexIrqSafe, MutexIrqSafeGuardRef, MutexIrqSafeGuardRefMut, interrupts_enabled};
 use memory::{MmiRef, VirtualAddress, get_frame_allocator_ref};
@@ -65,6 +73,7 @@ use mod_mgmt::{
     AppCrateRef,
 };
 use environment::Environment;
+use fs_node::FsNode;
 use spin::Mutex;
 use x86_64::registers::msr::{rdmsr, wrmsr, IA32_FS_BASE};
 
@@ -73,6 +82,10 @@ use x86_64::registers::msr::{rdmsr, wrmsr, IA32_FS_BASE};
 /// when a given Task panics or otherwise fails, e.g., a machine exception occurs.
 pub type KillHandler = Box<dyn Fn(&KillReason) + Send>;
 
+/// The function signature of the callback that will be invoked
+/// when another task requests that a given Task be killed, e.g., upon `Ctrl + C`.
+pub type KillRequestHandler = Box<dyn Fn() + Send>;
+
 /// Just like `core::panic::PanicInfo`, but with owned String types instead of &str references.
 #[derive(Debug, Clone)]
 pub struct PanicInfoOwned {
@@ -113,6 +126,36 @@ pub fn get_task(task_id: usize) -> Option<TaskRef> {
     TASKLIST.lock().get(&task_id).cloned()
 }
 
+/// Returns the IDs of all non-exited tasks whose kernel stack contains a value within the given `range`
+/// of virtual addresses, e.g., a return address into the `.text` sections of a crate. 
+/// 
+/// This is conservative: any stack word that happens to fall within the `range` is counted.
+/// Because a running task's stack pointer isn't saved, its entire stack is scanned,
+/// so stale values beneath its current stack pointer may be counted too.
+pub fn tasks_with_frames_in(range: &Range<VirtualAddress>) -> Vec<usize> {
+    // Don't hold the task list lock while locking each task.
+    let all_tasks: Vec<TaskRef> = TASKLIST.lock().values().cloned().collect();
+    all_tasks.iter().filter_map(|taskref| {
+        let task = taskref.lock();
+        if task.has_exited() {
+            return None;
+        }
+        let bottom = task.kstack.bottom().value();
+        let top = task.kstack.top_unusable().value();
+        let start = if task.is_running() || task.saved_sp < bottom || task.saved_sp >= top {
+            bottom
+        } else {
+            task.saved_sp
+        };
+        let words: &[usize] = task.kstack.as_slice(start - bottom, (top - start) / core::mem::size_of::<usize>()).ok()?;
+        if words.iter().any(|&word| word >= range.start.value() && word < range.end.value()) {
+            Some(task.id)
+        } else {
+            None
+        }
+    }).collect()
+}
+
 
 /// Sets the kill handler function for the current `Task`
 pub fn set_my_kill_handler(handler: KillHandler) -> Result<(), &'static str> {
@@ -121,6 +164,14 @@ pub fn set_my_kill_handler(handler: KillHandler) -> Result<(), &'static str> {
         .map(|taskref| taskref.set_kill_handler(handler))
 }
 
+/// Sets the kill request handler function for the current `Task`.
+/// See [`TaskRef::request_kill()`](struct.TaskRef.html#method.request_kill).
+pub fn set_my_kill_request_handler(handler: KillRequestHandler) -> Result<(), &'static str> {
+    get_my_current_task()
+        .ok_or("couldn't get_my_current_task")
+        .map(|taskref| taskref.set_kill_request_handler(handler))
+}
+
 
 
 /// The list of possible reasons that a given `Task` was killed prematurely.
@@ -153,11 +204,44 @@ pub enum ExitValue {
     /// The caller of this type should know what type this Task returned,
     /// and should therefore be able to downcast it appropriately.
     Completed(Box<dyn Any + Send>),
-    /// The Task did NOT run to completion, and was instead killed.
+    /// The Task did NOT run to completion because it panicked,
+    /// and was killed after being unwound. The panic's details are enclosed.
+    Panicked(PanicInfoOwned),
+    /// The Task did NOT run to completion, and was instead killed for a reason other than a panic,
+    /// e.g., it was requested to be killed or it caused a machine exception.
     /// The reason for it being killed is enclosed. 
     Killed(KillReason),
 }
 
+impl ExitValue {
+    /// Returns `true` if the Task ran to completion.
+    pub fn is_completed(&self) -> bool {
+        match self {
+            ExitValue::Completed(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the return value of the Task, if it ran to completion.
+    pub fn completed_value(&self) -> Option<&(dyn Any + Send)> {
+        match self {
+            ExitValue::Completed(val) => Some(&**val),
+            _ => None,
+        }
+    }
+}
+
+impl From<KillReason> for ExitValue {
+    /// Converts the reason a Task was killed into its exit value,
+    /// which separates panics from other kinds of kills.
+    fn from(reason: KillReason) -> ExitValue {
+        match reason {
+            KillReason::Panic(panic_info) => ExitValue::Panicked(panic_info),
+            other => ExitValue::Killed(other),
+        }
+    }
+}
+
 
 /// The set of possible runstates that a task can be in, e.g.,
 /// runnable, blocked, exited, etc. 
@@ -205,12 +289,235 @@ pub struct RestartInfo {
     pub argument: Box<dyn Any + Send>,
     /// Stores the function of the task for restartable tasks
     pub func: Box<dyn Any + Send>,
+    /// When the task should be restarted after it exits.
+    pub policy: RestartPolicy,
+    /// How long to wait before restarting the task, if at all.
+    pub backoff: Option<RestartBackoff>,
+    /// The number of times this task has already been restarted.
+    pub restart_count: usize,
+    /// The ID of the original instance of this task, which identifies it across restarts.
+    pub supervision_id: usize,
+}
+
+/// The conditions under which a restartable task is restarted after it exits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// The task is never restarted.
+    Never,
+    /// The task is restarted only if it failed, i.e., it panicked or was killed.
+    OnFailure,
+    /// The task is restarted whenever it exits, even if it ran to completion.
+    Always,
+}
+
+impl RestartPolicy {
+    /// Returns `true` if a task with this policy should be restarted after exiting with the given `exit_value`.
+    pub fn should_restart(&self, exit_value: &ExitValue) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !exit_value.is_completed(),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// An exponential backoff between successive restarts of a task,
+/// which prevents a task that fails repeatedly from monopolizing the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartBackoff {
+    /// The delay before the first restart, which doubles with each subsequent restart.
+    pub initial: Duration,
+    /// The maximum delay between restarts.
+    pub max: Duration,
+}
+
+impl RestartBackoff {
+    /// Returns the delay before restarting a task that has already been restarted `restart_count` times.
+    pub fn delay(&self, restart_count: usize) -> Duration {
+        let shift = core::cmp::min(restart_count, 31) as u32;
+        let delay = self.initial.checked_mul(1 << shift).unwrap_or(self.max);
+        core::cmp::min(delay, self.max)
+    }
 }
 
 /// The signature of a Task's failure cleanup function.
 pub type FailureCleanupFunction = fn(TaskRef, KillReason) -> !;
 
 
+/// The highest scheduling priority that a `Task` can have.
+pub const MAX_PRIORITY: u8 = 40;
+/// The scheduling priority that each new `Task` has by default.
+pub const DEFAULT_PRIORITY: u8 = 20;
+
+
+/// Runtime accounting statistics for a `Task`, which are updated by the scheduler at every task switch.
+/// 
+/// All times are measured in TSC ticks and converted to a `Duration` upon request.
+/// To obtain an up-to-date snapshot that includes the current time slice of a running task,
+/// use [`TaskRef::stats()`](struct.TaskRef.html#method.stats).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskStats {
+    /// The total number of TSC ticks this task has spent running on any core.
+    cpu_ticks: u64,
+    /// The total number of TSC ticks this task has spent runnable but waiting to be scheduled in.
+    wait_ticks: u64,
+    /// The number of times this task has been switched in.
+    context_switches: u64,
+    /// The core that this task most recently ran on, if it has ever run.
+    last_ran_on_cpu: Option<u8>,
+    /// The TSC value when this task was last switched in.
+    switched_in_at: u64,
+    /// The TSC value when this task last became runnable without yet being switched in.
+    runnable_since: Option<u64>,
+}
+
+impl TaskStats {
+    /// Returns the total time this task has spent running.
+    pub fn cpu_time(&self) -> Duration {
+        ticks_to_duration(self.cpu_ticks)
+    }
+
+    /// Returns the total time this task has spent waiting on a runqueue while runnable.
+    pub fn wait_time(&self) -> Duration {
+        ticks_to_duration(self.wait_ticks)
+    }
+
+    /// Returns the total time this task has spent running, in TSC ticks.
+    pub fn cpu_ticks(&self) -> u64 {
+        self.cpu_ticks
+    }
+
+    /// Returns the number of times this task has been switched in.
+    pub fn context_switches(&self) -> u64 {
+        self.context_switches
+    }
+
+    /// Returns the core that this task most recently ran on, or `None` if it has never run.
+    pub fn last_ran_on_cpu(&self) -> Option<u8> {
+        self.last_ran_on_cpu
+    }
+
+    /// Records that this task has become runnable, e.g., it was unblocked, 
+    /// such that the time until it's next switched in counts as wait time.
+    pub fn mark_runnable(&mut self) {
+        if self.runnable_since.is_none() {
+            self.runnable_since = Some(tsc::tsc_ticks().into());
+        }
+    }
+
+    fn switched_in(&mut self, now: u64, apic_id: u8) {
+        if let Some(since) = self.runnable_since.take() {
+            self.wait_ticks += now.saturating_sub(since);
+        }
+        self.switched_in_at = now;
+        self.context_switches += 1;
+        self.last_ran_on_cpu = Some(apic_id);
+    }
+
+    fn switched_out(&mut self, now: u64, still_runnable: bool) {
+        self.cpu_ticks += now.saturating_sub(self.switched_in_at);
+        self.runnable_since = if still_runnable { Some(now) } else { None };
+    }
+}
+
+/// Converts the given number of TSC ticks into a `Duration`,
+/// which is zero if the TSC frequency is unknown.
+fn ticks_to_duration(ticks: u64) -> Duration {
+    match tsc::get_tsc_frequency() {
+        Ok(freq) if freq != 0 => Duration::from_nanos(((ticks as u128 * 1_000_000_000) / freq as u128) as u64),
+        _ => Duration::from_secs(0),
+    }
+}
+
+
+/// The set of cores that a `Task` is allowed to run on, in which each core is identified by its APIC ID.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AffinityMask([u64; 4]);
+
+impl AffinityMask {
+    /// Returns a mask that allows a task to run on every core, which is the default.
+    pub const fn all() -> AffinityMask {
+        AffinityMask([u64::max_value(); 4])
+    }
+
+    /// Returns a mask that doesn't allow a task to run on any core.
+    pub const fn empty() -> AffinityMask {
+        AffinityMask([0; 4])
+    }
+
+    /// Returns a mask that only allows a task to run on the given `core`.
+    pub fn single(core: u8) -> AffinityMask {
+        let mut mask = AffinityMask::empty();
+        mask.insert(core);
+        mask
+    }
+
+    /// Returns a mask that allows a task to run on each of the given `cores`.
+    pub fn from_cores(cores: &[u8]) -> AffinityMask {
+        let mut mask = AffinityMask::empty();
+        for &core in cores {
+            mask.insert(core);
+        }
+        mask
+    }
+
+    /// Allows a task to run on the given `core`.
+    pub fn insert(&mut self, core: u8) {
+        self.0[core as usize / 64] |= 1 << (core % 64);
+    }
+
+    /// Disallows a task from running on the given `core`.
+    pub fn remove(&mut self, core: u8) {
+        self.0[core as usize / 64] &= !(1 << (core % 64));
+    }
+
+    /// Returns `true` if this mask allows a task to run on the given `core`.
+    pub fn contains(&self, core: u8) -> bool {
+        self.0[core as usize / 64] & (1 << (core % 64)) != 0
+    }
+
+    /// Returns `true` if this mask doesn't allow a task to run on any core.
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&bits| bits == 0)
+    }
+
+    /// Returns `true` if this mask allows a task to run on every core.
+    pub fn is_all(&self) -> bool {
+        *self == AffinityMask::all()
+    }
+
+    /// If this mask only allows a task to run on a single core, returns that core.
+    pub fn single_core(&self) -> Option<u8> {
+        let mut cores = self.iter();
+        match (cores.next(), cores.next()) {
+            (Some(core), None) => Some(core),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over all of the cores that this mask allows a task to run on, in ascending order.
+    pub fn iter<'m>(&'m self) -> impl Iterator<Item = u8> + 'm {
+        (0 ..= u8::max_value()).filter(move |&core| self.contains(core))
+    }
+}
+
+impl Default for AffinityMask {
+    fn default() -> AffinityMask {
+        AffinityMask::all()
+    }
+}
+
+impl fmt::Debug for AffinityMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_all() {
+            write!(f, "all")
+        } else {
+            f.debug_set().entries(self.iter()).finish()
+        }
+    }
+}
+
+
 /// A structure that contains contextual information for a thread of execution. 
 pub struct Task {
     /// the unique id of this Task.
@@ -238,9 +545,10 @@ pub struct Task {
     pub mmi: MmiRef, 
     /// The kernel stack, which all `Task`s must have in order to execute.
     pub kstack: Stack,
-    /// Whether or not this task is pinned to a certain core.
+    /// The set of cores that this task is allowed to run on; by default, it can run on any core.
     /// The idle tasks (like idle_task) are always pinned to their respective cores.
-    pub pinned_core: Option<u8>,
+    /// See [`pinned_core()`](#method.pinned_core).
+    pub affinity: AffinityMask,
     /// Whether this Task is an idle task, the task that runs by default when no other task is running.
     /// There exists one idle task per core, so this is `false` for most tasks.
     pub is_an_idle_task: bool,
@@ -254,7 +562,14 @@ pub struct Task {
     /// It will be invoked before the task is cleaned up via stack unwinding.
     /// This is similar to Rust's built-in panic hook, but is also called upon a machine exception, not just a panic.
     pub kill_handler: Option<KillHandler>,
-    /// The environment of the task, Wrapped in an Arc & Mutex because it is shared among child and parent tasks
+    /// The function that will be called when another task requests that this `Task` be killed,
+    /// e.g., when the user presses `Ctrl + C` in the shell that started this `Task`.
+    /// If present, this `Task` is trusted to clean up and exit on its own rather than being killed immediately.
+    pub kill_request_handler: Option<KillRequestHandler>,
+    /// Whether another task has already requested that this `Task` be killed.
+    pub kill_requested: bool,
+    /// The environment of the task, wrapped in an Arc & Mutex because it can be shared among multiple tasks.
+    /// By default, each new task receives its own copy of its parent's environment.
     pub env: Arc<Mutex<Environment>>,
     /// The function that should be run as a last-ditch attempt to recover from this task's failure,
     /// e.g., this can be called when unwinding itself fails. 
@@ -264,6 +579,14 @@ pub struct Task {
     /// Stores the restartable information of the task. 
     /// `Some(RestartInfo)` indicates that the task is restartable.
     pub restart_info: Option<RestartInfo>,
+    /// The scheduling priority of this task, from `0` (lowest) to [`MAX_PRIORITY`](constant.MAX_PRIORITY.html).
+    /// See [`priority()`](#method.priority) for the effective priority used by the scheduler.
+    pub base_priority: u8,
+    /// A priority temporarily inherited from a higher-priority task that is blocked
+    /// waiting for a lock held by this task, i.e., priority inheritance.
+    pub inherited_priority: Option<u8>,
+    /// CPU time and scheduling statistics for this task, updated upon every task switch.
+    pub stats: TaskStats,
     
     #[cfg(simd_personality)]
     /// Whether this Task is SIMD enabled and what level of SIMD extensions it uses.
@@ -273,14 +596,15 @@ pub struct Task {
 impl fmt::Debug for Task {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{{Task \"{}\" ({}), running_on_cpu: {:?}, runstate: {:?}, pinned: {:?}}}", 
-               self.name, self.id, self.running_on_cpu, self.runstate, self.pinned_core)
+               self.name, self.id, self.running_on_cpu, self.runstate, self.pinned_core())
     }
 }
 
 impl Task {
     /// Creates a new Task structure and initializes it to be non-Runnable.
     /// By default, the new `Task` will inherit some of the same states from the currently-running `Task`:
-    /// its `Environment`, `MemoryManagementInfo`, `CrateNamespace`, and `app_crate` reference.
+    /// its `MemoryManagementInfo`, `CrateNamespace`, and `app_crate` reference,
+    /// as well as a copy of its `Environment` (which is not shared with the current `Task`).
     /// If needed, those states can be changed by setting them for the returned `Task`.
     /// 
     /// # Arguments
@@ -296,10 +620,12 @@ impl Task {
         failure_cleanup_function: FailureCleanupFunction
     ) -> Result<Task, &'static str> {
         let curr_task = get_my_current_task().ok_or("Task::new(): couldn't get current task (not yet initialized)")?;
-        let (mmi, namespace, env, app_crate) = {
+        let (mmi, namespace, parent_env, app_crate) = {
             let t = curr_task.lock();
             (Arc::clone(&t.mmi), Arc::clone(&t.namespace), Arc::clone(&t.env), t.app_crate.clone())
         };
+        // The new task gets its own copy of the current task's environment.
+        let env = Arc::new(Mutex::new(parent_env.lock().clone()));
 
         let kstack = kstack
             .or_else(|| get_frame_allocator_ref().and_then(|fa_ref| 
@@ -341,14 +667,19 @@ impl Task {
             name: format!("task_{}", task_id),
             kstack,
             mmi,
-            pinned_core: None,
+            affinity: AffinityMask::all(),
             is_an_idle_task: false,
             app_crate,
             namespace,
             kill_handler: None,
+            kill_request_handler: None,
+            kill_requested: false,
             env,
             failure_cleanup_function,
             restart_info: None,
+            base_priority: DEFAULT_PRIORITY,
+            inherited_priority: None,
+            stats: TaskStats::default(),
             
             #[cfg(simd_personality)]
             simd: SimdExt::None,
@@ -359,6 +690,20 @@ impl Task {
         self.env = new_env;
     }
 
+    /// Returns the effective scheduling priority of this task, 
+    /// which is the higher of its base priority and its inherited priority.
+    pub fn priority(&self) -> u8 {
+        match self.inherited_priority {
+            Some(inherited) if inherited > self.base_priority => inherited,
+            _ => self.base_priority,
+        }
+    }
+
+    /// Returns the core that this task is pinned to, if its affinity only allows it to run on a single core.
+    pub fn pinned_core(&self) -> Option<u8> {
+        self.affinity.single_core()
+    }
+
     /// returns true if this Task is currently running on any cpu.
     pub fn is_running(&self) -> bool {
         self.running_on_cpu.is_some()
@@ -411,6 +756,12 @@ impl Task {
         self.kill_handler.take()
     }
 
+    /// Registers a function or closure that will be called if another task
+    /// requests that this `Task` be killed, e.g., upon `Ctrl + C`.
+    pub fn set_kill_request_handler(&mut self, callback: KillRequestHandler) {
+        self.kill_request_handler = Some(callback);
+    }
+
 
     /// Returns a reference to the exit value of this `Task`, 
     /// if its runstate is `RunState::Exited`. 
@@ -511,6 +862,12 @@ impl Task {
         self.running_on_cpu = None; // no longer running
         next.running_on_cpu = Some(apic_id); // now running on this core
 
+        // update runtime accounting
+        let now = tsc::tsc_ticks().into();
+        let still_runnable = self.is_runnable();
+        self.stats.switched_out(now, still_runnable);
+        next.stats.switched_in(now, apic_id);
+
         // Switch page tables. 
         // Since there is only a single address space (as userspace support is currently disabled),
         // we do not need to do this at all.
@@ -636,6 +993,7 @@ impl Drop for Task {
             if let Some(_kill_handler) = self.take_kill_handler() {
                 warn!("While dropping task {:?}, its kill handler callback was still present. Removing it now.", self);
             }
+            let _kill_request_handler = self.kill_request_handler.take();
             // Scoping rules ensure the kill handler is dropped now, before this Task's app_crate could possibly be dropped.
         }
     }
@@ -704,14 +1062,7 @@ impl TaskRef {
     /// * You cannot call `join()` with interrupts disabled, because it will result in permanent deadlock
     ///   (well, this is only true if the requested `task` is running on the same cpu...  but good enough for now).
     pub fn join(&self) -> Result<(), &'static str> {
-        let curr_task = get_my_current_task().ok_or("join(): failed to check what current task is")?;
-        if Arc::ptr_eq(&self.0, &curr_task.0) {
-            return Err("BUG: cannot call join() on yourself (the current task).");
-        }
-
-        if !interrupts_enabled() {
-            return Err("BUG: cannot call join() with interrupts disabled; it will cause deadlock.")
-        }
+        self.check_joinable()?;
         
         // First, wait for this Task to be marked as Exited (no longer runnable).
         loop {
@@ -729,6 +1080,52 @@ impl TaskRef {
         }
     }
 
+    /// Similar to [`join()`](#method.join), but gives up waiting once the given `timeout` elapses.
+    /// 
+    /// # Return
+    /// * Returns `Ok(true)` if the given `task` exited before the `timeout` elapsed,
+    /// * Returns `Ok(false)` if the `timeout` elapsed first, 
+    /// * Returns `Err()` under the same conditions as `join()`, or if the TSC frequency isn't known, which is needed to measure time.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<bool, &'static str> {
+        self.check_joinable()?;
+        match tsc::get_tsc_frequency() {
+            Ok(freq) if freq != 0 => { }
+            _ => return Err("join_timeout(): couldn't get the TSC frequency to measure the timeout"),
+        }
+        let start: u64 = tsc::tsc_ticks().into();
+
+        loop {
+            if self.try_join() {
+                return Ok(true);
+            }
+            let now: u64 = tsc::tsc_ticks().into();
+            if ticks_to_duration(now.saturating_sub(start)) >= timeout {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Checks whether the given `task` has finished executing without blocking, 
+    /// i.e., whether its runstate is `RunState::Exited` and it is no longer running on any CPU core.
+    /// 
+    /// If this returns `true`, its exit value can be obtained via [`take_exit_value()`](#method.take_exit_value).
+    pub fn try_join(&self) -> bool {
+        self.0.deref().1.load(Ordering::SeqCst) && !self.0.deref().0.lock().is_running()
+    }
+
+    /// Returns an error if the current task cannot wait for this task to exit.
+    fn check_joinable(&self) -> Result<(), &'static str> {
+        let curr_task = get_my_current_task().ok_or("join(): failed to check what current task is")?;
+        if Arc::ptr_eq(&self.0, &curr_task.0) {
+            return Err("BUG: cannot call join() on yourself (the current task).");
+        }
+
+        if !interrupts_enabled() {
+            return Err("BUG: cannot call join() with interrupts disabled; it will cause deadlock.")
+        }
+        Ok(())
+    }
+
 
     /// The internal routine that actually exits or kills a Task.
     /// It also performs select cleanup routines, e.g., removing the task from the task list.
@@ -807,7 +1204,7 @@ impl TaskRef {
     pub fn mark_as_killed(&self, reason: KillReason) -> Result<(), &'static str> {
         let curr_task = get_my_current_task().ok_or("mark_as_exited(): failed to check what the current task is")?;
         if curr_task == self {
-            self.internal_exit(ExitValue::Killed(reason))
+            self.internal_exit(ExitValue::from(reason))
         } else {
             Err("`mark_as_exited()` can only be invoked on the current task, not on another task.")
         }
@@ -835,10 +1232,46 @@ impl TaskRef {
     pub fn kill(&self, reason: KillReason) -> Result<(), &'static str> {
         // TODO FIXME: cause a panic in this Task such that it will start the unwinding process
         // instead of immediately causing it to exit
-        self.internal_exit(ExitValue::Killed(reason))
+        self.internal_exit(ExitValue::from(reason))
     }
 
 
+    /// Asks this `Task` to terminate itself cooperatively, e.g., because the user pressed `Ctrl + C`.
+    ///
+    /// If this `Task` has registered a [`KillRequestHandler`](type.KillRequestHandler.html),
+    /// that handler is taken and invoked (in the context of the calling task), 
+    /// giving this `Task` a chance to clean up (e.g., flush files) and then exit on its own.
+    /// The handler should be brief, e.g., it can set a flag that this `Task` periodically checks.
+    ///
+    /// # Return
+    /// * Returns `true` if this `Task` handled the request and is expected to exit by itself.
+    /// * Returns `false` if the caller should [`kill()`](#method.kill) this `Task` instead, namely
+    ///   if it has no handler, has already exited, or was already asked to terminate before.
+    ///   Thus, a repeated request (e.g., pressing `Ctrl + C` twice) falls back to a forced kill.
+    pub fn request_kill(&self) -> bool {
+        let handler = {
+            let mut task = self.0.deref().0.lock();
+            if task.has_exited() || core::mem::replace(&mut task.kill_requested, true) {
+                return false;
+            }
+            task.kill_request_handler.take()
+        };
+        // Invoke the handler without holding this Task's lock.
+        match handler {
+            Some(handler) => {
+                handler();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` if another task has requested that this `Task` be killed,
+    /// see [`request_kill()`](#method.request_kill).
+    pub fn is_kill_requested(&self) -> bool {
+        self.0.deref().0.lock().kill_requested
+    }
+
     /// Obtains the lock on the underlying `Task` in a read-only, blocking fashion.
     /// This is okay because we want to allow any other part of the OS to read 
     /// the details of the `Task` struct.
@@ -853,7 +1286,22 @@ impl TaskRef {
 
     /// Unblocks this `Task` by setting its `RunState` to runnable.
     pub fn unblock(&self) {
-        self.0.deref().0.lock().runstate = RunState::Runnable;
+        let mut task = self.0.deref().0.lock();
+        task.runstate = RunState::Runnable;
+        task.stats.mark_runnable();
+    }
+
+    /// Returns a snapshot of this `Task`'s CPU time and scheduling statistics.
+    /// 
+    /// If this `Task` is currently running, its ongoing time slice is included in its CPU time.
+    pub fn stats(&self) -> TaskStats {
+        let task = self.0.deref().0.lock();
+        let mut stats = task.stats;
+        if task.is_running() {
+            let now = tsc::tsc_ticks().into();
+            stats.cpu_ticks += now.saturating_sub(stats.switched_in_at);
+        }
+        stats
     }
 
     /// Registers a function or closure that will be called if this `Task` panics
@@ -874,6 +1322,14 @@ impl TaskRef {
         self.0.deref().0.lock().take_kill_handler()
     }
 
+    /// Registers a function or closure that will be called if another task
+    /// requests that this `Task` be killed, see [`request_kill()`](#method.request_kill).
+    /// # Locking / Deadlock
+    /// Obtains a write lock on the enclosed `Task` in order to mutate its state.
+    pub fn set_kill_request_handler(&self, callback: KillRequestHandler) {
+        self.0.deref().0.lock().set_kill_request_handler(callback)
+    }
+
     /// Takes ownership of this `Task`'s exit value and returns it,
     /// if and only if this `Task` was in the `Exited` runstate.
     /// After invoking this, the `Task`'s runstate will be `Reaped`.
@@ -897,7 +1353,24 @@ impl TaskRef {
     pub fn get_namespace(&self) -> Arc<CrateNamespace> {
         Arc::clone(&self.0.deref().0.lock().namespace)
     }
-    
+
+    /// Returns the absolute path of the object file that this task's application crate was loaded from,
+    /// or `None` if this is not an application `Task`.
+    ///
+    /// If the object file is no longer linked into any directory (e.g., its namespace directory was removed),
+    /// the application crate's name is returned instead.
+    pub fn get_app_crate_path(&self) -> Option<String> {
+        // Clone the app crate reference so that we don't hold this Task's lock while locking the crate.
+        let app_crate = self.0.deref().0.lock().app_crate.clone()?;
+        let krate = app_crate.lock_as_ref();
+        let object_file = krate.object_file.lock();
+        if object_file.get_parent_dir().is_some() {
+            Some(object_file.get_absolute_path())
+        } else {
+            Some(krate.crate_name.clone())
+        }
+    }
+
     /// Obtains the lock on the underlying `Task` in a writable, blocking fashion.
     #[deprecated(note = "This method exposes inner Task details for debugging purposes. Do not use it.")]
     #[doc(hidden)]
@@ -908,6 +1381,60 @@ impl TaskRef {
     pub fn is_restartable(&self) -> bool {
         self.0.deref().0.lock().restart_info.is_some()
     }
+
+    /// Returns the effective scheduling priority of this `Task`, 
+    /// including any priority it has inherited. See [`Task::priority()`](struct.Task.html#method.priority).
+    pub fn priority(&self) -> u8 {
+        self.0.deref().0.lock().priority()
+    }
+
+    /// Returns the base scheduling priority of this `Task`, ignoring any priority it has inherited.
+    pub fn base_priority(&self) -> u8 {
+        self.0.deref().0.lock().base_priority
+    }
+
+    /// Sets the base scheduling priority of this `Task`, 
+    /// which is clamped to [`MAX_PRIORITY`](constant.MAX_PRIORITY.html).
+    pub fn set_priority(&self, priority: u8) {
+        self.0.deref().0.lock().base_priority = core::cmp::min(priority, MAX_PRIORITY);
+    }
+
+    /// Raises this `Task`'s inherited priority to the given `priority`, if it is higher than the current inherited priority.
+    /// 
+    /// This is used for priority inheritance: when a higher-priority task blocks on a lock held by this `Task`,
+    /// this `Task` runs with that higher priority until it releases the lock,
+    /// see [`clear_inherited_priority()`](#method.clear_inherited_priority).
+    pub fn inherit_priority(&self, priority: u8) {
+        let mut task = self.0.deref().0.lock();
+        let priority = core::cmp::min(priority, MAX_PRIORITY);
+        if task.inherited_priority.map_or(true, |p| priority > p) {
+            task.inherited_priority = Some(priority);
+        }
+    }
+
+    /// Removes any priority that this `Task` has inherited, reverting it to its base priority.
+    pub fn clear_inherited_priority(&self) {
+        self.0.deref().0.lock().inherited_priority = None;
+    }
+
+    /// Returns the set of cores that this `Task` is allowed to run on.
+    pub fn affinity(&self) -> AffinityMask {
+        self.0.deref().0.lock().affinity
+    }
+
+    /// Sets the set of cores that this `Task` is allowed to run on.
+    /// 
+    /// If this `Task` is currently on the runqueue of a core that is no longer allowed,
+    /// it will be migrated to an allowed core the next time that core schedules.
+    /// If it is currently running on a core that is no longer allowed, it will continue running there
+    /// until it is next switched out.
+    pub fn set_affinity(&self, affinity: AffinityMask) -> Result<(), &'static str> {
+        if affinity.is_empty() {
+            return Err("TaskRef::set_affinity(): a task's affinity must allow it to run on at least one core");
+        }
+        self.0.deref().0.lock().affinity = affinity;
+        Ok(())
+    }
 }
 
 impl PartialEq for TaskRef {
@@ -939,7 +1466,7 @@ pub fn bootstrap_task(
     bootstrap_task.name = format!("bootstrap_task_core_{}", apic_id);
     bootstrap_task.runstate = RunState::Runnable;
     bootstrap_task.running_on_cpu = Some(apic_id); 
-    bootstrap_task.pinned_core = Some(apic_id); // can only run on this CPU core
+    bootstrap_task.affinity = AffinityMask::single(apic_id); // can only run on this CPU core
     let bootstrap_task_id = bootstrap_task.id;
     let task_ref = TaskRef::new(bootstrap_task);
 
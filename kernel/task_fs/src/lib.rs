@@ -247,7 +247,7 @@ impl TaskFile {
             RunState::Reaped     => "Reaped",
         };
         let cpu = self.taskref.lock().running_on_cpu.map(|cpu| format!("{}", cpu)).unwrap_or(String::from("-"));
-        let pinned = &self.taskref.lock().pinned_core.map(|pin| format!("{}", pin)).unwrap_or(String::from("-"));
+        let pinned = &self.taskref.lock().pinned_core().map(|pin| format!("{}", pin)).unwrap_or(String::from("-"));
         let task_type = if self.taskref.lock().is_an_idle_task {
             "I"
         } else if self.taskref.lock().is_application() {
@@ -0,0 +1,202 @@
+//! Finds all in-kernel tests registered with the [`ktest`](../ktest/index.html) crate, runs each one
+//! in its own task, and reports the overall result to the host via QEMU's `isa-debug-exit` device.
+//!
+//! The runner is started by the captain when Theseus is built with the `theseus_test` config option,
+//! which `make test` does automatically. QEMU's exit status is then 
+//! `(EXIT_CODE_SUCCESS << 1) | 1` (i.e., 33) if all tests passed, and something else otherwise.
+//!
+//! Only tests in crates that have been loaded into the initial kernel namespace are found.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate port_io;
+extern crate ktest;
+extern crate mod_mgmt;
+extern crate task;
+extern crate spawn;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+use core::time::Duration;
+use ktest::{TestFunction, TEST_FUNCTION_NAME};
+use mod_mgmt::{CrateNamespace, SectionType};
+use port_io::Port;
+use task::{ExitValue, KillReason, TaskRef};
+
+
+/// The I/O port of QEMU's `isa-debug-exit` device, as given by `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
+pub const QEMU_EXIT_PORT: u16 = 0xF4;
+/// The exit code written to QEMU if all tests passed; QEMU then exits with status `(0x10 << 1) | 1 = 33`.
+pub const EXIT_CODE_SUCCESS: u32 = 0x10;
+/// The exit code written to QEMU if any test failed; QEMU then exits with status `(0x11 << 1) | 1 = 35`.
+pub const EXIT_CODE_FAILURE: u32 = 0x11;
+/// How long a single test may run before it is killed and considered to have failed.
+pub const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// A test function found in a loaded crate.
+pub struct TestCase {
+    /// The full path of the test, e.g., `my_crate::my_module::my_test`.
+    pub name: String,
+    func: TestFunction,
+}
+
+/// The result of running a single `TestCase`.
+#[derive(Debug)]
+pub enum TestOutcome {
+    Passed,
+    /// The test returned the enclosed error.
+    Failed(String),
+    /// The test's task panicked or was killed, e.g., due to an exception.
+    Crashed(String),
+    /// The test didn't finish within `TEST_TIMEOUT`.
+    TimedOut,
+}
+
+impl TestOutcome {
+    pub fn is_passed(&self) -> bool {
+        match self {
+            TestOutcome::Passed => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestOutcome::Passed       => write!(f, "ok"),
+            TestOutcome::Failed(e)    => write!(f, "FAILED: {}", e),
+            TestOutcome::Crashed(e)   => write!(f, "CRASHED: {}", e),
+            TestOutcome::TimedOut     => write!(f, "TIMED OUT after {:?}", TEST_TIMEOUT),
+        }
+    }
+}
+
+
+/// Spawns a new task that runs all tests in the initial kernel namespace
+/// and then exits QEMU with the overall result.
+pub fn start() -> Result<TaskRef, &'static str> {
+    spawn::new_task_builder(test_runner_task, ())
+        .name(String::from("test_runner"))
+        .spawn()
+}
+
+fn test_runner_task(_: ()) -> Result<(), &'static str> {
+    let namespace = mod_mgmt::get_initial_kernel_namespace().ok_or("test_runner: couldn't get the initial kernel namespace")?;
+    let success = run_all_tests(namespace);
+    exit_qemu(success);
+}
+
+/// Runs all tests found in the given `namespace` (including its recursive namespace) one at a time,
+/// logging the outcome of each. 
+/// 
+/// Returns `true` if every test passed.
+pub fn run_all_tests(namespace: &CrateNamespace) -> bool {
+    let tests = find_tests(namespace);
+    info!("test_runner: running {} tests", tests.len());
+
+    let mut failed: Vec<&str> = Vec::new();
+    for test in &tests {
+        let outcome = run_test(test);
+        if outcome.is_passed() {
+            info!("test {} ... {}", test.name, outcome);
+        } else {
+            error!("test {} ... {}", test.name, outcome);
+            failed.push(&test.name);
+        }
+    }
+
+    if failed.is_empty() {
+        info!("test_runner: test result: ok. {} passed; 0 failed", tests.len());
+    } else {
+        error!("test_runner: test result: FAILED. {} passed; {} failed", tests.len() - failed.len(), failed.len());
+        for name in &failed {
+            error!("    {}", name);
+        }
+    }
+    failed.is_empty()
+}
+
+/// Returns all test functions defined in crates loaded into the given `namespace`, sorted by name.
+pub fn find_tests(namespace: &CrateNamespace) -> Vec<TestCase> {
+    let suffix = format!("::{}", TEST_FUNCTION_NAME);
+    let mut tests = Vec::new();
+    namespace.for_each_crate(true, |_crate_name, crate_ref| {
+        let krate = crate_ref.lock_as_ref();
+        for sec in krate.sections.values() {
+            if sec.get_type() != SectionType::Text || !sec.name_without_hash().ends_with(&suffix) {
+                continue;
+            }
+            let name = sec.name_without_hash().trim_end_matches(&suffix).to_string();
+            let mut space: usize = 0; // must live as long as the function reference, see MappedPages::as_func()
+            let func = {
+                let mapped_pages = sec.mapped_pages.lock();
+                mapped_pages.as_func::<TestFunction>(sec.mapped_pages_offset, &mut space).map(|f| *f)
+            };
+            match func {
+                Ok(func) => tests.push(TestCase { name, func }),
+                Err(e) => error!("test_runner: couldn't get test function {}: {}", name, e),
+            }
+        }
+        true
+    });
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    tests.dedup_by(|a, b| a.name == b.name);
+    tests
+}
+
+/// Runs the given test in a new task, such that a test that panics or crashes
+/// doesn't affect other tests, and waits for it to complete.
+pub fn run_test(test: &TestCase) -> TestOutcome {
+    let taskref = match spawn::new_task_builder(run_test_function, test.func)
+        .name(format!("test {}", test.name))
+        .spawn()
+    {
+        Ok(t) => t,
+        Err(e) => return TestOutcome::Crashed(format!("couldn't spawn test task: {}", e)),
+    };
+
+    let finished = match taskref.join_timeout(TEST_TIMEOUT) {
+        Ok(finished) => finished,
+        // The HPET isn't available, so just wait for the test without a timeout.
+        Err(_) => taskref.join().is_ok(),
+    };
+    if !finished {
+        let _ = taskref.kill(KillReason::Requested);
+        return TestOutcome::TimedOut;
+    }
+
+    match taskref.take_exit_value() {
+        Some(ExitValue::Completed(ret)) => match ret.downcast_ref::<Result<(), &'static str>>() {
+            Some(Ok(())) => TestOutcome::Passed,
+            Some(Err(e)) => TestOutcome::Failed(e.to_string()),
+            None => TestOutcome::Crashed(String::from("test task returned an unexpected type")),
+        },
+        Some(ExitValue::Panicked(info)) => TestOutcome::Crashed(format!("panicked at {}", info)),
+        Some(ExitValue::Killed(reason)) => TestOutcome::Crashed(format!("killed: {}", reason)),
+        None => TestOutcome::Crashed(String::from("test task had no exit value")),
+    }
+}
+
+fn run_test_function(func: TestFunction) -> Result<(), &'static str> {
+    func()
+}
+
+/// Exits QEMU via its `isa-debug-exit` device, reporting whether all tests passed.
+///
+/// If Theseus isn't running in QEMU with that device, this just halts the current task forever.
+pub fn exit_qemu(success: bool) -> ! {
+    let code = if success { EXIT_CODE_SUCCESS } else { EXIT_CODE_FAILURE };
+    let port: Port<u32> = Port::new(QEMU_EXIT_PORT);
+    // SAFE: writing to this port only has an effect if QEMU's isa-debug-exit device is present.
+    unsafe { port.write(code); }
+
+    warn!("test_runner: couldn't exit QEMU; is the isa-debug-exit device enabled?");
+    loop { }
+}
@@ -0,0 +1,153 @@
+//! The monotonic clock, which counts nanoseconds using the TSC or the HPET's main counter.
+//!
+//! The TSC is preferred because it is much cheaper to read, but only if it is invariant,
+//! i.e., it ticks at a constant rate regardless of the core's frequency and power state.
+//! Its frequency is then calibrated against the HPET, or against the PIT if there is no HPET.
+//! Otherwise, the HPET's main counter is used directly, as its frequency is given by the hardware.
+
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+
+
+/// How long the TSC is measured against the HPET during calibration, in femtoseconds (10 ms).
+const CALIBRATION_PERIOD_FS: u64 = 10_000_000_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+
+/// The hardware counter that the monotonic clock reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The invariant time-stamp counter.
+    Tsc,
+    /// The HPET's main counter.
+    Hpet,
+}
+
+/// The calibrated monotonic clock.
+pub struct Clock {
+    pub source: ClockSource,
+    /// The frequency of the counter, in Hz.
+    pub frequency: u64,
+    /// The factor that converts counter ticks into nanoseconds, as a fixed-point number with 32 fractional bits.
+    nanos_per_tick: u64,
+}
+
+/// The clock, which is calibrated when it's first used.
+static CLOCK: Once<Clock> = Once::new();
+/// The counter value at which the clock was started (or resumed), i.e., the counter value at `OFFSET_NS`.
+static BASE_TICKS: AtomicU64 = AtomicU64::new(0);
+/// The number of nanoseconds that the clock showed when it was started (or resumed).
+static OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+
+
+/// Returns the clock, calibrating it first if that hasn't happened yet.
+pub fn get() -> &'static Clock {
+    CLOCK.call_once(|| {
+        let clock = Clock::calibrate();
+        BASE_TICKS.store(clock.read_counter(), Ordering::Release);
+        clock
+    })
+}
+
+/// Returns the clock if it has already been calibrated.
+pub fn try_get() -> Option<&'static Clock> {
+    CLOCK.try()
+}
+
+/// Returns whether the CPU's TSC is invariant.
+pub fn has_invariant_tsc() -> bool {
+    // SAFE: CPUID is available on every x86_64 CPU.
+    unsafe {
+        __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+    }
+}
+
+impl Clock {
+    fn calibrate() -> Clock {
+        let hpet_frequency = hpet::get_hpet()
+            .map(|hpet| hpet.counter_period_femtoseconds() as u64)
+            .filter(|&period_fs| period_fs != 0)
+            .map(|period_fs| FEMTOS_PER_SEC / period_fs);
+
+        let (source, frequency) = match (has_invariant_tsc(), hpet_frequency) {
+            (true, Some(hpet_frequency)) => match calibrate_tsc_against_hpet() {
+                Some(frequency) => (ClockSource::Tsc, frequency),
+                None => (ClockSource::Hpet, hpet_frequency),
+            },
+            (false, Some(frequency)) => (ClockSource::Hpet, frequency),
+            (invariant, None) => {
+                if !invariant {
+                    warn!("timer: there is no HPET and the TSC isn't invariant, so the clock may drift");
+                }
+                (ClockSource::Tsc, tsc::get_tsc_frequency().unwrap_or(0))
+            }
+        };
+        // A frequency of zero would be a broken counter; avoid dividing by it.
+        let frequency = core::cmp::max(frequency, 1);
+        Clock {
+            source,
+            frequency,
+            nanos_per_tick: (((NANOS_PER_SEC as u128) << 32) / frequency as u128) as u64,
+        }
+    }
+
+    /// Returns the current value of this clock's counter.
+    pub fn read_counter(&self) -> u64 {
+        match self.source {
+            ClockSource::Tsc => tsc::tsc_ticks().into(),
+            ClockSource::Hpet => hpet::get_hpet().map(|hpet| hpet.get_counter()).unwrap_or(0),
+        }
+    }
+
+    /// Returns the number of nanoseconds since the clock was started.
+    pub fn now_ns(&self) -> u64 {
+        let ticks = self.read_counter().saturating_sub(BASE_TICKS.load(Ordering::Acquire));
+        OFFSET_NS.load(Ordering::Acquire).saturating_add(self.ticks_to_ns(ticks))
+    }
+
+    /// Converts a number of counter ticks into nanoseconds.
+    pub fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        ((ticks as u128 * self.nanos_per_tick as u128) >> 32) as u64
+    }
+
+    /// Converts a number of nanoseconds into counter ticks.
+    pub fn ns_to_ticks(&self, ns: u64) -> u64 {
+        (ns as u128 * self.frequency as u128 / NANOS_PER_SEC as u128) as u64
+    }
+
+    /// Returns the counter value at which the clock will show `ns` nanoseconds.
+    pub fn counter_at(&self, ns: u64) -> u64 {
+        let offset = OFFSET_NS.load(Ordering::Acquire);
+        BASE_TICKS.load(Ordering::Acquire).saturating_add(self.ns_to_ticks(ns.saturating_sub(offset)))
+    }
+
+    /// Restarts the counting from the current counter value at the given time,
+    /// e.g., after the counter was reset by the system's sleep state.
+    pub fn restart_at(&self, ns: u64) {
+        BASE_TICKS.store(self.read_counter(), Ordering::Release);
+        OFFSET_NS.store(ns, Ordering::Release);
+    }
+}
+
+/// Measures how many times the TSC ticks while the HPET's main counter advances by `CALIBRATION_PERIOD_FS`.
+fn calibrate_tsc_against_hpet() -> Option<u64> {
+    let hpet = hpet::get_hpet()?;
+    let period_fs = hpet.counter_period_femtoseconds() as u64;
+    let hpet_ticks = CALIBRATION_PERIOD_FS / period_fs;
+
+    let hpet_start = hpet.get_counter();
+    let tsc_start = tsc::tsc_ticks().into();
+    let mut hpet_end = hpet_start;
+    while hpet_end.wrapping_sub(hpet_start) < hpet_ticks {
+        pause::spin_loop_hint();
+        hpet_end = hpet.get_counter();
+    }
+    let tsc_end = tsc::tsc_ticks().into();
+
+    let elapsed_fs = hpet_end.wrapping_sub(hpet_start) as u128 * period_fs as u128;
+    let frequency = (tsc_end.checked_sub(tsc_start)? as u128 * FEMTOS_PER_SEC as u128 / elapsed_fs) as u64;
+    info!("timer: TSC frequency calibrated against the HPET is {} Hz", frequency);
+    Some(frequency)
+}
@@ -0,0 +1,119 @@
+//! Driving each core's APIC timer in TSC-deadline mode, if the hardware supports it.
+//!
+//! In TSC-deadline mode, a core's timer interrupt is armed for the earlier of two deadlines:
+//! the core's next preemption tick, which recurs every `CONFIG_TIMESLICE_PERIOD_MICROSECONDS`,
+//! and the earliest deadline of all software timers. Thus, software timers expire precisely
+//! rather than at the granularity of the periodic preemption tick.
+//!
+//! Otherwise, the APIC timer stays in periodic mode, every timer interrupt is a preemption tick,
+//! and software timers are delivered at the next tick after they expire.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use atomic_linked_list::atomic_map::AtomicMap;
+use irq_safety::hold_interrupts;
+use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
+use clock::{self, ClockSource};
+use timers;
+
+
+/// The timer state of a core.
+struct CoreTimer {
+    /// Whether this core's APIC timer has been switched to TSC-deadline mode.
+    deadline_mode: AtomicBool,
+    /// The TSC value of this core's next preemption tick.
+    next_tick: AtomicU64,
+}
+
+lazy_static! {
+    /// The timer state of every core, indexed by APIC ID. This is only populated if TSC-deadline mode is used.
+    static ref CORE_TIMERS: AtomicMap<u8, CoreTimer> = AtomicMap::new();
+}
+
+/// The number of TSC ticks between preemption ticks, which is zero unless TSC-deadline mode is used.
+static TICK_PERIOD: AtomicU64 = AtomicU64::new(0);
+
+
+/// Decides whether the cores' APIC timers can be driven in TSC-deadline mode, and if so, prepares every core for it.
+///
+/// Each core switches to TSC-deadline mode upon its next periodic timer interrupt.
+pub fn init() -> bool {
+    let clock = clock::get();
+    if clock.source != ClockSource::Tsc || !clock::has_invariant_tsc() || !apic::has_tsc_deadline() {
+        return false;
+    }
+    let period = clock.ns_to_ticks(CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u64 * 1000);
+    if period == 0 {
+        return false;
+    }
+    for (&apic_id, _lapic) in apic::get_lapics().iter() {
+        CORE_TIMERS.insert(apic_id, CoreTimer { deadline_mode: AtomicBool::new(false), next_tick: AtomicU64::new(0) });
+    }
+    TICK_PERIOD.store(period, Ordering::Release);
+    true
+}
+
+/// Returns whether the timer interrupts of this core are driven by TSC deadlines.
+pub fn is_enabled() -> bool {
+    CORE_TIMERS.get(&apic::get_my_apic_id()).map_or(false, |core| core.deadline_mode.load(Ordering::Acquire))
+}
+
+/// Handles a timer interrupt on the current core, and returns whether it is a preemption tick.
+///
+/// This switches the core to TSC-deadline mode if that hasn't happened yet, and re-arms its timer.
+pub fn handle_interrupt() -> bool {
+    let core = match CORE_TIMERS.get(&apic::get_my_apic_id()) {
+        Some(core) => core,
+        None => return true,
+    };
+    let now = tsc::tsc_ticks().into();
+    if !core.deadline_mode.load(Ordering::Acquire) {
+        // This was the last periodic interrupt on this core.
+        let switched = apic::get_my_apic()
+            .ok_or("couldn't get this core's LocalApic")
+            .and_then(|lapic| lapic.write().enable_tsc_deadline_mode());
+        if let Err(e) = switched {
+            error!("timer: failed to switch APIC {} to TSC-deadline mode: {}", apic::get_my_apic_id(), e);
+            return true;
+        }
+        core.next_tick.store(now.saturating_add(TICK_PERIOD.load(Ordering::Acquire)), Ordering::Release);
+        core.deadline_mode.store(true, Ordering::Release);
+        arm(core);
+        return true;
+    }
+
+    let is_tick = now >= core.next_tick.load(Ordering::Acquire);
+    if is_tick {
+        core.next_tick.store(now.saturating_add(TICK_PERIOD.load(Ordering::Acquire)), Ordering::Release);
+    }
+    arm(core);
+    is_tick
+}
+
+/// Re-arms the current core's timer, e.g., after a software timer with an earlier deadline was added.
+pub fn rearm() {
+    let _held_interrupts = hold_interrupts();
+    if let Some(core) = CORE_TIMERS.get(&apic::get_my_apic_id()) {
+        if core.deadline_mode.load(Ordering::Acquire) {
+            arm(core);
+        }
+    }
+}
+
+/// Restarts the preemption ticks of the current core from now, after its APIC timer was re-initialized in periodic mode,
+/// such that it switches to TSC-deadline mode again upon its next timer interrupt.
+pub fn reset() {
+    if let Some(core) = CORE_TIMERS.get(&apic::get_my_apic_id()) {
+        core.deadline_mode.store(false, Ordering::Release);
+    }
+}
+
+/// Arms the current core's timer for the earlier of its next preemption tick and the earliest software timer.
+fn arm(core: &CoreTimer) {
+    let next_tick = core.next_tick.load(Ordering::Acquire);
+    let deadline = match timers::earliest_deadline() {
+        u64::MAX => next_tick,
+        earliest_ns => core::cmp::min(next_tick, clock::get().counter_at(earliest_ns)),
+    };
+    // A deadline of zero would disarm the timer.
+    apic::set_tsc_deadline(core::cmp::max(deadline, 1));
+}
@@ -0,0 +1,253 @@
+//! The system's monotonic clock and its high-resolution software timers.
+//!
+//! The clock counts nanoseconds since it was started, using the invariant TSC calibrated against the HPET,
+//! or the HPET itself (see the `clock` module). Time is represented by [`Instant`](struct.Instant.html)s,
+//! which have the same interface as `std::time::Instant`, such that a port of `std` can be built on top of them.
+//!
+//! On top of the clock, this crate offers:
+//! * One-shot and periodic software timers, which are delivered by invoking a callback or waking a `Waker`,
+//!   see [`call_at()`](fn.call_at.html), [`call_every()`](fn.call_every.html), [`wake_at()`](fn.wake_at.html),
+//!   and [`wake_every()`](fn.wake_every.html).
+//! * Blocking sleeps for tasks, see [`sleep()`](fn.sleep.html) and [`sleep_until()`](fn.sleep_until.html),
+//!   which wait on a `WaitQueue` until a timer notifies it.
+//!
+//! The APIC timer interrupt drives both the scheduler's preemption tick and the software timers,
+//! see [`handle_timer_interrupt()`](fn.handle_timer_interrupt.html).
+//! If the CPU supports it, each core's APIC timer is switched to TSC-deadline mode,
+//! in which its interrupts arrive right when the next preemption tick or software timer is due.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate irq_safety;
+extern crate atomic_linked_list;
+extern crate kernel_config;
+extern crate hpet;
+extern crate tsc;
+extern crate apic;
+extern crate wait_queue;
+extern crate pause;
+
+mod clock;
+mod timers;
+mod deadline;
+
+pub use clock::ClockSource;
+pub use timers::{TimerHandle, call_at, call_after, call_every, wake_at, wake_every, process_timers};
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use alloc::sync::Arc;
+use wait_queue::{WaitQueue, WaitError};
+
+
+/// A point in time as measured by the monotonic clock, i.e., the number of nanoseconds since the clock was started.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the current time.
+    pub fn now() -> Instant {
+        Instant(clock::get().now_ns())
+    }
+
+    /// Returns the instant that lies the given number of nanoseconds after the clock was started.
+    pub fn from_nanos(nanos: u64) -> Instant {
+        Instant(nanos)
+    }
+
+    /// Returns the number of nanoseconds between the start of the clock and this instant.
+    pub fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the amount of time between the start of the clock and this instant.
+    pub fn since_start(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+
+    /// Returns the amount of time that passed from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns the amount of time that passed from `earlier` to this instant, or `None` if `earlier` is later.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    /// Returns the amount of time that passed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the instant `duration` after this one, or `None` if it can't be represented.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        duration_to_nanos(duration).and_then(|nanos| self.0.checked_add(nanos)).map(Instant)
+    }
+
+    /// Returns the instant `duration` before this one, or `None` if it lies before the start of the clock.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        duration_to_nanos(duration).and_then(|nanos| self.0.checked_sub(nanos)).map(Instant)
+    }
+}
+
+fn duration_to_nanos(duration: Duration) -> Option<u64> {
+    let nanos = duration.as_nanos();
+    if nanos > u64::MAX as u128 { None } else { Some(nanos as u64) }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Instant({}.{:09}s)", self.0 / 1_000_000_000, self.0 % 1_000_000_000)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    /// Saturates at the latest representable instant.
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).unwrap_or(Instant(u64::MAX))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+    /// Saturates at the start of the clock.
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration).unwrap_or(Instant(0))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+    /// Saturates at zero, like [`duration_since()`](#method.duration_since).
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+
+/// The time at which `suspend()` was last invoked, in nanoseconds.
+static SUSPENDED_AT: AtomicU64 = AtomicU64::new(0);
+
+
+/// Calibrates the clock and, if possible, prepares every core's APIC timer to be switched to TSC-deadline mode.
+///
+/// This must be invoked after the HPET has been initialized and all cores have been booted up.
+pub fn init() -> Result<(), &'static str> {
+    let clock = clock::get();
+    let tsc_deadline = deadline::init();
+    info!("timer: the clock uses the {:?} at {} Hz, APIC timers {} TSC-deadline mode",
+        clock.source, clock.frequency, if tsc_deadline { "use" } else { "don't support" });
+    Ok(())
+}
+
+/// Returns the current time. This is the same as [`Instant::now()`](struct.Instant.html#method.now).
+pub fn now() -> Instant {
+    Instant::now()
+}
+
+/// Returns the hardware counter that the clock reads, or `None` if the clock hasn't been calibrated yet.
+pub fn clock_source() -> Option<ClockSource> {
+    clock::try_get().map(|clock| clock.source)
+}
+
+/// Returns whether the current core's timer interrupts are driven by TSC deadlines
+/// rather than the periodic preemption tick.
+pub fn is_tsc_deadline_enabled() -> bool {
+    deadline::is_enabled()
+}
+
+
+/// Handles an APIC timer interrupt on the current core: delivers the software timers that have expired
+/// and re-arms the core's timer. Returns whether this interrupt is a preemption tick,
+/// in which case the interrupt handler should invoke the scheduler.
+///
+/// This must only be invoked by the APIC timer interrupt handler.
+pub fn handle_timer_interrupt() -> bool {
+    process_timers();
+    deadline::handle_interrupt()
+}
+
+
+/// Blocks the current task until `duration` has passed.
+pub fn sleep(duration: Duration) -> Result<(), &'static str> {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Blocks the current task until the clock reaches `deadline`.
+///
+/// If there is no current task yet, e.g., early during boot, this spins instead.
+pub fn sleep_until(deadline: Instant) -> Result<(), &'static str> {
+    if Instant::now() >= deadline {
+        return Ok(());
+    }
+    let state = Arc::new((AtomicBool::new(false), WaitQueue::new()));
+    let timer = {
+        let state = state.clone();
+        call_at(deadline, move || {
+            state.0.store(true, Ordering::Release);
+            state.1.notify_all();
+        })
+    };
+    let result = state.1.wait_until(&|| if state.0.load(Ordering::Acquire) { Some(()) } else { None });
+    timer.cancel();
+    match result {
+        Ok(()) => Ok(()),
+        Err(WaitError::NoCurrentTask) => {
+            spin_until(deadline);
+            Ok(())
+        }
+        Err(_) => Err("timer: failed to wait for the deadline"),
+    }
+}
+
+/// Spins until `duration` has passed, without blocking the current task.
+///
+/// This is meant for short delays, e.g., those that drivers need between accesses to a device.
+pub fn spin_for(duration: Duration) {
+    spin_until(Instant::now() + duration);
+}
+
+/// Spins until the clock reaches `deadline`, without blocking the current task.
+pub fn spin_until(deadline: Instant) {
+    while Instant::now() < deadline {
+        pause::spin_loop_hint();
+    }
+}
+
+
+/// Records the current time before the system enters a sleep state that may reset the clock's counter,
+/// e.g., the ACPI S3 sleep state.
+pub fn suspend() {
+    SUSPENDED_AT.store(Instant::now().as_nanos(), Ordering::Release);
+}
+
+/// Continues the clock from the time recorded by [`suspend()`](fn.suspend.html) after the system has woken up,
+/// such that it remains monotonic even if its counter was reset, and restarts the current core's preemption ticks.
+///
+/// This must be invoked after the current core's APIC timer has been re-initialized.
+pub fn resume() {
+    if let Some(clock) = clock::try_get() {
+        clock.restart_at(SUSPENDED_AT.load(Ordering::Acquire));
+    }
+    deadline::reset();
+}
@@ -0,0 +1,185 @@
+//! Software timers, which invoke a callback or wake a `Waker` once or periodically.
+//!
+//! All pending timers are kept in a single list sorted by deadline,
+//! whose earliest deadline is also kept in an atomic so that timer interrupts can cheaply check it.
+//! Expired timers are delivered by [`process_timers()`](../fn.process_timers.html).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::Waker;
+use core::time::Duration;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use irq_safety::MutexIrqSafe;
+use Instant;
+
+
+/// How an expired timer is delivered.
+#[derive(Clone)]
+enum Delivery {
+    Callback(Arc<dyn Fn() + Send + Sync>),
+    Waker(Waker),
+}
+
+/// A pending timer.
+struct Entry {
+    deadline: Instant,
+    /// The interval at which a periodic timer is re-armed, which is `None` for a one-shot timer.
+    period: Option<Duration>,
+    id: u64,
+    delivery: Delivery,
+}
+
+/// The list of all pending timers, sorted by ascending deadline.
+static TIMERS: MutexIrqSafe<Vec<Entry>> = MutexIrqSafe::new(Vec::new());
+/// The earliest deadline in `TIMERS` in nanoseconds, or `u64::MAX` if there are no pending timers.
+static EARLIEST_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+/// The ID that will be given to the next timer.
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+
+
+/// A handle to a pending timer, which can be used to cancel it.
+///
+/// Dropping the handle does not cancel the timer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TimerHandle {
+    id: u64,
+}
+
+impl TimerHandle {
+    /// Cancels the timer, such that it won't expire (again).
+    ///
+    /// Returns `false` if a one-shot timer had already expired.
+    /// A callback that is running on another core at the same time may still complete.
+    pub fn cancel(&self) -> bool {
+        let entry = {
+            let mut timers = TIMERS.lock();
+            let entry = timers.iter().position(|t| t.id == self.id).map(|i| timers.remove(i));
+            update_earliest_deadline(&timers);
+            entry
+        };
+        // The callback or waker is dropped here, after the lock has been released.
+        entry.is_some()
+    }
+
+    /// Returns whether the timer is still pending, i.e., it hasn't been cancelled and isn't a one-shot timer that expired.
+    pub fn is_pending(&self) -> bool {
+        TIMERS.lock().iter().any(|t| t.id == self.id)
+    }
+}
+
+
+/// Invokes `callback` once the clock reaches `deadline`.
+///
+/// Callbacks run in the context of a timer interrupt, so they must be brief and must never block;
+/// they would typically unblock a task or notify a `WaitQueue`.
+pub fn call_at<F>(deadline: Instant, callback: F) -> TimerHandle
+    where F: Fn() + Send + Sync + 'static
+{
+    add(deadline, None, Delivery::Callback(Arc::new(callback)))
+}
+
+/// Invokes `callback` once `delay` has passed. See [`call_at()`](fn.call_at.html).
+pub fn call_after<F>(delay: Duration, callback: F) -> TimerHandle
+    where F: Fn() + Send + Sync + 'static
+{
+    call_at(Instant::now() + delay, callback)
+}
+
+/// Invokes `callback` every `period`, starting one `period` from now, until the timer is cancelled.
+/// See [`call_at()`](fn.call_at.html) for the restrictions on callbacks.
+///
+/// If the callbacks fall behind, e.g., because interrupts were disabled for a while, the missed ones are skipped.
+pub fn call_every<F>(period: Duration, callback: F) -> Result<TimerHandle, &'static str>
+    where F: Fn() + Send + Sync + 'static
+{
+    if period == Duration::from_secs(0) {
+        return Err("timer: the period of a periodic timer must not be zero");
+    }
+    Ok(add(Instant::now() + period, Some(period), Delivery::Callback(Arc::new(callback))))
+}
+
+/// Wakes `waker` once the clock reaches `deadline`, which is how futures wait for time to pass.
+pub fn wake_at(deadline: Instant, waker: Waker) -> TimerHandle {
+    add(deadline, None, Delivery::Waker(waker))
+}
+
+/// Wakes `waker` every `period`, starting one `period` from now, until the timer is cancelled.
+pub fn wake_every(period: Duration, waker: Waker) -> Result<TimerHandle, &'static str> {
+    if period == Duration::from_secs(0) {
+        return Err("timer: the period of a periodic timer must not be zero");
+    }
+    Ok(add(Instant::now() + period, Some(period), Delivery::Waker(waker)))
+}
+
+fn add(deadline: Instant, period: Option<Duration>, delivery: Delivery) -> TimerHandle {
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    {
+        let mut timers = TIMERS.lock();
+        insert(&mut timers, Entry { deadline, period, id, delivery });
+        update_earliest_deadline(&timers);
+    }
+    // This core's timer interrupt may have to come sooner to deliver the new timer on time.
+    ::deadline::rearm();
+    TimerHandle { id }
+}
+
+/// Inserts the given timer into the list of pending timers, after all timers with the same or an earlier deadline.
+fn insert(timers: &mut Vec<Entry>, entry: Entry) {
+    let index = timers.iter().position(|t| t.deadline > entry.deadline).unwrap_or(timers.len());
+    timers.insert(index, entry);
+}
+
+fn update_earliest_deadline(timers: &[Entry]) {
+    let earliest = timers.first().map_or(u64::MAX, |t| t.deadline.as_nanos());
+    EARLIEST_DEADLINE.store(earliest, Ordering::Release);
+}
+
+/// Returns the earliest deadline of all pending timers, in nanoseconds, or `u64::MAX` if there are none.
+pub fn earliest_deadline() -> u64 {
+    EARLIEST_DEADLINE.load(Ordering::Acquire)
+}
+
+
+/// Delivers every timer that has expired, re-arms the periodic ones, and returns how many were delivered.
+///
+/// This is invoked by the timer interrupt handler, and may also be invoked by any task.
+pub fn process_timers() -> usize {
+    let now = match ::clock::try_get() {
+        Some(clock) => Instant::from_nanos(clock.now_ns()),
+        None => return 0,
+    };
+    if earliest_deadline() > now.as_nanos() {
+        return 0;
+    }
+    let mut num_expired = 0;
+    loop {
+        // Callbacks and wakers must run without holding the lock on `TIMERS`,
+        // as they may acquire other locks or add new timers.
+        let delivery = {
+            let mut timers = TIMERS.lock();
+            if !timers.first().map_or(false, |t| t.deadline <= now) {
+                return num_expired;
+            }
+            let mut entry = timers.remove(0);
+            let delivery = match entry.period {
+                Some(period) => {
+                    let period_ns = period.as_nanos();
+                    let missed = (now - entry.deadline).as_nanos() / period_ns;
+                    let next = entry.deadline.as_nanos() as u128 + (missed + 1) * period_ns;
+                    entry.deadline = Instant::from_nanos(core::cmp::min(next, u64::MAX as u128) as u64);
+                    let delivery = entry.delivery.clone();
+                    insert(&mut timers, entry);
+                    delivery
+                }
+                None => entry.delivery,
+            };
+            update_earliest_deadline(&timers);
+            delivery
+        };
+        match delivery {
+            Delivery::Callback(callback) => callback(),
+            Delivery::Waker(waker) => waker.wake(),
+        }
+        num_expired += 1;
+    }
+}
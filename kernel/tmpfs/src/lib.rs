@@ -0,0 +1,176 @@
+#![no_std]
+
+//! An in-memory filesystem, registered with the VFS as the `tmpfs` filesystem type.
+//!
+//! Each mount of a tmpfs is a new, empty tree of [`TmpDirectory`] and [`TmpFile`] nodes,
+//! which are created with `Directory::create_file()` and `Directory::create_dir()`
+//! and whose contents are lost when the filesystem is unmounted.
+//!
+//! Unlike plain VFS nodes, tmpfs nodes record their metadata (inode numbers, permissions, owners,
+//! and creation, modification and access times from the RTC), which is returned by `FsNode::metadata()`.
+//! New nodes are owned by the user and group of the task that creates them.
+//! File contents are stored in separately-allocated pages, so files may be sparse:
+//! pages are only allocated when they're written, and holes read as zeros.
+//!
+//! Each mount has a size limit on the total size of its allocated pages, set with the `size` mount option.
+//! The supported mount options are:
+//! * `size=<bytes>[k|m|g]`: the size limit, which defaults to [`DEFAULT_SIZE_LIMIT`]; `size=0` means no limit,
+//! * `mode=<octal>`: the permissions of the root directory, which default to `1777`.
+//!
+//! [`init()`](fn.init.html) mounts a tmpfs at `/tmp`.
+
+#[macro_use] extern crate alloc;
+extern crate spin;
+extern crate fs_node;
+extern crate memory;
+extern crate kernel_config;
+extern crate wall_clock;
+extern crate vfs;
+extern crate root;
+extern crate vfs_node;
+extern crate access_control;
+
+mod node;
+
+pub use node::{TmpDirectory, TmpFile};
+
+use core::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use fs_node::{DirRef, WeakDirRef};
+use vfs::{FileSystem, Device};
+use vfs_node::VFSDirectory;
+
+
+/// The name of this filesystem type in the VFS.
+pub const FILESYSTEM_NAME: &'static str = "tmpfs";
+/// The size limit of a tmpfs mounted without the `size` option.
+pub const DEFAULT_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+/// The permissions of a tmpfs root directory mounted without the `mode` option,
+/// which allow everyone to create files but only delete their own.
+pub const DEFAULT_ROOT_PERMISSIONS: u16 = 0o1777;
+/// The name of the directory within the root directory at which [`init()`](fn.init.html) mounts a tmpfs.
+pub const TMP_DIRECTORY_NAME: &'static str = "tmp";
+
+
+/// Registers tmpfs as a filesystem type with the VFS, and mounts a tmpfs at `/tmp`.
+pub fn init() -> Result<(), &'static str> {
+    vfs::register_filesystem(Arc::new(TmpFileSystem))?;
+    let root = root::get_root();
+    let existing = root.lock().get(TMP_DIRECTORY_NAME);
+    if existing.is_none() {
+        VFSDirectory::new(TMP_DIRECTORY_NAME.to_string(), root)?;
+    }
+    vfs::mount(None, &format!("/{}", TMP_DIRECTORY_NAME), FILESYSTEM_NAME)?;
+    Ok(())
+}
+
+
+/// The tmpfs filesystem type, through which the VFS mounts new in-memory filesystems.
+pub struct TmpFileSystem;
+
+impl FileSystem for TmpFileSystem {
+    fn name(&self) -> &'static str {
+        FILESYSTEM_NAME
+    }
+
+    fn requires_device(&self) -> bool {
+        false
+    }
+
+    fn mount(&self, _device: Option<&Device>, name: String, parent: WeakDirRef, options: &str) -> Result<DirRef, &'static str> {
+        let mut size_limit = Some(DEFAULT_SIZE_LIMIT);
+        let mut permissions = DEFAULT_ROOT_PERMISSIONS;
+        for (option, value) in vfs::parse_options(options) {
+            match (option, value) {
+                ("size", Some(value)) => {
+                    size_limit = Some(parse_size(value)?).filter(|&limit| limit != 0);
+                }
+                ("mode", Some(value)) => {
+                    permissions = u16::from_str_radix(value, 8).ok()
+                        .filter(|&mode| mode <= 0o7777)
+                        .ok_or("tmpfs: invalid mode, expected octal permissions like 1777")?;
+                }
+                _ => return Err("tmpfs: unsupported mount option"),
+            }
+        }
+        let volume = Arc::new(Volume::new(size_limit));
+        Ok(TmpDirectory::new_ref(name, parent, volume, permissions))
+    }
+}
+
+/// Parses a size like `4096`, `512k`, `16M` or `1g` into a number of bytes.
+fn parse_size(size: &str) -> Result<usize, &'static str> {
+    let (digits, multiplier) = match size.chars().last() {
+        Some('k') | Some('K') => (&size[.. size.len() - 1], 1024),
+        Some('m') | Some('M') => (&size[.. size.len() - 1], 1024 * 1024),
+        Some('g') | Some('G') => (&size[.. size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or("tmpfs: invalid size, expected a number of bytes optionally followed by k, m or g")
+}
+
+
+/// The state shared by all of the nodes of one mounted tmpfs.
+pub struct Volume {
+    /// The maximum number of bytes of file contents, or `None` if there is no limit.
+    size_limit: Option<usize>,
+    /// The number of bytes of file contents currently allocated.
+    used: AtomicUsize,
+    /// The inode number that will be given to the next node.
+    next_inode: AtomicU64,
+    /// The number that identifies this filesystem in its nodes' metadata.
+    id: u64,
+}
+
+impl Volume {
+    fn new(size_limit: Option<usize>) -> Volume {
+        Volume {
+            size_limit,
+            used: AtomicUsize::new(0),
+            next_inode: AtomicU64::new(1),
+            id: fs_node::new_filesystem_id(),
+        }
+    }
+
+    /// Returns the size limit of this filesystem, or `None` if there is no limit.
+    pub fn size_limit(&self) -> Option<usize> {
+        self.size_limit
+    }
+
+    /// Returns the number of bytes of file contents currently allocated on this filesystem.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Returns the number that identifies this filesystem in its nodes' metadata.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn allocate_inode(&self) -> u64 {
+        self.next_inode.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Accounts for `bytes` more bytes of file contents, failing if that would exceed the size limit.
+    fn reserve(&self, bytes: usize) -> Result<(), &'static str> {
+        let mut used = self.used.load(Ordering::Acquire);
+        loop {
+            let new_used = used.checked_add(bytes).ok_or("tmpfs: no space left on the filesystem")?;
+            if self.size_limit.map_or(false, |limit| new_used > limit) {
+                return Err("tmpfs: no space left on the filesystem");
+            }
+            match self.used.compare_exchange_weak(used, new_used, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Ok(()),
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    /// Accounts for `bytes` bytes of file contents being freed.
+    fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+}
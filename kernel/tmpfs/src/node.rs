@@ -0,0 +1,342 @@
+//! The directories and files of a tmpfs.
+
+use core::cmp::min;
+use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use fs_node::{DirRef, WeakDirRef, FileRef, Directory, File, FileOrDir, FsNode, Metadata};
+use memory::MappedPages;
+use kernel_config::memory::PAGE_SIZE;
+use Volume;
+
+
+/// The permissions given to new files (`rw-r--r--`).
+const DEFAULT_FILE_PERMISSIONS: u16 = 0o644;
+/// The permissions given to new directories (`rwxr-xr-x`).
+const DEFAULT_DIR_PERMISSIONS: u16 = 0o755;
+/// The bits of a mode that are permissions, including the set-user-ID, set-group-ID and sticky bits.
+const PERMISSION_BITS: u16 = 0o7777;
+
+/// Returns the current wall-clock time as seconds since the Unix epoch, which is how node timestamps are kept.
+fn unix_time() -> u64 {
+    wall_clock::now().to_unix().0.max(0) as u64
+}
+
+
+/// A directory in a tmpfs.
+pub struct TmpDirectory {
+    name: String,
+    children: BTreeMap<String, FileOrDir>,
+    parent: WeakDirRef,
+    /// A weak reference to this directory itself, which is the parent of its children.
+    self_ref: WeakDirRef,
+    volume: Arc<Volume>,
+    inode: u64,
+    permissions: u16,
+    owner: u32,
+    group: u32,
+    created: u64,
+    modified: u64,
+}
+
+impl TmpDirectory {
+    /// Creates a new, empty directory whose parent directory is `parent`,
+    /// but doesn't insert it into that directory.
+    pub(crate) fn new_ref(name: String, parent: WeakDirRef, volume: Arc<Volume>, permissions: u16) -> DirRef {
+        let now = unix_time();
+        let credentials = access_control::current_credentials();
+        let dir = TmpDirectory {
+            name,
+            children: BTreeMap::new(),
+            parent,
+            self_ref: Weak::<Mutex<TmpDirectory>>::new(),
+            inode: volume.allocate_inode(),
+            volume,
+            permissions,
+            owner: credentials.uid,
+            group: credentials.gid,
+            created: now,
+            modified: now,
+        };
+        let concrete_ref = Arc::new(Mutex::new(dir));
+        let dir_ref = concrete_ref.clone() as DirRef;
+        concrete_ref.lock().self_ref = Arc::downgrade(&dir_ref);
+        dir_ref
+    }
+
+    /// Returns the tmpfs that this directory is in.
+    pub fn volume(&self) -> &Arc<Volume> {
+        &self.volume
+    }
+
+    fn check_new_name(&self, name: &str) -> Result<(), &'static str> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err("tmpfs: invalid file name");
+        }
+        if self.children.contains_key(name) {
+            return Err("tmpfs: a file or directory with that name already exists");
+        }
+        Ok(())
+    }
+}
+
+impl Directory for TmpDirectory {
+    fn insert(&mut self, node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        let name = node.get_name();
+        self.modified = unix_time();
+        if let Some(mut old_node) = self.children.insert(name, node) {
+            old_node.set_parent_dir(Weak::<Mutex<TmpDirectory>>::new());
+            Ok(Some(old_node))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        self.children.get(name).cloned()
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.children.keys().cloned().collect()
+    }
+
+    /// Removes the given node from this directory.
+    /// A removed file's pages are freed once the last reference to it is dropped.
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        if let Some(mut old_node) = self.children.remove(&node.get_name()) {
+            self.modified = unix_time();
+            old_node.set_parent_dir(Weak::<Mutex<TmpDirectory>>::new());
+            Some(old_node)
+        } else {
+            None
+        }
+    }
+
+    fn create_file(&mut self, name: &str) -> Result<FileRef, &'static str> {
+        self.check_new_name(name)?;
+        let now = unix_time();
+        let credentials = access_control::current_credentials();
+        let file = TmpFile {
+            name: name.to_string(),
+            parent: self.self_ref.clone(),
+            volume: Arc::clone(&self.volume),
+            inode: self.volume.allocate_inode(),
+            permissions: DEFAULT_FILE_PERMISSIONS,
+            owner: credentials.uid,
+            group: credentials.gid,
+            created: now,
+            modified: now,
+            accessed: AtomicU64::new(now),
+            size: 0,
+            pages: BTreeMap::new(),
+        };
+        let file_ref = Arc::new(Mutex::new(file)) as FileRef;
+        self.children.insert(name.to_string(), FileOrDir::File(file_ref.clone()));
+        self.modified = now;
+        Ok(file_ref)
+    }
+
+    fn create_dir(&mut self, name: &str) -> Result<DirRef, &'static str> {
+        self.check_new_name(name)?;
+        let dir = TmpDirectory::new_ref(name.to_string(), self.self_ref.clone(), Arc::clone(&self.volume), DEFAULT_DIR_PERMISSIONS);
+        self.children.insert(name.to_string(), FileOrDir::Dir(dir.clone()));
+        self.modified = unix_time();
+        Ok(dir)
+    }
+}
+
+impl FsNode for TmpDirectory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            filesystem: Some(self.volume.id()),
+            inode: Some(self.inode),
+            created: Some(self.created),
+            modified: Some(self.modified),
+            accessed: None,
+            permissions: Some(self.permissions),
+            owner: Some(self.owner),
+            group: Some(self.group),
+            allocated_bytes: Some(0),
+        }
+    }
+
+    fn set_permissions(&mut self, permissions: u16) -> Result<(), &'static str> {
+        self.permissions = permissions & PERMISSION_BITS;
+        Ok(())
+    }
+
+    fn set_owner(&mut self, owner: u32, group: u32) -> Result<(), &'static str> {
+        self.owner = owner;
+        self.group = group;
+        Ok(())
+    }
+}
+
+
+/// A file in a tmpfs, whose contents are stored in pages that are allocated as they are written.
+pub struct TmpFile {
+    name: String,
+    parent: WeakDirRef,
+    volume: Arc<Volume>,
+    inode: u64,
+    permissions: u16,
+    owner: u32,
+    group: u32,
+    created: u64,
+    modified: u64,
+    /// The access time is updated by reads, which only borrow the file immutably.
+    accessed: AtomicU64,
+    size: usize,
+    /// The allocated pages of this file's contents, by page index; missing pages are holes that read as zeros.
+    pages: BTreeMap<usize, Box<[u8]>>,
+}
+
+impl TmpFile {
+    /// Returns the number of bytes of memory allocated for this file's contents.
+    fn allocated_bytes(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    /// Frees the pages that lie entirely at or beyond the byte offset `size`.
+    fn free_pages_from(&mut self, size: usize) {
+        let first_freed = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let freed = self.pages.split_off(&first_freed);
+        self.volume.release(freed.len() * PAGE_SIZE);
+    }
+}
+
+impl File for TmpFile {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        if offset > self.size {
+            return Err("read offset exceeds file size");
+        }
+        let read_bytes = min(self.size - offset, buffer.len());
+        let mut read = 0;
+        while read < read_bytes {
+            let pos = offset + read;
+            let within = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - within, read_bytes - read);
+            let dest = &mut buffer[read .. read + len];
+            match self.pages.get(&(pos / PAGE_SIZE)) {
+                Some(page) => dest.copy_from_slice(&page[within .. within + len]),
+                None => for byte in dest.iter_mut() { *byte = 0; },
+            }
+            read += len;
+        }
+        self.accessed.store(unix_time(), Ordering::Relaxed);
+        Ok(read_bytes)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(buffer.len()).ok_or("tmpfs: write would exceed the maximum file size")?;
+        // Reserve all of the new pages up front, so that a write that doesn't fit changes nothing.
+        let first_page = offset / PAGE_SIZE;
+        let last_page = (end - 1) / PAGE_SIZE;
+        let new_pages = (first_page ..= last_page).filter(|index| !self.pages.contains_key(index)).count();
+        self.volume.reserve(new_pages * PAGE_SIZE)?;
+
+        let mut written = 0;
+        while written < buffer.len() {
+            let pos = offset + written;
+            let within = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - within, buffer.len() - written);
+            let page = self.pages.entry(pos / PAGE_SIZE).or_insert_with(|| vec![0u8; PAGE_SIZE].into_boxed_slice());
+            page[within .. within + len].copy_from_slice(&buffer[written .. written + len]);
+            written += len;
+        }
+        if end > self.size {
+            self.size = end;
+        }
+        self.modified = unix_time();
+        Ok(buffer.len())
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("cannot treat a tmpfs file as a memory mapped region, use memory_mapped_file instead")
+    }
+
+    /// Shrinking a file frees its pages past the new end, while growing a file leaves a hole that reads as zeros.
+    fn truncate(&mut self, size: usize) -> Result<(), &'static str> {
+        if size < self.size {
+            self.free_pages_from(size);
+            // Zero the rest of the new last page, so that it reads as zeros if the file grows again.
+            let within = size % PAGE_SIZE;
+            if let Some(page) = self.pages.get_mut(&(size / PAGE_SIZE)) {
+                for byte in page[within ..].iter_mut() {
+                    *byte = 0;
+                }
+            }
+        }
+        self.size = size;
+        self.modified = unix_time();
+        Ok(())
+    }
+}
+
+impl FsNode for TmpFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            filesystem: Some(self.volume.id()),
+            inode: Some(self.inode),
+            created: Some(self.created),
+            modified: Some(self.modified),
+            accessed: Some(self.accessed.load(Ordering::Relaxed)),
+            permissions: Some(self.permissions),
+            owner: Some(self.owner),
+            group: Some(self.group),
+            allocated_bytes: Some(self.allocated_bytes()),
+        }
+    }
+
+    fn set_permissions(&mut self, permissions: u16) -> Result<(), &'static str> {
+        self.permissions = permissions & PERMISSION_BITS;
+        Ok(())
+    }
+
+    fn set_owner(&mut self, owner: u32, group: u32) -> Result<(), &'static str> {
+        self.owner = owner;
+        self.group = group;
+        Ok(())
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        self.volume.release(self.allocated_bytes());
+    }
+}
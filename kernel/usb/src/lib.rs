@@ -0,0 +1,390 @@
+//! Core USB definitions that are shared by host controller drivers and class drivers.
+//!
+//! This crate is independent of any particular host controller. It provides:
+//! * the standard descriptors (device, configuration, interface, and endpoint) and how to parse them,
+//! * the [`SetupPacket`](struct.SetupPacket.html) of control transfers and the standard requests,
+//! * the [`UsbHostController`](trait.UsbHostController.html) trait, through which enumeration
+//!   and class drivers issue control transfers to a device,
+//! * [`enumerate()`](fn.enumerate.html), which reads a newly-addressed device's descriptors and configures it,
+//! * the [`UsbClassDriver`](trait.UsbClassDriver.html) trait, which host controller drivers use
+//!   to hand data from a device's interrupt endpoints to the class driver bound to it.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+
+use core::fmt;
+use alloc::vec::Vec;
+
+
+// Bits of the `request_type` field of a setup packet.
+pub const REQUEST_TYPE_HOST_TO_DEVICE:   u8 = 0x00;
+pub const REQUEST_TYPE_DEVICE_TO_HOST:   u8 = 0x80;
+pub const REQUEST_TYPE_STANDARD:         u8 = 0x00;
+pub const REQUEST_TYPE_CLASS:            u8 = 0x20;
+pub const REQUEST_TYPE_VENDOR:           u8 = 0x40;
+pub const REQUEST_RECIPIENT_DEVICE:      u8 = 0x00;
+pub const REQUEST_RECIPIENT_INTERFACE:   u8 = 0x01;
+pub const REQUEST_RECIPIENT_ENDPOINT:    u8 = 0x02;
+
+// Standard requests
+pub const REQUEST_GET_STATUS:            u8 = 0x00;
+pub const REQUEST_CLEAR_FEATURE:         u8 = 0x01;
+pub const REQUEST_SET_FEATURE:           u8 = 0x03;
+pub const REQUEST_SET_ADDRESS:           u8 = 0x05;
+pub const REQUEST_GET_DESCRIPTOR:        u8 = 0x06;
+pub const REQUEST_GET_CONFIGURATION:     u8 = 0x08;
+pub const REQUEST_SET_CONFIGURATION:     u8 = 0x09;
+
+// Descriptor types
+pub const DESCRIPTOR_TYPE_DEVICE:        u8 = 0x01;
+pub const DESCRIPTOR_TYPE_CONFIGURATION: u8 = 0x02;
+pub const DESCRIPTOR_TYPE_STRING:        u8 = 0x03;
+pub const DESCRIPTOR_TYPE_INTERFACE:     u8 = 0x04;
+pub const DESCRIPTOR_TYPE_ENDPOINT:      u8 = 0x05;
+
+/// The length of a device descriptor.
+pub const DEVICE_DESCRIPTOR_LENGTH: usize = 18;
+/// The length of the fixed part of a configuration descriptor, which is followed by its interfaces and endpoints.
+pub const CONFIGURATION_DESCRIPTOR_LENGTH: usize = 9;
+
+
+/// An identifier that a host controller assigns to each device attached to it,
+/// e.g., an xHCI slot ID.
+pub type UsbDeviceId = u8;
+
+
+/// The speed at which a device communicates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsbSpeed {
+    /// USB 1.x, 1.5 Mbit/s
+    Low,
+    /// USB 1.x, 12 Mbit/s
+    Full,
+    /// USB 2.0, 480 Mbit/s
+    High,
+    /// USB 3.x, 5 Gbit/s or more
+    Super,
+}
+
+impl UsbSpeed {
+    /// Returns the maximum packet size of the default control endpoint to use
+    /// before the device descriptor has been read.
+    pub fn default_max_packet_size(&self) -> u16 {
+        match self {
+            UsbSpeed::Low | UsbSpeed::Full => 8,
+            UsbSpeed::High => 64,
+            UsbSpeed::Super => 512,
+        }
+    }
+}
+
+
+/// The 8-byte packet that begins every control transfer.
+#[derive(Clone, Copy, Debug)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// The number of bytes in the data stage of the transfer.
+    pub length: u16,
+}
+
+impl SetupPacket {
+    /// A standard GET_DESCRIPTOR request for `length` bytes of the descriptor of the given type and index.
+    pub fn get_descriptor(descriptor_type: u8, descriptor_index: u8, length: u16) -> SetupPacket {
+        SetupPacket {
+            request_type: REQUEST_TYPE_DEVICE_TO_HOST | REQUEST_TYPE_STANDARD | REQUEST_RECIPIENT_DEVICE,
+            request: REQUEST_GET_DESCRIPTOR,
+            value: (descriptor_type as u16) << 8 | descriptor_index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    /// A standard SET_CONFIGURATION request.
+    pub fn set_configuration(configuration_value: u8) -> SetupPacket {
+        SetupPacket {
+            request_type: REQUEST_TYPE_HOST_TO_DEVICE | REQUEST_TYPE_STANDARD | REQUEST_RECIPIENT_DEVICE,
+            request: REQUEST_SET_CONFIGURATION,
+            value: configuration_value as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    /// Returns `true` if the data stage of this transfer goes from the device to the host.
+    pub fn is_device_to_host(&self) -> bool {
+        self.request_type & REQUEST_TYPE_DEVICE_TO_HOST != 0
+    }
+
+    /// Returns this packet in the little-endian layout in which it is sent to the device.
+    pub fn to_u64(&self) -> u64 {
+        self.request_type as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
+
+
+/// Reads a little-endian `u16` at the given offset.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+
+/// A device descriptor, which describes a device as a whole.
+#[derive(Clone, Debug)]
+pub struct DeviceDescriptor {
+    /// The USB version in binary-coded decimal, e.g., `0x0200` for USB 2.0.
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    /// The maximum packet size of the default control endpoint.
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    pub manufacturer_index: u8,
+    pub product_index: u8,
+    pub serial_number_index: u8,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    /// Parses a device descriptor from the given bytes.
+    pub fn parse(data: &[u8]) -> Result<DeviceDescriptor, &'static str> {
+        if data.len() < DEVICE_DESCRIPTOR_LENGTH || data[1] != DESCRIPTOR_TYPE_DEVICE {
+            return Err("USB: invalid device descriptor");
+        }
+        Ok(DeviceDescriptor {
+            usb_version:            read_u16(data, 2),
+            class:                  data[4],
+            subclass:               data[5],
+            protocol:               data[6],
+            max_packet_size0:       data[7],
+            vendor_id:              read_u16(data, 8),
+            product_id:             read_u16(data, 10),
+            device_version:         read_u16(data, 12),
+            manufacturer_index:     data[14],
+            product_index:          data[15],
+            serial_number_index:    data[16],
+            num_configurations:     data[17],
+        })
+    }
+}
+
+
+/// The way that data is transferred to or from an endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+/// An endpoint descriptor, which describes one endpoint of an interface.
+#[derive(Clone, Copy, Debug)]
+pub struct EndpointDescriptor {
+    /// The endpoint number in bits 0-3, and the direction in bit 7 (1 means IN, i.e., device to host).
+    pub address: u8,
+    /// The transfer type in bits 0-1.
+    pub attributes: u8,
+    /// The maximum packet size in bits 0-10.
+    pub max_packet_size: u16,
+    /// The polling interval, whose unit depends on the device's speed.
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    /// Returns the endpoint number, from 0 to 15.
+    pub fn number(&self) -> u8 {
+        self.address & 0xF
+    }
+
+    /// Returns `true` if this is an IN endpoint, i.e., data goes from the device to the host.
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0x3 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// Returns the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.max_packet_size & 0x7FF
+    }
+}
+
+/// An interface descriptor, along with the descriptors of its endpoints.
+#[derive(Clone, Debug)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+/// A configuration descriptor, along with the descriptors of its interfaces.
+///
+/// Class-specific descriptors, e.g., HID descriptors, are skipped.
+#[derive(Clone, Debug)]
+pub struct ConfigurationDescriptor {
+    /// The value to pass to SET_CONFIGURATION to select this configuration.
+    pub value: u8,
+    pub attributes: u8,
+    /// The maximum power consumption in units of 2 mA.
+    pub max_power: u8,
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+impl ConfigurationDescriptor {
+    /// Parses a complete configuration descriptor, i.e., all `wTotalLength` bytes of it.
+    pub fn parse(data: &[u8]) -> Result<ConfigurationDescriptor, &'static str> {
+        if data.len() < CONFIGURATION_DESCRIPTOR_LENGTH || data[1] != DESCRIPTOR_TYPE_CONFIGURATION {
+            return Err("USB: invalid configuration descriptor");
+        }
+        let total_length = core::cmp::min(read_u16(data, 2) as usize, data.len());
+        let mut config = ConfigurationDescriptor {
+            value: data[5],
+            attributes: data[7],
+            max_power: data[8],
+            interfaces: Vec::new(),
+        };
+
+        let mut offset = data[0] as usize;
+        while offset + 2 <= total_length {
+            let length = data[offset] as usize;
+            if length < 2 || offset + length > total_length {
+                return Err("USB: malformed descriptor in configuration descriptor");
+            }
+            let descriptor = &data[offset .. offset + length];
+            match descriptor[1] {
+                DESCRIPTOR_TYPE_INTERFACE if length >= 9 => {
+                    config.interfaces.push(InterfaceDescriptor {
+                        number:             descriptor[2],
+                        alternate_setting:  descriptor[3],
+                        class:              descriptor[5],
+                        subclass:           descriptor[6],
+                        protocol:           descriptor[7],
+                        endpoints:          Vec::new(),
+                    });
+                }
+                DESCRIPTOR_TYPE_ENDPOINT if length >= 7 => {
+                    let endpoint = EndpointDescriptor {
+                        address:            descriptor[2],
+                        attributes:         descriptor[3],
+                        max_packet_size:    read_u16(descriptor, 4),
+                        interval:           descriptor[6],
+                    };
+                    match config.interfaces.last_mut() {
+                        Some(interface) => interface.endpoints.push(endpoint),
+                        None => warn!("USB: ignoring endpoint descriptor that precedes all interface descriptors"),
+                    }
+                }
+                _ => { }
+            }
+            offset += length;
+        }
+        Ok(config)
+    }
+}
+
+
+/// The functionality that every USB host controller driver must provide
+/// for devices to be enumerated and for class drivers to configure them.
+pub trait UsbHostController {
+    /// Performs a control transfer on the default control endpoint of the given device.
+    ///
+    /// The `data` buffer must be `setup.length` bytes long; it is filled by the device if
+    /// the transfer is device-to-host, or sent to the device otherwise.
+    fn control_transfer(&mut self, device: UsbDeviceId, setup: SetupPacket, data: &mut [u8]) -> Result<(), &'static str>;
+
+    /// Updates the maximum packet size of the given device's default control endpoint,
+    /// once it has been read from the first bytes of the device descriptor.
+    fn set_max_packet_size0(&mut self, _device: UsbDeviceId, _max_packet_size: u16) -> Result<(), &'static str> {
+        Ok(())
+    }
+}
+
+
+/// A device that has been enumerated and configured.
+#[derive(Clone, Debug)]
+pub struct UsbDevice {
+    pub id: UsbDeviceId,
+    pub speed: UsbSpeed,
+    pub descriptor: DeviceDescriptor,
+    /// The active configuration, which is always the first one.
+    pub configuration: ConfigurationDescriptor,
+}
+
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "USB device {} ({:04x}:{:04x}, {:?} speed, class {:#X})",
+            self.id, self.descriptor.vendor_id, self.descriptor.product_id, self.speed, self.descriptor.class
+        )
+    }
+}
+
+
+/// Enumerates a device that the host controller has just addressed as `device`:
+/// reads its device descriptor and first configuration descriptor, then selects that configuration.
+pub fn enumerate<C: UsbHostController + ?Sized>(controller: &mut C, device: UsbDeviceId, speed: UsbSpeed) -> Result<UsbDevice, &'static str> {
+    // Only the first 8 bytes of the device descriptor are guaranteed to fit in the first packet,
+    // and they contain the real maximum packet size of the default control endpoint.
+    let mut data = [0u8; DEVICE_DESCRIPTOR_LENGTH];
+    controller.control_transfer(device, SetupPacket::get_descriptor(DESCRIPTOR_TYPE_DEVICE, 0, 8), &mut data[..8])?;
+    let max_packet_size0 = match speed {
+        // For SuperSpeed devices, the field is an exponent.
+        UsbSpeed::Super => 1u16 << data[7],
+        _ => data[7] as u16,
+    };
+    if max_packet_size0 != speed.default_max_packet_size() {
+        controller.set_max_packet_size0(device, max_packet_size0)?;
+    }
+
+    controller.control_transfer(device, SetupPacket::get_descriptor(DESCRIPTOR_TYPE_DEVICE, 0, DEVICE_DESCRIPTOR_LENGTH as u16), &mut data)?;
+    let descriptor = DeviceDescriptor::parse(&data)?;
+    if descriptor.num_configurations == 0 {
+        return Err("USB: device has no configurations");
+    }
+
+    let mut header = [0u8; CONFIGURATION_DESCRIPTOR_LENGTH];
+    controller.control_transfer(device, SetupPacket::get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, 0, header.len() as u16), &mut header)?;
+    let total_length = read_u16(&header, 2);
+    let mut config_data = vec![0u8; total_length as usize];
+    controller.control_transfer(device, SetupPacket::get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, 0, total_length), &mut config_data)?;
+    let configuration = ConfigurationDescriptor::parse(&config_data)?;
+
+    controller.control_transfer(device, SetupPacket::set_configuration(configuration.value), &mut [])?;
+
+    let usb_device = UsbDevice { id: device, speed, descriptor, configuration };
+    debug!("Enumerated {}", usb_device);
+    Ok(usb_device)
+}
+
+
+/// A class driver that has been bound to one interface of a device,
+/// which receives the data that the host controller reads from that interface's interrupt IN endpoint.
+pub trait UsbClassDriver: Send {
+    /// Returns a short name for this driver, used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Handles one transfer's worth of data received from the interrupt IN endpoint.
+    /// This is typically invoked from the host controller's interrupt handler, so it must not block.
+    fn handle_interrupt_in(&mut self, data: &[u8]);
+}
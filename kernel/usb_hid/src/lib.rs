@@ -0,0 +1,223 @@
+//! USB HID (Human Interface Device) class drivers for keyboards and mice.
+//!
+//! These drivers put devices into the simple "boot protocol" defined by the HID specification,
+//! whose fixed report formats don't require parsing HID report descriptors.
+//! Reports are translated into the same input that the PS/2 drivers produce
+//! (scancodes for the `keyboard` crate and packets for the `mouse` crate),
+//! so USB and PS/2 input devices feed the same keyboard and mouse event queues.
+//!
+//! Unlike PS/2 keyboards, USB keyboards don't repeat held keys; only presses and releases are reported.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate usb;
+extern crate keycodes_ascii;
+extern crate keyboard;
+extern crate mouse;
+
+use alloc::boxed::Box;
+use keycodes_ascii::KEY_RELEASED_OFFSET;
+use usb::{
+    EndpointDescriptor, InterfaceDescriptor, SetupPacket, TransferType, UsbClassDriver, UsbDevice, UsbHostController,
+    REQUEST_TYPE_HOST_TO_DEVICE, REQUEST_TYPE_CLASS, REQUEST_RECIPIENT_INTERFACE,
+};
+
+
+/// The interface class of HID devices.
+pub const HID_CLASS: u8 = 0x03;
+/// The interface subclass of HID devices that support the boot protocol.
+pub const HID_SUBCLASS_BOOT: u8 = 0x01;
+/// The interface protocol of boot keyboards.
+pub const HID_PROTOCOL_KEYBOARD: u8 = 0x01;
+/// The interface protocol of boot mice.
+pub const HID_PROTOCOL_MOUSE: u8 = 0x02;
+
+// HID class requests
+const HID_REQUEST_SET_IDLE:     u8 = 0x0A;
+const HID_REQUEST_SET_PROTOCOL: u8 = 0x0B;
+/// The value of SET_PROTOCOL that selects the boot protocol.
+const HID_BOOT_PROTOCOL: u16 = 0;
+
+/// The length of a boot keyboard report: modifiers, a reserved byte, and up to six pressed keys.
+const KEYBOARD_REPORT_LENGTH: usize = 8;
+/// The key usage that a keyboard reports in every key slot when too many keys are pressed.
+const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+
+
+/// Binds a HID class driver to the given interface of an enumerated device,
+/// if it is a boot keyboard or boot mouse, and switches the interface to the boot protocol.
+///
+/// Returns the interrupt IN endpoint from which the host controller should read reports,
+/// along with the driver that handles them, or `None` if this interface isn't supported.
+pub fn bind<C: UsbHostController + ?Sized>(
+    controller: &mut C,
+    device: &UsbDevice,
+    interface: &InterfaceDescriptor,
+) -> Result<Option<(EndpointDescriptor, Box<dyn UsbClassDriver>)>, &'static str> {
+    if interface.class != HID_CLASS || interface.subclass != HID_SUBCLASS_BOOT || interface.alternate_setting != 0 {
+        return Ok(None);
+    }
+    let driver: Box<dyn UsbClassDriver> = match interface.protocol {
+        HID_PROTOCOL_KEYBOARD => Box::new(HidKeyboard { previous_report: [0; KEYBOARD_REPORT_LENGTH] }),
+        HID_PROTOCOL_MOUSE => Box::new(HidMouse),
+        _ => return Ok(None),
+    };
+    let endpoint = match interface.endpoints.iter().find(|ep| ep.is_in() && ep.transfer_type() == TransferType::Interrupt) {
+        Some(ep) => *ep,
+        None => return Err("USB HID interface has no interrupt IN endpoint"),
+    };
+
+    let class_request = |request: u8, value: u16| SetupPacket {
+        request_type: REQUEST_TYPE_HOST_TO_DEVICE | REQUEST_TYPE_CLASS | REQUEST_RECIPIENT_INTERFACE,
+        request,
+        value,
+        index: interface.number as u16,
+        length: 0,
+    };
+    controller.control_transfer(device.id, class_request(HID_REQUEST_SET_PROTOCOL, HID_BOOT_PROTOCOL), &mut [])?;
+    // Only send a report when something changes; an idle rate of 0 means "indefinite".
+    // Some devices don't support SET_IDLE, which is harmless.
+    if let Err(_e) = controller.control_transfer(device.id, class_request(HID_REQUEST_SET_IDLE, 0), &mut []) {
+        debug!("USB HID: device {} doesn't support SET_IDLE: {}", device.id, _e);
+    }
+
+    info!("USB HID: bound {} driver to interface {} of {}", driver.name(), interface.number, device);
+    Ok(Some((endpoint, driver)))
+}
+
+
+/// A boot-protocol keyboard.
+///
+/// Each report lists the keys that are currently pressed,
+/// so presses and releases are found by comparing it to the previous report.
+struct HidKeyboard {
+    previous_report: [u8; KEYBOARD_REPORT_LENGTH],
+}
+
+impl UsbClassDriver for HidKeyboard {
+    fn name(&self) -> &'static str {
+        "keyboard"
+    }
+
+    fn handle_interrupt_in(&mut self, data: &[u8]) {
+        if data.len() < KEYBOARD_REPORT_LENGTH {
+            return;
+        }
+        let mut report = [0u8; KEYBOARD_REPORT_LENGTH];
+        report.copy_from_slice(&data[..KEYBOARD_REPORT_LENGTH]);
+        if report[2..].contains(&USAGE_ERROR_ROLL_OVER) {
+            return;
+        }
+        let previous = self.previous_report;
+
+        // The modifier keys are reported as bits in the first byte.
+        let changed_modifiers = previous[0] ^ report[0];
+        for (bit, &(scancode, extended)) in MODIFIER_SCANCODES.iter().enumerate() {
+            if changed_modifiers & (1 << bit) != 0 {
+                send_scancode(scancode, extended, report[0] & (1 << bit) == 0);
+            }
+        }
+
+        for &usage in previous[2..].iter().filter(|&&u| u != 0 && !report[2..].contains(&u)) {
+            send_key(usage, true);
+        }
+        for &usage in report[2..].iter().filter(|&&u| u != 0 && !previous[2..].contains(&u)) {
+            send_key(usage, false);
+        }
+        self.previous_report = report;
+    }
+}
+
+/// Sends the scancode of the given key usage to the keyboard driver.
+fn send_key(usage: u8, released: bool) {
+    match usage_to_scancode(usage) {
+        Some((scancode, extended)) => send_scancode(scancode, extended, released),
+        None => debug!("USB HID: ignoring unsupported key usage {:#X}", usage),
+    }
+}
+
+fn send_scancode(scancode: u8, extended: bool, released: bool) {
+    let scancode = if released { scancode + KEY_RELEASED_OFFSET } else { scancode };
+    if let Err(_e) = keyboard::handle_keyboard_input(scancode, extended) {
+        warn!("USB HID: error handling keyboard input: {}", _e);
+    }
+}
+
+/// The scancode set 1 scancodes of the modifier keys, in the order of their bits in a keyboard report:
+/// left control, left shift, left alt, left GUI, right control, right shift, right alt, right GUI.
+/// The second element is whether the scancode is an extended (0xE0-prefixed) one.
+const MODIFIER_SCANCODES: [(u8, bool); 8] = [
+    (0x1D, false), (0x2A, false), (0x38, false), (0x5B, true),
+    (0x1D, true),  (0x36, false), (0x38, true),  (0x5C, true),
+];
+
+/// Translates a HID keyboard usage into a scancode set 1 scancode and whether it is an extended one.
+fn usage_to_scancode(usage: u8) -> Option<(u8, bool)> {
+    /// The scancodes of usages 0x04 (the "A" key) through 0x65 (the application/menu key).
+    /// Extended scancodes are marked by 0x80, which no valid make code has.
+    const SCANCODES: [u8; 0x62] = [
+        0x1E, 0x30, 0x2E, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32, 0x31, 0x18, 0x19, // a - p
+        0x10, 0x13, 0x1F, 0x14, 0x16, 0x2F, 0x11, 0x2D, 0x15, 0x2C,                                     // q - z
+        0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,                                     // 1 - 0
+        0x1C, 0x01, 0x0E, 0x0F, 0x39, 0x0C, 0x0D, 0x1A, 0x1B, 0x2B, 0x2B, 0x27, 0x28, 0x29, 0x33, 0x34, // enter - .
+        0x35, 0x3A,                                                                                     // /, caps lock
+        0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x40, 0x41, 0x42, 0x43, 0x44, 0x57, 0x58,                         // F1 - F12
+        0x80 | 0x37, 0x46, 0x00,                                        // print screen, scroll lock, pause (unsupported)
+        0x80 | 0x52, 0x80 | 0x47, 0x80 | 0x49, 0x80 | 0x53, 0x80 | 0x4F, 0x80 | 0x51,  // insert, home, page up, delete, end, page down
+        0x80 | 0x4D, 0x80 | 0x4B, 0x80 | 0x50, 0x80 | 0x48,                            // right, left, down, up
+        0x45, 0x80 | 0x35, 0x37, 0x4A, 0x4E, 0x80 | 0x1C,                              // num lock, keypad / * - + enter
+        0x4F, 0x50, 0x51, 0x4B, 0x4C, 0x4D, 0x47, 0x48, 0x49, 0x52, 0x53,              // keypad 1 - 9, 0, .
+        0x56, 0x80 | 0x5D,                                                             // non-US \, application
+    ];
+    let index = usage.checked_sub(0x04)? as usize;
+    match *SCANCODES.get(index)? {
+        0 => None,
+        code => Some((code & 0x7F, code & 0x80 != 0)),
+    }
+}
+
+
+/// A boot-protocol mouse.
+///
+/// Each report contains the buttons, the X and Y displacements, and optionally the wheel displacement,
+/// which are converted into a PS/2 mouse packet.
+struct HidMouse;
+
+impl UsbClassDriver for HidMouse {
+    fn name(&self) -> &'static str {
+        "mouse"
+    }
+
+    fn handle_interrupt_in(&mut self, data: &[u8]) {
+        if data.len() < 3 {
+            return;
+        }
+        let buttons = data[0];
+        let dx = data[1] as i8;
+        // In HID reports positive Y is down, whereas in PS/2 packets it is up.
+        let dy = (-(data[2] as i8 as i16)).max(-128).min(127) as i8;
+        let wheel = data.get(3).map(|&w| w as i8).unwrap_or(0);
+
+        // The first byte has the left, right, and middle buttons, an always-set bit, and the X and Y sign bits.
+        let mut first_byte = (buttons & 0x07) | 0x08;
+        if dx < 0 { first_byte |= 0x10; }
+        if dy < 0 { first_byte |= 0x20; }
+        // The fourth byte has the wheel movement and the fourth and fifth buttons.
+        let wheel_bits = match wheel {
+            w if w > 0 => 0x01,
+            w if w < 0 => 0x0F,
+            _ => 0x00,
+        };
+        let fourth_byte = wheel_bits | ((buttons & 0x18) << 1);
+
+        let packet = first_byte as u32
+            | (dx as u8 as u32) << 8
+            | (dy as u8 as u32) << 16
+            | (fourth_byte as u32) << 24;
+        if let Err(_e) = mouse::handle_mouse_input(packet) {
+            warn!("USB HID: error handling mouse input: {}", _e);
+        }
+    }
+}
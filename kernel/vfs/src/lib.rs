@@ -0,0 +1,373 @@
+#![no_std]
+
+//! The mount table of the virtual filesystem, and the registry of filesystem types that can be mounted.
+//!
+//! A filesystem driver implements the [`FileSystem`] trait and registers it with
+//! [`register_filesystem()`](fn.register_filesystem.html), after which its volumes can be attached
+//! to the directory tree with [`mount()`](fn.mount.html) and detached with [`unmount()`](fn.unmount.html).
+//!
+//! Mounting a filesystem over a directory (the "mount point") replaces that directory within its parent
+//! by the root directory of the mounted filesystem, which has the same name.
+//! Thus, path resolution crosses mount points without any special handling:
+//! descending into the mount point's name yields the mounted filesystem's root,
+//! and `..` from that root yields the mount point's parent.
+//! The covered directory is kept in the mount table and put back when the filesystem is unmounted.
+//!
+//! Block devices are named after their `storage_manager` block queue, e.g., `disk0`,
+//! and a partition of a device is named by appending its MBR partition number, e.g., `disk0p1`.
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate fs_node;
+extern crate vfs_node;
+extern crate root;
+extern crate path;
+extern crate task;
+extern crate block_io;
+extern crate storage_manager;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use fs_node::{DirRef, WeakDirRef, FileOrDir, FsNode};
+use vfs_node::VFSDirectory;
+use path::Path;
+use block_io::BlockQueue;
+
+
+/// The name of the directory within the root directory in which [`automount()`](fn.automount.html) mounts volumes.
+pub const MOUNT_DIRECTORY_NAME: &'static str = "mnt";
+
+
+/// A type of filesystem that can be mounted into the VFS, e.g., FAT32 or ext2.
+pub trait FileSystem: Send + Sync {
+    /// Returns the name of this filesystem type, e.g., `"fat32"`, which is used to choose it when mounting.
+    fn name(&self) -> &'static str;
+
+    /// Returns whether volumes of this type are stored on a block device.
+    ///
+    /// The default implementation returns `true`.
+    /// In-memory filesystems return `false`, and are mounted without a device.
+    fn requires_device(&self) -> bool {
+        true
+    }
+
+    /// Returns the volumes of this type that are on the given block device,
+    /// each given as its MBR partition number, or `None` if the volume spans the whole device.
+    ///
+    /// The default implementation finds no volumes.
+    fn probe(&self, _queue: &BlockQueue) -> Vec<Option<usize>> {
+        Vec::new()
+    }
+
+    /// Opens the volume on the given `device` (or a new volume, if this filesystem doesn't use devices)
+    /// and returns its root directory, which must be named `name` and have the given `parent`.
+    /// The `options` are a comma-separated list of filesystem-specific mount options, see [`parse_options()`].
+    ///
+    /// The root directory must not be inserted into its `parent`; [`mount()`](fn.mount.html) does that.
+    fn mount(&self, device: Option<&Device>, name: String, parent: WeakDirRef, options: &str) -> Result<DirRef, &'static str>;
+
+    /// Prepares the volume with the given `root` directory to be detached, e.g., by writing back its changes.
+    ///
+    /// The default implementation does nothing.
+    fn unmount(&self, _root: &DirRef, _device: Option<&Device>) -> Result<(), &'static str> {
+        Ok(())
+    }
+}
+
+
+/// A block device, or a partition of a block device, that holds a volume.
+#[derive(Clone)]
+pub struct Device {
+    /// The name of the device, e.g., `disk0` or `disk0p1`.
+    pub name: String,
+    /// The request queue of the block device.
+    pub queue: BlockQueue,
+    /// The MBR partition number, or `None` for a volume that spans the whole device.
+    pub partition: Option<usize>,
+}
+
+impl Device {
+    /// Returns the device with the given name, e.g., `disk0` or `disk0p1`.
+    pub fn find(name: &str) -> Result<Device, &'static str> {
+        let queues = storage_manager::BLOCK_QUEUES.lock();
+        for queue in queues.iter() {
+            if !name.starts_with(queue.name()) {
+                continue;
+            }
+            let partition = match &name[queue.name().len() ..] {
+                "" => None,
+                rest if rest.starts_with('p') => match rest[1..].parse::<usize>() {
+                    Ok(number) => Some(number),
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            return Ok(Device { name: name.to_string(), queue: queue.clone(), partition });
+        }
+        Err("no such block device")
+    }
+}
+
+
+/// Information about a mounted filesystem.
+#[derive(Clone, Debug)]
+pub struct MountInfo {
+    /// The name of the mounted device, or `"none"` for a filesystem without a device.
+    pub device: String,
+    /// The absolute path of the mount point.
+    pub path: String,
+    /// The name of the filesystem type.
+    pub fstype: &'static str,
+    /// The mount options given when the filesystem was mounted.
+    pub options: String,
+}
+
+/// An entry in the mount table.
+struct Mount {
+    info: MountInfo,
+    filesystem: Arc<dyn FileSystem>,
+    device: Option<Device>,
+    /// The root directory of the mounted filesystem.
+    root: DirRef,
+    /// The directory that the mounted filesystem replaced in the directory tree.
+    covered: DirRef,
+}
+
+lazy_static! {
+    /// The registered filesystem types, by name.
+    static ref FILESYSTEMS: Mutex<BTreeMap<&'static str, Arc<dyn FileSystem>>> = Mutex::new(BTreeMap::new());
+    /// The mounted filesystems, in the order they were mounted.
+    static ref MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+}
+
+
+/// Registers the given filesystem type so that its volumes can be mounted.
+pub fn register_filesystem(filesystem: Arc<dyn FileSystem>) -> Result<(), &'static str> {
+    let mut filesystems = FILESYSTEMS.lock();
+    if filesystems.contains_key(filesystem.name()) {
+        return Err("a filesystem type with that name is already registered");
+    }
+    filesystems.insert(filesystem.name(), filesystem);
+    Ok(())
+}
+
+/// Returns the names of the registered filesystem types.
+pub fn filesystems() -> Vec<&'static str> {
+    FILESYSTEMS.lock().keys().cloned().collect()
+}
+
+/// Returns the registered filesystem type with the given name.
+fn filesystem(fstype: &str) -> Result<Arc<dyn FileSystem>, &'static str> {
+    FILESYSTEMS.lock().get(fstype).cloned().ok_or("unknown filesystem type")
+}
+
+/// Returns the name of the first registered filesystem type that finds a volume on the given device.
+pub fn detect_filesystem(device: &str) -> Result<&'static str, &'static str> {
+    let device = Device::find(device)?;
+    let filesystems: Vec<Arc<dyn FileSystem>> = FILESYSTEMS.lock().values().cloned().collect();
+    filesystems.iter()
+        .filter(|fs| fs.requires_device())
+        .find(|fs| fs.probe(&device.queue).contains(&device.partition))
+        .map(|fs| fs.name())
+        .ok_or("couldn't detect the type of filesystem on the device")
+}
+
+/// Parses a comma-separated list of mount options, like `"size=16M,mode=1777"`,
+/// into each option's name and its value, if it has one.
+pub fn parse_options<'a>(options: &'a str) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+    options.split(',')
+        .filter(|option| !option.is_empty())
+        .map(|option| match option.find('=') {
+            Some(i) => (&option[.. i], Some(&option[i + 1 ..])),
+            None => (option, None),
+        })
+}
+
+/// Returns the currently mounted filesystems, in the order they were mounted.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS.lock().iter().map(|m| m.info.clone()).collect()
+}
+
+
+/// Mounts the volume of type `fstype` on the given `device` at the directory with the given absolute `path`.
+///
+/// Filesystems that don't use a device ignore the `device`, which may then be `None`.
+/// The mount point must be an existing directory other than the root directory,
+/// and its parent directory must allow its children to be replaced, i.e., it must not be on a storage device.
+///
+/// Returns the root directory of the mounted filesystem.
+pub fn mount(device: Option<&str>, path: &str, fstype: &str) -> Result<DirRef, &'static str> {
+    mount_with_options(device, path, fstype, "")
+}
+
+/// Like [`mount()`](fn.mount.html), but passes the given comma-separated list of mount `options`
+/// to the filesystem, e.g., `"size=16M"`.
+pub fn mount_with_options(device: Option<&str>, path: &str, fstype: &str, options: &str) -> Result<DirRef, &'static str> {
+    let filesystem = filesystem(fstype)?;
+    let device = if filesystem.requires_device() {
+        Some(Device::find(device.ok_or("this filesystem type requires a device")?)?)
+    } else {
+        None
+    };
+    let mount_point = match resolve(path)? {
+        FileOrDir::Dir(dir) => dir,
+        FileOrDir::File(_) => return Err("the mount point is not a directory"),
+    };
+    if Arc::ptr_eq(&mount_point, root::get_root()) {
+        return Err("cannot mount over the root directory");
+    }
+    let (name, parent, mount_path) = {
+        let locked = mount_point.lock();
+        (locked.get_name(), locked.get_parent_dir().ok_or("the mount point has no parent directory")?, locked.get_absolute_path())
+    };
+
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|m| Arc::ptr_eq(&m.root, &mount_point)) {
+        return Err("a filesystem is already mounted at that directory");
+    }
+    if let Some(ref device) = device {
+        if mounts.iter().any(|m| m.info.device == device.name) {
+            return Err("that device is already mounted");
+        }
+    }
+
+    let root = filesystem.mount(device.as_ref(), name, Arc::downgrade(&parent), options)?;
+    let inserted = parent.lock().insert(FileOrDir::Dir(root.clone()));
+    let covered = match inserted {
+        Ok(Some(FileOrDir::Dir(ref covered))) if Arc::ptr_eq(covered, &mount_point) => mount_point,
+        Ok(replaced) => {
+            // The mount point must have been removed or replaced meanwhile, so undo the insertion.
+            parent.lock().remove(&FileOrDir::Dir(root.clone()));
+            if let Some(mut replaced) = replaced {
+                replaced.set_parent_dir(Arc::downgrade(&parent));
+                let _ = parent.lock().insert(replaced);
+            }
+            let _ = filesystem.unmount(&root, device.as_ref());
+            return Err("the mount point was removed while mounting");
+        }
+        Err(e) => {
+            let _ = filesystem.unmount(&root, device.as_ref());
+            return Err(e);
+        }
+    };
+
+    let info = MountInfo {
+        device: device.as_ref().map(|d| d.name.clone()).unwrap_or_else(|| "none".to_string()),
+        path: mount_path,
+        fstype: filesystem.name(),
+        options: options.to_string(),
+    };
+    info!("vfs: mounted {} ({}) at {}", info.device, info.fstype, info.path);
+    mounts.push(Mount { info, filesystem, device, root: root.clone(), covered });
+    Ok(root)
+}
+
+/// Unmounts the filesystem mounted at the directory with the given absolute `path`,
+/// putting back the directory it covered.
+///
+/// Fails if the filesystem is busy: if another filesystem is mounted within it,
+/// if a task's working directory is within it, or if its root directory is otherwise in use.
+pub fn unmount(path: &str) -> Result<(), &'static str> {
+    let target = match resolve(path)? {
+        FileOrDir::Dir(dir) => dir,
+        FileOrDir::File(_) => return Err("not a mount point"),
+    };
+    let mut mounts = MOUNTS.lock();
+    let index = mounts.iter().position(|m| Arc::ptr_eq(&m.root, &target)).ok_or("not a mount point")?;
+    drop(target);
+
+    {
+        let mount = &mounts[index];
+        let nested_prefix = format!("{}/", mount.info.path);
+        if mounts.iter().any(|m| m.info.path.starts_with(&nested_prefix)) {
+            return Err("target is busy: another filesystem is mounted within it");
+        }
+        if is_working_dir_within(&mount.root) {
+            return Err("target is busy: a task's working directory is within it");
+        }
+        // The only references to the root directory should be in the mount table and in its parent.
+        if Arc::strong_count(&mount.root) > 2 {
+            return Err("target is busy: its root directory is in use");
+        }
+        mount.filesystem.unmount(&mount.root, mount.device.as_ref())?;
+    }
+
+    let mount = mounts.remove(index);
+    let parent = mount.root.lock().get_parent_dir().ok_or("the mounted filesystem has no parent directory")?;
+    parent.lock().remove(&FileOrDir::Dir(mount.root.clone()));
+    mount.covered.lock().set_parent_dir(Arc::downgrade(&parent));
+    parent.lock().insert(FileOrDir::Dir(mount.covered.clone()))?;
+    info!("vfs: unmounted {} ({}) from {}", mount.info.device, mount.info.fstype, mount.info.path);
+    Ok(())
+}
+
+/// Mounts every volume of type `fstype` on the storage devices known to the `storage_manager`
+/// at `/mnt/<device>`, creating that directory if it doesn't yet exist.
+///
+/// Volumes that fail to mount are logged and skipped.
+pub fn automount(fstype: &str) -> Result<(), &'static str> {
+    let filesystem = filesystem(fstype)?;
+    let queues = storage_manager::BLOCK_QUEUES.lock().clone();
+    for queue in queues {
+        for partition in filesystem.probe(&queue) {
+            let device = match partition {
+                Some(number) => format!("{}p{}", queue.name(), number),
+                None => queue.name().to_string(),
+            };
+            let mount_dir = get_or_create_dir(root::get_root(), MOUNT_DIRECTORY_NAME)?;
+            let result = get_or_create_dir(&mount_dir, &device)
+                .and_then(|_| mount(Some(&device), &format!("/{}/{}", MOUNT_DIRECTORY_NAME, device), fstype));
+            if let Err(e) = result {
+                error!("vfs: couldn't mount {} ({}): {}", device, fstype, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the directory named `name` in the `parent` directory, creating it if it doesn't yet exist.
+fn get_or_create_dir(parent: &DirRef, name: &str) -> Result<DirRef, &'static str> {
+    let existing = parent.lock().get(name);
+    match existing {
+        Some(FileOrDir::Dir(dir)) => Ok(dir),
+        Some(FileOrDir::File(_)) => Err("a file exists where a directory was expected"),
+        None => VFSDirectory::new(name.to_string(), parent),
+    }
+}
+
+/// Returns the node at the given absolute `path`.
+fn resolve(path: &str) -> Result<FileOrDir, &'static str> {
+    let path = Path::new(path.to_string());
+    if !path.is_absolute() {
+        return Err("the path must be absolute");
+    }
+    Path::get_absolute(&path).ok_or("no such file or directory")
+}
+
+/// Returns whether any task's working directory is the given directory or within it.
+fn is_working_dir_within(dir: &DirRef) -> bool {
+    // Don't hold the task list lock while locking each task.
+    let all_tasks: Vec<task::TaskRef> = task::TASKLIST.lock().values().cloned().collect();
+    all_tasks.iter().any(|taskref| {
+        let mut curr = Arc::clone(&taskref.get_env().lock().working_dir);
+        loop {
+            if Arc::ptr_eq(&curr, dir) {
+                return true;
+            }
+            // The root directory is its own parent.
+            if Arc::ptr_eq(&curr, root::get_root()) {
+                return false;
+            }
+            let parent = curr.lock().get_parent_dir();
+            match parent {
+                Some(parent) => curr = parent,
+                None => return false,
+            }
+        }
+    })
+}
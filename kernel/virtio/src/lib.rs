@@ -0,0 +1,412 @@
+//! The virtio PCI transport, which is shared by all virtio device drivers.
+//!
+//! Virtio devices are the paravirtualized devices offered by QEMU, KVM, and other hypervisors.
+//! Rather than emulating real hardware, they exchange buffers with the driver through
+//! [`Virtqueue`](struct.Virtqueue.html)s in memory, which makes them simple and fast.
+//!
+//! This crate implements the "modern" (virtio 1.0) PCI transport,
+//! in which vendor-specific PCI capabilities point to the device's configuration structures in its BARs.
+//! A device driver, e.g., `virtio_blk` or `virtio_net`, uses it as follows:
+//! 1. [`VirtioTransport::new()`] finds and maps the configuration structures and resets the device.
+//! 2. [`negotiate_features()`] agrees on the set of features that both the device and the driver support.
+//! 3. [`setup_interrupts()`] optionally enables MSI-X vectors,
+//!    and [`setup_queue()`] creates each of the device's virtqueues.
+//! 4. [`driver_ok()`] tells the device that the driver is ready to use it.
+//!
+//! [`VirtioTransport::new()`]: struct.VirtioTransport.html#method.new
+//! [`negotiate_features()`]: struct.VirtioTransport.html#method.negotiate_features
+//! [`setup_interrupts()`]: struct.VirtioTransport.html#method.setup_interrupts
+//! [`setup_queue()`]: struct.VirtioTransport.html#method.setup_queue
+//! [`driver_ok()`]: struct.VirtioTransport.html#method.driver_ok
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate volatile;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate memory;
+extern crate pci;
+extern crate pause;
+
+mod virtqueue;
+pub use virtqueue::{Virtqueue, VirtqueueBuffer};
+
+use core::{
+    mem::size_of,
+    ops::DerefMut,
+};
+use alloc::vec::Vec;
+use volatile::Volatile;
+use zerocopy::FromBytes;
+use x86_64::structures::idt::HandlerFunc;
+use memory::{
+    EntryFlags, FrameRange, MappedPages,
+    allocate_pages_by_bytes, get_frame_allocator_ref, get_kernel_mmi_ref,
+};
+use pci::{PciDevice, PciLocation, MsiInterrupt};
+use pause::spin_loop_hint;
+
+
+/// The PCI vendor ID of all virtio devices.
+pub const VIRTIO_PCI_VENDOR_ID: u16 = 0x1AF4;
+
+/// The virtio device type of network cards.
+pub const VIRTIO_DEVICE_TYPE_NET: u16 = 1;
+/// The virtio device type of block devices, i.e., disks.
+pub const VIRTIO_DEVICE_TYPE_BLOCK: u16 = 2;
+
+/// The feature bit that indicates compliance with virtio 1.0, which the modern transport requires.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// How many times to poll for the device to finish resetting before giving up.
+const POLL_ITERATIONS: usize = 100_000_000;
+
+/// The value of an MSI-X vector register that means no vector is used.
+const VIRTIO_MSI_NO_VECTOR: u16 = 0xFFFF;
+
+// Bits of the device status register.
+const STATUS_ACKNOWLEDGE:   u8 = 1;
+const STATUS_DRIVER:        u8 = 2;
+const STATUS_DRIVER_OK:     u8 = 4;
+const STATUS_FEATURES_OK:   u8 = 8;
+const STATUS_FAILED:        u8 = 128;
+
+/// The PCI capability ID of vendor-specific capabilities, which virtio uses to describe its configuration structures.
+const PCI_CAPABILITY_VENDOR_SPECIFIC: u16 = 0x09;
+
+// The types of configuration structures described by virtio's vendor-specific PCI capabilities.
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+const VIRTIO_PCI_CAP_ISR_CFG:    u8 = 3;
+const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+/// The mapping flags used for the device's configuration structures and for virtqueue memory.
+pub(crate) const VIRTIO_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+
+/// Returns the virtio device type of the given PCI device, e.g., `VIRTIO_DEVICE_TYPE_NET`,
+/// or `None` if it isn't a virtio device.
+pub fn device_type(pci_device: &PciDevice) -> Option<u16> {
+    if pci_device.vendor_id != VIRTIO_PCI_VENDOR_ID {
+        return None;
+    }
+    match pci_device.device_id {
+        // Transitional devices, which support both the legacy and modern transports.
+        0x1000 => Some(VIRTIO_DEVICE_TYPE_NET),
+        0x1001 => Some(VIRTIO_DEVICE_TYPE_BLOCK),
+        0x1002 => Some(5), // memory balloon
+        0x1003 => Some(3), // console
+        0x1004 => Some(8), // SCSI host
+        0x1005 => Some(4), // entropy source
+        0x1009 => Some(9), // 9P transport
+        // Modern-only devices, whose device ID is offset by their type.
+        id @ 0x1041 ..= 0x107F => Some(id - 0x1040),
+        _ => None,
+    }
+}
+
+
+/// The common configuration structure of a virtio device.
+///
+/// The 64-bit queue addresses are split into two 32-bit halves,
+/// because devices are only required to support accesses of up to 32 bits.
+#[derive(FromBytes)]
+#[repr(C)]
+struct CommonConfig {
+    device_feature_select:  Volatile<u32>,  // 0x00
+    device_feature:         Volatile<u32>,  // 0x04
+    driver_feature_select:  Volatile<u32>,  // 0x08
+    driver_feature:         Volatile<u32>,  // 0x0C
+    msix_config:            Volatile<u16>,  // 0x10
+    num_queues:             Volatile<u16>,  // 0x12
+    device_status:          Volatile<u8>,   // 0x14
+    config_generation:      Volatile<u8>,   // 0x15
+    queue_select:           Volatile<u16>,  // 0x16
+    queue_size:             Volatile<u16>,  // 0x18
+    queue_msix_vector:      Volatile<u16>,  // 0x1A
+    queue_enable:           Volatile<u16>,  // 0x1C
+    queue_notify_off:       Volatile<u16>,  // 0x1E
+    queue_desc_lo:          Volatile<u32>,  // 0x20
+    queue_desc_hi:          Volatile<u32>,  // 0x24
+    queue_driver_lo:        Volatile<u32>,  // 0x28
+    queue_driver_hi:        Volatile<u32>,  // 0x2C
+    queue_device_lo:        Volatile<u32>,  // 0x30
+    queue_device_hi:        Volatile<u32>,  // 0x34
+}
+
+
+/// A configuration structure that has been mapped from one of the device's BARs.
+struct MappedRegion {
+    mapped_pages: MappedPages,
+    /// The offset of the region from the start of `mapped_pages`.
+    offset: usize,
+    length: usize,
+}
+
+impl MappedRegion {
+    fn new(pci_device: &PciDevice, bar: u8, offset: u32, length: u32) -> Result<MappedRegion, &'static str> {
+        let start = pci_device.bar_address(bar)? + offset as usize;
+        let length = length as usize;
+        let offset_in_pages = start.frame_offset();
+
+        // The region is in device memory rather than RAM, so the frame allocator doesn't own these frames.
+        let pages = allocate_pages_by_bytes(offset_in_pages + length).ok_or("virtio: couldn't allocate pages for a configuration structure")?;
+        let frames = FrameRange::from_phys_addr(start, length);
+        let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("virtio: KERNEL_MMI was not yet initialized!")?;
+        let fa = get_frame_allocator_ref().ok_or("virtio: couldn't get the frame allocator")?;
+        let mapped_pages = kernel_mmi_ref.lock().page_table.map_allocated_pages_to(pages, frames, VIRTIO_MAPPING_FLAGS, fa.lock().deref_mut())?;
+        Ok(MappedRegion { mapped_pages, offset: offset_in_pages, length })
+    }
+
+    fn as_type<T: FromBytes>(&self, offset: usize) -> Result<&T, &'static str> {
+        if offset + size_of::<T>() > self.length {
+            return Err("virtio: access beyond the end of a configuration structure");
+        }
+        self.mapped_pages.as_type::<T>(self.offset + offset)
+    }
+
+    fn as_type_mut<T: FromBytes>(&mut self, offset: usize) -> Result<&mut T, &'static str> {
+        if offset + size_of::<T>() > self.length {
+            return Err("virtio: access beyond the end of a configuration structure");
+        }
+        self.mapped_pages.as_type_mut::<T>(self.offset + offset)
+    }
+}
+
+
+/// The transport through which a driver configures a virtio PCI device and notifies it of new buffers.
+pub struct VirtioTransport {
+    location: PciLocation,
+    common: MappedRegion,
+    notify: MappedRegion,
+    /// The number of bytes by which a queue's `notify_offset` is multiplied to get its notification address.
+    notify_off_multiplier: u32,
+    isr: MappedRegion,
+    device: Option<MappedRegion>,
+    features: u64,
+    /// The MSI-X vectors that the device's interrupts arrive on, if enabled.
+    interrupts: Vec<MsiInterrupt>,
+}
+
+impl VirtioTransport {
+    /// Finds and maps the configuration structures of the virtio device connected as the given `PciDevice`,
+    /// resets it, and acknowledges that a driver has been found for it.
+    pub fn new(pci_device: &PciDevice) -> Result<VirtioTransport, &'static str> {
+        if pci_device.vendor_id != VIRTIO_PCI_VENDOR_ID {
+            return Err("virtio: PCI device is not a virtio device");
+        }
+
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut device = None;
+        for cap_addr in pci_device.find_pci_capabilities(PCI_CAPABILITY_VENDOR_SPECIFIC) {
+            let cfg_type = (pci_device.pci_read_32(cap_addr) >> 24) as u8;
+            let bar = pci_device.pci_read_8(cap_addr + 4);
+            let offset = pci_device.pci_read_32(cap_addr + 8);
+            let length = pci_device.pci_read_32(cap_addr + 12);
+            // Only the first structure of each type is used, as the device lists them in order of preference.
+            match cfg_type {
+                VIRTIO_PCI_CAP_COMMON_CFG if common.is_none() => {
+                    common = Some(MappedRegion::new(pci_device, bar, offset, length)?);
+                }
+                VIRTIO_PCI_CAP_NOTIFY_CFG if notify.is_none() => {
+                    let notify_off_multiplier = pci_device.pci_read_32(cap_addr + 16);
+                    notify = Some((MappedRegion::new(pci_device, bar, offset, length)?, notify_off_multiplier));
+                }
+                VIRTIO_PCI_CAP_ISR_CFG if isr.is_none() => {
+                    isr = Some(MappedRegion::new(pci_device, bar, offset, length)?);
+                }
+                VIRTIO_PCI_CAP_DEVICE_CFG if device.is_none() => {
+                    device = Some(MappedRegion::new(pci_device, bar, offset, length)?);
+                }
+                _ => { }
+            }
+        }
+        let common = common.ok_or("virtio: device has no common configuration structure (is it a legacy-only device?)")?;
+        let (notify, notify_off_multiplier) = notify.ok_or("virtio: device has no notification structure")?;
+        let isr = isr.ok_or("virtio: device has no ISR status structure")?;
+
+        pci_device.pci_set_command_bus_master_bit();
+
+        let mut transport = VirtioTransport {
+            location: pci_device.location,
+            common,
+            notify,
+            notify_off_multiplier,
+            isr,
+            device,
+            features: 0,
+            interrupts: Vec::new(),
+        };
+        transport.reset()?;
+        transport.add_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER)?;
+        Ok(transport)
+    }
+
+    fn common_cfg(&mut self) -> Result<&mut CommonConfig, &'static str> {
+        self.common.as_type_mut::<CommonConfig>(0)
+    }
+
+    fn add_status(&mut self, status: u8) -> Result<(), &'static str> {
+        let common = self.common_cfg()?;
+        let old_status = common.device_status.read();
+        common.device_status.write(old_status | status);
+        Ok(())
+    }
+
+    /// Resets the device, which then stops using all of its virtqueues.
+    pub fn reset(&mut self) -> Result<(), &'static str> {
+        let common = self.common_cfg()?;
+        common.device_status.write(0);
+        for _ in 0..POLL_ITERATIONS {
+            if common.device_status.read() == 0 {
+                return Ok(());
+            }
+            spin_loop_hint();
+        }
+        Err("virtio: timed out waiting for the device to reset")
+    }
+
+    /// Tells the device that the driver has given up on it, e.g., due to an initialization error.
+    pub fn fail(&mut self) {
+        let _ = self.add_status(STATUS_FAILED);
+    }
+
+    /// Returns the PCI location of this device.
+    pub fn location(&self) -> PciLocation {
+        self.location
+    }
+
+    /// Negotiates the device's features, accepting those that both the device
+    /// and `driver_features` support, plus `VIRTIO_F_VERSION_1`.
+    ///
+    /// Returns the negotiated features, or an error if the device doesn't accept them.
+    pub fn negotiate_features(&mut self, driver_features: u64) -> Result<u64, &'static str> {
+        let common = self.common_cfg()?;
+        common.device_feature_select.write(0);
+        let low = common.device_feature.read() as u64;
+        common.device_feature_select.write(1);
+        let high = common.device_feature.read() as u64;
+        let device_features = (high << 32) | low;
+        if device_features & VIRTIO_F_VERSION_1 == 0 {
+            return Err("virtio: device doesn't support virtio 1.0");
+        }
+
+        let features = device_features & (driver_features | VIRTIO_F_VERSION_1);
+        common.driver_feature_select.write(0);
+        common.driver_feature.write(features as u32);
+        common.driver_feature_select.write(1);
+        common.driver_feature.write((features >> 32) as u32);
+
+        common.device_status.write(common.device_status.read() | STATUS_FEATURES_OK);
+        if common.device_status.read() & STATUS_FEATURES_OK == 0 {
+            return Err("virtio: device didn't accept the negotiated features");
+        }
+        self.features = features;
+        Ok(features)
+    }
+
+    /// Returns the features negotiated with the device.
+    pub fn features(&self) -> u64 {
+        self.features
+    }
+
+    /// Returns the number of virtqueues that the device supports.
+    pub fn num_queues(&mut self) -> Result<u16, &'static str> {
+        Ok(self.common_cfg()?.num_queues.read())
+    }
+
+    /// Enables `num_vectors` MSI-X vectors for the given device, all of which invoke the given `handler`.
+    /// The vector with index `i` can then be given to [`setup_queue()`](#method.setup_queue).
+    ///
+    /// Returns an error if the device doesn't support MSI-X, in which case
+    /// the driver must either poll its virtqueues or use the legacy interrupt line.
+    pub fn setup_interrupts(&mut self, pci_device: &PciDevice, num_vectors: usize, handler: HandlerFunc) -> Result<(), &'static str> {
+        let mut interrupts = pci_device.enable_msix(num_vectors)?;
+        for vector in interrupts.iter_mut() {
+            vector.set_handler(handler)?;
+        }
+        // Configuration change notifications aren't used.
+        self.common_cfg()?.msix_config.write(VIRTIO_MSI_NO_VECTOR);
+        self.interrupts = interrupts;
+        Ok(())
+    }
+
+    /// Returns the MSI-X vectors enabled by [`setup_interrupts()`](#method.setup_interrupts), if any.
+    pub fn interrupts(&self) -> &[MsiInterrupt] {
+        &self.interrupts
+    }
+
+    /// Creates and enables the virtqueue with the given `index`, which will have at most `max_size` descriptors.
+    /// Its used buffer notifications are sent on the MSI-X vector with index `msix_vector`, if given.
+    ///
+    /// This must be called after the features have been negotiated and before [`driver_ok()`](#method.driver_ok).
+    pub fn setup_queue(&mut self, index: u16, max_size: u16, msix_vector: Option<u16>) -> Result<Virtqueue, &'static str> {
+        let common = self.common_cfg()?;
+        common.queue_select.write(index);
+        if common.queue_enable.read() != 0 {
+            return Err("virtio: virtqueue is already enabled");
+        }
+        let device_max_size = common.queue_size.read();
+        if device_max_size == 0 {
+            return Err("virtio: virtqueue doesn't exist");
+        }
+        // Both sizes are powers of two, so their minimum is too.
+        let size = core::cmp::min(device_max_size, max_size);
+
+        let mut queue = Virtqueue::new(index, size)?;
+        let desc = queue.descriptor_table_address().value() as u64;
+        let driver = queue.available_ring_address().value() as u64;
+        let device = queue.used_ring_address().value() as u64;
+
+        common.queue_size.write(size);
+        common.queue_desc_lo.write(desc as u32);
+        common.queue_desc_hi.write((desc >> 32) as u32);
+        common.queue_driver_lo.write(driver as u32);
+        common.queue_driver_hi.write((driver >> 32) as u32);
+        common.queue_device_lo.write(device as u32);
+        common.queue_device_hi.write((device >> 32) as u32);
+
+        let vector = msix_vector.unwrap_or(VIRTIO_MSI_NO_VECTOR);
+        common.queue_msix_vector.write(vector);
+        if common.queue_msix_vector.read() != vector {
+            return Err("virtio: device couldn't assign an MSI-X vector to the virtqueue");
+        }
+
+        queue.notify_offset = common.queue_notify_off.read();
+        common.queue_enable.write(1);
+        debug!("virtio device at {}: enabled queue {} with {} descriptors", self.location, index, size);
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is fully set up, after which the device may start using its virtqueues.
+    pub fn driver_ok(&mut self) -> Result<(), &'static str> {
+        self.add_status(STATUS_DRIVER_OK)
+    }
+
+    /// Notifies the device that new buffers are available in the given virtqueue.
+    pub fn notify(&mut self, queue: &Virtqueue) -> Result<(), &'static str> {
+        let offset = queue.notify_offset as usize * self.notify_off_multiplier as usize;
+        self.notify.as_type_mut::<Volatile<u16>>(offset)?.write(queue.index());
+        Ok(())
+    }
+
+    /// Reads and clears the ISR status, which is only needed when using the legacy interrupt line.
+    /// Bit 0 indicates a used buffer notification, and bit 1 a configuration change.
+    pub fn read_isr_status(&mut self) -> Result<u8, &'static str> {
+        Ok(self.isr.as_type::<Volatile<u8>>(0)?.read())
+    }
+
+    /// Returns the device-specific configuration structure, e.g., a disk's capacity or a NIC's MAC address.
+    pub fn device_config<T: FromBytes>(&mut self) -> Result<&mut T, &'static str> {
+        self.device.as_mut().ok_or("virtio: device has no device-specific configuration structure")?.as_type_mut::<T>(0)
+    }
+}
@@ -0,0 +1,233 @@
+//! Split virtqueues, the rings of buffer descriptors through which a driver and a virtio device exchange data.
+//!
+//! A split virtqueue consists of three parts in DMA memory:
+//! * the descriptor table, in which each descriptor points to one buffer and may be chained to a next descriptor,
+//! * the available ring, in which the driver places the heads of descriptor chains that the device may consume,
+//! * the used ring, in which the device places the heads of descriptor chains that it has finished with.
+
+use core::{
+    mem::size_of,
+    sync::atomic::{fence, Ordering},
+};
+use volatile::Volatile;
+use zerocopy::FromBytes;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use super::VIRTIO_MAPPING_FLAGS;
+
+
+/// This descriptor continues via its `next` field.
+const VIRTQ_DESC_F_NEXT:  u16 = 1;
+/// The buffer of this descriptor is written by the device rather than read by it.
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// A descriptor in the descriptor table.
+#[derive(FromBytes, Clone, Copy, Default)]
+#[repr(C)]
+struct Descriptor {
+    addr:   u64,
+    len:    u32,
+    flags:  u16,
+    next:   u16,
+}
+
+/// An entry in the used ring.
+#[derive(FromBytes, Clone, Copy)]
+#[repr(C)]
+struct UsedElement {
+    /// The index of the head of the descriptor chain that was used.
+    id:     u32,
+    /// The number of bytes that the device wrote into the chain's buffers.
+    len:    u32,
+}
+
+
+/// One buffer of a descriptor chain that is added to a virtqueue.
+#[derive(Clone, Copy, Debug)]
+pub struct VirtqueueBuffer {
+    pub phys_addr: PhysicalAddress,
+    pub length: u32,
+    /// Whether the device writes to this buffer (`true`) or reads from it (`false`).
+    pub device_writable: bool,
+}
+
+
+/// A split virtqueue, whose descriptor table and rings reside in one physically-contiguous region.
+///
+/// Free descriptors form a linked list through their `next` fields,
+/// so a chain allocated from the front of that list is already linked together.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    memory: MappedPages,
+    memory_phys: PhysicalAddress,
+    avail_offset: usize,
+    used_offset: usize,
+    /// The first descriptor in the list of free descriptors.
+    free_head: u16,
+    num_free: u16,
+    /// Our copy of the available ring's index, which only the driver writes.
+    next_avail: u16,
+    /// The used ring index up to which completed chains have been popped.
+    last_used: u16,
+    /// The queue's offset into the notification region, in units of the notify offset multiplier.
+    pub(crate) notify_offset: u16,
+}
+
+impl Virtqueue {
+    /// Allocates and initializes a virtqueue with `size` descriptors, which must be a power of two.
+    pub(crate) fn new(index: u16, size: u16) -> Result<Virtqueue, &'static str> {
+        if size == 0 || !size.is_power_of_two() {
+            return Err("virtqueue size must be a nonzero power of two");
+        }
+        let n = size as usize;
+        // The descriptor table must be 16-byte aligned, the available ring 2-byte aligned,
+        // and the used ring 4-byte aligned.
+        let avail_offset = n * size_of::<Descriptor>();
+        let avail_size = 2 * size_of::<u16>() + n * size_of::<u16>() + size_of::<u16>();
+        let used_offset = (avail_offset + avail_size + 3) & !3;
+        let used_size = 2 * size_of::<u16>() + n * size_of::<UsedElement>() + size_of::<u16>();
+        let total_size = used_offset + used_size;
+
+        let (mut memory, memory_phys) = create_contiguous_mapping(total_size, VIRTIO_MAPPING_FLAGS)?;
+        for b in memory.as_slice_mut::<u8>(0, total_size)? { *b = 0; }
+        for (i, desc) in memory.as_slice_mut::<Descriptor>(0, n)?.iter_mut().enumerate() {
+            desc.next = (i + 1) as u16;
+        }
+
+        Ok(Virtqueue {
+            index,
+            size,
+            memory,
+            memory_phys,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            next_avail: 0,
+            last_used: 0,
+            notify_offset: 0,
+        })
+    }
+
+    /// Returns this queue's index within its device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns the number of descriptors in this queue.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the number of descriptors that are not part of any outstanding chain.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    pub(crate) fn descriptor_table_address(&self) -> PhysicalAddress {
+        self.memory_phys
+    }
+
+    pub(crate) fn available_ring_address(&self) -> PhysicalAddress {
+        self.memory_phys + self.avail_offset
+    }
+
+    pub(crate) fn used_ring_address(&self) -> PhysicalAddress {
+        self.memory_phys + self.used_offset
+    }
+
+    fn descriptor_mut(&mut self, index: u16) -> Result<&mut Descriptor, &'static str> {
+        self.memory.as_type_mut::<Descriptor>(index as usize * size_of::<Descriptor>())
+    }
+
+    /// Adds a chain of descriptors for the given buffers to the available ring,
+    /// in which the device will process them in order.
+    /// The device is not told about the new chain until [`VirtioTransport::notify()`] is called.
+    ///
+    /// Returns the index of the chain's head descriptor, which identifies the chain once the device has used it.
+    ///
+    /// [`VirtioTransport::notify()`]: struct.VirtioTransport.html#method.notify
+    pub fn add(&mut self, buffers: &[VirtqueueBuffer]) -> Result<u16, &'static str> {
+        if buffers.is_empty() {
+            return Err("cannot add an empty descriptor chain to a virtqueue");
+        }
+        if buffers.len() > self.num_free as usize {
+            return Err("virtqueue is full");
+        }
+
+        let head = self.free_head;
+        let mut current = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let is_last = i + 1 == buffers.len();
+            let desc = self.descriptor_mut(current)?;
+            desc.addr = buffer.phys_addr.value() as u64;
+            desc.len = buffer.length;
+            desc.flags = if buffer.device_writable { VIRTQ_DESC_F_WRITE } else { 0 }
+                | if is_last { 0 } else { VIRTQ_DESC_F_NEXT };
+            // The last descriptor keeps its `next` field, which still points to the rest of the free list.
+            current = desc.next;
+        }
+        self.free_head = current;
+        self.num_free -= buffers.len() as u16;
+
+        let slot_offset = self.avail_offset + 2 * size_of::<u16>() + (self.next_avail % self.size) as usize * size_of::<u16>();
+        self.memory.as_type_mut::<Volatile<u16>>(slot_offset)?.write(head);
+        // The descriptors and ring entry must be visible before the device sees the new index.
+        fence(Ordering::SeqCst);
+        self.next_avail = self.next_avail.wrapping_add(1);
+        let avail_idx_offset = self.avail_offset + size_of::<u16>();
+        self.memory.as_type_mut::<Volatile<u16>>(avail_idx_offset)?.write(self.next_avail);
+        fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// Returns the head index of the next descriptor chain that the device has used
+    /// and the number of bytes that it wrote into that chain,
+    /// or `None` if the device hasn't used any more chains.
+    ///
+    /// The chain's descriptors are returned to the free list,
+    /// but the buffers they pointed to are the caller's responsibility.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = self.memory.as_type::<Volatile<u16>>(self.used_offset + size_of::<u16>()).ok()?.read();
+        if used_idx == self.last_used {
+            return None;
+        }
+        // Read the used element only after observing the index that covers it.
+        fence(Ordering::SeqCst);
+
+        let elem_offset = self.used_offset + 2 * size_of::<u16>() + (self.last_used % self.size) as usize * size_of::<UsedElement>();
+        let elem = unsafe { core::ptr::read_volatile(self.memory.as_type::<UsedElement>(elem_offset).ok()?) };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = elem.id as u16;
+        if let Err(_e) = self.free_chain(head) {
+            error!("virtqueue {}: device used an invalid descriptor chain {}: {}", self.index, head, _e);
+        }
+        Some((head, elem.len))
+    }
+
+    /// Returns the descriptor chain starting at `head` to the front of the free list.
+    fn free_chain(&mut self, head: u16) -> Result<(), &'static str> {
+        if head >= self.size {
+            return Err("descriptor index out of bounds");
+        }
+        let old_free_head = self.free_head;
+        let mut current = head;
+        let mut count = 1;
+        loop {
+            let desc = self.descriptor_mut(current)?;
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                desc.next = old_free_head;
+                break;
+            }
+            current = desc.next;
+            count += 1;
+            if count > self.size {
+                return Err("descriptor chain has a loop");
+            }
+        }
+        self.free_head = head;
+        self.num_free += count;
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+//! Support for virtio block devices, the paravirtualized disks offered by QEMU, KVM, and other hypervisors.
+//!
+//! A virtio block device has a single request virtqueue.
+//! Each request is a chain of three buffers: a header that specifies the operation and starting sector,
+//! the data to be read or written, and a status byte that the device writes once the request has completed.
+//!
+//! Only one request is outstanding on a device at a time;
+//! the submitting task sleeps until the device's MSI-X interrupt signals completion,
+//! or polls the virtqueue if the device doesn't support MSI-X.
+//!
+//! The primary struct of interest is [`VirtioBlkController`](struct.VirtioBlkController.html).
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate volatile;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate pause;
+extern crate storage_device;
+extern crate virtio;
+
+use core::fmt;
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+};
+use spin::Mutex;
+use volatile::Volatile;
+use zerocopy::FromBytes;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{EntryFlags, MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pci::{PciDevice, PciLocation};
+use interrupts::eoi;
+use wait_queue::WaitQueue;
+use pause::spin_loop_hint;
+use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+use virtio::{VirtioTransport, Virtqueue, VirtqueueBuffer, VIRTIO_DEVICE_TYPE_BLOCK};
+
+
+/// Virtio block devices always address data in units of 512-byte sectors,
+/// regardless of the block size of the underlying storage.
+const SECTOR_SIZE_IN_BYTES: usize = 512;
+
+/// The index of the request virtqueue.
+const REQUEST_QUEUE_INDEX: u16 = 0;
+/// The maximum number of descriptors in the request virtqueue.
+/// Only one 3-descriptor request is outstanding at a time, so a small queue suffices.
+const REQUEST_QUEUE_SIZE: u16 = 16;
+
+/// The size of the DMA buffer through which all data is transferred, which bounds the size of one request.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = 32 * PAGE_SIZE;
+
+/// How many times to poll for a request to complete before giving up.
+const POLL_ITERATIONS: usize = 100_000_000;
+
+// Feature bits
+const VIRTIO_BLK_F_RO:      u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH:   u64 = 1 << 9;
+
+// Request types
+const VIRTIO_BLK_T_IN:      u32 = 0;
+const VIRTIO_BLK_T_OUT:     u32 = 1;
+const VIRTIO_BLK_T_FLUSH:   u32 = 4;
+
+// Request status values written by the device
+const VIRTIO_BLK_S_OK:      u8 = 0;
+const VIRTIO_BLK_S_UNSUPP:  u8 = 2;
+
+/// The offset of the status byte in the request memory, right after the request header.
+const STATUS_OFFSET: usize = core::mem::size_of::<RequestHeader>();
+
+/// The mapping flags used for DMA memory.
+const VIRTIO_BLK_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+
+lazy_static! {
+    /// The tasks waiting for a virtio block request to complete, which are woken up by virtio block interrupts.
+    static ref COMPLETION_WAIT_QUEUE: WaitQueue = WaitQueue::new();
+}
+
+
+/// Returns `true` if the given PCI device is a virtio block device.
+pub fn is_virtio_blk_device(pci_device: &PciDevice) -> bool {
+    virtio::device_type(pci_device) == Some(VIRTIO_DEVICE_TYPE_BLOCK)
+}
+
+
+/// The start of a virtio block device's configuration structure.
+#[derive(FromBytes)]
+#[repr(C)]
+struct VirtioBlkConfig {
+    /// The capacity in 512-byte sectors, split into two halves because 64-bit accesses may not be supported.
+    capacity_lo:    Volatile<u32>,
+    capacity_hi:    Volatile<u32>,
+}
+
+/// The header at the start of every request.
+#[derive(FromBytes)]
+#[repr(C)]
+struct RequestHeader {
+    request_type:   u32,
+    _reserved:      u32,
+    sector:         u64,
+}
+
+
+/// A controller for a single virtio block device.
+///
+/// Each virtio block device is its own PCI device, so this only ever contains one drive.
+pub struct VirtioBlkController {
+    location: PciLocation,
+    drive: VirtioBlkDriveRef,
+}
+
+impl VirtioBlkController {
+    /// Initializes the virtio block device that is connected as the given `PciDevice`.
+    pub fn new(pci_device: &PciDevice) -> Result<VirtioBlkController, &'static str> {
+        let mut transport = VirtioTransport::new(pci_device)?;
+        match VirtioBlkDrive::new(pci_device, &mut transport) {
+            Ok((queue, size_in_sectors, features, interrupts_enabled)) => {
+                let (request, request_phys) = create_contiguous_mapping(PAGE_SIZE, VIRTIO_BLK_MAPPING_FLAGS)?;
+                let (dma_buffer, dma_buffer_phys) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, VIRTIO_BLK_MAPPING_FLAGS)?;
+                let drive = VirtioBlkDrive {
+                    transport,
+                    queue,
+                    size_in_sectors,
+                    read_only: features & VIRTIO_BLK_F_RO != 0,
+                    can_flush: features & VIRTIO_BLK_F_FLUSH != 0,
+                    interrupts_enabled,
+                    request,
+                    request_phys,
+                    dma_buffer,
+                    dma_buffer_phys,
+                };
+                let controller = VirtioBlkController {
+                    location: pci_device.location,
+                    drive: Arc::new(Mutex::new(drive)),
+                };
+                info!("{}", controller);
+                Ok(controller)
+            }
+            Err(e) => {
+                transport.fail();
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the drive (disk) of this controller.
+    pub fn drive(&self) -> &VirtioBlkDriveRef {
+        &self.drive
+    }
+}
+
+impl StorageController for VirtioBlkController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(core::iter::once(Arc::clone(&self.drive) as StorageDeviceRef))
+    }
+}
+
+impl fmt::Display for VirtioBlkController {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let drive = self.drive.lock();
+        write!(f, "virtio block device at {}: {} sectors of {} bytes{}",
+            self.location, drive.size_in_sectors, SECTOR_SIZE_IN_BYTES,
+            if drive.read_only { " (read-only)" } else { "" }
+        )
+    }
+}
+
+
+/// A virtio block device, i.e., a paravirtualized disk.
+pub struct VirtioBlkDrive {
+    transport: VirtioTransport,
+    queue: Virtqueue,
+    size_in_sectors: usize,
+    read_only: bool,
+    /// Whether the device has a write cache that must be flushed for writes to be durable.
+    can_flush: bool,
+    /// Whether the request queue raises interrupts, such that tasks can sleep while waiting for it.
+    interrupts_enabled: bool,
+    /// The memory holding the request header, followed by the status byte.
+    request: MappedPages,
+    request_phys: PhysicalAddress,
+    /// The buffer through which all data is transferred to and from the device.
+    dma_buffer: MappedPages,
+    dma_buffer_phys: PhysicalAddress,
+}
+
+impl VirtioBlkDrive {
+    /// Negotiates features, sets up the request queue and its interrupt, and starts the device.
+    /// Returns the request queue, the capacity in sectors, the negotiated features, and whether interrupts are enabled.
+    fn new(pci_device: &PciDevice, transport: &mut VirtioTransport) -> Result<(Virtqueue, usize, u64, bool), &'static str> {
+        let features = transport.negotiate_features(VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH)?;
+
+        let interrupts_enabled = match transport.setup_interrupts(pci_device, 1, virtio_blk_handler) {
+            Ok(()) => true,
+            Err(_e) => {
+                warn!("virtio block device at {} doesn't support MSI-X ({}), so requests will be polled", pci_device.location, _e);
+                false
+            }
+        };
+        let queue = transport.setup_queue(REQUEST_QUEUE_INDEX, REQUEST_QUEUE_SIZE, if interrupts_enabled { Some(0) } else { None })?;
+
+        let size_in_sectors = {
+            let config = transport.device_config::<VirtioBlkConfig>()?;
+            ((config.capacity_hi.read() as u64) << 32 | config.capacity_lo.read() as u64) as usize
+        };
+        transport.driver_ok()?;
+        Ok((queue, size_in_sectors, features, interrupts_enabled))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Checks that a transfer of `buffer_len` bytes starting at `offset_in_sectors` is within this drive.
+    fn check_bounds(&self, buffer_len: usize, offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if buffer_len % SECTOR_SIZE_IN_BYTES != 0 {
+            return Err("The buffer length must be a multiple of the virtio block device's sector size");
+        }
+        let sector_count = buffer_len / SECTOR_SIZE_IN_BYTES;
+        if offset_in_sectors + sector_count > self.size_in_sectors {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(sector_count)
+    }
+
+    /// Submits a request of the given type that transfers `data_len` bytes
+    /// to or from the start of the DMA buffer, and waits for it to complete.
+    ///
+    /// If the request queue raises interrupts, the current task sleeps until the request completes.
+    /// Otherwise, or if there is no current task yet, this polls the request queue.
+    fn submit_and_wait(&mut self, request_type: u32, sector: u64, data_len: usize) -> Result<(), &'static str> {
+        {
+            let header = self.request.as_type_mut::<RequestHeader>(0)?;
+            header.request_type = request_type;
+            header.sector = sector;
+        }
+        *self.request.as_type_mut::<u8>(STATUS_OFFSET)? = 0xFF;
+
+        let header_buffer = VirtqueueBuffer {
+            phys_addr: self.request_phys,
+            length: STATUS_OFFSET as u32,
+            device_writable: false,
+        };
+        let status_buffer = VirtqueueBuffer {
+            phys_addr: self.request_phys + STATUS_OFFSET,
+            length: 1,
+            device_writable: true,
+        };
+        let buffers = if data_len > 0 {
+            vec![header_buffer, VirtqueueBuffer {
+                phys_addr: self.dma_buffer_phys,
+                length: data_len as u32,
+                device_writable: request_type == VIRTIO_BLK_T_IN,
+            }, status_buffer]
+        } else {
+            vec![header_buffer, status_buffer]
+        };
+        let head = self.queue.add(&buffers)?;
+        self.transport.notify(&self.queue)?;
+
+        let queue = &mut self.queue;
+        let mut poll = || queue.pop_used();
+        // Sleeping requires a current task, so this falls back to polling during early initialization.
+        let woken = if self.interrupts_enabled { COMPLETION_WAIT_QUEUE.wait_until_mut(&mut poll).ok() } else { None };
+        let (used_head, _len) = match woken {
+            Some(used) => used,
+            None => {
+                let mut iterations = 0;
+                loop {
+                    if let Some(used) = poll() {
+                        break used;
+                    }
+                    iterations += 1;
+                    if iterations > POLL_ITERATIONS {
+                        return Err("virtio block request timed out");
+                    }
+                    spin_loop_hint();
+                }
+            }
+        };
+
+        if used_head != head {
+            error!("virtio_blk: expected completion of request {}, got {}", head, used_head);
+            return Err("virtio block device completed an unexpected request");
+        }
+        let status = unsafe { core::ptr::read_volatile(self.request.as_type::<u8>(STATUS_OFFSET)?) };
+        match status {
+            VIRTIO_BLK_S_OK => Ok(()),
+            VIRTIO_BLK_S_UNSUPP => Err("virtio block device doesn't support the request"),
+            _ => {
+                error!("virtio_blk: request type {} for sector {} failed with status {}", request_type, sector, status);
+                Err("virtio block request failed")
+            }
+        }
+    }
+}
+
+impl StorageDevice for VirtioBlkDrive {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        for (i, chunk) in buffer.chunks_mut(DMA_BUFFER_SIZE_IN_BYTES).enumerate() {
+            let sector = (offset_in_sectors + i * DMA_BUFFER_SIZE_IN_BYTES / SECTOR_SIZE_IN_BYTES) as u64;
+            self.submit_and_wait(VIRTIO_BLK_T_IN, sector, chunk.len())?;
+            chunk.copy_from_slice(self.dma_buffer.as_slice::<u8>(0, chunk.len())?);
+        }
+        Ok(sector_count)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if self.read_only {
+            return Err("virtio block device is read-only");
+        }
+        let sector_count = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        for (i, chunk) in buffer.chunks(DMA_BUFFER_SIZE_IN_BYTES).enumerate() {
+            let sector = (offset_in_sectors + i * DMA_BUFFER_SIZE_IN_BYTES / SECTOR_SIZE_IN_BYTES) as u64;
+            self.dma_buffer.as_slice_mut::<u8>(0, chunk.len())?.copy_from_slice(chunk);
+            self.submit_and_wait(VIRTIO_BLK_T_OUT, sector, chunk.len())?;
+        }
+        if self.can_flush {
+            self.submit_and_wait(VIRTIO_BLK_T_FLUSH, 0, 0)?;
+        }
+        Ok(sector_count)
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        SECTOR_SIZE_IN_BYTES
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        self.size_in_sectors
+    }
+}
+
+pub type VirtioBlkDriveRef = Arc<Mutex<VirtioBlkDrive>>;
+
+
+/// The handler for all virtio block interrupts, which wakes up the tasks waiting for requests to complete.
+/// Each waiting task checks its own request queue, so it doesn't matter which device raised it.
+extern "x86-interrupt" fn virtio_blk_handler(_stack_frame: &mut ExceptionStackFrame) {
+    COMPLETION_WAIT_QUEUE.notify_all();
+    eoi(None);
+}
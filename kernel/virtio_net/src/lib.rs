@@ -0,0 +1,342 @@
+//! Support for virtio network devices, the paravirtualized NICs offered by QEMU, KVM, and other hypervisors.
+//!
+//! A virtio network device has a receive virtqueue and a transmit virtqueue.
+//! Every packet in either direction is preceded by a small virtio-net header,
+//! so each descriptor chain consists of a header buffer followed by a packet buffer.
+//! Keeping the header in its own buffer means that the `ReceiveBuffer`s given to higher layers
+//! contain only the Ethernet frame, just like with other NICs.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate pic;
+extern crate interrupts;
+extern crate pause;
+extern crate mpmc;
+extern crate network_interface_card;
+extern crate nic_buffers;
+extern crate nic_initialization;
+extern crate channel;
+extern crate virtio;
+
+use spin::Once;
+use alloc::vec::Vec;
+use irq_safety::MutexIrqSafe;
+use zerocopy::FromBytes;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pci::{PciDevice, PCI_INTERRUPT_LINE};
+use interrupts::{eoi, register_interrupt};
+use pause::spin_loop_hint;
+use network_interface_card::NetworkInterfaceCard;
+use nic_buffers::{TransmitBuffer, ReceiveBuffer, ReceivedFrame};
+use nic_initialization::{NIC_MAPPING_FLAGS, init_rx_buf_pool};
+use channel::{Sender, Receiver};
+use virtio::{VirtioTransport, Virtqueue, VirtqueueBuffer, VIRTIO_DEVICE_TYPE_NET};
+
+
+/// The index of the receive virtqueue.
+const RX_QUEUE_INDEX: u16 = 0;
+/// The index of the transmit virtqueue.
+const TX_QUEUE_INDEX: u16 = 1;
+/// The maximum number of descriptors in each virtqueue.
+/// Each packet uses two descriptors, so half as many packets can be in flight.
+const MAX_QUEUE_SIZE: u16 = 128;
+
+/// Currently, each receive buffer is a single page.
+const RX_BUFFER_SIZE_IN_BYTES: u16 = PAGE_SIZE as u16;
+
+/// The size of the virtio-net header that precedes every packet.
+/// With `VIRTIO_F_VERSION_1`, the header always includes the `num_buffers` field.
+const NET_HEADER_SIZE: usize = 12;
+/// The distance between consecutive headers in the header memory.
+const NET_HEADER_SLOT_SIZE: usize = 16;
+
+/// How many times to poll for a packet to be sent before giving up.
+const POLL_ITERATIONS: usize = 100_000_000;
+
+/// The MAC address to use if the device doesn't provide one.
+const DEFAULT_MAC_ADDRESS: [u8; 6] = [0x52, 0x54, 0x00, 0xd1, 0x55, 0x01];
+
+// Feature bits
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+
+
+/// The single instance of the virtio NIC.
+/// TODO: in the future, we should support multiple NICs all stored elsewhere,
+/// e.g., on the PCI bus or somewhere else.
+static VIRTIO_NET_NIC: Once<MutexIrqSafe<VirtioNetNic>> = Once::new();
+
+/// Returns a reference to the VirtioNetNic wrapped in a MutexIrqSafe,
+/// if it exists and has been initialized.
+pub fn get_virtio_net_nic() -> Option<&'static MutexIrqSafe<VirtioNetNic>> {
+    VIRTIO_NET_NIC.try()
+}
+
+/// How many ReceiveBuffers are preallocated for this driver to use.
+const RX_BUFFER_POOL_SIZE: usize = 256;
+lazy_static! {
+    /// The pool of pre-allocated receive buffers that are used by the virtio NIC
+    /// and temporarily given to higher layers in the networking stack.
+    static ref RX_BUFFER_POOL: mpmc::Queue<ReceiveBuffer> = mpmc::Queue::with_capacity(RX_BUFFER_POOL_SIZE);
+}
+
+
+/// Returns `true` if the given PCI device is a virtio network device.
+pub fn is_virtio_net_device(pci_device: &PciDevice) -> bool {
+    virtio::device_type(pci_device) == Some(VIRTIO_DEVICE_TYPE_NET)
+}
+
+
+/// The start of a virtio network device's configuration structure.
+#[derive(FromBytes)]
+#[repr(C)]
+struct VirtioNetConfig {
+    /// Only valid if `VIRTIO_NET_F_MAC` was negotiated.
+    mac: [u8; 6],
+}
+
+
+/// A virtio network device, i.e., a paravirtualized NIC.
+pub struct VirtioNetNic {
+    transport: VirtioTransport,
+    rx_queue: Virtqueue,
+    tx_queue: Virtqueue,
+    /// The receive buffer and header slot of each chain in the receive queue, indexed by the chain's head descriptor.
+    rx_bufs_in_use: Vec<Option<(ReceiveBuffer, usize)>>,
+    /// The virtio-net headers: one slot per receive chain, followed by one zeroed slot used for every transmit.
+    _headers: MappedPages,
+    headers_phys: PhysicalAddress,
+    tx_header_slot: usize,
+    received_frames_producer: Sender<ReceivedFrame>,
+    received_frames: Receiver<ReceivedFrame>,
+    interrupt_num: u8,
+    /// Whether interrupts arrive on the legacy interrupt line rather than an MSI-X vector.
+    legacy_interrupt: bool,
+    mac_hardware: [u8; 6],
+    mac_spoofed: Option<[u8; 6]>,
+}
+
+impl NetworkInterfaceCard for VirtioNetNic {
+
+    fn send_packet(&mut self, transmit_buffer: TransmitBuffer) -> Result<(), &'static str> {
+        let header = VirtqueueBuffer {
+            phys_addr: self.header_address(self.tx_header_slot),
+            length: NET_HEADER_SIZE as u32,
+            device_writable: false,
+        };
+        let packet = VirtqueueBuffer {
+            phys_addr: transmit_buffer.phys_addr,
+            length: transmit_buffer.length as u32,
+            device_writable: false,
+        };
+        let head = self.tx_queue.add(&[header, packet])?;
+        self.transport.notify(&self.tx_queue)?;
+
+        // Wait for the packet to be sent
+        for _ in 0..POLL_ITERATIONS {
+            if let Some((used_head, _)) = self.tx_queue.pop_used() {
+                if used_head == head {
+                    return Ok(());
+                }
+            }
+            spin_loop_hint();
+        }
+        // The device may still read from the buffer, so it must never be freed.
+        core::mem::forget(transmit_buffer);
+        Err("virtio_net: timed out waiting for a packet to be sent")
+    }
+
+    fn get_received_frame(&mut self) -> Option<ReceivedFrame> {
+        self.received_frames.try_recv().ok()
+    }
+
+    fn poll_receive(&mut self) -> Result<(), &'static str> {
+        let mut reposted = false;
+        while let Some((head, length)) = self.rx_queue.pop_used() {
+            let (mut rx_buf, slot) = match self.rx_bufs_in_use.get_mut(head as usize).and_then(|b| b.take()) {
+                Some(in_use) => in_use,
+                None => {
+                    error!("virtio_net::poll_receive(): device used unknown receive chain {}", head);
+                    continue;
+                }
+            };
+
+            // Give the device a new receive buffer in place of the one we're passing up to higher layers.
+            let new_receive_buf = match RX_BUFFER_POOL.pop() {
+                Some(rx_buf) => rx_buf,
+                None => {
+                    warn!("NIC RX BUF POOL WAS EMPTY.... reallocating! This means that no task is consuming the accumulated received ethernet frames.");
+                    let (mp, phys_addr) = create_contiguous_mapping(RX_BUFFER_SIZE_IN_BYTES as usize, NIC_MAPPING_FLAGS)?;
+                    ReceiveBuffer::new(mp, phys_addr, RX_BUFFER_SIZE_IN_BYTES, &RX_BUFFER_POOL)
+                }
+            };
+            self.post_receive_buffer(new_receive_buf, slot)?;
+            reposted = true;
+
+            // The length written by the device includes the virtio-net header.
+            rx_buf.length = (length as usize).saturating_sub(NET_HEADER_SIZE) as u16;
+            // The channel is unbounded and this NIC holds its receiving side, so this should never fail.
+            if let Err(_e) = self.received_frames_producer.try_send(ReceivedFrame(vec![rx_buf])) {
+                error!("virtio_net::poll_receive(): failed to push a received frame onto the receive channel");
+            }
+        }
+
+        if reposted {
+            self.transport.notify(&self.rx_queue)?;
+        }
+        Ok(())
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac_spoofed.unwrap_or(self.mac_hardware)
+    }
+}
+
+
+impl VirtioNetNic {
+    /// Initializes the new virtio network device that is connected as the given PciDevice.
+    pub fn init(pci_device: &PciDevice) -> Result<&'static MutexIrqSafe<VirtioNetNic>, &'static str> {
+        let mut transport = VirtioTransport::new(pci_device)?;
+        let (rx_queue, tx_queue, mac_hardware, msix_vector) = match Self::setup_device(pci_device, &mut transport) {
+            Ok(setup) => setup,
+            Err(e) => {
+                transport.fail();
+                return Err(e);
+            }
+        };
+
+        // initialize the buffer pool
+        init_rx_buf_pool(RX_BUFFER_POOL_SIZE, RX_BUFFER_SIZE_IN_BYTES, &RX_BUFFER_POOL)?;
+
+        let num_rx_chains = rx_queue.size() as usize / 2;
+        let headers_size = (num_rx_chains + 1) * NET_HEADER_SLOT_SIZE;
+        let (mut headers, headers_phys) = create_contiguous_mapping(headers_size, NIC_MAPPING_FLAGS)?;
+        for b in headers.as_slice_mut::<u8>(0, headers_size)? { *b = 0; }
+
+        let (received_frames_producer, received_frames) = channel::unbounded();
+        let mut rx_bufs_in_use = Vec::with_capacity(rx_queue.size() as usize);
+        rx_bufs_in_use.resize_with(rx_queue.size() as usize, || None);
+
+        let mut nic = VirtioNetNic {
+            transport,
+            rx_queue,
+            tx_queue,
+            rx_bufs_in_use,
+            _headers: headers,
+            headers_phys,
+            tx_header_slot: num_rx_chains,
+            received_frames_producer,
+            received_frames,
+            interrupt_num: msix_vector.unwrap_or(0),
+            legacy_interrupt: msix_vector.is_none(),
+            mac_hardware,
+            mac_spoofed: None,
+        };
+        for slot in 0..num_rx_chains {
+            let rx_buf = RX_BUFFER_POOL.pop().ok_or("virtio_net: rx buffer pool was empty")?;
+            nic.post_receive_buffer(rx_buf, slot)?;
+        }
+
+        let nic_ref = VIRTIO_NET_NIC.call_once(|| MutexIrqSafe::new(nic));
+        let mut nic = nic_ref.lock();
+        if nic.legacy_interrupt {
+            use pic::PIC_MASTER_OFFSET;
+            let interrupt_num = pci_device.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
+            register_interrupt(interrupt_num, virtio_net_handler)?;
+            nic.interrupt_num = interrupt_num;
+        }
+        nic.transport.driver_ok()?;
+        nic.transport.notify(&nic.rx_queue)?;
+        info!("virtio_net: initialized NIC at {} with MAC address {:02x?}, interrupt {}", pci_device.location, mac_hardware, nic.interrupt_num);
+        Ok(nic_ref)
+    }
+
+    /// Negotiates features, sets up interrupts and both virtqueues, and reads the MAC address.
+    /// Returns the receive and transmit queues, the MAC address, and the MSI-X interrupt vector, if enabled.
+    fn setup_device(pci_device: &PciDevice, transport: &mut VirtioTransport) -> Result<(Virtqueue, Virtqueue, [u8; 6], Option<u8>), &'static str> {
+        let features = transport.negotiate_features(VIRTIO_NET_F_MAC)?;
+
+        let msix_vector = match transport.setup_interrupts(pci_device, 1, virtio_net_handler) {
+            Ok(()) => transport.interrupts().first().map(|v| v.vector()),
+            Err(_e) => {
+                debug!("virtio_net: couldn't enable MSI-X ({}), using the legacy interrupt line", _e);
+                None
+            }
+        };
+        // Only received packets raise interrupts; sent packets are polled for.
+        let rx_queue = transport.setup_queue(RX_QUEUE_INDEX, MAX_QUEUE_SIZE, msix_vector.map(|_| 0))?;
+        let tx_queue = transport.setup_queue(TX_QUEUE_INDEX, MAX_QUEUE_SIZE, None)?;
+
+        let mac = if features & VIRTIO_NET_F_MAC != 0 {
+            let config = transport.device_config::<VirtioNetConfig>()?;
+            unsafe { core::ptr::read_volatile(&config.mac) }
+        } else {
+            warn!("virtio_net: device has no MAC address, using {:02x?}", DEFAULT_MAC_ADDRESS);
+            DEFAULT_MAC_ADDRESS
+        };
+        Ok((rx_queue, tx_queue, mac, msix_vector))
+    }
+
+    /// Returns the physical address of the virtio-net header in the given slot.
+    fn header_address(&self, slot: usize) -> PhysicalAddress {
+        self.headers_phys + slot * NET_HEADER_SLOT_SIZE
+    }
+
+    /// Adds the given receive buffer to the receive queue, preceded by the header in the given slot.
+    /// The device is not notified until the caller does so.
+    fn post_receive_buffer(&mut self, rx_buf: ReceiveBuffer, slot: usize) -> Result<(), &'static str> {
+        let header = VirtqueueBuffer {
+            phys_addr: self.header_address(slot),
+            length: NET_HEADER_SIZE as u32,
+            device_writable: true,
+        };
+        let packet = VirtqueueBuffer {
+            phys_addr: rx_buf.phys_addr,
+            // buffers returned to the pool have a length of 0, so use their full size
+            length: RX_BUFFER_SIZE_IN_BYTES as u32,
+            device_writable: true,
+        };
+        let head = self.rx_queue.add(&[header, packet])?;
+        let entry = self.rx_bufs_in_use.get_mut(head as usize).ok_or("virtio_net: receive chain index out of bounds")?;
+        *entry = Some((rx_buf, slot));
+        Ok(())
+    }
+
+    pub fn spoof_mac(&mut self, spoofed_mac_addr: [u8; 6]) {
+        self.mac_spoofed = Some(spoofed_mac_addr);
+    }
+
+    /// The main interrupt handling routine for the virtio NIC.
+    /// This should be invoked from the actual interrupt handler entry point.
+    fn handle_interrupt(&mut self) -> Result<(), &'static str> {
+        if self.legacy_interrupt {
+            // reading the ISR status acknowledges the interrupt
+            self.transport.read_isr_status()?;
+        }
+        self.poll_receive()
+    }
+}
+
+extern "x86-interrupt" fn virtio_net_handler(_stack_frame: &mut ExceptionStackFrame) {
+    if let Some(ref nic_ref) = VIRTIO_NET_NIC.try() {
+        let mut nic = nic_ref.lock();
+        if let Err(e) = nic.handle_interrupt() {
+            error!("virtio_net_handler(): error handling interrupt: {:?}", e);
+        }
+        eoi(Some(nic.interrupt_num));
+    } else {
+        error!("BUG: virtio_net_handler(): virtio NIC hasn't yet been initialized!");
+    }
+}
@@ -177,6 +177,18 @@ impl WaitQueue {
         self.notify(None)
     }
 
+    /// Wake up all `Task`s that are waiting on this queue.
+    /// # Return
+    /// * returns the number of `Task`s that were woken up.
+    pub fn notify_all(&self) -> usize {
+        let mut wq_locked = self.0.lock();
+        let num_tasks = wq_locked.len();
+        for t in wq_locked.drain(..) {
+            t.unblock();
+        }
+        num_tasks
+    }
+
     /// Wake up a specific `Task` that is waiting on this queue.
     /// # Return
     /// * returns `true` if the given `Task` was waiting and was woken up,
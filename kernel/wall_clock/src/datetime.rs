@@ -0,0 +1,143 @@
+//! Conversions between Unix timestamps and calendar dates and times in the proleptic Gregorian calendar.
+
+use core::fmt;
+
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+const WEEKDAY_NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTH_NAMES: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+
+/// A calendar date and time of day, without a time zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i64,
+    /// The month, from 1 to 12.
+    pub month: u8,
+    /// The day of the month, from 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Returns the date and time that is the given number of seconds and nanoseconds after the Unix epoch.
+    pub fn from_unix(seconds: i64, nanosecond: u32) -> DateTime {
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let second_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day % 3600 / 60) as u8,
+            second: (second_of_day % 60) as u8,
+            nanosecond,
+        }
+    }
+
+    /// Returns the number of whole seconds between the Unix epoch and this date and time.
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    /// Returns the day of the week, from 0 (Sunday) to 6 (Saturday).
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday.
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as u8
+    }
+
+    /// Returns the abbreviated English name of the day of the week, e.g., "Thu".
+    pub fn weekday_name(&self) -> &'static str {
+        WEEKDAY_NAMES[self.weekday() as usize]
+    }
+
+    /// Returns the abbreviated English name of the month, e.g., "Oct".
+    pub fn month_name(&self) -> &'static str {
+        MONTH_NAMES[(self.month as usize).saturating_sub(1) % 12]
+    }
+
+    /// Parses a date and time in the form `YYYY-MM-DD HH:MM[:SS]`, where a `T` may separate the date from the time.
+    pub fn parse(s: &str) -> Result<DateTime, &'static str> {
+        const INVALID: &str = "wall_clock: the date must have the form YYYY-MM-DD HH:MM[:SS]";
+        let s = s.trim();
+        let separator = s.find(&[' ', 'T'][..]).ok_or(INVALID)?;
+        let (date, time) = (&s[.. separator], s[separator + 1 ..].trim());
+
+        let mut date_fields = date.split('-');
+        let year = date_fields.next().and_then(|y| y.parse::<i64>().ok()).ok_or(INVALID)?;
+        let month = date_fields.next().and_then(|m| m.parse::<u8>().ok()).ok_or(INVALID)?;
+        let day = date_fields.next().and_then(|d| d.parse::<u8>().ok()).ok_or(INVALID)?;
+        let mut time_fields = time.split(':');
+        let hour = time_fields.next().and_then(|h| h.parse::<u8>().ok()).ok_or(INVALID)?;
+        let minute = time_fields.next().and_then(|m| m.parse::<u8>().ok()).ok_or(INVALID)?;
+        let second = match time_fields.next() {
+            Some(s) => s.parse::<u8>().map_err(|_| INVALID)?,
+            None => 0,
+        };
+        if date_fields.next().is_some() || time_fields.next().is_some() {
+            return Err(INVALID);
+        }
+
+        if !(1 ..= 12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err("wall_clock: the date doesn't exist");
+        }
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err("wall_clock: the time of day doesn't exist");
+        }
+        Ok(DateTime { year, month, day, hour, minute, second, nanosecond: 0 })
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats the date and time as `YYYY-MM-DD HH:MM:SS`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days between 1970-01-01 and the given date.
+///
+/// Years are counted from March, so that leap days fall at the end of a year (see Howard Hinnant's `days_from_civil`).
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let (month, day) = (month as i64, day as i64);
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the year, month, and day of the date that lies the given number of days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
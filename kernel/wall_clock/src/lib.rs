@@ -0,0 +1,187 @@
+//! The system's wall-clock time, i.e., the current date and time of day.
+//!
+//! The wall clock is kept as an offset from the monotonic clock of the `timer` crate,
+//! so reading it is as cheap and precise as reading the monotonic clock.
+//! The offset is initialized from the CMOS real-time clock (RTC) at boot, and can be changed by
+//! setting the time with [`set_time()`](fn.set_time.html) or by synchronizing with an NTP server
+//! with [`sntp::sync()`](sntp/fn.sync.html), both of which also update the RTC.
+//!
+//! The wall clock is always kept in UTC; the system's [`TimeZone`](struct.TimeZone.html) only affects
+//! how it is presented as local time, see [`local_now()`](fn.local_now.html).
+//!
+//! Time is represented by [`SystemTime`](struct.SystemTime.html), which has the same interface
+//! as `std::time::SystemTime`, such that a port of `std` can be built on top of it.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate rtc;
+extern crate timer;
+extern crate net_stack;
+
+mod datetime;
+mod timezone;
+pub mod sntp;
+
+pub use datetime::DateTime;
+pub use timezone::TimeZone;
+
+use core::sync::atomic::{AtomicI64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+
+/// The wall-clock time at which the monotonic clock was started, in nanoseconds since the Unix epoch.
+/// Adding the monotonic time to this yields the wall-clock time.
+static BOOT_TIME_NS: AtomicI64 = AtomicI64::new(0);
+/// The time zone in which local time is presented.
+static TIME_ZONE: Mutex<TimeZone> = Mutex::new(TimeZone::UTC);
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+
+/// A point in wall-clock time, measured from the Unix epoch, 1970-01-01 00:00:00 UTC.
+///
+/// Unlike an `Instant`, a `SystemTime` may jump backwards when the wall clock is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime {
+    /// Nanoseconds since the Unix epoch, which are negative for times before it.
+    nanos: i128,
+}
+
+/// The Unix epoch, 1970-01-01 00:00:00 UTC.
+pub const UNIX_EPOCH: SystemTime = SystemTime { nanos: 0 };
+
+impl SystemTime {
+    /// Returns the current wall-clock time.
+    pub fn now() -> SystemTime {
+        SystemTime {
+            nanos: BOOT_TIME_NS.load(Ordering::Acquire) as i128 + timer::now().as_nanos() as i128,
+        }
+    }
+
+    /// Returns the amount of time from `earlier` to this time,
+    /// or, as the error, the amount of time from this time to `earlier` if `earlier` is later.
+    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, Duration> {
+        let difference = self.nanos - earlier.nanos;
+        if difference >= 0 {
+            Ok(nanos_to_duration(difference))
+        } else {
+            Err(nanos_to_duration(-difference))
+        }
+    }
+
+    /// Returns the amount of time that passed since this time, or, as the error, how far this time lies in the future.
+    pub fn elapsed(&self) -> Result<Duration, Duration> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    /// Returns the time `duration` after this one, or `None` if it can't be represented.
+    pub fn checked_add(&self, duration: Duration) -> Option<SystemTime> {
+        self.nanos.checked_add(duration.as_nanos() as i128).map(|nanos| SystemTime { nanos })
+    }
+
+    /// Returns the time `duration` before this one, or `None` if it can't be represented.
+    pub fn checked_sub(&self, duration: Duration) -> Option<SystemTime> {
+        self.nanos.checked_sub(duration.as_nanos() as i128).map(|nanos| SystemTime { nanos })
+    }
+
+    /// Returns the time that lies the given number of seconds and nanoseconds after (or before, if negative) the Unix epoch.
+    pub fn from_unix(seconds: i64, nanosecond: u32) -> SystemTime {
+        SystemTime { nanos: seconds as i128 * NANOS_PER_SEC + nanosecond as i128 }
+    }
+
+    /// Returns this time as the number of whole seconds since the Unix epoch, rounded down,
+    /// and the number of nanoseconds after that second.
+    pub fn to_unix(&self) -> (i64, u32) {
+        (self.nanos.div_euclid(NANOS_PER_SEC) as i64, self.nanos.rem_euclid(NANOS_PER_SEC) as u32)
+    }
+
+    /// Returns this time as a date and time of day in UTC.
+    pub fn to_utc(&self) -> DateTime {
+        let (seconds, nanosecond) = self.to_unix();
+        DateTime::from_unix(seconds, nanosecond)
+    }
+
+    /// Returns this time as a date and time of day in the given time zone.
+    pub fn to_local(&self, zone: TimeZone) -> DateTime {
+        let (seconds, nanosecond) = self.to_unix();
+        DateTime::from_unix(seconds + zone.offset_seconds() as i64, nanosecond)
+    }
+
+    /// Returns the time at which the given date and time of day occurs in the given time zone.
+    pub fn from_local(datetime: &DateTime, zone: TimeZone) -> SystemTime {
+        SystemTime::from_unix(datetime.to_unix() - zone.offset_seconds() as i64, datetime.nanosecond)
+    }
+}
+
+fn nanos_to_duration(nanos: i128) -> Duration {
+    Duration::new((nanos / NANOS_PER_SEC) as u64, (nanos % NANOS_PER_SEC) as u32)
+}
+
+
+/// Initializes the wall clock from the RTC.
+///
+/// The RTC only counts whole seconds, so the wall clock may be up to one second behind until it is set or synchronized.
+pub fn init() -> Result<(), &'static str> {
+    let rtc_time = rtc::read_rtc();
+    set_clock(SystemTime::from_unix(rtc_time.unix_timestamp() as i64, 0));
+    info!("wall_clock: initialized from the RTC to {} UTC", now().to_utc());
+    Ok(())
+}
+
+/// Returns the current wall-clock time. This is the same as [`SystemTime::now()`](struct.SystemTime.html#method.now).
+pub fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Returns the current date and time of day in the system's time zone.
+pub fn local_now() -> DateTime {
+    now().to_local(time_zone())
+}
+
+/// Sets the wall clock to the given time, and writes it to the RTC so that it persists across reboots.
+pub fn set_time(time: SystemTime) {
+    set_clock(time);
+    write_rtc();
+    info!("wall_clock: the time was set to {} UTC", time.to_utc());
+}
+
+/// Returns the time zone in which local time is presented.
+pub fn time_zone() -> TimeZone {
+    *TIME_ZONE.lock()
+}
+
+/// Sets the time zone in which local time is presented. This doesn't change the wall clock, which is kept in UTC.
+pub fn set_time_zone(zone: TimeZone) {
+    *TIME_ZONE.lock() = zone;
+}
+
+/// Changes the offset from the monotonic clock such that the wall clock shows the given time right now.
+fn set_clock(time: SystemTime) {
+    let boot_time = time.nanos - timer::now().as_nanos() as i128;
+    BOOT_TIME_NS.store(boot_time as i64, Ordering::Release);
+}
+
+/// Moves the wall clock forward by the given number of nanoseconds (or backward, if negative).
+fn adjust(offset_ns: i64) {
+    BOOT_TIME_NS.fetch_add(offset_ns, Ordering::AcqRel);
+}
+
+/// Writes the current time to the RTC, which can only store years from 2000 to 2099.
+fn write_rtc() {
+    let utc = now().to_utc();
+    if utc.year < 2000 || utc.year > 2099 {
+        warn!("wall_clock: the RTC can't store the year {}", utc.year);
+        return;
+    }
+    rtc::write_rtc(&rtc::RtcTime {
+        seconds: utc.second,
+        minutes: utc.minute,
+        hours: utc.hour,
+        days: utc.day,
+        months: utc.month,
+        years: (utc.year - 2000) as u8,
+    });
+}
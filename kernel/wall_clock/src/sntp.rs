@@ -0,0 +1,141 @@
+//! A simple SNTP client (RFC 4330), which asks an NTP server for the time and corrects the wall clock.
+//!
+//! A single request is sent, and the clock's offset is computed from the four timestamps of the exchange
+//! as `((t2 - t1) + (t3 - t4)) / 2`, which assumes that the request and reply took equally long.
+
+use core::time::Duration;
+use net_stack::{IpAddress, IpEndpoint, WOULD_BLOCK};
+use net_stack::udp;
+use timer;
+use {SystemTime, UNIX_EPOCH};
+
+
+/// The UDP port of NTP servers.
+pub const NTP_PORT: u16 = 123;
+/// How long to wait for a reply by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The size of an NTP packet without extension fields or authentication.
+const PACKET_SIZE: usize = 48;
+/// The number of seconds between the NTP epoch (1900-01-01) and the Unix epoch (1970-01-01).
+const NTP_EPOCH_OFFSET_SECONDS: u64 = 2_208_988_800;
+/// How often the socket is checked for the reply.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// The leap indicator that means that the server's clock is unsynchronized.
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+
+/// The result of a successful exchange with an NTP server.
+#[derive(Clone, Copy, Debug)]
+pub struct SntpReply {
+    pub server: IpAddress,
+    /// How far the server's clock is ahead of the wall clock, in nanoseconds (behind it, if negative).
+    pub offset_ns: i64,
+    /// How long the request and reply spent in the network, excluding the server's processing time.
+    pub round_trip: Duration,
+    /// The server's distance from a reference clock, where 1 is a primary server.
+    pub stratum: u8,
+}
+
+
+/// Asks the NTP server at the given address for the time, waiting up to `timeout` for its reply,
+/// and returns how far the wall clock is off. The wall clock isn't changed.
+pub fn query(server: IpAddress, timeout: Duration) -> Result<SntpReply, &'static str> {
+    let socket = udp::bind(0)?;
+    let result = exchange(&socket, server, timeout);
+    socket.close();
+    result
+}
+
+/// Asks the NTP server at the given address for the time and corrects the wall clock and the RTC accordingly.
+pub fn sync(server: IpAddress, timeout: Duration) -> Result<SntpReply, &'static str> {
+    let reply = query(server, timeout)?;
+    ::adjust(reply.offset_ns);
+    ::write_rtc();
+    info!("wall_clock: synchronized with NTP server {} (offset {} ns, stratum {})", server, reply.offset_ns, reply.stratum);
+    Ok(reply)
+}
+
+fn exchange(socket: &udp::UdpHandle, server: IpAddress, timeout: Duration) -> Result<SntpReply, &'static str> {
+    let mut request = [0u8; PACKET_SIZE];
+    request[0] = (VERSION << 3) | MODE_CLIENT;
+    // The server echoes the transmit timestamp as the reply's originate timestamp, which identifies the reply.
+    let t1 = SystemTime::now();
+    let t1_ntp = to_ntp_timestamp(t1);
+    request[40 .. 48].copy_from_slice(&t1_ntp.to_be_bytes());
+    socket.send_to(&request, IpEndpoint::new(server, NTP_PORT))?;
+
+    let deadline = timer::now() + timeout;
+    let mut reply = [0u8; PACKET_SIZE];
+    loop {
+        match socket.try_recv_from(&mut reply) {
+            Ok((len, endpoint)) => {
+                let t4 = SystemTime::now();
+                if len < PACKET_SIZE || endpoint.addr != server || endpoint.port != NTP_PORT || read_u64(&reply, 24) != t1_ntp {
+                    continue; // not the reply to our request
+                }
+                return parse_reply(&reply, server, t1, t4);
+            }
+            Err(WOULD_BLOCK) => { }
+            Err(e) => return Err(e),
+        }
+        if timer::now() >= deadline {
+            return Err("wall_clock: the NTP server didn't reply in time");
+        }
+        timer::sleep(POLL_INTERVAL)?;
+    }
+}
+
+fn parse_reply(reply: &[u8; PACKET_SIZE], server: IpAddress, t1: SystemTime, t4: SystemTime) -> Result<SntpReply, &'static str> {
+    let leap_indicator = reply[0] >> 6;
+    let mode = reply[0] & 0x7;
+    let stratum = reply[1];
+    if mode != MODE_SERVER {
+        return Err("wall_clock: the NTP reply wasn't sent by a server");
+    }
+    if stratum == 0 || leap_indicator == LEAP_UNSYNCHRONIZED {
+        // stratum 0 is a "kiss-o'-death" reply, which tells the client to back off
+        return Err("wall_clock: the NTP server's clock isn't synchronized");
+    }
+    let t2 = from_ntp_timestamp(read_u64(reply, 32));
+    let t3 = from_ntp_timestamp(read_u64(reply, 40));
+    let (t1, t4) = (nanos(t1), nanos(t4));
+
+    let offset_ns = ((t2 - t1) + (t3 - t4)) / 2;
+    let round_trip = (t4 - t1) - (t3 - t2);
+    Ok(SntpReply {
+        server,
+        offset_ns: offset_ns as i64,
+        round_trip: Duration::from_nanos(if round_trip < 0 { 0 } else { round_trip as u64 }),
+        stratum,
+    })
+}
+
+fn read_u64(packet: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&packet[offset .. offset + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+fn nanos(time: SystemTime) -> i128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i128
+}
+
+/// Converts a time into an NTP timestamp, i.e., seconds since 1900 as a fixed-point number with 32 fractional bits.
+fn to_ntp_timestamp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() + NTP_EPOCH_OFFSET_SECONDS;
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+/// Converts an NTP timestamp into nanoseconds since the Unix epoch.
+fn from_ntp_timestamp(timestamp: u64) -> i128 {
+    let seconds = (timestamp >> 32) as i128 - NTP_EPOCH_OFFSET_SECONDS as i128;
+    let fraction = ((timestamp & 0xFFFF_FFFF) as i128 * 1_000_000_000) >> 32;
+    seconds * 1_000_000_000 + fraction
+}
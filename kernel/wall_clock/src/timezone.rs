@@ -0,0 +1,71 @@
+//! Time zones, which are fixed offsets from UTC.
+
+use core::fmt;
+
+
+/// A time zone, i.e., the offset of local time from UTC.
+///
+/// Daylight saving time isn't modeled; a zone that observes it must be set again when it changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeZone {
+    offset_seconds: i32,
+}
+
+/// The largest offset from UTC that a time zone may have, 18 hours.
+const MAX_OFFSET_SECONDS: i32 = 18 * 3600;
+
+impl TimeZone {
+    /// Coordinated Universal Time, the default time zone.
+    pub const UTC: TimeZone = TimeZone { offset_seconds: 0 };
+
+    /// Returns the time zone whose local time is `offset_seconds` ahead of UTC (or behind it, if negative).
+    pub fn from_offset_seconds(offset_seconds: i32) -> Result<TimeZone, &'static str> {
+        if offset_seconds.abs() > MAX_OFFSET_SECONDS {
+            return Err("wall_clock: a time zone's offset from UTC must be at most 18 hours");
+        }
+        Ok(TimeZone { offset_seconds })
+    }
+
+    /// Returns how many seconds local time is ahead of UTC.
+    pub fn offset_seconds(&self) -> i32 {
+        self.offset_seconds
+    }
+
+    /// Parses a time zone such as `UTC`, `Z`, `UTC+2`, `GMT-8`, `+05:30`, or `-0800`.
+    pub fn parse(s: &str) -> Result<TimeZone, &'static str> {
+        const INVALID: &str = "wall_clock: the time zone must have the form UTC, UTC+H, +HH:MM, or -HHMM";
+        let s = s.trim();
+        let offset = if s.starts_with("UTC") || s.starts_with("GMT") { &s[3 ..] } else { s };
+        if offset.is_empty() || offset == "Z" {
+            return Ok(TimeZone::UTC);
+        }
+        let (sign, digits) = match offset.as_bytes()[0] {
+            b'+' => (1, &offset[1 ..]),
+            b'-' => (-1, &offset[1 ..]),
+            _ => return Err(INVALID),
+        };
+        let (hours, minutes) = match digits.find(':') {
+            Some(colon) => (&digits[.. colon], &digits[colon + 1 ..]),
+            None if digits.len() > 2 => (&digits[.. digits.len() - 2], &digits[digits.len() - 2 ..]),
+            None => (digits, "0"),
+        };
+        let hours = hours.parse::<i32>().map_err(|_| INVALID)?;
+        let minutes = minutes.parse::<i32>().map_err(|_| INVALID)?;
+        if minutes > 59 {
+            return Err(INVALID);
+        }
+        TimeZone::from_offset_seconds(sign * (hours * 3600 + minutes * 60))
+    }
+}
+
+impl fmt::Display for TimeZone {
+    /// Formats the time zone as `UTC` or, e.g., `UTC+05:30`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.offset_seconds == 0 {
+            return write!(f, "UTC");
+        }
+        let sign = if self.offset_seconds < 0 { '-' } else { '+' };
+        let offset = self.offset_seconds.abs();
+        write!(f, "UTC{}{:02}:{:02}", sign, offset / 3600, offset % 3600 / 60)
+    }
+}
@@ -3,7 +3,10 @@
 //! This library will create a window with default title bar and border. It handles the commonly used interactions like moving
 //! the window or close the window. Also, it is responsible to show title bar differently when window is active. 
 //!
-//! A window can render itself to the screen via a window manager. The window manager will compute the bounding box of the updated part and composites it with other existing windows according to their order.
+//! A window can render itself to the screen via a window manager. Rendering only marks the updated part of the window as damaged;
+//! the window manager then composites it with other existing windows according to their order in its next frame.
+//! Applications that change several small parts of a window can submit just those parts via `Window::render_areas()`.
+//! A window can also be made translucent via `Window::set_opacity()`, in which case the windows below it shine through.
 //!
 //! The library
 //! frees applications from handling the complicated interaction with window manager, however, advanced users could learn from
@@ -27,6 +30,7 @@ extern crate shapes;
 extern crate color;
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use mpmc::Queue;
 use event_types::{Event, MousePositionEvent};
 use owning_ref::{MutexGuardRef, MutexGuardRefMut};
@@ -151,17 +155,14 @@ impl Window {
             window.show_button(TopButton::Hide, 1, &mut inner);
         }
 
-        let _window_bounding_box = Rectangle {
+        let window_bounding_box = Rectangle {
             top_left: coordinate,
             bottom_right: coordinate + (width as isize, height as isize)
         };
 
         let mut wm = wm_ref.lock();
         wm.set_active(&window.inner, false)?; 
-
-        // Currently, refresh the whole screen instead of just the new window's bounds
-        // wm.refresh_bottom_windows(Some(window_bounding_box), true)?;
-        wm.refresh_bottom_windows(Option::<Rectangle>::None, true)?;
+        wm.damage(window_bounding_box);
         
         Ok(window)
     }
@@ -193,6 +194,8 @@ impl Window {
             self.show_button(TopButton::Close, 1, &mut inner);
             self.show_button(TopButton::MinimizeMaximize, 1, &mut inner);
             self.show_button(TopButton::Hide, 1, &mut inner);
+            // the title bar was redrawn in a different color
+            need_refresh_three_button = true;
         }
 
         // If we cannot handle this event as an "internal" event (e.g., clicking on the window title bar or border),
@@ -304,9 +307,8 @@ impl Window {
         }
 
         if need_refresh_three_button {
-            let area = self.get_button_area();
-            wm.refresh_active_window(Some(area))?;
-            wm.refresh_mouse()?;
+            let area = self.get_button_area() + self.inner.lock().get_position();
+            wm.damage(area);
         }
 
         if call_later_do_refresh_floating_border {
@@ -327,6 +329,7 @@ impl Window {
     /// Refreshes the whole window if `bounding_box` is `None`.
     /// 
     /// This method should be invoked after updating the window's contents in order to see its new content.
+    /// The new content appears on the screen when the window manager composites its next frame.
     pub fn render(&mut self, bounding_box: Option<Rectangle>) -> Result<(), &'static str> {
 
         // Induced bug rendering attempting to access out of bound memory
@@ -337,16 +340,52 @@ impl Window {
             }
         }
 
-        let wm_ref = WINDOW_MANAGER.try().ok_or("The static window manager was not yet initialized")?;
+        match bounding_box {
+            Some(bounding_box) => self.render_areas(Some(bounding_box)),
+            None => {
+                let bounds = self.inner.lock().bounds();
+                Self::damage(Some(bounds))
+            }
+        }
+    }
 
-        // Convert the given relative `bounding_box` to an absolute one (relative to the screen, not the window).
-        let coordinate = {
+    /// Renders the given `areas` of this `Window`, which are relative to the top-left coordinate of this `Window`.
+    ///
+    /// This is more efficient than rendering their bounding box if the areas are small and far apart,
+    /// e.g., when an application updates a few separate widgets at once.
+    pub fn render_areas<I: IntoIterator<Item = Rectangle>>(&mut self, areas: I) -> Result<(), &'static str> {
+        // Convert the given relative areas to absolute ones (relative to the screen, not the window),
+        // clipped to the window such that they can't damage other parts of the screen.
+        let absolute_areas = {
             let window = self.inner.lock();
-            window.get_position()
+            let bounds = window.bounds();
+            let coordinate = window.get_position();
+            areas.into_iter()
+                .filter_map(|area| (area + coordinate).intersection(&bounds))
+                .collect::<Vec<_>>()
         };
-        let absolute_bounding_box = bounding_box.map(|bb| bb + coordinate);
+        Self::damage(absolute_areas)
+    }
 
-        wm_ref.lock().refresh_windows(absolute_bounding_box)
+    /// Sets the opacity of this window, from 0 (invisible) to 255 (opaque), and re-renders it.
+    ///
+    /// The windows below a translucent window shine through it.
+    pub fn set_opacity(&mut self, opacity: u8) -> Result<(), &'static str> {
+        let bounds = {
+            let mut window = self.inner.lock();
+            window.set_opacity(opacity);
+            window.bounds()
+        };
+        Self::damage(Some(bounds))
+    }
+
+    /// Marks the given absolute `areas` of the screen as damaged, such that the window manager redraws them.
+    fn damage<I: IntoIterator<Item = Rectangle>>(areas: I) -> Result<(), &'static str> {
+        let mut wm = WINDOW_MANAGER.try().ok_or("The static window manager was not yet initialized")?.lock();
+        for area in areas {
+            wm.damage(area);
+        }
+        Ok(())
     }
 
     /// Returns a `Rectangle` describing the position and dimensions of this Window's content region,
@@ -377,6 +416,17 @@ impl Window {
             .unwrap_or(false)
     }
 
+    /// Attaches this window to the virtual terminal with the given `number`,
+    /// such that pressing Alt and the corresponding function key brings it to the front.
+    ///
+    /// Returns an error if another window is already attached to that virtual terminal.
+    pub fn attach_virtual_terminal(&self, number: usize) -> Result<(), &'static str> {
+        WINDOW_MANAGER.try()
+            .ok_or("The window manager is not initialized")?
+            .lock()
+            .attach_virtual_terminal(number, &self.inner)
+    }
+
     /// Draw the border of this window, with argument of whether this window is active now
     fn draw_border(&mut self, active: bool) {
         let mut inner = self.inner.lock();
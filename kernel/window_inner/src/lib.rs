@@ -76,6 +76,9 @@ pub struct WindowInner {
     /// 
     /// TODO: FIXME (kevinaboos): this should be private, and window moving logic should be moved into this crate.
     pub moving: WindowMovingStatus,
+    /// The opacity with which the window manager blends this window over the windows below it,
+    /// where 255 means fully opaque.
+    opacity: u8,
 }
 
 impl WindowInner {
@@ -93,6 +96,7 @@ impl WindowInner {
             event_producer,
             framebuffer,
             moving: WindowMovingStatus::Stationary,
+            opacity: 255,
         }
     }
 
@@ -117,6 +121,25 @@ impl WindowInner {
         self.coordinate = coordinate;
     }
 
+    /// Returns the bounds of this window, expressed relative to the top-left of the screen.
+    pub fn bounds(&self) -> Rectangle {
+        let (width, height) = self.get_size();
+        Rectangle {
+            top_left: self.coordinate,
+            bottom_right: self.coordinate + (width as isize, height as isize),
+        }
+    }
+
+    /// Returns the opacity of this window, where 255 means fully opaque and 0 means invisible.
+    pub fn opacity(&self) -> u8 {
+        self.opacity
+    }
+
+    /// Sets the opacity of this window, where 255 means fully opaque and 0 means invisible.
+    pub fn set_opacity(&mut self, opacity: u8) {
+        self.opacity = opacity;
+    }
+
     /// Returns an immutable reference to this window's virtual Framebuffer. 
     pub fn framebuffer(&self) -> &Framebuffer<AlphaPixel> {
         &self.framebuffer
@@ -3,16 +3,25 @@
 //! A window manager holds a set of `WindowInner` objects, including an active window, a list of shown windows and a list of hidden windows. The hidden windows are totally overlapped by others.
 //!
 //! A window manager owns a bottom framebuffer and a top framebuffer. The bottom is the background of the desktop and the top framebuffer contains a floating window border and a mouse arrow. 
-//! A window manager also contains a final framebuffer which is mapped to the screen. In refreshing an area, the manager will render all the framebuffers to the final one in order: bottom -> hide list -> showlist -> active -> top.
+//! A window manager also contains a final framebuffer which is mapped to the screen, and a back buffer of the same size.
 //!
-//! The window manager provides methods to update within some bounding boxes rather than the whole screen for better performance.
+//! # Damage tracking and double buffering
+//! Changes to the screen are not drawn immediately. Instead, every change marks the area it covers as damaged via `WindowManager::damage()`,
+//! e.g., when a window renders new content, is moved, or the mouse pointer moves.
+//! A separate compositor task then redraws the damaged areas at most once per frame (see `FRAME_INTERVAL`):
+//! it composites all framebuffers in a single pass into the back buffer in order: bottom -> hide list -> showlist -> active -> top,
+//! blending each window according to its opacity, and then copies only the finished areas to the final framebuffer.
+//! As a result, many small updates within one frame are coalesced, and intermediate states are never visible on the screen.
+//!
+//! Terminal windows can be attached to one of several virtual terminals, which the user switches between with Alt+F1 through Alt+F4.
+//! Switching to a virtual terminal that has no window yet starts a new shell in it.
 
 #![no_std]
 
 extern crate spin;
 #[macro_use] extern crate log;
 #[macro_use] extern crate alloc;
-extern crate mpmc;
+extern crate channel;
 extern crate event_types;
 extern crate compositor;
 extern crate framebuffer;
@@ -22,19 +31,22 @@ extern crate keycodes_ascii;
 extern crate mod_mgmt;
 extern crate mouse_data;
 extern crate path;
-extern crate scheduler; 
 extern crate spawn;
+extern crate task;
 extern crate window_inner;
 extern crate shapes;
 extern crate color;
+extern crate timer;
 
 use alloc::collections::VecDeque;
+use core::time::Duration;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::{Vec};
-use compositor::{Compositor, FramebufferUpdates, CompositableRegion};
+use compositor::{Compositor, FramebufferUpdates};
+use compositor::damage::Damage;
 
-use mpmc::Queue;
+use channel::{Sender, Receiver};
 use event_types::{Event, MousePositionEvent};
 use framebuffer::{Framebuffer, AlphaPixel};
 use color::{Color};
@@ -44,6 +56,7 @@ use keycodes_ascii::{KeyAction, KeyEvent, Keycode};
 use mouse_data::MouseEvent;
 use path::Path;
 use spin::{Mutex, Once};
+use task::RestartPolicy;
 use window_inner::{WindowInner, WindowMovingStatus};
 
 /// The instance of the default window manager
@@ -69,6 +82,12 @@ static MOUSE_POINTER_IMAGE: [[Color; MOUSE_POINTER_SIZE]; MOUSE_POINTER_SIZE] =
     ]
 };
 
+/// The number of virtual terminals, which are numbered from 1 and switched to with Alt+F1, Alt+F2, and so on.
+pub const VIRTUAL_TERMINAL_COUNT: usize = 4;
+
+/// The minimum time between two frames composited by the compositor task, which limits it to about 60 frames per second.
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
 // the border indicating new window position and size
 const WINDOW_BORDER_SIZE: usize = 3;
 // border's inner color
@@ -94,6 +113,14 @@ pub struct WindowManager {
     top_fb: Framebuffer<AlphaPixel>,
     /// The final framebuffer which is mapped to the screen (the actual display device).
     pub final_fb: Framebuffer<AlphaPixel>,
+    /// The back buffer, into which all framebuffers are composited before the result is copied to the final framebuffer.
+    back_fb: Framebuffer<AlphaPixel>,
+    /// The areas of the screen that changed since the last frame was composited.
+    damage: Damage,
+    /// Used to wake up the compositor task when an area of the screen was damaged.
+    frame_requests: Sender<()>,
+    /// The window attached to each virtual terminal, where index 0 holds virtual terminal 1.
+    virtual_terminals: Vec<Weak<Mutex<WindowInner>>>,
 }
 
 impl WindowManager {
@@ -135,17 +162,9 @@ impl WindowManager {
             None => {}
         }
         self.active = Arc::downgrade(inner_ref);
-        let area = {
-            let window = inner_ref.lock();
-            let top_left = window.get_position();
-            let (width, height) = window.get_size();          
-            Rectangle {
-                top_left: top_left,
-                bottom_right: top_left + (width as isize, height as isize)
-            }
-        };
         if refresh {
-            self.refresh_bottom_windows(Some(area), true)?;
+            let area = inner_ref.lock().bounds();
+            self.damage(area);
         }
         Ok(first_active)
     }
@@ -180,23 +199,10 @@ impl WindowManager {
 
     /// delete a window and refresh its region
     pub fn delete_window(&mut self, inner_ref: &Arc<Mutex<WindowInner>>) -> Result<(), &'static str> {
-        let (top_left, bottom_right) = {
-            let inner = inner_ref.lock();
-            let top_left = inner.get_position();
-            let (width, height) = inner.get_size();
-            let bottom_right = top_left + (width as isize, height as isize);
-            (top_left, bottom_right)
-        };
-        let area = Some(
-            Rectangle {
-                top_left: top_left,
-                bottom_right: bottom_right
-            }
-        );
+        let area = inner_ref.lock().bounds();
 
         if let Some(current_active) = self.active.upgrade() {
             if Arc::ptr_eq(&current_active, inner_ref) {
-                self.refresh_bottom_windows(area, false)?;
                 if let Some(window) = self.show_list.remove(0) {
                     self.active = window;
                 } else if let Some(window) = self.hide_list.remove(0) {
@@ -204,13 +210,14 @@ impl WindowManager {
                 } else {
                     self.active = Weak::new(); // delete reference
                 }
+                self.damage(area);
                 return Ok(());
             }
         }
         
         if let Some(index) = self.is_window_in_show_list(inner_ref) {
             self.show_list.remove(index);
-            self.refresh_windows(area)?;
+            self.damage(area);
             return Ok(())        
         }
 
@@ -221,114 +228,85 @@ impl WindowManager {
         Err("cannot find this window")
     }
 
-    /// Refresh the region in `bounding_box`. Only render the bottom final framebuffer and windows. Ignore the active window if `active` is false.
-    pub fn refresh_bottom_windows<B: CompositableRegion + Clone>(
-        &mut self, 
-        bounding_box: impl IntoIterator<Item = B> + Clone,
-        active: bool,
-    ) -> Result<(), &'static str> {
-        // bottom framebuffer
-        let bottom_fb_area = FramebufferUpdates {
-            src_framebuffer: &self.bottom_fb,
-            coordinate_in_dest_framebuffer: Coord::new(0, 0),
-        };
-
-        // list of windows to be updated
-        let mut window_ref_list = Vec::new();
-        for window in &self.hide_list {
-            if let Some(window_ref) = window.upgrade() {
-                window_ref_list.push(window_ref);
-            }
-        }
-        for window in &self.show_list {
-            if let Some(window_ref) = window.upgrade() {
-                window_ref_list.push(window_ref);
-            }
-        }
-        if active {
-            if let Some(window_ref) = self.active.upgrade() {
-                window_ref_list.push(window_ref)
-            }
-        }
-
-        // lock windows
-        let locked_window_list = &window_ref_list.iter().map(|x| x.lock()).collect::<Vec<_>>();
-
-        // create updated framebuffer info objects
-        let window_bufferlist = locked_window_list.iter().map(|window| {
-            FramebufferUpdates {
-                src_framebuffer: window.framebuffer(),
-                coordinate_in_dest_framebuffer: window.get_position(),
-            }
-        }).collect::<Vec<_>>();
-        
-        let buffer_iter = Some(bottom_fb_area).into_iter().chain(window_bufferlist.into_iter());
-        FRAME_COMPOSITOR.lock().composite(buffer_iter, &mut self.final_fb, bounding_box)?;
-        
-        Ok(())
+    /// Marks the given `area` of the screen as damaged, such that it is redrawn in the next frame.
+    /// The `area` is expressed relative to the top-left of the screen.
+    ///
+    /// This is how windows submit (partial) updates of their contents, see `Window::render()`.
+    pub fn damage(&mut self, area: Rectangle) {
+        self.damage.add(area);
+        self.request_frame();
     }
 
-    /// Refresh the region of `bounding_box` in the top framebuffer
-    pub fn refresh_top<B: CompositableRegion + Clone>(
-        &mut self, 
-        bounding_box: impl IntoIterator<Item = B> + Clone
-    ) -> Result<(), &'static str> {
-        let top_buffer = FramebufferUpdates {
-            src_framebuffer: &self.top_fb,
-            coordinate_in_dest_framebuffer: Coord::new(0, 0),
-        }; 
+    /// Marks the whole screen as damaged, such that it is redrawn in the next frame.
+    pub fn damage_all(&mut self) {
+        self.damage.add_all();
+        self.request_frame();
+    }
 
-        FRAME_COMPOSITOR.lock().composite(Some(top_buffer), &mut self.final_fb, bounding_box)
+    /// Wakes up the compositor task, unless it was already asked to composite a frame.
+    fn request_frame(&self) {
+        // A full channel means that a frame is already pending, which will include the latest damage.
+        let _ = self.frame_requests.try_send(());
     }
 
-    /// Refresh the part in `bounding_box` of every window. `bounding_box` is a region relative to the top-left of the screen. Refresh the whole screen if the bounding box is None.
-    pub fn refresh_windows<B: CompositableRegion + Clone>(
-        &mut self, 
-        bounding_box: impl IntoIterator<Item = B> + Clone,
-    ) -> Result<(), &'static str> {
-        // reference of windows
+    /// Composites the damaged regions of the screen and shows them.
+    ///
+    /// All layers, i.e., the bottom framebuffer, the hidden windows, the shown windows, the active window, and the top framebuffer,
+    /// are composited in a single pass into the back buffer, from which only the finished regions are copied to the screen.
+    /// This is normally invoked by the compositor task once per frame.
+    pub fn compose_frame(&mut self) -> Result<(), &'static str> {
+        if self.damage.is_empty() {
+            return Ok(());
+        }
+        let damaged = self.damage.take();
+
+        // list of windows from bottom to top
         let mut window_ref_list = Vec::new();
-        for window in &self.hide_list {
+        for window in self.hide_list.iter().chain(self.show_list.iter()) {
             if let Some(window_ref) = window.upgrade() {
                 window_ref_list.push(window_ref);
             }
         }
-        for window in &self.show_list {
-            if let Some(window_ref) = window.upgrade() {
-                window_ref_list.push(window_ref);
-            }
+        if let Some(window_ref) = self.active.upgrade() {
+            window_ref_list.push(window_ref);
         }
 
-        if let Some(window_ref) = self.active.upgrade() {
-            window_ref_list.push(window_ref)
+        {
+            // lock windows
+            let locked_window_list = window_ref_list.iter().map(|x| x.lock()).collect::<Vec<_>>();
+
+            // create updated framebuffer info objects, skipping windows that don't overlap any damaged region
+            let window_bufferlist = locked_window_list.iter()
+                .filter(|window| {
+                    let bounds = window.bounds();
+                    damaged.iter().any(|area| area.intersection(&bounds).is_some())
+                })
+                .map(|window| FramebufferUpdates {
+                    src_framebuffer: window.framebuffer(),
+                    coordinate_in_dest_framebuffer: window.get_position(),
+                    opacity: window.opacity(),
+                });
+
+            let buffer_iter = Some(FramebufferUpdates::new(&self.bottom_fb, Coord::new(0, 0))).into_iter()
+                .chain(window_bufferlist)
+                .chain(Some(FramebufferUpdates::new(&self.top_fb, Coord::new(0, 0))));
+            FRAME_COMPOSITOR.lock().composite(buffer_iter, &mut self.back_fb, damaged.iter().cloned())?;
         }
 
-        // lock windows
-        let locked_window_list = &window_ref_list.iter().map(|x| x.lock()).collect::<Vec<_>>();
-        // create updated framebuffer info objects
-        let bufferlist = locked_window_list.iter().map(|window| {
-            FramebufferUpdates {
-                src_framebuffer: window.framebuffer(),
-                coordinate_in_dest_framebuffer: window.get_position(),
+        // Copy the finished regions to the screen without blending them again.
+        let (width, _height) = self.back_fb.get_size();
+        for area in damaged.iter() {
+            let row_width = area.width();
+            for y in area.top_left.y .. area.bottom_right.y {
+                let start = match self.back_fb.index_of(Coord::new(area.top_left.x, y)) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                let end = core::cmp::min(start + row_width, (y as usize + 1) * width);
+                self.final_fb.overwrite_buffer(&self.back_fb.buffer()[start..end], start);
             }
-        }).collect::<Vec<_>>();
-
-        FRAME_COMPOSITOR.lock().composite(bufferlist.into_iter(), &mut self.final_fb, bounding_box)
-    }
-
-
-    /// Refresh the part in `bounding_box` of the active window. `bounding_box` is a region relative to the top-left of the screen. Refresh the whole screen if the bounding box is None.
-    pub fn refresh_active_window(&mut self, bounding_box: Option<Rectangle>) -> Result<(), &'static str> {
-        if let Some(window_ref) = self.active.upgrade() {
-            let window = window_ref.lock();
-            let buffer_update = FramebufferUpdates {
-                src_framebuffer: window.framebuffer(),
-                coordinate_in_dest_framebuffer: window.get_position(),
-            };
-            FRAME_COMPOSITOR.lock().composite(Some(buffer_update), &mut self.final_fb, bounding_box)
-        } else {
-            Ok(())
-        } 
+        }
+        Ok(())
     }
     
     /// Passes the given keyboard event to the currently active window.
@@ -405,18 +383,13 @@ impl WindowManager {
         new_border: Rectangle,
     ) -> Result<(), &'static str> {
         // first clear old border if exists
-        match self.repositioned_border {
-            Some(border) => {
-                let pixels = self.draw_floating_border(&border, color::TRANSPARENT);
-                self.refresh_bottom_windows(pixels.into_iter(), true)?;
-            },
-            None =>{}
+        if let Some(border) = self.repositioned_border {
+            self.draw_floating_border(&border, color::TRANSPARENT);
         }
 
         // then draw current border
         if show {
-            let pixels = self.draw_floating_border(&new_border, WINDOW_BORDER_COLOR_INNER);
-            self.refresh_top(pixels.into_iter())?;
+            self.draw_floating_border(&new_border, WINDOW_BORDER_COLOR_INNER);
             self.repositioned_border = Some(new_border);
         } else {
             self.repositioned_border = None;
@@ -425,11 +398,10 @@ impl WindowManager {
         Ok(())
     }
 
-    /// draw the floating border with `pixel`. Return the list of coordinates of pixels that were updated.
+    /// draw the floating border with `pixel`, and mark the area it covers as damaged.
     /// `border` indicates the position of the border as a rectangle.
     /// `color` is the color of the floating border.
-    fn draw_floating_border(&mut self, border: &Rectangle, color: Color) -> Vec<Coord> {
-        let mut coordinates = Vec::new();
+    fn draw_floating_border(&mut self, border: &Rectangle, color: Color) {
         let pixel = color.into();
         for i in 0..(WINDOW_BORDER_SIZE) as isize {
             let width = (border.bottom_right.x - border.top_left.x) - 2 * i;
@@ -445,19 +417,20 @@ impl WindowManager {
                 height as usize, 
                 pixel
             );
-
-            for m in 0..width {
-                coordinates.push(coordinate + (m, 0));
-                coordinates.push(coordinate + (m, height));
-            }            
-            
-            for m in 1..height - 1 {
-                coordinates.push(coordinate + (0, m));
-                coordinates.push(coordinate + (width, m));
-            }            
         }
 
-        coordinates
+        // The border consists of four thin strips, which are damaged separately to avoid redrawing the whole area inside it.
+        let size = WINDOW_BORDER_SIZE as isize;
+        let (top_left, bottom_right) = (border.top_left, border.bottom_right);
+        let strips = [
+            Rectangle { top_left, bottom_right: Coord::new(bottom_right.x, top_left.y + size) },
+            Rectangle { top_left: Coord::new(top_left.x, bottom_right.y - size), bottom_right },
+            Rectangle { top_left, bottom_right: Coord::new(top_left.x + size, bottom_right.y) },
+            Rectangle { top_left: Coord::new(bottom_right.x - size, top_left.y), bottom_right },
+        ];
+        for strip in strips.iter() {
+            self.damage(*strip);
+        }
     }
 
     /// take active window's base position and current mouse, move the window with delta
@@ -469,7 +442,7 @@ impl WindowManager {
             };
             self.refresh_floating_border(false, border)?;
 
-            let (old_top_left, old_bottom_right, new_top_left, new_bottom_right) = {
+            let (old_bounds, new_bounds) = {
                 let mut current_active_win = current_active.lock();
                 let (current_x, current_y) = {
                     let m = &self.mouse;
@@ -477,23 +450,18 @@ impl WindowManager {
                 };
                 match current_active_win.moving {
                     WindowMovingStatus::Moving(base) => {
-                        let old_top_left = current_active_win.get_position();
-                        let new_top_left = old_top_left + ((current_x - base.x), (current_y - base.y));
-                        let (width, height) = current_active_win.get_size();
-                        let old_bottom_right = old_top_left + (width as isize, height as isize);
-                        let new_bottom_right = new_top_left + (width as isize, height as isize);
+                        let old_bounds = current_active_win.bounds();
+                        let new_top_left = old_bounds.top_left + ((current_x - base.x), (current_y - base.y));
                         current_active_win.set_position(new_top_left);
-                        (old_top_left, old_bottom_right, new_top_left, new_bottom_right)        
+                        (old_bounds, current_active_win.bounds())
                     },
                     WindowMovingStatus::Stationary => {
                         return Err("The window is not moving");
                     }
                 }
             };
-            self.refresh_bottom_windows(Some(Rectangle{top_left: old_top_left, bottom_right: old_bottom_right}), false)?;
-
-            self.refresh_active_window(Some(Rectangle{top_left: new_top_left, bottom_right: new_bottom_right}))?;
-            self.refresh_mouse()?;
+            self.damage(old_bounds);
+            self.damage(new_bounds);
         } else {
             return Err("cannot find active window to move");
         }
@@ -502,12 +470,12 @@ impl WindowManager {
 
     /// Refresh the mouse display
     pub fn refresh_mouse(&mut self) -> Result<(), &'static str> {
-        let bounding_box = Some(Rectangle {
+        let bounding_box = Rectangle {
             top_left: self.mouse,
             bottom_right: self.mouse + (MOUSE_POINTER_SIZE as isize, MOUSE_POINTER_SIZE as isize)
-        });
-        
-        self.refresh_top(bounding_box.into_iter())
+        };
+        self.damage(bounding_box);
+        Ok(())
     }
 
     /// Move mouse. `relative` indicates the new position relative to current position.
@@ -541,11 +509,7 @@ impl WindowManager {
                 self.top_fb.overwrite_pixel(coordinate, color::TRANSPARENT.into());
             }
         }
-        let bounding_box = Some(Rectangle {
-            top_left: self.mouse,
-            bottom_right: self.mouse + (MOUSE_POINTER_SIZE as isize, MOUSE_POINTER_SIZE as isize)
-        });
-        self.refresh_bottom_windows(bounding_box.into_iter(), true)?;
+        self.refresh_mouse()?;
 
         // draw new mouse
         self.mouse = new;
@@ -612,15 +576,68 @@ impl WindowManager {
     pub fn get_screen_size(&self) -> (usize, usize) {
         self.final_fb.get_size()
     }
+
+    /// Attaches `window` to the virtual terminal with the given `number`, from 1 to `VIRTUAL_TERMINAL_COUNT`,
+    /// such that pressing Alt and the corresponding function key brings that window to the front.
+    ///
+    /// Returns an error if another window is already attached to that virtual terminal.
+    pub fn attach_virtual_terminal(&mut self, number: usize, window: &Arc<Mutex<WindowInner>>) -> Result<(), &'static str> {
+        let slot = self.virtual_terminals.get_mut(number.wrapping_sub(1))
+            .ok_or("window_manager: there is no virtual terminal with that number")?;
+        if let Some(existing) = slot.upgrade() {
+            if !Arc::ptr_eq(&existing, window) {
+                return Err("window_manager: another window is already attached to that virtual terminal");
+            }
+        }
+        *slot = Arc::downgrade(window);
+        Ok(())
+    }
+
+    /// Returns the number of the first virtual terminal that no window is attached to, if any.
+    pub fn free_virtual_terminal(&self) -> Option<usize> {
+        self.virtual_terminals.iter()
+            .position(|window| window.upgrade().is_none())
+            .map(|index| index + 1)
+    }
+
+    /// Returns the number of the virtual terminal whose window is currently active, if any.
+    pub fn current_virtual_terminal(&self) -> Option<usize> {
+        let active = self.active.upgrade()?;
+        self.virtual_terminals.iter()
+            .position(|window| window.upgrade().map(|w| Arc::ptr_eq(&w, &active)).unwrap_or(false))
+            .map(|index| index + 1)
+    }
+
+    /// Brings the window attached to the virtual terminal with the given `number` to the front and makes it active.
+    ///
+    /// Returns `false` if no window is attached to that virtual terminal.
+    pub fn switch_virtual_terminal(&mut self, number: usize) -> Result<bool, &'static str> {
+        let window = match self.virtual_terminals.get(number.wrapping_sub(1)) {
+            Some(window) => window.upgrade(),
+            None => return Err("window_manager: there is no virtual terminal with that number"),
+        };
+        match window {
+            Some(window) => {
+                self.set_active(&window, true)?;
+                self.refresh_mouse()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
 }
 
+/// The maximum number of keyboard and mouse events that can be pending before new events are dropped.
+const INPUT_CHANNEL_CAPACITY: usize = 200;
+
 /// Initialize the window manager. It returns (keyboard_producer, mouse_producer) for the I/O devices.
-pub fn init() -> Result<(Queue<Event>, Queue<Event>), &'static str> {
+pub fn init() -> Result<(Sender<Event>, Sender<Event>), &'static str> {
     let final_framebuffer: Framebuffer<AlphaPixel> = framebuffer::init()?;
     let (width, height) = final_framebuffer.get_size();
 
     let mut bottom_framebuffer = Framebuffer::new(width, height, None)?;
     let mut top_framebuffer = Framebuffer::new(width, height, None)?;
+    let back_framebuffer = Framebuffer::new(width, height, None)?;
     let (screen_width, screen_height) = bottom_framebuffer.get_size();
     bottom_framebuffer.fill(color::LIGHT_GRAY.into());
     top_framebuffer.fill(color::TRANSPARENT.into()); 
@@ -631,6 +648,13 @@ pub fn init() -> Result<(Queue<Event>, Queue<Event>), &'static str> {
         y: screen_height as isize / 2,
     }; 
 
+    // A single pending request suffices, as the next frame includes all damage accumulated until it is composited.
+    let (frame_requests, frame_request_consumer) = channel::bounded::<()>(1);
+    let screen = Rectangle {
+        top_left: Coord::new(0, 0),
+        bottom_right: Coord::new(screen_width as isize, screen_height as isize),
+    };
+
     // initialize static window manager
     let window_manager = WindowManager {
         hide_list: VecDeque::new(),
@@ -641,97 +665,122 @@ pub fn init() -> Result<(Queue<Event>, Queue<Event>), &'static str> {
         bottom_fb: bottom_framebuffer,
         top_fb: top_framebuffer,
         final_fb: final_framebuffer,
+        back_fb: back_framebuffer,
+        damage: Damage::with_bounds(screen),
+        frame_requests,
+        virtual_terminals: (0 .. VIRTUAL_TERMINAL_COUNT).map(|_| Weak::new()).collect(),
     };
-    let _wm = WINDOW_MANAGER.call_once(|| Mutex::new(window_manager));
-
-    // wm.refresh_bottom_windows(None, false)?;
-
-    // keyinput queue initialization
-    let key_consumer: Queue<Event> = Queue::with_capacity(100);
-    let key_producer = key_consumer.clone();
-
-    // mouse input queue initialization
-    let mouse_consumer: Queue<Event> = Queue::with_capacity(100);
-    let mouse_producer = mouse_consumer.clone();
-
-    spawn::new_task_builder(window_manager_loop, (key_consumer, mouse_consumer))
+    let wm = WINDOW_MANAGER.call_once(|| Mutex::new(window_manager));
+    wm.lock().damage_all();
+
+    // The compositor task draws every frame, so it is restarted if it ever panics, just like the window manager loop.
+    spawn::new_task_builder(compositor_loop, frame_request_consumer)
+        .name("window_manager_compositor".to_string())
+        .restart_policy(RestartPolicy::OnFailure)
+        .restart_backoff(Duration::from_millis(10), Duration::from_secs(1))
+        .spawn_restartable()?;
+
+    // keyboard and mouse input channel initialization, 
+    // in which both devices share one channel such that the window manager loop can block on it.
+    let (input_producer, input_consumer) = channel::bounded::<Event>(INPUT_CHANNEL_CAPACITY);
+    let key_producer = input_producer.clone();
+    let mouse_producer = input_producer;
+
+    // The window manager loop is critical, so it is restarted if it ever panics.
+    spawn::new_task_builder(window_manager_loop, input_consumer)
         .name("window_manager_loop".to_string())
-        .spawn()?;
+        .restart_policy(RestartPolicy::OnFailure)
+        .restart_backoff(Duration::from_millis(10), Duration::from_secs(1))
+        .spawn_restartable()?;
 
     Ok((key_producer, mouse_producer))
 }
 
+/// Composites a new frame whenever an area of the screen was damaged, but at most once per `FRAME_INTERVAL`.
+fn compositor_loop(frame_requests: Receiver<()>) -> Result<(), &'static str> {
+    loop {
+        frame_requests.recv()
+            .map_err(|_e| "compositor_loop(): the frame request channel was disconnected")?;
+        let frame_start = timer::now();
+        WINDOW_MANAGER
+            .try()
+            .ok_or("The static window manager was not yet initialized")?
+            .lock()
+            .compose_frame()?;
+        // Damage that arrives in the meantime is coalesced into the next frame.
+        timer::sleep_until(frame_start + FRAME_INTERVAL)?;
+    }
+}
+
 /// handles all keyboard and mouse movement in this window manager
-fn window_manager_loop(
-    (key_consumer, mouse_consumer): (Queue<Event>, Queue<Event>),
-) -> Result<(), &'static str> {
+fn window_manager_loop(input_consumer: Receiver<Event>) -> Result<(), &'static str> {
+    // An event that was received while combining mouse events, which must be handled next.
+    let mut pending_event: Option<Event> = None;
     loop {
-        let event_opt = key_consumer.pop()
-            .or_else(||mouse_consumer.pop())
-            .or_else(||{
-                scheduler::schedule();
-                None
-            });
-
-        if let Some(event) = event_opt {
-            // Currently, the window manager only cares about keyboard or mouse events
-            match event {
-                Event::KeyboardEvent(ref input_event) => {
-                    let key_input = input_event.key_event;
-                    keyboard_handle_application(key_input)?;
-                }
-                Event::MouseMovementEvent(ref mouse_event) => {
-                    // mouse::mouse_to_print(&mouse_event);
-                    let mouse_displacement = &mouse_event.displacement;
-                    let mut x = (mouse_displacement.x as i8) as isize;
-                    let mut y = (mouse_displacement.y as i8) as isize;
-                    // need to combine mouse events if there pending a lot
-                    loop {
-                        let next_event = match mouse_consumer.pop() {
-                            Some(ev) => ev,
-                            _ => {
-                                break;
-                            }
-                        };
-                        match next_event {
-                            Event::MouseMovementEvent(ref next_mouse_event) => {
-                                if next_mouse_event.mousemove.scrolling_up
-                                    == mouse_event.mousemove.scrolling_up
-                                    && next_mouse_event.mousemove.scrolling_down
-                                        == mouse_event.mousemove.scrolling_down
-                                    && next_mouse_event.buttonact.left_button_hold
-                                        == mouse_event.buttonact.left_button_hold
-                                    && next_mouse_event.buttonact.right_button_hold
-                                        == mouse_event.buttonact.right_button_hold
-                                    && next_mouse_event.buttonact.fourth_button_hold
-                                        == mouse_event.buttonact.fourth_button_hold
-                                    && next_mouse_event.buttonact.fifth_button_hold
-                                        == mouse_event.buttonact.fifth_button_hold
-                                {
-                                    x += (next_mouse_event.displacement.x as i8) as isize;
-                                    y += (next_mouse_event.displacement.y as i8) as isize;
-                                }
-                            }
-                            _ => {
-                                break;
+        let event = match pending_event.take().or_else(|| input_consumer.recv().ok()) {
+            Some(ev) => ev,
+            None => return Err("window_manager_loop(): the keyboard and mouse input channel was disconnected"),
+        };
+
+        // Currently, the window manager only cares about keyboard or mouse events
+        match event {
+            Event::KeyboardEvent(ref input_event) => {
+                let key_input = input_event.key_event;
+                keyboard_handle_application(key_input)?;
+            }
+            Event::MouseMovementEvent(ref mouse_event) => {
+                // mouse::mouse_to_print(&mouse_event);
+                let mouse_displacement = &mouse_event.displacement;
+                let mut x = (mouse_displacement.x as i8) as isize;
+                let mut y = (mouse_displacement.y as i8) as isize;
+                // need to combine mouse events if there pending a lot
+                loop {
+                    let next_event = match input_consumer.try_recv() {
+                        Ok(ev) => ev,
+                        _ => {
+                            break;
+                        }
+                    };
+                    match next_event {
+                        Event::MouseMovementEvent(ref next_mouse_event) => {
+                            if next_mouse_event.mousemove.scrolling_up
+                                == mouse_event.mousemove.scrolling_up
+                                && next_mouse_event.mousemove.scrolling_down
+                                    == mouse_event.mousemove.scrolling_down
+                                && next_mouse_event.buttonact.left_button_hold
+                                    == mouse_event.buttonact.left_button_hold
+                                && next_mouse_event.buttonact.right_button_hold
+                                    == mouse_event.buttonact.right_button_hold
+                                && next_mouse_event.buttonact.fourth_button_hold
+                                    == mouse_event.buttonact.fourth_button_hold
+                                && next_mouse_event.buttonact.fifth_button_hold
+                                    == mouse_event.buttonact.fifth_button_hold
+                            {
+                                x += (next_mouse_event.displacement.x as i8) as isize;
+                                y += (next_mouse_event.displacement.y as i8) as isize;
                             }
                         }
-                        // next_event.mark_completed();
-                    }
-                    if x != 0 || y != 0 {
-                        let mut wm = WINDOW_MANAGER
-                            .try()
-                            .ok_or("The static window manager was not yet initialized")?
-                            .lock();
-                        wm.move_mouse(
-                            Coord::new(x as isize, -(y as isize))
-                        )?;
+                        // a keyboard event shares the same channel, so it must be handled next rather than dropped
+                        _ => {
+                            pending_event = Some(next_event);
+                            break;
+                        }
                     }
-                    cursor_handle_application(*mouse_event)?; // tell the event to application, or moving window
+                    // next_event.mark_completed();
                 }
-                _other => {
-                    trace!("WINDOW_MANAGER: ignoring unexpected event: {:?}", _other);
+                if x != 0 || y != 0 {
+                    let mut wm = WINDOW_MANAGER
+                        .try()
+                        .ok_or("The static window manager was not yet initialized")?
+                        .lock();
+                    wm.move_mouse(
+                        Coord::new(x as isize, -(y as isize))
+                    )?;
                 }
+                cursor_handle_application(*mouse_event)?; // tell the event to application, or moving window
+            }
+            _other => {
+                trace!("WINDOW_MANAGER: ignoring unexpected event: {:?}", _other);
             }
         }
     }
@@ -771,35 +820,36 @@ fn keyboard_handle_application(key_input: KeyEvent) -> Result<(), &'static str>
             let mut wm = win_mgr.lock();
             if let Some(active_window) = wm.active.upgrade() {
                 debug!("window_manager: resizing active window to {:?}", new_position);
-                active_window.lock().resize(position)?;
 
-                // force refresh the entire screen for now
-                // TODO: perform a proper screen refresh here: only refresh the area that contained the active_window's old bounds.
-                wm.refresh_bottom_windows(Option::<Rectangle>::None, true)?;
+                // The window may have shrunk, so redraw its old area too.
+                let old_bounds = active_window.lock().bounds();
+                active_window.lock().resize(position)?;
+                wm.damage(old_bounds);
+                wm.damage(position);
             }
         }
 
         return Ok(());
     }
 
+    // Switch to a virtual terminal via Alt+F1..F4, starting a new shell in it if it doesn't have one yet
+    if key_input.modifiers.is_alt() && key_input.action == KeyAction::Pressed {
+        if let Some(number) = virtual_terminal_for_key(key_input.keycode) {
+            let switched = win_mgr.lock().switch_virtual_terminal(number)?;
+            if !switched {
+                spawn_shell(Some(number))?;
+            }
+            return Ok(());
+        }
+    }
+
     // Spawn a new terminal via Ctrl+Alt+T
     if key_input.modifiers.is_control()
         && key_input.modifiers.is_alt()
         && key_input.keycode == Keycode::T
         && key_input.action == KeyAction::Pressed
     {
-        // Because this task (the window manager loop) runs in a kernel-only namespace,
-        // we have to create a new application namespace in order to be able to actually spawn a shell.
-
-        let new_app_namespace = mod_mgmt::create_application_namespace(None)?;
-        let shell_objfile = new_app_namespace.dir().get_file_starting_with("shell-")
-            .ok_or("Couldn't find shell application file to run upon Ctrl+Alt+T")?;
-        let path = Path::new(shell_objfile.lock().get_absolute_path());
-        spawn::new_application_task_builder(path, Some(new_app_namespace))?
-            .name(format!("shell"))
-            .spawn()?;
-
-        debug!("window_manager: spawned new shell app in new app namespace.");
+        spawn_shell(None)?;
         return Ok(());
     }
 
@@ -813,6 +863,39 @@ fn keyboard_handle_application(key_input: KeyEvent) -> Result<(), &'static str>
     Ok(())
 }
 
+/// Returns the number of the virtual terminal that Alt and the given function key switch to.
+fn virtual_terminal_for_key(keycode: Keycode) -> Option<usize> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Spawns a new shell, which attaches its terminal to the given virtual terminal,
+/// or to the first free one if `virtual_terminal` is `None`.
+fn spawn_shell(virtual_terminal: Option<usize>) -> Result<(), &'static str> {
+    // Because this task (the window manager loop) runs in a kernel-only namespace,
+    // we have to create a new application namespace in order to be able to actually spawn a shell.
+    let new_app_namespace = mod_mgmt::create_application_namespace(None)?;
+    let shell_objfile = new_app_namespace.dir().get_file_starting_with("shell-")
+        .ok_or("Couldn't find shell application file to run in a new terminal")?;
+    let path = Path::new(shell_objfile.lock().get_absolute_path());
+    let args = match virtual_terminal {
+        Some(number) => vec!["--vt".to_string(), number.to_string()],
+        None => Vec::new(),
+    };
+    spawn::new_application_task_builder(path, Some(new_app_namespace))?
+        .argument(args)
+        .name(format!("shell"))
+        .spawn()?;
+
+    debug!("window_manager: spawned new shell app in new app namespace (virtual terminal {:?}).", virtual_terminal);
+    Ok(())
+}
+
 /// handle mouse event, push it to related window or anyone asked for it
 fn cursor_handle_application(mouse_event: MouseEvent) -> Result<(), &'static str> {
     let wm = WINDOW_MANAGER.try().ok_or("The static window manager was not yet initialized")?.lock();
@@ -0,0 +1,786 @@
+//! Support for xHCI (eXtensible Host Controller Interface) USB host controllers,
+//! which handle USB devices of all speeds, from USB 1.x keyboards to USB 3.x disks.
+//!
+//! The controller is driven through three kinds of rings in memory:
+//! a command ring for controller-wide commands (e.g., assigning a slot to a new device),
+//! one transfer ring per device endpoint, and an event ring on which the controller reports completions.
+//!
+//! During initialization, this driver resets the controller, enumerates the devices attached to its root hub ports,
+//! and binds class drivers to their interfaces (currently, the `usb_hid` boot keyboard and mouse drivers).
+//! Events are polled while devices are being enumerated; afterwards, the controller's interrupt
+//! delivers the data read from interrupt IN endpoints to the bound class drivers.
+//!
+//! Devices behind external hubs and devices attached after boot are not yet supported.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate volatile;
+extern crate zerocopy;
+extern crate x86_64;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate pic;
+extern crate interrupts;
+extern crate pause;
+extern crate usb;
+extern crate usb_hid;
+
+mod regs;
+mod rings;
+
+use core::ops::DerefMut;
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use irq_safety::MutexIrqSafe;
+use volatile::Volatile;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{
+    EntryFlags, FrameRange, MappedPages, PhysicalAddress,
+    allocate_pages_by_bytes, create_contiguous_mapping, get_frame_allocator_ref, get_kernel_mmi_ref,
+};
+use pci::{PciDevice, PciLocation, MsiInterrupt, PCI_INTERRUPT_LINE};
+use interrupts::{eoi, register_interrupt};
+use pause::spin_loop_hint;
+use usb::{EndpointDescriptor, SetupPacket, UsbClassDriver, UsbDeviceId, UsbHostController, UsbSpeed};
+use regs::*;
+use rings::*;
+
+
+/// The PCI class code of serial bus controllers.
+pub const XHCI_PCI_CLASS: u8 = 0x0C;
+/// The PCI subclass code of USB controllers.
+pub const XHCI_PCI_SUBCLASS: u8 = 0x03;
+/// The PCI programming interface of xHCI controllers.
+pub const XHCI_PCI_PROG_IF: u8 = 0x30;
+
+/// How many times to poll for an event or a controller state change before giving up.
+const POLL_ITERATIONS: usize = 100_000_000;
+
+// Endpoint types in an endpoint context
+const ENDPOINT_TYPE_CONTROL:        u32 = 4;
+const ENDPOINT_TYPE_INTERRUPT_IN:   u32 = 7;
+/// The number of times the controller retries a failed transaction before reporting an error.
+const ENDPOINT_ERROR_COUNT:         u32 = 3;
+/// The device context index of the default control endpoint.
+const CONTROL_ENDPOINT_DCI:         u8 = 1;
+
+/// The mapping flags used for the controller's registers and for DMA memory.
+pub(crate) const XHCI_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+
+/// All of the initialized xHCI controllers, which are accessed by the xHCI interrupt handler.
+static XHCI_CONTROLLERS: MutexIrqSafe<Vec<XhciController>> = MutexIrqSafe::new(Vec::new());
+
+
+/// Initializes the xHCI controller that is connected as the given `PciDevice`,
+/// enumerates the devices attached to it, and starts handling their input.
+pub fn init(pci_device: &PciDevice) -> Result<(), &'static str> {
+    let mut controller = XhciController::new(pci_device)?;
+    controller.enumerate_ports();
+
+    let mut controllers = XHCI_CONTROLLERS.lock();
+    controllers.push(controller);
+    if let Some(controller) = controllers.last_mut() {
+        controller.enable_interrupts(pci_device)?;
+    }
+    Ok(())
+}
+
+
+/// An interrupt IN endpoint of a device, from which data is continuously read
+/// and handed to the class driver bound to it.
+struct InterruptEndpoint {
+    /// The device context index of this endpoint.
+    dci: u8,
+    ring: ProducerRing,
+    buffer: MappedPages,
+    buffer_phys: PhysicalAddress,
+    transfer_length: u16,
+    driver: Box<dyn UsbClassDriver>,
+}
+
+impl InterruptEndpoint {
+    /// Queues a transfer that reads one packet into this endpoint's buffer.
+    /// The controller isn't told about it until the endpoint's doorbell is rung.
+    fn queue_transfer(&mut self) -> Result<(), &'static str> {
+        self.ring.push(Trb::new(
+            TRB_TYPE_NORMAL,
+            self.buffer_phys.value() as u64,
+            self.transfer_length as u32,
+            TRB_INTERRUPT_ON_COMPLETION | TRB_INTERRUPT_ON_SHORT_PACKET,
+        )).map(|_| ())
+    }
+}
+
+/// A device that has been assigned a slot on the controller.
+struct XhciDevice {
+    speed: UsbSpeed,
+    /// The device context, which the controller owns and updates.
+    output_context: MappedPages,
+    /// The input context, through which commands tell the controller how to change the device context.
+    input_context: MappedPages,
+    input_context_phys: PhysicalAddress,
+    control_ring: ProducerRing,
+    /// The buffer for the data stage of control transfers.
+    control_buffer: MappedPages,
+    control_buffer_phys: PhysicalAddress,
+    interrupt_endpoints: Vec<InterruptEndpoint>,
+}
+
+
+/// An xHCI host controller.
+pub struct XhciController {
+    location: PciLocation,
+    registers: MappedPages,
+    operational_offset: usize,
+    runtime_offset: usize,
+    doorbell_offset: usize,
+    max_ports: u8,
+    /// The size of each slot or endpoint context in bytes, either 32 or 64.
+    context_size: usize,
+    /// The Device Context Base Address Array, which points to the device context of each slot.
+    dcbaa: MappedPages,
+    /// The scratchpad buffer array and the buffers it points to, which are reserved for the controller's use.
+    _scratchpad: Vec<MappedPages>,
+    command_ring: ProducerRing,
+    event_ring: EventRing,
+    /// The devices that have been assigned a slot, indexed by slot ID.
+    devices: Vec<Option<XhciDevice>>,
+    /// The MSI-X or MSI vectors that the controller's interrupts arrive on.
+    msi: Vec<MsiInterrupt>,
+    /// The legacy interrupt line, if neither MSI-X nor MSI is supported.
+    legacy_interrupt: Option<u8>,
+}
+
+impl XhciController {
+    /// Takes ownership of the controller from the BIOS, resets it, and starts it.
+    fn new(pci_device: &PciDevice) -> Result<XhciController, &'static str> {
+        let mem_base = pci_device.determine_mem_base()?;
+        let mut registers = map_registers(pci_device, mem_base)?;
+        pci_device.pci_set_command_bus_master_bit();
+
+        let (operational_offset, runtime_offset, doorbell_offset, max_slots, max_ports, num_scratchpads, context_size, ext_caps_offset) = {
+            let caps = registers.as_type::<CapabilityRegisters>(0)?;
+            let hcsparams1 = caps.hcsparams1.read();
+            let hcsparams2 = caps.hcsparams2.read();
+            let hccparams1 = caps.hccparams1.read();
+            let version = caps.hciversion.read();
+            debug!("xHCI controller version {:x}.{:02x}", version >> 8, version & 0xFF);
+            (
+                caps.caplength.read() as usize,
+                (caps.rtsoff.read() & !0x1F) as usize,
+                (caps.dboff.read() & !0x3) as usize,
+                (hcsparams1 & 0xFF) as u8,
+                (hcsparams1 >> 24) as u8,
+                (((hcsparams2 >> 21) & 0x1F) << 5 | (hcsparams2 >> 27)) as usize,
+                if hccparams1 & (1 << 2) != 0 { 64 } else { 32 },
+                ((hccparams1 >> 16) as usize) * 4,
+            )
+        };
+
+        if ext_caps_offset != 0 {
+            take_ownership_from_bios(&mut registers, ext_caps_offset)?;
+        }
+
+        let (mut dcbaa, dcbaa_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        for entry in dcbaa.as_slice_mut::<u64>(0, PAGE_SIZE / 8)? { *entry = 0; }
+
+        let mut scratchpad = Vec::new();
+        if num_scratchpads > 0 {
+            let (mut array, array_phys) = create_contiguous_mapping(num_scratchpads * 8, XHCI_MAPPING_FLAGS)?;
+            for i in 0..num_scratchpads {
+                let (mut buffer, buffer_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+                for b in buffer.as_slice_mut::<u8>(0, PAGE_SIZE)? { *b = 0; }
+                *array.as_type_mut::<u64>(i * 8)? = buffer_phys.value() as u64;
+                scratchpad.push(buffer);
+            }
+            *dcbaa.as_type_mut::<u64>(0)? = array_phys.value() as u64;
+            scratchpad.push(array);
+        }
+
+        let mut devices = Vec::with_capacity(max_slots as usize + 1);
+        devices.resize_with(max_slots as usize + 1, || None);
+
+        let mut controller = XhciController {
+            location: pci_device.location,
+            registers,
+            operational_offset,
+            runtime_offset,
+            doorbell_offset,
+            max_ports,
+            context_size,
+            dcbaa,
+            _scratchpad: scratchpad,
+            command_ring: ProducerRing::new()?,
+            event_ring: EventRing::new()?,
+            devices,
+            msi: Vec::new(),
+            legacy_interrupt: None,
+        };
+        controller.reset()?;
+
+        let command_ring_phys = controller.command_ring.start_address().value() as u64;
+        let event_ring_dequeue = controller.event_ring.dequeue_address().value() as u64;
+        let segment_table_phys = controller.event_ring.segment_table_address().value() as u64;
+        {
+            let op = controller.operational_registers()?;
+            op.config.write(max_slots as u32);
+            write_64(&mut op.dcbaap_lo, &mut op.dcbaap_hi, dcbaa_phys.value() as u64);
+            write_64(&mut op.crcr_lo, &mut op.crcr_hi, command_ring_phys | CRCR_RING_CYCLE_STATE as u64);
+        }
+        {
+            // The segment table address must be written last, as that enables the event ring.
+            let interrupter = controller.interrupter_registers()?;
+            interrupter.erstsz.write(1);
+            write_64(&mut interrupter.erdp_lo, &mut interrupter.erdp_hi, event_ring_dequeue);
+            write_64(&mut interrupter.erstba_lo, &mut interrupter.erstba_hi, segment_table_phys);
+        }
+
+        let op = controller.operational_registers()?;
+        op.usbcmd.write(op.usbcmd.read() | USBCMD_RUN_STOP);
+        wait_for(|| op.usbsts.read() & USBSTS_HC_HALTED == 0, "timed out waiting for the xHCI controller to start")?;
+
+        info!("xHCI controller at {}: {} slots, {} ports, {}-byte contexts, {} scratchpad buffers",
+            controller.location, max_slots, max_ports, context_size, num_scratchpads
+        );
+        Ok(controller)
+    }
+
+    fn operational_registers(&mut self) -> Result<&mut OperationalRegisters, &'static str> {
+        self.registers.as_type_mut::<OperationalRegisters>(self.operational_offset)
+    }
+
+    /// Returns the registers of the given root hub port, which are numbered starting from 1.
+    fn port_registers(&mut self, port: u8) -> Result<&mut PortRegisters, &'static str> {
+        if port == 0 || port > self.max_ports {
+            return Err("xHCI port number out of bounds");
+        }
+        let offset = self.operational_offset + PORT_REGISTERS_OFFSET + (port as usize - 1) * core::mem::size_of::<PortRegisters>();
+        self.registers.as_type_mut::<PortRegisters>(offset)
+    }
+
+    /// Returns the registers of the primary interrupter, the only one used.
+    fn interrupter_registers(&mut self) -> Result<&mut InterrupterRegisters, &'static str> {
+        self.registers.as_type_mut::<InterrupterRegisters>(self.runtime_offset + INTERRUPTER_REGISTERS_OFFSET)
+    }
+
+    /// Stops and resets the controller.
+    fn reset(&mut self) -> Result<(), &'static str> {
+        let op = self.operational_registers()?;
+        wait_for(|| op.usbsts.read() & USBSTS_CONTROLLER_NOT_READY == 0, "timed out waiting for the xHCI controller to be ready")?;
+        op.usbcmd.write(op.usbcmd.read() & !USBCMD_RUN_STOP);
+        wait_for(|| op.usbsts.read() & USBSTS_HC_HALTED != 0, "timed out waiting for the xHCI controller to halt")?;
+        op.usbcmd.write(USBCMD_HC_RESET);
+        wait_for(|| op.usbcmd.read() & USBCMD_HC_RESET == 0, "timed out waiting for the xHCI controller to reset")?;
+        wait_for(|| op.usbsts.read() & USBSTS_CONTROLLER_NOT_READY == 0, "timed out waiting for the xHCI controller to be ready after reset")
+    }
+
+    /// Enables the controller's interrupt, preferring MSI-X, then MSI, then the legacy interrupt line.
+    fn enable_interrupts(&mut self, pci_device: &PciDevice) -> Result<(), &'static str> {
+        match pci_device.enable_msix(1).or_else(|_e| pci_device.enable_msi().map(|msi| vec![msi])) {
+            Ok(mut msi) => {
+                for vector in msi.iter_mut() {
+                    vector.set_handler(xhci_handler)?;
+                }
+                self.msi = msi;
+            }
+            Err(_e) => {
+                use pic::PIC_MASTER_OFFSET;
+                debug!("xHCI: couldn't enable MSI-X or MSI ({}), using the legacy interrupt line", _e);
+                let interrupt_num = pci_device.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
+                register_interrupt(interrupt_num, xhci_handler)?;
+                self.legacy_interrupt = Some(interrupt_num);
+            }
+        }
+
+        let interrupter = self.interrupter_registers()?;
+        interrupter.imod.write(0);
+        interrupter.iman.write(IMAN_INTERRUPT_PENDING | IMAN_INTERRUPT_ENABLE);
+        let op = self.operational_registers()?;
+        op.usbcmd.write(op.usbcmd.read() | USBCMD_INTERRUPTER_ENABLE);
+        Ok(())
+    }
+
+    /// Enumerates the devices attached to every root hub port, logging any that fail.
+    fn enumerate_ports(&mut self) {
+        for port in 1 ..= self.max_ports {
+            let connected = self.port_registers(port).map(|p| p.portsc.read() & PORTSC_CURRENT_CONNECT != 0).unwrap_or(false);
+            if connected {
+                if let Err(e) = self.enumerate_port(port) {
+                    error!("xHCI controller at {}: failed to enumerate the device on port {}: {}", self.location, port, e);
+                }
+            }
+        }
+    }
+
+    /// Enumerates the device attached to the given port and binds class drivers to its interfaces.
+    fn enumerate_port(&mut self, port: u8) -> Result<(), &'static str> {
+        let speed = self.reset_port(port)?;
+        let slot_id = self.enable_slot()?;
+        self.address_device(slot_id, port, speed)?;
+        let device = usb::enumerate(self, slot_id, speed)?;
+
+        let mut bound = false;
+        for interface in &device.configuration.interfaces {
+            match usb_hid::bind(self, &device, interface) {
+                Ok(Some((endpoint, driver))) => {
+                    self.configure_interrupt_endpoint(slot_id, &endpoint, driver)?;
+                    bound = true;
+                }
+                Ok(None) => { }
+                Err(e) => warn!("xHCI: couldn't bind a driver to interface {} of {}: {}", interface.number, device, e),
+            }
+        }
+        if !bound {
+            info!("xHCI: no driver for {} on port {}", device, port);
+        }
+        Ok(())
+    }
+
+    /// Resets the given port, if needed, such that it becomes enabled. Returns the speed of the attached device.
+    fn reset_port(&mut self, port: u8) -> Result<UsbSpeed, &'static str> {
+        let port_regs = self.port_registers(port)?;
+        let mut portsc = port_regs.portsc.read();
+        if portsc & PORTSC_POWER == 0 {
+            port_regs.portsc.write((portsc & PORTSC_PRESERVE_MASK) | PORTSC_POWER);
+            wait_for(|| port_regs.portsc.read() & PORTSC_POWER != 0, "timed out waiting for an xHCI port to power on")?;
+            portsc = port_regs.portsc.read();
+        }
+        // Clear any status changes that happened before now.
+        port_regs.portsc.write((portsc & PORTSC_PRESERVE_MASK) | (portsc & PORTSC_CHANGE_BITS));
+
+        // USB 3 ports are enabled automatically, whereas USB 2 ports must be reset first.
+        if portsc & PORTSC_ENABLED == 0 {
+            port_regs.portsc.write((portsc & PORTSC_PRESERVE_MASK) | PORTSC_RESET);
+            wait_for(|| port_regs.portsc.read() & PORTSC_RESET_CHANGE != 0, "timed out waiting for an xHCI port to reset")?;
+            let portsc = port_regs.portsc.read();
+            port_regs.portsc.write((portsc & PORTSC_PRESERVE_MASK) | PORTSC_RESET_CHANGE);
+            if portsc & PORTSC_ENABLED == 0 {
+                return Err("xHCI port wasn't enabled after being reset");
+            }
+        }
+
+        match (port_regs.portsc.read() & PORTSC_SPEED_MASK) >> PORTSC_SPEED_SHIFT {
+            1 => Ok(UsbSpeed::Full),
+            2 => Ok(UsbSpeed::Low),
+            3 => Ok(UsbSpeed::High),
+            4 | 5 => Ok(UsbSpeed::Super),
+            _ => Err("xHCI port reported an unknown speed"),
+        }
+    }
+
+    /// Rings the doorbell of the given slot for the given target, i.e., an endpoint's device context index.
+    /// Slot 0 is the controller's own doorbell, whose target 0 is the command ring.
+    fn ring_doorbell(registers: &mut MappedPages, doorbell_offset: usize, slot_id: u8, target: u8) -> Result<(), &'static str> {
+        let doorbell = registers.as_type_mut::<Volatile<u32>>(doorbell_offset + slot_id as usize * 4)?;
+        doorbell.write(target as u32);
+        Ok(())
+    }
+
+    /// Returns the next event on the event ring, if any, and tells the controller that it has been consumed.
+    fn next_event(&mut self) -> Option<Trb> {
+        let event = self.event_ring.pop()?;
+        let dequeue = self.event_ring.dequeue_address().value() as u64;
+        if let Ok(interrupter) = self.interrupter_registers() {
+            write_64(&mut interrupter.erdp_lo, &mut interrupter.erdp_hi, dequeue | ERDP_EVENT_HANDLER_BUSY as u64);
+        }
+        Some(event)
+    }
+
+    /// Polls the event ring until an event for which `is_match` returns `true` arrives, and returns it.
+    /// Other events that arrive in the meantime are handled normally.
+    fn wait_for_event(&mut self, is_match: &dyn Fn(&Trb) -> bool) -> Result<Trb, &'static str> {
+        for _ in 0..POLL_ITERATIONS {
+            while let Some(event) = self.next_event() {
+                if is_match(&event) {
+                    return Ok(event);
+                }
+                self.handle_event(event);
+            }
+            spin_loop_hint();
+        }
+        Err("timed out waiting for an xHCI event")
+    }
+
+    /// Submits the given command and waits for it to complete. Returns the command completion event.
+    fn execute_command(&mut self, command: Trb) -> Result<Trb, &'static str> {
+        let command_phys = self.command_ring.push(command)?.value() as u64;
+        Self::ring_doorbell(&mut self.registers, self.doorbell_offset, 0, 0)?;
+        let event = self.wait_for_event(&|e| e.trb_type() == TRB_TYPE_COMMAND_COMPLETION && e.parameter == command_phys)?;
+        if event.completion_code() != COMPLETION_SUCCESS {
+            error!("xHCI: command of type {} failed with completion code {}", command.trb_type(), event.completion_code());
+            return Err("xHCI command failed");
+        }
+        Ok(event)
+    }
+
+    /// Asks the controller for a free device slot, and returns its ID.
+    fn enable_slot(&mut self) -> Result<u8, &'static str> {
+        let event = self.execute_command(Trb::new(TRB_TYPE_ENABLE_SLOT, 0, 0, 0))?;
+        Ok(event.slot_id())
+    }
+
+    /// Sets up the device context of the given slot for a device with the given speed on the given port,
+    /// and assigns the device a USB address.
+    fn address_device(&mut self, slot_id: u8, port: u8, speed: UsbSpeed) -> Result<(), &'static str> {
+        let (mut output_context, output_context_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        let (mut input_context, input_context_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        let (control_buffer, control_buffer_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        let control_ring = ProducerRing::new()?;
+        for b in output_context.as_slice_mut::<u8>(0, PAGE_SIZE)? { *b = 0; }
+        for b in input_context.as_slice_mut::<u8>(0, PAGE_SIZE)? { *b = 0; }
+
+        let speed_id: u32 = match speed {
+            UsbSpeed::Full => 1,
+            UsbSpeed::Low => 2,
+            UsbSpeed::High => 3,
+            UsbSpeed::Super => 4,
+        };
+        let context_size = self.context_size;
+        // Add the slot context and the default control endpoint's context.
+        write_context(&mut input_context, context_size, 0, 1, 0b11)?;
+        // The slot context has one valid endpoint context, and the device's speed and root hub port.
+        write_context(&mut input_context, context_size, 1, 0, (1 << 27) | (speed_id << 20))?;
+        write_context(&mut input_context, context_size, 1, 1, (port as u32) << 16)?;
+        let ring_phys = control_ring.start_address().value() as u64;
+        let ep0 = 1 + CONTROL_ENDPOINT_DCI as usize;
+        write_context(&mut input_context, context_size, ep0, 1,
+            (ENDPOINT_ERROR_COUNT << 1) | (ENDPOINT_TYPE_CONTROL << 3) | ((speed.default_max_packet_size() as u32) << 16))?;
+        write_context(&mut input_context, context_size, ep0, 2, ring_phys as u32 | control_ring.cycle_state() as u32)?;
+        write_context(&mut input_context, context_size, ep0, 3, (ring_phys >> 32) as u32)?;
+        // The average TRB length of control transfers is 8 bytes.
+        write_context(&mut input_context, context_size, ep0, 4, 8)?;
+
+        *self.dcbaa.as_type_mut::<u64>(slot_id as usize * 8)? = output_context_phys.value() as u64;
+        let device = XhciDevice {
+            speed,
+            output_context,
+            input_context,
+            input_context_phys,
+            control_ring,
+            control_buffer,
+            control_buffer_phys,
+            interrupt_endpoints: Vec::new(),
+        };
+        *self.devices.get_mut(slot_id as usize).ok_or("xHCI controller returned an invalid slot ID")? = Some(device);
+
+        self.execute_command(Trb::new(
+            TRB_TYPE_ADDRESS_DEVICE,
+            input_context_phys.value() as u64,
+            0,
+            (slot_id as u32) << TRB_SLOT_ID_SHIFT,
+        ))?;
+        Ok(())
+    }
+
+    fn device_mut(devices: &mut Vec<Option<XhciDevice>>, slot_id: u8) -> Result<&mut XhciDevice, &'static str> {
+        devices.get_mut(slot_id as usize).and_then(|d| d.as_mut()).ok_or("no USB device in the given xHCI slot")
+    }
+
+    /// Enables the given interrupt IN endpoint of the device in the given slot,
+    /// and starts reading data from it on behalf of the given class driver.
+    fn configure_interrupt_endpoint(&mut self, slot_id: u8, endpoint: &EndpointDescriptor, driver: Box<dyn UsbClassDriver>) -> Result<(), &'static str> {
+        let dci = endpoint.number() * 2 + 1;
+        let max_packet_size = endpoint.max_packet_size();
+        let ring = ProducerRing::new()?;
+        let (buffer, buffer_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        let context_size = self.context_size;
+        let ring_phys = ring.start_address().value() as u64;
+
+        let input_context_phys = {
+            let device = Self::device_mut(&mut self.devices, slot_id)?;
+            // The interval is an exponent in units of 125 microseconds.
+            let interval = match device.speed {
+                // For low and full speed devices, `bInterval` is in units of 1 millisecond, i.e., 8 * 125 microseconds.
+                UsbSpeed::Low | UsbSpeed::Full => {
+                    let microframes = core::cmp::max(endpoint.interval as u32, 1) * 8;
+                    core::cmp::min(core::cmp::max(31 - microframes.leading_zeros(), 3), 10)
+                }
+                // For high and super speed devices, `bInterval` is already an exponent, offset by one.
+                UsbSpeed::High | UsbSpeed::Super => core::cmp::min(core::cmp::max(endpoint.interval as u32, 1), 16) - 1,
+            };
+
+            let input = &mut device.input_context;
+            for b in input.as_slice_mut::<u8>(0, PAGE_SIZE)? { *b = 0; }
+            // Add the slot context and the new endpoint's context.
+            write_context(input, context_size, 0, 1, 1 | (1 << dci))?;
+            // Copy the current slot context, updating the number of valid endpoint contexts.
+            for dword in 0 .. context_size / 4 {
+                let value = *device.output_context.as_type::<u32>(dword * 4)?;
+                write_context(input, context_size, 1, dword, value)?;
+            }
+            let slot_dword0 = *device.output_context.as_type::<u32>(0)?;
+            let context_entries = core::cmp::max(slot_dword0 >> 27, dci as u32);
+            write_context(input, context_size, 1, 0, (slot_dword0 & 0x07FF_FFFF) | (context_entries << 27))?;
+            // Clear the slot state and device address, which are output-only fields.
+            write_context(input, context_size, 1, 3, 0)?;
+
+            let ep = 1 + dci as usize;
+            write_context(input, context_size, ep, 0, interval << 16)?;
+            write_context(input, context_size, ep, 1,
+                (ENDPOINT_ERROR_COUNT << 1) | (ENDPOINT_TYPE_INTERRUPT_IN << 3) | ((max_packet_size as u32) << 16))?;
+            write_context(input, context_size, ep, 2, ring_phys as u32 | ring.cycle_state() as u32)?;
+            write_context(input, context_size, ep, 3, (ring_phys >> 32) as u32)?;
+            // The max ESIT payload and average TRB length are both one packet.
+            write_context(input, context_size, ep, 4, ((max_packet_size as u32) << 16) | max_packet_size as u32)?;
+            device.input_context_phys
+        };
+
+        self.execute_command(Trb::new(
+            TRB_TYPE_CONFIGURE_ENDPOINT,
+            input_context_phys.value() as u64,
+            0,
+            (slot_id as u32) << TRB_SLOT_ID_SHIFT,
+        ))?;
+
+        let mut interrupt_endpoint = InterruptEndpoint {
+            dci,
+            ring,
+            buffer,
+            buffer_phys,
+            transfer_length: max_packet_size,
+            driver,
+        };
+        interrupt_endpoint.queue_transfer()?;
+        Self::device_mut(&mut self.devices, slot_id)?.interrupt_endpoints.push(interrupt_endpoint);
+        Self::ring_doorbell(&mut self.registers, self.doorbell_offset, slot_id, dci)
+    }
+
+    /// Handles an event that nobody was waiting for, e.g., data from an interrupt endpoint.
+    fn handle_event(&mut self, event: Trb) {
+        match event.trb_type() {
+            TRB_TYPE_TRANSFER_EVENT => {
+                let slot_id = event.slot_id();
+                let dci = event.endpoint_id();
+                let endpoint = match Self::device_mut(&mut self.devices, slot_id).ok()
+                    .and_then(|d| d.interrupt_endpoints.iter_mut().find(|ep| ep.dci == dci))
+                {
+                    Some(ep) => ep,
+                    None => {
+                        warn!("xHCI: unexpected transfer event for slot {} endpoint {}: {:?}", slot_id, dci, event);
+                        return;
+                    }
+                };
+                match event.completion_code() {
+                    COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => {
+                        let length = (endpoint.transfer_length as usize).saturating_sub(event.transfer_residual() as usize);
+                        if let Ok(data) = endpoint.buffer.as_slice::<u8>(0, length) {
+                            endpoint.driver.handle_interrupt_in(data);
+                        }
+                    }
+                    code => warn!("xHCI: {} transfer on slot {} failed with completion code {}", endpoint.driver.name(), slot_id, code),
+                }
+                // Keep reading from the endpoint.
+                if let Err(e) = endpoint.queue_transfer() {
+                    error!("xHCI: couldn't queue another {} transfer: {}", endpoint.driver.name(), e);
+                    return;
+                }
+                if let Err(e) = Self::ring_doorbell(&mut self.registers, self.doorbell_offset, slot_id, dci) {
+                    error!("xHCI: couldn't ring doorbell: {}", e);
+                }
+            }
+            TRB_TYPE_PORT_STATUS_CHANGE => {
+                let port = (event.parameter >> 24) as u8;
+                if let Ok(port_regs) = self.port_registers(port) {
+                    let portsc = port_regs.portsc.read();
+                    port_regs.portsc.write((portsc & PORTSC_PRESERVE_MASK) | (portsc & PORTSC_CHANGE_BITS));
+                    if portsc & PORTSC_CONNECT_CHANGE != 0 {
+                        if portsc & PORTSC_CURRENT_CONNECT != 0 {
+                            warn!("xHCI: a USB device was attached to port {}, but hot-plugging is not yet supported", port);
+                        } else {
+                            info!("xHCI: a USB device was detached from port {}", port);
+                        }
+                    }
+                }
+            }
+            _other => debug!("xHCI: ignoring event of type {}: {:?}", _other, event),
+        }
+    }
+
+    /// Handles an interrupt from this controller by processing all pending events.
+    fn handle_interrupt(&mut self) -> Result<(), &'static str> {
+        let op = self.operational_registers()?;
+        let status = op.usbsts.read();
+        if status & USBSTS_HOST_SYSTEM_ERROR != 0 {
+            error!("xHCI controller at {} reported a host system error", self.location);
+        }
+        op.usbsts.write(USBSTS_EVENT_INTERRUPT);
+        let interrupter = self.interrupter_registers()?;
+        interrupter.iman.write(interrupter.iman.read() | IMAN_INTERRUPT_PENDING);
+
+        while let Some(event) = self.next_event() {
+            self.handle_event(event);
+        }
+        Ok(())
+    }
+}
+
+impl UsbHostController for XhciController {
+    fn control_transfer(&mut self, device: UsbDeviceId, setup: SetupPacket, data: &mut [u8]) -> Result<(), &'static str> {
+        if data.len() != setup.length as usize || data.len() > PAGE_SIZE {
+            return Err("xHCI: invalid control transfer length");
+        }
+        let is_in = setup.is_device_to_host();
+        {
+            let dev = Self::device_mut(&mut self.devices, device)?;
+            let transfer_type = match (data.len(), is_in) {
+                (0, _) => TRB_TRANSFER_TYPE_NO_DATA,
+                (_, true) => TRB_TRANSFER_TYPE_IN_DATA,
+                (_, false) => TRB_TRANSFER_TYPE_OUT_DATA,
+            };
+            dev.control_ring.push(Trb::new(
+                TRB_TYPE_SETUP_STAGE,
+                setup.to_u64(),
+                8,
+                TRB_IMMEDIATE_DATA | (transfer_type << TRB_TRANSFER_TYPE_SHIFT),
+            ))?;
+            if !data.is_empty() {
+                if !is_in {
+                    dev.control_buffer.as_slice_mut::<u8>(0, data.len())?.copy_from_slice(data);
+                }
+                dev.control_ring.push(Trb::new(
+                    TRB_TYPE_DATA_STAGE,
+                    dev.control_buffer_phys.value() as u64,
+                    data.len() as u32,
+                    if is_in { TRB_DIRECTION_IN } else { 0 },
+                ))?;
+            }
+            // The status stage goes in the opposite direction of the data stage.
+            let status_direction = if data.is_empty() || !is_in { TRB_DIRECTION_IN } else { 0 };
+            dev.control_ring.push(Trb::new(TRB_TYPE_STATUS_STAGE, 0, 0, status_direction | TRB_INTERRUPT_ON_COMPLETION))?;
+        }
+        Self::ring_doorbell(&mut self.registers, self.doorbell_offset, device, CONTROL_ENDPOINT_DCI)?;
+
+        let event = self.wait_for_event(&|e| {
+            e.trb_type() == TRB_TYPE_TRANSFER_EVENT && e.slot_id() == device && e.endpoint_id() == CONTROL_ENDPOINT_DCI
+        })?;
+        match event.completion_code() {
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => { }
+            code => {
+                error!("xHCI: control request {:#X} to slot {} failed with completion code {}", setup.request, device, code);
+                return Err("xHCI control transfer failed");
+            }
+        }
+        if is_in && !data.is_empty() {
+            let dev = Self::device_mut(&mut self.devices, device)?;
+            data.copy_from_slice(dev.control_buffer.as_slice::<u8>(0, data.len())?);
+        }
+        Ok(())
+    }
+
+    fn set_max_packet_size0(&mut self, device: UsbDeviceId, max_packet_size: u16) -> Result<(), &'static str> {
+        let context_size = self.context_size;
+        let input_context_phys = {
+            let dev = Self::device_mut(&mut self.devices, device)?;
+            let input = &mut dev.input_context;
+            for b in input.as_slice_mut::<u8>(0, PAGE_SIZE)? { *b = 0; }
+            // Only the default control endpoint's context is evaluated.
+            write_context(input, context_size, 0, 1, 1 << CONTROL_ENDPOINT_DCI)?;
+            let ep0 = 1 + CONTROL_ENDPOINT_DCI as usize;
+            write_context(input, context_size, ep0, 1,
+                (ENDPOINT_ERROR_COUNT << 1) | (ENDPOINT_TYPE_CONTROL << 3) | ((max_packet_size as u32) << 16))?;
+            dev.input_context_phys
+        };
+        self.execute_command(Trb::new(
+            TRB_TYPE_EVALUATE_CONTEXT,
+            input_context_phys.value() as u64,
+            0,
+            (device as u32) << TRB_SLOT_ID_SHIFT,
+        )).map(|_| ())
+    }
+}
+
+
+/// Writes the given dword of the context with the given index in an input context,
+/// in which index 0 is the input control context, 1 is the slot context,
+/// and `1 + dci` is the context of the endpoint with device context index `dci`.
+fn write_context(context: &mut MappedPages, context_size: usize, index: usize, dword: usize, value: u32) -> Result<(), &'static str> {
+    let field = context.as_type_mut::<Volatile<u32>>(index * context_size + dword * 4)?;
+    field.write(value);
+    Ok(())
+}
+
+/// Writes a 64-bit value to a register that is split into two 32-bit halves, low half first.
+fn write_64(lo: &mut Volatile<u32>, hi: &mut Volatile<u32>, value: u64) {
+    lo.write(value as u32);
+    hi.write((value >> 32) as u32);
+}
+
+/// Polls the given condition until it is true, or returns the given error if it never becomes true.
+fn wait_for<F: FnMut() -> bool>(mut condition: F, error: &'static str) -> Result<(), &'static str> {
+    for _ in 0..POLL_ITERATIONS {
+        if condition() {
+            return Ok(());
+        }
+        spin_loop_hint();
+    }
+    Err(error)
+}
+
+/// Asks the BIOS to hand over control of the controller and disables its System Management Interrupts,
+/// through which the BIOS emulates a PS/2 keyboard with a USB one.
+fn take_ownership_from_bios(registers: &mut MappedPages, ext_caps_offset: usize) -> Result<(), &'static str> {
+    // The SMI enable bits of the USB Legacy Support Control/Status register, and its SMI status bits.
+    const LEGACY_SMI_ENABLE_MASK: u32 = (1 << 0) | (1 << 4) | (0x7 << 13);
+    const LEGACY_SMI_EVENTS: u32 = 0x7 << 29;
+
+    let mut offset = ext_caps_offset;
+    loop {
+        let header = registers.as_type_mut::<Volatile<u32>>(offset)?.read();
+        if header & 0xFF == EXT_CAP_LEGACY_SUPPORT {
+            let legacy_support = registers.as_type_mut::<Volatile<u32>>(offset)?;
+            if header & LEGACY_BIOS_OWNED != 0 {
+                legacy_support.write(header | LEGACY_OS_OWNED);
+                if wait_for(|| legacy_support.read() & LEGACY_BIOS_OWNED == 0, "").is_err() {
+                    warn!("xHCI: the BIOS didn't release the controller, taking it anyway");
+                    legacy_support.write((legacy_support.read() & !LEGACY_BIOS_OWNED) | LEGACY_OS_OWNED);
+                }
+            }
+            let control_status = registers.as_type_mut::<Volatile<u32>>(offset + 4)?;
+            control_status.write((control_status.read() & !LEGACY_SMI_ENABLE_MASK) | LEGACY_SMI_EVENTS);
+            return Ok(());
+        }
+        let next = ((header >> 8) & 0xFF) as usize;
+        if next == 0 {
+            return Ok(());
+        }
+        offset += next * 4;
+    }
+}
+
+/// Maps the xHCI controller's registers (its BAR0 memory region).
+fn map_registers(pci_device: &PciDevice, mem_base: PhysicalAddress) -> Result<MappedPages, &'static str> {
+    let mem_size_in_bytes = pci_device.determine_mem_size() as usize;
+    let pages = allocate_pages_by_bytes(mem_size_in_bytes).ok_or("xHCI: couldn't allocate pages for the controller registers")?;
+    let frames = FrameRange::from_phys_addr(mem_base, mem_size_in_bytes);
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("xHCI: KERNEL_MMI was not yet initialized!")?;
+    let fa = get_frame_allocator_ref().ok_or("xHCI: couldn't get the frame allocator")?;
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+    kernel_mmi.page_table.map_allocated_pages_to(pages, frames, XHCI_MAPPING_FLAGS, fa.lock().deref_mut())
+}
+
+
+/// The handler for all xHCI interrupts, which processes the pending events of every controller.
+extern "x86-interrupt" fn xhci_handler(_stack_frame: &mut ExceptionStackFrame) {
+    let mut legacy_interrupt = None;
+    for controller in XHCI_CONTROLLERS.lock().iter_mut() {
+        if let Err(e) = controller.handle_interrupt() {
+            error!("xhci_handler(): error handling interrupt: {:?}", e);
+        }
+        legacy_interrupt = legacy_interrupt.or(controller.legacy_interrupt);
+    }
+    eoi(legacy_interrupt);
+}
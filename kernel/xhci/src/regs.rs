@@ -0,0 +1,125 @@
+//! The layout of an xHCI controller's memory-mapped registers and the bits within them.
+
+use volatile::Volatile;
+use zerocopy::FromBytes;
+
+
+/// The capability registers at the start of the controller's BAR0, which are read-only.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct CapabilityRegisters {
+    /// The length of the capability registers, i.e., the offset of the operational registers.
+    pub caplength:      Volatile<u8>,   // 0x00
+    _reserved:          u8,             // 0x01
+    pub hciversion:     Volatile<u16>,  // 0x02
+    pub hcsparams1:     Volatile<u32>,  // 0x04
+    pub hcsparams2:     Volatile<u32>,  // 0x08
+    pub hcsparams3:     Volatile<u32>,  // 0x0C
+    pub hccparams1:     Volatile<u32>,  // 0x10
+    /// The offset of the doorbell array.
+    pub dboff:          Volatile<u32>,  // 0x14
+    /// The offset of the runtime registers.
+    pub rtsoff:         Volatile<u32>,  // 0x18
+    pub hccparams2:     Volatile<u32>,  // 0x1C
+}
+
+/// The operational registers, which follow the capability registers.
+///
+/// The 64-bit registers are split into two halves, which are written low half first.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct OperationalRegisters {
+    pub usbcmd:         Volatile<u32>,  // 0x00
+    pub usbsts:         Volatile<u32>,  // 0x04
+    pub pagesize:       Volatile<u32>,  // 0x08
+    _reserved0:         [u32; 2],       // 0x0C
+    pub dnctrl:         Volatile<u32>,  // 0x14
+    /// Command Ring Control
+    pub crcr_lo:        Volatile<u32>,  // 0x18
+    pub crcr_hi:        Volatile<u32>,  // 0x1C
+    _reserved1:         [u32; 4],       // 0x20
+    /// Device Context Base Address Array Pointer
+    pub dcbaap_lo:      Volatile<u32>,  // 0x30
+    pub dcbaap_hi:      Volatile<u32>,  // 0x34
+    pub config:         Volatile<u32>,  // 0x38
+}
+
+/// The offset of the first port's registers from the start of the operational registers.
+pub const PORT_REGISTERS_OFFSET: usize = 0x400;
+
+/// The registers of one root hub port.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct PortRegisters {
+    /// Port Status and Control
+    pub portsc:         Volatile<u32>,
+    pub portpmsc:       Volatile<u32>,
+    pub portli:         Volatile<u32>,
+    pub porthlpmc:      Volatile<u32>,
+}
+
+/// The offset of the first interrupter's registers from the start of the runtime registers.
+pub const INTERRUPTER_REGISTERS_OFFSET: usize = 0x20;
+
+/// The registers of one interrupter, each of which has its own event ring.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct InterrupterRegisters {
+    /// Interrupter Management
+    pub iman:           Volatile<u32>,
+    /// Interrupter Moderation
+    pub imod:           Volatile<u32>,
+    /// Event Ring Segment Table Size
+    pub erstsz:         Volatile<u32>,
+    _reserved:          u32,
+    /// Event Ring Segment Table Base Address
+    pub erstba_lo:      Volatile<u32>,
+    pub erstba_hi:      Volatile<u32>,
+    /// Event Ring Dequeue Pointer
+    pub erdp_lo:        Volatile<u32>,
+    pub erdp_hi:        Volatile<u32>,
+}
+
+
+// USBCMD bits
+pub const USBCMD_RUN_STOP:              u32 = 1 << 0;
+pub const USBCMD_HC_RESET:              u32 = 1 << 1;
+pub const USBCMD_INTERRUPTER_ENABLE:    u32 = 1 << 2;
+
+// USBSTS bits
+pub const USBSTS_HC_HALTED:             u32 = 1 << 0;
+pub const USBSTS_HOST_SYSTEM_ERROR:     u32 = 1 << 2;
+pub const USBSTS_EVENT_INTERRUPT:       u32 = 1 << 3;
+pub const USBSTS_CONTROLLER_NOT_READY:  u32 = 1 << 11;
+
+// CRCR bits
+pub const CRCR_RING_CYCLE_STATE:        u32 = 1 << 0;
+
+// PORTSC bits
+pub const PORTSC_CURRENT_CONNECT:       u32 = 1 << 0;
+pub const PORTSC_ENABLED:               u32 = 1 << 1;
+pub const PORTSC_RESET:                 u32 = 1 << 4;
+pub const PORTSC_POWER:                 u32 = 1 << 9;
+pub const PORTSC_SPEED_SHIFT:           u32 = 10;
+pub const PORTSC_SPEED_MASK:            u32 = 0xF << PORTSC_SPEED_SHIFT;
+pub const PORTSC_CONNECT_CHANGE:        u32 = 1 << 17;
+pub const PORTSC_RESET_CHANGE:          u32 = 1 << 21;
+/// The status change bits, which are cleared by writing 1 to them.
+pub const PORTSC_CHANGE_BITS:           u32 = 0x7F << 17;
+/// The bits that must be preserved when writing PORTSC,
+/// which excludes the "port enabled" bit (writing 1 disables the port) and the status change bits.
+pub const PORTSC_PRESERVE_MASK:         u32 = !(PORTSC_ENABLED | PORTSC_CHANGE_BITS);
+
+// IMAN bits
+pub const IMAN_INTERRUPT_PENDING:       u32 = 1 << 0;
+pub const IMAN_INTERRUPT_ENABLE:        u32 = 1 << 1;
+
+// ERDP bits
+pub const ERDP_EVENT_HANDLER_BUSY:      u32 = 1 << 3;
+
+/// The ID of the USB Legacy Support extended capability, through which the BIOS hands over the controller.
+pub const EXT_CAP_LEGACY_SUPPORT:       u32 = 1;
+/// The "BIOS owned" semaphore in the USB Legacy Support capability.
+pub const LEGACY_BIOS_OWNED:            u32 = 1 << 16;
+/// The "OS owned" semaphore in the USB Legacy Support capability.
+pub const LEGACY_OS_OWNED:              u32 = 1 << 24;
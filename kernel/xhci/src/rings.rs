@@ -0,0 +1,232 @@
+//! Transfer Request Blocks (TRBs) and the rings through which they are exchanged with the controller.
+//!
+//! Software produces TRBs onto the command ring and onto one transfer ring per endpoint,
+//! and the controller produces TRBs onto the event ring.
+//! The owner of each TRB is indicated by its cycle bit, whose meaning flips every time a ring wraps around.
+
+use core::{
+    mem::size_of,
+    sync::atomic::{fence, Ordering},
+};
+use zerocopy::FromBytes;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use super::XHCI_MAPPING_FLAGS;
+
+
+/// The number of TRBs in each ring, which occupies exactly one page.
+pub const TRBS_PER_RING: usize = PAGE_SIZE / size_of::<Trb>();
+
+// TRB types
+pub const TRB_TYPE_NORMAL:                  u32 = 1;
+pub const TRB_TYPE_SETUP_STAGE:             u32 = 2;
+pub const TRB_TYPE_DATA_STAGE:              u32 = 3;
+pub const TRB_TYPE_STATUS_STAGE:            u32 = 4;
+pub const TRB_TYPE_LINK:                    u32 = 6;
+pub const TRB_TYPE_ENABLE_SLOT:             u32 = 9;
+pub const TRB_TYPE_ADDRESS_DEVICE:          u32 = 11;
+pub const TRB_TYPE_CONFIGURE_ENDPOINT:      u32 = 12;
+pub const TRB_TYPE_EVALUATE_CONTEXT:        u32 = 13;
+pub const TRB_TYPE_TRANSFER_EVENT:          u32 = 32;
+pub const TRB_TYPE_COMMAND_COMPLETION:      u32 = 33;
+pub const TRB_TYPE_PORT_STATUS_CHANGE:      u32 = 34;
+
+// Bits of a TRB's control field
+pub const TRB_CYCLE:                        u32 = 1 << 0;
+/// In a link TRB, toggles the cycle state when the controller follows it.
+pub const TRB_TOGGLE_CYCLE:                 u32 = 1 << 1;
+/// Interrupt on Short Packet
+pub const TRB_INTERRUPT_ON_SHORT_PACKET:    u32 = 1 << 2;
+/// Interrupt On Completion
+pub const TRB_INTERRUPT_ON_COMPLETION:      u32 = 1 << 5;
+/// Immediate Data, i.e., the data is in the parameter field rather than pointed to by it.
+pub const TRB_IMMEDIATE_DATA:               u32 = 1 << 6;
+pub const TRB_TYPE_SHIFT:                   u32 = 10;
+/// In a data or status stage TRB, the direction of the transfer is IN (device to host).
+pub const TRB_DIRECTION_IN:                 u32 = 1 << 16;
+/// In a setup stage TRB, the shift of the Transfer Type field.
+pub const TRB_TRANSFER_TYPE_SHIFT:          u32 = 16;
+pub const TRB_TRANSFER_TYPE_NO_DATA:        u32 = 0;
+pub const TRB_TRANSFER_TYPE_OUT_DATA:       u32 = 2;
+pub const TRB_TRANSFER_TYPE_IN_DATA:        u32 = 3;
+pub const TRB_SLOT_ID_SHIFT:                u32 = 24;
+
+// Completion codes
+pub const COMPLETION_SUCCESS:               u8 = 1;
+pub const COMPLETION_SHORT_PACKET:          u8 = 13;
+
+
+/// A Transfer Request Block, the 16-byte unit of every ring.
+#[derive(FromBytes, Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Trb {
+    pub parameter: u64,
+    pub status: u32,
+    pub control: u32,
+}
+
+impl Trb {
+    /// Returns a TRB of the given type with the given parameter, status, and other control bits.
+    pub fn new(trb_type: u32, parameter: u64, status: u32, control: u32) -> Trb {
+        Trb { parameter, status, control: control | (trb_type << TRB_TYPE_SHIFT) }
+    }
+
+    pub fn trb_type(&self) -> u32 {
+        (self.control >> TRB_TYPE_SHIFT) & 0x3F
+    }
+
+    /// Returns the completion code of an event TRB.
+    pub fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    /// Returns the slot ID of an event TRB.
+    pub fn slot_id(&self) -> u8 {
+        (self.control >> TRB_SLOT_ID_SHIFT) as u8
+    }
+
+    /// Returns the endpoint ID (device context index) of a transfer event TRB.
+    pub fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1F) as u8
+    }
+
+    /// Returns the number of bytes that were *not* transferred, according to a transfer event TRB.
+    pub fn transfer_residual(&self) -> u32 {
+        self.status & 0xFF_FFFF
+    }
+}
+
+
+/// A ring onto which software produces TRBs: the command ring or a transfer ring.
+///
+/// The last TRB of the ring is a link TRB that points back to its start.
+pub struct ProducerRing {
+    memory: MappedPages,
+    memory_phys: PhysicalAddress,
+    enqueue_index: usize,
+    /// The producer cycle state, which marks TRBs that are ready for the controller.
+    cycle: bool,
+}
+
+impl ProducerRing {
+    pub fn new() -> Result<ProducerRing, &'static str> {
+        let (mut memory, memory_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        {
+            let trbs = memory.as_slice_mut::<Trb>(0, TRBS_PER_RING)?;
+            for trb in trbs.iter_mut() { *trb = Trb::default(); }
+            trbs[TRBS_PER_RING - 1] = Trb::new(TRB_TYPE_LINK, memory_phys.value() as u64, 0, TRB_TOGGLE_CYCLE);
+        }
+        Ok(ProducerRing { memory, memory_phys, enqueue_index: 0, cycle: true })
+    }
+
+    /// Returns the physical address of the start of the ring.
+    pub fn start_address(&self) -> PhysicalAddress {
+        self.memory_phys
+    }
+
+    /// Returns the initial cycle state to program into the controller along with the ring's address.
+    pub fn cycle_state(&self) -> bool {
+        self.cycle
+    }
+
+    /// Places the given TRB onto the ring, giving ownership of it to the controller,
+    /// and returns the physical address of the TRB.
+    pub fn push(&mut self, trb: Trb) -> Result<PhysicalAddress, &'static str> {
+        let cycle_bit = if self.cycle { TRB_CYCLE } else { 0 };
+        let index = self.enqueue_index;
+        {
+            let slot = self.memory.as_type_mut::<Trb>(index * size_of::<Trb>())?;
+            slot.parameter = trb.parameter;
+            slot.status = trb.status;
+            // The cycle bit hands the TRB over, so it must be written last.
+            fence(Ordering::SeqCst);
+            unsafe { core::ptr::write_volatile(&mut slot.control, (trb.control & !TRB_CYCLE) | cycle_bit); }
+        }
+        let trb_phys = self.memory_phys + index * size_of::<Trb>();
+
+        self.enqueue_index += 1;
+        if self.enqueue_index == TRBS_PER_RING - 1 {
+            // Hand the link TRB over too, then start again at the beginning with the opposite cycle state.
+            let link = self.memory.as_type_mut::<Trb>((TRBS_PER_RING - 1) * size_of::<Trb>())?;
+            let control = (link.control & !TRB_CYCLE) | cycle_bit;
+            fence(Ordering::SeqCst);
+            unsafe { core::ptr::write_volatile(&mut link.control, control); }
+            self.enqueue_index = 0;
+            self.cycle = !self.cycle;
+        }
+        Ok(trb_phys)
+    }
+}
+
+
+/// An entry in the Event Ring Segment Table.
+#[derive(FromBytes)]
+#[repr(C)]
+struct EventRingSegment {
+    base_address: u64,
+    size: u32,
+    _reserved: u32,
+}
+
+/// A ring onto which the controller produces event TRBs, which consists of a single segment.
+pub struct EventRing {
+    memory: MappedPages,
+    memory_phys: PhysicalAddress,
+    _segment_table: MappedPages,
+    segment_table_phys: PhysicalAddress,
+    dequeue_index: usize,
+    /// The consumer cycle state, which marks TRBs that the controller has produced.
+    cycle: bool,
+}
+
+impl EventRing {
+    pub fn new() -> Result<EventRing, &'static str> {
+        let (mut memory, memory_phys) = create_contiguous_mapping(PAGE_SIZE, XHCI_MAPPING_FLAGS)?;
+        for trb in memory.as_slice_mut::<Trb>(0, TRBS_PER_RING)? { *trb = Trb::default(); }
+        let (mut segment_table, segment_table_phys) = create_contiguous_mapping(size_of::<EventRingSegment>(), XHCI_MAPPING_FLAGS)?;
+        {
+            let segment = segment_table.as_type_mut::<EventRingSegment>(0)?;
+            segment.base_address = memory_phys.value() as u64;
+            segment.size = TRBS_PER_RING as u32;
+            segment._reserved = 0;
+        }
+        Ok(EventRing {
+            memory,
+            memory_phys,
+            _segment_table: segment_table,
+            segment_table_phys,
+            dequeue_index: 0,
+            cycle: true,
+        })
+    }
+
+    /// Returns the physical address of the Event Ring Segment Table, which has one entry.
+    pub fn segment_table_address(&self) -> PhysicalAddress {
+        self.segment_table_phys
+    }
+
+    /// Returns the physical address of the next TRB to be consumed,
+    /// which must be written to the interrupter's dequeue pointer register after consuming events.
+    pub fn dequeue_address(&self) -> PhysicalAddress {
+        self.memory_phys + self.dequeue_index * size_of::<Trb>()
+    }
+
+    /// Returns the next event that the controller has produced, if any.
+    pub fn pop(&mut self) -> Option<Trb> {
+        let slot = self.memory.as_type::<Trb>(self.dequeue_index * size_of::<Trb>()).ok()?;
+        // The controller writes this memory, so it must be read anew every time.
+        // It writes the cycle bit last, so the rest of the TRB is only read once the cycle bit matches.
+        let control = unsafe { core::ptr::read_volatile(&slot.control) };
+        if (control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let trb = unsafe { core::ptr::read_volatile(slot) };
+        self.dequeue_index += 1;
+        if self.dequeue_index == TRBS_PER_RING {
+            self.dequeue_index = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+}
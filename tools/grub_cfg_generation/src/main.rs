@@ -15,6 +15,7 @@ fn main() -> Result<(), String> {
 
     let mut opts = Options::new();
     opts.optopt("o", "", "set output file path, e.g., \"/my/dir/grub.cfg\"", "OUTPUT_PATH");
+    opts.optopt("c", "cmdline", "set the kernel command line, e.g., \"gdb=wait\"", "KERNEL_CMDLINE");
     opts.optflag("h", "help", "print this help menu");
 
     let matches = opts.parse(&args[1..]).map_err(|e| e.to_string())?;
@@ -31,7 +32,8 @@ fn main() -> Result<(), String> {
         _ => return Err(format!("Too many arguments entered")),
     };
     
-    let grub_cfg_string = create_grub_cfg_string(input_directory)?;
+    let kernel_cmdline = matches.opt_str("c").unwrap_or_default();
+    let grub_cfg_string = create_grub_cfg_string(input_directory, kernel_cmdline)?;
     
     // Write to output file (if provided) 
     if matches.opt_present("o") {
@@ -52,7 +54,7 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
-fn create_grub_cfg_string(input_directory: String) -> Result<String, String> {
+fn create_grub_cfg_string(input_directory: String, kernel_cmdline: String) -> Result<String, String> {
     // Creates string to write to grub.cfg file by looking through all files in input_directory
     let mut content = String::new();
     
@@ -66,7 +68,7 @@ fn create_grub_cfg_string(input_directory: String) -> Result<String, String> {
     content.push_str("set timeout=0\n");
     content.push_str("set default=0\n\n");
     content.push_str("menuentry \"Theseus OS\" {\n");
-    content.push_str("\tmultiboot2 /boot/kernel.bin \n");
+    content.push_str(&format!("\tmultiboot2 /boot/kernel.bin {}\n", kernel_cmdline));
 
     for path in fs::read_dir(input_directory).map_err(|e| e.to_string())? {
         let path = path.map_err(|e| e.to_string())?;